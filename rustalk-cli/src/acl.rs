@@ -0,0 +1,246 @@
+//! ACL management commands
+//!
+//! Commands talk to the running server through the API by default, or edit
+//! the `acls` section of a configuration file when `--config` is given.
+
+use anyhow::{Context, Result};
+use rustalk_core::acl::{Acl, AclAction, AclManager, AclRule};
+use rustalk_core::prelude::Config;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::api::ApiClient;
+use crate::{AclCommands, AclSource};
+
+/// Handle ACL management commands
+pub async fn handle_acl_command(cmd: AclCommands) -> Result<()> {
+    match cmd {
+        AclCommands::List { source } => list_acls(&source).await,
+        AclCommands::AddRule {
+            acl,
+            cidr,
+            action,
+            name,
+            priority,
+            source,
+        } => {
+            let rule = AclRule {
+                name: name.unwrap_or_else(|| cidr.clone()),
+                cidr,
+                action: parse_action(&action)?,
+                priority,
+            };
+            add_rule(&source, &acl, rule).await
+        }
+        AclCommands::RemoveRule { acl, rule, source } => remove_rule(&source, &acl, &rule).await,
+        AclCommands::Check { acl, ip, source } => check_ip(&source, &acl, ip).await,
+    }
+}
+
+async fn list_acls(source: &AclSource) -> Result<()> {
+    let acls: Vec<Acl> = match &source.config {
+        Some(path) => load_acls(path).await?.acls,
+        None => {
+            let response = ApiClient::new(&source.server).get("/acls").await?;
+            serde_json::from_value(response["acls"].clone())
+                .context("Unexpected ACL list response")?
+        }
+    };
+
+    if acls.is_empty() {
+        println!("No ACLs configured");
+    }
+    for acl in &acls {
+        print_acl(acl);
+    }
+
+    Ok(())
+}
+
+async fn add_rule(source: &AclSource, acl_name: &str, rule: AclRule) -> Result<()> {
+    let summary = format!(
+        "{} {} (priority {})",
+        action_name(rule.action),
+        rule.cidr,
+        rule.priority
+    );
+
+    update_acl(source, acl_name, |acl| {
+        acl.add_rule(rule);
+        Ok(())
+    })
+    .await?;
+
+    println!("✓ Added rule to '{}': {}", acl_name, summary);
+    Ok(())
+}
+
+async fn remove_rule(source: &AclSource, acl_name: &str, rule: &str) -> Result<()> {
+    update_acl(source, acl_name, |acl| match acl.remove_rule(rule) {
+        0 => anyhow::bail!("No rule named or matching '{}' in ACL '{}'", rule, acl_name),
+        _ => Ok(()),
+    })
+    .await?;
+
+    println!("✓ Removed rule '{}' from '{}'", rule, acl_name);
+    Ok(())
+}
+
+/// Fetch an ACL, apply a change and write it back
+async fn update_acl<F>(source: &AclSource, acl_name: &str, change: F) -> Result<()>
+where
+    F: FnOnce(&mut Acl) -> Result<()>,
+{
+    match &source.config {
+        Some(path) => {
+            let mut config = Config::from_file(path).await?;
+            let manager = config.acls.get_or_insert_with(AclManager::new);
+            let acl = manager
+                .get_acl_mut(acl_name)
+                .with_context(|| format!("ACL '{}' not found", acl_name))?;
+            change(acl)?;
+            config.save_to_file(path).await
+        }
+        None => {
+            let client = ApiClient::new(&source.server);
+            let path = format!("/acls/{}", acl_name);
+            let mut acl: Acl = serde_json::from_value(client.get(&path).await?)
+                .context("Unexpected ACL response")?;
+            change(&mut acl)?;
+            client.put(&path, &acl).await.map(|_| ())
+        }
+    }
+}
+
+async fn check_ip(source: &AclSource, acl_name: &str, ip: IpAddr) -> Result<()> {
+    let (allowed, rule, default_policy, enabled) = match &source.config {
+        Some(path) => {
+            let manager = load_acls(path).await?;
+            let acl = manager
+                .get_acl(acl_name)
+                .with_context(|| format!("ACL '{}' not found", acl_name))?;
+            let rule = acl.matching_rule(ip)?.cloned();
+            (acl.is_allowed(ip)?, rule, acl.default_policy, acl.enabled)
+        }
+        None => {
+            let response = ApiClient::new(&source.server)
+                .get(&format!("/acls/{}/check/{}", acl_name, ip))
+                .await?;
+            (
+                response["allowed"].as_bool().unwrap_or(false),
+                serde_json::from_value(response["matched_rule"].clone())?,
+                serde_json::from_value(response["default_policy"].clone())?,
+                response["enabled"].as_bool().unwrap_or(true),
+            )
+        }
+    };
+
+    let verdict = if allowed { "✓ ALLOWED" } else { "✗ DENIED" };
+    println!("{} {} by ACL '{}'", verdict, ip, acl_name);
+    match rule {
+        Some(rule) => println!(
+            "  Matched rule '{}' ({} {}, priority {})",
+            rule.name,
+            action_name(rule.action),
+            rule.cidr,
+            rule.priority
+        ),
+        None if !enabled => println!(
+            "  ACL is disabled; default policy '{}' applies",
+            action_name(default_policy)
+        ),
+        None => println!(
+            "  No rule matched; default policy '{}' applies",
+            action_name(default_policy)
+        ),
+    }
+
+    Ok(())
+}
+
+async fn load_acls(path: &Path) -> Result<AclManager> {
+    let config = Config::from_file(path).await?;
+    Ok(config.acls.unwrap_or_default())
+}
+
+fn print_acl(acl: &Acl) {
+    println!(
+        "\n[{}]{}",
+        acl.name,
+        if acl.enabled { "" } else { " (disabled)" }
+    );
+    if let Some(description) = &acl.description {
+        println!("  {}", description);
+    }
+    for rule in &acl.rules {
+        println!(
+            "  {:>4}  {:<5}  {:<20}  {}",
+            rule.priority,
+            action_name(rule.action),
+            rule.cidr,
+            rule.name
+        );
+    }
+    println!("  default: {}", action_name(acl.default_policy));
+}
+
+fn parse_action(action: &str) -> Result<AclAction> {
+    match action.to_lowercase().as_str() {
+        "allow" => Ok(AclAction::Allow),
+        "deny" => Ok(AclAction::Deny),
+        other => anyhow::bail!("Invalid ACL action '{}'. Use 'allow' or 'deny'", other),
+    }
+}
+
+fn action_name(action: AclAction) -> &'static str {
+    match action {
+        AclAction::Allow => "allow",
+        AclAction::Deny => "deny",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offline(path: &Path) -> AclSource {
+        AclSource {
+            server: String::new(),
+            config: Some(path.to_path_buf()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_offline_add_and_remove_rule() {
+        let path = std::env::temp_dir().join("rustalk_cli_acl_test.json");
+        Config::default().save_to_file(&path).await.unwrap();
+        let source = offline(&path);
+
+        let rule = AclRule {
+            name: "carrier".to_string(),
+            cidr: "203.0.113.0/24".to_string(),
+            action: AclAction::Allow,
+            priority: 5,
+        };
+        add_rule(&source, "rfc1918", rule).await.unwrap();
+
+        let manager = load_acls(&path).await.unwrap();
+        let ip = "203.0.113.9".parse().unwrap();
+        assert!(manager.is_allowed("rfc1918", ip).unwrap());
+        check_ip(&source, "rfc1918", ip).await.unwrap();
+
+        remove_rule(&source, "rfc1918", "carrier").await.unwrap();
+        let manager = load_acls(&path).await.unwrap();
+        assert!(!manager.is_allowed("rfc1918", ip).unwrap());
+
+        assert!(remove_rule(&source, "rfc1918", "carrier").await.is_err());
+        assert!(check_ip(&source, "missing", ip).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(parse_action("ALLOW").unwrap(), AclAction::Allow);
+        assert_eq!(parse_action("deny").unwrap(), AclAction::Deny);
+        assert!(parse_action("drop").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! HTTP client for the RusTalk management API
+
+use anyhow::{Context, Result};
+use reqwest::header::{ETAG, IF_MATCH};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Thin JSON client for the cloud API
+pub struct ApiClient {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl ApiClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Value> {
+        self.send(self.client.get(self.url(path))).await
+    }
+
+    pub async fn post(&self, path: &str, body: &impl Serialize) -> Result<Value> {
+        self.send(self.client.post(self.url(path)).json(body)).await
+    }
+
+    /// POST without treating error statuses as failures, for endpoints whose
+    /// error responses carry details worth showing
+    pub async fn post_unchecked(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<(StatusCode, Value)> {
+        let response = self
+            .client
+            .post(self.url(path))
+            .json(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach the RusTalk API at {}", self.base_url))?;
+
+        let status = response.status();
+        Ok((status, response.json().await.unwrap_or(Value::Null)))
+    }
+
+    pub async fn put(&self, path: &str, body: &impl Serialize) -> Result<Value> {
+        self.send(self.client.put(self.url(path)).json(body)).await
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<Value> {
+        self.send(self.client.delete(self.url(path))).await
+    }
+
+    /// DELETE a versioned resource whatever its current version
+    pub async fn delete_any_version(&self, path: &str) -> Result<Value> {
+        self.send(self.client.delete(self.url(path)).header(IF_MATCH, "*"))
+            .await
+    }
+
+    /// Fetch a resource, apply a change and write it back with PUT
+    ///
+    /// The PUT carries the ETag of the fetched resource, so a change someone
+    /// else made in between fails instead of being overwritten.
+    pub async fn update<F>(&self, path: &str, change: F) -> Result<Value>
+    where
+        F: FnOnce(&mut Value),
+    {
+        let response = self
+            .client
+            .get(self.url(path))
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach the RusTalk API at {}", self.base_url))?;
+        let etag = response.headers().get(ETAG).cloned();
+        let mut resource = Self::body(response).await?;
+
+        change(&mut resource);
+        let mut request = self.client.put(self.url(path)).json(&resource);
+        if let Some(etag) = etag {
+            request = request.header(IF_MATCH, etag);
+        }
+        self.send(request).await?;
+        Ok(resource)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v1{}", self.base_url, path)
+    }
+
+    /// Send a request and parse the response with [`Self::body`]
+    async fn send(&self, request: RequestBuilder) -> Result<Value> {
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach the RusTalk API at {}", self.base_url))?;
+
+        Self::body(response).await
+    }
+
+    /// Parse a JSON response, turning non-2xx statuses into errors carrying
+    /// the server's message
+    async fn body(response: Response) -> Result<Value> {
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+
+        if !status.is_success() {
+            // Problem details carry the message in `detail`
+            let message = body["detail"]
+                .as_str()
+                .or_else(|| body["message"].as_str())
+                .or_else(|| body["error"].as_str())
+                .unwrap_or_else(|| status.canonical_reason().unwrap_or("Request failed"));
+            anyhow::bail!("{} ({})", message, status);
+        }
+
+        Ok(body)
+    }
+}
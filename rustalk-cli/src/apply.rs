@@ -0,0 +1,112 @@
+//! Declarative apply command
+//!
+//! Shows the plan the server makes for a desired-state file and, once it is
+//! confirmed, applies exactly that plan.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use crate::api::ApiClient;
+use crate::output::cell;
+
+/// Plan `file` against the server and apply it once confirmed
+pub async fn apply(file: &Path, auto_approve: bool, server: &str) -> Result<()> {
+    let text =
+        fs::read_to_string(file).with_context(|| format!("Cannot read {}", file.display()))?;
+    let desired: Value = serde_json::from_str(&text)
+        .with_context(|| format!("{} is not valid JSON", file.display()))?;
+
+    let client = ApiClient::new(server);
+    let response = client.post("/apply", &desired).await?;
+    let plan = &response["plan"];
+    if plan["changes"].as_array().is_none_or(Vec::is_empty)
+        && plan["reordered"].as_array().is_none_or(Vec::is_empty)
+    {
+        println!("✓ No changes; the server matches {}", file.display());
+        return Ok(());
+    }
+    print!("{}", render_plan(plan));
+    println!("{}", cell(&response["message"]));
+
+    if !auto_approve && !confirm()? {
+        println!("Apply cancelled");
+        return Ok(());
+    }
+    let plan_id = plan["plan_id"]
+        .as_str()
+        .context("The server did not name its plan")?;
+    let response = client
+        .post(&format!("/apply?confirm={}", plan_id), &desired)
+        .await?;
+    println!("✓ {}", cell(&response["message"]));
+    Ok(())
+}
+
+/// One line per planned change, `+` to create, `~` to update, `-` to delete
+fn render_plan(plan: &Value) -> String {
+    let mut text = String::new();
+    for change in plan["changes"].as_array().into_iter().flatten() {
+        let sign = match change["action"].as_str() {
+            Some("create") => '+',
+            Some("delete") => '-',
+            _ => '~',
+        };
+        text.push_str(&format!(
+            "  {} {} {}",
+            sign,
+            cell(&change["resource"]),
+            cell(&change["id"])
+        ));
+        if let Some(fields) = change["fields"].as_array() {
+            let fields: Vec<String> = fields.iter().map(cell).collect();
+            text.push_str(&format!(" ({})", fields.join(", ")));
+        }
+        text.push('\n');
+    }
+    for resource in plan["reordered"].as_array().into_iter().flatten() {
+        text.push_str(&format!("  ↕ {}s reordered\n", cell(resource)));
+    }
+    text
+}
+
+/// Ask whether to apply the plan; only `yes` does
+fn confirm() -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("Refusing to apply without confirmation; pass --auto-approve");
+    }
+    print!("Apply these changes? Only 'yes' is accepted: ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("Cannot read the answer")?;
+    Ok(answer.trim() == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_plan() {
+        let plan = json!({
+            "plan_id": "0123",
+            "changes": [
+                { "resource": "extension", "id": "front-desk", "action": "create" },
+                { "resource": "trunk", "id": "carrier", "action": "update", "fields": ["host", "port"] },
+                { "resource": "route", "id": "old", "action": "delete" }
+            ],
+            "reordered": ["route"],
+            "unchanged": 4
+        });
+        assert_eq!(
+            render_plan(&plan),
+            "  + extension front-desk\n  ~ trunk carrier (host, port)\n  - route old\n  ↕ routes reordered\n"
+        );
+    }
+}
@@ -4,19 +4,28 @@
 //! allowing users to execute commands interactively with history and editing support.
 
 use anyhow::Result;
+use rustalk_core::acl::{Acl, AclAction};
+use rustalk_core::call_trace::TraceTarget;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
 use std::path::PathBuf;
 
+use crate::api::ApiClient;
+use crate::output::{cell, yes_no, OutputOptions, Table};
+
 /// Console command types
 #[derive(Debug, Clone, PartialEq)]
 pub enum ConsoleCommand {
     /// Show commands
-    Show(ShowTarget),
+    Show(ShowTarget, OutputOptions),
     /// Profile management commands
     Profile(ProfileAction),
     /// Module management commands
     Module(ModuleAction),
+    /// Per-call debug trace commands
+    Debug(DebugAction),
+    /// Re-read the configuration in the running server, or only check it
+    ReloadConfig { dry_run: bool },
     /// Help command
     Help,
     /// Exit the console
@@ -34,6 +43,30 @@ pub enum ShowTarget {
     Status,
     /// Show active calls
     Calls,
+    /// Show active debug traces
+    Debug,
+    /// Show registered endpoints
+    Registrations,
+    /// Show devices seen registering
+    Devices,
+    /// Show call legs
+    Channels,
+}
+
+impl ShowTarget {
+    pub fn parse(target: &str) -> Result<Self> {
+        Ok(match target.to_lowercase().as_str() {
+            "acls" | "acl" => ShowTarget::Acls,
+            "profiles" | "profile" => ShowTarget::Profiles,
+            "status" => ShowTarget::Status,
+            "calls" | "call" => ShowTarget::Calls,
+            "debug" | "traces" => ShowTarget::Debug,
+            "registrations" | "registration" | "regs" => ShowTarget::Registrations,
+            "devices" | "device" => ShowTarget::Devices,
+            "channels" | "channel" => ShowTarget::Channels,
+            other => anyhow::bail!("Unknown show target: {}", other),
+        })
+    }
 }
 
 /// Profile management actions
@@ -65,9 +98,18 @@ pub enum ModuleActionType {
     Reload,
 }
 
+/// Debug trace actions
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugAction {
+    /// Start tracing a target
+    Start(TraceTarget),
+    /// Stop tracing a target
+    Stop(TraceTarget),
+}
+
 /// Parse a console command from input string
 pub fn parse_command(input: &str) -> Result<ConsoleCommand> {
-    let parts: Vec<&str> = input.trim().split_whitespace().collect();
+    let parts: Vec<&str> = input.split_whitespace().collect();
 
     if parts.is_empty() {
         anyhow::bail!("Empty command");
@@ -81,6 +123,13 @@ pub fn parse_command(input: &str) -> Result<ConsoleCommand> {
         "load" => parse_load_command(&parts[1..]),
         "unload" => parse_unload_command(&parts[1..]),
         "reload" => parse_reload_command(&parts[1..]),
+        "debug" => parse_debug_command(&parts[1..]),
+        "nodebug" => parse_nodebug_command(&parts[1..]),
+        "reloadconfig" | "reloadxml" => match parts.get(1).map(|a| a.to_lowercase()) {
+            None => Ok(ConsoleCommand::ReloadConfig { dry_run: false }),
+            Some(arg) if arg == "check" => Ok(ConsoleCommand::ReloadConfig { dry_run: true }),
+            Some(arg) => anyhow::bail!("Unknown reloadconfig option: {}. Use 'check'", arg),
+        },
         cmd => anyhow::bail!(
             "Unknown command: {}. Type 'help' for available commands.",
             cmd
@@ -89,19 +138,25 @@ pub fn parse_command(input: &str) -> Result<ConsoleCommand> {
 }
 
 fn parse_show_command(args: &[&str]) -> Result<ConsoleCommand> {
-    if args.is_empty() {
-        anyhow::bail!("show command requires a target (acls, profiles, status, calls)");
+    let mut options = OutputOptions::default();
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg.to_lowercase().as_str() {
+            "--csv" => options.csv = true,
+            "--no-page" | "--nopage" => options.paging = false,
+            flag if flag.starts_with("--") => anyhow::bail!("Unknown show option: {}", flag),
+            _ => rest.push(*arg),
+        }
     }
+    let args = rest.as_slice();
 
-    let target = match args[0].to_lowercase().as_str() {
-        "acls" | "acl" => ShowTarget::Acls,
-        "profiles" | "profile" => ShowTarget::Profiles,
-        "status" => ShowTarget::Status,
-        "calls" | "call" => ShowTarget::Calls,
-        other => anyhow::bail!("Unknown show target: {}", other),
-    };
+    if args.is_empty() {
+        anyhow::bail!(
+            "show command requires a target (acls, profiles, status, calls, registrations, devices, channels, debug)"
+        );
+    }
 
-    Ok(ConsoleCommand::Show(target))
+    Ok(ConsoleCommand::Show(ShowTarget::parse(args[0])?, options))
 }
 
 fn parse_profile_command(args: &[&str]) -> Result<ConsoleCommand> {
@@ -154,6 +209,24 @@ fn parse_reload_command(args: &[&str]) -> Result<ConsoleCommand> {
     }))
 }
 
+fn parse_debug_command(args: &[&str]) -> Result<ConsoleCommand> {
+    if args.len() < 2 {
+        anyhow::bail!("debug command requires: debug <call|extension|ip> <value>");
+    }
+
+    let target = TraceTarget::parse(args[0], args[1])?;
+    Ok(ConsoleCommand::Debug(DebugAction::Start(target)))
+}
+
+fn parse_nodebug_command(args: &[&str]) -> Result<ConsoleCommand> {
+    if args.len() < 2 {
+        anyhow::bail!("nodebug command requires: nodebug <call|extension|ip> <value>");
+    }
+
+    let target = TraceTarget::parse(args[0], args[1])?;
+    Ok(ConsoleCommand::Debug(DebugAction::Stop(target)))
+}
+
 /// Display help information
 pub fn display_help() {
     println!("\nRusTalk Console Commands:");
@@ -163,6 +236,12 @@ pub fn display_help() {
     println!("  show profiles          - Display SIP profiles");
     println!("  show status            - Display server status");
     println!("  show calls             - Display active calls");
+    println!("  show registrations     - Display registered endpoints");
+    println!("  show devices           - Display devices seen registering");
+    println!("  show channels          - Display call legs with state and codec");
+    println!("  show debug             - Display active debug traces");
+    println!("  show <target> --csv    - Output as CSV");
+    println!("  show <target> --no-page - Disable paging of long output");
     println!("\nProfile Management:");
     println!("  profile <name> start   - Start a SIP profile");
     println!("  profile <name> stop    - Stop a SIP profile");
@@ -172,6 +251,14 @@ pub fn display_help() {
     println!("  load <module>          - Load a module");
     println!("  unload <module>        - Unload a module");
     println!("  reload <module>        - Reload a module");
+    println!("\nDebug Tracing:");
+    println!("  debug call <call-id>   - Trace a single call");
+    println!("  debug extension <ext>  - Trace calls to or from an extension");
+    println!("  debug ip <address>     - Trace calls from a source IP");
+    println!("  nodebug <type> <value> - Stop a debug trace");
+    println!("\nConfiguration:");
+    println!("  reloadconfig           - Reload ACLs, routes, codecs and profiles");
+    println!("  reloadconfig check     - Validate and shadow-test without applying");
     println!("\nGeneral:");
     println!("  help, ?                - Display this help");
     println!("  exit, quit, q          - Exit the console");
@@ -179,13 +266,20 @@ pub fn display_help() {
 }
 
 /// Execute a console command
-pub async fn execute_command(command: ConsoleCommand, config_path: &PathBuf) -> Result<()> {
+///
+/// `api_url` is the base URL of the running server's management API, used by
+/// commands that change runtime state.
+pub async fn execute_command(
+    command: ConsoleCommand,
+    config_path: &PathBuf,
+    api_url: &str,
+) -> Result<()> {
     match command {
         ConsoleCommand::Help => {
             display_help();
         }
-        ConsoleCommand::Show(target) => {
-            execute_show_command(target, config_path).await?;
+        ConsoleCommand::Show(target, options) => {
+            execute_show_command(target, options, config_path, api_url).await?;
         }
         ConsoleCommand::Profile(action) => {
             execute_profile_command(action).await?;
@@ -193,6 +287,12 @@ pub async fn execute_command(command: ConsoleCommand, config_path: &PathBuf) ->
         ConsoleCommand::Module(action) => {
             execute_module_command(action).await?;
         }
+        ConsoleCommand::Debug(action) => {
+            execute_debug_command(action, api_url).await?;
+        }
+        ConsoleCommand::ReloadConfig { dry_run } => {
+            execute_reload_config(dry_run, api_url).await?;
+        }
         ConsoleCommand::Exit => {
             // Exit is handled in the main loop
         }
@@ -200,36 +300,67 @@ pub async fn execute_command(command: ConsoleCommand, config_path: &PathBuf) ->
     Ok(())
 }
 
-async fn execute_show_command(target: ShowTarget, config_path: &PathBuf) -> Result<()> {
+/// Run a show command against the config file and management API
+pub async fn execute_show_command(
+    target: ShowTarget,
+    options: OutputOptions,
+    config_path: &PathBuf,
+    api_url: &str,
+) -> Result<()> {
     match target {
         ShowTarget::Acls => {
-            println!("\nAccess Control Lists:");
-            println!("=====================");
-            println!("  [default]");
-            println!("    - allow: 0.0.0.0/0");
-            println!("  [trusted]");
-            println!("    - allow: 192.168.0.0/16");
-            println!("    - allow: 10.0.0.0/8");
-            println!();
+            let response = ApiClient::new(api_url).get("/acls").await?;
+            let acls: Vec<Acl> = serde_json::from_value(response["acls"].clone())?;
+
+            let mut table = Table::new(&["ACL", "PRIORITY", "ACTION", "CIDR", "RULE"]);
+            for acl in &acls {
+                for rule in &acl.rules {
+                    table.add_row(vec![
+                        acl.name.clone(),
+                        rule.priority.to_string(),
+                        action_name(rule.action).to_string(),
+                        rule.cidr.clone(),
+                        rule.name.clone(),
+                    ]);
+                }
+                let default = if acl.enabled {
+                    "default"
+                } else {
+                    "default (disabled)"
+                };
+                table.add_row(vec![
+                    acl.name.clone(),
+                    "-".to_string(),
+                    action_name(acl.default_policy).to_string(),
+                    "*".to_string(),
+                    default.to_string(),
+                ]);
+            }
+            show_table(
+                "Access Control Lists",
+                &table,
+                options,
+                "No ACLs configured",
+            )?;
         }
         ShowTarget::Profiles => {
-            println!("\nSIP Profiles:");
-            println!("=============");
+            let mut table = Table::new(&["NAME", "DOMAIN", "BIND", "PROTOCOLS", "STATUS"]);
 
             // Load config to get profile information
-            if let Ok(config) = rustalk_core::Config::from_file(config_path).await {
-                println!("  [default]");
-                println!("    Domain: {}", config.sip.domain);
-                println!(
-                    "    Bind: {}:{}",
-                    config.server.bind_address, config.server.bind_port
-                );
-                println!("    Protocols: {}", config.transport.protocols.join(", "));
-                println!("    Status: configured");
-            } else {
-                println!("  No profiles configured (config file not found)");
-            }
-            println!();
+            let empty = match rustalk_core::Config::from_file(config_path).await {
+                Ok(config) => {
+                    table.add_row(vec![
+                        "default".to_string(),
+                        config.sip.domain.clone(),
+                        format!("{}:{}", config.server.bind_address, config.server.bind_port),
+                        config.transport.protocols.join(","),
+                        "configured".to_string(),
+                    ]);
+                    "No profiles configured"
+                }
+                Err(_) => "No profiles configured (config file not found)",
+            };
+            show_table("SIP Profiles", &table, options, empty)?;
         }
         ShowTarget::Status => {
             println!("\nServer Status:");
@@ -242,15 +373,142 @@ async fn execute_show_command(target: ShowTarget, config_path: &PathBuf) -> Resu
             println!();
         }
         ShowTarget::Calls => {
-            println!("\nActive Calls:");
-            println!("=============");
-            println!("  No active calls");
-            println!();
+            let response = ApiClient::new(api_url).get("/calls").await?;
+
+            let mut table = Table::new(&["ID", "FROM", "TO", "STATE", "DURATION"]);
+            for call in response["calls"].as_array().into_iter().flatten() {
+                table.add_row(vec![
+                    cell(&call["id"]),
+                    cell(&call["from"]),
+                    cell(&call["to"]),
+                    cell(&call["status"]),
+                    cell(&call["duration"]),
+                ]);
+            }
+            show_table("Active Calls", &table, options, "No active calls")?;
+        }
+        ShowTarget::Debug => {
+            let response = ApiClient::new(api_url).get("/debug/traces").await?;
+
+            let mut table = Table::new(&["TYPE", "TARGET", "ENTRIES", "FILE"]);
+            for trace in response["traces"].as_array().into_iter().flatten() {
+                table.add_row(vec![
+                    cell(&trace["target"]["type"]),
+                    cell(&trace["target"]["value"]),
+                    cell(&trace["entries"]),
+                    cell(&trace["file"]),
+                ]);
+            }
+            show_table("Debug Traces", &table, options, "No active traces")?;
+        }
+        ShowTarget::Registrations => {
+            let response = ApiClient::new(api_url).get("/registrations").await?;
+
+            let mut table =
+                Table::new(&["AOR", "CONTACT", "EXPIRES", "USER AGENT", "RECEIVED", "NAT"]);
+            for reg in response["registrations"].as_array().into_iter().flatten() {
+                table.add_row(vec![
+                    cell(&reg["aor"]),
+                    cell(&reg["contact"]),
+                    format!("{}s", cell(&reg["expires_in"])),
+                    cell(&reg["user_agent"]),
+                    cell(&reg["received"]),
+                    yes_no(&reg["behind_nat"]),
+                ]);
+            }
+            show_table("Registrations", &table, options, "No registered endpoints")?;
+        }
+        ShowTarget::Devices => {
+            let response = ApiClient::new(api_url).get("/devices").await?;
+
+            let mut table = Table::new(&[
+                "ID",
+                "EXTENSION",
+                "VENDOR",
+                "MODEL",
+                "FIRMWARE",
+                "MAC",
+                "LAST IP",
+                "REVOKED",
+            ]);
+            for device in response["devices"].as_array().into_iter().flatten() {
+                table.add_row(vec![
+                    cell(&device["id"]),
+                    cell(&device["extension"]),
+                    cell(&device["vendor"]),
+                    cell(&device["model"]),
+                    cell(&device["firmware"]),
+                    cell(&device["mac"]),
+                    cell(&device["last_ip"]),
+                    yes_no(&serde_json::Value::Bool(!device["revoked_at"].is_null())),
+                ]);
+            }
+            show_table("Devices", &table, options, "No devices seen")?;
+        }
+        ShowTarget::Channels => {
+            let response = ApiClient::new(api_url).get("/channels").await?;
+
+            let mut table = Table::new(&[
+                "CALL-ID", "LEG", "STATE", "FROM", "TO", "REMOTE", "CODEC", "DURATION",
+            ]);
+            for channel in response["channels"].as_array().into_iter().flatten() {
+                table.add_row(vec![
+                    cell(&channel["call_id"]),
+                    cell(&channel["leg"]).to_uppercase(),
+                    cell(&channel["state"]),
+                    cell(&channel["from"]),
+                    cell(&channel["to"]),
+                    cell(&channel["remote_addr"]),
+                    cell(&channel["codec"]),
+                    format_duration(channel["duration_seconds"].as_i64().unwrap_or(0)),
+                ]);
+            }
+            show_table("Channels", &table, options, "No active channels")?;
         }
     }
     Ok(())
 }
 
+/// Print a show command's table under a title, or a message when empty
+fn show_table(
+    title: &str,
+    table: &Table,
+    options: OutputOptions,
+    empty_message: &str,
+) -> Result<()> {
+    if options.csv {
+        table.display(options)?;
+        return Ok(());
+    }
+
+    println!("\n{}:", title);
+    println!("{}", "=".repeat(title.len() + 1));
+    if table.is_empty() {
+        println!("  {}", empty_message);
+    } else {
+        table.display(options)?;
+    }
+    println!();
+    Ok(())
+}
+
+/// Format seconds as h:mm:ss
+pub fn format_duration(seconds: i64) -> String {
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
+fn action_name(action: AclAction) -> &'static str {
+    match action {
+        AclAction::Allow => "allow",
+        AclAction::Deny => "deny",
+    }
+}
+
 async fn execute_profile_command(action: ProfileAction) -> Result<()> {
     match action.action {
         ProfileActionType::Start => {
@@ -291,8 +549,109 @@ async fn execute_module_command(action: ModuleAction) -> Result<()> {
     Ok(())
 }
 
+async fn execute_debug_command(action: DebugAction, api_url: &str) -> Result<()> {
+    let (path, target) = match &action {
+        DebugAction::Start(target) => ("/debug/traces", target),
+        DebugAction::Stop(target) => ("/debug/traces/stop", target),
+    };
+
+    let response = ApiClient::new(api_url).post(path, target).await?;
+
+    println!(
+        "✓ {}",
+        response["message"].as_str().unwrap_or("Trace updated")
+    );
+    if let Some(file) = response["trace"]["file"].as_str() {
+        println!("  Writing trace to {}", file);
+    }
+    Ok(())
+}
+
+async fn execute_reload_config(dry_run: bool, api_url: &str) -> Result<()> {
+    let path = if dry_run {
+        "/config/reload?dry_run=true"
+    } else {
+        "/config/reload"
+    };
+    // Validation failures are error responses that still carry a report
+    let (status, response) = ApiClient::new(api_url)
+        .post_unchecked(path, &serde_json::json!({}))
+        .await?;
+
+    let message = response["detail"]
+        .as_str()
+        .or_else(|| response["message"].as_str())
+        .unwrap_or("Reload failed");
+    if status.is_success() {
+        println!("✓ {}", message);
+    } else {
+        println!("✗ {} ({})", message, status);
+    }
+    if !response["report"].is_null() {
+        print_reload_report(&response["report"]);
+    }
+    Ok(())
+}
+
+fn print_reload_report(report: &serde_json::Value) {
+    for issue in report["issues"].as_array().into_iter().flatten() {
+        println!(
+            "  ! {} {}: {}",
+            cell(&issue["section"]),
+            cell(&issue["name"]),
+            cell(&issue["message"])
+        );
+    }
+
+    let changes = report["changes"].as_array().cloned().unwrap_or_default();
+    if changes.is_empty() {
+        println!("  No changes");
+    }
+    for change in &changes {
+        let marker = match change["kind"].as_str() {
+            Some("added") => "+",
+            Some("removed") => "-",
+            _ => "~",
+        };
+        println!(
+            "  {} {} {}",
+            marker,
+            cell(&change["section"]),
+            cell(&change["name"])
+        );
+    }
+
+    let restart: Vec<String> = report["restart_required"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(cell)
+        .collect();
+    if !restart.is_empty() {
+        println!("  Restart required to apply: {}", restart.join(", "));
+    }
+
+    let shadow = &report["shadow"];
+    let decisions = shadow["changes"].as_array().cloned().unwrap_or_default();
+    println!(
+        "  Shadow test: {} of {} recent calls would be handled differently",
+        decisions.len(),
+        cell(&shadow["samples"])
+    );
+    for decision in &decisions {
+        println!(
+            "    {} -> {} [{}]: {} => {}",
+            cell(&decision["sample"]["caller_id"]),
+            cell(&decision["sample"]["destination"]),
+            cell(&decision["decision"]),
+            cell(&decision["before"]),
+            cell(&decision["after"])
+        );
+    }
+}
+
 /// Run the interactive console
-pub async fn run_console(config_path: PathBuf) -> Result<()> {
+pub async fn run_console(config_path: PathBuf, api_url: String) -> Result<()> {
     println!("RusTalk Interactive Console");
     println!("===========================");
     println!("Type 'help' for available commands, 'exit' to quit");
@@ -328,7 +687,7 @@ pub async fn run_console(config_path: PathBuf) -> Result<()> {
                         break;
                     }
                     Ok(command) => {
-                        if let Err(e) = execute_command(command, &config_path).await {
+                        if let Err(e) = execute_command(command, &config_path, &api_url).await {
                             eprintln!("Error executing command: {}", e);
                         }
                     }
@@ -380,22 +739,68 @@ mod tests {
     fn test_parse_show_commands() {
         assert!(matches!(
             parse_command("show acls").unwrap(),
-            ConsoleCommand::Show(ShowTarget::Acls)
+            ConsoleCommand::Show(ShowTarget::Acls, _)
         ));
         assert!(matches!(
             parse_command("show profiles").unwrap(),
-            ConsoleCommand::Show(ShowTarget::Profiles)
+            ConsoleCommand::Show(ShowTarget::Profiles, _)
         ));
         assert!(matches!(
             parse_command("show status").unwrap(),
-            ConsoleCommand::Show(ShowTarget::Status)
+            ConsoleCommand::Show(ShowTarget::Status, _)
         ));
         assert!(matches!(
             parse_command("show calls").unwrap(),
-            ConsoleCommand::Show(ShowTarget::Calls)
+            ConsoleCommand::Show(ShowTarget::Calls, _)
+        ));
+        assert!(matches!(
+            parse_command("show registrations").unwrap(),
+            ConsoleCommand::Show(ShowTarget::Registrations, _)
+        ));
+        assert!(matches!(
+            parse_command("show devices").unwrap(),
+            ConsoleCommand::Show(ShowTarget::Devices, _)
+        ));
+        assert!(matches!(
+            parse_command("show channels --csv").unwrap(),
+            ConsoleCommand::Show(ShowTarget::Channels, _)
         ));
     }
 
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0:00:00");
+        assert_eq!(format_duration(3725), "1:02:05");
+    }
+
+    #[test]
+    fn test_parse_show_options() {
+        assert_eq!(
+            parse_command("show calls").unwrap(),
+            ConsoleCommand::Show(ShowTarget::Calls, OutputOptions::default())
+        );
+        assert_eq!(
+            parse_command("show acls --csv").unwrap(),
+            ConsoleCommand::Show(
+                ShowTarget::Acls,
+                OutputOptions {
+                    csv: true,
+                    paging: true
+                }
+            )
+        );
+        assert_eq!(
+            parse_command("show --no-page calls").unwrap(),
+            ConsoleCommand::Show(
+                ShowTarget::Calls,
+                OutputOptions {
+                    csv: false,
+                    paging: false
+                }
+            )
+        );
+    }
+
     #[test]
     fn test_parse_profile_commands() {
         let cmd = parse_command("profile default start").unwrap();
@@ -448,5 +853,51 @@ mod tests {
         assert!(parse_command("show").is_err());
         assert!(parse_command("profile").is_err());
         assert!(parse_command("profile default").is_err());
+        assert!(parse_command("debug call").is_err());
+        assert!(parse_command("debug ip not-an-ip").is_err());
+        assert!(parse_command("show calls --wide").is_err());
+    }
+
+    #[test]
+    fn test_parse_reloadconfig() {
+        assert_eq!(
+            parse_command("reloadconfig").unwrap(),
+            ConsoleCommand::ReloadConfig { dry_run: false }
+        );
+        assert_eq!(
+            parse_command("reloadxml").unwrap(),
+            ConsoleCommand::ReloadConfig { dry_run: false }
+        );
+        assert_eq!(
+            parse_command("reloadconfig check").unwrap(),
+            ConsoleCommand::ReloadConfig { dry_run: true }
+        );
+        assert!(parse_command("reloadconfig now").is_err());
+    }
+
+    #[test]
+    fn test_parse_debug_commands() {
+        assert_eq!(
+            parse_command("debug call abc123@host").unwrap(),
+            ConsoleCommand::Debug(DebugAction::Start(TraceTarget::CallId(
+                "abc123@host".to_string()
+            )))
+        );
+        assert_eq!(
+            parse_command("debug extension 1001").unwrap(),
+            ConsoleCommand::Debug(DebugAction::Start(TraceTarget::Extension(
+                "1001".to_string()
+            )))
+        );
+        assert_eq!(
+            parse_command("nodebug ip 192.0.2.10").unwrap(),
+            ConsoleCommand::Debug(DebugAction::Stop(TraceTarget::SourceIp(
+                "192.0.2.10".parse().unwrap()
+            )))
+        );
+        assert!(matches!(
+            parse_command("show debug").unwrap(),
+            ConsoleCommand::Show(ShowTarget::Debug, _)
+        ));
     }
 }
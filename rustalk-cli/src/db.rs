@@ -0,0 +1,72 @@
+//! Database schema commands
+
+use anyhow::{anyhow, bail, Result};
+use rustalk_core::db::{self, SchemaReport, SchemaStatus};
+use rustalk_core::prelude::Config;
+
+use crate::DbCommands;
+
+/// Handle database schema commands
+pub async fn handle_db_command(cmd: DbCommands) -> Result<()> {
+    match cmd {
+        DbCommands::Migrate { config } => {
+            let config = Config::from_file(&config).await?;
+            let database = config
+                .database
+                .as_ref()
+                .ok_or_else(|| anyhow!("No 'database' section in the configuration file"))?;
+            let database = db::connect(database).await?;
+            let applied = db::migrate(&database).await?;
+            if applied.is_empty() {
+                println!("✓ Database schema is up to date");
+            } else {
+                for version in &applied {
+                    println!("  Applied {}", version);
+                }
+                println!("✓ Applied {} migration(s)", applied.len());
+            }
+            Ok(())
+        }
+        DbCommands::Status { config, json } => {
+            let config = Config::from_file(&config).await?;
+            let database = config
+                .database
+                .as_ref()
+                .ok_or_else(|| anyhow!("No 'database' section in the configuration file"))?;
+            let database = db::connect(database).await?;
+            let report = db::status(&database).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_report(&report);
+            }
+            if let SchemaStatus::Incompatible { reason } = &report.status {
+                bail!("Incompatible database schema: {}", reason);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_report(report: &SchemaReport) {
+    let version = |v: Option<i64>| v.map_or("none".to_string(), |v| v.to_string());
+    println!("Schema version: {}", version(report.version));
+    println!("Release expects: {}", version(report.expected));
+    println!();
+    for migration in &report.migrations {
+        let mark = if migration.applied { "✓" } else { " " };
+        println!(
+            "  [{}] {} {}",
+            mark, migration.version, migration.description
+        );
+    }
+    println!();
+    match &report.status {
+        SchemaStatus::Current => println!("✓ Up to date"),
+        SchemaStatus::Pending { versions } => println!(
+            "⚠ {} migration(s) pending; run `rustalk db migrate`",
+            versions.len()
+        ),
+        SchemaStatus::Incompatible { reason } => println!("✗ {}", reason),
+    }
+}
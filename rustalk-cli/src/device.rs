@@ -0,0 +1,105 @@
+//! Device inventory commands
+
+use anyhow::{Context, Result};
+use serde_json::json;
+
+use crate::api::ApiClient;
+use crate::output::{cell, Table};
+use crate::DeviceCommands;
+
+/// Handle device inventory commands
+pub async fn handle_device_command(cmd: DeviceCommands) -> Result<()> {
+    match cmd {
+        DeviceCommands::List {
+            extension,
+            json,
+            server,
+        } => list_devices(&server, extension.as_deref(), json).await,
+        DeviceCommands::Revoke {
+            id,
+            rotate_password,
+            server,
+        } => {
+            let response = ApiClient::new(&server)
+                .post(
+                    &format!("/devices/{}/revoke", id),
+                    &json!({ "rotate_password": rotate_password }),
+                )
+                .await?;
+            println!(
+                "✓ Revoked device '{}' of extension {}",
+                id,
+                cell(&response["device"]["extension"])
+            );
+            println!(
+                "  Registrations dropped: {}",
+                cell(&response["unregistered"])
+            );
+            if let Some(password) = response["password"].as_str() {
+                println!("  New extension password: {}", password);
+                println!("  (reprovision the extension's other devices with it)");
+            }
+            Ok(())
+        }
+        DeviceCommands::Reinstate { id, server } => {
+            ApiClient::new(&server)
+                .post(&format!("/devices/{}/reinstate", id), &json!({}))
+                .await?;
+            println!("✓ Reinstated device '{}'", id);
+            Ok(())
+        }
+    }
+}
+
+async fn list_devices(server: &str, extension: Option<&str>, as_json: bool) -> Result<()> {
+    let path = match extension {
+        Some(extension) => format!("/devices?extension={}", extension),
+        None => "/devices".to_string(),
+    };
+    let response = ApiClient::new(server).get(&path).await?;
+    let devices = response["devices"]
+        .as_array()
+        .context("Unexpected device list response")?;
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(devices)?);
+        return Ok(());
+    }
+
+    let mut table = Table::new(&[
+        "ID",
+        "EXTENSION",
+        "VENDOR",
+        "MODEL",
+        "FIRMWARE",
+        "MAC",
+        "LAST IP",
+        "LAST REGISTERED",
+        "STATUS",
+    ]);
+    for device in devices {
+        let status = if device["revoked_at"].is_null() {
+            "active"
+        } else {
+            "revoked"
+        };
+        table.add_row(vec![
+            cell(&device["id"]),
+            cell(&device["extension"]),
+            cell(&device["vendor"]),
+            cell(&device["model"]),
+            cell(&device["firmware"]),
+            cell(&device["mac"]),
+            cell(&device["last_ip"]),
+            cell(&device["last_registered"]),
+            status.to_string(),
+        ]);
+    }
+
+    if table.is_empty() {
+        println!("No devices seen");
+    } else {
+        table.print();
+    }
+    Ok(())
+}
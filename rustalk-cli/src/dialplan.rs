@@ -0,0 +1,109 @@
+//! Dialplan inspection commands
+
+use anyhow::{Context, Result};
+use rustalk_core::prelude::Config;
+use rustalk_core::routing::graph::NodeKind;
+use rustalk_core::routing::{run_route_tests, RouteGraph, RouteTestReport};
+use rustalk_core::schedules::ScheduleDirectory;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::api::ApiClient;
+use crate::DialplanCommands;
+
+/// Handle dialplan commands
+pub async fn handle_dialplan_command(cmd: DialplanCommands) -> Result<()> {
+    match cmd {
+        DialplanCommands::Export {
+            format,
+            output,
+            config,
+            server,
+        } => {
+            let graph = match config {
+                Some(path) => graph_from_config(&path).await?,
+                None => {
+                    let value = ApiClient::new(&server)
+                        .get("/routes/graph?format=json")
+                        .await?;
+                    serde_json::from_value(value).context("Unexpected route graph response")?
+                }
+            };
+            let rendered = render(&graph, &format)?;
+
+            match output {
+                Some(path) => {
+                    tokio::fs::write(&path, rendered)
+                        .await
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    println!(
+                        "✓ Exported {} routes to {}",
+                        graph
+                            .nodes
+                            .iter()
+                            .filter(|n| n.kind == NodeKind::Route)
+                            .count(),
+                        path.display()
+                    );
+                }
+                None => print!("{}", rendered),
+            }
+            Ok(())
+        }
+        DialplanCommands::Test { config, server } => {
+            let report = match config {
+                Some(path) => tests_from_config(&path).await?,
+                None => {
+                    // Failing suites are error responses that still carry a report
+                    let (status, response) = ApiClient::new(&server)
+                        .post_unchecked("/routes/tests/run", &serde_json::json!({}))
+                        .await?;
+                    if response["report"].is_null() {
+                        anyhow::bail!("Route tests could not be run ({})", status);
+                    }
+                    serde_json::from_value(response["report"].clone())
+                        .context("Unexpected route test response")?
+                }
+            };
+
+            for result in &report.results {
+                match &result.message {
+                    None => println!("✓ {}", result.name),
+                    Some(message) => println!("✗ {}: {}", result.name, message),
+                }
+            }
+            println!("{} passed, {} failed", report.passed, report.failed);
+            if !report.is_success() {
+                anyhow::bail!("{} route tests failed", report.failed);
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn tests_from_config(path: &Path) -> Result<RouteTestReport> {
+    let config = Config::from_file(path)
+        .await
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+    let schedules = ScheduleDirectory::new(config.schedules.unwrap_or_default());
+    Ok(run_route_tests(
+        &config.routing.unwrap_or_default(),
+        Default::default(),
+        Arc::new(schedules),
+    ))
+}
+
+async fn graph_from_config(path: &Path) -> Result<RouteGraph> {
+    let config = Config::from_file(path)
+        .await
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+    Ok(RouteGraph::from_config(&config.routing.unwrap_or_default()))
+}
+
+fn render(graph: &RouteGraph, format: &str) -> Result<String> {
+    match format {
+        "dot" => Ok(graph.to_dot()),
+        "json" => Ok(serde_json::to_string_pretty(graph)? + "\n"),
+        other => anyhow::bail!("Unknown format '{}', expected dot or json", other),
+    }
+}
@@ -0,0 +1,263 @@
+//! Extension management commands
+
+use anyhow::{Context, Result};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde_json::{json, Value};
+
+use crate::api::ApiClient;
+use crate::output::{cell, yes_no, Table};
+use crate::ExtensionCommands;
+
+/// Handle extension management commands
+pub async fn handle_extension_command(cmd: ExtensionCommands) -> Result<()> {
+    match cmd {
+        ExtensionCommands::List { json, server } => list_extensions(&server, json).await,
+        ExtensionCommands::Add {
+            extension,
+            name,
+            password,
+            id,
+            voicemail,
+            cos,
+            require_pin,
+            priority,
+            json,
+            server,
+        } => {
+            let password_generated = password.is_none();
+            let extension = json!({
+                "id": id.unwrap_or_else(|| extension.clone()),
+                "extension": extension,
+                "display_name": name,
+                "password": password.unwrap_or_else(generate_password),
+                "enabled": true,
+                "voicemail_enabled": voicemail,
+                "priority": priority,
+                "class_of_service": cos,
+                "require_dial_pin": require_pin,
+            });
+            add_extension(&server, extension, password_generated, json).await
+        }
+        ExtensionCommands::Delete { id, server } => {
+            let response = ApiClient::new(&server)
+                .delete_any_version(&format!("/extensions/{}", id))
+                .await?;
+            println!("✓ Deleted extension '{}'", id);
+            if let Some(purge_at) = response["purge_at"].as_str() {
+                println!("  Restorable until {}", purge_at);
+            }
+            Ok(())
+        }
+        ExtensionCommands::Restore { id, server } => {
+            ApiClient::new(&server)
+                .post(&format!("/extensions/{}/restore", id), &json!({}))
+                .await?;
+            println!("✓ Restored extension '{}'", id);
+            Ok(())
+        }
+        ExtensionCommands::SetPassword {
+            id,
+            password,
+            server,
+        } => {
+            let generated = password.is_none();
+            let password = password.unwrap_or_else(generate_password);
+            let new_password = password.clone();
+            ApiClient::new(&server)
+                .update(&format!("/extensions/{}", id), |ext| {
+                    ext["password"] = json!(new_password)
+                })
+                .await?;
+            println!("✓ Updated password for extension '{}'", id);
+            if generated {
+                println!("  Password: {}", password);
+            }
+            Ok(())
+        }
+        ExtensionCommands::Enable { id, server } => set_enabled(&server, &id, true).await,
+        ExtensionCommands::Disable { id, server } => set_enabled(&server, &id, false).await,
+        ExtensionCommands::SetCos {
+            id,
+            profile,
+            clear: _,
+            server,
+        } => {
+            let value = json!(profile);
+            ApiClient::new(&server)
+                .update(&format!("/extensions/{}", id), |ext| {
+                    ext["class_of_service"] = value
+                })
+                .await?;
+            match profile {
+                Some(profile) => {
+                    println!("✓ Extension '{}' now uses COS profile '{}'", id, profile)
+                }
+                None => println!("✓ Extension '{}' now uses the default COS profile", id),
+            }
+            Ok(())
+        }
+        ExtensionCommands::SetScreening { id, mode, server } => {
+            let value = json!(mode);
+            ApiClient::new(&server)
+                .update(&format!("/extensions/{}", id), |ext| {
+                    ext["screening"] = value
+                })
+                .await?;
+            println!("✓ Extension '{}' call screening set to {}", id, mode);
+            Ok(())
+        }
+        ExtensionCommands::SetNoAnswer {
+            id,
+            action,
+            destination,
+            ring_seconds,
+            server,
+        } => {
+            let no_answer = match action.as_str() {
+                "off" => Value::Null,
+                "forward" => json!({ "action": "forward", "destination": destination }),
+                _ => json!({ "action": action }),
+            };
+            let ring_seconds = if action == "off" {
+                Value::Null
+            } else {
+                json!(ring_seconds)
+            };
+            ApiClient::new(&server)
+                .update(&format!("/extensions/{}", id), |ext| {
+                    ext["no_answer"] = no_answer;
+                    ext["ring_seconds"] = ring_seconds;
+                })
+                .await?;
+            match (action.as_str(), destination) {
+                ("off", _) => println!("✓ Extension '{}' rings until the phone gives up", id),
+                ("forward", Some(destination)) => println!(
+                    "✓ Extension '{}' forwards unanswered calls to {}",
+                    id, destination
+                ),
+                _ => println!("✓ Extension '{}' sends unanswered calls to {}", id, action),
+            }
+            Ok(())
+        }
+        ExtensionCommands::RequirePin { id, off, server } => {
+            ApiClient::new(&server)
+                .update(&format!("/extensions/{}", id), |ext| {
+                    ext["require_dial_pin"] = json!(!off)
+                })
+                .await?;
+            if off {
+                println!("✓ Extension '{}' no longer needs a dialing PIN", id);
+            } else {
+                println!(
+                    "✓ Extension '{}' now needs a dialing PIN for outbound calls",
+                    id
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn list_extensions(server: &str, as_json: bool) -> Result<()> {
+    let response = ApiClient::new(server).get("/extensions").await?;
+    let extensions = response["extensions"]
+        .as_array()
+        .context("Unexpected extension list response")?;
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(extensions)?);
+        return Ok(());
+    }
+
+    let mut table = Table::new(&[
+        "ID",
+        "EXTENSION",
+        "NAME",
+        "ENABLED",
+        "VOICEMAIL",
+        "COS",
+        "PIN",
+    ]);
+    for ext in extensions {
+        table.add_row(vec![
+            cell(&ext["id"]),
+            cell(&ext["extension"]),
+            cell(&ext["display_name"]),
+            yes_no(&ext["enabled"]),
+            yes_no(&ext["voicemail_enabled"]),
+            cell(&ext["class_of_service"]),
+            yes_no(&ext["require_dial_pin"]),
+        ]);
+    }
+
+    if table.is_empty() {
+        println!("No extensions configured");
+    } else {
+        table.print();
+    }
+    Ok(())
+}
+
+async fn add_extension(
+    server: &str,
+    extension: Value,
+    password_generated: bool,
+    as_json: bool,
+) -> Result<()> {
+    ApiClient::new(server)
+        .post("/extensions", &extension)
+        .await?;
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&extension)?);
+        return Ok(());
+    }
+
+    println!(
+        "✓ Created extension {} ({})",
+        cell(&extension["extension"]),
+        cell(&extension["display_name"])
+    );
+    if password_generated {
+        println!("  Password: {}", cell(&extension["password"]));
+    }
+    Ok(())
+}
+
+async fn set_enabled(server: &str, id: &str, enabled: bool) -> Result<()> {
+    ApiClient::new(server)
+        .update(&format!("/extensions/{}", id), |ext| {
+            ext["enabled"] = json!(enabled)
+        })
+        .await?;
+
+    println!(
+        "✓ Extension '{}' {}",
+        id,
+        if enabled { "enabled" } else { "disabled" }
+    );
+    Ok(())
+}
+
+/// Random 16 character SIP password
+fn generate_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_password() {
+        let password = generate_password();
+        assert_eq!(password.len(), 16);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(password, generate_password());
+    }
+}
@@ -1,12 +1,77 @@
 //! RusTalk CLI - Admin tool for managing RusTalk SIP servers
 
+mod acl;
+mod api;
+mod apply;
 mod cert;
 mod console;
+mod db;
+mod device;
+mod dialplan;
+mod extension;
+mod output;
+mod overrides;
+mod remote_console;
+mod server;
+mod simulate;
+mod sipp;
+mod teams;
+mod trunk;
+mod voicemail;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use rustalk_core::prelude::{Config, B2BUA};
+use clap::{Args, Parser, Subcommand};
+use console::ShowTarget;
+use output::OutputOptions;
+use remote_console::ServerCommands;
+use rustalk_cloud::call_log_store::CallLogStore;
+use rustalk_cloud::CloudApi;
+use rustalk_core::call_history::CallHistory;
+use rustalk_core::callback::CallbackQueue;
+use rustalk_core::cdr_sink::{CdrPipeline, DatabaseSink, HttpSink};
+use rustalk_core::cert_expiry::CertificateMonitor;
+use rustalk_core::config::ConfigReloader;
+use rustalk_core::console::{CommandHandler, RemoteConsole};
+use rustalk_core::cos::CosPolicy;
+use rustalk_core::crm::CrmClient;
+use rustalk_core::dialer::Dialer;
+use rustalk_core::direct_routing::ValidationTarget;
+use rustalk_core::domains::DomainMap;
+use rustalk_core::enum_resolver::EnumResolver;
+use rustalk_core::events::EventBus;
+use rustalk_core::fax::FaxService;
+use rustalk_core::lcr::LeastCostRouter;
+use rustalk_core::lnp::{LnpProvider, PortabilityDip};
+use rustalk_core::locale::PromptSet;
+use rustalk_core::media::mixer::AnnouncementMixer;
+use rustalk_core::media::webrtc::DtlsIdentity;
+use rustalk_core::media_quality::MediaQualityTracker;
+use rustalk_core::missed_calls::MissedCallNotifier;
+use rustalk_core::mwi::MwiNotifier;
+use rustalk_core::nat::NatPolicy;
+use rustalk_core::no_answer::NoAnswerPolicy;
+use rustalk_core::pdd::PddTracker;
+use rustalk_core::prelude::{Config, RouteEvaluator, B2BUA};
+use rustalk_core::presence::{BlfLists, ResourceListServer};
+use rustalk_core::quotas::Quotas;
+use rustalk_core::radius::RadiusClient;
+use rustalk_core::registrar::Registrar;
+use rustalk_core::schedules::ScheduleDirectory;
+use rustalk_core::sms::{SmppProvider, SmsGateway, SmsProviderConfig};
+use rustalk_core::snmp::{ServerHealth, SnmpAgent};
+use rustalk_core::supervisor::Supervisor;
+use rustalk_core::teams_records::TeamsCallRecords;
+use rustalk_core::transcription::TranscriptionPolicy;
+use rustalk_core::transport::{Egress, Handoff, TransportConfig};
+use rustalk_core::trunk_health::TrunkHealth;
+use rustalk_core::voicemail::{VoicemailManager, VoicemailRetrieval};
+use rustalk_core::webhooks::WebhookDispatcher;
+use rustalk_core::wholesale::WholesaleGateway;
+use rustalk_edge::TeamsGateway;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "rustalk")]
@@ -29,6 +94,24 @@ enum Commands {
         /// Configuration file path
         #[arg(short, long, default_value = "config.json")]
         config: PathBuf,
+        /// Management API URL of the running server
+        #[arg(long, default_value = "http://localhost:8080")]
+        server: String,
+        /// Remote console address (host:port) of a server on another host,
+        /// reached over TLS instead of the management API
+        #[arg(long)]
+        remote: Option<String>,
+        /// Account to sign in to the remote console as
+        #[arg(long, default_value = "admin")]
+        user: String,
+        /// PEM certificate to trust for the remote console, rather than the
+        /// public roots
+        #[arg(long)]
+        ca: Option<PathBuf>,
+        /// Print the hash of a password, for a `remote_console.users`
+        /// account, and exit
+        #[arg(long)]
+        hash_password: bool,
     },
     /// Check configuration validity
     CheckConfig {
@@ -54,9 +137,604 @@ enum Commands {
         #[arg(short, long, default_value = "http://localhost:8080")]
         server: String,
     },
+    /// Show live server state, as the console's show command
+    Show {
+        /// What to show: acls, profiles, status, calls, registrations, devices, channels, debug
+        target: String,
+        /// Output as CSV
+        #[arg(long)]
+        csv: bool,
+        /// Print everything without paging
+        #[arg(long)]
+        no_page: bool,
+        /// Configuration file path
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
     /// Certificate management commands
     #[command(subcommand)]
     Cert(CertCommands),
+    /// Access control list management commands
+    #[command(subcommand)]
+    Acl(AclCommands),
+    /// Voicemail administration commands
+    #[command(subcommand)]
+    Voicemail(VoicemailCommands),
+    /// Extension management commands
+    #[command(subcommand)]
+    Extension(ExtensionCommands),
+    /// Trunk management commands
+    #[command(subcommand)]
+    Trunk(TrunkCommands),
+    /// Device inventory commands
+    #[command(subcommand)]
+    Device(DeviceCommands),
+    /// Dialplan inspection commands
+    #[command(subcommand)]
+    Dialplan(DialplanCommands),
+    /// Microsoft Teams Direct Routing commands
+    #[command(subcommand)]
+    Teams(TeamsCommands),
+    /// Database schema commands
+    #[command(subcommand)]
+    Db(DbCommands),
+    /// Configuration overrides shared through the database
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Bring extensions, trunks, routes and ACLs to a desired-state file
+    Apply {
+        /// Desired state (JSON) with any of extensions, trunks, routes, acls
+        file: PathBuf,
+        /// Apply without asking to confirm the plan
+        #[arg(long)]
+        auto_approve: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Run a SIPp XML scenario against a server
+    Sipp {
+        /// Scenario file path
+        scenario: PathBuf,
+        /// Target server address
+        #[arg(short, long, default_value = "127.0.0.1:5060")]
+        target: String,
+        /// Number of calls to run
+        #[arg(short = 'm', long, default_value_t = 1)]
+        calls: usize,
+        /// Value substituted for [service]
+        #[arg(short, long)]
+        service: Option<String>,
+    },
+    /// Generate synthetic call load for soak testing
+    Simulate {
+        /// Target server address
+        #[arg(short, long, default_value = "127.0.0.1:5060")]
+        target: String,
+        /// New calls per second
+        #[arg(long, default_value_t = 10.0)]
+        cps: f64,
+        /// Total number of calls to place
+        #[arg(short = 'n', long, default_value_t = 100)]
+        calls: usize,
+        /// Call hold time in seconds: fixed (30), range (10-60) or exponential mean (exp:45)
+        #[arg(short, long, default_value = "10-60")]
+        duration: String,
+        /// Weighted codec mix, e.g. PCMU:70,PCMA:20,G722:10
+        #[arg(long, default_value = "PCMU:70,PCMA:30")]
+        codecs: String,
+        /// Seconds to wait for a response before counting a failure
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+        /// Server process ID for memory growth sampling
+        #[arg(long)]
+        pid: Option<u32>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AclCommands {
+    /// List ACLs and their rules
+    List {
+        #[command(flatten)]
+        source: AclSource,
+    },
+    /// Add a rule to an ACL
+    AddRule {
+        /// ACL name
+        acl: String,
+        /// IP address or CIDR range
+        cidr: String,
+        /// Action: allow or deny
+        #[arg(short, long, default_value = "allow")]
+        action: String,
+        /// Rule name (defaults to the CIDR)
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Priority (lower number = evaluated first)
+        #[arg(short, long, default_value_t = 100)]
+        priority: u32,
+        #[command(flatten)]
+        source: AclSource,
+    },
+    /// Remove rules from an ACL by name or CIDR
+    RemoveRule {
+        /// ACL name
+        acl: String,
+        /// Rule name or CIDR
+        rule: String,
+        #[command(flatten)]
+        source: AclSource,
+    },
+    /// Check whether an IP is allowed by an ACL and which rule decides
+    Check {
+        /// ACL name
+        acl: String,
+        /// IP address to check
+        ip: IpAddr,
+        #[command(flatten)]
+        source: AclSource,
+    },
+}
+
+#[derive(Subcommand)]
+enum VoicemailCommands {
+    /// List mailboxes
+    List {
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Create a mailbox
+    Create {
+        /// Mailbox ID
+        mailbox: String,
+        /// Extension number (defaults to the mailbox ID)
+        #[arg(short, long)]
+        extension: Option<String>,
+        /// Owner's name
+        #[arg(short, long)]
+        name: String,
+        /// Access PIN (random 6 digits if not given)
+        #[arg(short, long)]
+        pin: Option<String>,
+        /// Email for new message notifications
+        #[arg(long)]
+        email: Option<String>,
+        /// Maximum number of stored messages
+        #[arg(long)]
+        max_messages: Option<usize>,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Delete a mailbox and all of its messages
+    Delete {
+        /// Mailbox ID
+        mailbox: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Reset a mailbox PIN
+    ResetPin {
+        /// Mailbox ID
+        mailbox: String,
+        /// New PIN (random 6 digits if not given)
+        #[arg(short, long)]
+        pin: Option<String>,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// List messages in a mailbox
+    Messages {
+        /// Mailbox ID
+        mailbox: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExtensionCommands {
+    /// List extensions
+    List {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Add an extension
+    Add {
+        /// Extension number
+        extension: String,
+        /// Display name
+        #[arg(short, long)]
+        name: String,
+        /// SIP password (random if not given)
+        #[arg(short, long)]
+        password: Option<String>,
+        /// Extension ID (defaults to the extension number)
+        #[arg(long)]
+        id: Option<String>,
+        /// Enable voicemail
+        #[arg(long)]
+        voicemail: bool,
+        /// Class of service profile (e.g. internal_only, national)
+        #[arg(long)]
+        cos: Option<String>,
+        /// Require a dialing PIN for outbound calls (shared phones)
+        #[arg(long)]
+        require_pin: bool,
+        /// Priority for ordering
+        #[arg(long, default_value_t = 0)]
+        priority: u32,
+        /// Print the created extension as JSON
+        #[arg(long)]
+        json: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Delete an extension; it can be restored until the trash is purged
+    Delete {
+        /// Extension ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Restore a deleted extension
+    Restore {
+        /// Extension ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Set an extension's SIP password
+    SetPassword {
+        /// Extension ID
+        id: String,
+        /// New password (random if not given)
+        #[arg(short, long)]
+        password: Option<String>,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Enable an extension
+    Enable {
+        /// Extension ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Disable an extension
+    Disable {
+        /// Extension ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Assign a class of service profile, or clear it with --clear
+    SetCos {
+        /// Extension ID
+        id: String,
+        /// Profile name
+        #[arg(required_unless_present = "clear")]
+        profile: Option<String>,
+        /// Fall back to the default profile
+        #[arg(long, conflicts_with = "profile")]
+        clear: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Set call screening: off, announce (name then connect) or screen
+    /// (name then 1 to accept, 2 for voicemail)
+    SetScreening {
+        /// Extension ID
+        id: String,
+        /// Screening mode
+        #[arg(value_parser = ["off", "announce", "screen"])]
+        mode: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Set how long calls ring and what happens when nobody answers:
+    /// voicemail, forward (with --destination) or busy; off leaves it to
+    /// the phones
+    SetNoAnswer {
+        /// Extension ID
+        id: String,
+        /// No-answer action
+        #[arg(value_parser = ["voicemail", "forward", "busy", "off"])]
+        action: String,
+        /// Number or extension to forward to
+        #[arg(short, long, required_if_eq("action", "forward"))]
+        destination: Option<String>,
+        /// Seconds to ring (server default if not given)
+        #[arg(short, long)]
+        ring_seconds: Option<u32>,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Require a dialing PIN for outbound calls, or stop with --off
+    RequirePin {
+        /// Extension ID
+        id: String,
+        /// No longer require a PIN
+        #[arg(long)]
+        off: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DialplanCommands {
+    /// Export the route tree with its conditions and destinations as a graph
+    Export {
+        /// Output format: dot or json
+        #[arg(short, long, default_value = "dot")]
+        format: String,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Read routes from this configuration file instead of the server
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Run the stored route test cases, failing if any outcome changed
+    Test {
+        /// Read routes and tests from this configuration file instead of the
+        /// server; caller group conditions never match without the server
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeviceCommands {
+    /// List devices seen registering
+    List {
+        /// Only devices of this extension
+        #[arg(short, long)]
+        extension: Option<String>,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Revoke a lost or stolen device so it can no longer register
+    Revoke {
+        /// Device ID
+        id: String,
+        /// Also give the device's extension a new SIP password
+        #[arg(long)]
+        rotate_password: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Let a revoked device register again
+    Reinstate {
+        /// Device ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TeamsCommands {
+    /// Run Microsoft's SBC validation steps against the Teams SIP proxy
+    Validate {
+        /// Configuration file path
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+        /// Number of a Teams user to ring with a test INVITE
+        #[arg(long)]
+        test_number: Option<String>,
+        /// Calling number of the test INVITE
+        #[arg(long)]
+        caller: Option<String>,
+        /// Teams SIP proxy to test against
+        #[arg(long)]
+        proxy: Option<String>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Show a setting's value with the overrides applied, or list the
+    /// overrides without a key
+    Get {
+        /// Dotted setting path, e.g. sip.domain
+        key: Option<String>,
+        /// Configuration file path
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+        /// Print JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Override a setting for every node sharing the database
+    Set {
+        /// Dotted setting path, e.g. sip.domain
+        key: String,
+        /// Value, as JSON or a plain string
+        value: String,
+        /// Configuration file path
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+    },
+    /// Remove a setting's override, so the file's value applies again
+    Unset {
+        /// Dotted setting path
+        key: String,
+        /// Configuration file path
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+    },
+    /// Export the JSON Schema of the configuration file
+    Schema {
+        /// Export the schema of the Cloud API's models instead
+        #[arg(long)]
+        models: bool,
+        /// Output file path; standard output when unset
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// Apply the migrations the database is missing
+    Migrate {
+        /// Configuration file path
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+    },
+    /// Show the schema version and each migration's state
+    Status {
+        /// Configuration file path
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrunkCommands {
+    /// List trunks
+    List {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Add a trunk
+    Add {
+        /// Trunk name
+        name: String,
+        /// Carrier host or IP address
+        #[arg(long)]
+        host: String,
+        /// Carrier SIP port
+        #[arg(long, default_value_t = 5060)]
+        port: u16,
+        /// Registration/authentication username
+        #[arg(short, long)]
+        username: Option<String>,
+        /// Registration/authentication password
+        #[arg(short, long)]
+        password: Option<String>,
+        /// Description
+        #[arg(short, long)]
+        description: Option<String>,
+        /// Trunk ID (defaults to the name)
+        #[arg(long)]
+        id: Option<String>,
+        /// Priority for ordering
+        #[arg(long, default_value_t = 0)]
+        priority: u32,
+        /// SIP compatibility profile (e.g. no-prack, user-phone)
+        #[arg(long)]
+        quirks: Option<String>,
+        /// Print the created trunk as JSON
+        #[arg(long)]
+        json: bool,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Delete a trunk; it can be restored until the trash is purged
+    Delete {
+        /// Trunk ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Restore a deleted trunk
+    Restore {
+        /// Trunk ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Set a trunk's authentication credentials
+    SetPassword {
+        /// Trunk ID
+        id: String,
+        /// New password
+        #[arg(short, long)]
+        password: String,
+        /// New username
+        #[arg(short, long)]
+        username: Option<String>,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Enable a trunk
+    Enable {
+        /// Trunk ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+    /// Disable a trunk
+    Disable {
+        /// Trunk ID
+        id: String,
+        /// Server address
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        server: String,
+    },
+}
+
+/// Where ACL commands read and write ACLs
+#[derive(Args)]
+struct AclSource {
+    /// Server address
+    #[arg(short, long, default_value = "http://localhost:8080")]
+    server: String,
+    /// Edit this configuration file instead of the running server
+    #[arg(short, long)]
+    config: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -107,20 +785,32 @@ enum CertCommands {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
-
     let cli = Cli::parse();
 
+    // The server configures its own log sinks once its config is loaded
+    if !matches!(cli.command, Commands::Start { .. }) {
+        tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+            .init();
+    }
+
     match cli.command {
         Commands::Start { config } => {
             println!("Starting RusTalk server with config: {}", config.display());
             start_server(config).await?;
         }
-        Commands::Console { config } => {
-            console::run_console(config).await?;
-        }
+        Commands::Console {
+            config,
+            server,
+            remote,
+            user,
+            ca,
+            hash_password,
+        } => match remote {
+            _ if hash_password => remote_console::print_password_hash()?,
+            Some(address) => remote_console::run_remote_console(address, user, ca).await?,
+            None => console::run_console(config, server).await?,
+        },
         Commands::CheckConfig { config } => {
             println!("Checking configuration: {}", config.display());
             check_config(config).await?;
@@ -137,16 +827,104 @@ async fn main() -> Result<()> {
             println!("Listing calls from: {}", server);
             list_calls(&server).await?;
         }
+        Commands::Show {
+            target,
+            csv,
+            no_page,
+            config,
+            server,
+        } => {
+            let options = OutputOptions {
+                csv,
+                paging: !no_page,
+            };
+            console::execute_show_command(ShowTarget::parse(&target)?, options, &config, &server)
+                .await?;
+        }
         Commands::Cert(cert_cmd) => {
             cert::handle_cert_command(cert_cmd).await?;
         }
+        Commands::Acl(acl_cmd) => {
+            acl::handle_acl_command(acl_cmd).await?;
+        }
+        Commands::Voicemail(voicemail_cmd) => {
+            voicemail::handle_voicemail_command(voicemail_cmd).await?;
+        }
+        Commands::Extension(extension_cmd) => {
+            extension::handle_extension_command(extension_cmd).await?;
+        }
+        Commands::Trunk(trunk_cmd) => {
+            trunk::handle_trunk_command(trunk_cmd).await?;
+        }
+        Commands::Device(device_cmd) => {
+            device::handle_device_command(device_cmd).await?;
+        }
+        Commands::Teams(teams_cmd) => {
+            teams::handle_teams_command(teams_cmd).await?;
+        }
+        Commands::Db(db_cmd) => {
+            db::handle_db_command(db_cmd).await?;
+        }
+        Commands::Config(config_cmd) => {
+            overrides::handle_config_command(config_cmd).await?;
+        }
+        Commands::Apply {
+            file,
+            auto_approve,
+            server,
+        } => {
+            apply::apply(&file, auto_approve, &server).await?;
+        }
+        Commands::Dialplan(dialplan_cmd) => {
+            dialplan::handle_dialplan_command(dialplan_cmd).await?;
+        }
+        Commands::Sipp {
+            scenario,
+            target,
+            calls,
+            service,
+        } => {
+            sipp::run_scenario(&scenario, &target, calls, service).await?;
+        }
+        Commands::Simulate {
+            target,
+            cps,
+            calls,
+            duration,
+            codecs,
+            timeout,
+            pid,
+        } => {
+            let target = rustalk_core::io::dns::lookup(&target)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Could not resolve {}", target))?;
+            let config = simulate::SimulationConfig {
+                target,
+                cps,
+                calls,
+                hold: duration.parse()?,
+                codecs: codecs.parse()?,
+                response_timeout: std::time::Duration::from_secs(timeout),
+                server_pid: pid,
+            };
+
+            println!(
+                "Simulating {} calls at {} CPS against {}",
+                calls, cps, target
+            );
+            let report = simulate::run_simulation(config).await?;
+            simulate::print_report(&report);
+        }
     }
 
     Ok(())
 }
 
 async fn start_server(config_path: PathBuf) -> Result<()> {
-    let config = Config::from_file(&config_path).await?;
+    let config = Config::from_file_with_db_overlay(&config_path).await?;
+    rustalk_core::logging::init(&config.server.logging)?;
 
     println!("Server configuration:");
     println!(
@@ -154,14 +932,623 @@ async fn start_server(config_path: PathBuf) -> Result<()> {
         config.server.bind_address, config.server.bind_port
     );
     println!("  SIP domain: {}", config.sip.domain);
+    // The schema must match this release before anything uses it
+    let mut db_pool = None;
+    if let Some(database) = &config.database {
+        let pool = rustalk_core::db::connect(database).await?;
+        let report = rustalk_core::db::ensure_compatible(&pool, database).await?;
+        println!(
+            "  Database schema: version {}",
+            report.version.unwrap_or_default()
+        );
+        db_pool = Some(pool);
+    }
+    let components = config.components.clone().unwrap_or_default();
 
-    let _b2bua = B2BUA::new();
+    let radius = config
+        .radius
+        .clone()
+        .map(|radius| Arc::new(RadiusClient::new(radius)));
+    let registrar_config = config.registrar.clone().unwrap_or_default();
+    let nat = NatPolicy::new(config.nat.clone().unwrap_or_default());
+    // Shared with the B2BUA and the API so profiles edited there serve
+    // their domains at once
+    let domains = DomainMap::new(config.domains.clone().unwrap_or_default());
+    if !domains.domains().is_empty() {
+        println!("  SIP domains: {}", domains.domains().join(", "));
+    }
+    // Calls, registrations and alarms are always published, for the API's
+    // event stream and whatever else subscribes below
+    let events = EventBus::new();
+    let mut registrar = Registrar::new()
+        .with_max_expires(registrar_config.max_expires)
+        .with_nat_policy(nat.clone())
+        .with_domains(domains.clone())
+        .with_event_bus(events.clone());
+    if !registrar_config.credentials.is_empty() {
+        println!(
+            "  Registration authentication: {} credential(s)",
+            registrar_config.credentials.len()
+        );
+        registrar = registrar.with_credentials(&registrar_config.credentials, &config.sip.domain);
+    } else if let Some(client) = radius.as_ref().filter(|c| c.config().auth_server.is_some()) {
+        println!(
+            "  RADIUS authentication: {}",
+            client.config().auth_server.as_deref().unwrap_or_default()
+        );
+        registrar = registrar.with_radius_auth(client.clone(), &config.sip.domain);
+    }
+    let mut b2bua = B2BUA::new()
+        .with_domains(domains.clone())
+        .with_nat_policy(nat.clone());
+    if components.registrar {
+        b2bua = b2bua.with_registrar(registrar.clone());
+    }
+    // Calls to registered extensions ring every contact at once, and their
+    // ringing and answer are relayed back to the caller
+    let (outbound_tx, outbound_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (relay_tx, relay_rx) = tokio::sync::mpsc::unbounded_channel();
+    let via = server::via_address(&config)?;
+    if let Some(via) = via {
+        b2bua = b2bua
+            .with_outbound_sink(outbound_tx.clone(), via)
+            .with_relay_sink(relay_tx);
+    }
+    let outbound: server::OutboundQueue = Arc::new(tokio::sync::Mutex::new(outbound_rx));
+    let relayed: server::RelayQueue = Arc::new(tokio::sync::Mutex::new(relay_rx));
+    // Trunks are sent OPTIONS, and routes to those that are down passed over
+    let trunk_health = TrunkHealth::new(config.trunk_health.clone().unwrap_or_default())
+        .with_event_bus(events.clone());
+    if !trunk_health.config().trunks.is_empty() {
+        println!(
+            "  Trunk health: OPTIONS to {} trunk(s) every {}s",
+            trunk_health.config().trunks.len(),
+            trunk_health.config().interval_seconds
+        );
+        trunk_health.spawn();
+    }
+    // Least-cost routes choose between their trunks by these rate cards
+    let least_cost = LeastCostRouter::new(config.lcr.clone().unwrap_or_default());
+    if !least_cost.config().rates.is_empty() {
+        println!(
+            "  Least-cost routing: {} rate card(s)",
+            least_cost.config().rates.len()
+        );
+    }
+    if let Some(routing) = config.routing.clone().filter(|_| components.routing) {
+        println!("  Routing: {} route(s)", routing.routes.len());
+        if routing.uses_enum() || config.enum_routing.is_some() {
+            let enum_config = config.enum_routing.clone().unwrap_or_default();
+            println!("  ENUM routing: under {}", enum_config.domain);
+            b2bua = b2bua.with_enum(EnumResolver::new(enum_config));
+        }
+        b2bua = b2bua.with_routing(Arc::new(
+            RouteEvaluator::new(routing)
+                .with_trunk_health(trunk_health.clone())
+                .with_least_cost(least_cost.clone()),
+        ));
+        if let Some(transcription) = config.transcription.clone() {
+            println!("  Transcription: streaming to {}", transcription.url);
+            b2bua = b2bua.with_transcription(Arc::new(TranscriptionPolicy::new(transcription)));
+        }
+    }
+    // Announcements played over answered calls through the API
+    b2bua = b2bua.with_announcements(AnnouncementMixer::new());
+    // Browsers calling over WebSocket have their DTLS-SRTP terminated and
+    // their media relayed to callees that speak plain RTP
+    if config
+        .transport
+        .protocols
+        .iter()
+        .any(|p| p.eq_ignore_ascii_case("ws") || p.eq_ignore_ascii_case("wss"))
+    {
+        let identity = DtlsIdentity::generate()?;
+        println!(
+            "  WebRTC media: DTLS fingerprint {}",
+            identity.fingerprint()
+        );
+        b2bua = b2bua.with_webrtc_media(identity);
+    }
+    if let Some(cos) = config.cos.clone() {
+        b2bua = b2bua.with_class_of_service(Arc::new(CosPolicy::new(cos)?));
+    }
+    if let Some(pins) = config.dial_pins.clone() {
+        b2bua = b2bua.with_dial_pins(Arc::new(pins));
+    }
+    if let Some(callbacks) = config.callbacks.clone() {
+        b2bua = b2bua.with_callbacks(CallbackQueue::new(callbacks));
+    }
+    if let Some(screening) = config.screening.clone() {
+        b2bua = b2bua.with_screening(Arc::new(screening));
+    }
+    if let Some(drop) = config.voicemail_drop.clone() {
+        b2bua = b2bua.with_voicemail_drop(drop);
+    }
+    // Shared with the API so ring times set on extensions apply to new calls
+    let no_answer = NoAnswerPolicy::new(config.no_answer.clone().unwrap_or_default());
+    b2bua = b2bua.with_no_answer(no_answer.clone());
+    // Shared with the API, which enforces them on provisioning and reports
+    // usage against them
+    let quotas = Quotas::new(config.quotas.clone().unwrap_or_default());
+    if !quotas.tenants().is_empty() {
+        println!("  Tenant quotas: {}", quotas.tenants().join(", "));
+    }
+    b2bua = b2bua.with_quotas(quotas.clone());
+    // Shared with the API so mailboxes created there can be dialed into
+    let voicemail = VoicemailManager::new("/var/lib/rustalk/voicemail").with_quotas(quotas.clone());
+    // Shared with the API so recordings uploaded there are played at once
+    let prompts = config.locales.clone().map(PromptSet::new);
+    if let Some(prompts) = &prompts {
+        match prompts.load().await {
+            Ok(recorded) => println!(
+                "  Prompts: {} by default, {} recording(s)",
+                prompts.config().default_locale,
+                recorded
+            ),
+            Err(e) => eprintln!("Failed to load recorded prompts: {}", e),
+        }
+    }
+    if let Some(retrieval) = config.voicemail_retrieval.clone() {
+        println!("  Voicemail retrieval: {}", retrieval.feature_code);
+        let mut retrieval = VoicemailRetrieval::new(retrieval, voicemail.clone());
+        if let Some(prompts) = prompts.clone() {
+            retrieval = retrieval.with_prompts(prompts);
+        }
+        b2bua = b2bua.with_voicemail_retrieval(retrieval);
+    }
+    // Message waiting lamps follow the mailboxes, BLF keys the calls
+    let presence = config.presence.clone().unwrap_or_default();
+    let blf_lists = BlfLists::new(presence.blf_lists.clone(), config.sip.domain.clone());
+    if let Some(via) = via {
+        let mut mwi = MwiNotifier::new(
+            config.mwi.clone().unwrap_or_default(),
+            voicemail.clone(),
+            outbound_tx.clone(),
+            via,
+        );
+        if components.registrar {
+            mwi = mwi.with_registrar(registrar.clone());
+        }
+        mwi.spawn();
+        b2bua = b2bua.with_mwi(mwi);
+
+        if !presence.blf_lists.is_empty() {
+            println!("  BLF lists: {}", presence.blf_lists.len());
+        }
+        let server = ResourceListServer::new(presence, blf_lists.clone(), outbound_tx, via);
+        server.spawn(&events);
+        b2bua = b2bua.with_presence(server);
+    }
+    if let Some(codes) = config.account_codes.clone() {
+        b2bua = b2bua.with_account_codes(Arc::new(codes));
+    }
+    if let Some(dial_strings) = config.dial_strings.clone() {
+        b2bua = b2bua.with_dial_strings(Arc::new(dial_strings));
+    }
+    if let Some(quirks) = config.quirks.clone() {
+        b2bua = b2bua.with_quirks(Arc::new(quirks));
+    }
+    if let Some(wholesale) = config.wholesale.clone() {
+        println!("  Wholesale peers: {}", wholesale.peers.len());
+        b2bua = b2bua.with_wholesale(Arc::new(WholesaleGateway::new(wholesale)?));
+    }
+    let mut sms_gateway = None;
+    if let Some(sms) = config.sms.clone() {
+        let gateway = SmsGateway::new(sms.clone())?;
+        println!("  SMS gateway: {}", gateway.provider_name());
+        // SMPP delivers inbound messages over its own receiver bind
+        if let SmsProviderConfig::Smpp(smpp) = sms.provider {
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                if let Err(e) = SmppProvider::new(smpp).receive(tx).await {
+                    eprintln!("SMPP receiver stopped: {}", e);
+                }
+            });
+            let inbound = gateway.clone();
+            tokio::spawn(async move {
+                while let Some(message) = rx.recv().await {
+                    if let Err(e) = inbound.receive(&message.from, &message.to, &message.text, None)
+                    {
+                        eprintln!("Dropping inbound SMS: {}", e);
+                    }
+                }
+            });
+        }
+        b2bua = b2bua.with_sms_gateway(gateway.clone());
+        sms_gateway = Some(gateway);
+    }
+    let mut teams_records = None;
+    if let Some(call_records) = config.teams.as_ref().and_then(|t| t.call_records.clone()) {
+        println!(
+            "  Teams call records: every {}s",
+            call_records.interval_seconds
+        );
+        let records = TeamsCallRecords::new(call_records.retention_days);
+        records.spawn_sync(call_records);
+        teams_records = Some(records);
+    }
+    let call_history = CallHistory::new(config.call_history.clone().unwrap_or_default());
+    println!(
+        "  Call history: last {} calls",
+        call_history.config().max_records
+    );
+    let cdr_config = config.cdr.clone().unwrap_or_default();
+    let mut cdr_pipeline = CdrPipeline::new(&cdr_config);
+    if let (true, Some(pool)) = (cdr_config.database, db_pool.clone()) {
+        cdr_pipeline = cdr_pipeline.with_sink(Arc::new(DatabaseSink::new(pool)));
+    }
+    if let Some(http) = cdr_config.http.clone() {
+        cdr_pipeline = cdr_pipeline.with_sink(Arc::new(HttpSink::new(http)));
+    }
+    let cdr_sinks = (!cdr_pipeline.is_empty()).then(|| {
+        println!(
+            "  CDR sinks: {}",
+            cdr_pipeline.sinks().collect::<Vec<_>>().join(", ")
+        );
+        cdr_pipeline.spawn()
+    });
+    // With a database the API reads the `call_logs` table the database sink
+    // writes, so records only need storing here when that sink is off
+    let call_logs = match db_pool {
+        Some(pool) => {
+            println!("  Call logs: database");
+            CallLogStore::database(pool)
+        }
+        None => CallLogStore::default(),
+    };
+    {
+        let history = call_history.clone();
+        let logs = (!(call_logs.is_persistent() && cdr_config.database)).then(|| call_logs.clone());
+        // Keep the SBC side of each Teams call for correlation
+        let teams_cdrs = teams_records.clone();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(cdr) = rx.recv().await {
+                history.record(&cdr).await;
+                if let Some(logs) = &logs {
+                    if let Err(e) = logs.ingest(&cdr).await {
+                        eprintln!("Failed to keep call log {}: {:#}", cdr.call_id, e);
+                    }
+                }
+                if let Some(records) = &teams_cdrs {
+                    records.record_cdr(&cdr).await;
+                }
+                if let Some(sinks) = &cdr_sinks {
+                    let _ = sinks.send(cdr);
+                }
+            }
+        });
+        b2bua = b2bua.with_cdr_sink(tx);
+    }
+    let webhooks = config.webhooks.clone().map(WebhookDispatcher::new);
+    let radius_accounting = radius.filter(|c| c.config().accounting_server.is_some());
+    if let Some(dispatcher) = &webhooks {
+        println!("  Webhooks: {} endpoint(s)", dispatcher.endpoints().len());
+        dispatcher.clone().spawn(&events);
+    }
+    if let Some(client) = radius_accounting {
+        println!(
+            "  RADIUS accounting: {}",
+            client
+                .config()
+                .accounting_server
+                .as_deref()
+                .unwrap_or_default()
+        );
+        client.spawn_accounting(&events);
+    }
+    if let Some(missed) = config.missed_calls.clone() {
+        println!(
+            "  Missed call emails: {} extension(s)",
+            missed
+                .extensions
+                .values()
+                .filter(|profile| profile.email.is_some())
+                .count()
+        );
+        b2bua = b2bua.with_missed_calls(Arc::new(missed.clone()));
+        MissedCallNotifier::new(missed)?.spawn(&events);
+    }
+    b2bua = b2bua.with_event_bus(events.clone());
+    // Certificate expiry is always watched, with the default thresholds
+    // unless configured
+    let expiry = config.cert_expiry.clone().unwrap_or_default();
+    println!(
+        "  Certificate expiry warnings: {:?} days",
+        expiry.thresholds_days
+    );
+    let certificate_monitor = CertificateMonitor::new(expiry)
+        .with_event_bus(events.clone())
+        .watching(&config)?;
+    certificate_monitor.spawn(webhooks.clone());
+    // Post-dial delay is always tracked, alerting past the default
+    // threshold unless configured
+    let pdd =
+        PddTracker::new(config.pdd.clone().unwrap_or_default()).with_event_bus(events.clone());
+    println!(
+        "  Post-dial delay alerts: p95 over {} ms",
+        pdd.config().alert_threshold_ms
+    );
+    pdd.spawn(webhooks.clone());
+    b2bua = b2bua.with_pdd_tracker(pdd.clone());
+    // So is media quality, from the RTP statistics on BYEs
+    let media_quality = MediaQualityTracker::new(config.media_quality.clone().unwrap_or_default())
+        .with_event_bus(events.clone());
+    println!(
+        "  Media quality alerts: loss over {}% or jitter over {} ms",
+        media_quality.config().max_loss_percent,
+        media_quality.config().max_jitter_ms
+    );
+    media_quality.spawn(webhooks.clone());
+    b2bua = b2bua.with_media_quality(media_quality.clone());
+    if let Some(crm) = config.crm.clone() {
+        println!("  CRM screen-pops: {}", crm.url);
+        b2bua = b2bua.with_crm(Arc::new(CrmClient::new(crm)));
+    }
+    if let Some(lnp) = config.lnp.clone() {
+        match &lnp.provider {
+            LnpProvider::Http { url, .. } => println!("  Portability dips: {}", url),
+            LnpProvider::Enum { domain } => println!("  Portability dips: ENUM under {}", domain),
+        }
+        b2bua = b2bua.with_portability(PortabilityDip::new(lnp));
+    }
+    if let Some(snmp) = config.snmp.clone() {
+        println!("  SNMP agent: {}", snmp.bind);
+        let mut health = ServerHealth::new(b2bua.clone()).with_registrar(registrar.clone());
+        if let Some(admission) = config.admission.clone() {
+            health = health.with_admission(admission);
+        }
+        if let Some(acme) = &config.acme {
+            health = health.with_certificates(acme.cert_dir.clone())?;
+        }
+        Arc::new(SnmpAgent::new(snmp, Arc::new(health))?).spawn();
+    }
+
+    // Each component restarts with backoff if it fails
+    let supervisor = Supervisor::new(config.supervisor.clone().unwrap_or_default())
+        .with_event_bus(events.clone());
+    let upgrade = config.upgrade.clone().unwrap_or_default();
+    let reuse_port = upgrade.reuse_port;
+    // Both processes of a rolling upgrade receive on the shared UDP ports,
+    // so each hands the other the messages of calls it holds
+    let handoff = match (components.sip && reuse_port, &upgrade.pid_file) {
+        (true, Some(_)) => Some(Handoff::bind().await?),
+        _ => None,
+    };
+    if components.sip {
+        // Requests the B2BUA originates leave from the interface facing
+        // their destination
+        let egress = Egress::new(config.egress_selector()).with_domains(domains.clone());
+        if !config.transport.interfaces.is_empty() {
+            println!(
+                "  Egress interfaces: {}",
+                config
+                    .transport
+                    .interfaces
+                    .iter()
+                    .map(|i| i.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        for listener in server::sip_listeners(&config)? {
+            println!(
+                "  SIP listening: {} {}",
+                listener.protocol, listener.bind_addr
+            );
+            let name = format!("sip-{}-{}", listener.protocol, listener.bind_addr);
+            let listener = TransportConfig {
+                reuse_port,
+                ..listener
+            };
+            // A protocol that cannot listen stops startup; restarts bind
+            // it afresh
+            let bound = std::sync::Mutex::new(Some(server::bind_sip_listener(&listener).await?));
+            let b2bua = b2bua.clone();
+            let egress = egress.clone();
+            let handoff = handoff.clone();
+            supervisor
+                .spawn(name, move || {
+                    server::run_sip_listener(
+                        listener.clone(),
+                        bound.lock().unwrap().take(),
+                        b2bua.clone(),
+                        egress.clone(),
+                        handoff.clone(),
+                    )
+                })
+                .await;
+        }
+        if let Some(handoff) = handoff.clone() {
+            let b2bua = b2bua.clone();
+            let egress = egress.clone();
+            supervisor
+                .spawn("sip-handoff", move || {
+                    server::run_handoff(handoff.clone(), b2bua.clone(), egress.clone())
+                })
+                .await;
+        }
+        supervisor
+            .spawn("sip-outbound", move || {
+                server::run_outbound(outbound.clone(), relayed.clone(), egress.clone())
+            })
+            .await;
+    }
+    {
+        let b2bua = b2bua.clone();
+        supervisor
+            .spawn("ring-timeouts", move || {
+                server::run_ring_timeouts(b2bua.clone())
+            })
+            .await;
+    }
+    if components.registrar {
+        let registrar = registrar.clone();
+        supervisor
+            .spawn("registration-expiry", move || {
+                server::run_registration_expiry(registrar.clone())
+            })
+            .await;
+    }
+    let acme = config.acme.clone().filter(|a| a.enabled);
+    let acme_client = acme.as_ref().map(server::acme_client).transpose()?;
+    if let (Some(acme), Some(client)) = (acme, &acme_client) {
+        if components.acme_renewal {
+            println!("  ACME renewal: {}", acme.domains.join(", "));
+            let client = client.clone();
+            supervisor
+                .spawn("acme-renewal", move || {
+                    server::run_acme_renewal(client.clone(), acme.clone())
+                })
+                .await;
+        }
+    }
+    if let Some(console) = config.remote_console.clone() {
+        println!("  Remote console: {}", console.bind);
+        let handler: Arc<dyn CommandHandler> =
+            Arc::new(ServerCommands::new(b2bua.clone(), registrar.clone()));
+        supervisor
+            .spawn("remote-console", move || {
+                let (console, handler) = (console.clone(), handler.clone());
+                async move { RemoteConsole::new(console, handler).await?.run().await }
+            })
+            .await;
+    }
+    if components.api {
+        let addr = components.api_bind.parse().map_err(|e| {
+            anyhow::anyhow!("Invalid components.api_bind {}: {}", components.api_bind, e)
+        })?;
+        let mut api = CloudApi::new(addr)
+            .with_registrar(registrar)
+            .with_b2bua(b2bua.clone())
+            .with_config_reloader(ConfigReloader::new(&config_path, config.clone()))
+            .with_certificate_monitor(certificate_monitor)
+            .with_class_of_service(config.cos.clone().unwrap_or_default())
+            .with_schedules(config.schedules.clone().unwrap_or_default())
+            .with_ivr_flows(config.ivr.clone().unwrap_or_default().flows)
+            .with_supervisor(supervisor.clone())
+            .with_voicemail_manager(voicemail.clone())
+            .with_no_answer_policy(no_answer)
+            .with_reuse_port(reuse_port);
+        api = api.with_nat_policy(nat);
+        api = api.with_domains(domains);
+        api = api.with_blf_lists(blf_lists);
+        if let Some(codecs) = config.codecs.clone() {
+            api = api.with_codec_config(codecs);
+        }
+        if let Some(acls) = config.acls.clone() {
+            api = api.with_acls(acls);
+        }
+        if let Some(routing) = &config.routing {
+            api = api.with_route_tests(routing.tests.clone());
+        }
+        if let Some(client) = acme_client {
+            api = api.with_acme_client(client);
+        }
+        if let Some(gateway) = sms_gateway {
+            api = api.with_sms_gateway(gateway);
+        }
+        api = api.with_call_history(call_history);
+        api = api.with_call_logs(call_logs);
+        api = api.with_pdd_tracker(pdd);
+        api = api.with_media_quality(media_quality);
+        api = api.with_quotas(quotas);
+        api = api.with_trunk_health(trunk_health.clone());
+        api = api.with_least_cost(least_cost);
+        if let Some(records) = teams_records {
+            api = api.with_teams_call_records(records);
+        }
+        if let Some(teams) = config.teams.as_ref().filter(|t| t.enabled) {
+            api = api.with_teams_validation(ValidationTarget::from_teams(teams));
+        }
+        api = api.with_event_bus(events);
+        if let Some(dispatcher) = webhooks {
+            api = api.with_webhooks(dispatcher);
+        }
+        if let Some(fax) = config.fax.clone() {
+            api = api.with_fax_service(FaxService::new(fax)?);
+        }
+        if let Some(dialer) = config.dialer.clone() {
+            println!("  Dialer: {} campaign(s)", dialer.campaigns.len());
+            let schedules = ScheduleDirectory::new(config.schedules.clone().unwrap_or_default());
+            api = api.with_dialer(Dialer::new(dialer).with_schedules(schedules));
+        }
+        if let Some(prompts) = prompts {
+            api = api.with_prompts(prompts);
+        }
+        if let Some(path) = components.webui_path.clone() {
+            api = api.with_webui_path(path);
+        }
+        println!("  API: http://{}", addr);
+        let api = Arc::new(api);
+        supervisor
+            .spawn("api", move || {
+                let api = api.clone();
+                async move { api.start().await }
+            })
+            .await;
+    }
+    if let Some(teams) = config
+        .teams
+        .as_ref()
+        .filter(|t| t.enabled && components.edge)
+    {
+        println!("  Teams gateway: {}", teams.sbc_fqdn);
+        let gateway = Arc::new(
+            TeamsGateway::new(server::edge_config(teams), config.clone())
+                .with_trunk_health(trunk_health.clone()),
+        );
+        supervisor
+            .spawn("edge", move || {
+                let gateway = gateway.clone();
+                async move { gateway.run().await }
+            })
+            .await;
+    }
+
+    if !server::wait_ready(&supervisor, Duration::from_secs(30)).await {
+        eprintln!("Warning: not every component is running; see `rustalk status`");
+    }
+    // Only hand over once this process is ready to take the traffic
+    if let Some(pid_file) = &upgrade.pid_file {
+        if let Some(previous) = server::take_over(pid_file, handoff.as_ref())? {
+            println!("  Took over from process {}, which is draining", previous);
+        }
+    }
 
     println!("RusTalk server started successfully!");
     println!("Press Ctrl+C to stop");
 
-    // Keep running
-    tokio::signal::ctrl_c().await?;
+    server::shutdown_signal().await;
+    if let (Some(handoff), Some(pid_file)) = (&handoff, &upgrade.pid_file) {
+        if let Some(successor) = server::successor(pid_file) {
+            handoff.set_successor(successor);
+        }
+    }
+    let active = b2bua.session_count().await;
+    if active > 0 {
+        println!(
+            "\nDraining {} call(s), up to {}s (Ctrl+C again to stop now)...",
+            active, upgrade.drain_timeout_seconds
+        );
+    }
+    let drain = b2bua.drain(Duration::from_secs(upgrade.drain_timeout_seconds));
+    tokio::select! {
+        remaining = drain => {
+            if remaining > 0 {
+                println!("Drain timed out with {} call(s) still up", remaining);
+            }
+        }
+        _ = server::shutdown_signal() => println!("Stopping without waiting for calls"),
+    }
+    if let Some(handoff) = &handoff {
+        if let Err(e) = handoff.finish().await {
+            eprintln!("Warning: failed to tell the next process: {:#}", e);
+        }
+    }
+    if let Some(pid_file) = &upgrade.pid_file {
+        server::release(pid_file);
+    }
     println!("\nShutting down...");
 
     Ok(())
@@ -169,6 +1556,16 @@ async fn start_server(config_path: PathBuf) -> Result<()> {
 
 async fn check_config(config_path: PathBuf) -> Result<()> {
     let config = Config::from_file(&config_path).await?;
+    // Parsing drops settings it does not know, so typos go unnoticed
+    let document: serde_json::Value =
+        serde_json::from_str(&tokio::fs::read_to_string(&config_path).await?)?;
+    let unknown = rustalk_core::config::schema::unknown_settings(&document);
+    if !unknown.is_empty() {
+        for key in &unknown {
+            println!("  ✗ Unknown setting {}", key);
+        }
+        anyhow::bail!("The configuration has {} unknown setting(s)", unknown.len());
+    }
 
     println!("✓ Configuration is valid");
     println!("\nConfiguration details:");
@@ -206,11 +1603,25 @@ async fn generate_config(output_path: PathBuf) -> Result<()> {
     Ok(())
 }
 
-async fn get_status(_server: &str) -> Result<()> {
-    println!("Status: Online");
-    println!("Version: 0.1.0");
-    println!("Uptime: N/A");
-    println!("Active calls: 0");
+async fn get_status(server: &str) -> Result<()> {
+    let status = api::ApiClient::new(server).get("/status").await?;
+    println!("Status: {}", output::cell(&status["status"]));
+    println!("Version: {}", output::cell(&status["version"]));
+
+    let mut table = output::Table::new(&["COMPONENT", "STATE", "RESTARTS", "SINCE", "LAST ERROR"]);
+    for component in status["components"].as_array().into_iter().flatten() {
+        table.add_row(vec![
+            output::cell(&component["name"]),
+            output::cell(&component["state"]),
+            output::cell(&component["restarts"]),
+            output::cell(&component["started_at"]),
+            output::cell(&component["last_error"]),
+        ]);
+    }
+    if !table.is_empty() {
+        println!();
+        table.print();
+    }
 
     Ok(())
 }
@@ -0,0 +1,250 @@
+//! Tabular output helpers
+//!
+//! Tables render as aligned columns or CSV. Long output is paged when stdout
+//! is a terminal so large registration and call lists stay readable.
+
+use serde_json::Value;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Lines per page when the terminal height is unknown
+const DEFAULT_PAGE_SIZE: usize = 22;
+
+/// How a table is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// Write comma separated values instead of aligned columns
+    pub csv: bool,
+    /// Pause between pages on a terminal
+    pub paging: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            csv: false,
+            paging: true,
+        }
+    }
+}
+
+/// A table of text cells printed with aligned columns
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Render with each column padded to its widest cell
+    pub fn render(&self) -> String {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate() {
+                if i < widths.len() {
+                    widths[i] = widths[i].max(cell.chars().count());
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for row in std::iter::once(&self.headers).chain(&self.rows) {
+            let line: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(i, width)| {
+                    let cell = row.get(i).map(String::as_str).unwrap_or("");
+                    format!("{:<width$}", cell, width = width)
+                })
+                .collect();
+            out.push_str(line.join("  ").trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Render as CSV, quoting cells that need it
+    pub fn render_csv(&self) -> String {
+        let mut out = String::new();
+        for row in std::iter::once(&self.headers).chain(&self.rows) {
+            let line: Vec<String> = row.iter().map(|cell| csv_escape(cell)).collect();
+            out.push_str(&line.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn print(&self) {
+        print!("{}", self.render());
+    }
+
+    /// Write the table using the given options
+    pub fn display(&self, options: OutputOptions) -> io::Result<()> {
+        if options.csv {
+            print!("{}", self.render_csv());
+            Ok(())
+        } else if options.paging {
+            page(&self.render())
+        } else {
+            self.print();
+            Ok(())
+        }
+    }
+}
+
+fn csv_escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Print text a page at a time when stdout is a terminal
+pub fn page(text: &str) -> io::Result<()> {
+    let stdout = io::stdout();
+    if !stdout.is_terminal() || !io::stdin().is_terminal() {
+        print!("{}", text);
+        return Ok(());
+    }
+
+    page_to(
+        text,
+        page_size(),
+        &mut stdout.lock(),
+        &mut io::stdin().lock(),
+    )
+}
+
+/// Page size from the `LINES` environment variable, leaving room for the
+/// prompt
+fn page_size() -> usize {
+    std::env::var("LINES")
+        .ok()
+        .and_then(|lines| lines.parse::<usize>().ok())
+        .map(|lines| lines.saturating_sub(2).max(1))
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+}
+
+fn page_to<W: Write, R: BufRead>(
+    text: &str,
+    page_size: usize,
+    out: &mut W,
+    input: &mut R,
+) -> io::Result<()> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = lines.chunks(page_size).peekable();
+
+    while let Some(chunk) = chunks.next() {
+        for line in chunk {
+            writeln!(out, "{}", line)?;
+        }
+        if chunks.peek().is_none() {
+            break;
+        }
+
+        write!(out, "-- More -- (Enter for next page, q to quit) ")?;
+        out.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 || answer.trim().eq_ignore_ascii_case("q") {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Display a JSON field as a table cell, `-` when absent
+pub fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Display a JSON boolean as yes/no
+pub fn yes_no(value: &Value) -> String {
+    if value.as_bool().unwrap_or(false) {
+        "yes".to_string()
+    } else {
+        "no".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_value_cells() {
+        assert_eq!(cell(&json!("Alice")), "Alice");
+        assert_eq!(cell(&Value::Null), "-");
+        assert_eq!(cell(&json!(5060)), "5060");
+        assert_eq!(yes_no(&json!(true)), "yes");
+        assert_eq!(yes_no(&Value::Null), "no");
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let mut table = Table::new(&["ID", "NAME"]);
+        table.add_row(vec!["1001".into(), "Smith, Alice".into()]);
+        table.add_row(vec!["1002".into(), "Bob \"The Builder\"".into()]);
+
+        assert_eq!(
+            table.render_csv(),
+            "ID,NAME\n1001,\"Smith, Alice\"\n1002,\"Bob \"\"The Builder\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_page_to_stops_on_quit() {
+        let text = (1..=10)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+
+        let mut out = Vec::new();
+        page_to(&text, 4, &mut out, &mut "\nq\n".as_bytes()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        // Two pages shown, then quit at the second prompt
+        assert!(out.contains("line 8\n"));
+        assert!(!out.contains("line 9"));
+        assert_eq!(out.matches("-- More --").count(), 2);
+    }
+
+    #[test]
+    fn test_page_to_single_page_has_no_prompt() {
+        let mut out = Vec::new();
+        page_to("a\nb\n", 4, &mut out, &mut "".as_bytes()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn test_render_aligns_columns() {
+        let mut table = Table::new(&["ID", "NAME", "ENABLED"]);
+        table.add_row(vec!["1001".into(), "Alice".into(), "yes".into()]);
+        table.add_row(vec!["10020".into(), "Bob".into(), "no".into()]);
+
+        assert_eq!(
+            table.render(),
+            "ID     NAME   ENABLED\n\
+             1001   Alice  yes\n\
+             10020  Bob    no\n"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Configuration override and schema commands
+
+use anyhow::{anyhow, bail, Result};
+use rustalk_core::config::overrides::{self, ConfigOverride};
+use rustalk_core::config::schema;
+use rustalk_core::db::{self, Database};
+use rustalk_core::prelude::Config;
+use std::path::Path;
+
+use crate::ConfigCommands;
+
+/// Handle configuration override commands
+pub async fn handle_config_command(cmd: ConfigCommands) -> Result<()> {
+    match cmd {
+        ConfigCommands::Get { key, config, json } => {
+            let (config, database) = open(&config).await?;
+            let stored = overrides::load(&database).await?;
+            let Some(key) = key else {
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&stored)?);
+                } else if stored.is_empty() {
+                    println!("No configuration overrides");
+                } else {
+                    for entry in &stored {
+                        let updated = entry
+                            .updated_at
+                            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                            .unwrap_or_default();
+                        println!("  {} = {}  ({})", entry.key, entry.value, updated);
+                    }
+                }
+                return Ok(());
+            };
+            let effective = serde_json::to_value(overrides::apply(config, &stored)?)?;
+            let value = overrides::get_path(&effective, &key)
+                .ok_or_else(|| anyhow!("No setting {}", key))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(value)?);
+            } else {
+                let source = if stored.iter().any(|o| o.key == key) {
+                    "database override"
+                } else {
+                    "configuration file"
+                };
+                println!("{} = {}  ({})", key, value, source);
+            }
+            Ok(())
+        }
+        ConfigCommands::Set { key, value, config } => {
+            let (config, database) = open(&config).await?;
+            let value = overrides::parse_value(&value);
+            // Checked against the setting's type with the others applied
+            let mut stored = overrides::load(&database).await?;
+            stored.retain(|o| o.key != key);
+            stored.push(ConfigOverride::new(key.as_str(), value.clone()));
+            let merged = overrides::apply(config, &stored)?;
+            let issues = rustalk_core::config::validate(&merged);
+            if !issues.is_empty() {
+                for issue in &issues {
+                    println!("  ✗ {} '{}': {}", issue.section, issue.name, issue.message);
+                }
+                bail!(
+                    "The override would leave {} validation issue(s)",
+                    issues.len()
+                );
+            }
+            overrides::set(&database, &key, &value).await?;
+            println!("✓ {} = {}", key, value);
+            println!("  Applied by each node on its next start or `reloadconfig`");
+            Ok(())
+        }
+        ConfigCommands::Unset { key, config } => {
+            let (_, database) = open(&config).await?;
+            if !overrides::unset(&database, &key).await? {
+                bail!("{} is not overridden", key);
+            }
+            println!("✓ Removed the override of {}", key);
+            Ok(())
+        }
+        ConfigCommands::Schema { models, output } => {
+            let schema = if models {
+                rustalk_cloud::models::models_schema()
+            } else {
+                schema::config_schema()
+            };
+            let document = serde_json::to_string_pretty(&schema)?;
+            match output {
+                Some(path) => {
+                    tokio::fs::write(&path, document + "\n").await?;
+                    println!("✓ Wrote the schema to {}", path.display());
+                }
+                None => println!("{}", document),
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The configuration file and a connection to its database
+async fn open(path: &Path) -> Result<(Config, Database)> {
+    let config = Config::from_file(path).await?;
+    let database = config
+        .database
+        .as_ref()
+        .ok_or_else(|| anyhow!("No 'database' section in the configuration file"))?;
+    let database = db::connect(database).await?;
+    Ok((config, database))
+}
@@ -0,0 +1,294 @@
+//! Remote console
+//!
+//! The commands a running server answers over its TLS console, and the
+//! `rustalk console --remote` client that sends them.
+
+use anyhow::{bail, Context, Result};
+use rustalk_core::b2bua::B2BUA;
+use rustalk_core::console::{hash_password, CommandHandler, ConsoleClient, ConsoleUser};
+use rustalk_core::registrar::Registrar;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::console::format_duration;
+use crate::output::{cell, yes_no, Table};
+
+/// Environment variable holding the password, to sign in without a prompt
+const PASSWORD_VAR: &str = "RUSTALK_CONSOLE_PASSWORD";
+
+/// Runs console commands against the server's live state
+pub struct ServerCommands {
+    b2bua: B2BUA,
+    registrar: Registrar,
+}
+
+impl ServerCommands {
+    pub fn new(b2bua: B2BUA, registrar: Registrar) -> Self {
+        Self { b2bua, registrar }
+    }
+
+    async fn show(&self, target: &str) -> Result<String> {
+        let table = match target {
+            "calls" | "call" => {
+                let mut table = Table::new(&["CALL-ID", "FROM", "TO", "STATE", "DURATION"]);
+                let mut listed = HashSet::new();
+                for channel in self.b2bua.channels().await {
+                    // One row per call, from its first leg
+                    if !listed.insert(channel.call_id.clone()) {
+                        continue;
+                    }
+                    let channel = json!(channel);
+                    table.add_row(vec![
+                        cell(&channel["call_id"]),
+                        cell(&channel["from"]),
+                        cell(&channel["to"]),
+                        cell(&channel["state"]),
+                        format_duration(channel["duration_seconds"].as_i64().unwrap_or(0)),
+                    ]);
+                }
+                table
+            }
+            "channels" | "channel" => {
+                let mut table = Table::new(&[
+                    "CALL-ID", "LEG", "STATE", "FROM", "TO", "REMOTE", "CODEC", "DURATION",
+                ]);
+                for channel in self.b2bua.channels().await {
+                    let channel = json!(channel);
+                    table.add_row(vec![
+                        cell(&channel["call_id"]),
+                        cell(&channel["leg"]).to_uppercase(),
+                        cell(&channel["state"]),
+                        cell(&channel["from"]),
+                        cell(&channel["to"]),
+                        cell(&channel["remote_addr"]),
+                        cell(&channel["codec"]),
+                        format_duration(channel["duration_seconds"].as_i64().unwrap_or(0)),
+                    ]);
+                }
+                table
+            }
+            "registrations" | "registration" | "regs" => {
+                let mut table =
+                    Table::new(&["AOR", "CONTACT", "EXPIRES", "USER AGENT", "RECEIVED", "NAT"]);
+                for binding in self.registrar.bindings().await {
+                    let expires_in = binding.expires_in();
+                    let binding = json!(binding);
+                    table.add_row(vec![
+                        cell(&binding["aor"]),
+                        cell(&binding["contact"]),
+                        format!("{}s", expires_in),
+                        cell(&binding["user_agent"]),
+                        cell(&binding["received"]),
+                        yes_no(&binding["behind_nat"]),
+                    ]);
+                }
+                table
+            }
+            "" => bail!("show command requires a target (calls, channels, registrations)"),
+            other => bail!("Unknown show target: {}", other),
+        };
+        Ok(if table.is_empty() {
+            "Nothing to show\n".to_string()
+        } else {
+            table.render()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl CommandHandler for ServerCommands {
+    async fn execute(&self, user: &ConsoleUser, line: &str) -> Result<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = words.get(1).copied().unwrap_or("");
+        match words[0].to_lowercase().as_str() {
+            "help" | "?" => Ok(help(user)),
+            "show" => self.show(&argument.to_lowercase()).await,
+            "hangup" => {
+                if argument.is_empty() {
+                    bail!("hangup command requires a Call-ID");
+                }
+                if !self.b2bua.hangup(argument).await {
+                    bail!("No active call {}", argument);
+                }
+                Ok(format!("Hung up {}\n", argument))
+            }
+            cmd => bail!(
+                "Unknown command: {}. Type 'help' for available commands.",
+                cmd
+            ),
+        }
+    }
+}
+
+/// The commands `user` may run
+fn help(user: &ConsoleUser) -> String {
+    let mut out = String::from("Remote console commands:\n");
+    if user.may_run("show") {
+        out.push_str("  show calls             - Display active calls\n");
+        out.push_str("  show channels          - Display call legs with state and codec\n");
+        out.push_str("  show registrations     - Display registered endpoints\n");
+    }
+    if user.may_run("hangup") {
+        out.push_str("  hangup <call-id>       - Hang up an active call\n");
+    }
+    out.push_str("  exit, quit             - Close the connection\n");
+    out
+}
+
+/// Sign in to a server's remote console and run commands interactively
+pub async fn run_remote_console(
+    address: String,
+    username: String,
+    ca: Option<PathBuf>,
+) -> Result<()> {
+    let mut client = ConsoleClient::connect(&address, ca.as_deref()).await?;
+    let password = match std::env::var(PASSWORD_VAR) {
+        Ok(password) => password,
+        Err(_) => read_password(&format!("Password for {}@{}: ", username, address))?,
+    };
+    client.login(&username, &password).await?;
+
+    println!("RusTalk Remote Console ({}@{})", username, address);
+    println!("Type 'help' for available commands, 'exit' to quit");
+    println!();
+
+    let mut rl = DefaultEditor::new()?;
+    loop {
+        match rl.readline("rustalk> ") {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let _ = rl.add_history_entry(line);
+                if matches!(line.to_lowercase().as_str(), "exit" | "quit" | "q") {
+                    break;
+                }
+                match client.execute(line).await {
+                    Ok(output) => print!("{}", output),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("^C");
+                println!("Use 'exit' or 'quit' to leave the console");
+            }
+            Err(ReadlineError::Eof) => {
+                println!("^D");
+                break;
+            }
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                break;
+            }
+        }
+    }
+    client.close().await
+}
+
+/// Prompt for a password twice and print its hash for `remote_console.users`
+pub fn print_password_hash() -> Result<()> {
+    let password = read_password("Password: ")?;
+    if password.is_empty() {
+        bail!("The password is empty");
+    }
+    if read_password("Again: ")? != password {
+        bail!("The passwords do not match");
+    }
+    println!("{}", hash_password(&password));
+    Ok(())
+}
+
+/// Read a line from the terminal without echoing it
+fn read_password(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let echo = EchoGuard::disable();
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .context("Cannot read the password")?;
+    drop(echo);
+    println!();
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Turns terminal echo off until dropped
+struct EchoGuard {
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl EchoGuard {
+    #[cfg(unix)]
+    fn disable() -> Self {
+        // SAFETY: termios is plain data, filled in by tcgetattr before use
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: stdin is a valid descriptor; tcgetattr fails harmlessly
+        // when it is not a terminal
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Self { saved: None };
+        }
+        let saved = termios;
+        termios.c_lflag &= !libc::ECHO;
+        // SAFETY: as above, with a termios tcgetattr filled in
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+        Self { saved: Some(saved) }
+    }
+
+    #[cfg(not(unix))]
+    fn disable() -> Self {
+        Self {}
+    }
+}
+
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = &self.saved {
+            // SAFETY: restores the settings tcgetattr returned
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(commands: &[&str]) -> ConsoleUser {
+        ConsoleUser {
+            username: "noc".to_string(),
+            password_hash: String::new(),
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_commands() {
+        let commands = ServerCommands::new(B2BUA::new(), Registrar::new());
+        let viewer = user(&["show"]);
+
+        let help = commands.execute(&viewer, "help").await.unwrap();
+        assert!(help.contains("show calls"));
+        assert!(!help.contains("hangup"));
+        assert!(commands
+            .execute(&user(&["*"]), "help")
+            .await
+            .unwrap()
+            .contains("hangup <call-id>"));
+
+        assert_eq!(
+            commands.execute(&viewer, "show calls").await.unwrap(),
+            "Nothing to show\n"
+        );
+        assert!(commands.execute(&viewer, "show profiles").await.is_err());
+        let missing = commands.execute(&viewer, "hangup abc").await.unwrap_err();
+        assert_eq!(missing.to_string(), "No active call abc");
+    }
+}
@@ -0,0 +1,586 @@
+//! Components started by `rustalk start`
+//!
+//! `start_server` builds the registrar and B2BUA from the configuration and
+//! runs the components that put them on the network under a supervisor:
+//! the SIP listeners, ring timeouts, ACME certificate renewal, the
+//! management API and the Teams edge gateway. The helpers here run each one
+//! in the foreground.
+
+use anyhow::{Context, Result};
+use rustalk_core::acme::{AcmeClient, AcmeConfig as AcmeClientConfig, ChallengeType};
+use rustalk_core::b2bua::{OutboundRequest, OutboundResponse};
+use rustalk_core::config::{AcmeConfig, TeamsConfig};
+use rustalk_core::nat::NatPolicy;
+use rustalk_core::prelude::{Config, B2BUA};
+use rustalk_core::registrar::Registrar;
+use rustalk_core::sip::Message;
+use rustalk_core::supervisor::Supervisor;
+use rustalk_core::transport::{
+    Egress, Handoff, TcpTransport, TlsTransport, Transport, TransportConfig, TransportProtocol,
+    UdpTransport, WsTransport, WssTransport,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, info, warn};
+
+/// Requests the B2BUA originates, waiting to be sent
+pub type OutboundQueue = Arc<Mutex<mpsc::UnboundedReceiver<OutboundRequest>>>;
+
+/// Responses the B2BUA relays between the legs of a call, waiting to be sent
+pub type RelayQueue = Arc<Mutex<mpsc::UnboundedReceiver<OutboundResponse>>>;
+
+/// How often ACME certificates are checked for renewal
+const ACME_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How often ringing calls are checked against their ring time
+const RING_TIMEOUT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often registrations that were not refreshed are dropped
+const REGISTRATION_EXPIRY_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Transports to listen for SIP on
+///
+/// Each protocol in `transport.protocols` listens on its port (UDP and TCP
+/// falling back to `server.bind_port`) at the server's bind address and on
+/// each configured interface. A SIP profile on a listener's port sets how
+/// often its flows are pinged. TLS and secure WebSockets need
+/// `transport.tls_cert` and `transport.tls_key`.
+pub fn sip_listeners(config: &Config) -> Result<Vec<TransportConfig>> {
+    let nat = NatPolicy::new(config.nat.clone().unwrap_or_default());
+    let bind_address: IpAddr = config
+        .server
+        .bind_address
+        .parse()
+        .with_context(|| format!("Invalid bind address {}", config.server.bind_address))?;
+    let mut listen: Vec<TransportConfig> = Vec::new();
+    for mut listener in
+        TransportConfig::for_settings(&config.transport, bind_address, config.server.bind_port)?
+    {
+        if matches!(
+            listener.protocol,
+            TransportProtocol::Tls | TransportProtocol::Wss
+        ) && (listener.cert_path.is_none() || listener.key_path.is_none())
+        {
+            anyhow::bail!(
+                "SIP over {} needs transport.tls_cert and transport.tls_key",
+                listener.protocol
+            );
+        }
+        // A wildcard bind already covers every interface
+        let interfaces = if bind_address.is_unspecified() {
+            &[][..]
+        } else {
+            &config.transport.interfaces[..]
+        };
+        let port = listener.bind_addr.port();
+        if let Some(profile) = nat.for_port(port) {
+            let base = listener.keepalive.clone().unwrap_or_default();
+            listener.keepalive = Some(profile.keepalive(&base));
+        }
+        let protocol = listener.protocol;
+        listen.push(listener.clone());
+        for interface in interfaces {
+            let addr = SocketAddr::new(interface.bind_address, port);
+            if !listen
+                .iter()
+                .any(|l| l.protocol == protocol && l.bind_addr == addr)
+            {
+                listen.push(TransportConfig {
+                    bind_addr: addr,
+                    ..listener.clone()
+                });
+            }
+        }
+    }
+    Ok(listen)
+}
+
+/// Address to name in the Via of requests the B2BUA originates
+///
+/// The first UDP listen address, which the requests are sent from, or the
+/// default interface's address when that is a wildcard.
+pub fn via_address(config: &Config) -> Result<Option<SocketAddr>> {
+    let Some(listen) = sip_listeners(config)?
+        .into_iter()
+        .find(|l| l.protocol == TransportProtocol::Udp)
+        .map(|l| l.bind_addr)
+    else {
+        return Ok(None);
+    };
+    if !listen.ip().is_unspecified() {
+        return Ok(Some(listen));
+    }
+    let interfaces = &config.transport.interfaces;
+    let ip = interfaces
+        .iter()
+        .find(|i| i.default)
+        .or(interfaces.first())
+        .map(|i| {
+            i.advertised_address
+                .as_deref()
+                .and_then(|a| a.parse().ok())
+                .unwrap_or(i.bind_address)
+        })
+        .unwrap_or(listen.ip());
+    Ok(Some(SocketAddr::new(ip, listen.port())))
+}
+
+/// Bind the transport for `listener`
+pub async fn bind_sip_listener(listener: &TransportConfig) -> Result<Arc<dyn Transport>> {
+    let context = || {
+        format!(
+            "Cannot listen on {} {}",
+            listener.protocol, listener.bind_addr
+        )
+    };
+    let transport: Arc<dyn Transport> = match listener.protocol {
+        TransportProtocol::Udp => {
+            Arc::new(UdpTransport::new(listener).await.with_context(context)?)
+        }
+        TransportProtocol::Tcp => {
+            Arc::new(TcpTransport::new(listener).await.with_context(context)?)
+        }
+        TransportProtocol::Tls => {
+            Arc::new(TlsTransport::new(listener).await.with_context(context)?)
+        }
+        TransportProtocol::Ws => Arc::new(WsTransport::new(listener).await.with_context(context)?),
+        TransportProtocol::Wss => {
+            Arc::new(WssTransport::new(listener).await.with_context(context)?)
+        }
+    };
+    Ok(transport)
+}
+
+/// Listen for SIP on `listener`, handing every message to the B2BUA
+///
+/// Serves `bound` when given, and otherwise binds the listener afresh. UDP
+/// listeners also register with `egress` to send the requests the B2BUA
+/// originates, which name UDP in their Via, and pass messages of calls held
+/// by the other process of a rolling upgrade over `handoff`. Only returns
+/// if the socket cannot be bound.
+pub async fn run_sip_listener(
+    listener: TransportConfig,
+    bound: Option<Arc<dyn Transport>>,
+    b2bua: B2BUA,
+    egress: Egress,
+    handoff: Option<Handoff>,
+) -> Result<()> {
+    let transport = match bound {
+        Some(transport) => transport,
+        None => bind_sip_listener(&listener).await?,
+    };
+    let handoff = if listener.protocol == TransportProtocol::Udp {
+        egress.register(transport.clone());
+        handoff
+    } else {
+        // Connections stay with the process that accepted them
+        None
+    };
+    serve(transport, b2bua, handoff).await;
+    Ok(())
+}
+
+/// Handle the messages the other process of a rolling upgrade hands over,
+/// replying from the UDP listener each arrived on
+pub async fn run_handoff(handoff: Handoff, b2bua: B2BUA, egress: Egress) -> Result<()> {
+    loop {
+        let handed = handoff.receive().await?;
+        let Some(transport) = egress.socket(handed.local) else {
+            debug!(
+                "No UDP listener on {} for a handed over message",
+                handed.local
+            );
+            continue;
+        };
+        tokio::spawn(handle(
+            transport,
+            b2bua.clone(),
+            handed.message,
+            handed.source,
+        ));
+    }
+}
+
+/// Send the requests the B2BUA originates and the responses it relays,
+/// each from the interface facing its destination
+pub async fn run_outbound(
+    outbound: OutboundQueue,
+    relayed: RelayQueue,
+    egress: Egress,
+) -> Result<()> {
+    let mut outbound = outbound.lock().await;
+    let mut relayed = relayed.lock().await;
+    loop {
+        let (destination, sent) = tokio::select! {
+            Some(request) = outbound.recv() => {
+                let destination = request.destination;
+                (destination, egress.send(request.request, destination).await)
+            }
+            Some(response) = relayed.recv() => {
+                let destination = response.destination;
+                (destination, egress.send_response(response.response, destination).await)
+            }
+            else => return Ok(()),
+        };
+        if let Err(e) = sent {
+            warn!("Failed to send to {}: {:#}", destination, e);
+        }
+    }
+}
+
+/// Receive loop for one transport; each message is handled on its own task
+async fn serve(transport: Arc<dyn Transport>, b2bua: B2BUA, handoff: Option<Handoff>) {
+    info!("SIP listening on {}", transport.local_addr());
+    loop {
+        let (message, source) = match transport.receive().await {
+            Ok(received) => received,
+            Err(e) => {
+                debug!("Dropping unreadable message: {:#}", e);
+                continue;
+            }
+        };
+        let transport = transport.clone();
+        let b2bua = b2bua.clone();
+        let handoff = handoff.clone();
+        tokio::spawn(async move {
+            if let Some(handoff) = handoff {
+                let destination = match b2bua.holds(&message).await {
+                    true => None,
+                    false => handoff.destination(&message, b2bua.is_draining()),
+                };
+                if let Some(destination) = destination {
+                    let local = transport.local_addr();
+                    if let Err(e) = handoff.forward(destination, local, source, &message).await {
+                        warn!("Failed to hand over a message from {}: {:#}", source, e);
+                    }
+                    return;
+                }
+            }
+            handle(transport, b2bua, message, source).await;
+        });
+    }
+}
+
+/// Handle a message from `source`, replying over `transport`
+async fn handle(transport: Arc<dyn Transport>, b2bua: B2BUA, message: Message, source: SocketAddr) {
+    match b2bua.handle_message_from(message, source).await {
+        Ok(Some(reply)) => {
+            if let Err(e) = transport.send(&reply, source).await {
+                warn!("Failed to reply to {}: {:#}", source, e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to handle message from {}: {:#}", source, e),
+    }
+}
+
+/// ACME client for the `acme` configuration section
+pub fn acme_client(config: &AcmeConfig) -> Result<AcmeClient> {
+    AcmeClient::new(AcmeClientConfig {
+        email: config.email.clone(),
+        cert_dir: config.cert_dir.clone(),
+        account_dir: config.account_dir.clone(),
+        use_staging: config.use_staging,
+        http_challenge_port: config.http_challenge_port,
+    })
+}
+
+/// Apply the no-answer action to calls that have rung too long, and move
+/// calls whose route destination timed out on to the next
+pub async fn run_ring_timeouts(b2bua: B2BUA) -> Result<()> {
+    let mut interval = tokio::time::interval(RING_TIMEOUT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let expired = b2bua.expire_ring_timeouts().await;
+        if expired > 0 {
+            debug!("{} call(s) rang out", expired);
+        }
+        let failed_over = b2bua.expire_hop_timeouts().await;
+        if failed_over > 0 {
+            debug!("{} call(s) timed out at a route destination", failed_over);
+        }
+    }
+}
+
+/// Drop registrations that have expired, so their expiry is announced
+/// rather than noticed on the next lookup
+pub async fn run_registration_expiry(registrar: Registrar) -> Result<()> {
+    let mut interval = tokio::time::interval(REGISTRATION_EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        registrar.purge_expired().await;
+    }
+}
+
+/// Request the configured certificate if it is missing and renew it once
+/// it is within `auto_renew_days` of expiry, checking twice a day
+pub async fn run_acme_renewal(client: AcmeClient, config: AcmeConfig) -> Result<()> {
+    let Some(primary) = config.domains.first().cloned() else {
+        warn!("ACME is enabled but no domains are configured");
+        return Ok(());
+    };
+    let challenge = match config.challenge_type.as_str() {
+        "dns-01" => ChallengeType::Dns01,
+        _ => ChallengeType::Http01,
+    };
+    let mut interval = tokio::time::interval(ACME_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = match client.storage().get_certificate_info(&primary).await {
+            Err(_) => {
+                info!("Requesting certificate for {}", config.domains.join(", "));
+                client
+                    .request_certificate(config.domains.clone(), challenge.clone())
+                    .await
+            }
+            Ok(info) if info.days_until_expiry < i64::from(config.auto_renew_days) => {
+                info!(
+                    "Renewing certificate for {} ({} days left)",
+                    primary, info.days_until_expiry
+                );
+                client.renew_certificate(&primary).await
+            }
+            Ok(_) => Ok(()),
+        };
+        // A failed renewal is retried at the next check, well before expiry
+        if let Err(e) = result {
+            warn!("Certificate renewal for {} failed: {:#}", primary, e);
+        }
+    }
+}
+
+/// Wait up to `timeout` for every supervised component to be running
+pub async fn wait_ready(supervisor: &Supervisor, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    // Give listeners a moment to bind, or fail to
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    while !supervisor.is_healthy().await {
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    true
+}
+
+/// Process recorded in a pid file: its pid, then its handoff socket when
+/// it has one
+fn recorded(pid_file: &Path) -> Option<(u32, Option<SocketAddr>)> {
+    let contents = std::fs::read_to_string(pid_file).ok()?;
+    let mut lines = contents.lines();
+    let pid = lines.next()?.trim().parse().ok()?;
+    let handoff = lines.next().and_then(|addr| addr.trim().parse().ok());
+    Some((pid, handoff))
+}
+
+/// Record this process and its `handoff` socket in `pid_file`, telling the
+/// process recorded there before it to drain
+///
+/// The previous process's calls are handed to it over `handoff` from then
+/// on. Returns the pid of the previous process, if one was signalled.
+pub fn take_over(pid_file: &Path, handoff: Option<&Handoff>) -> Result<Option<u32>> {
+    let own = std::process::id();
+    let previous = recorded(pid_file).filter(|(pid, _)| *pid != own);
+    let mut contents = format!("{}\n", own);
+    if let Some(handoff) = handoff {
+        contents.push_str(&format!("{}\n", handoff.local_addr()));
+    }
+    std::fs::write(pid_file, contents)
+        .with_context(|| format!("Cannot write {}", pid_file.display()))?;
+    let Some((pid, previous_handoff)) = previous else {
+        return Ok(None);
+    };
+    if let (Some(handoff), Some(addr)) = (handoff, previous_handoff) {
+        handoff.set_predecessor(addr);
+    }
+    Ok(signal_drain(pid).then_some(pid))
+}
+
+/// Handoff socket of the process that took over `pid_file` from this one
+pub fn successor(pid_file: &Path) -> Option<SocketAddr> {
+    recorded(pid_file)
+        .filter(|(pid, _)| *pid != std::process::id())
+        .and_then(|(_, handoff)| handoff)
+}
+
+/// Remove `pid_file` unless a newer process has taken it over
+pub fn release(pid_file: &Path) {
+    if recorded(pid_file).is_some_and(|(pid, _)| pid == std::process::id()) {
+        let _ = std::fs::remove_file(pid_file);
+    }
+}
+
+/// Send SIGTERM to `pid`, returning whether it was delivered
+#[cfg(unix)]
+fn signal_drain(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: kill has no memory-safety preconditions
+    unsafe { libc::kill(pid, libc::SIGTERM) == 0 }
+}
+
+#[cfg(not(unix))]
+fn signal_drain(pid: u32) -> bool {
+    warn!("Cannot signal process {} on this platform", pid);
+    false
+}
+
+/// Resolve once the process is asked to stop (SIGTERM or Ctrl+C)
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Cannot listen for SIGTERM: {}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Edge gateway settings for the core `teams` section
+pub fn edge_config(teams: &TeamsConfig) -> rustalk_edge::TeamsConfig {
+    rustalk_edge::TeamsConfig {
+        sbc_fqdn: teams.sbc_fqdn.clone(),
+        mtls_cert_path: teams.mtls_cert.clone(),
+        mtls_key_path: teams.mtls_key.clone(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::sip::parser::parse_message;
+    use rustalk_core::sip::Message;
+    use rustalk_core::transport::NetworkInterface;
+    use tokio::net::UdpSocket;
+
+    #[test]
+    fn test_sip_listeners() {
+        let mut config = Config::default();
+        config.server.bind_address = "10.0.0.1".to_string();
+        config.transport.protocols = vec!["tcp".to_string(), "udp".to_string(), "tls".to_string()];
+        config.transport.udp_port = Some(5070);
+        config.transport.tcp_port = Some(5080);
+        // TLS cannot listen without a certificate
+        assert!(sip_listeners(&config).is_err());
+        config.transport.protocols.pop();
+        let listeners: Vec<_> = sip_listeners(&config)
+            .unwrap()
+            .into_iter()
+            .map(|l| format!("{} {}", l.protocol, l.bind_addr))
+            .collect();
+        assert_eq!(listeners, ["tcp 10.0.0.1:5080", "udp 10.0.0.1:5070"]);
+
+        // A profile on the TCP port pings its flows more often
+        let nat = serde_json::json!({
+            "profiles": [{
+                "name": "external",
+                "domain": "example.com",
+                "bind_port": 5080,
+                "keepalive_interval_seconds": 30
+            }]
+        });
+        config.nat = Some(serde_json::from_value(nat).unwrap());
+        let listeners = sip_listeners(&config).unwrap();
+        assert_eq!(
+            listeners[0]
+                .keepalive
+                .as_ref()
+                .map(|k| k.ping_interval_seconds),
+            Some(30)
+        );
+        assert_eq!(listeners[1].keepalive, None);
+        assert_eq!(
+            via_address(&config).unwrap(),
+            Some("10.0.0.1:5070".parse().unwrap())
+        );
+
+        // A wildcard bind is named by the default interface
+        config.server.bind_address = "0.0.0.0".to_string();
+        config.transport.interfaces = vec![
+            NetworkInterface::new("internal", "10.0.0.1".parse().unwrap()),
+            NetworkInterface::new("external", "198.51.100.4".parse().unwrap()).as_default(),
+        ];
+        assert_eq!(
+            via_address(&config).unwrap(),
+            Some("198.51.100.4:5070".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_take_over_pid_file() {
+        let pid_file = std::env::temp_dir().join("rustalk-take-over-test.pid");
+        let _ = std::fs::remove_file(&pid_file);
+
+        // No predecessor: nothing is signalled
+        assert_eq!(take_over(&pid_file, None).unwrap(), None);
+        let own = std::process::id().to_string();
+        assert_eq!(std::fs::read_to_string(&pid_file).unwrap().trim(), own);
+        // Our own pid is never signalled
+        let handoff = Handoff::bind().await.unwrap();
+        assert_eq!(take_over(&pid_file, Some(&handoff)).unwrap(), None);
+        assert_eq!(
+            std::fs::read_to_string(&pid_file).unwrap(),
+            format!("{}\n{}\n", own, handoff.local_addr())
+        );
+        assert_eq!(successor(&pid_file), None);
+
+        // A newer process owns the file: leave it in place, and hand it
+        // what is not ours while draining
+        std::fs::write(&pid_file, "4194305\n127.0.0.1:40000\n").unwrap();
+        assert_eq!(
+            successor(&pid_file),
+            Some("127.0.0.1:40000".parse().unwrap())
+        );
+        release(&pid_file);
+        assert!(pid_file.exists());
+
+        std::fs::write(&pid_file, &own).unwrap();
+        release(&pid_file);
+        assert!(!pid_file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_udp_listener_answers_options() {
+        let transport = UdpTransport::new(&TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let addr = transport.local_addr();
+        tokio::spawn(serve(Arc::new(transport), B2BUA::new(), None));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let options = "OPTIONS sip:rustalk.local SIP/2.0\r\n\
+                       Via: SIP/2.0/UDP 127.0.0.1:5099;branch=z9hG4bK-start\r\n\
+                       From: <sip:probe@127.0.0.1>;tag=1\r\n\
+                       To: <sip:rustalk.local>\r\n\
+                       Call-ID: start-test\r\n\
+                       CSeq: 1 OPTIONS\r\n\
+                       Content-Length: 0\r\n\r\n";
+        client.send_to(options.as_bytes(), addr).await.unwrap();
+
+        let mut buf = vec![0u8; 65535];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        match parse_message(&buf[..len]).unwrap() {
+            Message::Response(response) => assert_eq!(response.status_code.0, 200),
+            Message::Request(_) => panic!("expected a response"),
+        }
+    }
+}
@@ -0,0 +1,562 @@
+//! Synthetic call load generation for soak testing
+//!
+//! `rustalk simulate` places calls against a server at a fixed rate, holds
+//! them for a randomised duration and hangs up, offering codecs according to
+//! a weighted mix. The report covers failure rates, setup latency percentiles
+//! and, when the server PID is known, resident memory growth.
+
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use rustalk_core::media::codec::{standard_codecs, Codec};
+use rustalk_core::sip::{
+    parser::parse_message, DialogContext, LocalProfile, Message, MessageBuilder, Method, Uri,
+};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+
+/// Call hold time distribution
+#[derive(Debug, Clone, PartialEq)]
+pub enum DurationDistribution {
+    /// Every call lasts the same time (e.g. `30`)
+    Fixed(Duration),
+    /// Uniformly distributed between two bounds (e.g. `10-60`)
+    Uniform(Duration, Duration),
+    /// Exponentially distributed around a mean (e.g. `exp:45`)
+    Exponential(Duration),
+}
+
+impl DurationDistribution {
+    fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match self {
+            DurationDistribution::Fixed(d) => *d,
+            DurationDistribution::Uniform(min, max) => {
+                Duration::from_secs_f64(rng.gen_range(min.as_secs_f64()..=max.as_secs_f64()))
+            }
+            DurationDistribution::Exponential(mean) => {
+                let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+                Duration::from_secs_f64(-mean.as_secs_f64() * u.ln())
+            }
+        }
+    }
+}
+
+impl FromStr for DurationDistribution {
+    type Err = anyhow::Error;
+
+    /// Durations are in seconds and may be fractional
+    fn from_str(s: &str) -> Result<Self> {
+        let secs = |v: &str| -> Result<Duration> {
+            let value: f64 = v
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid duration: {}", v))?;
+            if value < 0.0 {
+                bail!("Duration must not be negative: {}", v);
+            }
+            Ok(Duration::from_secs_f64(value))
+        };
+
+        if let Some(mean) = s.strip_prefix("exp:") {
+            Ok(DurationDistribution::Exponential(secs(mean)?))
+        } else if let Some((min, max)) = s.split_once('-') {
+            let (min, max) = (secs(min)?, secs(max)?);
+            if min > max {
+                bail!("Invalid duration range: {}", s);
+            }
+            Ok(DurationDistribution::Uniform(min, max))
+        } else {
+            Ok(DurationDistribution::Fixed(secs(s)?))
+        }
+    }
+}
+
+/// Weighted codec selection (e.g. `PCMU:70,PCMA:20,G722:10`)
+#[derive(Debug, Clone)]
+pub struct CodecMix {
+    entries: Vec<(Codec, u32)>,
+}
+
+impl CodecMix {
+    fn pick(&self, rng: &mut impl Rng) -> &Codec {
+        let total: u32 = self.entries.iter().map(|(_, w)| w).sum();
+        let mut roll = rng.gen_range(0..total);
+        for (codec, weight) in &self.entries {
+            if roll < *weight {
+                return codec;
+            }
+            roll -= weight;
+        }
+        &self.entries[0].0
+    }
+}
+
+impl FromStr for CodecMix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let codecs = standard_codecs();
+        let mut entries = Vec::new();
+
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, weight) = part.split_once(':').unwrap_or((part, "1"));
+            let codec = codecs
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name))
+                .with_context(|| format!("Unknown codec: {}", name))?;
+            let weight: u32 = weight
+                .parse()
+                .with_context(|| format!("Invalid weight for {}: {}", name, weight))?;
+            if weight > 0 {
+                entries.push((codec.clone(), weight));
+            }
+        }
+
+        if entries.is_empty() {
+            bail!("Codec mix must contain at least one codec with a non-zero weight");
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+/// Simulation parameters
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub target: SocketAddr,
+    /// New calls per second
+    pub cps: f64,
+    /// Total calls to place
+    pub calls: usize,
+    pub hold: DurationDistribution,
+    pub codecs: CodecMix,
+    /// How long to wait for a response before counting a failure
+    pub response_timeout: Duration,
+    /// Server process to sample memory from (Linux only)
+    pub server_pid: Option<u32>,
+}
+
+/// Outcome of a single simulated call
+#[derive(Debug)]
+struct CallResult {
+    codec: String,
+    setup_latency: Option<Duration>,
+    failure: Option<String>,
+}
+
+/// Aggregated simulation results
+#[derive(Debug, Default)]
+pub struct SimulationReport {
+    pub attempted: usize,
+    pub succeeded: usize,
+    pub failures: BTreeMap<String, usize>,
+    pub codecs: BTreeMap<String, usize>,
+    setup_latencies: Vec<Duration>,
+    pub memory_start_kb: Option<u64>,
+    pub memory_peak_kb: Option<u64>,
+    pub memory_end_kb: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl SimulationReport {
+    pub fn failed(&self) -> usize {
+        self.attempted - self.succeeded
+    }
+
+    /// Failed calls as a percentage of attempts
+    pub fn failure_rate(&self) -> f64 {
+        if self.attempted == 0 {
+            0.0
+        } else {
+            self.failed() as f64 * 100.0 / self.attempted as f64
+        }
+    }
+
+    /// Setup latency at the given percentile (0-100)
+    pub fn setup_latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.setup_latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = self.setup_latencies.clone();
+        sorted.sort();
+        let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+
+    /// Resident memory growth over the run
+    pub fn memory_growth_kb(&self) -> Option<i64> {
+        Some(self.memory_end_kb? as i64 - self.memory_start_kb? as i64)
+    }
+
+    fn record(&mut self, result: CallResult) {
+        self.attempted += 1;
+        *self.codecs.entry(result.codec).or_default() += 1;
+        if let Some(latency) = result.setup_latency {
+            self.setup_latencies.push(latency);
+        }
+        match result.failure {
+            Some(reason) => *self.failures.entry(reason).or_default() += 1,
+            None => self.succeeded += 1,
+        }
+    }
+}
+
+/// Run a simulation to completion
+pub async fn run_simulation(config: SimulationConfig) -> Result<SimulationReport> {
+    if config.cps <= 0.0 {
+        bail!("CPS must be greater than zero");
+    }
+
+    let started = Instant::now();
+    let report = Arc::new(Mutex::new(SimulationReport {
+        memory_start_kb: config.server_pid.and_then(resident_memory_kb),
+        ..Default::default()
+    }));
+    let peak = Arc::new(std::sync::atomic::AtomicU64::new(0));
+
+    let sampler = config.server_pid.map(|pid| {
+        let peak = peak.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Some(kb) = resident_memory_kb(pid) {
+                    peak.fetch_max(kb, std::sync::atomic::Ordering::Relaxed);
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+    });
+
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / config.cps));
+    let mut tasks = Vec::with_capacity(config.calls);
+
+    for call_number in 1..=config.calls {
+        interval.tick().await;
+
+        let (codec, hold) = {
+            let mut rng = rand::thread_rng();
+            (
+                config.codecs.pick(&mut rng).clone(),
+                config.hold.sample(&mut rng),
+            )
+        };
+        let target = config.target;
+        let timeout = config.response_timeout;
+        let report = report.clone();
+
+        tasks.push(tokio::spawn(async move {
+            let result = simulate_call(target, call_number, codec, hold, timeout).await;
+            report.lock().await.record(result);
+        }));
+    }
+
+    for task in tasks {
+        task.await?;
+    }
+
+    if let Some(sampler) = sampler {
+        sampler.abort();
+    }
+
+    let mut report = Arc::try_unwrap(report)
+        .map_err(|_| anyhow::anyhow!("Simulation tasks still running"))?
+        .into_inner();
+    report.elapsed = started.elapsed();
+    report.memory_end_kb = config.server_pid.and_then(resident_memory_kb);
+    let peak = peak.load(std::sync::atomic::Ordering::Relaxed);
+    if peak > 0 {
+        report.memory_peak_kb = Some(peak);
+    }
+
+    Ok(report)
+}
+
+/// Place one call: INVITE, wait for a response, ACK, hold, BYE
+async fn simulate_call(
+    target: SocketAddr,
+    call_number: usize,
+    codec: Codec,
+    hold: Duration,
+    timeout: Duration,
+) -> CallResult {
+    let mut result = CallResult {
+        codec: codec.name.clone(),
+        setup_latency: None,
+        failure: None,
+    };
+
+    if let Err(e) = place_call(target, call_number, &codec, hold, timeout, &mut result).await {
+        result.failure = Some(e.to_string());
+    }
+
+    result
+}
+
+async fn place_call(
+    target: SocketAddr,
+    call_number: usize,
+    codec: &Codec,
+    hold: Duration,
+    timeout: Duration,
+    result: &mut CallResult,
+) -> Result<()> {
+    let socket = UdpSocket::bind(if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })
+    .await?;
+    socket.connect(target).await?;
+    let local = socket.local_addr()?;
+    let call_id = format!("sim-{}-{}@{}", std::process::id(), call_number, local.ip());
+
+    let sdp = format!(
+        "v=0\r\no=- {n} 1 IN IP4 {ip}\r\ns=rustalk-simulate\r\nc=IN IP4 {ip}\r\nt=0 0\r\nm=audio {port} RTP/AVP {pt}\r\na=rtpmap:{rtpmap}\r\n",
+        n = call_number,
+        ip = local.ip(),
+        port = 10000 + (call_number % 20000) * 2,
+        pt = codec.payload_type,
+        rtpmap = codec.rtpmap(),
+    );
+    let builder = MessageBuilder::new(
+        LocalProfile::new("UDP", local.ip().to_string(), local.port()).with_user("simulate"),
+    );
+    let uri = Uri::new("sip".to_string(), target.ip().to_string())
+        .with_user("simulate".to_string())
+        .with_port(target.port());
+    let mut dialog =
+        DialogContext::new(format!("sip:simulate@{}", local), uri).with_call_id(call_id);
+    let invite = builder
+        .in_dialog(Method::Invite, &mut dialog)
+        .body("application/sdp", sdp)
+        .build();
+
+    let sent = Instant::now();
+    socket.send(&invite.to_bytes()).await?;
+
+    let status = recv_status(&socket, timeout)
+        .await?
+        .context("INVITE timeout")?;
+    result.setup_latency = Some(sent.elapsed());
+    if status >= 300 {
+        bail!("INVITE rejected with {}", status);
+    }
+
+    let ack = builder.in_dialog(Method::Ack, &mut dialog).build();
+    socket.send(&ack.to_bytes()).await?;
+
+    tokio::time::sleep(hold).await;
+
+    let bye = builder.in_dialog(Method::Bye, &mut dialog).build();
+    socket.send(&bye.to_bytes()).await?;
+
+    // Skip any late provisional responses to the INVITE
+    loop {
+        match recv_status(&socket, timeout).await? {
+            Some(200) => return Ok(()),
+            Some(status) if status < 200 => continue,
+            Some(status) => bail!("BYE rejected with {}", status),
+            None => bail!("BYE timeout"),
+        }
+    }
+}
+
+/// Wait for the next response and return its status code
+async fn recv_status(socket: &UdpSocket, timeout: Duration) -> Result<Option<u16>> {
+    let mut buf = vec![0u8; 65535];
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let len = match tokio::time::timeout(remaining, socket.recv(&mut buf)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(None),
+        };
+
+        if let Ok(Message::Response(res)) = parse_message(&buf[..len]) {
+            return Ok(Some(res.status_code.0));
+        }
+    }
+}
+
+/// Resident set size of a process in KiB (Linux only)
+fn resident_memory_kb(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|kb| kb.parse().ok())
+}
+
+/// Print a report in the CLI's usual style
+pub fn print_report(report: &SimulationReport) {
+    let ms = |d: Option<Duration>| {
+        d.map(|d| format!("{:.1} ms", d.as_secs_f64() * 1000.0))
+            .unwrap_or_else(|| "n/a".to_string())
+    };
+
+    println!();
+    println!(
+        "Simulation complete in {:.1}s",
+        report.elapsed.as_secs_f64()
+    );
+    println!("  Calls attempted: {}", report.attempted);
+    println!("  Calls succeeded: {}", report.succeeded);
+    println!(
+        "  Calls failed:    {} ({:.2}%)",
+        report.failed(),
+        report.failure_rate()
+    );
+    for (reason, count) in &report.failures {
+        println!("    {}: {}", reason, count);
+    }
+
+    println!("\nSetup latency:");
+    println!("  p50: {}", ms(report.setup_latency_percentile(50.0)));
+    println!("  p90: {}", ms(report.setup_latency_percentile(90.0)));
+    println!("  p99: {}", ms(report.setup_latency_percentile(99.0)));
+    println!("  max: {}", ms(report.setup_latency_percentile(100.0)));
+
+    println!("\nCodec mix:");
+    for (codec, count) in &report.codecs {
+        println!("  {}: {}", codec, count);
+    }
+
+    if let (Some(start), Some(end), Some(growth)) = (
+        report.memory_start_kb,
+        report.memory_end_kb,
+        report.memory_growth_kb(),
+    ) {
+        println!("\nServer memory (RSS):");
+        println!("  start: {} KiB", start);
+        if let Some(peak) = report.memory_peak_kb {
+            println!("  peak:  {} KiB", peak);
+        }
+        println!("  end:   {} KiB", end);
+        println!("  growth: {} KiB", growth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::b2bua::B2BUA;
+    use rustalk_core::transport::{Transport, TransportConfig, UdpTransport};
+
+    async fn start_server() -> SocketAddr {
+        let config = TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        };
+        let transport = Arc::new(UdpTransport::new(&config).await.unwrap());
+        let addr = transport.local_addr();
+        let b2bua = B2BUA::new();
+
+        tokio::spawn(async move {
+            while let Ok((message, source)) = transport.receive().await {
+                if let Ok(Some(reply)) = b2bua.handle_message(message).await {
+                    let _ = transport.send(&reply, source).await;
+                }
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_parse_duration_distribution() {
+        assert_eq!(
+            "30".parse::<DurationDistribution>().unwrap(),
+            DurationDistribution::Fixed(Duration::from_secs(30))
+        );
+        assert_eq!(
+            "10-60".parse::<DurationDistribution>().unwrap(),
+            DurationDistribution::Uniform(Duration::from_secs(10), Duration::from_secs(60))
+        );
+        assert_eq!(
+            "exp:0.5".parse::<DurationDistribution>().unwrap(),
+            DurationDistribution::Exponential(Duration::from_millis(500))
+        );
+        assert!("60-10".parse::<DurationDistribution>().is_err());
+        assert!("abc".parse::<DurationDistribution>().is_err());
+    }
+
+    #[test]
+    fn test_duration_sampling_bounds() {
+        let mut rng = rand::thread_rng();
+        let dist = DurationDistribution::Uniform(Duration::from_secs(1), Duration::from_secs(2));
+        for _ in 0..100 {
+            let d = dist.sample(&mut rng);
+            assert!(d >= Duration::from_secs(1) && d <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn test_parse_codec_mix() {
+        let mix: CodecMix = "PCMU:70, pcma:30, G722:0".parse().unwrap();
+        assert_eq!(mix.entries.len(), 2);
+        assert_eq!(mix.entries[1].0.name, "PCMA");
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..50 {
+            let codec = mix.pick(&mut rng);
+            assert!(codec.name == "PCMU" || codec.name == "PCMA");
+        }
+
+        assert!("FOO:1".parse::<CodecMix>().is_err());
+        assert!("PCMU:0".parse::<CodecMix>().is_err());
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut report = SimulationReport::default();
+        for ms in 1..=100 {
+            report.record(CallResult {
+                codec: "PCMU".to_string(),
+                setup_latency: Some(Duration::from_millis(ms)),
+                failure: None,
+            });
+        }
+        report.record(CallResult {
+            codec: "PCMA".to_string(),
+            setup_latency: None,
+            failure: Some("INVITE timeout".to_string()),
+        });
+
+        assert_eq!(
+            report.setup_latency_percentile(50.0),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            report.setup_latency_percentile(99.0),
+            Some(Duration::from_millis(99))
+        );
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.failures["INVITE timeout"], 1);
+        assert!((report.failure_rate() - 100.0 / 101.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_simulation_against_local_b2bua() {
+        let target = start_server().await;
+        let config = SimulationConfig {
+            target,
+            cps: 100.0,
+            calls: 10,
+            hold: DurationDistribution::Uniform(Duration::ZERO, Duration::from_millis(20)),
+            codecs: "PCMU:1,PCMA:1".parse().unwrap(),
+            response_timeout: Duration::from_secs(2),
+            server_pid: Some(std::process::id()),
+        };
+
+        let report = run_simulation(config).await.unwrap();
+        assert_eq!(report.attempted, 10);
+        assert_eq!(report.succeeded, 10, "failures: {:?}", report.failures);
+        assert!(report.setup_latency_percentile(90.0).is_some());
+        assert_eq!(report.codecs.values().sum::<usize>(), 10);
+    }
+}
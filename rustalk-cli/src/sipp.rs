@@ -0,0 +1,574 @@
+//! SIPp scenario runner
+//!
+//! Executes SIPp XML scenarios against a running RusTalk instance so existing
+//! carrier certification scenarios can be reused for interop testing. This is
+//! a minimal embedded engine covering the common UAC subset of SIPp: `send`,
+//! `recv` (with `optional` and `timeout`), `pause` and the usual message
+//! keywords. Statistics elements are ignored; anything else is rejected so a
+//! scenario never passes by silently skipping steps.
+
+use anyhow::{bail, Context, Result};
+use rustalk_core::io;
+use rustalk_core::sip::{parser::parse_message, Message};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Default time to wait for a `recv` step
+const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Media port advertised through `[media_port]`
+const MEDIA_PORT: u16 = 6000;
+
+/// What a `recv` step expects
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expect {
+    /// A response with the given status code
+    Response(u16),
+    /// A request with the given method
+    Request(String),
+}
+
+impl Expect {
+    fn matches(&self, message: &Message) -> bool {
+        match (self, message) {
+            (Expect::Response(code), Message::Response(res)) => res.status_code.0 == *code,
+            (Expect::Request(method), Message::Request(req)) => {
+                req.method.to_string().eq_ignore_ascii_case(method)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Expect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expect::Response(code) => write!(f, "{} response", code),
+            Expect::Request(method) => write!(f, "{} request", method),
+        }
+    }
+}
+
+/// A single scenario step
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Send {
+        template: String,
+    },
+    Recv {
+        expect: Expect,
+        optional: bool,
+        timeout: Option<Duration>,
+    },
+    Pause(Duration),
+}
+
+/// A parsed SIPp scenario
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    pub actions: Vec<Action>,
+}
+
+impl Scenario {
+    /// Parse a SIPp XML scenario
+    pub fn parse(xml: &str) -> Result<Self> {
+        // SIPp scenarios conventionally reference sipp.dtd
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let doc = roxmltree::Document::parse_with_options(xml, options)
+            .context("Invalid scenario XML")?;
+        let root = doc.root_element();
+        if root.tag_name().name() != "scenario" {
+            bail!("Root element must be <scenario>");
+        }
+
+        let mut actions = Vec::new();
+        for node in root.children().filter(|n| n.is_element()) {
+            match node.tag_name().name() {
+                "send" => {
+                    let template: String = node
+                        .children()
+                        .filter_map(|c| c.text())
+                        .collect::<Vec<_>>()
+                        .concat();
+                    actions.push(Action::Send { template });
+                }
+                "recv" => {
+                    let expect = if let Some(code) = node.attribute("response") {
+                        Expect::Response(
+                            code.parse()
+                                .with_context(|| format!("Invalid response code: {}", code))?,
+                        )
+                    } else if let Some(method) = node.attribute("request") {
+                        Expect::Request(method.to_string())
+                    } else {
+                        bail!("<recv> needs a response or request attribute");
+                    };
+                    let optional = node.attribute("optional") == Some("true");
+                    let timeout = node
+                        .attribute("timeout")
+                        .map(|ms| ms.parse().map(Duration::from_millis))
+                        .transpose()
+                        .context("Invalid recv timeout")?;
+
+                    actions.push(Action::Recv {
+                        expect,
+                        optional,
+                        timeout,
+                    });
+                }
+                "pause" => {
+                    let ms = node
+                        .attribute("milliseconds")
+                        .unwrap_or("0")
+                        .parse()
+                        .context("Invalid pause duration")?;
+                    actions.push(Action::Pause(Duration::from_millis(ms)));
+                }
+                "nop" | "label" | "ResponseTimeRepartition" | "CallLengthRepartition" => {}
+                other => bail!("Unsupported scenario element <{}>", other),
+            }
+        }
+
+        Ok(Self {
+            name: root.attribute("name").unwrap_or("scenario").to_string(),
+            actions,
+        })
+    }
+
+    /// Load and parse a scenario file
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let xml = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read scenario {}", path.display()))?;
+        Self::parse(&xml)
+    }
+}
+
+/// Summary of a scenario run
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub calls: usize,
+    pub successful: usize,
+    pub failed: usize,
+    /// Failure reason per failed call number
+    pub failures: Vec<(usize, String)>,
+}
+
+/// Runs a scenario against a target
+pub struct ScenarioRunner {
+    scenario: Scenario,
+    target: SocketAddr,
+    service: String,
+}
+
+impl ScenarioRunner {
+    pub fn new(scenario: Scenario, target: SocketAddr) -> Self {
+        Self {
+            scenario,
+            target,
+            service: "service".to_string(),
+        }
+    }
+
+    /// Set the value substituted for `[service]`
+    pub fn with_service(mut self, service: impl Into<String>) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    /// Run `calls` sequential calls
+    pub async fn run(&self, calls: usize) -> RunSummary {
+        let mut summary = RunSummary::default();
+
+        for call_number in 1..=calls {
+            summary.calls += 1;
+            match self.run_call(call_number).await {
+                Ok(()) => summary.successful += 1,
+                Err(e) => {
+                    summary.failed += 1;
+                    summary.failures.push((call_number, e.to_string()));
+                }
+            }
+        }
+
+        summary
+    }
+
+    /// Run a single call through the scenario
+    pub async fn run_call(&self, call_number: usize) -> Result<()> {
+        let socket = UdpSocket::bind(if self.target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })
+        .await?;
+        socket.connect(self.target).await?;
+
+        let mut call = CallContext {
+            call_number,
+            local: socket.local_addr()?,
+            cseq: 0,
+            last_message: None,
+        };
+        let mut pending: Option<Message> = None;
+
+        for (step, action) in self.scenario.actions.iter().enumerate() {
+            match action {
+                Action::Send { template } => {
+                    let message = self.render(template, &mut call);
+                    socket.send(message.as_bytes()).await?;
+                }
+                Action::Recv {
+                    expect,
+                    optional,
+                    timeout,
+                } => {
+                    let message = match pending.take() {
+                        Some(message) => Some(message),
+                        None => recv(&socket, timeout.unwrap_or(DEFAULT_RECV_TIMEOUT)).await?,
+                    };
+
+                    match message {
+                        Some(message) if expect.matches(&message) => {
+                            call.last_message = Some(message);
+                        }
+                        Some(message) if *optional => pending = Some(message),
+                        Some(message) => {
+                            bail!(
+                                "step {}: expected {}, got {}",
+                                step + 1,
+                                expect,
+                                describe(&message)
+                            );
+                        }
+                        None if *optional => {}
+                        None => bail!("step {}: timed out waiting for {}", step + 1, expect),
+                    }
+                }
+                Action::Pause(duration) => tokio::time::sleep(*duration).await,
+            }
+        }
+
+        if let Some(message) = pending {
+            bail!("unexpected {} at end of scenario", describe(&message));
+        }
+
+        Ok(())
+    }
+
+    /// Substitute SIPp keywords and normalise line endings
+    fn render(&self, template: &str, call: &mut CallContext) -> String {
+        let mut text = template.to_string();
+        let local_ip = call.local.ip().to_string();
+
+        let is_ack = text.trim_start().starts_with("ACK");
+        if text.contains("[cseq]") && !is_ack {
+            call.cseq += 1;
+        }
+
+        for (keyword, value) in [
+            ("[service]", self.service.clone()),
+            ("[remote_ip]", self.target.ip().to_string()),
+            ("[remote_port]", self.target.port().to_string()),
+            ("[local_ip]", local_ip.clone()),
+            ("[local_ip_type]", ip_type(&call.local).to_string()),
+            ("[local_port]", call.local.port().to_string()),
+            ("[media_ip]", local_ip.clone()),
+            ("[media_ip_type]", ip_type(&call.local).to_string()),
+            ("[media_port]", MEDIA_PORT.to_string()),
+            ("[transport]", "UDP".to_string()),
+            ("[pid]", std::process::id().to_string()),
+            ("[call_number]", call.call_number.to_string()),
+            (
+                "[call_id]",
+                format!("{}-{}@{}", call.call_number, std::process::id(), local_ip),
+            ),
+            ("[cseq]", call.cseq.max(1).to_string()),
+            (
+                "[branch]",
+                format!(
+                    "z9hG4bK-{}-{}-{}",
+                    std::process::id(),
+                    call.call_number,
+                    call.cseq
+                ),
+            ),
+            ("[peer_tag_param]", call.peer_tag_param()),
+        ] {
+            text = text.replace(keyword, &value);
+        }
+
+        // [last_Header:] copies the full header line from the last message
+        while let Some(start) = text.find("[last_") {
+            let Some(len) = text[start..].find(']') else {
+                break;
+            };
+            let name = text[start + 6..start + len].trim_end_matches(':');
+            let line = call.last_header_line(name).unwrap_or_default();
+            text.replace_range(start..=start + len, &line);
+        }
+
+        finalize_message(&text)
+    }
+}
+
+/// Per-call keyword state
+struct CallContext {
+    call_number: usize,
+    local: SocketAddr,
+    cseq: u32,
+    last_message: Option<Message>,
+}
+
+impl CallContext {
+    fn last_header_line(&self, name: &str) -> Option<String> {
+        self.last_message.as_ref().and_then(|message| {
+            message
+                .headers()
+                .iter()
+                .find(|h| h.name.as_str().eq_ignore_ascii_case(name))
+                .map(|h| format!("{}: {}", h.name, h.value))
+        })
+    }
+
+    fn peer_tag_param(&self) -> String {
+        self.last_message
+            .as_ref()
+            .and_then(|message| {
+                message
+                    .headers()
+                    .iter()
+                    .find(|h| h.name.as_str().eq_ignore_ascii_case("To"))
+                    .and_then(|h| h.value.as_str().split(";tag=").nth(1))
+                    .map(|tag| format!(";tag={}", tag.split(';').next().unwrap_or(tag)))
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Trim SIPp indentation, use CRLF line endings and fix up `[len]`
+fn finalize_message(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let start = lines.iter().position(|l| !l.is_empty()).unwrap_or(0);
+    let lines = &lines[start..];
+
+    let split = lines
+        .iter()
+        .position(|l| l.is_empty())
+        .unwrap_or(lines.len());
+    let headers = &lines[..split];
+    let body_lines: Vec<&str> = lines[split..]
+        .iter()
+        .copied()
+        .skip_while(|l| l.is_empty())
+        .collect();
+    let body_end = body_lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(0, |i| i + 1);
+
+    let body = if body_end == 0 {
+        String::new()
+    } else {
+        format!("{}\r\n", body_lines[..body_end].join("\r\n"))
+    };
+
+    let headers = headers
+        .join("\r\n")
+        .replace("[len]", &body.len().to_string());
+    format!("{}\r\n\r\n{}", headers, body)
+}
+
+fn ip_type(addr: &SocketAddr) -> &'static str {
+    if addr.is_ipv4() {
+        "4"
+    } else {
+        "6"
+    }
+}
+
+async fn recv(socket: &UdpSocket, timeout: Duration) -> Result<Option<Message>> {
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0u8; 65535];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let len = match tokio::time::timeout(remaining, socket.recv(&mut buf)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(None),
+        };
+
+        // Keep-alive CRLFs and garbage are skipped rather than failing the call
+        if let Ok(message) = parse_message(&buf[..len]) {
+            return Ok(Some(message));
+        }
+    }
+}
+
+fn describe(message: &Message) -> String {
+    match message {
+        Message::Request(req) => format!("{} request", req.method),
+        Message::Response(res) => format!("{} response", res.status_code.0),
+    }
+}
+
+/// Run a scenario file and print a SIPp-style summary
+pub async fn run_scenario(
+    scenario_path: &Path,
+    target: &str,
+    calls: usize,
+    service: Option<String>,
+) -> Result<()> {
+    let scenario = Scenario::from_file(scenario_path).await?;
+    let target: SocketAddr = io::dns::lookup(target)
+        .await?
+        .into_iter()
+        .next()
+        .with_context(|| format!("Could not resolve {}", target))?;
+
+    println!(
+        "Running scenario '{}' ({} steps) against {}",
+        scenario.name,
+        scenario.actions.len(),
+        target
+    );
+
+    let mut runner = ScenarioRunner::new(scenario, target);
+    if let Some(service) = service {
+        runner = runner.with_service(service);
+    }
+
+    let summary = runner.run(calls).await;
+
+    println!();
+    println!("  Total calls:      {}", summary.calls);
+    println!("  Successful calls: {}", summary.successful);
+    println!("  Failed calls:     {}", summary.failed);
+    for (call, reason) in &summary.failures {
+        println!("    call {}: {}", call, reason);
+    }
+
+    if summary.failed > 0 {
+        bail!("{} of {} calls failed", summary.failed, summary.calls);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::b2bua::B2BUA;
+    use rustalk_core::transport::{Transport, TransportConfig, UdpTransport};
+    use std::sync::Arc;
+
+    const BASIC_CALL: &str = include_str!("../scenarios/basic_call.xml");
+    const OPTIONS_PING: &str = include_str!("../scenarios/options_ping.xml");
+
+    async fn start_server() -> SocketAddr {
+        let config = TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        };
+        let transport = Arc::new(UdpTransport::new(&config).await.unwrap());
+        let addr = transport.local_addr();
+        let b2bua = B2BUA::new();
+
+        tokio::spawn(async move {
+            while let Ok((message, source)) = transport.receive().await {
+                if let Ok(Some(reply)) = b2bua.handle_message(message).await {
+                    let _ = transport.send(&reply, source).await;
+                }
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_parse_scenario() {
+        let scenario = Scenario::parse(BASIC_CALL).unwrap();
+        assert_eq!(scenario.name, "RusTalk basic call");
+        assert!(matches!(scenario.actions[0], Action::Send { .. }));
+        assert_eq!(
+            scenario.actions[1],
+            Action::Recv {
+                expect: Expect::Response(100),
+                optional: false,
+                timeout: None
+            }
+        );
+        assert!(scenario
+            .actions
+            .iter()
+            .any(|a| matches!(a, Action::Pause(d) if *d == Duration::from_millis(100))));
+    }
+
+    #[test]
+    fn test_parse_rejects_unsupported_elements() {
+        let xml = r#"<scenario name="x"><sendCmd/></scenario>"#;
+        assert!(Scenario::parse(xml).is_err());
+    }
+
+    #[test]
+    fn test_finalize_message() {
+        let text = "\n    OPTIONS sip:a@b SIP/2.0\n    Content-Length: [len]\n\n    v=0\n\n";
+        assert_eq!(
+            finalize_message(text),
+            "OPTIONS sip:a@b SIP/2.0\r\nContent-Length: 5\r\n\r\nv=0\r\n"
+        );
+
+        let text = "ACK sip:a@b SIP/2.0\nContent-Length: 0\n";
+        assert_eq!(
+            finalize_message(text),
+            "ACK sip:a@b SIP/2.0\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_options_scenario() {
+        let target = start_server().await;
+        let runner = ScenarioRunner::new(Scenario::parse(OPTIONS_PING).unwrap(), target);
+
+        let summary = runner.run(3).await;
+        assert_eq!(summary.successful, 3, "failures: {:?}", summary.failures);
+    }
+
+    #[tokio::test]
+    async fn test_run_basic_call_scenario() {
+        let target = start_server().await;
+        let runner =
+            ScenarioRunner::new(Scenario::parse(BASIC_CALL).unwrap(), target).with_service("bob");
+
+        let summary = runner.run(2).await;
+        assert_eq!(summary.successful, 2, "failures: {:?}", summary.failures);
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_unexpected_response() {
+        let target = start_server().await;
+        let xml = r#"<scenario name="expects ringing">
+            <send><![CDATA[
+              INVITE sip:[service]@[remote_ip]:[remote_port] SIP/2.0
+              Call-ID: [call_id]
+              CSeq: 1 INVITE
+              Content-Length: 0
+            ]]></send>
+            <recv response="180" timeout="500"/>
+        </scenario>"#;
+
+        let runner = ScenarioRunner::new(Scenario::parse(xml).unwrap(), target);
+        let summary = runner.run(1).await;
+        assert_eq!(summary.failed, 1);
+        assert!(summary.failures[0]
+            .1
+            .contains("expected 180 response, got 100"));
+    }
+}
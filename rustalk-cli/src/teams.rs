@@ -0,0 +1,70 @@
+//! Teams Direct Routing commands
+
+use anyhow::{anyhow, bail, Result};
+use rustalk_core::direct_routing::{self, CheckStatus, ValidationReport, ValidationTarget};
+use rustalk_core::prelude::Config;
+
+use crate::TeamsCommands;
+
+/// Handle Teams Direct Routing commands
+pub async fn handle_teams_command(cmd: TeamsCommands) -> Result<()> {
+    match cmd {
+        TeamsCommands::Validate {
+            config,
+            test_number,
+            caller,
+            proxy,
+            json,
+        } => {
+            let config = Config::from_file(&config).await?;
+            let teams = config
+                .teams
+                .as_ref()
+                .ok_or_else(|| anyhow!("No 'teams' section in the configuration file"))?;
+            let mut target = ValidationTarget::from_teams(teams);
+            if let Some(number) = test_number {
+                target = target.with_test_number(number);
+            }
+            if let Some(caller) = caller {
+                target = target.with_caller(caller);
+            }
+            if let Some(proxy) = proxy {
+                let port = target.port;
+                target = target.with_proxy(proxy, port);
+            }
+
+            if !json {
+                println!(
+                    "🔍 Validating Direct Routing for {} against {}",
+                    target.sbc_fqdn, target.proxy
+                );
+                println!();
+            }
+            let report = direct_routing::validate(&target).await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_report(&report);
+            }
+            if !report.passed {
+                bail!("Direct Routing validation failed");
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_report(report: &ValidationReport) {
+    for check in &report.checks {
+        let mark = match check.status {
+            CheckStatus::Pass => "✓",
+            CheckStatus::Fail => "✗",
+            CheckStatus::Skipped => "-",
+        };
+        println!("  {} {:<12} {}", mark, check.name, check.detail);
+    }
+    println!();
+    if report.passed {
+        println!("✅ All checks passed");
+    }
+}
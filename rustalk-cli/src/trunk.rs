@@ -0,0 +1,136 @@
+//! Trunk management commands
+
+use anyhow::{Context, Result};
+use serde_json::json;
+
+use crate::api::ApiClient;
+use crate::output::{cell, yes_no, Table};
+use crate::TrunkCommands;
+
+/// Handle trunk management commands
+pub async fn handle_trunk_command(cmd: TrunkCommands) -> Result<()> {
+    match cmd {
+        TrunkCommands::List { json, server } => list_trunks(&server, json).await,
+        TrunkCommands::Add {
+            name,
+            host,
+            port,
+            username,
+            password,
+            description,
+            id,
+            priority,
+            quirks,
+            json,
+            server,
+        } => {
+            let trunk = json!({
+                "id": id.unwrap_or_else(|| name.clone()),
+                "name": name,
+                "description": description,
+                "host": host,
+                "port": port,
+                "username": username,
+                "password": password,
+                "enabled": true,
+                "priority": priority,
+                "quirks": quirks,
+            });
+            ApiClient::new(&server).post("/trunks", &trunk).await?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&trunk)?);
+            } else {
+                println!(
+                    "✓ Created trunk '{}' ({}:{})",
+                    cell(&trunk["name"]),
+                    cell(&trunk["host"]),
+                    port
+                );
+            }
+            Ok(())
+        }
+        TrunkCommands::Delete { id, server } => {
+            let response = ApiClient::new(&server)
+                .delete_any_version(&format!("/trunks/{}", id))
+                .await?;
+            println!("✓ Deleted trunk '{}'", id);
+            if let Some(purge_at) = response["purge_at"].as_str() {
+                println!("  Restorable until {}", purge_at);
+            }
+            Ok(())
+        }
+        TrunkCommands::Restore { id, server } => {
+            ApiClient::new(&server)
+                .post(&format!("/trunks/{}/restore", id), &json!({}))
+                .await?;
+            println!("✓ Restored trunk '{}'", id);
+            Ok(())
+        }
+        TrunkCommands::SetPassword {
+            id,
+            password,
+            username,
+            server,
+        } => {
+            ApiClient::new(&server)
+                .update(&format!("/trunks/{}", id), |trunk| {
+                    trunk["password"] = json!(password);
+                    if let Some(username) = username {
+                        trunk["username"] = json!(username);
+                    }
+                })
+                .await?;
+            println!("✓ Updated credentials for trunk '{}'", id);
+            Ok(())
+        }
+        TrunkCommands::Enable { id, server } => set_enabled(&server, &id, true).await,
+        TrunkCommands::Disable { id, server } => set_enabled(&server, &id, false).await,
+    }
+}
+
+async fn list_trunks(server: &str, as_json: bool) -> Result<()> {
+    let response = ApiClient::new(server).get("/trunks").await?;
+    let trunks = response["trunks"]
+        .as_array()
+        .context("Unexpected trunk list response")?;
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(trunks)?);
+        return Ok(());
+    }
+
+    let mut table = Table::new(&["ID", "NAME", "HOST", "PORT", "USERNAME", "ENABLED"]);
+    for trunk in trunks {
+        table.add_row(vec![
+            cell(&trunk["id"]),
+            cell(&trunk["name"]),
+            cell(&trunk["host"]),
+            cell(&trunk["port"]),
+            cell(&trunk["username"]),
+            yes_no(&trunk["enabled"]),
+        ]);
+    }
+
+    if table.is_empty() {
+        println!("No trunks configured");
+    } else {
+        table.print();
+    }
+    Ok(())
+}
+
+async fn set_enabled(server: &str, id: &str, enabled: bool) -> Result<()> {
+    ApiClient::new(server)
+        .update(&format!("/trunks/{}", id), |trunk| {
+            trunk["enabled"] = json!(enabled)
+        })
+        .await?;
+
+    println!(
+        "✓ Trunk '{}' {}",
+        id,
+        if enabled { "enabled" } else { "disabled" }
+    );
+    Ok(())
+}
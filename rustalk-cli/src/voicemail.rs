@@ -0,0 +1,175 @@
+//! Voicemail administration commands
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use rustalk_core::voicemail::{MwiStatus, VoicemailBox, VoicemailMessage};
+use serde_json::json;
+
+use crate::api::ApiClient;
+use crate::VoicemailCommands;
+
+/// Handle voicemail administration commands
+pub async fn handle_voicemail_command(cmd: VoicemailCommands) -> Result<()> {
+    match cmd {
+        VoicemailCommands::List { server } => list_mailboxes(&server).await,
+        VoicemailCommands::Create {
+            mailbox,
+            extension,
+            name,
+            pin,
+            email,
+            max_messages,
+            server,
+        } => {
+            let mut mailbox = VoicemailBox {
+                extension: extension.unwrap_or_else(|| mailbox.clone()),
+                id: mailbox,
+                name,
+                pin: pin.unwrap_or_else(generate_pin),
+                email,
+                ..Default::default()
+            };
+            if let Some(max_messages) = max_messages {
+                mailbox.max_messages = max_messages;
+            }
+            create_mailbox(&server, mailbox).await
+        }
+        VoicemailCommands::Delete { mailbox, server } => delete_mailbox(&server, &mailbox).await,
+        VoicemailCommands::ResetPin {
+            mailbox,
+            pin,
+            server,
+        } => reset_pin(&server, &mailbox, pin).await,
+        VoicemailCommands::Messages { mailbox, server } => list_messages(&server, &mailbox).await,
+    }
+}
+
+async fn list_mailboxes(server: &str) -> Result<()> {
+    let response = ApiClient::new(server).get("/voicemail").await?;
+    let mailboxes: Vec<VoicemailBox> = serde_json::from_value(response["mailboxes"].clone())
+        .context("Unexpected mailbox list response")?;
+
+    if mailboxes.is_empty() {
+        println!("No mailboxes configured");
+        return Ok(());
+    }
+
+    println!(
+        "{:<12} {:<10} {:<24} {:<8} EMAIL",
+        "MAILBOX", "EXTENSION", "NAME", "ENABLED"
+    );
+    for mailbox in mailboxes {
+        println!(
+            "{:<12} {:<10} {:<24} {:<8} {}",
+            mailbox.id,
+            mailbox.extension,
+            mailbox.name,
+            if mailbox.enabled { "yes" } else { "no" },
+            mailbox.email.as_deref().unwrap_or("-")
+        );
+    }
+
+    Ok(())
+}
+
+async fn create_mailbox(server: &str, mailbox: VoicemailBox) -> Result<()> {
+    ApiClient::new(server).post("/voicemail", &mailbox).await?;
+
+    println!(
+        "✓ Created mailbox '{}' for extension {}",
+        mailbox.id, mailbox.extension
+    );
+    println!("  PIN: {}", mailbox.pin);
+    Ok(())
+}
+
+async fn delete_mailbox(server: &str, mailbox: &str) -> Result<()> {
+    ApiClient::new(server)
+        .delete(&format!("/voicemail/{}", mailbox))
+        .await?;
+
+    println!("✓ Deleted mailbox '{}' and its messages", mailbox);
+    Ok(())
+}
+
+async fn reset_pin(server: &str, mailbox: &str, pin: Option<String>) -> Result<()> {
+    let pin = pin.unwrap_or_else(generate_pin);
+    ApiClient::new(server)
+        .post(
+            &format!("/voicemail/{}/pin", mailbox),
+            &json!({ "pin": pin }),
+        )
+        .await?;
+
+    println!("✓ Reset PIN for mailbox '{}'", mailbox);
+    println!("  New PIN: {}", pin);
+    Ok(())
+}
+
+async fn list_messages(server: &str, mailbox: &str) -> Result<()> {
+    let client = ApiClient::new(server);
+    let mwi: MwiStatus =
+        serde_json::from_value(client.get(&format!("/voicemail/{}/mwi", mailbox)).await?)
+            .context("Unexpected MWI response")?;
+    let response = client
+        .get(&format!("/voicemail/{}/messages", mailbox))
+        .await?;
+    let messages: Vec<VoicemailMessage> = serde_json::from_value(response["messages"].clone())
+        .context("Unexpected message list response")?;
+
+    println!(
+        "Mailbox '{}': {} new, {} old",
+        mailbox, mwi.new_messages, mwi.old_messages
+    );
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!(
+        "{:<36} {:<20} {:<20} {:>6}  FLAGS",
+        "ID", "RECEIVED", "FROM", "SECS"
+    );
+    for message in messages {
+        let from = match &message.from_name {
+            Some(name) => format!("{} <{}>", name, message.from_number),
+            None => message.from_number.clone(),
+        };
+        let mut flags = Vec::new();
+        if !message.read {
+            flags.push("new");
+        }
+        if message.urgent {
+            flags.push("urgent");
+        }
+        println!(
+            "{:<36} {:<20} {:<20} {:>6}  {}",
+            message.id,
+            message.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            from,
+            message.duration,
+            flags.join(",")
+        );
+    }
+
+    Ok(())
+}
+
+/// Random 6 digit PIN for new mailboxes and resets
+fn generate_pin() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_pin() {
+        for _ in 0..100 {
+            let pin = generate_pin();
+            assert_eq!(pin.len(), 6);
+            assert!(pin.chars().all(|c| c.is_ascii_digit()));
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Embeds the built web UI when the `embed-webui` feature is enabled
+//!
+//! The bundle is read from `RUSTALK_WEBUI_DIST`, or `rustalk-webui/dist`
+//! by default, and written to `$OUT_DIR/webui_assets.rs` as a table of
+//! paths and `include_bytes!` contents. Without the feature the table is
+//! empty.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=RUSTALK_WEBUI_DIST");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("webui_assets.rs");
+
+    let mut assets = Vec::new();
+    if env::var_os("CARGO_FEATURE_EMBED_WEBUI").is_some() {
+        let dist = env::var_os("RUSTALK_WEBUI_DIST")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../rustalk-webui/dist")
+            });
+        let dist = dist.canonicalize().unwrap_or_else(|_| {
+            panic!(
+                "WebUI bundle not found at {}; run `npm run build` in rustalk-webui or set RUSTALK_WEBUI_DIST",
+                dist.display()
+            )
+        });
+        println!("cargo:rerun-if-changed={}", dist.display());
+        collect(&dist, &dist, &mut assets);
+        assets.sort();
+    }
+
+    let mut table = String::from("static ASSETS: &[(&str, &[u8])] = &[\n");
+    for (name, path) in assets {
+        table.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name,
+            path.display().to_string()
+        ));
+    }
+    table.push_str("];\n");
+    fs::write(out, table).unwrap();
+}
+
+/// Every file under `dir`, named by its `/`-separated path from `root`
+fn collect(root: &Path, dir: &Path, assets: &mut Vec<(String, PathBuf)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        if path.is_dir() {
+            collect(root, &path, assets);
+        } else {
+            let name = path
+                .strip_prefix(root)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+            assets.push((name, path));
+        }
+    }
+}
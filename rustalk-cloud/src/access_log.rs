@@ -0,0 +1,304 @@
+//! Access logs and latency histograms for API requests
+//!
+//! Every request is logged once it has been answered, as a structured
+//! event on the `rustalk::access` target with the method, path, status,
+//! latency and the principal that made it. Latencies are also counted in a
+//! histogram per route - the route pattern such as `/api/v1/extensions/:id`,
+//! not each concrete path - which `GET /api/v1/metrics` reports, so slow
+//! API operations stand out.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header::AUTHORIZATION, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use base64::Engine;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Upper bounds of the latency buckets, in milliseconds
+pub const BUCKET_BOUNDS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Route label of requests no route matched
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Who made a request, set as a request extension by authentication
+///
+/// Requests without one are attributed to the user of their Basic
+/// credentials, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal(pub String);
+
+/// Latencies of one route
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    /// Requests per bucket of [`BUCKET_BOUNDS_MS`], then over the last bound
+    buckets: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    count: u64,
+    /// Answered with a 5xx
+    errors: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn observe(&mut self, latency: Duration, status: StatusCode) {
+        let ms = latency.as_millis() as u64;
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        if status.is_server_error() {
+            self.errors += 1;
+        }
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Upper bound of the bucket holding the given percentile (0-100),
+    /// or the slowest request for the overflow bucket
+    pub fn percentile_ms(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKET_BOUNDS_MS
+                    .get(bucket)
+                    .copied()
+                    .unwrap_or(self.max.as_millis() as u64);
+            }
+        }
+        self.max.as_millis() as u64
+    }
+}
+
+/// One bucket of a route's histogram
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketCount {
+    /// Upper bound in milliseconds, `None` for the overflow bucket
+    pub le_ms: Option<u64>,
+    /// Requests at or under the bound
+    pub count: u64,
+}
+
+/// Latency summary of one route
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteLatency {
+    pub method: String,
+    pub route: String,
+    pub count: u64,
+    pub errors: u64,
+    pub mean_ms: f64,
+    pub max_ms: u64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+    /// Cumulative counts per bucket
+    pub buckets: Vec<BucketCount>,
+}
+
+/// Latency histograms per route, shared by the middleware and the metrics
+/// endpoint
+#[derive(Clone, Default)]
+pub struct RouteMetrics {
+    routes: Arc<Mutex<BTreeMap<(String, String), LatencyHistogram>>>,
+}
+
+impl RouteMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, method: &Method, route: &str, status: StatusCode, latency: Duration) {
+        self.routes
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(latency, status);
+    }
+
+    /// Summary of every route called so far, by route then method
+    pub fn snapshot(&self) -> Vec<RouteLatency> {
+        let routes = self.routes.lock().unwrap();
+        let mut snapshot: Vec<RouteLatency> = routes
+            .iter()
+            .map(|((method, route), histogram)| {
+                let mut cumulative = 0;
+                let buckets = histogram
+                    .buckets
+                    .iter()
+                    .enumerate()
+                    .map(|(bucket, count)| {
+                        cumulative += count;
+                        BucketCount {
+                            le_ms: BUCKET_BOUNDS_MS.get(bucket).copied(),
+                            count: cumulative,
+                        }
+                    })
+                    .collect();
+                RouteLatency {
+                    method: method.clone(),
+                    route: route.clone(),
+                    count: histogram.count,
+                    errors: histogram.errors,
+                    mean_ms: histogram.total.as_secs_f64() * 1000.0 / histogram.count as f64,
+                    max_ms: histogram.max.as_millis() as u64,
+                    p50_ms: histogram.percentile_ms(50.0),
+                    p95_ms: histogram.percentile_ms(95.0),
+                    p99_ms: histogram.percentile_ms(99.0),
+                    buckets,
+                }
+            })
+            .collect();
+        snapshot.sort_by(|a, b| (&a.route, &a.method).cmp(&(&b.route, &b.method)));
+        snapshot
+    }
+}
+
+/// Principal of a request: its [`Principal`] extension, else the user of
+/// its Basic credentials
+fn principal(request: &Request) -> Option<String> {
+    if let Some(Principal(name)) = request.extensions().get::<Principal>() {
+        return Some(name.clone());
+    }
+    let credentials = request
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, _) = decoded.split_once(':')?;
+    Some(user.to_string())
+}
+
+/// Middleware logging each request and timing it against its route
+pub async fn access_log(
+    State(metrics): State<RouteMetrics>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+    let principal = principal(&request);
+
+    let response = next.run(request).await;
+    let latency = started.elapsed();
+    let status = response.status();
+    metrics.observe(&method, &route, status, latency);
+    info!(
+        target: "rustalk::access",
+        method = %method,
+        path = %path,
+        route = %route,
+        status = status.as_u16(),
+        latency_ms = latency.as_secs_f64() * 1000.0,
+        principal = principal.as_deref().unwrap_or("-"),
+        "{} {} {}",
+        method,
+        path,
+        status.as_u16()
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware::from_fn_with_state, routing::get, Router};
+    use tower::Service;
+
+    fn app(metrics: RouteMetrics) -> Router {
+        Router::new()
+            .route("/api/v1/extensions/:id", get(|| async { "1001" }))
+            .route(
+                "/api/v1/fail",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .layer(from_fn_with_state(metrics, access_log))
+    }
+
+    #[tokio::test]
+    async fn test_latency_per_route() {
+        let metrics = RouteMetrics::new();
+        let mut app = app(metrics.clone());
+        for uri in [
+            "/api/v1/extensions/1001",
+            "/api/v1/extensions/1002",
+            "/api/v1/fail",
+        ] {
+            let request = Request::get(uri)
+                .header(AUTHORIZATION, "Basic YWRtaW46c2VjcmV0")
+                .body(Body::empty())
+                .unwrap();
+            app.call(request).await.unwrap();
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        // Concrete paths are counted under their route
+        assert_eq!(snapshot[0].route, "/api/v1/extensions/:id");
+        assert_eq!(snapshot[0].method, "GET");
+        assert_eq!(snapshot[0].count, 2);
+        assert_eq!(snapshot[0].errors, 0);
+        assert_eq!(snapshot[0].buckets.last().unwrap().le_ms, None);
+        assert_eq!(snapshot[0].buckets.last().unwrap().count, 2);
+        assert_eq!(snapshot[1].route, "/api/v1/fail");
+        assert_eq!(snapshot[1].errors, 1);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        for ms in [3, 4, 20, 40, 90, 200, 30000] {
+            histogram.observe(Duration::from_millis(ms), StatusCode::OK);
+        }
+        histogram.observe(Duration::from_millis(7), StatusCode::OK);
+        assert_eq!(histogram.count(), 8);
+        assert_eq!(histogram.percentile_ms(50.0), 25);
+        assert_eq!(histogram.percentile_ms(75.0), 100);
+        // Beyond the last bucket the slowest request stands in
+        assert_eq!(histogram.percentile_ms(99.0), 30000);
+        assert_eq!(LatencyHistogram::default().percentile_ms(50.0), 0);
+    }
+
+    #[test]
+    fn test_principal() {
+        let request = Request::get("/")
+            .header(AUTHORIZATION, "Basic YWRtaW46c2VjcmV0")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(principal(&request).as_deref(), Some("admin"));
+
+        let mut request = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(principal(&request), None);
+        request
+            .extensions_mut()
+            .insert(Principal("provisioning".to_string()));
+        assert_eq!(principal(&request).as_deref(), Some("provisioning"));
+    }
+}
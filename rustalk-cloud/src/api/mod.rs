@@ -1,19 +1,58 @@
 //! REST API service implementation
 
+use crate::access_log::{access_log, RouteMetrics};
+use crate::caching::api_cache_control;
+use crate::call_log_store::CallLogStore;
 use crate::handlers::{self, certificates::AcmeState};
-use crate::models::{Did, Extension, RingGroup, Route, SipProfile, Trunk};
+use crate::idempotency::{idempotency, IdempotencyCache};
+use crate::models::{Did, Extension, ExtensionGroup, RingGroup, Route, SipProfile, Trunk};
+use crate::trash::Trash;
+use crate::webui::{serve_webui, WebUi};
 use axum::{
+    middleware,
     routing::{delete, get, post, put},
     Router,
 };
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
-use tower_http::services::ServeDir;
+use tower_http::compression::CompressionLayer;
 use tracing::info;
 
+use rustalk_core::acl::{create_default_acls, AclManager};
 use rustalk_core::acme::AcmeClient;
+use rustalk_core::b2bua::B2BUA;
+use rustalk_core::call_history::CallHistory;
+use rustalk_core::call_trace::CallTracer;
+use rustalk_core::cert_expiry::CertificateMonitor;
+use rustalk_core::config::ConfigReloader;
+use rustalk_core::cos::CosConfig;
+use rustalk_core::dialer::Dialer;
+use rustalk_core::direct_routing::ValidationTarget;
+use rustalk_core::domains::DomainMap;
+use rustalk_core::events::EventBus;
+use rustalk_core::fax::FaxService;
+use rustalk_core::ivr::IvrFlow;
+use rustalk_core::lcr::LeastCostRouter;
+use rustalk_core::locale::PromptSet;
 use rustalk_core::media::CodecConfig;
+use rustalk_core::media_quality::MediaQualityTracker;
+use rustalk_core::nat::NatPolicy;
+use rustalk_core::no_answer::NoAnswerPolicy;
+use rustalk_core::pdd::PddTracker;
+use rustalk_core::presence::BlfLists;
+use rustalk_core::quotas::Quotas;
+use rustalk_core::registrar::Registrar;
+use rustalk_core::routing::RouteTestCase;
+use rustalk_core::schedules::Schedule;
+use rustalk_core::sms::SmsGateway;
+use rustalk_core::supervisor::Supervisor;
+use rustalk_core::teams_records::TeamsCallRecords;
+use rustalk_core::transport::bind_tcp;
+use rustalk_core::trunk_health::TrunkHealth;
+use rustalk_core::voicemail::VoicemailManager;
+use rustalk_core::webhooks::WebhookDispatcher;
 
 /// Cloud API server
 pub struct CloudApi {
@@ -21,12 +60,47 @@ pub struct CloudApi {
     webui_path: Option<String>,
     acme_client: Option<AcmeClient>,
     codec_config: CodecConfig,
+    acls: AclManager,
+    voicemail: VoicemailManager,
+    no_answer: NoAnswerPolicy,
+    nat: NatPolicy,
+    domains: DomainMap,
+    blf_lists: BlfLists,
     dids: Vec<Did>,
     extensions: Vec<Extension>,
     trunks: Vec<Trunk>,
     ring_groups: Vec<RingGroup>,
+    groups: Vec<ExtensionGroup>,
+    schedules: Vec<Schedule>,
+    ivr_flows: Vec<IvrFlow>,
     routes: Vec<Route>,
+    route_tests: Vec<RouteTestCase>,
     sip_profiles: Vec<SipProfile>,
+    call_tracer: Arc<CallTracer>,
+    registrar: Registrar,
+    b2bua: B2BUA,
+    config_reloader: Option<ConfigReloader>,
+    cos: CosConfig,
+    sms: Option<SmsGateway>,
+    teams_records: Option<TeamsCallRecords>,
+    teams_validation: Option<ValidationTarget>,
+    events: Option<EventBus>,
+    webhooks: Option<WebhookDispatcher>,
+    call_history: Option<CallHistory>,
+    certificate_monitor: Option<CertificateMonitor>,
+    pdd: Option<PddTracker>,
+    media_quality: Option<MediaQualityTracker>,
+    trunk_health: Option<TrunkHealth>,
+    least_cost: LeastCostRouter,
+    quotas: Quotas,
+    fax: Option<FaxService>,
+    dialer: Option<Dialer>,
+    prompts: Option<PromptSet>,
+    call_logs: Option<CallLogStore>,
+    supervisor: Option<Supervisor>,
+    reuse_port: bool,
+    idempotency_ttl: Duration,
+    trash_retention: Duration,
 }
 
 impl CloudApi {
@@ -36,16 +110,52 @@ impl CloudApi {
             webui_path: None,
             acme_client: None,
             codec_config: CodecConfig::default(),
+            acls: create_default_acls(),
+            voicemail: VoicemailManager::new("/var/lib/rustalk/voicemail"),
+            no_answer: NoAnswerPolicy::default(),
+            nat: NatPolicy::default(),
+            domains: DomainMap::default(),
+            blf_lists: BlfLists::default(),
             dids: Vec::new(),
             extensions: Vec::new(),
             trunks: Vec::new(),
             ring_groups: Vec::new(),
+            groups: Vec::new(),
+            schedules: Vec::new(),
+            ivr_flows: Vec::new(),
             routes: Vec::new(),
+            route_tests: Vec::new(),
             sip_profiles: Vec::new(),
+            call_tracer: Arc::new(CallTracer::new("/var/log/rustalk/traces")),
+            registrar: Registrar::new(),
+            b2bua: B2BUA::new(),
+            config_reloader: None,
+            cos: CosConfig::default(),
+            sms: None,
+            teams_records: None,
+            teams_validation: None,
+            events: None,
+            webhooks: None,
+            call_history: None,
+            certificate_monitor: None,
+            pdd: None,
+            media_quality: None,
+            trunk_health: None,
+            least_cost: LeastCostRouter::default(),
+            quotas: Quotas::default(),
+            fax: None,
+            dialer: None,
+            prompts: None,
+            call_logs: None,
+            supervisor: None,
+            reuse_port: false,
+            idempotency_ttl: crate::idempotency::DEFAULT_TTL,
+            trash_retention: crate::trash::DEFAULT_RETENTION,
         }
     }
 
-    /// Set the path to the WebUI static files
+    /// Serve the WebUI from a directory of built files instead of the
+    /// bundle embedded in the binary
     pub fn with_webui_path(mut self, path: String) -> Self {
         self.webui_path = Some(path);
         self
@@ -63,25 +173,323 @@ impl CloudApi {
         self
     }
 
+    /// Set the access control lists
+    pub fn with_acls(mut self, acls: AclManager) -> Self {
+        self.acls = acls;
+        self
+    }
+
+    /// Set the voicemail manager
+    pub fn with_voicemail_manager(mut self, manager: VoicemailManager) -> Self {
+        self.voicemail = manager;
+        self
+    }
+
+    /// Share the B2BUA's ring timeouts so extension updates apply to new
+    /// calls
+    pub fn with_no_answer_policy(mut self, policy: NoAnswerPolicy) -> Self {
+        self.no_answer = policy;
+        self
+    }
+
+    /// Share the SIP profiles' NAT settings so profile updates apply to
+    /// new registrations
+    pub fn with_nat_policy(mut self, policy: NatPolicy) -> Self {
+        self.nat = policy;
+        self
+    }
+
+    /// Share the domains the registrar and B2BUA serve so profile updates
+    /// apply to new registrations and calls
+    pub fn with_domains(mut self, domains: DomainMap) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Share the BLF lists the resource list server serves so list changes
+    /// apply to new subscriptions
+    pub fn with_blf_lists(mut self, lists: BlfLists) -> Self {
+        self.blf_lists = lists;
+        self
+    }
+
+    /// Share the call tracer used by the B2BUA so traces can be managed remotely
+    pub fn with_call_tracer(mut self, tracer: Arc<CallTracer>) -> Self {
+        self.call_tracer = tracer;
+        self
+    }
+
+    /// Share the SIP registrar so live registrations can be listed
+    pub fn with_registrar(mut self, registrar: Registrar) -> Self {
+        self.registrar = registrar;
+        self
+    }
+
+    /// Share the B2BUA so live channels can be listed
+    pub fn with_b2bua(mut self, b2bua: B2BUA) -> Self {
+        self.b2bua = b2bua;
+        self
+    }
+
+    /// Enable runtime reloads of ACLs, codecs and routes from the config file
+    pub fn with_config_reloader(mut self, reloader: ConfigReloader) -> Self {
+        self.config_reloader = Some(reloader);
+        self
+    }
+
+    /// Set the class of service profiles and assignments
+    pub fn with_class_of_service(mut self, config: CosConfig) -> Self {
+        self.cos = config;
+        self
+    }
+
+    /// Send and receive SMS through the gateway
+    pub fn with_sms_gateway(mut self, gateway: SmsGateway) -> Self {
+        self.sms = Some(gateway);
+        self
+    }
+
+    /// Report on Teams call records imported from Microsoft Graph
+    pub fn with_teams_call_records(mut self, records: TeamsCallRecords) -> Self {
+        self.teams_records = Some(records);
+        self
+    }
+
+    /// Validate this SBC's Teams Direct Routing setup on request
+    pub fn with_teams_validation(mut self, target: ValidationTarget) -> Self {
+        self.teams_validation = Some(target);
+        self
+    }
+
+    /// Stream call, registration and alarm events to dashboards and
+    /// screen-pop apps
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// List and test-deliver the outbound webhooks
+    pub fn with_webhooks(mut self, dispatcher: WebhookDispatcher) -> Self {
+        self.webhooks = Some(dispatcher);
+        self
+    }
+
+    /// List each extension's recent calls from the recorded CDRs
+    pub fn with_call_history(mut self, history: CallHistory) -> Self {
+        self.call_history = Some(history);
+        self
+    }
+
+    /// Report certificate expiry warnings from the health check
+    pub fn with_certificate_monitor(mut self, monitor: CertificateMonitor) -> Self {
+        self.certificate_monitor = Some(monitor);
+        self
+    }
+
+    /// Report post-dial delay percentiles from the analytics API
+    pub fn with_pdd_tracker(mut self, tracker: PddTracker) -> Self {
+        self.pdd = Some(tracker);
+        self
+    }
+
+    /// Report packet loss and jitter per trunk from the analytics API
+    pub fn with_media_quality(mut self, tracker: MediaQualityTracker) -> Self {
+        self.media_quality = Some(tracker);
+        self
+    }
+
+    /// Report the state of each trunk sent OPTIONS
+    pub fn with_trunk_health(mut self, health: TrunkHealth) -> Self {
+        self.trunk_health = Some(health);
+        self
+    }
+
+    /// Cost simulated calls with these rate cards
+    pub fn with_least_cost(mut self, router: LeastCostRouter) -> Self {
+        self.least_cost = router;
+        self
+    }
+
+    /// Enforce tenant quotas on extensions and trunks and report usage
+    /// against them from the analytics API
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Bridge faxes between the T.38 gateway and email
+    pub fn with_fax_service(mut self, fax: FaxService) -> Self {
+        self.fax = Some(fax);
+        self
+    }
+
+    /// Manage outbound campaigns and hand their calls to an originator
+    pub fn with_dialer(mut self, dialer: Dialer) -> Self {
+        self.dialer = Some(dialer);
+        self
+    }
+
+    /// Manage per-locale prompt recordings
+    pub fn with_prompts(mut self, prompts: PromptSet) -> Self {
+        self.prompts = Some(prompts);
+        self
+    }
+
+    /// Keep call logs POSTed by SBCs from their call detail records
+    pub fn with_call_logs(mut self, store: CallLogStore) -> Self {
+        self.call_logs = Some(store);
+        self
+    }
+
+    /// Report the components run by this supervisor on `/api/v1/status`
+    pub fn with_supervisor(mut self, supervisor: Supervisor) -> Self {
+        self.supervisor = Some(supervisor);
+        self
+    }
+
+    /// Bind with `SO_REUSEPORT` so a new process can take over the port
+    pub fn with_reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Set the extension groups
+    pub fn with_groups(mut self, groups: Vec<ExtensionGroup>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    /// Set the stored route regression test cases
+    pub fn with_route_tests(mut self, cases: Vec<RouteTestCase>) -> Self {
+        self.route_tests = cases;
+        self
+    }
+
+    /// Set the named schedules routes and ring groups refer to
+    pub fn with_schedules(mut self, schedules: Vec<Schedule>) -> Self {
+        self.schedules = schedules;
+        self
+    }
+
+    /// Set the IVR flows the builder edits
+    pub fn with_ivr_flows(mut self, flows: Vec<IvrFlow>) -> Self {
+        self.ivr_flows = flows;
+        self
+    }
+
+    /// Set how long responses to POSTs with an `Idempotency-Key` are replayed
+    pub fn with_idempotency_ttl(mut self, ttl: Duration) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    /// Set how long deleted extensions, DIDs and trunks can be restored
+    pub fn with_trash_retention(mut self, retention: Duration) -> Self {
+        self.trash_retention = retention;
+        self
+    }
+
     /// Build the API router
+    #[allow(clippy::too_many_arguments)]
     fn router(
-        webui_path: Option<String>,
+        webui: Option<WebUi>,
+        health_state: handlers::HealthState,
         acme_state: AcmeState,
         codec_state: Arc<RwLock<CodecConfig>>,
+        acls_state: handlers::acls::AclsState,
+        voicemail_state: handlers::voicemail::VoicemailState,
         dids_state: Arc<RwLock<Vec<Did>>>,
         extensions_state: Arc<RwLock<Vec<Extension>>>,
+        no_answer: NoAnswerPolicy,
         trunks_state: Arc<RwLock<Vec<Trunk>>>,
+        dids_trash: Trash<Did>,
+        extensions_trash: Trash<Extension>,
+        trunks_trash: Trash<Trunk>,
+        trunk_health_state: handlers::trunks::TrunkHealthState,
+        quotas: Quotas,
         ring_groups_state: Arc<RwLock<Vec<RingGroup>>>,
+        groups_state: handlers::groups::GroupsState,
+        schedules_state: handlers::schedules::SchedulesState,
+        ivr_state: handlers::ivr::IvrState,
         routes_state: Arc<RwLock<Vec<Route>>>,
+        route_tests_state: handlers::routes::RouteTestsState,
+        simulation_state: handlers::routes::SimulationState,
         sip_profiles_state: Arc<RwLock<Vec<SipProfile>>>,
+        nat: NatPolicy,
+        domains: DomainMap,
+        debug_state: handlers::debug::DebugState,
+        registrations_state: handlers::registrations::RegistrationsState,
+        channels_state: handlers::channels::ChannelsState,
+        reload_state: handlers::reload::ReloadState,
+        cos_state: handlers::cos::CosState,
+        messages_state: handlers::messages::MessagesState,
+        analytics_state: handlers::analytics::AnalyticsState,
+        pdd_state: handlers::analytics::PddState,
+        media_quality_state: handlers::analytics::MediaQualityState,
+        tenants_state: handlers::analytics::TenantsState,
+        events_state: handlers::events::EventsState,
+        webhooks_state: handlers::webhooks::WebhooksState,
+        fax_state: handlers::fax::FaxState,
+        campaigns_state: handlers::campaigns::CampaignsState,
+        prompts_state: handlers::prompts::PromptsState,
+        call_logs_state: handlers::call_logs::CallLogsState,
+        search_state: handlers::search::SearchState,
+        devices_state: handlers::devices::DevicesState,
+        call_history_state: handlers::call_history::CallHistoryState,
+        teams_state: handlers::teams::TeamsValidationState,
+        status_state: handlers::status::StatusState,
+        idempotency_cache: IdempotencyCache,
+        metrics: RouteMetrics,
     ) -> Router {
+        let apply_state = handlers::apply::ApplyState {
+            extensions: extensions_state.clone(),
+            trunks: trunks_state.clone(),
+            routes: routes_state.clone(),
+            acls: acls_state.clone(),
+            extensions_trash: extensions_trash.clone(),
+            trunks_trash: trunks_trash.clone(),
+            no_answer: no_answer.clone(),
+            quotas: quotas.clone(),
+        };
         let mut app = Router::new()
-            .route("/health", get(handlers::health))
+            .route("/health", get(handlers::health).with_state(health_state))
+            .route(
+                "/ready",
+                get(handlers::status::ready).with_state(status_state.clone()),
+            )
             .route("/api/v1/calls", get(handlers::list_calls))
             .route("/api/v1/calls/:id", get(handlers::get_call))
             .route("/api/v1/config", get(handlers::get_config))
             .route("/api/v1/config", post(handlers::update_config))
+            .route(
+                "/api/v1/config/reload",
+                post(handlers::reload::reload_config).with_state(reload_state),
+            )
+            .route(
+                "/api/v1/apply",
+                post(handlers::apply::apply).with_state(apply_state),
+            )
+            .route(
+                "/api/v1/schema/config",
+                get(handlers::schema::get_config_schema),
+            )
+            .route(
+                "/api/v1/schema/models",
+                get(handlers::schema::get_models_schema),
+            )
             .route("/api/v1/stats", get(handlers::get_stats))
+            .route(
+                "/api/v1/metrics",
+                get(handlers::metrics::get_metrics).with_state(metrics.clone()),
+            )
+            .route(
+                "/api/v1/status",
+                get(handlers::status::get_status).with_state(status_state),
+            )
+            .route(
+                "/api/v1/search",
+                get(handlers::search::search).with_state(search_state),
+            )
             // Certificate management endpoints
             .route(
                 "/api/v1/certificates",
@@ -102,11 +510,17 @@ impl CloudApi {
             // Call logs and ratings endpoints
             .route(
                 "/api/v1/call-logs",
-                get(handlers::call_logs::list_call_logs),
+                get(handlers::call_logs::list_call_logs)
+                    .post(handlers::call_logs::ingest_call_log)
+                    .with_state(call_logs_state.clone()),
             )
             .route(
                 "/api/v1/call-logs/:id",
-                get(handlers::call_logs::get_call_log),
+                get(handlers::call_logs::get_call_log).with_state(call_logs_state.clone()),
+            )
+            .route(
+                "/api/v1/call-logs/account-codes",
+                get(handlers::call_logs::account_code_costs).with_state(call_logs_state),
             )
             .route(
                 "/api/v1/call-logs/export",
@@ -163,7 +577,16 @@ impl CloudApi {
             )
             .route(
                 "/api/v1/dids/:id",
-                delete(handlers::dids::delete_did).with_state(dids_state.clone()),
+                delete(handlers::dids::delete_did)
+                    .with_state((dids_state.clone(), dids_trash.clone())),
+            )
+            .route(
+                "/api/v1/dids/deleted",
+                get(handlers::dids::list_deleted_dids).with_state(dids_trash.clone()),
+            )
+            .route(
+                "/api/v1/dids/:id/restore",
+                post(handlers::dids::restore_did).with_state((dids_state.clone(), dids_trash)),
             )
             .route(
                 "/api/v1/dids/reorder",
@@ -180,15 +603,42 @@ impl CloudApi {
             )
             .route(
                 "/api/v1/extensions",
-                post(handlers::extensions::create_extension).with_state(extensions_state.clone()),
+                post(handlers::extensions::create_extension).with_state((
+                    extensions_state.clone(),
+                    no_answer.clone(),
+                    quotas.clone(),
+                )),
             )
             .route(
                 "/api/v1/extensions/:id",
-                put(handlers::extensions::update_extension).with_state(extensions_state.clone()),
+                put(handlers::extensions::update_extension).with_state((
+                    extensions_state.clone(),
+                    no_answer,
+                    quotas.clone(),
+                )),
             )
             .route(
                 "/api/v1/extensions/:id",
-                delete(handlers::extensions::delete_extension).with_state(extensions_state.clone()),
+                delete(handlers::extensions::delete_extension)
+                    .with_state((extensions_state.clone(), extensions_trash.clone())),
+            )
+            .route(
+                "/api/v1/extensions/deleted",
+                get(handlers::extensions::list_deleted_extensions)
+                    .with_state(extensions_trash.clone()),
+            )
+            .route(
+                "/api/v1/extensions/:id/restore",
+                post(handlers::extensions::restore_extension).with_state((
+                    extensions_state.clone(),
+                    extensions_trash,
+                    quotas.clone(),
+                )),
+            )
+            .route(
+                "/api/v1/extensions/:id/call-history",
+                get(handlers::call_history::get_call_history)
+                    .with_state(call_history_state.clone()),
             )
             .route(
                 "/api/v1/extensions/reorder",
@@ -205,15 +655,34 @@ impl CloudApi {
             )
             .route(
                 "/api/v1/trunks",
-                post(handlers::trunks::create_trunk).with_state(trunks_state.clone()),
+                post(handlers::trunks::create_trunk)
+                    .with_state((trunks_state.clone(), quotas.clone())),
             )
             .route(
                 "/api/v1/trunks/:id",
-                put(handlers::trunks::update_trunk).with_state(trunks_state.clone()),
+                put(handlers::trunks::update_trunk)
+                    .with_state((trunks_state.clone(), quotas.clone())),
             )
             .route(
                 "/api/v1/trunks/:id",
-                delete(handlers::trunks::delete_trunk).with_state(trunks_state.clone()),
+                delete(handlers::trunks::delete_trunk)
+                    .with_state((trunks_state.clone(), trunks_trash.clone())),
+            )
+            .route(
+                "/api/v1/trunks/health",
+                get(handlers::trunks::trunk_health).with_state(trunk_health_state),
+            )
+            .route(
+                "/api/v1/trunks/deleted",
+                get(handlers::trunks::list_deleted_trunks).with_state(trunks_trash.clone()),
+            )
+            .route(
+                "/api/v1/trunks/:id/restore",
+                post(handlers::trunks::restore_trunk).with_state((
+                    trunks_state.clone(),
+                    trunks_trash,
+                    quotas,
+                )),
             )
             .route(
                 "/api/v1/trunks/reorder",
@@ -273,7 +742,238 @@ impl CloudApi {
             )
             .route(
                 "/api/v1/routes/test",
-                post(handlers::routes::test_route).with_state(routes_state),
+                post(handlers::routes::test_route).with_state((
+                    routes_state.clone(),
+                    groups_state.clone(),
+                    schedules_state.clone(),
+                )),
+            )
+            .route(
+                "/api/v1/routes/simulate",
+                post(handlers::routes::simulate_route).with_state(simulation_state),
+            )
+            .route(
+                "/api/v1/routes/tests",
+                get(handlers::routes::list_route_tests).with_state(route_tests_state.clone()),
+            )
+            .route(
+                "/api/v1/routes/tests/run",
+                post(handlers::routes::run_tests).with_state(route_tests_state.clone()),
+            )
+            .route(
+                "/api/v1/routes/tests/:name",
+                put(handlers::routes::save_route_test).with_state(route_tests_state.clone()),
+            )
+            .route(
+                "/api/v1/routes/tests/:name",
+                delete(handlers::routes::delete_route_test).with_state(route_tests_state),
+            )
+            .route(
+                "/api/v1/routes/graph",
+                get(handlers::routes::export_route_graph).with_state(routes_state),
+            )
+            // Extension group endpoints
+            .route(
+                "/api/v1/groups",
+                get(handlers::groups::list_groups).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/groups",
+                post(handlers::groups::create_group).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/groups/:id",
+                get(handlers::groups::get_group).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/groups/:id",
+                put(handlers::groups::update_group).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/groups/:id",
+                delete(handlers::groups::delete_group).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/groups/:id/members/:extension",
+                put(handlers::groups::add_member).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/groups/:id/members/:extension",
+                delete(handlers::groups::remove_member).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/groups/:id/paging",
+                get(handlers::groups::paging_members).with_state(groups_state.clone()),
+            )
+            .route(
+                "/api/v1/extensions/:id/groups",
+                get(handlers::groups::extension_groups).with_state(groups_state),
+            )
+            // Schedule endpoints
+            .route(
+                "/api/v1/schedules",
+                get(handlers::schedules::list_schedules).with_state(schedules_state.clone()),
+            )
+            .route(
+                "/api/v1/schedules",
+                post(handlers::schedules::create_schedule).with_state(schedules_state.clone()),
+            )
+            .route(
+                "/api/v1/schedules/:id",
+                get(handlers::schedules::get_schedule).with_state(schedules_state.clone()),
+            )
+            .route(
+                "/api/v1/schedules/:id",
+                put(handlers::schedules::update_schedule).with_state(schedules_state.clone()),
+            )
+            .route(
+                "/api/v1/schedules/:id",
+                delete(handlers::schedules::delete_schedule).with_state(schedules_state.clone()),
+            )
+            .route(
+                "/api/v1/schedules/:id/status",
+                get(handlers::schedules::schedule_status).with_state(schedules_state),
+            )
+            // IVR flow endpoints
+            .route(
+                "/api/v1/ivr/flows",
+                get(handlers::ivr::list_flows)
+                    .post(handlers::ivr::create_flow)
+                    .with_state(ivr_state.clone()),
+            )
+            .route(
+                "/api/v1/ivr/flows/:id",
+                get(handlers::ivr::get_flow)
+                    .put(handlers::ivr::update_flow)
+                    .delete(handlers::ivr::delete_flow)
+                    .with_state(ivr_state.clone()),
+            )
+            .route(
+                "/api/v1/ivr/flows/:id/simulate",
+                post(handlers::ivr::simulate_flow).with_state(ivr_state),
+            )
+            .route("/api/v1/ivr/validate", post(handlers::ivr::validate_flow))
+            // Class of service endpoints
+            .route(
+                "/api/v1/cos",
+                get(handlers::cos::get_cos).with_state(cos_state.clone()),
+            )
+            .route(
+                "/api/v1/cos/check",
+                post(handlers::cos::check_cos).with_state(cos_state),
+            )
+            // Analytics endpoints
+            .route(
+                "/api/v1/analytics/teams",
+                get(handlers::analytics::teams_summary).with_state(analytics_state.clone()),
+            )
+            .route(
+                "/api/v1/analytics/teams/calls",
+                get(handlers::analytics::teams_calls).with_state(analytics_state),
+            )
+            .route(
+                "/api/v1/analytics/hangup-causes",
+                get(handlers::analytics::hangup_causes).with_state(call_history_state.history),
+            )
+            .route(
+                "/api/v1/analytics/pdd",
+                get(handlers::analytics::post_dial_delay).with_state(pdd_state),
+            )
+            .route(
+                "/api/v1/analytics/media-quality",
+                get(handlers::analytics::media_quality).with_state(media_quality_state),
+            )
+            .route(
+                "/api/v1/analytics/tenants",
+                get(handlers::analytics::tenant_usage).with_state(tenants_state),
+            )
+            // Teams Direct Routing validation
+            .route(
+                "/api/v1/teams/validate",
+                post(handlers::teams::validate_direct_routing).with_state(teams_state),
+            )
+            // SMS messaging endpoints
+            .route(
+                "/api/v1/messages",
+                get(handlers::messages::list_messages).with_state(messages_state.clone()),
+            )
+            .route(
+                "/api/v1/messages",
+                post(handlers::messages::send_message).with_state(messages_state.clone()),
+            )
+            .route(
+                "/api/v1/messages/inbound",
+                post(handlers::messages::receive_message).with_state(messages_state.clone()),
+            )
+            .route(
+                "/api/v1/messages/twilio",
+                post(handlers::messages::twilio_webhook).with_state(messages_state),
+            )
+            // Fax endpoints
+            .route(
+                "/api/v1/fax",
+                get(handlers::fax::list_faxes).with_state(fax_state.clone()),
+            )
+            .route(
+                "/api/v1/fax",
+                post(handlers::fax::send_fax).with_state(fax_state.clone()),
+            )
+            .route(
+                "/api/v1/fax/inbound",
+                post(handlers::fax::receive_fax).with_state(fax_state.clone()),
+            )
+            .route(
+                "/api/v1/fax/outbound/claim",
+                post(handlers::fax::claim_outbound_fax).with_state(fax_state.clone()),
+            )
+            .route(
+                "/api/v1/fax/:id/result",
+                post(handlers::fax::fax_result).with_state(fax_state),
+            )
+            // Outbound campaign endpoints
+            .route(
+                "/api/v1/campaigns",
+                get(handlers::campaigns::list_campaigns)
+                    .post(handlers::campaigns::create_campaign)
+                    .with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/campaigns/:id",
+                get(handlers::campaigns::get_campaign)
+                    .delete(handlers::campaigns::delete_campaign)
+                    .with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/campaigns/:id/contacts",
+                get(handlers::campaigns::list_contacts)
+                    .post(handlers::campaigns::add_contacts)
+                    .with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/campaigns/:id/start",
+                post(handlers::campaigns::start_campaign).with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/campaigns/:id/pause",
+                post(handlers::campaigns::pause_campaign).with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/campaigns/attempts/claim",
+                post(handlers::campaigns::claim_attempt).with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/campaigns/attempts/:id/result",
+                post(handlers::campaigns::attempt_result).with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/dnc",
+                get(handlers::campaigns::list_do_not_call)
+                    .post(handlers::campaigns::add_do_not_call)
+                    .with_state(campaigns_state.clone()),
+            )
+            .route(
+                "/api/v1/dnc/:number",
+                delete(handlers::campaigns::remove_do_not_call).with_state(campaigns_state),
             )
             // SIP Profile management endpoints
             .route(
@@ -287,59 +987,349 @@ impl CloudApi {
             )
             .route(
                 "/api/v1/sip-profiles",
-                post(handlers::sip_profiles::create_sip_profile)
-                    .with_state(sip_profiles_state.clone()),
+                post(handlers::sip_profiles::create_sip_profile).with_state((
+                    sip_profiles_state.clone(),
+                    nat.clone(),
+                    domains.clone(),
+                )),
             )
             .route(
                 "/api/v1/sip-profiles/:id",
-                put(handlers::sip_profiles::update_sip_profile)
-                    .with_state(sip_profiles_state.clone()),
+                put(handlers::sip_profiles::update_sip_profile).with_state((
+                    sip_profiles_state.clone(),
+                    nat.clone(),
+                    domains.clone(),
+                )),
             )
             .route(
                 "/api/v1/sip-profiles/:id",
-                delete(handlers::sip_profiles::delete_sip_profile)
-                    .with_state(sip_profiles_state.clone()),
+                delete(handlers::sip_profiles::delete_sip_profile).with_state((
+                    sip_profiles_state.clone(),
+                    nat,
+                    domains,
+                )),
             )
             .route(
                 "/api/v1/sip-profiles/reorder",
                 post(handlers::sip_profiles::reorder_sip_profiles).with_state(sip_profiles_state),
-            );
+            )
+            // ACL management endpoints
+            .route(
+                "/api/v1/acls",
+                get(handlers::acls::list_acls).with_state(acls_state.clone()),
+            )
+            .route(
+                "/api/v1/acls",
+                post(handlers::acls::create_acl).with_state(acls_state.clone()),
+            )
+            .route(
+                "/api/v1/acls/:name",
+                get(handlers::acls::get_acl).with_state(acls_state.clone()),
+            )
+            .route(
+                "/api/v1/acls/:name",
+                put(handlers::acls::update_acl).with_state(acls_state.clone()),
+            )
+            .route(
+                "/api/v1/acls/:name",
+                delete(handlers::acls::delete_acl).with_state(acls_state.clone()),
+            )
+            .route(
+                "/api/v1/acls/:name/check/:ip",
+                get(handlers::acls::check_ip).with_state(acls_state),
+            )
+            // Voicemail management endpoints
+            .route(
+                "/api/v1/voicemail",
+                get(handlers::voicemail::list_mailboxes).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail",
+                post(handlers::voicemail::create_mailbox).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail/:id",
+                get(handlers::voicemail::get_mailbox).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail/:id",
+                delete(handlers::voicemail::delete_mailbox).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail/:id/pin",
+                post(handlers::voicemail::reset_pin).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail/:id/mwi",
+                get(handlers::voicemail::get_mwi_status).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail/:id/messages",
+                get(handlers::voicemail::get_messages).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail/:id/messages/:message_id/read",
+                post(handlers::voicemail::mark_message_read).with_state(voicemail_state.clone()),
+            )
+            .route(
+                "/api/v1/voicemail/:id/messages/:message_id",
+                delete(handlers::voicemail::delete_message).with_state(voicemail_state),
+            )
+            // Prompt language endpoints
+            .route(
+                "/api/v1/prompts",
+                get(handlers::prompts::list_locales).with_state(prompts_state.clone()),
+            )
+            .route(
+                "/api/v1/prompts/:locale",
+                get(handlers::prompts::list_prompts).with_state(prompts_state.clone()),
+            )
+            .route(
+                "/api/v1/prompts/:locale/:key",
+                get(handlers::prompts::get_prompt).with_state(prompts_state.clone()),
+            )
+            .route(
+                "/api/v1/prompts/:locale/:key",
+                put(handlers::prompts::put_prompt).with_state(prompts_state.clone()),
+            )
+            .route(
+                "/api/v1/prompts/:locale/:key",
+                delete(handlers::prompts::delete_prompt).with_state(prompts_state),
+            )
+            // Per-call debug trace endpoints
+            .route(
+                "/api/v1/debug/traces",
+                get(handlers::debug::list_traces).with_state(debug_state.clone()),
+            )
+            .route(
+                "/api/v1/debug/traces",
+                post(handlers::debug::start_trace).with_state(debug_state.clone()),
+            )
+            .route(
+                "/api/v1/debug/traces/stop",
+                post(handlers::debug::stop_trace).with_state(debug_state),
+            )
+            // Live registrar and B2BUA state
+            .route(
+                "/api/v1/registrations",
+                get(handlers::registrations::list_registrations).with_state(registrations_state),
+            )
+            .route(
+                "/api/v1/devices",
+                get(handlers::devices::list_devices).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id",
+                get(handlers::devices::get_device).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id",
+                put(handlers::devices::update_device).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id",
+                delete(handlers::devices::delete_device).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id/revoke",
+                post(handlers::devices::revoke_device).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id/reinstate",
+                post(handlers::devices::reinstate_device).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id/blf",
+                get(handlers::devices::get_blf_list).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id/blf",
+                put(handlers::devices::set_blf_list).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id/blf",
+                delete(handlers::devices::delete_blf_list).with_state(devices_state.clone()),
+            )
+            .route(
+                "/api/v1/devices/:id/provisioning",
+                get(handlers::devices::get_provisioning).with_state(devices_state),
+            )
+            .route(
+                "/api/v1/channels",
+                get(handlers::channels::list_channels).with_state(channels_state.clone()),
+            )
+            .route(
+                "/api/v1/calls/:id/play",
+                post(handlers::channels::play_announcement).with_state(channels_state.clone()),
+            )
+            .route(
+                "/api/v1/calls/:id/topology",
+                get(handlers::channels::get_topology).with_state(channels_state),
+            )
+            .route(
+                "/api/v1/events",
+                get(handlers::events::stream_events).with_state(events_state),
+            )
+            // Outbound webhook endpoints
+            .route(
+                "/api/v1/webhooks",
+                get(handlers::webhooks::list_webhooks).with_state(webhooks_state.clone()),
+            )
+            .route(
+                "/api/v1/webhooks/:name/preview",
+                get(handlers::webhooks::preview_webhook).with_state(webhooks_state.clone()),
+            )
+            .route(
+                "/api/v1/webhooks/:name/test",
+                post(handlers::webhooks::test_webhook).with_state(webhooks_state),
+            )
+            .layer(middleware::from_fn_with_state(
+                idempotency_cache,
+                idempotency,
+            ))
+            .layer(middleware::map_response(api_cache_control))
+            .layer(middleware::from_fn_with_state(metrics, access_log));
 
-        // If webui_path is provided, serve static files
-        if let Some(path) = webui_path {
-            info!("Serving WebUI from: {}", path);
-            app = app.nest_service("/", ServeDir::new(path));
+        // Everything else is the WebUI, with its own routes falling back to
+        // index.html
+        if let Some(webui) = webui {
+            match &webui {
+                WebUi::Directory(path) => info!("Serving WebUI from: {}", path.display()),
+                WebUi::Embedded => info!("Serving embedded WebUI"),
+            }
+            app = app.fallback_service(get(serve_webui).with_state(webui));
         }
 
-        app
+        // Brotli or gzip as the client accepts, for API and UI alike; files
+        // the UI sends pre-compressed, images and event streams are left
+        // as they are
+        app.layer(CompressionLayer::new())
     }
 
     /// Start the API server
     pub async fn start(&self) -> anyhow::Result<()> {
         let acme_state = Arc::new(RwLock::new(self.acme_client.clone()));
         let codec_state = Arc::new(RwLock::new(self.codec_config.clone()));
+        let acls_state = Arc::new(RwLock::new(self.acls.clone()));
+        let voicemail_state = self.voicemail.clone();
         let dids_state = Arc::new(RwLock::new(self.dids.clone()));
         let extensions_state = Arc::new(RwLock::new(self.extensions.clone()));
         let trunks_state = Arc::new(RwLock::new(self.trunks.clone()));
         let ring_groups_state = Arc::new(RwLock::new(self.ring_groups.clone()));
+        let groups_state = Arc::new(RwLock::new(self.groups.clone()));
+        let schedules_state = Arc::new(RwLock::new(self.schedules.clone()));
         let routes_state = Arc::new(RwLock::new(self.routes.clone()));
         let sip_profiles_state = Arc::new(RwLock::new(self.sip_profiles.clone()));
+        let route_tests_state = handlers::routes::RouteTestsState {
+            routes: routes_state.clone(),
+            groups: groups_state.clone(),
+            schedules: schedules_state.clone(),
+            cases: Arc::new(RwLock::new(self.route_tests.clone())),
+        };
+        let simulation_state = handlers::routes::SimulationState {
+            routes: routes_state.clone(),
+            groups: groups_state.clone(),
+            schedules: schedules_state.clone(),
+            least_cost: self.least_cost.clone(),
+            trunk_health: self.trunk_health.clone(),
+        };
+        let reload_state = handlers::reload::ReloadState {
+            reloader: self.config_reloader.clone(),
+            acls: acls_state.clone(),
+            codecs: codec_state.clone(),
+            routes: routes_state.clone(),
+            extensions: extensions_state.clone(),
+            trunks: trunks_state.clone(),
+            ring_groups: ring_groups_state.clone(),
+            voicemail: voicemail_state.clone(),
+            groups: groups_state.clone(),
+            schedules: schedules_state.clone(),
+            route_tests: route_tests_state.cases.clone(),
+        };
+        let search_state = handlers::search::SearchState {
+            extensions: extensions_state.clone(),
+            dids: dids_state.clone(),
+            trunks: trunks_state.clone(),
+            routes: routes_state.clone(),
+            voicemail: voicemail_state.clone(),
+        };
+        let devices_state = handlers::devices::DevicesState {
+            registrar: self.registrar.clone(),
+            extensions: extensions_state.clone(),
+            blf_lists: self.blf_lists.clone(),
+        };
+        let call_history_state = handlers::call_history::CallHistoryState {
+            history: self.call_history.clone(),
+            extensions: extensions_state.clone(),
+        };
+        let tenants_state = handlers::analytics::TenantsState {
+            quotas: self.quotas.clone(),
+            extensions: extensions_state.clone(),
+            trunks: trunks_state.clone(),
+            voicemail: voicemail_state.clone(),
+        };
+        let cos_state = handlers::cos::CosState {
+            config: Arc::new(RwLock::new(self.cos.clone())),
+            extensions: extensions_state.clone(),
+        };
 
         let app = Self::router(
-            self.webui_path.clone(),
+            WebUi::from_path(self.webui_path.clone()),
+            self.certificate_monitor.clone(),
             acme_state,
             codec_state,
+            acls_state,
+            voicemail_state,
             dids_state,
             extensions_state,
+            self.no_answer.clone(),
             trunks_state,
+            Trash::new(self.trash_retention),
+            Trash::new(self.trash_retention),
+            Trash::new(self.trash_retention),
+            self.trunk_health.clone(),
+            self.quotas.clone(),
             ring_groups_state,
+            groups_state,
+            schedules_state,
+            Arc::new(RwLock::new(self.ivr_flows.clone())),
             routes_state,
+            route_tests_state,
+            simulation_state,
             sip_profiles_state,
+            self.nat.clone(),
+            self.domains.clone(),
+            self.call_tracer.clone(),
+            self.registrar.clone(),
+            self.b2bua.clone(),
+            reload_state,
+            cos_state,
+            self.sms.clone(),
+            self.teams_records.clone(),
+            self.pdd.clone(),
+            self.media_quality.clone(),
+            tenants_state,
+            self.events.clone(),
+            self.webhooks.clone(),
+            self.fax.clone(),
+            self.dialer.clone(),
+            self.prompts.clone(),
+            self.call_logs.clone(),
+            search_state,
+            devices_state,
+            call_history_state,
+            self.teams_validation.clone(),
+            handlers::status::StatusState {
+                supervisor: self.supervisor.clone(),
+                b2bua: self.b2bua.clone(),
+            },
+            IdempotencyCache::new(self.idempotency_ttl),
+            RouteMetrics::new(),
         );
 
         info!("Starting Cloud API server on {}", self.addr);
 
-        let listener = tokio::net::TcpListener::bind(self.addr).await?;
+        let listener = bind_tcp(self.addr, self.reuse_port)?;
         axum::serve(listener, app).await?;
 
         Ok(())
@@ -0,0 +1,128 @@
+//! Cache-Control policy and content encoding negotiation
+//!
+//! API responses are per-user and change from one request to the next, so
+//! they are marked `no-store` unless the handler chose otherwise. Web UI
+//! files are cached according to what they are: the hashed files Vite
+//! writes under `assets/` never change and are cached for a year, while
+//! `index.html`, which names them, is revalidated on every load - cheaply,
+//! as it carries an ETag and unchanged copies are answered with 304.
+//!
+//! Clients that accept brotli or gzip are sent a `.br` or `.gz` copy of a
+//! Web UI file when the bundle has one next to the original. Everything
+//! else is compressed on the fly by the API server's compression layer.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+};
+
+/// Cache-Control of API responses
+pub const API_CACHE_CONTROL: &str = "no-store";
+
+/// Cache-Control of content-hashed Web UI files
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Cache-Control of Web UI files that keep their name across releases
+pub const REVALIDATE_CACHE_CONTROL: &str = "no-cache";
+
+/// Cache-Control of other Web UI files, such as the favicon
+pub const DEFAULT_CACHE_CONTROL: &str = "public, max-age=3600";
+
+/// Encodings of pre-compressed files, best first, with their suffix
+pub const ENCODINGS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// Cache-Control for a Web UI file
+pub fn cache_control_for(name: &str) -> &'static str {
+    if name == "index.html" || name.ends_with(".html") {
+        REVALIDATE_CACHE_CONTROL
+    } else if name.starts_with("assets/") {
+        IMMUTABLE_CACHE_CONTROL
+    } else {
+        DEFAULT_CACHE_CONTROL
+    }
+}
+
+/// Encodings of [`ENCODINGS`] the request accepts, best first
+///
+/// An encoding listed with `q=0` is refused, as is every encoding not
+/// listed unless `*` is.
+pub fn accepted_encodings(headers: &HeaderMap) -> Vec<(&'static str, &'static str)> {
+    let Some(accept) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Vec::new();
+    };
+    let weights: Vec<(String, f32)> = accept
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let coding = parts.next()?.to_ascii_lowercase();
+            let weight = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (!coding.is_empty()).then_some((coding, weight))
+        })
+        .collect();
+    let weight_of = |coding: &str| {
+        weights
+            .iter()
+            .find(|(c, _)| c == coding)
+            .or_else(|| weights.iter().find(|(c, _)| c == "*"))
+            .map(|(_, weight)| *weight)
+    };
+    ENCODINGS
+        .iter()
+        .copied()
+        .filter(|(coding, _)| weight_of(coding).is_some_and(|weight| weight > 0.0))
+        .collect()
+}
+
+/// Middleware marking API responses `no-store` unless the handler set its
+/// own Cache-Control
+pub async fn api_cache_control(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .entry(header::CACHE_CONTROL)
+        .or_insert(HeaderValue::from_static(API_CACHE_CONTROL));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> Vec<&'static str> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_str(value).unwrap(),
+        );
+        accepted_encodings(&headers)
+            .into_iter()
+            .map(|(coding, _)| coding)
+            .collect()
+    }
+
+    #[test]
+    fn test_accepted_encodings() {
+        assert!(accepted_encodings(&HeaderMap::new()).is_empty());
+        assert_eq!(accept("gzip, deflate, br"), ["br", "gzip"]);
+        assert_eq!(accept("gzip"), ["gzip"]);
+        assert_eq!(accept("br;q=0, gzip;q=0.8"), ["gzip"]);
+        assert_eq!(accept("*"), ["br", "gzip"]);
+        assert_eq!(accept("*, br;q=0"), ["gzip"]);
+        assert!(accept("identity").is_empty());
+    }
+
+    #[test]
+    fn test_cache_control_for() {
+        assert_eq!(cache_control_for("index.html"), REVALIDATE_CACHE_CONTROL);
+        assert_eq!(
+            cache_control_for("assets/index-3f2a.js"),
+            IMMUTABLE_CACHE_CONTROL
+        );
+        assert_eq!(cache_control_for("favicon.svg"), DEFAULT_CACHE_CONTROL);
+    }
+}
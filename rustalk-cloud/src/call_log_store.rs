@@ -0,0 +1,526 @@
+//! Call logs ingested from B2BUA call detail records
+//!
+//! SBCs POST each call detail record to `/api/v1/call-logs` when a call
+//! ends. Records are converted to [`CallLogDetail`]s and kept for the call
+//! log listing, detail and cost reports, either in memory, newest first and
+//! up to a limit, or in the server's database.
+//!
+//! In the database, PostgreSQL or SQLite, call logs are the rows of the
+//! migrated `call_logs` table, the one the B2BUA's database CDR sink writes:
+//! the columns calls are filtered and sorted on, and the whole record as
+//! JSON, from which the detail is built when it is read.
+
+use anyhow::{Context, Result};
+use rustalk_core::b2bua::CallDetailRecord;
+use rustalk_core::cdr_sink::{CdrSink, DatabaseSink};
+use rustalk_core::db::Database;
+use rustalk_core::sip::builder::header_tag;
+use sqlx::database::HasArguments;
+use sqlx::query::Query;
+use sqlx::{Encode, Row, Type};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::models::{CallLog, CallLogDetail, ChargeItem};
+
+/// Call logs kept when no limit is given
+pub const DEFAULT_MAX_RECORDS: usize = 10_000;
+
+/// Which call logs a query returns
+#[derive(Debug, Clone, Default)]
+pub struct CallLogFilter {
+    /// Started at or after, in seconds since the epoch
+    pub start_date: Option<i64>,
+    /// Started at or before, in seconds since the epoch
+    pub end_date: Option<i64>,
+    pub status: Option<String>,
+    /// Part of the calling user
+    pub caller: Option<String>,
+    /// Part of the called user
+    pub callee: Option<String>,
+    pub account_code: Option<String>,
+}
+
+impl CallLogFilter {
+    pub fn matches(&self, log: &CallLog) -> bool {
+        self.start_date.is_none_or(|start| log.start_time >= start)
+            && self.end_date.is_none_or(|end| log.start_time <= end)
+            && self.status.as_ref().is_none_or(|s| &log.status == s)
+            && self
+                .caller
+                .as_ref()
+                .is_none_or(|caller| log.from_user.contains(caller.as_str()))
+            && self
+                .callee
+                .as_ref()
+                .is_none_or(|callee| log.to_user.contains(callee.as_str()))
+            && self
+                .account_code
+                .as_ref()
+                .is_none_or(|code| log.account_code.as_ref() == Some(code))
+    }
+
+    /// SQL condition for `database` and the values bound to its `$n`
+    /// placeholders
+    fn to_sql(&self, database: &Database) -> (String, Vec<Param>) {
+        // SQLite keeps times as seconds since the epoch
+        let timestamp = match database {
+            Database::Postgres(_) => "to_timestamp(?)",
+            Database::Sqlite(_) => "?",
+        };
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        let mut push = |clause: &str, param: Param| {
+            params.push(param);
+            clauses.push(clause.replace('?', &format!("${}", params.len())));
+        };
+        if let Some(start) = self.start_date {
+            push(&format!("start_time >= {}", timestamp), Param::Int(start));
+        }
+        if let Some(end) = self.end_date {
+            push(&format!("start_time <= {}", timestamp), Param::Int(end));
+        }
+        if let Some(status) = &self.status {
+            push("disposition = ?", Param::Text(status.clone()));
+        }
+        if let Some(caller) = &self.caller {
+            push("from_user LIKE ?", Param::Text(like_pattern(caller)));
+        }
+        if let Some(callee) = &self.callee {
+            push("to_user LIKE ?", Param::Text(like_pattern(callee)));
+        }
+        if let Some(code) = &self.account_code {
+            push("account_code = ?", Param::Text(code.clone()));
+        }
+
+        if clauses.is_empty() {
+            (String::new(), params)
+        } else {
+            (format!(" WHERE {}", clauses.join(" AND ")), params)
+        }
+    }
+}
+
+/// `LIKE` pattern matching text containing `part`
+fn like_pattern(part: &str) -> String {
+    // Wildcards in the search are taken literally
+    let part: String = part.chars().filter(|c| !matches!(c, '%' | '_')).collect();
+    format!("%{}%", part)
+}
+
+enum Param {
+    Int(i64),
+    Text(String),
+}
+
+/// One page of call logs and how many match in all
+#[derive(Debug, Clone)]
+pub struct CallLogPage {
+    pub logs: Vec<CallLog>,
+    pub total: usize,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Memory {
+        logs: Arc<RwLock<VecDeque<CallLogDetail>>>,
+        max_records: usize,
+    },
+    Database(Database),
+}
+
+/// Recent call logs, newest first
+#[derive(Clone)]
+pub struct CallLogStore {
+    backend: Backend,
+}
+
+impl CallLogStore {
+    /// Keep up to `max_records` call logs in memory
+    pub fn new(max_records: usize) -> Self {
+        Self {
+            backend: Backend::Memory {
+                logs: Arc::new(RwLock::new(VecDeque::new())),
+                max_records,
+            },
+        }
+    }
+
+    /// Keep call logs in the `call_logs` table of a migrated database
+    pub fn database(database: Database) -> Self {
+        Self {
+            backend: Backend::Database(database),
+        }
+    }
+
+    /// Whether call logs are kept in a database
+    pub fn is_persistent(&self) -> bool {
+        matches!(self.backend, Backend::Database(_))
+    }
+
+    /// Store the call log of a record, replacing one already ingested for
+    /// the same call
+    pub async fn ingest(&self, cdr: &CallDetailRecord) -> Result<CallLogDetail> {
+        let detail = call_log_from_cdr(cdr);
+        match &self.backend {
+            Backend::Memory { logs, max_records } => {
+                let mut logs = logs.write().await;
+                logs.retain(|log| log.log.id != detail.log.id);
+                logs.push_front(detail.clone());
+                logs.truncate(*max_records);
+            }
+            Backend::Database(database) => {
+                DatabaseSink::new(database.clone())
+                    .write(cdr)
+                    .await
+                    .with_context(|| format!("Failed to store call log {}", cdr.call_id))?;
+            }
+        }
+        Ok(detail)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<CallLogDetail>> {
+        match &self.backend {
+            Backend::Memory { logs, .. } => Ok(logs
+                .read()
+                .await
+                .iter()
+                .find(|log| log.log.id == id)
+                .cloned()),
+            Backend::Database(database) => {
+                const SELECT: &str =
+                    "SELECT CAST(record AS TEXT) AS record FROM call_logs WHERE call_id = $1";
+                let record: Option<String> = match database {
+                    Database::Postgres(pool) => {
+                        sqlx::query_scalar(SELECT)
+                            .bind(id)
+                            .fetch_optional(pool)
+                            .await
+                    }
+                    Database::Sqlite(pool) => {
+                        sqlx::query_scalar(SELECT)
+                            .bind(id)
+                            .fetch_optional(pool)
+                            .await
+                    }
+                }
+                .with_context(|| format!("Failed to load call log {}", id))?;
+                record.as_deref().map(parse_record).transpose()
+            }
+        }
+    }
+
+    /// Page `page` (from 1) of the call logs matching `filter`, newest first
+    pub async fn query(
+        &self,
+        filter: &CallLogFilter,
+        page: usize,
+        per_page: usize,
+    ) -> Result<CallLogPage> {
+        let offset = page.saturating_sub(1).saturating_mul(per_page);
+        match &self.backend {
+            Backend::Memory { logs, .. } => {
+                let logs = logs.read().await;
+                let matching: Vec<&CallLogDetail> =
+                    logs.iter().filter(|log| filter.matches(&log.log)).collect();
+                Ok(CallLogPage {
+                    total: matching.len(),
+                    logs: matching
+                        .into_iter()
+                        .skip(offset)
+                        .take(per_page)
+                        .map(|log| log.log.clone())
+                        .collect(),
+                })
+            }
+            Backend::Database(database) => {
+                let (condition, params) = filter.to_sql(database);
+                let count = format!("SELECT COUNT(*) AS total FROM call_logs{}", condition);
+                let total: i64 = match database {
+                    Database::Postgres(pool) => bind(sqlx::query(&count), &params)
+                        .fetch_one(pool)
+                        .await
+                        .and_then(|row| row.try_get("total")),
+                    Database::Sqlite(pool) => bind(sqlx::query(&count), &params)
+                        .fetch_one(pool)
+                        .await
+                        .and_then(|row| row.try_get("total")),
+                }
+                .context("Failed to count call logs")?;
+
+                let limit = i64::try_from(per_page).unwrap_or(i64::MAX);
+                let offset = i64::try_from(offset).context("Call log page is out of range")?;
+                let select = format!(
+                    "SELECT CAST(record AS TEXT) AS record FROM call_logs{} \
+                     ORDER BY start_time DESC, call_id DESC LIMIT {} OFFSET {}",
+                    condition, limit, offset
+                );
+                let records: Vec<String> = match database {
+                    Database::Postgres(pool) => bind(sqlx::query(&select), &params)
+                        .fetch_all(pool)
+                        .await
+                        .and_then(|rows| rows.iter().map(|row| row.try_get("record")).collect()),
+                    Database::Sqlite(pool) => bind(sqlx::query(&select), &params)
+                        .fetch_all(pool)
+                        .await
+                        .and_then(|rows| rows.iter().map(|row| row.try_get("record")).collect()),
+                }
+                .context("Failed to list call logs")?;
+                let logs = records
+                    .iter()
+                    .map(|record| Ok(parse_record(record)?.log))
+                    .collect::<Result<_>>()?;
+                Ok(CallLogPage {
+                    logs,
+                    total: total as usize,
+                })
+            }
+        }
+    }
+
+    /// Every call log matching `filter`, newest first
+    pub async fn matching(&self, filter: &CallLogFilter) -> Result<Vec<CallLog>> {
+        Ok(self.query(filter, 1, usize::MAX >> 1).await?.logs)
+    }
+}
+
+impl Default for CallLogStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_RECORDS)
+    }
+}
+
+fn bind<'q, DB>(
+    mut query: Query<'q, DB, <DB as HasArguments<'q>>::Arguments>,
+    params: &'q [Param],
+) -> Query<'q, DB, <DB as HasArguments<'q>>::Arguments>
+where
+    DB: sqlx::Database,
+    i64: Encode<'q, DB> + Type<DB>,
+    &'q str: Encode<'q, DB> + Type<DB>,
+{
+    for param in params {
+        query = match param {
+            Param::Int(value) => query.bind(*value),
+            Param::Text(value) => query.bind(value.as_str()),
+        };
+    }
+    query
+}
+
+fn parse_record(json: &str) -> Result<CallLogDetail> {
+    let cdr: CallDetailRecord =
+        serde_json::from_str(json).context("Stored call log is not valid")?;
+    Ok(call_log_from_cdr(&cdr))
+}
+
+/// Call log of a call detail record
+pub fn call_log_from_cdr(cdr: &CallDetailRecord) -> CallLogDetail {
+    let (from_user, from_domain) = cdr.caller_party();
+    let (to_user, to_domain) = cdr.callee_party();
+    let charge_breakdown = cdr.charge.as_ref().map(|charge| {
+        vec![ChargeItem {
+            description: format!("{} ({})", charge.rate_deck, charge.prefix),
+            rate: charge.per_minute,
+            quantity: charge.billed_seconds as f64 / 60.0,
+            unit: "minutes".to_string(),
+            amount: charge.amount,
+        }]
+    });
+    let cost = cdr.charge.as_ref().map(|charge| charge.amount);
+
+    CallLogDetail {
+        log: CallLog {
+            id: cdr.call_id.clone(),
+            call_id: cdr.call_id.clone(),
+            from_user,
+            from_domain,
+            to_user,
+            to_domain,
+            start_time: cdr.start_time.timestamp(),
+            end_time: Some(cdr.end_time.timestamp()),
+            duration_seconds: Some(cdr.duration_seconds.max(0) as u32),
+            status: serde_json::to_value(cdr.disposition)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            termination_reason: cdr
+                .denial_reason
+                .clone()
+                .or_else(|| cdr.hangup_cause.map(|cause| cause.as_str().to_string())),
+            a_leg_codec: cdr.caller_codec.clone(),
+            b_leg_codec: cdr.callee_codec.clone(),
+            recording_path: None,
+            cost,
+            account_code: cdr.account_code.clone(),
+        },
+        sip_call_id: cdr.call_id.clone(),
+        from_tag: cdr
+            .caller
+            .as_deref()
+            .and_then(header_tag)
+            .map(str::to_string),
+        to_tag: cdr
+            .callee
+            .as_deref()
+            .and_then(header_tag)
+            .map(str::to_string),
+        charge_breakdown,
+        total_cost: cost,
+        decision_trail: cdr.decisions.clone(),
+        attempts: cdr.attempts.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::b2bua::{CallDisposition, HangupCause, Session};
+    use rustalk_core::wholesale::Charge;
+
+    fn cdr(call_id: &str) -> CallDetailRecord {
+        let mut cdr = CallDetailRecord::from_session(
+            &Session::new(call_id.to_string()),
+            CallDisposition::Answered,
+        );
+        cdr.caller = Some("\"Alice\" <sip:1001@example.com:5060>;tag=a1".to_string());
+        cdr.callee = Some("<sip:+12125551234@carrier.example.com;user=phone>;tag=b2".to_string());
+        cdr.hangup_cause = Some(HangupCause::NormalClearing);
+        cdr.caller_codec = Some("PCMU".to_string());
+        cdr
+    }
+
+    #[tokio::test]
+    async fn test_ingest() {
+        let store = CallLogStore::new(2);
+        let detail = store.ingest(&cdr("call-1")).await.unwrap();
+        assert_eq!(detail.log.from_user, "1001");
+        assert_eq!(detail.log.from_domain, "example.com");
+        assert_eq!(detail.log.to_user, "+12125551234");
+        assert_eq!(detail.log.to_domain, "carrier.example.com");
+        assert_eq!(detail.log.status, "answered");
+        assert_eq!(
+            detail.log.termination_reason.as_deref(),
+            Some("NORMAL_CLEARING")
+        );
+        assert_eq!(detail.log.a_leg_codec.as_deref(), Some("PCMU"));
+        assert_eq!(detail.from_tag.as_deref(), Some("a1"));
+        assert_eq!(detail.to_tag.as_deref(), Some("b2"));
+
+        // Re-sent records replace the first, and the oldest are dropped
+        store.ingest(&cdr("call-1")).await.unwrap();
+        store.ingest(&cdr("call-2")).await.unwrap();
+        store.ingest(&cdr("call-3")).await.unwrap();
+        let ids: Vec<String> = store
+            .matching(&CallLogFilter::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.id)
+            .collect();
+        assert_eq!(ids, ["call-3", "call-2"]);
+        assert!(store.get("call-1").await.unwrap().is_none());
+    }
+
+    async fn sqlite() -> Database {
+        let database = rustalk_core::db::connect(&rustalk_core::config::DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            max_connections: 1,
+            min_connections: 0,
+            auto_migrate: false,
+        })
+        .await
+        .unwrap();
+        rustalk_core::db::migrate(&database).await.unwrap();
+        database
+    }
+
+    #[tokio::test]
+    async fn test_filter_sql() {
+        let postgres =
+            Database::Postgres(sqlx::PgPool::connect_lazy("postgres://db/rustalk").unwrap());
+        let (condition, _) = CallLogFilter::default().to_sql(&postgres);
+        assert_eq!(condition, "");
+
+        let filter = CallLogFilter {
+            end_date: Some(1_790_000_000),
+            status: Some("busy".to_string()),
+            callee: Some("21_2%".to_string()),
+            ..Default::default()
+        };
+        let (condition, params) = filter.to_sql(&postgres);
+        assert_eq!(
+            condition,
+            " WHERE start_time <= to_timestamp($1) AND disposition = $2 AND to_user LIKE $3"
+        );
+        assert!(matches!(params[2], Param::Text(ref p) if p == "%212%"));
+
+        let (condition, _) = filter.to_sql(&sqlite().await);
+        assert_eq!(
+            condition,
+            " WHERE start_time <= $1 AND disposition = $2 AND to_user LIKE $3"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let store = CallLogStore::database(sqlite().await);
+        assert!(store.is_persistent());
+        let first = cdr("call-1");
+        let mut second = cdr("call-2");
+        second.start_time = first.start_time + chrono::Duration::seconds(60);
+        second.disposition = CallDisposition::Busy;
+        second.account_code = Some("sales".to_string());
+        store.ingest(&first).await.unwrap();
+        store.ingest(&second).await.unwrap();
+        // Re-sent records replace the one stored
+        store.ingest(&second).await.unwrap();
+
+        let page = store.query(&CallLogFilter::default(), 1, 10).await.unwrap();
+        assert_eq!(page.total, 2);
+        let ids: Vec<&str> = page.logs.iter().map(|l| l.id.as_str()).collect();
+        assert_eq!(ids, ["call-2", "call-1"]);
+
+        let filter = CallLogFilter {
+            start_date: Some(second.start_time.timestamp()),
+            status: Some("busy".to_string()),
+            callee: Some("2125".to_string()),
+            account_code: Some("sales".to_string()),
+            ..Default::default()
+        };
+        let matching = store.matching(&filter).await.unwrap();
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].id, "call-2");
+
+        let detail = store.get("call-1").await.unwrap().unwrap();
+        assert_eq!(detail.log.from_user, "1001");
+        assert_eq!(detail.to_tag.as_deref(), Some("b2"));
+        assert!(store.get("call-3").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut record = cdr("call-1");
+        record.disposition = CallDisposition::Busy;
+        record.charge = Some(Charge {
+            rate_deck: "retail".to_string(),
+            prefix: "44".to_string(),
+            per_minute: 0.1,
+            billed_seconds: 60,
+            amount: 0.1,
+            currency: "USD".to_string(),
+        });
+
+        // What the database sink stores in `record` reads back as the same log
+        let detail = parse_record(&serde_json::to_string(&record).unwrap()).unwrap();
+        assert_eq!(detail.log.status, "busy");
+        assert_eq!(detail.log.to_user, "+12125551234");
+        assert_eq!(detail.total_cost, Some(0.1));
+        assert_eq!(
+            detail.charge_breakdown.unwrap()[0].description,
+            "retail (44)"
+        );
+        assert!(parse_record("{}").is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! API error responses
+//!
+//! Every endpoint reports failures as RFC 7807 problem details
+//! (`application/problem+json`). Besides the standard `type`, `title`,
+//! `status` and `detail` members each problem carries a `code` that clients
+//! can match on; codes are stable across releases even when the wording of
+//! `detail` changes.
+//!
+//! ```json
+//! {
+//!   "type": "urn:rustalk:problem:not_found",
+//!   "title": "Not Found",
+//!   "status": 404,
+//!   "detail": "Extension not found",
+//!   "code": "not_found"
+//! }
+//! ```
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rustalk_core::quotas::QuotaExceeded;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Media type of problem detail responses
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Result of a handler returning a JSON body
+pub type ApiResult = Result<(StatusCode, Json<Value>), ApiError>;
+
+/// Machine-readable error code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is malformed or a parameter is out of range
+    InvalidRequest,
+    /// The resource does not exist
+    NotFound,
+    /// The request conflicts with an existing resource
+    Conflict,
+    /// The request is well-formed but fails validation
+    ValidationFailed,
+    /// Stored route tests fail against the routes
+    RouteTestsFailed,
+    /// An idempotency key was reused for a different request
+    IdempotencyKeyReused,
+    /// A request with the same idempotency key is still running
+    RequestInProgress,
+    /// The request body is too large
+    PayloadTooLarge,
+    /// A write was sent without the `If-Match` header it requires
+    PreconditionRequired,
+    /// The resource changed since the client read it
+    PreconditionFailed,
+    /// The tenant has reached its quota of the resource
+    QuotaExceeded,
+    /// The feature behind the endpoint is not configured on this server
+    NotConfigured,
+    /// A provider or other upstream service failed
+    UpstreamFailed,
+    /// The server cannot take the request right now
+    ServiceUnavailable,
+    /// Unexpected server-side failure
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::RouteTestsFailed => "route_tests_failed",
+            ErrorCode::IdempotencyKeyReused => "idempotency_key_reused",
+            ErrorCode::RequestInProgress => "request_in_progress",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::PreconditionRequired => "precondition_required",
+            ErrorCode::PreconditionFailed => "precondition_failed",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::NotConfigured => "not_configured",
+            ErrorCode::UpstreamFailed => "upstream_failed",
+            ErrorCode::ServiceUnavailable => "service_unavailable",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    /// HTTP status the code is reported with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict | ErrorCode::RequestInProgress => StatusCode::CONFLICT,
+            ErrorCode::ValidationFailed
+            | ErrorCode::RouteTestsFailed
+            | ErrorCode::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ErrorCode::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ErrorCode::QuotaExceeded => StatusCode::FORBIDDEN,
+            ErrorCode::NotConfigured | ErrorCode::ServiceUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ErrorCode::UpstreamFailed => StatusCode::BAD_GATEWAY,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error response in problem details form
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    code: ErrorCode,
+    detail: String,
+    /// Extension members added to the problem
+    extensions: Map<String, Value>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+            extensions: Map::new(),
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidRequest, detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, detail)
+    }
+
+    pub fn conflict(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Conflict, detail)
+    }
+
+    pub fn unprocessable(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::ValidationFailed, detail)
+    }
+
+    pub fn quota_exceeded(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::QuotaExceeded, detail)
+    }
+
+    pub fn not_configured(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotConfigured, detail)
+    }
+
+    pub fn unavailable(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::ServiceUnavailable, detail)
+    }
+
+    pub fn bad_gateway(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::UpstreamFailed, detail)
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, detail)
+    }
+
+    /// Add an extension member, such as a validation report
+    pub fn with(mut self, name: &str, value: impl Serialize) -> Self {
+        self.extensions.insert(
+            name.to_string(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+        self
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+
+    /// The problem details document
+    pub fn body(&self) -> Value {
+        let status = self.status();
+        let mut body = Map::new();
+        body.insert(
+            "type".to_string(),
+            Value::from(format!("urn:rustalk:problem:{}", self.code)),
+        );
+        body.insert(
+            "title".to_string(),
+            Value::from(status.canonical_reason().unwrap_or("Error")),
+        );
+        body.insert("status".to_string(), Value::from(status.as_u16()));
+        body.insert("detail".to_string(), Value::from(self.detail.as_str()));
+        body.insert("code".to_string(), Value::from(self.code.as_str()));
+        for (name, value) in &self.extensions {
+            body.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+        Value::Object(body)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.detail)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<QuotaExceeded> for ApiError {
+    fn from(error: QuotaExceeded) -> Self {
+        ApiError::quota_exceeded(error.to_string())
+            .with("tenant", &error.tenant)
+            .with("resource", error.resource)
+            .with("limit", error.limit)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status(), Json(self.body())).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_problem_details() {
+        let error = ApiError::unprocessable("Validation failed with 1 issues")
+            .with("report", json!({ "issues": [] }))
+            .with("status", 200);
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            error.body(),
+            json!({
+                "type": "urn:rustalk:problem:validation_failed",
+                "title": "Unprocessable Entity",
+                "status": 422,
+                "detail": "Validation failed with 1 issues",
+                "code": "validation_failed",
+                "report": { "issues": [] }
+            })
+        );
+
+        let response = ApiError::not_found("Extension not found").into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            PROBLEM_JSON
+        );
+    }
+}
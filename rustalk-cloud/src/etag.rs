@@ -0,0 +1,134 @@
+//! Entity tags for optimistic concurrency
+//!
+//! GET on an extension, trunk or route returns an `ETag` derived from the
+//! resource's content. PUT and DELETE must send it back in `If-Match`; if
+//! another admin changed the resource in the meantime the tags differ and the
+//! write is refused with 412 instead of silently overwriting their change.
+//! `If-Match: *` matches whatever version is current, for scripts that mean
+//! to overwrite.
+//!
+//! Web UI files are tagged by their bytes instead, and a GET sending a
+//! matching `If-None-Match` is answered 304 without the body.
+
+use axum::{
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::error::{ApiError, ErrorCode};
+
+/// Result of a handler returning a resource along with its ETag
+pub type TaggedResult = Result<(StatusCode, [(HeaderName, String); 1], Json<Value>), ApiError>;
+
+/// Strong entity tag of a resource's JSON representation
+pub fn etag<T: Serialize>(resource: &T) -> String {
+    etag_bytes(&serde_json::to_vec(resource).unwrap_or_default())
+}
+
+/// Strong entity tag of a file's contents
+pub fn etag_bytes(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// `ETag` response header for a resource
+pub fn etag_header<T: Serialize>(resource: &T) -> [(HeaderName, String); 1] {
+    [(header::ETAG, etag(resource))]
+}
+
+/// Check the request's `If-Match` header against the resource's current tag
+///
+/// Weak tags never match, as RFC 9110 requires strong comparison here.
+pub fn check_if_match(headers: &HeaderMap, current: &str) -> Result<(), ApiError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Err(ApiError::new(
+            ErrorCode::PreconditionRequired,
+            "If-Match is required; send the ETag from a GET of the resource",
+        ));
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| ApiError::bad_request("If-Match is not a valid header value"))?;
+
+    if value
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == current)
+    {
+        Ok(())
+    } else {
+        Err(ApiError::new(
+            ErrorCode::PreconditionFailed,
+            "The resource was changed since it was read",
+        )
+        .with("etag", current))
+    }
+}
+
+/// Whether the request's `If-None-Match` already names the current tag,
+/// so the client's cached copy can be used
+///
+/// Uses weak comparison, as RFC 9110 allows for `If-None-Match`.
+pub fn is_not_modified(headers: &HeaderMap, current: &str) -> bool {
+    let Some(value) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    value.split(',').map(str::trim).any(|tag| {
+        tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == current.trim_start_matches("W/")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    fn if_match(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_if_match() {
+        let tag = etag(&json!({ "id": "1001", "enabled": true }));
+        assert!(tag.starts_with('"') && tag.ends_with('"'));
+        assert_eq!(tag, etag(&json!({ "id": "1001", "enabled": true })));
+        assert_ne!(tag, etag(&json!({ "id": "1001", "enabled": false })));
+
+        assert!(check_if_match(&if_match(&tag), &tag).is_ok());
+        assert!(check_if_match(&if_match(&format!("\"stale\", {}", tag)), &tag).is_ok());
+        assert!(check_if_match(&if_match("*"), &tag).is_ok());
+
+        let error = check_if_match(&if_match("\"stale\""), &tag).unwrap_err();
+        assert_eq!(error.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(error.body()["etag"], json!(tag));
+        let weak = format!("W/{}", tag);
+        assert!(check_if_match(&if_match(&weak), &tag).is_err());
+
+        let error = check_if_match(&HeaderMap::new(), &tag).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::PreconditionRequired);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let tag = etag_bytes(b"render()");
+        assert_eq!(tag, etag_bytes(b"render()"));
+        let mut headers = HeaderMap::new();
+        assert!(!is_not_modified(&headers, &tag));
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_str(&format!("\"stale\", W/{}", tag)).unwrap(),
+        );
+        assert!(is_not_modified(&headers, &tag));
+        assert!(!is_not_modified(&headers, &etag_bytes(b"render(2)")));
+    }
+}
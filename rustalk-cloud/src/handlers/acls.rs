@@ -1,11 +1,12 @@
 //! ACL management handlers
 
+use crate::error::{ApiError, ApiResult};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     Json,
 };
-use rustalk_core::acl::{Acl, AclManager};
+use rustalk_core::acl::{Acl, AclAction, AclManager};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -25,52 +26,35 @@ pub async fn list_acls(State(state): State<AclsState>) -> (StatusCode, Json<Valu
 }
 
 /// Get a specific ACL
-pub async fn get_acl(
-    Path(name): Path<String>,
-    State(state): State<AclsState>,
-) -> (StatusCode, Json<Value>) {
+pub async fn get_acl(Path(name): Path<String>, State(state): State<AclsState>) -> ApiResult {
     let manager = state.read().await;
 
     if let Some(acl) = manager.get_acl(&name) {
-        (StatusCode::OK, Json(json!(acl)))
+        Ok((StatusCode::OK, Json(json!(acl))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "ACL not found"
-            })),
-        )
+        Err(ApiError::not_found("ACL not found"))
     }
 }
 
 /// Create a new ACL
-pub async fn create_acl(
-    State(state): State<AclsState>,
-    Json(payload): Json<Acl>,
-) -> (StatusCode, Json<Value>) {
+pub async fn create_acl(State(state): State<AclsState>, Json(payload): Json<Acl>) -> ApiResult {
     let mut manager = state.write().await;
 
     // Check if ACL already exists
     if manager.get_acl(&payload.name).is_some() {
-        return (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": "ACL already exists"
-            })),
-        );
+        return Err(ApiError::conflict("ACL already exists"));
     }
 
     manager.add_acl(payload.clone());
 
-    (
+    Ok((
         StatusCode::CREATED,
         Json(json!({
             "success": true,
             "message": "ACL created successfully",
             "name": payload.name
         })),
-    )
+    ))
 }
 
 /// Update an existing ACL
@@ -78,52 +62,37 @@ pub async fn update_acl(
     Path(name): Path<String>,
     State(state): State<AclsState>,
     Json(payload): Json<Acl>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut manager = state.write().await;
 
     if manager.get_acl(&name).is_some() {
         manager.add_acl(payload);
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "ACL updated successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "ACL not found"
-            })),
-        )
+        Err(ApiError::not_found("ACL not found"))
     }
 }
 
 /// Delete an ACL
-pub async fn delete_acl(
-    Path(name): Path<String>,
-    State(state): State<AclsState>,
-) -> (StatusCode, Json<Value>) {
+pub async fn delete_acl(Path(name): Path<String>, State(state): State<AclsState>) -> ApiResult {
     let mut manager = state.write().await;
 
     if manager.remove_acl(&name) {
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "ACL deleted successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "ACL not found"
-            })),
-        )
+        Err(ApiError::not_found("ACL not found"))
     }
 }
 
@@ -131,43 +100,41 @@ pub async fn delete_acl(
 pub async fn check_ip(
     Path((name, ip)): Path<(String, String)>,
     State(state): State<AclsState>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let manager = state.read().await;
 
     let ip_addr = match ip.parse() {
         Ok(addr) => addr,
         Err(_) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(json!({
-                    "error": "Invalid IP address"
-                })),
-            );
+            return Err(ApiError::bad_request("Invalid IP address"));
         }
     };
 
-    match manager.is_allowed(&name, ip_addr) {
-        Ok(allowed) => (
+    let Some(acl) = manager.get_acl(&name) else {
+        return Err(ApiError::not_found(format!("ACL '{}' not found", name)));
+    };
+
+    // Report the deciding rule so admins can see why a peer is blocked
+    match acl.matching_rule(ip_addr) {
+        Ok(rule) => Ok((
             StatusCode::OK,
             Json(json!({
                 "acl": name,
                 "ip": ip,
-                "allowed": allowed
+                "allowed": matches!(rule.map_or(acl.default_policy, |r| r.action), AclAction::Allow),
+                "enabled": acl.enabled,
+                "matched_rule": rule,
+                "default_policy": acl.default_policy
             })),
-        ),
-        Err(e) => (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": format!("ACL check failed: {}", e)
-            })),
-        ),
+        )),
+        Err(e) => Err(ApiError::not_found(format!("ACL check failed: {}", e))),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rustalk_core::acl::{create_default_acls, AclAction, AclRule};
+    use rustalk_core::acl::{create_default_acls, AclRule};
 
     #[tokio::test]
     async fn test_list_acls() {
@@ -183,7 +150,9 @@ mod tests {
     #[tokio::test]
     async fn test_get_acl() {
         let state = Arc::new(RwLock::new(create_default_acls()));
-        let (status, response) = get_acl(Path("localhost".to_string()), State(state)).await;
+        let (status, response) = get_acl(Path("localhost".to_string()), State(state))
+            .await
+            .unwrap();
         assert_eq!(status, StatusCode::OK);
 
         let value = response.0;
@@ -202,7 +171,7 @@ mod tests {
             priority: 10,
         });
 
-        let (status, response) = create_acl(State(state.clone()), Json(acl)).await;
+        let (status, response) = create_acl(State(state.clone()), Json(acl)).await.unwrap();
         assert_eq!(status, StatusCode::CREATED);
         assert!(response.0["success"].as_bool().unwrap());
 
@@ -219,9 +188,28 @@ mod tests {
             Path(("localhost".to_string(), "127.0.0.1".to_string())),
             State(state),
         )
-        .await;
+        .await
+        .unwrap();
 
         assert_eq!(status, StatusCode::OK);
         assert!(response.0["allowed"].as_bool().unwrap());
+        assert_eq!(response.0["matched_rule"]["cidr"], "127.0.0.0/8");
+    }
+
+    #[tokio::test]
+    async fn test_check_ip_default_policy() {
+        let state = Arc::new(RwLock::new(create_default_acls()));
+
+        let (status, response) = check_ip(
+            Path(("rfc1918".to_string(), "8.8.8.8".to_string())),
+            State(state),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(!response.0["allowed"].as_bool().unwrap());
+        assert!(response.0["matched_rule"].is_null());
+        assert_eq!(response.0["default_policy"], "deny");
     }
 }
@@ -0,0 +1,372 @@
+//! Analytics handlers
+
+use crate::error::{ApiError, ApiResult};
+use crate::handlers::extensions::ExtensionsState;
+use crate::handlers::trunks::TrunksState;
+use crate::handlers::voicemail::VoicemailState;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use rustalk_core::call_history::CallHistory;
+use rustalk_core::media_quality::MediaQualityTracker;
+use rustalk_core::pdd::PddTracker;
+use rustalk_core::quotas::{Quotas, TenantUsage};
+use rustalk_core::teams_records::{summarize, TeamsCallRecords};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+
+/// Teams call records, when the Graph sync is configured
+pub type AnalyticsState = Option<TeamsCallRecords>;
+
+/// Recorded calls, for breakdowns across all extensions
+pub type HangupCausesState = Option<CallHistory>;
+
+/// Post-dial delays of recent calls
+pub type PddState = Option<PddTracker>;
+
+/// RTP statistics of recent calls
+pub type MediaQualityState = Option<MediaQualityTracker>;
+
+/// Tenant quotas and what counts against them
+#[derive(Clone)]
+pub struct TenantsState {
+    pub quotas: Quotas,
+    pub extensions: ExtensionsState,
+    pub trunks: TrunksState,
+    pub voicemail: VoicemailState,
+}
+
+/// Reporting period as Unix timestamps
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsQuery {
+    pub start_date: Option<i64>,
+    pub end_date: Option<i64>,
+}
+
+fn timestamp(seconds: Option<i64>) -> Option<DateTime<Utc>> {
+    seconds.and_then(|s| DateTime::from_timestamp(s, 0))
+}
+
+fn not_configured() -> ApiResult {
+    Err(ApiError::not_configured(
+        "Teams call record sync is not configured",
+    ))
+}
+
+/// Direct Routing volumes and quality, from Teams and SBC records combined
+pub async fn teams_summary(
+    State(state): State<AnalyticsState>,
+    Query(params): Query<AnalyticsQuery>,
+) -> ApiResult {
+    let Some(records) = state else {
+        return not_configured();
+    };
+    let calls = records
+        .correlated(timestamp(params.start_date), timestamp(params.end_date))
+        .await;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "summary": summarize(&calls),
+            "last_sync": records.last_sync().await
+        })),
+    ))
+}
+
+/// Teams calls matched with their SBC CDRs
+pub async fn teams_calls(
+    State(state): State<AnalyticsState>,
+    Query(params): Query<AnalyticsQuery>,
+) -> ApiResult {
+    let Some(records) = state else {
+        return not_configured();
+    };
+    let calls = records
+        .correlated(timestamp(params.start_date), timestamp(params.end_date))
+        .await;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "calls": calls,
+            "total": calls.len()
+        })),
+    ))
+}
+
+/// Calls in the period counted by normalized hangup cause
+pub async fn hangup_causes(
+    State(state): State<HangupCausesState>,
+    Query(params): Query<AnalyticsQuery>,
+) -> ApiResult {
+    let Some(history) = state else {
+        return Err(ApiError::not_configured("Call history is not configured"));
+    };
+    let causes = history
+        .hangup_causes(timestamp(params.start_date), timestamp(params.end_date))
+        .await;
+    let total: usize = causes.iter().map(|c| c.count).sum();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "causes": causes,
+            "total": total
+        })),
+    ))
+}
+
+/// Post-dial delay percentiles over recent calls, overall and per trunk,
+/// with the trunks over the alert threshold
+pub async fn post_dial_delay(State(state): State<PddState>) -> ApiResult {
+    let Some(pdd) = state else {
+        return Err(ApiError::not_configured(
+            "Post-dial delay tracking is not configured",
+        ));
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "overall": pdd.overall(),
+            "trunks": pdd.trunks(),
+            "degraded": pdd.degraded(),
+            "alert_threshold_ms": pdd.config().alert_threshold_ms
+        })),
+    ))
+}
+
+/// Packet loss and jitter per trunk over the sliding window, with the
+/// trunks over an alert threshold
+pub async fn media_quality(State(state): State<MediaQualityState>) -> ApiResult {
+    let Some(tracker) = state else {
+        return Err(ApiError::not_configured(
+            "Media quality tracking is not configured",
+        ));
+    };
+    let config = tracker.config();
+    let degraded: Vec<_> = tracker
+        .degraded()
+        .into_iter()
+        .map(|(trunk, metric)| json!({ "trunk": trunk, "metric": metric }))
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "trunks": tracker.trunks(),
+            "degraded": degraded,
+            "window_seconds": config.window_seconds,
+            "max_loss_percent": config.max_loss_percent,
+            "max_jitter_ms": config.max_jitter_ms
+        })),
+    ))
+}
+
+/// Each tenant's extensions, trunks, calls up and voicemail storage
+/// against its quota, for billing
+///
+/// Tenants with a quota are listed along with any named on an extension or
+/// trunk.
+pub async fn tenant_usage(State(state): State<TenantsState>) -> ApiResult {
+    let extensions = state.extensions.read().await;
+    let trunks = state.trunks.read().await;
+    let mut tenants: BTreeSet<String> = state.quotas.tenants().into_iter().collect();
+    tenants.extend(
+        extensions
+            .iter()
+            .filter_map(|e| e.tenant.as_deref())
+            .chain(trunks.iter().filter_map(|t| t.tenant.as_deref()))
+            .map(str::to_ascii_lowercase),
+    );
+
+    let belongs = |tenant: &Option<String>, domain: &str| {
+        tenant
+            .as_deref()
+            .is_some_and(|t| t.eq_ignore_ascii_case(domain))
+    };
+    let mut usage = Vec::with_capacity(tenants.len());
+    for tenant in tenants {
+        usage.push(TenantUsage {
+            extensions: extensions
+                .iter()
+                .filter(|e| belongs(&e.tenant, &tenant))
+                .count() as u64,
+            trunks: trunks
+                .iter()
+                .filter(|t| belongs(&t.tenant, &tenant))
+                .count() as u64,
+            concurrent_calls: state.quotas.active_calls(&tenant),
+            storage_bytes: state.voicemail.storage_used(&tenant).await,
+            quota: state.quotas.quota(&tenant).cloned(),
+            tenant,
+        });
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "total": usage.len(),
+            "tenants": usage
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::media_quality::MediaReport;
+    use rustalk_core::teams_records::DirectRoutingCall;
+    use rustalk_core::voicemail::VoicemailManager;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_teams_summary_period() {
+        let records = TeamsCallRecords::new(30);
+        let call: DirectRoutingCall = serde_json::from_value(json!({
+            "id": "row-1",
+            "correlationId": "corr-1",
+            "startDateTime": "2026-10-01T09:00:00Z",
+            "duration": 120,
+            "successfulCall": true,
+            "finalSipCode": 200
+        }))
+        .unwrap();
+        records.add_teams_calls(vec![call]).await;
+
+        let (status, response) = teams_summary(
+            State(Some(records.clone())),
+            Query(AnalyticsQuery {
+                start_date: None,
+                end_date: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["summary"]["total_calls"], 1);
+        assert_eq!(response.0["summary"]["teams_only_calls"], 1);
+
+        // 2026-10-02T00:00:00Z onwards
+        let (_, response) = teams_calls(
+            State(Some(records)),
+            Query(AnalyticsQuery {
+                start_date: Some(1_790_899_200),
+                end_date: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0["total"], 0);
+
+        let error = teams_summary(
+            State(None),
+            Query(AnalyticsQuery {
+                start_date: None,
+                end_date: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_post_dial_delay() {
+        let pdd = PddTracker::default();
+        pdd.record("carrier.example.com", Duration::from_millis(1200));
+        pdd.record("carrier.example.com", Duration::from_millis(2400));
+
+        let (status, response) = post_dial_delay(State(Some(pdd))).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["overall"]["samples"], 2);
+        assert_eq!(response.0["trunks"][0]["trunk"], "carrier.example.com");
+        assert_eq!(response.0["trunks"][0]["p50_ms"], 1200);
+        assert_eq!(response.0["trunks"][0]["max_ms"], 2400);
+        assert_eq!(response.0["alert_threshold_ms"], 4000);
+
+        let error = post_dial_delay(State(None)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_media_quality() {
+        let tracker = MediaQualityTracker::default();
+        let report = MediaReport::parse("PR=980,PL=20,JI=12").unwrap();
+        tracker.record("carrier.example.com", report);
+
+        let (status, response) = media_quality(State(Some(tracker))).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["trunks"][0]["trunk"], "carrier.example.com");
+        assert_eq!(response.0["trunks"][0]["calls"], 1);
+        assert_eq!(response.0["trunks"][0]["loss_percent"], 2.0);
+        assert_eq!(response.0["max_jitter_ms"], 30.0);
+
+        let error = media_quality(State(None)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_usage() {
+        let quotas = Quotas::new(
+            serde_json::from_value(json!({
+                "tenants": { "acme.example": { "max_extensions": 10, "max_trunks": 1 } }
+            }))
+            .unwrap(),
+        );
+        quotas.start_call("acme.example").unwrap();
+        let extension = serde_json::from_value(json!({
+            "id": "front-desk",
+            "extension": "1001",
+            "display_name": "Front Desk",
+            "password": "secret",
+            "enabled": true,
+            "voicemail_enabled": false,
+            "priority": 0,
+            "tenant": "ACME.example"
+        }))
+        .unwrap();
+        let trunk = serde_json::from_value(json!({
+            "id": "carrier",
+            "name": "Carrier",
+            "description": null,
+            "host": "sip.carrier.example",
+            "port": 5060,
+            "username": null,
+            "password": null,
+            "enabled": true,
+            "priority": 1,
+            "tenant": "beta.example"
+        }))
+        .unwrap();
+        let state = TenantsState {
+            quotas,
+            extensions: Arc::new(RwLock::new(vec![extension])),
+            trunks: Arc::new(RwLock::new(vec![trunk])),
+            voicemail: VoicemailManager::new(std::env::temp_dir().join("rustalk_tenant_usage")),
+        };
+
+        let (status, response) = tenant_usage(State(state)).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["total"], 2);
+        let acme = &response.0["tenants"][0];
+        assert_eq!(acme["tenant"], "acme.example");
+        assert_eq!(acme["extensions"], 1);
+        assert_eq!(acme["trunks"], 0);
+        assert_eq!(acme["concurrent_calls"], 1);
+        assert_eq!(acme["storage_bytes"], 0);
+        assert_eq!(acme["quota"]["max_trunks"], 1);
+        // Named on a trunk without a quota of its own
+        let beta = &response.0["tenants"][1];
+        assert_eq!(beta["tenant"], "beta.example");
+        assert_eq!(beta["trunks"], 1);
+        assert!(beta["quota"].is_null());
+    }
+}
@@ -0,0 +1,682 @@
+//! Declarative apply handler
+//!
+//! `POST /api/v1/apply` takes the desired state of extensions, trunks,
+//! routes and ACLs as one document, such as one kept in version control.
+//! Each section present replaces its collection; sections left out are not
+//! touched.
+//!
+//! Without `?confirm` the answer is the plan: what would be created,
+//! updated and deleted, and a `plan_id` naming it. Sending the same
+//! document with `?confirm=<plan_id>` applies the plan, all of it or none.
+//! If anything changed since the plan was made the ids differ, and the apply
+//! is refused with 412 and the new plan to review.
+//!
+//! Deleted extensions and trunks go to the trash, as a DELETE of them does.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use rustalk_core::acl::Acl;
+use rustalk_core::no_answer::NoAnswerPolicy;
+use rustalk_core::quotas::{QuotaResource, Quotas};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::etag::etag;
+use crate::handlers::{
+    acls::AclsState,
+    extensions::{apply_no_answer, validate_no_answer, ExtensionsState},
+    routes::RoutesState,
+    trunks::TrunksState,
+};
+use crate::models::{DesiredState, Extension, Route, RouteDestination, Trunk};
+use crate::trash::Trash;
+
+/// The collections an apply replaces, and what deleting from them involves
+#[derive(Clone)]
+pub struct ApplyState {
+    pub extensions: ExtensionsState,
+    pub trunks: TrunksState,
+    pub routes: RoutesState,
+    pub acls: AclsState,
+    pub extensions_trash: Trash<Extension>,
+    pub trunks_trash: Trash<Trunk>,
+    pub no_answer: NoAnswerPolicy,
+    pub quotas: Quotas,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ApplyParams {
+    /// Id of the reviewed plan, to apply it
+    #[serde(default)]
+    pub confirm: Option<String>,
+}
+
+/// Kind of resource a planned change is to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceKind {
+    Extension,
+    Trunk,
+    Route,
+    Acl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// One resource the plan creates, updates or deletes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct PlannedChange {
+    pub resource: ResourceKind,
+    /// Id of the resource; name for ACLs
+    pub id: String,
+    pub action: ChangeAction,
+    /// Fields an update changes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+}
+
+/// Changes that bring the current state to the desired state
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Plan {
+    /// Names this plan against the state it was made from
+    pub plan_id: String,
+    pub changes: Vec<PlannedChange>,
+    /// Collections whose order changes, which matters for routes
+    pub reordered: Vec<ResourceKind>,
+    /// Resources the desired state leaves as they are
+    pub unchanged: usize,
+}
+
+impl Plan {
+    /// Whether applying the plan would change nothing
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.reordered.is_empty()
+    }
+
+    fn count(&self, action: ChangeAction) -> usize {
+        self.changes.iter().filter(|c| c.action == action).count()
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} to create, {} to update, {} to delete",
+            self.count(ChangeAction::Create),
+            self.count(ChangeAction::Update),
+            self.count(ChangeAction::Delete)
+        )
+    }
+}
+
+/// Plan the changes from the current collections to `desired`
+pub fn plan(
+    extensions: &[Extension],
+    trunks: &[Trunk],
+    routes: &[Route],
+    acls: &[Acl],
+    desired: &DesiredState,
+) -> Plan {
+    let mut plan = Plan {
+        plan_id: etag(&(extensions, trunks, routes, acls, desired))
+            .trim_matches('"')
+            .to_string(),
+        changes: Vec::new(),
+        reordered: Vec::new(),
+        unchanged: 0,
+    };
+    if let Some(desired) = &desired.extensions {
+        diff(
+            ResourceKind::Extension,
+            extensions,
+            desired,
+            |e| &e.id,
+            &mut plan,
+        );
+    }
+    if let Some(desired) = &desired.trunks {
+        diff(ResourceKind::Trunk, trunks, desired, |t| &t.id, &mut plan);
+    }
+    if let Some(desired) = &desired.routes {
+        diff(ResourceKind::Route, routes, desired, |r| &r.id, &mut plan);
+    }
+    if let Some(desired) = &desired.acls {
+        diff(ResourceKind::Acl, acls, desired, |a| &a.name, &mut plan);
+    }
+    plan
+}
+
+/// Add the changes from `current` to `desired` to the plan, matching
+/// resources by `key`
+fn diff<T: Serialize>(
+    resource: ResourceKind,
+    current: &[T],
+    desired: &[T],
+    key: impl Fn(&T) -> &String,
+    plan: &mut Plan,
+) {
+    for wanted in desired {
+        let Some(existing) = current.iter().find(|c| key(c) == key(wanted)) else {
+            plan.changes.push(PlannedChange {
+                resource,
+                id: key(wanted).clone(),
+                action: ChangeAction::Create,
+                fields: Vec::new(),
+            });
+            continue;
+        };
+        let fields = changed_fields(&json!(existing), &json!(wanted));
+        if fields.is_empty() {
+            plan.unchanged += 1;
+        } else {
+            plan.changes.push(PlannedChange {
+                resource,
+                id: key(wanted).clone(),
+                action: ChangeAction::Update,
+                fields,
+            });
+        }
+    }
+    for existing in current {
+        if !desired.iter().any(|d| key(d) == key(existing)) {
+            plan.changes.push(PlannedChange {
+                resource,
+                id: key(existing).clone(),
+                action: ChangeAction::Delete,
+                fields: Vec::new(),
+            });
+        }
+    }
+
+    // Resources kept on both sides must come in the same order
+    let kept: HashSet<&String> = desired.iter().map(&key).collect();
+    let before = current.iter().map(&key).filter(|k| kept.contains(k));
+    let after = desired
+        .iter()
+        .map(&key)
+        .filter(|k| current.iter().any(|c| key(c) == *k));
+    if !before.eq(after) {
+        plan.reordered.push(resource);
+    }
+}
+
+/// Top-level fields that differ between two resources, sorted
+fn changed_fields(before: &Value, after: &Value) -> Vec<String> {
+    let (Some(before), Some(after)) = (before.as_object(), after.as_object()) else {
+        return Vec::new();
+    };
+    let mut fields: Vec<String> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|field| before.get(*field) != after.get(*field))
+        .cloned()
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+/// Check the desired state the way creating each resource would, and that
+/// routes are left with the trunks they send calls to
+fn check(
+    quotas: &Quotas,
+    extensions: &[Extension],
+    trunks: &[Trunk],
+    routes: &[Route],
+    desired: &DesiredState,
+) -> Result<(), ApiError> {
+    if let Some(wanted) = &desired.extensions {
+        unique("extensions", wanted, |e| &e.id, "Extension id")?;
+        unique("extensions", wanted, |e| &e.extension, "Extension number")?;
+        for ext in wanted {
+            validate_no_answer(ext)?;
+        }
+        check_quotas(
+            quotas,
+            QuotaResource::Extensions,
+            extensions.iter().map(|e| &e.tenant),
+            wanted.iter().map(|e| &e.tenant),
+        )?;
+    }
+    if let Some(wanted) = &desired.trunks {
+        unique("trunks", wanted, |t| &t.id, "Trunk id")?;
+        unique("trunks", wanted, |t| &t.name, "Trunk name")?;
+        check_quotas(
+            quotas,
+            QuotaResource::Trunks,
+            trunks.iter().map(|t| &t.tenant),
+            wanted.iter().map(|t| &t.tenant),
+        )?;
+    }
+    if let Some(wanted) = &desired.routes {
+        unique("routes", wanted, |r| &r.id, "Route id")?;
+        unique("routes", wanted, |r| &r.name, "Route name")?;
+    }
+    if let Some(wanted) = &desired.acls {
+        unique("acls", wanted, |a| &a.name, "ACL name")?;
+    }
+
+    let trunks = desired.trunks.as_deref().unwrap_or(trunks);
+    for route in desired.routes.as_deref().unwrap_or(routes) {
+        let named: Vec<&String> = match &route.destination {
+            RouteDestination::Trunk(trunk) | RouteDestination::Enum(trunk) => vec![trunk],
+            RouteDestination::LeastCost(candidates) => candidates.iter().collect(),
+            RouteDestination::DestinationList(list) => list
+                .hops
+                .iter()
+                .flat_map(|hop| hop.destination.trunks())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let rerouted = route
+            .on_failure
+            .iter()
+            .flat_map(|rule| &rule.destinations.hops)
+            .flat_map(|hop| hop.destination.trunks());
+        for trunk in named.into_iter().chain(rerouted) {
+            if !trunks.iter().any(|t| &t.id == trunk || &t.name == trunk) {
+                return Err(ApiError::unprocessable(format!(
+                    "Route '{}' sends calls to trunk '{}', which would not exist",
+                    route.id, trunk
+                ))
+                .with("field", "routes"));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn unique<T>(
+    section: &str,
+    resources: &[T],
+    key: impl Fn(&T) -> &String,
+    what: &str,
+) -> Result<(), ApiError> {
+    let mut seen = HashSet::new();
+    for resource in resources {
+        if !seen.insert(key(resource)) {
+            return Err(ApiError::unprocessable(format!(
+                "{} '{}' is listed twice",
+                what,
+                key(resource)
+            ))
+            .with("field", section));
+        }
+    }
+    Ok(())
+}
+
+/// Check every tenant the desired state gives more of `resource` against
+/// its quota
+///
+/// Tenants already over a lowered quota may keep what they have.
+fn check_quotas<'a>(
+    quotas: &Quotas,
+    resource: QuotaResource,
+    current: impl Iterator<Item = &'a Option<String>>,
+    desired: impl Iterator<Item = &'a Option<String>>,
+) -> Result<(), ApiError> {
+    let current = tenant_counts(current);
+    for (tenant, count) in tenant_counts(desired) {
+        if count > current.get(&tenant).copied().unwrap_or(0) {
+            quotas.check(&tenant, resource, count)?;
+        }
+    }
+    Ok(())
+}
+
+fn tenant_counts<'a>(tenants: impl Iterator<Item = &'a Option<String>>) -> HashMap<String, u64> {
+    let mut counts = HashMap::new();
+    for tenant in tenants.flatten() {
+        *counts.entry(tenant.to_ascii_lowercase()).or_default() += 1;
+    }
+    counts
+}
+
+/// Plan the desired state, and apply it when the plan is confirmed
+pub async fn apply(
+    State(state): State<ApplyState>,
+    Query(params): Query<ApplyParams>,
+    Json(desired): Json<DesiredState>,
+) -> ApiResult {
+    // Plan and apply under every lock, so the plan confirmed is the one
+    // applied and requests never see half of it
+    let mut acls = state.acls.write().await;
+    let mut routes = state.routes.write().await;
+    let mut extensions = state.extensions.write().await;
+    let mut trunks = state.trunks.write().await;
+
+    let plan = plan(&extensions, &trunks, &routes, &acls.acls, &desired);
+    check(&state.quotas, &extensions, &trunks, &routes, &desired)
+        .map_err(|e| e.with("plan", &plan))?;
+
+    let Some(confirm) = params.confirm else {
+        return Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "applied": false,
+                "message": format!("Plan: {}", plan.summary()),
+                "plan": plan
+            })),
+        ));
+    };
+    if confirm != plan.plan_id {
+        return Err(ApiError::new(
+            ErrorCode::PreconditionFailed,
+            "The state changed since the plan was made; review the new plan and confirm it",
+        )
+        .with("plan", &plan));
+    }
+
+    // Everything is checked; from here nothing fails
+    if let Some(wanted) = desired.extensions {
+        for ext in extensions.drain(..) {
+            match wanted.iter().find(|w| w.id == ext.id) {
+                Some(kept) if kept.extension != ext.extension => {
+                    state.no_answer.remove_extension(&ext.extension);
+                }
+                Some(_) => {}
+                None => {
+                    let mut ext = ext;
+                    let was_enabled = std::mem::replace(&mut ext.enabled, false);
+                    state.extensions_trash.bury(ext, was_enabled).await;
+                }
+            }
+        }
+        for ext in &wanted {
+            apply_no_answer(&state.no_answer, ext);
+        }
+        *extensions = wanted;
+    }
+    if let Some(wanted) = desired.trunks {
+        for mut trunk in trunks.drain(..) {
+            if !wanted.iter().any(|w| w.id == trunk.id) {
+                let was_enabled = std::mem::replace(&mut trunk.enabled, false);
+                state.trunks_trash.bury(trunk, was_enabled).await;
+            }
+        }
+        *trunks = wanted;
+    }
+    if let Some(wanted) = desired.routes {
+        *routes = wanted;
+    }
+    if let Some(wanted) = desired.acls {
+        acls.acls = wanted;
+    }
+
+    info!("Applied plan {}: {}", plan.plan_id, plan.summary());
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "applied": true,
+            "message": format!("Applied: {}", plan.summary()),
+            "plan": plan
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{RouteAction, ScreeningMode};
+    use rustalk_core::quotas::{QuotaConfig, TenantQuota};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    fn extension(id: &str, number: &str) -> Extension {
+        Extension {
+            id: id.to_string(),
+            extension: number.to_string(),
+            display_name: "Front Desk".to_string(),
+            password: "secret".to_string(),
+            enabled: true,
+            voicemail_enabled: true,
+            priority: 0,
+            class_of_service: None,
+            require_dial_pin: false,
+            screening: ScreeningMode::Off,
+            ring_seconds: None,
+            no_answer: None,
+            tenant: None,
+        }
+    }
+
+    fn trunk(id: &str) -> Trunk {
+        Trunk {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: None,
+            host: "sip.carrier.example".to_string(),
+            port: 5060,
+            username: None,
+            password: None,
+            enabled: true,
+            priority: 0,
+            quirks: None,
+            tenant: None,
+        }
+    }
+
+    fn route(id: &str, trunk: &str) -> Route {
+        Route {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: None,
+            pattern: "^9(.*)$".to_string(),
+            destination: RouteDestination::Trunk(trunk.to_string()),
+            enabled: true,
+            priority: 0,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        }
+    }
+
+    fn state(extensions: Vec<Extension>, trunks: Vec<Trunk>, routes: Vec<Route>) -> ApplyState {
+        ApplyState {
+            extensions: Arc::new(RwLock::new(extensions)),
+            trunks: Arc::new(RwLock::new(trunks)),
+            routes: Arc::new(RwLock::new(routes)),
+            acls: Arc::new(RwLock::new(Default::default())),
+            extensions_trash: Trash::default(),
+            trunks_trash: Trash::default(),
+            no_answer: NoAnswerPolicy::default(),
+            quotas: Quotas::default(),
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let extensions = vec![extension("a", "1001"), extension("b", "1002")];
+        let trunks = vec![trunk("carrier")];
+        let mut renamed = extension("a", "1001");
+        renamed.display_name = "Lobby".to_string();
+        let desired = DesiredState {
+            extensions: Some(vec![extension("c", "1003"), renamed]),
+            ..Default::default()
+        };
+
+        let plan = plan(&extensions, &trunks, &[], &[], &desired);
+        assert_eq!(
+            plan.changes,
+            vec![
+                PlannedChange {
+                    resource: ResourceKind::Extension,
+                    id: "c".to_string(),
+                    action: ChangeAction::Create,
+                    fields: Vec::new(),
+                },
+                PlannedChange {
+                    resource: ResourceKind::Extension,
+                    id: "a".to_string(),
+                    action: ChangeAction::Update,
+                    fields: vec!["display_name".to_string()],
+                },
+                PlannedChange {
+                    resource: ResourceKind::Extension,
+                    id: "b".to_string(),
+                    action: ChangeAction::Delete,
+                    fields: Vec::new(),
+                },
+            ]
+        );
+        assert!(plan.reordered.is_empty());
+        assert_eq!(plan.summary(), "1 to create, 1 to update, 1 to delete");
+
+        // Trunks are left out, so left alone
+        let same = DesiredState {
+            extensions: Some(extensions.clone()),
+            ..Default::default()
+        };
+        let unchanged = super::plan(&extensions, &trunks, &[], &[], &same);
+        assert!(unchanged.is_empty());
+        assert_eq!(unchanged.unchanged, 2);
+
+        let swapped = DesiredState {
+            extensions: Some(vec![extension("b", "1002"), extension("a", "1001")]),
+            ..Default::default()
+        };
+        let reordered = super::plan(&extensions, &trunks, &[], &[], &swapped);
+        assert!(reordered.changes.is_empty());
+        assert_eq!(reordered.reordered, vec![ResourceKind::Extension]);
+    }
+
+    #[tokio::test]
+    async fn test_apply_confirmed_plan() {
+        let state = state(
+            vec![extension("a", "1001")],
+            vec![trunk("old"), trunk("carrier")],
+            vec![route("outbound", "old")],
+        );
+        let desired = DesiredState {
+            extensions: Some(Vec::new()),
+            trunks: Some(vec![trunk("carrier")]),
+            routes: Some(vec![route("outbound", "carrier")]),
+            acls: None,
+        };
+
+        // Planning changes nothing
+        let (status, Json(body)) = apply(
+            State(state.clone()),
+            Query(ApplyParams::default()),
+            Json(desired.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["applied"], false);
+        assert_eq!(body["plan"]["changes"].as_array().unwrap().len(), 3);
+        assert_eq!(state.trunks.read().await.len(), 2);
+        let plan_id = body["plan"]["plan_id"].as_str().unwrap().to_string();
+
+        // A plan made against other state is refused
+        let stale = apply(
+            State(state.clone()),
+            Query(ApplyParams {
+                confirm: Some("0123".to_string()),
+            }),
+            Json(desired.clone()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(stale.code(), ErrorCode::PreconditionFailed);
+        assert_eq!(state.extensions.read().await.len(), 1);
+
+        let (_, Json(body)) = apply(
+            State(state.clone()),
+            Query(ApplyParams {
+                confirm: Some(plan_id),
+            }),
+            Json(desired.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(body["applied"], true);
+        assert!(state.extensions.read().await.is_empty());
+        assert_eq!(state.trunks.read().await[0].id, "carrier");
+        assert!(matches!(
+            &state.routes.read().await[0].destination,
+            RouteDestination::Trunk(trunk) if trunk == "carrier"
+        ));
+        // Deleted resources can be restored
+        let buried = state.trunks_trash.list().await;
+        assert_eq!(buried[0].resource.id, "old");
+        assert!(!buried[0].resource.enabled);
+        assert_eq!(state.extensions_trash.list().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_rejects_invalid_state() {
+        let mut state = state(Vec::new(), vec![trunk("carrier")], Vec::new());
+
+        // Routes must keep the trunks they send calls to
+        let orphaned = DesiredState {
+            trunks: Some(Vec::new()),
+            routes: Some(vec![route("outbound", "carrier")]),
+            ..Default::default()
+        };
+        let err = apply(
+            State(state.clone()),
+            Query(ApplyParams::default()),
+            Json(orphaned),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ValidationFailed);
+        assert!(err.detail().contains("trunk 'carrier'"));
+
+        let duplicated = DesiredState {
+            extensions: Some(vec![extension("a", "1001"), extension("b", "1001")]),
+            ..Default::default()
+        };
+        let err = apply(
+            State(state.clone()),
+            Query(ApplyParams::default()),
+            Json(duplicated),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.detail(), "Extension number '1001' is listed twice");
+
+        let mut tenants = HashMap::new();
+        tenants.insert(
+            "acme.example".to_string(),
+            TenantQuota {
+                max_extensions: Some(1),
+                ..Default::default()
+            },
+        );
+        state.quotas = Quotas::new(QuotaConfig { tenants });
+        let mut first = extension("a", "1001");
+        first.tenant = Some("acme.example".to_string());
+        let mut second = extension("b", "1002");
+        second.tenant = Some("ACME.example".to_string());
+        let over = DesiredState {
+            extensions: Some(vec![first, second]),
+            ..Default::default()
+        };
+        let err = apply(
+            State(state.clone()),
+            Query(ApplyParams::default()),
+            Json(over),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::QuotaExceeded);
+        assert!(state.extensions.read().await.is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+//! Per-extension call history handlers
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use rustalk_core::call_history::CallHistory;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::access_log::Principal;
+use crate::error::{ApiError, ApiResult};
+use crate::handlers::extensions::ExtensionsState;
+
+/// Calls returned when no limit is given
+const DEFAULT_LIMIT: usize = 50;
+
+/// Most calls returned in one request
+const MAX_LIMIT: usize = 500;
+
+/// Recorded calls and the extensions they are listed by
+#[derive(Clone)]
+pub struct CallHistoryState {
+    pub history: Option<CallHistory>,
+    pub extensions: ExtensionsState,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CallHistoryQuery {
+    pub limit: Option<usize>,
+}
+
+/// List an extension's recent calls, newest first
+///
+/// The role of the principal the request authenticated as decides how the
+/// other party's number is masked; internal extensions stay readable unless
+/// the rule masks them too.
+pub async fn get_call_history(
+    Path(id): Path<String>,
+    State(state): State<CallHistoryState>,
+    Query(query): Query<CallHistoryQuery>,
+    principal: Option<Extension<Principal>>,
+) -> ApiResult {
+    let history = state
+        .history
+        .as_ref()
+        .ok_or_else(|| ApiError::not_configured("Call history is not configured"))?;
+    let extensions = state.extensions.read().await;
+    let extension = extensions
+        .iter()
+        .find(|e| e.id == id)
+        .ok_or_else(|| ApiError::not_found("Extension not found"))?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let mut calls = history.for_extension(&extension.extension, limit).await;
+
+    let config = history.config();
+    let role = principal.and_then(|Extension(Principal(name))| config.role_of(&name));
+    if let Some(rule) = config.rule_for(role) {
+        let is_internal = |number: &str| extensions.iter().any(|e| e.extension == number);
+        calls = calls
+            .into_iter()
+            .map(|call| call.masked(rule, is_internal))
+            .collect();
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "extension": extension.extension,
+            "calls": calls,
+            "total": calls.len()
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::models::Extension;
+    use rustalk_core::b2bua::CallDetailRecord;
+    use rustalk_core::call_history::CallHistoryConfig;
+    use rustalk_core::cos::CallClass;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_call_history_masking() {
+        let extensions: Vec<Extension> = serde_json::from_value(json!([
+            { "id": "ext-1", "extension": "1001", "display_name": "Alice", "password": "secret", "enabled": true, "voicemail_enabled": true, "priority": 0 },
+            { "id": "ext-2", "extension": "1002", "display_name": "Bob", "password": "secret", "enabled": true, "voicemail_enabled": true, "priority": 0 }
+        ]))
+        .unwrap();
+        let config: CallHistoryConfig = serde_json::from_value(json!({
+            "privacy": { "agent": { "visible_digits": 4 } },
+            "unmasked_roles": ["admin"],
+            "roles": { "alice": "admin", "bob": "agent" }
+        }))
+        .unwrap();
+        let history = CallHistory::new(config);
+        for (call_id, caller, callee) in [("1", "+12125551234", "1001"), ("2", "1001", "1002")] {
+            history
+                .record(&CallDetailRecord::denied(
+                    call_id,
+                    Some(format!("<sip:{}@example.com>", caller)),
+                    Some(format!("<sip:{}@example.com>", callee)),
+                    CallClass::National,
+                    "test".to_string(),
+                ))
+                .await;
+        }
+        let state = CallHistoryState {
+            history: Some(history),
+            extensions: Arc::new(RwLock::new(extensions)),
+        };
+        let get = |id: &str, principal: Option<&str>| {
+            get_call_history(
+                Path(id.to_string()),
+                State(state.clone()),
+                Query(CallHistoryQuery::default()),
+                principal.map(|name| axum::Extension(Principal(name.to_string()))),
+            )
+        };
+
+        let (_, Json(body)) = get("ext-1", Some("alice")).await.unwrap();
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["calls"][0]["direction"], "outbound");
+        assert_eq!(body["calls"][0]["peer"], "1002");
+        assert_eq!(body["calls"][1]["peer"], "+12125551234");
+
+        let (_, Json(body)) = get("ext-1", Some("bob")).await.unwrap();
+        assert_eq!(body["calls"][0]["peer"], "1002");
+        assert_eq!(body["calls"][1]["peer"], "+*******1234");
+
+        // Without a role, numbers stay masked
+        for principal in [None, Some("mallory")] {
+            let (_, Json(body)) = get("ext-1", principal).await.unwrap();
+            assert_eq!(body["calls"][1]["peer"], "+*******1234");
+        }
+
+        let error = get("ext-9", None).await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::NotFound);
+    }
+}
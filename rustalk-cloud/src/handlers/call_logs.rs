@@ -1,17 +1,25 @@
 //! Call log and rating handlers
 
 use axum::{
-    extract::{Path, Query},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
+use rustalk_core::b2bua::{CallAttempt, CallDecision, CallDetailRecord, DecisionStage, LegSide};
+use rustalk_core::sip::StatusCode as SipStatus;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
+use crate::call_log_store::{CallLogFilter, CallLogStore};
+use crate::error::{ApiError, ApiResult};
 use crate::models::{
     CallLog, CallLogDetail, CallLogExportRequest, CallLogList, ChargeItem, RateCard,
     RateImportRequest, RateImportResponse,
 };
+use crate::ratings::RatingEngine;
+
+/// Call logs ingested from SBCs, when the API keeps them
+pub type CallLogsState = Option<CallLogStore>;
 
 /// Query parameters for call log listing
 #[derive(Debug, Deserialize)]
@@ -21,31 +29,103 @@ pub struct CallLogQuery {
     pub start_date: Option<i64>,
     pub end_date: Option<i64>,
     pub status: Option<String>,
+    /// Part of the calling user
+    pub caller: Option<String>,
+    /// Part of the called user
+    pub callee: Option<String>,
+    pub account_code: Option<String>,
 }
 
-/// List call logs with pagination
-pub async fn list_call_logs(Query(params): Query<CallLogQuery>) -> (StatusCode, Json<Value>) {
-    // Placeholder - would query database
-    let page = params.page.unwrap_or(1);
-    let per_page = params.per_page.unwrap_or(50);
+impl CallLogQuery {
+    fn filter(&self) -> CallLogFilter {
+        CallLogFilter {
+            start_date: self.start_date,
+            end_date: self.end_date,
+            status: self.status.clone(),
+            caller: self.caller.clone(),
+            callee: self.callee.clone(),
+            account_code: self.account_code.clone(),
+        }
+    }
+}
 
-    let logs: Vec<CallLog> = vec![];
+/// Query parameters for the account code cost report
+#[derive(Debug, Deserialize)]
+pub struct AccountCodeReportQuery {
+    pub start_date: Option<i64>,
+    pub end_date: Option<i64>,
+}
 
-    (
+/// Largest page a listing returns
+const MAX_PER_PAGE: usize = 500;
+
+fn store_error(e: anyhow::Error) -> ApiError {
+    ApiError::internal(format!("Call log storage failed: {:#}", e))
+}
+
+/// Store the call log of a call detail record sent by an SBC
+pub async fn ingest_call_log(
+    State(store): State<CallLogsState>,
+    Json(cdr): Json<CallDetailRecord>,
+) -> ApiResult {
+    let store = store.ok_or_else(|| ApiError::not_configured("Call logs are not kept"))?;
+    if cdr.call_id.is_empty() {
+        return Err(ApiError::bad_request("Call-ID is required"));
+    }
+    let detail = store.ingest(&cdr).await.map_err(store_error)?;
+    Ok((StatusCode::CREATED, Json(json!(detail))))
+}
+
+/// List call logs matching the query, a page at a time, newest first
+pub async fn list_call_logs(
+    State(store): State<CallLogsState>,
+    Query(params): Query<CallLogQuery>,
+) -> ApiResult {
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params.per_page.unwrap_or(50).clamp(1, MAX_PER_PAGE);
+    // The offset must fit the database's signed 64-bit OFFSET
+    if (page - 1)
+        .checked_mul(per_page)
+        .and_then(|offset| i64::try_from(offset).ok())
+        .is_none()
+    {
+        return Err(ApiError::bad_request("page is out of range").with("field", "page"));
+    }
+
+    let (logs, total) = match &store {
+        Some(store) => {
+            let result = store
+                .query(&params.filter(), page, per_page)
+                .await
+                .map_err(store_error)?;
+            (result.logs, result.total)
+        }
+        None => (vec![], 0),
+    };
+
+    Ok((
         StatusCode::OK,
         Json(json!(CallLogList {
             logs,
-            total: 0,
+            total,
             page,
             per_page,
         })),
-    )
+    ))
 }
 
 /// Get detailed call log with charges
-pub async fn get_call_log(Path(id): Path<String>) -> (StatusCode, Json<Value>) {
-    // Placeholder - would query database
-    // For now, return a mock response
+pub async fn get_call_log(State(store): State<CallLogsState>, Path(id): Path<String>) -> ApiResult {
+    if let Some(store) = store {
+        let detail = store
+            .get(&id)
+            .await
+            .map_err(store_error)?
+            .ok_or_else(|| ApiError::not_found("Call log not found"))?;
+        return Ok((StatusCode::OK, Json(json!(detail))));
+    }
+
+    // Sample call log for APIs that do not keep call logs
     let detail = CallLogDetail {
         log: CallLog {
             id: id.clone(),
@@ -63,6 +143,7 @@ pub async fn get_call_log(Path(id): Path<String>) -> (StatusCode, Json<Value>) {
             b_leg_codec: Some("PCMU".to_string()),
             recording_path: None,
             cost: Some(0.35),
+            account_code: None,
         },
         sip_call_id: format!("{}@example.com", id),
         from_tag: Some("abc123".to_string()),
@@ -84,47 +165,92 @@ pub async fn get_call_log(Path(id): Path<String>) -> (StatusCode, Json<Value>) {
             },
         ]),
         total_cost: Some(0.35),
+        decision_trail: vec![
+            CallDecision::new(
+                DecisionStage::Acl,
+                true,
+                "192.0.2.10 allowed by ACL internal (rule pbx)",
+            ),
+            CallDecision::new(
+                DecisionStage::Route,
+                true,
+                "route uk-mobile (UK Mobile) matched 447700900123 on pattern ^447 -> Trunk uk-carrier",
+            ),
+            CallDecision::new(
+                DecisionStage::Trunk,
+                true,
+                "dialing sip:447700900123@sip.uk-carrier.example via sip.uk-carrier.example",
+            ),
+            CallDecision::response(LegSide::A, SipStatus::TRYING),
+            CallDecision::response(LegSide::B, SipStatus::OK),
+        ],
+        attempts: vec![CallAttempt::new(
+            Some("Trunk uk-carrier"),
+            "sip:447700900123@sip.uk-carrier.example",
+        )],
     };
 
-    (StatusCode::OK, Json(json!(detail)))
+    Ok((StatusCode::OK, Json(json!(detail))))
+}
+
+/// Call costs grouped by account code
+pub async fn account_code_costs(
+    State(store): State<CallLogsState>,
+    Query(params): Query<AccountCodeReportQuery>,
+) -> ApiResult {
+    let logs = match &store {
+        Some(store) => {
+            let filter = CallLogFilter {
+                start_date: params.start_date,
+                end_date: params.end_date,
+                ..Default::default()
+            };
+            store.matching(&filter).await.map_err(store_error)?
+        }
+        None => vec![],
+    };
+    let report = RatingEngine::costs_by_account_code(&logs);
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "account_codes": report,
+            "total": report.len()
+        })),
+    ))
 }
 
 /// Export call logs in various formats
-pub async fn export_call_logs(
-    Json(request): Json<CallLogExportRequest>,
-) -> (StatusCode, Json<Value>) {
+pub async fn export_call_logs(Json(request): Json<CallLogExportRequest>) -> ApiResult {
     // Placeholder - would generate export file
     match request.format.as_str() {
-        "json" => (
+        "json" => Ok((
             StatusCode::OK,
             Json(json!({
                 "format": "json",
                 "data": "[]",
                 "count": 0
             })),
-        ),
-        "csv" => (
+        )),
+        "csv" => Ok((
             StatusCode::OK,
             Json(json!({
                 "format": "csv",
                 "data": "id,from,to,duration,cost\n",
                 "count": 0
             })),
-        ),
-        "pdf" => (
+        )),
+        "pdf" => Ok((
             StatusCode::OK,
             Json(json!({
                 "format": "pdf",
                 "url": "/exports/call-logs.pdf",
                 "count": 0
             })),
-        ),
-        _ => (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "error": "Invalid format. Supported formats: json, csv, pdf"
-            })),
-        ),
+        )),
+        _ => Err(ApiError::bad_request(
+            "Invalid format. Supported formats: json, csv, pdf",
+        )),
     }
 }
 
@@ -145,6 +271,7 @@ pub async fn list_rates() -> (StatusCode, Json<Value>) {
             effective_date: 1700000000,
             end_date: None,
             active: true,
+            trunk: None,
         },
         RateCard {
             id: "2".to_string(),
@@ -159,6 +286,7 @@ pub async fn list_rates() -> (StatusCode, Json<Value>) {
             effective_date: 1700000000,
             end_date: None,
             active: true,
+            trunk: None,
         },
     ];
 
@@ -172,7 +300,7 @@ pub async fn list_rates() -> (StatusCode, Json<Value>) {
 }
 
 /// Import rates from JSON or CSV
-pub async fn import_rates(Json(request): Json<RateImportRequest>) -> (StatusCode, Json<Value>) {
+pub async fn import_rates(Json(_request): Json<RateImportRequest>) -> (StatusCode, Json<Value>) {
     // Placeholder - would parse and save to database
     let response = RateImportResponse {
         success: true,
@@ -207,3 +335,78 @@ pub async fn delete_rate(Path(id): Path<String>) -> (StatusCode, Json<Value>) {
         })),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use rustalk_core::b2bua::{CallDisposition, Session};
+
+    fn cdr(call_id: &str, disposition: CallDisposition) -> CallDetailRecord {
+        let mut cdr =
+            CallDetailRecord::from_session(&Session::new(call_id.to_string()), disposition);
+        cdr.caller = Some("<sip:1001@example.com>;tag=a".to_string());
+        cdr
+    }
+
+    #[tokio::test]
+    async fn test_ingest_and_list() {
+        let error = ingest_call_log(State(None), Json(cdr("call-1", CallDisposition::Busy)))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::NotConfigured);
+
+        let store = Some(CallLogStore::default());
+        for (call_id, disposition) in [
+            ("call-1", CallDisposition::Answered),
+            ("call-2", CallDisposition::Busy),
+        ] {
+            let (status, _) =
+                ingest_call_log(State(store.clone()), Json(cdr(call_id, disposition)))
+                    .await
+                    .unwrap();
+            assert_eq!(status, StatusCode::CREATED);
+        }
+
+        let query = CallLogQuery {
+            page: None,
+            per_page: None,
+            start_date: None,
+            end_date: None,
+            status: Some("busy".to_string()),
+            caller: None,
+            callee: None,
+            account_code: None,
+        };
+        let (_, Json(list)) = list_call_logs(State(store.clone()), Query(query))
+            .await
+            .unwrap();
+        assert_eq!(list["total"], 1);
+        assert_eq!(list["logs"][0]["call_id"], "call-2");
+        assert_eq!(list["logs"][0]["from_user"], "1001");
+
+        // A page whose offset would overflow is refused, not wrapped
+        let query = CallLogQuery {
+            page: Some(usize::MAX),
+            per_page: Some(500),
+            start_date: None,
+            end_date: None,
+            status: None,
+            caller: None,
+            callee: None,
+            account_code: None,
+        };
+        let error = list_call_logs(State(store.clone()), Query(query))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
+
+        let (_, Json(detail)) = get_call_log(State(store.clone()), Path("call-1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(detail["status"], "answered");
+        assert!(get_call_log(State(store), Path("call-9".to_string()))
+            .await
+            .is_err());
+    }
+}
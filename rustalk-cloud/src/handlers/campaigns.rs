@@ -0,0 +1,395 @@
+//! Outbound campaign handlers
+//!
+//! Campaigns are created and started here, contact lists uploaded as JSON
+//! or CSV, and the do-not-call list kept. The originator placing the calls
+//! claims due attempts and reports how each ended, like the fax gateway.
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use rustalk_core::dialer::{
+    parse_contacts_csv, Campaign, CampaignStatus, Contact, DialOutcome, Dialer,
+};
+use rustalk_core::media::amd::AmdResult;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The dialer, when one is configured
+pub type CampaignsState = Option<Dialer>;
+
+/// Contacts to add to a campaign, as a list or CSV text
+#[derive(Debug, Deserialize)]
+pub struct ContactsRequest {
+    #[serde(default)]
+    pub contacts: Vec<Contact>,
+    /// `number,name` rows, with an optional header row
+    #[serde(default)]
+    pub csv: Option<String>,
+}
+
+/// Originator's result for a dial attempt
+#[derive(Debug, Deserialize)]
+pub struct AttemptResultRequest {
+    pub outcome: DialOutcome,
+    /// Answering machine detection result for answered calls
+    #[serde(default)]
+    pub amd: Option<AmdResult>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DoNotCallRequest {
+    pub number: String,
+}
+
+fn not_configured() -> ApiResult {
+    Err(ApiError::not_configured("Dialer is not configured"))
+}
+
+fn not_found() -> ApiResult {
+    Err(ApiError::not_found("Campaign not found"))
+}
+
+/// List all campaigns with their status and stats
+pub async fn list_campaigns(State(state): State<CampaignsState>) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    let campaigns = dialer.campaigns();
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "campaigns": campaigns,
+            "total": campaigns.len()
+        })),
+    ))
+}
+
+/// Get a specific campaign
+pub async fn get_campaign(
+    Path(id): Path<String>,
+    State(state): State<CampaignsState>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    match dialer.campaign(&id) {
+        Some(campaign) => Ok((StatusCode::OK, Json(json!(campaign)))),
+        None => not_found(),
+    }
+}
+
+/// Create a new campaign; it places no calls until started
+pub async fn create_campaign(
+    State(state): State<CampaignsState>,
+    Json(payload): Json<Campaign>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    let id = payload.id.clone();
+    dialer
+        .add_campaign(payload)
+        .map_err(|e| ApiError::conflict(e.to_string()))?;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "success": true,
+            "message": "Campaign created successfully",
+            "id": id
+        })),
+    ))
+}
+
+/// Delete a campaign
+pub async fn delete_campaign(
+    Path(id): Path<String>,
+    State(state): State<CampaignsState>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    if !dialer.remove_campaign(&id) {
+        return not_found();
+    }
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "Campaign deleted successfully"
+        })),
+    ))
+}
+
+/// List a campaign's contacts and how far each has got
+pub async fn list_contacts(
+    Path(id): Path<String>,
+    State(state): State<CampaignsState>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    match dialer.contacts(&id) {
+        Some(contacts) => Ok((
+            StatusCode::OK,
+            Json(json!({
+                "contacts": contacts,
+                "total": contacts.len()
+            })),
+        )),
+        None => not_found(),
+    }
+}
+
+/// Upload contacts to a campaign
+pub async fn add_contacts(
+    Path(id): Path<String>,
+    State(state): State<CampaignsState>,
+    Json(payload): Json<ContactsRequest>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    if dialer.campaign(&id).is_none() {
+        return not_found();
+    }
+    let mut contacts = payload.contacts;
+    if let Some(csv) = &payload.csv {
+        contacts.extend(parse_contacts_csv(csv).map_err(|e| ApiError::bad_request(e.to_string()))?);
+    }
+    let added = dialer
+        .add_contacts(&id, contacts)
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    Ok((StatusCode::OK, Json(json!(added))))
+}
+
+/// Start or resume a campaign
+pub async fn start_campaign(
+    Path(id): Path<String>,
+    State(state): State<CampaignsState>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    change_status(&dialer, &id, Dialer::start)
+}
+
+/// Pause a running campaign
+pub async fn pause_campaign(
+    Path(id): Path<String>,
+    State(state): State<CampaignsState>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    change_status(&dialer, &id, Dialer::pause)
+}
+
+fn change_status(
+    dialer: &Dialer,
+    id: &str,
+    change: impl FnOnce(&Dialer, &str) -> anyhow::Result<CampaignStatus>,
+) -> ApiResult {
+    if dialer.campaign(id).is_none() {
+        return not_found();
+    }
+    match change(dialer, id) {
+        Ok(status) => Ok((StatusCode::OK, Json(json!({ "id": id, "status": status })))),
+        Err(e) => Err(ApiError::conflict(e.to_string())),
+    }
+}
+
+/// Hand the next due call to the originator
+pub async fn claim_attempt(State(state): State<CampaignsState>) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    match dialer.claim(Utc::now()) {
+        Some(attempt) => Ok((StatusCode::OK, Json(json!(attempt)))),
+        None => Ok((StatusCode::NO_CONTENT, Json(Value::Null))),
+    }
+}
+
+/// Record how a claimed call ended
+pub async fn attempt_result(
+    Path(id): Path<String>,
+    State(state): State<CampaignsState>,
+    Json(payload): Json<AttemptResultRequest>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    match dialer.report(&id, payload.outcome, payload.amd, Utc::now()) {
+        Ok(progress) => Ok((StatusCode::OK, Json(json!(progress)))),
+        Err(e) => Err(ApiError::not_found(e.to_string())),
+    }
+}
+
+/// List the do-not-call numbers
+pub async fn list_do_not_call(State(state): State<CampaignsState>) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    let numbers = dialer.do_not_call();
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "numbers": numbers,
+            "total": numbers.len()
+        })),
+    ))
+}
+
+/// Add a number to the do-not-call list
+pub async fn add_do_not_call(
+    State(state): State<CampaignsState>,
+    Json(payload): Json<DoNotCallRequest>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    if !payload.number.chars().any(|c| c.is_ascii_digit()) {
+        return Err(ApiError::bad_request(format!(
+            "'{}' is not a phone number",
+            payload.number
+        )));
+    }
+    if !dialer.add_do_not_call(&payload.number) {
+        return Err(ApiError::conflict(
+            "Number is already on the do-not-call list",
+        ));
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "success": true,
+            "message": "Number added to the do-not-call list"
+        })),
+    ))
+}
+
+/// Remove a number from the do-not-call list
+pub async fn remove_do_not_call(
+    Path(number): Path<String>,
+    State(state): State<CampaignsState>,
+) -> ApiResult {
+    let Some(dialer) = state else {
+        return not_configured();
+    };
+    if !dialer.remove_do_not_call(&number) {
+        return Err(ApiError::not_found("Number is not on the do-not-call list"));
+    }
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "Number removed from the do-not-call list"
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn campaign() -> Campaign {
+        serde_json::from_value(json!({
+            "id": "outage",
+            "name": "Outage notice",
+            "caller_id": "+12125550100",
+            "connect_to": { "type": "queue", "target": "support" }
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_campaign_flow() {
+        let state: CampaignsState = Some(Dialer::default());
+
+        let (status, _) = create_campaign(State(state.clone()), Json(campaign()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let error = create_campaign(State(state.clone()), Json(campaign()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+
+        let (status, _) = add_do_not_call(
+            State(state.clone()),
+            Json(DoNotCallRequest {
+                number: "+12125550124".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, added) = add_contacts(
+            Path("outage".to_string()),
+            State(state.clone()),
+            Json(ContactsRequest {
+                contacts: Vec::new(),
+                csv: Some("number,name\n+12125550123,Ada\n+12125550124,Bob\n".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            added.0,
+            json!({ "added": 2, "duplicates": 0, "do_not_call": 1 })
+        );
+
+        // Nothing is dialed before the campaign is started
+        let (status, _) = claim_attempt(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, started) = start_campaign(Path("outage".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(started.0["status"], "running");
+
+        let (status, attempt) = claim_attempt(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(attempt.0["number"], "+12125550123");
+        assert_eq!(
+            attempt.0["connect_to"],
+            json!({ "type": "queue", "target": "support" })
+        );
+        let id = attempt.0["id"].as_str().unwrap().to_string();
+
+        let (_, progress) = attempt_result(
+            Path(id.clone()),
+            State(state.clone()),
+            Json(AttemptResultRequest {
+                outcome: DialOutcome::Answered,
+                amd: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(progress.0["status"], "answered");
+        let error = attempt_result(
+            Path(id),
+            State(state.clone()),
+            Json(AttemptResultRequest {
+                outcome: DialOutcome::Answered,
+                amd: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+
+        let (_, campaign) = get_campaign(Path("outage".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(campaign.0["status"], "completed");
+        assert_eq!(campaign.0["stats"]["answered"], 1);
+        assert_eq!(campaign.0["stats"]["do_not_call"], 1);
+
+        let error = list_campaigns(State(None)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -6,11 +6,12 @@ use axum::{
     Json,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::json;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{error, info};
 
+use crate::error::{ApiError, ApiResult};
 use rustalk_core::acme::{AcmeClient, ChallengeType};
 
 /// Shared ACME client state
@@ -35,26 +36,15 @@ pub struct CertificateRenewalPayload {
 pub async fn get_certificate_status(
     State(acme_state): State<AcmeState>,
     Path(domain): Path<String>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let acme_lock = acme_state.read().await;
 
     let Some(client) = acme_lock.as_ref() else {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(json!({
-                "error": "ACME client not configured"
-            })),
-        );
+        return Err(ApiError::not_configured("ACME client not configured"));
     };
 
     if !client.storage().certificate_exists(&domain).await {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "Certificate not found for domain",
-                "domain": domain
-            })),
-        );
+        return Err(ApiError::not_found("Certificate not found for domain").with("domain", domain));
     }
 
     match client.storage().get_certificate_info(&domain).await {
@@ -67,7 +57,7 @@ pub async fn get_certificate_status(
                 "expired"
             };
 
-            (
+            Ok((
                 StatusCode::OK,
                 Json(json!({
                     "domain": cert_info.domain,
@@ -80,32 +70,24 @@ pub async fn get_certificate_status(
                     "serial": cert_info.serial,
                     "needs_renewal": cert_info.days_until_expiry < 30,
                 })),
-            )
+            ))
         }
         Err(e) => {
             error!("Failed to get certificate info: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": "Failed to get certificate information",
-                    "message": e.to_string()
-                })),
-            )
+            Err(ApiError::internal(format!(
+                "Failed to get certificate information: {}",
+                e
+            )))
         }
     }
 }
 
 /// List all certificates
-pub async fn list_certificates(State(acme_state): State<AcmeState>) -> (StatusCode, Json<Value>) {
+pub async fn list_certificates(State(acme_state): State<AcmeState>) -> ApiResult {
     let acme_lock = acme_state.read().await;
 
     let Some(client) = acme_lock.as_ref() else {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(json!({
-                "error": "ACME client not configured"
-            })),
-        );
+        return Err(ApiError::not_configured("ACME client not configured"));
     };
 
     match client.storage().list_certificates().await {
@@ -133,23 +115,20 @@ pub async fn list_certificates(State(acme_state): State<AcmeState>) -> (StatusCo
                 }
             }
 
-            (
+            Ok((
                 StatusCode::OK,
                 Json(json!({
                     "certificates": certificates,
                     "total": certificates.len()
                 })),
-            )
+            ))
         }
         Err(e) => {
             error!("Failed to list certificates: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": "Failed to list certificates",
-                    "message": e.to_string()
-                })),
-            )
+            Err(ApiError::internal(format!(
+                "Failed to list certificates: {}",
+                e
+            )))
         }
     }
 }
@@ -158,7 +137,7 @@ pub async fn list_certificates(State(acme_state): State<AcmeState>) -> (StatusCo
 pub async fn request_certificate(
     State(acme_state): State<AcmeState>,
     Json(payload): Json<CertificateRequestPayload>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     info!(
         "Certificate request: domains={:?}, email={}",
         payload.domains, payload.email
@@ -167,12 +146,7 @@ pub async fn request_certificate(
     let acme_lock = acme_state.read().await;
 
     let Some(client) = acme_lock.as_ref() else {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(json!({
-                "error": "ACME client not configured"
-            })),
-        );
+        return Err(ApiError::not_configured("ACME client not configured"));
     };
 
     // Parse challenge type
@@ -180,13 +154,9 @@ pub async fn request_certificate(
         "http-01" => ChallengeType::Http01,
         "dns-01" => ChallengeType::Dns01,
         _ => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(json!({
-                    "error": "Invalid challenge type",
-                    "message": "Challenge type must be 'http-01' or 'dns-01'"
-                })),
-            );
+            return Err(ApiError::bad_request(
+                "Invalid challenge type, must be 'http-01' or 'dns-01'",
+            ));
         }
     };
 
@@ -204,7 +174,7 @@ pub async fn request_certificate(
                 .get_certificate_info(&payload.domains[0])
                 .await
             {
-                Ok(cert_info) => (
+                Ok(cert_info) => Ok((
                     StatusCode::CREATED,
                     Json(json!({
                         "success": true,
@@ -215,28 +185,25 @@ pub async fn request_certificate(
                         "cert_path": cert_info.cert_path.to_string_lossy(),
                         "key_path": cert_info.key_path.to_string_lossy(),
                     })),
-                ),
+                )),
                 Err(e) => {
                     error!("Failed to get certificate info after issuance: {}", e);
-                    (
+                    Ok((
                         StatusCode::CREATED,
                         Json(json!({
                             "success": true,
                             "message": "Certificate issued but failed to retrieve details"
                         })),
-                    )
+                    ))
                 }
             }
         }
         Err(e) => {
             error!("Failed to request certificate: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": "Failed to request certificate",
-                    "message": e.to_string()
-                })),
-            )
+            Err(ApiError::internal(format!(
+                "Failed to request certificate: {}",
+                e
+            )))
         }
     }
 }
@@ -245,28 +212,19 @@ pub async fn request_certificate(
 pub async fn renew_certificate(
     State(acme_state): State<AcmeState>,
     Json(payload): Json<CertificateRenewalPayload>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     info!("Certificate renewal request: domain={}", payload.domain);
 
     let acme_lock = acme_state.read().await;
 
     let Some(client) = acme_lock.as_ref() else {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(json!({
-                "error": "ACME client not configured"
-            })),
-        );
+        return Err(ApiError::not_configured("ACME client not configured"));
     };
 
     // Check if certificate exists
     if !client.storage().certificate_exists(&payload.domain).await {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "Certificate not found for domain",
-                "domain": payload.domain
-            })),
+        return Err(
+            ApiError::not_found("Certificate not found for domain").with("domain", payload.domain)
         );
     }
 
@@ -274,13 +232,13 @@ pub async fn renew_certificate(
     match client.check_renewal_needed(&payload.domain).await {
         Ok(needs_renewal) => {
             if !needs_renewal {
-                return (
+                return Ok((
                     StatusCode::OK,
                     Json(json!({
                         "success": false,
                         "message": "Certificate does not need renewal yet (more than 30 days until expiry)"
                     })),
-                );
+                ));
             }
         }
         Err(e) => {
@@ -295,7 +253,7 @@ pub async fn renew_certificate(
 
             // Get updated certificate info
             match client.storage().get_certificate_info(&payload.domain).await {
-                Ok(cert_info) => (
+                Ok(cert_info) => Ok((
                     StatusCode::OK,
                     Json(json!({
                         "success": true,
@@ -304,28 +262,25 @@ pub async fn renew_certificate(
                         "expires_at": cert_info.expires_at,
                         "days_until_expiry": cert_info.days_until_expiry,
                     })),
-                ),
+                )),
                 Err(e) => {
                     error!("Failed to get certificate info after renewal: {}", e);
-                    (
+                    Ok((
                         StatusCode::OK,
                         Json(json!({
                             "success": true,
                             "message": "Certificate renewed but failed to retrieve details"
                         })),
-                    )
+                    ))
                 }
             }
         }
         Err(e) => {
             error!("Failed to renew certificate: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": "Failed to renew certificate",
-                    "message": e.to_string()
-                })),
-            )
+            Err(ApiError::internal(format!(
+                "Failed to renew certificate: {}",
+                e
+            )))
         }
     }
 }
@@ -0,0 +1,215 @@
+//! Live B2BUA channel handlers
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rustalk_core::b2bua::{LegSide, B2BUA};
+use rustalk_core::io;
+use rustalk_core::media::mixer::PlaybackRefusal;
+use rustalk_core::media::wav;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub type ChannelsState = B2BUA;
+
+/// Announcement to play over a call
+#[derive(Debug, Deserialize)]
+pub struct PlayRequest {
+    /// 8 kHz mono 16-bit WAV file on the server
+    pub file: String,
+    /// Legs that hear it; both by default
+    #[serde(default = "default_legs")]
+    pub legs: Vec<LegSide>,
+}
+
+fn default_legs() -> Vec<LegSide> {
+    vec![LegSide::A, LegSide::B]
+}
+
+/// List the legs of every active call
+pub async fn list_channels(State(b2bua): State<ChannelsState>) -> (StatusCode, Json<Value>) {
+    let channels = b2bua.channels().await;
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "channels": channels,
+            "total": channels.len()
+        })),
+    )
+}
+
+/// Legs of a call and how they relate: the branches it forked to and the
+/// targets it was forwarded between
+pub async fn get_topology(
+    Path(call_id): Path<String>,
+    State(b2bua): State<ChannelsState>,
+) -> ApiResult {
+    match b2bua.topology(&call_id).await {
+        Some(topology) => Ok((StatusCode::OK, Json(json!(topology)))),
+        None => Err(ApiError::not_found("Call not found")),
+    }
+}
+
+/// Mix an announcement into an answered call
+pub async fn play_announcement(
+    Path(call_id): Path<String>,
+    State(b2bua): State<ChannelsState>,
+    Json(payload): Json<PlayRequest>,
+) -> ApiResult {
+    if payload.legs.is_empty() {
+        return Err(ApiError::bad_request(
+            "At least one leg must hear the announcement",
+        ));
+    }
+    let samples = match io::fs::read(&payload.file).await {
+        Ok(data) => wav::decode(&data),
+        Err(e) => Err(e),
+    }
+    .map_err(|e| ApiError::bad_request(format!("Cannot play {}: {:#}", payload.file, e)))?;
+
+    match b2bua
+        .play_announcement(&call_id, &payload.legs, &samples)
+        .await
+    {
+        Ok(playback) => Ok((StatusCode::ACCEPTED, Json(json!(playback)))),
+        Err(refusal @ PlaybackRefusal::NoSuchCall) => Err(ApiError::not_found(refusal.to_string())),
+        Err(refusal @ PlaybackRefusal::NotAnswered) => Err(ApiError::conflict(refusal.to_string())),
+        Err(refusal @ PlaybackRefusal::NoMedia) => {
+            Err(ApiError::not_configured(refusal.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::sip::{Message, Method, Request, Uri};
+
+    #[tokio::test]
+    async fn test_list_channels() {
+        let b2bua = B2BUA::new();
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "call1")
+        .with_header("From", "<sip:1001@example.com>");
+        b2bua
+            .handle_message_from(Message::Request(invite), "192.0.2.10:5060".parse().unwrap())
+            .await
+            .unwrap();
+
+        let (status, response) = list_channels(State(b2bua)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["total"], 1);
+        assert_eq!(response.0["channels"][0]["call_id"], "call1");
+        assert_eq!(response.0["channels"][0]["leg"], "a");
+        assert_eq!(response.0["channels"][0]["state"], "initial");
+        assert_eq!(response.0["channels"][0]["remote_addr"], "192.0.2.10:5060");
+    }
+
+    #[tokio::test]
+    async fn test_get_topology() {
+        let b2bua = B2BUA::new();
+        let error = get_topology(Path("call1".to_string()), State(b2bua.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Call-ID", "call1")
+        .with_header("From", "<sip:2000@example.com>;tag=a")
+        .with_header("To", "<sip:1001@example.com>");
+        b2bua
+            .handle_message_from(Message::Request(invite), "192.0.2.10:5060".parse().unwrap())
+            .await
+            .unwrap();
+
+        let (status, Json(topology)) = get_topology(Path("call1".to_string()), State(b2bua))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(topology["call_id"], "call1");
+        assert_eq!(topology["legs"][0]["kind"], "caller");
+        assert_eq!(topology["legs"][0]["remote_addr"], "192.0.2.10:5060");
+        assert_eq!(topology["legs"][1]["kind"], "target");
+        assert_eq!(topology["legs"][1]["uri"], "sip:1001@example.com");
+        assert_eq!(topology["legs"][2]["kind"], "outbound");
+        assert_eq!(
+            topology["links"],
+            json!([
+                { "from": "caller", "to": "target-0", "relation": "child" },
+                { "from": "target-0", "to": "outbound-0", "relation": "child" }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_play_announcement() {
+        use rustalk_core::media::mixer::AnnouncementMixer;
+
+        let mixer = AnnouncementMixer::new();
+        let b2bua = B2BUA::new().with_announcements(mixer.clone());
+        let file = std::env::temp_dir().join(format!("rustalk-play-{}.wav", std::process::id()));
+        std::fs::write(&file, wav::encode(&[1000; 8000])).unwrap();
+        let play = |call_id: &str, legs: Vec<LegSide>| {
+            play_announcement(
+                Path(call_id.to_string()),
+                State(b2bua.clone()),
+                Json(PlayRequest {
+                    file: file.display().to_string(),
+                    legs,
+                }),
+            )
+        };
+
+        let error = play("call1", default_legs()).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "call1")
+        .with_header("From", "<sip:1001@example.com>;tag=a");
+        b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap();
+        let error = play("call1", default_legs()).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+
+        let ack = Request::new(
+            Method::Ack,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "call1");
+        b2bua.handle_message(Message::Request(ack)).await.unwrap();
+        let (status, playback) = play("call1", vec![LegSide::B]).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(playback.0["duration_ms"], 1000);
+        assert_eq!(playback.0["legs"], json!(["b"]));
+        assert!(mixer.is_playing("call1", LegSide::B));
+        assert!(!mixer.is_playing("call1", LegSide::A));
+
+        let error = play_announcement(
+            Path("call1".to_string()),
+            State(b2bua.clone()),
+            Json(PlayRequest {
+                file: "/nonexistent.wav".to_string(),
+                legs: default_legs(),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        let _ = std::fs::remove_file(&file);
+    }
+}
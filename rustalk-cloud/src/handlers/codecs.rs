@@ -1,5 +1,6 @@
 //! Codec management API handlers
 
+use crate::error::{ApiError, ApiResult};
 use axum::{extract::State, http::StatusCode, Json};
 use rustalk_core::media::{Codec, CodecConfig};
 use serde::{Deserialize, Serialize};
@@ -62,7 +63,7 @@ pub async fn list_codecs(
 pub async fn update_codec(
     State(codec_config): State<Arc<RwLock<CodecConfig>>>,
     Json(request): Json<CodecUpdateRequest>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut config = codec_config.write().await;
 
     let success = if request.enabled {
@@ -76,21 +77,18 @@ pub async fn update_codec(
             "Codec '{}' set to enabled={}",
             request.name, request.enabled
         );
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": format!("Codec '{}' updated successfully", request.name)
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "error": format!("Codec '{}' not found", request.name)
-            })),
-        )
+        Err(ApiError::not_found(format!(
+            "Codec '{}' not found",
+            request.name
+        )))
     }
 }
 
@@ -98,7 +96,7 @@ pub async fn update_codec(
 pub async fn add_codec(
     State(codec_config): State<Arc<RwLock<CodecConfig>>>,
     Json(request): Json<CodecAddRequest>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut config = codec_config.write().await;
 
     let codec = Codec::new(
@@ -113,21 +111,15 @@ pub async fn add_codec(
     match config.add_codec(codec) {
         Ok(_) => {
             info!("Custom codec '{}' added successfully", request.name);
-            (
+            Ok((
                 StatusCode::CREATED,
                 Json(json!({
                     "success": true,
                     "message": format!("Codec '{}' added successfully", request.name)
                 })),
-            )
+            ))
         }
-        Err(err) => (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "error": err
-            })),
-        ),
+        Err(err) => Err(ApiError::bad_request(err)),
     }
 }
 
@@ -135,27 +127,21 @@ pub async fn add_codec(
 pub async fn remove_codec(
     State(codec_config): State<Arc<RwLock<CodecConfig>>>,
     Json(request): Json<CodecRemoveRequest>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut config = codec_config.write().await;
 
     match config.remove_codec(&request.name) {
         Ok(_) => {
             info!("Codec '{}' removed successfully", request.name);
-            (
+            Ok((
                 StatusCode::OK,
                 Json(json!({
                     "success": true,
                     "message": format!("Codec '{}' removed successfully", request.name)
                 })),
-            )
+            ))
         }
-        Err(err) => (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "error": err
-            })),
-        ),
+        Err(err) => Err(ApiError::bad_request(err)),
     }
 }
 
@@ -163,7 +149,7 @@ pub async fn remove_codec(
 pub async fn reorder_codecs(
     State(codec_config): State<Arc<RwLock<CodecConfig>>>,
     Json(request): Json<CodecReorderRequest>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut config = codec_config.write().await;
 
     match config.reorder_codec(request.from_index, request.to_index) {
@@ -172,28 +158,23 @@ pub async fn reorder_codecs(
                 "Codecs reordered: {} -> {}",
                 request.from_index, request.to_index
             );
-            (
+            Ok((
                 StatusCode::OK,
                 Json(json!({
                     "success": true,
                     "message": "Codecs reordered successfully",
                     "codecs": config.codecs.clone()
                 })),
-            )
+            ))
         }
-        Err(err) => (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "error": err
-            })),
-        ),
+        Err(err) => Err(ApiError::bad_request(err)),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ErrorCode;
 
     #[tokio::test]
     async fn test_list_codecs() {
@@ -215,7 +196,9 @@ mod tests {
             enabled: false,
         };
 
-        let (status, response) = update_codec(State(config.clone()), Json(request)).await;
+        let (status, response) = update_codec(State(config.clone()), Json(request))
+            .await
+            .unwrap();
         assert_eq!(status, StatusCode::OK);
         assert_eq!(response.0["success"], true);
 
@@ -237,7 +220,9 @@ mod tests {
             description: "Test codec".to_string(),
         };
 
-        let (status, response) = add_codec(State(config.clone()), Json(request)).await;
+        let (status, response) = add_codec(State(config.clone()), Json(request))
+            .await
+            .unwrap();
         assert_eq!(status, StatusCode::CREATED);
         assert_eq!(response.0["success"], true);
 
@@ -260,14 +245,19 @@ mod tests {
             channels: 1,
             description: "Test codec".to_string(),
         };
-        add_codec(State(config.clone()), Json(add_request)).await;
+        let (status, _) = add_codec(State(config.clone()), Json(add_request))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
 
         // Now remove it
         let remove_request = CodecRemoveRequest {
             name: "TestCodec".to_string(),
         };
 
-        let (status, response) = remove_codec(State(config.clone()), Json(remove_request)).await;
+        let (status, response) = remove_codec(State(config.clone()), Json(remove_request))
+            .await
+            .unwrap();
         assert_eq!(status, StatusCode::OK);
         assert_eq!(response.0["success"], true);
 
@@ -284,9 +274,11 @@ mod tests {
             name: "PCMU".to_string(),
         };
 
-        let (status, response) = remove_codec(State(config), Json(request)).await;
-        assert_eq!(status, StatusCode::BAD_REQUEST);
-        assert_eq!(response.0["success"], false);
+        let error = remove_codec(State(config), Json(request))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
     }
 
     #[tokio::test]
@@ -304,7 +296,9 @@ mod tests {
             to_index: 0,
         };
 
-        let (status, response) = reorder_codecs(State(config.clone()), Json(request)).await;
+        let (status, response) = reorder_codecs(State(config.clone()), Json(request))
+            .await
+            .unwrap();
         assert_eq!(status, StatusCode::OK);
         assert_eq!(response.0["success"], true);
 
@@ -328,8 +322,10 @@ mod tests {
             to_index: 0,
         };
 
-        let (status, response) = reorder_codecs(State(config), Json(request)).await;
-        assert_eq!(status, StatusCode::BAD_REQUEST);
-        assert_eq!(response.0["success"], false);
+        let error = reorder_codecs(State(config), Json(request))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
     }
 }
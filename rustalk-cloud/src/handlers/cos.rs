@@ -0,0 +1,115 @@
+//! Class of service handlers
+
+use axum::{extract::State, http::StatusCode, Json};
+use rustalk_core::cos::{CosConfig, CosPolicy};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::Extension;
+
+/// COS configuration plus the extensions whose assignments override it
+#[derive(Clone)]
+pub struct CosState {
+    pub config: Arc<RwLock<CosConfig>>,
+    pub extensions: Arc<RwLock<Vec<Extension>>>,
+}
+
+impl CosState {
+    /// Configuration with extension assignments applied
+    async fn effective_config(&self) -> CosConfig {
+        let mut config = self.config.read().await.clone();
+        for ext in self.extensions.read().await.iter() {
+            if let Some(profile) = &ext.class_of_service {
+                config
+                    .extensions
+                    .insert(ext.extension.clone(), profile.clone());
+            }
+        }
+        config
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CosCheckRequest {
+    pub extension: String,
+    pub number: String,
+    pub pin: Option<String>,
+}
+
+/// Get profiles, number classification and extension assignments
+pub async fn get_cos(State(state): State<CosState>) -> (StatusCode, Json<Value>) {
+    let config = state.effective_config().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "profiles": config.profiles,
+            "default_profile": config.default_profile,
+            "extensions": config.extensions,
+            "patterns": config.patterns,
+            // PINs themselves are never returned
+            "override_pins": config.override_pins.len(),
+        })),
+    )
+}
+
+/// Check whether an extension may dial a number
+pub async fn check_cos(
+    State(state): State<CosState>,
+    Json(payload): Json<CosCheckRequest>,
+) -> ApiResult {
+    let policy = match CosPolicy::new(state.effective_config().await) {
+        Ok(policy) => policy,
+        Err(e) => return Err(ApiError::internal(format!("{:#}", e))),
+    };
+
+    let decision = policy.check(&payload.extension, &payload.number, payload.pin.as_deref());
+    Ok((StatusCode::OK, Json(json!(decision))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_uses_extension_assignment() {
+        let state = CosState {
+            config: Arc::new(RwLock::new(CosConfig::default())),
+            extensions: Arc::new(RwLock::new(vec![Extension {
+                id: "ext-1001".to_string(),
+                extension: "1001".to_string(),
+                display_name: "Lobby".to_string(),
+                password: "secret".to_string(),
+                enabled: true,
+                voicemail_enabled: false,
+                priority: 1,
+                class_of_service: Some("internal_only".to_string()),
+                require_dial_pin: false,
+                screening: Default::default(),
+                ring_seconds: None,
+                no_answer: None,
+                tenant: None,
+            }])),
+        };
+
+        let (status, response) = check_cos(
+            State(state.clone()),
+            Json(CosCheckRequest {
+                extension: "1001".to_string(),
+                number: "2125551234".to_string(),
+                pin: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["decision"], "deny");
+        assert_eq!(response.0["class"], "national");
+
+        let (_, response) = get_cos(State(state)).await;
+        assert_eq!(response.0["extensions"]["1001"], "internal_only");
+        assert_eq!(response.0["override_pins"], 0);
+    }
+}
@@ -0,0 +1,96 @@
+//! Per-call debug trace handlers
+
+use crate::error::{ApiError, ApiResult};
+use axum::{extract::State, http::StatusCode, Json};
+use rustalk_core::call_trace::{CallTracer, TraceTarget};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::info;
+
+pub type DebugState = Arc<CallTracer>;
+
+/// List active call traces
+pub async fn list_traces(State(tracer): State<DebugState>) -> (StatusCode, Json<Value>) {
+    let traces = tracer.list();
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "traces": traces,
+            "total": traces.len(),
+            "output_dir": tracer.output_dir()
+        })),
+    )
+}
+
+/// Start tracing a Call-ID, extension or source IP
+pub async fn start_trace(
+    State(tracer): State<DebugState>,
+    Json(target): Json<TraceTarget>,
+) -> ApiResult {
+    match tracer.enable(target.clone()) {
+        Ok(trace) => {
+            info!("Call trace enabled for {}", target);
+            Ok((
+                StatusCode::CREATED,
+                Json(json!({
+                    "success": true,
+                    "message": format!("Tracing {}", target),
+                    "trace": trace
+                })),
+            ))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to start trace: {}", e))),
+    }
+}
+
+/// Stop tracing a target
+pub async fn stop_trace(
+    State(tracer): State<DebugState>,
+    Json(target): Json<TraceTarget>,
+) -> ApiResult {
+    if tracer.disable(&target) {
+        info!("Call trace disabled for {}", target);
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": format!("Stopped tracing {}", target)
+            })),
+        ))
+    } else {
+        Err(ApiError::not_found(format!(
+            "No active trace for {}",
+            target
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_start_list_stop_trace() {
+        let dir = std::env::temp_dir().join("rustalk_cloud_debug_test");
+        let state: DebugState = Arc::new(CallTracer::new(dir));
+        let target = TraceTarget::Extension("1001".to_string());
+
+        let (status, _) = start_trace(State(state.clone()), Json(target.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (_, response) = list_traces(State(state.clone())).await;
+        assert_eq!(response.0["total"], 1);
+        assert_eq!(response.0["traces"][0]["target"]["type"], "extension");
+
+        let (status, _) = stop_trace(State(state.clone()), Json(target.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+
+        let error = stop_trace(State(state), Json(target)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+}
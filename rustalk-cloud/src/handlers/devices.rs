@@ -0,0 +1,487 @@
+//! Device inventory handlers
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rustalk_core::devices::{normalize_mac, provisioning, Device, DeviceInventory, DeviceUpdate};
+use rustalk_core::presence::BlfLists;
+use rustalk_core::registrar::Registrar;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::{ApiError, ApiResult};
+use crate::handlers::extensions::ExtensionsState;
+
+/// Length of passwords generated when a device's credentials are rotated
+const PASSWORD_LEN: usize = 20;
+
+/// Devices, the registrar holding their bindings, the extensions they
+/// authenticate as and the BLF lists they watch
+#[derive(Clone)]
+pub struct DevicesState {
+    pub registrar: Registrar,
+    pub extensions: ExtensionsState,
+    pub blf_lists: BlfLists,
+}
+
+impl DevicesState {
+    fn inventory(&self) -> &DeviceInventory {
+        self.registrar.devices()
+    }
+
+    async fn device(&self, id: &str) -> Result<Device, ApiError> {
+        self.inventory()
+            .get(id)
+            .await
+            .ok_or_else(|| ApiError::not_found("Device not found"))
+    }
+}
+
+/// Extensions a device watches
+#[derive(Debug, Deserialize)]
+pub struct BlfListRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub extensions: Vec<String>,
+}
+
+/// Filters for listing devices
+#[derive(Debug, Deserialize)]
+pub struct DeviceQuery {
+    pub extension: Option<String>,
+    pub revoked: Option<bool>,
+}
+
+/// Options for revoking a device
+#[derive(Debug, Default, Deserialize)]
+pub struct RevokeRequest {
+    /// Also give the extension a new SIP password, locking out anything that
+    /// copied the old one
+    #[serde(default)]
+    pub rotate_password: bool,
+}
+
+/// List devices seen registering
+pub async fn list_devices(
+    State(state): State<DevicesState>,
+    Query(query): Query<DeviceQuery>,
+) -> (StatusCode, Json<Value>) {
+    let devices: Vec<_> = state
+        .inventory()
+        .list()
+        .await
+        .into_iter()
+        .filter(|d| {
+            query
+                .extension
+                .as_ref()
+                .is_none_or(|ext| &d.extension == ext)
+        })
+        .filter(|d| {
+            query
+                .revoked
+                .is_none_or(|revoked| d.is_revoked() == revoked)
+        })
+        .collect();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "devices": devices,
+            "total": devices.len()
+        })),
+    )
+}
+
+/// Get a device
+pub async fn get_device(Path(id): Path<String>, State(state): State<DevicesState>) -> ApiResult {
+    let device = state
+        .inventory()
+        .get(&id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Device not found"))?;
+    Ok((StatusCode::OK, Json(json!(device))))
+}
+
+/// Set a device's provisioned MAC address or label
+pub async fn update_device(
+    Path(id): Path<String>,
+    State(state): State<DevicesState>,
+    Json(mut update): Json<DeviceUpdate>,
+) -> ApiResult {
+    if let Some(mac) = update.mac.as_deref().filter(|mac| !mac.trim().is_empty()) {
+        let normalized = normalize_mac(mac).ok_or_else(|| {
+            ApiError::unprocessable(format!("'{}' is not a MAC address", mac)).with("field", "mac")
+        })?;
+        update.mac = Some(normalized);
+    }
+
+    let device = state
+        .inventory()
+        .update(&id, update)
+        .await
+        .ok_or_else(|| ApiError::not_found("Device not found"))?;
+    Ok((StatusCode::OK, Json(json!(device))))
+}
+
+/// Revoke a lost or stolen device
+///
+/// The device's registrations are dropped and further REGISTERs from it are
+/// refused. With `rotate_password` the extension also gets a new password,
+/// returned once in the response so the extension's other devices can be
+/// reprovisioned.
+pub async fn revoke_device(
+    Path(id): Path<String>,
+    State(state): State<DevicesState>,
+    request: Option<Json<RevokeRequest>>,
+) -> ApiResult {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let device = state
+        .inventory()
+        .revoke(&id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Device not found"))?;
+    let unregistered = state.registrar.remove_device(&id).await;
+
+    let mut response = json!({
+        "success": true,
+        "message": "Device revoked",
+        "device": device,
+        "unregistered": unregistered
+    });
+    if request.rotate_password {
+        let mut extensions = state.extensions.write().await;
+        let extension = extensions
+            .iter_mut()
+            .find(|e| e.extension == device.extension)
+            .ok_or_else(|| {
+                ApiError::not_found(format!(
+                    "Device revoked, but extension {} was not found to rotate its password",
+                    device.extension
+                ))
+            })?;
+        extension.password = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(PASSWORD_LEN)
+            .map(char::from)
+            .collect();
+        response["password"] = json!(extension.password);
+    }
+
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Let a revoked device register again
+pub async fn reinstate_device(
+    Path(id): Path<String>,
+    State(state): State<DevicesState>,
+) -> ApiResult {
+    let device = state
+        .inventory()
+        .reinstate(&id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Device not found"))?;
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "Device reinstated",
+            "device": device
+        })),
+    ))
+}
+
+/// Forget a device
+pub async fn delete_device(Path(id): Path<String>, State(state): State<DevicesState>) -> ApiResult {
+    state
+        .inventory()
+        .remove(&id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Device not found"))?;
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "Device removed"
+        })),
+    ))
+}
+
+/// Get the BLF list of a device, as served to it
+pub async fn get_blf_list(Path(id): Path<String>, State(state): State<DevicesState>) -> ApiResult {
+    let device = state.device(&id).await?;
+    let list = state
+        .blf_lists
+        .for_device(&device)
+        .ok_or_else(|| ApiError::not_found("Device has no BLF list"))?;
+    Ok((
+        StatusCode::OK,
+        Json(json!(list.resource_list(state.blf_lists.domain()))),
+    ))
+}
+
+/// Set the extensions a device watches
+pub async fn set_blf_list(
+    Path(id): Path<String>,
+    State(state): State<DevicesState>,
+    Json(request): Json<BlfListRequest>,
+) -> ApiResult {
+    let device = state.device(&id).await?;
+    if request.extensions.is_empty() {
+        return Err(
+            ApiError::unprocessable("A BLF list needs at least one extension")
+                .with("field", "extensions"),
+        );
+    }
+    {
+        let extensions = state.extensions.read().await;
+        let mut seen = Vec::new();
+        for extension in &request.extensions {
+            if seen.contains(&extension) {
+                return Err(ApiError::unprocessable(format!(
+                    "Extension {} is listed twice",
+                    extension
+                ))
+                .with("field", "extensions"));
+            }
+            if !extensions.iter().any(|e| &e.extension == extension) {
+                return Err(ApiError::unprocessable(format!(
+                    "Extension {} does not exist",
+                    extension
+                ))
+                .with("field", "extensions"));
+            }
+            seen.push(extension);
+        }
+    }
+
+    let list = state
+        .blf_lists
+        .set(&device, request.name, request.extensions);
+    Ok((
+        StatusCode::OK,
+        Json(json!(list.resource_list(state.blf_lists.domain()))),
+    ))
+}
+
+/// Remove the BLF list of a device
+pub async fn delete_blf_list(
+    Path(id): Path<String>,
+    State(state): State<DevicesState>,
+) -> ApiResult {
+    let device = state.device(&id).await?;
+    state
+        .blf_lists
+        .remove(&device)
+        .ok_or_else(|| ApiError::not_found("Device has no BLF list"))?;
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "BLF list removed"
+        })),
+    ))
+}
+
+/// Provisioning file for a device, in its vendor's format
+pub async fn get_provisioning(
+    Path(id): Path<String>,
+    State(state): State<DevicesState>,
+) -> Response {
+    let device = match state.device(&id).await {
+        Ok(device) => device,
+        Err(e) => return e.into_response(),
+    };
+    let Some(list) = state.blf_lists.for_device(&device) else {
+        return ApiError::not_found("Device has no BLF list").into_response();
+    };
+    match provisioning::render(&device, &list.resource_list(state.blf_lists.domain())) {
+        Ok(file) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, file.content_type.to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", file.filename),
+                ),
+            ],
+            file.body,
+        )
+            .into_response(),
+        Err(e) => ApiError::unprocessable(e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::models::Extension;
+    use rustalk_core::sip::{Method, Request, Uri};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_revoke_device() {
+        let extensions: Vec<Extension> = serde_json::from_value(json!([
+            { "id": "1001", "extension": "1001", "display_name": "Alice", "password": "old-secret", "enabled": true, "voicemail_enabled": true, "priority": 0 }
+        ]))
+        .unwrap();
+        let state = DevicesState {
+            registrar: Registrar::new(),
+            extensions: Arc::new(RwLock::new(extensions)),
+            blf_lists: BlfLists::default(),
+        };
+        let request = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1001@192.0.2.10:5060>")
+        .with_header("User-Agent", "Yealink SIP-T46S 66.86.0.15");
+        state
+            .registrar
+            .handle_register(&request, Some("192.0.2.10:5060".parse().unwrap()))
+            .await;
+
+        let (_, response) = list_devices(
+            State(state.clone()),
+            Query(DeviceQuery {
+                extension: Some("1001".to_string()),
+                revoked: None,
+            }),
+        )
+        .await;
+        assert_eq!(response.0["total"], 1);
+        assert_eq!(response.0["devices"][0]["vendor"], "Yealink");
+        assert_eq!(response.0["devices"][0]["kind"], "desk_phone");
+        let id = response.0["devices"][0]["id"].as_str().unwrap().to_string();
+
+        let error = update_device(
+            Path(id.clone()),
+            State(state.clone()),
+            Json(DeviceUpdate {
+                mac: Some("not a mac".to_string()),
+                label: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ValidationFailed);
+
+        let (_, response) = revoke_device(
+            Path(id.clone()),
+            State(state.clone()),
+            Some(Json(RevokeRequest {
+                rotate_password: true,
+            })),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0["unregistered"], 1);
+        let password = response.0["password"].as_str().unwrap().to_string();
+        assert_eq!(password.len(), PASSWORD_LEN);
+        assert_eq!(state.extensions.read().await[0].password, password);
+        assert!(state.registrar.bindings().await.is_empty());
+
+        let (_, response) = list_devices(
+            State(state.clone()),
+            Query(DeviceQuery {
+                extension: None,
+                revoked: Some(false),
+            }),
+        )
+        .await;
+        assert_eq!(response.0["total"], 0);
+
+        let (status, _) = reinstate_device(Path(id.clone()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let error = delete_device(Path("missing".to_string()), State(state))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_blf_list_provisioning() {
+        let extensions: Vec<Extension> = serde_json::from_value(json!([
+            { "id": "1001", "extension": "1001", "display_name": "Alice", "password": "secret", "enabled": true, "voicemail_enabled": true, "priority": 0 },
+            { "id": "1002", "extension": "1002", "display_name": "Bob", "password": "secret", "enabled": true, "voicemail_enabled": true, "priority": 0 }
+        ]))
+        .unwrap();
+        let state = DevicesState {
+            registrar: Registrar::new(),
+            extensions: Arc::new(RwLock::new(extensions)),
+            blf_lists: BlfLists::new(Vec::new(), "example.com"),
+        };
+        let request = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1001@192.0.2.10:5060>")
+        .with_header(
+            "User-Agent",
+            "Yealink SIP-T46S 66.86.0.15 00:15:65:12:34:56",
+        );
+        state
+            .registrar
+            .handle_register(&request, Some("192.0.2.10:5060".parse().unwrap()))
+            .await;
+        let id = state.inventory().list().await[0].id.clone();
+
+        let error = get_blf_list(Path(id.clone()), State(state.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+        let error = set_blf_list(
+            Path(id.clone()),
+            State(state.clone()),
+            Json(BlfListRequest {
+                name: None,
+                extensions: vec!["1002".to_string(), "1003".to_string()],
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ValidationFailed);
+
+        let (_, response) = set_blf_list(
+            Path(id.clone()),
+            State(state.clone()),
+            Json(BlfListRequest {
+                name: Some("Reception".to_string()),
+                extensions: vec!["1002".to_string()],
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0["uri"], "sip:blf-001565123456@example.com");
+        assert_eq!(response.0["resources"][0]["uri"], "sip:1002@example.com");
+
+        let response = get_provisioning(Path(id.clone()), State(state.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"001565123456.cfg\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body)
+            .contains("account.1.blf_list_uri = sip:blf-001565123456@example.com"));
+
+        let (status, _) = delete_blf_list(Path(id.clone()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let response = get_provisioning(Path(id), State(state)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
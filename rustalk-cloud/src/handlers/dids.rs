@@ -9,10 +9,15 @@ use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::error::{ApiError, ApiResult};
 use crate::models::Did;
+use crate::trash::Trash;
 
 pub type DidsState = Arc<RwLock<Vec<Did>>>;
 
+/// DIDs and the DIDs deleted from them
+pub type DidTrashState = (DidsState, Trash<Did>);
+
 /// List all DIDs
 pub async fn list_dids(State(state): State<DidsState>) -> (StatusCode, Json<Value>) {
     let dids = state.read().await;
@@ -26,29 +31,18 @@ pub async fn list_dids(State(state): State<DidsState>) -> (StatusCode, Json<Valu
 }
 
 /// Get a specific DID
-pub async fn get_did(
-    Path(id): Path<String>,
-    State(state): State<DidsState>,
-) -> (StatusCode, Json<Value>) {
+pub async fn get_did(Path(id): Path<String>, State(state): State<DidsState>) -> ApiResult {
     let dids = state.read().await;
 
     if let Some(did) = dids.iter().find(|d| d.id == id) {
-        (StatusCode::OK, Json(json!(did)))
+        Ok((StatusCode::OK, Json(json!(did))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "DID not found"
-            })),
-        )
+        Err(ApiError::not_found("DID not found"))
     }
 }
 
 /// Create a new DID
-pub async fn create_did(
-    State(state): State<DidsState>,
-    Json(payload): Json<Did>,
-) -> (StatusCode, Json<Value>) {
+pub async fn create_did(State(state): State<DidsState>, Json(payload): Json<Did>) -> ApiResult {
     let mut dids = state.write().await;
 
     // Check if DID already exists
@@ -56,25 +50,19 @@ pub async fn create_did(
         .iter()
         .any(|d| d.id == payload.id || d.number == payload.number)
     {
-        return (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": "DID already exists"
-            })),
-        );
+        return Err(ApiError::conflict("DID already exists"));
     }
 
     dids.push(payload.clone());
 
-    (
+    Ok((
         StatusCode::CREATED,
         Json(json!({
             "success": true,
             "message": "DID created successfully",
             "id": payload.id
         })),
-    )
+    ))
 }
 
 /// Update an existing DID
@@ -82,74 +70,102 @@ pub async fn update_did(
     Path(id): Path<String>,
     State(state): State<DidsState>,
     Json(payload): Json<Did>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut dids = state.write().await;
 
     if let Some(did) = dids.iter_mut().find(|d| d.id == id) {
         *did = payload;
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "DID updated successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "DID not found"
-            })),
-        )
+        Err(ApiError::not_found("DID not found"))
     }
 }
 
 /// Delete a DID
+///
+/// The DID is disabled and kept in the trash, where it can be restored
+/// until the retention window passes.
 pub async fn delete_did(
     Path(id): Path<String>,
-    State(state): State<DidsState>,
-) -> (StatusCode, Json<Value>) {
+    State((state, trash)): State<DidTrashState>,
+) -> ApiResult {
     let mut dids = state.write().await;
 
     if let Some(pos) = dids.iter().position(|d| d.id == id) {
-        dids.remove(pos);
-        (
+        let mut did = dids.remove(pos);
+        let was_enabled = std::mem::replace(&mut did.enabled, false);
+        let tombstone = trash.bury(did, was_enabled).await;
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
-                "message": "DID deleted successfully"
+                "message": "DID deleted successfully",
+                "purge_at": tombstone.purge_at
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "DID not found"
-            })),
-        )
+        Err(ApiError::not_found("DID not found"))
+    }
+}
+
+/// List deleted DIDs that can still be restored
+pub async fn list_deleted_dids(State(trash): State<Trash<Did>>) -> (StatusCode, Json<Value>) {
+    let deleted = trash.list().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "dids": deleted,
+            "total": deleted.len()
+        })),
+    )
+}
+
+/// Restore a deleted DID
+pub async fn restore_did(
+    Path(id): Path<String>,
+    State((state, trash)): State<DidTrashState>,
+) -> ApiResult {
+    let mut dids = state.write().await;
+
+    let Some(tombstone) = trash.get(|d| d.id == id).await else {
+        return Err(ApiError::not_found("No deleted DID with that ID"));
+    };
+    if dids
+        .iter()
+        .any(|d| d.id == id || d.number == tombstone.resource.number)
+    {
+        return Err(ApiError::conflict("DID already exists"));
     }
+
+    // The collection lock is held, so this is the tombstone checked above
+    trash.take(|d| d.id == id).await;
+    let mut did = tombstone.resource;
+    did.enabled = tombstone.was_enabled;
+    dids.push(did);
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "DID restored successfully"
+        })),
+    ))
 }
 
 /// Reorder DIDs (for priority management)
-pub async fn reorder_dids(
-    State(state): State<DidsState>,
-    Json(payload): Json<Value>,
-) -> (StatusCode, Json<Value>) {
+pub async fn reorder_dids(State(state): State<DidsState>, Json(payload): Json<Value>) -> ApiResult {
     let mut dids = state.write().await;
 
     let from_index = payload["from_index"].as_u64().unwrap_or(0) as usize;
     let to_index = payload["to_index"].as_u64().unwrap_or(0) as usize;
 
     if from_index >= dids.len() || to_index >= dids.len() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "message": "Invalid index"
-            })),
-        );
+        return Err(ApiError::bad_request("Invalid index"));
     }
 
     let item = dids.remove(from_index);
@@ -160,12 +176,12 @@ pub async fn reorder_dids(
         did.priority = index as u32;
     }
 
-    (
+    Ok((
         StatusCode::OK,
         Json(json!({
             "success": true,
             "message": "DIDs reordered successfully",
             "dids": *dids
         })),
-    )
+    ))
 }
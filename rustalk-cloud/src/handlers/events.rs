@@ -0,0 +1,158 @@
+//! Live event stream
+//!
+//! Call state transitions, registrations and alarms, pushed as they happen
+//! so dashboards need not poll the calls endpoint. The same URL serves
+//! server-sent events, or a WebSocket when the request asks to upgrade.
+
+use crate::error::ApiError;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures_util::{stream, Stream, StreamExt};
+use rustalk_core::events::EventBus;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// The B2BUA's event bus, when events are published
+pub type EventsState = Option<EventBus>;
+
+/// Events from a broadcast channel, skipping those a slow client missed
+fn receive<T: Clone + Send + 'static>(rx: broadcast::Receiver<T>) -> impl Stream<Item = T> {
+    stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                // A slow client misses the oldest events but stays connected
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+fn named(name: &str, event: &impl Serialize) -> (String, Value) {
+    (name.to_string(), json!(event))
+}
+
+/// Every event published from now on, with the name it is streamed under
+fn named_events(events: &EventBus) -> impl Stream<Item = (String, Value)> {
+    let calls = receive(events.subscribe()).map(|event| {
+        let kind = json!(event.kind);
+        named(kind.as_str().unwrap_or_default(), &event)
+    });
+    let system = receive(events.subscribe_system()).map(|event| named(event.name(), &event));
+    stream::select(calls, system)
+}
+
+/// Stream events as server-sent events, or over a WebSocket
+///
+/// Call events are named after their kind (`ringing`, `answered`,
+/// `hangup`, `missed`), registrations after theirs (`registered`,
+/// `unregistered`, `expired`) and alarms `alarm`, so screen-pop apps can
+/// listen for `ringing` alone. Over a WebSocket each text message is a
+/// JSON object with the name as `event` and the event as `data`.
+pub async fn stream_events(
+    State(state): State<EventsState>,
+    upgrade: Option<WebSocketUpgrade>,
+) -> Response {
+    let Some(events) = state else {
+        return ApiError::not_configured("Events are not enabled").into_response();
+    };
+
+    if let Some(upgrade) = upgrade {
+        return upgrade.on_upgrade(move |socket| forward(socket, events));
+    }
+    let stream = named_events(&events).map(|(name, data)| {
+        Ok::<_, Infallible>(
+            Event::default()
+                .event(name)
+                .json_data(data)
+                .unwrap_or_default(),
+        )
+    });
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Send events to a WebSocket client until it goes away
+async fn forward(mut socket: WebSocket, events: EventBus) {
+    let mut stream = Box::pin(named_events(&events));
+    loop {
+        tokio::select! {
+            next = stream.next() => {
+                let Some((name, data)) = next else {
+                    break;
+                };
+                let text = json!({ "event": name, "data": data }).to_string();
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            // Pings are answered for us; anything else from the client is
+            // ignored until it closes
+            received = socket.recv() => match received {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use chrono::Utc;
+    use rustalk_core::events::{AlarmEvent, AlarmSeverity, CallEvent, CallEventKind, SystemEvent};
+
+    #[tokio::test]
+    async fn test_stream_requires_event_bus() {
+        let response = stream_events(State(None), None).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = stream_events(State(Some(EventBus::new())), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+    }
+
+    #[tokio::test]
+    async fn test_call_and_system_events_named() {
+        let bus = EventBus::new();
+        let mut stream = Box::pin(named_events(&bus));
+
+        bus.publish(CallEvent {
+            kind: CallEventKind::Ringing,
+            call_id: "abc".to_string(),
+            caller: Some("+12125550123".to_string()),
+            callee: Some("2000".to_string()),
+            timestamp: Utc::now(),
+            did: None,
+            crm: None,
+        });
+        let (name, data) = stream.next().await.unwrap();
+        assert_eq!(name, "ringing");
+        assert_eq!(data["call_id"], "abc");
+
+        bus.publish_system(SystemEvent::Alarm(AlarmEvent::new(
+            AlarmSeverity::Critical,
+            "component",
+            "api",
+            "Component api failed: bind failed",
+        )));
+        let (name, data) = stream.next().await.unwrap();
+        assert_eq!(name, "alarm");
+        assert_eq!(data["type"], "alarm");
+        assert_eq!(data["severity"], "critical");
+        assert_eq!(data["subject"], "api");
+    }
+}
@@ -2,17 +2,87 @@
 
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     Json,
 };
+use rustalk_core::no_answer::{NoAnswerAction, NoAnswerPolicy, NoAnswerProfile};
+use rustalk_core::quotas::{QuotaResource, Quotas};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::error::{ApiError, ApiResult};
+use crate::etag::{check_if_match, etag, etag_header, TaggedResult};
 use crate::models::Extension;
+use crate::trash::Trash;
 
 pub type ExtensionsState = Arc<RwLock<Vec<Extension>>>;
 
+/// Extensions and the extensions deleted from them
+pub type ExtensionTrashState = (ExtensionsState, Trash<Extension>);
+
+/// Extensions, the ring timeouts the B2BUA applies to them and the tenant
+/// quotas they count against
+pub type ExtensionRingState = (ExtensionsState, NoAnswerPolicy, Quotas);
+
+/// Deleted extensions and the tenant quotas restoring one counts against
+pub type ExtensionRestoreState = (ExtensionsState, Trash<Extension>, Quotas);
+
+/// Longest ring time an extension can be given
+const MAX_RING_SECONDS: u32 = 600;
+
+pub(crate) fn validate_no_answer(ext: &Extension) -> Result<(), ApiError> {
+    if let Some(seconds) = ext.ring_seconds {
+        if seconds == 0 || seconds > MAX_RING_SECONDS {
+            return Err(ApiError::unprocessable(format!(
+                "ring_seconds must be between 1 and {}",
+                MAX_RING_SECONDS
+            ))
+            .with("field", "ring_seconds"));
+        }
+    }
+    if let Some(NoAnswerAction::Forward { destination }) = &ext.no_answer {
+        if destination.trim().is_empty() {
+            return Err(ApiError::unprocessable("Forwarding needs a destination")
+                .with("field", "no_answer"));
+        }
+    }
+    Ok(())
+}
+
+/// Check that `ext` fits within its tenant's extension quota alongside the
+/// other extensions
+fn check_quota(quotas: &Quotas, extensions: &[Extension], ext: &Extension) -> Result<(), ApiError> {
+    let Some(tenant) = &ext.tenant else {
+        return Ok(());
+    };
+    let others = extensions
+        .iter()
+        .filter(|e| e.id != ext.id)
+        .filter(|e| {
+            e.tenant
+                .as_ref()
+                .is_some_and(|t| t.eq_ignore_ascii_case(tenant))
+        })
+        .count() as u64;
+    Ok(quotas.check(tenant, QuotaResource::Extensions, others + 1)?)
+}
+
+/// Hand an extension's ring time and no-answer action to the call engine
+pub(crate) fn apply_no_answer(policy: &NoAnswerPolicy, ext: &Extension) {
+    if ext.ring_seconds.is_none() && ext.no_answer.is_none() {
+        policy.remove_extension(&ext.extension);
+        return;
+    }
+    policy.set_extension(
+        &ext.extension,
+        NoAnswerProfile {
+            ring_seconds: ext.ring_seconds,
+            action: ext.no_answer.clone(),
+        },
+    );
+}
+
 /// List all extensions
 pub async fn list_extensions(State(state): State<ExtensionsState>) -> (StatusCode, Json<Value>) {
     let extensions = state.read().await;
@@ -29,26 +99,22 @@ pub async fn list_extensions(State(state): State<ExtensionsState>) -> (StatusCod
 pub async fn get_extension(
     Path(id): Path<String>,
     State(state): State<ExtensionsState>,
-) -> (StatusCode, Json<Value>) {
+) -> TaggedResult {
     let extensions = state.read().await;
 
     if let Some(ext) = extensions.iter().find(|e| e.id == id) {
-        (StatusCode::OK, Json(json!(ext)))
+        Ok((StatusCode::OK, etag_header(ext), Json(json!(ext))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "Extension not found"
-            })),
-        )
+        Err(ApiError::not_found("Extension not found"))
     }
 }
 
 /// Create a new extension
 pub async fn create_extension(
-    State(state): State<ExtensionsState>,
+    State((state, policy, quotas)): State<ExtensionRingState>,
     Json(payload): Json<Extension>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
+    validate_no_answer(&payload)?;
     let mut extensions = state.write().await;
 
     // Check if extension already exists
@@ -56,100 +122,147 @@ pub async fn create_extension(
         .iter()
         .any(|e| e.id == payload.id || e.extension == payload.extension)
     {
-        return (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": "Extension already exists"
-            })),
-        );
+        return Err(ApiError::conflict("Extension already exists"));
     }
+    check_quota(&quotas, &extensions, &payload)?;
 
+    apply_no_answer(&policy, &payload);
     extensions.push(payload.clone());
 
-    (
+    Ok((
         StatusCode::CREATED,
         Json(json!({
             "success": true,
             "message": "Extension created successfully",
             "id": payload.id
         })),
-    )
+    ))
 }
 
 /// Update an existing extension
 pub async fn update_extension(
     Path(id): Path<String>,
-    State(state): State<ExtensionsState>,
+    State((state, policy, quotas)): State<ExtensionRingState>,
+    headers: HeaderMap,
     Json(payload): Json<Extension>,
-) -> (StatusCode, Json<Value>) {
+) -> TaggedResult {
+    validate_no_answer(&payload)?;
     let mut extensions = state.write().await;
 
-    if let Some(ext) = extensions.iter_mut().find(|e| e.id == id) {
+    if let Some(pos) = extensions.iter().position(|e| e.id == id) {
+        check_if_match(&headers, &etag(&extensions[pos]))?;
+        // Moving to another tenant counts against that tenant's quota
+        if extensions[pos].tenant != payload.tenant {
+            let mut moved = payload.clone();
+            moved.id = id.clone();
+            check_quota(&quotas, &extensions, &moved)?;
+        }
+        let ext = &mut extensions[pos];
+        if ext.extension != payload.extension {
+            policy.remove_extension(&ext.extension);
+        }
+        apply_no_answer(&policy, &payload);
         *ext = payload;
-        (
+        Ok((
             StatusCode::OK,
+            etag_header(ext),
             Json(json!({
                 "success": true,
                 "message": "Extension updated successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Extension not found"
-            })),
-        )
+        Err(ApiError::not_found("Extension not found"))
     }
 }
 
 /// Delete an extension
+///
+/// The extension is disabled and kept in the trash, where it can be restored
+/// until the retention window passes.
 pub async fn delete_extension(
     Path(id): Path<String>,
-    State(state): State<ExtensionsState>,
-) -> (StatusCode, Json<Value>) {
+    State((state, trash)): State<ExtensionTrashState>,
+    headers: HeaderMap,
+) -> ApiResult {
     let mut extensions = state.write().await;
 
     if let Some(pos) = extensions.iter().position(|e| e.id == id) {
-        extensions.remove(pos);
-        (
+        check_if_match(&headers, &etag(&extensions[pos]))?;
+        let mut ext = extensions.remove(pos);
+        let was_enabled = std::mem::replace(&mut ext.enabled, false);
+        let tombstone = trash.bury(ext, was_enabled).await;
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
-                "message": "Extension deleted successfully"
+                "message": "Extension deleted successfully",
+                "purge_at": tombstone.purge_at
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Extension not found"
-            })),
-        )
+        Err(ApiError::not_found("Extension not found"))
+    }
+}
+
+/// List deleted extensions that can still be restored
+pub async fn list_deleted_extensions(
+    State(trash): State<Trash<Extension>>,
+) -> (StatusCode, Json<Value>) {
+    let deleted = trash.list().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "extensions": deleted,
+            "total": deleted.len()
+        })),
+    )
+}
+
+/// Restore a deleted extension
+pub async fn restore_extension(
+    Path(id): Path<String>,
+    State((state, trash, quotas)): State<ExtensionRestoreState>,
+) -> ApiResult {
+    let mut extensions = state.write().await;
+
+    let Some(tombstone) = trash.get(|e| e.id == id).await else {
+        return Err(ApiError::not_found("No deleted extension with that ID"));
+    };
+    if extensions
+        .iter()
+        .any(|e| e.id == id || e.extension == tombstone.resource.extension)
+    {
+        return Err(ApiError::conflict("Extension already exists"));
     }
+    check_quota(&quotas, &extensions, &tombstone.resource)?;
+
+    // The collection lock is held, so this is the tombstone checked above
+    trash.take(|e| e.id == id).await;
+    let mut ext = tombstone.resource;
+    ext.enabled = tombstone.was_enabled;
+    extensions.push(ext);
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "Extension restored successfully"
+        })),
+    ))
 }
 
 /// Reorder extensions (for priority management)
 pub async fn reorder_extensions(
     State(state): State<ExtensionsState>,
     Json(payload): Json<Value>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut extensions = state.write().await;
 
     let from_index = payload["from_index"].as_u64().unwrap_or(0) as usize;
     let to_index = payload["to_index"].as_u64().unwrap_or(0) as usize;
 
     if from_index >= extensions.len() || to_index >= extensions.len() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "message": "Invalid index"
-            })),
-        );
+        return Err(ApiError::bad_request("Invalid index"));
     }
 
     let item = extensions.remove(from_index);
@@ -160,12 +273,201 @@ pub async fn reorder_extensions(
         ext.priority = index as u32;
     }
 
-    (
+    Ok((
         StatusCode::OK,
         Json(json!({
             "success": true,
             "message": "Extensions reordered successfully",
             "extensions": *extensions
         })),
-    )
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::models::ScreeningMode;
+    use axum::http::header;
+
+    fn extension(id: &str, number: &str) -> Extension {
+        Extension {
+            id: id.to_string(),
+            extension: number.to_string(),
+            display_name: "Front Desk".to_string(),
+            password: "secret".to_string(),
+            enabled: true,
+            voicemail_enabled: true,
+            priority: 0,
+            class_of_service: None,
+            require_dial_pin: false,
+            screening: ScreeningMode::Off,
+            ring_seconds: None,
+            no_answer: None,
+            tenant: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ring_timeout_reaches_call_engine() {
+        let policy = NoAnswerPolicy::default();
+        let state: ExtensionRingState = (
+            Arc::new(RwLock::new(Vec::new())),
+            policy.clone(),
+            Quotas::default(),
+        );
+        let mut payload = extension("front-desk", "1001");
+        payload.ring_seconds = Some(15);
+        payload.no_answer = Some(NoAnswerAction::Voicemail);
+        let (status, _) = create_extension(State(state.clone()), Json(payload.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let timeout = policy.timeout_for("1001").unwrap();
+        assert_eq!(timeout.ring_seconds, 15);
+        assert_eq!(timeout.action, NoAnswerAction::Voicemail);
+
+        // Renumbered without a ring time: left to the phones again
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, "*".parse().unwrap());
+        let (status, _, _) = update_extension(
+            Path("front-desk".to_string()),
+            State(state.clone()),
+            headers.clone(),
+            Json(extension("front-desk", "1002")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(policy.timeout_for("1001").is_none());
+        assert!(policy.timeout_for("1002").is_none());
+
+        payload.ring_seconds = Some(0);
+        let error = update_extension(
+            Path("front-desk".to_string()),
+            State(state),
+            headers,
+            Json(payload),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ValidationFailed);
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_restore() {
+        let state: ExtensionRestoreState = (
+            Arc::new(RwLock::new(vec![extension("front-desk", "1001")])),
+            Trash::default(),
+            Quotas::default(),
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, "*".parse().unwrap());
+
+        let (_, response) = delete_extension(
+            Path("front-desk".to_string()),
+            State((state.0.clone(), state.1.clone())),
+            headers,
+        )
+        .await
+        .unwrap();
+        assert!(response.0["purge_at"].is_string());
+        assert!(state.0.read().await.is_empty());
+        let (_, deleted) = list_deleted_extensions(State(state.1.clone())).await;
+        assert_eq!(deleted.0["total"], 1);
+        assert_eq!(deleted.0["extensions"][0]["resource"]["enabled"], false);
+
+        // The number was handed out again in the meantime
+        state.0.write().await.push(extension("lobby", "1001"));
+        let error = restore_extension(Path("front-desk".to_string()), State(state.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Conflict);
+
+        state.0.write().await.clear();
+        let (status, _) = restore_extension(Path("front-desk".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let restored = state.0.read().await[0].clone();
+        assert_eq!(restored.id, "front-desk");
+        assert!(restored.enabled);
+        assert!(state.1.list().await.is_empty());
+
+        let error = restore_extension(Path("front-desk".to_string()), State(state.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_extension_quota() {
+        let quotas = Quotas::new(
+            serde_json::from_value(json!({
+                "tenants": { "acme.example": { "max_extensions": 1 } }
+            }))
+            .unwrap(),
+        );
+        let state: ExtensionRingState = (
+            Arc::new(RwLock::new(Vec::new())),
+            NoAnswerPolicy::default(),
+            quotas,
+        );
+        let in_tenant = |id: &str, number: &str, tenant: &str| Extension {
+            tenant: Some(tenant.to_string()),
+            ..extension(id, number)
+        };
+
+        let (status, _) = create_extension(
+            State(state.clone()),
+            Json(in_tenant("front-desk", "1001", "acme.example")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let error = create_extension(
+            State(state.clone()),
+            Json(in_tenant("lobby", "1002", "ACME.example")),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::QuotaExceeded);
+        assert_eq!(error.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error.body()["resource"], "extensions");
+        assert_eq!(error.body()["limit"], 1);
+
+        // Other tenants, and extensions without one, are not held back
+        assert!(create_extension(
+            State(state.clone()),
+            Json(in_tenant("lobby", "1002", "other.example")),
+        )
+        .await
+        .is_ok());
+        assert!(
+            create_extension(State(state.clone()), Json(extension("fax", "1003")))
+                .await
+                .is_ok()
+        );
+
+        // Nor is an update that leaves the tenant alone
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, "*".parse().unwrap());
+        assert!(update_extension(
+            Path("front-desk".to_string()),
+            State(state.clone()),
+            headers.clone(),
+            Json(in_tenant("front-desk", "1001", "acme.example")),
+        )
+        .await
+        .is_ok());
+        let error = update_extension(
+            Path("lobby".to_string()),
+            State(state),
+            headers,
+            Json(in_tenant("lobby", "1002", "acme.example")),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::QuotaExceeded);
+    }
 }
@@ -0,0 +1,199 @@
+//! Fax handlers
+//!
+//! Documents travel as base64-encoded TIFF-F images in JSON bodies.
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustalk_core::fax::FaxService;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The fax service, when one is configured
+pub type FaxState = Option<FaxService>;
+
+/// Fax to send, or a fax received by the gateway
+#[derive(Debug, Deserialize)]
+pub struct FaxRequest {
+    pub from: String,
+    pub to: String,
+    /// Base64 TIFF-F image
+    pub document: String,
+}
+
+/// Gateway's result for an outbound fax
+#[derive(Debug, Deserialize)]
+pub struct FaxResultRequest {
+    /// Why transmission failed; absent when the fax went through
+    pub error: Option<String>,
+}
+
+fn not_configured() -> ApiResult {
+    Err(ApiError::not_configured("Fax service is not configured"))
+}
+
+fn decode(document: &str) -> Result<Vec<u8>, ApiError> {
+    STANDARD
+        .decode(document.trim())
+        .map_err(|e| ApiError::bad_request(format!("Document is not valid base64: {}", e)))
+}
+
+/// List recent faxes
+pub async fn list_faxes(State(state): State<FaxState>) -> ApiResult {
+    let Some(fax) = state else {
+        return not_configured();
+    };
+    let jobs = fax.jobs();
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "faxes": jobs,
+            "total": jobs.len()
+        })),
+    ))
+}
+
+/// Queue a document to be sent as a fax
+pub async fn send_fax(State(state): State<FaxState>, Json(payload): Json<FaxRequest>) -> ApiResult {
+    let Some(fax) = state else {
+        return not_configured();
+    };
+    let document = decode(&payload.document)?;
+    match fax.submit(&payload.from, &payload.to, document) {
+        Ok(job) => Ok((StatusCode::CREATED, Json(json!(job)))),
+        Err(e) => Err(ApiError::bad_request(format!("Failed to queue fax: {}", e))),
+    }
+}
+
+/// Accept a fax received by the T.38 gateway and email it
+pub async fn receive_fax(
+    State(state): State<FaxState>,
+    Json(payload): Json<FaxRequest>,
+) -> ApiResult {
+    let Some(fax) = state else {
+        return not_configured();
+    };
+    if fax.email_for_did(&payload.to).is_none() {
+        return Err(ApiError::not_found(format!(
+            "No fax mailbox for DID {}",
+            payload.to
+        )));
+    }
+    let document = decode(&payload.document)?;
+    match fax.receive(&payload.from, &payload.to, &document).await {
+        Ok(job) => Ok((StatusCode::CREATED, Json(json!(job)))),
+        Err(e) => Err(ApiError::bad_request(format!("Invalid fax: {}", e))),
+    }
+}
+
+/// Hand the oldest queued fax to the T.38 gateway
+pub async fn claim_outbound_fax(State(state): State<FaxState>) -> ApiResult {
+    let Some(fax) = state else {
+        return not_configured();
+    };
+    match fax.claim_outbound() {
+        Some((job, document)) => Ok((
+            StatusCode::OK,
+            Json(json!({
+                "job": job,
+                "document": STANDARD.encode(document)
+            })),
+        )),
+        None => Ok((StatusCode::NO_CONTENT, Json(Value::Null))),
+    }
+}
+
+/// Record the T.38 gateway's result for an outbound fax
+pub async fn fax_result(
+    State(state): State<FaxState>,
+    Path(id): Path<String>,
+    Json(payload): Json<FaxResultRequest>,
+) -> ApiResult {
+    let Some(fax) = state else {
+        return not_configured();
+    };
+    match fax.complete(&id, payload.error) {
+        Ok(job) => Ok((StatusCode::OK, Json(json!(job)))),
+        Err(e) => Err(ApiError::not_found(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::fax::FaxConfig;
+
+    fn tiff() -> Vec<u8> {
+        // One 1728x1 page with a single empty Group 3 strip
+        let mut out = b"II*\0\x08\0\0\0".to_vec();
+        let entries: [(u16, u16, u32); 5] = [
+            (256, 4, 1728),
+            (257, 4, 1),
+            (259, 3, 3),
+            (273, 4, 0),
+            (279, 4, 0),
+        ];
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, field_type, value) in entries {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&field_type.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out
+    }
+
+    #[tokio::test]
+    async fn test_outbound_fax_flow() {
+        let config: FaxConfig = serde_json::from_value(json!({
+            "smtp": { "host": "smtp.example.com", "from": "fax@example.com" },
+            "dids": { "+12125551900": "accounts@example.com" }
+        }))
+        .unwrap();
+        let state = Some(FaxService::new(config).unwrap());
+
+        let request = |document: String| FaxRequest {
+            from: "+12125551900".to_string(),
+            to: "+13105550123".to_string(),
+            document,
+        };
+        let error = send_fax(State(state.clone()), Json(request("%%%".to_string())))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+
+        let (status, job) = send_fax(State(state.clone()), Json(request(STANDARD.encode(tiff()))))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let id = job.0["id"].as_str().unwrap().to_string();
+
+        let (status, claimed) = claim_outbound_fax(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(claimed.0["job"]["status"], "sending");
+        assert_eq!(claimed.0["document"], STANDARD.encode(tiff()));
+        let (status, _) = claim_outbound_fax(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, job) = fax_result(
+            State(state.clone()),
+            Path(id),
+            Json(FaxResultRequest { error: None }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(job.0["status"], "delivered");
+
+        // Faxes to DIDs without a mailbox are refused before decoding
+        let error = receive_fax(State(state), Json(request(String::new())))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,265 @@
+//! Extension group management handlers
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rustalk_core::groups::GroupDirectory;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::ExtensionGroup;
+
+pub type GroupsState = Arc<RwLock<Vec<ExtensionGroup>>>;
+
+/// List all extension groups
+pub async fn list_groups(State(state): State<GroupsState>) -> (StatusCode, Json<Value>) {
+    let groups = state.read().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "groups": *groups,
+            "total": groups.len()
+        })),
+    )
+}
+
+/// Get a specific extension group
+pub async fn get_group(Path(id): Path<String>, State(state): State<GroupsState>) -> ApiResult {
+    let groups = state.read().await;
+
+    if let Some(group) = groups.iter().find(|g| g.id == id) {
+        Ok((StatusCode::OK, Json(json!(group))))
+    } else {
+        Err(ApiError::not_found("Group not found"))
+    }
+}
+
+/// Create a new extension group
+pub async fn create_group(
+    State(state): State<GroupsState>,
+    Json(payload): Json<ExtensionGroup>,
+) -> ApiResult {
+    let mut groups = state.write().await;
+
+    if groups
+        .iter()
+        .any(|g| g.id == payload.id || g.name == payload.name)
+    {
+        return Err(ApiError::conflict("Group already exists"));
+    }
+
+    groups.push(payload.clone());
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "success": true,
+            "message": "Group created successfully",
+            "id": payload.id
+        })),
+    ))
+}
+
+/// Update an existing extension group
+pub async fn update_group(
+    Path(id): Path<String>,
+    State(state): State<GroupsState>,
+    Json(payload): Json<ExtensionGroup>,
+) -> ApiResult {
+    let mut groups = state.write().await;
+
+    if let Some(group) = groups.iter_mut().find(|g| g.id == id) {
+        *group = payload;
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": "Group updated successfully"
+            })),
+        ))
+    } else {
+        not_found()
+    }
+}
+
+/// Delete an extension group
+pub async fn delete_group(Path(id): Path<String>, State(state): State<GroupsState>) -> ApiResult {
+    let mut groups = state.write().await;
+
+    if let Some(pos) = groups.iter().position(|g| g.id == id) {
+        groups.remove(pos);
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": "Group deleted successfully"
+            })),
+        ))
+    } else {
+        not_found()
+    }
+}
+
+/// Add an extension to a group
+pub async fn add_member(
+    Path((id, extension)): Path<(String, String)>,
+    State(state): State<GroupsState>,
+) -> ApiResult {
+    let mut groups = state.write().await;
+
+    let Some(group) = groups.iter_mut().find(|g| g.id == id) else {
+        return not_found();
+    };
+    if !group.members.contains(&extension) {
+        group.members.push(extension.clone());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": format!("Extension {} added to group {}", extension, id)
+        })),
+    ))
+}
+
+/// Remove an extension from a group
+pub async fn remove_member(
+    Path((id, extension)): Path<(String, String)>,
+    State(state): State<GroupsState>,
+) -> ApiResult {
+    let mut groups = state.write().await;
+
+    let Some(group) = groups.iter_mut().find(|g| g.id == id) else {
+        return not_found();
+    };
+    let Some(pos) = group.members.iter().position(|m| *m == extension) else {
+        return Err(ApiError::not_found(format!(
+            "Extension {} is not in group {}",
+            extension, id
+        )));
+    };
+    group.members.remove(pos);
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": format!("Extension {} removed from group {}", extension, id)
+        })),
+    ))
+}
+
+/// Groups an extension belongs to and the extensions it may pick up
+pub async fn extension_groups(
+    Path(extension): Path<String>,
+    State(state): State<GroupsState>,
+) -> (StatusCode, Json<Value>) {
+    let directory = group_directory(&state.read().await);
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "extension": extension,
+            "groups": directory.groups_of(&extension),
+            "pickup_peers": directory.pickup_peers(&extension),
+        })),
+    )
+}
+
+/// Members to page for a group
+pub async fn paging_members(Path(id): Path<String>, State(state): State<GroupsState>) -> ApiResult {
+    let directory = group_directory(&state.read().await);
+
+    if directory.get(&id).is_none() {
+        return not_found();
+    }
+    match directory.paging_members(&id) {
+        Some(members) => Ok((
+            StatusCode::OK,
+            Json(json!({
+                "group": id,
+                "members": members,
+            })),
+        )),
+        None => Err(ApiError::conflict(format!(
+            "Paging is not enabled for group {}",
+            id
+        ))),
+    }
+}
+
+/// Convert API groups to the core membership directory
+pub fn group_directory(groups: &[ExtensionGroup]) -> GroupDirectory {
+    GroupDirectory::new(
+        groups
+            .iter()
+            .filter_map(|g| serde_json::to_value(g).ok())
+            .filter_map(|v| serde_json::from_value(v).ok())
+            .collect(),
+    )
+}
+
+fn not_found() -> ApiResult {
+    Err(ApiError::not_found("Group not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::GroupKind;
+
+    fn sales() -> ExtensionGroup {
+        ExtensionGroup {
+            id: "sales".to_string(),
+            name: "Sales".to_string(),
+            kind: GroupKind::Department,
+            description: None,
+            members: vec!["1001".to_string()],
+            pickup: true,
+            paging: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_membership_and_pickup() {
+        let state: GroupsState = Arc::new(RwLock::new(vec![sales()]));
+
+        let (status, _) = add_member(
+            Path(("sales".to_string(), "1002".to_string())),
+            State(state.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, response) = extension_groups(Path("1001".to_string()), State(state.clone())).await;
+        assert_eq!(response.0["groups"], json!(["sales"]));
+        assert_eq!(response.0["pickup_peers"], json!(["1002"]));
+
+        let error = paging_members(Path("sales".to_string()), State(state.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+
+        let error = remove_member(
+            Path(("sales".to_string(), "1003".to_string())),
+            State(state.clone()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+        let (status, _) = remove_member(
+            Path(("sales".to_string(), "1002".to_string())),
+            State(state.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.read().await[0].members, vec!["1001"]);
+    }
+}
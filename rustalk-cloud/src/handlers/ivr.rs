@@ -0,0 +1,309 @@
+//! IVR flow handlers
+//!
+//! Flows are stored as the node graphs the WebUI's builder draws. Flows are
+//! validated before they are stored, and can be checked without storing
+//! them or run against a sequence of caller inputs to see what a call would
+//! hear.
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rustalk_core::ivr::{self, IvrAction, IvrFlow, IvrSession};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub type IvrState = Arc<RwLock<Vec<IvrFlow>>>;
+
+/// What the caller does in a simulated call
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SimulatedInput {
+    /// A key, `0`-`9`, `*` or `#`
+    Key(char),
+    /// `timeout`: the caller pressed nothing
+    Timeout(String),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SimulateRequest {
+    #[serde(default)]
+    pub inputs: Vec<SimulatedInput>,
+    /// Variables the call arrives with, such as `amd`
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+/// List all IVR flows
+pub async fn list_flows(State(state): State<IvrState>) -> (StatusCode, Json<Value>) {
+    let flows = state.read().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "flows": *flows,
+            "total": flows.len()
+        })),
+    )
+}
+
+/// Get a specific IVR flow
+pub async fn get_flow(Path(id): Path<String>, State(state): State<IvrState>) -> ApiResult {
+    let flows = state.read().await;
+
+    if let Some(flow) = flows.iter().find(|f| f.id == id) {
+        Ok((StatusCode::OK, Json(json!(flow))))
+    } else {
+        not_found()
+    }
+}
+
+/// Create a new IVR flow
+pub async fn create_flow(State(state): State<IvrState>, Json(payload): Json<IvrFlow>) -> ApiResult {
+    check(&payload)?;
+    let mut flows = state.write().await;
+
+    if flows.iter().any(|f| f.id == payload.id) {
+        return Err(ApiError::conflict("IVR flow already exists"));
+    }
+
+    let id = payload.id.clone();
+    flows.push(payload);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "success": true,
+            "message": "IVR flow created successfully",
+            "id": id
+        })),
+    ))
+}
+
+/// Update an existing IVR flow
+pub async fn update_flow(
+    Path(id): Path<String>,
+    State(state): State<IvrState>,
+    Json(payload): Json<IvrFlow>,
+) -> ApiResult {
+    check(&payload)?;
+    let mut flows = state.write().await;
+
+    if let Some(flow) = flows.iter_mut().find(|f| f.id == id) {
+        *flow = payload;
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": "IVR flow updated successfully"
+            })),
+        ))
+    } else {
+        not_found()
+    }
+}
+
+/// Delete an IVR flow
+pub async fn delete_flow(Path(id): Path<String>, State(state): State<IvrState>) -> ApiResult {
+    let mut flows = state.write().await;
+
+    if let Some(pos) = flows.iter().position(|f| f.id == id) {
+        flows.remove(pos);
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": "IVR flow deleted successfully"
+            })),
+        ))
+    } else {
+        not_found()
+    }
+}
+
+/// Check a flow without storing it, so the builder can show issues as the
+/// flow is drawn
+pub async fn validate_flow(Json(payload): Json<IvrFlow>) -> ApiResult {
+    let issues = ivr::validate(&payload);
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "valid": issues.is_empty(),
+            "issues": issues
+        })),
+    ))
+}
+
+/// Run a stored flow against a sequence of caller inputs and return what
+/// the call would do after each
+pub async fn simulate_flow(
+    Path(id): Path<String>,
+    State(state): State<IvrState>,
+    Json(payload): Json<SimulateRequest>,
+) -> ApiResult {
+    let flow = {
+        let flows = state.read().await;
+        match flows.iter().find(|f| f.id == id) {
+            Some(flow) => Arc::new(flow.clone()),
+            None => return not_found(),
+        }
+    };
+
+    let mut session = IvrSession::new(flow);
+    for (name, value) in &payload.variables {
+        session.set_variable(name, value);
+    }
+    let mut steps = vec![step("start", session.start())];
+    for input in payload.inputs {
+        if session.is_finished() {
+            break;
+        }
+        match input {
+            SimulatedInput::Key(key @ ('0'..='9' | '*' | '#')) => {
+                steps.push(step(&key.to_string(), session.key(key)))
+            }
+            SimulatedInput::Timeout(input) if input == "timeout" => {
+                steps.push(step("timeout", session.timeout()))
+            }
+            SimulatedInput::Key(key) => {
+                return Err(ApiError::bad_request(format!(
+                    "'{}' is not a key; use 0-9, * or #",
+                    key
+                )))
+            }
+            SimulatedInput::Timeout(input) => {
+                return Err(ApiError::bad_request(format!(
+                    "'{}' is neither a key nor 'timeout'",
+                    input
+                )))
+            }
+        }
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "flow": id,
+            "steps": steps,
+            "variables": session.variables(),
+            "finished": session.is_finished()
+        })),
+    ))
+}
+
+fn step(input: &str, actions: Vec<IvrAction>) -> Value {
+    json!({ "input": input, "actions": actions })
+}
+
+/// Reject flows that cannot run as drawn
+fn check(flow: &IvrFlow) -> Result<(), ApiError> {
+    let issues = ivr::validate(flow);
+    if issues.is_empty() {
+        return Ok(());
+    }
+    Err(
+        ApiError::unprocessable(format!("IVR flow has {} issue(s)", issues.len()))
+            .with("issues", issues),
+    )
+}
+
+fn not_found() -> ApiResult {
+    Err(ApiError::not_found("IVR flow not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    fn flow(nodes: Value) -> IvrFlow {
+        serde_json::from_value(json!({
+            "id": "main",
+            "name": "Main",
+            "start": "menu",
+            "nodes": nodes,
+        }))
+        .unwrap()
+    }
+
+    fn main_menu() -> IvrFlow {
+        flow(json!([
+            { "id": "menu", "type": "menu", "prompt": "menu", "retries": 0,
+              "options": { "1": "sales" }, "on_failure": "operator" },
+            { "id": "sales", "type": "transfer", "destination": "2000" },
+            { "id": "operator", "type": "voicemail", "mailbox": "1000" }
+        ]))
+    }
+
+    #[tokio::test]
+    async fn test_flow_crud_and_simulate() {
+        let state: IvrState = Arc::new(RwLock::new(Vec::new()));
+
+        let broken = flow(json!([
+            { "id": "menu", "type": "menu", "prompt": "menu", "options": { "1": "gone" } }
+        ]));
+        let error = create_flow(State(state.clone()), Json(broken))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ValidationFailed);
+        assert_eq!(
+            error.body()["issues"][0]["message"],
+            "option 1 leads to missing node 'gone'"
+        );
+
+        let (status, _) = create_flow(State(state.clone()), Json(main_menu()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let error = create_flow(State(state.clone()), Json(main_menu()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+
+        let request: SimulateRequest =
+            serde_json::from_value(json!({ "inputs": ["timeout"] })).unwrap();
+        let (_, response) = simulate_flow(
+            Path("main".to_string()),
+            State(state.clone()),
+            Json(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.0["steps"],
+            json!([
+                { "input": "start", "actions": [
+                    { "action": "play", "value": "menu" },
+                    { "action": "await_input", "value": 5000 }
+                ] },
+                { "input": "timeout", "actions": [
+                    { "action": "voicemail", "value": "1000" }
+                ] }
+            ])
+        );
+        assert_eq!(response.0["finished"], true);
+
+        let request: SimulateRequest = serde_json::from_value(json!({ "inputs": ["x"] })).unwrap();
+        let error = simulate_flow(
+            Path("main".to_string()),
+            State(state.clone()),
+            Json(request),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+
+        let (status, _) = delete_flow(Path("main".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let error = get_flow(Path("main".to_string()), State(state))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,201 @@
+//! SMS messaging handlers
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rustalk_core::sms::{twilio::TwilioWebhook, SmsGateway};
+use serde::Deserialize;
+use serde_json::json;
+
+/// The SMS gateway, when one is configured
+pub type MessagesState = Option<SmsGateway>;
+
+/// Query parameters for the message log
+#[derive(Debug, Deserialize)]
+pub struct MessageQuery {
+    /// Only messages sent from or delivered to this extension
+    pub extension: Option<String>,
+}
+
+/// Outbound message request
+#[derive(Debug, Deserialize)]
+pub struct SendMessageRequest {
+    /// Extension or DID to send from
+    pub from: String,
+    pub to: String,
+    pub body: String,
+}
+
+/// Inbound message relayed by a provider integration
+#[derive(Debug, Deserialize)]
+pub struct InboundMessageRequest {
+    pub from: String,
+    /// DID the message was sent to
+    pub to: String,
+    pub body: String,
+    pub provider_id: Option<String>,
+}
+
+fn not_configured() -> ApiResult {
+    Err(ApiError::not_configured("SMS gateway is not configured"))
+}
+
+/// List recent messages
+pub async fn list_messages(
+    State(state): State<MessagesState>,
+    Query(params): Query<MessageQuery>,
+) -> ApiResult {
+    let Some(gateway) = state else {
+        return not_configured();
+    };
+    let messages: Vec<_> = gateway
+        .messages()
+        .into_iter()
+        .filter(|m| {
+            params
+                .extension
+                .as_ref()
+                .is_none_or(|ext| m.extension.as_ref() == Some(ext))
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "messages": messages,
+            "total": messages.len()
+        })),
+    ))
+}
+
+/// Send an SMS
+pub async fn send_message(
+    State(state): State<MessagesState>,
+    Json(payload): Json<SendMessageRequest>,
+) -> ApiResult {
+    let Some(gateway) = state else {
+        return not_configured();
+    };
+
+    match gateway
+        .send(&payload.from, &payload.to, &payload.body)
+        .await
+    {
+        Ok(message) => Ok((StatusCode::CREATED, Json(json!(message)))),
+        Err(e) => Err(ApiError::bad_request(format!(
+            "Failed to send message: {}",
+            e
+        ))),
+    }
+}
+
+/// Accept an inbound SMS and deliver it to the DID's extension
+pub async fn receive_message(
+    State(state): State<MessagesState>,
+    Json(payload): Json<InboundMessageRequest>,
+) -> ApiResult {
+    let Some(gateway) = state else {
+        return not_configured();
+    };
+
+    match gateway.receive(
+        &payload.from,
+        &payload.to,
+        &payload.body,
+        payload.provider_id,
+    ) {
+        Ok(message) => Ok((StatusCode::CREATED, Json(json!(message)))),
+        Err(e) => Err(ApiError::not_found(e.to_string())),
+    }
+}
+
+/// Twilio messaging webhook
+///
+/// Answers with empty TwiML so Twilio sends no auto-reply.
+pub async fn twilio_webhook(State(state): State<MessagesState>, body: String) -> Response {
+    let Some(gateway) = state else {
+        return not_configured().into_response();
+    };
+    let webhook = match TwilioWebhook::parse(&body) {
+        Ok(webhook) => webhook,
+        Err(e) => return ApiError::bad_request(format!("Invalid webhook: {}", e)).into_response(),
+    };
+
+    if let Err(e) = gateway.receive(
+        &webhook.from,
+        &webhook.to,
+        &webhook.body,
+        webhook.message_sid,
+    ) {
+        tracing::warn!("Dropping inbound SMS: {}", e);
+    }
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/xml")],
+        "<Response></Response>",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::sms::{SmsConfig, SmsProviderConfig, TwilioConfig};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_twilio_webhook_and_message_log() {
+        let gateway = SmsGateway::new(SmsConfig {
+            provider: SmsProviderConfig::Twilio(TwilioConfig::default()),
+            dids: HashMap::from([("+12125551000".to_string(), "1001".to_string())]),
+            sip_bridge: false,
+        })
+        .unwrap();
+
+        let response = twilio_webhook(
+            State(Some(gateway.clone())),
+            "MessageSid=SM1&From=%2B13105550123&To=%2B12125551000&Body=Hello".to_string(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (status, response) = list_messages(
+            State(Some(gateway.clone())),
+            Query(MessageQuery {
+                extension: Some("1001".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["total"], 1);
+        assert_eq!(response.0["messages"][0]["direction"], "inbound");
+        assert_eq!(response.0["messages"][0]["provider_id"], "SM1");
+
+        let (_, response) = list_messages(
+            State(Some(gateway)),
+            Query(MessageQuery {
+                extension: Some("1002".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0["total"], 0);
+
+        let error = send_message(
+            State(None),
+            Json(SendMessageRequest {
+                from: "1001".to_string(),
+                to: "+13105550123".to_string(),
+                body: "Hi".to_string(),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,50 @@
+//! API latency metrics handler
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+
+use crate::access_log::{RouteMetrics, BUCKET_BOUNDS_MS};
+
+/// Request counts and latency histograms per API route
+pub async fn get_metrics(State(metrics): State<RouteMetrics>) -> (StatusCode, Json<Value>) {
+    let routes = metrics.snapshot();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "bucket_bounds_ms": BUCKET_BOUNDS_MS,
+            "routes": routes,
+            "total": routes.iter().map(|r| r.count).sum::<u64>()
+        })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_metrics_report_routes() {
+        let metrics = RouteMetrics::new();
+        metrics.observe(
+            &Method::GET,
+            "/api/v1/extensions",
+            StatusCode::OK,
+            Duration::from_millis(12),
+        );
+        metrics.observe(
+            &Method::POST,
+            "/api/v1/extensions",
+            StatusCode::CREATED,
+            Duration::from_millis(300),
+        );
+
+        let (status, response) = get_metrics(State(metrics)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["total"], 2);
+        assert_eq!(response.0["routes"][0]["method"], "GET");
+        assert_eq!(response.0["routes"][0]["p95_ms"], 25);
+        assert_eq!(response.0["routes"][1]["p50_ms"], 500);
+    }
+}
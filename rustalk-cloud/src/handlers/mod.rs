@@ -1,28 +1,72 @@
 //! API request handlers
 
-use axum::{extract::Path, http::StatusCode, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rustalk_core::cert_expiry::CertificateMonitor;
 use serde_json::{json, Value};
 
 pub mod acls;
+pub mod analytics;
+pub mod apply;
+pub mod call_history;
 pub mod call_logs;
+pub mod campaigns;
 pub mod certificates;
+pub mod channels;
 pub mod codecs;
+pub mod cos;
+pub mod debug;
+pub mod devices;
 pub mod dids;
+pub mod events;
 pub mod extensions;
+pub mod fax;
+pub mod groups;
+pub mod ivr;
+pub mod messages;
+pub mod metrics;
+pub mod prompts;
+pub mod registrations;
+pub mod reload;
 pub mod ring_groups;
 pub mod routes;
+pub mod schedules;
+pub mod schema;
+pub mod search;
 pub mod sip_profiles;
+pub mod status;
+pub mod teams;
 pub mod trunks;
 pub mod voicemail;
+pub mod webhooks;
+
+/// Certificate monitor whose warnings the health check reports
+pub type HealthState = Option<CertificateMonitor>;
 
 /// Health check endpoint
-pub async fn health() -> (StatusCode, Json<Value>) {
+///
+/// Warnings such as an expiring certificate are listed without changing
+/// the status, so load balancers keep sending traffic.
+pub async fn health(State(certificates): State<HealthState>) -> (StatusCode, Json<Value>) {
+    let warnings: Vec<Value> = match &certificates {
+        Some(monitor) => monitor
+            .warnings()
+            .await
+            .iter()
+            .map(|warning| warning.to_event())
+            .collect(),
+        None => Vec::new(),
+    };
     (
         StatusCode::OK,
         Json(json!({
             "status": "healthy",
             "service": "rustalk-cloud",
-            "version": "0.1.0"
+            "version": "0.1.0",
+            "warnings": warnings
         })),
     )
 }
@@ -64,7 +108,7 @@ pub async fn get_config() -> (StatusCode, Json<Value>) {
 }
 
 /// Update configuration
-pub async fn update_config(Json(payload): Json<Value>) -> (StatusCode, Json<Value>) {
+pub async fn update_config(Json(_payload): Json<Value>) -> (StatusCode, Json<Value>) {
     // Placeholder - would update database
     (
         StatusCode::OK,
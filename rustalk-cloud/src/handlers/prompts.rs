@@ -0,0 +1,229 @@
+//! Prompt language handlers
+//!
+//! Recordings travel as base64-encoded WAV files in JSON bodies.
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustalk_core::locale::{self, PromptSet};
+use serde::Deserialize;
+use serde_json::json;
+
+/// Prompt text and recordings, when prompt languages are configured
+pub type PromptsState = Option<PromptSet>;
+
+/// Recording of a prompt
+#[derive(Debug, Deserialize)]
+pub struct PromptRecordingRequest {
+    /// Base64 WAV file
+    pub audio: String,
+}
+
+fn not_configured() -> ApiResult {
+    Err(ApiError::not_configured(
+        "Prompt languages are not configured",
+    ))
+}
+
+fn check(locale: &str, key: &str) -> Result<(), ApiError> {
+    if !locale::is_valid_locale(&locale::normalize(locale)) {
+        return Err(ApiError::bad_request(format!("Invalid locale: {}", locale)));
+    }
+    if !locale::is_prompt_key(key) {
+        return Err(ApiError::not_found(format!("Prompt {} not found", key)));
+    }
+    Ok(())
+}
+
+/// List locales with text or recordings
+pub async fn list_locales(State(state): State<PromptsState>) -> ApiResult {
+    let Some(prompts) = state else {
+        return not_configured();
+    };
+    let locales = prompts.locales();
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "default_locale": locale::normalize(&prompts.config().default_locale),
+            "locales": locales,
+            "total": locales.len()
+        })),
+    ))
+}
+
+/// List the prompts of a locale and where each is spoken from
+pub async fn list_prompts(
+    State(state): State<PromptsState>,
+    Path(locale): Path<String>,
+) -> ApiResult {
+    let Some(prompts) = state else {
+        return not_configured();
+    };
+    if !locale::is_valid_locale(&locale::normalize(&locale)) {
+        return Err(ApiError::bad_request(format!("Invalid locale: {}", locale)));
+    }
+    let list = prompts.prompts(&locale);
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "locale": locale::normalize(&locale),
+            "fallback_chain": prompts.config().fallback_chain(&locale),
+            "prompts": list,
+            "total": list.len()
+        })),
+    ))
+}
+
+/// Get a prompt's recording in a locale
+pub async fn get_prompt(
+    State(state): State<PromptsState>,
+    Path((locale, key)): Path<(String, String)>,
+) -> ApiResult {
+    let Some(prompts) = state else {
+        return not_configured();
+    };
+    check(&locale, &key)?;
+    match prompts.recording(&locale, &key).await {
+        Ok(Some(audio)) => Ok((
+            StatusCode::OK,
+            Json(json!({
+                "locale": locale::normalize(&locale),
+                "key": key,
+                "audio": STANDARD.encode(audio)
+            })),
+        )),
+        Ok(None) => Err(ApiError::not_found(format!(
+            "No {} recording of prompt {}",
+            locale, key
+        ))),
+        Err(e) => Err(ApiError::internal(format!(
+            "Failed to read prompt {}: {}",
+            key, e
+        ))),
+    }
+}
+
+/// Store a prompt's recording in a locale
+pub async fn put_prompt(
+    State(state): State<PromptsState>,
+    Path((locale, key)): Path<(String, String)>,
+    Json(payload): Json<PromptRecordingRequest>,
+) -> ApiResult {
+    let Some(prompts) = state else {
+        return not_configured();
+    };
+    check(&locale, &key)?;
+    let audio = STANDARD
+        .decode(payload.audio.trim())
+        .map_err(|e| ApiError::bad_request(format!("Audio is not valid base64: {}", e)))?;
+    if audio.is_empty() {
+        return Err(ApiError::bad_request("Audio is empty"));
+    }
+    match prompts.store(&locale, &key, &audio).await {
+        Ok(_) => Ok((
+            StatusCode::OK,
+            Json(json!({
+                "locale": locale::normalize(&locale),
+                "key": key,
+                "recorded": true,
+                "size": audio.len()
+            })),
+        )),
+        Err(e) => Err(ApiError::internal(format!(
+            "Failed to store prompt {}: {}",
+            key, e
+        ))),
+    }
+}
+
+/// Remove a prompt's recording in a locale, falling back to text
+pub async fn delete_prompt(
+    State(state): State<PromptsState>,
+    Path((locale, key)): Path<(String, String)>,
+) -> ApiResult {
+    let Some(prompts) = state else {
+        return not_configured();
+    };
+    check(&locale, &key)?;
+    match prompts.remove(&locale, &key).await {
+        Ok(true) => Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": format!("Removed {} recording of prompt {}", locale, key)
+            })),
+        )),
+        Ok(false) => Err(ApiError::not_found(format!(
+            "No {} recording of prompt {}",
+            locale, key
+        ))),
+        Err(e) => Err(ApiError::internal(format!(
+            "Failed to remove prompt {}: {}",
+            key, e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::locale::LocaleConfig;
+
+    #[tokio::test]
+    async fn test_prompt_recordings() {
+        let dir = std::env::temp_dir().join("rustalk_prompts_api_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let state = Some(PromptSet::new(LocaleConfig {
+            prompts_dir: dir.to_string_lossy().into_owned(),
+            ..LocaleConfig::default()
+        }));
+        let path = |locale: &str, key: &str| Path((locale.to_string(), key.to_string()));
+
+        let (status, _) = put_prompt(
+            State(state.clone()),
+            path("fr", "vm-goodbye"),
+            Json(PromptRecordingRequest {
+                audio: STANDARD.encode(b"RIFF"),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, Json(body)) = get_prompt(State(state.clone()), path("FR", "vm-goodbye"))
+            .await
+            .unwrap();
+        assert_eq!(body["audio"], STANDARD.encode(b"RIFF"));
+
+        let (_, Json(body)) = list_prompts(State(state.clone()), Path("fr".to_string()))
+            .await
+            .unwrap();
+        let goodbye = body["prompts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["key"] == "vm-goodbye")
+            .unwrap();
+        assert_eq!(goodbye["recorded"], true);
+
+        assert!(
+            get_prompt(State(state.clone()), path("fr", "no-such-prompt"))
+                .await
+                .is_err()
+        );
+        assert!(get_prompt(State(state.clone()), path("..", "vm-goodbye"))
+            .await
+            .is_err());
+        let (status, _) = delete_prompt(State(state.clone()), path("fr", "vm-goodbye"))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(delete_prompt(State(state), path("fr", "vm-goodbye"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Registrar binding handlers
+
+use axum::{extract::State, http::StatusCode, Json};
+use rustalk_core::registrar::Registrar;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+pub type RegistrationsState = Registrar;
+
+/// List active registrations
+///
+/// Each contact is listed with its reachability as last seen by a call
+/// forked to it, and how many contacts its address-of-record has.
+pub async fn list_registrations(
+    State(registrar): State<RegistrationsState>,
+) -> (StatusCode, Json<Value>) {
+    let bindings = registrar.bindings().await;
+    let mut contacts: HashMap<&str, usize> = HashMap::new();
+    for binding in &bindings {
+        *contacts.entry(binding.aor.as_str()).or_default() += 1;
+    }
+    let registrations: Vec<Value> = bindings
+        .iter()
+        .map(|binding| {
+            let mut value = json!(binding);
+            value["expires_in"] = json!(binding.expires_in());
+            value["contacts"] = json!(contacts[binding.aor.as_str()]);
+            value
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "registrations": registrations,
+            "total": registrations.len()
+        })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::sip::{Method, Request, Uri};
+
+    #[tokio::test]
+    async fn test_list_registrations() {
+        let registrar = Registrar::new();
+        let request = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1001@192.0.2.10:5060>")
+        .with_header("Contact", "<sip:1001@192.0.2.11:5060>")
+        .with_header("Expires", "300");
+        registrar
+            .handle_register(&request, Some("192.0.2.10:5060".parse().unwrap()))
+            .await;
+        registrar
+            .set_reachable("sip:1001@192.0.2.11:5060", false)
+            .await;
+
+        let (status, response) = list_registrations(State(registrar)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["total"], 2);
+        assert_eq!(response.0["registrations"][0]["contacts"], 2);
+        assert_eq!(response.0["registrations"][0]["reachable"], Value::Null);
+        assert_eq!(response.0["registrations"][1]["reachable"], false);
+        assert_eq!(
+            response.0["registrations"][0]["aor"],
+            "sip:1001@example.com"
+        );
+        assert_eq!(response.0["registrations"][0]["behind_nat"], false);
+        assert!(
+            response.0["registrations"][0]["expires_in"]
+                .as_i64()
+                .unwrap()
+                > 290
+        );
+    }
+}
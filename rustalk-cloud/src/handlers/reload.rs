@@ -0,0 +1,372 @@
+//! Runtime configuration reload handler
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use rustalk_core::config::{Config, ConfigReloader, References};
+use rustalk_core::media::CodecConfig;
+use rustalk_core::routing::{run_route_tests, RouteTestCase, RouteTestReport};
+use rustalk_core::schedules::ScheduleDirectory;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::handlers::{
+    acls::AclsState,
+    groups::{group_directory, GroupsState},
+    routes::RoutesState,
+    schedules::SchedulesState,
+    voicemail::VoicemailState,
+};
+use crate::models::{Extension, RingGroup, Route, Trunk};
+
+/// Reloader plus the live state it swaps and validates against
+#[derive(Clone)]
+pub struct ReloadState {
+    pub reloader: Option<ConfigReloader>,
+    pub acls: AclsState,
+    pub codecs: Arc<RwLock<CodecConfig>>,
+    pub routes: RoutesState,
+    pub extensions: Arc<RwLock<Vec<Extension>>>,
+    pub trunks: Arc<RwLock<Vec<Trunk>>>,
+    pub ring_groups: Arc<RwLock<Vec<RingGroup>>>,
+    pub voicemail: VoicemailState,
+    pub groups: GroupsState,
+    pub schedules: SchedulesState,
+    pub route_tests: Arc<RwLock<Vec<RouteTestCase>>>,
+}
+
+impl ReloadState {
+    /// Destinations routes may point at, by id or number/name
+    async fn references(&self) -> References {
+        let mut references = References::default();
+        for ext in self.extensions.read().await.iter() {
+            references.extensions.insert(ext.id.clone());
+            references.extensions.insert(ext.extension.clone());
+        }
+        for trunk in self.trunks.read().await.iter() {
+            references.trunks.insert(trunk.id.clone());
+            references.trunks.insert(trunk.name.clone());
+        }
+        for group in self.ring_groups.read().await.iter() {
+            references.ring_groups.insert(group.id.clone());
+            references.ring_groups.insert(group.name.clone());
+        }
+        for mailbox in self.voicemail.list_mailboxes().await {
+            references.mailboxes.insert(mailbox.id.clone());
+            references.mailboxes.insert(mailbox.extension.clone());
+        }
+        for group in self.groups.read().await.iter() {
+            references.groups.insert(group.id.clone());
+        }
+        for schedule in self.schedules.read().await.iter() {
+            references.schedules.insert(schedule.id.clone());
+        }
+        references
+    }
+
+    /// Run the candidate's route tests against its routes and schedules
+    async fn route_tests(&self, config: &Config) -> RouteTestReport {
+        let Some(routing) = &config.routing else {
+            return RouteTestReport::default();
+        };
+        let groups = group_directory(&self.groups.read().await);
+        let schedules = match &config.schedules {
+            Some(schedules) => schedules.clone(),
+            None => self.schedules.read().await.clone(),
+        };
+        run_route_tests(
+            routing,
+            Arc::new(groups),
+            Arc::new(ScheduleDirectory::new(schedules)),
+        )
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReloadParams {
+    /// Validate and shadow-test only
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Re-read the configuration and swap ACLs, codecs, routes and schedules
+///
+/// The candidate is validated, its route tests are run and it is replayed
+/// against recent traffic first. With `?dry_run=true`, or when validation
+/// or a route test fails, nothing is swapped and the report shows what would
+/// have changed.
+pub async fn reload_config(
+    State(state): State<ReloadState>,
+    Query(params): Query<ReloadParams>,
+) -> ApiResult {
+    let Some(reloader) = &state.reloader else {
+        return Err(ApiError::not_configured(
+            "Server was not started from a configuration file",
+        ));
+    };
+
+    let mut staged = match reloader.stage(&state.references().await).await {
+        Ok(staged) => staged,
+        Err(e) => {
+            error!("Configuration reload failed: {:#}", e);
+            return Err(ApiError::unprocessable(format!(
+                "Reload failed, running configuration kept: {:#}",
+                e
+            )));
+        }
+    };
+
+    if !staged.is_valid() {
+        warn!(
+            "Configuration reload rejected: {} validation issues",
+            staged.report.issues.len()
+        );
+        return Err(ApiError::unprocessable(format!(
+            "Validation failed with {} issues, running configuration kept",
+            staged.report.issues.len()
+        ))
+        .with("report", staged.report));
+    }
+
+    let route_tests = state.route_tests(&staged.config).await;
+    if !route_tests.is_success() {
+        warn!(
+            "Configuration reload rejected: {} route tests failed",
+            route_tests.failed
+        );
+        return Err(ApiError::new(
+            ErrorCode::RouteTestsFailed,
+            format!(
+                "{} route tests failed, running configuration kept",
+                route_tests.failed
+            ),
+        )
+        .with("report", staged.report)
+        .with("route_tests", route_tests));
+    }
+
+    if params.dry_run {
+        return Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": format!("{} validated, not applied", reloader.path().display()),
+                "report": staged.report,
+                "route_tests": route_tests
+            })),
+        ));
+    }
+
+    let routes: Vec<Route> = match staged
+        .config
+        .routing
+        .as_ref()
+        .map(|routing| serde_json::from_value(json!(routing.routes)))
+        .transpose()
+    {
+        Ok(routes) => routes.unwrap_or_default(),
+        Err(e) => {
+            return Err(ApiError::unprocessable(format!("Invalid routes: {}", e)));
+        }
+    };
+
+    // Take every lock before writing so requests never see a mix of old
+    // and new configuration
+    {
+        let mut acls = state.acls.write().await;
+        let mut codecs = state.codecs.write().await;
+        let mut live_routes = state.routes.write().await;
+        let mut schedules = state.schedules.write().await;
+        let mut tests = state.route_tests.write().await;
+        if let Err(e) = reloader.apply(&mut staged).await {
+            return Err(ApiError::unprocessable(format!(
+                "Reload failed, running configuration kept: {:#}",
+                e
+            )));
+        }
+        *acls = staged.config.acls.clone().unwrap_or_default();
+        *codecs = staged.config.codecs.clone().unwrap_or_default();
+        *live_routes = routes;
+        // Routes and the schedules they refer to change together
+        if let Some(staged_schedules) = &staged.config.schedules {
+            *schedules = staged_schedules.clone();
+        }
+        *tests = staged
+            .config
+            .routing
+            .as_ref()
+            .map(|routing| routing.tests.clone())
+            .unwrap_or_default();
+    }
+
+    info!("Configuration reloaded from {}", reloader.path().display());
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": format!("Reloaded {}", reloader.path().display()),
+            "report": staged.report
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::routing::{RouteAction, RouteDestination, RouteRule};
+    use rustalk_core::voicemail::VoicemailManager;
+    use rustalk_core::Config;
+
+    fn state(path: &std::path::Path, config: &Config) -> ReloadState {
+        ReloadState {
+            reloader: Some(ConfigReloader::new(path, config.clone())),
+            acls: Arc::new(RwLock::new(config.acls.clone().unwrap())),
+            codecs: Arc::new(RwLock::new(config.codecs.clone().unwrap())),
+            routes: Arc::new(RwLock::new(Vec::new())),
+            extensions: Arc::new(RwLock::new(Vec::new())),
+            trunks: Arc::new(RwLock::new(vec![Trunk {
+                id: "carrier".to_string(),
+                name: "Carrier".to_string(),
+                description: None,
+                host: "sip.carrier.example".to_string(),
+                port: 5060,
+                username: None,
+                password: None,
+                enabled: true,
+                priority: 1,
+                quirks: None,
+                tenant: None,
+            }])),
+            ring_groups: Arc::new(RwLock::new(Vec::new())),
+            voicemail: VoicemailManager::new(std::env::temp_dir().join("rustalk_cloud_reload_vm")),
+            groups: Arc::new(RwLock::new(Vec::new())),
+            schedules: Arc::new(RwLock::new(Vec::new())),
+            route_tests: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    fn trunk_route(trunk: &str) -> RouteRule {
+        RouteRule {
+            id: "outbound".to_string(),
+            name: "Outbound".to_string(),
+            description: None,
+            pattern: "^9".to_string(),
+            destination: RouteDestination::Trunk(trunk.to_string()),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let path = std::env::temp_dir().join("rustalk_cloud_reload_test.json");
+        let mut config = Config::default();
+        config.save_to_file(&path).await.unwrap();
+        let state = state(&path, &config);
+
+        config.acls.as_mut().unwrap().acls.clear();
+        config.save_to_file(&path).await.unwrap();
+
+        // Dry run reports the change without applying it
+        let (status, response) =
+            reload_config(State(state.clone()), Query(ReloadParams { dry_run: true }))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["report"]["applied"], false);
+        assert!(!state.acls.read().await.acls.is_empty());
+
+        let (status, response) =
+            reload_config(State(state.clone()), Query(ReloadParams::default()))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.acls.read().await.acls.is_empty());
+        assert_eq!(response.0["report"]["applied"], true);
+        assert_eq!(response.0["report"]["changes"][0]["section"], "acls");
+        assert_eq!(response.0["report"]["changes"][0]["kind"], "removed");
+
+        let error = reload_config(
+            State(ReloadState {
+                reloader: None,
+                ..state
+            }),
+            Query(ReloadParams::default()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_unknown_trunk() {
+        let path = std::env::temp_dir().join("rustalk_cloud_reload_refs_test.json");
+        let mut config = Config::default();
+        config.save_to_file(&path).await.unwrap();
+        let state = state(&path, &config);
+
+        config
+            .routing
+            .as_mut()
+            .unwrap()
+            .add_route(trunk_route("missing"));
+        config.save_to_file(&path).await.unwrap();
+
+        let error = reload_config(State(state.clone()), Query(ReloadParams::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), ErrorCode::ValidationFailed);
+        assert!(error.body()["report"]["issues"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("trunk 'missing'"));
+        assert!(state.routes.read().await.is_empty());
+
+        config.routing = Some(Default::default());
+        config
+            .routing
+            .as_mut()
+            .unwrap()
+            .add_route(trunk_route("carrier"));
+        config.save_to_file(&path).await.unwrap();
+
+        let (status, _) = reload_config(State(state.clone()), Query(ReloadParams::default()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.routes.read().await.len(), 1);
+
+        // A route test the new routes break blocks the reload
+        let routing = config.routing.as_mut().unwrap();
+        routing.tests = serde_json::from_value(json!([
+            { "name": "outbound", "caller_id": "1001", "destination": "912125551234", "expected_route": "outbound" }
+        ]))
+        .unwrap();
+        routing.routes[0].pattern = "^8".to_string();
+        config.save_to_file(&path).await.unwrap();
+
+        let error = reload_config(State(state.clone()), Query(ReloadParams::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), ErrorCode::RouteTestsFailed);
+        assert_eq!(error.body()["route_tests"]["failed"], json!(1));
+        assert_eq!(state.routes.read().await[0].pattern, "^9");
+        assert!(state.route_tests.read().await.is_empty());
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
@@ -9,6 +9,7 @@ use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::error::{ApiError, ApiResult};
 use crate::models::RingGroup;
 
 pub type RingGroupsState = Arc<RwLock<Vec<RingGroup>>>;
@@ -29,18 +30,13 @@ pub async fn list_ring_groups(State(state): State<RingGroupsState>) -> (StatusCo
 pub async fn get_ring_group(
     Path(id): Path<String>,
     State(state): State<RingGroupsState>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let ring_groups = state.read().await;
 
     if let Some(group) = ring_groups.iter().find(|g| g.id == id) {
-        (StatusCode::OK, Json(json!(group)))
+        Ok((StatusCode::OK, Json(json!(group))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "Ring group not found"
-            })),
-        )
+        Err(ApiError::not_found("Ring group not found"))
     }
 }
 
@@ -48,7 +44,7 @@ pub async fn get_ring_group(
 pub async fn create_ring_group(
     State(state): State<RingGroupsState>,
     Json(payload): Json<RingGroup>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut ring_groups = state.write().await;
 
     // Check if ring group already exists
@@ -56,25 +52,19 @@ pub async fn create_ring_group(
         .iter()
         .any(|g| g.id == payload.id || g.name == payload.name)
     {
-        return (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": "Ring group already exists"
-            })),
-        );
+        return Err(ApiError::conflict("Ring group already exists"));
     }
 
     ring_groups.push(payload.clone());
 
-    (
+    Ok((
         StatusCode::CREATED,
         Json(json!({
             "success": true,
             "message": "Ring group created successfully",
             "id": payload.id
         })),
-    )
+    ))
 }
 
 /// Update an existing ring group
@@ -82,26 +72,20 @@ pub async fn update_ring_group(
     Path(id): Path<String>,
     State(state): State<RingGroupsState>,
     Json(payload): Json<RingGroup>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut ring_groups = state.write().await;
 
     if let Some(group) = ring_groups.iter_mut().find(|g| g.id == id) {
         *group = payload;
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "Ring group updated successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Ring group not found"
-            })),
-        )
+        Err(ApiError::not_found("Ring group not found"))
     }
 }
 
@@ -109,26 +93,20 @@ pub async fn update_ring_group(
 pub async fn delete_ring_group(
     Path(id): Path<String>,
     State(state): State<RingGroupsState>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut ring_groups = state.write().await;
 
     if let Some(pos) = ring_groups.iter().position(|g| g.id == id) {
         ring_groups.remove(pos);
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "Ring group deleted successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Ring group not found"
-            })),
-        )
+        Err(ApiError::not_found("Ring group not found"))
     }
 }
 
@@ -136,20 +114,14 @@ pub async fn delete_ring_group(
 pub async fn reorder_ring_groups(
     State(state): State<RingGroupsState>,
     Json(payload): Json<Value>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut ring_groups = state.write().await;
 
     let from_index = payload["from_index"].as_u64().unwrap_or(0) as usize;
     let to_index = payload["to_index"].as_u64().unwrap_or(0) as usize;
 
     if from_index >= ring_groups.len() || to_index >= ring_groups.len() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "message": "Invalid index"
-            })),
-        );
+        return Err(ApiError::bad_request("Invalid index"));
     }
 
     let item = ring_groups.remove(from_index);
@@ -160,12 +132,12 @@ pub async fn reorder_ring_groups(
         group.priority = index as u32;
     }
 
-    (
+    Ok((
         StatusCode::OK,
         Json(json!({
             "success": true,
             "message": "Ring groups reordered successfully",
             "ring_groups": *ring_groups
         })),
-    )
+    ))
 }
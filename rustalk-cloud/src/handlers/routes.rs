@@ -1,18 +1,65 @@
 //! Route/Dialplan management handlers
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use chrono::Utc;
+use rustalk_core::lcr::{LeastCostRouter, TrunkQuote};
+use rustalk_core::routing::{
+    run_route_tests, CallContext, ConditionMatcher, RouteAction, RouteDestination, RouteEvaluator,
+    RouteGraph, RouteRule, RouteTestCase, RoutingConfig,
+};
+use rustalk_core::trunk_health::TrunkHealth;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::etag::{check_if_match, etag, etag_header, TaggedResult};
+use crate::handlers::groups::{group_directory, GroupsState};
+use crate::handlers::schedules::{schedule_directory, SchedulesState};
 use crate::models::Route;
 
 pub type RoutesState = Arc<RwLock<Vec<Route>>>;
 
+/// Stored route test cases and what they run against
+#[derive(Clone)]
+pub struct RouteTestsState {
+    pub routes: RoutesState,
+    pub groups: GroupsState,
+    pub schedules: SchedulesState,
+    pub cases: Arc<RwLock<Vec<RouteTestCase>>>,
+}
+
+/// Routes, rate cards and trunk states calls are simulated against
+#[derive(Clone)]
+pub struct SimulationState {
+    pub routes: RoutesState,
+    pub groups: GroupsState,
+    pub schedules: SchedulesState,
+    pub least_cost: LeastCostRouter,
+    pub trunk_health: Option<TrunkHealth>,
+}
+
+/// A test call to simulate
+#[derive(Debug, Deserialize)]
+pub struct RouteSimulation {
+    /// Number dialed
+    pub number: String,
+    #[serde(default)]
+    pub caller_id: Option<String>,
+    /// Canonical domain called, for domain conditions
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Length of the call to cost, the LCR reference length by default
+    #[serde(default)]
+    pub duration_seconds: Option<u32>,
+}
+
 /// List all routes
 pub async fn list_routes(State(state): State<RoutesState>) -> (StatusCode, Json<Value>) {
     let routes = state.read().await;
@@ -26,21 +73,13 @@ pub async fn list_routes(State(state): State<RoutesState>) -> (StatusCode, Json<
 }
 
 /// Get a specific route
-pub async fn get_route(
-    Path(id): Path<String>,
-    State(state): State<RoutesState>,
-) -> (StatusCode, Json<Value>) {
+pub async fn get_route(Path(id): Path<String>, State(state): State<RoutesState>) -> TaggedResult {
     let routes = state.read().await;
 
     if let Some(route) = routes.iter().find(|r| r.id == id) {
-        (StatusCode::OK, Json(json!(route)))
+        Ok((StatusCode::OK, etag_header(route), Json(json!(route))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "Route not found"
-            })),
-        )
+        Err(ApiError::not_found("Route not found"))
     }
 }
 
@@ -48,7 +87,7 @@ pub async fn get_route(
 pub async fn create_route(
     State(state): State<RoutesState>,
     Json(payload): Json<Route>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut routes = state.write().await;
 
     // Check if route already exists
@@ -56,52 +95,43 @@ pub async fn create_route(
         .iter()
         .any(|r| r.id == payload.id || r.name == payload.name)
     {
-        return (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": "Route already exists"
-            })),
-        );
+        return Err(ApiError::conflict("Route already exists"));
     }
 
     routes.push(payload.clone());
 
-    (
+    Ok((
         StatusCode::CREATED,
         Json(json!({
             "success": true,
             "message": "Route created successfully",
             "id": payload.id
         })),
-    )
+    ))
 }
 
 /// Update an existing route
 pub async fn update_route(
     Path(id): Path<String>,
     State(state): State<RoutesState>,
+    headers: HeaderMap,
     Json(payload): Json<Route>,
-) -> (StatusCode, Json<Value>) {
+) -> TaggedResult {
     let mut routes = state.write().await;
 
     if let Some(route) = routes.iter_mut().find(|r| r.id == id) {
+        check_if_match(&headers, &etag(route))?;
         *route = payload;
-        (
+        Ok((
             StatusCode::OK,
+            etag_header(route),
             Json(json!({
                 "success": true,
                 "message": "Route updated successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Route not found"
-            })),
-        )
+        Err(ApiError::not_found("Route not found"))
     }
 }
 
@@ -109,26 +139,22 @@ pub async fn update_route(
 pub async fn delete_route(
     Path(id): Path<String>,
     State(state): State<RoutesState>,
-) -> (StatusCode, Json<Value>) {
+    headers: HeaderMap,
+) -> ApiResult {
     let mut routes = state.write().await;
 
     if let Some(pos) = routes.iter().position(|r| r.id == id) {
+        check_if_match(&headers, &etag(&routes[pos]))?;
         routes.remove(pos);
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "Route deleted successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Route not found"
-            })),
-        )
+        Err(ApiError::not_found("Route not found"))
     }
 }
 
@@ -136,20 +162,14 @@ pub async fn delete_route(
 pub async fn reorder_routes(
     State(state): State<RoutesState>,
     Json(payload): Json<Value>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut routes = state.write().await;
 
     let from_index = payload["from_index"].as_u64().unwrap_or(0) as usize;
     let to_index = payload["to_index"].as_u64().unwrap_or(0) as usize;
 
     if from_index >= routes.len() || to_index >= routes.len() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "message": "Invalid index"
-            })),
-        );
+        return Err(ApiError::bad_request("Invalid index"));
     }
 
     let item = routes.remove(from_index);
@@ -160,44 +180,52 @@ pub async fn reorder_routes(
         route.priority = index as u32;
     }
 
-    (
+    Ok((
         StatusCode::OK,
         Json(json!({
             "success": true,
             "message": "Routes reordered successfully",
             "routes": *routes
         })),
-    )
+    ))
 }
 
 /// Test/validate a route against sample call data
 pub async fn test_route(
-    State(state): State<RoutesState>,
+    State((state, groups, schedules)): State<(RoutesState, GroupsState, SchedulesState)>,
     Json(payload): Json<Value>,
 ) -> (StatusCode, Json<Value>) {
-    use rustalk_core::routing::{CallContext, RouteEvaluator, RouteRule, RoutingConfig};
+    use rustalk_core::voicemail::VoicemailDropConfig;
 
     let routes = state.read().await;
+    let groups = group_directory(&groups.read().await);
+    let schedules = schedule_directory(&schedules.read().await);
 
     // Extract test parameters
     let caller_id = payload["caller_id"].as_str().unwrap_or("unknown");
     let destination = payload["destination"].as_str().unwrap_or("unknown");
-
-    // Convert API routes to routing engine format
-    let mut routing_config = RoutingConfig::new();
-    for route in routes.iter() {
-        // Convert the route to RouteRule format
-        let route_json = serde_json::to_value(route).unwrap_or_default();
-        if let Ok(route_rule) = serde_json::from_value::<RouteRule>(route_json) {
-            routing_config.add_route(route_rule);
-        }
-    }
+    // Canonical domain called, for domain conditions
+    let domain = payload["domain"].as_str().map(str::to_string);
+    // Straight to voicemail, as if dialed with the voicemail drop prefix
+    let voicemail_drop = payload["voicemail_drop"].as_bool().unwrap_or(false);
+    let drop_config = VoicemailDropConfig::default();
+    let destination = if voicemail_drop {
+        format!("{}{}", drop_config.prefix, destination)
+    } else {
+        destination.to_string()
+    };
 
     // Create evaluator and test
-    let evaluator = RouteEvaluator::new(routing_config);
+    let matcher = ConditionMatcher::new()
+        .with_groups(Arc::new(groups))
+        .with_schedules(Arc::new(schedules));
+    let evaluator = RouteEvaluator::with_matcher(routing_config(&routes), Arc::new(matcher))
+        .with_voicemail_drop(drop_config);
     let context = CallContext {
         caller_id: caller_id.to_string(),
-        destination: destination.to_string(),
+        destination,
+        enum_uri: None,
+        domain,
     };
 
     match evaluator.evaluate(&context) {
@@ -222,3 +250,431 @@ pub async fn test_route(
         ),
     }
 }
+
+/// Show which route, trunk and cost a call to a number would get
+///
+/// Least-cost routes list every trunk in the order it would be tried, and
+/// name the trunks passed over for being down.
+pub async fn simulate_route(
+    State(state): State<SimulationState>,
+    Json(call): Json<RouteSimulation>,
+) -> ApiResult {
+    if call.number.is_empty() {
+        return Err(
+            ApiError::bad_request("A number to simulate is required").with("field", "number")
+        );
+    }
+    let routes = state.routes.read().await;
+    let matcher = ConditionMatcher::new()
+        .with_groups(Arc::new(group_directory(&state.groups.read().await)))
+        .with_schedules(Arc::new(schedule_directory(&state.schedules.read().await)));
+    let mut evaluator = RouteEvaluator::with_matcher(routing_config(&routes), Arc::new(matcher))
+        .with_least_cost(state.least_cost.clone());
+    if let Some(health) = state.trunk_health.clone() {
+        evaluator = evaluator.with_trunk_health(health);
+    }
+    let context = CallContext {
+        caller_id: call.caller_id.unwrap_or_default(),
+        destination: call.number.clone(),
+        enum_uri: None,
+        domain: call.domain,
+    };
+
+    let Some(route_match) = evaluator.evaluate(&context) else {
+        return Ok((
+            StatusCode::OK,
+            Json(json!({
+                "number": call.number,
+                "matched": false,
+                "message": "No route matches the number"
+            })),
+        ));
+    };
+    let seconds = call
+        .duration_seconds
+        .unwrap_or(state.least_cost.config().reference_seconds);
+    // Calls routed to a single trunk are costed on it too
+    let chosen = match (&route_match.destination, &route_match.action) {
+        (RouteDestination::Trunk(trunk), RouteAction::Accept) => Some(
+            route_match
+                .least_cost
+                .first()
+                .cloned()
+                .unwrap_or_else(|| state.least_cost.quote(trunk, &call.number, Utc::now())),
+        ),
+        _ => None,
+    };
+    let candidates: Vec<Value> = route_match
+        .least_cost
+        .iter()
+        .map(|quote| costed(quote, seconds))
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "number": call.number,
+            "matched": true,
+            "route_id": route_match.route_id,
+            "route_name": route_match.route_name,
+            "action": route_match.action,
+            "destination": route_match.destination,
+            "duration_seconds": seconds,
+            "chosen": chosen.map(|quote| costed(&quote, seconds)),
+            "candidates": candidates,
+            "hops": route_match.hops,
+            "failover": route_match.failover,
+            "on_failure": route_match.on_failure,
+            "trunks_down": route_match.trunks_down,
+            "bypassed": route_match.bypassed,
+        })),
+    ))
+}
+
+/// A trunk with its rate and the cost of a call lasting `seconds`
+fn costed(quote: &TrunkQuote, seconds: u32) -> Value {
+    json!({
+        "trunk": quote.trunk,
+        "rate": quote.rate,
+        "cost": quote.rate.as_ref().map(|rate| rate.cost(seconds)),
+        "currency": quote.rate.as_ref().map(|rate| &rate.currency),
+    })
+}
+
+/// List the stored route test cases
+pub async fn list_route_tests(State(state): State<RouteTestsState>) -> (StatusCode, Json<Value>) {
+    let cases = state.cases.read().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "tests": *cases,
+            "total": cases.len()
+        })),
+    )
+}
+
+/// Save a named route test case, replacing one with the same name
+pub async fn save_route_test(
+    Path(name): Path<String>,
+    State(state): State<RouteTestsState>,
+    Json(mut payload): Json<RouteTestCase>,
+) -> (StatusCode, Json<Value>) {
+    let mut cases = state.cases.write().await;
+    payload.name = name.clone();
+
+    let status = match cases.iter_mut().find(|c| c.name == name) {
+        Some(case) => {
+            *case = payload;
+            StatusCode::OK
+        }
+        None => {
+            cases.push(payload);
+            StatusCode::CREATED
+        }
+    };
+    (
+        status,
+        Json(json!({
+            "success": true,
+            "message": format!("Route test '{}' saved", name)
+        })),
+    )
+}
+
+/// Delete a route test case
+pub async fn delete_route_test(
+    Path(name): Path<String>,
+    State(state): State<RouteTestsState>,
+) -> ApiResult {
+    let mut cases = state.cases.write().await;
+
+    if let Some(pos) = cases.iter().position(|c| c.name == name) {
+        cases.remove(pos);
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": format!("Route test '{}' deleted", name)
+            })),
+        ))
+    } else {
+        Err(ApiError::not_found("Route test not found"))
+    }
+}
+
+/// Run every stored test case against the live routes
+///
+/// Responds 422 when any case fails, so scripts can gate on the status.
+pub async fn run_tests(State(state): State<RouteTestsState>) -> ApiResult {
+    let mut routing = routing_config(&state.routes.read().await);
+    routing.tests = state.cases.read().await.clone();
+    let groups = group_directory(&state.groups.read().await);
+    let schedules = schedule_directory(&state.schedules.read().await);
+
+    let report = run_route_tests(&routing, Arc::new(groups), Arc::new(schedules));
+    if !report.is_success() {
+        return Err(ApiError::new(
+            ErrorCode::RouteTestsFailed,
+            format!(
+                "{} of {} route tests failed",
+                report.failed,
+                report.results.len()
+            ),
+        )
+        .with("report", report));
+    }
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "report": report
+        })),
+    ))
+}
+
+/// Query parameters for the route graph export
+#[derive(Debug, Default, Deserialize)]
+pub struct GraphParams {
+    /// `json` (default) or `dot`
+    pub format: Option<String>,
+}
+
+/// Export the route tree as a graph
+pub async fn export_route_graph(
+    State(state): State<RoutesState>,
+    Query(params): Query<GraphParams>,
+) -> Response {
+    let routes = state.read().await;
+    let graph = RouteGraph::from_config(&routing_config(&routes));
+
+    match params.format.as_deref().unwrap_or("json") {
+        "json" => (StatusCode::OK, Json(json!(graph))).into_response(),
+        "dot" => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/vnd.graphviz")],
+            graph.to_dot(),
+        )
+            .into_response(),
+        other => ApiError::bad_request(format!(
+            "Unknown graph format '{}', expected json or dot",
+            other
+        ))
+        .into_response(),
+    }
+}
+
+/// Convert API routes to routing engine format
+fn routing_config(routes: &[Route]) -> RoutingConfig {
+    let mut routing_config = RoutingConfig::new();
+    for route in routes {
+        let route_json = serde_json::to_value(route).unwrap_or_default();
+        if let Ok(route_rule) = serde_json::from_value::<RouteRule>(route_json) {
+            routing_config.add_route(route_rule);
+        }
+    }
+    routing_config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{RouteAction, RouteDestination};
+
+    fn state() -> RouteTestsState {
+        RouteTestsState {
+            routes: Arc::new(RwLock::new(vec![Route {
+                id: "sales".to_string(),
+                name: "Sales".to_string(),
+                description: None,
+                pattern: "^2".to_string(),
+                destination: RouteDestination::RingGroup("sales".to_string()),
+                enabled: true,
+                priority: 10,
+                conditions: None,
+                action: RouteAction::Accept,
+                continue_on_match: false,
+                on_failure: Vec::new(),
+            }])),
+            groups: Arc::new(RwLock::new(Vec::new())),
+            schedules: Arc::new(RwLock::new(Vec::new())),
+            cases: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    fn case(destination: &str, expected_route: Option<&str>) -> RouteTestCase {
+        RouteTestCase {
+            name: String::new(),
+            caller_id: "1001".to_string(),
+            destination: destination.to_string(),
+            timestamp: None,
+            expected_route: expected_route.map(str::to_string),
+            expected_destination: None,
+            domain: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stored_route_tests() {
+        let state = state();
+
+        let (status, _) = save_route_test(
+            Path("sales".to_string()),
+            State(state.clone()),
+            Json(case("2000", Some("sales"))),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = save_route_test(
+            Path("unrouted".to_string()),
+            State(state.clone()),
+            Json(case("3000", None)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, response) = run_tests(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["report"]["passed"], json!(2));
+
+        // Widening the route breaks the unrouted case
+        state.routes.write().await[0].pattern = ".*".to_string();
+        let error = run_tests(State(state.clone())).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), ErrorCode::RouteTestsFailed);
+        let body = error.body();
+        assert_eq!(body["report"]["results"][1]["name"], json!("unrouted"));
+        assert_eq!(body["report"]["results"][1]["passed"], json!(false));
+
+        let (status, _) = delete_route_test(Path("unrouted".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = run_tests(State(state)).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_least_cost_route() {
+        let routes = state().routes;
+        routes.write().await.push(Route {
+            id: "uk".to_string(),
+            name: "UK".to_string(),
+            description: None,
+            pattern: r"^\+44".to_string(),
+            destination: RouteDestination::LeastCost(vec![
+                "carrier-a".to_string(),
+                "carrier-b".to_string(),
+            ]),
+            enabled: true,
+            priority: 20,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        });
+        let rate = |id: &str, per_minute: f64, fee: f64| {
+            json!({
+                "id": id, "name": id, "description": null, "prefix": "44",
+                "rate_per_minute": per_minute, "connection_fee": fee,
+                "minimum_charge_seconds": 0, "billing_increment_seconds": 60,
+                "currency": "GBP", "effective_date": 0, "end_date": null,
+                "active": true, "trunk": id
+            })
+        };
+        let rates = serde_json::from_value(json!({
+            "rates": [rate("carrier-a", 0.02, 0.0), rate("carrier-b", 0.01, 0.05)]
+        }))
+        .unwrap();
+        let state = SimulationState {
+            routes,
+            groups: Arc::new(RwLock::new(Vec::new())),
+            schedules: Arc::new(RwLock::new(Vec::new())),
+            least_cost: LeastCostRouter::new(rates),
+            trunk_health: None,
+        };
+        let simulate = |number: &str, duration_seconds: Option<u32>| RouteSimulation {
+            number: number.to_string(),
+            caller_id: None,
+            domain: None,
+            duration_seconds,
+        };
+
+        // A minute costs 0.02 on a and 0.06 on b, with its connection fee
+        let (status, response) =
+            simulate_route(State(state.clone()), Json(simulate("+442079460000", None)))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["route_id"], json!("uk"));
+        assert_eq!(response.0["chosen"]["trunk"], json!("carrier-a"));
+        assert_eq!(response.0["chosen"]["currency"], json!("GBP"));
+        assert_eq!(response.0["candidates"][1]["trunk"], json!("carrier-b"));
+
+        // Ten minutes cost 0.20 on a and 0.15 on b, but the ranking is for
+        // a minute
+        let (_, response) = simulate_route(
+            State(state.clone()),
+            Json(simulate("+442079460000", Some(600))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0["chosen"]["trunk"], json!("carrier-a"));
+        let cost = response.0["candidates"][1]["cost"].as_f64().unwrap();
+        assert!((cost - 0.15).abs() < 1e-9);
+
+        let (_, response) = simulate_route(State(state.clone()), Json(simulate("+15550100", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.0["matched"], json!(false));
+
+        let error = simulate_route(State(state), Json(simulate("", None)))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_edits_need_current_etag() {
+        let routes = state().routes;
+        let path = || Path("sales".to_string());
+
+        let (_, [(_, tag)], route) = get_route(path(), State(routes.clone())).await.unwrap();
+        let mut first: Route = serde_json::from_value(route.0).unwrap();
+        let mut second = first.clone();
+        first.pattern = "^3".to_string();
+        second.priority = 20;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, tag.parse().unwrap());
+        let (status, [(_, new_tag)], _) =
+            update_route(path(), State(routes.clone()), headers.clone(), Json(first))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(new_tag, tag);
+
+        // The second admin still holds the old tag
+        let error = update_route(path(), State(routes.clone()), headers.clone(), Json(second))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::PreconditionFailed);
+        assert_eq!(routes.read().await[0].pattern, "^3");
+
+        let error = delete_route(path(), State(routes.clone()), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::PreconditionRequired);
+        let error = delete_route(path(), State(routes.clone()), headers)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::PreconditionFailed);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, new_tag.parse().unwrap());
+        let (status, _) = delete_route(path(), State(routes.clone()), headers)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(routes.read().await.is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! Schedule management handlers
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use rustalk_core::schedules::{Schedule, ScheduleDirectory};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub type SchedulesState = Arc<RwLock<Vec<Schedule>>>;
+
+/// List all schedules
+pub async fn list_schedules(State(state): State<SchedulesState>) -> (StatusCode, Json<Value>) {
+    let schedules = state.read().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "schedules": *schedules,
+            "total": schedules.len()
+        })),
+    )
+}
+
+/// Get a specific schedule
+pub async fn get_schedule(
+    Path(id): Path<String>,
+    State(state): State<SchedulesState>,
+) -> ApiResult {
+    let schedules = state.read().await;
+
+    if let Some(schedule) = schedules.iter().find(|s| s.id == id) {
+        Ok((StatusCode::OK, Json(json!(schedule))))
+    } else {
+        not_found()
+    }
+}
+
+/// Create a new schedule
+pub async fn create_schedule(
+    State(state): State<SchedulesState>,
+    Json(payload): Json<Schedule>,
+) -> ApiResult {
+    let mut schedules = state.write().await;
+
+    if schedules.iter().any(|s| s.id == payload.id) {
+        return Err(ApiError::conflict("Schedule already exists"));
+    }
+
+    let id = payload.id.clone();
+    schedules.push(payload);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "success": true,
+            "message": "Schedule created successfully",
+            "id": id
+        })),
+    ))
+}
+
+/// Update an existing schedule
+pub async fn update_schedule(
+    Path(id): Path<String>,
+    State(state): State<SchedulesState>,
+    Json(payload): Json<Schedule>,
+) -> ApiResult {
+    let mut schedules = state.write().await;
+
+    if let Some(schedule) = schedules.iter_mut().find(|s| s.id == id) {
+        *schedule = payload;
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": "Schedule updated successfully"
+            })),
+        ))
+    } else {
+        not_found()
+    }
+}
+
+/// Delete a schedule
+pub async fn delete_schedule(
+    Path(id): Path<String>,
+    State(state): State<SchedulesState>,
+) -> ApiResult {
+    let mut schedules = state.write().await;
+
+    if let Some(pos) = schedules.iter().position(|s| s.id == id) {
+        schedules.remove(pos);
+        Ok((
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "message": "Schedule deleted successfully"
+            })),
+        ))
+    } else {
+        not_found()
+    }
+}
+
+/// Whether a schedule is open right now, and the holiday in force if any
+pub async fn schedule_status(
+    Path(id): Path<String>,
+    State(state): State<SchedulesState>,
+) -> ApiResult {
+    let schedules = state.read().await;
+
+    let Some(schedule) = schedules.iter().find(|s| s.id == id) else {
+        return not_found();
+    };
+    let now = Utc::now();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "schedule": id,
+            "open": schedule.is_open(now.naive_utc()),
+            "holiday": schedule.holiday_on(now.date_naive()).map(|h| &h.name),
+            "checked_at": now,
+        })),
+    ))
+}
+
+/// Build the directory route conditions are resolved against
+pub fn schedule_directory(schedules: &[Schedule]) -> ScheduleDirectory {
+    ScheduleDirectory::new(schedules.to_vec())
+}
+
+fn not_found() -> ApiResult {
+    Err(ApiError::not_found("Schedule not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn always_closed() -> Schedule {
+        Schedule {
+            id: "closed".to_string(),
+            name: "Closed".to_string(),
+            description: None,
+            hours: Vec::new(),
+            holidays: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_schedule_crud_and_status() {
+        let state: SchedulesState = Arc::new(RwLock::new(Vec::new()));
+
+        let (status, _) = create_schedule(State(state.clone()), Json(always_closed()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let error = create_schedule(State(state.clone()), Json(always_closed()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+
+        let (status, response) = schedule_status(Path("closed".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["open"], json!(false));
+        assert_eq!(response.0["holiday"], Value::Null);
+
+        let (status, _) = delete_schedule(Path("closed".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let error = schedule_status(Path("closed".to_string()), State(state))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+}
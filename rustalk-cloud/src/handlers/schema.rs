@@ -0,0 +1,43 @@
+//! JSON Schema handlers
+//!
+//! Let external tooling validate configuration and API payloads before
+//! applying them. Both documents are generated from this build's types.
+
+use axum::{http::StatusCode, Json};
+use rustalk_core::config::schema::config_schema;
+use schemars::schema::RootSchema;
+
+use crate::models::models_schema;
+
+/// JSON Schema of the configuration file
+pub async fn get_config_schema() -> (StatusCode, Json<RootSchema>) {
+    (StatusCode::OK, Json(config_schema()))
+}
+
+/// JSON Schema of the resources managed through the API
+pub async fn get_models_schema() -> (StatusCode, Json<RootSchema>) {
+    (StatusCode::OK, Json(models_schema()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_models_schema() {
+        let (status, Json(schema)) = get_models_schema().await;
+        assert_eq!(status, StatusCode::OK);
+        let schema = serde_json::to_value(schema).unwrap();
+        let definitions = schema["definitions"].as_object().unwrap();
+        for model in ["Extension", "Trunk", "Route", "SipProfile", "NatSettings"] {
+            assert!(definitions.contains_key(model), "{} missing", model);
+        }
+        assert_eq!(
+            schema["definitions"]["RingStrategy"]["enum"],
+            serde_json::json!(["simultaneous", "sequential", "roundrobin"])
+        );
+
+        let (_, Json(config)) = get_config_schema().await;
+        assert_eq!(config.schema.metadata.unwrap().title.unwrap(), "Config");
+    }
+}
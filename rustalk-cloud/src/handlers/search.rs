@@ -0,0 +1,334 @@
+//! Search across configuration entities
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::error::{ApiError, ApiResult};
+use crate::handlers::dids::DidsState;
+use crate::handlers::extensions::ExtensionsState;
+use crate::handlers::routes::RoutesState;
+use crate::handlers::trunks::TrunksState;
+use crate::handlers::voicemail::VoicemailState;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+/// Entities the search looks through
+#[derive(Clone)]
+pub struct SearchState {
+    pub extensions: ExtensionsState,
+    pub dids: DidsState,
+    pub trunks: TrunksState,
+    pub routes: RoutesState,
+    pub voicemail: VoicemailState,
+}
+
+/// Query parameters for a search
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    /// Most results to return (default 20, at most 100)
+    pub limit: Option<usize>,
+}
+
+/// Kind of entity a result refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    Extension,
+    Did,
+    Trunk,
+    Route,
+    Mailbox,
+}
+
+/// One search result
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub kind: SearchKind,
+    pub id: String,
+    /// Text to show for the result
+    pub label: String,
+    /// Field the query matched best
+    pub field: &'static str,
+    /// Higher is more relevant
+    pub score: u32,
+}
+
+/// A field to match, with its weight
+type Field<'a> = (&'static str, Option<&'a str>, u32);
+
+/// Primary fields such as numbers and names count for more than descriptions
+const PRIMARY: u32 = 2;
+const SECONDARY: u32 = 1;
+
+/// Search extensions, DIDs, trunks, routes and mailboxes
+///
+/// Results are ordered by relevance: an exact match beats a prefix, which
+/// beats a word prefix, which beats a match anywhere. Numbers also match
+/// ignoring punctuation, so `212-555` finds `+12125551234`.
+pub async fn search(
+    State(state): State<SearchState>,
+    Query(params): Query<SearchParams>,
+) -> ApiResult {
+    let query = params.q.trim().to_lowercase();
+    if query.is_empty() {
+        return Err(ApiError::bad_request("Search query must not be empty"));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let mut hits = Vec::new();
+    for ext in state.extensions.read().await.iter() {
+        hits.extend(hit(
+            &query,
+            SearchKind::Extension,
+            &ext.id,
+            format!("{} {}", ext.extension, ext.display_name),
+            &[
+                ("extension", Some(&ext.extension), PRIMARY),
+                ("display_name", Some(&ext.display_name), PRIMARY),
+                ("id", Some(&ext.id), SECONDARY),
+            ],
+        ));
+    }
+    for did in state.dids.read().await.iter() {
+        hits.extend(hit(
+            &query,
+            SearchKind::Did,
+            &did.id,
+            did.number.clone(),
+            &[
+                ("number", Some(&did.number), PRIMARY),
+                ("description", did.description.as_deref(), SECONDARY),
+                ("destination", Some(&did.destination), SECONDARY),
+                ("id", Some(&did.id), SECONDARY),
+            ],
+        ));
+    }
+    for trunk in state.trunks.read().await.iter() {
+        hits.extend(hit(
+            &query,
+            SearchKind::Trunk,
+            &trunk.id,
+            trunk.name.clone(),
+            &[
+                ("name", Some(&trunk.name), PRIMARY),
+                ("host", Some(&trunk.host), SECONDARY),
+                ("description", trunk.description.as_deref(), SECONDARY),
+                ("id", Some(&trunk.id), SECONDARY),
+            ],
+        ));
+    }
+    for route in state.routes.read().await.iter() {
+        hits.extend(hit(
+            &query,
+            SearchKind::Route,
+            &route.id,
+            route.name.clone(),
+            &[
+                ("name", Some(&route.name), PRIMARY),
+                ("description", route.description.as_deref(), SECONDARY),
+                ("pattern", Some(&route.pattern), SECONDARY),
+                ("id", Some(&route.id), SECONDARY),
+            ],
+        ));
+    }
+    for mailbox in state.voicemail.list_mailboxes().await {
+        hits.extend(hit(
+            &query,
+            SearchKind::Mailbox,
+            &mailbox.id,
+            format!("{} {}", mailbox.extension, mailbox.name),
+            &[
+                ("extension", Some(&mailbox.extension), PRIMARY),
+                ("name", Some(&mailbox.name), PRIMARY),
+                ("email", mailbox.email.as_deref(), SECONDARY),
+                ("id", Some(&mailbox.id), SECONDARY),
+            ],
+        ));
+    }
+
+    hits.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(a.kind.cmp(&b.kind))
+            .then_with(|| a.label.cmp(&b.label))
+    });
+    let total = hits.len();
+    hits.truncate(limit);
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "query": params.q.trim(),
+            "results": hits,
+            "total": total
+        })),
+    ))
+}
+
+/// The best match of `query` among an entity's fields
+fn hit(
+    query: &str,
+    kind: SearchKind,
+    id: &str,
+    label: String,
+    fields: &[Field<'_>],
+) -> Option<SearchHit> {
+    fields
+        .iter()
+        .filter_map(|(name, value, weight)| {
+            let score = score(query, (*value)?)?;
+            Some((*name, score * weight))
+        })
+        .max_by_key(|(_, score)| *score)
+        .map(|(field, score)| SearchHit {
+            kind,
+            id: id.to_string(),
+            label,
+            field,
+            score,
+        })
+}
+
+/// Relevance of `value` to a lower-cased query, if it matches at all
+fn score(query: &str, value: &str) -> Option<u32> {
+    let value = value.to_lowercase();
+    let text = if value == query {
+        Some(100)
+    } else if value.starts_with(query) {
+        Some(75)
+    } else if value
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(query))
+    {
+        Some(50)
+    } else if value.contains(query) {
+        Some(25)
+    } else {
+        None
+    };
+
+    // Dialable numbers match ignoring formatting, a little below a text match
+    let digits = |s: &str| s.chars().filter(char::is_ascii_digit).collect::<String>();
+    let (query_digits, value_digits) = (digits(query), digits(&value));
+    let number = if query_digits.len() < 3
+        || !query
+            .chars()
+            .all(|c| c.is_ascii_digit() || "+-(). ".contains(c))
+    {
+        None
+    } else if value_digits == query_digits {
+        Some(90)
+    } else if value_digits.contains(&query_digits) {
+        Some(20)
+    } else {
+        None
+    };
+
+    text.max(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Did, Extension, Trunk};
+    use rustalk_core::voicemail::{VoicemailBox, VoicemailManager};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    async fn state() -> SearchState {
+        let extensions: Vec<Extension> = serde_json::from_value(json!([
+            { "id": "1001", "extension": "1001", "display_name": "Alice Smith", "password": "x", "enabled": true, "voicemail_enabled": true, "priority": 0 },
+            { "id": "1002", "extension": "1002", "display_name": "Sales Desk", "password": "x", "enabled": true, "voicemail_enabled": true, "priority": 0 }
+        ]))
+        .unwrap();
+        let dids: Vec<Did> = serde_json::from_value(json!([
+            { "id": "main", "number": "+12125551000", "description": "Sales line", "destination": "1002", "enabled": true, "priority": 0 }
+        ]))
+        .unwrap();
+        let trunks: Vec<Trunk> = serde_json::from_value(json!([
+            { "id": "carrier", "name": "Carrier", "description": null, "host": "sip.sales-carrier.example", "port": 5060, "username": null, "password": null, "enabled": true, "priority": 0 }
+        ]))
+        .unwrap();
+        let voicemail = VoicemailManager::new("/tmp/rustalk-search-test");
+        voicemail
+            .add_mailbox(VoicemailBox {
+                id: "1001".to_string(),
+                extension: "1001".to_string(),
+                name: "Alice Smith".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        SearchState {
+            extensions: Arc::new(RwLock::new(extensions)),
+            dids: Arc::new(RwLock::new(dids)),
+            trunks: Arc::new(RwLock::new(trunks)),
+            routes: Arc::new(RwLock::new(Vec::new())),
+            voicemail,
+        }
+    }
+
+    async fn results(q: &str) -> Vec<(String, String)> {
+        let (_, response) = search(
+            State(state().await),
+            Query(SearchParams {
+                q: q.to_string(),
+                limit: None,
+            }),
+        )
+        .await
+        .unwrap();
+        response.0["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["kind"].as_str().unwrap().to_string(),
+                    r["id"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(kind, id)| (kind.to_string(), id.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_search_relevance() {
+        // A name starting with the query beats a word or substring match
+        assert_eq!(
+            results("sales").await,
+            pairs(&[("extension", "1002"), ("did", "main"), ("trunk", "carrier")])
+        );
+        assert_eq!(
+            results("1001").await,
+            pairs(&[("extension", "1001"), ("mailbox", "1001")])
+        );
+        assert_eq!(results("(212) 555-1000").await, pairs(&[("did", "main")]));
+        assert!(results("nobody").await.is_empty());
+
+        let error = search(
+            State(state().await),
+            Query(SearchParams {
+                q: "  ".to_string(),
+                limit: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -5,14 +5,95 @@ use axum::{
     http::StatusCode,
     Json,
 };
+use rustalk_core::domains::{DomainMap, DomainProfile};
+use rustalk_core::nat::{NatPolicy, NatProfile};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::error::{ApiError, ApiResult};
 use crate::models::SipProfile;
 
 pub type SipProfilesState = Arc<RwLock<Vec<SipProfile>>>;
 
+/// SIP profiles with the NAT settings and domains the registrar, B2BUA and
+/// transports apply
+pub type SipProfilePolicyState = (SipProfilesState, NatPolicy, DomainMap);
+
+fn validate_nat(profile: &SipProfile) -> Result<(), ApiError> {
+    if profile.nat.keepalive_interval_seconds == Some(0) {
+        return Err(
+            ApiError::unprocessable("keepalive_interval_seconds must be at least 1")
+                .with("field", "nat"),
+        );
+    }
+    if profile.nat.min_expires == Some(0) {
+        return Err(ApiError::unprocessable("min_expires must be at least 1").with("field", "nat"));
+    }
+    Ok(())
+}
+
+/// Hand a profile's NAT settings to the registrar and transports; disabled
+/// profiles stop applying
+fn apply_nat(policy: &NatPolicy, profile: &SipProfile) {
+    if !profile.enabled {
+        policy.remove_profile(&profile.name);
+        return;
+    }
+    policy.set_profile(NatProfile {
+        name: profile.name.clone(),
+        domain: profile.domain.clone(),
+        bind_port: Some(profile.bind_port),
+        nat: profile.nat.clone(),
+    });
+}
+
+/// Refuse a domain or alias an enabled profile other than `replacing`
+/// already serves
+fn check_domains(
+    profiles: &[SipProfile],
+    profile: &SipProfile,
+    replacing: Option<&str>,
+) -> Result<(), ApiError> {
+    if !profile.enabled {
+        return Ok(());
+    }
+    let names = std::iter::once(&profile.domain).chain(&profile.aliases);
+    for name in names {
+        let owner = profiles.iter().find(|p| {
+            p.enabled
+                && Some(p.id.as_str()) != replacing
+                && std::iter::once(&p.domain)
+                    .chain(&p.aliases)
+                    .any(|d| d.eq_ignore_ascii_case(name))
+        });
+        if let Some(owner) = owner {
+            return Err(ApiError::conflict(format!(
+                "Domain {} is already served by SIP profile {}",
+                name, owner.name
+            ))
+            .with("field", "aliases"));
+        }
+    }
+    Ok(())
+}
+
+/// Hand a profile's domain, aliases and egress interface to the registrar,
+/// B2BUA and outbound sender; disabled profiles stop serving them
+fn apply_domains(domains: &DomainMap, profile: &SipProfile) {
+    if !profile.enabled {
+        domains.remove_profile(&profile.name);
+        return;
+    }
+    domains.set_profile(DomainProfile {
+        name: profile.name.clone(),
+        domain: profile.domain.clone(),
+        aliases: profile.aliases.clone(),
+        realm: profile.realm.clone(),
+        egress_interface: profile.egress_interface.clone(),
+    });
+}
+
 /// List all SIP profiles
 pub async fn list_sip_profiles(State(state): State<SipProfilesState>) -> (StatusCode, Json<Value>) {
     let profiles = state.read().await;
@@ -29,26 +110,22 @@ pub async fn list_sip_profiles(State(state): State<SipProfilesState>) -> (Status
 pub async fn get_sip_profile(
     Path(id): Path<String>,
     State(state): State<SipProfilesState>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let profiles = state.read().await;
 
     if let Some(profile) = profiles.iter().find(|p| p.id == id) {
-        (StatusCode::OK, Json(json!(profile)))
+        Ok((StatusCode::OK, Json(json!(profile))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "SIP profile not found"
-            })),
-        )
+        Err(ApiError::not_found("SIP profile not found"))
     }
 }
 
 /// Create a new SIP profile
 pub async fn create_sip_profile(
-    State(state): State<SipProfilesState>,
+    State((state, nat, domains)): State<SipProfilePolicyState>,
     Json(payload): Json<SipProfile>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
+    validate_nat(&payload)?;
     let mut profiles = state.write().await;
 
     // Check if SIP profile already exists
@@ -56,79 +133,72 @@ pub async fn create_sip_profile(
         .iter()
         .any(|p| p.id == payload.id || p.name == payload.name)
     {
-        return (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": "SIP profile already exists"
-            })),
-        );
+        return Err(ApiError::conflict("SIP profile already exists"));
     }
+    check_domains(&profiles, &payload, None)?;
 
+    apply_nat(&nat, &payload);
+    apply_domains(&domains, &payload);
     profiles.push(payload.clone());
 
-    (
+    Ok((
         StatusCode::CREATED,
         Json(json!({
             "success": true,
             "message": "SIP profile created successfully",
             "id": payload.id
         })),
-    )
+    ))
 }
 
 /// Update an existing SIP profile
 pub async fn update_sip_profile(
     Path(id): Path<String>,
-    State(state): State<SipProfilesState>,
+    State((state, nat, domains)): State<SipProfilePolicyState>,
     Json(payload): Json<SipProfile>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
+    validate_nat(&payload)?;
     let mut profiles = state.write().await;
+    check_domains(&profiles, &payload, Some(&id))?;
 
     if let Some(profile) = profiles.iter_mut().find(|p| p.id == id) {
+        nat.remove_profile(&profile.name);
+        domains.remove_profile(&profile.name);
+        apply_nat(&nat, &payload);
+        apply_domains(&domains, &payload);
         *profile = payload;
-        (
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "SIP profile updated successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "SIP profile not found"
-            })),
-        )
+        Err(ApiError::not_found("SIP profile not found"))
     }
 }
 
 /// Delete a SIP profile
 pub async fn delete_sip_profile(
     Path(id): Path<String>,
-    State(state): State<SipProfilesState>,
-) -> (StatusCode, Json<Value>) {
+    State((state, nat, domains)): State<SipProfilePolicyState>,
+) -> ApiResult {
     let mut profiles = state.write().await;
 
     if let Some(pos) = profiles.iter().position(|p| p.id == id) {
-        profiles.remove(pos);
-        (
+        let profile = profiles.remove(pos);
+        nat.remove_profile(&profile.name);
+        domains.remove_profile(&profile.name);
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "SIP profile deleted successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "SIP profile not found"
-            })),
-        )
+        Err(ApiError::not_found("SIP profile not found"))
     }
 }
 
@@ -136,20 +206,14 @@ pub async fn delete_sip_profile(
 pub async fn reorder_sip_profiles(
     State(state): State<SipProfilesState>,
     Json(payload): Json<Value>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut profiles = state.write().await;
 
     let from_index = payload["from_index"].as_u64().unwrap_or(0) as usize;
     let to_index = payload["to_index"].as_u64().unwrap_or(0) as usize;
 
     if from_index >= profiles.len() || to_index >= profiles.len() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "message": "Invalid index"
-            })),
-        );
+        return Err(ApiError::bad_request("Invalid index"));
     }
 
     let item = profiles.remove(from_index);
@@ -160,12 +224,147 @@ pub async fn reorder_sip_profiles(
         profile.priority = index as u32;
     }
 
-    (
+    Ok((
         StatusCode::OK,
         Json(json!({
             "success": true,
             "message": "SIP profiles reordered successfully",
             "sip_profiles": *profiles
         })),
-    )
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use rustalk_core::nat::{NatRewrite, NatSettings};
+
+    fn profile(nat: NatSettings) -> SipProfile {
+        SipProfile {
+            id: "external".to_string(),
+            name: "external".to_string(),
+            description: None,
+            bind_address: "0.0.0.0".to_string(),
+            bind_port: 5080,
+            domain: "example.com".to_string(),
+            aliases: Vec::new(),
+            realm: None,
+            enabled: true,
+            priority: 0,
+            egress_interface: None,
+            nat,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nat_settings_reach_registrar() {
+        let policy = NatPolicy::default();
+        let state: SipProfilePolicyState = (
+            Arc::new(RwLock::new(Vec::new())),
+            policy.clone(),
+            DomainMap::default(),
+        );
+        let nat = NatSettings {
+            keepalive_interval_seconds: Some(20),
+            min_expires: Some(120),
+            rewrite_contact: NatRewrite::Never,
+            rewrite_sdp: NatRewrite::Detect,
+        };
+        let (status, _) = create_sip_profile(State(state.clone()), Json(profile(nat.clone())))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(policy.for_domain("example.com"), nat);
+        assert_eq!(policy.for_port(5080), Some(nat));
+
+        // Disabled profiles stop applying
+        let mut disabled = profile(NatSettings::default());
+        disabled.enabled = false;
+        let (status, _) = update_sip_profile(
+            Path("external".to_string()),
+            State(state.clone()),
+            Json(disabled),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(policy.for_port(5080), None);
+
+        let error = update_sip_profile(
+            Path("external".to_string()),
+            State(state.clone()),
+            Json(profile(NatSettings {
+                keepalive_interval_seconds: Some(0),
+                ..NatSettings::default()
+            })),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ValidationFailed);
+
+        let (status, _) = update_sip_profile(
+            Path("external".to_string()),
+            State(state.clone()),
+            Json(profile(NatSettings::default())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(policy.for_port(5080).is_some());
+        let (status, _) = delete_sip_profile(Path("external".to_string()), State(state))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(policy.for_port(5080), None);
+    }
+
+    #[tokio::test]
+    async fn test_domains_reach_registrar() {
+        let domains = DomainMap::default();
+        let state: SipProfilePolicyState = (
+            Arc::new(RwLock::new(Vec::new())),
+            NatPolicy::default(),
+            domains.clone(),
+        );
+        let mut brand_a = profile(NatSettings::default());
+        brand_a.aliases = vec!["voice.example.com".to_string()];
+        let (status, _) = create_sip_profile(State(state.clone()), Json(brand_a.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            domains.canonical("voice.example.com").as_deref(),
+            Some("example.com")
+        );
+
+        // No two profiles serve the same domain
+        let mut brand_b = profile(NatSettings::default());
+        brand_b.id = "brand-b".to_string();
+        brand_b.name = "brand-b".to_string();
+        brand_b.domain = "other.example".to_string();
+        brand_b.aliases = vec!["Voice.example.com".to_string()];
+        let error = create_sip_profile(State(state.clone()), Json(brand_b.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Conflict);
+
+        // A profile keeps its own domains when updated
+        brand_a.aliases.push("sip.example.com".to_string());
+        let (status, _) = update_sip_profile(
+            Path("external".to_string()),
+            State(state.clone()),
+            Json(brand_a),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(domains.canonical("sip.example.com").is_some());
+
+        let (status, _) = delete_sip_profile(Path("external".to_string()), State(state))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(domains.domains().is_empty());
+    }
 }
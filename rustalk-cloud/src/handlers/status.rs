@@ -0,0 +1,129 @@
+//! Server status and readiness handlers
+
+use axum::{extract::State, http::StatusCode, Json};
+use rustalk_core::b2bua::B2BUA;
+use rustalk_core::supervisor::Supervisor;
+use serde_json::{json, Value};
+
+use crate::error::{ApiError, ApiResult};
+
+/// Components and call handling of the running server
+#[derive(Clone)]
+pub struct StatusState {
+    /// Supervisor running the server's components, under `rustalk start`
+    pub supervisor: Option<Supervisor>,
+    pub b2bua: B2BUA,
+}
+
+/// Status of each component `rustalk start` runs
+///
+/// The overall status is `draining` while handing over to a new process
+/// during an upgrade, and `degraded` while any component is restarting or
+/// has stopped.
+pub async fn get_status(State(state): State<StatusState>) -> ApiResult {
+    let supervisor = state.supervisor.as_ref().ok_or_else(|| {
+        ApiError::not_configured("This API is not running under the component supervisor")
+    })?;
+    let status = if state.b2bua.is_draining() {
+        "draining"
+    } else if supervisor.is_healthy().await {
+        "running"
+    } else {
+        "degraded"
+    };
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "status": status,
+            "version": env!("CARGO_PKG_VERSION"),
+            "active_calls": state.b2bua.session_count().await,
+            "components": supervisor.status().await,
+        })),
+    ))
+}
+
+/// Readiness probe
+///
+/// 503 until every component is running and again once the server starts
+/// draining, so load balancers and upgrade scripts move traffic to the
+/// process that is ready.
+pub async fn ready(State(state): State<StatusState>) -> (StatusCode, Json<Value>) {
+    let draining = state.b2bua.is_draining();
+    let running = match &state.supervisor {
+        Some(supervisor) => supervisor.is_healthy().await,
+        None => true,
+    };
+    let status = if draining || !running {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (
+        status,
+        Json(json!({
+            "ready": status == StatusCode::OK,
+            "draining": draining,
+        })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use rustalk_core::supervisor::{RestartPolicy, SupervisorConfig};
+
+    #[tokio::test]
+    async fn test_status_reports_components() {
+        let state = StatusState {
+            supervisor: None,
+            b2bua: B2BUA::new(),
+        };
+        let error = get_status(State(state)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let supervisor = Supervisor::new(SupervisorConfig {
+            restart: RestartPolicy::Never,
+            ..Default::default()
+        });
+        supervisor
+            .spawn("api", || async { std::future::pending().await })
+            .await;
+        supervisor
+            .spawn("edge", || async { Err(anyhow!("mTLS key not found")) })
+            .await
+            .await
+            .unwrap();
+        let state = StatusState {
+            supervisor: Some(supervisor),
+            b2bua: B2BUA::new(),
+        };
+
+        let (status, Json(body)) = get_status(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["components"][0]["name"], "api");
+        assert_eq!(body["components"][0]["state"], "running");
+        assert_eq!(body["components"][1]["state"], "failed");
+        assert_eq!(body["components"][1]["last_error"], "mTLS key not found");
+
+        let (status, _) = ready(State(state)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_while_draining() {
+        let state = StatusState {
+            supervisor: None,
+            b2bua: B2BUA::new(),
+        };
+        let (status, Json(body)) = ready(State(state.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+
+        state.b2bua.start_draining();
+        let (status, Json(body)) = ready(State(state)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["draining"], true);
+    }
+}
@@ -0,0 +1,84 @@
+//! Teams Direct Routing validation handlers
+
+use axum::{extract::State, http::StatusCode, Json};
+use rustalk_core::direct_routing::{self, ValidationTarget};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::{ApiError, ApiResult};
+
+/// The SBC to validate, from the `teams` configuration section
+pub type TeamsValidationState = Option<ValidationTarget>;
+
+/// Overrides for one validation run
+#[derive(Debug, Default, Deserialize)]
+pub struct ValidateRequest {
+    /// Number of a Teams user to ring with the test INVITE
+    pub test_number: Option<String>,
+    pub caller: Option<String>,
+    /// Teams SIP proxy, e.g. `sip2.pstnhub.microsoft.com`
+    pub proxy: Option<String>,
+}
+
+/// Run the Direct Routing checks and return the report
+///
+/// The report is returned with 200 whether or not the checks pass; see
+/// its `passed` field.
+pub async fn validate_direct_routing(
+    State(state): State<TeamsValidationState>,
+    body: Option<Json<ValidateRequest>>,
+) -> ApiResult {
+    let Some(mut target) = state else {
+        return Err(ApiError::not_configured(
+            "Teams Direct Routing is not configured",
+        ));
+    };
+    let request = body.map(|Json(request)| request).unwrap_or_default();
+    if let Some(number) = request.test_number {
+        target = target.with_test_number(number);
+    }
+    if let Some(caller) = request.caller {
+        target = target.with_caller(caller);
+    }
+    if let Some(proxy) = request.proxy {
+        let port = target.port;
+        target = target.with_proxy(proxy, port);
+    }
+
+    let report = direct_routing::validate(&target).await;
+    Ok((StatusCode::OK, Json(json!(report))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_validate_without_teams() {
+        let error = validate_direct_routing(State(None), None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_validate_reports_failures() {
+        // Local names keep the DNS checks off the network
+        let target = ValidationTarget::new(
+            "localhost",
+            "/nonexistent/teams-cert.pem",
+            "/nonexistent/teams-key.pem",
+        )
+        .with_proxy("localhost", 5061);
+        let (status, Json(report)) = validate_direct_routing(State(Some(target)), None)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["passed"], false);
+        assert_eq!(report["checks"][0]["name"], "certificate");
+        assert_eq!(report["checks"][0]["status"], "fail");
+        // Nothing that needs the certificate is attempted
+        assert_eq!(report["checks"][4]["status"], "skipped");
+        assert_eq!(report["checks"][5]["status"], "skipped");
+    }
+}
@@ -2,17 +2,52 @@
 
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     Json,
 };
+use rustalk_core::quotas::{QuotaResource, Quotas};
+use rustalk_core::trunk_health::{TrunkHealth, TrunkState};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::error::{ApiError, ApiResult};
+use crate::etag::{check_if_match, etag, etag_header, TaggedResult};
 use crate::models::Trunk;
+use crate::trash::Trash;
 
 pub type TrunksState = Arc<RwLock<Vec<Trunk>>>;
 
+/// Trunks and the trunks deleted from them
+pub type TrunkTrashState = (TrunksState, Trash<Trunk>);
+
+/// Trunks and the tenant quotas they count against
+pub type TrunkQuotaState = (TrunksState, Quotas);
+
+/// Deleted trunks and the tenant quotas restoring one counts against
+pub type TrunkRestoreState = (TrunksState, Trash<Trunk>, Quotas);
+
+/// OPTIONS probes of trunks, when configured
+pub type TrunkHealthState = Option<TrunkHealth>;
+
+/// Check that `trunk` fits within its tenant's trunk quota alongside the
+/// other trunks
+fn check_quota(quotas: &Quotas, trunks: &[Trunk], trunk: &Trunk) -> Result<(), ApiError> {
+    let Some(tenant) = &trunk.tenant else {
+        return Ok(());
+    };
+    let others = trunks
+        .iter()
+        .filter(|t| t.id != trunk.id)
+        .filter(|t| {
+            t.tenant
+                .as_ref()
+                .is_some_and(|x| x.eq_ignore_ascii_case(tenant))
+        })
+        .count() as u64;
+    Ok(quotas.check(tenant, QuotaResource::Trunks, others + 1)?)
+}
+
 /// List all trunks
 pub async fn list_trunks(State(state): State<TrunksState>) -> (StatusCode, Json<Value>) {
     let trunks = state.read().await;
@@ -26,29 +61,21 @@ pub async fn list_trunks(State(state): State<TrunksState>) -> (StatusCode, Json<
 }
 
 /// Get a specific trunk
-pub async fn get_trunk(
-    Path(id): Path<String>,
-    State(state): State<TrunksState>,
-) -> (StatusCode, Json<Value>) {
+pub async fn get_trunk(Path(id): Path<String>, State(state): State<TrunksState>) -> TaggedResult {
     let trunks = state.read().await;
 
     if let Some(trunk) = trunks.iter().find(|t| t.id == id) {
-        (StatusCode::OK, Json(json!(trunk)))
+        Ok((StatusCode::OK, etag_header(trunk), Json(json!(trunk))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "Trunk not found"
-            })),
-        )
+        Err(ApiError::not_found("Trunk not found"))
     }
 }
 
 /// Create a new trunk
 pub async fn create_trunk(
-    State(state): State<TrunksState>,
+    State((state, quotas)): State<TrunkQuotaState>,
     Json(payload): Json<Trunk>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut trunks = state.write().await;
 
     // Check if trunk already exists
@@ -56,100 +83,139 @@ pub async fn create_trunk(
         .iter()
         .any(|t| t.id == payload.id || t.name == payload.name)
     {
-        return (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": "Trunk already exists"
-            })),
-        );
+        return Err(ApiError::conflict("Trunk already exists"));
     }
+    check_quota(&quotas, &trunks, &payload)?;
 
     trunks.push(payload.clone());
 
-    (
+    Ok((
         StatusCode::CREATED,
         Json(json!({
             "success": true,
             "message": "Trunk created successfully",
             "id": payload.id
         })),
-    )
+    ))
 }
 
 /// Update an existing trunk
 pub async fn update_trunk(
     Path(id): Path<String>,
-    State(state): State<TrunksState>,
+    State((state, quotas)): State<TrunkQuotaState>,
+    headers: HeaderMap,
     Json(payload): Json<Trunk>,
-) -> (StatusCode, Json<Value>) {
+) -> TaggedResult {
     let mut trunks = state.write().await;
 
-    if let Some(trunk) = trunks.iter_mut().find(|t| t.id == id) {
+    if let Some(pos) = trunks.iter().position(|t| t.id == id) {
+        check_if_match(&headers, &etag(&trunks[pos]))?;
+        // Moving to another tenant counts against that tenant's quota
+        if trunks[pos].tenant != payload.tenant {
+            let mut moved = payload.clone();
+            moved.id = id.clone();
+            check_quota(&quotas, &trunks, &moved)?;
+        }
+        let trunk = &mut trunks[pos];
         *trunk = payload;
-        (
+        Ok((
             StatusCode::OK,
+            etag_header(trunk),
             Json(json!({
                 "success": true,
                 "message": "Trunk updated successfully"
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Trunk not found"
-            })),
-        )
+        Err(ApiError::not_found("Trunk not found"))
     }
 }
 
 /// Delete a trunk
+///
+/// The trunk is disabled and kept in the trash, where it can be restored
+/// until the retention window passes.
 pub async fn delete_trunk(
     Path(id): Path<String>,
-    State(state): State<TrunksState>,
-) -> (StatusCode, Json<Value>) {
+    State((state, trash)): State<TrunkTrashState>,
+    headers: HeaderMap,
+) -> ApiResult {
     let mut trunks = state.write().await;
 
     if let Some(pos) = trunks.iter().position(|t| t.id == id) {
-        trunks.remove(pos);
-        (
+        check_if_match(&headers, &etag(&trunks[pos]))?;
+        let mut trunk = trunks.remove(pos);
+        let was_enabled = std::mem::replace(&mut trunk.enabled, false);
+        let tombstone = trash.bury(trunk, was_enabled).await;
+        Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
-                "message": "Trunk deleted successfully"
+                "message": "Trunk deleted successfully",
+                "purge_at": tombstone.purge_at
             })),
-        )
+        ))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Trunk not found"
-            })),
-        )
+        Err(ApiError::not_found("Trunk not found"))
+    }
+}
+
+/// List deleted trunks that can still be restored
+pub async fn list_deleted_trunks(State(trash): State<Trash<Trunk>>) -> (StatusCode, Json<Value>) {
+    let deleted = trash.list().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "trunks": deleted,
+            "total": deleted.len()
+        })),
+    )
+}
+
+/// Restore a deleted trunk
+pub async fn restore_trunk(
+    Path(id): Path<String>,
+    State((state, trash, quotas)): State<TrunkRestoreState>,
+) -> ApiResult {
+    let mut trunks = state.write().await;
+
+    let Some(tombstone) = trash.get(|t| t.id == id).await else {
+        return Err(ApiError::not_found("No deleted trunk with that ID"));
+    };
+    if trunks
+        .iter()
+        .any(|t| t.id == id || t.name == tombstone.resource.name)
+    {
+        return Err(ApiError::conflict("Trunk already exists"));
     }
+    check_quota(&quotas, &trunks, &tombstone.resource)?;
+
+    // The collection lock is held, so this is the tombstone checked above
+    trash.take(|t| t.id == id).await;
+    let mut trunk = tombstone.resource;
+    trunk.enabled = tombstone.was_enabled;
+    trunks.push(trunk);
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "message": "Trunk restored successfully"
+        })),
+    ))
 }
 
 /// Reorder trunks (for priority management)
 pub async fn reorder_trunks(
     State(state): State<TrunksState>,
     Json(payload): Json<Value>,
-) -> (StatusCode, Json<Value>) {
+) -> ApiResult {
     let mut trunks = state.write().await;
 
     let from_index = payload["from_index"].as_u64().unwrap_or(0) as usize;
     let to_index = payload["to_index"].as_u64().unwrap_or(0) as usize;
 
     if from_index >= trunks.len() || to_index >= trunks.len() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "message": "Invalid index"
-            })),
-        );
+        return Err(ApiError::bad_request("Invalid index"));
     }
 
     let item = trunks.remove(from_index);
@@ -160,12 +226,66 @@ pub async fn reorder_trunks(
         trunk.priority = index as u32;
     }
 
-    (
+    Ok((
         StatusCode::OK,
         Json(json!({
             "success": true,
             "message": "Trunks reordered successfully",
             "trunks": *trunks
         })),
-    )
+    ))
+}
+
+/// State of each trunk sent OPTIONS: up, degraded or down, with its last
+/// probe
+pub async fn trunk_health(State(state): State<TrunkHealthState>) -> ApiResult {
+    let Some(health) = state else {
+        return Err(ApiError::not_configured(
+            "Trunk health monitoring is not configured",
+        ));
+    };
+    let statuses = health.statuses();
+    let down: Vec<&str> = statuses
+        .iter()
+        .filter(|s| s.state == TrunkState::Down)
+        .map(|s| s.trunk.as_str())
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "trunks": statuses,
+            "down": down,
+            "interval_seconds": health.config().interval_seconds
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::trunk_health::TrunkHealthConfig;
+
+    #[tokio::test]
+    async fn test_trunk_health() {
+        let health = TrunkHealth::new(TrunkHealthConfig {
+            down_after: 1,
+            ..Default::default()
+        });
+        health.record(
+            "carrier-a",
+            Ok(("200 OK".to_string(), std::time::Duration::from_millis(12))),
+        );
+        health.record("carrier-b", Err(anyhow::anyhow!("No answer")));
+
+        let (status, response) = trunk_health(State(Some(health))).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["trunks"][0]["state"], "up");
+        assert_eq!(response.0["trunks"][0]["last_rtt_ms"], 12);
+        assert_eq!(response.0["trunks"][1]["state"], "down");
+        assert_eq!(response.0["down"], json!(["carrier-b"]));
+
+        let error = trunk_health(State(None)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
 }
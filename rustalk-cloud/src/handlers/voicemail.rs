@@ -1,21 +1,27 @@
 //! Voicemail management handlers
 
+use crate::error::{ApiError, ApiResult};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     Json,
 };
 use rustalk_core::voicemail::{VoicemailBox, VoicemailManager};
+use serde::Deserialize;
 use serde_json::{json, Value};
-use std::sync::Arc;
-use tokio::sync::RwLock;
 
-pub type VoicemailState = Arc<RwLock<VoicemailManager>>;
+/// Shared voicemail manager; clones see the same mailboxes
+pub type VoicemailState = VoicemailManager;
+
+/// PIN reset request
+#[derive(Debug, Deserialize)]
+pub struct PinRequest {
+    pub pin: String,
+}
 
 /// List all voicemail boxes
 pub async fn list_mailboxes(State(state): State<VoicemailState>) -> (StatusCode, Json<Value>) {
-    let manager = state.read().await;
-    let mailboxes = manager.list_mailboxes();
+    let mailboxes = state.list_mailboxes().await;
 
     (
         StatusCode::OK,
@@ -30,18 +36,11 @@ pub async fn list_mailboxes(State(state): State<VoicemailState>) -> (StatusCode,
 pub async fn get_mailbox(
     Path(mailbox_id): Path<String>,
     State(state): State<VoicemailState>,
-) -> (StatusCode, Json<Value>) {
-    let manager = state.read().await;
-
-    if let Some(mailbox) = manager.get_mailbox(&mailbox_id) {
-        (StatusCode::OK, Json(json!(mailbox)))
+) -> ApiResult {
+    if let Some(mailbox) = state.get_mailbox(&mailbox_id).await {
+        Ok((StatusCode::OK, Json(json!(mailbox))))
     } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "error": "Mailbox not found"
-            })),
-        )
+        Err(ApiError::not_found("Mailbox not found"))
     }
 }
 
@@ -49,25 +48,20 @@ pub async fn get_mailbox(
 pub async fn create_mailbox(
     State(state): State<VoicemailState>,
     Json(payload): Json<VoicemailBox>,
-) -> (StatusCode, Json<Value>) {
-    let mut manager = state.write().await;
-
-    match manager.add_mailbox(payload.clone()) {
-        Ok(_) => (
+) -> ApiResult {
+    match state.add_mailbox(payload.clone()).await {
+        Ok(_) => Ok((
             StatusCode::CREATED,
             Json(json!({
                 "success": true,
                 "message": "Mailbox created successfully",
                 "id": payload.id
             })),
-        ),
-        Err(e) => (
-            StatusCode::CONFLICT,
-            Json(json!({
-                "success": false,
-                "message": format!("Failed to create mailbox: {}", e)
-            })),
-        ),
+        )),
+        Err(e) => Err(ApiError::conflict(format!(
+            "Failed to create mailbox: {}",
+            e
+        ))),
     }
 }
 
@@ -75,31 +69,42 @@ pub async fn create_mailbox(
 pub async fn delete_mailbox(
     Path(mailbox_id): Path<String>,
     State(state): State<VoicemailState>,
-) -> (StatusCode, Json<Value>) {
-    let mut manager = state.write().await;
-
-    match manager.remove_mailbox(&mailbox_id) {
-        Ok(true) => (
+) -> ApiResult {
+    match state.remove_mailbox(&mailbox_id).await {
+        Ok(true) => Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "Mailbox deleted successfully"
             })),
-        ),
-        Ok(false) => (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Mailbox not found"
-            })),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+        Ok(false) => Err(ApiError::not_found("Mailbox not found")),
+        Err(e) => Err(ApiError::internal(format!(
+            "Failed to delete mailbox: {}",
+            e
+        ))),
+    }
+}
+
+/// Reset the PIN for a mailbox
+pub async fn reset_pin(
+    Path(mailbox_id): Path<String>,
+    State(state): State<VoicemailState>,
+    Json(payload): Json<PinRequest>,
+) -> ApiResult {
+    if state.get_mailbox(&mailbox_id).await.is_none() {
+        return Err(ApiError::not_found("Mailbox not found"));
+    }
+
+    match state.set_pin(&mailbox_id, &payload.pin).await {
+        Ok(_) => Ok((
+            StatusCode::OK,
             Json(json!({
-                "success": false,
-                "message": format!("Failed to delete mailbox: {}", e)
+                "success": true,
+                "message": "PIN reset successfully"
             })),
-        ),
+        )),
+        Err(e) => Err(ApiError::bad_request(format!("Failed to reset PIN: {}", e))),
     }
 }
 
@@ -108,9 +113,7 @@ pub async fn get_messages(
     Path(mailbox_id): Path<String>,
     State(state): State<VoicemailState>,
 ) -> (StatusCode, Json<Value>) {
-    let manager = state.read().await;
-
-    let messages = manager.get_messages(&mailbox_id, true);
+    let messages = state.get_messages(&mailbox_id, true).await;
 
     (
         StatusCode::OK,
@@ -127,59 +130,48 @@ pub async fn get_mwi_status(
     Path(mailbox_id): Path<String>,
     State(state): State<VoicemailState>,
 ) -> (StatusCode, Json<Value>) {
-    let manager = state.read().await;
-    let status = manager.get_mwi_status(&mailbox_id);
+    let status = state.get_mwi_status(&mailbox_id).await;
 
     (StatusCode::OK, Json(json!(status)))
 }
 
 /// Mark a message as read
 pub async fn mark_message_read(
-    Path((mailbox_id, message_id)): Path<(String, String)>,
+    Path((_mailbox_id, message_id)): Path<(String, String)>,
     State(state): State<VoicemailState>,
-) -> (StatusCode, Json<Value>) {
-    let mut manager = state.write().await;
-
-    match manager.mark_message_read(&message_id) {
-        Ok(_) => (
+) -> ApiResult {
+    match state.mark_message_read(&message_id).await {
+        Ok(_) => Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "Message marked as read"
             })),
-        ),
-        Err(e) => (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": format!("Failed to mark message as read: {}", e)
-            })),
-        ),
+        )),
+        Err(e) => Err(ApiError::not_found(format!(
+            "Failed to mark message as read: {}",
+            e
+        ))),
     }
 }
 
 /// Delete a voicemail message
 pub async fn delete_message(
-    Path((mailbox_id, message_id)): Path<(String, String)>,
+    Path((_mailbox_id, message_id)): Path<(String, String)>,
     State(state): State<VoicemailState>,
-) -> (StatusCode, Json<Value>) {
-    let mut manager = state.write().await;
-
-    match manager.delete_message(&message_id) {
-        Ok(_) => (
+) -> ApiResult {
+    match state.delete_message(&message_id).await {
+        Ok(_) => Ok((
             StatusCode::OK,
             Json(json!({
                 "success": true,
                 "message": "Message deleted successfully"
             })),
-        ),
-        Err(e) => (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": format!("Failed to delete message: {}", e)
-            })),
-        ),
+        )),
+        Err(e) => Err(ApiError::not_found(format!(
+            "Failed to delete message: {}",
+            e
+        ))),
     }
 }
 
@@ -191,7 +183,7 @@ mod tests {
     #[tokio::test]
     async fn test_list_mailboxes() {
         let manager = VoicemailManager::new("/tmp/voicemail_test");
-        let state = Arc::new(RwLock::new(manager));
+        let state = manager;
 
         let (status, response) = list_mailboxes(State(state)).await;
         assert_eq!(status, StatusCode::OK);
@@ -203,7 +195,7 @@ mod tests {
     #[tokio::test]
     async fn test_create_mailbox() {
         let manager = VoicemailManager::new("/tmp/voicemail_test");
-        let state = Arc::new(RwLock::new(manager));
+        let state = manager;
 
         let mailbox = VoicemailBox {
             id: "test_mailbox".to_string(),
@@ -213,14 +205,16 @@ mod tests {
             ..Default::default()
         };
 
-        let (status, response) = create_mailbox(State(state.clone()), Json(mailbox)).await;
+        let (status, response) = create_mailbox(State(state.clone()), Json(mailbox))
+            .await
+            .unwrap();
         assert_eq!(status, StatusCode::CREATED);
         assert!(response.0["success"].as_bool().unwrap());
     }
 
     #[tokio::test]
     async fn test_get_mwi_status() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -230,9 +224,9 @@ mod tests {
             ..Default::default()
         };
 
-        manager.add_mailbox(mailbox).unwrap();
+        manager.add_mailbox(mailbox).await.unwrap();
 
-        let state = Arc::new(RwLock::new(manager));
+        let state = manager;
 
         let (status, response) = get_mwi_status(Path("1001".to_string()), State(state)).await;
         assert_eq!(status, StatusCode::OK);
@@ -241,4 +235,53 @@ mod tests {
         assert_eq!(value["new_messages"], 0);
         assert_eq!(value["old_messages"], 0);
     }
+
+    #[tokio::test]
+    async fn test_reset_pin() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
+        manager
+            .add_mailbox(VoicemailBox {
+                id: "2001".to_string(),
+                extension: "2001".to_string(),
+                name: "Carol".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let state = manager;
+
+        let (status, _) = reset_pin(
+            Path("2001".to_string()),
+            State(state.clone()),
+            Json(PinRequest {
+                pin: "4821".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.verify_pin("2001", "4821").await);
+
+        let error = reset_pin(
+            Path("2001".to_string()),
+            State(state.clone()),
+            Json(PinRequest {
+                pin: "1".to_string(),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+
+        let error = reset_pin(
+            Path("missing".to_string()),
+            State(state),
+            Json(PinRequest {
+                pin: "4821".to_string(),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -0,0 +1,134 @@
+//! Outbound webhook handlers
+
+use crate::error::{ApiError, ApiResult};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rustalk_core::webhooks::{sample_event, WebhookDispatcher};
+use serde_json::{json, Value};
+
+/// The webhook dispatcher, when webhooks are configured
+pub type WebhooksState = Option<WebhookDispatcher>;
+
+fn not_configured() -> ApiResult {
+    Err(ApiError::not_configured("Webhooks are not configured"))
+}
+
+fn unknown_webhook(name: &str) -> ApiResult {
+    Err(ApiError::not_found(format!("Unknown webhook '{}'", name)))
+}
+
+/// List webhook endpoints
+///
+/// Request headers are left out as they usually carry secrets.
+pub async fn list_webhooks(State(state): State<WebhooksState>) -> ApiResult {
+    let Some(dispatcher) = state else {
+        return not_configured();
+    };
+    let webhooks: Vec<Value> = dispatcher
+        .endpoints()
+        .iter()
+        .map(|endpoint| {
+            json!({
+                "name": endpoint.name,
+                "url": endpoint.url,
+                "events": endpoint.events,
+                "format": endpoint.format,
+                "fields": endpoint.fields,
+                "certificate_warnings": endpoint.certificate_warnings,
+                "pdd_alerts": endpoint.pdd_alerts,
+                "quality_alerts": endpoint.quality_alerts,
+            })
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "webhooks": webhooks,
+            "total": webhooks.len()
+        })),
+    ))
+}
+
+/// Payload a webhook would receive for a sample ringing call, without
+/// sending it
+pub async fn preview_webhook(
+    State(state): State<WebhooksState>,
+    Path(name): Path<String>,
+) -> ApiResult {
+    let Some(dispatcher) = state else {
+        return not_configured();
+    };
+    match dispatcher.endpoint(&name) {
+        Some(endpoint) => Ok((StatusCode::OK, Json(endpoint.render(&sample_event())))),
+        None => unknown_webhook(&name),
+    }
+}
+
+/// Send a sample ringing call to a webhook
+pub async fn test_webhook(
+    State(state): State<WebhooksState>,
+    Path(name): Path<String>,
+) -> ApiResult {
+    let Some(dispatcher) = state else {
+        return not_configured();
+    };
+    if dispatcher.endpoint(&name).is_none() {
+        return unknown_webhook(&name);
+    }
+    match dispatcher.test_delivery(&name).await {
+        Ok(delivery) => Ok((StatusCode::OK, Json(json!(delivery)))),
+        Err(e) => Err(ApiError::bad_gateway(format!(
+            "Test delivery failed: {:#}",
+            e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustalk_core::webhooks::WebhookConfig;
+
+    #[tokio::test]
+    async fn test_preview_and_test_delivery() {
+        let config: WebhookConfig = serde_json::from_value(json!({
+            "endpoints": [{
+                "name": "ifttt",
+                "url": "http://127.0.0.1:9/trigger",
+                "headers": { "X-Secret": "hidden" },
+                "fields": { "value1": "caller", "value2": "crm.customer_name" }
+            }]
+        }))
+        .unwrap();
+        let state = Some(WebhookDispatcher::new(config));
+
+        let (status, response) = list_webhooks(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["total"], 1);
+        assert!(response.0["webhooks"][0].get("headers").is_none());
+
+        let (status, response) = preview_webhook(State(state.clone()), Path("ifttt".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.0["value2"], "Example Customer");
+
+        let error = test_webhook(State(state.clone()), Path("zapier".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+
+        // Nothing listens on the discard port
+        let error = test_webhook(State(state), Path("ifttt".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_GATEWAY);
+
+        let error = list_webhooks(State(None)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
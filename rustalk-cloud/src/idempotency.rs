@@ -0,0 +1,397 @@
+//! Idempotency keys for mutation endpoints
+//!
+//! Automation tools retry POSTs when a connection drops, which can create a
+//! second extension or trunk when the first request did go through. A client
+//! that sends an `Idempotency-Key` header gets the stored response of the
+//! first request back for every retry with the same key, instead of the
+//! request running again.
+//!
+//! A key is bound to the method, path and body it was first used with;
+//! reusing it for a different request is refused. Server errors are not
+//! stored, and neither is a request the client hung up on before it
+//! finished, so a retry after either runs the request again.
+//!
+//! At most [`DEFAULT_MAX_ENTRIES`] keys are held; when full, the stored
+//! response closest to expiry makes way for a new key.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{ApiError, ErrorCode};
+
+/// Request header carrying the client's key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Response header set on replayed responses
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// How long responses are kept by default
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many keys are held by default
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+const MAX_KEY_LEN: usize = 255;
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Responses stored by idempotency key
+#[derive(Clone)]
+pub struct IdempotencyCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+struct Entry {
+    fingerprint: u64,
+    expires: Instant,
+    /// `None` while the first request is still running
+    response: Option<StoredResponse>,
+}
+
+#[derive(Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+enum Begin {
+    /// First use of the key; run the request
+    Proceed,
+    Replay(StoredResponse),
+    InFlight,
+    Mismatch,
+    /// Every key held is still in flight
+    Full,
+}
+
+/// A key whose first request is running; dropped without completing, as
+/// when the client disconnects, it frees the key for a retry
+struct Pending<'a> {
+    cache: &'a IdempotencyCache,
+    key: &'a str,
+    completed: bool,
+}
+
+impl Pending<'_> {
+    fn complete(mut self, response: StoredResponse) {
+        self.cache.complete(self.key, response);
+        self.completed = true;
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cache.abandon(self.key);
+        }
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+
+    /// Hold at most `max_entries` keys
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Number of keys currently held
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn begin(&self, key: &str, fingerprint: u64) -> Begin {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| e.expires > now);
+
+        match entries.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Begin::Mismatch,
+            Some(Entry {
+                response: Some(response),
+                ..
+            }) => Begin::Replay(response.clone()),
+            Some(_) => Begin::InFlight,
+            None => {
+                if entries.len() >= self.max_entries {
+                    let oldest = entries
+                        .iter()
+                        .filter(|(_, e)| e.response.is_some())
+                        .min_by_key(|(_, e)| e.expires)
+                        .map(|(key, _)| key.clone());
+                    match oldest {
+                        Some(oldest) => entries.remove(&oldest),
+                        None => return Begin::Full,
+                    };
+                }
+                entries.insert(
+                    key.to_string(),
+                    Entry {
+                        fingerprint,
+                        expires: now + self.ttl,
+                        response: None,
+                    },
+                );
+                Begin::Proceed
+            }
+        }
+    }
+
+    fn complete(&self, key: &str, response: StoredResponse) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    fn abandon(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL)
+    }
+}
+
+/// Middleware replaying POST responses for repeated idempotency keys
+///
+/// Requests without the header, and methods other than POST, pass through.
+pub async fn idempotency(
+    State(cache): State<IdempotencyCache>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        _ => {
+            return ApiError::bad_request(
+                "Idempotency-Key must be 1 to 255 visible ASCII characters",
+            )
+            .into_response()
+        }
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return ApiError::new(ErrorCode::PayloadTooLarge, "Request body too large").into_response();
+    };
+    let mut hasher = DefaultHasher::new();
+    parts.method.hash(&mut hasher);
+    parts.uri.hash(&mut hasher);
+    body.hash(&mut hasher);
+
+    let pending = match cache.begin(&key, hasher.finish()) {
+        Begin::Proceed => Pending {
+            cache: &cache,
+            key: &key,
+            completed: false,
+        },
+        Begin::Replay(stored) => {
+            let mut response = (stored.status, stored.headers, stored.body).into_response();
+            response
+                .headers_mut()
+                .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+            return response;
+        }
+        Begin::InFlight => {
+            return ApiError::new(
+                ErrorCode::RequestInProgress,
+                "A request with this Idempotency-Key is still in progress",
+            )
+            .into_response()
+        }
+        Begin::Mismatch => {
+            return ApiError::new(
+                ErrorCode::IdempotencyKeyReused,
+                "Idempotency-Key was already used for a different request",
+            )
+            .into_response()
+        }
+        Begin::Full => {
+            return ApiError::unavailable("Too many requests with an Idempotency-Key in progress")
+                .into_response()
+        }
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let (parts, body) = response.into_parts();
+    let Ok(body) = to_bytes(body, usize::MAX).await else {
+        return ApiError::internal("Failed to read response").into_response();
+    };
+    if !parts.status.is_server_error() {
+        pending.complete(StoredResponse {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+        });
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware::from_fn_with_state, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::Service;
+
+    fn app(cache: IdempotencyCache, created: Arc<AtomicUsize>) -> Router {
+        Router::new()
+            .route(
+                "/api/v1/extensions",
+                post(move |body: Bytes| async move {
+                    if body.as_ref() == b"hang" {
+                        std::future::pending::<()>().await;
+                    }
+                    let id = created.fetch_add(1, Ordering::SeqCst);
+                    let status = if body.as_ref() == b"fail" {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::CREATED
+                    };
+                    (status, format!("created {}", id))
+                }),
+            )
+            .layer(from_fn_with_state(cache, idempotency))
+    }
+
+    async fn send(app: &mut Router, key: Option<&str>, body: &'static str) -> Response {
+        let mut request = Request::post("/api/v1/extensions");
+        if let Some(key) = key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        app.call(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retries_replay_first_response() {
+        let cache = IdempotencyCache::default();
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut app = app(cache.clone(), created.clone());
+
+        let first = send(&mut app, Some("abc"), "1001").await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(text(first).await, "created 0");
+
+        let retry = send(&mut app, Some("abc"), "1001").await;
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(
+            retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(),
+            "true"
+        );
+        assert_eq!(text(retry).await, "created 0");
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+
+        // Same key, different body
+        let reused = send(&mut app, Some("abc"), "1002").await;
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // No key, no protection
+        send(&mut app, None, "1001").await;
+        send(&mut app, None, "1001").await;
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_not_stored() {
+        let cache = IdempotencyCache::default();
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut app = app(cache.clone(), created.clone());
+
+        let failed = send(&mut app, Some("retry-me"), "fail").await;
+        assert_eq!(failed.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(cache.is_empty());
+        let retry = send(&mut app, Some("retry-me"), "fail").await;
+        assert!(retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+
+        let invalid = send(&mut app, Some(""), "1001").await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_frees_key() {
+        let cache = IdempotencyCache::default();
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut app = app(cache.clone(), created.clone());
+
+        // The client hangs up while the handler is still running
+        let request = send(&mut app, Some("dropped"), "hang");
+        assert!(tokio::time::timeout(Duration::from_millis(50), request)
+            .await
+            .is_err());
+        assert!(cache.is_empty());
+
+        let retry = send(&mut app, Some("dropped"), "hang");
+        assert!(tokio::time::timeout(Duration::from_millis(50), retry)
+            .await
+            .is_err());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_entries_are_capped() {
+        let cache = IdempotencyCache::default().with_max_entries(2);
+        assert!(matches!(cache.begin("a", 1), Begin::Proceed));
+        assert!(matches!(cache.begin("b", 1), Begin::Proceed));
+        // Both still in flight: nothing can make way
+        assert!(matches!(cache.begin("c", 1), Begin::Full));
+
+        let stored = StoredResponse {
+            status: StatusCode::CREATED,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+        };
+        cache.complete("a", stored);
+        assert!(matches!(cache.begin("c", 1), Begin::Proceed));
+        assert_eq!(cache.len(), 2);
+        // "a" made way for "c"; its replay is gone
+        assert!(matches!(cache.begin("a", 1), Begin::Full));
+    }
+
+    #[test]
+    fn test_expired_keys_are_dropped() {
+        let cache = IdempotencyCache::new(Duration::ZERO);
+        assert!(matches!(cache.begin("k", 1), Begin::Proceed));
+        // Already expired, so the next request with the key runs again
+        assert!(matches!(cache.begin("k", 2), Begin::Proceed));
+    }
+}
@@ -2,14 +2,26 @@
 //!
 //! This crate provides a cloud-hosted REST API service for:
 //! - Call management and monitoring
+//! - Call logs ingested from SBC call detail records
 //! - Configuration management
 //! - Analytics and reporting
+//! - Access logs and per-route latency metrics
 //! - Webhook notifications
+//! - WebUI serving, from disk or embedded, with SPA fallback
+//! - Caching headers and pre-compressed WebUI files
 
+pub mod access_log;
 pub mod api;
+pub mod caching;
+pub mod call_log_store;
+pub mod error;
+pub mod etag;
 pub mod handlers;
+pub mod idempotency;
 pub mod models;
 pub mod ratings;
+pub mod trash;
+pub mod webui;
 
 pub use api::CloudApi;
 
@@ -1,9 +1,18 @@
 //! Data models for the Cloud API
 
+use rustalk_core::acl::Acl;
+use rustalk_core::b2bua::{CallAttempt, CallDecision};
+pub use rustalk_core::lcr::RateCard;
+use rustalk_core::nat::NatSettings;
+use rustalk_core::no_answer::NoAnswerAction;
+use rustalk_core::routing::{DestinationList, RerouteRule};
+use schemars::gen::SchemaSettings;
+use schemars::schema::{Metadata, RootSchema, SchemaObject};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Call information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CallInfo {
     pub id: String,
     pub from: String,
@@ -13,7 +22,7 @@ pub struct CallInfo {
     pub duration: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum CallStatus {
     Ringing,
@@ -23,14 +32,14 @@ pub enum CallStatus {
 }
 
 /// Configuration update request
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ConfigUpdate {
     pub key: String,
     pub value: serde_json::Value,
 }
 
 /// System statistics
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Stats {
     pub active_calls: usize,
     pub total_calls_today: usize,
@@ -40,7 +49,7 @@ pub struct Stats {
 }
 
 /// Call log entry with detailed information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CallLog {
     pub id: String,
     pub call_id: String,
@@ -57,10 +66,13 @@ pub struct CallLog {
     pub b_leg_codec: Option<String>,
     pub recording_path: Option<String>,
     pub cost: Option<f64>,
+    /// Account code the call is billed to
+    #[serde(default)]
+    pub account_code: Option<String>,
 }
 
 /// Detailed call log with SIP session info and charges
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CallLogDetail {
     #[serde(flatten)]
     pub log: CallLog,
@@ -69,10 +81,16 @@ pub struct CallLogDetail {
     pub to_tag: Option<String>,
     pub charge_breakdown: Option<Vec<ChargeItem>>,
     pub total_cost: Option<f64>,
+    /// ACL, route, trunk and response decisions, oldest first
+    #[serde(default)]
+    pub decision_trail: Vec<CallDecision>,
+    /// Destinations the call was tried at, in order
+    #[serde(default)]
+    pub attempts: Vec<CallAttempt>,
 }
 
 /// Individual charge item for a call
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ChargeItem {
     pub description: String,
     pub rate: f64,
@@ -81,25 +99,8 @@ pub struct ChargeItem {
     pub amount: f64,
 }
 
-/// Rate card for call charging
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RateCard {
-    pub id: String,
-    pub name: String,
-    pub description: Option<String>,
-    pub prefix: String,
-    pub rate_per_minute: f64,
-    pub connection_fee: f64,
-    pub minimum_charge_seconds: u32,
-    pub billing_increment_seconds: u32,
-    pub currency: String,
-    pub effective_date: i64,
-    pub end_date: Option<i64>,
-    pub active: bool,
-}
-
 /// Request to import rates
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RateImportRequest {
     pub format: String, // "json" or "csv"
     pub data: String,   // Base64 encoded or raw data
@@ -107,7 +108,7 @@ pub struct RateImportRequest {
 }
 
 /// Response from rate import
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RateImportResponse {
     pub success: bool,
     pub imported_count: usize,
@@ -115,7 +116,7 @@ pub struct RateImportResponse {
 }
 
 /// Request to export call logs
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CallLogExportRequest {
     pub format: String, // "json", "csv", or "pdf"
     pub start_date: Option<i64>,
@@ -123,8 +124,18 @@ pub struct CallLogExportRequest {
     pub include_charges: bool,
 }
 
+/// Call costs for one account code
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AccountCodeCost {
+    /// `None` for calls placed without an account code
+    pub account_code: Option<String>,
+    pub calls: usize,
+    pub duration_seconds: u64,
+    pub total_cost: f64,
+}
+
 /// Paginated call log list
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CallLogList {
     pub logs: Vec<CallLog>,
     pub total: usize,
@@ -133,7 +144,7 @@ pub struct CallLogList {
 }
 
 /// DID (Direct Inward Dialing) configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Did {
     pub id: String,
     pub number: String,
@@ -144,7 +155,7 @@ pub struct Did {
 }
 
 /// Endpoint/Extension configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Extension {
     pub id: String,
     pub extension: String,
@@ -153,10 +164,37 @@ pub struct Extension {
     pub enabled: bool,
     pub voicemail_enabled: bool,
     pub priority: u32,
+    /// Class of service profile (calling permissions)
+    #[serde(default)]
+    pub class_of_service: Option<String>,
+    /// Outbound calls need a dialing PIN (lobby and common-area phones)
+    #[serde(default)]
+    pub require_dial_pin: bool,
+    /// Announce or screen incoming calls
+    #[serde(default)]
+    pub screening: ScreeningMode,
+    /// Seconds to ring before the no-answer action applies
+    #[serde(default)]
+    pub ring_seconds: Option<u32>,
+    /// What happens when nobody answers within the ring time
+    #[serde(default)]
+    pub no_answer: Option<NoAnswerAction>,
+    /// Tenant (SIP domain) whose extension quota it counts against
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreeningMode {
+    #[default]
+    Off,
+    Announce, // Announce the caller, then connect
+    Screen,   // Announce, then 1 to accept or 2 for voicemail
 }
 
 /// SIP Trunk configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Trunk {
     pub id: String,
     pub name: String,
@@ -167,10 +205,16 @@ pub struct Trunk {
     pub password: Option<String>,
     pub enabled: bool,
     pub priority: u32,
+    /// SIP compatibility profile applied to requests sent to the trunk
+    #[serde(default)]
+    pub quirks: Option<String>,
+    /// Tenant (SIP domain) whose trunk quota it counts against
+    #[serde(default)]
+    pub tenant: Option<String>,
 }
 
 /// Ring Group configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RingGroup {
     pub id: String,
     pub name: String,
@@ -180,9 +224,13 @@ pub struct RingGroup {
     pub timeout_seconds: u32,
     pub enabled: bool,
     pub priority: u32,
+    /// Schedule the group rings during; calls outside it fall through to
+    /// the next route
+    #[serde(default)]
+    pub schedule: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum RingStrategy {
     Simultaneous, // Ring all extensions at once
@@ -190,8 +238,35 @@ pub enum RingStrategy {
     RoundRobin,   // Distribute calls evenly
 }
 
+/// Extension group (department or site)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExtensionGroup {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub kind: GroupKind,
+    pub description: Option<String>,
+    /// Member extension numbers
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// Members may pick up each other's ringing calls
+    #[serde(default)]
+    pub pickup: bool,
+    /// The group can be paged as a whole
+    #[serde(default)]
+    pub paging: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupKind {
+    #[default]
+    Department,
+    Site,
+}
+
 /// Route/Dialplan configuration with advanced conditions
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Route {
     pub id: String,
     pub name: String,
@@ -206,22 +281,28 @@ pub struct Route {
     pub action: RouteAction,
     /// Whether to continue processing more routes after this one matches
     pub continue_on_match: bool,
+    /// Alternate destinations for calls that fail with particular responses
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<RerouteRule>,
 }
 
 /// Destination type for a route
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", content = "value")]
 pub enum RouteDestination {
-    Extension(String), // Route to specific extension
-    Trunk(String),     // Route to trunk
-    RingGroup(String), // Route to ring group
-    Voicemail(String), // Send to voicemail
-    Hangup,            // Hangup the call
-    Custom(String),    // Custom destination string
+    Extension(String),                // Route to specific extension
+    Trunk(String),                    // Route to trunk
+    RingGroup(String),                // Route to ring group
+    Voicemail(String),                // Send to voicemail
+    Hangup,                           // Hangup the call
+    Custom(String),                   // Custom destination string
+    Enum(String),                     // ENUM-mapped SIP URI, else this trunk
+    LeastCost(Vec<String>),           // Cheapest of these trunks
+    DestinationList(DestinationList), // Each in turn until one answers
 }
 
 /// Action to perform when route matches
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum RouteAction {
     Accept,   // Accept and route the call
@@ -230,7 +311,7 @@ pub enum RouteAction {
 }
 
 /// Routing conditions that must match
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum RouteCondition {
     /// Time-based condition
@@ -243,10 +324,18 @@ pub enum RouteCondition {
     CallerId(CallerIdCondition),
     /// Called number (destination) pattern match
     Destination(DestinationCondition),
+    /// Caller belongs to an extension group
+    CallerGroup(CallerGroupCondition),
+    /// Named schedule is open (or closed, when negated)
+    Schedule(ScheduleCondition),
+    /// Destination has an ENUM mapping (or none, when negated)
+    Enum(EnumCondition),
+    /// Call is to a SIP domain or its aliases (or elsewhere, when negated)
+    Domain(DomainCondition),
 }
 
 /// Time of day condition (in 24-hour format)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TimeCondition {
     /// Start time (HH:MM format, e.g., "09:00")
     pub start_time: String,
@@ -255,14 +344,14 @@ pub struct TimeCondition {
 }
 
 /// Day of week condition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DayOfWeekCondition {
     /// Days when this route is active (1=Monday, 7=Sunday)
     pub days: Vec<u8>,
 }
 
 /// Date range condition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DateRangeCondition {
     /// Start date (ISO 8601: YYYY-MM-DD)
     pub start_date: String,
@@ -271,7 +360,7 @@ pub struct DateRangeCondition {
 }
 
 /// Caller ID filtering condition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CallerIdCondition {
     /// Pattern to match caller ID (regex)
     pub pattern: String,
@@ -280,7 +369,7 @@ pub struct CallerIdCondition {
 }
 
 /// Destination number filtering condition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DestinationCondition {
     /// Pattern to match destination (regex)
     pub pattern: String,
@@ -288,8 +377,45 @@ pub struct DestinationCondition {
     pub negate: bool,
 }
 
+/// Extension group membership condition
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallerGroupCondition {
+    /// Group ID the caller must belong to
+    pub group: String,
+    /// Whether to invert the match
+    pub negate: bool,
+}
+
+/// Schedule condition
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScheduleCondition {
+    /// Schedule ID
+    pub schedule: String,
+    /// Match while the schedule is closed instead
+    #[serde(default)]
+    pub negate: bool,
+}
+
+/// ENUM mapping condition
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EnumCondition {
+    /// Match destinations without a mapping instead
+    #[serde(default)]
+    pub negate: bool,
+}
+
+/// SIP domain condition
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DomainCondition {
+    /// Canonical domain; calls to its aliases match too
+    pub domain: String,
+    /// Match calls to other domains instead
+    #[serde(default)]
+    pub negate: bool,
+}
+
 /// SIP Profile configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SipProfile {
     pub id: String,
     pub name: String,
@@ -297,6 +423,74 @@ pub struct SipProfile {
     pub bind_address: String,
     pub bind_port: u16,
     pub domain: String,
+    /// Other domains served as `domain`, e.g. `voice.example.com`
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Digest realm REGISTERs in the domain are challenged with; `domain`
+    /// when unset
+    #[serde(default)]
+    pub realm: Option<String>,
     pub enabled: bool,
     pub priority: u32,
+    /// Named transport interface used for egress (multihomed deployments)
+    pub egress_interface: Option<String>,
+    /// Keep-alive interval, shortest registration and contact rewriting
+    /// for endpoints behind NAT
+    #[serde(default)]
+    pub nat: NatSettings,
+}
+
+/// Desired state of the provisioning resources, as applied by
+/// `POST /api/v1/apply`
+///
+/// Each section present replaces its collection: resources left out of it
+/// are deleted. Sections left out are not touched.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Vec<Extension>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trunks: Option<Vec<Trunk>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routes: Option<Vec<Route>>,
+    /// ACLs, matched by name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acls: Option<Vec<Acl>>,
+}
+
+/// JSON Schema (draft 7) of the resources managed through the API, one
+/// definition per model
+pub fn models_schema() -> RootSchema {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    generator.subschema_for::<AccountCodeCost>();
+    generator.subschema_for::<CallInfo>();
+    generator.subschema_for::<CallLog>();
+    generator.subschema_for::<CallLogDetail>();
+    generator.subschema_for::<CallLogExportRequest>();
+    generator.subschema_for::<CallLogList>();
+    generator.subschema_for::<ConfigUpdate>();
+    generator.subschema_for::<DesiredState>();
+    generator.subschema_for::<Did>();
+    generator.subschema_for::<Extension>();
+    generator.subschema_for::<ExtensionGroup>();
+    generator.subschema_for::<RateCard>();
+    generator.subschema_for::<RateImportRequest>();
+    generator.subschema_for::<RateImportResponse>();
+    generator.subschema_for::<RingGroup>();
+    generator.subschema_for::<Route>();
+    generator.subschema_for::<SipProfile>();
+    generator.subschema_for::<Stats>();
+    generator.subschema_for::<Trunk>();
+    RootSchema {
+        meta_schema: generator.settings().meta_schema.clone(),
+        schema: SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                title: Some("RusTalk Cloud API models".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        definitions: generator.take_definitions(),
+    }
 }
@@ -1,7 +1,8 @@
 //! Call rating engine
 
-use crate::models::{CallLog, ChargeItem, RateCard};
+use crate::models::{AccountCodeCost, CallLog, ChargeItem, RateCard};
 use anyhow::{Context, Result};
+use std::collections::BTreeMap;
 
 /// Rating engine for calculating call charges
 pub struct RatingEngine;
@@ -54,41 +55,31 @@ impl RatingEngine {
         Ok((charges, total))
     }
 
+    /// Total rated calls per account code, ordered by code
+    pub fn costs_by_account_code(call_logs: &[CallLog]) -> Vec<AccountCodeCost> {
+        let mut totals: BTreeMap<Option<&str>, AccountCodeCost> = BTreeMap::new();
+        for log in call_logs {
+            let code = log.account_code.as_deref();
+            let entry = totals.entry(code).or_insert_with(|| AccountCodeCost {
+                account_code: code.map(str::to_string),
+                calls: 0,
+                duration_seconds: 0,
+                total_cost: 0.0,
+            });
+            entry.calls += 1;
+            entry.duration_seconds += u64::from(log.duration_seconds.unwrap_or(0));
+            entry.total_cost += log.cost.unwrap_or(0.0);
+        }
+        totals.into_values().collect()
+    }
+
     /// Find the best matching rate card for a call
     fn find_matching_rate<'a>(
         call_log: &CallLog,
         rate_cards: &'a [RateCard],
     ) -> Result<&'a RateCard> {
-        // Extract destination number
-        let destination = &call_log.to_user;
-
-        // Find the longest matching prefix
-        let mut best_match: Option<&RateCard> = None;
-        let mut best_match_length = 0;
-
-        for rate in rate_cards {
-            if !rate.active {
-                continue;
-            }
-
-            // Check if rate is within effective date range
-            if rate.effective_date > call_log.start_time {
-                continue;
-            }
-            if let Some(end_date) = rate.end_date {
-                if end_date < call_log.start_time {
-                    continue;
-                }
-            }
-
-            // Check prefix match
-            if destination.starts_with(&rate.prefix) && rate.prefix.len() > best_match_length {
-                best_match = Some(rate);
-                best_match_length = rate.prefix.len();
-            }
-        }
-
-        best_match.context("No matching rate card found for destination")
+        rustalk_core::lcr::find_rate(rate_cards, &call_log.to_user, None, call_log.start_time)
+            .context("No matching rate card found for destination")
     }
 
     /// Calculate billable duration considering minimum charge and billing increments
@@ -97,16 +88,7 @@ impl RatingEngine {
         minimum_seconds: u32,
         increment_seconds: u32,
     ) -> u32 {
-        // Apply minimum charge
-        let duration = actual_seconds.max(minimum_seconds);
-
-        // Round up to next billing increment
-        if increment_seconds > 0 {
-            let increments = duration.div_ceil(increment_seconds);
-            increments * increment_seconds
-        } else {
-            duration
-        }
+        rustalk_core::lcr::billable_seconds(actual_seconds, minimum_seconds, increment_seconds)
     }
 }
 
@@ -144,6 +126,7 @@ mod tests {
             b_leg_codec: Some("PCMU".to_string()),
             recording_path: None,
             cost: None,
+            account_code: None,
         };
 
         let rate_cards = vec![
@@ -160,6 +143,7 @@ mod tests {
                 effective_date: 0,
                 end_date: None,
                 active: true,
+                trunk: None,
             },
             RateCard {
                 id: "2".to_string(),
@@ -174,6 +158,7 @@ mod tests {
                 effective_date: 0,
                 end_date: None,
                 active: true,
+                trunk: None,
             },
         ];
 
@@ -181,6 +166,41 @@ mod tests {
         assert_eq!(rate.prefix, "4477"); // Should match longest prefix
     }
 
+    #[test]
+    fn test_costs_by_account_code() {
+        let log = |id: &str, code: Option<&str>, duration: u32, cost: f64| CallLog {
+            id: id.to_string(),
+            call_id: format!("call-{}", id),
+            from_user: "1000".to_string(),
+            from_domain: "test.com".to_string(),
+            to_user: "447700900123".to_string(),
+            to_domain: "test.com".to_string(),
+            start_time: 1000,
+            end_time: None,
+            duration_seconds: Some(duration),
+            status: "completed".to_string(),
+            termination_reason: None,
+            a_leg_codec: None,
+            b_leg_codec: None,
+            recording_path: None,
+            cost: Some(cost),
+            account_code: code.map(str::to_string),
+        };
+        let logs = vec![
+            log("1", Some("2001"), 60, 0.10),
+            log("2", None, 30, 0.05),
+            log("3", Some("1001"), 120, 0.20),
+            log("4", Some("2001"), 90, 0.15),
+        ];
+
+        let report = RatingEngine::costs_by_account_code(&logs);
+        let codes: Vec<Option<&str>> = report.iter().map(|r| r.account_code.as_deref()).collect();
+        assert_eq!(codes, vec![None, Some("1001"), Some("2001")]);
+        assert_eq!(report[2].calls, 2);
+        assert_eq!(report[2].duration_seconds, 150);
+        assert!((report[2].total_cost - 0.25).abs() < 1e-9);
+    }
+
     #[test]
     fn test_calculate_charges() {
         let call_log = CallLog {
@@ -199,6 +219,7 @@ mod tests {
             b_leg_codec: Some("PCMU".to_string()),
             recording_path: None,
             cost: None,
+            account_code: None,
         };
 
         let rate_cards = vec![RateCard {
@@ -214,6 +235,7 @@ mod tests {
             effective_date: 0,
             end_date: None,
             active: true,
+            trunk: None,
         }];
 
         let (charges, total) = RatingEngine::calculate_charges(&call_log, &rate_cards).unwrap();
@@ -0,0 +1,135 @@
+//! Soft-deleted provisioning resources
+//!
+//! Deleting an extension, DID or trunk moves it here, disabled, instead of
+//! dropping it, so a mistaken delete of numbering configuration can be
+//! restored. Tombstones are purged for good once the retention window has
+//! passed.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How long deleted resources can be restored by default
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// A deleted resource awaiting purge
+#[derive(Debug, Clone, Serialize)]
+pub struct Tombstone<T> {
+    /// The resource as it was deleted, disabled
+    pub resource: T,
+    /// Whether the resource was enabled before it was deleted
+    pub was_enabled: bool,
+    pub deleted_at: DateTime<Utc>,
+    /// When the resource is purged and can no longer be restored
+    pub purge_at: DateTime<Utc>,
+}
+
+/// Deleted resources of one kind
+#[derive(Clone)]
+pub struct Trash<T> {
+    tombstones: Arc<RwLock<Vec<Tombstone<T>>>>,
+    retention: chrono::Duration,
+}
+
+impl<T: Clone> Trash<T> {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            tombstones: Arc::new(RwLock::new(Vec::new())),
+            retention: chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::MAX),
+        }
+    }
+
+    /// Keep a deleted resource until the retention window passes
+    pub async fn bury(&self, resource: T, was_enabled: bool) -> Tombstone<T> {
+        let now = Utc::now();
+        let tombstone = Tombstone {
+            resource,
+            was_enabled,
+            deleted_at: now,
+            purge_at: now
+                .checked_add_signed(self.retention)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        };
+        let mut tombstones = self.tombstones.write().await;
+        Self::purge(&mut tombstones, now);
+        tombstones.push(tombstone.clone());
+        tombstone
+    }
+
+    /// Resources that can still be restored, oldest deletion first
+    pub async fn list(&self) -> Vec<Tombstone<T>> {
+        let mut tombstones = self.tombstones.write().await;
+        Self::purge(&mut tombstones, Utc::now());
+        tombstones.clone()
+    }
+
+    /// The most recently deleted resource matching `matches`
+    pub async fn get<F>(&self, matches: F) -> Option<Tombstone<T>>
+    where
+        F: Fn(&T) -> bool,
+    {
+        self.list()
+            .await
+            .into_iter()
+            .rev()
+            .find(|t| matches(&t.resource))
+    }
+
+    /// Take the most recently deleted resource matching `matches` out of the
+    /// trash
+    pub async fn take<F>(&self, matches: F) -> Option<Tombstone<T>>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let mut tombstones = self.tombstones.write().await;
+        Self::purge(&mut tombstones, Utc::now());
+        let pos = tombstones.iter().rposition(|t| matches(&t.resource))?;
+        Some(tombstones.remove(pos))
+    }
+
+    /// Drop tombstones past their retention window, returning how many went
+    pub async fn purge_expired(&self) -> usize {
+        Self::purge(&mut *self.tombstones.write().await, Utc::now())
+    }
+
+    fn purge(tombstones: &mut Vec<Tombstone<T>>, now: DateTime<Utc>) -> usize {
+        let before = tombstones.len();
+        tombstones.retain(|t| t.purge_at > now);
+        before - tombstones.len()
+    }
+}
+
+impl<T: Clone> Default for Trash<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETENTION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_restore_and_purge() {
+        let trash = Trash::default();
+        trash.bury("1001".to_string(), true).await;
+        trash.bury("1002".to_string(), false).await;
+        trash.bury("1001".to_string(), false).await;
+        assert_eq!(trash.list().await.len(), 3);
+
+        // The latest deletion of a resource comes back first
+        let restored = trash.take(|r| r == "1001").await.unwrap();
+        assert!(!restored.was_enabled);
+        assert!(restored.purge_at > restored.deleted_at);
+        assert_eq!(trash.list().await.len(), 2);
+        assert!(trash.take(|r| r == "2000").await.is_none());
+
+        let expired = Trash::new(Duration::ZERO);
+        expired.bury("1001".to_string(), true).await;
+        assert!(expired.take(|r| r == "1001").await.is_none());
+        assert_eq!(expired.purge_expired().await, 0);
+        assert!(expired.list().await.is_empty());
+    }
+}
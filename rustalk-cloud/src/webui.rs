@@ -0,0 +1,347 @@
+//! Web UI serving with single-page app fallback
+//!
+//! The UI is served from a directory (`components.webui_path`) or, in
+//! builds with the `embed-webui` feature, from a bundle compiled into the
+//! binary so nothing needs to be installed alongside it. The UI routes on
+//! the client, so a refresh on `/extensions/1001` asks the server for a
+//! path that is not a file: any path without a file extension that is not
+//! an API path is answered with `index.html` and the UI takes it from
+//! there. Unknown API paths and missing assets stay 404s.
+//!
+//! Files carry an ETag and a Cache-Control chosen by [`crate::caching`],
+//! and are sent pre-compressed when the bundle has a `.br` or `.gz` copy
+//! the client accepts.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use std::path::{Component, Path, PathBuf};
+
+use crate::caching::{accepted_encodings, cache_control_for};
+use crate::error::ApiError;
+use crate::etag::{etag_bytes, is_not_modified};
+
+// `ASSETS`, generated by build.rs: empty unless built with `embed-webui`
+include!(concat!(env!("OUT_DIR"), "/webui_assets.rs"));
+
+const INDEX: &str = "index.html";
+
+/// Where the web UI is served from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebUi {
+    /// A built bundle on disk
+    Directory(PathBuf),
+    /// The bundle compiled into the binary
+    Embedded,
+}
+
+impl WebUi {
+    /// The directory when one is configured, else the embedded bundle if
+    /// this build has one
+    pub fn from_path(path: Option<String>) -> Option<Self> {
+        match path {
+            Some(path) => Some(Self::Directory(path.into())),
+            None if has_embedded() => Some(Self::Embedded),
+            None => None,
+        }
+    }
+
+    /// Contents of a file of the bundle
+    async fn read(&self, name: &str) -> Option<Vec<u8>> {
+        match self {
+            Self::Directory(dir) => rustalk_core::io::fs::read(dir.join(name)).await.ok(),
+            Self::Embedded => embedded_asset(name).map(<[u8]>::to_vec),
+        }
+    }
+
+    /// A file of the bundle, pre-compressed in the best accepted encoding
+    /// the bundle has it in
+    async fn load(&self, name: &str, headers: &HeaderMap) -> Option<Asset> {
+        for (encoding, suffix) in accepted_encodings(headers) {
+            if let Some(contents) = self.read(&format!("{}{}", name, suffix)).await {
+                return Some(Asset {
+                    contents,
+                    encoding: Some(encoding),
+                });
+            }
+        }
+        let contents = self.read(name).await?;
+        Some(Asset {
+            contents,
+            encoding: None,
+        })
+    }
+}
+
+/// Contents of a file as they are sent
+struct Asset {
+    contents: Vec<u8>,
+    /// Content-Encoding of a pre-compressed copy
+    encoding: Option<&'static str>,
+}
+
+/// Whether this binary was built with the web UI inside it
+pub fn has_embedded() -> bool {
+    !ASSETS.is_empty()
+}
+
+/// A file of the embedded bundle, e.g. `assets/index-3f2a.js`
+pub fn embedded_asset(name: &str) -> Option<&'static [u8]> {
+    ASSETS
+        .iter()
+        .find(|(asset, _)| *asset == name)
+        .map(|(_, contents)| *contents)
+}
+
+/// Paths that belong to the API rather than the UI
+fn is_api_path(path: &str) -> bool {
+    ["api", "health", "ready"]
+        .iter()
+        .any(|prefix| path == *prefix || path.starts_with(&format!("{}/", prefix)))
+}
+
+/// Requested file of the bundle, or `None` for paths that could leave it
+fn asset_name(path: &str) -> Option<String> {
+    let path = path.trim_start_matches('/');
+    if path.is_empty() {
+        return Some(INDEX.to_string());
+    }
+    let normal = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    normal.then(|| path.to_string())
+}
+
+fn asset_response(name: &str, asset: Asset, headers: &HeaderMap) -> Response {
+    let etag = etag_bytes(&asset.contents);
+    let mut response = if is_not_modified(headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mut response = asset.contents.into_response();
+        let mime = mime_guess::from_path(name).first_or_octet_stream();
+        if let Ok(value) = HeaderValue::from_str(mime.as_ref()) {
+            response.headers_mut().insert(header::CONTENT_TYPE, value);
+        }
+        if let Some(encoding) = asset.encoding {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        response
+    };
+    let response_headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control_for(name)),
+    );
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    response
+}
+
+/// Serve a file of the UI, or `index.html` for the UI's own routes
+pub async fn serve_webui(State(webui): State<WebUi>, uri: Uri, headers: HeaderMap) -> Response {
+    let path = uri.path().trim_start_matches('/');
+    if is_api_path(path) {
+        return ApiError::not_found(format!("No API endpoint at {}", uri.path())).into_response();
+    }
+    let Some(name) = asset_name(path) else {
+        return ApiError::not_found("Not found").into_response();
+    };
+    if let Some(asset) = webui.load(&name, &headers).await {
+        return asset_response(&name, asset, &headers);
+    }
+    // Client-side routes have no extension; missing assets do
+    let is_asset = name
+        .rsplit('/')
+        .next()
+        .is_some_and(|file| file.contains('.'));
+    if !is_asset {
+        if let Some(index) = webui.load(INDEX, &headers).await {
+            return asset_response(INDEX, index, &headers);
+        }
+    }
+    ApiError::not_found(format!("No file at {}", uri.path())).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+    use tower::Service;
+    use tower_http::compression::CompressionLayer;
+
+    async fn fetch(app: &mut Router, path: &str) -> (StatusCode, String, String) {
+        let response = app
+            .call(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8_lossy(&body).into_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_spa_fallback() {
+        let dir = std::env::temp_dir().join("rustalk_cloud_webui_test");
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "<div id=\"root\"></div>").unwrap();
+        std::fs::write(dir.join("assets/app.js"), "render()").unwrap();
+
+        let mut app = Router::new()
+            .route("/api/v1/stats", get(|| async { "stats" }))
+            .fallback_service(get(serve_webui).with_state(WebUi::Directory(dir)));
+
+        let (status, content_type, body) = fetch(&mut app, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(content_type.starts_with("text/html"));
+        assert_eq!(body, "<div id=\"root\"></div>");
+
+        let (_, content_type, body) = fetch(&mut app, "/assets/app.js").await;
+        assert!(content_type.contains("javascript"));
+        assert_eq!(body, "render()");
+
+        // A refresh on a client-side route gets the app
+        let (status, _, body) = fetch(&mut app, "/extensions/1001").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "<div id=\"root\"></div>");
+
+        assert_eq!(fetch(&mut app, "/api/v1/stats").await.2, "stats");
+        assert_eq!(
+            fetch(&mut app, "/api/v1/unknown").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            fetch(&mut app, "/assets/missing.js").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            fetch(&mut app, "/../etc/passwd").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_and_cached() {
+        let dir = std::env::temp_dir().join("rustalk_cloud_webui_cache_test");
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "<div id=\"root\"></div>").unwrap();
+        std::fs::write(dir.join("assets/app-3f2a.js"), "render()").unwrap();
+        std::fs::write(dir.join("assets/app-3f2a.js.gz"), "gzipped").unwrap();
+        let mut app =
+            Router::new().fallback_service(get(serve_webui).with_state(WebUi::Directory(dir)));
+        let mut get_with = |path: &str, headers: &[(header::HeaderName, &str)]| {
+            let mut request = Request::get(path);
+            for (name, value) in headers {
+                request = request.header(name, *value);
+            }
+            app.call(request.body(Body::empty()).unwrap())
+        };
+
+        let response = get_with(
+            "/assets/app-3f2a.js",
+            &[(header::ACCEPT_ENCODING, "gzip, br")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .contains("javascript"));
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        assert!(response.headers()[header::CACHE_CONTROL]
+            .to_str()
+            .unwrap()
+            .contains("immutable"));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"gzipped");
+
+        // Without gzip in Accept-Encoding the original is sent
+        let response = get_with("/assets/app-3f2a.js", &[]).await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = get_with("/assets/app-3f2a.js", &[(header::IF_NONE_MATCH, &etag)])
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+
+        let response = get_with("/extensions/1001", &[]).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+    }
+
+    #[tokio::test]
+    async fn test_compressed_on_the_fly() {
+        let dir = std::env::temp_dir().join("rustalk_cloud_webui_compression_test");
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(
+            dir.join("index.html"),
+            "<div id=\"root\"></div>".repeat(100),
+        )
+        .unwrap();
+        std::fs::write(dir.join("assets/app-3f2a.js"), "render()".repeat(100)).unwrap();
+        std::fs::write(dir.join("assets/app-3f2a.js.gz"), "gzipped").unwrap();
+        let listing = "{\"extension\":\"1001\"}".repeat(100);
+        let mut app = Router::new()
+            .route("/api/v1/extensions", get(move || async move { listing }))
+            .fallback_service(get(serve_webui).with_state(WebUi::Directory(dir)))
+            .layer(CompressionLayer::new());
+        let mut get_gzip = |path: &str| {
+            let request = Request::get(path).header(header::ACCEPT_ENCODING, "gzip");
+            app.call(request.body(Body::empty()).unwrap())
+        };
+
+        for path in ["/api/v1/extensions", "/"] {
+            let response = get_gzip(path).await.unwrap();
+            assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(&body[..2], &[0x1f, 0x8b]);
+            assert!(body.len() < 1000);
+        }
+
+        // A pre-compressed copy is not compressed again
+        let response = get_gzip("/assets/app-3f2a.js").await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"gzipped");
+    }
+
+    #[test]
+    fn test_webui_source() {
+        assert_eq!(
+            WebUi::from_path(Some("/srv/webui".to_string())),
+            Some(WebUi::Directory("/srv/webui".into()))
+        );
+        // Only builds with `embed-webui` fall back to the bundle
+        assert_eq!(WebUi::from_path(None).is_some(), has_embedded());
+        assert!(is_api_path("api"));
+        assert!(is_api_path("health"));
+        assert!(!is_api_path("apis"));
+        assert_eq!(asset_name("/"), Some(INDEX.to_string()));
+        assert_eq!(asset_name("assets/../../secret"), None);
+    }
+}
@@ -0,0 +1,4 @@
+// New migrations must be embedded by `sqlx::migrate!` without a clean build
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,8 @@
+-- Settings stored in the database and merged over the JSON configuration on
+-- every load, keyed by their dotted path, e.g. 'sip.domain'. Overrides are
+-- managed with `rustalk config set/get/unset`.
+CREATE TABLE config_overrides (
+    key TEXT PRIMARY KEY,
+    value JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
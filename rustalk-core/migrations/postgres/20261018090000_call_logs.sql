@@ -0,0 +1,23 @@
+-- Call detail records written by the B2BUA when a call ends. The columns
+-- reports filter on are broken out, including the users of the calling and
+-- called parties searches match on; the full record is kept in `record`.
+CREATE TABLE call_logs (
+    call_id TEXT PRIMARY KEY,
+    caller TEXT,
+    callee TEXT,
+    from_user TEXT NOT NULL DEFAULT '',
+    to_user TEXT NOT NULL DEFAULT '',
+    start_time TIMESTAMPTZ NOT NULL,
+    answer_time TIMESTAMPTZ,
+    end_time TIMESTAMPTZ NOT NULL,
+    duration_seconds BIGINT NOT NULL,
+    setup_ms BIGINT,
+    disposition TEXT NOT NULL,
+    hangup_cause TEXT,
+    caller_codec TEXT,
+    callee_codec TEXT,
+    account_code TEXT,
+    record JSONB NOT NULL
+);
+
+CREATE INDEX call_logs_start_time ON call_logs (start_time);
@@ -0,0 +1,9 @@
+-- Settings stored in the database and merged over the JSON configuration on
+-- every load, keyed by their dotted path, e.g. 'sip.domain'. Overrides are
+-- managed with `rustalk config set/get/unset`. Values are JSON text and
+-- times seconds since the epoch.
+CREATE TABLE config_overrides (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+);
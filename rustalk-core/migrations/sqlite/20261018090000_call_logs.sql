@@ -0,0 +1,24 @@
+-- Call detail records written by the B2BUA when a call ends. The columns
+-- reports filter on are broken out, including the users of the calling and
+-- called parties searches match on; the full record is kept in `record` as
+-- JSON text. Times are seconds since the epoch.
+CREATE TABLE call_logs (
+    call_id TEXT PRIMARY KEY,
+    caller TEXT,
+    callee TEXT,
+    from_user TEXT NOT NULL DEFAULT '',
+    to_user TEXT NOT NULL DEFAULT '',
+    start_time INTEGER NOT NULL,
+    answer_time INTEGER,
+    end_time INTEGER NOT NULL,
+    duration_seconds INTEGER NOT NULL,
+    setup_ms INTEGER,
+    disposition TEXT NOT NULL,
+    hangup_cause TEXT,
+    caller_codec TEXT,
+    callee_codec TEXT,
+    account_code TEXT,
+    record TEXT NOT NULL
+);
+
+CREATE INDEX call_logs_start_time ON call_logs (start_time);
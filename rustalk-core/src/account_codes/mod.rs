@@ -0,0 +1,174 @@
+//! Account codes for billing attribution
+//!
+//! Outbound calls can be tagged with an account code, either by dialing the
+//! feature code in front of the number (`*7<code>*<number>`) or by sending
+//! the code in an `X-Account-Code` header. The code is stored in the CDR so
+//! call costs can be grouped by client or matter. Calls of the configured
+//! classes are refused when no valid code is given.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::cos::CallClass;
+
+/// INVITE header carrying an account code
+pub const ACCOUNT_CODE_HEADER: &str = "X-Account-Code";
+
+/// A known account code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AccountCode {
+    pub code: String,
+    /// Client or matter the code bills to
+    pub description: Option<String>,
+}
+
+/// Account code configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AccountCodeConfig {
+    /// Prefix that starts an account code in the dialed number
+    #[serde(default = "default_feature_code")]
+    pub feature_code: String,
+    /// Call classes that must carry an account code
+    #[serde(default = "default_required_classes")]
+    pub required_classes: Vec<CallClass>,
+    /// Accepted codes; when empty any code of digits is accepted
+    #[serde(default)]
+    pub codes: Vec<AccountCode>,
+}
+
+impl Default for AccountCodeConfig {
+    fn default() -> Self {
+        Self {
+            feature_code: default_feature_code(),
+            required_classes: default_required_classes(),
+            codes: Vec::new(),
+        }
+    }
+}
+
+fn default_feature_code() -> String {
+    "*7".to_string()
+}
+
+fn default_required_classes() -> Vec<CallClass> {
+    vec![
+        CallClass::National,
+        CallClass::International,
+        CallClass::Premium,
+    ]
+}
+
+/// Dialed number with any account code prefix removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialedNumber {
+    pub number: String,
+    pub account_code: Option<String>,
+}
+
+/// Outcome of checking a call's account code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountCodeDecision {
+    /// Call may proceed, tagged with the code if one was given
+    Accept(Option<String>),
+    /// Call must be refused
+    Reject(String),
+}
+
+impl AccountCodeConfig {
+    /// Split a feature-code prefix (`*7<code>*<number>`) off a dialed number
+    pub fn parse_dialed(&self, dialed: &str) -> DialedNumber {
+        let parsed = dialed
+            .strip_prefix(self.feature_code.as_str())
+            .filter(|_| !self.feature_code.is_empty())
+            .and_then(|rest| rest.split_once('*'))
+            .filter(|(code, number)| !code.is_empty() && !number.is_empty());
+
+        match parsed {
+            Some((code, number)) => DialedNumber {
+                number: number.to_string(),
+                account_code: Some(code.to_string()),
+            },
+            None => DialedNumber {
+                number: dialed.to_string(),
+                account_code: None,
+            },
+        }
+    }
+
+    pub fn is_valid(&self, code: &str) -> bool {
+        if self.codes.is_empty() {
+            !code.is_empty() && code.chars().all(|c| c.is_ascii_digit())
+        } else {
+            self.codes.iter().any(|c| c.code == code)
+        }
+    }
+
+    /// Decide whether a call of `class` with `code` may proceed
+    pub fn check(&self, class: CallClass, code: Option<&str>) -> AccountCodeDecision {
+        match code {
+            Some(code) if self.is_valid(code) => {
+                AccountCodeDecision::Accept(Some(code.to_string()))
+            }
+            Some(code) => AccountCodeDecision::Reject(format!("unknown account code {}", code)),
+            None if self.required_classes.contains(&class) => {
+                AccountCodeDecision::Reject(format!("{} calls require an account code", class))
+            }
+            None => AccountCodeDecision::Accept(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dialed() {
+        let config = AccountCodeConfig::default();
+        assert_eq!(
+            config.parse_dialed("*71234*2125551234"),
+            DialedNumber {
+                number: "2125551234".to_string(),
+                account_code: Some("1234".to_string()),
+            }
+        );
+        assert_eq!(config.parse_dialed("2125551234").account_code, None);
+        // An incomplete prefix is left as dialed
+        assert_eq!(config.parse_dialed("*71234").number, "*71234");
+        assert_eq!(config.parse_dialed("*7*2125551234").account_code, None);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut config = AccountCodeConfig::default();
+        assert_eq!(
+            config.check(CallClass::National, None),
+            AccountCodeDecision::Reject("national calls require an account code".to_string())
+        );
+        assert_eq!(
+            config.check(CallClass::Internal, None),
+            AccountCodeDecision::Accept(None)
+        );
+        assert_eq!(
+            config.check(CallClass::National, Some("42")),
+            AccountCodeDecision::Accept(Some("42".to_string()))
+        );
+        assert!(matches!(
+            config.check(CallClass::Local, Some("abc")),
+            AccountCodeDecision::Reject(_)
+        ));
+
+        config.codes.push(AccountCode {
+            code: "1001".to_string(),
+            description: Some("Acme Corp".to_string()),
+        });
+        assert!(matches!(
+            config.check(CallClass::National, Some("42")),
+            AccountCodeDecision::Reject(_)
+        ));
+        assert_eq!(
+            config.check(CallClass::National, Some("1001")),
+            AccountCodeDecision::Accept(Some("1001".to_string()))
+        );
+    }
+}
@@ -5,12 +5,13 @@
 //! source IP addresses and CIDR ranges.
 
 use anyhow::{Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 
 /// ACL rule action
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum AclAction {
     /// Allow traffic from this IP/range
@@ -20,7 +21,7 @@ pub enum AclAction {
 }
 
 /// A single ACL rule
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AclRule {
     /// Rule name/description
     pub name: String,
@@ -33,7 +34,7 @@ pub struct AclRule {
 }
 
 /// Access Control List containing multiple rules
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Acl {
     /// ACL name
     pub name: String,
@@ -66,26 +67,46 @@ impl Acl {
         self.rules.sort_by_key(|r| r.priority);
     }
 
-    /// Check if an IP address is allowed by this ACL
-    pub fn is_allowed(&self, ip: IpAddr) -> Result<bool> {
+    /// Remove rules by name or CIDR, returning how many were removed
+    pub fn remove_rule(&mut self, name_or_cidr: &str) -> usize {
+        let len_before = self.rules.len();
+        self.rules
+            .retain(|r| r.name != name_or_cidr && r.cidr != name_or_cidr);
+        len_before - self.rules.len()
+    }
+
+    /// Find the rule that decides an IP address, if any
+    ///
+    /// Returns `None` when the ACL is disabled or no rule matches, in which
+    /// case the default policy applies.
+    pub fn matching_rule(&self, ip: IpAddr) -> Result<Option<&AclRule>> {
         if !self.enabled {
-            return Ok(matches!(self.default_policy, AclAction::Allow));
+            return Ok(None);
         }
 
         // Evaluate rules in priority order
         for rule in &self.rules {
             if matches_cidr(ip, &rule.cidr)? {
-                return Ok(matches!(rule.action, AclAction::Allow));
+                return Ok(Some(rule));
             }
         }
 
-        // No rule matched, use default policy
-        Ok(matches!(self.default_policy, AclAction::Allow))
+        Ok(None)
+    }
+
+    /// Check if an IP address is allowed by this ACL
+    pub fn is_allowed(&self, ip: IpAddr) -> Result<bool> {
+        let action = match self.matching_rule(ip)? {
+            Some(rule) => rule.action,
+            // No rule matched, use default policy
+            None => self.default_policy,
+        };
+        Ok(matches!(action, AclAction::Allow))
     }
 }
 
 /// Collection of ACLs
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct AclManager {
     /// Map of ACL name to ACL
     pub acls: Vec<Acl>,
@@ -109,6 +130,11 @@ impl AclManager {
         self.acls.iter().find(|a| a.name == name)
     }
 
+    /// Get a mutable ACL by name
+    pub fn get_acl_mut(&mut self, name: &str) -> Option<&mut Acl> {
+        self.acls.iter_mut().find(|a| a.name == name)
+    }
+
     /// Check if an IP is allowed by a specific ACL
     pub fn is_allowed(&self, acl_name: &str, ip: IpAddr) -> Result<bool> {
         let acl = self
@@ -131,7 +157,7 @@ impl AclManager {
 }
 
 /// Check if an IP address matches a CIDR range
-fn matches_cidr(ip: IpAddr, cidr: &str) -> Result<bool> {
+pub(crate) fn matches_cidr(ip: IpAddr, cidr: &str) -> Result<bool> {
     // Handle single IP addresses
     if !cidr.contains('/') {
         let target_ip = IpAddr::from_str(cidr).context(format!("Invalid IP address: {}", cidr))?;
@@ -384,5 +410,23 @@ mod tests {
         let ip = IpAddr::from_str("192.168.1.100").unwrap();
         // When disabled, should use default policy
         assert!(!acl.is_allowed(ip).unwrap());
+        assert!(acl.matching_rule(ip).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_matching_rule_and_remove() {
+        let mut manager = create_default_acls();
+        let acl = manager.get_acl_mut("rfc1918").unwrap();
+
+        let ip = IpAddr::from_str("172.16.5.1").unwrap();
+        assert_eq!(
+            acl.matching_rule(ip).unwrap().unwrap().cidr,
+            "172.16.0.0/12"
+        );
+
+        assert_eq!(acl.remove_rule("172.16.0.0/12"), 1);
+        assert_eq!(acl.remove_rule("172.16.0.0/12"), 0);
+        assert!(acl.matching_rule(ip).unwrap().is_none());
+        assert!(!manager.is_allowed("rfc1918", ip).unwrap());
     }
 }
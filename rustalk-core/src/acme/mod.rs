@@ -9,14 +9,12 @@ mod validation;
 
 pub use client::{AcmeClient, AcmeConfig};
 pub use storage::{CertificateInfo, CertificateStorage};
-pub use validation::{ChallengeType, ValidationResult};
+pub use validation::{ChallengeType, ChallengeValidator, ValidationResult};
 
-use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
 
 /// Certificate status information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CertificateStatus {
     /// Whether a valid certificate exists
     pub exists: bool,
@@ -53,15 +51,3 @@ pub struct CertificateOperationResult {
     /// Certificate info if successful
     pub certificate: Option<CertificateInfo>,
 }
-
-impl Default for CertificateStatus {
-    fn default() -> Self {
-        Self {
-            exists: false,
-            domains: Vec::new(),
-            expires_at: None,
-            days_until_expiry: None,
-            needs_renewal: false,
-        }
-    }
-}
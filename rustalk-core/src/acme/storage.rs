@@ -1,12 +1,14 @@
 //! Certificate storage and management
 
-use anyhow::{Context, Result};
-use rustls_pemfile::{certs, pkcs8_private_keys};
+use anyhow::Result;
+use rustls_pemfile::certs;
 use serde::{Deserialize, Serialize};
 use std::io::BufReader;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use tokio::fs;
-use tracing::{info, warn};
+use tracing::info;
+
+use crate::io;
 
 /// Information about a stored certificate
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,15 +68,8 @@ impl CertificateStorage {
 
         // Write new certificate and key
         fs::write(&cert_path, cert_pem).await?;
-        fs::write(&key_path, key_pem).await?;
-
-        // Set restrictive permissions on private key (Unix only)
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let permissions = std::fs::Permissions::from_mode(0o600);
-            std::fs::set_permissions(&key_path, permissions)?;
-        }
+        // Only the owner may read the private key (Unix only)
+        io::fs::write_private(&key_path, key_pem).await?;
 
         info!("Saved certificate for {} to {:?}", domain, cert_path);
         Ok(())
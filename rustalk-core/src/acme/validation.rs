@@ -83,8 +83,8 @@ impl ChallengeValidator {
         // http://<domain>/.well-known/acme-challenge/<token>
 
         info!(
-            "HTTP-01 challenge ready at http://{}/.well-known/acme-challenge/{}",
-            domain, token
+            "HTTP-01 challenge ready on port {} at http://{}/.well-known/acme-challenge/{}",
+            self.http_port, domain, token
         );
 
         Ok(())
@@ -0,0 +1,635 @@
+//! Call admission control (CAC)
+//!
+//! Enforces calls-per-second (CPS) and concurrent call limits per trunk.
+//! Counters live in a [`CounterStore`] so that, when every node points at the
+//! same shared store (Redis), trunk-wide caps hold even when traffic is
+//! load-balanced across the cluster.
+//!
+//! To avoid a round trip to the shared store for every call, each node claims
+//! a small local burst budget of CPS tokens per one-second window and spends it
+//! locally before claiming more.
+//!
+//! Part of a trunk's concurrent capacity can be reserved, on a schedule, for
+//! calls of given classes or to given prefixes (e.g. five channels kept free
+//! for emergency calls during business hours). Other calls are refused once
+//! only reserved channels remain; calls a reservation covers may use any
+//! free channel.
+
+mod store;
+
+pub use store::{CounterStore, LocalCounterStore};
+
+#[cfg(feature = "redis")]
+pub use store::RedisCounterStore;
+
+use crate::cos::CallClass;
+use crate::routing::{matcher::SystemTimeProvider, ConditionMatcher, RouteCondition, TimeProvider};
+use crate::sip::StatusCode;
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Admission control configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AdmissionConfig {
+    /// Where shared counters are stored
+    pub backend: CounterBackend,
+    /// Limits applied to trunks without an explicit entry
+    pub default_limits: Option<CallLimits>,
+    /// Per-trunk limits
+    pub trunks: Vec<TrunkLimits>,
+    /// Admit calls when the counter backend is unreachable
+    pub fail_open: bool,
+}
+
+/// Counter backend selection
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CounterBackend {
+    /// Counters are kept in memory on this node only
+    Local,
+    /// Counters are shared through Redis
+    Redis {
+        url: String,
+        /// Prefix for all keys written by RusTalk
+        key_prefix: Option<String>,
+    },
+}
+
+/// Call limits for a trunk
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallLimits {
+    /// Maximum new calls per second across the cluster
+    pub max_cps: Option<u32>,
+    /// Maximum concurrent calls across the cluster
+    pub max_concurrent: Option<u32>,
+    /// CPS tokens a node claims from the shared store at a time
+    pub local_burst: u32,
+    /// Concurrent capacity held back for particular calls
+    #[serde(default)]
+    pub reservations: Vec<CapacityReservation>,
+}
+
+/// Channels kept free for particular calls
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CapacityReservation {
+    pub name: String,
+    /// Channels other calls may not take
+    pub channels: u32,
+    /// Call classes that may use the reserved channels
+    #[serde(default)]
+    pub call_classes: Vec<CallClass>,
+    /// Dialed number prefixes that may use the reserved channels
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    /// Time, day of week and date range conditions; always in force when
+    /// empty
+    #[serde(default)]
+    pub schedule: Vec<RouteCondition>,
+}
+
+impl CapacityReservation {
+    /// Whether a call may use this reservation's channels
+    pub fn covers(&self, call: &CallDetails) -> bool {
+        call.call_class
+            .is_some_and(|class| self.call_classes.contains(&class))
+            || self.prefixes.iter().any(|prefix| {
+                call.number
+                    .trim_start_matches('+')
+                    .starts_with(prefix.trim_start_matches('+'))
+            })
+    }
+}
+
+/// What the admission controller knows about a call
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallDetails<'a> {
+    pub call_class: Option<CallClass>,
+    /// Dialed number
+    pub number: &'a str,
+}
+
+/// Limits bound to a named trunk
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TrunkLimits {
+    pub trunk: String,
+    #[serde(flatten)]
+    pub limits: CallLimits,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            backend: CounterBackend::Local,
+            default_limits: None,
+            trunks: Vec::new(),
+            fail_open: true,
+        }
+    }
+}
+
+impl AdmissionConfig {
+    /// Get the limits that apply to a trunk
+    pub fn limits_for(&self, trunk: &str) -> Option<&CallLimits> {
+        self.trunks
+            .iter()
+            .find(|t| t.trunk == trunk)
+            .map(|t| &t.limits)
+            .or(self.default_limits.as_ref())
+    }
+}
+
+/// Reason a call was refused admission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Trunk-wide calls-per-second cap reached
+    CpsExceeded { limit: u32 },
+    /// Trunk-wide concurrent call cap reached
+    ConcurrencyExceeded { limit: u32 },
+    /// Only channels reserved for other calls remain
+    CapacityReserved { limit: u32, reserved: u32 },
+    /// Counter backend unreachable and fail_open is disabled
+    BackendUnavailable,
+}
+
+impl RejectReason {
+    /// SIP status used when rejecting the call
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    /// Suggested Retry-After value in seconds
+    pub fn retry_after(&self) -> u32 {
+        match self {
+            RejectReason::CpsExceeded { .. } => 1,
+            RejectReason::ConcurrencyExceeded { .. } | RejectReason::CapacityReserved { .. } => 5,
+            RejectReason::BackendUnavailable => 10,
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::CpsExceeded { limit } => write!(f, "CPS limit of {} reached", limit),
+            RejectReason::ConcurrencyExceeded { limit } => {
+                write!(f, "concurrent call limit of {} reached", limit)
+            }
+            RejectReason::CapacityReserved { limit, reserved } => {
+                write!(f, "{} of {} channels are reserved", reserved, limit)
+            }
+            RejectReason::BackendUnavailable => write!(f, "admission backend unavailable"),
+        }
+    }
+}
+
+/// Outcome of an admission check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdmissionDecision {
+    Admit,
+    Reject(RejectReason),
+}
+
+impl AdmissionDecision {
+    pub fn is_admitted(&self) -> bool {
+        matches!(self, AdmissionDecision::Admit)
+    }
+}
+
+/// CPS tokens claimed by this node for the current one-second window
+#[derive(Debug, Clone, Copy)]
+struct LocalBudget {
+    window: i64,
+    remaining: u32,
+}
+
+/// Call admission controller
+pub struct AdmissionController {
+    config: AdmissionConfig,
+    store: Arc<dyn CounterStore>,
+    time_provider: Arc<dyn TimeProvider>,
+    budgets: Mutex<HashMap<String, LocalBudget>>,
+}
+
+impl AdmissionController {
+    /// Create a controller using the given counter store
+    pub fn new(config: AdmissionConfig, store: Arc<dyn CounterStore>) -> Self {
+        Self {
+            config,
+            store,
+            time_provider: Arc::new(SystemTimeProvider),
+            budgets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a controller with a custom time provider (for testing)
+    pub fn with_time_provider(mut self, time_provider: Arc<dyn TimeProvider>) -> Self {
+        self.time_provider = time_provider;
+        self
+    }
+
+    /// Create a controller, connecting to the configured backend
+    pub async fn from_config(config: AdmissionConfig) -> Result<Self> {
+        let store: Arc<dyn CounterStore> = match &config.backend {
+            CounterBackend::Local => Arc::new(LocalCounterStore::new()),
+            #[cfg(feature = "redis")]
+            CounterBackend::Redis { url, .. } => Arc::new(RedisCounterStore::connect(url).await?),
+            #[cfg(not(feature = "redis"))]
+            CounterBackend::Redis { .. } => {
+                anyhow::bail!("Redis admission backend requires the 'redis' feature")
+            }
+        };
+
+        Ok(Self::new(config, store))
+    }
+
+    /// Get the admission configuration
+    pub fn config(&self) -> &AdmissionConfig {
+        &self.config
+    }
+
+    /// Decide whether a new call on `trunk` may proceed
+    ///
+    /// An admitted call holds a concurrency slot until [`release`](Self::release)
+    /// is called for the same trunk. The call is not covered by any capacity
+    /// reservation.
+    pub async fn admit(&self, trunk: &str) -> AdmissionDecision {
+        self.admit_call(trunk, &CallDetails::default()).await
+    }
+
+    /// Like [`admit`](Self::admit), letting the call use the capacity
+    /// reserved for it
+    pub async fn admit_call(&self, trunk: &str, call: &CallDetails<'_>) -> AdmissionDecision {
+        let Some(limits) = self.config.limits_for(trunk).cloned() else {
+            return AdmissionDecision::Admit;
+        };
+
+        if let Some(max_cps) = limits.max_cps {
+            match self
+                .take_cps_token(trunk, max_cps, limits.local_burst)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!("Rejecting call on {}: CPS limit {}", trunk, max_cps);
+                    return AdmissionDecision::Reject(RejectReason::CpsExceeded { limit: max_cps });
+                }
+                Err(e) => return self.backend_failure(trunk, e),
+            }
+        }
+
+        if let Some(max_concurrent) = limits.max_concurrent {
+            let reserved = self.reserved_against(&limits, call);
+            let key = self.key(trunk, "active");
+            match self.store.incr_by(&key, 1, None).await {
+                Ok(active) if active > i64::from(max_concurrent.saturating_sub(reserved)) => {
+                    if let Err(e) = self.store.incr_by(&key, -1, None).await {
+                        warn!("Failed to roll back concurrency slot on {}: {}", trunk, e);
+                    }
+                    let reason = if active > i64::from(max_concurrent) {
+                        RejectReason::ConcurrencyExceeded {
+                            limit: max_concurrent,
+                        }
+                    } else {
+                        RejectReason::CapacityReserved {
+                            limit: max_concurrent,
+                            reserved,
+                        }
+                    };
+                    debug!("Rejecting call on {}: {}", trunk, reason);
+                    return AdmissionDecision::Reject(reason);
+                }
+                Ok(_) => {}
+                Err(e) => return self.backend_failure(trunk, e),
+            }
+        }
+
+        AdmissionDecision::Admit
+    }
+
+    /// Release the concurrency slot held by a call on `trunk`
+    pub async fn release(&self, trunk: &str) {
+        let Some(limits) = self.config.limits_for(trunk) else {
+            return;
+        };
+        if limits.max_concurrent.is_none() {
+            return;
+        }
+
+        let key = self.key(trunk, "active");
+        match self.store.incr_by(&key, -1, None).await {
+            Ok(active) if active < 0 => {
+                // Counter drifted (e.g. store restarted); clamp back to zero
+                let _ = self.store.incr_by(&key, -active, None).await;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to release concurrency slot on {}: {}", trunk, e),
+        }
+    }
+
+    /// Channels held back from `call`: every reservation in force now,
+    /// unless one of them covers the call, which may then use any channel
+    fn reserved_against(&self, limits: &CallLimits, call: &CallDetails) -> u32 {
+        if limits.reservations.is_empty() {
+            return 0;
+        }
+        let matcher = ConditionMatcher::with_time_provider(self.time_provider.clone());
+        let in_force: Vec<&CapacityReservation> = limits
+            .reservations
+            .iter()
+            .filter(|r| matcher.matches(&r.schedule, "", ""))
+            .collect();
+        if in_force.iter().any(|r| r.covers(call)) {
+            return 0;
+        }
+        in_force.iter().map(|r| r.channels).sum()
+    }
+
+    /// Take one CPS token, claiming a new local budget from the store if needed
+    ///
+    /// The budgets are not locked across the store round trip, so calls
+    /// racing for a new budget each claim their own and pool what they get.
+    async fn take_cps_token(&self, trunk: &str, max_cps: u32, burst: u32) -> Result<bool> {
+        let window = self.time_provider.now().timestamp();
+        if let Some(budget) = self.budgets.lock().unwrap().get_mut(trunk) {
+            if budget.window == window && budget.remaining > 0 {
+                budget.remaining -= 1;
+                return Ok(true);
+            }
+        }
+
+        let burst = burst.clamp(1, max_cps.max(1));
+        let key = self.key(trunk, &format!("cps:{}", window));
+        let total = self
+            .store
+            .incr_by(&key, i64::from(burst), Some(Duration::from_secs(2)))
+            .await?;
+
+        let claimed_before = total - i64::from(burst);
+        let granted = (i64::from(max_cps) - claimed_before).clamp(0, i64::from(burst)) as u32;
+
+        let mut budgets = self.budgets.lock().unwrap();
+        let budget = budgets.entry(trunk.to_string()).or_insert(LocalBudget {
+            window,
+            remaining: 0,
+        });
+        if budget.window != window {
+            // A claim for a window that has since passed spends only its own
+            // token
+            if budget.window > window {
+                return Ok(granted > 0);
+            }
+            *budget = LocalBudget {
+                window,
+                remaining: 0,
+            };
+        }
+        budget.remaining += granted;
+        if budget.remaining == 0 {
+            return Ok(false);
+        }
+        budget.remaining -= 1;
+        Ok(true)
+    }
+
+    fn backend_failure(&self, trunk: &str, error: anyhow::Error) -> AdmissionDecision {
+        warn!("Admission backend error for {}: {}", trunk, error);
+        if self.config.fail_open {
+            AdmissionDecision::Admit
+        } else {
+            AdmissionDecision::Reject(RejectReason::BackendUnavailable)
+        }
+    }
+
+    fn key(&self, trunk: &str, suffix: &str) -> String {
+        let prefix = match &self.config.backend {
+            CounterBackend::Redis {
+                key_prefix: Some(prefix),
+                ..
+            } => prefix.as_str(),
+            _ => "rustalk",
+        };
+        format!("{}:cac:{}:{}", prefix, trunk, suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    struct MockTimeProvider {
+        fixed_time: DateTime<Utc>,
+    }
+
+    impl TimeProvider for MockTimeProvider {
+        fn now(&self) -> DateTime<Utc> {
+            self.fixed_time
+        }
+    }
+
+    fn config(max_cps: Option<u32>, max_concurrent: Option<u32>, burst: u32) -> AdmissionConfig {
+        AdmissionConfig {
+            trunks: vec![TrunkLimits {
+                trunk: "pstn".to_string(),
+                limits: CallLimits {
+                    max_cps,
+                    max_concurrent,
+                    local_burst: burst,
+                    reservations: Vec::new(),
+                },
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn node(config: AdmissionConfig, store: Arc<dyn CounterStore>) -> AdmissionController {
+        let provider = Arc::new(MockTimeProvider {
+            fixed_time: Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap(),
+        });
+        AdmissionController::new(config, store).with_time_provider(provider)
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_trunk_admitted() {
+        let controller = node(config(Some(1), None, 1), Arc::new(LocalCounterStore::new()));
+        for _ in 0..10 {
+            assert!(controller.admit("other").await.is_admitted());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cps_limit_single_node() {
+        let controller = node(config(Some(5), None, 2), Arc::new(LocalCounterStore::new()));
+
+        let admitted = count_admitted(&controller, 10).await;
+        assert_eq!(admitted, 5);
+        assert_eq!(
+            controller.admit("pstn").await,
+            AdmissionDecision::Reject(RejectReason::CpsExceeded { limit: 5 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cps_limit_shared_across_nodes() {
+        let store: Arc<dyn CounterStore> = Arc::new(LocalCounterStore::new());
+        let node_a = node(config(Some(10), None, 3), store.clone());
+        let node_b = node(config(Some(10), None, 3), store);
+
+        let mut admitted = 0;
+        for _ in 0..10 {
+            if node_a.admit("pstn").await.is_admitted() {
+                admitted += 1;
+            }
+            if node_b.admit("pstn").await.is_admitted() {
+                admitted += 1;
+            }
+        }
+
+        // Local budgets may strand a few tokens but never exceed the cap
+        assert!(admitted <= 10);
+        assert!(admitted >= 8);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_and_release() {
+        let store: Arc<dyn CounterStore> = Arc::new(LocalCounterStore::new());
+        let node_a = node(config(None, Some(2), 1), store.clone());
+        let node_b = node(config(None, Some(2), 1), store);
+
+        assert!(node_a.admit("pstn").await.is_admitted());
+        assert!(node_b.admit("pstn").await.is_admitted());
+        assert_eq!(
+            node_a.admit("pstn").await,
+            AdmissionDecision::Reject(RejectReason::ConcurrencyExceeded { limit: 2 })
+        );
+
+        node_b.release("pstn").await;
+        assert!(node_a.admit("pstn").await.is_admitted());
+    }
+
+    #[tokio::test]
+    async fn test_default_limits_apply() {
+        let config = AdmissionConfig {
+            default_limits: Some(CallLimits {
+                max_cps: None,
+                max_concurrent: Some(1),
+                local_burst: 1,
+                reservations: Vec::new(),
+            }),
+            ..Default::default()
+        };
+        let controller = node(config, Arc::new(LocalCounterStore::new()));
+
+        assert!(controller.admit("any").await.is_admitted());
+        assert!(!controller.admit("any").await.is_admitted());
+    }
+
+    #[tokio::test]
+    async fn test_capacity_reservations() {
+        let mut config = config(None, Some(4), 1);
+        config.trunks[0].limits.reservations = serde_json::from_value(serde_json::json!([
+            {
+                "name": "emergency",
+                "channels": 2,
+                "call_classes": ["emergency"],
+                "schedule": [
+                    { "type": "DayOfWeek", "days": [1, 2, 3, 4, 5] },
+                    { "type": "Time", "start_time": "09:00", "end_time": "17:00" }
+                ]
+            },
+            { "name": "hotline", "channels": 1, "prefixes": ["+1800"] }
+        ]))
+        .unwrap();
+        let emergency = CallDetails {
+            call_class: Some(CallClass::Emergency),
+            number: "911",
+        };
+        let hotline = CallDetails {
+            call_class: Some(CallClass::National),
+            number: "18005551234",
+        };
+
+        // Monday morning: three channels are held back from general calls
+        let controller = node(config.clone(), Arc::new(LocalCounterStore::new()));
+        assert!(controller.admit("pstn").await.is_admitted());
+        assert_eq!(
+            controller.admit("pstn").await,
+            AdmissionDecision::Reject(RejectReason::CapacityReserved {
+                limit: 4,
+                reserved: 3
+            })
+        );
+        assert!(controller.admit_call("pstn", &hotline).await.is_admitted());
+        assert!(controller
+            .admit_call("pstn", &emergency)
+            .await
+            .is_admitted());
+        assert!(controller
+            .admit_call("pstn", &emergency)
+            .await
+            .is_admitted());
+        assert_eq!(
+            controller.admit_call("pstn", &emergency).await,
+            AdmissionDecision::Reject(RejectReason::ConcurrencyExceeded { limit: 4 })
+        );
+
+        // Saturday: only the hotline reservation is in force
+        let weekend = AdmissionController::new(config, Arc::new(LocalCounterStore::new()))
+            .with_time_provider(Arc::new(MockTimeProvider {
+                fixed_time: Utc.with_ymd_and_hms(2024, 1, 20, 10, 30, 0).unwrap(),
+            }));
+        assert_eq!(count_admitted(&weekend, 4).await, 3);
+    }
+
+    /// Store whose calls wait until two are in flight at once
+    struct RendezvousStore {
+        inner: LocalCounterStore,
+        barrier: tokio::sync::Barrier,
+    }
+
+    #[async_trait::async_trait]
+    impl CounterStore for RendezvousStore {
+        async fn incr_by(&self, key: &str, delta: i64, ttl: Option<Duration>) -> Result<i64> {
+            self.barrier.wait().await;
+            self.inner.incr_by(key, delta, ttl).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cps_claims_do_not_wait_on_each_other() {
+        let mut config = config(Some(5), None, 1);
+        let mut other = config.trunks[0].clone();
+        other.trunk = "sip-trunk".to_string();
+        config.trunks.push(other);
+        let store = Arc::new(RendezvousStore {
+            inner: LocalCounterStore::new(),
+            barrier: tokio::sync::Barrier::new(2),
+        });
+        let controller = node(config, store);
+
+        // Each claim is only answered once the other has reached the store
+        let (pstn, sip) = tokio::time::timeout(Duration::from_secs(1), async {
+            tokio::join!(controller.admit("pstn"), controller.admit("sip-trunk"))
+        })
+        .await
+        .expect("CPS claims were serialized");
+        assert!(pstn.is_admitted());
+        assert!(sip.is_admitted());
+    }
+
+    async fn count_admitted(controller: &AdmissionController, attempts: usize) -> usize {
+        let mut admitted = 0;
+        for _ in 0..attempts {
+            if controller.admit("pstn").await.is_admitted() {
+                admitted += 1;
+            }
+        }
+        admitted
+    }
+}
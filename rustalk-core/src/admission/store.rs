@@ -0,0 +1,153 @@
+//! Counter storage backends for call admission control
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// How often [`LocalCounterStore`] sweeps out expired windows
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shared counter storage used by the admission controller
+///
+/// All nodes in a cluster point at the same store so that trunk-wide limits
+/// hold regardless of which node a call lands on.
+#[async_trait::async_trait]
+pub trait CounterStore: Send + Sync {
+    /// Add `delta` to a counter and return the new value.
+    ///
+    /// When `ttl` is set the counter expires after that duration, which is
+    /// how per-second CPS windows clean themselves up.
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<Duration>) -> Result<i64>;
+}
+
+/// In-process counter store
+///
+/// Used for single-node deployments and as the fallback when no cluster
+/// backend is configured.
+#[derive(Debug, Default)]
+pub struct LocalCounterStore {
+    counters: Mutex<Counters>,
+}
+
+/// Counter values with the instant each expires, if it does
+#[derive(Debug, Default)]
+struct Counters {
+    values: HashMap<String, (i64, Option<Instant>)>,
+    swept_at: Option<Instant>,
+}
+
+impl LocalCounterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl CounterStore for LocalCounterStore {
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<Duration>) -> Result<i64> {
+        let now = Instant::now();
+        let mut counters = self.counters.lock().await;
+
+        // Drop expired windows now and then so the map doesn't grow without
+        // bound
+        if counters
+            .swept_at
+            .is_none_or(|at| now.duration_since(at) >= SWEEP_INTERVAL)
+        {
+            counters
+                .values
+                .retain(|_, (_, expires)| expires.is_none_or(|at| at > now));
+            counters.swept_at = Some(now);
+        }
+
+        let entry = counters
+            .values
+            .entry(key.to_string())
+            .or_insert((0, ttl.map(|ttl| now + ttl)));
+        // A window that expired since the last sweep starts over
+        if entry.1.is_some_and(|at| at <= now) {
+            *entry = (0, ttl.map(|ttl| now + ttl));
+        }
+        entry.0 += delta;
+
+        Ok(entry.0)
+    }
+}
+
+/// Redis-backed counter store shared by every node in the cluster
+#[cfg(feature = "redis")]
+pub struct RedisCounterStore {
+    conn: redis::aio::ConnectionManager,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCounterStore {
+    /// Connect to Redis at the given URL (e.g. `redis://10.0.0.5:6379/0`)
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = redis::aio::ConnectionManager::new(client).await?;
+        Ok(Self { conn })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait::async_trait]
+impl CounterStore for RedisCounterStore {
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<Duration>) -> Result<i64> {
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic().incr(key, delta);
+        if let Some(ttl) = ttl {
+            pipe.expire(key, ttl.as_secs().max(1) as i64).ignore();
+        }
+
+        let (value,): (i64,) = pipe.query_async(&mut conn).await?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_store_incr() {
+        let store = LocalCounterStore::new();
+        assert_eq!(store.incr_by("a", 1, None).await.unwrap(), 1);
+        assert_eq!(store.incr_by("a", 4, None).await.unwrap(), 5);
+        assert_eq!(store.incr_by("a", -2, None).await.unwrap(), 3);
+        assert_eq!(store.incr_by("b", 1, None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_local_store_expiry() {
+        let store = LocalCounterStore::new();
+        let ttl = Some(Duration::from_millis(10));
+        assert_eq!(store.incr_by("w", 3, ttl).await.unwrap(), 3);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.incr_by("w", 1, ttl).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_local_store_sweeps_expired_windows() {
+        let store = LocalCounterStore::new();
+        store
+            .incr_by("old", 1, Some(Duration::from_millis(10)))
+            .await
+            .unwrap();
+        store.incr_by("kept", 1, None).await.unwrap();
+
+        // Expired windows stay until the next sweep is due
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        store.incr_by("kept", 1, None).await.unwrap();
+        assert_eq!(store.counters.lock().await.values.len(), 2);
+
+        store.counters.lock().await.swept_at = None;
+        store.incr_by("kept", 1, None).await.unwrap();
+        let counters = store.counters.lock().await;
+        assert_eq!(counters.values.len(), 1);
+        assert_eq!(counters.values["kept"].0, 3);
+    }
+}
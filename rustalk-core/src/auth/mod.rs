@@ -5,9 +5,13 @@
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Most challenges outstanding at once; past this the oldest nonce is
+/// forgotten, so a flood of unanswered REGISTERs cannot grow the cache
+pub const MAX_OUTSTANDING_NONCES: usize = 10_000;
+
 /// Digest authentication challenge
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DigestChallenge {
@@ -37,12 +41,16 @@ pub struct AuthManager {
     realm: String,
     /// Nonce cache to track issued nonces and prevent replay attacks
     nonces: HashMap<String, NonceInfo>,
+    /// Nonces in the order they were issued, for evicting the oldest
+    issued: VecDeque<String>,
 }
 
 #[derive(Debug, Clone)]
 struct NonceInfo {
     timestamp: u64,
     used: bool,
+    /// Realm the nonce was issued in
+    realm: String,
 }
 
 impl AuthManager {
@@ -51,15 +59,24 @@ impl AuthManager {
         Self {
             realm: realm.into(),
             nonces: HashMap::new(),
+            issued: VecDeque::new(),
         }
     }
 
     /// Generate a new digest challenge
     pub fn generate_challenge(&mut self) -> DigestChallenge {
-        let nonce = self.generate_nonce();
+        let realm = self.realm.clone();
+        self.generate_challenge_in(&realm)
+    }
+
+    /// Generate a challenge in another realm, for a server answering for
+    /// several domains; responses are checked in the realm their nonce was
+    /// issued in
+    pub fn generate_challenge_in(&mut self, realm: &str) -> DigestChallenge {
+        let nonce = self.generate_nonce(realm);
 
         DigestChallenge {
-            realm: self.realm.clone(),
+            realm: realm.to_string(),
             nonce,
             algorithm: "MD5".to_string(),
             qop: Some("auth".to_string()),
@@ -67,7 +84,7 @@ impl AuthManager {
     }
 
     /// Generate a nonce value
-    fn generate_nonce(&mut self) -> String {
+    fn generate_nonce(&mut self, realm: &str) -> String {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -76,12 +93,24 @@ impl AuthManager {
         // Create a unique nonce using timestamp and random component
         let nonce = format!("{:x}{:x}", timestamp, rand::random::<u64>());
 
+        if self.nonces.len() >= MAX_OUTSTANDING_NONCES {
+            self.cleanup_nonces();
+        }
+        while self.nonces.len() >= MAX_OUTSTANDING_NONCES {
+            let Some(oldest) = self.issued.pop_front() else {
+                break;
+            };
+            self.nonces.remove(&oldest);
+        }
+
         // Store nonce info for validation
+        self.issued.push_back(nonce.clone());
         self.nonces.insert(
             nonce.clone(),
             NonceInfo {
                 timestamp,
                 used: false,
+                realm: realm.to_string(),
             },
         );
 
@@ -95,11 +124,34 @@ impl AuthManager {
         password: &str,
         method: &str,
     ) -> Result<bool> {
-        // Check if nonce is valid and not expired
-        let nonce_info = self
+        let realm = self
             .nonces
-            .get_mut(&response.nonce)
+            .get(&response.nonce)
+            .map(|info| info.realm.clone())
             .context("Invalid nonce")?;
+        self.consume_nonce(&response.nonce)?;
+
+        // Calculate expected response
+        let expected = self.calculate_response(
+            &response.username,
+            &realm,
+            password,
+            method,
+            &response.uri,
+            &response.nonce,
+            response.nc.as_deref(),
+            response.cnonce.as_deref(),
+            response.qop.as_deref(),
+        )?;
+
+        Ok(expected == response.response)
+    }
+
+    /// Check that a nonce was issued here, is fresh and has not been used,
+    /// then mark it used. For responses checked elsewhere, e.g. by RADIUS.
+    pub fn consume_nonce(&mut self, nonce: &str) -> Result<()> {
+        // Check if nonce is valid and not expired
+        let nonce_info = self.nonces.get_mut(nonce).context("Invalid nonce")?;
 
         // Check nonce age (valid for 5 minutes)
         let now = SystemTime::now()
@@ -116,26 +168,15 @@ impl AuthManager {
             anyhow::bail!("Nonce already used");
         }
         nonce_info.used = true;
-
-        // Calculate expected response
-        let expected = self.calculate_response(
-            &response.username,
-            password,
-            method,
-            &response.uri,
-            &response.nonce,
-            response.nc.as_deref(),
-            response.cnonce.as_deref(),
-            response.qop.as_deref(),
-        )?;
-
-        Ok(expected == response.response)
+        Ok(())
     }
 
     /// Calculate the digest response value
+    #[allow(clippy::too_many_arguments)]
     fn calculate_response(
         &self,
         username: &str,
+        realm: &str,
         password: &str,
         method: &str,
         uri: &str,
@@ -145,7 +186,7 @@ impl AuthManager {
         qop: Option<&str>,
     ) -> Result<String> {
         // HA1 = MD5(username:realm:password)
-        let ha1_input = format!("{}:{}:{}", username, self.realm, password);
+        let ha1_input = format!("{}:{}:{}", username, realm, password);
         let ha1 = format!("{:x}", md5::compute(ha1_input.as_bytes()));
 
         // HA2 = MD5(method:uri)
@@ -219,6 +260,8 @@ impl AuthManager {
             .as_secs();
 
         self.nonces.retain(|_, info| now - info.timestamp <= 300);
+        let nonces = &self.nonces;
+        self.issued.retain(|nonce| nonces.contains_key(nonce));
     }
 }
 
@@ -304,6 +347,7 @@ mod tests {
         let response = auth
             .calculate_response(
                 username,
+                &challenge.realm,
                 password,
                 method,
                 uri,
@@ -344,6 +388,7 @@ mod tests {
         let response = auth
             .calculate_response(
                 username,
+                &challenge.realm,
                 password,
                 method,
                 uri,
@@ -391,4 +436,18 @@ mod tests {
         auth.cleanup_nonces();
         assert_eq!(auth.nonces.len(), 2);
     }
+
+    #[test]
+    fn test_outstanding_nonces_are_capped() {
+        let mut auth = AuthManager::new("rustalk.local");
+        let first = auth.generate_challenge().nonce;
+        for _ in 0..MAX_OUTSTANDING_NONCES {
+            auth.generate_challenge();
+        }
+
+        assert_eq!(auth.nonces.len(), MAX_OUTSTANDING_NONCES);
+        assert_eq!(auth.issued.len(), MAX_OUTSTANDING_NONCES);
+        // The oldest challenge was forgotten to make room
+        assert!(auth.consume_nonce(&first).is_err());
+    }
 }
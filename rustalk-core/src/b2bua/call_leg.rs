@@ -1,5 +1,6 @@
 //! Call leg representation
 
+use crate::media::{codec::standard_codecs, sdp::SdpSession};
 use std::net::SocketAddr;
 
 /// Represents one leg of a B2BUA call
@@ -37,4 +38,56 @@ impl CallLeg {
         self.sdp = Some(sdp);
         self
     }
+
+    /// Name of the first audio codec in the leg's SDP
+    ///
+    /// Dynamic payload types are resolved through `a=rtpmap`; static ones
+    /// fall back to the standard codec table.
+    pub fn codec(&self) -> Option<String> {
+        let sdp = self.sdp.as_deref()?;
+        let session = SdpSession::parse(sdp).ok()?;
+        let payload_type = session
+            .media
+            .iter()
+            .find(|m| m.media_type == "audio")?
+            .formats
+            .first()
+            .copied()?;
+
+        let rtpmap = format!("a=rtpmap:{} ", payload_type);
+        sdp.lines()
+            .find_map(|line| line.trim().strip_prefix(rtpmap.as_str()))
+            .and_then(|encoding| encoding.split('/').next())
+            .map(str::to_string)
+            .or_else(|| {
+                standard_codecs()
+                    .into_iter()
+                    .find(|c| c.payload_type == payload_type)
+                    .map(|c| c.name)
+            })
+            .or_else(|| Some(format!("PT {}", payload_type)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_from_sdp() {
+        let addr: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let leg = CallLeg::new(addr, "sip:a@x".to_string(), "sip:b@y".to_string());
+        assert_eq!(leg.codec(), None);
+
+        let leg = leg.with_sdp(
+            "v=0\r\nm=audio 4000 RTP/AVP 8 0 101\r\na=rtpmap:101 telephone-event/8000\r\n"
+                .to_string(),
+        );
+        assert_eq!(leg.codec().as_deref(), Some("PCMA"));
+
+        let leg = leg.with_sdp(
+            "v=0\r\nm=audio 4000 RTP/AVP 111\r\na=rtpmap:111 opus/48000/2\r\n".to_string(),
+        );
+        assert_eq!(leg.codec().as_deref(), Some("opus"));
+    }
 }
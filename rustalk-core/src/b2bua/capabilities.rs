@@ -0,0 +1,185 @@
+//! Methods and extensions the running B2BUA offers
+//!
+//! Allow and Supported are derived from the modules that are actually
+//! configured rather than a fixed list: REGISTER is only allowed with a
+//! registrar, MESSAGE only with an SMS gateway. A peer bound to a quirk
+//! profile is offered less again, so a trunk that rejects UPDATE is never
+//! told we accept it. Methods we know but do not offer are refused with
+//! `405` and an Allow header (RFC 3261 section 8.2.1).
+
+use crate::quirks::{Quirk, QuirkSet};
+use crate::sip::builder::MessageBuilder;
+use crate::sip::{Header, Method, Request, Response, StatusCode};
+
+/// Methods every B2BUA handles
+const CORE_METHODS: [Method; 7] = [
+    Method::Invite,
+    Method::Ack,
+    Method::Bye,
+    Method::Cancel,
+    Method::Options,
+    Method::Info,
+    Method::Update,
+];
+
+/// What the B2BUA offers to one peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    methods: Vec<Method>,
+    /// Option tags for the Supported header
+    supported: Vec<&'static str>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            methods: CORE_METHODS.to_vec(),
+            supported: Vec::new(),
+        }
+    }
+}
+
+impl Capabilities {
+    /// Offer `method` as well
+    pub fn with_method(mut self, method: Method) -> Self {
+        if !self.methods.contains(&method) {
+            self.methods.push(method);
+        }
+        self
+    }
+
+    /// Offer the extension named by `tag` as well
+    pub fn with_supported(mut self, tag: &'static str) -> Self {
+        if !self.supported.contains(&tag) {
+            self.supported.push(tag);
+        }
+        self
+    }
+
+    /// Narrow to what a peer with `quirks` accepts
+    pub fn for_profile(mut self, quirks: &QuirkSet) -> Self {
+        if quirks.has(Quirk::NoPrack) {
+            self.methods.retain(|m| *m != Method::Prack);
+            self.supported.retain(|t| *t != "100rel");
+        }
+        if quirks.has(Quirk::NoUpdate) {
+            self.methods.retain(|m| *m != Method::Update);
+        }
+        self
+    }
+
+    pub fn allows(&self, method: Method) -> bool {
+        self.methods.contains(&method)
+    }
+
+    pub fn methods(&self) -> &[Method] {
+        &self.methods
+    }
+
+    /// Allow header value
+    pub fn allow(&self) -> String {
+        let methods: Vec<_> = self.methods.iter().map(Method::as_str).collect();
+        methods.join(", ")
+    }
+
+    /// Supported header value, `None` when no extension is offered
+    pub fn supported(&self) -> Option<String> {
+        (!self.supported.is_empty()).then(|| self.supported.join(", "))
+    }
+
+    /// Add Allow and Supported to a response
+    pub fn advertise(&self, mut response: Response) -> Response {
+        response = response.with_header("Allow", self.allow().as_str());
+        if let Some(supported) = self.supported() {
+            response = response.with_header("Supported", supported.as_str());
+        }
+        response
+    }
+
+    /// Replace the Allow and Supported of a request we pass on with our own
+    pub fn apply_to_request(&self, request: &mut Request) {
+        request.headers.retain(|h| {
+            let name = h.name.as_str();
+            !name.eq_ignore_ascii_case("Allow") && !name.eq_ignore_ascii_case("Supported")
+        });
+        request
+            .headers
+            .push(Header::new("Allow", self.allow().as_str()));
+        if let Some(supported) = self.supported() {
+            request
+                .headers
+                .push(Header::new("Supported", supported.as_str()));
+        }
+    }
+
+    /// `405 Method Not Allowed` for a method we do not offer
+    pub fn method_not_allowed(&self, builder: &MessageBuilder, request: &Request) -> Response {
+        builder
+            .response(request, StatusCode::METHOD_NOT_ALLOWED)
+            .header("Allow", self.allow())
+            .build()
+    }
+}
+
+/// Host of the SIP URI in a From or To header value
+pub(crate) fn uri_host(value: &str) -> Option<&str> {
+    let start = value
+        .find("sip:")
+        .map(|i| i + 4)
+        .or_else(|| value.find("sips:").map(|i| i + 5))?;
+    let rest = &value[start..];
+    let rest = rest.find('@').map(|at| &rest[at + 1..]).unwrap_or(rest);
+    let end = rest.find([':', ';', '>']).unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quirks(quirks: &[Quirk]) -> QuirkSet {
+        QuirkSet {
+            profile: "test".to_string(),
+            quirks: quirks.to_vec(),
+            auth_user: None,
+        }
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::default();
+        assert_eq!(
+            capabilities.allow(),
+            "INVITE, ACK, BYE, CANCEL, OPTIONS, INFO, UPDATE"
+        );
+        assert!(capabilities.supported().is_none());
+        assert!(!capabilities.allows(Method::Register));
+
+        let capabilities = capabilities
+            .with_method(Method::Register)
+            .with_method(Method::Prack)
+            .with_supported("100rel")
+            .with_supported("path");
+        assert!(capabilities.allows(Method::Register));
+        assert_eq!(capabilities.supported().as_deref(), Some("100rel, path"));
+
+        let trunk = capabilities.for_profile(&quirks(&[Quirk::NoPrack, Quirk::NoUpdate]));
+        assert!(!trunk.allows(Method::Update));
+        assert!(!trunk.allows(Method::Prack));
+        assert_eq!(trunk.supported().as_deref(), Some("path"));
+        assert_eq!(
+            trunk.allow(),
+            "INVITE, ACK, BYE, CANCEL, OPTIONS, INFO, REGISTER"
+        );
+    }
+
+    #[test]
+    fn test_uri_host() {
+        assert_eq!(
+            uri_host("\"Carrier\" <sip:+12125551000@sip.carrier.example:5060>;tag=1"),
+            Some("sip.carrier.example")
+        );
+        assert_eq!(uri_host("<sip:gw.example;lr>"), Some("gw.example"));
+        assert!(uri_host("<tel:+12125551000>").is_none());
+    }
+}
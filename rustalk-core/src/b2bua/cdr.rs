@@ -0,0 +1,243 @@
+//! Call detail records emitted by the B2BUA
+
+use crate::b2bua::{CallDecision, HangupCause, LegSide, Session};
+use crate::cos::CallClass;
+use crate::no_answer::NoAnswerAction;
+use crate::screening::ScreeningOutcome;
+use crate::wholesale::Charge;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How a call ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallDisposition {
+    /// Call was answered and later hung up
+    Answered,
+    /// Caller cancelled before answer
+    Cancelled,
+    /// Call ended without being answered
+    Failed,
+    /// Call was refused by class of service before routing
+    Denied,
+    /// Callee answered busy
+    Busy,
+}
+
+/// A destination a call was tried at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CallAttempt {
+    /// Route destination tried, when the call was routed
+    pub destination: Option<String>,
+    /// Request-URI the call was sent to
+    pub uri: String,
+    pub started_at: DateTime<Utc>,
+    /// Final response the destination failed with
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// Given up on for not answering in time
+    #[serde(default)]
+    pub timed_out: bool,
+    /// Reached by a reroute rule rather than the route's own destinations
+    #[serde(default)]
+    pub rerouted: bool,
+}
+
+impl CallAttempt {
+    pub fn new(destination: Option<&str>, uri: impl Into<String>) -> Self {
+        Self {
+            destination: destination.map(str::to_string),
+            uri: uri.into(),
+            started_at: Utc::now(),
+            status: None,
+            timed_out: false,
+            rerouted: false,
+        }
+    }
+}
+
+/// Record of a completed call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallDetailRecord {
+    pub call_id: String,
+    pub caller: Option<String>,
+    pub callee: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub answer_time: Option<DateTime<Utc>>,
+    pub end_time: DateTime<Utc>,
+    /// Billable seconds (answer to hangup)
+    pub duration_seconds: i64,
+    pub disposition: CallDisposition,
+    /// Normalized cause the call ended with
+    #[serde(default)]
+    pub hangup_cause: Option<HangupCause>,
+    /// Class of the dialed number, when class of service is enforced
+    #[serde(default)]
+    pub call_class: Option<CallClass>,
+    /// Placed under an override PIN's profile
+    #[serde(default)]
+    pub authorized_by_pin: bool,
+    /// Why the call was refused
+    #[serde(default)]
+    pub denial_reason: Option<String>,
+    /// Account code the call is billed to
+    #[serde(default)]
+    pub account_code: Option<String>,
+    /// User whose personal dialing PIN authorized the call
+    #[serde(default)]
+    pub dial_pin_user: Option<String>,
+    /// Result of caller announcement or screening
+    #[serde(default)]
+    pub screening: Option<ScreeningOutcome>,
+    /// Sent straight to voicemail without ringing the callee
+    #[serde(default)]
+    pub voicemail_drop: bool,
+    /// Applied when nobody answered within the ring time
+    #[serde(default)]
+    pub no_answer: Option<NoAnswerAction>,
+    /// Teams correlation ID of a Direct Routing call
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// Wholesale peer the call came from
+    #[serde(default)]
+    pub carrier_peer: Option<String>,
+    /// Cost under the peer's rate deck
+    #[serde(default)]
+    pub charge: Option<Charge>,
+    /// Why the call went where it did
+    #[serde(default)]
+    pub decisions: Vec<CallDecision>,
+    /// Milliseconds from sending the INVITE to the first ringing, progress
+    /// or answer
+    #[serde(default)]
+    pub post_dial_delay_ms: Option<u64>,
+    /// Milliseconds from the INVITE to the answer
+    #[serde(default)]
+    pub setup_ms: Option<u64>,
+    /// Audio codec the caller offered first
+    #[serde(default)]
+    pub caller_codec: Option<String>,
+    /// Audio codec the callee answered with
+    #[serde(default)]
+    pub callee_codec: Option<String>,
+    /// Destinations the call was tried at, in order
+    #[serde(default)]
+    pub attempts: Vec<CallAttempt>,
+}
+
+impl CallDetailRecord {
+    /// Build a record for a session that has just ended
+    pub fn from_session(session: &Session, disposition: CallDisposition) -> Self {
+        let end_time = Utc::now();
+        let duration_seconds = session
+            .answered_at()
+            .map(|answered| (end_time - answered).num_seconds().max(0))
+            .unwrap_or(0);
+
+        Self {
+            call_id: session.call_id().to_string(),
+            caller: session.caller().map(str::to_string),
+            callee: session.callee().map(str::to_string),
+            start_time: session.created_at(),
+            answer_time: session.answered_at(),
+            end_time,
+            duration_seconds,
+            disposition,
+            hangup_cause: None,
+            call_class: session.call_class(),
+            authorized_by_pin: session.authorized_by_pin(),
+            denial_reason: None,
+            account_code: session.account_code().map(str::to_string),
+            dial_pin_user: session.dial_pin_user().map(str::to_string),
+            screening: session.screening_outcome(),
+            voicemail_drop: session.voicemail_drop(),
+            no_answer: session.no_answer().cloned(),
+            correlation_id: session.correlation_id().map(str::to_string),
+            carrier_peer: session.carrier_peer().map(str::to_string),
+            charge: None,
+            decisions: session.decisions().to_vec(),
+            post_dial_delay_ms: session
+                .post_dial_delay()
+                .map(|delay| delay.as_millis() as u64),
+            setup_ms: session
+                .answered_at()
+                .map(|answered| (answered - session.created_at()).num_milliseconds().max(0) as u64),
+            caller_codec: session.codec(LegSide::A).map(str::to_string),
+            callee_codec: session.codec(LegSide::B).map(str::to_string),
+            attempts: session.attempts().to_vec(),
+        }
+    }
+
+    /// Build a record for a call refused before a session was created
+    pub fn denied(
+        call_id: &str,
+        caller: Option<String>,
+        callee: Option<String>,
+        call_class: CallClass,
+        reason: String,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            call_id: call_id.to_string(),
+            caller,
+            callee,
+            start_time: now,
+            answer_time: None,
+            end_time: now,
+            duration_seconds: 0,
+            disposition: CallDisposition::Denied,
+            hangup_cause: Some(HangupCause::OutgoingCallBarred),
+            call_class: Some(call_class),
+            authorized_by_pin: false,
+            denial_reason: Some(reason),
+            account_code: None,
+            dial_pin_user: None,
+            screening: None,
+            voicemail_drop: false,
+            no_answer: None,
+            correlation_id: None,
+            carrier_peer: None,
+            charge: None,
+            decisions: Vec::new(),
+            post_dial_delay_ms: None,
+            setup_ms: None,
+            caller_codec: None,
+            callee_codec: None,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// User and domain of the calling party
+    pub fn caller_party(&self) -> (String, String) {
+        party(self.caller.as_deref())
+    }
+
+    /// User and domain of the called party
+    pub fn callee_party(&self) -> (String, String) {
+        party(self.callee.as_deref())
+    }
+}
+
+/// User and domain of a From or To header value
+fn party(value: Option<&str>) -> (String, String) {
+    let Some(value) = value else {
+        return Default::default();
+    };
+    let uri = match value.find('<') {
+        Some(start) => value[start + 1..].split('>').next().unwrap_or_default(),
+        None => value.split(';').next().unwrap_or_default(),
+    };
+    let uri = ["sips:", "sip:", "tel:"]
+        .iter()
+        .find_map(|scheme| uri.strip_prefix(scheme))
+        .unwrap_or(uri);
+    let uri = uri.split([';', '?']).next().unwrap_or_default();
+    match uri.split_once('@') {
+        Some((user, host)) => (
+            user.to_string(),
+            host.split(':').next().unwrap_or_default().to_string(),
+        ),
+        None => (uri.to_string(), String::new()),
+    }
+}
@@ -0,0 +1,83 @@
+//! Live channel view of active B2BUA sessions
+
+use crate::b2bua::session::SessionState;
+use crate::b2bua::{CallLeg, Session};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// Which side of the B2BUA a leg is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LegSide {
+    /// Inbound leg from the caller
+    A,
+    /// Outbound leg towards the callee
+    B,
+}
+
+/// Snapshot of one call leg
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub session_id: String,
+    pub call_id: String,
+    pub leg: LegSide,
+    pub state: SessionState,
+    pub remote_addr: Option<SocketAddr>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Codec offered in the leg's SDP
+    pub codec: Option<String>,
+    /// Whether the leg's audio is streamed for transcription
+    pub transcribing: bool,
+    pub created_at: DateTime<Utc>,
+    /// Seconds since the session was created
+    pub duration_seconds: i64,
+}
+
+impl ChannelInfo {
+    /// One entry per leg of a session
+    ///
+    /// Sessions that have no legs yet are reported as a single A leg using
+    /// the parties from the initial INVITE.
+    pub fn from_session(session: &Session) -> Vec<Self> {
+        let legs: Vec<(LegSide, &CallLeg)> = [
+            session.a_leg().map(|leg| (LegSide::A, leg)),
+            session.b_leg().map(|leg| (LegSide::B, leg)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        let base = Self {
+            session_id: session.id().to_string(),
+            call_id: session.call_id().to_string(),
+            leg: LegSide::A,
+            state: session.state(),
+            remote_addr: None,
+            from: session.caller().map(str::to_string),
+            to: session.callee().map(str::to_string),
+            codec: None,
+            transcribing: session.is_transcribed(LegSide::A),
+            created_at: session.created_at(),
+            duration_seconds: (Utc::now() - session.created_at()).num_seconds().max(0),
+        };
+
+        if legs.is_empty() {
+            return vec![base];
+        }
+
+        legs.into_iter()
+            .map(|(side, leg)| Self {
+                leg: side,
+                remote_addr: Some(leg.remote_addr),
+                from: Some(leg.from_uri.clone()),
+                to: Some(leg.to_uri.clone()),
+                codec: leg.codec(),
+                transcribing: session.is_transcribed(side),
+                ..base.clone()
+            })
+            .collect()
+    }
+}
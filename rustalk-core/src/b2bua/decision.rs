@@ -0,0 +1,72 @@
+//! Decision trail of a call
+//!
+//! Every check an INVITE passes through on its way to a trunk leaves a step
+//! on its session: the ACL that admitted the source, the route that matched
+//! and why, the trunk it was shaped for and the responses each leg saw. The
+//! trail travels with the CDR so support staff can see why a call went where
+//! it did without reading logs.
+
+use crate::b2bua::LegSide;
+use crate::sip::StatusCode;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Stage of call handling a decision was made in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionStage {
+    /// Source address checked against an ACL
+    Acl,
+    /// Caller identified, by source address or credentials
+    Authentication,
+    /// Class of service, dialing PIN and account code checks
+    Policy,
+    /// Number portability dip of the destination
+    Portability,
+    /// Route evaluation, including voicemail drops
+    Route,
+    /// Call admission limits
+    Admission,
+    /// Trunk the call is sent to and how the INVITE was shaped for it
+    Trunk,
+    /// Response sent or received on a leg
+    Response,
+}
+
+/// One step of a call's decision trail
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CallDecision {
+    pub at: DateTime<Utc>,
+    pub stage: DecisionStage,
+    /// Whether the call got past this step
+    pub passed: bool,
+    pub detail: String,
+    /// Leg a response was sent or received on
+    #[serde(default)]
+    pub leg: Option<LegSide>,
+}
+
+impl CallDecision {
+    pub fn new(stage: DecisionStage, passed: bool, detail: impl Into<String>) -> Self {
+        Self {
+            at: Utc::now(),
+            stage,
+            passed,
+            detail: detail.into(),
+            leg: None,
+        }
+    }
+
+    /// A response on `leg`; final responses of 300 and above did not pass
+    pub fn response(leg: LegSide, status: StatusCode) -> Self {
+        Self {
+            leg: Some(leg),
+            ..Self::new(
+                DecisionStage::Response,
+                status.0 < 300,
+                format!("{} {}", status, status.reason_phrase()),
+            )
+        }
+    }
+}
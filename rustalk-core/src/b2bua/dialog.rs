@@ -0,0 +1,108 @@
+//! CSeq sequencing of a call's dialogs
+//!
+//! Each side of a call numbers its requests with its own CSeq space
+//! (RFC 3261 section 12.2). The first request from a side sets its
+//! sequence; later requests must not go backwards, or they are out of order
+//! and refused with `500` (section 12.2.2). ACK and CANCEL carry the number
+//! of the INVITE they belong to and are not sequenced themselves. Requests
+//! we originate on the callee leg take numbers from a counter of our own.
+
+use crate::b2bua::LegSide;
+use crate::sip::dialog::check_sequence;
+use crate::sip::{CSeq, Method, Request};
+
+pub use crate::sip::dialog::SequenceError;
+
+/// CSeq bookkeeping for both sides of a call
+#[derive(Debug, Clone, Default)]
+pub struct DialogSequence {
+    caller: Option<u32>,
+    callee: Option<u32>,
+    local: u32,
+}
+
+impl DialogSequence {
+    /// Check a request from `side` against its sequence and advance it
+    ///
+    /// Requests without a CSeq are not sequenced.
+    pub fn check(&mut self, side: LegSide, request: &Request) -> Result<(), SequenceError> {
+        let last = match side {
+            LegSide::A => &mut self.caller,
+            LegSide::B => &mut self.callee,
+        };
+        *last = check_sequence(*last, request)?;
+        Ok(())
+    }
+
+    /// Last CSeq number seen from `side`
+    pub fn remote(&self, side: LegSide) -> Option<u32> {
+        match side {
+            LegSide::A => self.caller,
+            LegSide::B => self.callee,
+        }
+    }
+
+    /// CSeq for the next request we originate with `method`
+    ///
+    /// ACK and CANCEL reuse the number of our last request.
+    pub fn next_local(&mut self, method: Method) -> CSeq {
+        if !matches!(method, Method::Ack | Method::Cancel) || self.local == 0 {
+            self.local += 1;
+        }
+        CSeq {
+            sequence: self.local,
+            method,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{StatusCode, Uri};
+
+    fn request(method: Method, cseq: &str) -> Request {
+        Request::new(
+            method,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("CSeq", cseq)
+    }
+
+    #[test]
+    fn test_sequence_per_side() {
+        let mut sequence = DialogSequence::default();
+        sequence
+            .check(LegSide::A, &request(Method::Invite, "10 INVITE"))
+            .unwrap();
+        // ACK and CANCEL share the INVITE's number
+        sequence
+            .check(LegSide::A, &request(Method::Ack, "10 ACK"))
+            .unwrap();
+        // The callee numbers its own requests
+        sequence
+            .check(LegSide::B, &request(Method::Info, "1 INFO"))
+            .unwrap();
+        assert_eq!(
+            sequence.check(LegSide::A, &request(Method::Info, "9 INFO")),
+            Err(SequenceError::OutOfOrder {
+                last: 10,
+                received: 9
+            })
+        );
+        sequence
+            .check(LegSide::A, &request(Method::Bye, "11 BYE"))
+            .unwrap();
+        assert_eq!(sequence.remote(LegSide::A), Some(11));
+        assert_eq!(sequence.remote(LegSide::B), Some(1));
+
+        let error = sequence
+            .check(LegSide::A, &request(Method::Bye, "12 INFO"))
+            .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+
+        assert_eq!(sequence.next_local(Method::Invite).to_string(), "1 INVITE");
+        assert_eq!(sequence.next_local(Method::Cancel).to_string(), "1 CANCEL");
+        assert_eq!(sequence.next_local(Method::Bye).to_string(), "2 BYE");
+    }
+}
@@ -0,0 +1,375 @@
+//! Parallel forking to registered contacts
+//!
+//! An extension may be registered from several devices at once - a desk
+//! phone, a softphone and a mobile client. A call to it rings every contact
+//! at the same time, each on its own branch. The first contact to answer
+//! takes the call and the others are sent a CANCEL saying the call was
+//! completed elsewhere, so they do not log it as missed. The call only fails
+//! once every contact has refused it. A call routed to a trunk is sent
+//! there as a branch of its own, so the trunk's answer is handled the same
+//! way.
+
+use crate::b2bua::CSeq;
+use crate::registrar::Registration;
+use crate::sip::builder::cancel_for;
+use crate::sip::{Header, Request, Response, StatusCode, Uri};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use uuid::Uuid;
+
+/// Reason given to the contacts that did not answer
+pub const COMPLETED_ELSEWHERE: &str = "SIP;cause=200;text=\"Call completed elsewhere\"";
+
+/// Port assumed for contacts that do not name one
+const DEFAULT_SIP_PORT: u16 = 5060;
+
+/// A request the B2BUA sends on its own rather than as a reply
+#[derive(Debug, Clone)]
+pub struct OutboundRequest {
+    pub request: Request,
+    pub destination: SocketAddr,
+}
+
+/// A response the B2BUA relays from one leg of a call to the other
+#[derive(Debug, Clone)]
+pub struct OutboundResponse {
+    pub response: Response,
+    pub destination: SocketAddr,
+}
+
+/// Progress of one branch of a forked call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForkState {
+    /// INVITE sent, nothing heard back yet
+    Trying,
+    /// The contact is alerting
+    Ringing,
+    /// The contact answered the call
+    Answered,
+    /// The contact refused the call
+    Failed,
+    /// Cancelled because the call was answered or given up on
+    Cancelled,
+}
+
+/// One contact a call is forked to
+#[derive(Debug, Clone)]
+pub struct ForkBranch {
+    pub contact: String,
+    pub destination: SocketAddr,
+    /// Via branch of the forked INVITE, which its responses carry
+    pub branch: String,
+    pub state: ForkState,
+    /// Final response, once the contact has refused the call
+    pub status: Option<StatusCode>,
+    /// `Reason` header of the final response
+    pub reason: Option<String>,
+    /// Index of the session target the branch rings
+    pub target: usize,
+    request: Request,
+}
+
+impl ForkBranch {
+    /// Fork `invite` to a registered contact, with a Via of our own at
+    /// `local_addr` and our own `cseq`
+    ///
+    /// Returns `None` when the contact cannot be addressed: it is not a SIP
+    /// URI, or names a host we cannot send to without a DNS lookup.
+    pub fn new(
+        invite: &Request,
+        binding: &Registration,
+        local_addr: SocketAddr,
+        cseq: CSeq,
+    ) -> Option<Self> {
+        let uri: Uri = binding.contact.parse().ok()?;
+        let destination = binding
+            .received
+            .filter(|_| !binding.send_to_contact)
+            .or_else(|| contact_address(&uri))?;
+        Some(Self::branch(
+            invite,
+            uri,
+            binding.contact.clone(),
+            destination,
+            local_addr,
+            cseq,
+        ))
+    }
+
+    /// Send `invite` on to a trunk at `destination`, keeping its
+    /// Request-URI, as the call's only branch
+    pub fn to_trunk(
+        invite: &Request,
+        destination: SocketAddr,
+        local_addr: SocketAddr,
+        cseq: CSeq,
+    ) -> Self {
+        Self::branch(
+            invite,
+            invite.uri.clone(),
+            invite.uri.to_string(),
+            destination,
+            local_addr,
+            cseq,
+        )
+    }
+
+    fn branch(
+        invite: &Request,
+        uri: Uri,
+        contact: String,
+        destination: SocketAddr,
+        local_addr: SocketAddr,
+        cseq: CSeq,
+    ) -> Self {
+        let branch = format!("z9hG4bK-{}", Uuid::new_v4().simple());
+
+        let mut request = invite.clone();
+        request.uri = uri;
+        request.headers.retain(|h| {
+            let name = h.name.as_str();
+            !name.eq_ignore_ascii_case("Via") && !name.eq_ignore_ascii_case("CSeq")
+        });
+        request
+            .headers
+            .push(Header::new("CSeq", cseq.to_string().as_str()));
+        request.headers.insert(
+            0,
+            Header::new(
+                "Via",
+                format!("SIP/2.0/UDP {};branch={};rport", local_addr, branch).as_str(),
+            ),
+        );
+
+        Self {
+            contact,
+            destination,
+            branch,
+            state: ForkState::Trying,
+            status: None,
+            reason: None,
+            target: 0,
+            request,
+        }
+    }
+
+    /// The INVITE sent to this contact
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// Still waiting on the contact to answer or refuse
+    pub fn is_pending(&self) -> bool {
+        matches!(self.state, ForkState::Trying | ForkState::Ringing)
+    }
+
+    /// CANCEL for the branch's INVITE (RFC 3261 section 9.1)
+    pub fn cancel(&self, reason: Option<&str>) -> OutboundRequest {
+        let mut cancel = cancel_for(&self.request);
+        if let Some(reason) = reason {
+            cancel = cancel.with_header("Reason", reason);
+        }
+        OutboundRequest {
+            request: cancel,
+            destination: self.destination,
+        }
+    }
+}
+
+/// Response that stands for a call every contact refused (RFC 3261 section
+/// 16.7): a global failure first, then busy, then the lowest status
+pub fn best_failure(forks: &[ForkBranch]) -> Option<&ForkBranch> {
+    let failed = forks.iter().filter(|f| f.state == ForkState::Failed);
+    failed.min_by_key(|f| {
+        let status = f.status.map(|s| s.0).unwrap_or(500);
+        let rank = match status {
+            600..=699 => 0,
+            486 => 1,
+            _ => 2,
+        };
+        (rank, status)
+    })
+}
+
+/// Branch parameter of a Via header value
+pub fn via_branch(via: &str) -> Option<&str> {
+    via.split(',').next()?.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("branch")
+            .then(|| value.trim())
+    })
+}
+
+/// Whether a response status shows the contact could not be reached at all
+pub fn is_unreachable(status: StatusCode) -> bool {
+    matches!(status.0, 408 | 503)
+}
+
+/// Address of a contact URI naming an IP address
+pub(crate) fn contact_address(uri: &Uri) -> Option<SocketAddr> {
+    let ip: IpAddr = uri.host.parse().ok()?;
+    Some(SocketAddr::new(ip, uri.port.unwrap_or(DEFAULT_SIP_PORT)))
+}
+
+/// Address a trunk's Request-URI is sent to, looking its host up when it
+/// is not an IP address
+pub(crate) async fn trunk_address(uri: &Uri) -> Result<SocketAddr> {
+    if let Some(address) = contact_address(uri) {
+        return Ok(address);
+    }
+    let port = uri.port.unwrap_or(DEFAULT_SIP_PORT);
+    tokio::net::lookup_host((uri.host.as_str(), port))
+        .await
+        .with_context(|| format!("Failed to resolve {}", uri.host))?
+        .next()
+        .with_context(|| format!("{} has no address", uri.host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::Method;
+    use chrono::Utc;
+
+    fn binding(contact: &str, received: Option<&str>) -> Registration {
+        Registration {
+            aor: "sip:1001@example.com".to_string(),
+            contact: contact.to_string(),
+            user_agent: None,
+            received: received.map(|r| r.parse().unwrap()),
+            behind_nat: false,
+            registered_at: Utc::now(),
+            expires_at: Utc::now(),
+            device_id: None,
+            reachable: None,
+            checked_at: None,
+            instance_id: None,
+            reg_id: None,
+            pub_gruu: None,
+            temp_gruu: None,
+            send_to_contact: false,
+        }
+    }
+
+    fn cseq() -> CSeq {
+        CSeq {
+            sequence: 1,
+            method: Method::Invite,
+        }
+    }
+
+    fn invite() -> Request {
+        Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK-caller")
+        .with_header("From", "<sip:1002@example.com>;tag=a")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Call-ID", "fork1")
+        .with_header("CSeq", "7 INVITE")
+    }
+
+    #[test]
+    fn test_fork_and_cancel() {
+        let local: SocketAddr = "192.0.2.5:5060".parse().unwrap();
+        let fork = ForkBranch::new(
+            &invite(),
+            &binding(
+                "sip:1001@10.0.0.20:5062;transport=udp",
+                Some("203.0.113.9:40000"),
+            ),
+            local,
+            cseq(),
+        )
+        .unwrap();
+        // Sent where the REGISTER came from, so NAT bindings are used
+        assert_eq!(fork.destination, "203.0.113.9:40000".parse().unwrap());
+        assert_eq!(
+            fork.request().uri.to_string(),
+            "sip:1001@10.0.0.20:5062;transport=udp"
+        );
+        let vias: Vec<_> = fork
+            .request()
+            .headers
+            .iter()
+            .filter(|h| h.name.as_str() == "Via")
+            .collect();
+        assert_eq!(vias.len(), 1);
+        assert_eq!(
+            via_branch(vias[0].value.as_str()),
+            Some(fork.branch.as_str())
+        );
+        assert!(fork.is_pending());
+
+        let cancel = fork.cancel(Some(COMPLETED_ELSEWHERE)).request;
+        assert_eq!(cancel.method, Method::Cancel);
+        assert_eq!(cancel.uri, fork.request().uri);
+        // The callee leg is numbered by us, not the caller
+        assert_eq!(fork.request().get_header_value("CSeq"), Some("1 INVITE"));
+        assert_eq!(cancel.get_header_value("CSeq"), Some("1 CANCEL"));
+        assert_eq!(cancel.get_header_value("Call-ID"), Some("fork1"));
+        assert_eq!(cancel.get_header_value("Reason"), Some(COMPLETED_ELSEWHERE));
+        assert_eq!(
+            cancel.get_header_value("Via").and_then(via_branch),
+            Some(fork.branch.as_str())
+        );
+
+        // Unless the SIP profile turned contact rewriting off
+        let mut pinned = binding("sip:1001@10.0.0.21", Some("203.0.113.9:40000"));
+        pinned.send_to_contact = true;
+        assert_eq!(
+            ForkBranch::new(&invite(), &pinned, local, cseq())
+                .unwrap()
+                .destination,
+            "10.0.0.21:5060".parse().unwrap()
+        );
+
+        // Without a source address the contact itself is used
+        let direct = ForkBranch::new(
+            &invite(),
+            &binding("sip:1001@10.0.0.21", None),
+            local,
+            cseq(),
+        );
+        assert_eq!(
+            direct.unwrap().destination,
+            "10.0.0.21:5060".parse().unwrap()
+        );
+        assert!(ForkBranch::new(
+            &invite(),
+            &binding("sip:1001@phone.example.com", None),
+            local,
+            cseq()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_best_failure() {
+        let local: SocketAddr = "192.0.2.5:5060".parse().unwrap();
+        let failed = |status: u16| {
+            let mut fork = ForkBranch::new(
+                &invite(),
+                &binding("sip:1001@10.0.0.20", None),
+                local,
+                cseq(),
+            )
+            .unwrap();
+            fork.state = ForkState::Failed;
+            fork.status = Some(StatusCode(status));
+            fork
+        };
+        let forks = vec![failed(480), failed(486), failed(408)];
+        assert_eq!(best_failure(&forks).unwrap().status, Some(StatusCode(486)));
+        let forks = vec![failed(480), failed(603)];
+        assert_eq!(best_failure(&forks).unwrap().status, Some(StatusCode(603)));
+        let forks = vec![failed(480), failed(408)];
+        assert_eq!(best_failure(&forks).unwrap().status, Some(StatusCode(408)));
+        assert!(is_unreachable(StatusCode(408)));
+        assert!(!is_unreachable(StatusCode(486)));
+    }
+}
@@ -0,0 +1,259 @@
+//! Normalized hangup causes
+//!
+//! However a call ends - a BYE, a CANCEL, a failure response from the far
+//! end or a refusal of our own - it is recorded with one cause from the
+//! Q.850 table, named the way switches commonly log them (`NORMAL_CLEARING`,
+//! `USER_BUSY`, ...). SIP responses are mapped as RFC 3398 describes; a
+//! `Reason: Q.850;cause=N` header (RFC 3326) takes precedence, since it
+//! carries the cause the far network saw.
+
+use crate::sip::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Why a call ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HangupCause {
+    UnallocatedNumber,
+    NoRouteDestination,
+    NormalClearing,
+    UserBusy,
+    NoUserResponse,
+    NoAnswer,
+    CallRejected,
+    NumberChanged,
+    DestinationOutOfOrder,
+    InvalidNumberFormat,
+    FacilityRejected,
+    NormalUnspecified,
+    NormalCircuitCongestion,
+    NetworkOutOfOrder,
+    NormalTemporaryFailure,
+    SwitchCongestion,
+    OutgoingCallBarred,
+    IncomingCallBarred,
+    BearerCapabilityNotAvail,
+    ServiceUnavailable,
+    BearerCapabilityNotImplemented,
+    FacilityNotImplemented,
+    ServiceNotImplemented,
+    IncompatibleDestination,
+    InvalidMsgUnspecified,
+    RecoveryOnTimerExpire,
+    ProtocolError,
+    InterworkingUnspecified,
+    /// The caller hung up before answer (no Q.850 equivalent)
+    OriginatorCancel,
+}
+
+impl HangupCause {
+    /// Cause for a Q.850 cause value
+    ///
+    /// Values without a cause of their own fall back to the generic cause
+    /// of their class, as Q.850 directs.
+    pub fn from_q850(code: u16) -> Self {
+        match code {
+            1 => Self::UnallocatedNumber,
+            2 | 3 => Self::NoRouteDestination,
+            16 => Self::NormalClearing,
+            17 => Self::UserBusy,
+            18 => Self::NoUserResponse,
+            19 => Self::NoAnswer,
+            21 => Self::CallRejected,
+            22 => Self::NumberChanged,
+            27 => Self::DestinationOutOfOrder,
+            28 => Self::InvalidNumberFormat,
+            29 => Self::FacilityRejected,
+            34 => Self::NormalCircuitCongestion,
+            38 => Self::NetworkOutOfOrder,
+            41 => Self::NormalTemporaryFailure,
+            42 => Self::SwitchCongestion,
+            52 => Self::OutgoingCallBarred,
+            54 => Self::IncomingCallBarred,
+            58 => Self::BearerCapabilityNotAvail,
+            65 => Self::BearerCapabilityNotImplemented,
+            69 => Self::FacilityNotImplemented,
+            79 => Self::ServiceNotImplemented,
+            88 => Self::IncompatibleDestination,
+            95 => Self::InvalidMsgUnspecified,
+            102 => Self::RecoveryOnTimerExpire,
+            111 => Self::ProtocolError,
+            0..=31 => Self::NormalUnspecified,
+            32..=47 => Self::NormalTemporaryFailure,
+            48..=63 => Self::ServiceUnavailable,
+            64..=79 => Self::ServiceNotImplemented,
+            80..=95 => Self::InvalidMsgUnspecified,
+            96..=111 => Self::ProtocolError,
+            _ => Self::InterworkingUnspecified,
+        }
+    }
+
+    /// Q.850 cause value; the caller cancelling is reported as normal,
+    /// unspecified
+    pub fn q850(&self) -> u16 {
+        match self {
+            Self::UnallocatedNumber => 1,
+            Self::NoRouteDestination => 3,
+            Self::NormalClearing => 16,
+            Self::UserBusy => 17,
+            Self::NoUserResponse => 18,
+            Self::NoAnswer => 19,
+            Self::CallRejected => 21,
+            Self::NumberChanged => 22,
+            Self::DestinationOutOfOrder => 27,
+            Self::InvalidNumberFormat => 28,
+            Self::FacilityRejected => 29,
+            Self::NormalUnspecified | Self::OriginatorCancel => 31,
+            Self::NormalCircuitCongestion => 34,
+            Self::NetworkOutOfOrder => 38,
+            Self::NormalTemporaryFailure => 41,
+            Self::SwitchCongestion => 42,
+            Self::OutgoingCallBarred => 52,
+            Self::IncomingCallBarred => 54,
+            Self::BearerCapabilityNotAvail => 58,
+            Self::ServiceUnavailable => 63,
+            Self::BearerCapabilityNotImplemented => 65,
+            Self::FacilityNotImplemented => 69,
+            Self::ServiceNotImplemented => 79,
+            Self::IncompatibleDestination => 88,
+            Self::InvalidMsgUnspecified => 95,
+            Self::RecoveryOnTimerExpire => 102,
+            Self::ProtocolError => 111,
+            Self::InterworkingUnspecified => 127,
+        }
+    }
+
+    /// Cause for a final response from the far end (RFC 3398 section 8.2.6)
+    pub fn from_sip_status(status: StatusCode) -> Self {
+        match status.0 {
+            200..=299 => Self::NormalClearing,
+            401..=403 | 407 | 603 => Self::CallRejected,
+            404 | 485 | 604 => Self::UnallocatedNumber,
+            405 => Self::ServiceUnavailable,
+            406 | 415 | 501 => Self::ServiceNotImplemented,
+            408 | 504 => Self::RecoveryOnTimerExpire,
+            410 => Self::NumberChanged,
+            414 | 484 => Self::InvalidNumberFormat,
+            480 => Self::NoUserResponse,
+            482 | 483 => Self::SwitchCongestion,
+            486 | 600 => Self::UserBusy,
+            487 => Self::OriginatorCancel,
+            488 | 606 => Self::IncompatibleDestination,
+            502 => Self::NetworkOutOfOrder,
+            400 | 481 | 500 | 503 => Self::NormalTemporaryFailure,
+            _ => Self::InterworkingUnspecified,
+        }
+    }
+
+    /// Cause carried by a `Reason` header's Q.850 entry
+    pub fn from_reason(reason: &str) -> Option<Self> {
+        reason.split(',').find_map(|reason| {
+            let mut params = reason.split(';');
+            let protocol = params.next().unwrap_or("").trim();
+            if !protocol.eq_ignore_ascii_case("Q.850") {
+                return None;
+            }
+            params.find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                if !name.trim().eq_ignore_ascii_case("cause") {
+                    return None;
+                }
+                value.trim().parse().ok().map(Self::from_q850)
+            })
+        })
+    }
+
+    /// The cause's name as logged, e.g. `NORMAL_CLEARING`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnallocatedNumber => "UNALLOCATED_NUMBER",
+            Self::NoRouteDestination => "NO_ROUTE_DESTINATION",
+            Self::NormalClearing => "NORMAL_CLEARING",
+            Self::UserBusy => "USER_BUSY",
+            Self::NoUserResponse => "NO_USER_RESPONSE",
+            Self::NoAnswer => "NO_ANSWER",
+            Self::CallRejected => "CALL_REJECTED",
+            Self::NumberChanged => "NUMBER_CHANGED",
+            Self::DestinationOutOfOrder => "DESTINATION_OUT_OF_ORDER",
+            Self::InvalidNumberFormat => "INVALID_NUMBER_FORMAT",
+            Self::FacilityRejected => "FACILITY_REJECTED",
+            Self::NormalUnspecified => "NORMAL_UNSPECIFIED",
+            Self::NormalCircuitCongestion => "NORMAL_CIRCUIT_CONGESTION",
+            Self::NetworkOutOfOrder => "NETWORK_OUT_OF_ORDER",
+            Self::NormalTemporaryFailure => "NORMAL_TEMPORARY_FAILURE",
+            Self::SwitchCongestion => "SWITCH_CONGESTION",
+            Self::OutgoingCallBarred => "OUTGOING_CALL_BARRED",
+            Self::IncomingCallBarred => "INCOMING_CALL_BARRED",
+            Self::BearerCapabilityNotAvail => "BEARER_CAPABILITY_NOT_AVAIL",
+            Self::ServiceUnavailable => "SERVICE_UNAVAILABLE",
+            Self::BearerCapabilityNotImplemented => "BEARER_CAPABILITY_NOT_IMPLEMENTED",
+            Self::FacilityNotImplemented => "FACILITY_NOT_IMPLEMENTED",
+            Self::ServiceNotImplemented => "SERVICE_NOT_IMPLEMENTED",
+            Self::IncompatibleDestination => "INCOMPATIBLE_DESTINATION",
+            Self::InvalidMsgUnspecified => "INVALID_MSG_UNSPECIFIED",
+            Self::RecoveryOnTimerExpire => "RECOVERY_ON_TIMER_EXPIRE",
+            Self::ProtocolError => "PROTOCOL_ERROR",
+            Self::InterworkingUnspecified => "INTERWORKING_UNSPECIFIED",
+            Self::OriginatorCancel => "ORIGINATOR_CANCEL",
+        }
+    }
+}
+
+impl fmt::Display for HangupCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sip_status_mapping() {
+        assert_eq!(
+            HangupCause::from_sip_status(StatusCode::BUSY_HERE),
+            HangupCause::UserBusy
+        );
+        assert_eq!(
+            HangupCause::from_sip_status(StatusCode(404)),
+            HangupCause::UnallocatedNumber
+        );
+        assert_eq!(
+            HangupCause::from_sip_status(StatusCode(480)),
+            HangupCause::NoUserResponse
+        );
+        assert_eq!(
+            HangupCause::from_sip_status(StatusCode(487)),
+            HangupCause::OriginatorCancel
+        );
+        assert_eq!(
+            HangupCause::from_sip_status(StatusCode(499)),
+            HangupCause::InterworkingUnspecified
+        );
+    }
+
+    #[test]
+    fn test_q850_reason() {
+        assert_eq!(
+            HangupCause::from_reason("Q.850;cause=19;text=\"No answer\""),
+            Some(HangupCause::NoAnswer)
+        );
+        assert_eq!(
+            HangupCause::from_reason("SIP;cause=200, q.850 ; cause = 16"),
+            Some(HangupCause::NormalClearing)
+        );
+        assert_eq!(HangupCause::from_reason("SIP;cause=487"), None);
+        // Unlisted values fall back to their class
+        assert_eq!(
+            HangupCause::from_q850(44),
+            HangupCause::NormalTemporaryFailure
+        );
+        assert_eq!(HangupCause::NoAnswer.q850(), 19);
+
+        let json = serde_json::to_value(HangupCause::OriginatorCancel).unwrap();
+        assert_eq!(json, "ORIGINATOR_CANCEL");
+        assert_eq!(HangupCause::UserBusy.to_string(), "USER_BUSY");
+    }
+}
@@ -1,17 +1,90 @@
 //! B2BUA (Back-to-Back User Agent) implementation
 
-use crate::sip::{Message, Method, Request, Response, StatusCode};
-use anyhow::Result;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{debug, error, info};
+use crate::account_codes::{AccountCodeConfig, AccountCodeDecision, ACCOUNT_CODE_HEADER};
+use crate::acl::Acl;
+use crate::admission::{AdmissionController, AdmissionDecision, CallDetails};
+use crate::call_trace::{uri_user, CallTracer, TraceDirection};
+use crate::callback::{CallbackQueue, CallbackRequest};
+use crate::cos::{CallClass, CosDecision, CosPolicy, NumberClassifier, OVERRIDE_PIN_HEADER};
+use crate::crm::CrmClient;
+use crate::dial_pin::{
+    DialPinAttempt, DialPinConfig, DialPinDecision, PinLockouts, DIAL_PIN_HEADER,
+};
+use crate::dial_string::{self, DialStringConfig};
+use crate::diversion::{DiversionReason, Redirection};
+use crate::domains::DomainMap;
+use crate::enum_resolver::EnumResolver;
+use crate::events::{CallEvent, CallEventKind, EventBus};
+use crate::lnp::{self, DipResult, LnpFailure, PortabilityDip};
+use crate::locale::LocaleContext;
+use crate::media::ivr::{self, IvrMedia};
+use crate::media::mixer::{AnnouncementMixer, Playback, PlaybackRefusal};
+use crate::media::webrtc::{self, DtlsIdentity, WebRtcRelay};
+use crate::media::{sdp, wav};
+use crate::media_quality::{MediaQualityTracker, MediaReport};
+use crate::missed_calls::{completed_elsewhere, MissedCallConfig};
+use crate::mwi::MwiNotifier;
+use crate::nat::{self, NatPolicy};
+use crate::no_answer::{NoAnswerAction, NoAnswerPolicy};
+use crate::pdd::PddTracker;
+use crate::presence::{ResourceListServer, EVENTLIST};
+use crate::quirks::QuirksConfig;
+use crate::quotas::Quotas;
+use crate::registrar::gruu::GRUU;
+use crate::registrar::outbound::{select_flows, OUTBOUND};
+use crate::registrar::Registrar;
+use crate::routing::graph::{describe, destination_label};
+use crate::routing::{
+    CallContext, DestinationHop, FailoverPlan, RouteAction, RouteDestination, RouteEvaluator,
+    RouteMatch, TrafficSample, TrafficSampler, DEFAULT_MAX_ATTEMPTS,
+};
+use crate::screening::{
+    display_name, dtmf_digit, Announcement, ScreeningConfig, ScreeningDecision, ScreeningMode,
+    ScreeningOutcome,
+};
+use crate::sip::builder::{header_tag, new_tag, LocalProfile, MessageBuilder, ResponseBuilder};
+use crate::sip::{Dialog, DialogState, Header, Message, Method, Request, Response, StatusCode};
+use crate::sms::SmsGateway;
+use crate::teams_records::CORRELATION_ID_HEADER;
+use crate::transcription::{CallMetadata, TranscriptionPolicy};
+use crate::voicemail::{
+    wants_voicemail_drop, RetrievalAction, VoicemailDropConfig, VoicemailRetrieval,
+    VOICEMAIL_DROP_HEADER,
+};
+use crate::wholesale::{PeerRejection, WholesaleGateway};
+use anyhow::{Context, Result};
+use capabilities::uri_host;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, error, info, warn};
 
 pub mod call_leg;
+pub mod capabilities;
+pub mod cdr;
+pub mod channel;
+pub mod decision;
+pub mod dialog;
+pub mod fork;
+pub mod hangup;
 pub mod session;
+pub mod topology;
 
+pub use crate::sip::CSeq;
 pub use call_leg::CallLeg;
-pub use session::{Session, SessionId};
+pub use capabilities::Capabilities;
+pub use cdr::{CallAttempt, CallDetailRecord, CallDisposition};
+pub use channel::{ChannelInfo, LegSide};
+pub use decision::{CallDecision, DecisionStage};
+pub use dialog::{DialogSequence, SequenceError};
+pub use fork::{ForkBranch, ForkState, OutboundRequest, OutboundResponse};
+pub use hangup::HangupCause;
+pub use session::{Session, SessionId, SessionState};
+pub use topology::{CallTarget, CallTopology};
 
 /// B2BUA core engine
 ///
@@ -20,40 +93,516 @@ pub use session::{Session, SessionId};
 #[derive(Clone)]
 pub struct B2BUA {
     sessions: Arc<RwLock<HashMap<SessionId, Session>>>,
+    admission: Option<Arc<AdmissionController>>,
+    quotas: Option<Quotas>,
+    cdr_sink: Option<mpsc::UnboundedSender<CallDetailRecord>>,
+    tracer: Option<Arc<CallTracer>>,
+    registrar: Option<Registrar>,
+    domains: DomainMap,
+    nat: NatPolicy,
+    sampler: Option<TrafficSampler>,
+    cos: Option<Arc<CosPolicy>>,
+    account_codes: Option<Arc<AccountCodeConfig>>,
+    dial_pins: Option<Arc<DialPinConfig>>,
+    dial_pin_audit: Option<mpsc::UnboundedSender<DialPinAttempt>>,
+    pin_lockouts: PinLockouts,
+    callbacks: Option<CallbackQueue>,
+    callback_sink: Option<mpsc::UnboundedSender<CallbackRequest>>,
+    screening: Option<Arc<ScreeningConfig>>,
+    no_answer: Option<NoAnswerPolicy>,
+    voicemail_drop: Option<VoicemailDropConfig>,
+    voicemail_retrieval: Option<VoicemailRetrieval>,
+    /// RTP endpoints of calls in the retrieval IVR, by Call-ID
+    ivr_media: Arc<RwLock<HashMap<String, IvrMedia>>>,
+    sms: Option<SmsGateway>,
+    events: Option<EventBus>,
+    missed_calls: Option<Arc<MissedCallConfig>>,
+    crm: Option<Arc<CrmClient>>,
+    portability: Option<PortabilityDip>,
+    enum_resolver: Option<EnumResolver>,
+    wholesale: Option<Arc<WholesaleGateway>>,
+    quirks: Option<Arc<QuirksConfig>>,
+    dial_strings: Option<Arc<DialStringConfig>>,
+    source_acl: Option<Arc<Acl>>,
+    routing: Option<Arc<RouteEvaluator>>,
+    outbound_sink: Option<mpsc::UnboundedSender<OutboundRequest>>,
+    relay_sink: Option<mpsc::UnboundedSender<OutboundResponse>>,
+    pdd: Option<PddTracker>,
+    media_quality: Option<MediaQualityTracker>,
+    mwi: Option<MwiNotifier>,
+    presence: Option<ResourceListServer>,
+    transcription: Option<Arc<TranscriptionPolicy>>,
+    announcements: Option<AnnouncementMixer>,
+    /// Certificate of the DTLS handshakes that key browser calls' SRTP
+    webrtc: Option<DtlsIdentity>,
+    /// Address put in the Via of requests we originate
+    local_addr: Option<SocketAddr>,
+    /// Set while handing over to a new process during an upgrade
+    draining: Arc<AtomicBool>,
+    /// INVITEs being set up, by [`invite_key`]
+    invites_in_progress: Arc<Mutex<HashSet<String>>>,
 }
 
 impl B2BUA {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            admission: None,
+            quotas: None,
+            cdr_sink: None,
+            tracer: None,
+            domains: DomainMap::default(),
+            nat: NatPolicy::default(),
+            registrar: None,
+            sampler: None,
+            cos: None,
+            account_codes: None,
+            dial_pins: None,
+            dial_pin_audit: None,
+            pin_lockouts: PinLockouts::default(),
+            callbacks: None,
+            callback_sink: None,
+            screening: None,
+            no_answer: None,
+            voicemail_drop: None,
+            voicemail_retrieval: None,
+            ivr_media: Arc::new(RwLock::new(HashMap::new())),
+            sms: None,
+            events: None,
+            missed_calls: None,
+            crm: None,
+            portability: None,
+            enum_resolver: None,
+            wholesale: None,
+            quirks: None,
+            dial_strings: None,
+            source_acl: None,
+            routing: None,
+            outbound_sink: None,
+            relay_sink: None,
+            pdd: None,
+            media_quality: None,
+            mwi: None,
+            presence: None,
+            transcription: None,
+            announcements: None,
+            webrtc: None,
+            local_addr: None,
+            draining: Arc::new(AtomicBool::new(false)),
+            invites_in_progress: Arc::default(),
         }
     }
 
+    /// Enforce call admission limits on new INVITEs
+    pub fn with_admission_control(mut self, controller: Arc<AdmissionController>) -> Self {
+        self.admission = Some(controller);
+        self
+    }
+
+    /// Enforce each tenant's concurrent call quota on new INVITEs
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// Ring only endpoints registered in the domain called, and route and
+    /// count tenant quotas by canonical domain rather than alias
+    pub fn with_domains(mut self, domains: DomainMap) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Rewrite the Contact and SDP of calls from a SIP profile's endpoints
+    /// as its NAT settings say
+    pub fn with_nat_policy(mut self, policy: NatPolicy) -> Self {
+        self.nat = policy;
+        self
+    }
+
+    /// Emit a call detail record for every session that ends
+    pub fn with_cdr_sink(mut self, sink: mpsc::UnboundedSender<CallDetailRecord>) -> Self {
+        self.cdr_sink = Some(sink);
+        self
+    }
+
+    /// Capture per-call debug traces
+    pub fn with_call_tracer(mut self, tracer: Arc<CallTracer>) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Accept REGISTER requests into a registrar
+    pub fn with_registrar(mut self, registrar: Registrar) -> Self {
+        self.registrar = Some(registrar);
+        self
+    }
+
+    /// Record new calls for shadow evaluation of configuration changes
+    pub fn with_traffic_sampler(mut self, sampler: TrafficSampler) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    /// Enforce calling permissions on new INVITEs before they are routed
+    pub fn with_class_of_service(mut self, policy: Arc<CosPolicy>) -> Self {
+        self.cos = Some(policy);
+        self
+    }
+
+    /// Collect account codes on new INVITEs and require them where configured
+    pub fn with_account_codes(mut self, config: Arc<AccountCodeConfig>) -> Self {
+        self.account_codes = Some(config);
+        self
+    }
+
+    /// Require a PIN for outbound calls from shared phones
+    pub fn with_dial_pins(mut self, config: Arc<DialPinConfig>) -> Self {
+        self.dial_pins = Some(config);
+        self
+    }
+
+    /// Deliver an audit record for every dialing PIN check
+    pub fn with_dial_pin_audit(mut self, sink: mpsc::UnboundedSender<DialPinAttempt>) -> Self {
+        self.dial_pin_audit = Some(sink);
+        self
+    }
+
+    /// Let callers camp on busy extensions with the callback feature code
+    pub fn with_callbacks(mut self, queue: CallbackQueue) -> Self {
+        self.callbacks = Some(queue);
+        self
+    }
+
+    /// Deliver callbacks whose target has become free, to be placed as new
+    /// calls
+    pub fn with_callback_sink(mut self, sink: mpsc::UnboundedSender<CallbackRequest>) -> Self {
+        self.callback_sink = Some(sink);
+        self
+    }
+
+    /// Announce or screen incoming calls to configured extensions
+    pub fn with_screening(mut self, config: Arc<ScreeningConfig>) -> Self {
+        self.screening = Some(config);
+        self
+    }
+
+    /// Stop ringing extensions after their ring time and apply their
+    /// no-answer action
+    pub fn with_no_answer(mut self, policy: NoAnswerPolicy) -> Self {
+        self.no_answer = Some(policy);
+        self
+    }
+
+    /// Send calls dialed with the voicemail drop prefix, or carrying the
+    /// voicemail drop header, straight to the callee's voicemail
+    pub fn with_voicemail_drop(mut self, config: VoicemailDropConfig) -> Self {
+        self.voicemail_drop = Some(config);
+        self
+    }
+
+    /// Answer calls to the voicemail retrieval feature code with the
+    /// retrieval IVR
+    pub fn with_voicemail_retrieval(mut self, retrieval: VoicemailRetrieval) -> Self {
+        self.voicemail_retrieval = Some(retrieval);
+        self
+    }
+
+    /// Send SIP MESSAGE requests to external numbers on as SMS
+    pub fn with_sms_gateway(mut self, gateway: SmsGateway) -> Self {
+        self.sms = Some(gateway);
+        self
+    }
+
+    /// Publish ringing, answered and hangup events for every call
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Publish missed call events for unanswered calls to extensions
+    pub fn with_missed_calls(mut self, config: Arc<MissedCallConfig>) -> Self {
+        self.missed_calls = Some(config);
+        self
+    }
+
+    /// Attach the caller's CRM record to ringing events
+    pub fn with_crm(mut self, crm: Arc<CrmClient>) -> Self {
+        self.crm = Some(crm);
+        self
+    }
+
+    /// Dip destinations in a number portability database before routing
+    pub fn with_portability(mut self, dip: PortabilityDip) -> Self {
+        self.portability = Some(dip);
+        self
+    }
+
+    /// Look destinations up in ENUM for routes that deliver over it
+    pub fn with_enum(mut self, resolver: EnumResolver) -> Self {
+        self.enum_resolver = Some(resolver);
+        self
+    }
+
+    /// Accept INVITEs from wholesale carrier peers by source address
+    pub fn with_wholesale(mut self, gateway: Arc<WholesaleGateway>) -> Self {
+        self.wholesale = Some(gateway);
+        self
+    }
+
+    /// Adjust requests to trunks bound to a compatibility profile
+    pub fn with_quirks(mut self, quirks: Arc<QuirksConfig>) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// Address INVITEs to trunks by their dial string templates
+    pub fn with_dial_strings(mut self, config: Arc<DialStringConfig>) -> Self {
+        self.dial_strings = Some(config);
+        self
+    }
+
     /// Handle incoming SIP message
+    /// Refuse INVITEs from source addresses the ACL does not allow
+    pub fn with_source_acl(mut self, acl: Arc<Acl>) -> Self {
+        self.source_acl = Some(acl);
+        self
+    }
+
+    /// Evaluate routes for new INVITEs, refusing those a route rejects
+    pub fn with_routing(mut self, evaluator: Arc<RouteEvaluator>) -> Self {
+        self.routing = Some(evaluator);
+        self
+    }
+
+    /// Send requests the B2BUA originates, such as INVITEs forked to each
+    /// registered contact of an extension, to `sink`
+    ///
+    /// `local_addr` is the address those requests name in their Via, where
+    /// the responses are expected.
+    pub fn with_outbound_sink(
+        mut self,
+        sink: mpsc::UnboundedSender<OutboundRequest>,
+        local_addr: SocketAddr,
+    ) -> Self {
+        self.outbound_sink = Some(sink);
+        self.local_addr = Some(local_addr);
+        self
+    }
+
+    /// Send the callee's ringing and answer, relayed to the caller, to
+    /// `sink`
+    pub fn with_relay_sink(mut self, sink: mpsc::UnboundedSender<OutboundResponse>) -> Self {
+        self.relay_sink = Some(sink);
+        self
+    }
+
+    /// Record each call's post-dial delay against the trunk it was sent to
+    pub fn with_pdd_tracker(mut self, tracker: PddTracker) -> Self {
+        self.pdd = Some(tracker);
+        self
+    }
+
+    /// Record the RTP statistics reported when a call hangs up against
+    /// the trunk it was sent to
+    pub fn with_media_quality(mut self, tracker: MediaQualityTracker) -> Self {
+        self.media_quality = Some(tracker);
+        self
+    }
+
+    /// Accept message waiting SUBSCRIBEs and notify phones as they
+    /// register
+    pub fn with_mwi(mut self, notifier: MwiNotifier) -> Self {
+        self.mwi = Some(notifier);
+        self
+    }
+
+    /// Accept SUBSCRIBEs to BLF lists
+    pub fn with_presence(mut self, server: ResourceListServer) -> Self {
+        self.presence = Some(server);
+        self
+    }
+
+    /// Decide as calls are routed which of them have their audio streamed
+    /// for transcription
+    pub fn with_transcription(mut self, policy: Arc<TranscriptionPolicy>) -> Self {
+        self.transcription = Some(policy);
+        self
+    }
+
+    /// Mix announcements played over answered calls into their media
+    pub fn with_announcements(mut self, mixer: AnnouncementMixer) -> Self {
+        self.announcements = Some(mixer);
+        self
+    }
+
+    /// Relay the media of calls from browsers, terminating their DTLS-SRTP
+    /// with `identity`, so they can reach endpoints that only speak RTP
+    pub fn with_webrtc_media(mut self, identity: DtlsIdentity) -> Self {
+        self.webrtc = Some(identity);
+        self
+    }
+
     pub async fn handle_message(&self, message: Message) -> Result<Option<Message>> {
-        match message {
-            Message::Request(req) => self.handle_request(req).await,
+        self.process_message(message, None).await
+    }
+
+    /// Handle incoming SIP message received from `source`
+    pub async fn handle_message_from(
+        &self,
+        message: Message,
+        source: SocketAddr,
+    ) -> Result<Option<Message>> {
+        self.process_message(message, Some(source)).await
+    }
+
+    async fn process_message(
+        &self,
+        message: Message,
+        source: Option<SocketAddr>,
+    ) -> Result<Option<Message>> {
+        // Calls stay traced until the reply to their BYE/CANCEL is captured
+        let ending_call = match &message {
+            Message::Request(req) if matches!(req.method, Method::Bye | Method::Cancel) => {
+                req.get_header_value("Call-ID").map(str::to_string)
+            }
+            _ => None,
+        };
+
+        if let Some(tracer) = &self.tracer {
+            tracer.trace_message(TraceDirection::Inbound, &message, source);
+        }
+
+        let reply = match message {
+            Message::Request(req) => self.handle_request(req, source).await,
             Message::Response(res) => self.handle_response(res).await,
+        }?;
+
+        if let (Some(tracer), Some(reply)) = (&self.tracer, &reply) {
+            tracer.trace_message(TraceDirection::Outbound, reply, source);
+        }
+        if let (Some(tracer), Some(call_id)) = (&self.tracer, ending_call) {
+            tracer.end_call(&call_id);
+        }
+
+        Ok(reply)
+    }
+
+    /// Record a decision in the call's debug trace, if it is being traced
+    fn trace_note(&self, call_id: &str, text: &str) {
+        if let Some(tracer) = &self.tracer {
+            tracer.note(call_id, text);
         }
     }
 
     /// Handle SIP request
-    async fn handle_request(&self, request: Request) -> Result<Option<Message>> {
+    async fn handle_request(
+        &self,
+        request: Request,
+        source: Option<SocketAddr>,
+    ) -> Result<Option<Message>> {
         info!("Handling {} request to {}", request.method, request.uri);
 
+        let capabilities =
+            self.capabilities_for(request.get_header_value("From").and_then(uri_host));
+        if !capabilities.allows(request.method) {
+            debug!("Method {} not allowed", request.method);
+            let response = capabilities.method_not_allowed(&self.builder(), &request);
+            return Ok(Some(Message::Response(response)));
+        }
+
+        if request.method != Method::Register {
+            if let Some(response) = self.check_sequence(&request).await {
+                return Ok(Some(Message::Response(response)));
+            }
+        }
+
         match request.method {
-            Method::Invite => self.handle_invite(request).await,
+            Method::Invite => self.handle_invite(request, source).await,
+            Method::Register if self.registrar.is_some() => {
+                self.handle_register(request, source).await
+            }
             Method::Bye => self.handle_bye(request).await,
             Method::Options => self.handle_options(request).await,
             Method::Ack => self.handle_ack(request).await,
             Method::Cancel => self.handle_cancel(request).await,
+            Method::Info => self.handle_info(request).await,
+            Method::Update => self.handle_in_dialog(request).await,
+            Method::Message if self.sms.is_some() => self.handle_sip_message(request).await,
+            Method::Subscribe if self.mwi.is_some() || self.presence.is_some() => {
+                self.handle_subscribe(request, source).await
+            }
             _ => {
                 debug!("Method {} not implemented", request.method);
-                Ok(Some(Message::Response(Response::new(
-                    StatusCode::NOT_IMPLEMENTED,
-                ))))
+                Ok(Some(Message::Response(
+                    self.response(&request, StatusCode::NOT_IMPLEMENTED).build(),
+                )))
+            }
+        }
+    }
+
+    /// Methods and extensions offered by the modules configured
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        if self.registrar.is_some() {
+            capabilities = capabilities
+                .with_method(Method::Register)
+                .with_supported(OUTBOUND)
+                .with_supported(GRUU);
+        }
+        if self.sms.is_some() {
+            capabilities = capabilities.with_method(Method::Message);
+        }
+        if self.mwi.is_some() {
+            capabilities = capabilities.with_method(Method::Subscribe);
+        }
+        if self.presence.is_some() {
+            capabilities = capabilities
+                .with_method(Method::Subscribe)
+                .with_supported(EVENTLIST);
+        }
+        capabilities
+    }
+
+    /// Capabilities offered to `peer`, narrowed by the quirk profile it is
+    /// bound to
+    fn capabilities_for(&self, peer: Option<&str>) -> Capabilities {
+        let capabilities = self.capabilities();
+        match peer.and_then(|host| self.quirks.as_ref()?.for_trunk(host)) {
+            Some(quirks) => capabilities.for_profile(&quirks),
+            None => capabilities,
+        }
+    }
+
+    /// Refuse a request in an existing call whose CSeq is malformed or
+    /// goes backwards for the side that sent it
+    ///
+    /// Requests are matched to the caller's or callee's dialog by their
+    /// tags, or before the dialogs exist by their From tag.
+    async fn check_sequence(&self, request: &Request) -> Option<Response> {
+        let call_id = request.get_header_value("Call-ID")?;
+        let mut sessions = self.sessions.write().await;
+        let session = sessions.values_mut().find(|s| s.call_id() == call_id)?;
+        let from_tag = request.get_header_value("From").and_then(header_tag);
+        let side = match session.dialog_side(request) {
+            Some(side) => side,
+            None if from_tag.is_some() && from_tag != session.caller().and_then(header_tag) => {
+                LegSide::B
             }
+            None => LegSide::A,
+        };
+        let error = session.sequence_mut().check(side, request).err()?;
+        drop(sessions);
+
+        warn!("Refusing {} for {}: {}", request.method, call_id, error);
+        self.trace_note(call_id, &format!("{} refused: {}", request.method, error));
+        let mut response = self
+            .response(request, error.status_code())
+            .header("Warning", format!("399 rustalk \"{}\"", error));
+        if let SequenceError::OutOfOrder { .. } = error {
+            let retry_after: u32 = rand::thread_rng().gen_range(0..=10);
+            response = response.header("Retry-After", retry_after.to_string());
         }
+        Some(response.build())
     }
 
     /// Handle SIP response
@@ -65,166 +614,6242 @@ impl B2BUA {
             .get_header_value("Call-ID")
             .ok_or_else(|| anyhow::anyhow!("No Call-ID in response"))?;
 
-        let sessions = self.sessions.read().await;
-        if let Some(session) = sessions.values().find(|s| s.call_id() == call_id) {
-            // Forward response to the other leg
-            debug!("Forwarding response to other leg");
-            // In a real implementation, we would modify headers and forward
-            Ok(None)
-        } else {
+        // Replies to message waiting and BLF NOTIFYs belong to no call
+        let notify_response = response
+            .get_header_value("CSeq")
+            .is_some_and(|cseq| cseq.trim_end().ends_with("NOTIFY"));
+        if notify_response && (self.mwi.is_some() || self.presence.is_some()) {
+            if let Some(mwi) = &self.mwi {
+                mwi.notify_failed(&response);
+            }
+            if let Some(presence) = &self.presence {
+                presence.notify_failed(&response);
+            }
+            return Ok(None);
+        }
+
+        let mut sessions = self.sessions.write().await;
+        let Some(session) = sessions.values_mut().find(|s| s.call_id() == call_id) else {
             error!("No session found for Call-ID: {}", call_id);
-            Ok(None)
+            return Ok(None);
+        };
+        session.record_decision(CallDecision::response(LegSide::B, response.status_code));
+
+        // Post-dial delay runs to the first ringing, progress or answer
+        let invite_response = response
+            .get_header_value("CSeq")
+            .is_some_and(|cseq| cseq.trim_end().ends_with("INVITE"));
+        if invite_response && (180..300).contains(&response.status_code.0) {
+            if let Some((trunk, delay)) = session.record_progress() {
+                debug!("Post-dial delay via {}: {:?}", trunk, delay);
+                if let Some(pdd) = &self.pdd {
+                    pdd.record(trunk, delay);
+                }
+            }
+            if let Some(codec) = sdp::audio_codec(&String::from_utf8_lossy(&response.body)) {
+                session.set_codec(LegSide::B, codec);
+            }
         }
-    }
 
-    /// Handle INVITE request - establish new session
-    async fn handle_invite(&self, request: Request) -> Result<Option<Message>> {
-        let call_id = request
-            .get_header_value("Call-ID")
-            .ok_or_else(|| anyhow::anyhow!("No Call-ID in INVITE"))?
-            .to_string();
+        let mut status = response.status_code;
+        let mut reason = response.get_header_value("Reason").map(str::to_string);
+        let fork = response
+            .get_header_value("Via")
+            .and_then(fork::via_branch)
+            .and_then(|branch| session.forks().iter().position(|f| f.branch == branch));
+        if let Some(index) = fork {
+            // Replies to our own CANCELs need no handling
+            if response
+                .get_header_value("CSeq")
+                .is_some_and(|cseq| cseq.trim_end().ends_with("CANCEL"))
+            {
+                return Ok(None);
+            }
+            match self.handle_fork_response(session, index, &response).await {
+                Some((final_status, final_reason)) => {
+                    status = final_status;
+                    reason = final_reason;
+                }
+                None => return Ok(None),
+            }
+        }
 
-        info!("Creating new session for Call-ID: {}", call_id);
+        let busy = matches!(status, StatusCode::BUSY_HERE | StatusCode::BUSY_EVERYWHERE);
+        if busy {
+            if let (Some(callbacks), Some(caller), Some(callee)) = (
+                &self.callbacks,
+                session.caller().and_then(uri_user),
+                session.callee().and_then(uri_user),
+            ) {
+                callbacks.record_busy(caller, callee);
+            }
+        }
+        // Authentication challenges are answered, not the end of the call
+        if status.0 >= 400
+            && !matches!(
+                status,
+                StatusCode::UNAUTHORIZED | StatusCode::PROXY_AUTHENTICATION_REQUIRED
+            )
+        {
+            let cause = reason
+                .as_deref()
+                .and_then(HangupCause::from_reason)
+                .unwrap_or_else(|| HangupCause::from_sip_status(status));
+            // The route's next destination, or a reroute, may still take
+            // the call
+            session.fail_attempt(Some(status.0));
+            let failure = format!("{} ({})", status.0, cause);
+            let next = match session
+                .failover_mut()
+                .filter(|plan| plan.fails_over(status.0))
+                .and_then(FailoverPlan::advance)
+            {
+                Some(hop) => self.fail_over(session, hop, &failure, false).await,
+                None => self.reroute(session, status.0, &failure).await,
+            };
+            if let Some(invites) = next {
+                drop(sessions);
+                self.send_outbound(invites);
+                return Ok(None);
+            }
+            let mut refusal = Response::new(status);
+            if let Some(reason) = &reason {
+                refusal = refusal.with_header("Reason", reason.as_str());
+            }
+            self.answer_caller(session, &refusal);
+            drop(sessions);
+            let call_id = call_id.to_string();
+            let disposition = if busy {
+                self.trace_note(&call_id, "callee busy");
+                CallDisposition::Busy
+            } else {
+                self.trace_note(&call_id, &format!("call failed: {}", cause));
+                CallDisposition::Failed
+            };
+            self.end_session(&call_id, Some(disposition), cause).await;
+            return Ok(None);
+        }
 
-        // Create new session
-        let session = Session::new(call_id.clone());
+        debug!("Response {} matches no branch of {}", status, call_id);
+        Ok(None)
+    }
 
-        let mut sessions = self.sessions.write().await;
-        sessions.insert(session.id().clone(), session);
+    /// Apply a contact's response to its branch of a forked call
+    ///
+    /// The first contact to answer gets the call and the others are
+    /// cancelled. Returns the status and `Reason` that end the call once
+    /// every contact has refused it, or `None` while the call goes on.
+    async fn handle_fork_response(
+        &self,
+        session: &mut Session,
+        index: usize,
+        response: &Response,
+    ) -> Option<(StatusCode, Option<String>)> {
+        let status = response.status_code;
+        let call_id = session.call_id().to_string();
+        let fork = &mut session.forks_mut()[index];
+        let contact = fork.contact.clone();
+        if let Some(registrar) = &self.registrar {
+            registrar
+                .set_reachable(&contact, !fork::is_unreachable(status))
+                .await;
+        }
+        // Late responses from branches already settled; the callee
+        // retransmits its 200 until it has our ACK
+        if !fork.is_pending() {
+            if fork.state == ForkState::Answered && status.is_success() {
+                self.send_outbound(self.ack_answer(session).into_iter().collect());
+            }
+            return None;
+        }
 
-        // Send 100 Trying
-        let response = Response::new(StatusCode::TRYING).with_header("Call-ID", call_id.as_str());
+        match status.0 {
+            100..=199 => {
+                if status != StatusCode::TRYING {
+                    fork.state = ForkState::Ringing;
+                    self.answer_caller(session, response);
+                }
+                None
+            }
+            200..=299 => {
+                fork.state = ForkState::Answered;
+                if let Some(dialog) = Dialog::uac(fork.request(), response) {
+                    session.set_dialog(LegSide::B, dialog);
+                }
+                self.answer_caller(session, response);
+                self.send_outbound(self.ack_answer(session).into_iter().collect());
+                // The contact's 200 answers the call; the caller's ACK only
+                // confirms its dialog
+                if session.answered_at().is_none() {
+                    self.publish_event(CallEvent::new(CallEventKind::Answered, session));
+                }
+                session.mark_answered();
+                let cancels: Vec<_> = session
+                    .forks_mut()
+                    .iter_mut()
+                    .filter(|f| f.is_pending())
+                    .map(|f| {
+                        f.state = ForkState::Cancelled;
+                        f.cancel(Some(fork::COMPLETED_ELSEWHERE))
+                    })
+                    .collect();
+                let note = format!(
+                    "answered at {}, cancelling {} other contact(s)",
+                    contact,
+                    cancels.len()
+                );
+                self.trace_note(&call_id, &note);
+                session.record_decision(CallDecision::new(DecisionStage::Route, true, note));
+                self.send_outbound(cancels);
+                None
+            }
+            _ => {
+                fork.state = ForkState::Failed;
+                fork.status = Some(status);
+                fork.reason = response.get_header_value("Reason").map(str::to_string);
+                self.trace_note(&call_id, &format!("{} refused: {}", contact, status.0));
+                if session
+                    .forks()
+                    .iter()
+                    .any(|f| f.is_pending() || f.state == ForkState::Answered)
+                {
+                    return None;
+                }
+                fork::best_failure(session.forks())
+                    .and_then(|f| Some((f.status?, f.reason.clone())))
+            }
+        }
+    }
 
-        Ok(Some(Message::Response(response)))
+    /// Fork an INVITE for a registered extension to each of its contacts,
+    /// returning the INVITEs to send
+    async fn fork_to_contacts(
+        &self,
+        request: &Request,
+        session: &mut Session,
+    ) -> Vec<OutboundRequest> {
+        let (Some(registrar), Some(_), Some(local_addr)) =
+            (&self.registrar, &self.outbound_sink, self.local_addr)
+        else {
+            return Vec::new();
+        };
+        let Some(callee) = request
+            .uri
+            .user
+            .as_deref()
+            .filter(|_| !session.voicemail_drop())
+        else {
+            return Vec::new();
+        };
+        // A GRUU rings only the instance it names
+        let bindings = match registrar.resolve_gruu(&request.uri).await {
+            Some(bindings) => bindings,
+            None => {
+                // In a domain a SIP profile serves, only its own extension
+                // rings, not the same number in another domain
+                let domain = self.domains.canonical(&request.uri.host);
+                let mut bindings = registrar.bindings().await;
+                bindings.retain(|r| {
+                    uri_user(&r.aor) == Some(callee)
+                        && domain.as_deref().is_none_or(|domain| {
+                            uri_host(&r.aor).is_some_and(|h| h.eq_ignore_ascii_case(domain))
+                        })
+                });
+                bindings
+            }
+        };
+        let bindings: Vec<_> = bindings.iter().collect();
+        // One live flow per outbound instance
+        let bindings = select_flows(&bindings);
+        if bindings.is_empty() {
+            return Vec::new();
+        }
+        // Every branch is the same transaction, so shares one CSeq
+        let cseq = session.sequence_mut().next_local(Method::Invite);
+        // Browsers are offered the caller's own WebRTC media, not the relay
+        let browser_invite = session
+            .webrtc_relay()
+            .and(session.caller_invite())
+            .map(|invite| {
+                let mut browser_invite = request.clone();
+                set_sdp(&mut browser_invite, &String::from_utf8_lossy(&invite.body));
+                browser_invite
+            });
+        let forks: Vec<ForkBranch> = bindings
+            .into_iter()
+            .filter_map(|binding| {
+                let invite = browser_invite
+                    .as_ref()
+                    .filter(|_| is_websocket_contact(&binding.contact))
+                    .unwrap_or(request);
+                ForkBranch::new(invite, binding, local_addr, cseq)
+            })
+            .collect();
+        if forks.is_empty() {
+            return Vec::new();
+        }
+
+        let contacts: Vec<&str> = forks.iter().map(|f| f.contact.as_str()).collect();
+        let note = format!(
+            "forking {} to {} contact(s): {}",
+            callee,
+            forks.len(),
+            contacts.join(", ")
+        );
+        self.trace_note(session.call_id(), &note);
+        session.record_decision(CallDecision::new(DecisionStage::Route, true, note));
+        let invites = forks
+            .iter()
+            .map(|f| OutboundRequest {
+                request: f.request().clone(),
+                destination: f.destination,
+            })
+            .collect();
+        session.add_forks(forks);
+        invites
     }
 
-    /// Handle BYE request - terminate session
-    async fn handle_bye(&self, request: Request) -> Result<Option<Message>> {
-        let call_id = request
-            .get_header_value("Call-ID")
-            .ok_or_else(|| anyhow::anyhow!("No Call-ID in BYE"))?;
+    /// Point a browser's offer at a media relay of our own, so callees that
+    /// only speak RTP are offered plain RTP
+    ///
+    /// The caller's INVITE keeps the browser's offer, which contacts that are
+    /// browsers themselves are sent as it is.
+    async fn relay_webrtc_offer(&self, request: &mut Request, session: &mut Session) {
+        let (Some(identity), Some(local_addr)) = (&self.webrtc, self.local_addr) else {
+            return;
+        };
+        let offer = String::from_utf8_lossy(&request.body).into_owned();
+        if !webrtc::sdp::is_webrtc(&offer) {
+            return;
+        }
+        match WebRtcRelay::offer(local_addr.ip(), identity.clone(), &offer).await {
+            Ok((relay, plain)) => {
+                set_sdp(request, &plain);
+                let note = format!("WebRTC media relayed at {}", relay.rtp_addr());
+                self.trace_note(session.call_id(), &note);
+                session.set_webrtc_relay(relay);
+            }
+            Err(e) => warn!(
+                "Not relaying WebRTC media of {}: {:#}",
+                session.call_id(),
+                e
+            ),
+        }
+    }
 
-        info!("Terminating session for Call-ID: {}", call_id);
+    /// Builds the messages we send, from the address outbound requests
+    /// name in their Via
+    fn builder(&self) -> MessageBuilder {
+        let local = self
+            .local_addr
+            .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 5060)));
+        MessageBuilder::new(LocalProfile::new(
+            "UDP",
+            local.ip().to_string(),
+            local.port(),
+        ))
+    }
 
-        // Find and remove session
-        let mut sessions = self.sessions.write().await;
-        let session_id = sessions
-            .values()
-            .find(|s| s.call_id() == call_id)
-            .map(|s| s.id().clone());
+    /// A response to `request`, echoing its Via, From, To, Call-ID and CSeq
+    fn response(&self, request: &Request, status: StatusCode) -> ResponseBuilder {
+        self.builder().response(request, status)
+    }
 
-        if let Some(id) = session_id {
-            sessions.remove(&id);
-            info!("Session terminated: {:?}", id);
+    /// Send the caller a response to its INVITE, in the caller's dialog:
+    /// the callee's ringing, answer or refusal, or our 487 once cancelled
+    fn answer_caller(&self, session: &mut Session, response: &Response) {
+        let (Some(sink), Some(invite), Some(dialog), Some(leg)) = (
+            &self.relay_sink,
+            session.caller_invite(),
+            session.dialog(LegSide::A),
+            session.a_leg(),
+        ) else {
+            return;
+        };
+        let mut builder = self
+            .response(invite, response.status_code)
+            .set_header("To", dialog.local_header());
+        if !response.body.is_empty() {
+            let content_type = response
+                .get_header_value("Content-Type")
+                .unwrap_or("application/sdp");
+            let mut body = response.body.clone();
+            // A plain answer to a browser's relayed offer is dressed up for
+            // the browser; one from another browser is passed on as it is
+            if let Some(relay) = session.webrtc_relay() {
+                let answer = String::from_utf8_lossy(&response.body);
+                if !webrtc::sdp::is_webrtc(&answer) {
+                    match relay.answer(&answer) {
+                        Ok(answer) => body = answer.into(),
+                        Err(e) => warn!("Not relaying WebRTC media: {:#}", e),
+                    }
+                }
+            }
+            builder = builder.body(content_type, body);
+        }
+        if let Some(reason) = response.get_header_value("Reason") {
+            builder = builder.header("Reason", reason);
+        }
+        let mut relayed = builder.build();
+        relayed.reason_phrase = response.reason_phrase.clone();
+        let destination = leg.remote_addr;
+        session.set_caller_response(relayed.clone());
+        if let Some(tracer) = &self.tracer {
+            tracer.trace_message(
+                TraceDirection::Outbound,
+                &Message::Response(relayed.clone()),
+                Some(destination),
+            );
+        }
+        let relayed = OutboundResponse {
+            response: relayed,
+            destination,
+        };
+        if sink.send(relayed).is_err() {
+            debug!("Relayed response receiver dropped");
         }
+    }
 
-        // Send 200 OK
-        let response = Response::new(StatusCode::OK).with_header("Call-ID", call_id);
+    /// ACK for the callee's answer, sent to the branch that answered
+    fn ack_answer(&self, session: &Session) -> Option<OutboundRequest> {
+        self.local_addr?;
+        let destination = session
+            .forks()
+            .iter()
+            .find(|f| f.state == ForkState::Answered)?
+            .destination;
+        let dialog = session
+            .dialog(LegSide::B)
+            .filter(|d| d.state != DialogState::Terminated)?;
+        let request = self
+            .builder()
+            .in_dialog(Method::Ack, &mut dialog.context())
+            .build();
+        Some(OutboundRequest {
+            request,
+            destination,
+        })
+    }
 
-        Ok(Some(Message::Response(response)))
+    /// BYE ending one leg's dialog, or `None` when the leg has no dialog
+    /// left to end
+    fn bye_leg(
+        &self,
+        session: &mut Session,
+        side: LegSide,
+        reason: Option<&str>,
+    ) -> Option<OutboundRequest> {
+        self.local_addr?;
+        let destination = match side {
+            LegSide::A => session.a_leg().map(|leg| leg.remote_addr),
+            LegSide::B => session
+                .forks()
+                .iter()
+                .find(|f| f.state == ForkState::Answered)
+                .map(|f| f.destination)
+                .or_else(|| session.b_leg().map(|leg| leg.remote_addr)),
+        }?;
+        let dialog = session
+            .dialog_mut(side)
+            .filter(|d| d.state != DialogState::Terminated)?;
+        let mut context = dialog.context();
+        let mut builder = self.builder().in_dialog(Method::Bye, &mut context);
+        if let Some(reason) = reason {
+            builder = builder.header("Reason", reason);
+        }
+        dialog.local_cseq = context.cseq;
+        dialog.state = DialogState::Terminated;
+        Some(OutboundRequest {
+            request: builder.build(),
+            destination,
+        })
     }
 
-    /// Handle OPTIONS request - capability query
-    async fn handle_options(&self, request: Request) -> Result<Option<Message>> {
-        info!("Handling OPTIONS request");
+    /// Hand requests we originate to the outbound sink
+    fn send_outbound(&self, requests: Vec<OutboundRequest>) {
+        let Some(sink) = &self.outbound_sink else {
+            return;
+        };
+        for outbound in requests {
+            if let Some(tracer) = &self.tracer {
+                tracer.trace_message(
+                    TraceDirection::Outbound,
+                    &Message::Request(outbound.request.clone()),
+                    Some(outbound.destination),
+                );
+            }
+            if sink.send(outbound).is_err() {
+                debug!("Outbound request receiver dropped");
+            }
+        }
+    }
 
+    /// Handle INVITE request - establish new session
+    async fn handle_invite(
+        &self,
+        mut request: Request,
+        source: Option<SocketAddr>,
+    ) -> Result<Option<Message>> {
         let call_id = request
             .get_header_value("Call-ID")
-            .unwrap_or("none")
+            .ok_or_else(|| anyhow::anyhow!("No Call-ID in INVITE"))?
             .to_string();
 
-        // Send 200 OK with capabilities
-        let response = Response::new(StatusCode::OK)
-            .with_header("Call-ID", call_id.as_str())
-            .with_header("Allow", "INVITE, ACK, BYE, CANCEL, OPTIONS, INFO")
-            .with_header("Accept", "application/sdp")
-            .with_header("Supported", "replaces, timer");
+        // A To tag makes this a re-INVITE in a dialog we already have
+        if request
+            .get_header_value("To")
+            .and_then(header_tag)
+            .is_some()
+        {
+            return self.handle_in_dialog(request).await;
+        }
 
-        Ok(Some(Message::Response(response)))
-    }
+        // A retransmission gets the last response its caller was sent
+        // rather than setting the call up again, and one arriving while the
+        // call is still being set up is dropped
+        let _in_progress = match invite_key(&request) {
+            Some(key) => {
+                let Some(in_progress) = InviteInProgress::start(&self.invites_in_progress, &key)
+                else {
+                    debug!("INVITE {} retransmitted while being set up", call_id);
+                    return Ok(None);
+                };
+                let sessions = self.sessions.read().await;
+                if let Some(session) = sessions
+                    .values()
+                    .find(|s| s.caller_invite().and_then(invite_key).as_ref() == Some(&key))
+                {
+                    debug!("INVITE {} retransmitted", call_id);
+                    return Ok(session.caller_response().cloned().map(Message::Response));
+                }
+                Some(in_progress)
+            }
+            None => None,
+        };
 
-    /// Handle ACK request
-    async fn handle_ack(&self, _request: Request) -> Result<Option<Message>> {
-        debug!("Handling ACK request");
-        // ACK doesn't require a response
-        Ok(None)
-    }
+        if self.is_draining()
+            && !self
+                .sessions
+                .read()
+                .await
+                .values()
+                .any(|s| s.call_id() == call_id)
+        {
+            self.trace_note(&call_id, "rejected: draining for upgrade");
+            let response = self
+                .response(&request, StatusCode::SERVICE_UNAVAILABLE)
+                .header("Retry-After", "1")
+                .build();
+            return Ok(Some(Message::Response(response)));
+        }
 
-    /// Handle CANCEL request
-    async fn handle_cancel(&self, request: Request) -> Result<Option<Message>> {
-        let call_id = request
-            .get_header_value("Call-ID")
-            .ok_or_else(|| anyhow::anyhow!("No Call-ID in CANCEL"))?;
+        if let Some(response) = self.handle_callback_request(&request, &call_id) {
+            return Ok(Some(Message::Response(response)));
+        }
 
-        info!("Canceling session for Call-ID: {}", call_id);
+        if let Some(response) = self.start_voicemail_retrieval(&request, &call_id).await {
+            return Ok(Some(Message::Response(response)));
+        }
 
-        let response = Response::new(StatusCode::OK).with_header("Call-ID", call_id);
+        // Calls from a profile's endpoints behind NAT are answered where
+        // their packets come from, not the private address they advertise
+        let nat = request
+            .get_header_value("From")
+            .and_then(uri_host)
+            .and_then(|host| {
+                self.nat
+                    .profile_for_domain(&self.domains.canonicalize(host))
+            });
+        if let (Some(source), Some(settings)) = (source, nat) {
+            if nat::rewrite_far_end(&mut request, source, &settings) {
+                self.trace_note(&call_id, &format!("far-end NAT: rewritten to {}", source));
+            }
+        }
 
-        Ok(Some(Message::Response(response)))
-    }
+        let mut session = Session::new(call_id.clone());
+        session.set_dialed(request.uri.user.clone());
+        if let Some(dialog) = Dialog::uas(&request, new_tag()) {
+            session.set_dialog(LegSide::A, dialog);
+        }
+        session.set_caller_invite(request.clone());
+        if let Err(error) = session.sequence_mut().check(LegSide::A, &request) {
+            self.trace_note(&call_id, &format!("rejected: {}", error));
+            let response = self
+                .response(&request, error.status_code())
+                .header("Warning", format!("399 rustalk \"{}\"", error))
+                .build();
+            return Ok(Some(Message::Response(response)));
+        }
 
-    /// Get session count
-    pub async fn session_count(&self) -> usize {
-        self.sessions.read().await.len()
-    }
-}
+        if let Some(response) = self.check_source_acl(&request, source, &mut session) {
+            return Ok(Some(Message::Response(response)));
+        }
 
-impl Default for B2BUA {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+        if let Some(response) = self.check_gruu(&request, &mut session).await {
+            return Ok(Some(Message::Response(response)));
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::sip::Uri;
+        if let Some(response) = self.admit_carrier_peer(&mut request, source, &mut session) {
+            return Ok(Some(Message::Response(response)));
+        }
 
-    #[tokio::test]
-    async fn test_b2bua_options() {
-        let b2bua = B2BUA::new();
+        // The tenant is the caller's domain or the one called, before
+        // routing points the request at a trunk
+        let tenant = self.quotas.as_ref().and_then(|quotas| {
+            let from = request
+                .get_header_value("From")
+                .and_then(uri_host)
+                .map(|host| self.domains.canonicalize(host));
+            let called = self.domains.canonicalize(&request.uri.host);
+            quotas.tenant_of(from.iter().map(String::as_str).chain([called.as_str()]))
+        });
 
-        let request = Request::new(
-            Method::Options,
-            Uri::new("sip".to_string(), "example.com".to_string()),
-        )
-        .with_header("Call-ID", "test123");
+        if let Some(drop) = &self.voicemail_drop {
+            let dialed = request.uri.user.as_deref().unwrap_or("");
+            if let Some(extension) = drop.target(dialed).map(str::to_string) {
+                request.uri.user = Some(extension);
+                session.set_voicemail_drop();
+            } else if request
+                .get_header_value(VOICEMAIL_DROP_HEADER)
+                .is_some_and(wants_voicemail_drop)
+            {
+                session.set_voicemail_drop();
+            }
+            if session.voicemail_drop() {
+                let note = format!(
+                    "straight to voicemail for {}",
+                    request.uri.user.as_deref().unwrap_or("")
+                );
+                self.trace_note(&call_id, &note);
+                session.record_decision(CallDecision::new(DecisionStage::Route, true, note));
+            }
+        }
 
-        let result = b2bua.handle_message(Message::Request(request)).await;
-        assert!(result.is_ok());
+        if let Some(response) = self.dip_portability(&mut request, &mut session).await {
+            self.release_carrier_peer(&session);
+            return Ok(Some(Message::Response(response)));
+        }
 
-        let response = result.unwrap();
-        assert!(response.is_some());
+        let enum_uri = self.resolve_enum(&request, &mut session).await;
+        if let Some(response) = self.evaluate_route(&mut request, &mut session, enum_uri) {
+            self.release_carrier_peer(&session);
+            return Ok(Some(Message::Response(response)));
+        }
 
-        if let Some(Message::Response(res)) = response {
-            assert_eq!(res.status_code, StatusCode::OK);
+        // Check calling permissions before the call reaches a trunk
+        if let Some(response) = self.check_calling_policy(&mut request, source, &mut session) {
+            self.release_carrier_peer(&session);
+            return Ok(Some(Message::Response(response)));
         }
-    }
 
-    #[tokio::test]
-    async fn test_b2bua_invite_bye() {
-        let b2bua = B2BUA::new();
+        if let (Some(quotas), Some(tenant)) = (&self.quotas, tenant) {
+            if let Err(exceeded) = quotas.start_call(&tenant) {
+                warn!("Rejecting INVITE {}: {}", call_id, exceeded);
+                let note = format!("quota rejected: {}", exceeded);
+                self.trace_note(&call_id, &note);
+                session.record_decision(CallDecision::new(DecisionStage::Admission, false, note));
+                let response = self
+                    .response(&request, StatusCode::SERVICE_UNAVAILABLE)
+                    .header("Retry-After", "5")
+                    .build();
+                self.release_carrier_peer(&session);
+                return Ok(Some(Message::Response(response)));
+            }
+            session.set_quota_tenant(tenant);
+        }
+
+        // Check admission limits for the trunk the call leaves by, or else
+        // the one it came in on; calls between local users touch no trunk
+        let admission_trunk = session
+            .route_trunk()
+            .or(session.carrier_peer())
+            .map(str::to_string);
+        if let (Some(admission), Some(trunk)) = (&self.admission, admission_trunk) {
+            let number = request.uri.user.as_deref().unwrap_or("");
+            let call = CallDetails {
+                call_class: Some(
+                    session
+                        .call_class()
+                        .unwrap_or_else(|| default_classifier().classify(number)),
+                ),
+                number,
+            };
+            if let AdmissionDecision::Reject(reason) = admission.admit_call(&trunk, &call).await {
+                warn!("Rejecting INVITE {} on {}: {}", call_id, trunk, reason);
+                let note = format!("admission rejected on {}: {}", trunk, reason);
+                self.trace_note(&call_id, &note);
+                session.record_decision(CallDecision::new(DecisionStage::Admission, false, note));
+                let response = self
+                    .response(&request, reason.status_code())
+                    .header("Retry-After", reason.retry_after().to_string())
+                    .build();
+                self.release_carrier_peer(&session);
+                self.release_quota(&session);
+                return Ok(Some(Message::Response(response)));
+            }
+            let note = format!("admission granted on {}", trunk);
+            self.trace_note(&call_id, &note);
+            session.record_decision(CallDecision::new(DecisionStage::Admission, true, note));
+            session.set_admission_key(trunk);
+        }
+
+        session.set_parties(
+            request.get_header_value("From").map(str::to_string),
+            request.get_header_value("To").map(str::to_string),
+        );
+        session.set_correlation_id(
+            request
+                .get_header_value(CORRELATION_ID_HEADER)
+                .map(str::to_string),
+        );
+        // Voicemail drops never ring the callee, so there is nothing to screen
+        if let Some(screening) = self
+            .screening
+            .as_ref()
+            .filter(|_| !session.voicemail_drop())
+        {
+            let callee = request.uri.user.as_deref().unwrap_or("");
+            let mode = screening.mode_for(callee, None);
+            if mode != ScreeningMode::Off {
+                let from = request.get_header_value("From").unwrap_or("");
+                let announcement = Announcement {
+                    recorded_name: None,
+                    cnam: display_name(from).map(str::to_string),
+                    number: uri_user(from).unwrap_or("").to_string(),
+                };
+                self.trace_note(&call_id, &format!("announcing: {}", announcement.text()));
+                session.set_screening_mode(mode);
+                if mode == ScreeningMode::Announce {
+                    session.set_screening_outcome(ScreeningOutcome::Announced);
+                }
+            }
+        }
+        if let Some(policy) = self
+            .no_answer
+            .as_ref()
+            .filter(|_| !session.voicemail_drop())
+        {
+            let callee = request.uri.user.as_deref().unwrap_or("");
+            if let Some(timeout) = policy.timeout_for(callee) {
+                self.trace_note(
+                    &call_id,
+                    &format!(
+                        "ringing {} for {}s, then {}",
+                        callee, timeout.ring_seconds, timeout.action
+                    ),
+                );
+                session.set_ring_timeout(timeout);
+            }
+        }
+        if let Some(sampler) = &self.sampler {
+            sampler.record(TrafficSample {
+                caller_id: request
+                    .get_header_value("From")
+                    .and_then(uri_user)
+                    .unwrap_or("")
+                    .to_string(),
+                destination: request
+                    .uri
+                    .user
+                    .clone()
+                    .unwrap_or_else(|| request.uri.host.clone()),
+                source_ip: source.map(|s| s.ip()),
+                timestamp: session.created_at(),
+            });
+        }
+        if let Some(source) = source {
+            let mut leg = CallLeg::new(
+                source,
+                request.get_header_value("From").unwrap_or("").to_string(),
+                request.get_header_value("To").unwrap_or("").to_string(),
+            );
+            if let Some(contact) = request.get_header_value("Contact") {
+                leg = leg.with_contact(contact.to_string());
+            }
+            if !request.body.is_empty() {
+                leg = leg.with_sdp(String::from_utf8_lossy(&request.body).into_owned());
+            }
+            session.set_a_leg(leg);
+        }
+        if let Some(codec) = sdp::audio_codec(&String::from_utf8_lossy(&request.body)) {
+            session.set_codec(LegSide::A, codec);
+        }
+
+        self.relay_webrtc_offer(&mut request, &mut session).await;
+        session.set_invite(request.clone());
+        session.add_target(
+            CallTarget::new(request.uri.to_string()).with_destination(session.route_destination()),
+        );
+        session.start_attempt(CallAttempt::new(
+            session.route_destination(),
+            request.uri.to_string(),
+        ));
+        let mut invites = self.fork_to_contacts(&request, &mut session).await;
+
+        // Parties are recorded, so the INVITE can now be shaped for the trunk
+        let trunk = self.shape_for_trunk(&mut request, &mut session);
+        let mut unreachable = false;
+        if invites.is_empty() {
+            match self.dial_trunk(&request, &mut session).await {
+                Ok(invite) => invites.extend(invite),
+                Err(e) => {
+                    warn!("Not sending INVITE {}: {:#}", call_id, e);
+                    let note = format!("{:#}", e);
+                    self.trace_note(&call_id, &note);
+                    session.record_decision(CallDecision::new(DecisionStage::Trunk, false, note));
+                    unreachable = true;
+                }
+            }
+        }
+        let status = if unreachable {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::TRYING
+        };
+        session.record_decision(CallDecision::response(LegSide::A, status));
+
+        self.publish_ringing(&session);
+        session.start_dialing(trunk);
+        let trying = self.response(&request, StatusCode::TRYING).build();
+        session.set_caller_response(trying.clone());
+
+        info!("Creating new session for Call-ID: {}", call_id);
+        self.trace_note(&call_id, &format!("session {} created", session.id()));
+
+        let mut sessions = self.sessions.write().await;
+        sessions.insert(session.id().clone(), session);
+        drop(sessions);
+        if unreachable {
+            self.end_session(
+                &call_id,
+                Some(CallDisposition::Failed),
+                HangupCause::NoRouteDestination,
+            )
+            .await;
+            let response = self.response(&request, status).build();
+            return Ok(Some(Message::Response(response)));
+        }
+        self.send_outbound(invites);
+
+        Ok(Some(Message::Response(trying)))
+    }
+
+    /// Shape an INVITE for the trunk in its Request-URI, with the trunk's
+    /// dial string, capabilities and quirks, returning the trunk
+    fn shape_for_trunk(&self, request: &mut Request, session: &mut Session) -> String {
+        let call_id = session.call_id().to_string();
+        let trunk = request.uri.host.clone();
+        if let Some(template) = self.dial_strings.as_ref().and_then(|d| d.for_trunk(&trunk)) {
+            match template.apply(request) {
+                Ok(()) => self.trace_note(&call_id, &format!("dialing {}", request.uri)),
+                Err(e) => {
+                    warn!("Dial string for {} not applied: {}", trunk, e);
+                    let note = format!("dial string not applied: {}", e);
+                    self.trace_note(&call_id, &note);
+                    session.record_decision(CallDecision::new(DecisionStage::Trunk, false, note));
+                }
+            }
+        }
+        self.capabilities_for(Some(&trunk))
+            .apply_to_request(request);
+        if let Some(quirks) = self.quirks.as_ref().and_then(|q| q.for_trunk(&trunk)) {
+            quirks.apply_to_request(request);
+            let note = format!("{} quirks applied for {}", quirks.profile, trunk);
+            self.trace_note(&call_id, &note);
+            session.record_decision(CallDecision::new(DecisionStage::Trunk, true, note));
+        }
+        session.record_decision(CallDecision::new(
+            DecisionStage::Trunk,
+            true,
+            format!("dialing {} via {}", request.uri, trunk),
+        ));
+        trunk
+    }
+
+    /// Point an INVITE's Request-URI at the host and port `trunk` is
+    /// probed at, returning false when its address is not configured
+    fn address_trunk(&self, request: &mut Request, trunk: &str) -> bool {
+        let Some((host, port)) = self.routing.as_ref().and_then(|r| r.trunk_target(trunk)) else {
+            return false;
+        };
+        request.uri.host = host;
+        request.uri.port = Some(port);
+        true
+    }
+
+    /// Send a call routed to a trunk on to it, as the call's only branch
+    ///
+    /// Returns `None` when the call is not bound for a trunk or there is
+    /// nowhere to send from, and an error when the trunk cannot be found.
+    async fn dial_trunk(
+        &self,
+        request: &Request,
+        session: &mut Session,
+    ) -> Result<Option<OutboundRequest>> {
+        let (Some(_), Some(local_addr), Some(trunk)) =
+            (&self.outbound_sink, self.local_addr, session.route_trunk())
+        else {
+            return Ok(None);
+        };
+        let destination = fork::trunk_address(&request.uri)
+            .await
+            .with_context(|| format!("trunk {} unreachable", trunk))?;
+        let note = format!("sending to trunk {} at {}", trunk, destination);
+        let cseq = session.sequence_mut().next_local(Method::Invite);
+        let branch = ForkBranch::to_trunk(request, destination, local_addr, cseq);
+        let invite = OutboundRequest {
+            request: branch.request().clone(),
+            destination,
+        };
+        self.trace_note(session.call_id(), &note);
+        session.record_decision(CallDecision::new(DecisionStage::Trunk, true, note));
+        session.add_forks(vec![branch]);
+        Ok(Some(invite))
+    }
+
+    /// Check the INVITE's source address against the source ACL
+    fn check_source_acl(
+        &self,
+        request: &Request,
+        source: Option<SocketAddr>,
+        session: &mut Session,
+    ) -> Option<Response> {
+        let acl = self.source_acl.as_ref()?;
+        let ip = source?.ip();
+        let rule = acl.matching_rule(ip).ok().flatten();
+        let allowed = acl.is_allowed(ip).unwrap_or(false);
+        let decided_by = match rule {
+            Some(rule) => format!("rule {}", rule.name),
+            None => "default policy".to_string(),
+        };
+        let verdict = if allowed { "allowed" } else { "denied" };
+        session.record_decision(CallDecision::new(
+            DecisionStage::Acl,
+            allowed,
+            format!("{} {} by ACL {} ({})", ip, verdict, acl.name, decided_by),
+        ));
+        if allowed {
+            return None;
+        }
+        warn!(
+            "Denying INVITE {} from {}: not allowed by ACL {}",
+            session.call_id(),
+            ip,
+            acl.name
+        );
+        let class = default_classifier().classify(request.uri.user.as_deref().unwrap_or(""));
+        let reason = format!("source {} not allowed", ip);
+        Some(self.deny_invite(request, session, class, reason))
+    }
+
+    /// Refuse an INVITE for a GRUU whose instance is no longer registered
+    /// (RFC 5627 section 7)
+    async fn check_gruu(&self, request: &Request, session: &mut Session) -> Option<Response> {
+        let bindings = self.registrar.as_ref()?.resolve_gruu(&request.uri).await?;
+        if !bindings.is_empty() {
+            return None;
+        }
+        let note = format!("GRUU {} has no registered instance", request.uri);
+        warn!("Refusing INVITE {}: {}", session.call_id(), note);
+        self.trace_note(session.call_id(), &note);
+        session.record_decision(CallDecision::new(DecisionStage::Route, false, note));
+        Some(
+            self.response(request, StatusCode::TEMPORARILY_UNAVAILABLE)
+                .build(),
+        )
+    }
+
+    /// Record the route an INVITE matches and refuse it when the route
+    /// rejects the call
+    ///
+    /// Voicemail drops have already been routed and are not evaluated.
+    /// Look the destination up in the number portability database and
+    /// rewrite it with the LRN; a 503 when the dip fails and the
+    /// configuration says to reject
+    async fn dip_portability(
+        &self,
+        request: &mut Request,
+        session: &mut Session,
+    ) -> Option<Response> {
+        let dip = self.portability.as_ref()?;
+        if session.voicemail_drop() {
+            return None;
+        }
+        let user = request.uri.user.clone()?;
+        let result = dip.dip(&request.uri.host, &user).await;
+        let (passed, note) = match &result {
+            DipResult::Skipped => return None,
+            DipResult::NotPorted => (true, format!("{} is not ported", user)),
+            DipResult::Ported(lrn) => (true, format!("{} is ported to LRN {}", user, lrn)),
+            DipResult::Failed(error) => (
+                dip.config().on_failure == LnpFailure::Continue,
+                format!("portability dip for {} failed: {}", user, error),
+            ),
+        };
+        let call_id = session.call_id().to_string();
+        self.trace_note(&call_id, &note);
+        session.record_decision(CallDecision::new(DecisionStage::Portability, passed, note));
+        if !passed {
+            session.record_decision(CallDecision::response(
+                LegSide::A,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ));
+            return Some(
+                self.response(request, StatusCode::SERVICE_UNAVAILABLE)
+                    .header("Warning", "399 rustalk \"Portability dip failed\"")
+                    .build(),
+            );
+        }
+        if let Some(rewritten) = dip.rewrite(&user, &result) {
+            request.uri.user = Some(rewritten);
+        }
+        None
+    }
+
+    /// SIP URI the destination maps to in ENUM, looked up only when a route
+    /// uses the mapping
+    async fn resolve_enum(&self, request: &Request, session: &mut Session) -> Option<String> {
+        let resolver = self.enum_resolver.as_ref()?;
+        let evaluator = self.routing.as_ref()?;
+        if session.voicemail_drop() || !evaluator.config().uses_enum() {
+            return None;
+        }
+        let user = request.uri.user.as_deref()?;
+        let note = match resolver.resolve(user).await {
+            Ok(Some(uri)) => {
+                self.trace_note(
+                    session.call_id(),
+                    &format!("{} maps to {} in ENUM", user, uri),
+                );
+                return Some(uri);
+            }
+            Ok(None) => return None,
+            Err(e) => format!("ENUM lookup for {} failed: {:#}", user, e),
+        };
+        warn!("{}", note);
+        self.trace_note(session.call_id(), &note);
+        None
+    }
+
+    fn evaluate_route(
+        &self,
+        request: &mut Request,
+        session: &mut Session,
+        enum_uri: Option<String>,
+    ) -> Option<Response> {
+        let evaluator = self.routing.as_ref()?;
+        if session.voicemail_drop() {
+            return None;
+        }
+        // Dipped destinations route on their LRN
+        let destination = lnp::routing_number(request.uri.user.as_deref().unwrap_or(""));
+        let context = CallContext {
+            caller_id: request
+                .get_header_value("From")
+                .and_then(uri_user)
+                .unwrap_or("")
+                .to_string(),
+            destination: destination.clone(),
+            enum_uri,
+            domain: Some(self.domains.canonicalize(&request.uri.host)),
+        };
+        let Some(route_match) = evaluator.evaluate(&context) else {
+            session.record_decision(CallDecision::new(
+                DecisionStage::Route,
+                true,
+                format!("no route matched {}", destination),
+            ));
+            return None;
+        };
+
+        let mut detail = format!(
+            "route {} ({}) matched {}",
+            route_match.route_id, route_match.route_name, destination
+        );
+        if let Some(route) = evaluator
+            .config()
+            .routes
+            .iter()
+            .find(|r| r.id == route_match.route_id)
+        {
+            detail.push_str(&format!(" on pattern {}", route.pattern));
+            let conditions: Vec<String> = route.conditions.iter().flatten().map(describe).collect();
+            if !conditions.is_empty() {
+                detail.push_str(&format!(" with {}", conditions.join(", ")));
+            }
+        }
+        detail.push_str(&format!(
+            " -> {}",
+            destination_label(&route_match.destination)
+        ));
+        if let Some((cheapest, fallback)) = route_match.least_cost.split_first() {
+            detail.push_str(&format!(", cheapest {}", cheapest));
+            if !fallback.is_empty() {
+                let fallback: Vec<String> = fallback.iter().map(|q| q.to_string()).collect();
+                detail.push_str(&format!(", then {}", fallback.join(", ")));
+            }
+        } else if let Some((_, fallback)) = route_match.hops.split_first() {
+            if !fallback.is_empty() {
+                let fallback: Vec<String> = fallback
+                    .iter()
+                    .map(|hop| destination_label(&hop.destination))
+                    .collect();
+                detail.push_str(&format!(", falling back to {}", fallback.join(", then ")));
+            }
+        }
+        for rule in &route_match.on_failure {
+            let hops: Vec<String> = rule
+                .destinations
+                .hops
+                .iter()
+                .map(|hop| destination_label(&hop.destination))
+                .collect();
+            if !hops.is_empty() {
+                detail.push_str(&format!(
+                    ", on {} rerouting to {}",
+                    rule.codes.join("/"),
+                    hops.join(", then ")
+                ));
+            }
+        }
+        if !route_match.trunks_down.is_empty() {
+            detail.push_str(&format!(
+                ", passing over trunk(s) {} that are down",
+                route_match.trunks_down.join(", ")
+            ));
+        }
+        if !route_match.bypassed.is_empty() {
+            detail.push_str(&format!(
+                ", passing over route(s) {} whose trunk is down",
+                route_match.bypassed.join(", ")
+            ));
+        }
+
+        let rejected = matches!(route_match.action, RouteAction::Reject);
+        let mut route_trunk = match &route_match.destination {
+            RouteDestination::Trunk(trunk) | RouteDestination::Enum(trunk) => Some(trunk.clone()),
+            _ => None,
+        };
+        if let (RouteDestination::Enum(trunk), false) = (&route_match.destination, rejected) {
+            // Deliver straight to the mapped URI; unmapped numbers carry on
+            // to the fallback trunk
+            match context.enum_uri.as_deref().map(dial_string::parse_uri) {
+                Some(Ok(uri)) => {
+                    detail.push_str(&format!(", delivered over ENUM to {}", uri));
+                    request.uri = uri;
+                    route_trunk = None;
+                }
+                Some(Err(e)) => detail.push_str(&format!(
+                    ", unusable ENUM URI ({:#}), via trunk {}",
+                    e, trunk
+                )),
+                None => detail.push_str(&format!(", no ENUM mapping, via trunk {}", trunk)),
+            }
+        }
+        self.trace_note(session.call_id(), &detail);
+
+        session.record_decision(CallDecision::new(DecisionStage::Route, !rejected, detail));
+        if !rejected {
+            session.set_route_destination(destination_label(&route_match.destination));
+            if let Some(trunk) = route_trunk {
+                self.address_trunk(request, &trunk);
+                session.set_route_trunk(trunk);
+            }
+            let timed = route_match
+                .hops
+                .first()
+                .is_some_and(|hop| hop.timeout_seconds.is_some());
+            session.set_reroutes(route_match.on_failure.clone());
+            if route_match.hops.len() > 1 || timed {
+                session.set_failover(FailoverPlan::new(
+                    route_match.hops.clone(),
+                    route_match.failover.clone().unwrap_or_default(),
+                ));
+            }
+            self.plan_transcription(request, session, &context, &route_match);
+            return None;
+        }
+        let class = session
+            .call_class()
+            .unwrap_or_else(|| default_classifier().classify(&destination));
+        let reason = format!("rejected by route {}", route_match.route_id);
+        Some(self.deny_invite(request, session, class, reason))
+    }
+
+    /// Mark a routed call for transcription when the policy selects it
+    fn plan_transcription(
+        &self,
+        request: &Request,
+        session: &mut Session,
+        context: &CallContext,
+        route_match: &RouteMatch,
+    ) {
+        let Some(policy) = &self.transcription else {
+            return;
+        };
+        let call = CallMetadata {
+            call_id: session.call_id().to_string(),
+            caller: context.caller_id.clone(),
+            callee: context.destination.clone(),
+            route: Some(route_match.route_id.clone()),
+            queue: match &route_match.destination {
+                RouteDestination::RingGroup(group) => Some(group.clone()),
+                _ => None,
+            },
+            consent: request
+                .get_header_value(&policy.config().consent.header)
+                .map(str::to_string),
+            started_at: session.created_at(),
+        };
+        let note = match policy.select(&call) {
+            Ok(plan) => {
+                let legs: Vec<String> = plan.legs.legs().iter().map(|l| l.to_string()).collect();
+                let mut note = format!("transcription streams the {} audio", legs.join(" and "));
+                if let Some(announcement) = &plan.announcement {
+                    note.push_str(&format!(" after announcing {}", announcement));
+                }
+                session.set_transcription(plan);
+                note
+            }
+            Err(skip) => format!("not transcribed: {}", skip),
+        };
+        self.trace_note(session.call_id(), &note);
+    }
+
+    /// Identify an INVITE from a wholesale peer, strip its tech-prefix and
+    /// take a slot on the peer. INVITEs from elsewhere pass through untouched.
+    fn admit_carrier_peer(
+        &self,
+        request: &mut Request,
+        source: Option<SocketAddr>,
+        session: &mut Session,
+    ) -> Option<Response> {
+        let gateway = self.wholesale.as_ref()?;
+        let dialed = request.uri.user.as_deref().unwrap_or("");
+        let call = gateway.identify(source?, dialed)?;
+        let peer = call.peer.name.clone();
+        let call_id = session.call_id().to_string();
+
+        if let Err(rejection) = gateway.admit(&call) {
+            warn!("Rejecting INVITE {} from {}: {}", call_id, peer, rejection);
+            let note = format!("peer {} rejected: {}", peer, rejection);
+            self.trace_note(&call_id, &note);
+            session.record_decision(CallDecision::new(
+                DecisionStage::Authentication,
+                false,
+                note,
+            ));
+            let status = match rejection {
+                PeerRejection::AtCapacity { .. } => StatusCode::SERVICE_UNAVAILABLE,
+                PeerRejection::NoRate => StatusCode::FORBIDDEN,
+            };
+            return Some(
+                self.response(request, status)
+                    .header("Warning", format!("399 rustalk \"{}\"", rejection))
+                    .build(),
+            );
+        }
+
+        self.trace_note(
+            &call_id,
+            &format!("carrier peer {} dialing {}", peer, call.number),
+        );
+        session.record_decision(CallDecision::new(
+            DecisionStage::Authentication,
+            true,
+            format!("carrier peer {} identified by source address", peer),
+        ));
+        request.uri.user = Some(call.number.clone());
+        session.set_carrier_peer(peer, call.number);
+        None
+    }
+
+    /// Return the peer slot taken by `admit_carrier_peer`
+    fn release_carrier_peer(&self, session: &Session) {
+        if let (Some(gateway), Some(peer)) = (&self.wholesale, session.carrier_peer()) {
+            gateway.release(peer);
+        }
+    }
+
+    /// Return the call counted against the session's tenant
+    fn release_quota(&self, session: &Session) {
+        if let (Some(quotas), Some(tenant)) = (&self.quotas, session.quota_tenant()) {
+            quotas.end_call(tenant);
+        }
+    }
+
+    /// Queue a callback when the INVITE dials the callback feature code
+    fn handle_callback_request(&self, request: &Request, call_id: &str) -> Option<Response> {
+        let callbacks = self.callbacks.as_ref()?;
+        let dialed = request.uri.user.as_deref()?;
+        if !callbacks.is_feature_code(dialed) {
+            return None;
+        }
+        let caller = request
+            .get_header_value("From")
+            .and_then(uri_user)
+            .unwrap_or("");
+
+        let response = match callbacks.request(caller, dialed) {
+            Ok(queued) => {
+                info!(
+                    "Queued callback for {} when {} is free",
+                    caller, queued.target
+                );
+                self.trace_note(call_id, &format!("callback queued on {}", queued.target));
+                self.response(request, StatusCode::OK)
+            }
+            Err(reason) => {
+                self.trace_note(call_id, &format!("callback refused: {}", reason));
+                self.response(request, StatusCode::NOT_FOUND)
+                    .header("Warning", format!("399 rustalk \"{}\"", reason))
+            }
+        };
+        Some(response.build())
+    }
+
+    /// Answer a call to the voicemail retrieval feature code
+    async fn start_voicemail_retrieval(
+        &self,
+        request: &Request,
+        call_id: &str,
+    ) -> Option<Response> {
+        let retrieval = self.voicemail_retrieval.as_ref()?;
+        let dialed = request.uri.user.as_deref()?;
+        if !retrieval.is_feature_code(dialed) {
+            return None;
+        }
+        let caller = request
+            .get_header_value("From")
+            .and_then(uri_user)
+            .unwrap_or("");
+
+        info!("{} dialed voicemail retrieval", caller);
+        self.trace_note(call_id, "voicemail retrieval answered");
+        // Retrieval is answered before routing, so there is no route yet
+        let context = LocaleContext {
+            tenant: Some(request.uri.host.as_str()),
+            did: Some(dialed),
+            route: None,
+        };
+        let actions = retrieval.start_for(call_id, caller, dialed, &context).await;
+        let mut response = self.response(request, StatusCode::OK);
+        if let Some(answer) = self.start_ivr_media(request, call_id).await {
+            response = response.body("application/sdp", answer);
+        }
+        self.run_retrieval_actions(call_id, actions).await;
+        Some(response.build())
+    }
+
+    /// Answer the SDP offer of a call into the retrieval IVR with an RTP
+    /// endpoint of our own, returning the SDP answer
+    ///
+    /// Without an offer or a local address to advertise the call is
+    /// answered without media, and keys can only arrive in SIP INFO.
+    async fn start_ivr_media(&self, request: &Request, call_id: &str) -> Option<String> {
+        let local_addr = self.local_addr?;
+        if request.body.is_empty() {
+            return None;
+        }
+        let offer = String::from_utf8_lossy(&request.body);
+        let (media, answer, mut digits) = match IvrMedia::answer(&offer, local_addr.ip()).await {
+            Ok(answered) => answered,
+            Err(e) => {
+                warn!("No media for voicemail retrieval call {}: {:#}", call_id, e);
+                return None;
+            }
+        };
+        self.ivr_media
+            .write()
+            .await
+            .insert(call_id.to_string(), media);
+
+        let b2bua = self.clone();
+        let call_id = call_id.to_string();
+        tokio::spawn(async move {
+            // Ends when the endpoint is closed
+            while let Some(digit) = digits.recv().await {
+                b2bua.retrieval_key(&call_id, digit).await;
+            }
+        });
+        Some(answer)
+    }
+
+    /// Handle a key pressed in the retrieval IVR, by RFC 4733 event or INFO
+    async fn retrieval_key(&self, call_id: &str, digit: char) {
+        let Some(retrieval) = &self.voicemail_retrieval else {
+            return;
+        };
+        // A key cuts the prompt playing short
+        if let Some(media) = self.ivr_media.read().await.get(call_id) {
+            media.stop_playback();
+        }
+        if let Some(actions) = retrieval.press(call_id, digit).await {
+            self.run_retrieval_actions(call_id, actions).await;
+        }
+    }
+
+    /// Carry out what the retrieval IVR asked for after a key press
+    async fn run_retrieval_actions(&self, call_id: &str, actions: Vec<RetrievalAction>) {
+        let media = self.ivr_media.read().await.get(call_id).cloned();
+        for action in actions {
+            self.trace_note(call_id, &format!("voicemail retrieval: {}", action));
+            match action {
+                RetrievalAction::Callback { extension, number } => {
+                    let Some(sink) = &self.callback_sink else {
+                        debug!("No callback receiver for voicemail callback to {}", number);
+                        continue;
+                    };
+                    // Placed straight away, so it is never queued
+                    let now = chrono::Utc::now();
+                    let request = CallbackRequest {
+                        caller: extension,
+                        target: number,
+                        requested_at: now,
+                        expires_at: now + chrono::Duration::minutes(1),
+                    };
+                    if sink.send(request).is_err() {
+                        debug!("Callback receiver dropped");
+                    }
+                }
+                RetrievalAction::Prompt(prompt) => {
+                    let Some(media) = &media else { continue };
+                    match &prompt.file {
+                        Some(file) => {
+                            if let Err(e) = media.play_file(file).await {
+                                warn!("Failed to play prompt {}: {:#}", prompt.key, e);
+                            }
+                        }
+                        None => debug!("No {} recording of prompt {}", prompt.locale, prompt.key),
+                    }
+                }
+                RetrievalAction::Play { file_path, .. } => {
+                    let Some(media) = &media else { continue };
+                    if let Err(e) = media.play_file(&file_path).await {
+                        warn!("Failed to play voicemail {}: {:#}", file_path, e);
+                    }
+                }
+                RetrievalAction::Record => {
+                    let Some(media) = &media else { continue };
+                    media.play(&ivr::beep());
+                    media.start_recording();
+                }
+                RetrievalAction::FinishRecording { mailbox_id } => {
+                    let Some(media) = &media else { continue };
+                    let recording = media.stop_recording().unwrap_or_default();
+                    let (Some(mailbox_id), Some(retrieval)) =
+                        (mailbox_id, &self.voicemail_retrieval)
+                    else {
+                        continue;
+                    };
+                    if recording.is_empty() {
+                        debug!("Nothing recorded for greeting of {}", mailbox_id);
+                        continue;
+                    }
+                    match retrieval
+                        .save_greeting(&mailbox_id, &wav::encode(&recording))
+                        .await
+                    {
+                        Ok(path) => info!("Saved greeting of {} to {}", mailbox_id, path.display()),
+                        Err(e) => warn!("Failed to save greeting of {}: {:#}", mailbox_id, e),
+                    }
+                }
+                RetrievalAction::Hangup => {}
+            }
+        }
+    }
+
+    /// Stop the RTP endpoint of a call that left the retrieval IVR
+    async fn end_ivr_media(&self, call_id: &str) {
+        if let Some(media) = self.ivr_media.write().await.remove(call_id) {
+            media.close();
+        }
+    }
+
+    /// Whether `extension` is a party to an active session
+    async fn is_in_call(&self, extension: &str) -> bool {
+        let sessions = self.sessions.read().await;
+        sessions.values().any(|s| {
+            [s.caller(), s.callee()]
+                .into_iter()
+                .flatten()
+                .any(|party| uri_user(party) == Some(extension))
+        })
+    }
+
+    /// Hand over callbacks waiting on `extension` once it is registered and
+    /// not in a call
+    async fn release_callbacks(&self, extension: &str) {
+        let Some(callbacks) = &self.callbacks else {
+            return;
+        };
+        if self.is_in_call(extension).await {
+            return;
+        }
+        if let Some(registrar) = &self.registrar {
+            let registered = registrar
+                .bindings()
+                .await
+                .iter()
+                .any(|r| uri_user(&r.aor) == Some(extension));
+            if !registered {
+                return;
+            }
+        }
+
+        for request in callbacks.take_ready(extension) {
+            info!(
+                "{} is free, calling back {}",
+                request.target, request.caller
+            );
+            if let Some(sink) = &self.callback_sink {
+                if sink.send(request).is_err() {
+                    debug!("Callback receiver dropped");
+                }
+            }
+        }
+    }
+
+    /// Apply class of service, dialing PINs and account codes to a new INVITE
+    ///
+    /// Strips an account code prefix from the Request-URI and returns the
+    /// rejection to send when the call is not permitted.
+    fn check_calling_policy(
+        &self,
+        request: &mut Request,
+        source: Option<SocketAddr>,
+        session: &mut Session,
+    ) -> Option<Response> {
+        if self.cos.is_none() && self.dial_pins.is_none() && self.account_codes.is_none() {
+            return None;
+        }
+        let call_id = session.call_id().to_string();
+
+        let mut account_code = None;
+        if let Some(codes) = &self.account_codes {
+            if let Some(user) = &request.uri.user {
+                let dialed = codes.parse_dialed(user);
+                request.uri.user = Some(dialed.number);
+                account_code = dialed.account_code;
+            }
+            account_code = account_code.or_else(|| {
+                request
+                    .get_header_value(ACCOUNT_CODE_HEADER)
+                    .map(str::to_string)
+            });
+        }
+
+        let caller = request
+            .get_header_value("From")
+            .and_then(uri_user)
+            .unwrap_or("")
+            .to_string();
+        let number = request.uri.user.clone().unwrap_or_default();
+
+        let class = match &self.cos {
+            Some(cos) => {
+                let pin = request.get_header_value(OVERRIDE_PIN_HEADER);
+                match cos.check(&caller, &number, pin) {
+                    CosDecision::Deny {
+                        class,
+                        profile,
+                        reason,
+                    } => {
+                        warn!(
+                            "Denying INVITE {} from {} to {} ({}): {}",
+                            call_id, caller, number, profile, reason
+                        );
+                        session.record_decision(CallDecision::new(
+                            DecisionStage::Policy,
+                            false,
+                            format!("class of service denied {} call under {}", class, profile),
+                        ));
+                        return Some(self.deny_invite(request, session, class, reason));
+                    }
+                    CosDecision::Allow {
+                        class,
+                        profile,
+                        authorized_by_pin,
+                    } => {
+                        if authorized_by_pin {
+                            info!(
+                                "INVITE {} from {} authorized by override PIN for {}",
+                                call_id, caller, profile
+                            );
+                        }
+                        let note =
+                            format!("class of service allowed {} call under {}", class, profile);
+                        self.trace_note(&call_id, &note);
+                        session.record_decision(CallDecision::new(
+                            DecisionStage::Policy,
+                            true,
+                            note,
+                        ));
+                        session.set_call_class(class, authorized_by_pin);
+                        class
+                    }
+                }
+            }
+            None => {
+                let class = default_classifier().classify(&number);
+                session.set_call_class(class, false);
+                class
+            }
+        };
+
+        if let Some(pins) = &self.dial_pins {
+            let pin = request.get_header_value(DIAL_PIN_HEADER);
+            let now = Instant::now();
+            // Guesses are counted against the address they come from, as
+            // the From header is the caller's to choose. Requests handed
+            // over without one share a count.
+            let address = source.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |s| s.ip());
+            let mut locked_out = false;
+            let mut decision = pins.check(&caller, class, pin);
+            if decision != DialPinDecision::NotRequired {
+                // A locked out address is refused even with a valid PIN, and
+                // an invalid PIN counts towards locking it out
+                if self.pin_lockouts.is_locked(address, now) {
+                    locked_out = true;
+                    decision = DialPinDecision::Reject("too many invalid dialing PINs".to_string());
+                } else if pin.is_some() && matches!(decision, DialPinDecision::Reject(_)) {
+                    locked_out = self.pin_lockouts.record_failure(pins, address, now);
+                    if locked_out {
+                        warn!(
+                            "{} (extension {}) locked out of dialing for {}s after {} invalid PINs",
+                            address, caller, pins.lockout_seconds, pins.max_failures
+                        );
+                    }
+                } else if matches!(decision, DialPinDecision::Accept { .. }) {
+                    self.pin_lockouts.clear(address);
+                }
+                let (accepted, user) = match &decision {
+                    DialPinDecision::Accept { user } => (true, user.clone()),
+                    _ => (false, None),
+                };
+                info!(
+                    "Dialing PIN {} for INVITE {} from {} to {}",
+                    if accepted { "accepted" } else { "rejected" },
+                    call_id,
+                    caller,
+                    number
+                );
+                self.audit_dial_pin(DialPinAttempt {
+                    call_id: call_id.clone(),
+                    extension: caller.clone(),
+                    number: number.clone(),
+                    class,
+                    pin_supplied: pin.is_some(),
+                    accepted,
+                    user,
+                    source: source.map(|s| s.ip()),
+                    locked_out,
+                    timestamp: chrono::Utc::now(),
+                });
+            }
+            match decision {
+                DialPinDecision::Reject(reason) => {
+                    session.record_decision(CallDecision::new(
+                        DecisionStage::Policy,
+                        false,
+                        format!("dialing PIN rejected: {}", reason),
+                    ));
+                    return Some(self.deny_invite(request, session, class, reason));
+                }
+                DialPinDecision::Accept { user } => {
+                    session.record_decision(CallDecision::new(
+                        DecisionStage::Policy,
+                        true,
+                        "dialing PIN accepted",
+                    ));
+                    session.set_dial_pin_user(user);
+                }
+                DialPinDecision::NotRequired => {}
+            }
+        }
+
+        if let Some(codes) = &self.account_codes {
+            match codes.check(class, account_code.as_deref()) {
+                AccountCodeDecision::Reject(reason) => {
+                    warn!(
+                        "Denying INVITE {} from {} to {}: {}",
+                        call_id, caller, number, reason
+                    );
+                    session.record_decision(CallDecision::new(
+                        DecisionStage::Policy,
+                        false,
+                        format!("account code rejected: {}", reason),
+                    ));
+                    return Some(self.deny_invite(request, session, class, reason));
+                }
+                AccountCodeDecision::Accept(code) => {
+                    if let Some(code) = &code {
+                        let note = format!("account code {}", code);
+                        self.trace_note(&call_id, &note);
+                        session.record_decision(CallDecision::new(
+                            DecisionStage::Policy,
+                            true,
+                            note,
+                        ));
+                    }
+                    session.set_account_code(code);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn audit_dial_pin(&self, attempt: DialPinAttempt) {
+        if let Some(sink) = &self.dial_pin_audit {
+            if sink.send(attempt).is_err() {
+                debug!("Dialing PIN audit receiver dropped");
+            }
+        }
+    }
+
+    /// Record a refused INVITE and build its 403
+    fn deny_invite(
+        &self,
+        request: &Request,
+        session: &mut Session,
+        class: CallClass,
+        reason: String,
+    ) -> Response {
+        let call_id = session.call_id().to_string();
+        self.trace_note(&call_id, &format!("call denied: {}", reason));
+        let response = self
+            .response(request, StatusCode::FORBIDDEN)
+            .header("Warning", format!("399 rustalk \"{}\"", reason))
+            .build();
+        session.record_decision(CallDecision::response(LegSide::A, StatusCode::FORBIDDEN));
+        let mut record = CallDetailRecord::denied(
+            &call_id,
+            request.get_header_value("From").map(str::to_string),
+            request.get_header_value("To").map(str::to_string),
+            class,
+            reason,
+        );
+        record.decisions = session.decisions().to_vec();
+        self.emit_cdr(record);
+        response
+    }
+
+    /// Handle REGISTER request - update registrar bindings
+    async fn handle_register(
+        &self,
+        request: Request,
+        source: Option<SocketAddr>,
+    ) -> Result<Option<Message>> {
+        let Some(registrar) = &self.registrar else {
+            return Ok(Some(Message::Response(
+                self.response(&request, StatusCode::NOT_IMPLEMENTED).build(),
+            )));
+        };
+
+        let response = registrar.handle_register(&request, source).await;
+        if response.status_code == StatusCode::OK {
+            if let Some(extension) = request.get_header_value("To").and_then(uri_user) {
+                self.release_callbacks(extension).await;
+                if let Some(mwi) = &self.mwi {
+                    mwi.registered(extension).await;
+                }
+            }
+        }
+        Ok(Some(Message::Response(response)))
+    }
+
+    /// Handle SUBSCRIBE request - message waiting and BLF list
+    /// subscriptions
+    async fn handle_subscribe(
+        &self,
+        request: Request,
+        source: Option<SocketAddr>,
+    ) -> Result<Option<Message>> {
+        let dialog_event = request
+            .get_header_value("Event")
+            .and_then(|event| event.split(';').next())
+            .is_some_and(|event| event.trim().eq_ignore_ascii_case(crate::presence::EVENT));
+        let response = match (&self.presence, &self.mwi) {
+            (Some(presence), Some(_)) if dialog_event => presence.subscribe(&request, source),
+            (_, Some(mwi)) => mwi.subscribe(&request, source).await,
+            (Some(presence), None) => presence.subscribe(&request, source),
+            (None, None) => self.response(&request, StatusCode::NOT_IMPLEMENTED).build(),
+        };
+        Ok(Some(Message::Response(response)))
+    }
+
+    /// Handle BYE request - terminate session
+    async fn handle_bye(&self, request: Request) -> Result<Option<Message>> {
+        let call_id = request
+            .get_header_value("Call-ID")
+            .ok_or_else(|| anyhow::anyhow!("No Call-ID in BYE"))?;
+
+        info!("Terminating session for Call-ID: {}", call_id);
+
+        if let (Some(tracker), Some(report)) =
+            (&self.media_quality, MediaReport::from_request(&request))
+        {
+            let sessions = self.sessions.read().await;
+            let trunk = sessions
+                .values()
+                .find(|s| s.call_id() == call_id)
+                .and_then(|s| s.trunk());
+            if let Some(trunk) = trunk {
+                debug!("Media quality via {}: {:?}", trunk, report);
+                tracker.record(trunk, report);
+            }
+        }
+        if let Some(retrieval) = &self.voicemail_retrieval {
+            retrieval.end(call_id);
+            self.end_ivr_media(call_id).await;
+        }
+        // The leg that did not hang up is sent a BYE of its own
+        let bye = {
+            let mut sessions = self.sessions.write().await;
+            sessions
+                .values_mut()
+                .find(|s| s.call_id() == call_id)
+                .and_then(|session| {
+                    let side = session.dialog_side(&request).unwrap_or(LegSide::A);
+                    if let Some(dialog) = session.dialog_mut(side) {
+                        dialog.state = DialogState::Terminated;
+                    }
+                    let other = match side {
+                        LegSide::A => LegSide::B,
+                        LegSide::B => LegSide::A,
+                    };
+                    self.bye_leg(session, other, request.get_header_value("Reason"))
+                })
+        };
+        self.send_outbound(bye.into_iter().collect());
+        let cause = request
+            .get_header_value("Reason")
+            .and_then(HangupCause::from_reason)
+            .unwrap_or(HangupCause::NormalClearing);
+        self.end_session(call_id, None, cause).await;
+
+        // Send 200 OK
+        let response = self.response(&request, StatusCode::OK).build();
+
+        Ok(Some(Message::Response(response)))
+    }
+
+    /// Handle OPTIONS request - capability query
+    async fn handle_options(&self, request: Request) -> Result<Option<Message>> {
+        info!("Handling OPTIONS request");
+
+        // Send 200 OK with capabilities
+        let capabilities =
+            self.capabilities_for(request.get_header_value("From").and_then(uri_host));
+        let response = capabilities.advertise(
+            self.response(&request, StatusCode::OK)
+                .header("Accept", "application/sdp")
+                .build(),
+        );
+
+        Ok(Some(Message::Response(response)))
+    }
+
+    /// Handle ACK request
+    async fn handle_ack(&self, request: Request) -> Result<Option<Message>> {
+        debug!("Handling ACK request");
+
+        if let Some(call_id) = request.get_header_value("Call-ID") {
+            let mut sessions = self.sessions.write().await;
+            if let Some(session) = sessions.values_mut().find(|s| s.call_id() == call_id) {
+                if session.answered_at().is_none() {
+                    self.publish_event(CallEvent::new(CallEventKind::Answered, session));
+                }
+                session.mark_answered();
+                if let Some(dialog) = session.dialog_mut(LegSide::A) {
+                    dialog.state = DialogState::Confirmed;
+                }
+            }
+        }
+
+        // ACK doesn't require a response
+        Ok(None)
+    }
+
+    /// Handle a re-INVITE or UPDATE inside an established dialog
+    ///
+    /// The request is matched to the caller's or callee's dialog by its
+    /// tags, and a new Contact becomes that side's remote target. Requests
+    /// matching no dialog get `481` (RFC 3261 section 12.2.2).
+    async fn handle_in_dialog(&self, request: Request) -> Result<Option<Message>> {
+        let call_id = request
+            .get_header_value("Call-ID")
+            .ok_or_else(|| anyhow::anyhow!("No Call-ID in {}", request.method))?
+            .to_string();
+
+        let mut sessions = self.sessions.write().await;
+        let Some((side, session)) = sessions
+            .values_mut()
+            .find_map(|s| Some((s.dialog_side(&request)?, s)))
+        else {
+            drop(sessions);
+            debug!("{} for {} matches no dialog", request.method, call_id);
+            self.trace_note(&call_id, &format!("{} matches no dialog", request.method));
+            let response = self
+                .response(&request, StatusCode::CALL_DOES_NOT_EXIST)
+                .build();
+            return Ok(Some(Message::Response(response)));
+        };
+
+        let Some(dialog) = session.dialog_mut(side) else {
+            return Ok(None);
+        };
+        if let Err(error) = dialog.receive(&request) {
+            let response = self
+                .response(&request, error.status_code())
+                .header("Warning", format!("399 rustalk \"{}\"", error))
+                .build();
+            return Ok(Some(Message::Response(response)));
+        }
+        let note = format!(
+            "{} in dialog {}, target {}",
+            request.method, dialog.id, dialog.remote_target
+        );
+        let to = dialog.local_header();
+        if let (LegSide::A, Some(mut leg)) = (side, session.a_leg().cloned()) {
+            if let Some(contact) = request.get_header_value("Contact") {
+                leg.contact = Some(contact.to_string());
+            }
+            if !request.body.is_empty() {
+                leg.sdp = Some(String::from_utf8_lossy(&request.body).into_owned());
+            }
+            session.set_a_leg(leg);
+        }
+        drop(sessions);
+        info!("{}", note);
+        self.trace_note(&call_id, &note);
+
+        let response = self
+            .response(&request, StatusCode::OK)
+            .set_header("To", to)
+            .build();
+        Ok(Some(Message::Response(response)))
+    }
+
+    fn publish_event(&self, event: CallEvent) {
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+    }
+
+    /// Publish a ringing event, after looking the caller up in the CRM
+    ///
+    /// The lookup runs in the background so call setup never waits on the
+    /// CRM; the event follows once it answers or times out.
+    fn publish_ringing(&self, session: &Session) {
+        let Some(events) = self.events.clone() else {
+            return;
+        };
+        let mut event = CallEvent::new(CallEventKind::Ringing, session);
+        let (Some(crm), Some(number)) = (self.crm.clone(), event.caller.clone()) else {
+            events.publish(event);
+            return;
+        };
+        tokio::spawn(async move {
+            event.crm = crm.lookup(&number, &event.call_id).await;
+            events.publish(event);
+        });
+    }
+
+    /// Handle MESSAGE request - bridged to SMS when addressed to a phone
+    /// number
+    async fn handle_sip_message(&self, request: Request) -> Result<Option<Message>> {
+        let Some(sms) = &self.sms else {
+            return Ok(None);
+        };
+        let response = match sms.bridge_sip_message(&request).await {
+            Some(Ok(message)) => {
+                info!("Sent SIP MESSAGE to {} as SMS", message.to);
+                self.response(&request, StatusCode::ACCEPTED).build()
+            }
+            Some(Err(e)) => {
+                // The sender is not SMS-enabled, or the provider refused it
+                warn!("Failed to send SIP MESSAGE as SMS: {}", e);
+                self.response(&request, StatusCode::FORBIDDEN)
+                    .header("Warning", format!("399 rustalk \"{}\"", e))
+                    .build()
+            }
+            None => self.response(&request, StatusCode::NOT_IMPLEMENTED).build(),
+        };
+        Ok(Some(Message::Response(response)))
+    }
+
+    /// Handle INFO request - DTMF from a callee screening a call, or from
+    /// a subscriber in the voicemail retrieval IVR
+    async fn handle_info(&self, request: Request) -> Result<Option<Message>> {
+        let call_id = request
+            .get_header_value("Call-ID")
+            .ok_or_else(|| anyhow::anyhow!("No Call-ID in INFO"))?;
+
+        if self
+            .voicemail_retrieval
+            .as_ref()
+            .is_some_and(|r| r.is_active(call_id))
+        {
+            if let Some(digit) = dtmf_digit(&String::from_utf8_lossy(&request.body)) {
+                self.retrieval_key(call_id, digit).await;
+            }
+            let response = self.response(&request, StatusCode::OK).build();
+            return Ok(Some(Message::Response(response)));
+        }
+
+        let mut sessions = self.sessions.write().await;
+        let Some(session) = sessions.values_mut().find(|s| s.call_id() == call_id) else {
+            return Ok(Some(Message::Response(
+                self.response(&request, StatusCode::CALL_DOES_NOT_EXIST)
+                    .build(),
+            )));
+        };
+
+        let digit = dtmf_digit(&String::from_utf8_lossy(&request.body));
+        if let (true, Some(digit)) = (session.awaiting_screening(), digit) {
+            let outcome = match ScreeningMode::Screen.decide(Some(digit)) {
+                ScreeningDecision::Connect => Some(ScreeningOutcome::Accepted),
+                ScreeningDecision::Voicemail => Some(ScreeningOutcome::Voicemail),
+                ScreeningDecision::Repeat => None,
+            };
+            if let Some(outcome) = outcome {
+                info!("Screened call {}: {:?}", call_id, outcome);
+                session.set_screening_outcome(outcome);
+            }
+            drop(sessions);
+            self.trace_note(call_id, &format!("screening key {}", digit));
+        }
+
+        let response = self.response(&request, StatusCode::OK).build();
+        Ok(Some(Message::Response(response)))
+    }
+
+    /// Send screened calls the callee has not answered within the response
+    /// time to voicemail, returning how many were
+    pub async fn expire_screening(&self) -> usize {
+        let Some(screening) = &self.screening else {
+            return 0;
+        };
+        let deadline =
+            chrono::Utc::now() - chrono::Duration::seconds(screening.response_seconds as i64);
+
+        let mut sessions = self.sessions.write().await;
+        let mut expired = 0;
+        for session in sessions.values_mut() {
+            if session.awaiting_screening() && session.created_at() <= deadline {
+                session.set_screening_outcome(ScreeningOutcome::Voicemail);
+                expired += 1;
+            }
+        }
+        expired
+    }
+
+    /// Apply the no-answer action to calls that have rung for their full
+    /// ring time, returning how many were
+    ///
+    /// Busy calls are ended; voicemail and forwarded calls carry on to
+    /// their new destination.
+    pub async fn expire_ring_timeouts(&self) -> usize {
+        if self.no_answer.is_none() {
+            return 0;
+        }
+        let now = chrono::Utc::now();
+        let mut expired = Vec::new();
+        let mut cancels = Vec::new();
+        let mut silent = Vec::new();
+        let mut forwarded = Vec::new();
+        let mut sessions = self.sessions.write().await;
+        for session in sessions.values_mut() {
+            let Some(timeout) = session
+                .ring_timeout()
+                .filter(|_| session.ring_expired(now))
+                .cloned()
+            else {
+                continue;
+            };
+            // Contacts that never even sent a provisional response are down
+            for fork in session.forks_mut().iter_mut().filter(|f| f.is_pending()) {
+                if fork.state == ForkState::Trying {
+                    silent.push(fork.contact.clone());
+                }
+                fork.state = ForkState::Cancelled;
+                cancels.push(fork.cancel(None));
+            }
+            let note = format!(
+                "no answer after {}s: {}",
+                timeout.ring_seconds, timeout.action
+            );
+            let action = timeout.action.clone();
+            session.record_decision(CallDecision::new(DecisionStage::Route, true, note.clone()));
+            session.set_no_answer(action.clone());
+            if let NoAnswerAction::Forward { destination } = &action {
+                forwarded.extend(
+                    self.forward_call(session, destination, DiversionReason::NoAnswer)
+                        .await,
+                );
+            }
+            expired.push((session.call_id().to_string(), action, note));
+        }
+        drop(sessions);
+        self.send_outbound(cancels);
+        self.send_outbound(forwarded);
+        if let Some(registrar) = &self.registrar {
+            for contact in silent {
+                registrar.set_reachable(&contact, false).await;
+            }
+        }
+
+        let count = expired.len();
+        for (call_id, action, note) in expired {
+            self.trace_note(&call_id, &note);
+            if action == NoAnswerAction::Busy {
+                self.end_session(
+                    &call_id,
+                    Some(CallDisposition::Failed),
+                    HangupCause::NoAnswer,
+                )
+                .await;
+            }
+        }
+        count
+    }
+
+    /// Send a call on to `destination`, with Diversion and History-Info
+    /// naming the extension it was for, returning the INVITEs to send
+    ///
+    /// An extension's registered contacts are rung; other destinations are
+    /// dialed through the trunk in the Request-URI, shaped for it as a new
+    /// call would be.
+    async fn forward_call(
+        &self,
+        session: &mut Session,
+        destination: &str,
+        reason: DiversionReason,
+    ) -> Vec<OutboundRequest> {
+        let Some(mut request) = session.invite().cloned() else {
+            return Vec::new();
+        };
+        let mut diverted_from = request.uri.clone();
+        diverted_from.params.clear();
+        request.uri.user = Some(destination.to_string());
+        let redirection = Redirection {
+            diverted_from: diverted_from.to_string(),
+            target: request.uri.to_string(),
+            reason,
+        };
+        redirection.apply(&mut request);
+        let note = format!("forwarded to {} ({})", destination, reason);
+        self.trace_note(session.call_id(), &note);
+        session.record_decision(CallDecision::new(DecisionStage::Route, true, note));
+        session.add_target(CallTarget::new(request.uri.to_string()).with_diversion(reason));
+
+        let invites = self.fork_to_contacts(&request, session).await;
+        session.set_invite(request.clone());
+        if !invites.is_empty() {
+            return invites;
+        }
+        let trunk = request.uri.host.clone();
+        self.capabilities_for(Some(&trunk))
+            .apply_to_request(&mut request);
+        if let Some(quirks) = self.quirks.as_ref().and_then(|q| q.for_trunk(&trunk)) {
+            quirks.apply_to_request(&mut request);
+        }
+        session.record_decision(CallDecision::new(
+            DecisionStage::Trunk,
+            true,
+            format!("dialing {} via {}", request.uri, trunk),
+        ));
+        Vec::new()
+    }
+
+    /// Send a call on to the next destination of its route, or of a
+    /// reroute rule, after the current one failed with `failure`, returning
+    /// the INVITEs to send
+    ///
+    /// Returns `None` when the destination is to hang up, its trunk cannot
+    /// be found, or the call has been tried at as many destinations as it
+    /// may be, so the call ends.
+    async fn fail_over(
+        &self,
+        session: &mut Session,
+        hop: DestinationHop,
+        failure: &str,
+        rerouted: bool,
+    ) -> Option<Vec<OutboundRequest>> {
+        let max_attempts = self
+            .routing
+            .as_ref()
+            .map_or(DEFAULT_MAX_ATTEMPTS, |evaluator| {
+                evaluator.config().max_attempts()
+            });
+        if session.attempts().len() >= max_attempts as usize {
+            let note = format!("{}, giving up after {} attempts", failure, max_attempts);
+            self.trace_note(session.call_id(), &note);
+            session.record_decision(CallDecision::new(DecisionStage::Route, false, note));
+            return None;
+        }
+        let label = destination_label(&hop.destination);
+        let note = if rerouted {
+            format!("{}, rerouting to {}", failure, label)
+        } else {
+            format!("{}, failing over to {}", failure, label)
+        };
+        self.trace_note(session.call_id(), &note);
+        session.record_decision(CallDecision::new(DecisionStage::Route, true, note));
+        let mut request = session.invite().cloned()?;
+        match &hop.destination {
+            RouteDestination::Hangup => return None,
+            RouteDestination::Trunk(trunk) | RouteDestination::Enum(trunk) => {
+                if !self.address_trunk(&mut request, trunk) {
+                    request.uri.host = trunk.clone();
+                }
+            }
+            RouteDestination::Voicemail(mailbox) => {
+                request.uri.user = Some(mailbox.clone());
+                session.set_voicemail_drop();
+            }
+            RouteDestination::Extension(user)
+            | RouteDestination::RingGroup(user)
+            | RouteDestination::Custom(user) => request.uri.user = Some(user.clone()),
+            // The evaluator expands these into plain hops
+            RouteDestination::LeastCost(_) | RouteDestination::DestinationList(_) => {}
+        }
+        if let RouteDestination::Trunk(trunk) | RouteDestination::Enum(trunk) = &hop.destination {
+            if !self.move_admission(session, &request, trunk).await {
+                return None;
+            }
+            session.set_route_trunk(trunk.clone());
+        } else {
+            session.clear_route_trunk();
+        }
+        session.add_target(
+            CallTarget::new(request.uri.to_string()).with_destination(Some(label.as_str())),
+        );
+        let mut attempt = CallAttempt::new(Some(label.as_str()), request.uri.to_string());
+        attempt.rerouted = rerouted;
+        session.start_attempt(attempt);
+        session.set_invite(request.clone());
+
+        let mut invites = self.fork_to_contacts(&request, session).await;
+        if invites.is_empty() {
+            let trunk = self.shape_for_trunk(&mut request, session);
+            session.start_dialing(trunk);
+            match self.dial_trunk(&request, session).await {
+                Ok(invite) => invites.extend(invite),
+                Err(e) => {
+                    let note = format!("{:#}", e);
+                    self.trace_note(session.call_id(), &note);
+                    session.record_decision(CallDecision::new(DecisionStage::Trunk, false, note));
+                    return None;
+                }
+            }
+        }
+        Some(invites)
+    }
+
+    /// Move the call's admission slot to `trunk` as it fails over there,
+    /// returning false when `trunk` has no capacity left for it
+    async fn move_admission(&self, session: &mut Session, request: &Request, trunk: &str) -> bool {
+        let Some(admission) = &self.admission else {
+            return true;
+        };
+        if session.admission_key() == Some(trunk) {
+            return true;
+        }
+        let number = request.uri.user.as_deref().unwrap_or("");
+        let call = CallDetails {
+            call_class: Some(
+                session
+                    .call_class()
+                    .unwrap_or_else(|| default_classifier().classify(number)),
+            ),
+            number,
+        };
+        if let AdmissionDecision::Reject(reason) = admission.admit_call(trunk, &call).await {
+            let note = format!("admission rejected on {}: {}", trunk, reason);
+            self.trace_note(session.call_id(), &note);
+            session.record_decision(CallDecision::new(DecisionStage::Admission, false, note));
+            return false;
+        }
+        if let Some(previous) = session.admission_key() {
+            admission.release(previous).await;
+        }
+        let note = format!("admission granted on {}", trunk);
+        self.trace_note(session.call_id(), &note);
+        session.record_decision(CallDecision::new(DecisionStage::Admission, true, note));
+        session.set_admission_key(trunk.to_string());
+        true
+    }
+
+    /// Send a call whose route has no destination left to fail over to on
+    /// to the first unused reroute rule covering `status`, returning the
+    /// INVITEs to send
+    ///
+    /// Destinations the call was already tried at are left out of the
+    /// rule's list. Returns `None` when no rule applies, so the call ends.
+    async fn reroute(
+        &self,
+        session: &mut Session,
+        status: u16,
+        failure: &str,
+    ) -> Option<Vec<OutboundRequest>> {
+        while let Some(rule) = session.take_reroute(status) {
+            let hops: Vec<DestinationHop> = rule
+                .destinations
+                .hops
+                .into_iter()
+                .filter(|hop| {
+                    let label = destination_label(&hop.destination);
+                    !session
+                        .attempts()
+                        .iter()
+                        .any(|attempt| attempt.destination.as_deref() == Some(label.as_str()))
+                })
+                .collect();
+            let Some(first) = hops.first().cloned() else {
+                let note = format!(
+                    "{}, every destination rerouted to on {} already tried",
+                    failure,
+                    rule.codes.join("/")
+                );
+                self.trace_note(session.call_id(), &note);
+                session.record_decision(CallDecision::new(DecisionStage::Route, false, note));
+                continue;
+            };
+            let policy = rule.destinations.failover.unwrap_or_default();
+            session.set_failover(FailoverPlan::new(hops, policy));
+            return self.fail_over(session, first, failure, true).await;
+        }
+        None
+    }
+
+    /// Move calls whose current destination rang out its timeout on to
+    /// their route's next one, returning how many were
+    ///
+    /// Calls with no destination left end unanswered.
+    pub async fn expire_hop_timeouts(&self) -> usize {
+        let now = chrono::Utc::now();
+        let mut ended = Vec::new();
+        let mut cancels = Vec::new();
+        let mut invites = Vec::new();
+        let mut count = 0;
+        let mut sessions = self.sessions.write().await;
+        for session in sessions.values_mut().filter(|s| s.hop_expired(now)) {
+            count += 1;
+            for fork in session.forks_mut().iter_mut().filter(|f| f.is_pending()) {
+                fork.state = ForkState::Cancelled;
+                cancels.push(fork.cancel(None));
+            }
+            session.fail_attempt(None);
+            let failure = "no answer in time";
+            let forwarded = match session.failover_mut().and_then(FailoverPlan::advance) {
+                Some(hop) => self.fail_over(session, hop, failure, false).await,
+                // Reroute rules see a timeout as a 408
+                None if session.can_reroute(StatusCode::REQUEST_TIMEOUT.0) => {
+                    self.reroute(session, StatusCode::REQUEST_TIMEOUT.0, failure)
+                        .await
+                }
+                None => {
+                    let note = format!("{}, no destination left", failure);
+                    self.trace_note(session.call_id(), &note);
+                    session.record_decision(CallDecision::new(DecisionStage::Route, true, note));
+                    None
+                }
+            };
+            match forwarded {
+                Some(sent) => invites.extend(sent),
+                None => ended.push(session.call_id().to_string()),
+            }
+        }
+        drop(sessions);
+        self.send_outbound(cancels);
+        self.send_outbound(invites);
+
+        for call_id in ended {
+            self.end_session(
+                &call_id,
+                Some(CallDisposition::Failed),
+                HangupCause::NoAnswer,
+            )
+            .await;
+        }
+        count
+    }
+
+    /// Handle CANCEL request
+    async fn handle_cancel(&self, request: Request) -> Result<Option<Message>> {
+        let call_id = request
+            .get_header_value("Call-ID")
+            .ok_or_else(|| anyhow::anyhow!("No Call-ID in CANCEL"))?;
+
+        info!("Canceling session for Call-ID: {}", call_id);
+
+        let reason = request.get_header_value("Reason");
+        {
+            let mut sessions = self.sessions.write().await;
+            if let Some(session) = sessions.values_mut().find(|s| s.call_id() == call_id) {
+                if reason.is_some_and(completed_elsewhere) {
+                    session.set_answered_elsewhere();
+                }
+                // The INVITE the CANCEL stops is answered with a 487
+                if session.answered_at().is_none() {
+                    let terminated = Response::new(StatusCode::REQUEST_TERMINATED);
+                    self.answer_caller(session, &terminated);
+                }
+            }
+        }
+        let cause = reason
+            .and_then(HangupCause::from_reason)
+            .unwrap_or(HangupCause::OriginatorCancel);
+        self.end_session(call_id, Some(CallDisposition::Cancelled), cause)
+            .await;
+
+        let response = self.response(&request, StatusCode::OK).build();
+
+        Ok(Some(Message::Response(response)))
+    }
+
+    /// Remove a session, release its resources and emit its CDR
+    ///
+    /// When `disposition` is `None` it is derived from whether the call was
+    /// answered. `cause` is the normalized hangup cause recorded in the CDR.
+    async fn end_session(
+        &self,
+        call_id: &str,
+        disposition: Option<CallDisposition>,
+        cause: HangupCause,
+    ) {
+        let mut sessions = self.sessions.write().await;
+        let session_id = sessions
+            .values()
+            .find(|s| s.call_id() == call_id)
+            .map(|s| s.id().clone());
+
+        let Some(session) = session_id.and_then(|id| sessions.remove(&id)) else {
+            return;
+        };
+        drop(sessions);
+
+        // Stop any contacts of a forked call that are still ringing
+        let reason = session
+            .answered_elsewhere()
+            .then_some(fork::COMPLETED_ELSEWHERE);
+        self.send_outbound(
+            session
+                .forks()
+                .iter()
+                .filter(|f| f.is_pending())
+                .map(|f| f.cancel(reason))
+                .collect(),
+        );
+
+        if let (Some(admission), Some(key)) = (&self.admission, session.admission_key()) {
+            admission.release(key).await;
+        }
+        self.release_carrier_peer(&session);
+        self.release_quota(&session);
+        if let Some(mixer) = &self.announcements {
+            mixer.stop(call_id);
+        }
+        if let Some(relay) = session.webrtc_relay() {
+            relay.close();
+        }
+        self.publish_event(CallEvent::new(CallEventKind::Hangup, &session));
+        if self.is_missed(&session).await {
+            let mut event = CallEvent::new(CallEventKind::Missed, &session);
+            event.did = session.dialed().map(str::to_string);
+            self.trace_note(call_id, "missed call");
+            self.publish_event(event);
+        }
+
+        if self.cdr_sink.is_some() {
+            let disposition = disposition.unwrap_or(if session.answered_at().is_some() {
+                CallDisposition::Answered
+            } else {
+                CallDisposition::Failed
+            });
+            let mut record = CallDetailRecord::from_session(&session, disposition);
+            record.hangup_cause = Some(cause);
+            if let (Some(gateway), Some(peer), Some(number)) = (
+                &self.wholesale,
+                session.carrier_peer(),
+                session.carrier_number(),
+            ) {
+                let seconds = record.duration_seconds.clamp(0, u32::MAX as i64) as u32;
+                record.charge = gateway.charge(peer, number, seconds);
+            }
+            self.emit_cdr(record);
+        }
+
+        info!("Session terminated: {}", session.id());
+        self.trace_note(call_id, &format!("session {} terminated", session.id()));
+
+        // Either party may be what a queued callback is waiting on
+        if self.callbacks.is_some() {
+            for party in [session.caller(), session.callee()]
+                .into_iter()
+                .flatten()
+                .filter_map(uri_user)
+            {
+                self.release_callbacks(party).await;
+            }
+        }
+    }
+
+    /// Whether an ended session was a call to an extension that nobody
+    /// answered
+    ///
+    /// Calls answered on another phone, sent straight to voicemail, or sent
+    /// there by the callee while screening, were not missed.
+    async fn is_missed(&self, session: &Session) -> bool {
+        let Some(config) = &self.missed_calls else {
+            return false;
+        };
+        if session.answered_at().is_some()
+            || session.answered_elsewhere()
+            || session.voicemail_drop()
+            || session.screening_outcome() == Some(ScreeningOutcome::Voicemail)
+        {
+            return false;
+        }
+        let Some(extension) = session.callee().and_then(uri_user) else {
+            return false;
+        };
+        if config.extensions.contains_key(extension) {
+            return true;
+        }
+        match &self.registrar {
+            Some(registrar) => registrar
+                .bindings()
+                .await
+                .iter()
+                .any(|r| uri_user(&r.aor) == Some(extension)),
+            None => false,
+        }
+    }
+
+    fn emit_cdr(&self, record: CallDetailRecord) {
+        if let Some(sink) = &self.cdr_sink {
+            let call_id = record.call_id.clone();
+            if sink.send(record).is_err() {
+                debug!("CDR receiver dropped for Call-ID: {}", call_id);
+            }
+        }
+    }
+
+    /// Get session count
+    pub async fn session_count(&self) -> usize {
+        self.sessions.read().await.len()
+    }
+
+    /// Stop taking new calls: new INVITEs are answered 503 with
+    /// `Retry-After` while calls in progress carry on
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Whether `message` belongs here: to a call this B2BUA holds, or a
+    /// reply to the NOTIFYs it sends
+    pub async fn holds(&self, message: &Message) -> bool {
+        let (call_id, cseq) = match message {
+            Message::Request(req) => (req.get_header_value("Call-ID"), None),
+            Message::Response(res) => (
+                res.get_header_value("Call-ID"),
+                res.get_header_value("CSeq"),
+            ),
+        };
+        if cseq.is_some_and(|cseq| cseq.trim_end().ends_with("NOTIFY"))
+            && (self.mwi.is_some() || self.presence.is_some())
+        {
+            return true;
+        }
+        // Without a Call-ID it is nobody's, and gets its error here
+        let Some(call_id) = call_id else {
+            return true;
+        };
+        self.sessions
+            .read()
+            .await
+            .values()
+            .any(|s| s.call_id() == call_id)
+    }
+
+    /// Start draining and wait up to `timeout` for the calls in progress to
+    /// end, returning how many are still up
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        self.start_draining();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = self.session_count().await;
+            if remaining == 0 || Instant::now() >= deadline {
+                return remaining;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// Active sessions per admission-controlled trunk
+    pub async fn trunk_calls(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for session in self.sessions.read().await.values() {
+            if let Some(trunk) = session.admission_key() {
+                *counts.entry(trunk.to_string()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Play 8 kHz samples over an answered call to `legs`
+    ///
+    /// Calls answered by the retrieval IVR play to the caller from the
+    /// IVR's own endpoint; bridged calls are mixed by the announcement
+    /// mixer.
+    pub async fn play_announcement(
+        &self,
+        call_id: &str,
+        legs: &[LegSide],
+        samples: &[i16],
+    ) -> std::result::Result<Playback, PlaybackRefusal> {
+        let state = {
+            let sessions = self.sessions.read().await;
+            sessions
+                .values()
+                .find(|s| s.call_id() == call_id)
+                .map(|s| s.state())
+                .ok_or(PlaybackRefusal::NoSuchCall)?
+        };
+        if state != SessionState::Established {
+            return Err(PlaybackRefusal::NotAnswered);
+        }
+        let playback = if let Some(media) = self.ivr_media.read().await.get(call_id) {
+            media.play(samples);
+            Playback::new(call_id, &[LegSide::A], samples)
+        } else {
+            self.announcements
+                .as_ref()
+                .ok_or(PlaybackRefusal::NoMedia)?
+                .queue(call_id, legs, samples)
+        };
+        let legs: Vec<&str> = playback
+            .legs
+            .iter()
+            .map(|leg| match leg {
+                LegSide::A => "caller",
+                LegSide::B => "callee",
+            })
+            .collect();
+        self.trace_note(
+            call_id,
+            &format!(
+                "announcement of {} ms played to the {}",
+                playback.duration_ms,
+                legs.join(" and ")
+            ),
+        );
+        Ok(playback)
+    }
+
+    /// Hang up a call from outside it, as an operator does: each leg in a
+    /// dialog is sent a BYE and the call ends as normal clearing
+    ///
+    /// Returns whether there was such a call.
+    pub async fn hangup(&self, call_id: &str) -> bool {
+        let cause = HangupCause::NormalClearing;
+        let reason = format!("Q.850;cause={};text=\"{}\"", cause.q850(), cause.as_str());
+        let byes = {
+            let mut sessions = self.sessions.write().await;
+            let Some(session) = sessions.values_mut().find(|s| s.call_id() == call_id) else {
+                return false;
+            };
+            let mut byes = Vec::new();
+            for side in [LegSide::A, LegSide::B] {
+                byes.extend(self.bye_leg(session, side, Some(&reason)));
+            }
+            byes
+        };
+        info!("Hanging up Call-ID: {}", call_id);
+        self.trace_note(
+            call_id,
+            &format!("hung up by an operator, {} BYE(s) sent", byes.len()),
+        );
+        self.send_outbound(byes);
+        if let Some(retrieval) = &self.voicemail_retrieval {
+            retrieval.end(call_id);
+        }
+        self.end_ivr_media(call_id).await;
+        self.end_session(call_id, None, cause).await;
+        true
+    }
+
+    /// Legs of an active call and how they relate, by Call-ID or session ID
+    pub async fn topology(&self, call_id: &str) -> Option<CallTopology> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .values()
+            .find(|s| s.call_id() == call_id || s.id().as_str() == call_id)
+            .map(CallTopology::from_session)
+    }
+
+    /// Snapshot of every leg of every active session, oldest first
+    pub async fn channels(&self) -> Vec<ChannelInfo> {
+        let sessions = self.sessions.read().await;
+        let mut channels: Vec<ChannelInfo> = sessions
+            .values()
+            .flat_map(ChannelInfo::from_session)
+            .collect();
+        channels.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then(a.call_id.cmp(&b.call_id))
+        });
+        channels
+    }
+}
+
+/// Classifier used when class of service is not configured
+fn default_classifier() -> &'static NumberClassifier {
+    static CLASSIFIER: OnceLock<NumberClassifier> = OnceLock::new();
+    CLASSIFIER.get_or_init(NumberClassifier::default)
+}
+
+/// Identifies a caller's INVITE transaction by its Call-ID, From tag and
+/// CSeq, so retransmissions of it are recognised
+fn invite_key(request: &Request) -> Option<String> {
+    let from_tag = request.get_header_value("From").and_then(header_tag)?;
+    Some(format!(
+        "{} {} {}",
+        request.get_header_value("Call-ID")?,
+        from_tag,
+        request.get_header_value("CSeq")?.trim()
+    ))
+}
+
+/// Replace a request's SDP body, with a Content-Length to match
+fn set_sdp(request: &mut Request, sdp: &str) {
+    request.headers.retain(|h| {
+        let name = h.name.as_str();
+        !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("l")
+    });
+    request.headers.push(Header::new(
+        "Content-Length",
+        sdp.len().to_string().as_str(),
+    ));
+    request.body = sdp.to_string().into();
+}
+
+/// Whether a registered contact is reached over WebSocket, as browsers are
+fn is_websocket_contact(contact: &str) -> bool {
+    contact
+        .trim_end_matches('>')
+        .split(';')
+        .skip(1)
+        .any(|param| {
+            let param = param.trim();
+            param.eq_ignore_ascii_case("transport=ws")
+                || param.eq_ignore_ascii_case("transport=wss")
+        })
+}
+
+/// An INVITE being set up, until dropped
+struct InviteInProgress {
+    invites: Arc<Mutex<HashSet<String>>>,
+    key: String,
+}
+
+impl InviteInProgress {
+    /// Mark `key` as being set up, or `None` when it already is
+    fn start(invites: &Arc<Mutex<HashSet<String>>>, key: &str) -> Option<Self> {
+        if !invites.lock().unwrap().insert(key.to_string()) {
+            return None;
+        }
+        Some(Self {
+            invites: invites.clone(),
+            key: key.to_string(),
+        })
+    }
+}
+
+impl Drop for InviteInProgress {
+    fn drop(&mut self) {
+        self.invites.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl Default for B2BUA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::Uri;
+
+    #[tokio::test]
+    async fn test_b2bua_options() {
+        let b2bua = B2BUA::new();
+
+        let request = Request::new(
+            Method::Options,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bK-opt")
+        .with_header("From", "<sip:1001@example.com>;tag=o1")
+        .with_header("To", "<sip:example.com>")
+        .with_header("Call-ID", "test123")
+        .with_header("CSeq", "7 OPTIONS");
+
+        let result = b2bua.handle_message(Message::Request(request)).await;
+        assert!(result.is_ok());
+
+        let response = result.unwrap();
+        assert!(response.is_some());
+
+        if let Some(Message::Response(res)) = response {
+            assert_eq!(res.status_code, StatusCode::OK);
+            // The response echoes the request's transaction headers
+            assert_eq!(
+                res.get_header_value("Via"),
+                Some("SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bK-opt")
+            );
+            assert_eq!(res.get_header_value("CSeq"), Some("7 OPTIONS"));
+            assert!(res.get_header_value("To").and_then(header_tag).is_some());
+            assert_eq!(
+                res.get_header_value("Allow"),
+                Some("INVITE, ACK, BYE, CANCEL, OPTIONS, INFO, UPDATE")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_method_not_allowed() {
+        let request = |method: Method| {
+            Message::Request(
+                Request::new(
+                    method,
+                    Uri::new("sip".to_string(), "example.com".to_string()),
+                )
+                .with_header("Call-ID", "allow1")
+                .with_header("From", "<sip:1001@example.com>;tag=a"),
+            )
+        };
+
+        // Without a registrar REGISTER is refused, saying what is allowed
+        let b2bua = B2BUA::new();
+        for method in [Method::Register, Method::Subscribe] {
+            let Some(Message::Response(response)) =
+                b2bua.handle_message(request(method)).await.unwrap()
+            else {
+                panic!("expected a response");
+            };
+            assert_eq!(response.status_code, StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(
+                response.get_header_value("From"),
+                Some("<sip:1001@example.com>;tag=a")
+            );
+            assert!(!response
+                .get_header_value("Allow")
+                .unwrap()
+                .contains("REGISTER"));
+        }
+
+        let b2bua = B2BUA::new().with_registrar(Registrar::new());
+        assert!(b2bua.capabilities().allows(Method::Register));
+        let Some(Message::Response(response)) = b2bua
+            .handle_message(request(Method::Options))
+            .await
+            .unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert!(response
+            .get_header_value("Allow")
+            .unwrap()
+            .ends_with("REGISTER"));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_invite_bye() {
+        let b2bua = B2BUA::new();
 
         // Send INVITE
         let invite = Request::new(
             Method::Invite,
-            Uri::new("sip".to_string(), "bob@example.com".to_string()),
+            Uri::new("sip".to_string(), "bob@example.com".to_string()),
+        )
+        .with_header("Call-ID", "call123");
+
+        let result = b2bua.handle_message(Message::Request(invite)).await;
+        assert!(result.is_ok());
+        assert_eq!(b2bua.session_count().await, 1);
+
+        // Send BYE
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "bob@example.com".to_string()),
+        )
+        .with_header("Call-ID", "call123");
+
+        let result = b2bua.handle_message(Message::Request(bye)).await;
+        assert!(result.is_ok());
+        assert_eq!(b2bua.session_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_hangup_causes() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new().with_cdr_sink(tx);
+        let invite = |call_id: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user("+12125551234".to_string()),
+                )
+                .with_header("Call-ID", call_id),
+            )
+        };
+        let request = |method: Method, call_id: &str, reason: Option<&str>| {
+            let mut request = Request::new(
+                method,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", call_id);
+            if let Some(reason) = reason {
+                request = request.with_header("Reason", reason);
+            }
+            Message::Request(request)
+        };
+
+        // A failure response is mapped to its Q.850 cause
+        b2bua.handle_message(invite("not-found")).await.unwrap();
+        let response = Response::new(StatusCode::NOT_FOUND).with_header("Call-ID", "not-found");
+        b2bua
+            .handle_message(Message::Response(response))
+            .await
+            .unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.disposition, CallDisposition::Failed);
+        assert_eq!(cdr.hangup_cause, Some(HangupCause::UnallocatedNumber));
+
+        // An authentication challenge leaves the call up
+        b2bua.handle_message(invite("challenged")).await.unwrap();
+        let response = Response::new(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+            .with_header("Call-ID", "challenged");
+        b2bua
+            .handle_message(Message::Response(response))
+            .await
+            .unwrap();
+        assert!(rx.try_recv().is_err());
+
+        // A Q.850 Reason takes precedence over the response code
+        let response = Response::new(StatusCode::TEMPORARILY_UNAVAILABLE)
+            .with_header("Call-ID", "challenged")
+            .with_header("Reason", "Q.850;cause=19");
+        b2bua
+            .handle_message(Message::Response(response))
+            .await
+            .unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap().hangup_cause,
+            Some(HangupCause::NoAnswer)
+        );
+
+        b2bua.handle_message(invite("cancelled")).await.unwrap();
+        b2bua
+            .handle_message(request(Method::Cancel, "cancelled", None))
+            .await
+            .unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap().hangup_cause,
+            Some(HangupCause::OriginatorCancel)
+        );
+
+        b2bua.handle_message(invite("cleared")).await.unwrap();
+        b2bua
+            .handle_message(request(Method::Bye, "cleared", None))
+            .await
+            .unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap().hangup_cause,
+            Some(HangupCause::NormalClearing)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_cdr_codecs() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new().with_cdr_sink(tx);
+        let sdp = |formats: &str| {
+            format!(
+                "v=0\r\nc=IN IP4 192.0.2.1\r\nm=audio 4000 RTP/AVP {}\r\n\
+                 a=rtpmap:101 telephone-event/8000\r\n",
+                formats
+            )
+        };
+        let request = |method: Method| {
+            Request::new(
+                method,
+                Uri::new("sip".to_string(), "example.com".to_string())
+                    .with_user("+12125551234".to_string()),
+            )
+            .with_header("Call-ID", "codecs")
+        };
+
+        b2bua
+            .handle_message(Message::Request(
+                request(Method::Invite).with_body(sdp("0 8 101")),
+            ))
+            .await
+            .unwrap();
+        let ok = Response::new(StatusCode::OK)
+            .with_header("Call-ID", "codecs")
+            .with_header("CSeq", "1 INVITE")
+            .with_body(sdp("8 101"));
+        b2bua.handle_message(Message::Response(ok)).await.unwrap();
+        for method in [Method::Ack, Method::Bye] {
+            b2bua
+                .handle_message(Message::Request(request(method)))
+                .await
+                .unwrap();
+        }
+
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.disposition, CallDisposition::Answered);
+        assert_eq!(cdr.caller_codec.as_deref(), Some("PCMU"));
+        assert_eq!(cdr.callee_codec.as_deref(), Some("PCMA"));
+        assert!(cdr.setup_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_register_and_channels() {
+        let registrar = Registrar::new();
+        let b2bua = B2BUA::new().with_registrar(registrar.clone());
+        let source: SocketAddr = "192.0.2.10:5060".parse().unwrap();
+
+        let register = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "reg1")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1001@192.0.2.10:5060>");
+        let response = b2bua
+            .handle_message_from(Message::Request(register), source)
+            .await
+            .unwrap();
+        assert!(
+            matches!(response, Some(Message::Response(res)) if res.status_code == StatusCode::OK)
+        );
+        assert_eq!(registrar.bindings().await.len(), 1);
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "1002@example.com".to_string()),
+        )
+        .with_header("Call-ID", "call1")
+        .with_header("From", "<sip:1001@example.com>;tag=a")
+        .with_header("To", "<sip:1002@example.com>")
+        .with_body("v=0\r\nm=audio 4000 RTP/AVP 0\r\n");
+        b2bua
+            .handle_message_from(Message::Request(invite), source)
+            .await
+            .unwrap();
+
+        let channels = b2bua.channels().await;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].call_id, "call1");
+        assert_eq!(channels[0].leg, LegSide::A);
+        assert_eq!(channels[0].remote_addr, Some(source));
+        assert_eq!(channels[0].codec.as_deref(), Some("PCMU"));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_message_waiting() {
+        use crate::mwi::{MwiConfig, MwiNotifier};
+        use crate::voicemail::{VoicemailBox, VoicemailManager};
+
+        let voicemail = VoicemailManager::new(std::env::temp_dir().join("rustalk_b2bua_mwi_test"));
+        voicemail
+            .add_mailbox(VoicemailBox {
+                id: "1001".to_string(),
+                extension: "1001".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        voicemail
+            .leave_message("1001", "5551234", None, b"audio", 5)
+            .await
+            .unwrap();
+        let registrar = Registrar::new();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let mwi = MwiNotifier::new(MwiConfig::default(), voicemail, out_tx.clone(), local)
+            .with_registrar(registrar.clone());
+        let b2bua = B2BUA::new()
+            .with_registrar(registrar)
+            .with_outbound_sink(out_tx, local)
+            .with_mwi(mwi.clone());
+        assert!(b2bua.capabilities().allows(Method::Subscribe));
+
+        // A phone that never subscribes is told when it registers
+        let phone: SocketAddr = "192.0.2.10:5060".parse().unwrap();
+        let register = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "reg-mwi")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1001@192.0.2.10:5060>");
+        b2bua
+            .handle_message_from(Message::Request(register), phone)
+            .await
+            .unwrap();
+        let notify = out_rx.try_recv().unwrap();
+        assert_eq!(notify.destination, phone);
+        assert_eq!(notify.request.method, Method::Notify);
+        assert!(String::from_utf8_lossy(&notify.request.body).contains("Messages-Waiting: yes"));
+
+        // One that subscribes is notified inside its subscription
+        let subscribe = Request::new(
+            Method::Subscribe,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Call-ID", "sub-mwi")
+        .with_header("From", "<sip:1001@example.com>;tag=phone")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("CSeq", "1 SUBSCRIBE")
+        .with_header("Event", "message-summary");
+        let Some(Message::Response(response)) = b2bua
+            .handle_message_from(Message::Request(subscribe), phone)
+            .await
+            .unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.status_code, StatusCode::OK);
+        let notify = out_rx.try_recv().unwrap();
+        assert_eq!(notify.request.get_header_value("Call-ID"), Some("sub-mwi"));
+        assert_eq!(mwi.subscriptions().len(), 1);
+
+        // A phone that has forgotten the subscription ends it
+        let forgotten = Response::new(StatusCode::CALL_DOES_NOT_EXIST)
+            .with_header("Call-ID", "sub-mwi")
+            .with_header("CSeq", "2 NOTIFY");
+        assert!(b2bua
+            .handle_message(Message::Response(forgotten))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(mwi.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_blf_list_subscription() {
+        use crate::mwi::{MwiConfig, MwiNotifier};
+        use crate::presence::{BlfList, BlfLists, PresenceConfig, ResourceListServer};
+        use crate::voicemail::VoicemailManager;
+
+        let voicemail = VoicemailManager::new(std::env::temp_dir().join("rustalk_b2bua_blf_test"));
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let lists = BlfLists::new(
+            vec![BlfList {
+                device: "00:15:65:12:34:56".to_string(),
+                name: None,
+                extensions: vec!["1002".to_string()],
+            }],
+            "example.com",
+        );
+        let presence =
+            ResourceListServer::new(PresenceConfig::default(), lists, out_tx.clone(), local);
+        let b2bua = B2BUA::new()
+            .with_outbound_sink(out_tx.clone(), local)
+            .with_mwi(MwiNotifier::new(
+                MwiConfig::default(),
+                voicemail,
+                out_tx,
+                local,
+            ))
+            .with_presence(presence.clone());
+        assert_eq!(
+            b2bua.capabilities().supported().as_deref(),
+            Some("eventlist")
+        );
+
+        let subscribe = |user: &str, event: &str| {
+            Request::new(
+                Method::Subscribe,
+                Uri::new("sip".to_string(), "example.com".to_string()).with_user(user.to_string()),
+            )
+            .with_header("Call-ID", "sub-blf")
+            .with_header("From", "<sip:1001@example.com>;tag=phone")
+            .with_header("To", format!("<sip:{}@example.com>", user).as_str())
+            .with_header("CSeq", "1 SUBSCRIBE")
+            .with_header("Event", event)
+            .with_header("Supported", "eventlist")
+        };
+        let phone: SocketAddr = "192.0.2.10:5060".parse().unwrap();
+
+        // Dialog subscriptions go to the resource list server, others to MWI
+        let Some(Message::Response(response)) = b2bua
+            .handle_message_from(
+                Message::Request(subscribe("blf-001565123456", "dialog")),
+                phone,
+            )
+            .await
+            .unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.status_code, StatusCode::OK);
+        let notify = out_rx.try_recv().unwrap();
+        assert!(String::from_utf8_lossy(&notify.request.body).contains("sip:1002@example.com"));
+        assert_eq!(presence.subscriptions().len(), 1);
+        let Some(Message::Response(response)) = b2bua
+            .handle_message_from(
+                Message::Request(subscribe("1001", "message-summary")),
+                phone,
+            )
+            .await
+            .unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+
+        // A phone that has forgotten the subscription ends it
+        let forgotten = Response::new(StatusCode::CALL_DOES_NOT_EXIST)
+            .with_header("Call-ID", "sub-blf")
+            .with_header("CSeq", "2 NOTIFY");
+        assert!(b2bua
+            .handle_message(Message::Response(forgotten))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(presence.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_routes_gruu() {
+        let registrar = Registrar::new();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let b2bua = B2BUA::new()
+            .with_registrar(registrar.clone())
+            .with_outbound_sink(out_tx, local);
+        assert!(b2bua.capabilities().supported().unwrap().contains("gruu"));
+
+        // A desk phone, and a softphone asking for GRUUs
+        for contact in [
+            "<sip:1001@192.0.2.20:5060>",
+            "<sip:1001@192.0.2.21:5060>;+sip.instance=\"<urn:uuid:soft>\"",
+        ] {
+            let register = Request::new(
+                Method::Register,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", contact)
+            .with_header("To", "<sip:1001@example.com>")
+            .with_header("Contact", contact)
+            .with_header("Supported", "gruu");
+            b2bua
+                .handle_message(Message::Request(register))
+                .await
+                .unwrap();
+        }
+        let temp: Uri = registrar
+            .bindings()
+            .await
+            .into_iter()
+            .find_map(|r| r.temp_gruu)
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let invite = |uri: Uri, call_id: &str| {
+            Message::Request(
+                Request::new(Method::Invite, uri)
+                    .with_header("Via", "SIP/2.0/UDP 192.0.2.30:5060;branch=z9hG4bK-a")
+                    .with_header("Call-ID", call_id)
+                    .with_header("CSeq", "1 INVITE")
+                    .with_header("From", "<sip:1002@example.com>;tag=a")
+                    .with_header("To", "<sip:1001@example.com>"),
+            )
+        };
+        // Only the softphone rings for its GRUU
+        b2bua.handle_message(invite(temp, "gruu1")).await.unwrap();
+        let fork = out_rx.try_recv().unwrap();
+        assert_eq!(fork.destination, "192.0.2.21:5060".parse().unwrap());
+        assert!(out_rx.try_recv().is_err());
+
+        // A GRUU nobody holds any more is temporarily unavailable
+        let stale: Uri = "sip:tgruu.0000000000000000@example.com;gr".parse().unwrap();
+        let Some(Message::Response(response)) =
+            b2bua.handle_message(invite(stale, "gruu2")).await.unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.status_code, StatusCode::TEMPORARILY_UNAVAILABLE);
+        assert!(out_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_rings_only_the_called_domain() {
+        let domains = DomainMap::new(
+            serde_json::from_value(serde_json::json!({
+                "profiles": [
+                    { "name": "brand-a", "domain": "example.com", "aliases": ["voice.example.com"] },
+                    { "name": "brand-b", "domain": "other.example" }
+                ]
+            }))
+            .unwrap(),
+        );
+        let registrar = Registrar::new().with_domains(domains.clone());
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let b2bua = B2BUA::new()
+            .with_domains(domains)
+            .with_registrar(registrar.clone())
+            .with_outbound_sink(out_tx, local);
+
+        // Extension 1001 in each domain
+        for (domain, contact) in [
+            ("voice.example.com", "<sip:1001@192.0.2.20:5060>"),
+            ("other.example", "<sip:1001@192.0.2.21:5060>"),
+        ] {
+            let register = Request::new(
+                Method::Register,
+                Uri::new("sip".to_string(), domain.to_string()),
+            )
+            .with_header("Call-ID", contact)
+            .with_header("To", format!("<sip:1001@{}>", domain).as_str())
+            .with_header("Contact", contact);
+            registrar.handle_register(&register, None).await;
+        }
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "voice.example.com".to_string())
+                .with_user("1001".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.30:5060;branch=z9hG4bK-a")
+        .with_header("Call-ID", "brand-a")
+        .with_header("CSeq", "1 INVITE")
+        .with_header("From", "<sip:1002@example.com>;tag=a")
+        .with_header("To", "<sip:1001@voice.example.com>");
+        b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap();
+        let fork = out_rx.try_recv().unwrap();
+        assert_eq!(fork.destination, "192.0.2.20:5060".parse().unwrap());
+        assert!(out_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_rewrites_far_end_nat() {
+        let nat = NatPolicy::default();
+        nat.set_profile(crate::nat::NatProfile {
+            name: "phones".to_string(),
+            domain: "example.com".to_string(),
+            bind_port: None,
+            nat: crate::nat::NatSettings {
+                rewrite_contact: crate::nat::NatRewrite::Detect,
+                rewrite_sdp: crate::nat::NatRewrite::Detect,
+                ..Default::default()
+            },
+        });
+        let registrar = Registrar::new();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let b2bua = B2BUA::new()
+            .with_nat_policy(nat)
+            .with_registrar(registrar.clone())
+            .with_outbound_sink(out_tx, local);
+        let register = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "reg")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1001@192.0.2.20:5060>");
+        registrar
+            .handle_register(&register, Some("192.0.2.20:5060".parse().unwrap()))
+            .await;
+
+        let invite = |from: &str, call_id: &str| {
+            Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "example.com".to_string())
+                    .with_user("1001".to_string()),
+            )
+            .with_header("Via", "SIP/2.0/UDP 10.0.0.8:5062;branch=z9hG4bK-a")
+            .with_header("Call-ID", call_id)
+            .with_header("CSeq", "1 INVITE")
+            .with_header("From", format!("<sip:1002@{}>;tag=a", from).as_str())
+            .with_header("To", "<sip:1001@example.com>")
+            .with_header("Contact", "<sip:1002@10.0.0.8:5062>")
+            .with_header("Content-Type", "application/sdp")
+            .with_body("v=0\r\no=- 1 1 IN IP4 10.0.0.8\r\ns=-\r\nc=IN IP4 10.0.0.8\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n")
+        };
+        let source: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+
+        // A phone in the profile's domain is reached through its NAT
+        b2bua
+            .handle_message_from(Message::Request(invite("example.com", "nat1")), source)
+            .await
+            .unwrap();
+        let fork = out_rx.try_recv().unwrap();
+        assert_eq!(
+            fork.request.get_header_value("Contact"),
+            Some("<sip:1002@203.0.113.9:40000>")
+        );
+        assert!(String::from_utf8_lossy(&fork.request.body).contains("c=IN IP4 203.0.113.9"));
+
+        // Callers no profile serves are passed on as they are
+        b2bua
+            .handle_message_from(Message::Request(invite("carrier.example", "nat2")), source)
+            .await
+            .unwrap();
+        let fork = out_rx.try_recv().unwrap();
+        assert_eq!(
+            fork.request.get_header_value("Contact"),
+            Some("<sip:1002@10.0.0.8:5062>")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_forks_to_every_contact() {
+        let registrar = Registrar::new();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let (cdr_tx, mut cdr_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let b2bua = B2BUA::new()
+            .with_registrar(registrar.clone())
+            .with_outbound_sink(out_tx, local)
+            .with_cdr_sink(cdr_tx);
+
+        // Desk phone and softphone registered for the same extension
+        for (contact, source) in [
+            ("<sip:1001@192.0.2.20:5060>", "192.0.2.20:5060"),
+            ("<sip:1001@10.0.0.8:5062>", "203.0.113.9:40000"),
+        ] {
+            let register = Request::new(
+                Method::Register,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", contact)
+            .with_header("To", "<sip:1001@example.com>")
+            .with_header("Contact", contact);
+            b2bua
+                .handle_message_from(Message::Request(register), source.parse().unwrap())
+                .await
+                .unwrap();
+        }
+        assert_eq!(registrar.bindings().await.len(), 2);
+
+        let invite = |call_id: &str| {
+            Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "example.com".to_string())
+                    .with_user("1001".to_string()),
+            )
+            .with_header("Via", "SIP/2.0/UDP 192.0.2.30:5060;branch=z9hG4bK-a")
+            .with_header("Call-ID", call_id)
+            .with_header("CSeq", "1 INVITE")
+            .with_header("From", "<sip:1002@example.com>;tag=a")
+            .with_header("To", "<sip:1001@example.com>")
+        };
+        let response = |fork: &OutboundRequest, status: u16, method: &str| {
+            let request = &fork.request;
+            Message::Response(
+                Response::new(StatusCode(status))
+                    .with_header("Via", request.get_header_value("Via").unwrap())
+                    .with_header("Call-ID", request.get_header_value("Call-ID").unwrap())
+                    .with_header("CSeq", format!("1 {}", method).as_str()),
+            )
+        };
+
+        b2bua
+            .handle_message(Message::Request(invite("fork1")))
+            .await
+            .unwrap();
+        let mut forks = [out_rx.try_recv().unwrap(), out_rx.try_recv().unwrap()];
+        forks.sort_by_key(|f| f.destination);
+        assert_eq!(forks[0].destination, "192.0.2.20:5060".parse().unwrap());
+        assert_eq!(forks[1].destination, "203.0.113.9:40000".parse().unwrap());
+        assert_eq!(forks[1].request.uri.to_string(), "sip:1001@10.0.0.8:5062");
+        assert_ne!(
+            forks[0].request.get_header_value("Via"),
+            forks[1].request.get_header_value("Via")
+        );
+
+        // The softphone rings, the desk phone answers: the softphone is
+        // cancelled as completed elsewhere
+        b2bua
+            .handle_message(response(&forks[1], 180, "INVITE"))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(response(&forks[0], 200, "INVITE"))
+            .await
+            .unwrap();
+        let cancel = out_rx.try_recv().unwrap();
+        assert_eq!(cancel.request.method, Method::Cancel);
+        assert_eq!(cancel.destination, forks[1].destination);
+        assert_eq!(
+            cancel.request.get_header_value("Reason"),
+            Some(fork::COMPLETED_ELSEWHERE)
+        );
+        assert!(out_rx.try_recv().is_err());
+        b2bua
+            .handle_message(response(&forks[1], 200, "CANCEL"))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(response(&forks[1], 487, "INVITE"))
+            .await
+            .unwrap();
+        assert_eq!(b2bua.session_count().await, 1);
+        assert!(registrar
+            .bindings()
+            .await
+            .iter()
+            .all(|r| r.reachable == Some(true)));
+
+        // The call only fails once every contact has refused it
+        b2bua
+            .handle_message(Message::Request(invite("fork2")))
+            .await
+            .unwrap();
+        let mut forks = [out_rx.try_recv().unwrap(), out_rx.try_recv().unwrap()];
+        forks.sort_by_key(|f| f.destination);
+        b2bua
+            .handle_message(response(&forks[0], 486, "INVITE"))
+            .await
+            .unwrap();
+        assert_eq!(b2bua.session_count().await, 2);
+        b2bua
+            .handle_message(response(&forks[1], 408, "INVITE"))
+            .await
+            .unwrap();
+        assert_eq!(b2bua.session_count().await, 1);
+        let cdr = cdr_rx.try_recv().unwrap();
+        assert_eq!(cdr.call_id, "fork2");
+        assert_eq!(cdr.disposition, CallDisposition::Busy);
+        assert_eq!(cdr.hangup_cause, Some(HangupCause::UserBusy));
+        let softphone = registrar
+            .bindings()
+            .await
+            .into_iter()
+            .find(|r| r.contact == "sip:1001@10.0.0.8:5062")
+            .unwrap();
+        assert_eq!(softphone.reachable, Some(false));
+
+        // Hanging up before answer cancels every contact still ringing
+        b2bua
+            .handle_message(Message::Request(invite("fork3")))
+            .await
+            .unwrap();
+        while out_rx.try_recv().is_ok() {}
+        let cancel = Request::new(
+            Method::Cancel,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "fork3");
+        b2bua
+            .handle_message(Message::Request(cancel))
+            .await
+            .unwrap();
+        let cancels: Vec<_> = std::iter::from_fn(|| out_rx.try_recv().ok()).collect();
+        assert_eq!(cancels.len(), 2);
+        assert!(cancels.iter().all(|c| c.request.method == Method::Cancel));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_relays_answer_and_carries_bye() {
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let b2bua = B2BUA::new()
+            .with_registrar(Registrar::new())
+            .with_outbound_sink(out_tx, local)
+            .with_relay_sink(relay_tx);
+
+        let bob: SocketAddr = "192.0.2.20:5060".parse().unwrap();
+        let register = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "reg-bob")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1001@192.0.2.20:5060>");
+        b2bua
+            .handle_message_from(Message::Request(register), bob)
+            .await
+            .unwrap();
+
+        let alice: SocketAddr = "192.0.2.30:5060".parse().unwrap();
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.30:5060;branch=z9hG4bK-a")
+        .with_header("Call-ID", "relay1")
+        .with_header("CSeq", "1 INVITE")
+        .with_header("From", "<sip:1002@example.com>;tag=a")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Contact", "<sip:1002@192.0.2.30:5060>");
+        b2bua
+            .handle_message_from(Message::Request(invite), alice)
+            .await
+            .unwrap();
+        let fork = out_rx.try_recv().unwrap();
+        assert_eq!(fork.destination, bob);
+
+        let answer = |status: u16| {
+            let request = &fork.request;
+            Response::new(StatusCode(status))
+                .with_header("Via", request.get_header_value("Via").unwrap())
+                .with_header("From", request.get_header_value("From").unwrap())
+                .with_header("To", "<sip:1001@example.com>;tag=bob")
+                .with_header("Call-ID", "relay1")
+                .with_header("CSeq", request.get_header_value("CSeq").unwrap())
+                .with_header("Contact", "<sip:1001@192.0.2.20:5060>")
+        };
+
+        // Ringing and the answer reach the caller in the caller's dialog
+        b2bua
+            .handle_message(Message::Response(answer(180)))
+            .await
+            .unwrap();
+        let ringing = relay_rx.try_recv().unwrap();
+        assert_eq!(ringing.destination, alice);
+        assert_eq!(ringing.response.status_code, StatusCode::RINGING);
+        assert_eq!(
+            ringing.response.get_header_value("Via"),
+            Some("SIP/2.0/UDP 192.0.2.30:5060;branch=z9hG4bK-a")
+        );
+        assert_eq!(ringing.response.get_header_value("CSeq"), Some("1 INVITE"));
+        let tag = ringing
+            .response
+            .get_header_value("To")
+            .and_then(header_tag)
+            .unwrap()
+            .to_string();
+        assert_ne!(tag, "bob");
+
+        let answered = answer(200)
+            .with_header("Content-Type", "application/sdp")
+            .with_body("v=0\r\nm=audio 4000 RTP/AVP 0\r\n");
+        b2bua
+            .handle_message(Message::Response(answered))
+            .await
+            .unwrap();
+        let ok = relay_rx.try_recv().unwrap();
+        assert_eq!(ok.response.status_code, StatusCode::OK);
+        assert_eq!(
+            ok.response.get_header_value("To").and_then(header_tag),
+            Some(tag.as_str())
+        );
+        assert!(String::from_utf8_lossy(&ok.response.body).contains("m=audio 4000"));
+
+        // The callee's answer is ACKed where it came from
+        let ack = out_rx.try_recv().unwrap();
+        assert_eq!(ack.destination, bob);
+        assert_eq!(ack.request.method, Method::Ack);
+        assert_eq!(
+            ack.request.get_header_value("To").and_then(header_tag),
+            Some("bob")
+        );
+        let invite_cseq = fork.request.get_header_value("CSeq").unwrap();
+        assert_eq!(
+            ack.request.get_header_value("CSeq"),
+            Some(invite_cseq.replace("INVITE", "ACK").as_str())
+        );
+
+        // The caller hanging up sends the callee a BYE
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "192.0.2.1".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.30:5060;branch=z9hG4bK-b")
+        .with_header("Call-ID", "relay1")
+        .with_header("CSeq", "2 BYE")
+        .with_header("From", "<sip:1002@example.com>;tag=a")
+        .with_header("To", format!("<sip:1001@example.com>;tag={}", tag).as_str());
+        let Some(Message::Response(response)) = b2bua
+            .handle_message_from(Message::Request(bye), alice)
+            .await
+            .unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.status_code, StatusCode::OK);
+        let bye = out_rx.try_recv().unwrap();
+        assert_eq!(bye.destination, bob);
+        assert_eq!(bye.request.method, Method::Bye);
+        assert_eq!(bye.request.uri.to_string(), "sip:1001@192.0.2.20:5060");
+        assert_eq!(
+            bye.request.get_header_value("To").and_then(header_tag),
+            Some("bob")
+        );
+        assert_eq!(b2bua.session_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_relays_browser_media() {
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "127.0.0.1:5060".parse().unwrap();
+        let identity = DtlsIdentity::generate().unwrap();
+        let b2bua = B2BUA::new()
+            .with_registrar(Registrar::new())
+            .with_outbound_sink(out_tx, local)
+            .with_relay_sink(relay_tx)
+            .with_webrtc_media(identity.clone());
+
+        // 1001 has a desk phone and a browser softphone
+        let desk: SocketAddr = "192.0.2.20:5060".parse().unwrap();
+        let softphone: SocketAddr = "192.0.2.21:443".parse().unwrap();
+        for (call_id, contact, source) in [
+            ("reg-desk", "<sip:1001@192.0.2.20:5060>", desk),
+            (
+                "reg-soft",
+                "<sip:k3j2@h7sd.invalid;transport=ws>",
+                softphone,
+            ),
+        ] {
+            let register = Request::new(
+                Method::Register,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", call_id)
+            .with_header("To", "<sip:1001@example.com>")
+            .with_header("Contact", contact);
+            b2bua
+                .handle_message_from(Message::Request(register), source)
+                .await
+                .unwrap();
+        }
+
+        let offer = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n\
+                     m=audio 9 UDP/TLS/RTP/SAVPF 0\r\nc=IN IP4 0.0.0.0\r\na=mid:0\r\n\
+                     a=ice-ufrag:brws\r\na=ice-pwd:browser-password-0123456789\r\n\
+                     a=fingerprint:sha-256 AB:CD\r\na=setup:actpass\r\na=rtcp-mux\r\n";
+        let browser: SocketAddr = "192.0.2.30:443".parse().unwrap();
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/WSS 192.0.2.30:443;branch=z9hG4bK-w")
+        .with_header("Call-ID", "webrtc1")
+        .with_header("CSeq", "1 INVITE")
+        .with_header("From", "<sip:1002@example.com>;tag=w")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Content-Type", "application/sdp")
+        .with_header("Content-Length", offer.len().to_string().as_str())
+        .with_body(offer);
+        b2bua
+            .handle_message_from(Message::Request(invite), browser)
+            .await
+            .unwrap();
+
+        // The desk phone is offered plain RTP at the relay, the softphone
+        // the browser's own offer
+        let mut forks = [out_rx.try_recv().unwrap(), out_rx.try_recv().unwrap()];
+        forks.sort_by_key(|f| f.destination != desk);
+        let [desk_fork, soft_fork] = forks;
+        let plain = String::from_utf8_lossy(&desk_fork.request.body).into_owned();
+        assert!(plain.contains(" RTP/AVP 0"));
+        assert!(!plain.contains("fingerprint"));
+        assert_eq!(
+            desk_fork.request.get_header_value("Content-Length"),
+            Some(plain.len().to_string().as_str())
+        );
+        assert_eq!(soft_fork.destination, softphone);
+        assert_eq!(soft_fork.request.body, offer.as_bytes());
+
+        // The desk phone's plain answer reaches the browser as WebRTC
+        let request = &desk_fork.request;
+        let answered = Response::new(StatusCode::OK)
+            .with_header("Via", request.get_header_value("Via").unwrap())
+            .with_header("From", request.get_header_value("From").unwrap())
+            .with_header("To", "<sip:1001@example.com>;tag=desk")
+            .with_header("Call-ID", "webrtc1")
+            .with_header("CSeq", request.get_header_value("CSeq").unwrap())
+            .with_header("Contact", "<sip:1001@192.0.2.20:5060>")
+            .with_header("Content-Type", "application/sdp")
+            .with_body("v=0\r\no=- 2 2 IN IP4 192.0.2.20\r\ns=-\r\nc=IN IP4 192.0.2.20\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n");
+        b2bua
+            .handle_message(Message::Response(answered))
+            .await
+            .unwrap();
+        let ok = relay_rx.try_recv().unwrap();
+        assert_eq!(ok.destination, browser);
+        let answer = String::from_utf8_lossy(&ok.response.body);
+        assert!(answer.contains("UDP/TLS/RTP/SAVPF 0"));
+        assert!(answer.contains("a=ice-lite"));
+        assert!(answer.contains(&format!("a=fingerprint:{}", identity.fingerprint())));
+        assert!(answer.contains("a=setup:passive"));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_dialog_sequencing() {
+        let b2bua = B2BUA::new();
+        let request = |method: Method, from: &str, cseq: &str| {
+            Message::Request(
+                Request::new(
+                    method,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user("1001".to_string()),
+                )
+                .with_header("Call-ID", "seq1")
+                .with_header("From", from)
+                .with_header("To", "<sip:1001@example.com>")
+                .with_header("CSeq", cseq),
+            )
+        };
+        let status = |reply: Option<Message>| match reply {
+            Some(Message::Response(response)) => response,
+            other => panic!("expected a response, got {:?}", other),
+        };
+        let caller = "<sip:1002@example.com>;tag=a";
+        let callee = "<sip:1001@example.com>;tag=b";
+
+        let rejected = b2bua
+            .handle_message(request(Method::Invite, caller, "x INVITE"))
+            .await
+            .unwrap();
+        assert_eq!(status(rejected).status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(b2bua.session_count().await, 0);
+
+        b2bua
+            .handle_message(request(Method::Invite, caller, "5 INVITE"))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(request(Method::Ack, caller, "5 ACK"))
+            .await
+            .unwrap();
+
+        // Going backwards is out of order
+        let late = status(
+            b2bua
+                .handle_message(request(Method::Info, caller, "4 INFO"))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(late.status_code, StatusCode::SERVER_INTERNAL_ERROR);
+        assert!(late.get_header_value("Retry-After").is_some());
+        assert_eq!(late.get_header_value("CSeq"), Some("4 INFO"));
+        assert_eq!(late.get_header_value("From"), Some(caller));
+
+        let info = b2bua
+            .handle_message(request(Method::Info, caller, "6 INFO"))
+            .await
+            .unwrap();
+        assert_eq!(status(info).status_code, StatusCode::OK);
+        // The callee has a sequence of its own
+        let info = b2bua
+            .handle_message(request(Method::Info, callee, "1 INFO"))
+            .await
+            .unwrap();
+        assert_eq!(status(info).status_code, StatusCode::OK);
+
+        // A CSeq naming another method is malformed
+        let bye = b2bua
+            .handle_message(request(Method::Bye, caller, "7 INFO"))
+            .await
+            .unwrap();
+        assert_eq!(status(bye).status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(b2bua.session_count().await, 1);
+        let bye = b2bua
+            .handle_message(request(Method::Bye, callee, "2 BYE"))
+            .await
+            .unwrap();
+        assert_eq!(status(bye).status_code, StatusCode::OK);
+        assert_eq!(b2bua.session_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_hangup() {
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new().with_outbound_sink(out_tx, "192.0.2.1:5060".parse().unwrap());
+        let source: SocketAddr = "192.0.2.30:5060".parse().unwrap();
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Call-ID", "kill1")
+        .with_header("From", "<sip:1002@example.com>;tag=a")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("CSeq", "1 INVITE")
+        .with_header("Contact", "<sip:1002@192.0.2.30:5060>");
+        b2bua
+            .handle_message_from(Message::Request(invite), source)
+            .await
+            .unwrap();
+
+        assert!(b2bua.hangup("kill1").await);
+        assert_eq!(b2bua.session_count().await, 0);
+        let bye = out_rx.try_recv().unwrap();
+        assert_eq!(bye.destination, source);
+        assert_eq!(bye.request.method, Method::Bye);
+        assert_eq!(bye.request.uri.to_string(), "sip:1002@192.0.2.30:5060");
+        assert_eq!(
+            bye.request.get_header_value("To"),
+            Some("<sip:1002@example.com>;tag=a")
+        );
+        assert!(bye
+            .request
+            .get_header_value("Reason")
+            .unwrap()
+            .starts_with("Q.850;cause=16"));
+        assert!(out_rx.try_recv().is_err());
+
+        assert!(!b2bua.hangup("kill1").await);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_in_dialog_requests() {
+        let b2bua = B2BUA::new();
+        let source: SocketAddr = "192.0.2.30:5060".parse().unwrap();
+        let request = |method: Method, to: &str, cseq: &str, contact: &str| {
+            Message::Request(
+                Request::new(
+                    method,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user("1001".to_string()),
+                )
+                .with_header("Call-ID", "dlg1")
+                .with_header("From", "<sip:1002@example.com>;tag=a")
+                .with_header("To", to)
+                .with_header("CSeq", cseq)
+                .with_header("Contact", contact),
+            )
+        };
+        let status = |reply: Option<Message>| match reply {
+            Some(Message::Response(response)) => response,
+            other => panic!("expected a response, got {:?}", other),
+        };
+
+        b2bua
+            .handle_message_from(
+                request(
+                    Method::Invite,
+                    "<sip:1001@example.com>",
+                    "1 INVITE",
+                    "<sip:1002@192.0.2.30:5060>",
+                ),
+                source,
+            )
+            .await
+            .unwrap();
+        let (id, local_tag) = {
+            let sessions = b2bua.sessions.read().await;
+            let session = sessions.values().next().unwrap();
+            let dialog = session.dialog(LegSide::A).unwrap();
+            assert_eq!(dialog.state, DialogState::Early);
+            assert_eq!(dialog.id.remote_tag.as_deref(), Some("a"));
+            (session.id().clone(), dialog.id.local_tag.clone())
+        };
+        b2bua
+            .handle_message(request(
+                Method::Ack,
+                &format!("<sip:1001@example.com>;tag={}", local_tag),
+                "1 ACK",
+                "<sip:1002@192.0.2.30:5060>",
+            ))
+            .await
+            .unwrap();
+
+        // The caller moves networks and re-INVITEs from its new address
+        let to = format!("<sip:1001@example.com>;tag={}", local_tag);
+        let reinvite = status(
+            b2bua
+                .handle_message(request(
+                    Method::Invite,
+                    &to,
+                    "2 INVITE",
+                    "<sip:1002@198.51.100.7:5062>",
+                ))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(reinvite.status_code, StatusCode::OK);
+        assert_eq!(reinvite.get_header_value("To"), Some(to.as_str()));
+        assert_eq!(reinvite.get_header_value("CSeq"), Some("2 INVITE"));
+        let update = status(
+            b2bua
+                .handle_message(request(
+                    Method::Update,
+                    &to,
+                    "3 UPDATE",
+                    "<sip:1002@198.51.100.7:5064>",
+                ))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(update.status_code, StatusCode::OK);
+        {
+            let sessions = b2bua.sessions.read().await;
+            assert_eq!(sessions.len(), 1);
+            let session = &sessions[&id];
+            let dialog = session.dialog(LegSide::A).unwrap();
+            assert_eq!(dialog.state, DialogState::Confirmed);
+            assert_eq!(
+                dialog.remote_target.to_string(),
+                "sip:1002@198.51.100.7:5064"
+            );
+            assert_eq!(
+                session.a_leg().unwrap().contact.as_deref(),
+                Some("<sip:1002@198.51.100.7:5064>")
+            );
+        }
+
+        // A tag we never handed out names no dialog
+        let stale = status(
+            b2bua
+                .handle_message(request(
+                    Method::Update,
+                    "<sip:1001@example.com>;tag=unknown",
+                    "4 UPDATE",
+                    "<sip:1002@198.51.100.7:5064>",
+                ))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(stale.status_code, StatusCode::CALL_DOES_NOT_EXIST);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_records_traffic_samples() {
+        let sampler = TrafficSampler::new(10);
+        let b2bua = B2BUA::new().with_traffic_sampler(sampler.clone());
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1002".to_string()),
+        )
+        .with_header("Call-ID", "sampled")
+        .with_header("From", "<sip:1001@example.com>;tag=a");
+        b2bua
+            .handle_message_from(Message::Request(invite), "192.0.2.10:5060".parse().unwrap())
+            .await
+            .unwrap();
+
+        let samples = sampler.samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].caller_id, "1001");
+        assert_eq!(samples[0].destination, "1002");
+        assert_eq!(samples[0].source_ip, Some("192.0.2.10".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_class_of_service() {
+        use crate::cos::{CallClass, CosConfig, OverridePin};
+
+        let mut config = CosConfig::default();
+        config
+            .extensions
+            .insert("1001".to_string(), "internal_only".to_string());
+        config.override_pins.push(OverridePin {
+            pin: "4321".to_string(),
+            profile: "national".to_string(),
+            extension: None,
+        });
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_class_of_service(Arc::new(CosPolicy::new(config).unwrap()))
+            .with_cdr_sink(tx);
+
+        let invite = |call_id: &str, pin: Option<&str>| {
+            let mut invite = Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                    .with_user("2125551234".to_string()),
+            )
+            .with_header("Call-ID", call_id)
+            .with_header("From", "<sip:1001@example.com>;tag=a");
+            if let Some(pin) = pin {
+                invite = invite.with_header(OVERRIDE_PIN_HEADER, pin);
+            }
+            Message::Request(invite)
+        };
+
+        let reply = b2bua.handle_message(invite("denied", None)).await.unwrap();
+        match reply {
+            Some(Message::Response(res)) => {
+                assert_eq!(res.status_code, StatusCode::FORBIDDEN);
+                assert!(res
+                    .get_header_value("Warning")
+                    .unwrap()
+                    .contains("national"));
+            }
+            other => panic!("Expected 403, got {:?}", other),
+        }
+        assert_eq!(b2bua.session_count().await, 0);
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.disposition, CallDisposition::Denied);
+        assert_eq!(cdr.call_class, Some(CallClass::National));
+        assert_eq!(
+            cdr.denial_reason.as_deref(),
+            Some("national calls are not allowed by profile internal_only")
+        );
+
+        let reply = b2bua
+            .handle_message(invite("override", Some("4321")))
+            .await
+            .unwrap();
+        match reply {
+            Some(Message::Response(res)) => assert_eq!(res.status_code, StatusCode::TRYING),
+            other => panic!("Expected 100, got {:?}", other),
+        }
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "override");
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.call_class, Some(CallClass::National));
+        assert!(cdr.authorized_by_pin);
+        assert!(cdr.denial_reason.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_missed_calls() {
+        use crate::missed_calls::MissedCallProfile;
+
+        let config = MissedCallConfig {
+            extensions: HashMap::from([("1001".to_string(), MissedCallProfile::default())]),
+            smtp: None,
+        };
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let b2bua = B2BUA::new()
+            .with_event_bus(events)
+            .with_missed_calls(Arc::new(config));
+
+        let request = |method: Method, call_id: &str, to: &str| {
+            Request::new(
+                method,
+                Uri::new("sip".to_string(), "example.com".to_string()).with_user(to.to_string()),
+            )
+            .with_header("Call-ID", call_id)
+            .with_header("From", "<sip:+12125551234@carrier.example.com>;tag=a")
+            .with_header("To", format!("<sip:{}@example.com>", to).as_str())
+        };
+        let kinds = |rx: &mut tokio::sync::broadcast::Receiver<CallEvent>| {
+            std::iter::from_fn(|| rx.try_recv().ok())
+                .map(|e| e.kind)
+                .collect::<Vec<_>>()
+        };
+
+        // Cancelled before anyone answered
+        for method in [Method::Invite, Method::Cancel] {
+            b2bua
+                .handle_message(Message::Request(request(method, "missed", "1001")))
+                .await
+                .unwrap();
+        }
+        let events: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let missed = events.last().unwrap();
+        assert_eq!(missed.kind, CallEventKind::Missed);
+        assert_eq!(missed.caller.as_deref(), Some("+12125551234"));
+        assert_eq!(missed.callee.as_deref(), Some("1001"));
+        assert_eq!(missed.did.as_deref(), Some("1001"));
+
+        // Picked up on another phone
+        b2bua
+            .handle_message(Message::Request(request(
+                Method::Invite,
+                "elsewhere",
+                "1001",
+            )))
+            .await
+            .unwrap();
+        let cancel = request(Method::Cancel, "elsewhere", "1001")
+            .with_header("Reason", "SIP;cause=200;text=\"Call completed elsewhere\"");
+        b2bua
+            .handle_message(Message::Request(cancel))
+            .await
+            .unwrap();
+        assert!(!kinds(&mut rx).contains(&CallEventKind::Missed));
+
+        // Not an extension
+        for method in [Method::Invite, Method::Cancel] {
+            b2bua
+                .handle_message(Message::Request(request(method, "pstn", "+442079460000")))
+                .await
+                .unwrap();
+        }
+        assert!(!kinds(&mut rx).contains(&CallEventKind::Missed));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_call_events_with_crm() {
+        use crate::crm::CrmConfig;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/lookup", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await;
+            let body =
+                r#"{"customer_name":"Acme Corp","ticket_url":"https://crm.example.com/t/42"}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let b2bua = B2BUA::new()
+            .with_event_bus(events)
+            .with_crm(Arc::new(CrmClient::new(CrmConfig {
+                url,
+                headers: HashMap::new(),
+                timeout_ms: 5000,
+                cache_seconds: 60,
+            })));
+
+        let request = |method: Method| {
+            Message::Request(
+                Request::new(
+                    method,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user("1001".to_string()),
+                )
+                .with_header("Call-ID", "crm")
+                .with_header("From", "<sip:+12125551234@carrier.example.com>;tag=a")
+                .with_header("To", "<sip:1001@example.com>"),
+            )
+        };
+        b2bua.handle_message(request(Method::Invite)).await.unwrap();
+
+        let ringing = rx.recv().await.unwrap();
+        assert_eq!(ringing.kind, CallEventKind::Ringing);
+        assert_eq!(ringing.caller.as_deref(), Some("+12125551234"));
+        assert_eq!(ringing.callee.as_deref(), Some("1001"));
+        let crm = ringing.crm.unwrap();
+        assert_eq!(crm.customer_name.as_deref(), Some("Acme Corp"));
+        assert_eq!(
+            crm.ticket_url.as_deref(),
+            Some("https://crm.example.com/t/42")
+        );
+
+        b2bua.handle_message(request(Method::Ack)).await.unwrap();
+        b2bua.handle_message(request(Method::Bye)).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap().kind, CallEventKind::Answered);
+        let hangup = rx.recv().await.unwrap();
+        assert_eq!(hangup.kind, CallEventKind::Hangup);
+        assert_eq!(hangup.crm, None);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_records_teams_correlation_id() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new().with_cdr_sink(tx);
+
+        for (call_id, correlation_id) in [("teams", Some("5c4f2a1e")), ("local", None)] {
+            let mut invite = Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "sbc.example.com".to_string())
+                    .with_user("+12125551234".to_string()),
+            )
+            .with_header("Call-ID", call_id);
+            if let Some(id) = correlation_id {
+                invite = invite.with_header(CORRELATION_ID_HEADER, id);
+            }
+            b2bua
+                .handle_message(Message::Request(invite))
+                .await
+                .unwrap();
+            let bye = Request::new(
+                Method::Bye,
+                Uri::new("sip".to_string(), "sbc.example.com".to_string()),
+            )
+            .with_header("Call-ID", call_id);
+            b2bua.handle_message(Message::Request(bye)).await.unwrap();
+
+            let cdr = rx.try_recv().unwrap();
+            assert_eq!(cdr.correlation_id.as_deref(), correlation_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_wholesale_peer() {
+        let config = serde_json::from_value(serde_json::json!({
+            "peers": [{
+                "name": "acme",
+                "addresses": ["203.0.113.0/24"],
+                "tech_prefix": "1234#",
+                "max_concurrent": 1,
+                "rate_deck": "standard"
+            }],
+            "rate_decks": [{
+                "name": "standard",
+                "rates": [{ "prefix": "1", "per_minute": 0.01 }]
+            }]
+        }))
+        .unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_wholesale(Arc::new(WholesaleGateway::new(config).unwrap()))
+            .with_cdr_sink(tx);
+        let peer: SocketAddr = "203.0.113.10:5060".parse().unwrap();
+        let invite = |call_id: &str, dialed: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "sbc.example.com".to_string())
+                        .with_user(dialed.to_string()),
+                )
+                .with_header("Call-ID", call_id),
+            )
+        };
+        let status = |response: Option<Message>| match response {
+            Some(Message::Response(r)) => r.status_code,
+            other => panic!("expected a response, got {:?}", other),
+        };
+
+        let trying = b2bua
+            .handle_message_from(invite("w1", "1234#12125551234"), peer)
+            .await
+            .unwrap();
+        assert_eq!(status(trying), StatusCode::TRYING);
+        let sessions = b2bua.sessions.read().await;
+        let session = sessions.values().next().unwrap();
+        assert_eq!(session.carrier_peer(), Some("acme"));
+        assert_eq!(session.carrier_number(), Some("12125551234"));
+        drop(sessions);
+
+        // Over capacity, and a destination the deck has no rate for
+        let busy = b2bua
+            .handle_message_from(invite("w2", "1234#13105551234"), peer)
+            .await
+            .unwrap();
+        assert_eq!(status(busy), StatusCode::SERVICE_UNAVAILABLE);
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "sbc.example.com".to_string()),
+        )
+        .with_header("Call-ID", "w1");
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
+        let unrated = b2bua
+            .handle_message_from(invite("w3", "1234#4420712345"), peer)
+            .await
+            .unwrap();
+        assert_eq!(status(unrated), StatusCode::FORBIDDEN);
+
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.carrier_peer.as_deref(), Some("acme"));
+        let charge = cdr.charge.unwrap();
+        assert_eq!(charge.rate_deck, "standard");
+        assert_eq!(charge.amount, 0.0);
+        assert!(rx.try_recv().is_err());
+
+        // Calls from other addresses are not touched
+        let other: SocketAddr = "198.51.100.1:5060".parse().unwrap();
+        b2bua
+            .handle_message_from(invite("w4", "1234#12125551234"), other)
+            .await
+            .unwrap();
+        let sessions = b2bua.sessions.read().await;
+        let session = sessions.values().next().unwrap();
+        assert_eq!(session.carrier_peer(), None);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_account_codes() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_account_codes(Arc::new(AccountCodeConfig::default()))
+            .with_cdr_sink(tx);
+
+        let invite = |call_id: &str, dialed: &str, header: Option<&str>| {
+            let mut invite = Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                    .with_user(dialed.to_string()),
+            )
+            .with_header("Call-ID", call_id)
+            .with_header("From", "<sip:1001@example.com>;tag=a");
+            if let Some(code) = header {
+                invite = invite.with_header(ACCOUNT_CODE_HEADER, code);
+            }
+            Message::Request(invite)
+        };
+        let bye = |call_id: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Bye,
+                    Uri::new("sip".to_string(), "example.com".to_string()),
+                )
+                .with_header("Call-ID", call_id),
+            )
+        };
+
+        // National calls need a code
+        let reply = b2bua
+            .handle_message(invite("missing", "2125551234", None))
+            .await
+            .unwrap();
+        match reply {
+            Some(Message::Response(res)) => assert_eq!(res.status_code, StatusCode::FORBIDDEN),
+            other => panic!("Expected 403, got {:?}", other),
+        }
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.disposition, CallDisposition::Denied);
+        assert_eq!(
+            cdr.denial_reason.as_deref(),
+            Some("national calls require an account code")
+        );
+
+        // Dialed with the feature code prefix
+        b2bua
+            .handle_message(invite("dialed", "*7555*2125551234", None))
+            .await
+            .unwrap();
+        b2bua.handle_message(bye("dialed")).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.account_code.as_deref(), Some("555"));
+
+        // Supplied as a header
+        b2bua
+            .handle_message(invite("header", "2125551234", Some("777")))
+            .await
+            .unwrap();
+        b2bua.handle_message(bye("header")).await.unwrap();
+        assert_eq!(rx.try_recv().unwrap().account_code.as_deref(), Some("777"));
+
+        // Internal calls go through without one
+        b2bua
+            .handle_message(invite("internal", "1002", None))
+            .await
+            .unwrap();
+        b2bua.handle_message(bye("internal")).await.unwrap();
+        assert!(rx.try_recv().unwrap().account_code.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_dial_pins() {
+        use crate::dial_pin::DialPin;
+
+        let config = DialPinConfig {
+            extensions: vec!["1900".to_string()],
+            pins: vec![DialPin {
+                pin: "2468".to_string(),
+                user: Some("alice".to_string()),
+            }],
+            max_failures: 2,
+            ..Default::default()
+        };
+        let (cdr_tx, mut cdrs) = mpsc::unbounded_channel();
+        let (audit_tx, mut audit) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_dial_pins(Arc::new(config))
+            .with_dial_pin_audit(audit_tx)
+            .with_cdr_sink(cdr_tx);
+
+        let invite = |call_id: &str, from: &str, dialed: &str, pin: Option<&str>| {
+            let mut invite = Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                    .with_user(dialed.to_string()),
+            )
+            .with_header("Call-ID", call_id)
+            .with_header("From", format!("<sip:{}@example.com>;tag=a", from).as_str());
+            if let Some(pin) = pin {
+                invite = invite.with_header(DIAL_PIN_HEADER, pin);
+            }
+            Message::Request(invite)
+        };
+
+        let reply = b2bua
+            .handle_message(invite("wrong", "1900", "2125551234", Some("0000")))
+            .await
+            .unwrap();
+        match reply {
+            Some(Message::Response(res)) => assert_eq!(res.status_code, StatusCode::FORBIDDEN),
+            other => panic!("Expected 403, got {:?}", other),
+        }
+        let attempt = audit.try_recv().unwrap();
+        assert_eq!(attempt.extension, "1900");
+        assert!(attempt.pin_supplied);
+        assert!(!attempt.accepted);
+        assert_eq!(
+            cdrs.try_recv().unwrap().denial_reason.as_deref(),
+            Some("invalid dialing PIN")
+        );
+
+        b2bua
+            .handle_message(invite("right", "1900", "2125551234", Some("2468")))
+            .await
+            .unwrap();
+        let attempt = audit.try_recv().unwrap();
+        assert!(attempt.accepted);
+        assert_eq!(attempt.user.as_deref(), Some("alice"));
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "right");
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
+        assert_eq!(
+            cdrs.try_recv().unwrap().dial_pin_user.as_deref(),
+            Some("alice")
+        );
+
+        // Internal calls and other extensions are not checked or audited
+        b2bua
+            .handle_message(invite("internal", "1900", "1001", None))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(invite("desk", "1001", "2125551234", None))
+            .await
+            .unwrap();
+        assert!(audit.try_recv().is_err());
+        assert_eq!(b2bua.session_count().await, 2);
+
+        // Two invalid PINs from one address lock it out, after which even
+        // a valid PIN is refused from there
+        let lobby: SocketAddr = "192.0.2.50:5060".parse().unwrap();
+        for call_id in ["guess1", "guess2"] {
+            b2bua
+                .handle_message_from(invite(call_id, "1900", "2125551234", Some("1357")), lobby)
+                .await
+                .unwrap();
+        }
+        assert!(!audit.try_recv().unwrap().locked_out);
+        let attempt = audit.try_recv().unwrap();
+        assert!(attempt.locked_out);
+        assert_eq!(attempt.source, Some(lobby.ip()));
+        let reply = b2bua
+            .handle_message_from(invite("locked", "1900", "2125551234", Some("2468")), lobby)
+            .await
+            .unwrap();
+        match reply {
+            Some(Message::Response(res)) => assert_eq!(res.status_code, StatusCode::FORBIDDEN),
+            other => panic!("Expected 403, got {:?}", other),
+        }
+        let attempt = audit.try_recv().unwrap();
+        assert!(!attempt.accepted);
+        assert!(attempt.locked_out);
+
+        // A caller elsewhere naming the same extension is not locked out
+        let desk: SocketAddr = "192.0.2.51:5060".parse().unwrap();
+        b2bua
+            .handle_message_from(
+                invite("elsewhere", "1900", "2125551234", Some("2468")),
+                desk,
+            )
+            .await
+            .unwrap();
+        let attempt = audit.try_recv().unwrap();
+        assert!(attempt.accepted);
+        assert!(!attempt.locked_out);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_callback_when_busy() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_callbacks(CallbackQueue::default())
+            .with_callback_sink(tx);
+
+        let invite = |call_id: &str, from: &str, to: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user(to.to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", format!("<sip:{}@example.com>;tag=a", from).as_str())
+                .with_header("To", format!("<sip:{}@example.com>", to).as_str()),
+            )
+        };
+        let final_response = |call_id: &str, status: StatusCode| {
+            Message::Response(Response::new(status).with_header("Call-ID", call_id))
+        };
+        let bye = |call_id: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Bye,
+                    Uri::new("sip".to_string(), "example.com".to_string()),
+                )
+                .with_header("Call-ID", call_id),
+            )
+        };
+
+        // 1002 is talking to 1003 when 1001 calls and gets busy
+        b2bua
+            .handle_message(invite("busy-call", "1003", "1002"))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(invite("attempt", "1001", "1002"))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(final_response("attempt", StatusCode::BUSY_HERE))
+            .await
+            .unwrap();
+        assert_eq!(b2bua.session_count().await, 1);
+
+        let reply = b2bua
+            .handle_message(invite("camp", "1001", "*6"))
+            .await
+            .unwrap();
+        match reply {
+            Some(Message::Response(res)) => assert_eq!(res.status_code, StatusCode::OK),
+            other => panic!("Expected 200, got {:?}", other),
+        }
+        assert_eq!(b2bua.session_count().await, 1);
+        assert!(rx.try_recv().is_err());
+
+        // Nothing to camp on for a caller that never hit busy
+        let reply = b2bua
+            .handle_message(invite("none", "1004", "*6"))
+            .await
+            .unwrap();
+        match reply {
+            Some(Message::Response(res)) => assert_eq!(res.status_code, StatusCode::NOT_FOUND),
+            other => panic!("Expected 404, got {:?}", other),
+        }
+
+        b2bua.handle_message(bye("busy-call")).await.unwrap();
+        let callback = rx.try_recv().unwrap();
+        assert_eq!(callback.caller, "1001");
+        assert_eq!(callback.target, "1002");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_voicemail_retrieval() {
+        use crate::voicemail::{RetrievalConfig, VoicemailBox, VoicemailManager};
+
+        let manager = VoicemailManager::new(std::env::temp_dir().join("rustalk_b2bua_retrieval"));
+        manager
+            .add_mailbox(VoicemailBox {
+                id: "1001".to_string(),
+                extension: "1001".to_string(),
+                pin: "2468".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        manager
+            .leave_message("1001", "5551234", None, b"audio", 5)
+            .await
+            .unwrap();
+        let (tx, mut callbacks) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_voicemail_retrieval(VoicemailRetrieval::new(
+                RetrievalConfig::default(),
+                manager.clone(),
+            ))
+            .with_callback_sink(tx);
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("*98".to_string()),
         )
-        .with_header("Call-ID", "call123");
+        .with_header("Call-ID", "retrieval")
+        .with_header("From", "<sip:1001@example.com>;tag=a");
+        match b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap()
+        {
+            Some(Message::Response(response)) => assert_eq!(response.status_code, StatusCode::OK),
+            other => panic!("expected 200 OK, got {:?}", other),
+        }
 
-        let result = b2bua.handle_message(Message::Request(invite)).await;
-        assert!(result.is_ok());
+        // PIN, then call back the caller of the first message
+        for key in "2468#3".chars() {
+            let info = Request::new(
+                Method::Info,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", "retrieval")
+            .with_header("Content-Type", "application/dtmf-relay")
+            .with_body(format!("Signal={}\r\nDuration=160\r\n", key));
+            match b2bua.handle_message(Message::Request(info)).await.unwrap() {
+                Some(Message::Response(response)) => {
+                    assert_eq!(response.status_code, StatusCode::OK)
+                }
+                other => panic!("expected 200 OK, got {:?}", other),
+            }
+        }
+        let callback = callbacks.try_recv().unwrap();
+        assert_eq!(callback.caller, "1001");
+        assert_eq!(callback.target, "5551234");
+        assert_eq!(b2bua.session_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_retrieval_media() {
+        use crate::media::dtmf::{TelephoneEvent, DEFAULT_PAYLOAD_TYPE};
+        use crate::media::rtp::RtpPacket;
+        use crate::voicemail::{
+            RetrievalConfig, VoicemailBox, VoicemailGreeting, VoicemailManager,
+        };
+
+        let dir = std::env::temp_dir().join("rustalk_b2bua_retrieval_media");
+        let _ = std::fs::remove_dir_all(&dir);
+        let manager = VoicemailManager::new(dir);
+        manager
+            .add_mailbox(VoicemailBox {
+                id: "1001".to_string(),
+                extension: "1001".to_string(),
+                pin: "2468".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let (out_tx, _out_rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_voicemail_retrieval(VoicemailRetrieval::new(
+                RetrievalConfig::default(),
+                manager.clone(),
+            ))
+            .with_outbound_sink(out_tx, "127.0.0.1:5060".parse().unwrap());
+
+        let phone = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let offer = format!(
+            "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+             m=audio {} RTP/AVP 0 101\r\na=rtpmap:101 telephone-event/8000\r\n",
+            phone.local_addr().unwrap().port()
+        );
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("*98".to_string()),
+        )
+        .with_header("Call-ID", "retrieval-media")
+        .with_header("From", "<sip:1001@example.com>;tag=a")
+        .with_header("Content-Type", "application/sdp")
+        .with_body(offer);
+        let Some(Message::Response(response)) = b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(
+            response.get_header_value("Content-Type"),
+            Some("application/sdp")
+        );
+        let answer = String::from_utf8_lossy(&response.body).to_string();
+        let port: u16 = answer
+            .lines()
+            .find_map(|line| line.strip_prefix("m=audio "))
+            .and_then(|m| m.split_whitespace().next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        let endpoint: SocketAddr = ([127, 0, 0, 1], port).into();
+
+        let mut packet = RtpPacket {
+            payload_type: DEFAULT_PAYLOAD_TYPE,
+            marker: true,
+            sequence: 0,
+            timestamp: 0,
+            ssrc: 9,
+            payload: Vec::new(),
+        };
+        let mut press = |keys: &str| {
+            keys.chars()
+                .map(|key| {
+                    packet.sequence += 1;
+                    packet.timestamp += 1600;
+                    packet.payload = TelephoneEvent {
+                        event: "0123456789*#".find(key).unwrap() as u8,
+                        end: true,
+                        volume: 10,
+                        duration: 800,
+                    }
+                    .to_bytes()
+                    .to_vec();
+                    packet.to_bytes()
+                })
+                .collect::<Vec<_>>()
+        };
+        // Log in and record a greeting, the keys sent as RFC 4733 events
+        for data in press("2468#5") {
+            phone.send_to(&data, endpoint).await.unwrap();
+        }
+        // Speak once the beep has played
+        tokio::time::sleep(Duration::from_millis(800)).await;
+        let speech = RtpPacket {
+            payload_type: 0,
+            marker: false,
+            sequence: 100,
+            timestamp: 100,
+            ssrc: 9,
+            payload: vec![0x10; 160],
+        };
+        phone.send_to(&speech.to_bytes(), endpoint).await.unwrap();
+        for data in press("#") {
+            phone.send_to(&data, endpoint).await.unwrap();
+        }
+
+        let mut greeting = VoicemailGreeting::Default;
+        for _ in 0..50 {
+            greeting = manager.get_mailbox("1001").await.unwrap().greeting;
+            if matches!(greeting, VoicemailGreeting::Custom(_)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let VoicemailGreeting::Custom(path) = greeting else {
+            panic!("greeting not saved");
+        };
+        let saved = crate::media::wav::decode(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(saved.len(), 160);
+
+        // Hanging up stops the endpoint
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "retrieval-media");
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
+        assert!(b2bua.ivr_media.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_call_screening() {
+        use crate::screening::ScreeningProfile;
+
+        let mut config = ScreeningConfig {
+            response_seconds: 0,
+            ..Default::default()
+        };
+        for (extension, mode) in [
+            ("1002", ScreeningMode::Screen),
+            ("1003", ScreeningMode::Announce),
+        ] {
+            config.extensions.insert(
+                extension.to_string(),
+                ScreeningProfile {
+                    mode,
+                    ..Default::default()
+                },
+            );
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_screening(Arc::new(config))
+            .with_cdr_sink(tx);
+
+        let invite = |call_id: &str, to: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user(to.to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", "\"Alice\" <sip:1001@example.com>;tag=a"),
+            )
+        };
+        let info = |call_id: &str, body: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Info,
+                    Uri::new("sip".to_string(), "example.com".to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("Content-Type", "application/dtmf-relay")
+                .with_body(body.to_string()),
+            )
+        };
+        let bye = |call_id: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Bye,
+                    Uri::new("sip".to_string(), "example.com".to_string()),
+                )
+                .with_header("Call-ID", call_id),
+            )
+        };
+
+        // The callee sends the caller to voicemail; later keys are ignored
+        b2bua
+            .handle_message(invite("screened", "1002"))
+            .await
+            .unwrap();
+        for body in ["Signal=9\r\n", "Signal=2\r\n", "Signal=1\r\n"] {
+            let reply = b2bua.handle_message(info("screened", body)).await.unwrap();
+            match reply {
+                Some(Message::Response(res)) => assert_eq!(res.status_code, StatusCode::OK),
+                other => panic!("Expected 200, got {:?}", other),
+            }
+        }
+        b2bua.handle_message(bye("screened")).await.unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap().screening,
+            Some(ScreeningOutcome::Voicemail)
+        );
+
+        b2bua
+            .handle_message(invite("announced", "1003"))
+            .await
+            .unwrap();
+        b2bua.handle_message(bye("announced")).await.unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap().screening,
+            Some(ScreeningOutcome::Announced)
+        );
+
+        b2bua.handle_message(invite("plain", "1004")).await.unwrap();
+        b2bua.handle_message(bye("plain")).await.unwrap();
+        assert_eq!(rx.try_recv().unwrap().screening, None);
+
+        // No key press within the response time goes to voicemail
+        b2bua
+            .handle_message(invite("silent", "1002"))
+            .await
+            .unwrap();
+        assert_eq!(b2bua.expire_screening().await, 1);
+        assert_eq!(b2bua.expire_screening().await, 0);
+
+        let reply = b2bua.handle_message(info("unknown", "1")).await.unwrap();
+        match reply {
+            Some(Message::Response(res)) => {
+                assert_eq!(res.status_code, StatusCode::CALL_DOES_NOT_EXIST)
+            }
+            other => panic!("Expected 481, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_sip_message_to_sms() {
+        use crate::sms::{SmsConfig, SmsProvider, SmsProviderConfig, TwilioConfig};
+
+        struct AcceptAll;
+
+        #[async_trait::async_trait]
+        impl SmsProvider for AcceptAll {
+            fn name(&self) -> &str {
+                "accept"
+            }
+
+            async fn send(&self, _from: &str, _to: &str, _body: &str) -> Result<String> {
+                Ok("SM1".to_string())
+            }
+        }
+
+        let config = SmsConfig {
+            provider: SmsProviderConfig::Twilio(TwilioConfig::default()),
+            dids: HashMap::from([("+12125551000".to_string(), "1001".to_string())]),
+            sip_bridge: true,
+        };
+        let gateway = SmsGateway::with_provider(config, Arc::new(AcceptAll));
+        let b2bua = B2BUA::new().with_sms_gateway(gateway.clone());
+
+        let message = |from: &str, to: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Message,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user(to.to_string()),
+                )
+                .with_header("From", format!("<sip:{}@example.com>;tag=a", from).as_str())
+                .with_header("Content-Type", "text/plain")
+                .with_body("On my way"),
+            )
+        };
+        let status = |reply: Option<Message>| match reply {
+            Some(Message::Response(res)) => res.status_code,
+            other => panic!("expected a response, got {:?}", other),
+        };
+
+        let reply = b2bua
+            .handle_message(message("1001", "+13105550123"))
+            .await
+            .unwrap();
+        assert_eq!(status(reply), StatusCode::ACCEPTED);
+        assert_eq!(gateway.messages()[0].to, "+13105550123");
+
+        // Extensions without a DID cannot send SMS
+        let reply = b2bua
+            .handle_message(message("1002", "+13105550123"))
+            .await
+            .unwrap();
+        assert_eq!(status(reply), StatusCode::FORBIDDEN);
+
+        let reply = b2bua.handle_message(message("1001", "1002")).await.unwrap();
+        assert_eq!(status(reply), StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_voicemail_drop() {
+        use crate::screening::ScreeningProfile;
+
+        let mut screening = ScreeningConfig::default();
+        screening.extensions.insert(
+            "1002".to_string(),
+            ScreeningProfile {
+                mode: ScreeningMode::Announce,
+                ..Default::default()
+            },
+        );
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_voicemail_drop(VoicemailDropConfig::default())
+            .with_screening(Arc::new(screening))
+            .with_cdr_sink(tx);
+
+        let call = |call_id: &str, dialed: &str, header: bool| {
+            let mut invite = Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "example.com".to_string())
+                    .with_user(dialed.to_string()),
+            )
+            .with_header("Call-ID", call_id)
+            .with_header("From", "<sip:1000@example.com>;tag=a");
+            if header {
+                invite = invite.with_header(VOICEMAIL_DROP_HEADER, "yes");
+            }
+            let bye = Request::new(
+                Method::Bye,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", call_id);
+            [Message::Request(invite), Message::Request(bye)]
+        };
+
+        for (call_id, dialed, header, expected) in [
+            ("prefix", "*551002", false, true),
+            ("header", "1002", true, true),
+            ("normal", "1002", false, false),
+        ] {
+            for message in call(call_id, dialed, header) {
+                b2bua.handle_message(message).await.unwrap();
+            }
+            let cdr = rx.try_recv().unwrap();
+            assert_eq!(cdr.voicemail_drop, expected, "{}", call_id);
+            // Only calls that ring the callee are screened
+            assert_eq!(cdr.screening.is_some(), !expected, "{}", call_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_ring_timeouts() {
+        use crate::no_answer::{NoAnswerConfig, NoAnswerProfile};
+
+        let policy = NoAnswerPolicy::new(NoAnswerConfig {
+            ring_seconds: 0,
+            ..Default::default()
+        });
+        policy.set_extension("1001", NoAnswerProfile::default());
+        policy.set_extension(
+            "1002",
+            NoAnswerProfile {
+                ring_seconds: None,
+                action: Some(NoAnswerAction::Voicemail),
+            },
+        );
+        policy.set_extension(
+            "1003",
+            NoAnswerProfile {
+                ring_seconds: Some(60),
+                action: None,
+            },
+        );
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_cdr_sink(tx)
+            .with_no_answer(policy.clone());
+        for (call_id, callee) in [
+            ("busy", "1001"),
+            ("vm", "1002"),
+            ("long", "1003"),
+            ("other", "1004"),
+        ] {
+            let invite = Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "example.com".to_string())
+                    .with_user(callee.to_string()),
+            )
+            .with_header("Call-ID", call_id);
+            b2bua
+                .handle_message(Message::Request(invite))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(b2bua.expire_ring_timeouts().await, 2);
+        assert_eq!(b2bua.expire_ring_timeouts().await, 0);
+        // The busy call was ended, the others still ring
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.call_id, "busy");
+        assert_eq!(cdr.disposition, CallDisposition::Failed);
+        assert_eq!(cdr.hangup_cause, Some(HangupCause::NoAnswer));
+        assert_eq!(cdr.no_answer, Some(NoAnswerAction::Busy));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(b2bua.session_count().await, 3);
+
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "vm");
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.no_answer, Some(NoAnswerAction::Voicemail));
+        assert!(cdr
+            .decisions
+            .iter()
+            .any(|d| d.detail == "no answer after 0s: voicemail"));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_forwards_unanswered_calls() {
+        use crate::no_answer::{NoAnswerConfig, NoAnswerProfile};
+
+        let policy = NoAnswerPolicy::new(NoAnswerConfig {
+            ring_seconds: 0,
+            ..Default::default()
+        });
+        policy.set_extension(
+            "1001",
+            NoAnswerProfile {
+                ring_seconds: None,
+                action: Some(NoAnswerAction::Forward {
+                    destination: "1003".to_string(),
+                }),
+            },
+        );
+        let registrar = Registrar::new();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let b2bua = B2BUA::new()
+            .with_registrar(registrar.clone())
+            .with_outbound_sink(out_tx, local)
+            .with_no_answer(policy);
+        for (extension, contact) in [
+            ("1001", "<sip:1001@192.0.2.20:5060>"),
+            ("1003", "<sip:1003@192.0.2.23:5060>"),
+        ] {
+            let register = Request::new(
+                Method::Register,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", contact)
+            .with_header("To", format!("<sip:{}@example.com>", extension).as_str())
+            .with_header("Contact", contact);
+            b2bua
+                .handle_message(Message::Request(register))
+                .await
+                .unwrap();
+        }
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.30:5060;branch=z9hG4bK-a")
+        .with_header("Call-ID", "fwd1")
+        .with_header("CSeq", "1 INVITE")
+        .with_header("From", "<sip:1002@example.com>;tag=a")
+        .with_header("To", "<sip:1001@example.com>");
+        b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap();
+        let ringing = out_rx.try_recv().unwrap();
+        assert_eq!(ringing.destination, "192.0.2.20:5060".parse().unwrap());
+        assert!(ringing.request.get_header("Diversion").is_none());
+
+        // 1001 does not answer: it is cancelled and 1003 rung instead
+        assert_eq!(b2bua.expire_ring_timeouts().await, 1);
+        let cancel = out_rx.try_recv().unwrap();
+        assert_eq!(cancel.request.method, Method::Cancel);
+        let forwarded = out_rx.try_recv().unwrap();
+        assert_eq!(forwarded.destination, "192.0.2.23:5060".parse().unwrap());
+        let request = &forwarded.request;
+        assert_eq!(
+            request.get_header_value("To"),
+            Some("<sip:1001@example.com>")
+        );
+        assert_eq!(
+            request.get_header_value("Diversion"),
+            Some("<sip:1001@example.com>;reason=no-answer;counter=1")
+        );
+        let history: Vec<_> = request
+            .headers
+            .iter()
+            .filter(|h| h.name.as_str() == "History-Info")
+            .map(|h| h.value.as_str())
+            .collect();
+        assert_eq!(
+            history,
+            [
+                "<sip:1001@example.com>;index=1",
+                "<sip:1003@example.com;cause=408>;index=1.1;mp=1"
+            ]
+        );
+        // The forwarded branch is numbered after the first
+        assert_eq!(request.get_header_value("CSeq"), Some("2 INVITE"));
+
+        // The cancelled branch's 487 does not end the forwarded call
+        let terminated = Response::new(StatusCode::REQUEST_TERMINATED)
+            .with_header("Via", ringing.request.get_header_value("Via").unwrap())
+            .with_header("Call-ID", "fwd1")
+            .with_header("CSeq", "1 INVITE");
+        b2bua
+            .handle_message(Message::Response(terminated))
+            .await
+            .unwrap();
         assert_eq!(b2bua.session_count().await, 1);
+    }
 
-        // Send BYE
+    #[tokio::test]
+    async fn test_b2bua_draining_rejects_new_calls() {
+        let b2bua = B2BUA::new();
+        let request = |method: Method, call_id: &str| {
+            Message::Request(
+                Request::new(
+                    method,
+                    Uri::new("sip".to_string(), "carrier.example.com".to_string()),
+                )
+                .with_header("Call-ID", call_id),
+            )
+        };
+
+        b2bua
+            .handle_message(request(Method::Invite, "up"))
+            .await
+            .unwrap();
+        b2bua.start_draining();
+        assert!(b2bua.is_draining());
+
+        match b2bua.handle_message(request(Method::Invite, "new")).await {
+            Ok(Some(Message::Response(res))) => {
+                assert_eq!(res.status_code, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(res.get_header_value("Retry-After"), Some("1"));
+            }
+            _ => panic!("Expected 503 response"),
+        }
+        assert_eq!(b2bua.session_count().await, 1);
+        assert_eq!(b2bua.drain(Duration::from_millis(10)).await, 1);
+        // Only the call still up is held here through an upgrade
+        assert!(b2bua.holds(&request(Method::Bye, "up")).await);
+        assert!(!b2bua.holds(&request(Method::Bye, "new")).await);
+
+        b2bua
+            .handle_message(request(Method::Bye, "up"))
+            .await
+            .unwrap();
+        assert_eq!(b2bua.drain(Duration::from_secs(5)).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_admission_rejects_with_503() {
+        use crate::admission::{AdmissionConfig, CallLimits, LocalCounterStore, TrunkLimits};
+        use crate::routing::{RouteDestination, RouteRule, RoutingConfig};
+
+        let config = AdmissionConfig {
+            trunks: vec![TrunkLimits {
+                trunk: "carrier".to_string(),
+                limits: CallLimits {
+                    max_cps: None,
+                    max_concurrent: Some(1),
+                    local_burst: 1,
+                    reservations: Vec::new(),
+                },
+            }],
+            ..Default::default()
+        };
+        let mut routing = RoutingConfig::new();
+        routing.add_route(RouteRule {
+            id: "external".to_string(),
+            name: "external".to_string(),
+            description: None,
+            pattern: "^0".to_string(),
+            destination: RouteDestination::Trunk("carrier".to_string()),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        });
+        let controller = AdmissionController::new(config, Arc::new(LocalCounterStore::new()));
+        let b2bua = B2BUA::new()
+            .with_routing(Arc::new(RouteEvaluator::new(routing)))
+            .with_admission_control(Arc::new(controller));
+
+        let invite = |call_id: &str, number: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "example.com".to_string())
+                        .with_user(number.to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", "<sip:1001@example.com>;tag=a")
+                .with_header("CSeq", "1 INVITE"),
+            )
+        };
+
+        b2bua.handle_message(invite("call1", "0123")).await.unwrap();
+        assert_eq!(b2bua.session_count().await, 1);
+
+        // A retransmission is not a second call
+        let response = b2bua.handle_message(invite("call1", "0123")).await.unwrap();
+        assert!(
+            matches!(response, Some(Message::Response(res)) if res.status_code == StatusCode::TRYING)
+        );
+        assert_eq!(b2bua.session_count().await, 1);
+
+        let response = b2bua.handle_message(invite("call2", "0456")).await.unwrap();
+        match response {
+            Some(Message::Response(res)) => {
+                assert_eq!(res.status_code, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(res.get_header_value("Retry-After"), Some("5"));
+            }
+            _ => panic!("Expected 503 response"),
+        }
+        assert_eq!(b2bua.session_count().await, 1);
+
+        // Calls between local users take no trunk capacity
+        b2bua.handle_message(invite("local", "1002")).await.unwrap();
+        assert_eq!(b2bua.session_count().await, 2);
+
+        // Releasing the first call frees the slot
         let bye = Request::new(
             Method::Bye,
-            Uri::new("sip".to_string(), "bob@example.com".to_string()),
+            Uri::new("sip".to_string(), "example.com".to_string()),
         )
-        .with_header("Call-ID", "call123");
+        .with_header("Call-ID", "call1");
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
 
-        let result = b2bua.handle_message(Message::Request(bye)).await;
-        assert!(result.is_ok());
+        b2bua.handle_message(invite("call3", "0789")).await.unwrap();
+        assert_eq!(b2bua.session_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_tenant_call_quota() {
+        let quotas = Quotas::new(
+            serde_json::from_value(serde_json::json!({
+                "tenants": { "acme.example": { "max_concurrent_calls": 1 } }
+            }))
+            .unwrap(),
+        );
+        let b2bua = B2BUA::new().with_quotas(quotas.clone());
+        let invite = |call_id: &str, from: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "carrier.example.com".to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", from),
+            )
+        };
+
+        b2bua
+            .handle_message(invite("acme1", "<sip:1001@acme.example>;tag=a"))
+            .await
+            .unwrap();
+        assert_eq!(quotas.active_calls("acme.example"), 1);
+        match b2bua
+            .handle_message(invite("acme2", "<sip:1002@acme.example>;tag=b"))
+            .await
+            .unwrap()
+        {
+            Some(Message::Response(res)) => {
+                assert_eq!(res.status_code, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(res.get_header_value("Retry-After"), Some("5"));
+            }
+            other => panic!("Expected 503 response, got {:?}", other),
+        }
+        // Other tenants are not affected
+        b2bua
+            .handle_message(invite("other1", "<sip:2001@other.example>;tag=c"))
+            .await
+            .unwrap();
+        assert_eq!(b2bua.session_count().await, 2);
+
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "carrier.example.com".to_string()),
+        )
+        .with_header("Call-ID", "acme1");
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
+        assert_eq!(quotas.active_calls("acme.example"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_call_trace() {
+        use crate::call_trace::TraceTarget;
+
+        let dir = std::env::temp_dir().join(format!("rustalk_b2bua_trace_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let tracer = Arc::new(CallTracer::new(&dir));
+        let info = tracer
+            .enable(TraceTarget::SourceIp("192.0.2.1".parse().unwrap()))
+            .unwrap();
+        let b2bua = B2BUA::new().with_call_tracer(tracer.clone());
+        let source: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "traced");
+        b2bua
+            .handle_message_from(Message::Request(invite), source)
+            .await
+            .unwrap();
+
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "traced");
+        b2bua
+            .handle_message_from(Message::Request(bye), source)
+            .await
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&info.file).unwrap();
+        assert!(contents.contains("INVITE sip:example.com"));
+        assert!(contents.contains("SIP/2.0 100 Trying"));
+        assert!(contents.contains("created"));
+        assert!(contents.contains("terminated"));
+        assert!(contents.contains("SIP/2.0 200 OK"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_trunk_dial_string_and_quirks() {
+        use crate::call_trace::TraceTarget;
+
+        let dir = std::env::temp_dir().join(format!("rustalk_b2bua_quirks_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let tracer = Arc::new(CallTracer::new(&dir));
+        let info = tracer
+            .enable(TraceTarget::SourceIp("192.0.2.1".parse().unwrap()))
+            .unwrap();
+        let quirks = serde_json::from_value(serde_json::json!({
+            "trunks": [{
+                "trunk": "sip.carrier.example",
+                "profile": "auth-user-from",
+                "auth_user": "4420001"
+            }]
+        }))
+        .unwrap();
+        let dial_strings = serde_json::from_value(serde_json::json!({
+            "trunks": [{
+                "trunk": "sip.carrier.example",
+                "template": "sip:7788#{digits}@{host}:5080"
+            }]
+        }))
+        .unwrap();
+        let b2bua = B2BUA::new()
+            .with_quirks(Arc::new(quirks))
+            .with_dial_strings(Arc::new(dial_strings))
+            .with_call_tracer(tracer.clone());
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "sip.carrier.example".to_string())
+                .with_user("12125551234".to_string()),
+        )
+        .with_header("Call-ID", "quirky")
+        .with_header("From", "<sip:1001@pbx.example.com>;tag=a");
+        b2bua
+            .handle_message_from(Message::Request(invite), "192.0.2.1:5060".parse().unwrap())
+            .await
+            .unwrap();
+
+        // The session keeps the real caller; only the trunk sees the auth user
+        let sessions = b2bua.sessions.read().await;
+        let session = sessions.values().next().unwrap();
+        assert_eq!(session.caller(), Some("<sip:1001@pbx.example.com>;tag=a"));
+        drop(sessions);
+        let contents = std::fs::read_to_string(&info.file).unwrap();
+        assert!(contents.contains("dialing sip:7788#12125551234@sip.carrier.example:5080"));
+        assert!(contents.contains("auth-user-from quirks applied for sip.carrier.example"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_decision_trail() {
+        use crate::acl::{AclAction, AclRule};
+        use crate::routing::{
+            CallerIdCondition, RouteCondition, RouteDestination, RouteRule, RoutingConfig,
+        };
+
+        let mut acl = Acl::new("trunks");
+        acl.add_rule(AclRule {
+            name: "pbx".to_string(),
+            cidr: "192.0.2.0/24".to_string(),
+            action: AclAction::Allow,
+            priority: 10,
+        });
+        let route = |id: &str, pattern: &str, action: RouteAction| RouteRule {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: None,
+            pattern: pattern.to_string(),
+            destination: RouteDestination::Trunk("carrier".to_string()),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        };
+        let mut routing = RoutingConfig::new();
+        routing.add_route(route("international", "^00", RouteAction::Reject));
+        let mut sales = route("sales", "^2", RouteAction::Accept);
+        sales.conditions = Some(vec![RouteCondition::CallerId(CallerIdCondition {
+            pattern: "^1001$".to_string(),
+            negate: false,
+        })]);
+        routing.add_route(sales);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_source_acl(Arc::new(acl))
+            .with_routing(Arc::new(RouteEvaluator::new(routing)))
+            .with_cdr_sink(tx);
+        let invite = |call_id: &str, number: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                        .with_user(number.to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", "<sip:1001@example.com>;tag=a"),
+            )
+        };
+        let stages = |cdr: &CallDetailRecord| -> Vec<(DecisionStage, bool)> {
+            cdr.decisions.iter().map(|d| (d.stage, d.passed)).collect()
+        };
+
+        // Unknown source address
+        b2bua
+            .handle_message_from(
+                invite("stranger", "2000"),
+                "198.51.100.7:5060".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.disposition, CallDisposition::Denied);
+        assert_eq!(
+            stages(&cdr),
+            vec![
+                (DecisionStage::Acl, false),
+                (DecisionStage::Response, false)
+            ]
+        );
+        assert_eq!(
+            cdr.decisions[0].detail,
+            "198.51.100.7 denied by ACL trunks (default policy)"
+        );
+        assert_eq!(cdr.decisions[1].leg, Some(LegSide::A));
+        assert_eq!(cdr.decisions[1].detail, "403 Forbidden");
+
+        // Rejected by a route
+        b2bua
+            .handle_message_from(
+                invite("abroad", "0044123"),
+                "192.0.2.1:5060".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(
+            cdr.denial_reason.as_deref(),
+            Some("rejected by route international")
+        );
+        assert_eq!(
+            stages(&cdr),
+            vec![
+                (DecisionStage::Acl, true),
+                (DecisionStage::Route, false),
+                (DecisionStage::Response, false)
+            ]
+        );
+
+        // Routed, sent to the trunk and answered busy
+        b2bua
+            .handle_message_from(invite("sales", "2000"), "192.0.2.1:5060".parse().unwrap())
+            .await
+            .unwrap();
+        let busy = Response::new(StatusCode::BUSY_HERE).with_header("Call-ID", "sales");
+        b2bua.handle_message(Message::Response(busy)).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.disposition, CallDisposition::Busy);
+        assert_eq!(cdr.hangup_cause, Some(HangupCause::UserBusy));
+        assert_eq!(
+            stages(&cdr),
+            vec![
+                (DecisionStage::Acl, true),
+                (DecisionStage::Route, true),
+                (DecisionStage::Trunk, true),
+                (DecisionStage::Response, true),
+                (DecisionStage::Response, false)
+            ]
+        );
+        assert_eq!(
+            cdr.decisions[0].detail,
+            "192.0.2.1 allowed by ACL trunks (rule pbx)"
+        );
+        assert_eq!(
+            cdr.decisions[1].detail,
+            "route sales (sales) matched 2000 on pattern ^2 with caller matching ^1001$ -> Trunk carrier"
+        );
+        assert_eq!(
+            cdr.decisions[2].detail,
+            "dialing sip:2000@carrier.example.com via carrier.example.com"
+        );
+        assert_eq!(cdr.decisions[4].leg, Some(LegSide::B));
+        assert_eq!(cdr.decisions[4].detail, "486 Busy Here");
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_portability_dip() {
+        use crate::lnp::{LnpConfig, LnpProvider, LnpRewrite, PortabilityLookup};
+        use crate::routing::{RouteDestination, RouteRule, RoutingConfig};
+
+        struct Ported;
+
+        #[async_trait::async_trait]
+        impl PortabilityLookup for Ported {
+            async fn lookup(&self, number: &str) -> Result<Option<String>> {
+                match number {
+                    "+12125551234" => Ok(Some("+13125550000".to_string())),
+                    "+19999999999" => Err(anyhow::anyhow!("database unavailable")),
+                    _ => Ok(None),
+                }
+            }
+        }
+
+        let config = LnpConfig {
+            provider: LnpProvider::Enum {
+                domain: "e164.arpa".to_string(),
+            },
+            trunks: vec!["carrier.example.com".to_string()],
+            timeout_ms: 500,
+            cache_seconds: 60,
+            on_failure: LnpFailure::Reject,
+            rewrite: LnpRewrite::Npdi,
+        };
+        let mut routing = RoutingConfig::new();
+        routing.add_route(RouteRule {
+            id: "chicago".to_string(),
+            name: "chicago".to_string(),
+            description: None,
+            pattern: r"^\+1312".to_string(),
+            destination: RouteDestination::Trunk("carrier".to_string()),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        });
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_portability(PortabilityDip::with_lookup(config, Arc::new(Ported)))
+            .with_routing(Arc::new(RouteEvaluator::new(routing)))
+            .with_cdr_sink(tx);
+        let invite = |call_id: &str, host: &str, number: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), host.to_string()).with_user(number.to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", "<sip:1001@example.com>;tag=a"),
+            )
+        };
+        let busy = |call_id: &str| {
+            Message::Response(Response::new(StatusCode::BUSY_HERE).with_header("Call-ID", call_id))
+        };
+
+        // Ported numbers carry their LRN and route on it
+        b2bua
+            .handle_message(invite("ported", "carrier.example.com", "+12125551234"))
+            .await
+            .unwrap();
+        b2bua.handle_message(busy("ported")).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.decisions[0].stage, DecisionStage::Portability);
+        assert_eq!(
+            cdr.decisions[0].detail,
+            "+12125551234 is ported to LRN +13125550000"
+        );
+        assert!(cdr.decisions[1]
+            .detail
+            .starts_with("route chicago (chicago) matched +13125550000"));
+        assert_eq!(
+            cdr.decisions[2].detail,
+            "dialing sip:+12125551234;npdi;rn=+13125550000@carrier.example.com via carrier.example.com"
+        );
+
+        // Trunks without dips leave the number alone
+        b2bua
+            .handle_message(invite("local", "pbx.example.com", "+12125551234"))
+            .await
+            .unwrap();
+        b2bua.handle_message(busy("local")).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert!(cdr
+            .decisions
+            .iter()
+            .all(|d| d.stage != DecisionStage::Portability));
+
+        // A failed dip rejects the call when configured to
+        let response = b2bua
+            .handle_message(invite("failed", "carrier.example.com", "+19999999999"))
+            .await
+            .unwrap();
+        match response {
+            Some(Message::Response(response)) => {
+                assert_eq!(response.status_code, StatusCode::SERVICE_UNAVAILABLE)
+            }
+            other => panic!("expected a 503, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_enum_routing() {
+        use crate::enum_resolver::{EnumConfig, NaptrSource};
+        use crate::io::dns::Naptr;
+        use crate::routing::{RouteRule, RoutingConfig};
+
+        struct Zone;
+
+        #[async_trait::async_trait]
+        impl NaptrSource for Zone {
+            async fn naptr(&self, name: &str) -> Result<Vec<Naptr>> {
+                if name != "4.3.2.1.5.5.5.2.1.2.1.e164.arpa" {
+                    return Ok(Vec::new());
+                }
+                Ok(vec![Naptr {
+                    order: 10,
+                    preference: 10,
+                    flags: "u".to_string(),
+                    services: "E2U+sip".to_string(),
+                    regexp: r"!^(.*)$!sip:\1@voip.example.com!".to_string(),
+                    replacement: ".".to_string(),
+                }])
+            }
+        }
+
+        let mut routing = RoutingConfig::new();
+        routing.add_route(RouteRule {
+            id: "e164".to_string(),
+            name: "e164".to_string(),
+            description: None,
+            pattern: r"^\+".to_string(),
+            destination: RouteDestination::Enum("carrier".to_string()),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        });
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_enum(EnumResolver::with_source(
+                EnumConfig::default(),
+                Arc::new(Zone),
+            ))
+            .with_routing(Arc::new(RouteEvaluator::new(routing)))
+            .with_cdr_sink(tx);
+        let invite = |call_id: &str, number: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                        .with_user(number.to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", "<sip:1001@example.com>;tag=a"),
+            )
+        };
+        let busy = |call_id: &str| {
+            Message::Response(Response::new(StatusCode::BUSY_HERE).with_header("Call-ID", call_id))
+        };
+
+        // Mapped numbers are delivered straight to their URI
+        b2bua
+            .handle_message(invite("mapped", "+12125551234"))
+            .await
+            .unwrap();
+        b2bua.handle_message(busy("mapped")).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(
+            cdr.decisions[0].detail,
+            "route e164 (e164) matched +12125551234 on pattern ^\\+ -> ENUM, else trunk carrier, \
+             delivered over ENUM to sip:+12125551234@voip.example.com"
+        );
+        assert_eq!(
+            cdr.decisions[1].detail,
+            "dialing sip:+12125551234@voip.example.com via voip.example.com"
+        );
+
+        // Others go to the fallback trunk
+        b2bua
+            .handle_message(invite("unmapped", "+12125550000"))
+            .await
+            .unwrap();
+        b2bua.handle_message(busy("unmapped")).await.unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert!(cdr.decisions[0]
+            .detail
+            .ends_with("no ENUM mapping, via trunk carrier"));
+        assert_eq!(
+            cdr.decisions[1].detail,
+            "dialing sip:+12125550000@carrier.example.com via carrier.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_least_cost_trail() {
+        use crate::lcr::LeastCostRouter;
+        use crate::routing::{RouteRule, RoutingConfig};
+
+        let mut routing = RoutingConfig::new();
+        routing.add_route(RouteRule {
+            id: "uk".to_string(),
+            name: "UK".to_string(),
+            description: None,
+            pattern: r"^\+44".to_string(),
+            destination: RouteDestination::LeastCost(vec![
+                "carrier-a".to_string(),
+                "carrier-b".to_string(),
+            ]),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        });
+        let rates = serde_json::from_value(serde_json::json!({
+            "rates": [{
+                "id": "b", "name": "B", "description": null, "prefix": "44",
+                "rate_per_minute": 0.01, "connection_fee": 0.0,
+                "minimum_charge_seconds": 0, "billing_increment_seconds": 6,
+                "currency": "USD", "effective_date": 0, "end_date": null,
+                "active": true, "trunk": "carrier-b"
+            }]
+        }))
+        .unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_routing(Arc::new(
+                RouteEvaluator::new(routing).with_least_cost(LeastCostRouter::new(rates)),
+            ))
+            .with_cdr_sink(tx);
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                .with_user("+442079460000".to_string()),
+        )
+        .with_header("Call-ID", "lcr")
+        .with_header("From", "<sip:1001@example.com>;tag=a");
+        b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap();
+        let busy = Response::new(StatusCode::BUSY_HERE).with_header("Call-ID", "lcr");
+        b2bua.handle_message(Message::Response(busy)).await.unwrap();
+
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(
+            cdr.decisions[0].detail,
+            "route uk (UK) matched +442079460000 on pattern ^\\+44 -> Trunk carrier-b, \
+             cheapest carrier-b at 0.0100 USD/min, then carrier-a (unrated)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_fails_over_between_destinations() {
+        use crate::routing::{RouteRule, RoutingConfig};
+
+        let mut routing = RoutingConfig::new();
+        for (id, pattern, hops) in [
+            (
+                "us",
+                r"^\+1",
+                serde_json::json!([
+                    { "destination": { "type": "Trunk", "value": "carrier-a" } },
+                    { "destination": { "type": "Trunk", "value": "carrier-b" } },
+                    { "destination": { "type": "Voicemail", "value": "1001" } }
+                ]),
+            ),
+            (
+                "uk",
+                r"^\+44",
+                serde_json::json!([
+                    { "destination": { "type": "Trunk", "value": "carrier-a" }, "timeout_seconds": 0 },
+                    { "destination": { "type": "Hangup" } }
+                ]),
+            ),
+        ] {
+            routing.add_route(RouteRule {
+                id: id.to_string(),
+                name: id.to_string(),
+                description: None,
+                pattern: pattern.to_string(),
+                destination: serde_json::from_value(serde_json::json!({
+                    "type": "DestinationList",
+                    "value": { "hops": hops }
+                }))
+                .unwrap(),
+                enabled: true,
+                priority: 10,
+                conditions: None,
+                action: RouteAction::Accept,
+                continue_on_match: false,
+                on_failure: Vec::new(),
+            });
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_routing(Arc::new(RouteEvaluator::new(routing)))
+            .with_cdr_sink(tx);
+        for (call_id, number) in [("us", "+12125551234"), ("uk", "+442079460000")] {
+            let invite = Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "carrier-a".to_string()).with_user(number.to_string()),
+            )
+            .with_header("Call-ID", call_id)
+            .with_header("From", "<sip:2000@example.com>;tag=a");
+            b2bua
+                .handle_message(Message::Request(invite))
+                .await
+                .unwrap();
+        }
+
+        // Each 503 moves the call on, and the policy does not cover 480
+        for status in [
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TEMPORARILY_UNAVAILABLE,
+        ] {
+            let response = Response::new(status).with_header("Call-ID", "us");
+            b2bua
+                .handle_message(Message::Response(response))
+                .await
+                .unwrap();
+        }
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.call_id, "us");
+        assert!(cdr.voicemail_drop);
+        let details: Vec<&str> = cdr.decisions.iter().map(|d| d.detail.as_str()).collect();
+        assert!(details[0]
+            .ends_with("-> Trunk carrier-a, falling back to Trunk carrier-b, then Voicemail 1001"));
+        assert!(
+            details.contains(&"503 (NORMAL_TEMPORARY_FAILURE), failing over to Trunk carrier-b")
+        );
+        assert!(details.contains(&"dialing sip:+12125551234@carrier-b via carrier-b"));
+        assert!(details.contains(&"503 (NORMAL_TEMPORARY_FAILURE), failing over to Voicemail 1001"));
+
+        // The UK call ran out of time at its only trunk; the hangup after
+        // it is where calls end anyway
+        assert_eq!(b2bua.expire_hop_timeouts().await, 1);
+        assert_eq!(b2bua.expire_hop_timeouts().await, 0);
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.call_id, "uk");
+        assert_eq!(cdr.hangup_cause, Some(HangupCause::NoAnswer));
+        assert!(cdr
+            .decisions
+            .iter()
+            .any(|d| d.detail == "no answer in time, no destination left"));
         assert_eq!(b2bua.session_count().await, 0);
     }
+
+    #[tokio::test]
+    async fn test_b2bua_reroutes_on_failure() {
+        use crate::routing::{RouteRule, RoutingConfig};
+
+        let mut routing = RoutingConfig::new();
+        routing.max_attempts = Some(3);
+        routing.add_route(RouteRule {
+            id: "us".to_string(),
+            name: "US".to_string(),
+            description: None,
+            pattern: r"^\+1".to_string(),
+            destination: RouteDestination::Trunk("carrier-a".to_string()),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: serde_json::from_value(serde_json::json!([
+                { "codes": ["503"], "destinations": { "hops": [
+                    { "destination": { "type": "Trunk", "value": "carrier-b" } },
+                    { "destination": { "type": "Trunk", "value": "carrier-a" } }
+                ] } },
+                { "codes": ["404", "480"], "destinations": { "hops": [
+                    { "destination": { "type": "Voicemail", "value": "1001" } }
+                ] } },
+                { "codes": ["5xx"], "destinations": { "hops": [
+                    { "destination": { "type": "Trunk", "value": "carrier-c" } }
+                ] } }
+            ]))
+            .unwrap(),
+        });
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_routing(Arc::new(RouteEvaluator::new(routing)))
+            .with_cdr_sink(tx);
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "carrier-a".to_string())
+                .with_user("+12125551234".to_string()),
+        )
+        .with_header("Call-ID", "reroute")
+        .with_header("From", "<sip:2000@example.com>;tag=a");
+        b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap();
+
+        // carrier-a, already tried, is left out of the first reroute, and
+        // the third rule would be a fourth attempt
+        for status in [
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TEMPORARILY_UNAVAILABLE,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            let response = Response::new(status).with_header("Call-ID", "reroute");
+            b2bua
+                .handle_message(Message::Response(response))
+                .await
+                .unwrap();
+        }
+        let cdr = rx.try_recv().unwrap();
+        let attempts: Vec<(Option<&str>, Option<u16>, bool)> = cdr
+            .attempts
+            .iter()
+            .map(|a| (a.destination.as_deref(), a.status, a.rerouted))
+            .collect();
+        assert_eq!(
+            attempts,
+            [
+                (Some("Trunk carrier-a"), Some(503), false),
+                (Some("Trunk carrier-b"), Some(480), true),
+                (Some("Voicemail 1001"), Some(503), true),
+            ]
+        );
+        assert_eq!(cdr.attempts[1].uri, "sip:+12125551234@carrier-b");
+        let details: Vec<&str> = cdr.decisions.iter().map(|d| d.detail.as_str()).collect();
+        assert!(details[0].ends_with(
+            "-> Trunk carrier-a, on 503 rerouting to Trunk carrier-b, then Trunk carrier-a, \
+             on 404/480 rerouting to Voicemail 1001, on 5xx rerouting to Trunk carrier-c"
+        ));
+        assert!(details.contains(&"503 (NORMAL_TEMPORARY_FAILURE), rerouting to Trunk carrier-b"));
+        assert_eq!(
+            details.last(),
+            Some(&"503 (NORMAL_TEMPORARY_FAILURE), giving up after 3 attempts")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_transcription() {
+        use crate::routing::{RouteRule, RoutingConfig};
+
+        let mut routing = RoutingConfig::new();
+        for (id, destination) in [
+            (
+                "support",
+                RouteDestination::RingGroup("support".to_string()),
+            ),
+            ("other", RouteDestination::Extension("3000".to_string())),
+        ] {
+            routing.add_route(RouteRule {
+                id: id.to_string(),
+                name: id.to_string(),
+                description: None,
+                pattern: if id == "support" { "^2000$" } else { "^3000$" }.to_string(),
+                destination,
+                enabled: true,
+                priority: 10,
+                conditions: None,
+                action: RouteAction::Accept,
+                continue_on_match: false,
+                on_failure: Vec::new(),
+            });
+        }
+        let policy = TranscriptionPolicy::new(
+            serde_json::from_value(serde_json::json!({
+                "url": "wss://transcribe.example.com/stream",
+                "legs": "caller",
+                "rules": [{ "queue": "support" }],
+                "consent": { "mode": "explicit" }
+            }))
+            .unwrap(),
+        );
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let b2bua = B2BUA::new()
+            .with_routing(Arc::new(RouteEvaluator::new(routing)))
+            .with_transcription(Arc::new(policy))
+            .with_cdr_sink(tx);
+        let invite = |call_id: &str, number: &str, consent: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Invite,
+                    Uri::new("sip".to_string(), "pbx.example.com".to_string())
+                        .with_user(number.to_string()),
+                )
+                .with_header("Call-ID", call_id)
+                .with_header("From", "<sip:1001@example.com>;tag=a")
+                .with_header("X-Transcription-Consent", consent),
+            )
+        };
+
+        b2bua
+            .handle_message(invite("agreed", "2000", "yes"))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(invite("refused", "2000", "no"))
+            .await
+            .unwrap();
+        b2bua
+            .handle_message(invite("unmatched", "3000", "yes"))
+            .await
+            .unwrap();
+        let channels = b2bua.channels().await;
+        let transcribing = |call_id: &str| {
+            channels
+                .iter()
+                .find(|c| c.call_id == call_id)
+                .unwrap()
+                .transcribing
+        };
+        assert!(transcribing("agreed"));
+        assert!(!transcribing("refused"));
+        assert!(!transcribing("unmatched"));
+
+        b2bua
+            .handle_message(Message::Response(
+                Response::new(StatusCode::BUSY_HERE).with_header("Call-ID", "agreed"),
+            ))
+            .await
+            .unwrap();
+        let cdr = rx.try_recv().unwrap();
+        assert!(cdr.decisions[0]
+            .detail
+            .starts_with("route support (support)"));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_post_dial_delay() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let pdd = PddTracker::default();
+        let b2bua = B2BUA::new().with_cdr_sink(tx).with_pdd_tracker(pdd.clone());
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                .with_user("+12125551234".to_string()),
+        )
+        .with_header("Call-ID", "pdd1")
+        .with_header("CSeq", "1 INVITE")
+        .with_header("From", "<sip:1001@example.com>;tag=a");
+        b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap();
+        let response = |status: u16| {
+            Message::Response(
+                Response::new(StatusCode(status))
+                    .with_header("Call-ID", "pdd1")
+                    .with_header("CSeq", "1 INVITE"),
+            )
+        };
+
+        // 100 Trying is not progress; the 183 stops the clock once
+        b2bua.handle_message(response(100)).await.unwrap();
+        assert_eq!(pdd.overall().samples, 0);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        b2bua.handle_message(response(183)).await.unwrap();
+        b2bua.handle_message(response(180)).await.unwrap();
+        b2bua.handle_message(response(486)).await.unwrap();
+
+        let trunks = pdd.trunks();
+        assert_eq!(trunks.len(), 1);
+        assert_eq!(trunks[0].trunk.as_deref(), Some("carrier.example.com"));
+        assert_eq!(trunks[0].samples, 1);
+        assert!(trunks[0].p95_ms >= 20);
+        let cdr = rx.try_recv().unwrap();
+        assert_eq!(cdr.post_dial_delay_ms, Some(trunks[0].p95_ms));
+    }
+
+    #[tokio::test]
+    async fn test_b2bua_media_quality() {
+        let quality = MediaQualityTracker::default();
+        let b2bua = B2BUA::new().with_media_quality(quality.clone());
+
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "carrier.example.com".to_string())
+                .with_user("+12125551234".to_string()),
+        )
+        .with_header("Call-ID", "mq1")
+        .with_header("CSeq", "1 INVITE")
+        .with_header("From", "<sip:1001@example.com>;tag=a");
+        b2bua
+            .handle_message(Message::Request(invite))
+            .await
+            .unwrap();
+        let bye = Request::new(
+            Method::Bye,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "mq1")
+        .with_header("CSeq", "2 BYE")
+        .with_header("From", "<sip:1001@example.com>;tag=a")
+        .with_header(
+            "P-RTP-Stat",
+            "PS=1000,OS=160000,PR=990,OR=158400,PL=10,JI=4",
+        );
+        b2bua.handle_message(Message::Request(bye)).await.unwrap();
+
+        let trunks = quality.trunks();
+        assert_eq!(trunks.len(), 1);
+        assert_eq!(trunks[0].trunk, "carrier.example.com");
+        assert_eq!(trunks[0].packets_lost, 10);
+        assert_eq!(trunks[0].loss_percent, 1.0);
+        assert_eq!(trunks[0].max_jitter_ms, 4);
+    }
 }
@@ -1,7 +1,19 @@
 //! Session management for B2BUA
 
-use crate::b2bua::CallLeg;
+use crate::b2bua::{
+    CallAttempt, CallDecision, CallLeg, CallTarget, DialogSequence, ForkBranch, LegSide,
+};
+use crate::cos::CallClass;
+use crate::media::webrtc::WebRtcRelay;
+use crate::no_answer::{NoAnswerAction, RingTimeout};
+use crate::routing::{FailoverPlan, RerouteRule};
+use crate::screening::{ScreeningMode, ScreeningOutcome};
+use crate::sip::{Dialog, Request, Response};
+use crate::transcription::{ForkPlan, StreamLeg};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 /// Unique session identifier
@@ -31,7 +43,8 @@ impl fmt::Display for SessionId {
 }
 
 /// Session state
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SessionState {
     /// Initial state
     Initial,
@@ -53,6 +66,57 @@ pub struct Session {
     state: SessionState,
     a_leg: Option<CallLeg>,
     b_leg: Option<CallLeg>,
+    admission_key: Option<String>,
+    route_trunk: Option<String>,
+    quota_tenant: Option<String>,
+    caller: Option<String>,
+    callee: Option<String>,
+    created_at: DateTime<Utc>,
+    answered_at: Option<DateTime<Utc>>,
+    call_class: Option<CallClass>,
+    authorized_by_pin: bool,
+    account_code: Option<String>,
+    dial_pin_user: Option<String>,
+    screening_mode: Option<ScreeningMode>,
+    screening_outcome: Option<ScreeningOutcome>,
+    voicemail_drop: bool,
+    dialed: Option<String>,
+    answered_elsewhere: bool,
+    ring_timeout: Option<RingTimeout>,
+    no_answer: Option<NoAnswerAction>,
+    correlation_id: Option<String>,
+    carrier_peer: Option<(String, String)>,
+    forks: Vec<ForkBranch>,
+    /// Where the call was sent, first the routed destination then each
+    /// forward
+    targets: Vec<CallTarget>,
+    /// What the matched route sends the call to
+    route_destination: Option<String>,
+    /// Destinations of the route still to try if the current one fails
+    failover: Option<FailoverPlan>,
+    /// Reroute rules of the route not yet used
+    reroutes: Vec<RerouteRule>,
+    /// Destinations the call was tried at
+    attempts: Vec<CallAttempt>,
+    invite: Option<Request>,
+    /// INVITE as the caller sent it, which relayed responses answer
+    caller_invite: Option<Request>,
+    /// Last response the caller was sent to its INVITE
+    caller_response: Option<Response>,
+    sequence: DialogSequence,
+    caller_dialog: Option<Dialog>,
+    callee_dialog: Option<Dialog>,
+    decisions: Vec<CallDecision>,
+    /// Trunk the INVITE was sent to and when
+    dialing: Option<(String, Instant)>,
+    post_dial_delay: Option<Duration>,
+    /// Audio codec each leg's SDP put first
+    caller_codec: Option<String>,
+    callee_codec: Option<String>,
+    /// How the call's audio is streamed for transcription
+    transcription: Option<ForkPlan>,
+    /// Relay between a browser caller's SRTP and the callee's plain RTP
+    webrtc_relay: Option<WebRtcRelay>,
 }
 
 impl Session {
@@ -63,6 +127,45 @@ impl Session {
             state: SessionState::Initial,
             a_leg: None,
             b_leg: None,
+            admission_key: None,
+            route_trunk: None,
+            quota_tenant: None,
+            caller: None,
+            callee: None,
+            created_at: Utc::now(),
+            answered_at: None,
+            call_class: None,
+            authorized_by_pin: false,
+            account_code: None,
+            dial_pin_user: None,
+            screening_mode: None,
+            screening_outcome: None,
+            voicemail_drop: false,
+            dialed: None,
+            answered_elsewhere: false,
+            ring_timeout: None,
+            no_answer: None,
+            correlation_id: None,
+            carrier_peer: None,
+            forks: Vec::new(),
+            targets: Vec::new(),
+            route_destination: None,
+            failover: None,
+            reroutes: Vec::new(),
+            attempts: Vec::new(),
+            invite: None,
+            caller_invite: None,
+            caller_response: None,
+            sequence: DialogSequence::default(),
+            caller_dialog: None,
+            callee_dialog: None,
+            decisions: Vec::new(),
+            dialing: None,
+            post_dial_delay: None,
+            caller_codec: None,
+            callee_codec: None,
+            transcription: None,
+            webrtc_relay: None,
         }
     }
 
@@ -97,4 +200,436 @@ impl Session {
     pub fn set_b_leg(&mut self, leg: CallLeg) {
         self.b_leg = Some(leg);
     }
+
+    /// Trunk this session holds an admission slot on
+    pub fn admission_key(&self) -> Option<&str> {
+        self.admission_key.as_deref()
+    }
+
+    pub fn set_admission_key(&mut self, key: String) {
+        self.admission_key = Some(key);
+    }
+
+    /// Trunk the matched route sends the call out on
+    pub fn route_trunk(&self) -> Option<&str> {
+        self.route_trunk.as_deref()
+    }
+
+    pub fn set_route_trunk(&mut self, trunk: String) {
+        self.route_trunk = Some(trunk);
+    }
+
+    /// The call left its trunk for a destination that is not one
+    pub fn clear_route_trunk(&mut self) {
+        self.route_trunk = None;
+    }
+
+    /// Tenant whose concurrent call quota this session counts against
+    pub fn quota_tenant(&self) -> Option<&str> {
+        self.quota_tenant.as_deref()
+    }
+
+    pub fn set_quota_tenant(&mut self, tenant: String) {
+        self.quota_tenant = Some(tenant);
+    }
+
+    pub fn caller(&self) -> Option<&str> {
+        self.caller.as_deref()
+    }
+
+    pub fn callee(&self) -> Option<&str> {
+        self.callee.as_deref()
+    }
+
+    /// Record the From and To parties of the initial INVITE
+    pub fn set_parties(&mut self, caller: Option<String>, callee: Option<String>) {
+        self.caller = caller;
+        self.callee = callee;
+    }
+
+    /// Class of the dialed number, when class of service is enforced
+    pub fn call_class(&self) -> Option<CallClass> {
+        self.call_class
+    }
+
+    pub fn authorized_by_pin(&self) -> bool {
+        self.authorized_by_pin
+    }
+
+    /// Record the class of service decision that admitted the call
+    pub fn set_call_class(&mut self, class: CallClass, authorized_by_pin: bool) {
+        self.call_class = Some(class);
+        self.authorized_by_pin = authorized_by_pin;
+    }
+
+    /// Account code the call is billed to
+    pub fn account_code(&self) -> Option<&str> {
+        self.account_code.as_deref()
+    }
+
+    pub fn set_account_code(&mut self, code: Option<String>) {
+        self.account_code = code;
+    }
+
+    /// User whose personal dialing PIN authorized the call
+    pub fn dial_pin_user(&self) -> Option<&str> {
+        self.dial_pin_user.as_deref()
+    }
+
+    pub fn set_dial_pin_user(&mut self, user: Option<String>) {
+        self.dial_pin_user = user;
+    }
+
+    /// How the callee screens this call, if at all
+    pub fn screening_mode(&self) -> Option<ScreeningMode> {
+        self.screening_mode
+    }
+
+    pub fn set_screening_mode(&mut self, mode: ScreeningMode) {
+        self.screening_mode = Some(mode);
+    }
+
+    pub fn screening_outcome(&self) -> Option<ScreeningOutcome> {
+        self.screening_outcome
+    }
+
+    pub fn set_screening_outcome(&mut self, outcome: ScreeningOutcome) {
+        self.screening_outcome = Some(outcome);
+    }
+
+    /// Screened and still waiting for the callee's key press
+    pub fn awaiting_screening(&self) -> bool {
+        self.screening_mode == Some(ScreeningMode::Screen) && self.screening_outcome.is_none()
+    }
+
+    /// Sent straight to voicemail without ringing the callee
+    pub fn voicemail_drop(&self) -> bool {
+        self.voicemail_drop
+    }
+
+    pub fn set_voicemail_drop(&mut self) {
+        self.voicemail_drop = true;
+    }
+
+    /// Number the caller dialed, before any rewriting
+    pub fn dialed(&self) -> Option<&str> {
+        self.dialed.as_deref()
+    }
+
+    pub fn set_dialed(&mut self, dialed: Option<String>) {
+        self.dialed = dialed;
+    }
+
+    /// Cancelled because another phone answered the call
+    pub fn answered_elsewhere(&self) -> bool {
+        self.answered_elsewhere
+    }
+
+    pub fn set_answered_elsewhere(&mut self) {
+        self.answered_elsewhere = true;
+    }
+
+    /// How long the callee rings before the no-answer action applies
+    pub fn ring_timeout(&self) -> Option<&RingTimeout> {
+        self.ring_timeout.as_ref()
+    }
+
+    pub fn set_ring_timeout(&mut self, timeout: RingTimeout) {
+        self.ring_timeout = Some(timeout);
+    }
+
+    /// Still unanswered at `now` with the ring time used up, and no
+    /// no-answer action applied yet
+    pub fn ring_expired(&self, now: DateTime<Utc>) -> bool {
+        self.answered_at.is_none()
+            && self.no_answer.is_none()
+            && self.ring_timeout.as_ref().is_some_and(|timeout| {
+                self.created_at + chrono::Duration::seconds(timeout.ring_seconds as i64) <= now
+            })
+    }
+
+    /// Action applied when the ring time ran out
+    pub fn no_answer(&self) -> Option<&NoAnswerAction> {
+        self.no_answer.as_ref()
+    }
+
+    pub fn set_no_answer(&mut self, action: NoAnswerAction) {
+        self.no_answer = Some(action);
+    }
+
+    /// Teams correlation ID, for matching Graph call records
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
+    }
+
+    pub fn set_correlation_id(&mut self, correlation_id: Option<String>) {
+        self.correlation_id = correlation_id;
+    }
+
+    /// Wholesale peer the call came from
+    pub fn carrier_peer(&self) -> Option<&str> {
+        self.carrier_peer.as_ref().map(|(peer, _)| peer.as_str())
+    }
+
+    /// Number the peer dialed, without its tech-prefix
+    pub fn carrier_number(&self) -> Option<&str> {
+        self.carrier_peer
+            .as_ref()
+            .map(|(_, number)| number.as_str())
+    }
+
+    pub fn set_carrier_peer(&mut self, peer: String, number: String) {
+        self.carrier_peer = Some((peer, number));
+    }
+
+    /// Registered contacts the call was forked to
+    pub fn forks(&self) -> &[ForkBranch] {
+        &self.forks
+    }
+
+    pub fn forks_mut(&mut self) -> &mut [ForkBranch] {
+        &mut self.forks
+    }
+
+    /// Add branches, keeping earlier ones so their late responses are
+    /// still recognised
+    ///
+    /// The branches ring the latest target.
+    pub fn add_forks(&mut self, forks: Vec<ForkBranch>) {
+        let target = self.targets.len().saturating_sub(1);
+        self.forks.extend(forks.into_iter().map(|mut fork| {
+            fork.target = target;
+            fork
+        }));
+    }
+
+    /// Targets the call was sent to, oldest first
+    pub fn targets(&self) -> &[CallTarget] {
+        &self.targets
+    }
+
+    /// Send the call to a new target, replacing the current one
+    pub fn add_target(&mut self, target: CallTarget) {
+        self.targets.push(target);
+    }
+
+    pub fn route_destination(&self) -> Option<&str> {
+        self.route_destination.as_deref()
+    }
+
+    pub fn set_route_destination(&mut self, destination: String) {
+        self.route_destination = Some(destination);
+    }
+
+    pub fn failover(&self) -> Option<&FailoverPlan> {
+        self.failover.as_ref()
+    }
+
+    pub fn failover_mut(&mut self) -> Option<&mut FailoverPlan> {
+        self.failover.as_mut()
+    }
+
+    pub fn set_failover(&mut self, plan: FailoverPlan) {
+        self.failover = Some(plan);
+    }
+
+    /// Still unanswered at `now` with the current destination's timeout
+    /// run out
+    pub fn hop_expired(&self, now: DateTime<Utc>) -> bool {
+        self.answered_at.is_none() && self.failover.as_ref().is_some_and(|plan| plan.expired(now))
+    }
+
+    pub fn set_reroutes(&mut self, rules: Vec<RerouteRule>) {
+        self.reroutes = rules;
+    }
+
+    /// Whether an unused reroute rule covers a failure with `status`
+    pub fn can_reroute(&self, status: u16) -> bool {
+        self.reroutes.iter().any(|rule| rule.covers(status))
+    }
+
+    /// Use up the first reroute rule covering a failure with `status`
+    pub fn take_reroute(&mut self, status: u16) -> Option<RerouteRule> {
+        let index = self.reroutes.iter().position(|rule| rule.covers(status))?;
+        Some(self.reroutes.remove(index))
+    }
+
+    pub fn attempts(&self) -> &[CallAttempt] {
+        &self.attempts
+    }
+
+    pub fn start_attempt(&mut self, attempt: CallAttempt) {
+        self.attempts.push(attempt);
+    }
+
+    /// Record how the current attempt failed: with a final `status`, or
+    /// timed out when `None`
+    pub fn fail_attempt(&mut self, status: Option<u16>) {
+        if let Some(attempt) = self.attempts.last_mut() {
+            attempt.status = status;
+            attempt.timed_out = status.is_none();
+        }
+    }
+
+    /// INVITE as routed, before it was shaped for a trunk
+    pub fn invite(&self) -> Option<&Request> {
+        self.invite.as_ref()
+    }
+
+    pub fn set_invite(&mut self, invite: Request) {
+        self.invite = Some(invite);
+    }
+
+    /// INVITE as the caller sent it, before routing rewrote it
+    pub fn caller_invite(&self) -> Option<&Request> {
+        self.caller_invite.as_ref()
+    }
+
+    pub fn set_caller_invite(&mut self, invite: Request) {
+        self.caller_invite = Some(invite);
+    }
+
+    /// Last response the caller was sent to its INVITE, sent again when the
+    /// INVITE is retransmitted
+    pub fn caller_response(&self) -> Option<&Response> {
+        self.caller_response.as_ref()
+    }
+
+    pub fn set_caller_response(&mut self, response: Response) {
+        self.caller_response = Some(response);
+    }
+
+    pub fn webrtc_relay(&self) -> Option<&WebRtcRelay> {
+        self.webrtc_relay.as_ref()
+    }
+
+    pub fn set_webrtc_relay(&mut self, relay: WebRtcRelay) {
+        self.webrtc_relay = Some(relay);
+    }
+
+    /// CSeq bookkeeping for both sides of the call
+    pub fn sequence(&self) -> &DialogSequence {
+        &self.sequence
+    }
+
+    pub fn sequence_mut(&mut self) -> &mut DialogSequence {
+        &mut self.sequence
+    }
+
+    /// Dialog with the caller (`A`) or the callee (`B`), once set up
+    pub fn dialog(&self, side: LegSide) -> Option<&Dialog> {
+        match side {
+            LegSide::A => self.caller_dialog.as_ref(),
+            LegSide::B => self.callee_dialog.as_ref(),
+        }
+    }
+
+    pub fn dialog_mut(&mut self, side: LegSide) -> Option<&mut Dialog> {
+        match side {
+            LegSide::A => self.caller_dialog.as_mut(),
+            LegSide::B => self.callee_dialog.as_mut(),
+        }
+    }
+
+    pub fn set_dialog(&mut self, side: LegSide, dialog: Dialog) {
+        match side {
+            LegSide::A => self.caller_dialog = Some(dialog),
+            LegSide::B => self.callee_dialog = Some(dialog),
+        }
+    }
+
+    /// Side whose dialog `request` was sent in, matched by its tags
+    pub fn dialog_side(&self, request: &Request) -> Option<LegSide> {
+        [LegSide::A, LegSide::B]
+            .into_iter()
+            .find(|side| self.dialog(*side).is_some_and(|d| d.matches(request)))
+    }
+
+    /// Decision trail, oldest step first
+    pub fn decisions(&self) -> &[CallDecision] {
+        &self.decisions
+    }
+
+    pub fn record_decision(&mut self, decision: CallDecision) {
+        self.decisions.push(decision);
+    }
+
+    /// Start the post-dial delay clock for the INVITE sent to `trunk`
+    pub fn start_dialing(&mut self, trunk: String) {
+        self.dialing = Some((trunk, Instant::now()));
+    }
+
+    /// Trunk the call was sent out on
+    pub fn trunk(&self) -> Option<&str> {
+        self.dialing.as_ref().map(|(trunk, _)| trunk.as_str())
+    }
+
+    /// Stop the post-dial delay clock on the first ringing, progress or
+    /// answer, returning the trunk and delay the first time only
+    pub fn record_progress(&mut self) -> Option<(&str, Duration)> {
+        if self.post_dial_delay.is_some() {
+            return None;
+        }
+        let (trunk, started) = self.dialing.as_ref()?;
+        let delay = started.elapsed();
+        self.post_dial_delay = Some(delay);
+        Some((trunk.as_str(), delay))
+    }
+
+    /// Time from sending the INVITE to the first ringing, progress or
+    /// answer
+    pub fn post_dial_delay(&self) -> Option<Duration> {
+        self.post_dial_delay
+    }
+
+    /// Record the audio codec `side` offered or answered with
+    pub fn set_codec(&mut self, side: LegSide, codec: String) {
+        match side {
+            LegSide::A => self.caller_codec = Some(codec),
+            LegSide::B => self.callee_codec = Some(codec),
+        }
+    }
+
+    pub fn codec(&self, side: LegSide) -> Option<&str> {
+        match side {
+            LegSide::A => self.caller_codec.as_deref(),
+            LegSide::B => self.callee_codec.as_deref(),
+        }
+    }
+
+    /// How the call's audio is streamed for transcription, if it is
+    pub fn transcription(&self) -> Option<&ForkPlan> {
+        self.transcription.as_ref()
+    }
+
+    pub fn set_transcription(&mut self, plan: ForkPlan) {
+        self.transcription = Some(plan);
+    }
+
+    /// Whether `side`'s audio is streamed for transcription
+    pub fn is_transcribed(&self, side: LegSide) -> bool {
+        let leg = match side {
+            LegSide::A => StreamLeg::Caller,
+            LegSide::B => StreamLeg::Callee,
+        };
+        self.transcription
+            .as_ref()
+            .is_some_and(|plan| plan.legs.legs().contains(&leg))
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    pub fn answered_at(&self) -> Option<DateTime<Utc>> {
+        self.answered_at
+    }
+
+    /// Mark the session as answered
+    pub fn mark_answered(&mut self) {
+        if self.answered_at.is_none() {
+            self.answered_at = Some(Utc::now());
+        }
+        self.state = SessionState::Established;
+    }
 }
@@ -0,0 +1,353 @@
+//! Leg topology of a call
+//!
+//! One call may ring several contacts at once, be forwarded when nobody
+//! answers and be answered on one branch. The topology lists every leg the
+//! call has had and how they relate, so a picture of it can be drawn: the
+//! caller's leg is the root and each target the call was sent to is its
+//! child. A target's children are the branches forked to its registered
+//! contacts, or the outbound leg when it was dialed through a trunk. A
+//! target the call was forwarded away from is replaced by the next one.
+
+use crate::b2bua::{ForkState, Session, SessionState};
+use crate::diversion::DiversionReason;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::SocketAddr;
+
+/// A destination the call was sent to
+#[derive(Debug, Clone)]
+pub struct CallTarget {
+    /// Request-URI the call was sent to
+    pub uri: String,
+    /// What the matched route sends the call to, such as a ring group
+    pub destination: Option<String>,
+    /// Why the call was forwarded here, for targets after the first
+    pub diverted: Option<DiversionReason>,
+    pub since: DateTime<Utc>,
+}
+
+impl CallTarget {
+    pub fn new(uri: impl Into<String>) -> Self {
+        Self {
+            uri: uri.into(),
+            destination: None,
+            diverted: None,
+            since: Utc::now(),
+        }
+    }
+
+    pub fn with_destination(mut self, destination: Option<&str>) -> Self {
+        self.destination = destination.map(str::to_string);
+        self
+    }
+
+    pub fn with_diversion(mut self, reason: DiversionReason) -> Self {
+        self.diverted = Some(reason);
+        self
+    }
+}
+
+/// What a leg of the call is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LegKind {
+    /// The inbound leg from the caller
+    Caller,
+    /// A destination the call was sent to
+    Target,
+    /// The call forked to one registered contact of a target
+    Branch,
+    /// The call dialed out through a trunk
+    Outbound,
+}
+
+/// Whether the call is still with a target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetState {
+    Active,
+    /// The call was forwarded to another target
+    Replaced,
+}
+
+/// State of a leg, in the terms of its kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum LegState {
+    Session(SessionState),
+    Target(TargetState),
+    Branch(ForkState),
+}
+
+/// One leg of the call
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyLeg {
+    /// Identifies the leg within the topology
+    pub id: String,
+    pub kind: LegKind,
+    pub state: LegState,
+    /// Party, target or contact URI
+    pub uri: Option<String>,
+    /// Route destination of a target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    /// Why the call was forwarded to a target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diverted: Option<DiversionReason>,
+    pub remote_addr: Option<SocketAddr>,
+    /// Final status of a branch that refused the call
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// How one leg relates to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegRelation {
+    /// `to` was started by `from`
+    Child,
+    /// The call moved from `from` to `to`
+    ReplacedBy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LegLink {
+    pub from: String,
+    pub to: String,
+    pub relation: LegRelation,
+}
+
+/// Every leg of a call and how they relate
+#[derive(Debug, Clone, Serialize)]
+pub struct CallTopology {
+    pub session_id: String,
+    pub call_id: String,
+    pub state: SessionState,
+    pub created_at: DateTime<Utc>,
+    pub legs: Vec<TopologyLeg>,
+    pub links: Vec<LegLink>,
+}
+
+impl CallTopology {
+    pub fn from_session(session: &Session) -> Self {
+        let a_leg = session.a_leg();
+        let mut legs = vec![TopologyLeg {
+            id: "caller".to_string(),
+            kind: LegKind::Caller,
+            state: LegState::Session(session.state()),
+            uri: a_leg
+                .map(|leg| leg.from_uri.clone())
+                .or_else(|| session.caller().map(str::to_string)),
+            destination: None,
+            diverted: None,
+            remote_addr: a_leg.map(|leg| leg.remote_addr),
+            status: None,
+            reason: None,
+        }];
+        let mut links = Vec::new();
+
+        let targets = session.targets();
+        for (index, target) in targets.iter().enumerate() {
+            let id = format!("target-{}", index);
+            let current = index + 1 == targets.len();
+            legs.push(TopologyLeg {
+                id: id.clone(),
+                kind: LegKind::Target,
+                state: LegState::Target(if current {
+                    TargetState::Active
+                } else {
+                    TargetState::Replaced
+                }),
+                uri: Some(target.uri.clone()),
+                destination: target.destination.clone(),
+                diverted: target.diverted,
+                remote_addr: None,
+                status: None,
+                reason: None,
+            });
+            links.push(link("caller", &id, LegRelation::Child));
+            if index > 0 {
+                links.push(link(
+                    &format!("target-{}", index - 1),
+                    &id,
+                    LegRelation::ReplacedBy,
+                ));
+            }
+
+            let branches: Vec<_> = session
+                .forks()
+                .iter()
+                .filter(|fork| fork.target == index)
+                .collect();
+            for fork in &branches {
+                let branch = format!("branch-{}", fork.branch);
+                legs.push(TopologyLeg {
+                    id: branch.clone(),
+                    kind: LegKind::Branch,
+                    state: LegState::Branch(fork.state),
+                    uri: Some(fork.contact.clone()),
+                    destination: None,
+                    diverted: None,
+                    remote_addr: Some(fork.destination),
+                    status: fork.status.map(|status| status.0),
+                    reason: fork.reason.clone(),
+                });
+                links.push(link(&id, &branch, LegRelation::Child));
+            }
+            // Targets no contact is registered for are dialed out
+            if branches.is_empty() {
+                let outbound = format!("outbound-{}", index);
+                legs.push(TopologyLeg {
+                    id: outbound.clone(),
+                    kind: LegKind::Outbound,
+                    state: if current {
+                        LegState::Session(session.state())
+                    } else {
+                        LegState::Branch(ForkState::Cancelled)
+                    },
+                    uri: Some(target.uri.clone()),
+                    destination: None,
+                    diverted: None,
+                    remote_addr: None,
+                    status: None,
+                    reason: None,
+                });
+                links.push(link(&id, &outbound, LegRelation::Child));
+            }
+        }
+
+        Self {
+            session_id: session.id().to_string(),
+            call_id: session.call_id().to_string(),
+            state: session.state(),
+            created_at: session.created_at(),
+            legs,
+            links,
+        }
+    }
+}
+
+fn link(from: &str, to: &str, relation: LegRelation) -> LegLink {
+    LegLink {
+        from: from.to_string(),
+        to: to.to_string(),
+        relation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::b2bua::{CSeq, CallLeg, ForkBranch};
+    use crate::registrar::Registration;
+    use crate::sip::{Method, Request, StatusCode, Uri};
+
+    fn fork(invite: &Request, contact: &str) -> ForkBranch {
+        let binding = Registration {
+            aor: "sip:1001@example.com".to_string(),
+            contact: contact.to_string(),
+            user_agent: None,
+            received: None,
+            behind_nat: false,
+            registered_at: Utc::now(),
+            expires_at: Utc::now(),
+            device_id: None,
+            reachable: None,
+            checked_at: None,
+            instance_id: None,
+            reg_id: None,
+            pub_gruu: None,
+            temp_gruu: None,
+            send_to_contact: false,
+        };
+        let cseq = CSeq {
+            sequence: 1,
+            method: Method::Invite,
+        };
+        ForkBranch::new(invite, &binding, "192.0.2.1:5060".parse().unwrap(), cseq).unwrap()
+    }
+
+    #[test]
+    fn test_forwarded_call_topology() {
+        let invite = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        );
+        let mut session = Session::new("call1".to_string());
+        session.set_a_leg(CallLeg::new(
+            "198.51.100.7:5060".parse().unwrap(),
+            "<sip:2000@example.com>".to_string(),
+            "<sip:1001@example.com>".to_string(),
+        ));
+
+        // Rings two phones, neither answers, so goes on to a mobile
+        session.add_target(
+            CallTarget::new("sip:1001@example.com").with_destination(Some("Ring group support")),
+        );
+        session.add_forks(vec![
+            fork(&invite, "sip:1001@192.0.2.20:5060"),
+            fork(&invite, "sip:1001@192.0.2.21:5060"),
+        ]);
+        for branch in session.forks_mut() {
+            branch.state = ForkState::Cancelled;
+        }
+        session.forks_mut()[1].status = Some(StatusCode::BUSY_HERE);
+        session.add_target(
+            CallTarget::new("sip:+15550100@carrier.example")
+                .with_diversion(DiversionReason::NoAnswer),
+        );
+
+        let topology = CallTopology::from_session(&session);
+        let ids: Vec<&str> = topology.legs.iter().map(|l| l.id.as_str()).collect();
+        let first = format!("branch-{}", session.forks()[0].branch);
+        let second = format!("branch-{}", session.forks()[1].branch);
+        assert_eq!(
+            ids,
+            vec![
+                "caller",
+                "target-0",
+                &first,
+                &second,
+                "target-1",
+                "outbound-1"
+            ]
+        );
+        assert_eq!(
+            topology.legs[0].remote_addr,
+            Some("198.51.100.7:5060".parse().unwrap())
+        );
+        assert_eq!(
+            topology.legs[1].state,
+            LegState::Target(TargetState::Replaced)
+        );
+        assert_eq!(
+            topology.legs[1].destination.as_deref(),
+            Some("Ring group support")
+        );
+        assert_eq!(topology.legs[3].status, Some(486));
+        assert_eq!(topology.legs[4].diverted, Some(DiversionReason::NoAnswer));
+        assert_eq!(
+            topology.legs[5].state,
+            LegState::Session(SessionState::Initial)
+        );
+        assert_eq!(
+            topology.links,
+            vec![
+                link("caller", "target-0", LegRelation::Child),
+                link("target-0", &first, LegRelation::Child),
+                link("target-0", &second, LegRelation::Child),
+                link("caller", "target-1", LegRelation::Child),
+                link("target-0", "target-1", LegRelation::ReplacedBy),
+                link("target-1", "outbound-1", LegRelation::Child),
+            ]
+        );
+
+        let json = serde_json::to_value(&topology).unwrap();
+        assert_eq!(json["legs"][1]["state"], "replaced");
+        assert_eq!(json["legs"][2]["state"], "cancelled");
+        assert_eq!(json["legs"][4]["diverted"], "no-answer");
+        assert_eq!(json["links"][4]["relation"], "replaced_by");
+    }
+}
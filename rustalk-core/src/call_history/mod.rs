@@ -0,0 +1,393 @@
+//! Per-extension call history
+//!
+//! Completed calls are kept in memory, up to `max_records`, so each
+//! extension's recent calls can be listed: the direction, the other party,
+//! the duration and how the call ended. Roles with a privacy rule see the
+//! other party's number masked down to its last few digits; a requester's
+//! role comes from who they authenticated as, and numbers stay masked when
+//! no rule can be found for it.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::b2bua::{CallDetailRecord, CallDisposition, HangupCause};
+use crate::call_trace::uri_user;
+
+/// Call history configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallHistoryConfig {
+    /// Calls kept across all extensions; the oldest are dropped first
+    #[serde(default = "default_max_records")]
+    pub max_records: usize,
+    /// Number masking for each privacy-restricted role
+    #[serde(default)]
+    pub privacy: HashMap<String, PrivacyRule>,
+    /// Roles that see numbers in full
+    #[serde(default)]
+    pub unmasked_roles: Vec<String>,
+    /// Role of each API principal, by the name it authenticated as
+    #[serde(default)]
+    pub roles: HashMap<String, String>,
+    /// Role of principals without one, and of roles with no rule
+    #[serde(default)]
+    pub default_role: Option<String>,
+}
+
+impl Default for CallHistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_records: default_max_records(),
+            privacy: HashMap::new(),
+            unmasked_roles: Vec::new(),
+            roles: HashMap::new(),
+            default_role: None,
+        }
+    }
+}
+
+fn default_max_records() -> usize {
+    10_000
+}
+
+fn default_visible_digits() -> usize {
+    4
+}
+
+/// Masking applied when privacy rules are configured but none is found for
+/// a requester
+const FAIL_CLOSED: PrivacyRule = PrivacyRule {
+    visible_digits: 4,
+    mask_internal: false,
+};
+
+impl CallHistoryConfig {
+    /// Role of the API principal named `principal`
+    pub fn role_of(&self, principal: &str) -> Option<&str> {
+        self.roles.get(principal).map(String::as_str)
+    }
+
+    /// Masking for `role`, or `None` to show numbers in full
+    ///
+    /// A role that is neither unmasked nor has a rule of its own is treated
+    /// as the default role. When privacy rules are configured and that
+    /// leaves no role either, numbers are masked rather than shown.
+    pub fn rule_for(&self, role: Option<&str>) -> Option<&PrivacyRule> {
+        let unmasked = |role: &str| self.unmasked_roles.iter().any(|r| r == role);
+        let role = role
+            .filter(|role| unmasked(role) || self.privacy.contains_key(*role))
+            .or(self.default_role.as_deref());
+        match role {
+            Some(role) if unmasked(role) => None,
+            Some(role) if self.privacy.contains_key(role) => self.privacy.get(role),
+            _ if self.privacy.is_empty() => None,
+            _ => Some(&FAIL_CLOSED),
+        }
+    }
+}
+
+/// How numbers are masked for a role
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PrivacyRule {
+    /// Digits left visible at the end of a masked number
+    #[serde(default = "default_visible_digits")]
+    pub visible_digits: usize,
+    /// Mask internal extensions too, not only outside numbers
+    #[serde(default)]
+    pub mask_internal: bool,
+}
+
+impl Default for PrivacyRule {
+    fn default() -> Self {
+        Self {
+            visible_digits: default_visible_digits(),
+            mask_internal: false,
+        }
+    }
+}
+
+impl PrivacyRule {
+    /// `number` with all but its last `visible_digits` digits replaced
+    pub fn mask(&self, number: &str) -> String {
+        let digits = number.chars().filter(char::is_ascii_digit).count();
+        let mut hidden = digits.saturating_sub(self.visible_digits);
+        number
+            .chars()
+            .map(|c| {
+                if c.is_ascii_digit() && hidden > 0 {
+                    hidden -= 1;
+                    '*'
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallDirection {
+    Inbound,
+    Outbound,
+}
+
+/// One call in an extension's history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallHistoryEntry {
+    pub call_id: String,
+    pub direction: CallDirection,
+    /// The other party's number
+    pub peer: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub duration_seconds: i64,
+    pub disposition: CallDisposition,
+    #[serde(default)]
+    pub hangup_cause: Option<HangupCause>,
+}
+
+/// How many calls ended with a cause
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HangupCauseCount {
+    pub cause: HangupCause,
+    pub q850: u16,
+    pub count: usize,
+}
+
+impl CallHistoryEntry {
+    /// Mask the other party under `rule`; `is_internal` tells extensions
+    /// apart from outside numbers
+    pub fn masked(mut self, rule: &PrivacyRule, is_internal: impl Fn(&str) -> bool) -> Self {
+        if let Some(peer) = &self.peer {
+            if rule.mask_internal || !is_internal(peer) {
+                self.peer = Some(rule.mask(peer));
+            }
+        }
+        self
+    }
+}
+
+/// What is kept of each call
+#[derive(Debug, Clone)]
+struct HistoryRecord {
+    call_id: String,
+    caller: Option<String>,
+    callee: Option<String>,
+    start_time: DateTime<Utc>,
+    duration_seconds: i64,
+    disposition: CallDisposition,
+    hangup_cause: Option<HangupCause>,
+}
+
+/// Recent calls, shared between the CDR receiver and the API
+#[derive(Debug, Clone)]
+pub struct CallHistory {
+    config: Arc<CallHistoryConfig>,
+    records: Arc<RwLock<VecDeque<HistoryRecord>>>,
+}
+
+impl CallHistory {
+    pub fn new(config: CallHistoryConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            records: Arc::new(RwLock::new(VecDeque::new())),
+        }
+    }
+
+    pub fn config(&self) -> &CallHistoryConfig {
+        &self.config
+    }
+
+    /// Keep a completed call
+    pub async fn record(&self, cdr: &CallDetailRecord) {
+        let record = HistoryRecord {
+            call_id: cdr.call_id.clone(),
+            caller: cdr.caller.as_deref().and_then(uri_user).map(str::to_string),
+            callee: cdr.callee.as_deref().and_then(uri_user).map(str::to_string),
+            start_time: cdr.start_time,
+            duration_seconds: cdr.duration_seconds,
+            disposition: cdr.disposition,
+            hangup_cause: cdr.hangup_cause,
+        };
+        let mut records = self.records.write().await;
+        records.push_back(record);
+        while records.len() > self.config.max_records {
+            records.pop_front();
+        }
+    }
+
+    /// Up to `limit` of the calls `extension` made or received, newest
+    /// first
+    pub async fn for_extension(&self, extension: &str, limit: usize) -> Vec<CallHistoryEntry> {
+        self.records
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter_map(|record| {
+                let (direction, peer) = if record.caller.as_deref() == Some(extension) {
+                    (CallDirection::Outbound, &record.callee)
+                } else if record.callee.as_deref() == Some(extension) {
+                    (CallDirection::Inbound, &record.caller)
+                } else {
+                    return None;
+                };
+                Some(CallHistoryEntry {
+                    call_id: record.call_id.clone(),
+                    direction,
+                    peer: peer.clone(),
+                    start_time: record.start_time,
+                    duration_seconds: record.duration_seconds,
+                    disposition: record.disposition,
+                    hangup_cause: record.hangup_cause,
+                })
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// Calls started between `start` and `end` counted by hangup cause,
+    /// most common first
+    pub async fn hangup_causes(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Vec<HangupCauseCount> {
+        let mut counts: HashMap<HangupCause, usize> = HashMap::new();
+        for record in self.records.read().await.iter() {
+            if start.is_some_and(|start| record.start_time < start)
+                || end.is_some_and(|end| record.start_time >= end)
+            {
+                continue;
+            }
+            if let Some(cause) = record.hangup_cause {
+                *counts.entry(cause).or_default() += 1;
+            }
+        }
+        let mut counts: Vec<_> = counts
+            .into_iter()
+            .map(|(cause, count)| HangupCauseCount {
+                cause,
+                q850: cause.q850(),
+                count,
+            })
+            .collect();
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then(a.q850.cmp(&b.q850)));
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cos::CallClass;
+
+    fn cdr(call_id: &str, caller: &str, callee: &str) -> CallDetailRecord {
+        CallDetailRecord::denied(
+            call_id,
+            Some(format!("<sip:{}@example.com>;tag=a", caller)),
+            Some(format!("<sip:{}@example.com>", callee)),
+            CallClass::National,
+            "test".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_history_for_extension() {
+        let history = CallHistory::new(CallHistoryConfig {
+            max_records: 3,
+            ..Default::default()
+        });
+        history.record(&cdr("1", "1001", "+12125551234")).await;
+        history.record(&cdr("2", "+12125559876", "1001")).await;
+        history.record(&cdr("3", "1002", "1003")).await;
+        history.record(&cdr("4", "1002", "1001")).await;
+
+        // The oldest call was dropped
+        let calls = history.for_extension("1001", 10).await;
+        let summary: Vec<_> = calls
+            .iter()
+            .map(|c| (c.call_id.as_str(), c.direction, c.peer.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("4", CallDirection::Inbound, Some("1002")),
+                ("2", CallDirection::Inbound, Some("+12125559876")),
+            ]
+        );
+        assert_eq!(history.for_extension("1001", 1).await.len(), 1);
+
+        let causes = history.hangup_causes(None, None).await;
+        assert_eq!(
+            causes,
+            vec![HangupCauseCount {
+                cause: HangupCause::OutgoingCallBarred,
+                q850: 52,
+                count: 3,
+            }]
+        );
+        assert!(history
+            .hangup_causes(Some(Utc::now() + chrono::Duration::hours(1)), None)
+            .await
+            .is_empty());
+    }
+
+    #[test]
+    fn test_privacy_masking() {
+        let config: CallHistoryConfig = serde_json::from_value(serde_json::json!({
+            "privacy": { "agent": {}, "auditor": { "visible_digits": 0, "mask_internal": true } },
+            "default_role": "agent"
+        }))
+        .unwrap();
+        let agent = config.rule_for(None).unwrap();
+        assert_eq!(agent.mask("+1 212 555 1234"), "+* *** *** 1234");
+        assert_eq!(agent.mask("123"), "123");
+        // Roles without a rule are the default role
+        assert!(config.rule_for(Some("admin")).is_some());
+
+        // Only unmasked roles see numbers in full, and with no role to fall
+        // back on numbers stay masked
+        let principals: CallHistoryConfig = serde_json::from_value(serde_json::json!({
+            "privacy": { "agent": { "visible_digits": 2 } },
+            "unmasked_roles": ["admin"],
+            "roles": { "alice": "admin", "bob": "agent" }
+        }))
+        .unwrap();
+        assert!(principals.rule_for(principals.role_of("alice")).is_none());
+        assert_eq!(
+            principals
+                .rule_for(principals.role_of("bob"))
+                .unwrap()
+                .visible_digits,
+            2
+        );
+        assert_eq!(principals.role_of("mallory"), None);
+        assert_eq!(principals.rule_for(Some("root")).unwrap().visible_digits, 4);
+        assert!(principals.rule_for(None).is_some());
+        assert!(CallHistoryConfig::default().rule_for(None).is_none());
+
+        let entry = |peer: &str| CallHistoryEntry {
+            call_id: "1".to_string(),
+            direction: CallDirection::Inbound,
+            peer: Some(peer.to_string()),
+            start_time: Utc::now(),
+            duration_seconds: 0,
+            disposition: CallDisposition::Answered,
+            hangup_cause: Some(HangupCause::NormalClearing),
+        };
+        let internal = |peer: &str| peer == "1002";
+        assert_eq!(
+            entry("1002").masked(agent, internal).peer.as_deref(),
+            Some("1002")
+        );
+        let auditor = config.rule_for(Some("auditor")).unwrap();
+        assert_eq!(
+            entry("1002").masked(auditor, internal).peer.as_deref(),
+            Some("****")
+        );
+    }
+}
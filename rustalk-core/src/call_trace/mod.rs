@@ -0,0 +1,418 @@
+//! Per-call debug tracing
+//!
+//! Elevates logging for a single Call-ID, extension or source IP at runtime
+//! without touching the global log level. Every SIP message and B2BUA
+//! decision for a matching call is appended to a dedicated trace file.
+//!
+//! Once a message matches a target, its Call-ID is remembered so the rest of
+//! the call (responses, in-dialog requests) is captured even when later
+//! messages would not match on their own.
+
+use crate::sip::Message;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use tracing::warn;
+
+/// What to trace
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum TraceTarget {
+    /// A single call by Call-ID
+    CallId(String),
+    /// Calls to or from an extension (user part of From/To/Request-URI)
+    Extension(String),
+    /// Calls whose messages arrive from an IP address
+    SourceIp(IpAddr),
+}
+
+impl TraceTarget {
+    /// Parse a target from a kind (`call`, `extension`, `ip`) and value
+    pub fn parse(kind: &str, value: &str) -> Result<Self> {
+        match kind.to_lowercase().as_str() {
+            "call" | "call-id" | "call_id" => Ok(TraceTarget::CallId(value.to_string())),
+            "extension" | "ext" => Ok(TraceTarget::Extension(value.to_string())),
+            "ip" | "source" | "source_ip" => {
+                Ok(TraceTarget::SourceIp(value.parse().with_context(|| {
+                    format!("Invalid IP address: {}", value)
+                })?))
+            }
+            other => anyhow::bail!("Unknown trace target type: {}", other),
+        }
+    }
+
+    /// File name used for this target's trace
+    fn file_name(&self) -> String {
+        let raw = match self {
+            TraceTarget::CallId(id) => format!("call-{}", id),
+            TraceTarget::Extension(ext) => format!("ext-{}", ext),
+            TraceTarget::SourceIp(ip) => format!("ip-{}", ip),
+        };
+        let safe: String = raw
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{}.log", safe)
+    }
+}
+
+impl fmt::Display for TraceTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceTarget::CallId(id) => write!(f, "call {}", id),
+            TraceTarget::Extension(ext) => write!(f, "extension {}", ext),
+            TraceTarget::SourceIp(ip) => write!(f, "ip {}", ip),
+        }
+    }
+}
+
+/// Direction of a traced message relative to RusTalk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDirection {
+    Inbound,
+    Outbound,
+}
+
+/// An active trace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceInfo {
+    pub target: TraceTarget,
+    pub file: PathBuf,
+    pub started_at: DateTime<Utc>,
+    pub entries: u64,
+}
+
+/// Runtime per-call trace manager
+pub struct CallTracer {
+    output_dir: PathBuf,
+    targets: RwLock<HashMap<TraceTarget, TraceInfo>>,
+    /// Call-ID -> target it was first matched by
+    calls: RwLock<HashMap<String, TraceTarget>>,
+    write_lock: Mutex<()>,
+}
+
+impl CallTracer {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            output_dir: output_dir.into(),
+            targets: RwLock::new(HashMap::new()),
+            calls: RwLock::new(HashMap::new()),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Directory trace files are written to
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// Start tracing a target
+    pub fn enable(&self, target: TraceTarget) -> Result<TraceInfo> {
+        std::fs::create_dir_all(&self.output_dir).with_context(|| {
+            format!(
+                "Failed to create trace directory {}",
+                self.output_dir.display()
+            )
+        })?;
+
+        let mut targets = self.targets.write().unwrap();
+        let info = targets
+            .entry(target.clone())
+            .or_insert_with(|| TraceInfo {
+                file: self.output_dir.join(target.file_name()),
+                target,
+                started_at: Utc::now(),
+                entries: 0,
+            })
+            .clone();
+
+        Ok(info)
+    }
+
+    /// Stop tracing a target. Returns false if it was not active.
+    pub fn disable(&self, target: &TraceTarget) -> bool {
+        let removed = self.targets.write().unwrap().remove(target).is_some();
+        if removed {
+            self.calls.write().unwrap().retain(|_, t| t != target);
+        }
+        removed
+    }
+
+    /// List active traces
+    pub fn list(&self) -> Vec<TraceInfo> {
+        let mut traces: Vec<TraceInfo> = self.targets.read().unwrap().values().cloned().collect();
+        traces.sort_by_key(|t| t.started_at);
+        traces
+    }
+
+    /// Whether any trace is active
+    pub fn is_active(&self) -> bool {
+        !self.targets.read().unwrap().is_empty()
+    }
+
+    /// Record a SIP message if it belongs to a traced call
+    pub fn trace_message(
+        &self,
+        direction: TraceDirection,
+        message: &Message,
+        peer: Option<SocketAddr>,
+    ) {
+        if !self.is_active() {
+            return;
+        }
+
+        let Some(target) = self.match_message(message, peer) else {
+            return;
+        };
+
+        let arrow = match direction {
+            TraceDirection::Inbound => "<<<",
+            TraceDirection::Outbound => ">>>",
+        };
+        let peer = peer
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let bytes = match message {
+            Message::Request(req) => req.to_bytes(),
+            Message::Response(res) => res.to_bytes(),
+        };
+        let entry = format!(
+            "{} {} {}\n{}\n",
+            arrow,
+            peer,
+            match direction {
+                TraceDirection::Inbound => "received",
+                TraceDirection::Outbound => "sent",
+            },
+            String::from_utf8_lossy(&bytes).trim_end()
+        );
+
+        self.write(&target, &entry);
+    }
+
+    /// Record a B2BUA decision for a traced call
+    pub fn note(&self, call_id: &str, text: &str) {
+        if !self.is_active() {
+            return;
+        }
+
+        let target = self
+            .calls
+            .read()
+            .unwrap()
+            .get(call_id)
+            .cloned()
+            .or_else(|| {
+                let target = TraceTarget::CallId(call_id.to_string());
+                self.targets
+                    .read()
+                    .unwrap()
+                    .contains_key(&target)
+                    .then_some(target)
+            });
+
+        if let Some(target) = target {
+            self.write(&target, &format!("--- {}: {}\n", call_id, text));
+        }
+    }
+
+    /// Forget a finished call so its Call-ID stops matching
+    pub fn end_call(&self, call_id: &str) {
+        self.calls.write().unwrap().remove(call_id);
+    }
+
+    fn match_message(&self, message: &Message, peer: Option<SocketAddr>) -> Option<TraceTarget> {
+        let call_id = header(message, "Call-ID")?;
+
+        if let Some(target) = self.calls.read().unwrap().get(call_id) {
+            return Some(target.clone());
+        }
+
+        let targets = self.targets.read().unwrap();
+        let matched = targets.keys().find(|target| match target {
+            TraceTarget::CallId(id) => id == call_id,
+            TraceTarget::SourceIp(ip) => peer.is_some_and(|p| p.ip() == *ip),
+            TraceTarget::Extension(ext) => {
+                let request_user = match message {
+                    Message::Request(req) => req.uri.user.as_deref(),
+                    Message::Response(_) => None,
+                };
+                request_user == Some(ext.as_str())
+                    || ["From", "To"]
+                        .iter()
+                        .filter_map(|name| header(message, name))
+                        .any(|value| uri_user(value) == Some(ext.as_str()))
+            }
+        })?;
+
+        let matched = matched.clone();
+        drop(targets);
+        self.calls
+            .write()
+            .unwrap()
+            .insert(call_id.to_string(), matched.clone());
+        Some(matched)
+    }
+
+    fn write(&self, target: &TraceTarget, entry: &str) {
+        let file = {
+            let mut targets = self.targets.write().unwrap();
+            let Some(info) = targets.get_mut(target) else {
+                return;
+            };
+            info.entries += 1;
+            info.file.clone()
+        };
+
+        let _guard = self.write_lock.lock().unwrap();
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)
+            .and_then(|mut f| writeln!(f, "[{}] {}", Utc::now().to_rfc3339(), entry));
+
+        if let Err(e) = result {
+            warn!("Failed to write call trace to {}: {}", file.display(), e);
+        }
+    }
+}
+
+fn header<'a>(message: &'a Message, name: &str) -> Option<&'a str> {
+    match message {
+        Message::Request(req) => req.get_header_value(name),
+        Message::Response(res) => res.get_header_value(name),
+    }
+}
+
+/// Extract the user part of the URI in a From/To header value
+pub(crate) fn uri_user(value: &str) -> Option<&str> {
+    let start = value
+        .find("sip:")
+        .map(|i| i + 4)
+        .or_else(|| value.find("sips:").map(|i| i + 5))?;
+    let rest = &value[start..];
+    let at = rest.find('@')?;
+    Some(&rest[..at])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Method, Request, Response, StatusCode, Uri};
+
+    fn tracer(name: &str) -> CallTracer {
+        let dir =
+            std::env::temp_dir().join(format!("rustalk_trace_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        CallTracer::new(dir)
+    }
+
+    fn invite(call_id: &str, from: &str, to: &str) -> Message {
+        Message::Request(
+            Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "example.com".to_string()).with_user(to.to_string()),
+            )
+            .with_header("From", format!("<sip:{}@example.com>;tag=1", from))
+            .with_header("To", format!("<sip:{}@example.com>", to))
+            .with_header("Call-ID", call_id),
+        )
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            TraceTarget::parse("call", "abc").unwrap(),
+            TraceTarget::CallId("abc".to_string())
+        );
+        assert_eq!(
+            TraceTarget::parse("ip", "10.0.0.1").unwrap(),
+            TraceTarget::SourceIp("10.0.0.1".parse().unwrap())
+        );
+        assert!(TraceTarget::parse("ip", "nope").is_err());
+        assert!(TraceTarget::parse("foo", "bar").is_err());
+    }
+
+    #[test]
+    fn test_trace_by_extension_follows_call() {
+        let tracer = tracer("ext");
+        let info = tracer
+            .enable(TraceTarget::Extension("1001".to_string()))
+            .unwrap();
+
+        tracer.trace_message(
+            TraceDirection::Inbound,
+            &invite("call-1", "1001", "1002"),
+            None,
+        );
+        tracer.note("call-1", "session created");
+
+        // Response has no extension in it but belongs to the traced call
+        let trying =
+            Message::Response(Response::new(StatusCode::TRYING).with_header("Call-ID", "call-1"));
+        tracer.trace_message(TraceDirection::Outbound, &trying, None);
+
+        // Unrelated call is not captured
+        tracer.trace_message(
+            TraceDirection::Inbound,
+            &invite("call-2", "2001", "2002"),
+            None,
+        );
+
+        let contents = std::fs::read_to_string(&info.file).unwrap();
+        assert!(contents.contains("INVITE sip:1002@example.com"));
+        assert!(contents.contains("session created"));
+        assert!(contents.contains("SIP/2.0 100 Trying"));
+        assert!(!contents.contains("call-2"));
+        assert_eq!(tracer.list()[0].entries, 3);
+    }
+
+    #[test]
+    fn test_trace_by_source_ip() {
+        let tracer = tracer("ip");
+        let info = tracer
+            .enable(TraceTarget::SourceIp("192.0.2.10".parse().unwrap()))
+            .unwrap();
+
+        tracer.trace_message(
+            TraceDirection::Inbound,
+            &invite("call-a", "a", "b"),
+            Some("192.0.2.10:5060".parse().unwrap()),
+        );
+        tracer.trace_message(
+            TraceDirection::Inbound,
+            &invite("call-b", "a", "b"),
+            Some("192.0.2.11:5060".parse().unwrap()),
+        );
+
+        let contents = std::fs::read_to_string(&info.file).unwrap();
+        assert!(contents.contains("call-a"));
+        assert!(!contents.contains("call-b"));
+    }
+
+    #[test]
+    fn test_disable_stops_tracing() {
+        let tracer = tracer("disable");
+        let target = TraceTarget::CallId("call-x".to_string());
+        tracer.enable(target.clone()).unwrap();
+        assert!(tracer.is_active());
+
+        assert!(tracer.disable(&target));
+        assert!(!tracer.disable(&target));
+        assert!(!tracer.is_active());
+        assert!(tracer.list().is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+//! Automatic callback when busy (CCBS)
+//!
+//! When an extension answers busy, the caller can dial the callback feature
+//! code (`*6`, or `*6<extension>` for a specific target) to camp on it. The
+//! B2BUA watches the target's dialogs and registrations, and once it is free
+//! again hands the pending request to whatever places the callback.
+
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Callback configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallbackConfig {
+    /// Code dialed to request a callback
+    #[serde(default = "default_feature_code")]
+    pub feature_code: String,
+    /// How long a request stays queued before it is dropped
+    #[serde(default = "default_expiry_seconds")]
+    pub expiry_seconds: u32,
+    /// Pending requests allowed per caller
+    #[serde(default = "default_max_per_caller")]
+    pub max_per_caller: usize,
+}
+
+impl Default for CallbackConfig {
+    fn default() -> Self {
+        Self {
+            feature_code: default_feature_code(),
+            expiry_seconds: default_expiry_seconds(),
+            max_per_caller: default_max_per_caller(),
+        }
+    }
+}
+
+fn default_feature_code() -> String {
+    "*6".to_string()
+}
+
+fn default_expiry_seconds() -> u32 {
+    1800
+}
+
+fn default_max_per_caller() -> usize {
+    5
+}
+
+/// A caller waiting for a busy extension to become free
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallbackRequest {
+    pub caller: String,
+    pub target: String,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl CallbackRequest {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Last busy extension each caller reached
+    last_busy: HashMap<String, String>,
+    /// Pending requests, oldest first
+    pending: Vec<CallbackRequest>,
+}
+
+/// Pending callback requests
+#[derive(Debug, Clone)]
+pub struct CallbackQueue {
+    config: CallbackConfig,
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl CallbackQueue {
+    pub fn new(config: CallbackConfig) -> Self {
+        Self {
+            config,
+            queue: Arc::new(Mutex::new(Queue::default())),
+        }
+    }
+
+    pub fn config(&self) -> &CallbackConfig {
+        &self.config
+    }
+
+    /// Whether a dialed number is the callback feature code
+    pub fn is_feature_code(&self, dialed: &str) -> bool {
+        !self.config.feature_code.is_empty() && dialed.starts_with(&self.config.feature_code)
+    }
+
+    /// Remember that `caller` found `target` busy
+    pub fn record_busy(&self, caller: &str, target: &str) {
+        let mut queue = self.queue.lock().unwrap();
+        queue
+            .last_busy
+            .insert(caller.to_string(), target.to_string());
+    }
+
+    /// Queue a callback for a feature code dialed by `caller`
+    ///
+    /// The target is the extension after the feature code, or the last busy
+    /// extension the caller reached when none is given.
+    pub fn request(&self, caller: &str, dialed: &str) -> Result<CallbackRequest, String> {
+        let explicit = dialed
+            .strip_prefix(self.config.feature_code.as_str())
+            .filter(|target| !target.is_empty());
+
+        let now = Utc::now();
+        let mut queue = self.queue.lock().unwrap();
+        queue.pending.retain(|r| !r.is_expired(now));
+
+        let target = match explicit {
+            Some(target) => target.to_string(),
+            None => queue
+                .last_busy
+                .get(caller)
+                .cloned()
+                .ok_or_else(|| "no busy call to camp on".to_string())?,
+        };
+        if target == caller {
+            return Err("cannot camp on your own extension".to_string());
+        }
+        if let Some(existing) = queue
+            .pending
+            .iter()
+            .find(|r| r.caller == caller && r.target == target)
+        {
+            return Ok(existing.clone());
+        }
+        if queue.pending.iter().filter(|r| r.caller == caller).count() >= self.config.max_per_caller
+        {
+            return Err("too many pending callbacks".to_string());
+        }
+
+        let request = CallbackRequest {
+            caller: caller.to_string(),
+            target,
+            requested_at: now,
+            expires_at: now + Duration::seconds(self.config.expiry_seconds as i64),
+        };
+        queue.pending.push(request.clone());
+        Ok(request)
+    }
+
+    /// Drop a pending request, returning whether one existed
+    pub fn cancel(&self, caller: &str, target: &str) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let before = queue.pending.len();
+        queue
+            .pending
+            .retain(|r| !(r.caller == caller && r.target == target));
+        queue.pending.len() != before
+    }
+
+    /// Pending requests that have not expired, oldest first
+    pub fn pending(&self) -> Vec<CallbackRequest> {
+        let now = Utc::now();
+        let mut queue = self.queue.lock().unwrap();
+        queue.pending.retain(|r| !r.is_expired(now));
+        queue.pending.clone()
+    }
+
+    /// Remove and return the requests waiting on `target`, oldest first
+    pub fn take_ready(&self, target: &str) -> Vec<CallbackRequest> {
+        let now = Utc::now();
+        let mut queue = self.queue.lock().unwrap();
+        queue.pending.retain(|r| !r.is_expired(now));
+        let (ready, waiting) = queue
+            .pending
+            .drain(..)
+            .partition(|r: &CallbackRequest| r.target == target);
+        queue.pending = waiting;
+        ready
+    }
+}
+
+impl Default for CallbackQueue {
+    fn default() -> Self {
+        Self::new(CallbackConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_take_ready() {
+        let queue = CallbackQueue::default();
+
+        assert!(queue.is_feature_code("*6"));
+        assert!(!queue.is_feature_code("1002"));
+        assert_eq!(
+            queue.request("1001", "*6"),
+            Err("no busy call to camp on".to_string())
+        );
+
+        queue.record_busy("1001", "1002");
+        let request = queue.request("1001", "*6").unwrap();
+        assert_eq!(request.target, "1002");
+        // Asking twice keeps a single request
+        queue.request("1001", "*6").unwrap();
+        queue.request("1003", "*61002").unwrap();
+        queue.request("1003", "*61004").unwrap();
+        assert_eq!(queue.pending().len(), 3);
+        assert!(queue.request("1001", "*61001").is_err());
+
+        let ready: Vec<String> = queue
+            .take_ready("1002")
+            .into_iter()
+            .map(|r| r.caller)
+            .collect();
+        assert_eq!(ready, vec!["1001", "1003"]);
+        assert!(queue.take_ready("1002").is_empty());
+
+        assert!(queue.cancel("1003", "1004"));
+        assert!(queue.pending().is_empty());
+    }
+
+    #[test]
+    fn test_limits_and_expiry() {
+        let queue = CallbackQueue::new(CallbackConfig {
+            max_per_caller: 1,
+            expiry_seconds: 0,
+            ..Default::default()
+        });
+        queue.request("1001", "*61002").unwrap();
+        // Expired requests do not count against the limit
+        queue.request("1001", "*61003").unwrap();
+        assert!(queue.pending().is_empty());
+
+        let queue = CallbackQueue::new(CallbackConfig {
+            max_per_caller: 1,
+            ..Default::default()
+        });
+        queue.request("1001", "*61002").unwrap();
+        assert_eq!(
+            queue.request("1001", "*61003"),
+            Err("too many pending callbacks".to_string())
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! CDR sinks
+//!
+//! Call detail records the B2BUA emits when a session ends are handed to a
+//! [`CdrPipeline`], which writes each one to every sink configured: the
+//! `call_logs` table of the database, and the cloud API's call log ingest
+//! endpoint over HTTP. Other destinations plug in through [`CdrSink`].
+//! A failed write is retried with backoff a few times and then dropped
+//! with a warning, so a sink that is down never holds up calls.
+
+use anyhow::{anyhow, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::database::HasArguments;
+use sqlx::query::Query;
+use sqlx::{Encode, Type};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::b2bua::CallDetailRecord;
+use crate::db::Database;
+
+/// Where call detail records are written
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CdrConfig {
+    /// Insert records into `call_logs` when a database is configured
+    #[serde(default = "default_database")]
+    pub database: bool,
+    /// POST records to the cloud API
+    #[serde(default)]
+    pub http: Option<CdrHttpConfig>,
+    /// Attempts after the first before a record is dropped
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+}
+
+impl Default for CdrConfig {
+    fn default() -> Self {
+        Self {
+            database: default_database(),
+            http: None,
+            retries: default_retries(),
+            retry_delay_ms: default_retry_delay_ms(),
+        }
+    }
+}
+
+/// Cloud API call log ingest endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CdrHttpConfig {
+    /// e.g. `https://cloud.example.com/api/v1/call-logs`
+    pub url: String,
+    /// Extra request headers, e.g. an API key
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_database() -> bool {
+    true
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1000
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+/// Destination for call detail records
+#[async_trait::async_trait]
+pub trait CdrSink: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &str;
+
+    async fn write(&self, cdr: &CallDetailRecord) -> Result<()>;
+}
+
+/// Inserts records into the `call_logs` table, which the API's call log
+/// store reads
+pub struct DatabaseSink {
+    database: Database,
+}
+
+impl DatabaseSink {
+    pub fn new(database: Database) -> Self {
+        Self { database }
+    }
+}
+
+/// Values of a `call_logs` row, with times as RFC 3339 text and the record
+/// as JSON text for the database to convert
+struct CallLogRow {
+    call_id: String,
+    caller: Option<String>,
+    callee: Option<String>,
+    start_time: String,
+    answer_time: Option<String>,
+    end_time: String,
+    duration_seconds: i64,
+    setup_ms: Option<i64>,
+    disposition: Option<String>,
+    hangup_cause: Option<String>,
+    caller_codec: Option<String>,
+    callee_codec: Option<String>,
+    account_code: Option<String>,
+    record: String,
+    from_user: String,
+    to_user: String,
+}
+
+impl CallLogRow {
+    fn new(cdr: &CallDetailRecord) -> Result<Self> {
+        let (from_user, _) = cdr.caller_party();
+        let (to_user, _) = cdr.callee_party();
+        Ok(Self {
+            call_id: cdr.call_id.clone(),
+            caller: cdr.caller.clone(),
+            callee: cdr.callee.clone(),
+            start_time: cdr.start_time.to_rfc3339(),
+            answer_time: cdr.answer_time.map(|t| t.to_rfc3339()),
+            end_time: cdr.end_time.to_rfc3339(),
+            duration_seconds: cdr.duration_seconds,
+            setup_ms: cdr.setup_ms.map(|ms| ms as i64),
+            disposition: serde_json::to_value(cdr.disposition)?
+                .as_str()
+                .map(str::to_string),
+            hangup_cause: cdr.hangup_cause.map(|cause| cause.as_str().to_string()),
+            caller_codec: cdr.caller_codec.clone(),
+            callee_codec: cdr.callee_codec.clone(),
+            account_code: cdr.account_code.clone(),
+            record: serde_json::to_string(cdr)?,
+            from_user,
+            to_user,
+        })
+    }
+
+    /// Bind the values to `$1` to `$16` of `query`, in column order
+    fn bind<'q, DB>(
+        self,
+        query: Query<'q, DB, <DB as HasArguments<'q>>::Arguments>,
+    ) -> Query<'q, DB, <DB as HasArguments<'q>>::Arguments>
+    where
+        DB: sqlx::Database,
+        String: Encode<'q, DB> + Type<DB>,
+        Option<String>: Encode<'q, DB> + Type<DB>,
+        i64: Encode<'q, DB> + Type<DB>,
+        Option<i64>: Encode<'q, DB> + Type<DB>,
+    {
+        query
+            .bind(self.call_id)
+            .bind(self.caller)
+            .bind(self.callee)
+            .bind(self.start_time)
+            .bind(self.answer_time)
+            .bind(self.end_time)
+            .bind(self.duration_seconds)
+            .bind(self.setup_ms)
+            .bind(self.disposition)
+            .bind(self.hangup_cause)
+            .bind(self.caller_codec)
+            .bind(self.callee_codec)
+            .bind(self.account_code)
+            .bind(self.record)
+            .bind(self.from_user)
+            .bind(self.to_user)
+    }
+}
+
+#[async_trait::async_trait]
+impl CdrSink for DatabaseSink {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    async fn write(&self, cdr: &CallDetailRecord) -> Result<()> {
+        // Postgres casts the text it is given; SQLite keeps times as
+        // seconds since the epoch and the record as text
+        let values = match &self.database {
+            Database::Postgres(_) => {
+                "$1, $2, $3, $4::timestamptz, $5::timestamptz, $6::timestamptz, \
+                 $7, $8, $9, $10, $11, $12, $13, $14::jsonb, $15, $16"
+            }
+            Database::Sqlite(_) => {
+                "$1, $2, $3, CAST(strftime('%s', $4) AS INTEGER), \
+                 CAST(strftime('%s', $5) AS INTEGER), CAST(strftime('%s', $6) AS INTEGER), \
+                 $7, $8, $9, $10, $11, $12, $13, $14, $15, $16"
+            }
+        };
+        // A record re-sent for the same call replaces the one stored
+        let sql = format!(
+            "INSERT INTO call_logs (call_id, caller, callee, start_time, answer_time, \
+             end_time, duration_seconds, setup_ms, disposition, hangup_cause, \
+             caller_codec, callee_codec, account_code, record, from_user, to_user) \
+             VALUES ({}) \
+             ON CONFLICT (call_id) DO UPDATE SET caller = excluded.caller, \
+             callee = excluded.callee, start_time = excluded.start_time, \
+             answer_time = excluded.answer_time, end_time = excluded.end_time, \
+             duration_seconds = excluded.duration_seconds, setup_ms = excluded.setup_ms, \
+             disposition = excluded.disposition, hangup_cause = excluded.hangup_cause, \
+             caller_codec = excluded.caller_codec, callee_codec = excluded.callee_codec, \
+             account_code = excluded.account_code, record = excluded.record, \
+             from_user = excluded.from_user, to_user = excluded.to_user",
+            values
+        );
+        let row = CallLogRow::new(cdr)?;
+        match &self.database {
+            Database::Postgres(pool) => {
+                row.bind(sqlx::query(&sql)).execute(pool).await?;
+            }
+            Database::Sqlite(pool) => {
+                row.bind(sqlx::query(&sql)).execute(pool).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// POSTs records as JSON to the cloud API
+pub struct HttpSink {
+    config: CdrHttpConfig,
+    client: reqwest::Client,
+}
+
+impl HttpSink {
+    pub fn new(config: CdrHttpConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CdrSink for HttpSink {
+    fn name(&self) -> &str {
+        &self.config.url
+    }
+
+    async fn write(&self, cdr: &CallDetailRecord) -> Result<()> {
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .timeout(Duration::from_millis(self.config.timeout_ms))
+            .json(cdr);
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        let status = request.send().await?.status();
+        if !status.is_success() {
+            return Err(anyhow!("Call log ingest returned {}", status));
+        }
+        Ok(())
+    }
+}
+
+/// Writes each record to every sink
+#[derive(Clone)]
+pub struct CdrPipeline {
+    sinks: Vec<Arc<dyn CdrSink>>,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl CdrPipeline {
+    /// A pipeline without sinks, retrying as configured
+    pub fn new(config: &CdrConfig) -> Self {
+        Self {
+            sinks: Vec::new(),
+            retries: config.retries,
+            retry_delay: Duration::from_millis(config.retry_delay_ms),
+        }
+    }
+
+    pub fn with_sink(mut self, sink: Arc<dyn CdrSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    pub fn sinks(&self) -> impl Iterator<Item = &str> {
+        self.sinks.iter().map(|sink| sink.name())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Write `cdr` to every sink, retrying failures; returns the number of
+    /// sinks that took it
+    pub async fn write(&self, cdr: &CallDetailRecord) -> usize {
+        let mut written = 0;
+        for sink in &self.sinks {
+            let mut delay = self.retry_delay;
+            for attempt in 0..=self.retries {
+                match sink.write(cdr).await {
+                    Ok(()) => {
+                        debug!("CDR {} written to {}", cdr.call_id, sink.name());
+                        written += 1;
+                        break;
+                    }
+                    Err(e) if attempt < self.retries => {
+                        debug!(
+                            "CDR {} to {} failed, retrying: {:#}",
+                            cdr.call_id,
+                            sink.name(),
+                            e
+                        );
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    Err(e) => warn!(
+                        "Dropping CDR {} for {} after {} attempts: {:#}",
+                        cdr.call_id,
+                        sink.name(),
+                        attempt + 1,
+                        e
+                    ),
+                }
+            }
+        }
+        written
+    }
+
+    /// Write records sent on the returned channel in the background, each
+    /// on its own task so a retrying sink does not delay the next record
+    pub fn spawn(self) -> mpsc::UnboundedSender<CallDetailRecord> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<CallDetailRecord>();
+        let pipeline = Arc::new(self);
+        tokio::spawn(async move {
+            while let Some(cdr) = rx.recv().await {
+                let pipeline = pipeline.clone();
+                tokio::spawn(async move {
+                    pipeline.write(&cdr).await;
+                });
+            }
+        });
+        tx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::b2bua::{CallDisposition, Session};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Fails the first `failures` writes, then keeps records
+    struct Flaky {
+        failures: AtomicUsize,
+        records: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl CdrSink for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn write(&self, cdr: &CallDetailRecord) -> Result<()> {
+            if self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(anyhow!("unavailable"));
+            }
+            self.records.lock().unwrap().push(cdr.call_id.clone());
+            Ok(())
+        }
+    }
+
+    fn flaky(failures: usize) -> Arc<Flaky> {
+        Arc::new(Flaky {
+            failures: AtomicUsize::new(failures),
+            records: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn cdr(call_id: &str) -> CallDetailRecord {
+        CallDetailRecord::from_session(&Session::new(call_id.to_string()), CallDisposition::Failed)
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_retries() {
+        let config = CdrConfig {
+            retries: 2,
+            retry_delay_ms: 1,
+            ..Default::default()
+        };
+        let recovers = flaky(2);
+        let down = flaky(usize::MAX);
+        let pipeline = CdrPipeline::new(&config)
+            .with_sink(recovers.clone())
+            .with_sink(down.clone());
+
+        assert_eq!(pipeline.write(&cdr("call-1")).await, 1);
+        assert_eq!(*recovers.records.lock().unwrap(), ["call-1"]);
+        assert!(down.records.lock().unwrap().is_empty());
+        // Three attempts were made before giving up
+        assert_eq!(down.failures.load(Ordering::SeqCst), usize::MAX - 3);
+
+        let tx = pipeline.spawn();
+        tx.send(cdr("call-2")).unwrap();
+        for _ in 0..100 {
+            if recovers.records.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*recovers.records.lock().unwrap(), ["call-1", "call-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_http_sink() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v1/call-logs", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read until the whole JSON body has arrived
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let sink = HttpSink::new(CdrHttpConfig {
+            url,
+            headers: HashMap::from([("X-Api-Key".to_string(), "secret".to_string())]),
+            timeout_ms: default_timeout_ms(),
+        });
+        sink.write(&cdr("call-1")).await.unwrap();
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.starts_with("post /api/v1/call-logs"));
+        assert!(request.contains("x-api-key: secret"));
+        assert!(request.contains(r#""call_id":"call-1""#));
+    }
+}
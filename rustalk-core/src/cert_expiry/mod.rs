@@ -0,0 +1,366 @@
+//! Certificate expiry warnings
+//!
+//! Watches every certificate RusTalk presents: the ACME store, the SIP TLS
+//! certificate and the Teams mTLS certificate. As a certificate crosses
+//! each warning threshold (30, 14, 7 and 1 days before expiry by default)
+//! the monitor logs it and posts it to the outbound webhooks, once per
+//! threshold. A renewed certificate starts over. The warnings still in
+//! effect are reported by the API's health endpoint.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use rustls::pki_types::CertificateDer;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufReader, Cursor};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use x509_parser::prelude::*;
+
+use crate::acme::CertificateStorage;
+use crate::config::Config;
+use crate::events::{AlarmEvent, AlarmSeverity, EventBus, SystemEvent};
+use crate::webhooks::WebhookDispatcher;
+
+/// Warning thresholds and how often certificates are checked
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CertExpiryConfig {
+    /// Days before expiry at which to warn
+    #[serde(default = "default_thresholds_days")]
+    pub thresholds_days: Vec<u32>,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+fn default_thresholds_days() -> Vec<u32> {
+    vec![30, 14, 7, 1]
+}
+
+fn default_interval_seconds() -> u64 {
+    3600
+}
+
+impl Default for CertExpiryConfig {
+    fn default() -> Self {
+        Self {
+            thresholds_days: default_thresholds_days(),
+            interval_seconds: default_interval_seconds(),
+        }
+    }
+}
+
+/// A certificate nearing or past its expiry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExpiryWarning {
+    /// `sip-tls`, `teams-mtls` or the domain of an ACME certificate
+    pub certificate: String,
+    pub path: PathBuf,
+    pub expires_at: DateTime<Utc>,
+    /// Whole days left; negative once expired
+    pub days_until_expiry: i64,
+    /// Smallest threshold crossed, 0 once expired
+    pub threshold_days: u32,
+}
+
+impl ExpiryWarning {
+    pub fn is_expired(&self) -> bool {
+        self.days_until_expiry < 0
+    }
+
+    pub fn message(&self) -> String {
+        if self.is_expired() {
+            format!(
+                "Certificate {} expired on {}",
+                self.certificate,
+                self.expires_at.format("%Y-%m-%d")
+            )
+        } else {
+            format!(
+                "Certificate {} expires in {} day(s), on {}",
+                self.certificate,
+                self.days_until_expiry,
+                self.expires_at.format("%Y-%m-%d")
+            )
+        }
+    }
+
+    /// Webhook and health payload
+    pub fn to_event(&self) -> Value {
+        json!({
+            "kind": "certificate_expiring",
+            "certificate": self.certificate,
+            "path": self.path,
+            "expires_at": self.expires_at,
+            "days_until_expiry": self.days_until_expiry,
+            "threshold_days": self.threshold_days,
+            "message": self.message(),
+            "timestamp": Utc::now(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct MonitorState {
+    /// Warnings from the latest check
+    warnings: Vec<ExpiryWarning>,
+    /// Expiry and smallest threshold announced per certificate
+    announced: HashMap<String, (DateTime<Utc>, u32)>,
+}
+
+/// Checks certificates against the warning thresholds
+#[derive(Clone)]
+pub struct CertificateMonitor {
+    config: CertExpiryConfig,
+    certificates: Vec<(String, PathBuf)>,
+    store: Option<CertificateStorage>,
+    state: Arc<RwLock<MonitorState>>,
+    events: Option<EventBus>,
+}
+
+impl CertificateMonitor {
+    pub fn new(config: CertExpiryConfig) -> Self {
+        Self {
+            config,
+            certificates: Vec::new(),
+            store: None,
+            state: Arc::new(RwLock::new(MonitorState::default())),
+            events: None,
+        }
+    }
+
+    /// Raise an alarm for each threshold crossed, critical once expired
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Watch a PEM certificate file under `name`
+    pub fn with_certificate(mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.certificates.push((name.into(), path.into()));
+        self
+    }
+
+    /// Watch every certificate in an ACME store
+    pub fn with_store(mut self, cert_dir: PathBuf) -> Result<Self> {
+        self.store = Some(CertificateStorage::new(cert_dir)?);
+        Ok(self)
+    }
+
+    /// Watch the certificates named in `config`
+    pub fn watching(mut self, config: &Config) -> Result<Self> {
+        if let Some(cert) = &config.transport.tls_cert {
+            self = self.with_certificate("sip-tls", cert);
+        }
+        if let Some(teams) = config.teams.as_ref().filter(|t| t.enabled) {
+            self = self.with_certificate("teams-mtls", &teams.mtls_cert);
+        }
+        if let Some(acme) = config.acme.as_ref().filter(|a| a.enabled) {
+            self = self.with_store(acme.cert_dir.clone())?;
+        }
+        Ok(self)
+    }
+
+    /// Warnings from the latest check
+    pub async fn warnings(&self) -> Vec<ExpiryWarning> {
+        self.state.read().await.warnings.clone()
+    }
+
+    /// Check every certificate, returning the warnings that crossed a new
+    /// threshold since the last check
+    pub async fn check(&self) -> Vec<ExpiryWarning> {
+        self.check_at(Utc::now()).await
+    }
+
+    async fn check_at(&self, now: DateTime<Utc>) -> Vec<ExpiryWarning> {
+        let mut certificates = self.certificates.clone();
+        if let Some(store) = &self.store {
+            for domain in store.list_certificates().await.unwrap_or_default() {
+                let path = store.cert_path(&domain);
+                certificates.push((domain, path));
+            }
+        }
+
+        let mut warnings = Vec::new();
+        for (name, path) in certificates {
+            let expires_at = match read_expiry(&path).await {
+                Ok(expires_at) => expires_at,
+                Err(e) => {
+                    debug!("Skipping certificate {}: {:#}", name, e);
+                    continue;
+                }
+            };
+            let days_until_expiry = (expires_at - now).num_days();
+            let threshold = if expires_at <= now {
+                Some(0)
+            } else {
+                self.config
+                    .thresholds_days
+                    .iter()
+                    .copied()
+                    .filter(|t| days_until_expiry < i64::from(*t))
+                    .min()
+            };
+            if let Some(threshold_days) = threshold {
+                warnings.push(ExpiryWarning {
+                    certificate: name,
+                    path,
+                    expires_at,
+                    days_until_expiry: if expires_at <= now {
+                        days_until_expiry.min(-1)
+                    } else {
+                        days_until_expiry
+                    },
+                    threshold_days,
+                });
+            }
+        }
+        warnings.sort_by_key(|w| w.expires_at);
+
+        let mut state = self.state.write().await;
+        let mut crossed = Vec::new();
+        for warning in &warnings {
+            let announced = state.announced.get(&warning.certificate);
+            let new = match announced {
+                // Same certificate: only a smaller threshold is news
+                Some((expires_at, threshold)) if *expires_at == warning.expires_at => {
+                    warning.threshold_days < *threshold
+                }
+                _ => true,
+            };
+            if new {
+                state.announced.insert(
+                    warning.certificate.clone(),
+                    (warning.expires_at, warning.threshold_days),
+                );
+                crossed.push(warning.clone());
+            }
+        }
+        // Renewed or removed certificates are announced afresh next time
+        state
+            .announced
+            .retain(|name, _| warnings.iter().any(|w| &w.certificate == name));
+        state.warnings = warnings;
+        crossed
+    }
+
+    /// Check on the configured interval, logging each threshold crossed
+    /// and posting it to the webhooks that want certificate warnings
+    pub fn spawn(&self, webhooks: Option<WebhookDispatcher>) -> JoinHandle<()> {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(monitor.config.interval_seconds.max(1)));
+            loop {
+                interval.tick().await;
+                for warning in monitor.check().await {
+                    warn!("{}", warning.message());
+                    if let Some(bus) = &monitor.events {
+                        let severity = if warning.is_expired() {
+                            AlarmSeverity::Critical
+                        } else {
+                            AlarmSeverity::Warning
+                        };
+                        bus.publish_system(SystemEvent::Alarm(AlarmEvent::new(
+                            severity,
+                            "certificate",
+                            warning.certificate.as_str(),
+                            warning.message(),
+                        )));
+                    }
+                    let Some(dispatcher) = &webhooks else {
+                        continue;
+                    };
+                    let payload = warning.to_event();
+                    for endpoint in dispatcher.endpoints() {
+                        if !endpoint.certificate_warnings {
+                            continue;
+                        }
+                        if let Err(e) = dispatcher.deliver_value(endpoint, payload.clone()).await {
+                            warn!("Webhook {} failed: {:#}", endpoint.name, e);
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Expiry of the first certificate in a PEM file
+async fn read_expiry(path: &PathBuf) -> Result<DateTime<Utc>> {
+    let pem = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("reading {}", path.display()))?;
+    let mut reader = BufReader::new(Cursor::new(pem));
+    let der: CertificateDer = rustls_pemfile::certs(&mut reader)
+        .next()
+        .ok_or_else(|| anyhow!("no certificate in {}", path.display()))??;
+    let (_, cert) = X509Certificate::from_der(der.as_ref())
+        .map_err(|e| anyhow!("failed to parse certificate: {}", e))?;
+    DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow!("certificate expiry out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Datelike;
+
+    async fn write_certificate(path: &PathBuf, expires_at: DateTime<Utc>) {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["sbc.example.com".to_string()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(
+            expires_at.year(),
+            expires_at.month() as u8,
+            expires_at.day() as u8,
+        );
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        tokio::fs::write(path, cert.pem()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_thresholds_announced_once() {
+        let dir = std::env::temp_dir().join("rustalk-cert-expiry-test");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("teams.pem");
+        let now = Utc::now();
+        write_certificate(&path, now + chrono::Duration::days(20)).await;
+
+        let monitor = CertificateMonitor::new(CertExpiryConfig::default())
+            .with_certificate("teams-mtls", &path)
+            .with_certificate("missing", dir.join("missing.pem"));
+
+        // 19 or 20 whole days left, depending on the time of day
+        let crossed = monitor.check_at(now).await;
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].certificate, "teams-mtls");
+        assert_eq!(crossed[0].threshold_days, 30);
+        assert!(monitor.check_at(now).await.is_empty());
+        assert_eq!(monitor.warnings().await.len(), 1);
+
+        // Skipping past the 14 and 7 day thresholds announces only the 7
+        let later = now + chrono::Duration::days(15);
+        let crossed = monitor.check_at(later).await;
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].threshold_days, 7);
+
+        let expired = now + chrono::Duration::days(21);
+        let crossed = monitor.check_at(expired).await;
+        assert_eq!(crossed[0].threshold_days, 0);
+        assert!(crossed[0].is_expired());
+        assert!(crossed[0].message().contains("expired"));
+
+        // A renewed certificate drops out of the warnings
+        write_certificate(&path, now + chrono::Duration::days(90)).await;
+        assert!(monitor.check_at(now).await.is_empty());
+        assert!(monitor.warnings().await.is_empty());
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
@@ -1,17 +1,70 @@
 //! Configuration management with JSON and database overlay
 
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
+use crate::account_codes::AccountCodeConfig;
 use crate::acl::AclManager;
+use crate::admission::AdmissionConfig;
+use crate::call_history::CallHistoryConfig;
+use crate::callback::CallbackConfig;
+use crate::cdr_sink::CdrConfig;
+use crate::cert_expiry::CertExpiryConfig;
+use crate::console::RemoteConsoleConfig;
+use crate::cos::CosConfig;
+use crate::crm::CrmConfig;
+use crate::dial_pin::DialPinConfig;
+use crate::dial_string::DialStringConfig;
+use crate::dialer::DialerConfig;
+use crate::domains::DomainConfig;
+use crate::enum_resolver::EnumConfig;
+use crate::fax::FaxConfig;
+use crate::ivr::IvrConfig;
+use crate::lcr::LcrConfig;
+use crate::lnp::LnpConfig;
+use crate::locale::LocaleConfig;
+use crate::logging::LoggingConfig;
 use crate::media::CodecConfig;
+use crate::media_quality::MediaQualityConfig;
+use crate::missed_calls::MissedCallConfig;
+use crate::mwi::MwiConfig;
+use crate::nat::NatConfig;
+use crate::no_answer::NoAnswerConfig;
+use crate::pdd::PddConfig;
+use crate::presence::PresenceConfig;
+use crate::quirks::QuirksConfig;
+use crate::quotas::QuotaConfig;
+use crate::radius::RadiusConfig;
+use crate::registrar::RegistrarConfig;
 use crate::routing::RoutingConfig;
+use crate::schedules::Schedule;
+use crate::screening::ScreeningConfig;
+use crate::sms::SmsConfig;
+use crate::snmp::SnmpConfig;
+use crate::supervisor::SupervisorConfig;
+use crate::teams_records::CallRecordsConfig;
+use crate::transcription::TranscriptionConfig;
+use crate::transport::{EgressSelector, KeepaliveConfig, NetworkInterface};
+use crate::trunk_health::TrunkHealthConfig;
+use crate::voicemail::{RetrievalConfig, VoicemailDropConfig};
+use crate::webhooks::WebhookConfig;
+use crate::wholesale::WholesaleConfig;
+
+pub mod overrides;
+pub mod reload;
+pub mod schema;
+pub mod shadow;
+pub mod validate;
+
+pub use reload::{ConfigReloader, ReloadReport, StagedConfig};
+pub use validate::{validate, References, ValidationIssue};
 
 /// Main configuration structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Config {
     pub server: ServerConfig,
     pub sip: SipConfig,
@@ -22,16 +75,194 @@ pub struct Config {
     pub codecs: Option<CodecConfig>,
     pub routing: Option<RoutingConfig>,
     pub acls: Option<AclManager>,
+    pub admission: Option<AdmissionConfig>,
+    /// Calling permissions per extension
+    pub cos: Option<CosConfig>,
+    /// Account code collection for billing attribution
+    pub account_codes: Option<AccountCodeConfig>,
+    /// PINs required for outbound calls from shared phones
+    pub dial_pins: Option<DialPinConfig>,
+    /// Automatic callback when a dialed extension is busy
+    pub callbacks: Option<CallbackConfig>,
+    /// Caller announcement and screening per extension
+    pub screening: Option<ScreeningConfig>,
+    /// Ring time and no-answer action per extension
+    pub no_answer: Option<NoAnswerConfig>,
+    /// Prefix for dialing straight to an extension's voicemail
+    pub voicemail_drop: Option<VoicemailDropConfig>,
+    /// Feature code for subscribers to listen to their voicemail
+    pub voicemail_retrieval: Option<RetrievalConfig>,
+    /// Missed call events and email notifications per extension
+    pub missed_calls: Option<MissedCallConfig>,
+    /// Per-extension call history retention and number masking
+    pub call_history: Option<CallHistoryConfig>,
+    /// SMS provider and DID to extension mapping
+    pub sms: Option<SmsConfig>,
+    /// CRM webhook queried for screen-pops on ringing calls
+    pub crm: Option<CrmConfig>,
+    /// HTTP endpoints that receive call events
+    pub webhooks: Option<WebhookConfig>,
+    /// Fax DIDs bridged to email
+    pub fax: Option<FaxConfig>,
+    /// SNMP agent for network management systems
+    pub snmp: Option<SnmpConfig>,
+    /// RADIUS accounting and registration authentication
+    pub radius: Option<RadiusConfig>,
+    /// Registration credentials and expiry limits
+    pub registrar: Option<RegistrarConfig>,
+    /// Carrier peers authenticated by source address and tech-prefix
+    pub wholesale: Option<WholesaleConfig>,
+    /// SIP compatibility profiles bound to trunks
+    pub quirks: Option<QuirksConfig>,
+    /// Request-URI templates for INVITEs sent to trunks
+    pub dial_strings: Option<DialStringConfig>,
+    /// Named business hours that routes refer to
+    pub schedules: Option<Vec<Schedule>>,
+    /// Warnings before TLS, mTLS and ACME certificates expire
+    pub cert_expiry: Option<CertExpiryConfig>,
+    /// Parts of the stack `rustalk start` runs
+    pub components: Option<ComponentsConfig>,
+    /// Restart policy for the components `rustalk start` runs
+    pub supervisor: Option<SupervisorConfig>,
+    /// Zero-downtime restarts
+    pub upgrade: Option<UpgradeConfig>,
+    /// NAT traversal tunables per SIP profile
+    pub nat: Option<NatConfig>,
+    /// SIP domains served per profile, with their aliases
+    pub domains: Option<DomainConfig>,
+    /// Post-dial delay window and alert threshold
+    pub pdd: Option<PddConfig>,
+    /// Packet loss and jitter window and alert thresholds
+    pub media_quality: Option<MediaQualityConfig>,
+    /// OPTIONS probes of trunks; down trunks are routed around
+    pub trunk_health: Option<TrunkHealthConfig>,
+    /// Rate cards least-cost routes choose between their trunks with
+    pub lcr: Option<LcrConfig>,
+    /// Message waiting subscriptions and unsolicited NOTIFYs
+    pub mwi: Option<MwiConfig>,
+    /// BLF lists per device and their subscriptions
+    pub presence: Option<PresenceConfig>,
+    /// Prompt languages per tenant, DID and route
+    pub locales: Option<LocaleConfig>,
+    /// Extension, trunk, call and storage caps per tenant
+    pub quotas: Option<QuotaConfig>,
+    /// Number portability dips before routing
+    pub lnp: Option<LnpConfig>,
+    /// ENUM lookups for routes that deliver over ENUM; defaults apply when
+    /// such routes exist without it
+    pub enum_routing: Option<EnumConfig>,
+    /// Where call detail records are written
+    pub cdr: Option<CdrConfig>,
+    /// IVR flows
+    pub ivr: Option<IvrConfig>,
+    /// Outbound calling campaigns and the do-not-call list
+    pub dialer: Option<DialerConfig>,
+    /// Streaming call audio to a transcription service
+    pub transcription: Option<TranscriptionConfig>,
+    /// Console access over TLS for operators on other hosts
+    pub remote_console: Option<RemoteConsoleConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Parts of the stack `rustalk start` runs
+///
+/// Everything is on by default. Components that need a configuration
+/// section (`routing`, `acme`, `teams`) only run when it is present.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ComponentsConfig {
+    /// SIP transports feeding the B2BUA
+    #[serde(default = "enabled")]
+    pub sip: bool,
+    /// Accept REGISTERs
+    #[serde(default = "enabled")]
+    pub registrar: bool,
+    /// Evaluate `routing` rules for new INVITEs
+    #[serde(default = "enabled")]
+    pub routing: bool,
+    /// Request missing `acme` certificates and renew expiring ones
+    #[serde(default = "enabled")]
+    pub acme_renewal: bool,
+    /// Management API, and the web UI from `webui_path` or embedded in
+    /// the binary
+    #[serde(default = "enabled")]
+    pub api: bool,
+    #[serde(default = "default_api_bind")]
+    pub api_bind: String,
+    /// Directory of the built web UI; overrides the embedded one
+    #[serde(default)]
+    pub webui_path: Option<String>,
+    /// Teams Direct Routing gateway, when `teams` is enabled
+    #[serde(default = "enabled")]
+    pub edge: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_api_bind() -> String {
+    "0.0.0.0:8080".to_string()
+}
+
+impl Default for ComponentsConfig {
+    fn default() -> Self {
+        Self {
+            sip: true,
+            registrar: true,
+            routing: true,
+            acme_renewal: true,
+            api: true,
+            api_bind: default_api_bind(),
+            webui_path: None,
+            edge: true,
+        }
+    }
+}
+
+/// Handing the SIP and API ports over to a new process
+///
+/// With `reuse_port`, a new `rustalk start` binds alongside the running
+/// one. Once its components are up it signals the process recorded in
+/// `pid_file`, which stops taking new calls and exits when its calls have
+/// ended or `drain_timeout_seconds` has passed. Meanwhile the two hand
+/// each other the UDP messages of calls the other holds.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UpgradeConfig {
+    /// Bind listeners with `SO_REUSEPORT`
+    #[serde(default)]
+    pub reuse_port: bool,
+    /// Records the running process and its handoff socket so its successor
+    /// can signal it
+    #[serde(default)]
+    pub pid_file: Option<PathBuf>,
+    #[serde(default = "default_drain_timeout_seconds")]
+    pub drain_timeout_seconds: u64,
+}
+
+fn default_drain_timeout_seconds() -> u64 {
+    300
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        Self {
+            reuse_port: false,
+            pid_file: None,
+            drain_timeout_seconds: default_drain_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ServerConfig {
     pub bind_address: String,
     pub bind_port: u16,
     pub workers: usize,
+    /// Log level and output sinks
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SipConfig {
     pub domain: String,
     pub user_agent: String,
@@ -39,34 +270,51 @@ pub struct SipConfig {
     pub session_expires: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TransportSettings {
     pub protocols: Vec<String>,
     pub udp_port: Option<u16>,
     pub tcp_port: Option<u16>,
     pub tls_port: Option<u16>,
+    /// SIP over WebSocket for browser clients
+    #[serde(default)]
+    pub ws_port: Option<u16>,
+    #[serde(default)]
+    pub wss_port: Option<u16>,
     pub tls_cert: Option<String>,
     pub tls_key: Option<String>,
+    /// Local interfaces for multihomed deployments
+    #[serde(default)]
+    pub interfaces: Vec<NetworkInterface>,
+    /// CRLF keep-alive and idle timers for TCP, TLS and WebSocket flows
+    #[serde(default)]
+    pub keepalive: Option<KeepaliveConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DatabaseConfig {
     pub url: String,
     pub max_connections: u32,
     pub min_connections: u32,
+    /// Apply pending migrations at startup instead of refusing to start
+    #[serde(default)]
+    pub auto_migrate: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TeamsConfig {
     pub enabled: bool,
     pub sbc_fqdn: String,
     pub mtls_cert: String,
     pub mtls_key: String,
     pub trunk_fqdn: String,
+    /// Import Teams call records from Microsoft Graph
+    #[serde(default)]
+    pub call_records: Option<CallRecordsConfig>,
 }
 
 /// ACME/Let's Encrypt configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AcmeConfig {
     /// Enable ACME certificate management
     pub enabled: bool,
@@ -125,18 +373,15 @@ impl Config {
         Ok(config)
     }
 
-    /// Apply database configuration overlay
+    /// Merge the overrides stored in the database over `config`
     async fn apply_db_overlay(config: Config, db_config: &DatabaseConfig) -> Result<Self> {
-        // This would connect to the database and overlay configuration values
-        // For now, this is a placeholder
-        tracing::info!("Database overlay from: {}", db_config.url);
-
-        // In a real implementation, we would:
-        // 1. Connect to the database using sqlx
-        // 2. Query configuration overrides
-        // 3. Merge them into the config structure
-
-        Ok(config)
+        let database = crate::db::connect(db_config).await?;
+        let overrides = overrides::load(&database).await?;
+        database.close().await;
+        if !overrides.is_empty() {
+            tracing::info!("Applying {} configuration override(s)", overrides.len());
+        }
+        overrides::apply(config, &overrides)
     }
 
     /// Get bind address
@@ -145,6 +390,11 @@ impl Config {
         Ok(addr.parse()?)
     }
 
+    /// Build the egress selector for the configured interfaces
+    pub fn egress_selector(&self) -> EgressSelector {
+        EgressSelector::new(self.transport.interfaces.clone())
+    }
+
     /// Save configuration to file
     pub async fn save_to_file(&self, path: impl AsRef<Path>) -> Result<()> {
         let json = serde_json::to_string_pretty(self)?;
@@ -160,6 +410,7 @@ impl Default for Config {
                 bind_address: "0.0.0.0".to_string(),
                 bind_port: 5060,
                 workers: 4,
+                logging: LoggingConfig::default(),
             },
             sip: SipConfig {
                 domain: "rustalk.local".to_string(),
@@ -172,8 +423,12 @@ impl Default for Config {
                 udp_port: Some(5060),
                 tcp_port: Some(5060),
                 tls_port: Some(5061),
+                ws_port: None,
+                wss_port: None,
                 tls_cert: None,
                 tls_key: None,
+                interfaces: Vec::new(),
+                keepalive: None,
             },
             database: None,
             teams: None,
@@ -181,6 +436,49 @@ impl Default for Config {
             codecs: Some(CodecConfig::default()),
             routing: Some(RoutingConfig::default()),
             acls: Some(crate::acl::create_default_acls()),
+            admission: None,
+            cos: None,
+            account_codes: None,
+            dial_pins: None,
+            callbacks: None,
+            screening: None,
+            no_answer: None,
+            voicemail_drop: None,
+            voicemail_retrieval: None,
+            missed_calls: None,
+            call_history: None,
+            sms: None,
+            crm: None,
+            webhooks: None,
+            fax: None,
+            snmp: None,
+            radius: None,
+            registrar: None,
+            wholesale: None,
+            quirks: None,
+            dial_strings: None,
+            schedules: None,
+            cert_expiry: None,
+            components: None,
+            supervisor: None,
+            upgrade: None,
+            nat: None,
+            domains: None,
+            pdd: None,
+            media_quality: None,
+            trunk_health: None,
+            lcr: None,
+            mwi: None,
+            presence: None,
+            locales: None,
+            quotas: None,
+            lnp: None,
+            enum_routing: None,
+            cdr: None,
+            ivr: None,
+            dialer: None,
+            transcription: None,
+            remote_console: None,
         }
     }
 }
@@ -205,4 +503,13 @@ mod tests {
         let parsed: Config = serde_json::from_str(&json).unwrap();
         assert_eq!(parsed.sip.domain, config.sip.domain);
     }
+
+    #[test]
+    fn test_components_default_on() {
+        let components: ComponentsConfig =
+            serde_json::from_str(r#"{ "edge": false, "api_bind": "127.0.0.1:9090" }"#).unwrap();
+        assert!(components.sip && components.registrar && components.api);
+        assert!(!components.edge);
+        assert_eq!(components.api_bind, "127.0.0.1:9090");
+    }
 }
@@ -0,0 +1,338 @@
+//! Configuration overrides stored in the database
+//!
+//! Nodes of a cluster share settings through the `config_overrides` table.
+//! Each row sets one value by its dotted path into the configuration, e.g.
+//! `sip.domain` or `transport.interfaces.0.advertised_address`, where a
+//! number indexes a list. Values are JSON, so `5070` is a number and `true`
+//! a boolean; anything that is not JSON is taken as a string. The overrides
+//! are merged over the JSON file when the configuration is loaded and on
+//! every reload, and each is checked against the type of the setting it
+//! names. `rustalk config set`, `get` and `unset` manage them.
+//!
+//! Settings below the `database` section cannot be overridden, as the
+//! overrides are read from that database.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::Config;
+use crate::db::Database;
+
+/// A setting overridden in the database
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigOverride {
+    /// Dotted path of the setting
+    pub key: String,
+    pub value: Value,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl ConfigOverride {
+    pub fn new(key: impl Into<String>, value: Value) -> Self {
+        Self {
+            key: key.into(),
+            value,
+            updated_at: None,
+        }
+    }
+}
+
+/// A value given on the command line: JSON when it parses, a string
+/// otherwise
+pub fn parse_value(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+/// Segments of a dotted path, refusing ones that cannot name a setting
+fn segments(key: &str) -> Result<Vec<&str>> {
+    let segments: Vec<&str> = key.split('.').collect();
+    if segments.iter().any(|s| s.is_empty()) {
+        bail!("'{}' is not a dotted setting path", key);
+    }
+    if segments[0] == "database" {
+        bail!("Settings in the database section cannot be overridden");
+    }
+    Ok(segments)
+}
+
+/// The value at `key` in `root`, if there is one
+pub fn get_path<'a>(root: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(root, |value, segment| match value {
+        Value::Object(map) => map.get(segment),
+        Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+/// Set the value at `key` in `root`, creating the objects on the way
+pub fn set_path(root: &mut Value, key: &str, value: Value) -> Result<()> {
+    let segments = segments(key)?;
+    let (last, parents) = segments.split_last().expect("split yields a segment");
+    let mut current = root;
+    for segment in parents {
+        current = child(current, segment, key)?;
+    }
+    match current {
+        Value::Array(items) => {
+            let slot = index(items, last, key)?;
+            *slot = value;
+        }
+        value_slot => {
+            if value_slot.is_null() {
+                *value_slot = Value::Object(Map::new());
+            }
+            let Value::Object(map) = value_slot else {
+                bail!("'{}' goes below a setting that is not a section", key);
+            };
+            map.insert(last.to_string(), value);
+        }
+    }
+    Ok(())
+}
+
+fn child<'a>(value: &'a mut Value, segment: &str, key: &str) -> Result<&'a mut Value> {
+    if value.is_null() {
+        *value = Value::Object(Map::new());
+    }
+    match value {
+        Value::Object(map) => Ok(map.entry(segment).or_insert(Value::Null)),
+        Value::Array(items) => index(items, segment, key),
+        _ => bail!("'{}' goes below a setting that is not a section", key),
+    }
+}
+
+fn index<'a>(items: &'a mut [Value], segment: &str, key: &str) -> Result<&'a mut Value> {
+    let position: usize = segment
+        .parse()
+        .map_err(|_| anyhow!("'{}' indexes a list with '{}'", key, segment))?;
+    let len = items.len();
+    items
+        .get_mut(position)
+        .ok_or_else(|| anyhow!("'{}' indexes past the {} item(s) of its list", key, len))
+}
+
+/// Merge `overrides` over `config`, in order
+///
+/// Fails naming the first override that does not fit the type of its
+/// setting or names no setting at all.
+pub fn apply(config: Config, overrides: &[ConfigOverride]) -> Result<Config> {
+    let mut merged = serde_json::to_value(&config)?;
+    for entry in overrides {
+        set_path(&mut merged, &entry.key, entry.value.clone())
+            .with_context(|| format!("Override {}", entry.key))?;
+        // Checked one at a time so the error names the culprit
+        let typed: Config = serde_json::from_value(merged.clone())
+            .with_context(|| format!("Override {} does not fit its setting", entry.key))?;
+        // Unknown settings are dropped when the configuration is parsed
+        let round_trip = serde_json::to_value(&typed)?;
+        if !entry.value.is_null() && get_path(&round_trip, &entry.key).is_none() {
+            bail!("Override {} names no setting", entry.key);
+        }
+    }
+    Ok(serde_json::from_value(merged)?)
+}
+
+/// Overrides stored in the database, by key
+pub async fn load(database: &Database) -> Result<Vec<ConfigOverride>> {
+    // A database not yet migrated has none
+    let rows: Vec<(String, String, i64)> = match database {
+        Database::Postgres(pool) => {
+            let exists: bool =
+                sqlx::query_scalar("SELECT to_regclass('config_overrides') IS NOT NULL")
+                    .fetch_one(pool)
+                    .await
+                    .context("checking for the config_overrides table")?;
+            if !exists {
+                return Ok(Vec::new());
+            }
+            sqlx::query_as(
+                "SELECT key, value::text, extract(epoch FROM updated_at)::BIGINT \
+                 FROM config_overrides ORDER BY key",
+            )
+            .fetch_all(pool)
+            .await
+        }
+        Database::Sqlite(pool) => {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master \
+                 WHERE type = 'table' AND name = 'config_overrides')",
+            )
+            .fetch_one(pool)
+            .await
+            .context("checking for the config_overrides table")?;
+            if !exists {
+                return Ok(Vec::new());
+            }
+            sqlx::query_as("SELECT key, value, updated_at FROM config_overrides ORDER BY key")
+                .fetch_all(pool)
+                .await
+        }
+    }
+    .context("reading configuration overrides")?;
+    rows.into_iter()
+        .map(|(key, value, updated_at)| {
+            let value = serde_json::from_str(&value)
+                .with_context(|| format!("Stored override of {} is not JSON", key))?;
+            Ok(ConfigOverride {
+                key,
+                value,
+                updated_at: DateTime::from_timestamp(updated_at, 0),
+            })
+        })
+        .collect()
+}
+
+/// Store an override, replacing the one with the same key
+pub async fn set(database: &Database, key: &str, value: &Value) -> Result<()> {
+    segments(key)?;
+    let value = value.to_string();
+    match database {
+        Database::Postgres(pool) => sqlx::query(
+            "INSERT INTO config_overrides (key, value, updated_at) VALUES ($1, $2::jsonb, now())
+             ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = now()",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await
+        .map(drop),
+        Database::Sqlite(pool) => sqlx::query(
+            "INSERT INTO config_overrides (key, value, updated_at)
+             VALUES ($1, $2, CAST(strftime('%s', 'now') AS INTEGER))
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value,
+             updated_at = excluded.updated_at",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await
+        .map(drop),
+    }
+    .with_context(|| format!("storing the override of {}", key))?;
+    Ok(())
+}
+
+/// Remove an override, returning whether there was one
+pub async fn unset(database: &Database, key: &str) -> Result<bool> {
+    const DELETE: &str = "DELETE FROM config_overrides WHERE key = $1";
+    let removed = match database {
+        Database::Postgres(pool) => sqlx::query(DELETE)
+            .bind(key)
+            .execute(pool)
+            .await
+            .map(|r| r.rows_affected()),
+        Database::Sqlite(pool) => sqlx::query(DELETE)
+            .bind(key)
+            .execute(pool)
+            .await
+            .map(|r| r.rows_affected()),
+    }
+    .with_context(|| format!("removing the override of {}", key))?;
+    Ok(removed > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("5070"), Value::from(5070));
+        assert_eq!(parse_value("true"), Value::Bool(true));
+        assert_eq!(parse_value("[\"udp\"]"), serde_json::json!(["udp"]));
+        assert_eq!(parse_value("\"42\""), Value::from("42"));
+        assert_eq!(
+            parse_value("pbx.example.com"),
+            Value::from("pbx.example.com")
+        );
+    }
+
+    #[test]
+    fn test_set_path() {
+        let mut root = serde_json::json!({ "sip": { "domain": "a" }, "nat": null, "list": [1, 2] });
+        set_path(&mut root, "sip.domain", Value::from("b")).unwrap();
+        set_path(&mut root, "nat.profiles", serde_json::json!([])).unwrap();
+        set_path(&mut root, "list.1", Value::from(3)).unwrap();
+        assert_eq!(
+            root,
+            serde_json::json!({ "sip": { "domain": "b" }, "nat": { "profiles": [] }, "list": [1, 3] })
+        );
+        assert_eq!(get_path(&root, "list.1"), Some(&Value::from(3)));
+        assert_eq!(get_path(&root, "sip.port"), None);
+
+        assert!(set_path(&mut root, "list.5", Value::Null).is_err());
+        assert!(set_path(&mut root, "sip.domain.name", Value::Null).is_err());
+        assert!(set_path(&mut root, "sip..domain", Value::Null).is_err());
+        assert!(set_path(&mut root, "database.url", Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let config = apply(
+            Config::default(),
+            &[
+                ConfigOverride::new("sip.domain", parse_value("pbx.example.com")),
+                ConfigOverride::new("server.bind_port", parse_value("5070")),
+                ConfigOverride::new("mwi", serde_json::json!({})),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.sip.domain, "pbx.example.com");
+        assert_eq!(config.server.bind_port, 5070);
+        assert!(config.mwi.is_some());
+
+        // Values are checked against the setting's type
+        let error = apply(
+            Config::default(),
+            &[ConfigOverride::new("server.bind_port", parse_value("high"))],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Override server.bind_port does not fit its setting"
+        );
+
+        let error = apply(
+            Config::default(),
+            &[ConfigOverride::new("sip.domian", parse_value("x"))],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Override sip.domian names no setting");
+    }
+
+    #[tokio::test]
+    async fn test_stored_overrides() {
+        let database = crate::db::connect(&crate::config::DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            max_connections: 1,
+            min_connections: 0,
+            auto_migrate: false,
+        })
+        .await
+        .unwrap();
+        assert!(load(&database).await.unwrap().is_empty());
+        crate::db::migrate(&database).await.unwrap();
+
+        set(&database, "sip.domain", &Value::from("a"))
+            .await
+            .unwrap();
+        set(&database, "sip.domain", &Value::from("b"))
+            .await
+            .unwrap();
+        set(&database, "server.bind_port", &Value::from(5070))
+            .await
+            .unwrap();
+        let stored = load(&database).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[0].key, "server.bind_port");
+        assert_eq!(stored[0].value, Value::from(5070));
+        assert_eq!(stored[1].value, Value::from("b"));
+        assert!(stored[1].updated_at.is_some());
+
+        assert!(unset(&database, "sip.domain").await.unwrap());
+        assert!(!unset(&database, "sip.domain").await.unwrap());
+        assert_eq!(load(&database).await.unwrap().len(), 1);
+    }
+}
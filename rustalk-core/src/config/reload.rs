@@ -0,0 +1,370 @@
+//! Runtime configuration reload
+//!
+//! Re-reads the configuration file (and database overlay) and swaps the
+//! running configuration in one step. Each reload reports which ACLs, routes,
+//! codecs and profile settings changed. Settings that only take effect at
+//! startup, such as bind addresses, are applied to the stored configuration
+//! but flagged as needing a restart.
+//!
+//! A reload is staged before it is applied: the candidate is validated and
+//! replayed against recent traffic samples, and it only replaces the running
+//! configuration when validation passes.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+use super::shadow::{shadow_compare, ShadowReport};
+use super::validate::{validate_with_references, References, ValidationIssue};
+use super::Config;
+use crate::routing::TrafficSampler;
+
+/// Kind of change between two configurations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A single changed item
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    /// Section the item belongs to (`acls`, `routes`, `codecs`, `profile`)
+    pub section: String,
+    /// ACL name, route id, codec name or profile setting
+    pub name: String,
+    pub kind: ChangeKind,
+}
+
+/// Result of a reload
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReloadReport {
+    /// Whether the candidate replaced the running configuration
+    pub applied: bool,
+    pub changes: Vec<ConfigChange>,
+    /// Changed settings that only apply after a restart
+    pub restart_required: Vec<String>,
+    /// Validation problems; any issue blocks the reload
+    pub issues: Vec<ValidationIssue>,
+    /// Decisions on recent traffic that the candidate would change
+    pub shadow: ShadowReport,
+}
+
+impl ReloadReport {
+    /// Compare two configurations
+    pub fn diff(old: &Config, new: &Config) -> Self {
+        let mut report = Self::default();
+
+        report.compare(
+            "acls",
+            keyed(old.acls.iter().flat_map(|m| &m.acls), |a| a.name.clone()),
+            keyed(new.acls.iter().flat_map(|m| &m.acls), |a| a.name.clone()),
+        );
+        report.compare(
+            "routes",
+            keyed(old.routing.iter().flat_map(|r| &r.routes), |r| r.id.clone()),
+            keyed(new.routing.iter().flat_map(|r| &r.routes), |r| r.id.clone()),
+        );
+        report.compare(
+            "codecs",
+            keyed(old.codecs.iter().flat_map(|c| &c.codecs), |c| {
+                c.name.clone()
+            }),
+            keyed(new.codecs.iter().flat_map(|c| &c.codecs), |c| {
+                c.name.clone()
+            }),
+        );
+        report.compare("profile", profile_settings(old), profile_settings(new));
+
+        for (name, old_value, new_value) in [
+            (
+                "server.bind_address",
+                json(&old.server.bind_address),
+                json(&new.server.bind_address),
+            ),
+            (
+                "server.bind_port",
+                json(&old.server.bind_port),
+                json(&new.server.bind_port),
+            ),
+            (
+                "server.workers",
+                json(&old.server.workers),
+                json(&new.server.workers),
+            ),
+            (
+                "transport.interfaces",
+                json(&old.transport.interfaces),
+                json(&new.transport.interfaces),
+            ),
+        ] {
+            if old_value != new_value {
+                report.restart_required.push(name.to_string());
+            }
+        }
+
+        report
+    }
+
+    /// No configuration changes were found
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.restart_required.is_empty()
+    }
+
+    fn compare(
+        &mut self,
+        section: &str,
+        old: BTreeMap<String, Value>,
+        new: BTreeMap<String, Value>,
+    ) {
+        for (name, value) in &new {
+            let kind = match old.get(name) {
+                None => ChangeKind::Added,
+                Some(previous) if previous != value => ChangeKind::Modified,
+                Some(_) => continue,
+            };
+            self.push(section, name, kind);
+        }
+        for name in old.keys().filter(|name| !new.contains_key(*name)) {
+            self.push(section, name, ChangeKind::Removed);
+        }
+    }
+
+    fn push(&mut self, section: &str, name: &str, kind: ChangeKind) {
+        self.changes.push(ConfigChange {
+            section: section.to_string(),
+            name: name.to_string(),
+            kind,
+        });
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn keyed<'a, T: Serialize + 'a>(
+    items: impl Iterator<Item = &'a T>,
+    key: impl Fn(&T) -> String,
+) -> BTreeMap<String, Value> {
+    items.map(|item| (key(item), json(item))).collect()
+}
+
+/// SIP and transport settings that make up the default profile
+fn profile_settings(config: &Config) -> BTreeMap<String, Value> {
+    let mut settings = BTreeMap::new();
+    for (prefix, section) in [
+        ("sip", json(&config.sip)),
+        ("transport", json(&config.transport)),
+    ] {
+        if let Value::Object(fields) = section {
+            for (field, value) in fields {
+                // Interfaces are reported separately as restart-only
+                if field != "interfaces" {
+                    settings.insert(format!("{}.{}", prefix, field), value);
+                }
+            }
+        }
+    }
+    settings
+}
+
+/// A loaded candidate configuration and its report
+#[derive(Debug, Clone)]
+pub struct StagedConfig {
+    pub config: Config,
+    pub report: ReloadReport,
+}
+
+impl StagedConfig {
+    pub fn is_valid(&self) -> bool {
+        self.report.issues.is_empty()
+    }
+}
+
+/// Holds the running configuration and reloads it from disk
+#[derive(Clone)]
+pub struct ConfigReloader {
+    path: PathBuf,
+    current: Arc<RwLock<Config>>,
+    sampler: Option<TrafficSampler>,
+}
+
+impl ConfigReloader {
+    /// Track `config`, which was loaded from `path`
+    pub fn new(path: impl Into<PathBuf>, config: Config) -> Self {
+        Self {
+            path: path.into(),
+            current: Arc::new(RwLock::new(config)),
+            sampler: None,
+        }
+    }
+
+    /// Replay these samples through candidate configurations
+    pub fn with_sampler(mut self, sampler: TrafficSampler) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The running configuration
+    pub async fn current(&self) -> Config {
+        self.current.read().await.clone()
+    }
+
+    /// Load, validate and shadow-test the configuration without applying it
+    pub async fn stage(&self, references: &References) -> Result<StagedConfig> {
+        let config = Config::from_file_with_db_overlay(&self.path)
+            .await
+            .with_context(|| format!("Failed to load {}", self.path.display()))?;
+
+        let current = self.current.read().await;
+        let mut report = ReloadReport::diff(&current, &config);
+        report.issues = validate_with_references(&config, references);
+        let samples = self
+            .sampler
+            .as_ref()
+            .map(TrafficSampler::samples)
+            .unwrap_or_default();
+        report.shadow = shadow_compare(&current, &config, &samples);
+
+        Ok(StagedConfig { config, report })
+    }
+
+    /// Swap a staged configuration in
+    pub async fn apply(&self, staged: &mut StagedConfig) -> Result<()> {
+        if !staged.is_valid() {
+            anyhow::bail!(
+                "Configuration has {} validation issues",
+                staged.report.issues.len()
+            );
+        }
+
+        *self.current.write().await = staged.config.clone();
+        staged.report.applied = true;
+
+        info!(
+            "Configuration reloaded from {}: {} changes, {} decisions changed, {} require restart",
+            self.path.display(),
+            staged.report.changes.len(),
+            staged.report.shadow.changes.len(),
+            staged.report.restart_required.len()
+        );
+        Ok(())
+    }
+
+    /// Stage the configuration and apply it when it validates
+    ///
+    /// A configuration that fails to load is an error; one that loads but
+    /// fails validation is returned unapplied with its issues. Either way
+    /// the running configuration is left untouched.
+    pub async fn reload(&self, references: &References) -> Result<StagedConfig> {
+        let mut staged = self.stage(references).await?;
+        if staged.is_valid() {
+            self.apply(&mut staged).await?;
+        }
+        Ok(staged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::{AclAction, AclRule};
+
+    #[test]
+    fn test_diff_reports_changes() {
+        let old = Config::default();
+        let mut new = Config::default();
+
+        let acls = new.acls.as_mut().unwrap();
+        acls.acls.retain(|a| a.name != "localhost");
+        acls.get_acl_mut("rfc1918").unwrap().add_rule(AclRule {
+            name: "carrier".to_string(),
+            cidr: "203.0.113.0/24".to_string(),
+            action: AclAction::Allow,
+            priority: 5,
+        });
+        new.codecs.as_mut().unwrap().disable_codec("GSM");
+        new.sip.domain = "pbx.example.com".to_string();
+        new.server.bind_port = 5080;
+
+        let report = ReloadReport::diff(&old, &new);
+        let has = |section: &str, name: &str, kind: ChangeKind| {
+            report
+                .changes
+                .iter()
+                .any(|c| c.section == section && c.name == name && c.kind == kind)
+        };
+        assert!(has("acls", "localhost", ChangeKind::Removed));
+        assert!(has("acls", "rfc1918", ChangeKind::Modified));
+        assert!(has("codecs", "GSM", ChangeKind::Modified));
+        assert!(has("profile", "sip.domain", ChangeKind::Modified));
+        assert_eq!(report.restart_required, vec!["server.bind_port"]);
+
+        assert!(ReloadReport::diff(&old, &old).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reload_swaps_config() {
+        let path = std::env::temp_dir().join(format!("rustalk_reload_{}.json", std::process::id()));
+        Config::default().save_to_file(&path).await.unwrap();
+        let reloader = ConfigReloader::new(&path, Config::default());
+        let references = References::default();
+
+        let staged = reloader.reload(&references).await.unwrap();
+        assert!(staged.report.is_empty());
+
+        let mut edited = Config::default();
+        edited.sip.domain = "pbx.example.com".to_string();
+        edited.save_to_file(&path).await.unwrap();
+        let staged = reloader.reload(&references).await.unwrap();
+        assert!(staged.report.applied);
+        assert_eq!(staged.config.sip.domain, "pbx.example.com");
+        assert_eq!(staged.report.changes.len(), 1);
+        assert_eq!(reloader.current().await.sip.domain, "pbx.example.com");
+
+        // A broken file leaves the running configuration in place
+        tokio::fs::write(&path, "{ not json").await.unwrap();
+        assert!(reloader.reload(&references).await.is_err());
+        assert_eq!(reloader.current().await.sip.domain, "pbx.example.com");
+
+        // So does one that fails validation
+        let mut invalid = Config::default();
+        invalid.sip.domain = "other.example.com".to_string();
+        invalid
+            .routing
+            .as_mut()
+            .unwrap()
+            .add_route(crate::routing::RouteRule {
+                id: "broken".to_string(),
+                name: "Broken".to_string(),
+                description: None,
+                pattern: "^(".to_string(),
+                destination: crate::routing::RouteDestination::Hangup,
+                enabled: true,
+                priority: 1,
+                conditions: None,
+                action: crate::routing::RouteAction::Reject,
+                continue_on_match: false,
+                on_failure: Vec::new(),
+            });
+        invalid.save_to_file(&path).await.unwrap();
+        let staged = reloader.reload(&references).await.unwrap();
+        assert!(!staged.report.applied);
+        assert_eq!(staged.report.issues.len(), 1);
+        assert_eq!(reloader.current().await.sip.domain, "pbx.example.com");
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
@@ -0,0 +1,189 @@
+//! JSON Schema of the configuration
+//!
+//! The schema is generated from the configuration types themselves, so it
+//! always describes what this build accepts. Its definitions and properties
+//! come out sorted, so the same build always exports the same document and
+//! tooling can diff it between releases. `rustalk config schema` and
+//! `GET /api/v1/schema/config` export it.
+//!
+//! Settings the configuration does not know are dropped silently when it is
+//! parsed, so a misspelt key simply has no effect. [`unknown_settings`]
+//! walks a configuration document against the schema and names them.
+
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+use serde_json::{Map, Value};
+
+use super::Config;
+
+/// The JSON Schema (draft 7) of the configuration file
+pub fn config_schema() -> RootSchema {
+    SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Config>()
+}
+
+/// Dotted paths of the settings in `document` that the configuration does
+/// not know, sorted
+pub fn unknown_settings(document: &Value) -> Vec<String> {
+    let schema = serde_json::to_value(config_schema()).unwrap_or(Value::Null);
+    let empty = Map::new();
+    let definitions = schema
+        .get("definitions")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let mut unknown = Vec::new();
+    walk(definitions, &[&schema], document, "", &mut unknown);
+    unknown
+}
+
+/// Check `value` against every schema it may match
+///
+/// A value matching any of several alternatives may use the settings of
+/// any of them; a key is unknown only if none defines it.
+fn walk(
+    definitions: &Map<String, Value>,
+    schemas: &[&Value],
+    value: &Value,
+    path: &str,
+    unknown: &mut Vec<String>,
+) {
+    let mut candidates = Vec::new();
+    for schema in schemas {
+        expand(definitions, schema, &mut candidates);
+    }
+    // A schema saying nothing of the value's shape accepts anything
+    if candidates.iter().any(|schema| is_open(schema)) {
+        return;
+    }
+
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let mut known = false;
+                let mut children = Vec::new();
+                for schema in &candidates {
+                    if let Some(property) = schema.get("properties").and_then(|p| p.get(key)) {
+                        known = true;
+                        children.push(property);
+                    } else if let Some(extra) = schema
+                        .get("additionalProperties")
+                        .filter(|extra| extra.is_object())
+                    {
+                        known = true;
+                        children.push(extra);
+                    }
+                }
+                let child_path = join(path, key);
+                if known {
+                    walk(definitions, &children, child, &child_path, unknown);
+                } else {
+                    unknown.push(child_path);
+                }
+            }
+        }
+        Value::Array(items) => {
+            let children: Vec<&Value> = candidates
+                .iter()
+                .filter_map(|schema| schema.get("items"))
+                .collect();
+            if children.is_empty() {
+                return;
+            }
+            for (position, item) in items.iter().enumerate() {
+                let child_path = join(path, &position.to_string());
+                walk(definitions, &children, item, &child_path, unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collect `schema` and the alternatives and parts it is made of, with
+/// references resolved
+fn expand<'a>(definitions: &'a Map<String, Value>, schema: &'a Value, out: &mut Vec<&'a Value>) {
+    if let Some(name) = schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|r| r.strip_prefix("#/definitions/"))
+    {
+        if let Some(definition) = definitions.get(name) {
+            expand(definitions, definition, out);
+        }
+        return;
+    }
+    let mut composed = false;
+    for keyword in ["allOf", "anyOf", "oneOf"] {
+        for part in schema
+            .get(keyword)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            composed = true;
+            expand(definitions, part, out);
+        }
+    }
+    if !composed || schema.get("properties").is_some() {
+        out.push(schema);
+    }
+}
+
+fn is_open(schema: &Value) -> bool {
+    match schema {
+        Value::Bool(accepts) => *accepts,
+        Value::Object(map) => ![
+            "type",
+            "properties",
+            "additionalProperties",
+            "items",
+            "enum",
+        ]
+        .iter()
+        .any(|keyword| map.contains_key(*keyword)),
+        _ => false,
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_is_deterministic() {
+        let first = serde_json::to_string(&config_schema()).unwrap();
+        let second = serde_json::to_string(&config_schema()).unwrap();
+        assert_eq!(first, second);
+
+        let schema = serde_json::to_value(config_schema()).unwrap();
+        assert_eq!(schema["title"], "Config");
+        assert!(schema["properties"]["sip"].is_object());
+        assert!(schema["definitions"]["SipConfig"]["properties"]["domain"].is_object());
+    }
+
+    #[test]
+    fn test_unknown_settings() {
+        // Everything the configuration writes, it knows
+        let document = serde_json::to_value(Config::default()).unwrap();
+        assert_eq!(unknown_settings(&document), Vec::<String>::new());
+
+        let mut document = document;
+        document["sip"]["domian"] = Value::from("pbx.example.com");
+        document["nat"] = serde_json::json!({
+            "profiles": [{ "name": "office", "rewrite_contact": "detect", "rewrite_cotnact": true }]
+        });
+        document["colour"] = Value::from("blue");
+        assert_eq!(
+            unknown_settings(&document),
+            vec!["colour", "nat.profiles.0.rewrite_cotnact", "sip.domian"]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Shadow evaluation of candidate configurations
+//!
+//! Replays recent traffic samples through the running and the candidate
+//! routing and ACL configuration, reporting every decision that would
+//! change. Each sample is evaluated at the time it was recorded so time
+//! conditions behave as they did for the real call.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use super::Config;
+use crate::routing::matcher::FixedTimeProvider;
+use crate::routing::{CallContext, ConditionMatcher, RouteEvaluator, TrafficSample};
+use crate::schedules::ScheduleDirectory;
+
+/// A decision that differs between the running and candidate configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecisionChange {
+    pub sample: TrafficSample,
+    /// `route`, or `acl:<name>` for an ACL decision
+    pub decision: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// Outcome of replaying samples through both configurations
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShadowReport {
+    /// Number of samples replayed
+    pub samples: usize,
+    pub changes: Vec<DecisionChange>,
+}
+
+/// Replay `samples` through `current` and `candidate`
+pub fn shadow_compare(
+    current: &Config,
+    candidate: &Config,
+    samples: &[TrafficSample],
+) -> ShadowReport {
+    let acl_names: BTreeSet<&str> = current
+        .acls
+        .iter()
+        .chain(&candidate.acls)
+        .flat_map(|m| &m.acls)
+        .map(|a| a.name.as_str())
+        .collect();
+
+    let mut report = ShadowReport {
+        samples: samples.len(),
+        changes: Vec::new(),
+    };
+
+    for sample in samples {
+        let before = route_decision(current, sample);
+        let after = route_decision(candidate, sample);
+        if before != after {
+            report.changes.push(DecisionChange {
+                sample: sample.clone(),
+                decision: "route".to_string(),
+                before,
+                after,
+            });
+        }
+
+        let Some(ip) = sample.source_ip else {
+            continue;
+        };
+        for name in &acl_names {
+            let before = acl_decision(current, name, ip);
+            let after = acl_decision(candidate, name, ip);
+            if before != after {
+                report.changes.push(DecisionChange {
+                    sample: sample.clone(),
+                    decision: format!("acl:{}", name),
+                    before,
+                    after,
+                });
+            }
+        }
+    }
+
+    report
+}
+
+fn route_decision(config: &Config, sample: &TrafficSample) -> String {
+    let Some(routing) = &config.routing else {
+        return "no route".to_string();
+    };
+    let schedules = ScheduleDirectory::new(config.schedules.clone().unwrap_or_default());
+    let matcher =
+        ConditionMatcher::with_time_provider(Arc::new(FixedTimeProvider(sample.timestamp)))
+            .with_schedules(Arc::new(schedules));
+    let evaluator = RouteEvaluator::with_matcher(routing.clone(), Arc::new(matcher));
+    let context = CallContext {
+        caller_id: sample.caller_id.clone(),
+        destination: sample.destination.clone(),
+        enum_uri: None,
+        domain: None,
+    };
+
+    match evaluator.evaluate(&context) {
+        Some(m) => format!("{} -> {:?} ({:?})", m.route_id, m.destination, m.action),
+        None => "no route".to_string(),
+    }
+}
+
+fn acl_decision(config: &Config, name: &str, ip: std::net::IpAddr) -> String {
+    let acl = config.acls.as_ref().and_then(|m| m.get_acl(name));
+    match acl.map(|acl| acl.is_allowed(ip)) {
+        None => "absent".to_string(),
+        Some(Ok(true)) => "allow".to_string(),
+        Some(Ok(false)) => "deny".to_string(),
+        Some(Err(e)) => format!("error: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::{AclAction, AclRule};
+    use crate::routing::{RouteAction, RouteDestination, RouteRule};
+    use chrono::Utc;
+
+    fn sample(destination: &str, ip: &str) -> TrafficSample {
+        TrafficSample {
+            caller_id: "5551234".to_string(),
+            destination: destination.to_string(),
+            source_ip: Some(ip.parse().unwrap()),
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_shadow_compare_reports_changed_decisions() {
+        let current = Config::default();
+        let mut candidate = Config::default();
+
+        candidate.routing.as_mut().unwrap().add_route(RouteRule {
+            id: "local".to_string(),
+            name: "Local".to_string(),
+            description: None,
+            pattern: "^1\\d{3}$".to_string(),
+            destination: RouteDestination::Extension("1000".to_string()),
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        });
+        candidate
+            .acls
+            .as_mut()
+            .unwrap()
+            .get_acl_mut("rfc1918")
+            .unwrap()
+            .add_rule(AclRule {
+                name: "block-lab".to_string(),
+                cidr: "10.9.0.0/16".to_string(),
+                action: AclAction::Deny,
+                priority: 1,
+            });
+
+        let samples = vec![sample("1001", "10.9.1.1"), sample("5550000", "192.168.1.1")];
+        let report = shadow_compare(&current, &candidate, &samples);
+
+        assert_eq!(report.samples, 2);
+        assert_eq!(report.changes.len(), 2);
+        assert_eq!(report.changes[0].decision, "route");
+        assert_eq!(report.changes[0].before, "no route");
+        assert!(report.changes[0].after.starts_with("local ->"));
+        assert_eq!(report.changes[1].decision, "acl:rfc1918");
+        assert_eq!(report.changes[1].before, "allow");
+        assert_eq!(report.changes[1].after, "deny");
+
+        assert!(shadow_compare(&current, &current, &samples)
+            .changes
+            .is_empty());
+    }
+}
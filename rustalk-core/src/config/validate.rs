@@ -0,0 +1,2275 @@
+//! Configuration validation
+//!
+//! Catches mistakes that would otherwise only show up as calls failing to
+//! route: patterns that do not compile, malformed conditions and CIDRs,
+//! duplicate identifiers and routes pointing at destinations that do not
+//! exist.
+
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use super::Config;
+use crate::account_codes::AccountCodeConfig;
+use crate::acl::matches_cidr;
+use crate::admission::AdmissionConfig;
+use crate::console::{is_password_hash, RemoteConsoleConfig, COMMANDS};
+use crate::cos::CosConfig;
+use crate::dial_pin::DialPinConfig;
+use crate::dial_string::DialStringConfig;
+use crate::dialer::DialerConfig;
+use crate::domains::DomainConfig;
+use crate::fax::FaxConfig;
+use crate::lcr::LcrConfig;
+use crate::lnp::LnpProvider;
+use crate::presence::PresenceConfig;
+use crate::quirks::{Quirk, QuirksConfig};
+use crate::quotas::QuotaConfig;
+use crate::radius::RadiusConfig;
+use crate::registrar::RegistrarConfig;
+use crate::routing::matcher::parse_time;
+use crate::routing::{
+    DestinationList, FailoverPolicy, RouteCondition, RouteDestination, RouteRule, RoutingConfig,
+};
+use crate::schedules::Schedule;
+use crate::sms::{SmsConfig, SmsProviderConfig};
+use crate::snmp::ber::Oid;
+use crate::snmp::SnmpConfig;
+use crate::transcription::{ConsentMode, TranscriptionConfig};
+use crate::trunk_health::TrunkHealthConfig;
+use crate::webhooks::WebhookConfig;
+use crate::wholesale::WholesaleConfig;
+
+/// A problem found in a configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationIssue {
+    /// Section the problem is in (`acls`, `routes`, `codecs`, `cos`,
+    /// `account_codes`, `dial_pins`, `callbacks`, `voicemail_drop`, `sms`,
+    /// `crm`, `lnp`, `cdr`, `ivr`, `dialer`, `transcription`, `webhooks`,
+    /// `fax`, `snmp`, `radius`, `registrar`, `wholesale`, `quirks`,
+    /// `dial_strings`, `admission`, `schedules`, `remote_console`, `quotas`,
+    /// `domains`, `trunk_health`, `lcr`, `presence`, `routing`,
+    /// `route_tests`)
+    pub section: String,
+    /// ACL name, route id, codec name, COS profile, account code, PIN owner,
+    /// DID, test case or setting
+    pub name: String,
+    pub message: String,
+}
+
+/// Known destinations that routes may refer to
+///
+/// Sets left empty are not checked, so callers only fill in what they know.
+#[derive(Debug, Clone, Default)]
+pub struct References {
+    pub extensions: HashSet<String>,
+    pub trunks: HashSet<String>,
+    pub ring_groups: HashSet<String>,
+    pub mailboxes: HashSet<String>,
+    pub groups: HashSet<String>,
+    pub schedules: HashSet<String>,
+}
+
+/// Validate a configuration without reference checks
+pub fn validate(config: &Config) -> Vec<ValidationIssue> {
+    validate_with_references(config, &References::default())
+}
+
+/// Validate a configuration, checking route destinations against `references`
+pub fn validate_with_references(config: &Config, references: &References) -> Vec<ValidationIssue> {
+    let mut issues = Issues::default();
+
+    if let Some(acls) = &config.acls {
+        let mut names = HashSet::new();
+        for acl in &acls.acls {
+            if !names.insert(acl.name.as_str()) {
+                issues.push("acls", &acl.name, "duplicate ACL name".to_string());
+            }
+            for rule in &acl.rules {
+                let probe = rule
+                    .cidr
+                    .split('/')
+                    .next()
+                    .and_then(|addr| addr.parse::<IpAddr>().ok())
+                    .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+                if let Err(e) = matches_cidr(probe, &rule.cidr) {
+                    issues.push("acls", &acl.name, format!("rule '{}': {}", rule.name, e));
+                }
+            }
+        }
+    }
+
+    if let Some(database) = &config.database {
+        if !crate::db::is_postgres_url(&database.url) && !crate::db::is_sqlite_url(&database.url) {
+            issues.push(
+                "database",
+                "url",
+                "must be a postgres:// or sqlite:// URL".to_string(),
+            );
+        }
+    }
+
+    if let Some(routing) = &config.routing {
+        // Schedules defined alongside the routes are always known
+        let mut references = references.clone();
+        references
+            .schedules
+            .extend(config.schedules.iter().flatten().map(|s| s.id.clone()));
+        validate_failover(&routing.failover, "routing", "failover", &mut issues);
+        if routing.max_attempts == Some(0) {
+            issues.push("routing", "max_attempts", "must be at least 1".to_string());
+        }
+        let mut ids = HashSet::new();
+        for route in &routing.routes {
+            if !ids.insert(route.id.as_str()) {
+                issues.push("routes", &route.id, "duplicate route id".to_string());
+            }
+            validate_route(route, &references, &mut issues);
+        }
+        let mut names = HashSet::new();
+        for case in &routing.tests {
+            if !names.insert(case.name.as_str()) {
+                issues.push("route_tests", &case.name, "duplicate test name".to_string());
+            }
+            if let Some(expected) = &case.expected_route {
+                if !ids.contains(expected.as_str()) {
+                    issues.push(
+                        "route_tests",
+                        &case.name,
+                        format!("expected route '{}' does not exist", expected),
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(codecs) = &config.codecs {
+        let mut names = HashSet::new();
+        for codec in &codecs.codecs {
+            if !names.insert(codec.name.as_str()) {
+                issues.push("codecs", &codec.name, "duplicate codec name".to_string());
+            }
+            if codec.payload_type > 127 {
+                issues.push(
+                    "codecs",
+                    &codec.name,
+                    format!("payload type {} is above 127", codec.payload_type),
+                );
+            }
+        }
+    }
+
+    if let Some(cos) = &config.cos {
+        validate_cos(cos, &mut issues);
+    }
+    if let Some(pins) = &config.dial_pins {
+        validate_dial_pins(pins, &mut issues);
+    }
+    if let Some(codes) = &config.account_codes {
+        validate_account_codes(codes, &mut issues);
+    }
+    if let Some(callbacks) = &config.callbacks {
+        let feature_code = &callbacks.feature_code;
+        let clashes = config
+            .account_codes
+            .as_ref()
+            .is_some_and(|codes| codes.feature_code.starts_with(feature_code.as_str()));
+        if feature_code.is_empty() || clashes {
+            issues.push(
+                "callbacks",
+                feature_code,
+                "feature code must be non-empty and not prefix the account code feature code"
+                    .to_string(),
+            );
+        }
+    }
+    if let Some(drop) = &config.voicemail_drop {
+        let prefix = &drop.prefix;
+        // Callback requests are handled first and would swallow the prefix
+        let clashes = config
+            .callbacks
+            .as_ref()
+            .is_some_and(|callbacks| prefix.starts_with(callbacks.feature_code.as_str()));
+        if prefix.is_empty() || clashes {
+            issues.push(
+                "voicemail_drop",
+                prefix,
+                "prefix must be non-empty and not start with the callback feature code".to_string(),
+            );
+        }
+    }
+
+    if let Some(sms) = &config.sms {
+        validate_sms(sms, &mut issues);
+    }
+
+    if let Some(crm) = &config.crm {
+        if !crm.url.starts_with("http://") && !crm.url.starts_with("https://") {
+            issues.push("crm", "url", "URL must be http or https".to_string());
+        }
+    }
+
+    if let Some(http) = config.cdr.as_ref().and_then(|cdr| cdr.http.as_ref()) {
+        if !http.url.starts_with("http://") && !http.url.starts_with("https://") {
+            issues.push("cdr", "http.url", "URL must be http or https".to_string());
+        }
+    }
+
+    if let Some(LnpProvider::Http { url, .. }) = config.lnp.as_ref().map(|lnp| &lnp.provider) {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            issues.push("lnp", "url", "URL must be http or https".to_string());
+        } else if !url.contains("{number}") {
+            issues.push("lnp", "url", "URL must contain {number}".to_string());
+        }
+    }
+
+    if let Some(ivr) = &config.ivr {
+        let mut ids = HashSet::new();
+        for flow in &ivr.flows {
+            if !ids.insert(flow.id.as_str()) {
+                issues.push(
+                    "ivr",
+                    &flow.id,
+                    "flow id is used more than once".to_string(),
+                );
+            }
+            for issue in crate::ivr::validate(flow) {
+                issues.push("ivr", &flow.id, issue.to_string());
+            }
+        }
+    }
+
+    if let Some(dialer) = &config.dialer {
+        // Schedules defined alongside the campaigns are always known
+        let mut references = references.clone();
+        references
+            .schedules
+            .extend(config.schedules.iter().flatten().map(|s| s.id.clone()));
+        validate_dialer(dialer, &references, &mut issues);
+    }
+
+    if let Some(transcription) = &config.transcription {
+        let routes: HashSet<&str> = config
+            .routing
+            .iter()
+            .flat_map(|r| &r.routes)
+            .map(|r| r.id.as_str())
+            .collect();
+        validate_transcription(transcription, &routes, references, &mut issues);
+    }
+
+    if let Some(webhooks) = &config.webhooks {
+        validate_webhooks(webhooks, &mut issues);
+    }
+
+    if let Some(console) = &config.remote_console {
+        validate_remote_console(console, &mut issues);
+    }
+
+    if let Some(quotas) = &config.quotas {
+        validate_quotas(quotas, &mut issues);
+    }
+
+    if let Some(domains) = &config.domains {
+        validate_domains(domains, config.routing.as_ref(), &mut issues);
+    }
+
+    if let Some(fax) = &config.fax {
+        validate_fax(fax, &mut issues);
+    }
+
+    if let Some(snmp) = &config.snmp {
+        validate_snmp(snmp, &mut issues);
+    }
+
+    if let Some(radius) = &config.radius {
+        validate_radius(radius, &mut issues);
+    }
+
+    if let Some(registrar) = &config.registrar {
+        validate_registrar(registrar, &mut issues);
+    }
+
+    if let Some(wholesale) = &config.wholesale {
+        validate_wholesale(wholesale, &mut issues);
+    }
+
+    if let Some(quirks) = &config.quirks {
+        validate_quirks(quirks, &mut issues);
+    }
+
+    if let Some(trunk_health) = &config.trunk_health {
+        validate_trunk_health(trunk_health, references, &mut issues);
+    }
+
+    if let Some(lcr) = &config.lcr {
+        validate_lcr(lcr, references, &mut issues);
+    }
+
+    if let Some(presence) = &config.presence {
+        validate_presence(presence, references, &mut issues);
+    }
+
+    if let Some(admission) = &config.admission {
+        validate_admission(admission, &mut issues);
+    }
+
+    if let Some(dial_strings) = &config.dial_strings {
+        validate_dial_strings(dial_strings, &mut issues);
+    }
+
+    if let Some(schedules) = &config.schedules {
+        validate_schedules(schedules, &mut issues);
+    }
+
+    issues.0
+}
+
+fn validate_cos(cos: &CosConfig, issues: &mut Issues) {
+    let profiles: HashSet<&str> = cos.profiles.iter().map(|p| p.name.as_str()).collect();
+    let mut check_profile = |name: &str, used_by: &str| {
+        if !profiles.contains(name) {
+            issues.push("cos", name, format!("unknown profile used by {}", used_by));
+        }
+    };
+    check_profile(&cos.default_profile, "default_profile");
+    for (extension, profile) in &cos.extensions {
+        check_profile(profile, &format!("extension {}", extension));
+    }
+    for pin in &cos.override_pins {
+        check_profile(&pin.profile, "an override PIN");
+    }
+    for pattern in &cos.patterns {
+        if let Err(e) = Regex::new(&pattern.pattern) {
+            issues.push(
+                "cos",
+                &pattern.class.to_string(),
+                format!("pattern '{}': {}", pattern.pattern, e),
+            );
+        }
+    }
+}
+
+fn validate_dial_pins(config: &DialPinConfig, issues: &mut Issues) {
+    if !config.extensions.is_empty() && config.pins.is_empty() {
+        issues.push(
+            "dial_pins",
+            "pins",
+            "no PINs configured, so PIN-protected extensions cannot dial out".to_string(),
+        );
+    }
+    if config.max_failures > 0
+        && (config.failure_window_seconds == 0 || config.lockout_seconds == 0)
+    {
+        issues.push(
+            "dial_pins",
+            "max_failures",
+            "failure_window_seconds and lockout_seconds must be set to lock extensions out"
+                .to_string(),
+        );
+    }
+    let mut seen = HashSet::new();
+    for pin in &config.pins {
+        let name = pin.user.as_deref().unwrap_or("shared");
+        if pin.pin.is_empty() || !pin.pin.chars().all(|c| c.is_ascii_digit()) {
+            issues.push("dial_pins", name, "PIN must be digits".to_string());
+        }
+        if !seen.insert(pin.pin.as_str()) {
+            issues.push("dial_pins", name, "PIN is already in use".to_string());
+        }
+    }
+}
+
+fn validate_sms(config: &SmsConfig, issues: &mut Issues) {
+    let missing = match &config.provider {
+        SmsProviderConfig::Twilio(twilio) => {
+            twilio.account_sid.is_empty() || twilio.auth_token.is_empty()
+        }
+        SmsProviderConfig::Smpp(smpp) => smpp.host.is_empty() || smpp.system_id.is_empty(),
+    };
+    if missing {
+        issues.push(
+            "sms",
+            "provider",
+            "provider credentials are incomplete".to_string(),
+        );
+    }
+    let mut seen = HashSet::new();
+    for (did, extension) in &config.dids {
+        let digits: String = did.chars().filter(|c| c.is_ascii_digit()).collect();
+        if digits.is_empty() || extension.is_empty() {
+            issues.push(
+                "sms",
+                did,
+                "DID must be a number mapped to an extension".to_string(),
+            );
+        } else if !seen.insert(digits) {
+            issues.push("sms", did, "DID is listed more than once".to_string());
+        }
+    }
+}
+
+fn validate_webhooks(config: &WebhookConfig, issues: &mut Issues) {
+    let mut seen = HashSet::new();
+    for endpoint in &config.endpoints {
+        let name = endpoint.name.as_str();
+        if !seen.insert(name) {
+            issues.push("webhooks", name, "name is already in use".to_string());
+        }
+        if !endpoint.url.starts_with("http://") && !endpoint.url.starts_with("https://") {
+            issues.push("webhooks", name, "URL must be http or https".to_string());
+        }
+        for (field, path) in &endpoint.fields {
+            if path.is_empty() || path.split('.').any(str::is_empty) {
+                issues.push(
+                    "webhooks",
+                    name,
+                    format!("field '{}' has an invalid event path '{}'", field, path),
+                );
+            }
+        }
+    }
+}
+
+fn validate_fax(config: &FaxConfig, issues: &mut Issues) {
+    if config.smtp.host.is_empty() || !config.smtp.from.contains('@') {
+        issues.push(
+            "fax",
+            "smtp",
+            "SMTP host and sender address are required".to_string(),
+        );
+    }
+    for (did, email) in &config.dids {
+        if !did.chars().any(|c| c.is_ascii_digit()) || !email.contains('@') {
+            issues.push(
+                "fax",
+                did,
+                "DID must be a number mapped to an email address".to_string(),
+            );
+        }
+    }
+}
+
+fn validate_snmp(config: &SnmpConfig, issues: &mut Issues) {
+    if config.enterprise_oid.parse::<Oid>().is_err() {
+        issues.push("snmp", "enterprise_oid", "must be a dotted OID".to_string());
+    }
+    if config.engine_id.is_some() {
+        if let Err(e) = config.engine_id() {
+            issues.push("snmp", "engine_id", e.to_string());
+        }
+    }
+    if config.community.is_none() && config.users.is_empty() {
+        issues.push(
+            "snmp",
+            "community",
+            "a v2c community or at least one v3 user is required".to_string(),
+        );
+    }
+    for user in &config.users {
+        if user.priv_password.is_some() && user.auth_password.is_none() {
+            issues.push(
+                "snmp",
+                &user.name,
+                "privacy requires an authentication password".to_string(),
+            );
+        }
+        let passwords = [&user.auth_password, &user.priv_password];
+        if passwords.into_iter().flatten().any(|p| p.len() < 8) {
+            issues.push(
+                "snmp",
+                &user.name,
+                "passwords must be at least 8 characters".to_string(),
+            );
+        }
+    }
+}
+
+fn validate_radius(config: &RadiusConfig, issues: &mut Issues) {
+    if config.secret.is_empty() {
+        issues.push("radius", "secret", "shared secret is required".to_string());
+    }
+    let servers = [
+        ("auth_server", &config.auth_server),
+        ("accounting_server", &config.accounting_server),
+    ];
+    if servers.iter().all(|(_, server)| server.is_none()) {
+        issues.push(
+            "radius",
+            "auth_server",
+            "an authentication or accounting server is required".to_string(),
+        );
+    }
+    for (name, server) in servers {
+        let valid = server.as_deref().is_none_or(|server| {
+            server
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+        });
+        if !valid {
+            issues.push("radius", name, "server must be host:port".to_string());
+        }
+    }
+}
+
+fn validate_registrar(config: &RegistrarConfig, issues: &mut Issues) {
+    if config.max_expires == 0 {
+        issues.push(
+            "registrar",
+            "max_expires",
+            "must be at least 1 second".to_string(),
+        );
+    }
+    let mut usernames = HashSet::new();
+    for credential in &config.credentials {
+        let username = credential.username.as_str();
+        if username.is_empty() || !usernames.insert(username) {
+            issues.push(
+                "registrar",
+                username,
+                "username must be non-empty and unique".to_string(),
+            );
+        }
+        if credential.password.is_empty() {
+            issues.push("registrar", username, "password is required".to_string());
+        }
+    }
+}
+
+fn validate_wholesale(config: &WholesaleConfig, issues: &mut Issues) {
+    let mut decks = HashSet::new();
+    for deck in &config.rate_decks {
+        if !decks.insert(deck.name.as_str()) {
+            issues.push("wholesale", &deck.name, "duplicate rate deck".to_string());
+        }
+        for rate in &deck.rates {
+            let digits = rate.prefix.trim_start_matches('+');
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                issues.push(
+                    "wholesale",
+                    &deck.name,
+                    format!("rate prefix '{}' must be digits", rate.prefix),
+                );
+            }
+            if rate.per_minute < 0.0 || rate.initial_seconds == 0 || rate.increment_seconds == 0 {
+                issues.push(
+                    "wholesale",
+                    &deck.name,
+                    format!(
+                        "rate for '{}' has a negative price or zero period",
+                        rate.prefix
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut peers = HashSet::new();
+    for peer in &config.peers {
+        if !peers.insert(peer.name.as_str()) {
+            issues.push("wholesale", &peer.name, "duplicate peer".to_string());
+        }
+        if peer.addresses.is_empty() {
+            issues.push(
+                "wholesale",
+                &peer.name,
+                "at least one address is required".to_string(),
+            );
+        }
+        for address in &peer.addresses {
+            let valid = address.parse::<SocketAddr>().is_ok()
+                || matches_cidr(IpAddr::V4(Ipv4Addr::UNSPECIFIED), address).is_ok();
+            if !valid {
+                issues.push(
+                    "wholesale",
+                    &peer.name,
+                    format!("'{}' is not an IP, CIDR or ip:port", address),
+                );
+            }
+        }
+        if let Some(deck) = &peer.rate_deck {
+            if !decks.contains(deck.as_str()) {
+                issues.push(
+                    "wholesale",
+                    &peer.name,
+                    format!("unknown rate deck '{}'", deck),
+                );
+            }
+        }
+    }
+}
+
+fn validate_quirks(config: &QuirksConfig, issues: &mut Issues) {
+    let mut profiles = HashSet::new();
+    for profile in &config.profiles {
+        if !profiles.insert(profile.name.as_str()) {
+            issues.push("quirks", &profile.name, "duplicate profile".to_string());
+        }
+        if config.resolve(&profile.name).is_none() {
+            issues.push(
+                "quirks",
+                &profile.name,
+                "extends an unknown profile or itself".to_string(),
+            );
+        }
+    }
+    let mut trunks = HashSet::new();
+    for binding in &config.trunks {
+        if !trunks.insert(binding.trunk.to_ascii_lowercase()) {
+            issues.push("quirks", &binding.trunk, "trunk bound twice".to_string());
+        }
+        if config.profile(&binding.profile).is_none() {
+            issues.push(
+                "quirks",
+                &binding.trunk,
+                format!("unknown profile '{}'", binding.profile),
+            );
+        } else if config
+            .resolve(&binding.profile)
+            .is_some_and(|q| q.contains(&Quirk::FromAuthUser))
+            && binding.auth_user.is_none()
+        {
+            issues.push(
+                "quirks",
+                &binding.trunk,
+                "profile rewrites From but no auth_user is set".to_string(),
+            );
+        }
+    }
+}
+
+fn validate_presence(config: &PresenceConfig, references: &References, issues: &mut Issues) {
+    // Lists are subscribed to by URI, so two devices must not share one
+    let mut users = HashSet::new();
+    for list in &config.blf_lists {
+        if list.device.trim().is_empty() {
+            issues.push("presence", "blf_lists", "list without a device".to_string());
+            continue;
+        }
+        if !users.insert(list.user()) {
+            issues.push(
+                "presence",
+                &list.device,
+                format!("another list is served as {}", list.user()),
+            );
+        }
+        if list.extensions.is_empty() {
+            issues.push(
+                "presence",
+                &list.device,
+                "no extensions to watch".to_string(),
+            );
+        }
+        let mut extensions = HashSet::new();
+        for extension in &list.extensions {
+            if !extensions.insert(extension.as_str()) {
+                issues.push(
+                    "presence",
+                    &list.device,
+                    format!("extension '{}' is listed twice", extension),
+                );
+            } else if !references.extensions.is_empty()
+                && !references.extensions.contains(extension)
+            {
+                issues.push(
+                    "presence",
+                    &list.device,
+                    format!("extension '{}' does not exist", extension),
+                );
+            }
+        }
+    }
+}
+
+fn validate_trunk_health(config: &TrunkHealthConfig, references: &References, issues: &mut Issues) {
+    for (setting, value) in [
+        ("down_after", config.down_after),
+        ("up_after", config.up_after),
+    ] {
+        if value == 0 {
+            issues.push("trunk_health", setting, "must be at least 1".to_string());
+        }
+    }
+    let mut names = HashSet::new();
+    for trunk in &config.trunks {
+        if !names.insert(trunk.name.as_str()) {
+            issues.push(
+                "trunk_health",
+                &trunk.name,
+                "trunk monitored twice".to_string(),
+            );
+        }
+        if !references.trunks.is_empty() && !references.trunks.contains(&trunk.name) {
+            issues.push(
+                "trunk_health",
+                &trunk.name,
+                "trunk does not exist".to_string(),
+            );
+        }
+        let degraded_after = trunk.degraded_after.unwrap_or(config.degraded_after);
+        let down_after = trunk.down_after.unwrap_or(config.down_after);
+        if down_after < degraded_after {
+            issues.push(
+                "trunk_health",
+                &trunk.name,
+                format!(
+                    "down after {} failure(s), before it is degraded after {}",
+                    down_after, degraded_after
+                ),
+            );
+        }
+    }
+}
+
+fn validate_lcr(config: &LcrConfig, references: &References, issues: &mut Issues) {
+    if config.reference_seconds == 0 {
+        issues.push("lcr", "reference_seconds", "must be at least 1".to_string());
+    }
+    let mut ids = HashSet::new();
+    for rate in &config.rates {
+        if !ids.insert(rate.id.as_str()) {
+            issues.push("lcr", &rate.id, "duplicate rate id".to_string());
+        }
+        if rate.rate_per_minute < 0.0 || rate.connection_fee < 0.0 {
+            issues.push("lcr", &rate.id, "rates cannot be negative".to_string());
+        }
+        if rate.end_date.is_some_and(|end| end < rate.effective_date) {
+            issues.push("lcr", &rate.id, "ends before it takes effect".to_string());
+        }
+        if let Some(trunk) = &rate.trunk {
+            if !references.trunks.is_empty() && !references.trunks.contains(trunk) {
+                issues.push("lcr", &rate.id, format!("trunk '{}' does not exist", trunk));
+            }
+        }
+    }
+}
+
+fn validate_dial_strings(config: &DialStringConfig, issues: &mut Issues) {
+    let mut trunks = HashSet::new();
+    for template in &config.trunks {
+        if !trunks.insert(template.trunk.to_ascii_lowercase()) {
+            issues.push(
+                "dial_strings",
+                &template.trunk,
+                "trunk bound twice".to_string(),
+            );
+        }
+        if let Err(e) = template.validate() {
+            issues.push("dial_strings", &template.trunk, e.to_string());
+        }
+    }
+}
+
+fn validate_dialer(dialer: &DialerConfig, references: &References, issues: &mut Issues) {
+    let mut ids = HashSet::new();
+    for campaign in &dialer.campaigns {
+        let id = campaign.id.as_str();
+        if !ids.insert(id) {
+            issues.push("dialer", id, "duplicate campaign id".to_string());
+        }
+        if campaign.pacing.calls_per_minute == 0 || campaign.pacing.max_concurrent == 0 {
+            issues.push(
+                "dialer",
+                id,
+                "pacing must allow at least one call".to_string(),
+            );
+        }
+        if campaign.max_attempts == 0 {
+            issues.push("dialer", id, "max_attempts must be at least 1".to_string());
+        }
+        for hours in &campaign.calling_hours {
+            for time in [&hours.start_time, &hours.end_time] {
+                if let Err(e) = parse_time(time) {
+                    issues.push("dialer", id, format!("time '{}': {}", time, e));
+                }
+            }
+            if let Some(day) = hours.days.iter().find(|d| !(1..=7).contains(*d)) {
+                issues.push("dialer", id, format!("day of week {} is outside 1-7", day));
+            }
+        }
+        if let Some(schedule) = &campaign.schedule {
+            if !references.schedules.is_empty() && !references.schedules.contains(schedule) {
+                issues.push(
+                    "dialer",
+                    id,
+                    format!("schedule '{}' does not exist", schedule),
+                );
+            }
+        }
+        if let Some(trunk) = &campaign.trunk {
+            if !references.trunks.is_empty() && !references.trunks.contains(trunk) {
+                issues.push("dialer", id, format!("trunk '{}' does not exist", trunk));
+            }
+        }
+    }
+    for number in &dialer.do_not_call {
+        if !number.chars().any(|c| c.is_ascii_digit()) {
+            issues.push(
+                "dialer",
+                number,
+                "do-not-call entry is not a phone number".to_string(),
+            );
+        }
+    }
+}
+
+fn validate_transcription(
+    config: &TranscriptionConfig,
+    routes: &HashSet<&str>,
+    references: &References,
+    issues: &mut Issues,
+) {
+    match reqwest::Url::parse(&config.url) {
+        Ok(url) if matches!(url.scheme(), "ws" | "wss") && url.host_str().is_some() => {}
+        _ => issues.push(
+            "transcription",
+            "url",
+            format!("'{}' is not a ws:// or wss:// URL", config.url),
+        ),
+    }
+    if config.consent.mode == ConsentMode::Announced && config.consent.announcement.is_none() {
+        issues.push(
+            "transcription",
+            "consent",
+            "announced consent needs an announcement".to_string(),
+        );
+    }
+    for rule in &config.rules {
+        if let Some(route) = &rule.route {
+            if !routes.contains(route.as_str()) {
+                issues.push(
+                    "transcription",
+                    route,
+                    "rule refers to a route that does not exist".to_string(),
+                );
+            }
+        }
+        if let Some(queue) = &rule.queue {
+            if !references.ring_groups.is_empty() && !references.ring_groups.contains(queue) {
+                issues.push(
+                    "transcription",
+                    queue,
+                    "rule refers to a ring group that does not exist".to_string(),
+                );
+            }
+        }
+    }
+}
+
+fn validate_quotas(config: &QuotaConfig, issues: &mut Issues) {
+    let mut tenants: Vec<&String> = config.tenants.keys().collect();
+    tenants.sort();
+    let mut seen = HashSet::new();
+    for tenant in tenants {
+        if tenant.is_empty() || tenant.contains(char::is_whitespace) {
+            issues.push("quotas", tenant, "tenant is not a SIP domain".to_string());
+        } else if !seen.insert(tenant.to_ascii_lowercase()) {
+            // Tenants are matched case-insensitively, so only one would apply
+            issues.push(
+                "quotas",
+                tenant,
+                "duplicate tenant, differing only in case".to_string(),
+            );
+        }
+    }
+}
+
+fn validate_domains(config: &DomainConfig, routing: Option<&RoutingConfig>, issues: &mut Issues) {
+    let mut names = HashSet::new();
+    // Each domain or alias, lowercased, with the profile serving it
+    let mut served: Vec<(String, &str)> = Vec::new();
+    for profile in &config.profiles {
+        if !names.insert(profile.name.as_str()) {
+            issues.push(
+                "domains",
+                &profile.name,
+                "duplicate profile name".to_string(),
+            );
+        }
+        for domain in std::iter::once(&profile.domain).chain(&profile.aliases) {
+            if domain.is_empty() || domain.contains(char::is_whitespace) {
+                issues.push(
+                    "domains",
+                    &profile.name,
+                    format!("'{}' is not a SIP domain", domain),
+                );
+                continue;
+            }
+            let domain = domain.to_ascii_lowercase();
+            match served.iter().find(|(d, _)| *d == domain) {
+                // Only the first profile serving a domain would apply
+                Some((_, owner)) => issues.push(
+                    "domains",
+                    &profile.name,
+                    format!("{} is already served by profile '{}'", domain, owner),
+                ),
+                None => served.push((domain, &profile.name)),
+            }
+        }
+    }
+
+    // Calls are matched on their canonical domain, so an alias never matches
+    for route in routing.iter().flat_map(|r| &r.routes) {
+        for condition in route.conditions.iter().flatten() {
+            let RouteCondition::Domain(condition) = condition else {
+                continue;
+            };
+            let canonical = config.profiles.iter().find(|p| {
+                p.aliases
+                    .iter()
+                    .any(|a| a.eq_ignore_ascii_case(&condition.domain))
+            });
+            if let Some(profile) = canonical {
+                issues.push(
+                    "routes",
+                    &route.id,
+                    format!(
+                        "domain condition names {}, an alias of {}",
+                        condition.domain, profile.domain
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn validate_remote_console(config: &RemoteConsoleConfig, issues: &mut Issues) {
+    if config.users.is_empty() {
+        issues.push(
+            "remote_console",
+            "users",
+            "no user accounts, so nobody can sign in".to_string(),
+        );
+    }
+    let mut usernames = HashSet::new();
+    for user in &config.users {
+        let name = user.username.as_str();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            issues.push(
+                "remote_console",
+                name,
+                "username must be a single word".to_string(),
+            );
+        } else if !usernames.insert(name) {
+            issues.push("remote_console", name, "duplicate username".to_string());
+        }
+        if !is_password_hash(&user.password_hash) {
+            issues.push(
+                "remote_console",
+                name,
+                "password_hash is not an Argon2 hash; `rustalk console --hash-password` prints one"
+                    .to_string(),
+            );
+        }
+        for command in &user.commands {
+            if command != "*" && !COMMANDS.contains(&command.to_lowercase().as_str()) {
+                issues.push(
+                    "remote_console",
+                    name,
+                    format!("'{}' is not a console command", command),
+                );
+            }
+        }
+    }
+}
+
+fn validate_schedules(schedules: &[Schedule], issues: &mut Issues) {
+    let mut ids = HashSet::new();
+    for schedule in schedules {
+        let id = schedule.id.as_str();
+        if !ids.insert(id) {
+            issues.push("schedules", id, "duplicate schedule id".to_string());
+        }
+        let mut check_time = |time: &str| {
+            if let Err(e) = parse_time(time) {
+                issues.push("schedules", id, format!("time '{}': {}", time, e));
+            }
+        };
+        for hours in &schedule.hours {
+            check_time(&hours.start_time);
+            check_time(&hours.end_time);
+        }
+        for holiday in &schedule.holidays {
+            for hours in &holiday.hours {
+                check_time(&hours.start_time);
+                check_time(&hours.end_time);
+            }
+        }
+        for hours in &schedule.hours {
+            if let Some(day) = hours.days.iter().find(|d| !(1..=7).contains(*d)) {
+                issues.push(
+                    "schedules",
+                    id,
+                    format!("day of week {} is outside 1-7", day),
+                );
+            }
+        }
+        for holiday in &schedule.holidays {
+            let parse = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d");
+            let dates = std::iter::once(&holiday.date).chain(holiday.end_date.as_ref());
+            for date in dates {
+                if parse(date).is_err() {
+                    issues.push(
+                        "schedules",
+                        id,
+                        format!(
+                            "holiday '{}' date '{}' is not YYYY-MM-DD",
+                            holiday.name, date
+                        ),
+                    );
+                }
+            }
+            if let (Ok(start), Some(Ok(end))) =
+                (parse(&holiday.date), holiday.end_date.as_deref().map(parse))
+            {
+                if end < start {
+                    issues.push(
+                        "schedules",
+                        id,
+                        format!("holiday '{}' ends before it starts", holiday.name),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Check a time, day of week or date range condition
+fn validate_calendar(condition: &RouteCondition, section: &str, name: &str, issues: &mut Issues) {
+    match condition {
+        RouteCondition::Time(c) => {
+            for time in [&c.start_time, &c.end_time] {
+                if let Err(e) = parse_time(time) {
+                    issues.push(section, name, format!("time '{}': {}", time, e));
+                }
+            }
+        }
+        RouteCondition::DayOfWeek(c) => {
+            if let Some(day) = c.days.iter().find(|d| !(1..=7).contains(*d)) {
+                issues.push(section, name, format!("day of week {} is outside 1-7", day));
+            }
+        }
+        RouteCondition::DateRange(c) => {
+            for date in [&c.start_date, &c.end_date] {
+                if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+                    issues.push(section, name, format!("date '{}' is not YYYY-MM-DD", date));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn validate_admission(config: &AdmissionConfig, issues: &mut Issues) {
+    let limits = config
+        .trunks
+        .iter()
+        .map(|t| (t.trunk.as_str(), &t.limits))
+        .chain(config.default_limits.iter().map(|l| ("default_limits", l)));
+    for (trunk, limits) in limits {
+        if limits.reservations.is_empty() {
+            continue;
+        }
+        let reserved: u32 = limits.reservations.iter().map(|r| r.channels).sum();
+        match limits.max_concurrent {
+            None => issues.push(
+                "admission",
+                trunk,
+                "reservations need a max_concurrent limit".to_string(),
+            ),
+            Some(max) if reserved >= max => issues.push(
+                "admission",
+                trunk,
+                format!(
+                    "{} reserved channels leave none of {} for other calls",
+                    reserved, max
+                ),
+            ),
+            _ => {}
+        }
+        for reservation in &limits.reservations {
+            let name = format!("{}/{}", trunk, reservation.name);
+            if reservation.call_classes.is_empty() && reservation.prefixes.is_empty() {
+                issues.push(
+                    "admission",
+                    &name,
+                    "no call classes or prefixes may use the reservation".to_string(),
+                );
+            }
+            for condition in &reservation.schedule {
+                match condition {
+                    RouteCondition::Time(_)
+                    | RouteCondition::DayOfWeek(_)
+                    | RouteCondition::DateRange(_) => {
+                        validate_calendar(condition, "admission", &name, issues)
+                    }
+                    _ => issues.push(
+                        "admission",
+                        &name,
+                        "schedules only take time, day of week and date range conditions"
+                            .to_string(),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+fn validate_account_codes(config: &AccountCodeConfig, issues: &mut Issues) {
+    let feature_code = &config.feature_code;
+    if feature_code.is_empty() || feature_code.ends_with('*') {
+        issues.push(
+            "account_codes",
+            feature_code,
+            "feature code must be non-empty and not end with '*'".to_string(),
+        );
+    }
+    let mut seen = HashSet::new();
+    for code in &config.codes {
+        if code.code.is_empty() || code.code.contains('*') {
+            issues.push(
+                "account_codes",
+                &code.code,
+                "code must be non-empty and not contain '*'".to_string(),
+            );
+        }
+        if !seen.insert(code.code.as_str()) {
+            issues.push("account_codes", &code.code, "duplicate code".to_string());
+        }
+    }
+}
+
+fn validate_route(route: &RouteRule, references: &References, issues: &mut Issues) {
+    let mut check_regex = |what: &str, pattern: &str| {
+        if let Err(e) = Regex::new(pattern) {
+            issues.push(
+                "routes",
+                &route.id,
+                format!("{} '{}': {}", what, pattern, e),
+            );
+        }
+    };
+    check_regex("pattern", &route.pattern);
+    for condition in route.conditions.iter().flatten() {
+        match condition {
+            RouteCondition::CallerId(c) => check_regex("caller ID pattern", &c.pattern),
+            RouteCondition::Destination(c) => check_regex("destination pattern", &c.pattern),
+            _ => {}
+        }
+    }
+
+    for condition in route.conditions.iter().flatten() {
+        match condition {
+            RouteCondition::Time(_)
+            | RouteCondition::DayOfWeek(_)
+            | RouteCondition::DateRange(_) => {
+                validate_calendar(condition, "routes", &route.id, issues)
+            }
+            RouteCondition::CallerGroup(c)
+                if !references.groups.is_empty() && !references.groups.contains(&c.group) =>
+            {
+                issues.push(
+                    "routes",
+                    &route.id,
+                    format!("caller group '{}' does not exist", c.group),
+                );
+            }
+            RouteCondition::Schedule(c)
+                if !references.schedules.is_empty()
+                    && !references.schedules.contains(&c.schedule) =>
+            {
+                issues.push(
+                    "routes",
+                    &route.id,
+                    format!("schedule '{}' does not exist", c.schedule),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    validate_destination(&route.destination, &route.id, references, issues, true);
+    for rule in &route.on_failure {
+        if rule.codes.is_empty() {
+            issues.push(
+                "routes",
+                &route.id,
+                "reroute rule names no response codes".to_string(),
+            );
+        }
+        for code in rule.invalid_codes() {
+            issues.push(
+                "routes",
+                &route.id,
+                format!(
+                    "reroute code '{}' is not a final response status or class",
+                    code
+                ),
+            );
+        }
+        validate_list(&rule.destinations, &route.id, references, issues);
+    }
+}
+
+fn validate_destination(
+    destination: &RouteDestination,
+    route_id: &str,
+    references: &References,
+    issues: &mut Issues,
+    top_level: bool,
+) {
+    let (kind, known, target) = match destination {
+        RouteDestination::Extension(ext) => ("extension", &references.extensions, ext),
+        RouteDestination::Trunk(trunk) | RouteDestination::Enum(trunk) => {
+            ("trunk", &references.trunks, trunk)
+        }
+        RouteDestination::RingGroup(group) => ("ring group", &references.ring_groups, group),
+        RouteDestination::Voicemail(mailbox) => ("mailbox", &references.mailboxes, mailbox),
+        RouteDestination::LeastCost(trunks) => {
+            if trunks.is_empty() {
+                issues.push(
+                    "routes",
+                    route_id,
+                    "least-cost destination names no trunks".to_string(),
+                );
+            }
+            for trunk in trunks {
+                if !references.trunks.is_empty() && !references.trunks.contains(trunk) {
+                    issues.push(
+                        "routes",
+                        route_id,
+                        format!("destination trunk '{}' does not exist", trunk),
+                    );
+                }
+            }
+            return;
+        }
+        RouteDestination::DestinationList(list) => {
+            if top_level {
+                validate_list(list, route_id, references, issues);
+            } else {
+                issues.push(
+                    "routes",
+                    route_id,
+                    "destination lists cannot be nested".to_string(),
+                );
+            }
+            return;
+        }
+        RouteDestination::Hangup | RouteDestination::Custom(_) => return,
+    };
+    if !known.is_empty() && !known.contains(target) {
+        issues.push(
+            "routes",
+            route_id,
+            format!("destination {} '{}' does not exist", kind, target),
+        );
+    }
+}
+
+fn validate_list(
+    list: &DestinationList,
+    route_id: &str,
+    references: &References,
+    issues: &mut Issues,
+) {
+    if list.hops.is_empty() {
+        issues.push(
+            "routes",
+            route_id,
+            "destination list has no destinations".to_string(),
+        );
+    }
+    for hop in &list.hops {
+        if hop.timeout_seconds == Some(0) {
+            issues.push(
+                "routes",
+                route_id,
+                "destination timeout must be positive".to_string(),
+            );
+        }
+        validate_destination(&hop.destination, route_id, references, issues, false);
+    }
+    if let Some(policy) = &list.failover {
+        validate_failover(policy, "routes", route_id, issues);
+    }
+}
+
+fn validate_failover(policy: &FailoverPolicy, section: &str, name: &str, issues: &mut Issues) {
+    for code in policy.invalid_codes() {
+        issues.push(
+            section,
+            name,
+            format!(
+                "failover code '{}' is not a final response status or class",
+                code
+            ),
+        );
+    }
+}
+
+#[derive(Default)]
+struct Issues(Vec<ValidationIssue>);
+
+impl Issues {
+    fn push(&mut self, section: &str, name: &str, message: String) {
+        self.0.push(ValidationIssue {
+            section: section.to_string(),
+            name: name.to_string(),
+            message,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::{AclAction, AclRule};
+    use crate::routing::{
+        CallerGroupCondition, DayOfWeekCondition, DomainCondition, RouteAction, RoutingConfig,
+        ScheduleCondition, TimeCondition,
+    };
+
+    fn route(id: &str, pattern: &str, destination: RouteDestination) -> RouteRule {
+        RouteRule {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: None,
+            pattern: pattern.to_string(),
+            destination,
+            enabled: true,
+            priority: 10,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(validate(&Config::default()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_reports_issues() {
+        let mut config = Config::default();
+        let mut bad_conditions = route("r2", "^2", RouteDestination::Hangup);
+        bad_conditions.conditions = Some(vec![
+            RouteCondition::Time(TimeCondition {
+                start_time: "25:00".to_string(),
+                end_time: "17:00".to_string(),
+            }),
+            RouteCondition::DayOfWeek(DayOfWeekCondition { days: vec![0, 1] }),
+            RouteCondition::CallerGroup(CallerGroupCondition {
+                group: "ghost".to_string(),
+                negate: false,
+            }),
+        ]);
+        let mut routing = RoutingConfig::new();
+        routing.add_route(route(
+            "r1",
+            "^(1",
+            RouteDestination::Extension("1001".to_string()),
+        ));
+        routing.add_route(bad_conditions);
+        routing.add_route(route(
+            "r3",
+            "^3",
+            RouteDestination::Trunk("missing".to_string()),
+        ));
+        routing.add_route(route("r3", "^4", RouteDestination::Hangup));
+        routing.tests = serde_json::from_value(serde_json::json!([
+            { "name": "t1", "caller_id": "1001", "destination": "4000", "expected_route": "r3" },
+            { "name": "t1", "caller_id": "1001", "destination": "5000", "expected_route": "r9" }
+        ]))
+        .unwrap();
+        config.routing = Some(routing);
+        config
+            .acls
+            .as_mut()
+            .unwrap()
+            .get_acl_mut("rfc1918")
+            .unwrap()
+            .add_rule(AclRule {
+                name: "typo".to_string(),
+                cidr: "10.0.0.0/33".to_string(),
+                action: AclAction::Allow,
+                priority: 1,
+            });
+        config.database = Some(crate::config::DatabaseConfig {
+            url: "mysql://db/rustalk".to_string(),
+            max_connections: 5,
+            min_connections: 0,
+            auto_migrate: false,
+        });
+
+        let references = References {
+            trunks: HashSet::from(["carrier".to_string()]),
+            groups: HashSet::from(["sales".to_string()]),
+            ..Default::default()
+        };
+        let issues = validate_with_references(&config, &references);
+        let has = |name: &str, text: &str| {
+            issues
+                .iter()
+                .any(|i| i.name == name && i.message.contains(text))
+        };
+
+        assert!(has("r1", "pattern '^(1'"));
+        assert!(has("r2", "time '25:00'"));
+        assert!(has("r2", "day of week 0"));
+        assert!(has("r2", "caller group 'ghost' does not exist"));
+        assert!(has("r3", "trunk 'missing' does not exist"));
+        assert!(has("r3", "duplicate route id"));
+        assert!(has("rfc1918", "prefix length"));
+        assert!(has("t1", "duplicate test name"));
+        assert!(has("t1", "expected route 'r9' does not exist"));
+        assert!(has("url", "sqlite://"));
+        assert!(!has("t1", "route 'r3'"));
+        // Extensions were not supplied, so they are not checked
+        assert!(!has("r1", "does not exist"));
+    }
+
+    #[test]
+    fn test_validate_cos() {
+        let mut config = Config::default();
+        let mut cos = CosConfig::default();
+        cos.extensions
+            .insert("1001".to_string(), "missing".to_string());
+        config.cos = Some(cos);
+
+        let issues = validate(&config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].section, "cos");
+        assert_eq!(issues[0].message, "unknown profile used by extension 1001");
+    }
+
+    #[test]
+    fn test_validate_dial_pins() {
+        use crate::dial_pin::DialPin;
+
+        let mut config = Config::default();
+        let pin = |pin: &str, user: Option<&str>| DialPin {
+            pin: pin.to_string(),
+            user: user.map(str::to_string),
+        };
+        config.dial_pins = Some(DialPinConfig {
+            extensions: vec!["1900".to_string()],
+            pins: vec![
+                pin("1234", Some("alice")),
+                pin("1234", None),
+                pin("12a", Some("bob")),
+            ],
+            lockout_seconds: 0,
+            ..Default::default()
+        });
+
+        let issues = validate(&config);
+        let messages: Vec<(&str, &str)> = issues
+            .iter()
+            .map(|i| (i.name.as_str(), i.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    "max_failures",
+                    "failure_window_seconds and lockout_seconds must be set to lock extensions out"
+                ),
+                ("shared", "PIN is already in use"),
+                ("bob", "PIN must be digits")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_callback_feature_code() {
+        use crate::callback::CallbackConfig;
+
+        let mut config = Config {
+            callbacks: Some(CallbackConfig::default()),
+            account_codes: Some(AccountCodeConfig::default()),
+            ..Default::default()
+        };
+        assert!(validate(&config).is_empty());
+
+        config.callbacks = Some(CallbackConfig {
+            feature_code: "*".to_string(),
+            ..Default::default()
+        });
+        let issues = validate(&config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].section, "callbacks");
+    }
+
+    #[test]
+    fn test_validate_sms() {
+        use crate::sms::TwilioConfig;
+        use std::collections::HashMap;
+
+        let config = Config {
+            sms: Some(SmsConfig {
+                provider: SmsProviderConfig::Twilio(TwilioConfig {
+                    account_sid: "AC123".to_string(),
+                    ..Default::default()
+                }),
+                dids: HashMap::from([
+                    ("+12125551000".to_string(), "1001".to_string()),
+                    ("12125551000".to_string(), "1002".to_string()),
+                    ("main".to_string(), "1003".to_string()),
+                ]),
+                sip_bridge: true,
+            }),
+            ..Default::default()
+        };
+        let mut names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        names.sort();
+        // One of the two spellings of the same DID is reported
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0].trim_start_matches('+'), "12125551000");
+        assert_eq!(names[1..], ["main", "provider"]);
+    }
+
+    #[test]
+    fn test_validate_crm_url() {
+        use crate::crm::CrmConfig;
+
+        let mut config = Config {
+            crm: Some(serde_json::from_str(r#"{"url": "crm.example.com/lookup"}"#).unwrap()),
+            ..Default::default()
+        };
+        let issues = validate(&config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].section, "crm");
+
+        config.crm = Some(CrmConfig {
+            url: "https://crm.example.com/lookup".to_string(),
+            ..config.crm.unwrap()
+        });
+        assert!(validate(&config).is_empty());
+    }
+
+    #[test]
+    fn test_validate_lnp_url() {
+        let mut config = Config {
+            lnp: Some(
+                serde_json::from_str(
+                    r#"{"provider": {"type": "http", "url": "https://lnp.example.com/lrn"}}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let issues = validate(&config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].section, "lnp");
+
+        config.lnp.as_mut().unwrap().provider = LnpProvider::Http {
+            url: "https://lnp.example.com/lrn/{number}".to_string(),
+            headers: Default::default(),
+        };
+        assert!(validate(&config).is_empty());
+    }
+
+    #[test]
+    fn test_validate_ivr() {
+        let config = Config {
+            ivr: Some(
+                serde_json::from_str(
+                    r#"{"flows": [{"id": "main", "name": "Main", "start": "menu", "nodes": [
+                        {"id": "menu", "type": "menu", "prompt": "menu", "options": {"1": "sales"}}
+                    ]}]}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let issues = validate(&config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].section, "ivr");
+        assert_eq!(issues[0].name, "main");
+        assert_eq!(
+            issues[0].message,
+            "node 'menu': option 1 leads to missing node 'sales'"
+        );
+    }
+
+    #[test]
+    fn test_validate_dialer() {
+        let config = Config {
+            dialer: Some(
+                serde_json::from_str(
+                    r#"{"campaigns": [{"id": "reminders", "name": "Reminders", "caller_id": "+12125550100",
+                        "connect_to": {"type": "ivr", "target": "reminder"},
+                        "pacing": {"calls_per_minute": 0},
+                        "calling_hours": [{"days": [8], "start_time": "09:00", "end_time": "17:00"}],
+                        "schedule": "office"}],
+                        "do_not_call": ["unknown"]}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let messages: Vec<String> = validate(&config)
+            .into_iter()
+            .map(|issue| format!("{}: {}", issue.name, issue.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "reminders: pacing must allow at least one call",
+                "reminders: day of week 8 is outside 1-7",
+                "unknown: do-not-call entry is not a phone number",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_transcription() {
+        let config = Config {
+            transcription: Some(
+                serde_json::from_str(
+                    r#"{"url": "https://transcribe.example.com",
+                        "rules": [{"route": "support"}, {"queue": "sales"}]}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let messages: Vec<String> = validate(&config)
+            .into_iter()
+            .map(|issue| format!("{}: {}", issue.name, issue.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "url: 'https://transcribe.example.com' is not a ws:// or wss:// URL",
+                "consent: announced consent needs an announcement",
+                "support: rule refers to a route that does not exist",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_remote_console() {
+        let hash = crate::console::hash_password("secret");
+        let config = Config {
+            remote_console: Some(
+                serde_json::from_value(serde_json::json!({
+                    "cert_path": "console.pem",
+                    "key_path": "console.key",
+                    "users": [
+                        { "username": "noc", "password_hash": hash },
+                        { "username": "noc", "password_hash": hash },
+                        { "username": "oncall", "password_hash": "secret",
+                          "commands": ["show", "hangups"] }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let messages: Vec<String> = validate(&config)
+            .into_iter()
+            .map(|issue| format!("{}: {}", issue.name, issue.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "noc: duplicate username",
+                "oncall: password_hash is not an Argon2 hash; `rustalk console --hash-password` prints one",
+                "oncall: 'hangups' is not a console command",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_quotas() {
+        let config = Config {
+            quotas: Some(
+                serde_json::from_value(serde_json::json!({
+                    "tenants": {
+                        "acme.example": { "max_extensions": 10 },
+                        "Acme.example": { "max_trunks": 1 },
+                        "acme example": {}
+                    }
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let messages: Vec<String> = validate(&config)
+            .into_iter()
+            .map(|issue| format!("{}: {}", issue.name, issue.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "acme example: tenant is not a SIP domain",
+                "acme.example: duplicate tenant, differing only in case",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_domains() {
+        let mut config = Config {
+            domains: Some(
+                serde_json::from_value(serde_json::json!({
+                    "profiles": [
+                        { "name": "brand-a", "domain": "example.com", "aliases": ["voice.example.com"] },
+                        { "name": "brand-b", "domain": "Voice.example.com", "aliases": ["bad domain"] },
+                        { "name": "brand-a", "domain": "third.example" }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut sales = route("brand-a-sales", "^1", RouteDestination::Hangup);
+        sales.conditions = Some(vec![RouteCondition::Domain(DomainCondition {
+            domain: "voice.example.com".to_string(),
+            negate: false,
+        })]);
+        let mut routing = RoutingConfig::new();
+        routing.add_route(sales);
+        config.routing = Some(routing);
+
+        let messages: Vec<String> = validate(&config)
+            .into_iter()
+            .filter(|issue| issue.section == "domains" || issue.section == "routes")
+            .map(|issue| format!("{}: {}", issue.name, issue.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "brand-b: voice.example.com is already served by profile 'brand-a'",
+                "brand-b: 'bad domain' is not a SIP domain",
+                "brand-a: duplicate profile name",
+                "brand-a-sales: domain condition names voice.example.com, an alias of example.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_webhooks() {
+        let config = Config {
+            webhooks: Some(
+                serde_json::from_str(
+                    r#"{"endpoints": [
+                        {"name": "zapier", "url": "https://hooks.zapier.com/hooks/catch/1/a"},
+                        {"name": "zapier", "url": "https://hooks.zapier.com/hooks/catch/1/b"},
+                        {"name": "ifttt", "url": "maker.ifttt.com/trigger",
+                         "fields": {"value1": "crm..customer_name"}}
+                    ]}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let issues = validate(&config);
+        assert_eq!(issues.len(), 3);
+        assert!(issues.iter().all(|i| i.section == "webhooks"));
+        assert_eq!(issues[0].name, "zapier");
+        assert!(issues[2].message.contains("value1"));
+    }
+
+    #[test]
+    fn test_validate_fax() {
+        let config = Config {
+            fax: Some(
+                serde_json::from_str(
+                    r#"{"smtp": {"host": "smtp.example.com", "from": "fax"},
+                        "dids": {"+12125551900": "accounts@example.com",
+                                 "+12125551901": "accounts"}}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        names.sort();
+        assert_eq!(names, ["+12125551901", "smtp"]);
+    }
+
+    #[test]
+    fn test_validate_snmp() {
+        let config = Config {
+            snmp: Some(
+                serde_json::from_str(
+                    r#"{"enterprise_oid": "enterprises.8072", "engine_id": "80001f88",
+                        "users": [{"name": "nms", "priv_password": "privpassword"},
+                                  {"name": "ops", "auth_password": "short"}]}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        names.sort();
+        assert_eq!(names, ["engine_id", "enterprise_oid", "nms", "ops"]);
+    }
+
+    #[test]
+    fn test_validate_radius() {
+        let config = Config {
+            radius: Some(
+                serde_json::from_str(
+                    r#"{"accounting_server": "radius.example.com", "secret": ""}"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        names.sort();
+        assert_eq!(names, ["accounting_server", "secret"]);
+    }
+
+    #[test]
+    fn test_validate_registrar() {
+        let config = Config {
+            registrar: Some(
+                serde_json::from_value(serde_json::json!({
+                    "max_expires": 0,
+                    "credentials": [
+                        { "username": "1001", "password": "secret" },
+                        { "username": "1001", "password": "other" },
+                        { "username": "1002", "password": "" }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        assert_eq!(names, ["max_expires", "1001", "1002"]);
+    }
+
+    #[test]
+    fn test_validate_wholesale() {
+        let config = Config {
+            wholesale: Some(
+                serde_json::from_value(serde_json::json!({
+                    "peers": [
+                        { "name": "acme", "addresses": ["203.0.113.0/24", "198.51.100.1:5080"] },
+                        { "name": "acme", "addresses": ["carrier.example.com"], "rate_deck": "gold" },
+                    ],
+                    "rate_decks": [
+                        { "name": "standard", "rates": [{ "prefix": "44x", "per_minute": 0.02 }] }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut issues: Vec<String> = validate(&config)
+            .into_iter()
+            .map(|i| format!("{}: {}", i.name, i.message))
+            .collect();
+        issues.sort();
+        assert_eq!(
+            issues,
+            [
+                "acme: 'carrier.example.com' is not an IP, CIDR or ip:port",
+                "acme: duplicate peer",
+                "acme: unknown rate deck 'gold'",
+                "standard: rate prefix '44x' must be digits",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_quirks() {
+        let config = Config {
+            quirks: Some(
+                serde_json::from_value(serde_json::json!({
+                    "profiles": [
+                        { "name": "loop", "extends": "loop", "quirks": [] },
+                        { "name": "carrier", "extends": "no-prack", "quirks": ["user_phone"] }
+                    ],
+                    "trunks": [
+                        { "trunk": "a.example", "profile": "carrier" },
+                        { "trunk": "b.example", "profile": "missing" },
+                        { "trunk": "c.example", "profile": "auth-user-from" },
+                        { "trunk": "A.example", "profile": "standard" }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        names.sort();
+        assert_eq!(names, ["A.example", "b.example", "c.example", "loop"]);
+    }
+
+    #[test]
+    fn test_validate_trunk_health() {
+        let config = Config {
+            trunk_health: Some(
+                serde_json::from_value(serde_json::json!({
+                    "up_after": 0,
+                    "down_after": 2,
+                    "trunks": [
+                        { "name": "carrier", "host": "sip.carrier.example" },
+                        { "name": "carrier", "host": "sip2.carrier.example" },
+                        { "name": "backup", "host": "backup.example", "degraded_after": 3 }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let issues: Vec<String> = validate(&config)
+            .into_iter()
+            .map(|i| format!("{}: {}", i.name, i.message))
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "up_after: must be at least 1",
+                "carrier: trunk monitored twice",
+                "backup: down after 2 failure(s), before it is degraded after 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_presence() {
+        let config = Config {
+            presence: Some(
+                serde_json::from_value(serde_json::json!({
+                    "blf_lists": [
+                        { "device": "00:15:65:12:34:56", "extensions": ["1002", "1002", "9999"] },
+                        { "device": "001565123456", "extensions": [] },
+                        { "device": " ", "extensions": ["1002"] }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let references = References {
+            extensions: ["1001", "1002"].iter().map(|e| e.to_string()).collect(),
+            ..Default::default()
+        };
+        let issues: Vec<String> = validate_with_references(&config, &references)
+            .into_iter()
+            .map(|i| format!("{}: {}", i.name, i.message))
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "00:15:65:12:34:56: extension '1002' is listed twice",
+                "00:15:65:12:34:56: extension '9999' does not exist",
+                "001565123456: another list is served as blf-001565123456",
+                "001565123456: no extensions to watch",
+                "blf_lists: list without a device",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_lcr() {
+        let rate = |id: &str, trunk: &str| {
+            serde_json::json!({
+                "id": id, "name": id, "description": null, "prefix": "44",
+                "rate_per_minute": 0.01, "connection_fee": 0.0,
+                "minimum_charge_seconds": 0, "billing_increment_seconds": 6,
+                "currency": "USD", "effective_date": 100, "end_date": null,
+                "active": true, "trunk": trunk
+            })
+        };
+        let mut negative = rate("b", "carrier");
+        negative["rate_per_minute"] = serde_json::json!(-0.01);
+        let mut ended = rate("c", "carrier");
+        ended["end_date"] = serde_json::json!(50);
+        let mut routing = RoutingConfig::new();
+        routing.add_route(route(
+            "cheapest",
+            "^44",
+            RouteDestination::LeastCost(vec![]),
+        ));
+        routing.add_route(route(
+            "uk",
+            "^44",
+            RouteDestination::LeastCost(vec!["carrier".to_string(), "ghost".to_string()]),
+        ));
+        let config = Config {
+            routing: Some(routing),
+            lcr: Some(
+                serde_json::from_value(serde_json::json!({
+                    "reference_seconds": 0,
+                    "rates": [rate("a", "carrier"), rate("a", "carrier"), negative, ended, rate("d", "ghost")]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let references = References {
+            trunks: ["carrier".to_string()].into(),
+            ..Default::default()
+        };
+        let issues: Vec<String> = validate_with_references(&config, &references)
+            .into_iter()
+            .map(|i| format!("{} {}: {}", i.section, i.name, i.message))
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "routes cheapest: least-cost destination names no trunks",
+                "routes uk: destination trunk 'ghost' does not exist",
+                "lcr reference_seconds: must be at least 1",
+                "lcr a: duplicate rate id",
+                "lcr b: rates cannot be negative",
+                "lcr c: ends before it takes effect",
+                "lcr d: trunk 'ghost' does not exist",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_destination_lists() {
+        let list = |value: serde_json::Value| -> RouteDestination {
+            serde_json::from_value(serde_json::json!({ "type": "DestinationList", "value": value }))
+                .unwrap()
+        };
+        let mut routing = RoutingConfig::new();
+        routing.add_route(route(
+            "empty",
+            "^1",
+            list(serde_json::json!({ "hops": [] })),
+        ));
+        routing.add_route(route(
+            "us",
+            "^1",
+            list(serde_json::json!({
+                "hops": [
+                    { "destination": { "type": "Trunk", "value": "ghost" }, "timeout_seconds": 0 },
+                    { "destination": { "type": "DestinationList", "value": { "hops": [] } } },
+                    { "destination": { "type": "Voicemail", "value": "1001" } }
+                ],
+                "failover": { "codes": ["503", "2xx"] }
+            })),
+        ));
+        routing.failover.codes.push("180".to_string());
+        routing.max_attempts = Some(0);
+        routing.routes[1].on_failure = serde_json::from_value(serde_json::json!([
+            { "codes": [], "destinations": { "hops": [
+                { "destination": { "type": "Voicemail", "value": "1001" } }
+            ] } },
+            { "codes": ["404", "4x"], "destinations": { "hops": [] } }
+        ]))
+        .unwrap();
+        let config = Config {
+            routing: Some(routing),
+            ..Default::default()
+        };
+        let references = References {
+            trunks: ["carrier".to_string()].into(),
+            mailboxes: ["1001".to_string()].into(),
+            ..Default::default()
+        };
+        let issues: Vec<String> = validate_with_references(&config, &references)
+            .into_iter()
+            .map(|i| format!("{} {}: {}", i.section, i.name, i.message))
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "routing failover: failover code '180' is not a final response status or class",
+                "routing max_attempts: must be at least 1",
+                "routes empty: destination list has no destinations",
+                "routes us: destination timeout must be positive",
+                "routes us: destination trunk 'ghost' does not exist",
+                "routes us: destination lists cannot be nested",
+                "routes us: failover code '2xx' is not a final response status or class",
+                "routes us: reroute rule names no response codes",
+                "routes us: reroute code '4x' is not a final response status or class",
+                "routes us: destination list has no destinations",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_dial_strings() {
+        let config = Config {
+            dial_strings: Some(
+                serde_json::from_value(serde_json::json!({
+                    "trunks": [
+                        { "trunk": "a.example", "template": "sip:99{digits}@{host};user=phone" },
+                        { "trunk": "b.example", "template": "sip:{dialed}@{host}" },
+                        { "trunk": "A.example", "template": "sip:{number}@{host}" }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        names.sort();
+        assert_eq!(names, ["A.example", "b.example"]);
+    }
+
+    #[test]
+    fn test_validate_schedules() {
+        let mut config = Config {
+            schedules: Some(
+                serde_json::from_value(serde_json::json!([
+                    {
+                        "id": "office",
+                        "name": "Business hours",
+                        "description": null,
+                        "hours": [{ "days": [1, 2, 3, 4, 5], "start_time": "09:00", "end_time": "17:00" }],
+                        "holidays": [{ "name": "New Year", "date": "2025-01-01" }]
+                    },
+                    {
+                        "id": "broken",
+                        "name": "Broken",
+                        "description": null,
+                        "hours": [{ "days": [8], "start_time": "9am", "end_time": "17:00" }],
+                        "holidays": [
+                            { "name": "Typo", "date": "2025-13-01" },
+                            { "name": "Backwards", "date": "2025-12-31", "end_date": "2025-12-24" }
+                        ]
+                    },
+                    { "id": "office", "name": "Again", "description": null }
+                ]))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut open = route("day", "^1", RouteDestination::Hangup);
+        open.conditions = Some(vec![RouteCondition::Schedule(ScheduleCondition {
+            schedule: "office".to_string(),
+            negate: false,
+        })]);
+        let mut night = route("night", "^1", RouteDestination::Hangup);
+        night.conditions = Some(vec![RouteCondition::Schedule(ScheduleCondition {
+            schedule: "nights".to_string(),
+            negate: true,
+        })]);
+        let mut routing = RoutingConfig::new();
+        routing.add_route(open);
+        routing.add_route(night);
+        config.routing = Some(routing);
+
+        let issues = validate(&config);
+        let mut names: Vec<&str> = issues.iter().map(|i| i.name.as_str()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["broken", "broken", "broken", "broken", "night", "office"]
+        );
+        assert!(issues
+            .iter()
+            .any(|i| i.message == "schedule 'nights' does not exist"));
+    }
+
+    #[test]
+    fn test_validate_admission_reservations() {
+        let config = Config {
+            admission: Some(
+                serde_json::from_value(serde_json::json!({
+                    "backend": { "type": "local" },
+                    "default_limits": null,
+                    "fail_open": true,
+                    "trunks": [
+                        {
+                            "trunk": "pstn",
+                            "max_cps": null,
+                            "max_concurrent": 4,
+                            "local_burst": 1,
+                            "reservations": [
+                                { "name": "emergency", "channels": 4, "call_classes": ["emergency"] },
+                                {
+                                    "name": "nobody",
+                                    "channels": 0,
+                                    "schedule": [{ "type": "Time", "start_time": "9", "end_time": "17:00" }]
+                                }
+                            ]
+                        },
+                        {
+                            "trunk": "backup",
+                            "max_cps": 5,
+                            "max_concurrent": null,
+                            "local_burst": 1,
+                            "reservations": [{
+                                "name": "hotline",
+                                "channels": 1,
+                                "prefixes": ["1800"],
+                                "schedule": [{ "type": "CallerId", "pattern": ".*", "negate": false }]
+                            }]
+                        }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut names: Vec<String> = validate(&config).into_iter().map(|i| i.name).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "backup",
+                "backup/hotline",
+                "pstn",
+                "pstn/nobody",
+                "pstn/nobody"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_voicemail_drop_prefix() {
+        use crate::callback::CallbackConfig;
+        use crate::voicemail::VoicemailDropConfig;
+
+        let mut config = Config {
+            callbacks: Some(CallbackConfig::default()),
+            voicemail_drop: Some(VoicemailDropConfig::default()),
+            ..Default::default()
+        };
+        assert!(validate(&config).is_empty());
+
+        config.voicemail_drop = Some(VoicemailDropConfig {
+            prefix: "*66".to_string(),
+        });
+        let issues = validate(&config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].section, "voicemail_drop");
+    }
+
+    #[test]
+    fn test_validate_account_codes() {
+        use crate::account_codes::AccountCode;
+
+        let mut config = Config::default();
+        let mut codes = AccountCodeConfig::default();
+        for code in ["1001", "1001", "12*3"] {
+            codes.codes.push(AccountCode {
+                code: code.to_string(),
+                description: None,
+            });
+        }
+        config.account_codes = Some(codes);
+
+        let issues = validate(&config);
+        let messages: Vec<(&str, &str)> = issues
+            .iter()
+            .map(|i| (i.name.as_str(), i.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("1001", "duplicate code"),
+                ("12*3", "code must be non-empty and not contain '*'"),
+            ]
+        );
+    }
+}
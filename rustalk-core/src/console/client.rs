@@ -0,0 +1,132 @@
+//! Remote console client
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{BufReader as PemReader, Cursor};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use crate::io;
+
+type Stream = TlsStream<TcpStream>;
+
+/// A connection to a server's remote console
+pub struct ConsoleClient {
+    lines: Lines<BufReader<ReadHalf<Stream>>>,
+    writer: WriteHalf<Stream>,
+}
+
+impl ConsoleClient {
+    /// Connect to `address` (`host:port`), trusting the certificates in the
+    /// PEM file `ca`, or the public roots without one
+    pub async fn connect(address: &str, ca: Option<&Path>) -> Result<Self> {
+        let (host, _) = address
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Console address {} has no port", address))?;
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let mut roots = RootCertStore::empty();
+        match ca {
+            Some(path) => {
+                let pem = io::fs::read(path).await?;
+                for cert in rustls_pemfile::certs(&mut PemReader::new(Cursor::new(pem))) {
+                    roots.add(cert?)?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let tls = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let tcp = TcpStream::connect(address)
+            .await
+            .with_context(|| format!("Cannot connect to the remote console at {}", address))?;
+        let stream = TlsConnector::from(Arc::new(tls))
+            .connect(ServerName::try_from(host)?, tcp)
+            .await
+            .context("TLS handshake failed")?;
+        let (reader, writer) = tokio::io::split(stream);
+        let mut client = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        client.status().await?;
+        Ok(client)
+    }
+
+    /// Sign in as `username`
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        if username.contains(char::is_whitespace) || password.contains(char::is_whitespace) {
+            bail!("Usernames and passwords cannot contain spaces");
+        }
+        self.send(&format!("login {} {}", username, password))
+            .await?;
+        self.status().await?;
+        Ok(())
+    }
+
+    /// Run a command, returning its output
+    pub async fn execute(&mut self, line: &str) -> Result<String> {
+        if line.contains(['\r', '\n']) {
+            bail!("Commands are a single line");
+        }
+        self.send(line).await?;
+        self.status().await?;
+        let mut output = String::new();
+        loop {
+            let line = self.read_line().await?;
+            if line == "." {
+                return Ok(output);
+            }
+            output.push_str(line.strip_prefix('.').unwrap_or(&line));
+            output.push('\n');
+        }
+    }
+
+    /// Sign off and close the connection
+    pub async fn close(mut self) -> Result<()> {
+        self.send("quit").await?;
+        self.status().await?;
+        self.writer.shutdown().await.ok();
+        Ok(())
+    }
+
+    async fn send(&mut self, line: &str) -> Result<()> {
+        self.writer
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        self.lines
+            .next_line()
+            .await?
+            .ok_or_else(|| anyhow!("The remote console closed the connection"))
+    }
+
+    /// Read a `+OK` or `-ERR` status line, the latter as an error
+    async fn status(&mut self) -> Result<String> {
+        let line = self.read_line().await?;
+        if let Some(text) = line.strip_prefix("+OK") {
+            return Ok(text.trim().to_string());
+        }
+        match line.strip_prefix("-ERR") {
+            Some(reason) => Err(anyhow!("{}", reason.trim())),
+            None => Err(anyhow!(
+                "Unexpected reply from the remote console: {}",
+                line
+            )),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Remote console
+//!
+//! `rustalk console --remote` reaches a running server over TLS rather than
+//! through the management API on the same host. Each operator signs in with
+//! an account of their own, whose command list decides what they may run:
+//! one account may only `show` calls while another may also `hangup` them.
+//! Passwords are stored as salted Argon2 hashes, which
+//! `rustalk console --hash-password` prints. Every connection, sign-in,
+//! refused sign-in and command is appended to an audit log, one JSON object
+//! per line.
+//!
+//! ```json
+//! {
+//!   "remote_console": {
+//!     "bind": "0.0.0.0:8021",
+//!     "cert_path": "/etc/rustalk/console.pem",
+//!     "key_path": "/etc/rustalk/console.key",
+//!     "users": [
+//!       { "username": "noc", "password_hash": "$argon2id$…", "commands": ["show"] },
+//!       { "username": "oncall", "password_hash": "$argon2id$…", "commands": ["show", "hangup"] }
+//!     ]
+//!   }
+//! }
+//! ```
+//!
+//! The protocol is line based. The server greets with `+OK`, the client
+//! signs in with `login <username> <password>` and then sends one command
+//! per line. Each reply starts with `+OK` or `-ERR <reason>`; a `+OK` reply
+//! to a command is followed by its output and a line holding a single `.`,
+//! output lines starting with `.` having it doubled as in POP3.
+
+pub mod client;
+pub mod server;
+
+pub use client::ConsoleClient;
+pub use server::{CommandHandler, RemoteConsole};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use rand::rngs::OsRng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Commands the remote console runs, which users' command lists name
+pub const COMMANDS: &[&str] = &["show", "hangup"];
+
+/// Remote console configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RemoteConsoleConfig {
+    #[serde(default = "default_bind")]
+    pub bind: SocketAddr,
+    /// PEM certificate presented to clients
+    pub cert_path: String,
+    pub key_path: String,
+    #[serde(default)]
+    pub users: Vec<ConsoleUser>,
+    /// File the audit records are appended to
+    #[serde(default = "default_audit_log")]
+    pub audit_log: PathBuf,
+    /// Connections silent for this long are closed
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout_seconds: u64,
+}
+
+fn default_bind() -> SocketAddr {
+    "0.0.0.0:8021".parse().unwrap()
+}
+
+fn default_audit_log() -> PathBuf {
+    PathBuf::from("logs/console-audit.log")
+}
+
+fn default_idle_timeout() -> u64 {
+    900
+}
+
+impl RemoteConsoleConfig {
+    /// The account `username` signs in to, if `password` is its password
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<&ConsoleUser> {
+        self.users
+            .iter()
+            .find(|user| user.username == username)
+            .filter(|user| verify_password(password, &user.password_hash))
+    }
+}
+
+/// An operator account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ConsoleUser {
+    pub username: String,
+    /// Argon2 hash of the password with its own salt, in PHC string format
+    /// (`$argon2id$v=19$...`)
+    pub password_hash: String,
+    /// Commands the user may run, or `*` for all of them; `help` and
+    /// `exit` are always allowed
+    #[serde(default = "default_commands")]
+    pub commands: Vec<String>,
+}
+
+fn default_commands() -> Vec<String> {
+    vec!["show".to_string()]
+}
+
+impl ConsoleUser {
+    /// Whether the user may run `command`, named by its first word
+    pub fn may_run(&self, command: &str) -> bool {
+        let command = command.to_lowercase();
+        matches!(command.as_str(), "help" | "?" | "exit" | "quit")
+            || self
+                .commands
+                .iter()
+                .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(&command))
+    }
+}
+
+/// Argon2id hash of a password under a fresh random salt, as stored in
+/// `password_hash`
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Argon2 accepts a generated salt")
+        .to_string()
+}
+
+/// Whether `hash` is a password hash the console can check passwords
+/// against
+pub fn is_password_hash(hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| hash.algorithm.as_str().starts_with("argon2"))
+}
+
+/// Whether `password` matches `hash`; the comparison does not return early
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Something that happened in a console session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Connected,
+    /// Wrong username or password; the password is never recorded
+    LoginFailed {
+        username: String,
+    },
+    Login,
+    Command {
+        line: String,
+        /// Whether the user's command list allowed it
+        allowed: bool,
+        /// Whether it ran without error
+        ok: bool,
+    },
+    Disconnected,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    /// Identifies the connection, shared by all its records
+    pub session: String,
+    pub peer: SocketAddr,
+    /// Signed-in user, once there is one
+    pub username: Option<String>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// Appends audit records to a file, opened on first use
+#[derive(Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Append a record; failing to write is logged, never fatal
+    pub async fn record(&self, record: &AuditRecord) {
+        let mut line = serde_json::to_string(record).unwrap_or_default();
+        line.push('\n');
+        let mut file = self.file.lock().await;
+        if file.is_none() {
+            if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                let _ = tokio::fs::create_dir_all(dir).await;
+            }
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+            {
+                Ok(opened) => *file = Some(opened),
+                Err(e) => {
+                    warn!("Cannot open audit log {}: {}", self.path.display(), e);
+                    return;
+                }
+            }
+        }
+        if let Some(open) = file.as_mut() {
+            if let Err(e) = open.write_all(line.as_bytes()).await {
+                warn!("Cannot write audit log {}: {}", self.path.display(), e);
+                *file = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RemoteConsoleConfig {
+        serde_json::from_value(serde_json::json!({
+            "cert_path": "console.pem",
+            "key_path": "console.key",
+            "users": [
+                { "username": "noc", "password_hash": hash_password("viewer") },
+                {
+                    "username": "oncall",
+                    "password_hash": hash_password("operator"),
+                    "commands": ["show", "hangup"]
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_authenticate_and_command_acls() {
+        let config = config();
+        assert_eq!(config.bind.port(), 8021);
+        assert!(config.authenticate("noc", "operator").is_none());
+        assert!(config.authenticate("nobody", "viewer").is_none());
+
+        let noc = config.authenticate("noc", "viewer").unwrap();
+        assert!(noc.may_run("show"));
+        assert!(noc.may_run("help"));
+        assert!(!noc.may_run("hangup"));
+
+        let oncall = config.authenticate("oncall", "operator").unwrap();
+        assert!(oncall.may_run("HANGUP"));
+        assert!(!oncall.may_run("reloadconfig"));
+    }
+
+    #[test]
+    fn test_password_hashes_are_salted() {
+        let (first, second) = (hash_password("viewer"), hash_password("viewer"));
+        assert!(first.starts_with("$argon2id$"));
+        assert_ne!(first, second);
+        assert!(verify_password("viewer", &second));
+        assert!(!verify_password("viewer", &hash_password("operator")));
+
+        assert!(is_password_hash(&first));
+        // Unsalted digests are not accepted
+        assert!(!is_password_hash(&"ab".repeat(32)));
+        assert!(!verify_password("viewer", "not a hash"));
+    }
+}
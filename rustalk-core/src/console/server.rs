@@ -0,0 +1,348 @@
+//! Remote console listener
+//!
+//! Accepts TLS connections, signs each operator in, checks every command
+//! against their command list and hands the allowed ones to a
+//! [`CommandHandler`], auditing all of it.
+
+use super::{AuditEvent, AuditLog, AuditRecord, ConsoleUser, RemoteConsoleConfig};
+use crate::io;
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use std::io::{BufReader as PemReader, Cursor};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+/// Sign-in attempts allowed before the connection is closed
+const MAX_LOGIN_ATTEMPTS: u32 = 3;
+
+/// Longest line a client may send, so nobody can make the server buffer an
+/// endless line, signed in or not
+const MAX_LINE_BYTES: u64 = 4096;
+
+/// Runs the commands operators send
+#[async_trait::async_trait]
+pub trait CommandHandler: Send + Sync {
+    /// Run a command line for `user`, returning its output
+    async fn execute(&self, user: &ConsoleUser, line: &str) -> Result<String>;
+}
+
+/// TLS listener for remote console connections
+pub struct RemoteConsole {
+    config: Arc<RemoteConsoleConfig>,
+    handler: Arc<dyn CommandHandler>,
+    acceptor: TlsAcceptor,
+    audit: AuditLog,
+}
+
+impl RemoteConsole {
+    /// Load the certificate and key the console presents
+    pub async fn new(
+        config: RemoteConsoleConfig,
+        handler: Arc<dyn CommandHandler>,
+    ) -> Result<Self> {
+        let pem = io::fs::read(&config.cert_path).await?;
+        let certs = rustls_pemfile::certs(&mut PemReader::new(Cursor::new(pem)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let pem = io::fs::read(&config.key_path).await?;
+        let key = rustls_pemfile::private_key(&mut PemReader::new(Cursor::new(pem)))?
+            .ok_or_else(|| anyhow!("No private key in {}", config.key_path))?;
+        let tls = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("Certificate and key do not match")?;
+        Ok(Self {
+            audit: AuditLog::new(config.audit_log.clone()),
+            config: Arc::new(config),
+            handler,
+            acceptor: TlsAcceptor::from(Arc::new(tls)),
+        })
+    }
+
+    /// Listen on the configured address until the listener fails
+    pub async fn run(self) -> Result<()> {
+        let listener = TcpListener::bind(self.config.bind)
+            .await
+            .with_context(|| format!("Cannot bind the remote console to {}", self.config.bind))?;
+        info!("Remote console listening on {}", self.config.bind);
+        self.serve(listener).await
+    }
+
+    /// Accept connections from `listener`
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        let console = Arc::new(self);
+        loop {
+            let (tcp, peer) = listener.accept().await?;
+            let console = console.clone();
+            tokio::spawn(async move {
+                match console.acceptor.accept(tcp).await {
+                    Ok(stream) => console.session(stream, peer).await,
+                    Err(e) => debug!("Remote console TLS handshake with {} failed: {}", peer, e),
+                }
+            });
+        }
+    }
+
+    async fn session<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S, peer: SocketAddr) {
+        let mut session = Session {
+            id: Uuid::new_v4().to_string(),
+            peer,
+            user: None,
+            audit: &self.audit,
+        };
+        session.record(AuditEvent::Connected).await;
+        info!("Remote console connection from {}", peer);
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        let idle = Duration::from_secs(self.config.idle_timeout_seconds.max(1));
+        let mut failures = 0;
+
+        let greeting = writer.write_all(b"+OK RusTalk remote console\r\n").await;
+        while greeting.is_ok() && failures < MAX_LOGIN_ATTEMPTS {
+            let line = match tokio::time::timeout(idle, read_line(&mut reader)).await {
+                Ok(Some(line)) => line,
+                _ => break,
+            };
+            let line = line.trim();
+            let command = line.split_whitespace().next().unwrap_or("").to_lowercase();
+            let reply = match (&session.user, command.as_str()) {
+                (_, "") => continue,
+                (_, "exit" | "quit") => {
+                    session.record(AuditEvent::Disconnected).await;
+                    let _ = writer.write_all(b"+OK bye\r\n").await;
+                    return;
+                }
+                (None, "login") => {
+                    let mut words = line.split_whitespace().skip(1);
+                    let username = words.next().unwrap_or("");
+                    let password = words.next().unwrap_or("");
+                    // Argon2 is slow on purpose; keep it off the runtime
+                    let config = self.config.clone();
+                    let (name, secret) = (username.to_string(), password.to_string());
+                    let signed_in = tokio::task::spawn_blocking(move || {
+                        config.authenticate(&name, &secret).cloned()
+                    })
+                    .await
+                    .ok()
+                    .flatten();
+                    match signed_in {
+                        Some(user) => {
+                            session.user = Some(user);
+                            session.record(AuditEvent::Login).await;
+                            info!("{} signed in to the remote console from {}", username, peer);
+                            format!("+OK signed in as {}\r\n", username)
+                        }
+                        None => {
+                            failures += 1;
+                            session
+                                .record(AuditEvent::LoginFailed {
+                                    username: username.to_string(),
+                                })
+                                .await;
+                            "-ERR sign-in failed\r\n".to_string()
+                        }
+                    }
+                }
+                (None, _) => "-ERR sign in first\r\n".to_string(),
+                // Never audited, as the line carries a password
+                (Some(_), "login") => "-ERR already signed in\r\n".to_string(),
+                (Some(user), _) if !user.may_run(&command) => {
+                    session.record(command_event(line, false, false)).await;
+                    format!("-ERR permission denied: {}\r\n", command)
+                }
+                (Some(user), _) => match self.handler.execute(user, line).await {
+                    Ok(output) => {
+                        session.record(command_event(line, true, true)).await;
+                        format!("+OK\r\n{}.\r\n", dot_stuff(&output))
+                    }
+                    Err(e) => {
+                        session.record(command_event(line, true, false)).await;
+                        format!("-ERR {}\r\n", e.to_string().replace(['\r', '\n'], " "))
+                    }
+                },
+            };
+            if writer.write_all(reply.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        session.record(AuditEvent::Disconnected).await;
+    }
+}
+
+/// A connection being served
+struct Session<'a> {
+    id: String,
+    peer: SocketAddr,
+    user: Option<ConsoleUser>,
+    audit: &'a AuditLog,
+}
+
+impl Session<'_> {
+    async fn record(&self, event: AuditEvent) {
+        self.audit
+            .record(&AuditRecord {
+                timestamp: Utc::now(),
+                session: self.id.clone(),
+                peer: self.peer,
+                username: self.user.as_ref().map(|u| u.username.clone()),
+                event,
+            })
+            .await;
+    }
+}
+
+fn command_event(line: &str, allowed: bool, ok: bool) -> AuditEvent {
+    AuditEvent::Command {
+        line: line.to_string(),
+        allowed,
+        ok,
+    }
+}
+
+/// Read a line of at most [`MAX_LINE_BYTES`] without its ending, or `None`
+/// at the end of the stream or when the line is longer or not UTF-8
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Option<String> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(MAX_LINE_BYTES + 1)
+        .read_until(b'\n', &mut line)
+        .await
+        .ok()?;
+    if read == 0 || (line.last() != Some(&b'\n') && read as u64 > MAX_LINE_BYTES) {
+        return None;
+    }
+    let line = String::from_utf8(line).ok()?;
+    Some(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Output lines with CRLF endings, a leading `.` doubled
+fn dot_stuff(output: &str) -> String {
+    output
+        .lines()
+        .map(|line| {
+            let line = if line.starts_with('.') {
+                format!(".{}", line)
+            } else {
+                line.to_string()
+            };
+            line + "\r\n"
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::{hash_password, ConsoleClient};
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl CommandHandler for Echo {
+        async fn execute(&self, user: &ConsoleUser, line: &str) -> Result<String> {
+            match line {
+                "hangup missing" => Err(anyhow!("no call missing")),
+                _ => Ok(format!("{} ran {}\n.hidden", user.username, line)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_line_is_capped() {
+        let mut input: &[u8] = b"login noc viewer\r\nshow calls";
+        assert_eq!(read_line(&mut input).await.unwrap(), "login noc viewer");
+        assert_eq!(read_line(&mut input).await.unwrap(), "show calls");
+        assert_eq!(read_line(&mut input).await, None);
+
+        let longest = "a".repeat(MAX_LINE_BYTES as usize - 1) + "\n";
+        assert!(read_line(&mut longest.as_bytes()).await.is_some());
+        let endless = "a".repeat(MAX_LINE_BYTES as usize * 4);
+        assert_eq!(read_line(&mut endless.as_bytes()).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_remote_console_session() {
+        let dir = std::env::temp_dir().join(format!("rustalk-console-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let (cert_path, key_path) = (dir.join("console.pem"), dir.join("console.key"));
+        tokio::fs::write(&cert_path, cert.pem()).await.unwrap();
+        tokio::fs::write(&key_path, key.serialize_pem())
+            .await
+            .unwrap();
+        let audit_log = dir.join("audit.log");
+
+        let config: RemoteConsoleConfig = serde_json::from_value(serde_json::json!({
+            "cert_path": cert_path,
+            "key_path": key_path,
+            "audit_log": audit_log,
+            "users": [{ "username": "noc", "password_hash": hash_password("viewer") }]
+        }))
+        .unwrap();
+        let console = RemoteConsole::new(config, Arc::new(Echo)).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(console.serve(listener));
+
+        let address = format!("localhost:{}", port);
+        let mut client = ConsoleClient::connect(&address, Some(&cert_path))
+            .await
+            .unwrap();
+        assert!(client.execute("show calls").await.is_err());
+        assert!(client.login("noc", "wrong").await.is_err());
+        client.login("noc", "viewer").await.unwrap();
+
+        // Output comes back whole, dots and all
+        assert_eq!(
+            client.execute("show calls").await.unwrap(),
+            "noc ran show calls\n.hidden\n"
+        );
+        let denied = client.execute("hangup abc").await.unwrap_err();
+        assert_eq!(denied.to_string(), "permission denied: hangup");
+        client.close().await.unwrap();
+
+        // Every step is audited, without the password
+        let audit = tokio::fs::read_to_string(&audit_log).await.unwrap();
+        assert!(!audit.contains("viewer"));
+        let events: Vec<serde_json::Value> = audit
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let summary: Vec<(String, serde_json::Value)> = events
+            .iter()
+            .map(|e| {
+                (
+                    e["event"].as_str().unwrap().to_string(),
+                    e["allowed"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("connected".to_string(), serde_json::Value::Null),
+                ("login_failed".to_string(), serde_json::Value::Null),
+                ("login".to_string(), serde_json::Value::Null),
+                ("command".to_string(), serde_json::json!(true)),
+                ("command".to_string(), serde_json::json!(false)),
+                ("disconnected".to_string(), serde_json::Value::Null),
+            ]
+        );
+        assert_eq!(events[3]["username"], "noc");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
@@ -0,0 +1,382 @@
+//! Class of service (calling permissions)
+//!
+//! Every dialed number is classified (internal, local, national,
+//! international, premium, emergency) and checked against the COS profile
+//! assigned to the calling extension before the call is routed to a trunk.
+//! A denied call can still go through when the caller supplies an override
+//! PIN whose profile allows the class.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// INVITE header carrying an override PIN
+pub const OVERRIDE_PIN_HEADER: &str = "X-Override-PIN";
+
+/// Kind of number being dialed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CallClass {
+    Emergency,
+    Internal,
+    Local,
+    National,
+    International,
+    Premium,
+}
+
+impl fmt::Display for CallClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CallClass::Emergency => "emergency",
+            CallClass::Internal => "internal",
+            CallClass::Local => "local",
+            CallClass::National => "national",
+            CallClass::International => "international",
+            CallClass::Premium => "premium",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Named set of call classes an extension may dial
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CosProfile {
+    pub name: String,
+    pub allowed: Vec<CallClass>,
+}
+
+impl CosProfile {
+    pub fn allows(&self, class: CallClass) -> bool {
+        self.allowed.contains(&class)
+    }
+}
+
+/// Pattern that assigns a call class to dialed numbers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ClassPattern {
+    pub class: CallClass,
+    /// Regex matched against the dialed number
+    pub pattern: String,
+}
+
+/// PIN that lets a caller place calls under another profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct OverridePin {
+    pub pin: String,
+    pub profile: String,
+    /// Restrict the PIN to one extension
+    pub extension: Option<String>,
+}
+
+/// Class of service configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CosConfig {
+    #[serde(default = "default_profiles")]
+    pub profiles: Vec<CosProfile>,
+    /// Profile for extensions without an assignment
+    #[serde(default = "default_profile_name")]
+    pub default_profile: String,
+    /// Profile assigned to each extension
+    #[serde(default)]
+    pub extensions: HashMap<String, String>,
+    /// Number classification, first match wins; unmatched numbers are
+    /// treated as international
+    #[serde(default = "default_patterns")]
+    pub patterns: Vec<ClassPattern>,
+    #[serde(default)]
+    pub override_pins: Vec<OverridePin>,
+}
+
+impl Default for CosConfig {
+    fn default() -> Self {
+        Self {
+            profiles: default_profiles(),
+            default_profile: default_profile_name(),
+            extensions: HashMap::new(),
+            patterns: default_patterns(),
+            override_pins: Vec::new(),
+        }
+    }
+}
+
+fn default_profile_name() -> String {
+    "premium_blocked".to_string()
+}
+
+/// Built-in profiles: internal only, national, international and
+/// everything except premium numbers
+pub fn default_profiles() -> Vec<CosProfile> {
+    use CallClass::*;
+    let profile = |name: &str, allowed: &[CallClass]| CosProfile {
+        name: name.to_string(),
+        allowed: allowed.to_vec(),
+    };
+    vec![
+        profile("internal_only", &[Emergency, Internal]),
+        profile("national", &[Emergency, Internal, Local, National]),
+        profile(
+            "international",
+            &[Emergency, Internal, Local, National, International],
+        ),
+        profile(
+            "premium_blocked",
+            &[Emergency, Internal, Local, National, International],
+        ),
+        profile(
+            "unrestricted",
+            &[Emergency, Internal, Local, National, International, Premium],
+        ),
+    ]
+}
+
+/// North American numbering plan classification
+pub fn default_patterns() -> Vec<ClassPattern> {
+    let pattern = |class: CallClass, pattern: &str| ClassPattern {
+        class,
+        pattern: pattern.to_string(),
+    };
+    vec![
+        pattern(CallClass::Emergency, r"^(911|933|112)$"),
+        pattern(CallClass::Internal, r"^\d{2,6}$"),
+        pattern(CallClass::Premium, r"^(\+?1)?(900|976)\d{7}$"),
+        pattern(CallClass::Local, r"^[2-9]\d{6}$"),
+        pattern(CallClass::National, r"^(\+?1)?[2-9]\d{9}$"),
+        pattern(CallClass::International, r"^(\+|011|00)\d+$"),
+    ]
+}
+
+/// Outcome of a class of service check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "lowercase")]
+pub enum CosDecision {
+    Allow {
+        class: CallClass,
+        profile: String,
+        /// Allowed only because of an override PIN
+        authorized_by_pin: bool,
+    },
+    Deny {
+        class: CallClass,
+        profile: String,
+        reason: String,
+    },
+}
+
+impl CosDecision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, CosDecision::Allow { .. })
+    }
+
+    pub fn class(&self) -> CallClass {
+        match self {
+            CosDecision::Allow { class, .. } | CosDecision::Deny { class, .. } => *class,
+        }
+    }
+}
+
+/// Compiled number classification patterns
+#[derive(Debug, Clone)]
+pub struct NumberClassifier {
+    patterns: Vec<(CallClass, Regex)>,
+}
+
+impl NumberClassifier {
+    pub fn new(patterns: &[ClassPattern]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|p| {
+                Regex::new(&p.pattern)
+                    .map(|regex| (p.class, regex))
+                    .with_context(|| format!("Invalid {} pattern '{}'", p.class, p.pattern))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Classify a dialed number
+    pub fn classify(&self, number: &str) -> CallClass {
+        self.patterns
+            .iter()
+            .find(|(_, regex)| regex.is_match(number))
+            .map(|(class, _)| *class)
+            .unwrap_or(CallClass::International)
+    }
+}
+
+impl Default for NumberClassifier {
+    fn default() -> Self {
+        Self::new(&default_patterns()).expect("default patterns compile")
+    }
+}
+
+/// Compiled class of service configuration
+#[derive(Debug, Clone)]
+pub struct CosPolicy {
+    config: CosConfig,
+    classifier: NumberClassifier,
+}
+
+impl CosPolicy {
+    pub fn new(config: CosConfig) -> Result<Self> {
+        let classifier = NumberClassifier::new(&config.patterns)?;
+        Ok(Self { config, classifier })
+    }
+
+    pub fn config(&self) -> &CosConfig {
+        &self.config
+    }
+
+    /// Classify a dialed number
+    pub fn classify(&self, number: &str) -> CallClass {
+        self.classifier.classify(number)
+    }
+
+    /// Name of the profile that applies to `extension`
+    pub fn profile_name(&self, extension: &str) -> &str {
+        self.config
+            .extensions
+            .get(extension)
+            .unwrap_or(&self.config.default_profile)
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&CosProfile> {
+        self.config.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Check whether `extension` may dial `number`, optionally with an
+    /// override PIN
+    pub fn check(&self, extension: &str, number: &str, pin: Option<&str>) -> CosDecision {
+        let class = self.classify(number);
+        let profile = self.profile_name(extension).to_string();
+
+        // An unknown profile allows nothing but emergency calls
+        if self.profile(&profile).is_some_and(|p| p.allows(class)) || class == CallClass::Emergency
+        {
+            return CosDecision::Allow {
+                class,
+                profile,
+                authorized_by_pin: false,
+            };
+        }
+
+        let Some(pin) = pin else {
+            return CosDecision::Deny {
+                reason: format!("{} calls are not allowed by profile {}", class, profile),
+                class,
+                profile,
+            };
+        };
+
+        let granted = self
+            .config
+            .override_pins
+            .iter()
+            .filter(|p| p.pin == pin)
+            .filter(|p| p.extension.as_deref().is_none_or(|e| e == extension))
+            .find(|p| self.profile(&p.profile).is_some_and(|p| p.allows(class)));
+        match granted {
+            Some(grant) => CosDecision::Allow {
+                class,
+                profile: grant.profile.clone(),
+                authorized_by_pin: true,
+            },
+            None => CosDecision::Deny {
+                reason: format!("override PIN does not permit {} calls", class),
+                class,
+                profile,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> CosPolicy {
+        let mut config = CosConfig::default();
+        config
+            .extensions
+            .insert("1001".to_string(), "internal_only".to_string());
+        config
+            .extensions
+            .insert("1002".to_string(), "national".to_string());
+        config.override_pins.push(OverridePin {
+            pin: "4321".to_string(),
+            profile: "international".to_string(),
+            extension: None,
+        });
+        config.override_pins.push(OverridePin {
+            pin: "9999".to_string(),
+            profile: "unrestricted".to_string(),
+            extension: Some("1003".to_string()),
+        });
+        CosPolicy::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_classify() {
+        let policy = policy();
+        assert_eq!(policy.classify("911"), CallClass::Emergency);
+        assert_eq!(policy.classify("1002"), CallClass::Internal);
+        assert_eq!(policy.classify("5551234"), CallClass::Local);
+        assert_eq!(policy.classify("+12125551234"), CallClass::National);
+        assert_eq!(policy.classify("19005551234"), CallClass::Premium);
+        assert_eq!(policy.classify("011442071234567"), CallClass::International);
+        assert_eq!(policy.classify("*98"), CallClass::International);
+    }
+
+    #[test]
+    fn test_check_profiles_and_override_pins() {
+        let policy = policy();
+
+        assert!(policy.check("1001", "1002", None).is_allowed());
+        assert!(policy.check("1001", "911", None).is_allowed());
+        match policy.check("1001", "2125551234", None) {
+            CosDecision::Deny { reason, .. } => {
+                assert_eq!(
+                    reason,
+                    "national calls are not allowed by profile internal_only"
+                )
+            }
+            other => panic!("expected deny, got {:?}", other),
+        }
+
+        // Unassigned extensions use the default premium_blocked profile
+        assert!(policy.check("2000", "011442071234567", None).is_allowed());
+        assert!(!policy.check("2000", "19005551234", None).is_allowed());
+
+        assert_eq!(
+            policy.check("1002", "011442071234567", Some("4321")),
+            CosDecision::Allow {
+                class: CallClass::International,
+                profile: "international".to_string(),
+                authorized_by_pin: true,
+            }
+        );
+        assert!(!policy
+            .check("1002", "011442071234567", Some("0000"))
+            .is_allowed());
+        // PINs bound to another extension do not apply
+        assert!(!policy
+            .check("1002", "19005551234", Some("9999"))
+            .is_allowed());
+        assert!(policy
+            .check("1003", "19005551234", Some("9999"))
+            .is_allowed());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut config = CosConfig::default();
+        config.patterns.push(ClassPattern {
+            class: CallClass::Local,
+            pattern: "^(".to_string(),
+        });
+        assert!(CosPolicy::new(config).is_err());
+    }
+}
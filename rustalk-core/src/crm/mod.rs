@@ -0,0 +1,181 @@
+//! CRM lookup for screen-pops
+//!
+//! When a call starts ringing, the caller's number is posted to a CRM
+//! webhook. The customer name, ticket URL and any other fields it returns
+//! are attached to the ringing [`CallEvent`](crate::events::CallEvent) so
+//! screen-pop apps can show who is calling before the call is answered.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// CRM webhook configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CrmConfig {
+    /// Webhook that receives `{"number": ..., "call_id": ...}`
+    pub url: String,
+    /// Extra request headers, e.g. an API key
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Ringing events wait at most this long for the CRM
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How long answers, including "not found", are reused
+    #[serde(default = "default_cache_seconds")]
+    pub cache_seconds: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    1500
+}
+
+fn default_cache_seconds() -> u64 {
+    300
+}
+
+/// Caller details returned by the CRM
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CrmContact {
+    pub customer_name: Option<String>,
+    pub ticket_url: Option<String>,
+    /// Any other fields the CRM returned
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Looks callers up in the CRM
+pub struct CrmClient {
+    config: CrmConfig,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<String, (Instant, Option<CrmContact>)>>,
+}
+
+impl CrmClient {
+    pub fn new(config: CrmConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Contact for a calling number, or `None` when the CRM does not know
+    /// it or cannot be reached in time
+    pub async fn lookup(&self, number: &str, call_id: &str) -> Option<CrmContact> {
+        let ttl = Duration::from_secs(self.config.cache_seconds);
+        if let Some((at, contact)) = self.cache.lock().unwrap().get(number) {
+            if at.elapsed() < ttl {
+                return contact.clone();
+            }
+        }
+
+        let contact = match self.fetch(number, call_id).await {
+            Ok(contact) => contact,
+            Err(e) => {
+                // Failures are not cached so the next call tries again
+                warn!("CRM lookup for {} failed: {:#}", number, e);
+                return None;
+            }
+        };
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        cache.insert(number.to_string(), (Instant::now(), contact.clone()));
+        contact
+    }
+
+    async fn fetch(&self, number: &str, call_id: &str) -> anyhow::Result<Option<CrmContact>> {
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .timeout(Duration::from_millis(self.config.timeout_ms))
+            .json(&json!({ "number": number, "call_id": call_id }));
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await?;
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::NO_CONTENT => Ok(None),
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            status => Err(anyhow::anyhow!("CRM returned {}", status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Answer every request with `status` and `body`, counting requests
+    async fn serve(status: &'static str, body: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/lookup", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    fn client(url: String) -> CrmClient {
+        CrmClient::new(CrmConfig {
+            url,
+            headers: HashMap::from([("X-Api-Key".to_string(), "secret".to_string())]),
+            timeout_ms: default_timeout_ms(),
+            cache_seconds: default_cache_seconds(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_lookup_and_cache() {
+        let (url, requests) = serve(
+            "200 OK",
+            r#"{"customer_name":"Acme Corp","ticket_url":"https://crm.example.com/t/42","tier":"gold"}"#,
+        )
+        .await;
+        let crm = client(url);
+
+        let contact = crm.lookup("+12125551234", "call-1").await.unwrap();
+        assert_eq!(contact.customer_name.as_deref(), Some("Acme Corp"));
+        assert_eq!(
+            contact.ticket_url.as_deref(),
+            Some("https://crm.example.com/t/42")
+        );
+        assert_eq!(contact.extra["tier"], "gold");
+
+        crm.lookup("+12125551234", "call-2").await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_and_failing_crm() {
+        let (url, _) = serve("404 Not Found", "").await;
+        assert_eq!(client(url).lookup("+12125551234", "call-1").await, None);
+
+        let (url, requests) = serve("500 Internal Server Error", "").await;
+        let crm = client(url);
+        assert_eq!(crm.lookup("+12125551234", "call-1").await, None);
+        assert_eq!(crm.lookup("+12125551234", "call-2").await, None);
+        // Errors are retried rather than cached
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
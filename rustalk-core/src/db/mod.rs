@@ -0,0 +1,460 @@
+//! Database schema migrations
+//!
+//! The schema is defined by the SQL files in `rustalk-core/migrations`, one
+//! directory for each supported database, which are embedded in the binary
+//! when it is built, so a release always carries the schema it was written
+//! against. `rustalk db migrate` applies
+//! the ones a database is missing and `rustalk db status` reports what has
+//! been applied. Applied migrations are recorded by sqlx in
+//! `_sqlx_migrations`.
+//!
+//! The server checks the schema before it starts and refuses to run while
+//! migrations are pending, unless `auto_migrate` is set, or when the schema
+//! is incompatible: a migration failed part way, one was changed after it
+//! was applied, or the database was migrated by a newer release.
+//!
+//! The database is PostgreSQL (`postgres://`) or SQLite (`sqlite://`). Both
+//! have the same tables and migration versions; SQLite keeps JSON as text
+//! and times as seconds since the epoch. Other URLs are refused when
+//! connecting and flagged by configuration validation.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sqlx::migrate::{Migration, Migrator};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::info;
+
+use crate::config::DatabaseConfig;
+
+/// Migrations embedded from `rustalk-core/migrations/postgres`
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+
+/// Migrations embedded from `rustalk-core/migrations/sqlite`
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// How long to wait for a database connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connection pool of the configured database
+#[derive(Debug, Clone)]
+pub enum Database {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+impl Database {
+    /// Migrations of this kind of database
+    pub fn migrator(&self) -> &'static Migrator {
+        match self {
+            Database::Postgres(_) => &POSTGRES_MIGRATOR,
+            Database::Sqlite(_) => &SQLITE_MIGRATOR,
+        }
+    }
+
+    /// Close every connection of the pool
+    pub async fn close(&self) {
+        match self {
+            Database::Postgres(pool) => pool.close().await,
+            Database::Sqlite(pool) => pool.close().await,
+        }
+    }
+}
+
+/// A migration recorded as applied to the database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub description: String,
+    /// False when the migration failed part way
+    pub success: bool,
+    #[serde(skip)]
+    pub checksum: Vec<u8>,
+}
+
+/// Whether this release can run against a database's schema
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SchemaStatus {
+    /// Every migration of this release is applied
+    Current,
+    /// Migrations of this release not yet applied
+    Pending { versions: Vec<i64> },
+    /// The schema cannot be migrated by this release
+    Incompatible { reason: String },
+}
+
+/// One migration of this release and whether it has been applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationInfo {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// Schema of a database compared with this release's migrations
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaReport {
+    /// Latest migration applied to the database
+    pub version: Option<i64>,
+    /// Latest migration of this release
+    pub expected: Option<i64>,
+    pub status: SchemaStatus,
+    pub migrations: Vec<MigrationInfo>,
+}
+
+/// Compare the migrations applied to a database with `migrations`
+pub fn check(migrations: &[Migration], applied: &[AppliedMigration]) -> SchemaReport {
+    let migrations: Vec<&Migration> = migrations
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .collect();
+    let version = applied.iter().map(|a| a.version).max();
+    let expected = migrations.iter().map(|m| m.version).max();
+
+    let status = match incompatibility(&migrations, applied, expected) {
+        Some(reason) => SchemaStatus::Incompatible { reason },
+        None => {
+            let versions: Vec<i64> = migrations
+                .iter()
+                .map(|m| m.version)
+                .filter(|v| !applied.iter().any(|a| a.version == *v))
+                .collect();
+            if versions.is_empty() {
+                SchemaStatus::Current
+            } else {
+                SchemaStatus::Pending { versions }
+            }
+        }
+    };
+
+    SchemaReport {
+        version,
+        expected,
+        status,
+        migrations: migrations
+            .iter()
+            .map(|m| MigrationInfo {
+                version: m.version,
+                description: m.description.to_string(),
+                applied: applied.iter().any(|a| a.version == m.version && a.success),
+            })
+            .collect(),
+    }
+}
+
+/// Why this release cannot migrate the schema, if it cannot
+fn incompatibility(
+    migrations: &[&Migration],
+    applied: &[AppliedMigration],
+    expected: Option<i64>,
+) -> Option<String> {
+    for migration in applied {
+        if !migration.success {
+            return Some(format!(
+                "migration {} ({}) failed part way; repair the database before migrating again",
+                migration.version, migration.description
+            ));
+        }
+        match migrations.iter().find(|m| m.version == migration.version) {
+            Some(known) if *known.checksum != *migration.checksum => {
+                return Some(format!(
+                    "migration {} ({}) was changed after it was applied",
+                    migration.version, migration.description
+                ));
+            }
+            Some(_) => {}
+            None if expected.is_none_or(|e| migration.version > e) => {
+                return Some(format!(
+                    "schema version {} is newer than this release supports ({}); upgrade RusTalk",
+                    migration.version,
+                    expected.map_or("none".to_string(), |e| e.to_string())
+                ));
+            }
+            None => {
+                return Some(format!(
+                    "migration {} ({}) is not known to this release",
+                    migration.version, migration.description
+                ));
+            }
+        }
+    }
+    None
+}
+
+/// Whether `url` names a PostgreSQL database
+pub fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+/// Whether `url` names a SQLite database
+pub fn is_sqlite_url(url: &str) -> bool {
+    url.starts_with("sqlite:")
+}
+
+/// Connect to the configured database, PostgreSQL or SQLite by its URL
+///
+/// A SQLite database file is created when it does not exist yet.
+pub async fn connect(config: &DatabaseConfig) -> Result<Database> {
+    let max_connections = config.max_connections.max(1);
+    if is_postgres_url(&config.url) {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(CONNECT_TIMEOUT)
+            .connect(&config.url)
+            .await
+            .context("connecting to the database")?;
+        return Ok(Database::Postgres(pool));
+    }
+    if is_sqlite_url(&config.url) {
+        let options = SqliteConnectOptions::from_str(&config.url)
+            .context("parsing database.url")?
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(CONNECT_TIMEOUT)
+            .connect_with(options)
+            .await
+            .context("connecting to the database")?;
+        return Ok(Database::Sqlite(pool));
+    }
+    bail!("database.url must be a postgres://, postgresql:// or sqlite:// URL")
+}
+
+type AppliedRow = (i64, String, bool, Vec<u8>);
+
+/// Migrations recorded as applied, oldest first
+pub async fn applied_migrations(database: &Database) -> Result<Vec<AppliedMigration>> {
+    const APPLIED: &str =
+        "SELECT version, description, success, checksum FROM _sqlx_migrations ORDER BY version";
+    // Nothing has been applied to a database never migrated
+    let rows: Vec<AppliedRow> = match database {
+        Database::Postgres(pool) => {
+            let migrated: bool =
+                sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+                    .fetch_one(pool)
+                    .await
+                    .context("checking for the migrations table")?;
+            if !migrated {
+                return Ok(Vec::new());
+            }
+            sqlx::query_as(APPLIED).fetch_all(pool).await
+        }
+        Database::Sqlite(pool) => {
+            let migrated: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master \
+                 WHERE type = 'table' AND name = '_sqlx_migrations')",
+            )
+            .fetch_one(pool)
+            .await
+            .context("checking for the migrations table")?;
+            if !migrated {
+                return Ok(Vec::new());
+            }
+            sqlx::query_as(APPLIED).fetch_all(pool).await
+        }
+    }
+    .context("reading applied migrations")?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(version, description, success, checksum)| AppliedMigration {
+                version,
+                description,
+                success,
+                checksum,
+            },
+        )
+        .collect())
+}
+
+/// Schema of the database compared with this release's migrations
+pub async fn status(database: &Database) -> Result<SchemaReport> {
+    Ok(check(
+        &database.migrator().migrations,
+        &applied_migrations(database).await?,
+    ))
+}
+
+/// Apply pending migrations, returning the versions applied
+pub async fn migrate(database: &Database) -> Result<Vec<i64>> {
+    let report = status(database).await?;
+    match report.status {
+        SchemaStatus::Current => Ok(Vec::new()),
+        SchemaStatus::Incompatible { reason } => bail!("Incompatible database schema: {}", reason),
+        SchemaStatus::Pending { versions } => {
+            match database {
+                Database::Postgres(pool) => database.migrator().run(pool).await,
+                Database::Sqlite(pool) => database.migrator().run(pool).await,
+            }
+            .context("applying migrations")?;
+            info!("Applied {} migration(s)", versions.len());
+            Ok(versions)
+        }
+    }
+}
+
+/// Check the schema before starting, applying pending migrations when
+/// `config.auto_migrate` is set
+///
+/// Fails when the schema is incompatible, or has migrations pending and
+/// they are not to be applied automatically.
+pub async fn ensure_compatible(
+    database: &Database,
+    config: &DatabaseConfig,
+) -> Result<SchemaReport> {
+    let report = status(database).await?;
+    match &report.status {
+        SchemaStatus::Current => Ok(report),
+        SchemaStatus::Incompatible { reason } => {
+            bail!("Refusing to start on an incompatible database schema: {}", reason)
+        }
+        SchemaStatus::Pending { .. } if config.auto_migrate => {
+            migrate(database).await?;
+            status(database).await
+        }
+        SchemaStatus::Pending { versions } => bail!(
+            "Database schema is at version {} but this release expects {}, with {} migration(s) pending; run `rustalk db migrate`",
+            report.version.map_or("none".to_string(), |v| v.to_string()),
+            report.expected.unwrap_or_default(),
+            versions.len()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::migrate::MigrationType;
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(
+            version,
+            format!("migration {}", version).into(),
+            MigrationType::Simple,
+            sql.into(),
+        )
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            description: migration.description.to_string(),
+            success: true,
+            checksum: migration.checksum.to_vec(),
+        }
+    }
+
+    fn memory() -> DatabaseConfig {
+        DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            max_connections: 1,
+            min_connections: 0,
+            auto_migrate: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_database_urls() {
+        assert!(is_postgres_url("postgres://rustalk@db/rustalk"));
+        assert!(is_postgres_url("postgresql://db/rustalk"));
+        assert!(is_sqlite_url("sqlite://rustalk.db"));
+
+        let config = DatabaseConfig {
+            url: "mysql://db/rustalk".to_string(),
+            ..memory()
+        };
+        let error = connect(&config).await.unwrap_err();
+        assert!(error.to_string().contains("sqlite://"));
+    }
+
+    #[test]
+    fn test_embedded_migrations() {
+        for migrator in [&POSTGRES_MIGRATOR, &SQLITE_MIGRATOR] {
+            let versions: Vec<i64> = migrator.iter().map(|m| m.version).collect();
+            assert!(!versions.is_empty());
+            assert!(versions.windows(2).all(|w| w[0] < w[1]));
+
+            let report = check(&migrator.migrations, &[]);
+            assert_eq!(report.version, None);
+            assert_eq!(report.expected, versions.last().copied());
+            assert_eq!(report.status, SchemaStatus::Pending { versions });
+        }
+
+        // Both databases are at the same version after the same migrations
+        let descriptions = |migrator: &Migrator| {
+            migrator
+                .iter()
+                .map(|m| (m.version, m.description.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            descriptions(&POSTGRES_MIGRATOR),
+            descriptions(&SQLITE_MIGRATOR)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_sqlite() {
+        let config = memory();
+        let database = connect(&config).await.unwrap();
+        assert!(matches!(database, Database::Sqlite(_)));
+        assert!(ensure_compatible(&database, &config).await.is_err());
+
+        let applied = migrate(&database).await.unwrap();
+        assert_eq!(applied.len(), SQLITE_MIGRATOR.iter().count());
+        let report = status(&database).await.unwrap();
+        assert_eq!(report.status, SchemaStatus::Current);
+        assert!(migrate(&database).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_schema_status() {
+        let migrations = [
+            migration(1, "CREATE TABLE a (id INT)"),
+            migration(2, "CREATE TABLE b (id INT)"),
+        ];
+
+        let report = check(&migrations, &[applied(&migrations[0])]);
+        assert_eq!(report.version, Some(1));
+        assert_eq!(report.expected, Some(2));
+        assert_eq!(report.status, SchemaStatus::Pending { versions: vec![2] });
+        assert!(report.migrations[0].applied);
+        assert!(!report.migrations[1].applied);
+
+        let all = [applied(&migrations[0]), applied(&migrations[1])];
+        assert_eq!(check(&migrations, &all).status, SchemaStatus::Current);
+    }
+
+    #[test]
+    fn test_incompatible_schemas() {
+        let migrations = [
+            migration(1, "CREATE TABLE a (id INT)"),
+            migration(3, "CREATE TABLE c (id INT)"),
+        ];
+        let reason = |applied: &[AppliedMigration]| match check(&migrations, applied).status {
+            SchemaStatus::Incompatible { reason } => reason,
+            status => panic!("expected an incompatible schema, got {:?}", status),
+        };
+
+        // Migrated by a newer release
+        let newer = applied(&migration(4, "CREATE TABLE d (id INT)"));
+        assert!(reason(&[applied(&migrations[0]), newer]).contains("newer than this release"));
+
+        // A migration this release never had
+        let unknown = applied(&migration(2, "CREATE TABLE b (id INT)"));
+        assert!(reason(&[applied(&migrations[0]), unknown]).contains("not known"));
+
+        // Edited after it was applied
+        let edited = applied(&migration(1, "CREATE TABLE a (id BIGINT)"));
+        assert!(reason(&[edited]).contains("changed after it was applied"));
+
+        let mut failed = applied(&migrations[0]);
+        failed.success = false;
+        assert!(reason(&[failed]).contains("failed part way"));
+    }
+}
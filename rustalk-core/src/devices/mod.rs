@@ -0,0 +1,509 @@
+//! Device inventory
+//!
+//! Records the phones and softphones that register for each extension: who
+//! made them and what firmware they run (fingerprinted from the User-Agent),
+//! where they last registered from and, when known, their MAC address. A
+//! lost or stolen device can be revoked; the registrar then refuses its
+//! REGISTERs.
+//!
+//! A device is identified by its `+sip.instance` when the Contact carries
+//! one, otherwise by its MAC address, otherwise by the vendor and model in
+//! its User-Agent. Revoking a device therefore does not stop someone who
+//! changes the User-Agent; rotate the extension's password as well.
+
+pub mod provisioning;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// Broad category of a device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    DeskPhone,
+    Softphone,
+    /// ATAs, PBXs and other gateways registering on behalf of phones
+    Gateway,
+    #[default]
+    Unknown,
+}
+
+/// What a User-Agent string reveals about a device
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserAgentInfo {
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    pub kind: DeviceKind,
+    pub mac: Option<String>,
+}
+
+/// Known vendors: lower-case marker, vendor name and kind
+const VENDORS: &[(&str, &str, DeviceKind)] = &[
+    ("yealink", "Yealink", DeviceKind::DeskPhone),
+    ("polycom", "Polycom", DeviceKind::DeskPhone),
+    ("grandstream", "Grandstream", DeviceKind::DeskPhone),
+    ("snom", "Snom", DeviceKind::DeskPhone),
+    ("cisco", "Cisco", DeviceKind::DeskPhone),
+    ("fanvil", "Fanvil", DeviceKind::DeskPhone),
+    ("avaya", "Avaya", DeviceKind::DeskPhone),
+    ("mitel", "Mitel", DeviceKind::DeskPhone),
+    ("gigaset", "Gigaset", DeviceKind::DeskPhone),
+    ("obihai", "Obihai", DeviceKind::Gateway),
+    ("linksys", "Linksys", DeviceKind::Gateway),
+    ("asterisk", "Asterisk", DeviceKind::Gateway),
+    ("freeswitch", "FreeSWITCH", DeviceKind::Gateway),
+    ("zoiper", "Zoiper", DeviceKind::Softphone),
+    ("linphone", "Linphone", DeviceKind::Softphone),
+    ("microsip", "MicroSIP", DeviceKind::Softphone),
+    ("bria", "CounterPath", DeviceKind::Softphone),
+    ("x-lite", "CounterPath", DeviceKind::Softphone),
+    ("groundwire", "Acrobits", DeviceKind::Softphone),
+    ("acrobits", "Acrobits", DeviceKind::Softphone),
+    ("jssip", "JsSIP", DeviceKind::Softphone),
+    ("sip.js", "SIP.js", DeviceKind::Softphone),
+];
+
+/// Fingerprint a User-Agent header
+///
+/// Recognises common desk phone, ATA and softphone vendors, and picks out a
+/// model (a token mixing letters and digits), a dotted firmware version and
+/// a MAC address when the device includes one.
+pub fn fingerprint(user_agent: &str) -> UserAgentInfo {
+    let lower = user_agent.to_lowercase();
+    let vendor = VENDORS.iter().find(|(marker, _, _)| lower.contains(marker));
+    let mut info = UserAgentInfo {
+        vendor: vendor.map(|(_, name, _)| name.to_string()),
+        kind: vendor.map(|(_, _, kind)| *kind).unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let tokens = user_agent
+        .split(|c: char| c.is_whitespace() || matches!(c, '/' | '(' | ')' | ';' | ','))
+        .filter(|t| !t.is_empty());
+    for token in tokens {
+        if info.mac.is_none() {
+            if let Some(mac) = normalize_mac(token) {
+                info.mac = Some(mac);
+                continue;
+            }
+        }
+        if info.firmware.is_none() {
+            if let Some(version) = version(token) {
+                info.firmware = Some(version);
+                continue;
+            }
+            // Model and firmware run together, as in `SPA504G-7.6.2`
+            if let Some((model, version)) = token
+                .rsplit_once('-')
+                .and_then(|(model, v)| Some((model, self::version(v)?)))
+            {
+                info.model.get_or_insert_with(|| model.to_string());
+                info.firmware = Some(version);
+                continue;
+            }
+        }
+        if info.model.is_none() && info.firmware.is_none() {
+            let token = match vendor {
+                Some((marker, _, _)) if token.to_lowercase().starts_with(marker) => {
+                    &token[marker.len()..]
+                }
+                _ => token,
+            };
+            let token = token.trim_start_matches('-').trim_end_matches("-UA");
+            let letters = token.chars().any(|c| c.is_ascii_alphabetic());
+            let digits = token.chars().any(|c| c.is_ascii_digit());
+            if letters && digits {
+                info.model = Some(token.to_string());
+            }
+        }
+    }
+    info
+}
+
+/// A dotted version such as `66.86.0.15`, `v1.2` or `rv2.10.18.1`
+fn version(token: &str) -> Option<String> {
+    let digits = token.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let prefix = token.len() - digits.len();
+    (prefix <= 2
+        && digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits.contains('.')
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.'))
+    .then(|| digits.to_string())
+}
+
+/// A MAC address in any common notation, as upper-case colon-separated hex
+pub fn normalize_mac(value: &str) -> Option<String> {
+    let value = value.trim();
+    let hex: String = value
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect();
+    let separators = value.len() - hex.len();
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    // Bare hex, or fully separated (aa:bb:..., aa-bb-..., aabb.ccdd.eeff)
+    if !matches!(separators, 0 | 2 | 5) {
+        return None;
+    }
+    let hex = hex.to_uppercase();
+    Some(
+        (0..12)
+            .step_by(2)
+            .map(|i| &hex[i..i + 2])
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
+
+/// `+sip.instance` of a Contact header value
+pub fn sip_instance(contact: &str) -> Option<String> {
+    let start = contact.find("+sip.instance=")? + "+sip.instance=".len();
+    let value = contact[start..]
+        .split(';')
+        .next()?
+        .trim()
+        .trim_matches('"')
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// MAC address embedded in a version 1 UUID instance, as Polycom and others
+/// use
+fn instance_mac(instance: &str) -> Option<String> {
+    let uuid = instance.strip_prefix("urn:uuid:")?;
+    let groups: Vec<&str> = uuid.split('-').collect();
+    if groups.len() != 5 || !groups[2].starts_with('1') {
+        return None;
+    }
+    normalize_mac(groups[4])
+}
+
+/// A device as seen in the inventory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    pub extension: String,
+    pub user_agent: Option<String>,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    pub kind: DeviceKind,
+    /// Reported by the device or set when it was provisioned
+    pub mac: Option<String>,
+    /// `+sip.instance` the device registers with
+    pub instance: Option<String>,
+    /// Admin-assigned name, such as a desk or owner
+    pub label: Option<String>,
+    pub last_ip: Option<IpAddr>,
+    pub last_contact: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_registered: DateTime<Utc>,
+    /// Successful registrations seen
+    pub registrations: u64,
+    /// When set, the registrar refuses the device
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl Device {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+}
+
+/// A REGISTER from a device
+#[derive(Debug, Clone, Default)]
+pub struct Sighting {
+    pub extension: String,
+    pub user_agent: Option<String>,
+    /// Contact header value, with parameters
+    pub contact: Option<String>,
+    pub source: Option<IpAddr>,
+}
+
+impl Sighting {
+    /// Stable ID of the device behind this REGISTER
+    ///
+    /// Without an instance or MAC the vendor and model stand in, so a
+    /// firmware upgrade does not turn a phone into a new device.
+    pub fn device_id(&self) -> String {
+        let instance = self.contact.as_deref().and_then(sip_instance);
+        let info = self.user_agent.as_deref().map(fingerprint);
+        let mac = instance
+            .as_deref()
+            .and_then(instance_mac)
+            .or_else(|| info.as_ref().and_then(|info| info.mac.clone()));
+        let model = info
+            .filter(|info| info.vendor.is_some() || info.model.is_some())
+            .map(|info| {
+                format!(
+                    "{} {}",
+                    info.vendor.unwrap_or_default(),
+                    info.model.unwrap_or_default()
+                )
+            });
+        let identity = instance
+            .or(mac)
+            .or(model)
+            .or_else(|| self.user_agent.clone())
+            .unwrap_or_default();
+
+        let digest = Sha256::digest(format!("{}|{}", self.extension, identity).as_bytes());
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Changes an admin can make to a device
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DeviceUpdate {
+    /// Provisioned MAC address; an empty string clears it
+    pub mac: Option<String>,
+    /// An empty string clears the label
+    pub label: Option<String>,
+}
+
+/// Devices seen registering, shared between the registrar and the API
+#[derive(Clone, Default)]
+pub struct DeviceInventory {
+    devices: Arc<RwLock<HashMap<String, Device>>>,
+}
+
+impl DeviceInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the device behind a REGISTER has been revoked
+    pub async fn is_revoked(&self, id: &str) -> bool {
+        self.devices
+            .read()
+            .await
+            .get(id)
+            .is_some_and(Device::is_revoked)
+    }
+
+    /// Record a successful registration, returning the device
+    pub async fn record(&self, sighting: &Sighting) -> Device {
+        let id = sighting.device_id();
+        let now = Utc::now();
+        let info = sighting
+            .user_agent
+            .as_deref()
+            .map(fingerprint)
+            .unwrap_or_default();
+        let instance = sighting.contact.as_deref().and_then(sip_instance);
+        let mac = instance.as_deref().and_then(instance_mac).or(info.mac);
+
+        let mut devices = self.devices.write().await;
+        let device = devices.entry(id.clone()).or_insert_with(|| {
+            info!(
+                "New device for extension {}: {}",
+                sighting.extension,
+                sighting
+                    .user_agent
+                    .as_deref()
+                    .unwrap_or("unknown user agent")
+            );
+            Device {
+                id,
+                extension: sighting.extension.clone(),
+                user_agent: None,
+                vendor: None,
+                model: None,
+                firmware: None,
+                kind: DeviceKind::Unknown,
+                mac: None,
+                instance: None,
+                label: None,
+                last_ip: None,
+                last_contact: None,
+                first_seen: now,
+                last_registered: now,
+                registrations: 0,
+                revoked_at: None,
+            }
+        });
+        device.user_agent = sighting.user_agent.clone();
+        device.vendor = info.vendor;
+        device.model = info.model;
+        device.firmware = info.firmware;
+        device.kind = info.kind;
+        // A provisioned MAC is kept over one the device reports
+        if device.mac.is_none() {
+            device.mac = mac;
+        }
+        device.instance = instance;
+        device.last_ip = sighting.source;
+        device.last_contact = sighting.contact.clone();
+        device.last_registered = now;
+        device.registrations += 1;
+        device.clone()
+    }
+
+    /// Devices ordered by extension, most recently registered first
+    pub async fn list(&self) -> Vec<Device> {
+        let mut devices: Vec<Device> = self.devices.read().await.values().cloned().collect();
+        devices.sort_by(|a, b| {
+            a.extension
+                .cmp(&b.extension)
+                .then(b.last_registered.cmp(&a.last_registered))
+        });
+        devices
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Device> {
+        self.devices.read().await.get(id).cloned()
+    }
+
+    /// Set a device's MAC address or label
+    pub async fn update(&self, id: &str, update: DeviceUpdate) -> Option<Device> {
+        let mut devices = self.devices.write().await;
+        let device = devices.get_mut(id)?;
+        if let Some(mac) = update.mac {
+            device.mac = (!mac.trim().is_empty()).then_some(mac);
+        }
+        if let Some(label) = update.label {
+            device.label = (!label.trim().is_empty()).then_some(label);
+        }
+        Some(device.clone())
+    }
+
+    /// Refuse further registrations from a device
+    pub async fn revoke(&self, id: &str) -> Option<Device> {
+        let mut devices = self.devices.write().await;
+        let device = devices.get_mut(id)?;
+        if device.revoked_at.is_none() {
+            info!("Revoked device {} of extension {}", id, device.extension);
+            device.revoked_at = Some(Utc::now());
+        }
+        Some(device.clone())
+    }
+
+    /// Let a revoked device register again
+    pub async fn reinstate(&self, id: &str) -> Option<Device> {
+        let mut devices = self.devices.write().await;
+        let device = devices.get_mut(id)?;
+        device.revoked_at = None;
+        Some(device.clone())
+    }
+
+    /// Forget a device; it is listed afresh if it registers again
+    pub async fn remove(&self, id: &str) -> Option<Device> {
+        self.devices.write().await.remove(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_user_agents() {
+        let info = fingerprint("Yealink SIP-T46S 66.86.0.15 00:15:65:aa:bb:cc");
+        assert_eq!(info.vendor.as_deref(), Some("Yealink"));
+        assert_eq!(info.model.as_deref(), Some("SIP-T46S"));
+        assert_eq!(info.firmware.as_deref(), Some("66.86.0.15"));
+        assert_eq!(info.kind, DeviceKind::DeskPhone);
+        assert_eq!(info.mac.as_deref(), Some("00:15:65:AA:BB:CC"));
+
+        let info = fingerprint("Cisco/SPA504G-7.6.2");
+        assert_eq!(info.model.as_deref(), Some("SPA504G"));
+        assert_eq!(info.firmware.as_deref(), Some("7.6.2"));
+
+        let info = fingerprint("snomD785/10.1.54.13");
+        assert_eq!(info.vendor.as_deref(), Some("Snom"));
+        assert_eq!(info.model.as_deref(), Some("D785"));
+
+        let info = fingerprint("PolycomVVX-VVX_450-UA/6.3.1.8427");
+        assert_eq!(info.model.as_deref(), Some("VVX-VVX_450"));
+        assert_eq!(info.firmware.as_deref(), Some("6.3.1.8427"));
+
+        let info = fingerprint("Zoiper rv2.10.18.1");
+        assert_eq!(info.kind, DeviceKind::Softphone);
+        assert_eq!(info.model, None);
+        assert_eq!(info.firmware.as_deref(), Some("2.10.18.1"));
+
+        let info = fingerprint("Obihai OBi202 3.2.2 (Build: 5921EX) 9CADEF000000");
+        assert_eq!(info.kind, DeviceKind::Gateway);
+        assert_eq!(info.model.as_deref(), Some("OBi202"));
+        assert_eq!(info.mac.as_deref(), Some("9C:AD:EF:00:00:00"));
+
+        assert_eq!(fingerprint("curl/8.0").kind, DeviceKind::Unknown);
+    }
+
+    #[test]
+    fn test_mac_and_instance() {
+        assert_eq!(
+            normalize_mac("0004.f2aa.bbcc").as_deref(),
+            Some("00:04:F2:AA:BB:CC")
+        );
+        assert_eq!(normalize_mac("00-04-F2-AA-BB"), None);
+        assert_eq!(normalize_mac("0004f2aabbcg"), None);
+
+        let contact = r#"<sip:1001@192.0.2.10:5060>;+sip.instance="<urn:uuid:00000000-0000-1000-8000-0004f2aabbcc>""#;
+        let instance = sip_instance(contact).unwrap();
+        assert_eq!(instance, "urn:uuid:00000000-0000-1000-8000-0004f2aabbcc");
+        assert_eq!(
+            instance_mac(&instance).as_deref(),
+            Some("00:04:F2:AA:BB:CC")
+        );
+        assert_eq!(sip_instance("<sip:1001@192.0.2.10>"), None);
+    }
+
+    #[tokio::test]
+    async fn test_record_and_revoke() {
+        let inventory = DeviceInventory::new();
+        let sighting = Sighting {
+            extension: "1001".to_string(),
+            user_agent: Some("Yealink SIP-T46S 66.86.0.15".to_string()),
+            contact: Some("<sip:1001@192.0.2.10:5060>".to_string()),
+            source: Some("192.0.2.10".parse().unwrap()),
+        };
+        let device = inventory.record(&sighting).await;
+        assert_eq!(device.registrations, 1);
+
+        // A firmware upgrade is the same device
+        let upgraded = Sighting {
+            user_agent: Some("Yealink SIP-T46S 66.86.0.20".to_string()),
+            ..sighting.clone()
+        };
+        assert_eq!(upgraded.device_id(), sighting.device_id());
+        let softphone = Sighting {
+            user_agent: Some("Zoiper rv2.10.18.1".to_string()),
+            ..sighting.clone()
+        };
+        inventory.record(&softphone).await;
+        assert_eq!(inventory.list().await.len(), 2);
+
+        inventory
+            .update(
+                &device.id,
+                DeviceUpdate {
+                    mac: Some("00:15:65:AA:BB:CC".to_string()),
+                    label: Some("Front desk".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+        let device = inventory.record(&sighting).await;
+        assert_eq!(device.registrations, 2);
+        assert_eq!(device.mac.as_deref(), Some("00:15:65:AA:BB:CC"));
+        assert_eq!(device.label.as_deref(), Some("Front desk"));
+
+        assert!(!inventory.is_revoked(&device.id).await);
+        inventory.revoke(&device.id).await.unwrap();
+        assert!(inventory.is_revoked(&device.id).await);
+        inventory.reinstate(&device.id).await.unwrap();
+        assert!(!inventory.is_revoked(&device.id).await);
+        assert!(inventory.remove(&device.id).await.is_some());
+        assert!(inventory.get(&device.id).await.is_none());
+    }
+}
@@ -0,0 +1,151 @@
+//! Provisioning files for desk phones
+//!
+//! Renders the settings RusTalk manages on a phone in its vendor's
+//! auto-provisioning format: for now, the URI of the phone's BLF list,
+//! which the phone subscribes to as one resource list (see
+//! [`crate::presence`]). Files are named the way the vendor's phones ask
+//! for them, after their MAC address, so a provisioning server can serve
+//! them as they are or merge them into its own.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use super::Device;
+use crate::presence::rlmi::ResourceList;
+
+/// Vendors with a provisioning template
+pub const VENDORS: &[&str] = &["Yealink", "Polycom", "Grandstream"];
+
+/// A rendered provisioning file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProvisioningFile {
+    pub filename: String,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// Provisioning file subscribing `device` to its BLF list
+///
+/// Fails for devices of vendors without a template, and for devices whose
+/// MAC address is not known.
+pub fn render(device: &Device, list: &ResourceList) -> Result<ProvisioningFile> {
+    let vendor = device.vendor.as_deref().unwrap_or("unknown vendor");
+    if !VENDORS.contains(&vendor) {
+        bail!(
+            "no provisioning template for {}; templates exist for {}",
+            vendor,
+            VENDORS.join(", ")
+        );
+    }
+    let Some(mac) = &device.mac else {
+        bail!(
+            "device {} has no MAC address; set one to name its provisioning file",
+            device.id
+        );
+    };
+    let mac = mac.replace(':', "").to_lowercase();
+    let watches = list
+        .resources
+        .iter()
+        .map(|r| r.extension.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let file = match vendor {
+        "Yealink" => ProvisioningFile {
+            filename: format!("{}.cfg", mac),
+            content_type: "text/plain",
+            body: format!(
+                "#!version:1.0.0.1\n\
+                 ## BLF list, watching {}\n\
+                 account.1.blf_list_uri = {}\n\
+                 phone_setting.auto_blf_list_enable = 1\n",
+                watches, list.uri
+            ),
+        },
+        "Polycom" => ProvisioningFile {
+            filename: format!("{}-blf.cfg", mac),
+            content_type: "application/xml",
+            body: format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+                 <!-- BLF list, watching {} -->\n\
+                 <polycomConfig>\n  \
+                 <attendant attendant.reg=\"1\" attendant.uri=\"{}\"/>\n\
+                 </polycomConfig>\n",
+                watches, list.uri
+            ),
+        },
+        _ => ProvisioningFile {
+            filename: format!("cfg{}.xml", mac),
+            content_type: "application/xml",
+            // P134 is account 1's eventlist BLF URI, given as its user part
+            body: format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <!-- BLF list, watching {} -->\n\
+                 <gs_provision version=\"1\">\n  <mac>{}</mac>\n  <config version=\"1\">\n    \
+                 <P134>{}</P134>\n  </config>\n</gs_provision>\n",
+                watches,
+                mac,
+                list_user(&list.uri)
+            ),
+        },
+    };
+    Ok(file)
+}
+
+/// User part of a list URI
+fn list_user(uri: &str) -> &str {
+    let user = uri.strip_prefix("sip:").unwrap_or(uri);
+    user.split('@').next().unwrap_or(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presence::BlfList;
+
+    fn device(vendor: &str, mac: Option<&str>) -> Device {
+        serde_json::from_value(serde_json::json!({
+            "id": "9f2c", "extension": "1001", "user_agent": null, "vendor": vendor,
+            "model": null, "firmware": null, "kind": "desk_phone", "mac": mac,
+            "instance": null, "label": null, "last_ip": null, "last_contact": null,
+            "first_seen": "2026-01-01T00:00:00Z", "last_registered": "2026-01-01T00:00:00Z",
+            "registrations": 1, "revoked_at": null
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_render_blf_list() {
+        let list = BlfList {
+            device: "00:15:65:12:34:56".to_string(),
+            name: None,
+            extensions: vec!["1002".to_string(), "1003".to_string()],
+        }
+        .resource_list("example.com");
+
+        let yealink = render(&device("Yealink", Some("00:15:65:12:34:56")), &list).unwrap();
+        assert_eq!(yealink.filename, "001565123456.cfg");
+        assert!(yealink
+            .body
+            .contains("account.1.blf_list_uri = sip:blf-001565123456@example.com\n"));
+        assert!(yealink.body.contains("watching 1002, 1003"));
+
+        let polycom = render(&device("Polycom", Some("00:04:F2:AB:CD:EF")), &list).unwrap();
+        assert_eq!(polycom.filename, "0004f2abcdef-blf.cfg");
+        assert!(polycom
+            .body
+            .contains("attendant.uri=\"sip:blf-001565123456@example.com\""));
+
+        let grandstream = render(&device("Grandstream", Some("00:0B:82:12:34:56")), &list).unwrap();
+        assert_eq!(grandstream.filename, "cfg000b82123456.xml");
+        assert!(grandstream.body.contains("<P134>blf-001565123456</P134>"));
+
+        let error = render(&device("Zoiper", Some("00:15:65:12:34:56")), &list).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("no provisioning template for Zoiper"));
+        let error = render(&device("Yealink", None), &list).unwrap_err();
+        assert!(error.to_string().contains("has no MAC address"));
+    }
+}
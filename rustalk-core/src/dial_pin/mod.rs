@@ -0,0 +1,369 @@
+//! PIN-protected outbound dialing
+//!
+//! Phones in lobbies, break rooms and other shared areas can be required to
+//! supply a PIN in an `X-Dial-PIN` header before outbound PSTN calls are
+//! completed. PINs are either personal, so the call is attributed to the
+//! user who entered it, or shared. Every attempt is reported as a
+//! [`DialPinAttempt`] for auditing.
+//!
+//! An address that sends too many invalid PINs in a short time is locked
+//! out for a while, so PINs cannot be guessed by trying them all. Lockouts
+//! are kept by source address rather than by the From extension, which a
+//! caller could change with every guess or use to lock someone else out.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cos::CallClass;
+
+/// INVITE header carrying a dialing PIN
+pub const DIAL_PIN_HEADER: &str = "X-Dial-PIN";
+
+/// How often [`PinLockouts`] sweeps out addresses with nothing left to count
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A PIN accepted for outbound dialing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DialPin {
+    pub pin: String,
+    /// User the PIN belongs to; `None` for a shared PIN
+    pub user: Option<String>,
+}
+
+/// Dialing PIN configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DialPinConfig {
+    /// Extensions that must supply a PIN
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Call classes that need a PIN from those extensions
+    #[serde(default = "default_classes")]
+    pub classes: Vec<CallClass>,
+    #[serde(default)]
+    pub pins: Vec<DialPin>,
+    /// Invalid PINs an address may send within `failure_window_seconds`
+    /// before it is locked out; 0 never locks it out
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    #[serde(default = "default_failure_window")]
+    pub failure_window_seconds: u64,
+    /// How long a locked out address is refused, whatever PIN it sends
+    #[serde(default = "default_lockout")]
+    pub lockout_seconds: u64,
+}
+
+impl Default for DialPinConfig {
+    fn default() -> Self {
+        Self {
+            extensions: Vec::new(),
+            classes: default_classes(),
+            pins: Vec::new(),
+            max_failures: default_max_failures(),
+            failure_window_seconds: default_failure_window(),
+            lockout_seconds: default_lockout(),
+        }
+    }
+}
+
+fn default_max_failures() -> u32 {
+    5
+}
+
+fn default_failure_window() -> u64 {
+    300
+}
+
+fn default_lockout() -> u64 {
+    900
+}
+
+/// Calls that leave the system over a trunk
+fn default_classes() -> Vec<CallClass> {
+    vec![
+        CallClass::Local,
+        CallClass::National,
+        CallClass::International,
+        CallClass::Premium,
+    ]
+}
+
+/// Outcome of checking a dialing PIN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialPinDecision {
+    /// The call does not need a PIN
+    NotRequired,
+    /// A valid PIN was given, personal to `user` if set
+    Accept {
+        user: Option<String>,
+    },
+    Reject(String),
+}
+
+impl DialPinConfig {
+    /// Whether `extension` needs a PIN to place a call of `class`
+    pub fn requires_pin(&self, extension: &str, class: CallClass) -> bool {
+        self.classes.contains(&class) && self.extensions.iter().any(|e| e == extension)
+    }
+
+    /// Check the PIN supplied for a call
+    pub fn check(&self, extension: &str, class: CallClass, pin: Option<&str>) -> DialPinDecision {
+        if !self.requires_pin(extension, class) {
+            return DialPinDecision::NotRequired;
+        }
+        let Some(pin) = pin else {
+            return DialPinDecision::Reject(format!("{} calls require a dialing PIN", class));
+        };
+        match self.pins.iter().find(|p| p.pin == pin) {
+            Some(entry) => DialPinDecision::Accept {
+                user: entry.user.clone(),
+            },
+            None => DialPinDecision::Reject("invalid dialing PIN".to_string()),
+        }
+    }
+}
+
+/// Invalid PINs sent from each address, and the addresses locked out
+#[derive(Debug, Clone, Default)]
+pub struct PinLockouts {
+    sources: Arc<Mutex<Sources>>,
+}
+
+#[derive(Debug, Default)]
+struct Sources {
+    failures: HashMap<IpAddr, Failures>,
+    swept_at: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Failures {
+    /// When each invalid PIN still inside the window was entered
+    times: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    /// Whether nothing is left to hold against the address at `now`
+    fn expired(&self, window: Duration, now: Instant) -> bool {
+        self.locked_until.is_none_or(|until| now >= until)
+            && self
+                .times
+                .back()
+                .is_none_or(|&time| now.duration_since(time) >= window)
+    }
+}
+
+impl PinLockouts {
+    /// Whether `source` is locked out at `now`
+    pub fn is_locked(&self, source: IpAddr, now: Instant) -> bool {
+        let mut sources = self.sources.lock().unwrap();
+        let Some(failures) = sources.failures.get_mut(&source) else {
+            return false;
+        };
+        match failures.locked_until {
+            Some(until) if now < until => true,
+            Some(_) => {
+                // The lockout is over and the address starts afresh
+                sources.failures.remove(&source);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Record an invalid PIN from `source`, returning whether it locks the
+    /// address out
+    pub fn record_failure(&self, config: &DialPinConfig, source: IpAddr, now: Instant) -> bool {
+        if config.max_failures == 0 {
+            return false;
+        }
+        let window = Duration::from_secs(config.failure_window_seconds);
+        let mut sources = self.sources.lock().unwrap();
+
+        // Forget addresses whose failures and lockout have run out now and
+        // then, so addresses that stop sending don't stay forever
+        if sources
+            .swept_at
+            .is_none_or(|at| now.duration_since(at) >= SWEEP_INTERVAL)
+        {
+            sources
+                .failures
+                .retain(|_, failures| !failures.expired(window, now));
+            sources.swept_at = Some(now);
+        }
+
+        let failures = sources.failures.entry(source).or_default();
+        while failures
+            .times
+            .front()
+            .is_some_and(|&time| now.duration_since(time) >= window)
+        {
+            failures.times.pop_front();
+        }
+        failures.times.push_back(now);
+        if failures.times.len() < config.max_failures as usize {
+            return false;
+        }
+        failures.times.clear();
+        failures.locked_until = Some(now + Duration::from_secs(config.lockout_seconds));
+        true
+    }
+
+    /// Forget the invalid PINs of an address that has sent a valid one
+    pub fn clear(&self, source: IpAddr) {
+        self.sources.lock().unwrap().failures.remove(&source);
+    }
+}
+
+/// Audit record of a dialing PIN check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DialPinAttempt {
+    pub call_id: String,
+    pub extension: String,
+    pub number: String,
+    pub class: CallClass,
+    /// Whether a PIN was supplied at all
+    pub pin_supplied: bool,
+    pub accepted: bool,
+    /// Owner of the personal PIN that was accepted
+    pub user: Option<String>,
+    /// Address the INVITE came from, when it came over the network
+    #[serde(default)]
+    pub source: Option<IpAddr>,
+    /// Whether the address is locked out, by this attempt or an earlier one
+    #[serde(default)]
+    pub locked_out: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DialPinConfig {
+        DialPinConfig {
+            extensions: vec!["1900".to_string()],
+            pins: vec![
+                DialPin {
+                    pin: "2468".to_string(),
+                    user: Some("alice".to_string()),
+                },
+                DialPin {
+                    pin: "1111".to_string(),
+                    user: None,
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let config = config();
+
+        // Other extensions and internal calls are unaffected
+        assert_eq!(
+            config.check("1001", CallClass::National, None),
+            DialPinDecision::NotRequired
+        );
+        assert_eq!(
+            config.check("1900", CallClass::Internal, None),
+            DialPinDecision::NotRequired
+        );
+
+        assert_eq!(
+            config.check("1900", CallClass::National, None),
+            DialPinDecision::Reject("national calls require a dialing PIN".to_string())
+        );
+        assert_eq!(
+            config.check("1900", CallClass::National, Some("0000")),
+            DialPinDecision::Reject("invalid dialing PIN".to_string())
+        );
+        assert_eq!(
+            config.check("1900", CallClass::Local, Some("2468")),
+            DialPinDecision::Accept {
+                user: Some("alice".to_string())
+            }
+        );
+        assert_eq!(
+            config.check("1900", CallClass::International, Some("1111")),
+            DialPinDecision::Accept { user: None }
+        );
+    }
+
+    #[test]
+    fn test_lockout() {
+        let config = DialPinConfig {
+            max_failures: 3,
+            failure_window_seconds: 60,
+            lockout_seconds: 300,
+            ..config()
+        };
+        let lockouts = PinLockouts::default();
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let phone: IpAddr = "192.0.2.10".parse().unwrap();
+        let other: IpAddr = "192.0.2.11".parse().unwrap();
+
+        // Failures that fall out of the window do not count
+        assert!(!lockouts.record_failure(&config, phone, at(0)));
+        assert!(!lockouts.record_failure(&config, phone, at(30)));
+        assert!(!lockouts.record_failure(&config, phone, at(70)));
+        assert!(!lockouts.is_locked(phone, at(70)));
+        assert!(lockouts.record_failure(&config, phone, at(80)));
+        assert!(lockouts.is_locked(phone, at(81)));
+        assert!(!lockouts.is_locked(other, at(81)));
+        assert!(!lockouts.is_locked(phone, at(380)));
+
+        // A valid PIN clears the count
+        lockouts.record_failure(&config, phone, at(400));
+        lockouts.record_failure(&config, phone, at(401));
+        lockouts.clear(phone);
+        assert!(!lockouts.record_failure(&config, phone, at(402)));
+
+        let never = DialPinConfig {
+            max_failures: 0,
+            ..config
+        };
+        for second in 0..10 {
+            assert!(!lockouts.record_failure(&never, other, at(second)));
+        }
+    }
+
+    #[test]
+    fn test_lockouts_swept() {
+        let config = DialPinConfig {
+            max_failures: 2,
+            failure_window_seconds: 60,
+            lockout_seconds: 300,
+            ..config()
+        };
+        let lockouts = PinLockouts::default();
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let guesser: IpAddr = "198.51.100.1".parse().unwrap();
+        let typo: IpAddr = "198.51.100.2".parse().unwrap();
+        let locked: IpAddr = "198.51.100.3".parse().unwrap();
+        let count = || lockouts.sources.lock().unwrap().failures.len();
+
+        lockouts.record_failure(&config, guesser, at(0));
+        lockouts.record_failure(&config, typo, at(0));
+        lockouts.record_failure(&config, locked, at(0));
+        assert!(lockouts.record_failure(&config, locked, at(1)));
+        assert_eq!(count(), 3);
+
+        // Once their failures have left the window the addresses go, but
+        // one still locked out stays
+        lockouts.record_failure(&config, guesser, at(120));
+        assert_eq!(count(), 2);
+        assert!(lockouts.is_locked(locked, at(120)));
+
+        lockouts.record_failure(&config, guesser, at(400));
+        assert_eq!(count(), 1);
+        assert!(!lockouts.is_locked(locked, at(400)));
+    }
+}
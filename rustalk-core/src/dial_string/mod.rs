@@ -0,0 +1,224 @@
+//! Outbound dial string templates
+//!
+//! Carriers disagree on how a number should be addressed: one wants a
+//! tech-prefix in front of the digits, another national format on a different
+//! host and port, a third `user=phone`. A template per trunk describes the
+//! Request-URI of the B-leg INVITE, so a new carrier is a configuration
+//! change rather than a code change.
+//!
+//! Templates are SIP URIs with placeholders:
+//!
+//! - `{number}` - the dialed number as received, after `strip_prefix`
+//! - `{digits}` - the same without a leading `+`
+//! - `{host}` / `{port}` - host and port of the original Request-URI
+//! - `{caller}` - user part of the From header
+//!
+//! For example `sip:7788#{digits}@gw2.carrier.example:5080;user=phone`.
+
+use anyhow::{anyhow, bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::call_trace::uri_user;
+use crate::sip::{Request, Uri};
+
+const PLACEHOLDERS: [&str; 5] = ["number", "digits", "host", "port", "caller"];
+
+/// Templates bound to trunks
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DialStringConfig {
+    pub trunks: Vec<DialTemplate>,
+}
+
+/// How INVITEs to one trunk are addressed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DialTemplate {
+    /// Trunk host, as it appears in the routed Request-URI
+    pub trunk: String,
+    /// Request-URI template
+    pub template: String,
+    /// Removed from the front of the dialed number before substitution,
+    /// e.g. `+1` to send national format
+    #[serde(default)]
+    pub strip_prefix: Option<String>,
+}
+
+impl DialStringConfig {
+    /// Template for the trunk at `host`
+    pub fn for_trunk(&self, host: &str) -> Option<&DialTemplate> {
+        self.trunks
+            .iter()
+            .find(|t| t.trunk.eq_ignore_ascii_case(host))
+    }
+}
+
+impl DialTemplate {
+    /// Check the template expands to a valid URI
+    pub fn validate(&self) -> Result<()> {
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("unclosed '{{' in template"))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                bail!("unknown placeholder {{{}}}", name);
+            }
+            rest = &rest[start + end + 1..];
+        }
+        let sample = Uri::new("sip".to_string(), "trunk.example".to_string())
+            .with_user("+12125551234".to_string())
+            .with_port(5060);
+        parse_uri(&self.expand(&sample, "1001"))?;
+        Ok(())
+    }
+
+    /// Template text with placeholders filled in for `uri`
+    pub fn expand(&self, uri: &Uri, caller: &str) -> String {
+        let dialed = uri.user.as_deref().unwrap_or("");
+        let number = self
+            .strip_prefix
+            .as_deref()
+            .and_then(|prefix| dialed.strip_prefix(prefix))
+            .unwrap_or(dialed);
+        self.template
+            .replace("{number}", number)
+            .replace("{digits}", number.trim_start_matches('+'))
+            .replace("{host}", &uri.host)
+            .replace("{port}", &uri.port.unwrap_or(5060).to_string())
+            .replace("{caller}", caller)
+    }
+
+    /// Rewrite the Request-URI of an INVITE routed to this trunk
+    pub fn apply(&self, request: &mut Request) -> Result<()> {
+        let caller = request
+            .get_header_value("From")
+            .and_then(uri_user)
+            .unwrap_or("")
+            .to_string();
+        request.uri = parse_uri(&self.expand(&request.uri, &caller))?;
+        Ok(())
+    }
+}
+
+/// Parse `scheme:[user@]host[:port][;param[=value]]...`
+///
+/// Users may hold any character the templates produce (`+`, `#`, `*`),
+/// which the wire parser does not accept.
+pub(crate) fn parse_uri(text: &str) -> Result<Uri> {
+    let (scheme, rest) = text
+        .split_once(':')
+        .ok_or_else(|| anyhow!("'{}' has no scheme", text))?;
+    if !matches!(scheme, "sip" | "sips") {
+        bail!("unsupported scheme '{}'", scheme);
+    }
+    let mut parts = rest.split(';');
+    let address = parts.next().unwrap_or("");
+    let (user, hostport) = match address.rsplit_once('@') {
+        Some((user, hostport)) => (Some(user), hostport),
+        None => (None, address),
+    };
+    if user.is_some_and(str::is_empty) {
+        bail!("'{}' has an empty user", text);
+    }
+    let (host, port) = match hostport.rsplit_once(':') {
+        Some((host, port)) if !hostport.ends_with(']') => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| anyhow!("'{}' is not a port", port))?;
+            (host, Some(port))
+        }
+        _ => (hostport, None),
+    };
+    let valid_host = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '[' | ']' | ':'));
+    if !valid_host {
+        bail!("'{}' is not a valid host", host);
+    }
+
+    let mut uri = Uri::new(scheme.to_string(), host.to_string());
+    uri.user = user.map(str::to_string);
+    uri.port = port;
+    for param in parts.filter(|p| !p.is_empty()) {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (param, None),
+        };
+        uri.params.push((key.to_string(), value));
+    }
+    Ok(uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::Method;
+
+    fn invite(user: &str) -> Request {
+        Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "carrier.example".to_string()).with_user(user.to_string()),
+        )
+        .with_header("From", "<sip:1001@pbx.example.com>;tag=a")
+    }
+
+    #[test]
+    fn test_apply_templates() {
+        let config: DialStringConfig = serde_json::from_value(serde_json::json!({
+            "trunks": [
+                {
+                    "trunk": "carrier.example",
+                    "template": "sip:7788#{digits}@gw2.{host}:5080;user=phone;x-caller={caller}"
+                },
+                {
+                    "trunk": "national.example",
+                    "template": "sip:0{number}@{host}:{port}",
+                    "strip_prefix": "+44"
+                }
+            ]
+        }))
+        .unwrap();
+
+        let mut request = invite("+12125551234");
+        config
+            .for_trunk("Carrier.example")
+            .unwrap()
+            .apply(&mut request)
+            .unwrap();
+        assert_eq!(
+            request.uri.to_string(),
+            "sip:7788#12125551234@gw2.carrier.example:5080;user=phone;x-caller=1001"
+        );
+
+        let mut request = invite("+442071234567");
+        request.uri.host = "national.example".to_string();
+        config
+            .for_trunk("national.example")
+            .unwrap()
+            .apply(&mut request)
+            .unwrap();
+        assert_eq!(
+            request.uri.to_string(),
+            "sip:02071234567@national.example:5060"
+        );
+
+        assert!(config.for_trunk("other.example").is_none());
+    }
+
+    #[test]
+    fn test_validate_template() {
+        let template = |text: &str| DialTemplate {
+            trunk: "carrier.example".to_string(),
+            template: text.to_string(),
+            strip_prefix: None,
+        };
+        assert!(template("sip:{number}@{host}").validate().is_ok());
+        assert!(template("tel:{number}").validate().is_err());
+        assert!(template("sip:{dialed}@{host}").validate().is_err());
+        assert!(template("sip:{number@{host}").validate().is_err());
+        assert!(template("sip:{number}@{host}:99999").validate().is_err());
+        assert!(template("http://{host}").validate().is_err());
+    }
+}
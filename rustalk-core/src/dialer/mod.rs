@@ -0,0 +1,913 @@
+//! Outbound campaign dialer
+//!
+//! A campaign is a list of contacts to call with a message or menu, such as
+//! appointment reminders or outage notices. The dialer decides when each
+//! contact is called: only while the campaign is running and inside its
+//! calling hours, no faster than its pacing allows, never a number on the
+//! do-not-call list, and again after a delay when a call goes unanswered.
+//!
+//! Placing the calls is left to an originator, as with faxes: it claims the
+//! next due attempt, dials it through the campaign's trunk, connects the
+//! call to the campaign's queue, IVR flow or extension once it is answered,
+//! and reports the outcome back.
+//!
+//! Campaigns with answering machine detection have the originator run an
+//! [`AmdDetector`](crate::media::amd::AmdDetector) on each answered call.
+//! Calls a machine answered go to the campaign's `on_machine` target, such
+//! as a flow that leaves a message, or are hung up and retried like
+//! unanswered ones when it has none.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::media::amd::{AmdConfig, AmdResult, AmdVerdict};
+use crate::schedules::{OpeningHours, ScheduleDirectory};
+
+/// Dialer configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DialerConfig {
+    #[serde(default)]
+    pub campaigns: Vec<Campaign>,
+    /// Numbers that are never called by any campaign
+    #[serde(default)]
+    pub do_not_call: Vec<String>,
+}
+
+/// An outbound calling campaign
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Campaign {
+    pub id: String,
+    pub name: String,
+    /// Number presented to the called party
+    pub caller_id: String,
+    /// Trunk calls are placed through; the outbound routes decide without one
+    #[serde(default)]
+    pub trunk: Option<String>,
+    /// Where answered calls are connected
+    pub connect_to: CampaignTarget,
+    /// Detect answering machines on answered calls
+    #[serde(default)]
+    pub amd: Option<AmdConfig>,
+    /// Where calls answered by a machine are connected
+    #[serde(default)]
+    pub on_machine: Option<CampaignTarget>,
+    #[serde(default)]
+    pub pacing: Pacing,
+    /// Weekly hours calls may be placed in (UTC); any time when empty
+    #[serde(default)]
+    pub calling_hours: Vec<OpeningHours>,
+    /// Named schedule that must also be open for calls to be placed
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Calls placed to a contact before it is given up on
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Wait before a contact that did not answer or was busy is called again
+    #[serde(default = "default_retry_delay_seconds")]
+    pub retry_delay_seconds: u32,
+    /// How long each call rings before it counts as unanswered
+    #[serde(default = "default_ring_timeout_seconds")]
+    pub ring_timeout_seconds: u32,
+}
+
+/// Where an answered campaign call goes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", content = "target", rename_all = "snake_case")]
+pub enum CampaignTarget {
+    Queue(String),
+    Ivr(String),
+    Extension(String),
+}
+
+/// How fast a campaign places calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Pacing {
+    /// Calls started in any one minute
+    #[serde(default = "default_calls_per_minute")]
+    pub calls_per_minute: u32,
+    /// Calls in progress at once
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: u32,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            calls_per_minute: default_calls_per_minute(),
+            max_concurrent: default_max_concurrent(),
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_delay_seconds() -> u32 {
+    1800
+}
+
+fn default_ring_timeout_seconds() -> u32 {
+    30
+}
+
+fn default_calls_per_minute() -> u32 {
+    10
+}
+
+fn default_max_concurrent() -> u32 {
+    5
+}
+
+/// Someone a campaign calls
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contact {
+    pub number: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Values handed to the queue or IVR flow with the answered call
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Parse a contact list uploaded as CSV
+///
+/// The first column is the number and the optional second the name. A first
+/// row whose number column has no digits is taken as a header.
+pub fn parse_contacts_csv(text: &str) -> Result<Vec<Contact>> {
+    let mut contacts = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut columns = line.split(',').map(|c| c.trim().trim_matches('"'));
+        let number = columns.next().unwrap_or_default();
+        if !number.chars().any(|c| c.is_ascii_digit()) {
+            if index == 0 {
+                continue;
+            }
+            bail!("line {}: '{}' is not a phone number", index + 1, number);
+        }
+        contacts.push(Contact {
+            number: number.to_string(),
+            name: columns
+                .next()
+                .filter(|name| !name.is_empty())
+                .map(str::to_string),
+            variables: BTreeMap::new(),
+        });
+    }
+    Ok(contacts)
+}
+
+/// Number with everything but digits and a leading `+` removed, so lists
+/// written with spaces or dashes match
+fn normalize(number: &str) -> String {
+    let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+    if number.trim_start().starts_with('+') {
+        format!("+{}", digits)
+    } else {
+        digits
+    }
+}
+
+/// Whether a campaign is placing calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CampaignStatus {
+    /// Created, not started yet
+    Draft,
+    Running,
+    Paused,
+    /// Every contact has been reached, refused or given up on
+    Completed,
+}
+
+/// How a campaign call ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DialOutcome {
+    /// Answered and connected to the campaign's target
+    Answered,
+    NoAnswer,
+    Busy,
+    /// Rejected by the network or the number does not exist
+    Failed,
+}
+
+impl DialOutcome {
+    fn retries(&self) -> bool {
+        matches!(self, DialOutcome::NoAnswer | DialOutcome::Busy)
+    }
+}
+
+/// Where a contact is in a campaign
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactStatus {
+    /// Waiting for its first or next call
+    Pending,
+    Dialing,
+    Answered,
+    Failed,
+    /// Every attempt went unanswered or was busy
+    Exhausted,
+    /// On the do-not-call list, never called
+    DoNotCall,
+}
+
+/// A contact and the calls placed to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactProgress {
+    pub contact: Contact,
+    pub status: ContactStatus,
+    pub attempts: u32,
+    pub last_outcome: Option<DialOutcome>,
+    /// Who answered the last call, when the campaign detects machines
+    pub amd: Option<AmdVerdict>,
+    /// Earliest time the next call may be placed
+    pub next_attempt_at: Option<DateTime<Utc>>,
+}
+
+/// A call the originator should place
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialAttempt {
+    pub id: String,
+    pub campaign: String,
+    pub number: String,
+    pub caller_id: String,
+    pub trunk: Option<String>,
+    pub connect_to: CampaignTarget,
+    /// Run answering machine detection with these thresholds once answered
+    pub amd: Option<AmdConfig>,
+    pub on_machine: Option<CampaignTarget>,
+    pub ring_timeout_seconds: u32,
+    pub variables: BTreeMap<String, String>,
+    /// 1 for the first call to the contact
+    pub attempt: u32,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Counts of a campaign's contacts and calls
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CampaignStats {
+    pub contacts: usize,
+    pub pending: usize,
+    pub dialing: usize,
+    pub answered: usize,
+    pub failed: usize,
+    pub exhausted: usize,
+    pub do_not_call: usize,
+    /// Calls placed, including retries
+    pub attempts: u32,
+    pub no_answer: u32,
+    pub busy: u32,
+    /// Answered calls a machine picked up
+    pub machines: u32,
+    /// Share of placed calls that were answered
+    pub answer_rate: f64,
+}
+
+/// A campaign with its progress
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CampaignSummary {
+    #[serde(flatten)]
+    pub campaign: Campaign,
+    pub status: CampaignStatus,
+    pub stats: CampaignStats,
+}
+
+#[derive(Debug)]
+struct CampaignState {
+    campaign: Campaign,
+    status: CampaignStatus,
+    contacts: Vec<ContactProgress>,
+    /// When recent calls were started, for pacing
+    started: Vec<DateTime<Utc>>,
+    attempts: u32,
+    no_answer: u32,
+    busy: u32,
+    machines: u32,
+}
+
+impl CampaignState {
+    fn new(campaign: Campaign) -> Self {
+        Self {
+            campaign,
+            status: CampaignStatus::Draft,
+            contacts: Vec::new(),
+            started: Vec::new(),
+            attempts: 0,
+            no_answer: 0,
+            busy: 0,
+            machines: 0,
+        }
+    }
+
+    fn count(&self, status: ContactStatus) -> usize {
+        self.contacts.iter().filter(|c| c.status == status).count()
+    }
+
+    fn stats(&self) -> CampaignStats {
+        let answered = self.count(ContactStatus::Answered);
+        CampaignStats {
+            contacts: self.contacts.len(),
+            pending: self.count(ContactStatus::Pending),
+            dialing: self.count(ContactStatus::Dialing),
+            answered,
+            failed: self.count(ContactStatus::Failed),
+            exhausted: self.count(ContactStatus::Exhausted),
+            do_not_call: self.count(ContactStatus::DoNotCall),
+            attempts: self.attempts,
+            no_answer: self.no_answer,
+            busy: self.busy,
+            machines: self.machines,
+            answer_rate: if self.attempts == 0 {
+                0.0
+            } else {
+                answered as f64 / self.attempts as f64
+            },
+        }
+    }
+
+    fn summary(&self) -> CampaignSummary {
+        CampaignSummary {
+            campaign: self.campaign.clone(),
+            status: self.status,
+            stats: self.stats(),
+        }
+    }
+
+    fn in_window(&self, schedules: &ScheduleDirectory, now: DateTime<Utc>) -> bool {
+        let hours = &self.campaign.calling_hours;
+        (hours.is_empty() || hours.iter().any(|h| h.contains(now.naive_utc())))
+            && self
+                .campaign
+                .schedule
+                .as_deref()
+                .is_none_or(|schedule| schedules.is_open(schedule, now))
+    }
+
+    fn has_capacity(&mut self, now: DateTime<Utc>) -> bool {
+        self.started.retain(|t| now - *t < Duration::minutes(1));
+        let pacing = self.campaign.pacing;
+        self.started.len() < pacing.calls_per_minute as usize
+            && self.count(ContactStatus::Dialing) < pacing.max_concurrent as usize
+    }
+
+    /// Mark a running campaign completed once nothing is left to call
+    fn settle(&mut self) {
+        if self.status == CampaignStatus::Running
+            && !self
+                .contacts
+                .iter()
+                .any(|c| matches!(c.status, ContactStatus::Pending | ContactStatus::Dialing))
+        {
+            self.status = CampaignStatus::Completed;
+        }
+    }
+}
+
+/// Result of adding contacts to a campaign
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactsAdded {
+    pub added: usize,
+    /// Numbers already in the campaign, skipped
+    pub duplicates: usize,
+    /// Numbers added but never called because they are on the do-not-call
+    /// list
+    pub do_not_call: usize,
+}
+
+#[derive(Debug, Default)]
+struct DialerState {
+    campaigns: Vec<CampaignState>,
+    do_not_call: HashSet<String>,
+    /// Campaign and contact index of each call in progress
+    in_flight: HashMap<String, (String, usize)>,
+}
+
+/// Campaigns, their contacts and the do-not-call list
+#[derive(Debug, Clone)]
+pub struct Dialer {
+    schedules: ScheduleDirectory,
+    state: Arc<Mutex<DialerState>>,
+}
+
+impl Dialer {
+    pub fn new(config: DialerConfig) -> Self {
+        let state = DialerState {
+            campaigns: config
+                .campaigns
+                .into_iter()
+                .map(CampaignState::new)
+                .collect(),
+            do_not_call: config.do_not_call.iter().map(|n| normalize(n)).collect(),
+            in_flight: HashMap::new(),
+        };
+        Self {
+            schedules: ScheduleDirectory::default(),
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Schedules campaigns can name as calling windows
+    pub fn with_schedules(mut self, schedules: ScheduleDirectory) -> Self {
+        self.schedules = schedules;
+        self
+    }
+
+    pub fn campaigns(&self) -> Vec<CampaignSummary> {
+        let state = self.state.lock().unwrap();
+        state.campaigns.iter().map(CampaignState::summary).collect()
+    }
+
+    pub fn campaign(&self, id: &str) -> Option<CampaignSummary> {
+        let state = self.state.lock().unwrap();
+        state
+            .campaigns
+            .iter()
+            .find(|c| c.campaign.id == id)
+            .map(CampaignState::summary)
+    }
+
+    /// Contacts of a campaign with how far each has got
+    pub fn contacts(&self, id: &str) -> Option<Vec<ContactProgress>> {
+        let state = self.state.lock().unwrap();
+        state
+            .campaigns
+            .iter()
+            .find(|c| c.campaign.id == id)
+            .map(|c| c.contacts.clone())
+    }
+
+    /// Add a campaign; it does not place calls until it is started
+    pub fn add_campaign(&self, campaign: Campaign) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.campaigns.iter().any(|c| c.campaign.id == campaign.id) {
+            bail!("Campaign {} already exists", campaign.id);
+        }
+        state.campaigns.push(CampaignState::new(campaign));
+        Ok(())
+    }
+
+    /// Remove a campaign; calls already placed for it are forgotten
+    pub fn remove_campaign(&self, id: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let before = state.campaigns.len();
+        state.campaigns.retain(|c| c.campaign.id != id);
+        state.in_flight.retain(|_, (campaign, _)| campaign != id);
+        state.campaigns.len() != before
+    }
+
+    /// Add contacts to a campaign, skipping numbers it already has
+    pub fn add_contacts(&self, id: &str, contacts: Vec<Contact>) -> Result<ContactsAdded> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let campaign = state
+            .campaigns
+            .iter_mut()
+            .find(|c| c.campaign.id == id)
+            .ok_or_else(|| anyhow!("Unknown campaign {}", id))?;
+
+        let mut known: HashSet<String> = campaign
+            .contacts
+            .iter()
+            .map(|c| normalize(&c.contact.number))
+            .collect();
+        let mut result = ContactsAdded::default();
+        for contact in contacts {
+            let number = normalize(&contact.number);
+            if number.trim_start_matches('+').is_empty() {
+                bail!("'{}' is not a phone number", contact.number);
+            }
+            if !known.insert(number.clone()) {
+                result.duplicates += 1;
+                continue;
+            }
+            let status = if state.do_not_call.contains(&number) {
+                result.do_not_call += 1;
+                ContactStatus::DoNotCall
+            } else {
+                ContactStatus::Pending
+            };
+            campaign.contacts.push(ContactProgress {
+                contact,
+                status,
+                attempts: 0,
+                last_outcome: None,
+                amd: None,
+                next_attempt_at: None,
+            });
+            result.added += 1;
+        }
+        // New contacts give a completed campaign more to do
+        if campaign.status == CampaignStatus::Completed && result.added > result.do_not_call {
+            campaign.status = CampaignStatus::Running;
+        }
+        Ok(result)
+    }
+
+    /// Start or resume placing calls for a campaign
+    pub fn start(&self, id: &str) -> Result<CampaignStatus> {
+        self.set_status(id, |status| match status {
+            CampaignStatus::Completed => Err(anyhow!("Campaign {} is completed", id)),
+            _ => Ok(CampaignStatus::Running),
+        })
+    }
+
+    /// Stop placing new calls for a campaign; calls in progress finish
+    pub fn pause(&self, id: &str) -> Result<CampaignStatus> {
+        self.set_status(id, |status| match status {
+            CampaignStatus::Running => Ok(CampaignStatus::Paused),
+            other => Err(anyhow!("Campaign {} is {:?}, not running", id, other)),
+        })
+    }
+
+    fn set_status(
+        &self,
+        id: &str,
+        change: impl FnOnce(CampaignStatus) -> Result<CampaignStatus>,
+    ) -> Result<CampaignStatus> {
+        let mut state = self.state.lock().unwrap();
+        let campaign = state
+            .campaigns
+            .iter_mut()
+            .find(|c| c.campaign.id == id)
+            .ok_or_else(|| anyhow!("Unknown campaign {}", id))?;
+        campaign.status = change(campaign.status)?;
+        campaign.settle();
+        Ok(campaign.status)
+    }
+
+    /// The next call to place, if any campaign has one due
+    ///
+    /// Campaigns are taken in order; a campaign is skipped while it is
+    /// outside its calling hours or has used up its pacing.
+    pub fn claim(&self, now: DateTime<Utc>) -> Option<DialAttempt> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        for campaign in state.campaigns.iter_mut() {
+            if campaign.status != CampaignStatus::Running
+                || !campaign.in_window(&self.schedules, now)
+                || !campaign.has_capacity(now)
+            {
+                continue;
+            }
+            let Some(index) = campaign.contacts.iter().position(|c| {
+                c.status == ContactStatus::Pending && c.next_attempt_at.is_none_or(|t| t <= now)
+            }) else {
+                continue;
+            };
+            // The list may have grown since the contact was added
+            let number = normalize(&campaign.contacts[index].contact.number);
+            if state.do_not_call.contains(&number) {
+                campaign.contacts[index].status = ContactStatus::DoNotCall;
+                campaign.settle();
+                continue;
+            }
+
+            let progress = &mut campaign.contacts[index];
+            progress.status = ContactStatus::Dialing;
+            progress.attempts += 1;
+            campaign.attempts += 1;
+            campaign.started.push(now);
+            let attempt = DialAttempt {
+                id: Uuid::new_v4().to_string(),
+                campaign: campaign.campaign.id.clone(),
+                number: progress.contact.number.clone(),
+                caller_id: campaign.campaign.caller_id.clone(),
+                trunk: campaign.campaign.trunk.clone(),
+                connect_to: campaign.campaign.connect_to.clone(),
+                amd: campaign.campaign.amd,
+                on_machine: campaign.campaign.on_machine.clone(),
+                ring_timeout_seconds: campaign.campaign.ring_timeout_seconds,
+                variables: progress.contact.variables.clone(),
+                attempt: progress.attempts,
+                started_at: now,
+            };
+            state
+                .in_flight
+                .insert(attempt.id.clone(), (attempt.campaign.clone(), index));
+            return Some(attempt);
+        }
+        None
+    }
+
+    /// Record how a claimed call ended, with the answering machine
+    /// detection result for answered calls when there is one
+    pub fn report(
+        &self,
+        attempt: &str,
+        outcome: DialOutcome,
+        amd: Option<AmdResult>,
+        now: DateTime<Utc>,
+    ) -> Result<ContactProgress> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let (id, index) = state
+            .in_flight
+            .remove(attempt)
+            .ok_or_else(|| anyhow!("Unknown dial attempt {}", attempt))?;
+        let campaign = state
+            .campaigns
+            .iter_mut()
+            .find(|c| c.campaign.id == id)
+            .ok_or_else(|| anyhow!("Unknown campaign {}", id))?;
+
+        let verdict = amd
+            .filter(|_| outcome == DialOutcome::Answered)
+            .map(|amd| amd.verdict);
+        let machine = verdict == Some(AmdVerdict::Machine);
+        match outcome {
+            DialOutcome::NoAnswer => campaign.no_answer += 1,
+            DialOutcome::Busy => campaign.busy += 1,
+            DialOutcome::Answered if machine => campaign.machines += 1,
+            _ => {}
+        }
+        // Without somewhere to leave a message the contact is tried again
+        let outcome = if machine && campaign.campaign.on_machine.is_none() {
+            DialOutcome::NoAnswer
+        } else {
+            outcome
+        };
+        let max_attempts = campaign.campaign.max_attempts;
+        let retry_delay = Duration::seconds(campaign.campaign.retry_delay_seconds as i64);
+        let progress = &mut campaign.contacts[index];
+        progress.last_outcome = Some(outcome);
+        progress.amd = verdict;
+        progress.next_attempt_at = None;
+        progress.status = match outcome {
+            DialOutcome::Answered => ContactStatus::Answered,
+            DialOutcome::Failed => ContactStatus::Failed,
+            _ if outcome.retries() && progress.attempts < max_attempts => {
+                progress.next_attempt_at = Some(now + retry_delay);
+                ContactStatus::Pending
+            }
+            _ => ContactStatus::Exhausted,
+        };
+        let progress = progress.clone();
+        campaign.settle();
+        Ok(progress)
+    }
+
+    /// Numbers on the do-not-call list
+    pub fn do_not_call(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut numbers: Vec<String> = state.do_not_call.iter().cloned().collect();
+        numbers.sort();
+        numbers
+    }
+
+    pub fn is_do_not_call(&self, number: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.do_not_call.contains(&normalize(number))
+    }
+
+    /// Add a number to the do-not-call list; false if it was already there
+    pub fn add_do_not_call(&self, number: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.do_not_call.insert(normalize(number))
+    }
+
+    /// Remove a number from the do-not-call list; contacts already skipped
+    /// for it stay skipped
+    pub fn remove_do_not_call(&self, number: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.do_not_call.remove(&normalize(number))
+    }
+}
+
+impl Default for Dialer {
+    fn default() -> Self {
+        Self::new(DialerConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn campaign() -> Campaign {
+        serde_json::from_value(serde_json::json!({
+            "id": "reminders",
+            "name": "Appointment reminders",
+            "caller_id": "+12125550100",
+            "connect_to": { "type": "ivr", "target": "reminder" },
+            "pacing": { "calls_per_minute": 2, "max_concurrent": 1 },
+            "calling_hours": [{ "days": [1, 2, 3, 4, 5], "start_time": "09:00", "end_time": "17:00" }],
+            "max_attempts": 2,
+            "retry_delay_seconds": 600
+        }))
+        .unwrap()
+    }
+
+    fn contact(number: &str) -> Contact {
+        Contact {
+            number: number.to_string(),
+            name: None,
+            variables: BTreeMap::new(),
+        }
+    }
+
+    fn monday(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 15, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_contacts_csv() {
+        let contacts =
+            parse_contacts_csv("number,name\n+1 212 555 0123,\"Ada\"\n\n2125550124\n").unwrap();
+        assert_eq!(contacts.len(), 2);
+        assert_eq!(contacts[0].number, "+1 212 555 0123");
+        assert_eq!(contacts[0].name.as_deref(), Some("Ada"));
+        assert_eq!(contacts[1].name, None);
+
+        let error = parse_contacts_csv("2125550123\nnobody\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: 'nobody' is not a phone number");
+    }
+
+    #[test]
+    fn test_window_pacing_and_do_not_call() {
+        let dialer = Dialer::new(DialerConfig {
+            campaigns: vec![campaign()],
+            do_not_call: vec!["+1-212-555-0199".to_string()],
+        });
+        let added = dialer
+            .add_contacts(
+                "reminders",
+                vec![
+                    contact("+12125550123"),
+                    contact("+1 212 555 0123"),
+                    contact("+12125550124"),
+                    contact("+12125550199"),
+                    contact("+12125550125"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            added,
+            ContactsAdded {
+                added: 4,
+                duplicates: 1,
+                do_not_call: 1
+            }
+        );
+
+        // Drafts and campaigns outside their hours place no calls
+        assert!(dialer.claim(monday(10, 0)).is_none());
+        dialer.start("reminders").unwrap();
+        assert!(dialer.claim(monday(8, 59)).is_none());
+
+        let first = dialer.claim(monday(10, 0)).unwrap();
+        assert_eq!(first.number, "+12125550123");
+        assert_eq!(
+            first.connect_to,
+            CampaignTarget::Ivr("reminder".to_string())
+        );
+        assert_eq!(first.attempt, 1);
+        // One call at a time
+        assert!(dialer.claim(monday(10, 0)).is_none());
+
+        dialer
+            .report(&first.id, DialOutcome::Answered, None, monday(10, 0))
+            .unwrap();
+        let second = dialer.claim(monday(10, 0)).unwrap();
+        assert_eq!(second.number, "+12125550124");
+        dialer
+            .report(&second.id, DialOutcome::Failed, None, monday(10, 0))
+            .unwrap();
+        // Two calls per minute
+        assert!(dialer.claim(monday(10, 0)).is_none());
+        let third = dialer.claim(monday(10, 1)).unwrap();
+        assert_eq!(third.number, "+12125550125");
+        dialer
+            .report(&third.id, DialOutcome::Answered, None, monday(10, 1))
+            .unwrap();
+
+        let stats = dialer.campaign("reminders").unwrap().stats;
+        assert_eq!(stats.answered, 2);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.do_not_call, 1);
+        assert_eq!(stats.attempts, 3);
+        assert_eq!(
+            dialer.campaign("reminders").unwrap().status,
+            CampaignStatus::Completed
+        );
+    }
+
+    #[test]
+    fn test_retries_until_exhausted() {
+        let dialer = Dialer::new(DialerConfig {
+            campaigns: vec![campaign()],
+            do_not_call: Vec::new(),
+        });
+        dialer
+            .add_contacts("reminders", vec![contact("+12125550123")])
+            .unwrap();
+        dialer.start("reminders").unwrap();
+
+        let attempt = dialer.claim(monday(10, 0)).unwrap();
+        let progress = dialer
+            .report(&attempt.id, DialOutcome::Busy, None, monday(10, 0))
+            .unwrap();
+        assert_eq!(progress.status, ContactStatus::Pending);
+        assert_eq!(progress.next_attempt_at, Some(monday(10, 10)));
+        assert!(dialer.claim(monday(10, 5)).is_none());
+
+        let attempt = dialer.claim(monday(10, 10)).unwrap();
+        assert_eq!(attempt.attempt, 2);
+        let progress = dialer
+            .report(&attempt.id, DialOutcome::NoAnswer, None, monday(10, 10))
+            .unwrap();
+        assert_eq!(progress.status, ContactStatus::Exhausted);
+        assert!(dialer
+            .report(&attempt.id, DialOutcome::Answered, None, monday(10, 10))
+            .is_err());
+
+        let stats = dialer.campaign("reminders").unwrap().stats;
+        assert_eq!((stats.attempts, stats.busy, stats.no_answer), (2, 1, 1));
+    }
+
+    #[test]
+    fn test_answering_machines() {
+        let machine = AmdResult {
+            verdict: AmdVerdict::Machine,
+            cause: crate::media::amd::AmdCause::LongGreeting,
+            elapsed_ms: 1520,
+        };
+        let mut leave_message = campaign();
+        leave_message.id = "messages".to_string();
+        leave_message.amd = Some(AmdConfig::default());
+        leave_message.on_machine = Some(CampaignTarget::Ivr("leave-message".to_string()));
+        let mut reminders = campaign();
+        reminders.amd = Some(AmdConfig::default());
+        let dialer = Dialer::new(DialerConfig {
+            campaigns: vec![reminders, leave_message],
+            do_not_call: Vec::new(),
+        });
+        for id in ["reminders", "messages"] {
+            dialer
+                .add_contacts(id, vec![contact("+12125550123")])
+                .unwrap();
+            dialer.start(id).unwrap();
+        }
+
+        let attempt = dialer.claim(monday(10, 0)).unwrap();
+        assert_eq!(attempt.amd, Some(AmdConfig::default()));
+        let progress = dialer
+            .report(
+                &attempt.id,
+                DialOutcome::Answered,
+                Some(machine),
+                monday(10, 0),
+            )
+            .unwrap();
+        assert_eq!(progress.status, ContactStatus::Pending);
+        assert_eq!(progress.amd, Some(AmdVerdict::Machine));
+
+        let attempt = dialer.claim(monday(10, 0)).unwrap();
+        assert_eq!(attempt.campaign, "messages");
+        assert_eq!(
+            attempt.on_machine,
+            Some(CampaignTarget::Ivr("leave-message".to_string()))
+        );
+        let progress = dialer
+            .report(
+                &attempt.id,
+                DialOutcome::Answered,
+                Some(machine),
+                monday(10, 0),
+            )
+            .unwrap();
+        assert_eq!(progress.status, ContactStatus::Answered);
+        assert_eq!(dialer.campaign("messages").unwrap().stats.machines, 1);
+    }
+
+    #[test]
+    fn test_do_not_call_added_later() {
+        let dialer = Dialer::new(DialerConfig {
+            campaigns: vec![campaign()],
+            do_not_call: Vec::new(),
+        });
+        dialer
+            .add_contacts("reminders", vec![contact("+12125550123")])
+            .unwrap();
+        dialer.start("reminders").unwrap();
+        assert!(dialer.add_do_not_call("+1 (212) 555-0123"));
+        assert!(dialer.is_do_not_call("+12125550123"));
+
+        assert!(dialer.claim(monday(10, 0)).is_none());
+        let summary = dialer.campaign("reminders").unwrap();
+        assert_eq!(summary.stats.do_not_call, 1);
+        assert_eq!(summary.status, CampaignStatus::Completed);
+        assert!(dialer.start("reminders").is_err());
+    }
+}
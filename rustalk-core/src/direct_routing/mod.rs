@@ -0,0 +1,846 @@
+//! Microsoft Teams Direct Routing validation
+//!
+//! Runs the checks Microsoft recommends before an SBC is paired with a
+//! tenant, in the order a failure would surface:
+//!
+//! 1. The SBC certificate covers the SBC FQDN and is in date
+//! 2. The SBC FQDN and the Teams SIP proxy resolve
+//! 3. A TLS 1.2 handshake with the proxy succeeds using the SBC
+//!    certificate and one of the cipher suites Teams accepts
+//! 4. An OPTIONS sent over that connection, with the SBC FQDN in its
+//!    Contact, is answered 200
+//! 5. A test INVITE to a Teams user's number rings, after which it is
+//!    cancelled
+//!
+//! A check that depends on an earlier failed one is skipped. The result is
+//! a [`ValidationReport`] with a pass/fail line per check.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader as PemReader, Cursor};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::crypto::ring::{cipher_suite, default_provider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{version, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use x509_parser::prelude::*;
+
+use crate::config::TeamsConfig;
+use crate::io;
+use crate::sip::{parser::parse_message, Message, Method, Request, Response, Uri};
+
+/// Teams SIP proxy that SBCs connect to
+pub const TEAMS_SIP_PROXY: &str = "sip.pstnhub.microsoft.com";
+
+/// What to validate and how to reach Teams
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationTarget {
+    /// FQDN the SBC is (or will be) paired under
+    pub sbc_fqdn: String,
+    pub mtls_cert: PathBuf,
+    pub mtls_key: PathBuf,
+    #[serde(default = "default_proxy")]
+    pub proxy: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Number of a Teams user to ring; the test INVITE is skipped without one
+    #[serde(default)]
+    pub test_number: Option<String>,
+    /// Calling number of the test INVITE
+    #[serde(default)]
+    pub caller: Option<String>,
+    /// How long to wait for each step
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_proxy() -> String {
+    TEAMS_SIP_PROXY.to_string()
+}
+
+fn default_port() -> u16 {
+    5061
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+impl ValidationTarget {
+    pub fn new(
+        sbc_fqdn: impl Into<String>,
+        mtls_cert: impl Into<PathBuf>,
+        mtls_key: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            sbc_fqdn: sbc_fqdn.into(),
+            mtls_cert: mtls_cert.into(),
+            mtls_key: mtls_key.into(),
+            proxy: default_proxy(),
+            port: default_port(),
+            test_number: None,
+            caller: None,
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+
+    /// Validate the SBC described by the `teams` configuration section
+    pub fn from_teams(config: &TeamsConfig) -> Self {
+        Self::new(&config.sbc_fqdn, &config.mtls_cert, &config.mtls_key)
+    }
+
+    pub fn with_test_number(mut self, number: impl Into<String>) -> Self {
+        self.test_number = Some(number.into());
+        self
+    }
+
+    pub fn with_caller(mut self, caller: impl Into<String>) -> Self {
+        self.caller = Some(caller.into());
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: impl Into<String>, port: u16) -> Self {
+        self.proxy = proxy.into();
+        self.port = port;
+        self
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// Not run, because an earlier check failed or it was not asked for
+    Skipped,
+}
+
+/// Outcome of one validation step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    /// `certificate`, `sbc_dns`, `proxy_dns`, `tls`, `options` or `invite`
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    fn from_result(name: &str, result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => Self::new(name, CheckStatus::Pass, detail),
+            Err(e) => Self::new(name, CheckStatus::Fail, format!("{:#}", e)),
+        }
+    }
+
+    fn skipped(name: &str, reason: &str) -> Self {
+        Self::new(name, CheckStatus::Skipped, reason)
+    }
+}
+
+/// Pass/fail report for one SBC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub sbc_fqdn: String,
+    pub proxy: String,
+    /// No check failed
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Run every check against `target`
+pub async fn validate(target: &ValidationTarget) -> ValidationReport {
+    let mut checks = Vec::new();
+
+    let identity = load_identity(target).await;
+    checks.push(CheckResult::from_result(
+        "certificate",
+        identity
+            .as_ref()
+            .map(|(_, _, detail)| detail.clone())
+            .map_err(|e| anyhow!("{:#}", e)),
+    ));
+
+    let sbc_dns = io::dns::lookup_host(&target.sbc_fqdn, target.port).await;
+    checks.push(CheckResult::from_result(
+        "sbc_dns",
+        sbc_dns
+            .map(|addrs| format!("{} resolves to {}", target.sbc_fqdn, join_ips(&addrs)))
+            .with_context(|| format!("{} does not resolve", target.sbc_fqdn)),
+    ));
+
+    let proxy = io::dns::lookup_host(&target.proxy, target.port)
+        .await
+        .ok()
+        .and_then(|addrs| addrs.into_iter().next());
+    checks.push(CheckResult::from_result(
+        "proxy_dns",
+        proxy
+            .map(|addr| format!("{} resolves to {}", target.proxy, addr.ip()))
+            .ok_or_else(|| anyhow!("{} does not resolve", target.proxy)),
+    ));
+
+    let stream = match (identity, proxy) {
+        (Ok((certs, key, _)), Some(addr)) => {
+            let connected = connect(target, addr, certs, key).await;
+            match connected {
+                Ok((stream, detail)) => {
+                    checks.push(CheckResult::new("tls", CheckStatus::Pass, detail));
+                    Some(stream)
+                }
+                Err(e) => {
+                    checks.push(CheckResult::new(
+                        "tls",
+                        CheckStatus::Fail,
+                        format!("{:#}", e),
+                    ));
+                    None
+                }
+            }
+        }
+        _ => {
+            checks.push(CheckResult::skipped(
+                "tls",
+                "Needs a valid certificate and a resolvable proxy",
+            ));
+            None
+        }
+    };
+
+    match stream {
+        Some(stream) => {
+            let media_ip = stream.get_ref().0.local_addr().map(|a| a.ip()).ok();
+            let mut probe = Probe::new(stream, target);
+            let options = probe.options().await;
+            let options_passed = options.is_ok();
+            checks.push(CheckResult::from_result("options", options));
+            checks.push(match (&target.test_number, options_passed) {
+                (None, _) => CheckResult::skipped("invite", "No test number given"),
+                (Some(_), false) => CheckResult::skipped("invite", "Needs a successful OPTIONS"),
+                (Some(number), true) => CheckResult::from_result(
+                    "invite",
+                    probe
+                        .invite(number, media_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])))
+                        .await,
+                ),
+            });
+        }
+        None => {
+            checks.push(CheckResult::skipped("options", "Needs a TLS connection"));
+            checks.push(CheckResult::skipped("invite", "Needs a TLS connection"));
+        }
+    }
+
+    ValidationReport {
+        sbc_fqdn: target.sbc_fqdn.clone(),
+        proxy: target.proxy.clone(),
+        passed: checks.iter().all(|c| c.status != CheckStatus::Fail),
+        checks,
+        checked_at: Utc::now(),
+    }
+}
+
+/// Send the proxy an OPTIONS over mutual TLS, as Teams expects of a
+/// paired SBC, returning its answer
+///
+/// Fails unless the proxy answers 200, so it serves as the health probe of
+/// the Teams SIP proxies.
+pub async fn options_ping(target: &ValidationTarget) -> Result<String> {
+    let (certs, key, _) = load_identity(target).await?;
+    let addr = io::dns::lookup_host(&target.proxy, target.port)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("{} does not resolve", target.proxy))?;
+    let (stream, _) = connect(target, addr, certs, key).await?;
+    Probe::new(stream, target).options().await
+}
+
+fn join_ips(addrs: &[SocketAddr]) -> String {
+    let ips: Vec<String> = addrs.iter().map(|a| a.ip().to_string()).collect();
+    ips.join(", ")
+}
+
+/// The SBC certificate chain and key, once the certificate has been
+/// checked against the FQDN
+type Identity = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>, String);
+
+async fn load_identity(target: &ValidationTarget) -> Result<Identity> {
+    let pem = io::fs::read(&target.mtls_cert).await?;
+    let certs = rustls_pemfile::certs(&mut PemReader::new(Cursor::new(pem)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let leaf = certs
+        .first()
+        .ok_or_else(|| anyhow!("No certificate in {}", target.mtls_cert.display()))?;
+    let detail = check_certificate(leaf, &target.sbc_fqdn, Utc::now())?;
+
+    let pem = io::fs::read(&target.mtls_key).await?;
+    let key = rustls_pemfile::private_key(&mut PemReader::new(Cursor::new(pem)))?
+        .ok_or_else(|| anyhow!("No private key in {}", target.mtls_key.display()))?;
+    Ok((certs, key, detail))
+}
+
+/// Check that a certificate names `fqdn` and is in date
+fn check_certificate(der: &[u8], fqdn: &str, now: DateTime<Utc>) -> Result<String> {
+    let (_, cert) =
+        X509Certificate::from_der(der).map_err(|e| anyhow!("Invalid certificate: {}", e))?;
+
+    let mut names: Vec<String> = Vec::new();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(dns) = name {
+                names.push(dns.to_string());
+            }
+        }
+    }
+    if let Some(cn) = cert.subject().iter_common_name().next() {
+        if let Ok(cn) = cn.as_str() {
+            names.push(cn.to_string());
+        }
+    }
+    let Some(name) = names.iter().find(|name| covers(name, fqdn)) else {
+        bail!(
+            "Certificate names {} but not the SBC FQDN {}",
+            names.join(", "),
+            fqdn
+        );
+    };
+
+    let expires_at = DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow!("Certificate expiry out of range"))?;
+    if expires_at <= now {
+        bail!("Certificate expired on {}", expires_at.format("%Y-%m-%d"));
+    }
+    Ok(format!(
+        "{} covers {}, expires {}",
+        name,
+        fqdn,
+        expires_at.format("%Y-%m-%d")
+    ))
+}
+
+/// Whether a certificate name (possibly a wildcard) covers `fqdn`
+fn covers(name: &str, fqdn: &str) -> bool {
+    let (name, fqdn) = (name.to_ascii_lowercase(), fqdn.to_ascii_lowercase());
+    match name.strip_prefix("*.") {
+        // A wildcard covers exactly one label
+        Some(domain) => fqdn
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+        None => name == fqdn,
+    }
+}
+
+type TlsStream = tokio_rustls::client::TlsStream<TcpStream>;
+
+/// Connect to the proxy offering only the TLS 1.2 cipher suites Teams
+/// accepts, presenting the SBC certificate
+async fn connect(
+    target: &ValidationTarget,
+    addr: SocketAddr,
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<(TlsStream, String)> {
+    let mut provider = default_provider();
+    provider.cipher_suites = vec![
+        cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    ];
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&[&version::TLS12])?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(certs, key)
+        .context("SBC certificate and key do not match")?;
+    let server_name = ServerName::try_from(target.proxy.clone())?;
+
+    let handshake = async {
+        let tcp = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("Cannot connect to {}", addr))?;
+        TlsConnector::from(Arc::new(config))
+            .connect(server_name, tcp)
+            .await
+            .context("TLS handshake failed")
+    };
+    let stream = tokio::time::timeout(target.timeout(), handshake)
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "No TLS handshake with {} within {:?}",
+                addr,
+                target.timeout()
+            )
+        })??;
+
+    let (_, connection) = stream.get_ref();
+    let detail = format!(
+        "{:?} with {:?} to {}",
+        connection
+            .protocol_version()
+            .ok_or_else(|| anyhow!("No protocol negotiated"))?,
+        connection
+            .negotiated_cipher_suite()
+            .ok_or_else(|| anyhow!("No cipher suite negotiated"))?
+            .suite(),
+        addr
+    );
+    Ok((stream, detail))
+}
+
+/// Explanation of a Teams rejection, for the common causes
+fn hint(status: u16) -> &'static str {
+    match status {
+        403 => " (is the SBC FQDN paired with the tenant, and does the Contact match it?)",
+        404 => " (is the number assigned to a Teams user with Enterprise Voice?)",
+        408 | 480 => " (is the Teams user signed in?)",
+        488 => " (Teams wants SRTP unless media bypass is configured)",
+        _ => "",
+    }
+}
+
+/// SIP requests sent over the validation connection
+struct Probe<'a, S> {
+    stream: BufReader<S>,
+    target: &'a ValidationTarget,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> Probe<'a, S> {
+    fn new(stream: S, target: &'a ValidationTarget) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            target,
+        }
+    }
+
+    fn request(&self, method: Method, uri: Uri, call_id: &str, cseq: u32, branch: &str) -> Request {
+        let target = self.target;
+        Request::new(method, uri)
+            .with_header(
+                "Via",
+                format!(
+                    "SIP/2.0/TLS {}:{};branch={}",
+                    target.sbc_fqdn,
+                    default_port(),
+                    branch
+                ),
+            )
+            .with_header("Max-Forwards", "70")
+            .with_header("Call-ID", call_id)
+            .with_header("CSeq", format!("{} {}", cseq, method))
+            .with_header("User-Agent", user_agent())
+    }
+
+    async fn send(&mut self, request: Request) -> Result<()> {
+        let body_len = request.body.len();
+        let request = request.with_header("Content-Length", body_len.to_string());
+        self.stream.write_all(&request.to_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// The next response for `call_id`, skipping anything else on the
+    /// connection
+    async fn response(&mut self, call_id: &str) -> Result<Response> {
+        let timeout = self.target.timeout();
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Message::Response(response) = read_message(&mut self.stream).await? {
+                    if response.get_header_value("Call-ID") == Some(call_id) {
+                        return Ok(response);
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("No response within {:?}", timeout))?
+    }
+
+    fn proxy_uri(&self, user: Option<&str>) -> Uri {
+        let mut uri =
+            Uri::new("sip".to_string(), self.target.proxy.clone()).with_port(self.target.port);
+        if let Some(user) = user {
+            uri = uri.with_user(user.to_string());
+        }
+        uri.params
+            .push(("transport".to_string(), Some("tls".to_string())));
+        uri
+    }
+
+    /// OPTIONS with the SBC FQDN in the Contact, which is how Teams
+    /// recognises a paired SBC
+    async fn options(&mut self) -> Result<String> {
+        let call_id = random_token();
+        let fqdn = self.target.sbc_fqdn.clone();
+        let request = self
+            .request(
+                Method::Options,
+                self.proxy_uri(None),
+                &call_id,
+                1,
+                &branch(),
+            )
+            .with_header("From", format!("<sip:{}>;tag={}", fqdn, random_token()))
+            .with_header("To", format!("<sip:{}>", self.target.proxy))
+            .with_header(
+                "Contact",
+                format!("<sip:{}:{};transport=tls>", fqdn, default_port()),
+            );
+        self.send(request).await?;
+
+        let response = self.response(&call_id).await?;
+        let status = response.status_code.0;
+        if status != 200 {
+            bail!(
+                "Teams answered {} {}{}",
+                status,
+                response.reason_phrase,
+                hint(status)
+            );
+        }
+        Ok(format!("Teams answered 200 {}", response.reason_phrase))
+    }
+
+    /// INVITE `number`, wait for it to ring and cancel it
+    async fn invite(&mut self, number: &str, media_ip: IpAddr) -> Result<String> {
+        let call_id = random_token();
+        let branch = branch();
+        let fqdn = self.target.sbc_fqdn.clone();
+        let caller = self
+            .target
+            .caller
+            .clone()
+            .unwrap_or_else(|| "rustalk".to_string());
+        let mut uri = self.proxy_uri(Some(number));
+        uri.params
+            .push(("user".to_string(), Some("phone".to_string())));
+        let from = format!("<sip:{}@{}>;tag={}", caller, fqdn, random_token());
+        let to = format!("<sip:{}@{}>", number, self.target.proxy);
+        let sdp = offer(media_ip);
+
+        let invite = self
+            .request(Method::Invite, uri.clone(), &call_id, 1, &branch)
+            .with_header("From", from.clone())
+            .with_header("To", to.clone())
+            .with_header(
+                "Contact",
+                format!("<sip:{}@{}:{};transport=tls>", caller, fqdn, default_port()),
+            )
+            .with_header("P-Asserted-Identity", format!("<sip:{}@{}>", caller, fqdn))
+            // Teams reads the SBC vendor, model and version from this
+            .with_header(
+                "X-MS-SBC",
+                format!("RusTalk/SBC/{}", env!("CARGO_PKG_VERSION")),
+            )
+            .with_header("Allow", "INVITE, ACK, CANCEL, BYE, OPTIONS")
+            .with_header("Content-Type", "application/sdp")
+            .with_body(sdp);
+        self.send(invite).await?;
+
+        loop {
+            let response = self.response(&call_id).await?;
+            let status = response.status_code.0;
+            // Carries the tag Teams gave the dialog
+            let remote_to = response.get_header_value("To").unwrap_or(&to).to_string();
+            match status {
+                100 => continue,
+                101..=199 => {
+                    // Ringing is enough; hang up before anyone answers
+                    let cancel = self
+                        .request(Method::Cancel, uri.clone(), &call_id, 1, &branch)
+                        .with_header("From", from.clone())
+                        .with_header("To", to.clone());
+                    self.send(cancel).await?;
+                    self.finish_cancel(&call_id, &uri, &branch, &from).await;
+                    return Ok(format!(
+                        "Teams answered {} {}; call cancelled",
+                        status, response.reason_phrase
+                    ));
+                }
+                200..=299 => {
+                    let ack = self
+                        .request(Method::Ack, uri.clone(), &call_id, 1, &self::branch())
+                        .with_header("From", from.clone())
+                        .with_header("To", remote_to.clone());
+                    self.send(ack).await?;
+                    let bye = self
+                        .request(Method::Bye, uri.clone(), &call_id, 2, &self::branch())
+                        .with_header("From", from.clone())
+                        .with_header("To", remote_to);
+                    self.send(bye).await?;
+                    let _ = self.response(&call_id).await;
+                    return Ok(format!("Teams answered {}; call released", status));
+                }
+                _ => {
+                    // The INVITE transaction still needs its ACK
+                    let ack = self
+                        .request(Method::Ack, uri.clone(), &call_id, 1, &branch)
+                        .with_header("From", from.clone())
+                        .with_header("To", remote_to);
+                    self.send(ack).await?;
+                    bail!(
+                        "Teams answered {} {}{}",
+                        status,
+                        response.reason_phrase,
+                        hint(status)
+                    );
+                }
+            }
+        }
+    }
+
+    /// Wait for the INVITE's final response after a CANCEL and
+    /// acknowledge it; best effort, as the call is already judged
+    async fn finish_cancel(&mut self, call_id: &str, uri: &Uri, branch: &str, from: &str) {
+        while let Ok(response) = self.response(call_id).await {
+            let invite = response
+                .get_header_value("CSeq")
+                .is_some_and(|cseq| cseq.ends_with("INVITE"));
+            if !invite || response.status_code.0 < 200 {
+                continue;
+            }
+            let to = response
+                .get_header_value("To")
+                .unwrap_or_default()
+                .to_string();
+            let ack = self
+                .request(Method::Ack, uri.clone(), call_id, 1, branch)
+                .with_header("From", from)
+                .with_header("To", to);
+            let _ = self.send(ack).await;
+            break;
+        }
+    }
+}
+
+/// Read one SIP message framed by its Content-Length
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<Message> {
+    let mut head = Vec::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("Connection closed");
+        }
+        // Keep-alive CRLFs may precede a message
+        if line == "\r\n" && head.is_empty() {
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("Content-Length") || name == "l" {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+        head.extend_from_slice(line.as_bytes());
+        if line == "\r\n" {
+            break;
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    head.extend_from_slice(&body);
+    parse_message(&head).map_err(|e| anyhow!(e))
+}
+
+/// Audio offer with SDES-SRTP, as Teams requires without media bypass
+fn offer(media_ip: IpAddr) -> String {
+    let mut key = [0u8; 30];
+    rand::thread_rng().fill_bytes(&mut key);
+    let family = if media_ip.is_ipv6() { "IP6" } else { "IP4" };
+    format!(
+        "v=0\r\n\
+         o=rustalk 0 0 IN {family} {ip}\r\n\
+         s=Direct Routing validation\r\n\
+         c=IN {family} {ip}\r\n\
+         t=0 0\r\n\
+         m=audio 9 RTP/SAVP 0 8 101\r\n\
+         a=rtpmap:0 PCMU/8000\r\n\
+         a=rtpmap:8 PCMA/8000\r\n\
+         a=rtpmap:101 telephone-event/8000\r\n\
+         a=crypto:1 AES_CM_128_HMAC_SHA1_80 inline:{key}\r\n\
+         a=sendrecv\r\n",
+        family = family,
+        ip = media_ip,
+        key = BASE64.encode(key)
+    )
+}
+
+fn user_agent() -> String {
+    format!("RusTalk/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn branch() -> String {
+    format!("z9hG4bK-{}", random_token())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_names() {
+        let now = Utc::now();
+        let cert = rcgen::generate_simple_self_signed(vec!["*.sbc.example.com".to_string()])
+            .unwrap()
+            .cert;
+
+        let detail = check_certificate(cert.der(), "teams.sbc.example.com", now).unwrap();
+        assert!(detail.starts_with("*.sbc.example.com covers"));
+        // A wildcard covers one label only
+        assert!(check_certificate(cert.der(), "a.teams.sbc.example.com", now).is_err());
+        assert!(check_certificate(cert.der(), "sbc.example.com", now).is_err());
+
+        let error = check_certificate(
+            cert.der(),
+            "teams.sbc.example.com",
+            now + chrono::Duration::days(365 * 3000),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("expired"));
+    }
+
+    /// Request line and headers of a request sent by the probe
+    async fn read_request<R: AsyncRead + Unpin>(
+        reader: &mut BufReader<R>,
+    ) -> (String, Vec<(String, String)>) {
+        let mut start = String::new();
+        reader.read_line(&mut start).await.unwrap();
+        let mut headers = Vec::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            let (name, value) = line.trim_end().split_once(": ").unwrap();
+            if name == "Content-Length" {
+                content_length = value.parse().unwrap();
+            }
+            headers.push((name.to_string(), value.to_string()));
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await.unwrap();
+        (start.trim_end().to_string(), headers)
+    }
+
+    fn reply(status: u16, headers: &[(String, String)]) -> Vec<u8> {
+        let mut response = Response::new(status.into());
+        for (name, value) in headers {
+            if ["Via", "From", "To", "Call-ID", "CSeq"].contains(&name.as_str()) {
+                let value = if name == "To" && status > 100 {
+                    format!("{};tag=teams", value)
+                } else {
+                    value.clone()
+                };
+                response = response.with_header(name.as_str(), value);
+            }
+        }
+        response.with_header("Content-Length", "0").to_bytes()
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> &'a str {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_options_and_invite() {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let teams = tokio::spawn(async move {
+            let mut server = BufReader::new(server);
+            let mut seen = Vec::new();
+
+            let (line, options) = read_request(&mut server).await;
+            assert!(line.starts_with("OPTIONS sip:sip.pstnhub.microsoft.com:5061;transport=tls"));
+            assert_eq!(
+                header(&options, "Contact"),
+                "<sip:sbc.example.com:5061;transport=tls>"
+            );
+            server.write_all(&reply(200, &options)).await.unwrap();
+
+            let (line, invite) = read_request(&mut server).await;
+            assert!(line.starts_with(
+                "INVITE sip:+14255550100@sip.pstnhub.microsoft.com:5061;transport=tls;user=phone"
+            ));
+            assert!(header(&invite, "X-MS-SBC").starts_with("RusTalk/"));
+            server.write_all(&reply(100, &invite)).await.unwrap();
+            server.write_all(&reply(180, &invite)).await.unwrap();
+
+            let (line, cancel) = read_request(&mut server).await;
+            seen.push(line);
+            assert_eq!(header(&cancel, "Via"), header(&invite, "Via"));
+            server.write_all(&reply(200, &cancel)).await.unwrap();
+            server.write_all(&reply(487, &invite)).await.unwrap();
+
+            let (line, ack) = read_request(&mut server).await;
+            seen.push(line);
+            assert_eq!(
+                header(&ack, "To"),
+                format!("{};tag=teams", header(&invite, "To"))
+            );
+            seen
+        });
+
+        let target = ValidationTarget::new("sbc.example.com", "cert.pem", "key.pem")
+            .with_test_number("+14255550100");
+        let mut probe = Probe::new(client, &target);
+        assert!(probe.options().await.unwrap().contains("200"));
+        let detail = probe
+            .invite("+14255550100", IpAddr::from([192, 0, 2, 10]))
+            .await
+            .unwrap();
+        assert!(detail.contains("180"));
+
+        let seen = teams.await.unwrap();
+        assert!(seen[0].starts_with("CANCEL "));
+        assert!(seen[1].starts_with("ACK "));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_options() {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        tokio::spawn(async move {
+            let mut server = BufReader::new(server);
+            let (_, options) = read_request(&mut server).await;
+            server.write_all(&reply(403, &options)).await.unwrap();
+        });
+
+        let target = ValidationTarget::new("sbc.example.com", "cert.pem", "key.pem");
+        let error = Probe::new(client, &target).options().await.unwrap_err();
+        assert!(error.to_string().contains("403"));
+        assert!(error.to_string().contains("paired"));
+    }
+}
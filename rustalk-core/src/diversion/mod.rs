@@ -0,0 +1,209 @@
+//! Diversion and History-Info for forwarded calls
+//!
+//! When a call is forwarded the INVITE sent on to the new destination says
+//! who it was meant for and why it moved, so a carrier can bill the
+//! forwarding party and a phone or Teams client can show "forwarded by".
+//! Two headers carry this and trunks differ in which they accept: the older
+//! Diversion (RFC 5806), and History-Info (RFC 7044) with the redirection
+//! cause on the target URI (RFC 4458). Both are added; a trunk that rejects
+//! one is bound to a quirk profile that strips it.
+
+use crate::sip::{Header, Request};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Why a call was forwarded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiversionReason {
+    /// Forwarded always, without ringing
+    Unconditional,
+    UserBusy,
+    NoAnswer,
+    /// The callee could not be reached
+    Unavailable,
+    /// Sent on by the callee while ringing
+    Deflection,
+    Unknown,
+}
+
+impl DiversionReason {
+    /// `reason` parameter of a Diversion header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unconditional => "unconditional",
+            Self::UserBusy => "user-busy",
+            Self::NoAnswer => "no-answer",
+            Self::Unavailable => "unavailable",
+            Self::Deflection => "deflection",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// `cause` parameter of a History-Info target (RFC 4458 section 3.2)
+    pub fn cause(&self) -> u16 {
+        match self {
+            Self::Unconditional => 302,
+            Self::UserBusy => 486,
+            Self::NoAnswer => 408,
+            Self::Unavailable => 503,
+            Self::Deflection => 487,
+            Self::Unknown => 404,
+        }
+    }
+}
+
+impl fmt::Display for DiversionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A call moved from one destination to another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirection {
+    /// URI the call was for, e.g. `sip:1001@example.com`
+    pub diverted_from: String,
+    /// URI the call now goes to
+    pub target: String,
+    pub reason: DiversionReason,
+}
+
+impl Redirection {
+    /// Diversion header value for the redirection
+    pub fn diversion(&self) -> String {
+        format!("<{}>;reason={};counter=1", self.diverted_from, self.reason)
+    }
+
+    /// Add Diversion and History-Info for the redirection to the INVITE
+    /// sent on to the new target
+    ///
+    /// Diversion entries are listed most recent first, so ours goes above
+    /// any the request already carries. History-Info is extended from the
+    /// request's last entry, or started with the original target when it
+    /// has none.
+    pub fn apply(&self, request: &mut Request) {
+        let first = request
+            .headers
+            .iter()
+            .position(|h| h.name.as_str().eq_ignore_ascii_case("Diversion"))
+            .unwrap_or(request.headers.len());
+        request
+            .headers
+            .insert(first, Header::new("Diversion", self.diversion().as_str()));
+
+        let parent = match last_history_index(request) {
+            Some(index) => index,
+            None => {
+                request.headers.push(Header::new(
+                    "History-Info",
+                    format!("<{}>;index=1", self.diverted_from).as_str(),
+                ));
+                "1".to_string()
+            }
+        };
+        request.headers.push(Header::new(
+            "History-Info",
+            format!(
+                "<{};cause={}>;index={}.1;mp={}",
+                self.target,
+                self.reason.cause(),
+                parent,
+                parent
+            )
+            .as_str(),
+        ));
+    }
+}
+
+/// `index` of the last History-Info entry of a request
+fn last_history_index(request: &Request) -> Option<String> {
+    request
+        .headers
+        .iter()
+        .filter(|h| h.name.as_str().eq_ignore_ascii_case("History-Info"))
+        .flat_map(|h| h.value.as_str().split(','))
+        .rev()
+        .find_map(|entry| {
+            let params = &entry[entry.rfind('>')? + 1..];
+            params.split(';').find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("index")
+                    .then(|| value.trim().to_string())
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Method, Uri};
+
+    fn forwarded(reason: DiversionReason) -> Redirection {
+        Redirection {
+            diverted_from: "sip:1001@example.com".to_string(),
+            target: "sip:+12125551234@example.com".to_string(),
+            reason,
+        }
+    }
+
+    fn values<'a>(request: &'a Request, name: &str) -> Vec<&'a str> {
+        request
+            .headers
+            .iter()
+            .filter(|h| h.name.as_str() == name)
+            .map(|h| h.value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_redirection_headers() {
+        let mut request = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string())
+                .with_user("+12125551234".to_string()),
+        )
+        .with_header("Call-ID", "fwd1");
+        forwarded(DiversionReason::NoAnswer).apply(&mut request);
+        assert_eq!(
+            values(&request, "Diversion"),
+            ["<sip:1001@example.com>;reason=no-answer;counter=1"]
+        );
+        assert_eq!(
+            values(&request, "History-Info"),
+            [
+                "<sip:1001@example.com>;index=1",
+                "<sip:+12125551234@example.com;cause=408>;index=1.1;mp=1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redirection_extends_history() {
+        // Forwarded to us already by another PBX
+        let mut request = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Diversion", "<sip:555@pbx.example>;reason=unconditional")
+        .with_header(
+            "History-Info",
+            "<sip:555@pbx.example>;index=1, <sip:1001@example.com;cause=302>;index=1.1;mp=1",
+        );
+        forwarded(DiversionReason::UserBusy).apply(&mut request);
+        assert_eq!(
+            values(&request, "Diversion"),
+            [
+                "<sip:1001@example.com>;reason=user-busy;counter=1",
+                "<sip:555@pbx.example>;reason=unconditional"
+            ]
+        );
+        let history = values(&request, "History-Info");
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            history[1],
+            "<sip:+12125551234@example.com;cause=486>;index=1.1.1;mp=1.1"
+        );
+    }
+}
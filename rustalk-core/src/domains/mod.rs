@@ -0,0 +1,233 @@
+//! SIP domains and their aliases
+//!
+//! One deployment can serve several branded domains. Each SIP profile names
+//! the domain it serves and the aliases that mean the same thing, so
+//! `1001@voice.example.com` and `1001@example.com` are one address-of-record
+//! while `1001@other.example` is another. The registrar keys bindings by
+//! the canonical domain and challenges in that domain's realm, credentials
+//! can be limited to one domain, calls to a served domain only ring
+//! endpoints registered in it, and routes can match on the domain called.
+//!
+//! ```json
+//! {
+//!   "domains": {
+//!     "profiles": [
+//!       {
+//!         "name": "brand-a",
+//!         "domain": "example.com",
+//!         "aliases": ["voice.example.com", "sip.example.com"]
+//!       },
+//!       { "name": "brand-b", "domain": "other.example", "realm": "Other Voice" }
+//!     ]
+//!   }
+//! }
+//! ```
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+/// The domain a SIP profile serves and the other names for it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DomainProfile {
+    /// SIP profile name
+    pub name: String,
+    /// Canonical domain, the one addresses-of-record are stored under
+    pub domain: String,
+    /// Other domains that mean the same one
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Digest realm REGISTERs in the domain are challenged with; the
+    /// canonical domain when unset
+    #[serde(default)]
+    pub realm: Option<String>,
+    /// Transport interface requests from the domain are sent out of
+    #[serde(default)]
+    pub egress_interface: Option<String>,
+}
+
+impl DomainProfile {
+    /// Whether `host` is the profile's domain or one of its aliases
+    pub fn serves(&self, host: &str) -> bool {
+        self.domain.eq_ignore_ascii_case(host)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(host))
+    }
+}
+
+/// The `domains` configuration section
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DomainConfig {
+    #[serde(default)]
+    pub profiles: Vec<DomainProfile>,
+}
+
+/// Domains served per SIP profile, shared between the registrar, the B2BUA
+/// and the API
+#[derive(Debug, Clone, Default)]
+pub struct DomainMap {
+    profiles: Arc<RwLock<Vec<DomainProfile>>>,
+}
+
+impl DomainMap {
+    pub fn new(config: DomainConfig) -> Self {
+        Self {
+            profiles: Arc::new(RwLock::new(config.profiles)),
+        }
+    }
+
+    /// Canonical domains served, sorted
+    pub fn domains(&self) -> Vec<String> {
+        let mut domains: Vec<String> = self
+            .profiles
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| p.domain.to_ascii_lowercase())
+            .collect();
+        domains.sort();
+        domains.dedup();
+        domains
+    }
+
+    /// Canonical domain of `host`, or `None` when no profile serves it
+    pub fn canonical(&self, host: &str) -> Option<String> {
+        self.profiles
+            .read()
+            .unwrap()
+            .iter()
+            .find(|p| p.serves(host))
+            .map(|p| p.domain.to_ascii_lowercase())
+    }
+
+    /// Canonical domain of `host`, or `host` itself when no profile serves it
+    pub fn canonicalize(&self, host: &str) -> String {
+        self.canonical(host).unwrap_or_else(|| host.to_string())
+    }
+
+    /// Digest realm of the domain `host` belongs to, if a profile serves it
+    pub fn realm(&self, host: &str) -> Option<String> {
+        self.profiles
+            .read()
+            .unwrap()
+            .iter()
+            .find(|p| p.serves(host))
+            .map(|p| {
+                p.realm
+                    .clone()
+                    .unwrap_or_else(|| p.domain.to_ascii_lowercase())
+            })
+    }
+
+    /// Egress interface pinned for requests from the domain `host` belongs to
+    pub fn egress_interface(&self, host: &str) -> Option<String> {
+        self.profiles
+            .read()
+            .unwrap()
+            .iter()
+            .find(|p| p.serves(host))
+            .and_then(|p| p.egress_interface.clone())
+    }
+
+    /// `aor` with its host replaced by the canonical domain, so
+    /// registrations under an alias are found under the domain
+    pub fn canonical_aor(&self, aor: &str) -> String {
+        let Some((start, end)) = host_span(aor) else {
+            return aor.to_string();
+        };
+        match self.canonical(&aor[start..end]) {
+            Some(domain) => format!("{}{}{}", &aor[..start], domain, &aor[end..]),
+            None => aor.to_string(),
+        }
+    }
+
+    /// Add a profile, replacing the one of the same name
+    pub fn set_profile(&self, profile: DomainProfile) {
+        let mut profiles = self.profiles.write().unwrap();
+        profiles.retain(|p| p.name != profile.name);
+        profiles.push(profile);
+    }
+
+    /// Stop serving a profile's domains
+    pub fn remove_profile(&self, name: &str) {
+        self.profiles.write().unwrap().retain(|p| p.name != name);
+    }
+}
+
+/// Byte range of the host in a SIP URI
+fn host_span(uri: &str) -> Option<(usize, usize)> {
+    let scheme_end = uri.find(':')? + 1;
+    let rest = &uri[scheme_end..];
+    let start = scheme_end + rest.find('@').map(|at| at + 1).unwrap_or(0);
+    let end = uri[start..]
+        .find([':', ';', '>', '?'])
+        .map(|i| start + i)
+        .unwrap_or(uri.len());
+    (start < end).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains() -> DomainMap {
+        DomainMap::new(
+            serde_json::from_value(serde_json::json!({
+                "profiles": [
+                    {
+                        "name": "brand-a",
+                        "domain": "Example.com",
+                        "aliases": ["voice.example.com"]
+                    },
+                    { "name": "brand-b", "domain": "other.example", "realm": "Other Voice" }
+                ]
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_canonical_domains() {
+        let domains = domains();
+        assert_eq!(domains.domains(), ["example.com", "other.example"]);
+        assert_eq!(
+            domains.canonical("VOICE.example.com").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(domains.canonical("192.0.2.1"), None);
+        assert_eq!(domains.canonicalize("192.0.2.1"), "192.0.2.1");
+
+        assert_eq!(
+            domains.realm("voice.example.com").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            domains.realm("other.example").as_deref(),
+            Some("Other Voice")
+        );
+        assert_eq!(domains.realm("unknown.example"), None);
+
+        domains.remove_profile("brand-b");
+        assert_eq!(domains.canonical("other.example"), None);
+    }
+
+    #[test]
+    fn test_canonical_aor() {
+        let domains = domains();
+        assert_eq!(
+            domains.canonical_aor("sip:1001@voice.example.com"),
+            "sip:1001@example.com"
+        );
+        assert_eq!(
+            domains.canonical_aor("sips:1001@voice.example.com:5061;transport=tls"),
+            "sips:1001@example.com:5061;transport=tls"
+        );
+        assert_eq!(
+            domains.canonical_aor("sip:1001@other.example"),
+            "sip:1001@other.example"
+        );
+        assert_eq!(
+            domains.canonical_aor("sip:1001@192.0.2.1"),
+            "sip:1001@192.0.2.1"
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! ENUM resolution of E.164 numbers to SIP URIs (RFC 6116)
+//!
+//! A number such as `+12125551234` is looked up as NAPTR records of
+//! `4.3.2.1.5.5.5.2.1.2.1.e164.arpa`. Terminal records (flag `u`) for a SIP
+//! service carry a `!pattern!replacement!` regexp that, applied to the
+//! number, gives the URI the call can be delivered to directly instead of
+//! over a PSTN trunk. Routes use the mapping through the `Enum` destination
+//! and condition. Answers, including "no mapping", are cached; failures are
+//! not.
+
+use anyhow::{anyhow, bail, Result};
+use regex::RegexBuilder;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::io::dns::Naptr;
+
+/// ENUM lookup configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EnumConfig {
+    /// Zone numbers are looked up under
+    #[serde(default = "default_domain")]
+    pub domain: String,
+    /// ENUM services whose records are used, e.g. `E2U+sip`
+    #[serde(default = "default_services")]
+    pub services: Vec<String>,
+    /// Calls wait at most this long for an answer
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How long answers, including "no mapping", are reused
+    #[serde(default = "default_cache_seconds")]
+    pub cache_seconds: u64,
+}
+
+impl Default for EnumConfig {
+    fn default() -> Self {
+        Self {
+            domain: default_domain(),
+            services: default_services(),
+            timeout_ms: default_timeout_ms(),
+            cache_seconds: default_cache_seconds(),
+        }
+    }
+}
+
+fn default_domain() -> String {
+    "e164.arpa".to_string()
+}
+
+fn default_services() -> Vec<String> {
+    vec!["E2U+sip".to_string(), "E2U+voice:sip".to_string()]
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_cache_seconds() -> u64 {
+    300
+}
+
+/// Looks up NAPTR records
+#[async_trait::async_trait]
+pub trait NaptrSource: Send + Sync {
+    async fn naptr(&self, name: &str) -> Result<Vec<Naptr>>;
+}
+
+/// NAPTR records from DNS (needs the `hickory-dns` feature)
+pub struct DnsNaptrSource;
+
+#[async_trait::async_trait]
+impl NaptrSource for DnsNaptrSource {
+    async fn naptr(&self, name: &str) -> Result<Vec<Naptr>> {
+        crate::io::dns::lookup_naptr(name).await
+    }
+}
+
+/// Cached URIs by number, `None` when the number has no mapping
+type UriCache = HashMap<String, (Instant, Option<String>)>;
+
+/// Resolves E.164 numbers to SIP URIs
+#[derive(Clone)]
+pub struct EnumResolver {
+    config: Arc<EnumConfig>,
+    source: Arc<dyn NaptrSource>,
+    cache: Arc<Mutex<UriCache>>,
+}
+
+impl EnumResolver {
+    /// Resolve against DNS
+    pub fn new(config: EnumConfig) -> Self {
+        Self::with_source(config, Arc::new(DnsNaptrSource))
+    }
+
+    /// Resolve against `source` instead of DNS
+    pub fn with_source(config: EnumConfig, source: Arc<dyn NaptrSource>) -> Self {
+        Self {
+            config: Arc::new(config),
+            source,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &EnumConfig {
+        &self.config
+    }
+
+    /// SIP URI `number` maps to, or `None` when it has no mapping or is not
+    /// an E.164 number
+    pub async fn resolve(&self, number: &str) -> Result<Option<String>> {
+        let number = number.split(';').next().unwrap_or_default();
+        let Some(digits) = number.strip_prefix('+') else {
+            return Ok(None);
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(None);
+        }
+
+        let ttl = Duration::from_secs(self.config.cache_seconds);
+        if let Some((at, uri)) = self.cache.lock().unwrap().get(number) {
+            if at.elapsed() < ttl {
+                return Ok(uri.clone());
+            }
+        }
+
+        let name = enum_domain(number, &self.config.domain);
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let records = tokio::time::timeout(timeout, self.source.naptr(&name))
+            .await
+            .map_err(|_| anyhow!("ENUM lookup of {} timed out after {:?}", number, timeout))??;
+        let uri = records
+            .iter()
+            .filter(|r| r.flags.eq_ignore_ascii_case("u"))
+            .filter(|r| {
+                self.config
+                    .services
+                    .iter()
+                    .any(|s| s.eq_ignore_ascii_case(&r.services))
+            })
+            .filter_map(|r| apply_regexp(&r.regexp, number).ok())
+            .find(|uri| {
+                let scheme = uri.split(':').next().unwrap_or_default();
+                scheme.eq_ignore_ascii_case("sip") || scheme.eq_ignore_ascii_case("sips")
+            });
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        cache.insert(number.to_string(), (Instant::now(), uri.clone()));
+        Ok(uri)
+    }
+}
+
+/// ENUM name of a number: its digits reversed, dot-separated, under `domain`
+pub fn enum_domain(number: &str, domain: &str) -> String {
+    let mut labels: Vec<String> = number
+        .chars()
+        .filter(char::is_ascii_digit)
+        .rev()
+        .map(String::from)
+        .collect();
+    labels.push(domain.trim_matches('.').to_string());
+    labels.join(".")
+}
+
+/// Apply a NAPTR substitution expression such as `!^(.*)$!sip:\1@example.com!`
+/// to `input`
+fn apply_regexp(expression: &str, input: &str) -> Result<String> {
+    let delimiter = expression
+        .chars()
+        .next()
+        .ok_or_else(|| anyhow!("empty regexp"))?;
+    let parts: Vec<&str> = expression[delimiter.len_utf8()..]
+        .split(delimiter)
+        .collect();
+    let [pattern, replacement, flags] = parts[..] else {
+        bail!("'{}' is not a substitution expression", expression);
+    };
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(flags.contains('i'))
+        .build()?;
+    if !regex.is_match(input) {
+        bail!("'{}' does not match {}", pattern, input);
+    }
+
+    // Backreferences are written \1 to \9
+    let mut template = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => template.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => template.push_str(&format!("${{{}}}", d)),
+                Some(other) => template.push(other),
+                None => template.push('\\'),
+            },
+            c => template.push(c),
+        }
+    }
+    Ok(regex.replace(input, template.as_str()).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers from a fixed zone, counting queries
+    struct Zone {
+        records: HashMap<String, Vec<Naptr>>,
+        queries: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl NaptrSource for Zone {
+        async fn naptr(&self, name: &str) -> Result<Vec<Naptr>> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            if name.starts_with("9.") {
+                bail!("SERVFAIL");
+            }
+            Ok(self.records.get(name).cloned().unwrap_or_default())
+        }
+    }
+
+    fn naptr(order: u16, flags: &str, services: &str, regexp: &str) -> Naptr {
+        Naptr {
+            order,
+            preference: 10,
+            flags: flags.to_string(),
+            services: services.to_string(),
+            regexp: regexp.to_string(),
+            replacement: ".".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let zone = Arc::new(Zone {
+            records: HashMap::from([(
+                "4.3.2.1.5.5.5.2.1.2.1.e164.arpa".to_string(),
+                vec![
+                    naptr(
+                        10,
+                        "u",
+                        "E2U+email:mailto",
+                        "!^.*$!mailto:info@example.com!",
+                    ),
+                    naptr(20, "u", "E2U+sip", r"!^\+1(.*)$!sip:\1@voip.example.com!"),
+                    naptr(30, "u", "E2U+sip", "!^.*$!sip:fallback@example.com!"),
+                ],
+            )]),
+            queries: AtomicUsize::new(0),
+        });
+        let resolver = EnumResolver::with_source(EnumConfig::default(), zone.clone());
+
+        for _ in 0..2 {
+            assert_eq!(
+                resolver
+                    .resolve("+12125551234;npdi")
+                    .await
+                    .unwrap()
+                    .as_deref(),
+                Some("sip:2125551234@voip.example.com")
+            );
+        }
+        assert_eq!(resolver.resolve("+12125550000").await.unwrap(), None);
+        // Answers are cached, and national numbers are not looked up
+        assert_eq!(resolver.resolve("2125551234").await.unwrap(), None);
+        assert_eq!(zone.queries.load(Ordering::SeqCst), 2);
+
+        // Failures are retried rather than cached
+        for _ in 0..2 {
+            assert!(resolver.resolve("+12125550009").await.is_err());
+        }
+        assert_eq!(zone.queries.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_apply_regexp() {
+        assert_eq!(
+            apply_regexp("!^.*$!sip:info@example.com!", "+4420").unwrap(),
+            "sip:info@example.com"
+        );
+        assert_eq!(
+            apply_regexp(r"/^\+(44)(.*)$/sip:\2@uk.example.com;cc=\1/", "+4420").unwrap(),
+            "sip:20@uk.example.com;cc=44"
+        );
+        assert!(apply_regexp("!^\\+1!sip:x@example.com!", "+4420").is_err());
+        assert!(apply_regexp("!^.*$", "+4420").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Call, registration and alarm events
+//!
+//! The B2BUA publishes a [`CallEvent`] as each call rings, is answered and
+//! hangs up, and when a call to an extension goes unanswered. The
+//! registrar publishes a [`RegistrationEvent`] as contacts register,
+//! unregister and expire, and components that detect trouble (failing
+//! components, expiring certificates, degraded post-dial delay) raise an
+//! [`AlarmEvent`]; these two travel separately as [`SystemEvent`]s, so
+//! subscribers that only care about calls never see them. Every subscriber
+//! (the API's event stream, screen-pop apps) receives the events published
+//! after it subscribed; a subscriber that falls too far behind skips the
+//! oldest ones.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+
+use crate::b2bua::Session;
+use crate::call_trace::uri_user;
+use crate::crm::CrmContact;
+
+/// Events buffered for each subscriber
+const EVENT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CallEventKind {
+    Ringing,
+    Answered,
+    Hangup,
+    /// A call to an extension ended unanswered and was not picked up
+    /// elsewhere
+    Missed,
+}
+
+/// Something that happened to a call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallEvent {
+    pub kind: CallEventKind,
+    pub call_id: String,
+    /// Calling number
+    pub caller: Option<String>,
+    /// Called number
+    pub callee: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    /// Number the caller dialed, on missed call events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub did: Option<String>,
+    /// Caller details from the CRM, on ringing events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crm: Option<CrmContact>,
+}
+
+impl CallEvent {
+    pub fn new(kind: CallEventKind, session: &Session) -> Self {
+        Self {
+            kind,
+            call_id: session.call_id().to_string(),
+            caller: session.caller().and_then(uri_user).map(str::to_string),
+            callee: session.callee().and_then(uri_user).map(str::to_string),
+            timestamp: Utc::now(),
+            did: None,
+            crm: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationEventKind {
+    Registered,
+    Unregistered,
+    /// The binding was not refreshed in time
+    Expired,
+}
+
+/// A contact binding added or removed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistrationEvent {
+    pub kind: RegistrationEventKind,
+    /// Address-of-record
+    pub aor: String,
+    pub contact: String,
+    /// Address the REGISTER came from
+    pub source: Option<SocketAddr>,
+    pub user_agent: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmSeverity {
+    Warning,
+    Critical,
+}
+
+/// A condition an operator should look at
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlarmEvent {
+    pub severity: AlarmSeverity,
+    /// What raised it (`component`, `certificate`, `pdd`)
+    pub source: String,
+    /// Component, certificate or trunk it is about
+    pub subject: String,
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl AlarmEvent {
+    pub fn new(
+        severity: AlarmSeverity,
+        source: &str,
+        subject: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity,
+            source: source.to_string(),
+            subject: subject.into(),
+            message: message.into(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Events that are not about a call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SystemEvent {
+    Registration(RegistrationEvent),
+    Alarm(AlarmEvent),
+}
+
+impl SystemEvent {
+    /// Name the event is streamed under: the registration kind, or `alarm`
+    pub fn name(&self) -> &'static str {
+        match self {
+            SystemEvent::Registration(event) => match event.kind {
+                RegistrationEventKind::Registered => "registered",
+                RegistrationEventKind::Unregistered => "unregistered",
+                RegistrationEventKind::Expired => "expired",
+            },
+            SystemEvent::Alarm(_) => "alarm",
+        }
+    }
+}
+
+/// Fans events out to subscribers
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<CallEvent>,
+    system: broadcast::Sender<SystemEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+        let (system, _) = broadcast::channel(EVENT_CAPACITY);
+        Self { sender, system }
+    }
+
+    pub fn publish(&self, event: CallEvent) {
+        // No subscribers is not an error
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CallEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish_system(&self, event: SystemEvent) {
+        let _ = self.system.send(event);
+    }
+
+    /// Registration and alarm events
+    pub fn subscribe_system(&self) -> broadcast::Receiver<SystemEvent> {
+        self.system.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
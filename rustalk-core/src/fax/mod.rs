@@ -0,0 +1,424 @@
+//! Fax-to-email and email-to-fax
+//!
+//! RusTalk does not terminate T.38 itself; a fax gateway on a T.38 trunk
+//! does. The gateway hands each received fax over as a TIFF-F image, which
+//! is converted to PDF and emailed to the address configured for the DID
+//! it was sent to. Documents submitted through the API are queued as
+//! outbound jobs that the gateway claims, transmits and reports back on.
+
+pub mod pdf;
+pub mod tiff;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use lettre::message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+pub use pdf::fax_to_pdf;
+pub use tiff::{parse_tiff, FaxPage};
+
+/// Fax jobs kept for the fax log
+const FAX_LOG_SIZE: usize = 1000;
+
+/// How the SMTP connection is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    None,
+    #[default]
+    StartTls,
+    /// Implicit TLS, usually port 465
+    Tls,
+}
+
+/// Outgoing mail server
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender address of fax emails
+    pub from: String,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+/// Fax service configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FaxConfig {
+    pub smtp: SmtpConfig,
+    /// Email address that receives faxes sent to each DID
+    #[serde(default)]
+    pub dids: HashMap<String, String>,
+}
+
+/// Delivers received faxes, and other notifications, by email
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str, pdf: Vec<u8>) -> Result<()>;
+    /// Send a plain text email without an attachment
+    async fn send_text(&self, to: &str, subject: &str, body: &str) -> Result<()>;
+}
+
+/// Sends fax emails over SMTP
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &SmtpConfig) -> Result<Self> {
+        let mut builder = match config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        }
+        .port(config.port);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from.parse().context("invalid fax sender address")?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str, pdf: Vec<u8>) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse().context("invalid fax recipient address")?)
+            .subject(subject)
+            .multipart(
+                MultiPart::mixed()
+                    .singlepart(SinglePart::plain(body.to_string()))
+                    .singlepart(
+                        Attachment::new("fax.pdf".to_string())
+                            .body(pdf, ContentType::parse("application/pdf")?),
+                    ),
+            )?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+
+    async fn send_text(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse().context("invalid recipient address")?)
+            .subject(subject)
+            .singlepart(SinglePart::plain(body.to_string()))?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FaxDirection {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FaxStatus {
+    /// Waiting for the gateway to claim it
+    Queued,
+    /// Claimed by the gateway
+    Sending,
+    /// Transmitted, or emailed for inbound faxes
+    Delivered,
+    Failed,
+}
+
+/// A sent or received fax
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaxJob {
+    pub id: String,
+    pub direction: FaxDirection,
+    /// Sending number
+    pub from: String,
+    /// Receiving number
+    pub to: String,
+    pub pages: usize,
+    pub status: FaxStatus,
+    /// Address an inbound fax was emailed to
+    pub email: Option<String>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FaxJob {
+    fn new(direction: FaxDirection, from: &str, to: &str, pages: usize) -> Self {
+        let now = Utc::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            direction,
+            from: from.to_string(),
+            to: to.to_string(),
+            pages,
+            status: FaxStatus::Queued,
+            email: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Outbound fax waiting for the gateway
+struct QueuedFax {
+    id: String,
+    tiff: Vec<u8>,
+}
+
+/// Bridges faxes between the T.38 gateway, email and the API
+#[derive(Clone)]
+pub struct FaxService {
+    config: Arc<FaxConfig>,
+    mailer: Arc<dyn Mailer>,
+    jobs: Arc<Mutex<VecDeque<FaxJob>>>,
+    /// Documents of queued outbound jobs, in submission order
+    outbound: Arc<Mutex<VecDeque<QueuedFax>>>,
+}
+
+impl FaxService {
+    /// Service that emails through the configured SMTP server
+    pub fn new(config: FaxConfig) -> Result<Self> {
+        let mailer = SmtpMailer::new(&config.smtp)?;
+        Ok(Self::with_mailer(config, Arc::new(mailer)))
+    }
+
+    pub fn with_mailer(config: FaxConfig, mailer: Arc<dyn Mailer>) -> Self {
+        Self {
+            config: Arc::new(config),
+            mailer,
+            jobs: Arc::new(Mutex::new(VecDeque::new())),
+            outbound: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Address that receives faxes sent to `did`
+    pub fn email_for_did(&self, did: &str) -> Option<&str> {
+        let digits = |s: &str| s.chars().filter(char::is_ascii_digit).collect::<String>();
+        let wanted = digits(did);
+        self.config
+            .dids
+            .iter()
+            .find(|(number, _)| digits(number) == wanted)
+            .map(|(_, email)| email.as_str())
+    }
+
+    /// Convert a fax received from the gateway to PDF and email it
+    pub async fn receive(&self, from: &str, to: &str, tiff: &[u8]) -> Result<FaxJob> {
+        let email = self
+            .email_for_did(to)
+            .ok_or_else(|| anyhow!("No fax mailbox for DID {}", to))?
+            .to_string();
+        let pages = parse_tiff(tiff)?;
+        let mut job = FaxJob::new(FaxDirection::Inbound, from, to, pages.len());
+        job.email = Some(email.clone());
+
+        let subject = format!("Fax from {} ({} pages)", from, pages.len());
+        let body = format!(
+            "A {}-page fax from {} to {} was received at {}.\n",
+            pages.len(),
+            from,
+            to,
+            job.created_at.to_rfc2822()
+        );
+        match self
+            .mailer
+            .send(&email, &subject, &body, fax_to_pdf(&pages))
+            .await
+        {
+            Ok(()) => job.status = FaxStatus::Delivered,
+            Err(e) => {
+                warn!("Failed to email fax {} to {}: {:#}", job.id, email, e);
+                job.status = FaxStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+        self.log(job.clone());
+        Ok(job)
+    }
+
+    /// Queue a TIFF-F document for the gateway to send
+    pub fn submit(&self, from: &str, to: &str, tiff: Vec<u8>) -> Result<FaxJob> {
+        if self.email_for_did(from).is_none() {
+            bail!("{} is not a fax DID", from);
+        }
+        if !to.chars().any(|c| c.is_ascii_digit()) {
+            bail!("Invalid fax number {}", to);
+        }
+        let pages = parse_tiff(&tiff)?;
+        let job = FaxJob::new(FaxDirection::Outbound, from, to, pages.len());
+        self.outbound.lock().unwrap().push_back(QueuedFax {
+            id: job.id.clone(),
+            tiff,
+        });
+        self.log(job.clone());
+        Ok(job)
+    }
+
+    /// Oldest queued outbound fax and its document, marked as sending
+    pub fn claim_outbound(&self) -> Option<(FaxJob, Vec<u8>)> {
+        let queued = self.outbound.lock().unwrap().pop_front()?;
+        let job = self.update(&queued.id, |job| job.status = FaxStatus::Sending)?;
+        Some((job, queued.tiff))
+    }
+
+    /// Record the gateway's result for an outbound fax
+    pub fn complete(&self, id: &str, error: Option<String>) -> Result<FaxJob> {
+        self.update(id, |job| {
+            job.status = if error.is_some() {
+                FaxStatus::Failed
+            } else {
+                FaxStatus::Delivered
+            };
+            job.error = error;
+        })
+        .ok_or_else(|| anyhow!("Unknown fax job {}", id))
+    }
+
+    /// Recent faxes, newest first
+    pub fn jobs(&self) -> Vec<FaxJob> {
+        self.jobs.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut FaxJob)) -> Option<FaxJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.iter_mut().find(|job| job.id == id)?;
+        change(job);
+        job.updated_at = Utc::now();
+        Some(job.clone())
+    }
+
+    fn log(&self, job: FaxJob) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.len() == FAX_LOG_SIZE {
+            jobs.pop_front();
+        }
+        jobs.push_back(job);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tiff::tests::fax_tiff;
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingMailer {
+        sent: Mutex<Vec<(String, String, Vec<u8>)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Mailer for RecordingMailer {
+        async fn send(&self, to: &str, subject: &str, _body: &str, pdf: Vec<u8>) -> Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((to.to_string(), subject.to_string(), pdf));
+            Ok(())
+        }
+
+        async fn send_text(&self, to: &str, subject: &str, _body: &str) -> Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((to.to_string(), subject.to_string(), Vec::new()));
+            Ok(())
+        }
+    }
+
+    fn service(mailer: Arc<RecordingMailer>) -> FaxService {
+        let config: FaxConfig = serde_json::from_value(serde_json::json!({
+            "smtp": { "host": "smtp.example.com", "from": "fax@example.com" },
+            "dids": { "+12125551900": "accounts@example.com" }
+        }))
+        .unwrap();
+        FaxService::with_mailer(config, mailer)
+    }
+
+    #[tokio::test]
+    async fn test_fax_to_email() {
+        let mailer = Arc::new(RecordingMailer::default());
+        let fax = service(mailer.clone());
+        let tiff = fax_tiff(&[&[0x00; 4], &[0x00; 4]], 1);
+
+        let job = fax
+            .receive("+13105550123", "12125551900", &tiff)
+            .await
+            .unwrap();
+        assert_eq!(job.status, FaxStatus::Delivered);
+        assert_eq!(job.pages, 2);
+        assert_eq!(job.email.as_deref(), Some("accounts@example.com"));
+
+        {
+            let sent = mailer.sent.lock().unwrap();
+            assert_eq!(sent[0].0, "accounts@example.com");
+            assert_eq!(sent[0].1, "Fax from +13105550123 (2 pages)");
+            assert!(sent[0].2.starts_with(b"%PDF-"));
+        }
+
+        assert!(fax
+            .receive("+13105550123", "+12125550000", &tiff)
+            .await
+            .is_err());
+        assert_eq!(fax.jobs().len(), 1);
+    }
+
+    #[test]
+    fn test_outbound_queue() {
+        let fax = service(Arc::new(RecordingMailer::default()));
+        let tiff = fax_tiff(&[&[0x00; 4]], 1);
+
+        assert!(fax.submit("1001", "+13105550123", tiff.clone()).is_err());
+        assert!(fax
+            .submit("+12125551900", "+13105550123", b"hello".to_vec())
+            .is_err());
+
+        let first = fax
+            .submit("+12125551900", "+13105550123", tiff.clone())
+            .unwrap();
+        let second = fax
+            .submit("+12125551900", "+13105550124", tiff.clone())
+            .unwrap();
+        assert_eq!(first.status, FaxStatus::Queued);
+
+        let (claimed, document) = fax.claim_outbound().unwrap();
+        assert_eq!(claimed.id, first.id);
+        assert_eq!(claimed.status, FaxStatus::Sending);
+        assert_eq!(document, tiff);
+
+        let done = fax.complete(&first.id, None).unwrap();
+        assert_eq!(done.status, FaxStatus::Delivered);
+
+        assert_eq!(fax.claim_outbound().unwrap().0.id, second.id);
+        assert!(fax.claim_outbound().is_none());
+        let failed = fax.complete(&second.id, Some("busy".to_string())).unwrap();
+        assert_eq!(failed.status, FaxStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("busy"));
+        assert!(fax.complete("missing", None).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! PDF output for fax pages
+//!
+//! Each TIFF strip becomes a CCITTFaxDecode image, drawn at its place on a
+//! page sized from the fax resolution, so no decoding is needed.
+
+use super::tiff::{FaxCoding, FaxPage};
+
+/// Single PDF holding `pages`
+pub fn fax_to_pdf(pages: &[FaxPage]) -> Vec<u8> {
+    let mut pdf = Pdf::default();
+    let catalog = pdf.reserve();
+    let tree = pdf.reserve();
+
+    let mut kids = Vec::new();
+    for page in pages {
+        let width = page.width as f64 * 72.0 / page.dpi.0;
+        let height = page.height as f64 * 72.0 / page.dpi.1;
+
+        let mut content = String::new();
+        let mut images = String::new();
+        let mut top = 0.0;
+        for (i, strip) in page.strips.iter().enumerate() {
+            let strip_height = strip.rows as f64 * 72.0 / page.dpi.1;
+            top += strip_height;
+            let image = pdf.add_stream(&image_dict(page, strip.rows), &strip.data);
+            images.push_str(&format!("/Im{} {} 0 R ", i, image));
+            content.push_str(&format!(
+                "q {:.2} 0 0 {:.2} 0 {:.2} cm /Im{} Do Q\n",
+                width,
+                strip_height,
+                height - top,
+                i
+            ));
+        }
+
+        let contents = pdf.add_stream("", content.as_bytes());
+        kids.push(pdf.add(&format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {:.2} {:.2}] \
+             /Resources << /XObject << {}>> >> /Contents {} 0 R >>",
+            tree, width, height, images, contents
+        )));
+    }
+
+    let kids: Vec<String> = kids.iter().map(|id| format!("{} 0 R", id)).collect();
+    pdf.set(
+        tree,
+        &format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+    );
+    pdf.set(
+        catalog,
+        &format!("<< /Type /Catalog /Pages {} 0 R >>", tree),
+    );
+    pdf.finish(catalog)
+}
+
+fn image_dict(page: &FaxPage, rows: u32) -> String {
+    let (k, byte_aligned) = match page.coding {
+        FaxCoding::Group3 {
+            two_d,
+            byte_aligned,
+        } => (if two_d { 1 } else { 0 }, byte_aligned),
+        FaxCoding::Group4 => (-1, false),
+    };
+    format!(
+        "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray \
+         /BitsPerComponent 1 /Filter /CCITTFaxDecode \
+         /DecodeParms << /K {} /Columns {} /Rows {} /EncodedByteAlign {} >>{}",
+        page.width,
+        rows,
+        k,
+        page.width,
+        rows,
+        byte_aligned,
+        if page.black_is_one {
+            " /Decode [1 0]"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Minimal PDF object writer
+#[derive(Default)]
+struct Pdf {
+    objects: Vec<Vec<u8>>,
+}
+
+impl Pdf {
+    /// Object number for an object written later with `set`
+    fn reserve(&mut self) -> usize {
+        self.objects.push(Vec::new());
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, body: &str) {
+        self.objects[id - 1] = body.as_bytes().to_vec();
+    }
+
+    fn add(&mut self, body: &str) -> usize {
+        let id = self.reserve();
+        self.set(id, body);
+        id
+    }
+
+    /// Stream object; `dict` holds the dictionary entries besides `/Length`
+    fn add_stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        let id = self.reserve();
+        let mut body = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\nendstream");
+        self.objects[id - 1] = body;
+        id
+    }
+
+    fn finish(self, root: usize) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, body) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+
+        let xref = out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n", offsets.len() + 1).as_bytes());
+        out.extend_from_slice(b"0000000000 65535 f \n");
+        for offset in offsets {
+            out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        out.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+                self.objects.len() + 1,
+                root,
+                xref
+            )
+            .as_bytes(),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tiff::{parse_tiff, tests::fax_tiff};
+    use super::*;
+
+    #[test]
+    fn test_fax_to_pdf() {
+        let pages = parse_tiff(&fax_tiff(&[&[0xaa; 16], &[0x55; 8]], 1)).unwrap();
+        let pdf = fax_to_pdf(&pages);
+        let text = String::from_utf8_lossy(&pdf);
+
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Type /Pages /Kids [5 0 R 8 0 R] /Count 2"));
+        assert!(text.contains("/K 0 /Columns 1728 /Rows 1100 /EncodedByteAlign true"));
+        // 1728 dots at 204 dpi by 1100 rows at 98 dpi
+        assert!(text.contains("/MediaBox [0 0 609.88 808.16]"));
+
+        // startxref points at the cross-reference table
+        let start: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|tail| tail.lines().next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[start..].starts_with(b"xref\n0 9\n"));
+    }
+}
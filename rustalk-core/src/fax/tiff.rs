@@ -0,0 +1,319 @@
+//! TIFF-F fax images
+//!
+//! T.38 gateways hand received faxes over as TIFF Class F: one IFD per
+//! page, each holding Group 3 or Group 4 coded strips. The coded data is
+//! kept as-is; PDF decodes CCITT images natively, so conversion is a
+//! matter of re-wrapping the strips.
+
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC: u16 = 262;
+const TAG_FILL_ORDER: u16 = 266;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_X_RESOLUTION: u16 = 282;
+const TAG_Y_RESOLUTION: u16 = 283;
+const TAG_T4_OPTIONS: u16 = 292;
+const TAG_RESOLUTION_UNIT: u16 = 296;
+
+/// Upper bound on pages, against looping IFD chains
+const MAX_PAGES: usize = 1000;
+
+/// How a page's strips are coded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaxCoding {
+    /// T.4 (Group 3); `two_d` when rows may be 2D coded, `byte_aligned`
+    /// when each row starts on a byte boundary
+    Group3 { two_d: bool, byte_aligned: bool },
+    /// T.6 (Group 4)
+    Group4,
+}
+
+/// One coded strip of rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaxStrip {
+    pub rows: u32,
+    /// Coded data, most significant bit first
+    pub data: Vec<u8>,
+}
+
+/// One fax page
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaxPage {
+    pub width: u32,
+    pub height: u32,
+    pub coding: FaxCoding,
+    /// Set bits are black rather than white
+    pub black_is_one: bool,
+    /// Horizontal and vertical dots per inch
+    pub dpi: (f64, f64),
+    pub strips: Vec<FaxStrip>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| anyhow!("TIFF is truncated at offset {}", offset))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let b: [u8; 2] = self.bytes(offset, 2)?.try_into()?;
+        Ok(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let b: [u8; 4] = self.bytes(offset, 4)?.try_into()?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+}
+
+/// One IFD entry
+struct Entry {
+    field_type: u16,
+    count: u32,
+    /// Offset of the value, or of the value field when it fits inline
+    offset: usize,
+}
+
+impl Entry {
+    fn values(&self, reader: &Reader) -> Result<Vec<u32>> {
+        let size = match self.field_type {
+            3 => 2,
+            4 => 4,
+            t => bail!("unsupported TIFF field type {}", t),
+        };
+        let count = self.count as usize;
+        let start = if size * count > 4 {
+            reader.u32(self.offset)? as usize
+        } else {
+            self.offset
+        };
+        (0..count)
+            .map(|i| match size {
+                2 => reader.u16(start + i * 2).map(u32::from),
+                _ => reader.u32(start + i * 4),
+            })
+            .collect()
+    }
+
+    fn value(&self, reader: &Reader) -> Result<u32> {
+        self.values(reader)?
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("empty TIFF field"))
+    }
+
+    fn rational(&self, reader: &Reader) -> Result<f64> {
+        if self.field_type != 5 {
+            bail!("expected a rational TIFF field");
+        }
+        let at = reader.u32(self.offset)? as usize;
+        let (num, den) = (reader.u32(at)?, reader.u32(at + 4)?);
+        if den == 0 {
+            bail!("zero denominator in TIFF resolution");
+        }
+        Ok(num as f64 / den as f64)
+    }
+}
+
+/// Pages of a TIFF-F fax
+pub fn parse_tiff(data: &[u8]) -> Result<Vec<FaxPage>> {
+    let little_endian = match data.get(..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => bail!("not a TIFF image"),
+    };
+    let reader = Reader {
+        data,
+        little_endian,
+    };
+
+    let mut pages = Vec::new();
+    let mut ifd = reader.u32(4)? as usize;
+    while ifd != 0 {
+        if pages.len() == MAX_PAGES {
+            bail!("TIFF has more than {} pages", MAX_PAGES);
+        }
+        let count = reader.u16(ifd)? as usize;
+        let mut entries = HashMap::new();
+        for i in 0..count {
+            let at = ifd + 2 + i * 12;
+            entries.insert(
+                reader.u16(at)?,
+                Entry {
+                    field_type: reader.u16(at + 2)?,
+                    count: reader.u32(at + 4)?,
+                    offset: at + 8,
+                },
+            );
+        }
+        pages.push(parse_page(&reader, &entries)?);
+        ifd = reader.u32(ifd + 2 + count * 12)? as usize;
+    }
+    if pages.is_empty() {
+        bail!("TIFF has no pages");
+    }
+    Ok(pages)
+}
+
+fn parse_page(reader: &Reader, entries: &HashMap<u16, Entry>) -> Result<FaxPage> {
+    let field = |tag: u16| {
+        entries
+            .get(&tag)
+            .ok_or_else(|| anyhow!("TIFF page is missing tag {}", tag))
+    };
+    let optional = |tag: u16, default: u32| -> Result<u32> {
+        entries
+            .get(&tag)
+            .map_or(Ok(default), |entry| entry.value(reader))
+    };
+
+    let width = field(TAG_IMAGE_WIDTH)?.value(reader)?;
+    let height = field(TAG_IMAGE_LENGTH)?.value(reader)?;
+    let t4_options = optional(TAG_T4_OPTIONS, 0)?;
+    let coding = match optional(TAG_COMPRESSION, 1)? {
+        3 => FaxCoding::Group3 {
+            two_d: t4_options & 1 != 0,
+            byte_aligned: t4_options & 4 != 0,
+        },
+        4 => FaxCoding::Group4,
+        other => bail!("TIFF compression {} is not a fax coding", other),
+    };
+    let black_is_one = optional(TAG_PHOTOMETRIC, 0)? == 1;
+    let reversed = optional(TAG_FILL_ORDER, 1)? == 2;
+
+    let per_cm = optional(TAG_RESOLUTION_UNIT, 2)? == 3;
+    let dpi = |tag: u16, default: f64| -> Result<f64> {
+        let value = match entries.get(&tag) {
+            Some(entry) => entry.rational(reader)?,
+            None => return Ok(default),
+        };
+        Ok(if per_cm { value * 2.54 } else { value })
+    };
+    let dpi = (dpi(TAG_X_RESOLUTION, 204.0)?, dpi(TAG_Y_RESOLUTION, 196.0)?);
+
+    let offsets = field(TAG_STRIP_OFFSETS)?.values(reader)?;
+    let counts = field(TAG_STRIP_BYTE_COUNTS)?.values(reader)?;
+    if offsets.len() != counts.len() {
+        bail!("TIFF strip offsets and byte counts differ in length");
+    }
+    let rows_per_strip = optional(TAG_ROWS_PER_STRIP, height)?.clamp(1, height.max(1));
+    let mut strips = Vec::with_capacity(offsets.len());
+    for (i, (offset, count)) in offsets.iter().zip(&counts).enumerate() {
+        let first_row = rows_per_strip.saturating_mul(i as u32);
+        let rows = rows_per_strip.min(height.saturating_sub(first_row));
+        if rows == 0 {
+            break;
+        }
+        let mut data = reader.bytes(*offset as usize, *count as usize)?.to_vec();
+        if reversed {
+            data.iter_mut().for_each(|b| *b = b.reverse_bits());
+        }
+        strips.push(FaxStrip { rows, data });
+    }
+
+    Ok(FaxPage {
+        width,
+        height,
+        coding,
+        black_is_one,
+        dpi,
+        strips,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Little-endian TIFF-F with one single-strip Group 3 page per entry of
+    /// `pages`, each holding that page's coded bytes
+    pub(crate) fn fax_tiff(pages: &[&[u8]], fill_order: u32) -> Vec<u8> {
+        let mut out = b"II*\0\0\0\0\0".to_vec();
+        let mut next_pointer = 4;
+        for data in pages {
+            let strip = out.len() as u32;
+            out.extend_from_slice(data);
+            let resolution = out.len() as u32;
+            for (num, den) in [(204u32, 1u32), (98, 1)] {
+                out.extend_from_slice(&num.to_le_bytes());
+                out.extend_from_slice(&den.to_le_bytes());
+            }
+            let ifd = out.len() as u32;
+            out[next_pointer..next_pointer + 4].copy_from_slice(&ifd.to_le_bytes());
+
+            let entries: [(u16, u16, u32); 9] = [
+                (TAG_IMAGE_WIDTH, 4, 1728),
+                (TAG_IMAGE_LENGTH, 4, 1100),
+                (TAG_COMPRESSION, 3, 3),
+                (TAG_FILL_ORDER, 3, fill_order),
+                (TAG_STRIP_OFFSETS, 4, strip),
+                (TAG_STRIP_BYTE_COUNTS, 4, data.len() as u32),
+                (TAG_X_RESOLUTION, 5, resolution),
+                (TAG_Y_RESOLUTION, 5, resolution + 8),
+                (TAG_T4_OPTIONS, 4, 4),
+            ];
+            out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            for (tag, field_type, value) in entries {
+                out.extend_from_slice(&tag.to_le_bytes());
+                out.extend_from_slice(&field_type.to_le_bytes());
+                out.extend_from_slice(&1u32.to_le_bytes());
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            next_pointer = out.len();
+            out.extend_from_slice(&0u32.to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn test_parse_fax_tiff() {
+        let tiff = fax_tiff(&[&[0x00, 0x01, 0x80], &[0x0f]], 2);
+        let pages = parse_tiff(&tiff).unwrap();
+        assert_eq!(pages.len(), 2);
+
+        let page = &pages[0];
+        assert_eq!((page.width, page.height), (1728, 1100));
+        assert_eq!(page.dpi, (204.0, 98.0));
+        assert_eq!(
+            page.coding,
+            FaxCoding::Group3 {
+                two_d: false,
+                byte_aligned: true
+            }
+        );
+        assert!(!page.black_is_one);
+        // Fill order 2 is bit-reversed into PDF's order
+        assert_eq!(
+            page.strips,
+            vec![FaxStrip {
+                rows: 1100,
+                data: vec![0x00, 0x80, 0x01]
+            }]
+        );
+        assert_eq!(pages[1].strips[0].data, vec![0xf0]);
+
+        assert!(parse_tiff(b"%PDF-1.4").is_err());
+        assert!(parse_tiff(&tiff[..tiff.len() - 20]).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Extension groups
+//!
+//! Groups collect extensions by department or site. Routes can match on the
+//! caller's group, and a group can let its members pick up each other's
+//! calls or be paged together.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// What a group represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupKind {
+    #[default]
+    Department,
+    Site,
+}
+
+/// A named set of extensions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionGroup {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub kind: GroupKind,
+    pub description: Option<String>,
+    /// Member extension numbers
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// Members may pick up calls ringing another member
+    #[serde(default)]
+    pub pickup: bool,
+    /// The group can be paged as a whole
+    #[serde(default)]
+    pub paging: bool,
+}
+
+impl ExtensionGroup {
+    pub fn has_member(&self, extension: &str) -> bool {
+        self.members.iter().any(|m| m == extension)
+    }
+}
+
+/// Lookup of group membership
+#[derive(Debug, Clone, Default)]
+pub struct GroupDirectory {
+    groups: Vec<ExtensionGroup>,
+}
+
+impl GroupDirectory {
+    pub fn new(groups: Vec<ExtensionGroup>) -> Self {
+        Self { groups }
+    }
+
+    pub fn groups(&self) -> &[ExtensionGroup] {
+        &self.groups
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ExtensionGroup> {
+        self.groups.iter().find(|g| g.id == id)
+    }
+
+    /// Whether `extension` belongs to the group with id `group`
+    pub fn is_member(&self, group: &str, extension: &str) -> bool {
+        self.get(group).is_some_and(|g| g.has_member(extension))
+    }
+
+    /// Ids of the groups `extension` belongs to
+    pub fn groups_of(&self, extension: &str) -> Vec<&str> {
+        self.groups
+            .iter()
+            .filter(|g| g.has_member(extension))
+            .map(|g| g.id.as_str())
+            .collect()
+    }
+
+    /// Extensions whose ringing calls `extension` may pick up
+    pub fn pickup_peers(&self, extension: &str) -> BTreeSet<String> {
+        self.groups
+            .iter()
+            .filter(|g| g.pickup && g.has_member(extension))
+            .flat_map(|g| &g.members)
+            .filter(|m| *m != extension)
+            .cloned()
+            .collect()
+    }
+
+    /// Members to page for `group`, or `None` if it does not allow paging
+    pub fn paging_members(&self, group: &str) -> Option<&[String]> {
+        self.get(group)
+            .filter(|g| g.paging)
+            .map(|g| g.members.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(id: &str, members: &[&str], pickup: bool, paging: bool) -> ExtensionGroup {
+        ExtensionGroup {
+            id: id.to_string(),
+            name: id.to_string(),
+            kind: GroupKind::Department,
+            description: None,
+            members: members.iter().map(|m| m.to_string()).collect(),
+            pickup,
+            paging,
+        }
+    }
+
+    #[test]
+    fn test_membership_pickup_and_paging() {
+        let directory = GroupDirectory::new(vec![
+            group("sales", &["1001", "1002"], true, true),
+            group("support", &["1002", "1003"], false, false),
+            group("hq", &["1001", "1003"], true, false),
+        ]);
+
+        assert!(directory.is_member("sales", "1001"));
+        assert!(!directory.is_member("sales", "1003"));
+        assert!(!directory.is_member("missing", "1001"));
+        assert_eq!(directory.groups_of("1002"), vec!["sales", "support"]);
+
+        let peers: Vec<String> = directory.pickup_peers("1001").into_iter().collect();
+        assert_eq!(peers, vec!["1002", "1003"]);
+        assert!(directory.pickup_peers("1004").is_empty());
+
+        assert_eq!(
+            directory.paging_members("sales"),
+            Some(&["1001".to_string(), "1002".to_string()][..])
+        );
+        assert_eq!(directory.paging_members("hq"), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Async name resolution
+//!
+//! Without the `hickory-dns` feature this is `tokio::net::lookup_host`,
+//! which calls the libc resolver on the blocking pool. With it, names are
+//! resolved by hickory using `/etc/resolv.conf`, falling back to public
+//! resolvers when the system configuration cannot be read.
+//!
+//! NAPTR records, which ENUM lookups need, can only be queried with the
+//! `hickory-dns` feature, as the libc resolver has no async record lookup.
+
+use anyhow::{anyhow, Context, Result};
+use std::net::{IpAddr, SocketAddr};
+
+/// Resolve `host` to socket addresses on `port`
+pub async fn lookup_host(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    let addresses = resolve(host, port)
+        .await
+        .with_context(|| format!("Failed to resolve {}", host))?;
+    if addresses.is_empty() {
+        return Err(anyhow!("{} did not resolve to any address", host));
+    }
+    Ok(addresses)
+}
+
+/// Resolve a `host:port` target
+pub async fn lookup(target: &str) -> Result<Vec<SocketAddr>> {
+    let (host, port) = target
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("{} is not host:port", target))?;
+    let port = port
+        .parse()
+        .with_context(|| format!("Invalid port in {}", target))?;
+    lookup_host(host, port).await
+}
+
+/// A NAPTR record (RFC 3403)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Naptr {
+    pub order: u16,
+    pub preference: u16,
+    pub flags: String,
+    pub services: String,
+    pub regexp: String,
+    pub replacement: String,
+}
+
+/// NAPTR records of `name`, in order then preference
+pub async fn lookup_naptr(name: &str) -> Result<Vec<Naptr>> {
+    let mut records = resolve_naptr(name)
+        .await
+        .with_context(|| format!("Failed to look up NAPTR records of {}", name))?;
+    records.sort_by_key(|r| (r.order, r.preference));
+    Ok(records)
+}
+
+#[cfg(not(feature = "hickory-dns"))]
+async fn resolve_naptr(_name: &str) -> Result<Vec<Naptr>> {
+    Err(anyhow!("NAPTR lookups need the hickory-dns feature"))
+}
+
+#[cfg(not(feature = "hickory-dns"))]
+async fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    Ok(tokio::net::lookup_host((host, port)).await?.collect())
+}
+
+#[cfg(feature = "hickory-dns")]
+fn resolver() -> &'static hickory_resolver::TokioAsyncResolver {
+    use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+    use hickory_resolver::TokioAsyncResolver;
+    use std::sync::OnceLock;
+
+    static RESOLVER: OnceLock<TokioAsyncResolver> = OnceLock::new();
+    RESOLVER.get_or_init(|| {
+        TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            tracing::warn!("Cannot read the system DNS configuration: {}", e);
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        })
+    })
+}
+
+#[cfg(feature = "hickory-dns")]
+async fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let lookup = resolver().lookup_ip(host).await?;
+    Ok(lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+}
+
+#[cfg(feature = "hickory-dns")]
+async fn resolve_naptr(name: &str) -> Result<Vec<Naptr>> {
+    use hickory_resolver::error::ResolveErrorKind;
+    use hickory_resolver::proto::rr::{RData, RecordType};
+
+    let lookup = match resolver().lookup(name, RecordType::NAPTR).await {
+        Ok(lookup) => lookup,
+        // No records is an answer, not a failure
+        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+            return Ok(Vec::new())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    Ok(lookup
+        .iter()
+        .filter_map(|rdata| match rdata {
+            RData::NAPTR(naptr) => Some(Naptr {
+                order: naptr.order(),
+                preference: naptr.preference(),
+                flags: text(naptr.flags()),
+                services: text(naptr.services()),
+                regexp: text(naptr.regexp()),
+                replacement: naptr.replacement().to_string(),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_literal_addresses_skip_resolver() {
+        assert_eq!(
+            lookup("192.0.2.10:5060").await.unwrap(),
+            vec!["192.0.2.10:5060".parse().unwrap()]
+        );
+        assert_eq!(
+            lookup("[2001:db8::1]:5061").await.unwrap(),
+            vec!["[2001:db8::1]:5061".parse().unwrap()]
+        );
+        assert!(lookup("sbc.example.com").await.is_err());
+        assert!(lookup("sbc.example.com:sip").await.is_err());
+    }
+}
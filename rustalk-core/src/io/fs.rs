@@ -0,0 +1,124 @@
+//! Async file operations with the path in every error
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+pub async fn read(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+pub async fn read_to_string(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+pub async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    let path = path.as_ref();
+    tokio::fs::write(path, contents)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write a file only its owner can read, such as a private key
+pub async fn write_private(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    let path = path.as_ref();
+    write(path, contents).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .with_context(|| format!("Failed to restrict {}", path.display()))?;
+    }
+    Ok(())
+}
+
+pub async fn create_dir_all(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    tokio::fs::create_dir_all(path)
+        .await
+        .with_context(|| format!("Failed to create {}", path.display()))
+}
+
+/// Remove a file, succeeding if it is already gone
+pub async fn remove_file(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Paths of a directory's entries, none if the directory does not exist
+pub async fn read_dir(path: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let mut entries = match tokio::fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to list {}", path.display())),
+    };
+    let mut paths = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|| format!("Failed to list {}", path.display()))?
+    {
+        paths.push(entry.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Remove a directory tree, succeeding if it is already gone
+pub async fn remove_dir_all(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match tokio::fs::remove_dir_all(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let dir = std::env::temp_dir().join("rustalk-io-fs-test");
+        let path = dir.join("nested").join("key.pem");
+        create_dir_all(path.parent().unwrap()).await.unwrap();
+        write_private(&path, "secret").await.unwrap();
+        assert_eq!(read_to_string(&path).await.unwrap(), "secret");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = tokio::fs::metadata(&path)
+                .await
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert_eq!(
+            read_dir(path.parent().unwrap()).await.unwrap(),
+            std::slice::from_ref(&path)
+        );
+        remove_file(&path).await.unwrap();
+        remove_file(&path).await.unwrap();
+        let error = read(&path).await.unwrap_err();
+        assert!(error.to_string().contains("key.pem"));
+        remove_dir_all(&dir).await.unwrap();
+        remove_dir_all(&dir).await.unwrap();
+        assert!(read_dir(&dir).await.unwrap().is_empty());
+    }
+}
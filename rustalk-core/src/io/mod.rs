@@ -0,0 +1,12 @@
+//! Async file and DNS IO
+//!
+//! Everything RusTalk reads, writes or resolves while calls are up goes
+//! through here, so no request handler blocks an executor thread on a slow
+//! disk or a slow resolver. Files use `tokio::fs`, which runs the syscalls
+//! on the blocking pool. Names resolve through the system resolver on the
+//! blocking pool by default; the `hickory-dns` feature swaps in a pure-Rust
+//! async resolver, for musl and embedded ARM64 builds whose libc resolver
+//! is limited or slow.
+
+pub mod dns;
+pub mod fs;
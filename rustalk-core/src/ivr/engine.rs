@@ -0,0 +1,371 @@
+//! Runs calls through IVR flows
+//!
+//! An [`IvrSession`] is driven by what the caller does: it is started, then
+//! told each key pressed and each time the wait for a key runs out, and
+//! returns the actions for the call to take next, in order. It does no IO
+//! itself, so the same session drives a call's media or a simulation.
+
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+use super::{IvrFlow, IvrNodeKind};
+
+/// Nodes a call can pass through between two inputs before it is assumed
+/// to be looping
+const MAX_STEPS: usize = 64;
+
+/// Something for the call to do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", content = "value", rename_all = "snake_case")]
+pub enum IvrAction {
+    /// Play a prompt
+    Play(String),
+    /// Wait this many milliseconds for a key, then report a timeout
+    AwaitInput(u64),
+    /// Transfer the call
+    Transfer(String),
+    /// Send the call to a voicemail box
+    Voicemail(String),
+    /// End the call
+    Hangup,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    Menu { node: String, attempts: u32 },
+    Collect { node: String, digits: String },
+    Finished,
+}
+
+/// One call's progress through a flow
+#[derive(Debug, Clone)]
+pub struct IvrSession {
+    flow: Arc<IvrFlow>,
+    state: State,
+    variables: HashMap<String, String>,
+}
+
+impl IvrSession {
+    pub fn new(flow: Arc<IvrFlow>) -> Self {
+        Self {
+            flow,
+            state: State::Idle,
+            variables: HashMap::new(),
+        }
+    }
+
+    /// Enter the flow at its start node
+    pub fn start(&mut self) -> Vec<IvrAction> {
+        let mut actions = Vec::new();
+        if self.state == State::Idle {
+            let start = self.flow.start.clone();
+            self.enter(Some(start), &mut actions);
+        }
+        actions
+    }
+
+    /// The caller pressed `key`
+    pub fn key(&mut self, key: char) -> Vec<IvrAction> {
+        let mut actions = Vec::new();
+        match std::mem::replace(&mut self.state, State::Finished) {
+            State::Menu { node, attempts } => {
+                let IvrNodeKind::Menu(menu) = &self.kind(&node) else {
+                    unreachable!("menu state is only entered on menu nodes");
+                };
+                match menu.options.get(key.to_string().as_str()) {
+                    Some(target) => {
+                        let target = target.clone();
+                        self.enter(Some(target), &mut actions);
+                    }
+                    None => self.retry(node, attempts, true, &mut actions),
+                }
+            }
+            State::Collect { node, mut digits } => {
+                let IvrNodeKind::Collect(collect) = &self.kind(&node) else {
+                    unreachable!("collect state is only entered on collect nodes");
+                };
+                if key != collect.terminator {
+                    digits.push(key);
+                }
+                if key == collect.terminator || digits.len() >= collect.max_digits {
+                    self.collected(&node, digits, &mut actions);
+                } else {
+                    actions.push(IvrAction::AwaitInput(collect.timeout_ms));
+                    self.state = State::Collect { node, digits };
+                }
+            }
+            state => self.state = state,
+        }
+        actions
+    }
+
+    /// The wait for a key ran out
+    pub fn timeout(&mut self) -> Vec<IvrAction> {
+        let mut actions = Vec::new();
+        match std::mem::replace(&mut self.state, State::Finished) {
+            State::Menu { node, attempts } => self.retry(node, attempts, false, &mut actions),
+            State::Collect { node, digits } => self.collected(&node, digits, &mut actions),
+            state => self.state = state,
+        }
+        actions
+    }
+
+    /// Set a variable before the call enters the flow, such as the
+    /// answering machine detection result
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        self.variables.insert(name.to_string(), value.to_string());
+    }
+
+    /// Digits collected so far, by variable
+    pub fn variables(&self) -> &HashMap<String, String> {
+        &self.variables
+    }
+
+    /// Whether the call has left the flow
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    fn kind(&self, id: &str) -> IvrNodeKind {
+        self.flow
+            .node(id)
+            .map(|node| node.kind.clone())
+            .expect("sessions only wait on nodes of their flow")
+    }
+
+    /// Repeat a menu after a wrong key or none, or leave it once retries
+    /// run out
+    fn retry(&mut self, node: String, attempts: u32, invalid: bool, actions: &mut Vec<IvrAction>) {
+        let IvrNodeKind::Menu(menu) = self.kind(&node) else {
+            unreachable!("menu state is only entered on menu nodes");
+        };
+        if attempts >= menu.retries {
+            self.enter(menu.on_failure.clone(), actions);
+            return;
+        }
+        if let (true, Some(prompt)) = (invalid, &menu.invalid_prompt) {
+            actions.push(IvrAction::Play(prompt.clone()));
+        }
+        actions.push(IvrAction::Play(menu.prompt.clone()));
+        actions.push(IvrAction::AwaitInput(menu.timeout_ms));
+        self.state = State::Menu {
+            node,
+            attempts: attempts + 1,
+        };
+    }
+
+    fn collected(&mut self, node: &str, digits: String, actions: &mut Vec<IvrAction>) {
+        let IvrNodeKind::Collect(collect) = self.kind(node) else {
+            unreachable!("collect state is only entered on collect nodes");
+        };
+        self.variables.insert(collect.variable.clone(), digits);
+        self.enter(collect.next.clone(), actions);
+    }
+
+    /// Move to `next`, running nodes that need no input until one waits for
+    /// the caller or the call leaves the flow
+    fn enter(&mut self, mut next: Option<String>, actions: &mut Vec<IvrAction>) {
+        for _ in 0..MAX_STEPS {
+            let Some(id) = next.take() else {
+                break;
+            };
+            let Some(node) = self.flow.node(&id) else {
+                warn!("IVR flow {} has no node {}", self.flow.id, id);
+                break;
+            };
+            match &node.kind {
+                IvrNodeKind::Play(play) => {
+                    actions.push(IvrAction::Play(play.prompt.clone()));
+                    next = play.next.clone();
+                }
+                IvrNodeKind::Menu(menu) => {
+                    actions.push(IvrAction::Play(menu.prompt.clone()));
+                    actions.push(IvrAction::AwaitInput(menu.timeout_ms));
+                    self.state = State::Menu {
+                        node: id,
+                        attempts: 0,
+                    };
+                    return;
+                }
+                IvrNodeKind::Collect(collect) => {
+                    actions.push(IvrAction::Play(collect.prompt.clone()));
+                    actions.push(IvrAction::AwaitInput(collect.timeout_ms));
+                    self.state = State::Collect {
+                        node: id,
+                        digits: String::new(),
+                    };
+                    return;
+                }
+                IvrNodeKind::Branch(branch) => {
+                    let value = self
+                        .variables
+                        .get(&branch.variable)
+                        .map(String::as_str)
+                        .unwrap_or("");
+                    next = branch
+                        .cases
+                        .iter()
+                        .find(|case| Regex::new(&case.pattern).is_ok_and(|re| re.is_match(value)))
+                        .map(|case| case.next.clone())
+                        .or_else(|| branch.default.clone());
+                }
+                IvrNodeKind::Transfer(transfer) => {
+                    actions.push(IvrAction::Transfer(self.substitute(&transfer.destination)));
+                    self.state = State::Finished;
+                    return;
+                }
+                IvrNodeKind::Voicemail(voicemail) => {
+                    actions.push(IvrAction::Voicemail(self.substitute(&voicemail.mailbox)));
+                    self.state = State::Finished;
+                    return;
+                }
+            }
+        }
+        if next.is_some() {
+            warn!("IVR flow {} loops without waiting for input", self.flow.id);
+        }
+        actions.push(IvrAction::Hangup);
+        self.state = State::Finished;
+    }
+
+    /// `text` with each `{variable}` replaced by its digits
+    fn substitute(&self, text: &str) -> String {
+        self.variables
+            .iter()
+            .fold(text.to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use IvrAction::*;
+
+    fn session() -> IvrSession {
+        let flow: IvrFlow = serde_json::from_value(serde_json::json!({
+            "id": "main",
+            "name": "Main",
+            "start": "welcome",
+            "nodes": [
+                { "id": "welcome", "type": "play", "prompt": "welcome", "next": "menu" },
+                { "id": "menu", "type": "menu", "prompt": "menu", "retries": 1,
+                  "invalid_prompt": "invalid", "on_failure": "operator",
+                  "options": { "1": "sales", "2": "account" } },
+                { "id": "account", "type": "collect", "prompt": "account",
+                  "variable": "account", "max_digits": 4, "next": "check" },
+                { "id": "check", "type": "branch", "variable": "account",
+                  "cases": [{ "pattern": "^9", "next": "vip" }], "default": "support" },
+                { "id": "sales", "type": "transfer", "destination": "2000" },
+                { "id": "vip", "type": "transfer", "destination": "2100" },
+                { "id": "support", "type": "transfer", "destination": "queue-{account}" },
+                { "id": "operator", "type": "voicemail", "mailbox": "1000" }
+            ]
+        }))
+        .unwrap();
+        IvrSession::new(Arc::new(flow))
+    }
+
+    #[test]
+    fn test_menu() {
+        let mut call = session();
+        assert_eq!(
+            call.start(),
+            vec![
+                Play("welcome".to_string()),
+                Play("menu".to_string()),
+                AwaitInput(5000)
+            ]
+        );
+        assert_eq!(
+            call.key('7'),
+            vec![
+                Play("invalid".to_string()),
+                Play("menu".to_string()),
+                AwaitInput(5000)
+            ]
+        );
+        assert_eq!(call.key('1'), vec![Transfer("2000".to_string())]);
+        assert!(call.is_finished());
+        assert!(call.key('1').is_empty());
+
+        // Retries run out
+        let mut call = session();
+        call.start();
+        call.timeout();
+        assert_eq!(call.timeout(), vec![Voicemail("1000".to_string())]);
+    }
+
+    #[test]
+    fn test_collect_and_branch() {
+        let mut call = session();
+        call.start();
+        assert_eq!(
+            call.key('2'),
+            vec![Play("account".to_string()), AwaitInput(5000)]
+        );
+        assert_eq!(call.key('4'), vec![AwaitInput(5000)]);
+        assert_eq!(call.key('2'), vec![AwaitInput(5000)]);
+        assert_eq!(call.key('#'), vec![Transfer("queue-42".to_string())]);
+        assert_eq!(call.variables()["account"], "42");
+
+        // Collection ends at max_digits
+        let mut call = session();
+        call.start();
+        call.key('2');
+        for key in ['9', '1', '2'] {
+            call.key(key);
+        }
+        assert_eq!(call.key('3'), vec![Transfer("2100".to_string())]);
+    }
+
+    #[test]
+    fn test_loop_hangs_up() {
+        let flow: IvrFlow = serde_json::from_value(serde_json::json!({
+            "id": "spin",
+            "name": "Spin",
+            "start": "a",
+            "nodes": [
+                { "id": "a", "type": "play", "prompt": "a", "next": "a" }
+            ]
+        }))
+        .unwrap();
+        let actions = IvrSession::new(Arc::new(flow)).start();
+        assert_eq!(actions.len(), MAX_STEPS + 1);
+        assert_eq!(actions.last(), Some(&Hangup));
+    }
+
+    #[test]
+    fn test_branch_on_answering_machine() {
+        let flow: IvrFlow = serde_json::from_value(serde_json::json!({
+            "id": "reminder",
+            "name": "Reminder",
+            "start": "who",
+            "nodes": [
+                { "id": "who", "type": "branch", "variable": "amd",
+                  "cases": [{ "pattern": "^machine$", "next": "message" }], "default": "agent" },
+                { "id": "message", "type": "play", "prompt": "reminder-message" },
+                { "id": "agent", "type": "transfer", "destination": "queue-reminders" }
+            ]
+        }))
+        .unwrap();
+        assert!(crate::ivr::validate(&flow).is_empty());
+        let flow = Arc::new(flow);
+
+        let mut call = IvrSession::new(flow.clone());
+        call.set_variable("amd", "machine");
+        assert_eq!(
+            call.start(),
+            vec![Play("reminder-message".to_string()), Hangup]
+        );
+
+        let mut call = IvrSession::new(flow);
+        call.set_variable("amd", "human");
+        assert_eq!(call.start(), vec![Transfer("queue-reminders".to_string())]);
+    }
+}
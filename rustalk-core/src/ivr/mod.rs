@@ -0,0 +1,260 @@
+//! IVR flows
+//!
+//! An IVR flow is a graph of nodes a call moves through: menus that wait
+//! for a key, prompts that play and move on, digit collection into named
+//! variables, branches on those variables, and the transfers and voicemail
+//! boxes calls leave by. Flows are plain JSON so the WebUI's builder can
+//! edit them:
+//!
+//! ```json
+//! {
+//!   "id": "main",
+//!   "name": "Main menu",
+//!   "start": "welcome",
+//!   "nodes": [
+//!     { "id": "welcome", "type": "play", "prompt": "welcome", "next": "menu" },
+//!     { "id": "menu", "type": "menu", "prompt": "main-menu",
+//!       "options": { "1": "sales", "2": "account" }, "on_failure": "operator" },
+//!     { "id": "account", "type": "collect", "prompt": "enter-account",
+//!       "variable": "account", "max_digits": 8, "next": "check" },
+//!     { "id": "check", "type": "branch", "variable": "account",
+//!       "cases": [{ "pattern": "^9", "next": "vip" }], "default": "support" },
+//!     { "id": "sales", "type": "transfer", "destination": "2000" },
+//!     { "id": "support", "type": "transfer", "destination": "queue-{account}" },
+//!     { "id": "vip", "type": "transfer", "destination": "2100" },
+//!     { "id": "operator", "type": "voicemail", "mailbox": "1000" }
+//!   ]
+//! }
+//! ```
+//!
+//! Branches can also test variables the call arrives with, listed in
+//! [`CALL_VARIABLES`], such as whether a dialer call was answered by a
+//! machine.
+//!
+//! A node without a `next` hangs up. [`validate`] reports flows that
+//! cannot run as drawn, and an [`IvrSession`] runs one call through a flow.
+
+pub mod engine;
+pub mod validate;
+
+pub use engine::{IvrAction, IvrSession};
+pub use validate::{validate, FlowIssue};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Variables set on a call before it enters a flow: the answering machine
+/// detection verdict (`human`, `machine` or `notsure`) and its cause
+pub const CALL_VARIABLES: &[&str] = &["amd", "amd_cause"];
+
+/// IVR flows calls can be sent to
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct IvrConfig {
+    #[serde(default)]
+    pub flows: Vec<IvrFlow>,
+}
+
+/// A graph of IVR nodes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IvrFlow {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Node calls enter at
+    pub start: String,
+    pub nodes: Vec<IvrNode>,
+}
+
+impl IvrFlow {
+    pub fn node(&self, id: &str) -> Option<&IvrNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+}
+
+/// One step of a flow
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IvrNode {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: IvrNodeKind,
+    /// Where the builder draws the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<NodePosition>,
+}
+
+impl IvrNode {
+    /// Nodes this one can lead to, with what leads there
+    pub fn targets(&self) -> Vec<(String, &str)> {
+        fn next<'a>(label: &str, target: &'a Option<String>) -> Option<(String, &'a str)> {
+            target.as_deref().map(|target| (label.to_string(), target))
+        }
+        match &self.kind {
+            IvrNodeKind::Menu(menu) => menu
+                .options
+                .iter()
+                .map(|(key, target)| (format!("option {}", key), target.as_str()))
+                .chain(next("on_failure", &menu.on_failure))
+                .collect(),
+            IvrNodeKind::Play(play) => next("next", &play.next).into_iter().collect(),
+            IvrNodeKind::Collect(collect) => next("next", &collect.next).into_iter().collect(),
+            IvrNodeKind::Branch(branch) => branch
+                .cases
+                .iter()
+                .map(|case| (format!("case {}", case.pattern), case.next.as_str()))
+                .chain(next("default", &branch.default))
+                .collect(),
+            IvrNodeKind::Transfer(_) | IvrNodeKind::Voicemail(_) => Vec::new(),
+        }
+    }
+}
+
+/// Canvas coordinates of a node
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct NodePosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IvrNodeKind {
+    Menu(MenuNode),
+    Play(PlayNode),
+    Collect(CollectNode),
+    Branch(BranchNode),
+    Transfer(TransferNode),
+    Voicemail(VoicemailNode),
+}
+
+/// Plays a prompt and waits for one key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MenuNode {
+    pub prompt: String,
+    /// Node each key leads to
+    pub options: BTreeMap<String, String>,
+    /// How long to wait for a key
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Times the menu is repeated after a wrong key or no key
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Played before the menu is repeated after a wrong key
+    #[serde(default)]
+    pub invalid_prompt: Option<String>,
+    /// Node calls go to once retries run out; they hang up without one
+    #[serde(default)]
+    pub on_failure: Option<String>,
+}
+
+/// Plays a prompt and moves on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PlayNode {
+    pub prompt: String,
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// Plays a prompt and collects digits into a variable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CollectNode {
+    pub prompt: String,
+    pub variable: String,
+    /// Collection ends after this many digits
+    #[serde(default = "default_max_digits")]
+    pub max_digits: usize,
+    /// Key that ends collection early
+    #[serde(default = "default_terminator")]
+    pub terminator: char,
+    /// How long to wait for each digit
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// Goes to the first case whose pattern matches a variable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BranchNode {
+    pub variable: String,
+    pub cases: Vec<BranchCase>,
+    /// Node taken when no case matches
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BranchCase {
+    /// Regular expression the variable is matched against
+    pub pattern: String,
+    pub next: String,
+}
+
+/// Transfers the call; `{variable}` is replaced by what was collected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TransferNode {
+    pub destination: String,
+}
+
+/// Sends the call to a voicemail box; `{variable}` is replaced by what was
+/// collected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VoicemailNode {
+    pub mailbox: String,
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_max_digits() -> usize {
+    10
+}
+
+fn default_terminator() -> char {
+    '#'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_json() {
+        let flow: IvrFlow = serde_json::from_value(serde_json::json!({
+            "id": "main",
+            "name": "Main menu",
+            "start": "menu",
+            "nodes": [
+                { "id": "menu", "type": "menu", "prompt": "main-menu",
+                  "options": { "1": "sales" }, "position": { "x": 10.0, "y": 20.0 } },
+                { "id": "sales", "type": "transfer", "destination": "2000" }
+            ]
+        }))
+        .unwrap();
+
+        let IvrNodeKind::Menu(menu) = &flow.nodes[0].kind else {
+            panic!("expected a menu");
+        };
+        assert_eq!(menu.timeout_ms, 5000);
+        assert_eq!(menu.retries, 2);
+        assert_eq!(
+            flow.nodes[0].position,
+            Some(NodePosition { x: 10.0, y: 20.0 })
+        );
+        assert_eq!(
+            flow.nodes[0].targets(),
+            vec![("option 1".to_string(), "sales")]
+        );
+
+        let json = serde_json::to_value(&flow).unwrap();
+        assert_eq!(json["nodes"][1]["type"], "transfer");
+        assert!(json["nodes"][1].get("position").is_none());
+        assert_eq!(serde_json::from_value::<IvrFlow>(json).unwrap(), flow);
+    }
+}
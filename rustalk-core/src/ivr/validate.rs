@@ -0,0 +1,296 @@
+//! Checks that an IVR flow can run as drawn
+
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::{IvrFlow, IvrNodeKind, CALL_VARIABLES};
+
+/// Something wrong with a flow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlowIssue {
+    /// Node the issue is on, `None` for the flow as a whole
+    pub node: Option<String>,
+    pub message: String,
+}
+
+impl FlowIssue {
+    fn flow(message: impl Into<String>) -> Self {
+        Self {
+            node: None,
+            message: message.into(),
+        }
+    }
+
+    fn node(node: &str, message: impl Into<String>) -> Self {
+        Self {
+            node: Some(node.to_string()),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FlowIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.node {
+            Some(node) => write!(f, "node '{}': {}", node, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Issues that keep `flow` from running as drawn: missing and unreachable
+/// nodes, bad keys and patterns, and loops that never wait for the caller
+pub fn validate(flow: &IvrFlow) -> Vec<FlowIssue> {
+    let mut issues = Vec::new();
+    if flow.id.is_empty() {
+        issues.push(FlowIssue::flow("flow id must not be empty"));
+    }
+    if flow.nodes.is_empty() {
+        issues.push(FlowIssue::flow("flow has no nodes"));
+        return issues;
+    }
+
+    let mut ids = HashSet::new();
+    for node in &flow.nodes {
+        if node.id.is_empty() {
+            issues.push(FlowIssue::flow("node id must not be empty"));
+        } else if !ids.insert(node.id.as_str()) {
+            issues.push(FlowIssue::node(&node.id, "node id is used more than once"));
+        }
+    }
+    if !ids.contains(flow.start.as_str()) {
+        issues.push(FlowIssue::flow(format!(
+            "start node '{}' does not exist",
+            flow.start
+        )));
+    }
+
+    let collected: HashSet<&str> = flow
+        .nodes
+        .iter()
+        .filter_map(|node| match &node.kind {
+            IvrNodeKind::Collect(collect) => Some(collect.variable.as_str()),
+            _ => None,
+        })
+        .chain(CALL_VARIABLES.iter().copied())
+        .collect();
+    for node in &flow.nodes {
+        for (label, target) in node.targets() {
+            if !ids.contains(target) {
+                issues.push(FlowIssue::node(
+                    &node.id,
+                    format!("{} leads to missing node '{}'", label, target),
+                ));
+            }
+        }
+
+        match &node.kind {
+            IvrNodeKind::Menu(menu) => {
+                if menu.options.is_empty() {
+                    issues.push(FlowIssue::node(&node.id, "menu has no options"));
+                }
+                for key in menu.options.keys() {
+                    if !is_key(key) {
+                        issues.push(FlowIssue::node(
+                            &node.id,
+                            format!("'{}' is not a key; use 0-9, * or #", key),
+                        ));
+                    }
+                }
+            }
+            IvrNodeKind::Collect(collect) => {
+                if collect.variable.is_empty() {
+                    issues.push(FlowIssue::node(&node.id, "variable must not be empty"));
+                }
+                if collect.max_digits == 0 {
+                    issues.push(FlowIssue::node(&node.id, "max_digits must be at least 1"));
+                }
+                if !is_key(&collect.terminator.to_string()) {
+                    issues.push(FlowIssue::node(
+                        &node.id,
+                        format!("terminator '{}' is not a key", collect.terminator),
+                    ));
+                }
+            }
+            IvrNodeKind::Branch(branch) => {
+                if !collected.contains(branch.variable.as_str()) {
+                    issues.push(FlowIssue::node(
+                        &node.id,
+                        format!("variable '{}' is never collected", branch.variable),
+                    ));
+                }
+                for case in &branch.cases {
+                    if let Err(e) = Regex::new(&case.pattern) {
+                        issues.push(FlowIssue::node(
+                            &node.id,
+                            format!("pattern '{}' is invalid: {}", case.pattern, e),
+                        ));
+                    }
+                }
+            }
+            IvrNodeKind::Transfer(transfer) if transfer.destination.is_empty() => {
+                issues.push(FlowIssue::node(&node.id, "destination must not be empty"));
+            }
+            IvrNodeKind::Voicemail(voicemail) if voicemail.mailbox.is_empty() => {
+                issues.push(FlowIssue::node(&node.id, "mailbox must not be empty"));
+            }
+            _ => {}
+        }
+    }
+
+    let reachable = reachable(flow);
+    for node in &flow.nodes {
+        if !reachable.contains(node.id.as_str()) && ids.contains(flow.start.as_str()) {
+            issues.push(FlowIssue::node(
+                &node.id,
+                "node cannot be reached from the start",
+            ));
+        }
+    }
+
+    if let Some(node) = input_free_loop(flow) {
+        issues.push(FlowIssue::node(
+            node,
+            "node loops back to itself without waiting for the caller",
+        ));
+    }
+    issues
+}
+
+fn is_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(
+        (chars.next(), chars.next()),
+        (Some('0'..='9' | '*' | '#'), None)
+    )
+}
+
+/// Ids of the nodes a call can reach from the start
+fn reachable(flow: &IvrFlow) -> HashSet<&str> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![flow.start.as_str()];
+    while let Some(id) = pending.pop() {
+        let Some(node) = flow.node(id) else {
+            continue;
+        };
+        if seen.insert(node.id.as_str()) {
+            pending.extend(node.targets().into_iter().map(|(_, target)| target));
+        }
+    }
+    seen
+}
+
+/// A node on a cycle of plays and branches, which would spin without ever
+/// waiting for a key
+fn input_free_loop(flow: &IvrFlow) -> Option<&str> {
+    let edges: HashMap<&str, Vec<&str>> = flow
+        .nodes
+        .iter()
+        .filter(|node| matches!(node.kind, IvrNodeKind::Play(_) | IvrNodeKind::Branch(_)))
+        .map(|node| {
+            let targets = node.targets().into_iter().map(|(_, t)| t).collect();
+            (node.id.as_str(), targets)
+        })
+        .collect();
+
+    // Depth-first search for a back edge, tracking the current path
+    let mut done: HashSet<&str> = HashSet::new();
+    for &start in edges.keys() {
+        if done.contains(start) {
+            continue;
+        }
+        let mut path: Vec<(&str, usize)> = vec![(start, 0)];
+        let mut on_path: HashSet<&str> = HashSet::from([start]);
+        while let Some((id, next)) = path.last_mut() {
+            let targets = &edges[*id];
+            if *next == targets.len() {
+                on_path.remove(*id);
+                done.insert(*id);
+                path.pop();
+                continue;
+            }
+            let target = targets[*next];
+            *next += 1;
+            if on_path.contains(target) {
+                return Some(target);
+            }
+            if edges.contains_key(target) && !done.contains(target) {
+                on_path.insert(target);
+                path.push((target, 0));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(nodes: serde_json::Value) -> IvrFlow {
+        serde_json::from_value(serde_json::json!({
+            "id": "main",
+            "name": "Main",
+            "start": "menu",
+            "nodes": nodes,
+        }))
+        .unwrap()
+    }
+
+    fn messages(flow: &IvrFlow) -> Vec<String> {
+        validate(flow).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_valid_flow() {
+        let flow = flow(serde_json::json!([
+            { "id": "menu", "type": "menu", "prompt": "menu",
+              "options": { "1": "account", "#": "bye" } },
+            { "id": "account", "type": "collect", "prompt": "account",
+              "variable": "account", "next": "check" },
+            { "id": "check", "type": "branch", "variable": "account",
+              "cases": [{ "pattern": "^9", "next": "vip" }], "default": "menu" },
+            { "id": "vip", "type": "transfer", "destination": "2100" },
+            { "id": "bye", "type": "play", "prompt": "goodbye" }
+        ]));
+        assert!(validate(&flow).is_empty(), "{:?}", validate(&flow));
+    }
+
+    #[test]
+    fn test_invalid_flow() {
+        let flow = flow(serde_json::json!([
+            { "id": "menu", "type": "menu", "prompt": "menu",
+              "options": { "1": "gone", "12": "loop" } },
+            { "id": "loop", "type": "play", "prompt": "again", "next": "check" },
+            { "id": "check", "type": "branch", "variable": "nothing",
+              "cases": [{ "pattern": "(", "next": "loop" }] },
+            { "id": "orphan", "type": "voicemail", "mailbox": "" },
+            { "id": "orphan", "type": "transfer", "destination": "2000" }
+        ]));
+        let found = messages(&flow);
+        for expected in [
+            "node 'orphan': node id is used more than once",
+            "node 'menu': option 1 leads to missing node 'gone'",
+            "node 'menu': '12' is not a key; use 0-9, * or #",
+            "node 'check': variable 'nothing' is never collected",
+            "node 'orphan': mailbox must not be empty",
+            "node 'orphan': node cannot be reached from the start",
+        ] {
+            assert!(found.iter().any(|m| m == expected), "{:?}", found);
+        }
+        assert!(found
+            .iter()
+            .any(|m| m.starts_with("node 'check': pattern '(' is invalid")));
+        assert!(found
+            .iter()
+            .any(|m| m.ends_with("loops back to itself without waiting for the caller")));
+
+        let mut missing_start = flow.clone();
+        missing_start.start = "nowhere".to_string();
+        assert!(
+            messages(&missing_start).contains(&"start node 'nowhere' does not exist".to_string())
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Least-cost routing
+//!
+//! A `LeastCost` route names several trunks that can all carry the call.
+//! Each is priced with the rate card charged on it whose prefix matches
+//! the most of the dialed number, as the cost of a call lasting
+//! `reference_seconds`, and the call goes out over the cheapest trunk that
+//! is not down. The ranking is also the order the other trunks are fallen
+//! back to: by cost, or the order the route lists them in. Trunks no rate
+//! applies to come last, or are left out.
+//!
+//! Costs are compared as they are, so the rate cards of the trunks a route
+//! chooses between should share a currency.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+/// Rate card for call charging
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RateCard {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub prefix: String,
+    pub rate_per_minute: f64,
+    pub connection_fee: f64,
+    pub minimum_charge_seconds: u32,
+    pub billing_increment_seconds: u32,
+    pub currency: String,
+    pub effective_date: i64,
+    pub end_date: Option<i64>,
+    pub active: bool,
+    /// Trunk the rate is charged on; a rate without one applies over any
+    /// trunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trunk: Option<String>,
+}
+
+impl RateCard {
+    /// Whether the rate is active and in effect at `at`, in Unix seconds
+    pub fn is_effective(&self, at: i64) -> bool {
+        self.active && self.effective_date <= at && self.end_date.is_none_or(|end| end >= at)
+    }
+
+    /// Cost of a call lasting `seconds`, connection fee included
+    pub fn cost(&self, seconds: u32) -> f64 {
+        let billable = billable_seconds(
+            seconds,
+            self.minimum_charge_seconds,
+            self.billing_increment_seconds,
+        );
+        self.connection_fee + billable as f64 / 60.0 * self.rate_per_minute
+    }
+}
+
+/// Seconds charged for a call, after the minimum charge and rounded up to
+/// the billing increment
+pub fn billable_seconds(actual_seconds: u32, minimum_seconds: u32, increment_seconds: u32) -> u32 {
+    let duration = actual_seconds.max(minimum_seconds);
+    if increment_seconds > 0 {
+        duration.div_ceil(increment_seconds) * increment_seconds
+    } else {
+        duration
+    }
+}
+
+/// The longest-prefix rate for `number` among those in effect at `at`
+///
+/// With a trunk, only rates charged on it or on any trunk are considered,
+/// and one charged on it wins a tie.
+pub fn find_rate<'a>(
+    rates: &'a [RateCard],
+    number: &str,
+    trunk: Option<&str>,
+    at: i64,
+) -> Option<&'a RateCard> {
+    let number = number.trim_start_matches('+');
+    let mut best: Option<(&RateCard, (usize, bool))> = None;
+    for rate in rates {
+        if !rate.is_effective(at) {
+            continue;
+        }
+        let own = match (trunk, rate.trunk.as_deref()) {
+            (Some(trunk), Some(charged_on)) if !charged_on.eq_ignore_ascii_case(trunk) => continue,
+            (Some(_), Some(_)) => true,
+            _ => false,
+        };
+        let prefix = rate.prefix.trim_start_matches('+');
+        if !number.starts_with(prefix) {
+            continue;
+        }
+        let key = (prefix.len(), own);
+        if best.is_none_or(|(_, best_key)| key > best_key) {
+            best = Some((rate, key));
+        }
+    }
+    best.map(|(rate, _)| rate)
+}
+
+/// Order trunks are fallen back to after the cheapest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FallbackOrder {
+    /// Cheapest next
+    #[default]
+    Cost,
+    /// The order the route lists them in
+    Listed,
+}
+
+/// Rate cards trunks are priced with and how they are ranked
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LcrConfig {
+    #[serde(default)]
+    pub rates: Vec<RateCard>,
+    /// Length of the call trunks are priced for, so connection fees and
+    /// minimum charges weigh in
+    #[serde(default = "default_reference_seconds")]
+    pub reference_seconds: u32,
+    #[serde(default)]
+    pub fallback: FallbackOrder,
+    /// Leave out trunks no rate applies to, instead of trying them last
+    #[serde(default)]
+    pub skip_unrated: bool,
+}
+
+fn default_reference_seconds() -> u32 {
+    60
+}
+
+impl Default for LcrConfig {
+    fn default() -> Self {
+        Self {
+            rates: Vec::new(),
+            reference_seconds: default_reference_seconds(),
+            fallback: FallbackOrder::default(),
+            skip_unrated: false,
+        }
+    }
+}
+
+/// What a call over a trunk would cost
+#[derive(Debug, Clone, Serialize)]
+pub struct TrunkQuote {
+    pub trunk: String,
+    /// Rate the call would be charged at, if any applies
+    pub rate: Option<RateCard>,
+    /// Cost of a call lasting the reference length
+    pub cost: Option<f64>,
+}
+
+impl fmt::Display for TrunkQuote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rate {
+            Some(rate) => write!(
+                f,
+                "{} at {:.4} {}/min",
+                self.trunk, rate.rate_per_minute, rate.currency
+            ),
+            None => write!(f, "{} (unrated)", self.trunk),
+        }
+    }
+}
+
+/// Ranks the trunks of least-cost routes by what a call over each costs
+#[derive(Debug, Clone, Default)]
+pub struct LeastCostRouter {
+    config: Arc<LcrConfig>,
+}
+
+impl LeastCostRouter {
+    pub fn new(config: LcrConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn config(&self) -> &LcrConfig {
+        &self.config
+    }
+
+    /// Price a call to `number` over `trunk` at `at`
+    pub fn quote(&self, trunk: &str, number: &str, at: DateTime<Utc>) -> TrunkQuote {
+        let rate = find_rate(&self.config.rates, number, Some(trunk), at.timestamp());
+        TrunkQuote {
+            trunk: trunk.to_string(),
+            cost: rate.map(|rate| rate.cost(self.config.reference_seconds)),
+            rate: rate.cloned(),
+        }
+    }
+
+    /// `trunks` in the order a call to `number` tries them, cheapest first
+    pub fn rank(&self, trunks: &[String], number: &str, at: DateTime<Utc>) -> Vec<TrunkQuote> {
+        let mut quotes: Vec<TrunkQuote> = trunks
+            .iter()
+            .map(|trunk| self.quote(trunk, number, at))
+            .collect();
+        if self.config.skip_unrated {
+            quotes.retain(|quote| quote.cost.is_some());
+        }
+        match self.config.fallback {
+            // The sort is stable, so equal costs keep the listed order
+            FallbackOrder::Cost => quotes.sort_by(|a, b| by_cost(a.cost, b.cost)),
+            FallbackOrder::Listed => {
+                let cheapest = (0..quotes.len())
+                    .filter(|&i| quotes[i].cost.is_some())
+                    .min_by(|&a, &b| by_cost(quotes[a].cost, quotes[b].cost).then(a.cmp(&b)));
+                if let Some(cheapest) = cheapest {
+                    let quote = quotes.remove(cheapest);
+                    quotes.insert(0, quote);
+                }
+            }
+        }
+        quotes
+    }
+}
+
+/// Cheaper first, unrated last
+fn by_cost(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(id: &str, trunk: Option<&str>, prefix: &str, per_minute: f64, fee: f64) -> RateCard {
+        RateCard {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: None,
+            prefix: prefix.to_string(),
+            rate_per_minute: per_minute,
+            connection_fee: fee,
+            minimum_charge_seconds: 0,
+            billing_increment_seconds: 1,
+            currency: "USD".to_string(),
+            effective_date: 0,
+            end_date: None,
+            active: true,
+            trunk: trunk.map(str::to_string),
+        }
+    }
+
+    fn router(fallback: FallbackOrder, skip_unrated: bool) -> LeastCostRouter {
+        let mut retired = rate("retired", Some("carrier-d"), "44", 0.001, 0.0);
+        retired.active = false;
+        LeastCostRouter::new(LcrConfig {
+            rates: vec![
+                rate("a-uk", Some("carrier-a"), "44", 0.02, 0.0),
+                rate("a-uk-mobile", Some("carrier-a"), "447", 0.09, 0.0),
+                rate("b-uk", Some("carrier-b"), "44", 0.01, 0.05),
+                rate("c-any", Some("carrier-c"), "", 0.03, 0.0),
+                retired,
+            ],
+            fallback,
+            skip_unrated,
+            ..Default::default()
+        })
+    }
+
+    fn trunks(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn ranked(lcr: &LeastCostRouter, number: &str) -> Vec<String> {
+        let names = trunks(&["carrier-d", "carrier-c", "carrier-a", "carrier-b"]);
+        lcr.rank(&names, number, Utc::now())
+            .into_iter()
+            .map(|quote| quote.trunk)
+            .collect()
+    }
+
+    #[test]
+    fn test_find_rate() {
+        let rates = router(FallbackOrder::Cost, false).config().rates.clone();
+        let found = |number, trunk| find_rate(&rates, number, trunk, 1).map(|r| r.id.as_str());
+        assert_eq!(
+            found("+447700900123", Some("carrier-a")),
+            Some("a-uk-mobile")
+        );
+        assert_eq!(found("442079460000", Some("CARRIER-A")), Some("a-uk"));
+        assert_eq!(found("15550100", Some("carrier-a")), None);
+        assert_eq!(found("442079460000", Some("carrier-d")), None);
+        assert_eq!(found("447700900123", None), Some("a-uk-mobile"));
+
+        let mut rates = rates;
+        rates.push(rate("any-uk", None, "44", 0.5, 0.0));
+        let found = find_rate(&rates, "442079460000", Some("carrier-b"), 1);
+        assert_eq!(found.map(|r| r.id.as_str()), Some("b-uk"));
+        let found = find_rate(&rates, "442079460000", Some("carrier-e"), 1);
+        assert_eq!(found.map(|r| r.id.as_str()), Some("any-uk"));
+    }
+
+    #[test]
+    fn test_rank_by_cost() {
+        let lcr = router(FallbackOrder::Cost, false);
+        // A minute costs 0.02 on a, 0.06 on b with its fee and 0.03 on c
+        assert_eq!(
+            ranked(&lcr, "442079460000"),
+            ["carrier-a", "carrier-c", "carrier-b", "carrier-d"]
+        );
+        // Mobiles cost 0.09 on a
+        assert_eq!(
+            ranked(&lcr, "447700900123"),
+            ["carrier-c", "carrier-b", "carrier-a", "carrier-d"]
+        );
+
+        let quote = lcr.quote("carrier-b", "442079460000", Utc::now());
+        assert!((quote.cost.unwrap() - 0.06).abs() < 1e-9);
+        assert_eq!(quote.to_string(), "carrier-b at 0.0100 USD/min");
+        let quote = lcr.quote("carrier-d", "442079460000", Utc::now());
+        assert_eq!(quote.to_string(), "carrier-d (unrated)");
+    }
+
+    #[test]
+    fn test_rank_listed_fallback() {
+        // The cheapest first, then the rest as listed
+        let lcr = router(FallbackOrder::Listed, false);
+        assert_eq!(
+            ranked(&lcr, "442079460000"),
+            ["carrier-a", "carrier-d", "carrier-c", "carrier-b"]
+        );
+        let lcr = router(FallbackOrder::Listed, true);
+        assert_eq!(
+            ranked(&lcr, "442079460000"),
+            ["carrier-a", "carrier-c", "carrier-b"]
+        );
+    }
+}
@@ -6,17 +6,113 @@
 //! - SIP transaction handling
 //! - Media session management
 //! - ACME/Let's Encrypt certificate management
+//! - Certificate expiry warnings
+//! - Cluster-wide call admission control
+//! - Per-call debug tracing
+//! - CDR sinks for the database and the cloud API
+//! - Database schema migrations
+//! - SIP registrar
+//! - NAT traversal tunables per SIP profile
+//! - Multiple SIP domains per deployment, with aliases
+//! - Device inventory with User-Agent fingerprinting and provisioning files
+//! - Extension groups
+//! - Class of service (calling permissions)
+//! - Account codes for billing attribution
+//! - PIN-protected outbound dialing for shared phones
+//! - Automatic callback when busy
+//! - Call screening and caller announcement
+//! - Ring timeouts with per-extension no-answer actions
+//! - Diversion and History-Info on forwarded calls
+//! - Message waiting indication for voicemail (RFC 3842)
+//! - Busy lamp field lists per device, served as resource lists (RFC 4662)
+//! - SMS gateway with SIP MESSAGE bridging
+//! - Fax-to-email and email-to-fax through a T.38 gateway
+//! - Teams call record import from Microsoft Graph
+//! - Teams Direct Routing validation
+//! - Post-dial delay tracking per trunk
+//! - Packet loss and jitter alerting per trunk
+//! - Resource quotas per tenant
+//! - Call events with CRM screen-pop enrichment
+//! - Missed call notifications by email
+//! - Per-extension call history with number masking
+//! - Outbound webhooks with payload templates
+//! - SNMP agent for health and call statistics
+//! - RADIUS accounting and digest authentication
+//! - Wholesale carrier peers with IP and tech-prefix authentication
+//! - Per-trunk SIP compatibility profiles
+//! - Outbound dial string templates per trunk
+//! - Named schedules with holiday overlays
+//! - Number portability dips before routing
+//! - Least-cost routing across trunks by rate card
+//! - ENUM (E.164 to SIP URI) resolution for routing
+//! - Localized prompts selected per tenant, DID or route
+//! - IVR flows as node graphs, with validation and an execution engine
+//! - Outbound campaign dialer with pacing, calling hours and a do-not-call list
+//! - Call audio streaming to transcription services, with consent controls
+//! - Log output sinks with rotation
+//! - Async file and DNS IO, with an optional pure-Rust resolver
+//! - Supervised components with restart backoff
+//! - Remote console over TLS with per-user command lists and an audit log
 
+pub mod account_codes;
 pub mod acl;
 pub mod acme;
+pub mod admission;
 pub mod auth;
 pub mod b2bua;
+pub mod call_history;
+pub mod call_trace;
+pub mod callback;
+pub mod cdr_sink;
+pub mod cert_expiry;
 pub mod config;
+pub mod console;
+pub mod cos;
+pub mod crm;
+pub mod db;
+pub mod devices;
+pub mod dial_pin;
+pub mod dial_string;
+pub mod dialer;
+pub mod direct_routing;
+pub mod diversion;
+pub mod domains;
+pub mod enum_resolver;
+pub mod events;
+pub mod fax;
+pub mod groups;
+pub mod io;
+pub mod ivr;
+pub mod lcr;
+pub mod lnp;
+pub mod locale;
+pub mod logging;
 pub mod media;
+pub mod media_quality;
+pub mod missed_calls;
+pub mod mwi;
+pub mod nat;
+pub mod no_answer;
+pub mod pdd;
+pub mod presence;
+pub mod quirks;
+pub mod quotas;
+pub mod radius;
+pub mod registrar;
 pub mod routing;
+pub mod schedules;
+pub mod screening;
 pub mod sip;
+pub mod sms;
+pub mod snmp;
+pub mod supervisor;
+pub mod teams_records;
+pub mod transcription;
 pub mod transport;
+pub mod trunk_health;
 pub mod voicemail;
+pub mod webhooks;
+pub mod wholesale;
 
 pub use config::Config;
 
@@ -24,6 +120,7 @@ pub use config::Config;
 pub mod prelude {
     pub use crate::acl::{create_default_acls, Acl, AclAction, AclManager, AclRule};
     pub use crate::acme::{AcmeClient, AcmeConfig, CertificateStatus};
+    pub use crate::admission::{AdmissionConfig, AdmissionController, AdmissionDecision};
     pub use crate::auth::{AuthManager, DigestChallenge, DigestResponse};
     pub use crate::b2bua::B2BUA;
     pub use crate::config::Config;
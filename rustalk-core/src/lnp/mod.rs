@@ -0,0 +1,419 @@
+//! Number portability dips
+//!
+//! Before an INVITE is routed, the dialed number can be looked up in a
+//! number portability database to find the location routing number (LRN)
+//! of the carrier that now serves it. The lookup is pluggable: an HTTP
+//! service, an ENUM zone, or any [`PortabilityLookup`]. Results, including
+//! "not ported", are cached; failures are not.
+//!
+//! A dipped destination is rewritten in the RFC 4694 form
+//! `+12125551234;npdi;rn=+12125550000` by default, which tells downstream
+//! carriers not to dip again, or has its number replaced by the LRN.
+//! Numbers already marked `npdi` are left alone.
+
+use anyhow::{anyhow, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::enum_resolver::enum_domain;
+
+/// Number portability configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LnpConfig {
+    pub provider: LnpProvider,
+    /// Trunks whose calls are dipped; empty dips every call
+    #[serde(default)]
+    pub trunks: Vec<String>,
+    /// Calls wait at most this long for an answer
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How long answers, including "not ported", are reused
+    #[serde(default = "default_cache_seconds")]
+    pub cache_seconds: u64,
+    #[serde(default)]
+    pub on_failure: LnpFailure,
+    #[serde(default)]
+    pub rewrite: LnpRewrite,
+}
+
+fn default_timeout_ms() -> u64 {
+    500
+}
+
+fn default_cache_seconds() -> u64 {
+    3600
+}
+
+fn default_enum_domain() -> String {
+    "e164.arpa".to_string()
+}
+
+/// Where ported-number data comes from
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LnpProvider {
+    /// GET `url` with `{number}` replaced by the dialed number; the service
+    /// answers `{"lrn": ...}`, with a null LRN or 404 when not ported
+    Http {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// NAPTR records under `domain` whose regexp carries `rn=` (needs the
+    /// `hickory-dns` feature)
+    Enum {
+        #[serde(default = "default_enum_domain")]
+        domain: String,
+    },
+}
+
+/// What happens to a call when the dip fails or times out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LnpFailure {
+    /// Route on the dialed number as if it were not ported
+    #[default]
+    Continue,
+    /// Reject the call with 503
+    Reject,
+}
+
+/// How a dipped destination is rewritten
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LnpRewrite {
+    /// Add `;npdi` and, for ported numbers, `;rn=<lrn>`
+    #[default]
+    Npdi,
+    /// Replace ported numbers by their LRN
+    Replace,
+}
+
+/// Looks up the LRN of a number
+#[async_trait::async_trait]
+pub trait PortabilityLookup: Send + Sync {
+    /// LRN of `number`, or `None` when it is not ported
+    async fn lookup(&self, number: &str) -> Result<Option<String>>;
+}
+
+/// Dips against an HTTP service
+pub struct HttpLookup {
+    url: String,
+    headers: HashMap<String, String>,
+    client: reqwest::Client,
+}
+
+impl HttpLookup {
+    pub fn new(url: String, headers: HashMap<String, String>) -> Self {
+        Self {
+            url,
+            headers,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HttpAnswer {
+    #[serde(default)]
+    lrn: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl PortabilityLookup for HttpLookup {
+    async fn lookup(&self, number: &str) -> Result<Option<String>> {
+        let url = self.url.replace("{number}", &number.replace('+', "%2B"));
+        let mut request = self.client.get(url);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await?;
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::NO_CONTENT => Ok(None),
+            status if status.is_success() => Ok(response.json::<HttpAnswer>().await?.lrn),
+            status => Err(anyhow!("Portability service returned {}", status)),
+        }
+    }
+}
+
+/// Dips against an ENUM zone
+pub struct EnumLookup {
+    domain: String,
+}
+
+impl EnumLookup {
+    pub fn new(domain: String) -> Self {
+        Self { domain }
+    }
+}
+
+#[async_trait::async_trait]
+impl PortabilityLookup for EnumLookup {
+    async fn lookup(&self, number: &str) -> Result<Option<String>> {
+        let records = crate::io::dns::lookup_naptr(&enum_domain(number, &self.domain)).await?;
+        Ok(records.iter().find_map(|r| routing_param(&r.regexp)))
+    }
+}
+
+/// Value of an `rn=` parameter in a URI or NAPTR regexp
+fn routing_param(text: &str) -> Option<String> {
+    text.split(';')
+        .skip(1)
+        .find_map(|param| param.strip_prefix("rn="))
+        .map(|rn| {
+            rn.split([';', '!', '@', '>'])
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        })
+        .filter(|rn| !rn.is_empty())
+}
+
+/// Number a destination routes on: its `rn=` LRN when it has been dipped,
+/// otherwise the number without parameters
+pub fn routing_number(user: &str) -> String {
+    routing_param(user).unwrap_or_else(|| user.split(';').next().unwrap_or_default().to_string())
+}
+
+/// Cached LRNs by number, `None` when not ported
+type LrnCache = HashMap<String, (Instant, Option<String>)>;
+
+/// Outcome of a dip
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DipResult {
+    /// The destination was already dipped, or is not dipped on this trunk
+    Skipped,
+    NotPorted,
+    Ported(String),
+    Failed(String),
+}
+
+/// Pre-routing portability stage
+#[derive(Clone)]
+pub struct PortabilityDip {
+    config: Arc<LnpConfig>,
+    lookup: Arc<dyn PortabilityLookup>,
+    cache: Arc<Mutex<LrnCache>>,
+}
+
+impl PortabilityDip {
+    /// Dip against the configured provider
+    pub fn new(config: LnpConfig) -> Self {
+        let lookup: Arc<dyn PortabilityLookup> = match &config.provider {
+            LnpProvider::Http { url, headers } => {
+                Arc::new(HttpLookup::new(url.clone(), headers.clone()))
+            }
+            LnpProvider::Enum { domain } => Arc::new(EnumLookup::new(domain.clone())),
+        };
+        Self::with_lookup(config, lookup)
+    }
+
+    /// Dip against `lookup` instead of the configured provider
+    pub fn with_lookup(config: LnpConfig, lookup: Arc<dyn PortabilityLookup>) -> Self {
+        Self {
+            config: Arc::new(config),
+            lookup,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &LnpConfig {
+        &self.config
+    }
+
+    /// Whether calls on `trunk` are dipped
+    pub fn enabled_for(&self, trunk: &str) -> bool {
+        self.config.trunks.is_empty()
+            || self
+                .config
+                .trunks
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(trunk))
+    }
+
+    /// Dip the destination `user` of a call on `trunk`
+    pub async fn dip(&self, trunk: &str, user: &str) -> DipResult {
+        if !self.enabled_for(trunk) || is_dipped(user) {
+            return DipResult::Skipped;
+        }
+        let number = user.split(';').next().unwrap_or_default();
+        if !number
+            .trim_start_matches('+')
+            .chars()
+            .any(|c| c.is_ascii_digit())
+        {
+            return DipResult::Skipped;
+        }
+
+        let ttl = Duration::from_secs(self.config.cache_seconds);
+        if let Some((at, lrn)) = self.cache.lock().unwrap().get(number) {
+            if at.elapsed() < ttl {
+                return result(lrn.clone());
+            }
+        }
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let lrn = match tokio::time::timeout(timeout, self.lookup.lookup(number)).await {
+            Ok(Ok(lrn)) => lrn.filter(|lrn| !lrn.is_empty() && lrn != number),
+            Ok(Err(e)) => {
+                // Failures are not cached so the next call tries again
+                warn!("Portability dip for {} failed: {:#}", number, e);
+                return DipResult::Failed(format!("{:#}", e));
+            }
+            Err(_) => {
+                warn!("Portability dip for {} timed out", number);
+                return DipResult::Failed(format!("timed out after {:?}", timeout));
+            }
+        };
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        cache.insert(number.to_string(), (Instant::now(), lrn.clone()));
+        result(lrn)
+    }
+
+    /// Destination `user` rewritten for a dip result, or `None` to leave it
+    pub fn rewrite(&self, user: &str, result: &DipResult) -> Option<String> {
+        let number = user.split(';').next().unwrap_or_default();
+        match (self.config.rewrite, result) {
+            (LnpRewrite::Npdi, DipResult::NotPorted) => Some(format!("{};npdi", number)),
+            (LnpRewrite::Npdi, DipResult::Ported(lrn)) => {
+                Some(format!("{};npdi;rn={}", number, lrn))
+            }
+            (LnpRewrite::Replace, DipResult::Ported(lrn)) => Some(lrn.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn result(lrn: Option<String>) -> DipResult {
+    match lrn {
+        Some(lrn) => DipResult::Ported(lrn),
+        None => DipResult::NotPorted,
+    }
+}
+
+/// Whether a destination says it has been dipped already
+fn is_dipped(user: &str) -> bool {
+    user.split(';')
+        .skip(1)
+        .any(|param| param.eq_ignore_ascii_case("npdi"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn config(provider: LnpProvider) -> LnpConfig {
+        LnpConfig {
+            provider,
+            trunks: vec!["carrier.example.com".to_string()],
+            timeout_ms: default_timeout_ms(),
+            cache_seconds: default_cache_seconds(),
+            on_failure: LnpFailure::default(),
+            rewrite: LnpRewrite::default(),
+        }
+    }
+
+    /// Answer every request with `status` and `body`, counting requests
+    async fn serve(status: &'static str, body: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/lrn/{{number}}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    fn http(url: String) -> LnpProvider {
+        LnpProvider::Http {
+            url,
+            headers: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dip_and_cache() {
+        let (url, requests) = serve("200 OK", r#"{"lrn":"+12125550000"}"#).await;
+        let dip = PortabilityDip::new(config(http(url)));
+        let trunk = "carrier.example.com";
+
+        let result = dip.dip(trunk, "+12125551234").await;
+        assert_eq!(result, DipResult::Ported("+12125550000".to_string()));
+        assert_eq!(
+            dip.rewrite("+12125551234", &result).as_deref(),
+            Some("+12125551234;npdi;rn=+12125550000")
+        );
+        assert_eq!(
+            routing_number("+12125551234;npdi;rn=+12125550000"),
+            "+12125550000"
+        );
+        dip.dip(trunk, "+12125551234").await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Other trunks and numbers dipped upstream are left alone
+        assert_eq!(
+            dip.dip("pbx.example.com", "+12125559999").await,
+            DipResult::Skipped
+        );
+        assert_eq!(
+            dip.dip(trunk, "+12125559999;npdi").await,
+            DipResult::Skipped
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_not_ported_and_failures() {
+        let (url, _) = serve("404 Not Found", "").await;
+        let dip = PortabilityDip::new(config(http(url)));
+        let result = dip.dip("carrier.example.com", "+12125551234").await;
+        assert_eq!(result, DipResult::NotPorted);
+        assert_eq!(
+            dip.rewrite("+12125551234", &result).as_deref(),
+            Some("+12125551234;npdi")
+        );
+
+        let (url, requests) = serve("500 Internal Server Error", "").await;
+        let dip = PortabilityDip::new(config(http(url)));
+        for _ in 0..2 {
+            let result = dip.dip("carrier.example.com", "+12125551234").await;
+            assert!(matches!(result, DipResult::Failed(_)));
+            assert_eq!(dip.rewrite("+12125551234", &result), None);
+        }
+        // Errors are retried rather than cached
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_enum() {
+        assert_eq!(
+            enum_domain("+1-212-555-1234", "e164.arpa."),
+            "4.3.2.1.5.5.5.2.1.2.1.e164.arpa"
+        );
+        assert_eq!(
+            routing_param("!^.*$!tel:+12125551234;npdi;rn=+12125550000!").as_deref(),
+            Some("+12125550000")
+        );
+        assert_eq!(routing_param("!^.*$!tel:+12125551234;npdi!"), None);
+    }
+}
@@ -0,0 +1,745 @@
+//! Prompt languages
+//!
+//! Every prompt an IVR speaks has a key, such as `vm-enter-pin`, and text
+//! in each built-in locale (`en`, `fr`, `de`, `es`). A call's locale comes
+//! from its route, else the DID it was dialed on, else its tenant (the SIP
+//! domain it was sent to), else the default locale.
+//!
+//! Recordings of prompts are kept per locale in the prompts directory, as
+//! `<prompts_dir>/<locale>/<key>.wav`, and are played in place of the text
+//! for prompts with nothing filled in. A prompt missing from a locale is
+//! looked for along its fallback chain: the locale, its base language
+//! (`fr-ca` then `fr`), the configured fallbacks, the default locale and
+//! finally English. At each step a recording is preferred to text, but text
+//! in the caller's language is preferred to a recording in another.
+
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+use crate::io;
+
+/// Locale every built-in prompt has text in, tried last
+pub const BUILT_IN_LOCALE: &str = "en";
+
+/// Extension of recorded prompts
+const PROMPT_EXTENSION: &str = "wav";
+
+/// Built-in prompts: key, then text per locale
+///
+/// `{name}` marks where a value is filled in.
+const CATALOG: &[(&str, &[(&str, &str)])] = &[
+    (
+        "vm-enter-mailbox",
+        &[
+            ("en", "Please enter your mailbox number followed by pound"),
+            (
+                "fr",
+                "Veuillez saisir votre numéro de boîte vocale suivi de dièse",
+            ),
+            (
+                "de",
+                "Bitte geben Sie Ihre Mailboxnummer ein, gefolgt von der Raute",
+            ),
+            (
+                "es",
+                "Introduzca su número de buzón seguido de la tecla almohadilla",
+            ),
+        ],
+    ),
+    (
+        "vm-enter-pin",
+        &[
+            ("en", "Please enter your PIN followed by pound"),
+            ("fr", "Veuillez saisir votre code secret suivi de dièse"),
+            ("de", "Bitte geben Sie Ihre PIN ein, gefolgt von der Raute"),
+            ("es", "Introduzca su PIN seguido de la tecla almohadilla"),
+        ],
+    ),
+    (
+        "vm-login-incorrect",
+        &[
+            ("en", "Login incorrect"),
+            ("fr", "Identifiant incorrect"),
+            ("de", "Anmeldung fehlgeschlagen"),
+            ("es", "Acceso incorrecto"),
+        ],
+    ),
+    (
+        "vm-login-incorrect-goodbye",
+        &[
+            ("en", "Login incorrect. Goodbye"),
+            ("fr", "Identifiant incorrect. Au revoir"),
+            ("de", "Anmeldung fehlgeschlagen. Auf Wiederhören"),
+            ("es", "Acceso incorrecto. Adiós"),
+        ],
+    ),
+    (
+        "vm-message-count",
+        &[
+            ("en", "You have {new} new and {saved} saved messages"),
+            (
+                "fr",
+                "Vous avez {new} nouveaux messages et {saved} messages enregistrés",
+            ),
+            (
+                "de",
+                "Sie haben {new} neue und {saved} gespeicherte Nachrichten",
+            ),
+            ("es", "Tiene {new} mensajes nuevos y {saved} guardados"),
+        ],
+    ),
+    (
+        "vm-new-message",
+        &[
+            ("en", "New message {index} from {number}"),
+            ("fr", "Nouveau message {index} de {number}"),
+            ("de", "Neue Nachricht {index} von {number}"),
+            ("es", "Mensaje nuevo {index} de {number}"),
+        ],
+    ),
+    (
+        "vm-saved-message",
+        &[
+            ("en", "Saved message {index} from {number}"),
+            ("fr", "Message enregistré {index} de {number}"),
+            ("de", "Gespeicherte Nachricht {index} von {number}"),
+            ("es", "Mensaje guardado {index} de {number}"),
+        ],
+    ),
+    (
+        "vm-no-previous",
+        &[
+            ("en", "No previous message"),
+            ("fr", "Aucun message précédent"),
+            ("de", "Keine vorherige Nachricht"),
+            ("es", "No hay mensaje anterior"),
+        ],
+    ),
+    (
+        "vm-no-more",
+        &[
+            ("en", "No more messages. Press pound to hang up"),
+            ("fr", "Plus de messages. Appuyez sur dièse pour raccrocher"),
+            (
+                "de",
+                "Keine weiteren Nachrichten. Drücken Sie die Raute, um aufzulegen",
+            ),
+            ("es", "No hay más mensajes. Pulse almohadilla para colgar"),
+        ],
+    ),
+    (
+        "vm-calling",
+        &[
+            ("en", "Calling {number}"),
+            ("fr", "Appel vers {number}"),
+            ("de", "Rufe {number} an"),
+            ("es", "Llamando a {number}"),
+        ],
+    ),
+    (
+        "vm-deleted",
+        &[
+            ("en", "Message deleted"),
+            ("fr", "Message supprimé"),
+            ("de", "Nachricht gelöscht"),
+            ("es", "Mensaje eliminado"),
+        ],
+    ),
+    (
+        "vm-saved",
+        &[
+            ("en", "Message saved"),
+            ("fr", "Message enregistré"),
+            ("de", "Nachricht gespeichert"),
+            ("es", "Mensaje guardado"),
+        ],
+    ),
+    (
+        "vm-goodbye",
+        &[
+            ("en", "Goodbye"),
+            ("fr", "Au revoir"),
+            ("de", "Auf Wiederhören"),
+            ("es", "Adiós"),
+        ],
+    ),
+    (
+        "vm-record-greeting",
+        &[
+            (
+                "en",
+                "Record your greeting after the tone, then press pound. Press star to cancel",
+            ),
+            (
+                "fr",
+                "Enregistrez votre annonce après le bip, puis appuyez sur dièse. \
+                 Appuyez sur étoile pour annuler",
+            ),
+            (
+                "de",
+                "Sprechen Sie Ihre Ansage nach dem Signalton und drücken Sie dann die Raute. \
+                 Drücken Sie den Stern, um abzubrechen",
+            ),
+            (
+                "es",
+                "Grabe su saludo después del tono y pulse almohadilla. \
+                 Pulse asterisco para cancelar",
+            ),
+        ],
+    ),
+    (
+        "vm-greeting-saved",
+        &[
+            ("en", "Greeting saved"),
+            ("fr", "Annonce enregistrée"),
+            ("de", "Ansage gespeichert"),
+            ("es", "Saludo guardado"),
+        ],
+    ),
+    (
+        "vm-greeting-cancelled",
+        &[
+            ("en", "Greeting not changed"),
+            ("fr", "Annonce inchangée"),
+            ("de", "Ansage nicht geändert"),
+            ("es", "Saludo sin cambios"),
+        ],
+    ),
+    (
+        "vm-help",
+        &[
+            (
+                "en",
+                "Press 1 to replay, 3 to call back, 4 for the previous message, \
+                 5 to record your greeting, 6 for the next, 7 to delete, 9 to save, \
+                 or pound to hang up",
+            ),
+            (
+                "fr",
+                "Appuyez sur 1 pour réécouter, 3 pour rappeler, 4 pour le message précédent, \
+                 5 pour enregistrer votre annonce, 6 pour le suivant, 7 pour supprimer, 9 pour enregistrer, ou dièse pour raccrocher",
+            ),
+            (
+                "de",
+                "Drücken Sie 1 zum Wiederholen, 3 zum Zurückrufen, 4 für die vorherige Nachricht, \
+                 5 zum Aufnehmen Ihrer Ansage, 6 für die nächste, 7 zum Löschen, 9 zum Speichern oder die Raute zum Auflegen",
+            ),
+            (
+                "es",
+                "Pulse 1 para repetir, 3 para devolver la llamada, 4 para el mensaje anterior, \
+                 5 para grabar su saludo, 6 para el siguiente, 7 para eliminar, 9 para guardar, o almohadilla para colgar",
+            ),
+        ],
+    ),
+];
+
+/// Locale selection and prompt files
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LocaleConfig {
+    /// Locale of calls nothing else picks one for
+    #[serde(default = "default_locale")]
+    pub default_locale: String,
+    /// Locales tried after a call's own and its base language
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    /// Directory of recorded prompts, one subdirectory per locale
+    #[serde(default = "default_prompts_dir")]
+    pub prompts_dir: String,
+    /// Locale per tenant, by SIP domain
+    #[serde(default)]
+    pub tenants: HashMap<String, String>,
+    /// Locale per DID
+    #[serde(default)]
+    pub dids: HashMap<String, String>,
+    /// Locale per route, by route name
+    #[serde(default)]
+    pub routes: HashMap<String, String>,
+}
+
+fn default_locale() -> String {
+    BUILT_IN_LOCALE.to_string()
+}
+
+fn default_prompts_dir() -> String {
+    "/var/lib/rustalk/prompts".to_string()
+}
+
+impl Default for LocaleConfig {
+    fn default() -> Self {
+        Self {
+            default_locale: default_locale(),
+            fallback: Vec::new(),
+            prompts_dir: default_prompts_dir(),
+            tenants: HashMap::new(),
+            dids: HashMap::new(),
+            routes: HashMap::new(),
+        }
+    }
+}
+
+/// What a call's locale is chosen by
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocaleContext<'a> {
+    /// SIP domain the call was sent to
+    pub tenant: Option<&'a str>,
+    /// Number dialed
+    pub did: Option<&'a str>,
+    /// Name of the route the call took
+    pub route: Option<&'a str>,
+}
+
+impl LocaleConfig {
+    /// Locale of a call: its route's, else its DID's, else its tenant's,
+    /// else the default
+    pub fn select(&self, context: &LocaleContext<'_>) -> String {
+        let configured = context
+            .route
+            .and_then(|route| self.routes.get(route))
+            .or_else(|| context.did.and_then(|did| self.dids.get(did)))
+            .or_else(|| {
+                context
+                    .tenant
+                    .and_then(|tenant| self.tenants.get(&tenant.to_ascii_lowercase()))
+            })
+            .unwrap_or(&self.default_locale);
+        normalize(configured)
+    }
+
+    /// Locales a prompt is looked for in, most specific first
+    pub fn fallback_chain(&self, locale: &str) -> Vec<String> {
+        let mut chain: Vec<String> = Vec::new();
+        let configured = std::iter::once(locale)
+            .chain(self.fallback.iter().map(String::as_str))
+            .chain([self.default_locale.as_str(), BUILT_IN_LOCALE]);
+        for locale in configured {
+            let mut locale = normalize(locale);
+            loop {
+                if !chain.contains(&locale) {
+                    chain.push(locale.clone());
+                }
+                match locale.rfind('-') {
+                    Some(end) => locale.truncate(end),
+                    None => break,
+                }
+            }
+        }
+        chain
+    }
+}
+
+/// Lowercase form of a locale tag, as used in directory names
+pub fn normalize(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+/// Whether a locale tag is well formed, e.g. `fr` or `pt-br`
+pub fn is_valid_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (2..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+/// Keys of every built-in prompt
+pub fn prompt_keys() -> impl Iterator<Item = &'static str> {
+    CATALOG.iter().map(|(key, _)| *key)
+}
+
+/// Whether a prompt key is one of the built-in prompts
+pub fn is_prompt_key(key: &str) -> bool {
+    prompt_keys().any(|k| k == key)
+}
+
+/// Locales with built-in text
+pub fn built_in_locales() -> BTreeSet<&'static str> {
+    CATALOG
+        .iter()
+        .flat_map(|(_, texts)| texts.iter().map(|(locale, _)| *locale))
+        .collect()
+}
+
+fn built_in_text(key: &str, locale: &str) -> Option<&'static str> {
+    CATALOG
+        .iter()
+        .find(|(k, _)| *k == key)?
+        .1
+        .iter()
+        .find(|(l, _)| *l == locale)
+        .map(|(_, text)| *text)
+}
+
+/// A prompt ready to speak in a call's language
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocalizedPrompt {
+    pub key: String,
+    /// Locale the text or recording is in
+    pub locale: String,
+    pub text: String,
+    /// Recording to play instead of speaking the text
+    pub file: Option<PathBuf>,
+}
+
+impl fmt::Display for LocalizedPrompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// A prompt of one locale, as reported by the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PromptInfo {
+    pub key: String,
+    /// Built-in text in this locale, if it has one
+    pub text: Option<String>,
+    pub recorded: bool,
+    /// Locale the prompt is spoken in when this one is selected
+    pub resolved_locale: String,
+}
+
+/// A locale with text or recordings, as reported by the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocaleInfo {
+    pub locale: String,
+    pub built_in: bool,
+    /// Prompts recorded in this locale
+    pub recorded: usize,
+    pub fallback_chain: Vec<String>,
+}
+
+/// Built-in prompt text and recorded prompts, shared by the IVRs and the
+/// API
+#[derive(Debug, Clone)]
+pub struct PromptSet {
+    config: Arc<LocaleConfig>,
+    /// Locale and key of every recording in the prompts directory
+    recorded: Arc<RwLock<BTreeSet<(String, String)>>>,
+}
+
+impl PromptSet {
+    pub fn new(config: LocaleConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            recorded: Arc::new(RwLock::new(BTreeSet::new())),
+        }
+    }
+
+    pub fn config(&self) -> &LocaleConfig {
+        &self.config
+    }
+
+    /// Locale of a call
+    pub fn select(&self, context: &LocaleContext<'_>) -> String {
+        self.config.select(context)
+    }
+
+    /// Find the recordings in the prompts directory, returning how many
+    /// there are
+    pub async fn load(&self) -> Result<usize> {
+        let mut recorded = BTreeSet::new();
+        for dir in io::fs::read_dir(&self.config.prompts_dir).await? {
+            let Some(locale) = dir.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if !is_valid_locale(locale) {
+                continue;
+            }
+            let locale = normalize(locale);
+            for file in io::fs::read_dir(&dir).await? {
+                let key = file
+                    .extension()
+                    .filter(|ext| *ext == PROMPT_EXTENSION)
+                    .and(file.file_stem())
+                    .and_then(|stem| stem.to_str());
+                match key {
+                    Some(key) if is_prompt_key(key) => {
+                        recorded.insert((locale.clone(), key.to_string()));
+                    }
+                    _ => warn!("Ignoring unknown prompt file {}", file.display()),
+                }
+            }
+        }
+        let count = recorded.len();
+        *self.recorded.write().unwrap() = recorded;
+        Ok(count)
+    }
+
+    /// Prompt `key` in `locale`, or the nearest locale along its fallback
+    /// chain, with each `{name}` replaced by its value in `params`
+    ///
+    /// Recordings are only used for prompts with nothing filled in.
+    pub fn render(&self, key: &str, locale: &str, params: &[(&str, &str)]) -> LocalizedPrompt {
+        let recorded = self.recorded.read().unwrap();
+        for locale in self.config.fallback_chain(locale) {
+            let file = (params.is_empty() && recorded.contains(&(locale.clone(), key.to_string())))
+                .then(|| self.path(&locale, key));
+            let text = built_in_text(key, &locale);
+            if file.is_none() && text.is_none() {
+                continue;
+            }
+            let text = text
+                .or_else(|| built_in_text(key, BUILT_IN_LOCALE))
+                .unwrap_or(key);
+            return LocalizedPrompt {
+                key: key.to_string(),
+                locale,
+                text: fill(text, params),
+                file,
+            };
+        }
+        warn!("No text for prompt {}", key);
+        LocalizedPrompt {
+            key: key.to_string(),
+            locale: BUILT_IN_LOCALE.to_string(),
+            text: key.to_string(),
+            file: None,
+        }
+    }
+
+    /// Locales with built-in text or recordings
+    pub fn locales(&self) -> Vec<LocaleInfo> {
+        let recorded = self.recorded.read().unwrap();
+        let mut locales: BTreeSet<String> =
+            built_in_locales().into_iter().map(String::from).collect();
+        locales.extend(recorded.iter().map(|(locale, _)| locale.clone()));
+        locales
+            .into_iter()
+            .map(|locale| LocaleInfo {
+                built_in: built_in_locales().contains(locale.as_str()),
+                recorded: recorded.iter().filter(|(l, _)| *l == locale).count(),
+                fallback_chain: self.config.fallback_chain(&locale),
+                locale,
+            })
+            .collect()
+    }
+
+    /// Every prompt in `locale`, with where it is spoken from
+    pub fn prompts(&self, locale: &str) -> Vec<PromptInfo> {
+        let locale = normalize(locale);
+        prompt_keys()
+            .map(|key| {
+                let recorded = self
+                    .recorded
+                    .read()
+                    .unwrap()
+                    .contains(&(locale.clone(), key.to_string()));
+                PromptInfo {
+                    key: key.to_string(),
+                    text: built_in_text(key, &locale).map(String::from),
+                    recorded,
+                    resolved_locale: self.render(key, &locale, &[]).locale,
+                }
+            })
+            .collect()
+    }
+
+    /// Recording of `key` in `locale`, if there is one
+    pub async fn recording(&self, locale: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let locale = check(locale, key)?;
+        if !self.is_recorded(&locale, key) {
+            return Ok(None);
+        }
+        io::fs::read(self.path(&locale, key)).await.map(Some)
+    }
+
+    /// Store a recording of `key` in `locale`, replacing any there was
+    pub async fn store(&self, locale: &str, key: &str, audio: &[u8]) -> Result<PathBuf> {
+        let locale = check(locale, key)?;
+        if audio.is_empty() {
+            bail!("Recording is empty");
+        }
+        let path = self.path(&locale, key);
+        if let Some(dir) = path.parent() {
+            io::fs::create_dir_all(dir).await?;
+        }
+        io::fs::write(&path, audio).await?;
+        self.recorded
+            .write()
+            .unwrap()
+            .insert((locale, key.to_string()));
+        Ok(path)
+    }
+
+    /// Remove the recording of `key` in `locale`, returning whether there
+    /// was one
+    pub async fn remove(&self, locale: &str, key: &str) -> Result<bool> {
+        let locale = check(locale, key)?;
+        if !self.is_recorded(&locale, key) {
+            return Ok(false);
+        }
+        io::fs::remove_file(self.path(&locale, key)).await?;
+        self.recorded
+            .write()
+            .unwrap()
+            .remove(&(locale, key.to_string()));
+        Ok(true)
+    }
+
+    fn is_recorded(&self, locale: &str, key: &str) -> bool {
+        self.recorded
+            .read()
+            .unwrap()
+            .contains(&(locale.to_string(), key.to_string()))
+    }
+
+    fn path(&self, locale: &str, key: &str) -> PathBuf {
+        Path::new(&self.config.prompts_dir)
+            .join(locale)
+            .join(format!("{}.{}", key, PROMPT_EXTENSION))
+    }
+}
+
+impl Default for PromptSet {
+    fn default() -> Self {
+        Self::new(LocaleConfig::default())
+    }
+}
+
+/// Normalized locale, once the locale and key are known to be safe to use
+/// in a path
+fn check(locale: &str, key: &str) -> Result<String> {
+    let locale = normalize(locale);
+    if !is_valid_locale(&locale) {
+        bail!("Invalid locale: {}", locale);
+    }
+    if !is_prompt_key(key) {
+        bail!("Unknown prompt: {}", key);
+    }
+    Ok(locale)
+}
+
+/// Replace each `{name}` in `text` with its value
+fn fill(text: &str, params: &[(&str, &str)]) -> String {
+    params.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_complete() {
+        // Every prompt can be spoken in every built-in locale
+        for key in prompt_keys() {
+            for locale in built_in_locales() {
+                assert!(
+                    built_in_text(key, locale).is_some(),
+                    "{} has no {} text",
+                    key,
+                    locale
+                );
+            }
+        }
+        assert!(built_in_locales().contains(BUILT_IN_LOCALE));
+    }
+
+    #[test]
+    fn test_select_and_fallback() {
+        let config = LocaleConfig {
+            default_locale: "de".to_string(),
+            fallback: vec!["es".to_string()],
+            tenants: HashMap::from([("acme.example".to_string(), "fr".to_string())]),
+            dids: HashMap::from([("15145550100".to_string(), "fr-CA".to_string())]),
+            routes: HashMap::from([("spain".to_string(), "es".to_string())]),
+            ..LocaleConfig::default()
+        };
+        let context = LocaleContext {
+            tenant: Some("ACME.example"),
+            did: Some("15145550100"),
+            route: Some("spain"),
+        };
+        assert_eq!(config.select(&context), "es");
+        assert_eq!(
+            config.select(&LocaleContext {
+                route: None,
+                ..context
+            }),
+            "fr-ca"
+        );
+        assert_eq!(
+            config.select(&LocaleContext {
+                tenant: Some("acme.example"),
+                ..LocaleContext::default()
+            }),
+            "fr"
+        );
+        assert_eq!(config.select(&LocaleContext::default()), "de");
+
+        assert_eq!(
+            config.fallback_chain("fr-CA"),
+            ["fr-ca", "fr", "es", "de", "en"]
+        );
+        assert_eq!(config.fallback_chain("en"), ["en", "es", "de"]);
+    }
+
+    #[test]
+    fn test_render() {
+        let prompts = PromptSet::default();
+        let prompt = prompts.render("vm-message-count", "fr-CA", &[("new", "2"), ("saved", "1")]);
+        assert_eq!(prompt.locale, "fr");
+        assert_eq!(
+            prompt.text,
+            "Vous avez 2 nouveaux messages et 1 messages enregistrés"
+        );
+        assert_eq!(prompt.file, None);
+
+        // No Japanese text, so English is spoken
+        let prompt = prompts.render("vm-goodbye", "ja", &[]);
+        assert_eq!(prompt.locale, "en");
+        assert_eq!(prompt.text, "Goodbye");
+    }
+
+    #[tokio::test]
+    async fn test_recorded_prompts() {
+        let dir = std::env::temp_dir().join("rustalk_locale_test");
+        io::fs::remove_dir_all(&dir).await.unwrap();
+        let prompts = PromptSet::new(LocaleConfig {
+            prompts_dir: dir.to_string_lossy().into_owned(),
+            ..LocaleConfig::default()
+        });
+        let path = prompts.store("ja", "vm-goodbye", b"RIFF").await.unwrap();
+        assert!(path.ends_with("ja/vm-goodbye.wav"));
+
+        // A Japanese recording is played, with the English text alongside
+        let prompt = prompts.render("vm-goodbye", "ja", &[]);
+        assert_eq!(prompt.locale, "ja");
+        assert_eq!(prompt.file, Some(path));
+        assert_eq!(prompt.text, "Goodbye");
+        // French text wins over a recording in another language
+        assert_eq!(prompts.render("vm-goodbye", "fr", &[]).file, None);
+
+        // Found again by a fresh set
+        let reloaded = PromptSet::new(prompts.config().clone());
+        assert_eq!(reloaded.load().await.unwrap(), 1);
+        let ja = reloaded
+            .locales()
+            .into_iter()
+            .find(|l| l.locale == "ja")
+            .unwrap();
+        assert!(!ja.built_in);
+        assert_eq!(ja.recorded, 1);
+        assert_eq!(
+            reloaded.recording("ja", "vm-goodbye").await.unwrap(),
+            Some(b"RIFF".to_vec())
+        );
+
+        assert!(prompts
+            .store("../etc", "vm-goodbye", b"RIFF")
+            .await
+            .is_err());
+        assert!(prompts.store("ja", "../passwd", b"RIFF").await.is_err());
+        assert!(prompts.remove("ja", "vm-goodbye").await.unwrap());
+        assert!(!prompts.remove("ja", "vm-goodbye").await.unwrap());
+        assert_eq!(prompts.render("vm-goodbye", "ja", &[]).file, None);
+    }
+}
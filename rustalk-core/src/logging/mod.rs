@@ -0,0 +1,250 @@
+//! Log output configuration
+//!
+//! By default the server logs human-readable text to stdout. Production
+//! deployments can add file sinks with size/time based rotation, switch to
+//! JSON lines for log shippers, or forward to a local or remote syslog daemon.
+
+mod rotation;
+mod syslog;
+
+pub use rotation::RotatingFileWriter;
+pub use syslog::{SyslogFacility, SyslogWriter};
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Logging configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LoggingConfig {
+    /// Filter directive used when `RUST_LOG` is not set (e.g. "info",
+    /// "rustalk_core=debug,info")
+    #[serde(default = "default_level")]
+    pub level: String,
+    /// Where log output is written; stdout text when empty
+    #[serde(default)]
+    pub sinks: Vec<LogSink>,
+}
+
+fn default_level() -> String {
+    "info".to_string()
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_level(),
+            sinks: Vec::new(),
+        }
+    }
+}
+
+/// Output line format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// A log output destination
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogSink {
+    /// Standard output
+    Stdout {
+        #[serde(default)]
+        format: LogFormat,
+    },
+    /// A local file, optionally rotated
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        format: LogFormat,
+        #[serde(default)]
+        rotation: RotationPolicy,
+    },
+    /// Syslog over the local socket, or UDP when an address is given
+    Syslog {
+        /// Remote syslog server (host:port); `/dev/log` when unset
+        #[serde(default)]
+        address: Option<String>,
+        #[serde(default)]
+        facility: SyslogFacility,
+        #[serde(default = "default_app_name")]
+        app_name: String,
+    },
+}
+
+fn default_app_name() -> String {
+    "rustalk".to_string()
+}
+
+/// When to rotate a log file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RotationPolicy {
+    /// Rotate once the file reaches this size
+    #[serde(default)]
+    pub max_size_mb: Option<u64>,
+    /// Rotate at the start of each period
+    #[serde(default)]
+    pub interval: Option<RotationInterval>,
+    /// Number of rotated files to keep
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_max_files() -> usize {
+    7
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_size_mb: None,
+            interval: None,
+            max_files: default_max_files(),
+        }
+    }
+}
+
+/// Time based rotation period
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationInterval {
+    Hourly,
+    Daily,
+}
+
+impl RotationInterval {
+    /// Length of the period in seconds
+    pub fn seconds(&self) -> i64 {
+        match self {
+            RotationInterval::Hourly => 3600,
+            RotationInterval::Daily => 86400,
+        }
+    }
+}
+
+/// Install the global tracing subscriber for the configured sinks
+///
+/// `RUST_LOG` still takes precedence over the configured level so operators
+/// can raise verbosity without editing the config file.
+pub fn init(config: &LoggingConfig) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.level))
+        .with_context(|| format!("Invalid log level: {}", config.level))?;
+
+    let default_sinks = [LogSink::Stdout {
+        format: LogFormat::Text,
+    }];
+    let sinks = if config.sinks.is_empty() {
+        &default_sinks[..]
+    } else {
+        &config.sinks[..]
+    };
+
+    let layers = sinks.iter().map(build_layer).collect::<Result<Vec<_>>>()?;
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()
+        .context("Logging is already initialized")
+}
+
+fn build_layer(sink: &LogSink) -> Result<BoxedLayer> {
+    let layer = match sink {
+        LogSink::Stdout { format } => format_layer(*format, std::io::stdout, true),
+        LogSink::File {
+            path,
+            format,
+            rotation,
+        } => {
+            let writer = RotatingFileWriter::open(path, rotation.clone())?;
+            format_layer(*format, Mutex::new(writer), false)
+        }
+        LogSink::Syslog {
+            address,
+            facility,
+            app_name,
+        } => {
+            let writer = SyslogWriter::connect(address.as_deref(), *facility, app_name)?;
+            // Syslog stamps its own time and severity
+            fmt::layer()
+                .with_writer(writer)
+                .with_ansi(false)
+                .without_time()
+                .with_level(false)
+                .boxed()
+        }
+    };
+    Ok(layer)
+}
+
+fn format_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'a> fmt::MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_logging_config() {
+        let config: LoggingConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.level, "info");
+        assert!(config.sinks.is_empty());
+    }
+
+    #[test]
+    fn test_parse_sinks() {
+        let json = r#"{
+            "level": "debug",
+            "sinks": [
+                {"type": "stdout"},
+                {"type": "file", "path": "/var/log/rustalk/rustalk.log", "format": "json",
+                 "rotation": {"max_size_mb": 100, "interval": "daily", "max_files": 14}},
+                {"type": "syslog", "address": "10.0.0.1:514", "facility": "local3"}
+            ]
+        }"#;
+        let config: LoggingConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.sinks.len(), 3);
+
+        match &config.sinks[1] {
+            LogSink::File {
+                format, rotation, ..
+            } => {
+                assert_eq!(*format, LogFormat::Json);
+                assert_eq!(rotation.max_size_mb, Some(100));
+                assert_eq!(rotation.interval, Some(RotationInterval::Daily));
+                assert_eq!(rotation.max_files, 14);
+            }
+            other => panic!("Expected file sink, got {:?}", other),
+        }
+
+        match &config.sinks[2] {
+            LogSink::Syslog {
+                facility, app_name, ..
+            } => {
+                assert_eq!(*facility, SyslogFacility::Local3);
+                assert_eq!(app_name, "rustalk");
+            }
+            other => panic!("Expected syslog sink, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Size and time based log file rotation
+
+use super::RotationPolicy;
+use crate::routing::{matcher::SystemTimeProvider, TimeProvider};
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A log file that rotates itself
+///
+/// Rotated files are numbered like logrotate: `rustalk.log.1` is the most
+/// recent, and files beyond `max_files` are deleted.
+pub struct RotatingFileWriter {
+    path: PathBuf,
+    policy: RotationPolicy,
+    file: File,
+    size: u64,
+    period: Option<i64>,
+    time_provider: Arc<dyn TimeProvider>,
+}
+
+impl RotatingFileWriter {
+    /// Open (or create) a log file, appending to existing content
+    pub fn open(path: impl Into<PathBuf>, policy: RotationPolicy) -> Result<Self> {
+        Self::open_with_time_provider(path, policy, Arc::new(SystemTimeProvider))
+    }
+
+    /// Open a log file with a custom time provider (for testing)
+    pub fn open_with_time_provider(
+        path: impl Into<PathBuf>,
+        policy: RotationPolicy,
+        time_provider: Arc<dyn TimeProvider>,
+    ) -> Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create log directory {}", parent.display()))?;
+        }
+
+        let file = open_append(&path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let period = current_period(&policy, time_provider.as_ref());
+
+        Ok(Self {
+            path,
+            policy,
+            file,
+            size,
+            period,
+            time_provider,
+        })
+    }
+
+    /// Path of the active log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+
+        let size_exceeded = self
+            .policy
+            .max_size_mb
+            .is_some_and(|mb| self.size + incoming as u64 > mb * 1024 * 1024);
+        let period_changed =
+            current_period(&self.policy, self.time_provider.as_ref()) != self.period;
+
+        size_exceeded || period_changed
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.policy.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.policy.max_files));
+            for index in (1..self.policy.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.period = current_period(&self.policy, self.time_provider.as_ref());
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn current_period(policy: &RotationPolicy, time_provider: &dyn TimeProvider) -> Option<i64> {
+    policy
+        .interval
+        .map(|interval| time_provider.now().timestamp() / interval.seconds())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::RotationInterval;
+    use chrono::{DateTime, Duration, Utc};
+    use std::sync::Mutex;
+
+    struct MockTimeProvider {
+        now: Mutex<DateTime<Utc>>,
+    }
+
+    impl TimeProvider for MockTimeProvider {
+        fn now(&self) -> DateTime<Utc> {
+            *self.now.lock().unwrap()
+        }
+    }
+
+    fn temp_log(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustalk_rotation_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("rustalk.log")
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let path = temp_log("size");
+        let policy = RotationPolicy {
+            max_size_mb: Some(1),
+            interval: None,
+            max_files: 2,
+        };
+        let mut writer = RotatingFileWriter::open(&path, policy).unwrap();
+
+        let line = vec![b'x'; 700 * 1024];
+        for _ in 0..4 {
+            writer.write_all(&line).unwrap();
+        }
+
+        // Each 700KB write overflows the 1MB limit, so only the newest two
+        // rotated files survive alongside the active one
+        assert_eq!(fs::metadata(&path).unwrap().len(), 700 * 1024);
+        assert!(writer.rotated_path(1).exists());
+        assert!(writer.rotated_path(2).exists());
+        assert!(!writer.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn test_rotate_by_time() {
+        let path = temp_log("time");
+        let clock = Arc::new(MockTimeProvider {
+            now: Mutex::new("2024-01-15T23:59:00Z".parse().unwrap()),
+        });
+        let policy = RotationPolicy {
+            max_size_mb: None,
+            interval: Some(RotationInterval::Daily),
+            max_files: 7,
+        };
+        let mut writer =
+            RotatingFileWriter::open_with_time_provider(&path, policy, clock.clone()).unwrap();
+
+        writer.write_all(b"before midnight\n").unwrap();
+        writer.write_all(b"still the same day\n").unwrap();
+        assert!(!writer.rotated_path(1).exists());
+
+        *clock.now.lock().unwrap() += Duration::minutes(2);
+        writer.write_all(b"after midnight\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(writer.rotated_path(1)).unwrap(),
+            "before midnight\nstill the same day\n"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after midnight\n");
+    }
+}
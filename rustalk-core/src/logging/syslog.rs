@@ -0,0 +1,232 @@
+//! Syslog (RFC 5424) output
+
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::net::UdpSocket;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+const LOCAL_SOCKET: &str = "/dev/log";
+
+/// Syslog facility
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    User,
+    #[default]
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    fn code(&self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+enum SyslogSocket {
+    #[cfg(unix)]
+    Local(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl SyslogSocket {
+    fn send(&self, data: &[u8]) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            SyslogSocket::Local(socket) => socket.send(data).map(|_| ()),
+            SyslogSocket::Udp(socket) => socket.send(data).map(|_| ()),
+        }
+    }
+}
+
+/// Sends each formatted log event as one syslog message
+#[derive(Clone)]
+pub struct SyslogWriter {
+    socket: Arc<SyslogSocket>,
+    facility: SyslogFacility,
+    app_name: String,
+    hostname: String,
+}
+
+impl SyslogWriter {
+    /// Connect to a remote syslog server over UDP, or to the local syslog
+    /// socket when no address is given
+    pub fn connect(
+        address: Option<&str>,
+        facility: SyslogFacility,
+        app_name: impl Into<String>,
+    ) -> Result<Self> {
+        let socket = match address {
+            Some(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket
+                    .connect(address)
+                    .with_context(|| format!("Failed to connect to syslog at {}", address))?;
+                SyslogSocket::Udp(socket)
+            }
+            None => Self::local_socket()?,
+        };
+
+        Ok(Self {
+            socket: Arc::new(socket),
+            facility,
+            app_name: app_name.into(),
+            hostname: hostname(),
+        })
+    }
+
+    #[cfg(unix)]
+    fn local_socket() -> Result<SyslogSocket> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(LOCAL_SOCKET)
+            .with_context(|| format!("Failed to connect to syslog at {}", LOCAL_SOCKET))?;
+        Ok(SyslogSocket::Local(socket))
+    }
+
+    #[cfg(not(unix))]
+    fn local_socket() -> Result<SyslogSocket> {
+        anyhow::bail!("Local syslog is not supported on this platform; set an address")
+    }
+
+    fn format(&self, severity: u8, message: &str) -> String {
+        format!(
+            "<{}>1 {} {} {} {} - - {}",
+            self.facility.code() * 8 + severity,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            message.trim_end()
+        )
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogWriter {
+    type Writer = SyslogMessage<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogMessage::new(self, Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        SyslogMessage::new(self, *meta.level())
+    }
+}
+
+/// Buffers one formatted event and sends it when dropped
+pub struct SyslogMessage<'a> {
+    writer: &'a SyslogWriter,
+    severity: u8,
+    buf: Vec<u8>,
+}
+
+impl<'a> SyslogMessage<'a> {
+    fn new(writer: &'a SyslogWriter, level: Level) -> Self {
+        let severity = match level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+        Self {
+            writer,
+            severity,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl Write for SyslogMessage<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogMessage<'_> {
+    fn drop(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let message = self
+            .writer
+            .format(self.severity, &String::from_utf8_lossy(&self.buf));
+        // Logging must never take the server down
+        let _ = self.writer.socket.send(message.as_bytes());
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_syslog_udp_message() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        let writer =
+            SyslogWriter::connect(Some(&address), SyslogFacility::Local0, "rustalk").unwrap();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(writer)
+            .with_ansi(false)
+            .without_time()
+            .with_level(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!("trunk carrier1 unreachable");
+        });
+
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+
+        // local0 (16) * 8 + warning (4)
+        assert!(message.starts_with("<132>1 "), "{}", message);
+        assert!(message.contains(" rustalk "));
+        assert!(message.ends_with("trunk carrier1 unreachable"));
+    }
+}
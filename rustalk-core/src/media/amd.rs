@@ -0,0 +1,388 @@
+//! Answering machine detection
+//!
+//! Classifies the first seconds of audio on an answered outbound call from
+//! the pattern of speech and silence. People usually answer with a short
+//! greeting ("Hello?") and then wait; answering machines play a long
+//! greeting of several words, or say nothing while they start up. The
+//! detector works on 20 ms frames of 8 kHz audio, taking a frame whose
+//! average level is below the silence threshold as silence.
+//!
+//! The result is handed on as the `amd` and `amd_cause` call variables, so
+//! the dialer can leave a message on machines and IVR flows can branch on
+//! it.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::g711;
+use super::rtp::RtpPacket;
+use super::wav::SAMPLE_RATE;
+
+/// Samples in each analysed frame (20 ms)
+const FRAME_SAMPLES: usize = 160;
+
+/// Milliseconds in each analysed frame
+const FRAME_MS: u32 = (FRAME_SAMPLES as u32 * 1000) / SAMPLE_RATE;
+
+const PCMU: u8 = 0;
+const PCMA: u8 = 8;
+
+/// Detection thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AmdConfig {
+    /// Silence before anything is said that marks a machine
+    #[serde(default = "default_initial_silence_ms")]
+    pub initial_silence_ms: u32,
+    /// Greeting longer than this is a machine's
+    #[serde(default = "default_greeting_ms")]
+    pub greeting_ms: u32,
+    /// Silence after a short greeting that marks a person waiting for a reply
+    #[serde(default = "default_after_greeting_silence_ms")]
+    pub after_greeting_silence_ms: u32,
+    /// Speech shorter than this is noise, not a word
+    #[serde(default = "default_min_word_ms")]
+    pub min_word_ms: u32,
+    /// Silence that separates two words
+    #[serde(default = "default_between_words_silence_ms")]
+    pub between_words_silence_ms: u32,
+    /// Words in a greeting that mark a machine
+    #[serde(default = "default_max_words")]
+    pub max_words: u32,
+    /// Audio analysed before giving up undecided
+    #[serde(default = "default_total_analysis_ms")]
+    pub total_analysis_ms: u32,
+    /// Average absolute sample level below which a frame is silence
+    #[serde(default = "default_silence_threshold")]
+    pub silence_threshold: u16,
+}
+
+impl Default for AmdConfig {
+    fn default() -> Self {
+        Self {
+            initial_silence_ms: default_initial_silence_ms(),
+            greeting_ms: default_greeting_ms(),
+            after_greeting_silence_ms: default_after_greeting_silence_ms(),
+            min_word_ms: default_min_word_ms(),
+            between_words_silence_ms: default_between_words_silence_ms(),
+            max_words: default_max_words(),
+            total_analysis_ms: default_total_analysis_ms(),
+            silence_threshold: default_silence_threshold(),
+        }
+    }
+}
+
+fn default_initial_silence_ms() -> u32 {
+    2500
+}
+
+fn default_greeting_ms() -> u32 {
+    1500
+}
+
+fn default_after_greeting_silence_ms() -> u32 {
+    800
+}
+
+fn default_min_word_ms() -> u32 {
+    100
+}
+
+fn default_between_words_silence_ms() -> u32 {
+    50
+}
+
+fn default_max_words() -> u32 {
+    4
+}
+
+fn default_total_analysis_ms() -> u32 {
+    5000
+}
+
+fn default_silence_threshold() -> u16 {
+    256
+}
+
+/// Who answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmdVerdict {
+    Human,
+    Machine,
+    /// Analysis ran out of time without a clear pattern
+    NotSure,
+}
+
+impl AmdVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AmdVerdict::Human => "human",
+            AmdVerdict::Machine => "machine",
+            AmdVerdict::NotSure => "notsure",
+        }
+    }
+}
+
+/// What the verdict was based on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmdCause {
+    /// Nothing was said for `initial_silence_ms`
+    InitialSilence,
+    /// The greeting ran past `greeting_ms`
+    LongGreeting,
+    /// The greeting reached `max_words`
+    MaxWords,
+    /// A short greeting followed by silence
+    HumanSilence,
+    /// `total_analysis_ms` passed
+    TooLong,
+}
+
+impl AmdCause {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AmdCause::InitialSilence => "initial_silence",
+            AmdCause::LongGreeting => "long_greeting",
+            AmdCause::MaxWords => "max_words",
+            AmdCause::HumanSilence => "human_silence",
+            AmdCause::TooLong => "too_long",
+        }
+    }
+}
+
+/// Outcome of answering machine detection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AmdResult {
+    pub verdict: AmdVerdict,
+    pub cause: AmdCause,
+    /// Audio analysed before the verdict
+    pub elapsed_ms: u32,
+}
+
+impl AmdResult {
+    /// The `amd` and `amd_cause` call variables
+    pub fn variables(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("amd".to_string(), self.verdict.as_str().to_string()),
+            ("amd_cause".to_string(), self.cause.as_str().to_string()),
+        ])
+    }
+}
+
+/// Answering machine detection for one call
+#[derive(Debug, Clone)]
+pub struct AmdDetector {
+    config: AmdConfig,
+    /// Samples not yet making up a whole frame
+    pending: Vec<i16>,
+    elapsed_ms: u32,
+    /// Whether anything has been said yet
+    greeting_started: bool,
+    /// Speech heard since the greeting started
+    greeting_ms: u32,
+    voice_ms: u32,
+    silence_ms: u32,
+    /// Whether the current run of speech has been counted as a word
+    in_word: bool,
+    words: u32,
+    result: Option<AmdResult>,
+}
+
+impl AmdDetector {
+    pub fn new(config: AmdConfig) -> Self {
+        Self {
+            config,
+            pending: Vec::with_capacity(FRAME_SAMPLES),
+            elapsed_ms: 0,
+            greeting_started: false,
+            greeting_ms: 0,
+            voice_ms: 0,
+            silence_ms: 0,
+            in_word: false,
+            words: 0,
+            result: None,
+        }
+    }
+
+    /// The verdict, once there is one
+    pub fn result(&self) -> Option<AmdResult> {
+        self.result
+    }
+
+    /// Analyse 8 kHz linear samples; returns the verdict once reached
+    pub fn feed(&mut self, samples: &[i16]) -> Option<AmdResult> {
+        for &sample in samples {
+            if self.result.is_some() {
+                break;
+            }
+            self.pending.push(sample);
+            if self.pending.len() == FRAME_SAMPLES {
+                let frame = std::mem::take(&mut self.pending);
+                self.frame(&frame);
+                self.pending = frame;
+                self.pending.clear();
+            }
+        }
+        self.result
+    }
+
+    /// Analyse the audio in a G.711 RTP packet; packets of other payload
+    /// types, such as telephone events, are ignored
+    pub fn feed_rtp(&mut self, packet: &RtpPacket) -> Option<AmdResult> {
+        let decode: fn(u8) -> i16 = match packet.payload_type {
+            PCMU => g711::ulaw_to_linear,
+            PCMA => g711::alaw_to_linear,
+            _ => return self.result,
+        };
+        let samples: Vec<i16> = packet.payload.iter().map(|b| decode(*b)).collect();
+        self.feed(&samples)
+    }
+
+    fn frame(&mut self, frame: &[i16]) {
+        let level = frame.iter().map(|s| s.unsigned_abs() as u32).sum::<u32>() / frame.len() as u32;
+        self.elapsed_ms += FRAME_MS;
+        let config = self.config;
+
+        if level < config.silence_threshold as u32 {
+            self.silence_ms += FRAME_MS;
+            if self.silence_ms >= config.between_words_silence_ms {
+                self.voice_ms = 0;
+                self.in_word = false;
+            }
+            if !self.greeting_started && self.silence_ms >= config.initial_silence_ms {
+                return self.decide(AmdVerdict::Machine, AmdCause::InitialSilence);
+            }
+            if self.greeting_started && self.silence_ms >= config.after_greeting_silence_ms {
+                return self.decide(AmdVerdict::Human, AmdCause::HumanSilence);
+            }
+        } else {
+            self.silence_ms = 0;
+            self.voice_ms += FRAME_MS;
+            if self.greeting_started {
+                self.greeting_ms += FRAME_MS;
+                if self.greeting_ms > config.greeting_ms {
+                    return self.decide(AmdVerdict::Machine, AmdCause::LongGreeting);
+                }
+            }
+            if self.voice_ms >= config.min_word_ms {
+                if !self.greeting_started {
+                    // The word's first frames were part of the greeting too
+                    self.greeting_started = true;
+                    self.greeting_ms = self.voice_ms;
+                }
+                if !self.in_word {
+                    self.in_word = true;
+                    self.words += 1;
+                    if self.words >= config.max_words {
+                        return self.decide(AmdVerdict::Machine, AmdCause::MaxWords);
+                    }
+                }
+            }
+        }
+
+        if self.elapsed_ms >= config.total_analysis_ms {
+            self.decide(AmdVerdict::NotSure, AmdCause::TooLong);
+        }
+    }
+
+    fn decide(&mut self, verdict: AmdVerdict, cause: AmdCause) {
+        self.result = Some(AmdResult {
+            verdict,
+            cause,
+            elapsed_ms: self.elapsed_ms,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ms` of speech-level audio or silence
+    fn audio(ms: u32, speech: bool) -> Vec<i16> {
+        let samples = (ms * SAMPLE_RATE / 1000) as usize;
+        (0..samples)
+            .map(|i| match (speech, i % 2) {
+                (false, _) => 0,
+                (true, 0) => 4000,
+                (true, _) => -4000,
+            })
+            .collect()
+    }
+
+    fn detect(parts: &[(u32, bool)]) -> Option<AmdResult> {
+        let mut detector = AmdDetector::new(AmdConfig::default());
+        for (ms, speech) in parts {
+            if let Some(result) = detector.feed(&audio(*ms, *speech)) {
+                return Some(result);
+            }
+        }
+        detector.result()
+    }
+
+    #[test]
+    fn test_short_greeting_is_human() {
+        let result = detect(&[(300, false), (500, true), (1000, false)]).unwrap();
+        assert_eq!(result.verdict, AmdVerdict::Human);
+        assert_eq!(result.cause, AmdCause::HumanSilence);
+        assert_eq!(result.elapsed_ms, 1600);
+        assert_eq!(result.variables()["amd"], "human");
+    }
+
+    #[test]
+    fn test_machine_patterns() {
+        // Long uninterrupted greeting
+        let result = detect(&[(200, false), (3000, true)]).unwrap();
+        assert_eq!(
+            (result.verdict, result.cause),
+            (AmdVerdict::Machine, AmdCause::LongGreeting)
+        );
+
+        // Several words with short pauses
+        let words: Vec<(u32, bool)> = (0..4).flat_map(|_| [(200, true), (100, false)]).collect();
+        let result = detect(&words).unwrap();
+        assert_eq!(
+            (result.verdict, result.cause),
+            (AmdVerdict::Machine, AmdCause::MaxWords)
+        );
+
+        // Nothing said at all
+        let result = detect(&[(3000, false)]).unwrap();
+        assert_eq!(
+            (result.verdict, result.cause),
+            (AmdVerdict::Machine, AmdCause::InitialSilence)
+        );
+        assert_eq!(result.variables()["amd_cause"], "initial_silence");
+    }
+
+    #[test]
+    fn test_rtp_and_noise() {
+        let mut detector = AmdDetector::new(AmdConfig {
+            initial_silence_ms: 10_000,
+            total_analysis_ms: 1000,
+            ..Default::default()
+        });
+        // Clicks too short to be words, arriving over RTP
+        let click: Vec<u8> = audio(20, true)
+            .into_iter()
+            .chain(audio(40, false))
+            .map(g711::linear_to_ulaw)
+            .collect();
+        for _ in 0..20 {
+            detector.feed_rtp(&RtpPacket {
+                payload_type: PCMU,
+                marker: false,
+                sequence: 0,
+                timestamp: 0,
+                ssrc: 1,
+                payload: click.clone(),
+            });
+        }
+        let result = detector.result().unwrap();
+        assert_eq!(result.verdict, AmdVerdict::NotSure);
+        assert_eq!(result.cause, AmdCause::TooLong);
+        assert_eq!(result.elapsed_ms, 1000);
+    }
+}
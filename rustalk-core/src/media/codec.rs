@@ -1,10 +1,11 @@
 //! Codec definitions and management
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// Audio codec with RTP payload type
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct Codec {
     /// Codec name
     pub name: String,
@@ -118,7 +119,7 @@ pub fn standard_codecs() -> Vec<Codec> {
 }
 
 /// Codec configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CodecConfig {
     /// List of available codecs
     pub codecs: Vec<Codec>,
@@ -314,7 +315,7 @@ mod tests {
     fn test_codec_config_default() {
         let config = CodecConfig::default();
         assert!(!config.codecs.is_empty());
-        assert!(config.enabled_codecs().len() > 0);
+        assert!(!config.enabled_codecs().is_empty());
     }
 
     #[test]
@@ -0,0 +1,119 @@
+//! DTMF digits carried in RTP as telephone events (RFC 4733)
+//!
+//! A key press is sent as a run of packets sharing one RTP timestamp, the
+//! last few marked as the end of the event. The detector reports each
+//! press once, when its first packet arrives, however many packets or
+//! retransmitted ends follow.
+
+use super::rtp::RtpPacket;
+
+/// Payload type offered for telephone events when the other side names none
+pub const DEFAULT_PAYLOAD_TYPE: u8 = 101;
+
+/// Events 0-15 are the DTMF keys
+const DIGITS: &[u8; 16] = b"0123456789*#ABCD";
+
+/// One telephone-event payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelephoneEvent {
+    pub event: u8,
+    pub end: bool,
+    pub volume: u8,
+    pub duration: u16,
+}
+
+impl TelephoneEvent {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let bytes: [u8; 4] = payload.get(..4)?.try_into().ok()?;
+        Some(Self {
+            event: bytes[0],
+            end: bytes[1] & 0x80 != 0,
+            volume: bytes[1] & 0x3f,
+            duration: u16::from_be_bytes([bytes[2], bytes[3]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let duration = self.duration.to_be_bytes();
+        [
+            self.event,
+            (self.volume & 0x3f) | if self.end { 0x80 } else { 0 },
+            duration[0],
+            duration[1],
+        ]
+    }
+
+    /// Key pressed, if the event is a DTMF key
+    pub fn digit(&self) -> Option<char> {
+        DIGITS.get(self.event as usize).map(|d| *d as char)
+    }
+}
+
+/// Reports each key press in a stream of RTP packets once
+#[derive(Debug, Clone)]
+pub struct DtmfDetector {
+    payload_type: u8,
+    /// Timestamp of the last event reported
+    last: Option<u32>,
+}
+
+impl DtmfDetector {
+    pub fn new(payload_type: u8) -> Self {
+        Self {
+            payload_type,
+            last: None,
+        }
+    }
+
+    /// Key newly pressed in `packet`, if any
+    pub fn detect(&mut self, packet: &RtpPacket) -> Option<char> {
+        if packet.payload_type != self.payload_type || self.last == Some(packet.timestamp) {
+            return None;
+        }
+        let digit = TelephoneEvent::parse(&packet.payload)?.digit()?;
+        self.last = Some(packet.timestamp);
+        Some(digit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp: u32, event: u8, end: bool) -> RtpPacket {
+        RtpPacket {
+            payload_type: DEFAULT_PAYLOAD_TYPE,
+            marker: !end,
+            sequence: 0,
+            timestamp,
+            ssrc: 1,
+            payload: TelephoneEvent {
+                event,
+                end,
+                volume: 10,
+                duration: 800,
+            }
+            .to_bytes()
+            .to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut detector = DtmfDetector::new(DEFAULT_PAYLOAD_TYPE);
+        // Start, continuation and three end packets of one press
+        assert_eq!(detector.detect(&event(1000, 5, false)), Some('5'));
+        assert_eq!(detector.detect(&event(1000, 5, false)), None);
+        for _ in 0..3 {
+            assert_eq!(detector.detect(&event(1000, 5, true)), None);
+        }
+        // The same key again is a new press
+        assert_eq!(detector.detect(&event(2000, 5, true)), Some('5'));
+        assert_eq!(detector.detect(&event(3000, 11, false)), Some('#'));
+        // Flash and audio are not keys
+        assert_eq!(detector.detect(&event(4000, 16, false)), None);
+        let mut audio = event(5000, 1, false);
+        audio.payload_type = 0;
+        assert_eq!(detector.detect(&audio), None);
+    }
+}
@@ -0,0 +1,97 @@
+//! G.711 µ-law (PCMU) and A-law (PCMA) sample conversion
+
+const BIAS: i32 = 0x84;
+const CLIP: i32 = 32635;
+
+/// Encode a linear sample as µ-law
+pub fn linear_to_ulaw(sample: i16) -> u8 {
+    let mut value = sample as i32;
+    let sign = if value < 0 {
+        value = -value;
+        0x80
+    } else {
+        0
+    };
+    value = value.min(CLIP) + BIAS;
+    // Position of the highest set bit above the 8 the mantissa starts at
+    let exponent = (7 - (value << 17).leading_zeros().min(7)) as i32;
+    let mantissa = (value >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+/// Decode a µ-law sample
+pub fn ulaw_to_linear(byte: u8) -> i16 {
+    let byte = !byte;
+    let exponent = ((byte >> 4) & 0x07) as i32;
+    let mantissa = (byte & 0x0f) as i32;
+    let magnitude = (((mantissa << 3) + BIAS) << exponent) - BIAS;
+    if byte & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// Encode a linear sample as A-law
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    let (sign, magnitude) = if sample >= 0 {
+        (0x80u8, (sample as i32) >> 3)
+    } else {
+        (0x00u8, (-(sample as i32) - 1) >> 3)
+    };
+    let magnitude = magnitude.min(0x0fff);
+    let byte = if magnitude < 0x20 {
+        (magnitude >> 1) as u8
+    } else {
+        let exponent = (31 - (magnitude as u32).leading_zeros() - 4) as i32;
+        let mantissa = (magnitude >> exponent) & 0x0f;
+        (((exponent) << 4) | mantissa) as u8
+    };
+    (byte | sign) ^ 0x55
+}
+
+/// Decode an A-law sample
+pub fn alaw_to_linear(byte: u8) -> i16 {
+    let byte = byte ^ 0x55;
+    let exponent = ((byte >> 4) & 0x07) as i32;
+    let mantissa = (byte & 0x0f) as i32;
+    let magnitude = if exponent == 0 {
+        (mantissa << 4) + 8
+    } else {
+        ((mantissa << 4) + 0x108) << (exponent - 1)
+    };
+    if byte & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // Known code points
+        assert_eq!(linear_to_ulaw(0), 0xff);
+        assert_eq!(linear_to_alaw(0), 0xd5);
+        assert_eq!(ulaw_to_linear(0xff), 0);
+
+        // Companding keeps samples within a few percent
+        for sample in [-32000i16, -8000, -1000, -100, 100, 1000, 8000, 32000] {
+            for decoded in [
+                ulaw_to_linear(linear_to_ulaw(sample)),
+                alaw_to_linear(linear_to_alaw(sample)),
+            ] {
+                let error = (decoded as i32 - sample as i32).abs();
+                assert!(
+                    error <= (sample as i32).abs() / 16 + 8,
+                    "{} decoded as {}",
+                    sample,
+                    decoded
+                );
+            }
+        }
+    }
+}
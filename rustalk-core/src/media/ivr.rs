@@ -0,0 +1,384 @@
+//! RTP endpoint for calls answered by an IVR
+//!
+//! The endpoint answers the caller's SDP offer with G.711 and telephone
+//! events on a port of its own. It sends a 20 ms packet every 20 ms, of
+//! queued audio or of silence, reports keys pressed as RFC 4733 events and
+//! keeps what the caller says while recording, which starts once queued
+//! audio has played. The far end is taken from
+//! the offer until the first packet arrives, then from where packets come
+//! from, so callers behind NAT are heard.
+
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tracing::debug;
+
+use super::dtmf::{self, DtmfDetector};
+use super::rtp::RtpPacket;
+use super::sdp::SdpSession;
+use super::{g711, wav};
+use crate::io;
+
+/// Samples in each packet sent
+const FRAME_SAMPLES: usize = 160;
+
+/// Longest recording kept, in samples (two minutes)
+const MAX_RECORDING: usize = 120 * wav::SAMPLE_RATE as usize;
+
+const PCMU: u8 = 0;
+const PCMA: u8 = 8;
+
+#[derive(Debug, Default)]
+struct Audio {
+    queue: VecDeque<i16>,
+    recording: Option<Vec<i16>>,
+    /// Recording starts when the queue empties
+    record_pending: bool,
+}
+
+impl Audio {
+    fn start_pending_recording(&mut self) {
+        if self.record_pending && self.queue.is_empty() {
+            self.record_pending = false;
+            self.recording = Some(Vec::new());
+        }
+    }
+}
+
+/// RTP endpoint of one IVR call
+#[derive(Debug, Clone)]
+pub struct IvrMedia {
+    audio: Arc<Mutex<Audio>>,
+    local_port: u16,
+    payload_type: u8,
+    task: AbortHandle,
+}
+
+impl IvrMedia {
+    /// Answer an SDP offer, advertising `address` as where to send RTP
+    ///
+    /// Returns the endpoint, the SDP answer and the keys pressed.
+    pub async fn answer(
+        offer: &str,
+        address: IpAddr,
+    ) -> Result<(Self, String, mpsc::UnboundedReceiver<char>)> {
+        let session = SdpSession::parse(offer)?;
+        let media = session
+            .media
+            .iter()
+            .find(|m| m.media_type == "audio" && m.port != 0)
+            .context("Offer has no audio")?;
+        if media.protocol != "RTP/AVP" {
+            bail!("Unsupported media protocol {}", media.protocol);
+        }
+        let Some(payload_type) = media
+            .formats
+            .iter()
+            .copied()
+            .find(|pt| *pt == PCMU || *pt == PCMA)
+        else {
+            bail!("Offer has neither PCMU nor PCMA");
+        };
+        let events = telephone_event_type(offer).filter(|pt| media.formats.contains(pt));
+        let remote_ip = session
+            .connection
+            .as_deref()
+            .and_then(|c| c.split_whitespace().nth(2))
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .context("Offer has no connection address")?;
+        let remote = SocketAddr::new(remote_ip, media.port);
+
+        let unspecified = match address {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+        let local_port = socket.local_addr()?.port();
+
+        let audio = Arc::new(Mutex::new(Audio::default()));
+        let (digits_tx, digits_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run(
+            socket,
+            remote,
+            payload_type,
+            DtmfDetector::new(events.unwrap_or(dtmf::DEFAULT_PAYLOAD_TYPE)),
+            audio.clone(),
+            digits_tx,
+        ))
+        .abort_handle();
+
+        let answer = answer_sdp(address, local_port, payload_type, events);
+        let media = Self {
+            audio,
+            local_port,
+            payload_type,
+            task,
+        };
+        Ok((media, answer, digits_rx))
+    }
+
+    /// Port RTP is received on
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// Payload type audio is sent with
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    /// Queue samples to play after what is already queued
+    pub fn play(&self, samples: &[i16]) {
+        self.audio.lock().unwrap().queue.extend(samples);
+    }
+
+    /// Queue a WAV file to play
+    pub async fn play_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let data = io::fs::read(path.as_ref()).await?;
+        let samples = wav::decode(&data)
+            .with_context(|| format!("Failed to decode {}", path.as_ref().display()))?;
+        self.play(&samples);
+        Ok(())
+    }
+
+    /// Drop whatever is queued, as when the caller presses a key
+    pub fn stop_playback(&self) {
+        self.audio.lock().unwrap().queue.clear();
+    }
+
+    /// Whether queued audio is still playing
+    pub fn is_playing(&self) -> bool {
+        !self.audio.lock().unwrap().queue.is_empty()
+    }
+
+    /// Start keeping what the caller says once queued audio has played,
+    /// discarding any earlier recording
+    pub fn start_recording(&self) {
+        let mut audio = self.audio.lock().unwrap();
+        audio.recording = None;
+        audio.record_pending = true;
+        audio.start_pending_recording();
+    }
+
+    /// Stop recording, returning what was said
+    pub fn stop_recording(&self) -> Option<Vec<i16>> {
+        let mut audio = self.audio.lock().unwrap();
+        audio.record_pending = false;
+        audio.recording.take()
+    }
+
+    /// Stop sending and receiving
+    pub fn close(&self) {
+        self.task.abort();
+    }
+}
+
+/// Half a second of 1 kHz tone, played before recording
+pub fn beep() -> Vec<i16> {
+    (0..wav::SAMPLE_RATE / 2)
+        .map(|n| {
+            let phase = 2.0 * std::f32::consts::PI * 1000.0 * n as f32 / wav::SAMPLE_RATE as f32;
+            (phase.sin() * 8000.0) as i16
+        })
+        .collect()
+}
+
+/// Payload type the offer maps to `telephone-event/8000`
+fn telephone_event_type(offer: &str) -> Option<u8> {
+    offer.lines().find_map(|line| {
+        let (pt, encoding) = line.trim().strip_prefix("a=rtpmap:")?.split_once(' ')?;
+        encoding
+            .eq_ignore_ascii_case("telephone-event/8000")
+            .then(|| pt.parse().ok())
+            .flatten()
+    })
+}
+
+fn answer_sdp(address: IpAddr, port: u16, payload_type: u8, events: Option<u8>) -> String {
+    let family = if address.is_ipv6() { "IP6" } else { "IP4" };
+    let session: u32 = rand::thread_rng().gen();
+    let codec = if payload_type == PCMU { "PCMU" } else { "PCMA" };
+    let mut sdp = format!(
+        "v=0\r\no=rustalk {session} {session} IN {family} {address}\r\ns=rustalk\r\n\
+         c=IN {family} {address}\r\nt=0 0\r\nm=audio {port} RTP/AVP {payload_type}"
+    );
+    if let Some(events) = events {
+        sdp.push_str(&format!(" {}", events));
+    }
+    sdp.push_str(&format!("\r\na=rtpmap:{} {}/8000\r\n", payload_type, codec));
+    if let Some(events) = events {
+        sdp.push_str(&format!(
+            "a=rtpmap:{0} telephone-event/8000\r\na=fmtp:{0} 0-15\r\n",
+            events
+        ));
+    }
+    sdp.push_str("a=ptime:20\r\na=sendrecv\r\n");
+    sdp
+}
+
+async fn run(
+    socket: UdpSocket,
+    mut remote: SocketAddr,
+    payload_type: u8,
+    mut detector: DtmfDetector,
+    audio: Arc<Mutex<Audio>>,
+    digits: mpsc::UnboundedSender<char>,
+) {
+    let mut packet = {
+        let mut rng = rand::thread_rng();
+        RtpPacket {
+            payload_type,
+            marker: true,
+            sequence: rng.gen(),
+            timestamp: rng.gen(),
+            ssrc: rng.gen(),
+            payload: Vec::with_capacity(FRAME_SAMPLES),
+        }
+    };
+    let mut ticker = tokio::time::interval(Duration::from_millis(20));
+    let mut buf = [0u8; 2048];
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                packet.payload.clear();
+                {
+                    let mut audio = audio.lock().unwrap();
+                    let take = audio.queue.len().min(FRAME_SAMPLES);
+                    let samples = audio
+                        .queue
+                        .drain(..take)
+                        .chain(std::iter::repeat(0))
+                        .take(FRAME_SAMPLES);
+                    packet.payload.extend(samples.map(|sample| match payload_type {
+                        PCMU => g711::linear_to_ulaw(sample),
+                        _ => g711::linear_to_alaw(sample),
+                    }));
+                    audio.start_pending_recording();
+                }
+                if let Err(e) = socket.send_to(&packet.to_bytes(), remote).await {
+                    debug!("Failed to send RTP to {}: {}", remote, e);
+                }
+                packet.marker = false;
+                packet.sequence = packet.sequence.wrapping_add(1);
+                packet.timestamp = packet.timestamp.wrapping_add(FRAME_SAMPLES as u32);
+            }
+            received = socket.recv_from(&mut buf) => {
+                let Ok((len, from)) = received else {
+                    continue;
+                };
+                let Some(incoming) = RtpPacket::parse(&buf[..len]) else {
+                    continue;
+                };
+                remote = from;
+                if let Some(digit) = detector.detect(&incoming) {
+                    if digits.send(digit).is_err() {
+                        return;
+                    }
+                } else if incoming.payload_type == payload_type {
+                    let mut audio = audio.lock().unwrap();
+                    if let Some(recording) = audio
+                        .recording
+                        .as_mut()
+                        .filter(|r| r.len() < MAX_RECORDING)
+                    {
+                        recording.extend(incoming.payload.iter().map(|b| match payload_type {
+                            PCMU => g711::ulaw_to_linear(*b),
+                            _ => g711::alaw_to_linear(*b),
+                        }));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::dtmf::TelephoneEvent;
+
+    #[tokio::test]
+    async fn test_play_record_and_keys() {
+        let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let offer = format!(
+            "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+             m=audio {} RTP/AVP 8 96\r\na=rtpmap:96 telephone-event/8000\r\n",
+            phone.local_addr().unwrap().port()
+        );
+        let (media, answer, mut digits) = IvrMedia::answer(&offer, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(media.payload_type(), PCMA);
+        assert!(answer.contains(&format!("m=audio {} RTP/AVP 8 96", media.local_port())));
+        assert!(answer.contains("a=rtpmap:96 telephone-event/8000"));
+
+        // Queued audio goes out in 20 ms packets, after any silence sent
+        // before it was queued
+        media.play(&[1000; 400]);
+        let mut buf = [0u8; 2048];
+        let from = loop {
+            let (len, from) = phone.recv_from(&mut buf).await.unwrap();
+            let packet = RtpPacket::parse(&buf[..len]).unwrap();
+            assert_eq!(packet.payload_type, PCMA);
+            assert_eq!(packet.payload.len(), FRAME_SAMPLES);
+            if packet.payload[0] == g711::linear_to_alaw(1000) {
+                break from;
+            }
+        };
+        media.stop_playback();
+        assert!(!media.is_playing());
+
+        // What the caller says is kept while recording
+        media.start_recording();
+        let mut outgoing = RtpPacket {
+            payload_type: PCMA,
+            marker: false,
+            sequence: 1,
+            timestamp: 160,
+            ssrc: 7,
+            payload: vec![g711::linear_to_alaw(-2000); FRAME_SAMPLES],
+        };
+        let to = SocketAddr::new(from.ip(), media.local_port());
+        phone.send_to(&outgoing.to_bytes(), to).await.unwrap();
+
+        // Then a key, sent as a telephone event
+        outgoing.payload_type = 96;
+        outgoing.payload = TelephoneEvent {
+            event: 11,
+            end: true,
+            volume: 10,
+            duration: 800,
+        }
+        .to_bytes()
+        .to_vec();
+        phone.send_to(&outgoing.to_bytes(), to).await.unwrap();
+        assert_eq!(digits.recv().await, Some('#'));
+
+        let recording = media.stop_recording().unwrap();
+        assert_eq!(recording.len(), FRAME_SAMPLES);
+        assert_eq!(
+            recording[0],
+            g711::alaw_to_linear(g711::linear_to_alaw(-2000))
+        );
+        media.close();
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_offer() {
+        let offer = "v=0\r\nc=IN IP4 127.0.0.1\r\nm=audio 4000 RTP/AVP 9\r\n";
+        assert!(IvrMedia::answer(offer, "127.0.0.1".parse().unwrap())
+            .await
+            .is_err());
+        let offer = "v=0\r\nc=IN IP4 127.0.0.1\r\nm=audio 4000 RTP/SAVP 0\r\n";
+        assert!(IvrMedia::answer(offer, "127.0.0.1".parse().unwrap())
+            .await
+            .is_err());
+    }
+}
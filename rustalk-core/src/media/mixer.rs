@@ -0,0 +1,199 @@
+//! Announcements mixed into answered calls
+//!
+//! Compliance messages ("this call may be recorded") and supervisor
+//! interventions are played over a call without interrupting it: the
+//! announcement is added to the audio each leg receives, packet by packet,
+//! so both parties keep hearing each other underneath. Audio is queued per
+//! call and leg, and whatever forwards the call's RTP calls
+//! [`AnnouncementMixer::mix`] on each G.711 packet heading to a leg.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use super::g711;
+use super::rtp::RtpPacket;
+use super::wav::SAMPLE_RATE;
+use crate::b2bua::LegSide;
+
+const PCMU: u8 = 0;
+const PCMA: u8 = 8;
+
+/// Audio still to play, by Call-ID and leg
+type Queues = HashMap<(String, LegSide), VecDeque<i16>>;
+
+/// An announcement played over a call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Playback {
+    pub id: String,
+    pub call_id: String,
+    /// Legs that hear it
+    pub legs: Vec<LegSide>,
+    pub duration_ms: u64,
+}
+
+impl Playback {
+    pub fn new(call_id: &str, legs: &[LegSide], samples: &[i16]) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            call_id: call_id.to_string(),
+            legs: legs.to_vec(),
+            duration_ms: samples.len() as u64 * 1000 / SAMPLE_RATE as u64,
+        }
+    }
+}
+
+/// Why an announcement cannot be played
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaybackRefusal {
+    /// No active call has the Call-ID
+    NoSuchCall,
+    /// The call has not been answered
+    NotAnswered,
+    /// Nothing carries the call's media to mix into
+    NoMedia,
+}
+
+impl fmt::Display for PlaybackRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PlaybackRefusal::NoSuchCall => "no active call with that Call-ID",
+            PlaybackRefusal::NotAnswered => "call has not been answered",
+            PlaybackRefusal::NoMedia => "call media does not pass through a mixer",
+        })
+    }
+}
+
+/// Audio waiting to be mixed into each call's legs
+#[derive(Debug, Clone, Default)]
+pub struct AnnouncementMixer {
+    queues: Arc<Mutex<Queues>>,
+}
+
+impl AnnouncementMixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue 8 kHz samples for `legs` of a call, after anything already
+    /// queued for them
+    pub fn queue(&self, call_id: &str, legs: &[LegSide], samples: &[i16]) -> Playback {
+        let mut queues = self.queues.lock().unwrap();
+        for leg in legs {
+            queues
+                .entry((call_id.to_string(), *leg))
+                .or_default()
+                .extend(samples);
+        }
+        Playback::new(call_id, legs, samples)
+    }
+
+    /// Mix queued audio into a G.711 packet heading to `leg`, returning
+    /// whether anything was mixed in; other payload types pass untouched
+    pub fn mix(&self, call_id: &str, leg: LegSide, packet: &mut RtpPacket) -> bool {
+        let (decode, encode) = match packet.payload_type {
+            PCMU => (
+                g711::ulaw_to_linear as fn(u8) -> i16,
+                g711::linear_to_ulaw as fn(i16) -> u8,
+            ),
+            PCMA => (
+                g711::alaw_to_linear as fn(u8) -> i16,
+                g711::linear_to_alaw as fn(i16) -> u8,
+            ),
+            _ => return false,
+        };
+        let mut queues = self.queues.lock().unwrap();
+        let key = (call_id.to_string(), leg);
+        let Some(queue) = queues.get_mut(&key) else {
+            return false;
+        };
+        for byte in packet.payload.iter_mut() {
+            let Some(sample) = queue.pop_front() else {
+                break;
+            };
+            *byte = encode(decode(*byte).saturating_add(sample));
+        }
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+        true
+    }
+
+    /// Whether an announcement is still playing to `leg`
+    pub fn is_playing(&self, call_id: &str, leg: LegSide) -> bool {
+        self.queues
+            .lock()
+            .unwrap()
+            .contains_key(&(call_id.to_string(), leg))
+    }
+
+    /// Drop whatever is queued for a call, as when it ends
+    pub fn stop(&self, call_id: &str) -> bool {
+        let mut queues = self.queues.lock().unwrap();
+        let before = queues.len();
+        queues.retain(|(id, _), _| id != call_id);
+        queues.len() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(payload_type: u8, sample: i16) -> RtpPacket {
+        let encode = match payload_type {
+            PCMA => g711::linear_to_alaw,
+            _ => g711::linear_to_ulaw,
+        };
+        RtpPacket {
+            payload_type,
+            marker: false,
+            sequence: 0,
+            timestamp: 0,
+            ssrc: 1,
+            payload: vec![encode(sample); 160],
+        }
+    }
+
+    #[test]
+    fn test_mix_into_legs() {
+        let mixer = AnnouncementMixer::new();
+        let playback = mixer.queue("call1", &[LegSide::A], &[1000; 200]);
+        assert_eq!(playback.duration_ms, 25);
+
+        // The far end is still heard under the announcement
+        let mut first = packet(PCMU, 2000);
+        assert!(mixer.mix("call1", LegSide::A, &mut first));
+        let level = g711::ulaw_to_linear(first.payload[0]);
+        assert!((2900..=3100).contains(&level), "mixed level {}", level);
+
+        // The rest of it, then the untouched remainder of the packet
+        let mut second = packet(PCMA, 0);
+        assert!(mixer.mix("call1", LegSide::A, &mut second));
+        assert!(g711::alaw_to_linear(second.payload[39]) > 900);
+        assert_eq!(second.payload[40], g711::linear_to_alaw(0));
+        assert!(!mixer.is_playing("call1", LegSide::A));
+
+        // Other legs and other calls hear nothing
+        let mut other = packet(PCMU, 0);
+        assert!(!mixer.mix("call1", LegSide::B, &mut other));
+        assert!(!mixer.mix("call2", LegSide::A, &mut other));
+    }
+
+    #[test]
+    fn test_stop_and_other_payloads() {
+        let mixer = AnnouncementMixer::new();
+        mixer.queue("call1", &[LegSide::A, LegSide::B], &[1000; 400]);
+        let mut event = packet(101, 0);
+        let original = event.payload.clone();
+        assert!(!mixer.mix("call1", LegSide::B, &mut event));
+        assert_eq!(event.payload, original);
+
+        assert!(mixer.stop("call1"));
+        assert!(!mixer.is_playing("call1", LegSide::A));
+        assert!(!mixer.is_playing("call1", LegSide::B));
+        assert!(!mixer.stop("call1"));
+    }
+}
@@ -1,14 +1,25 @@
-//! Media handling - SRTP pass-through and SDP manipulation
+//! Media handling - SRTP pass-through, SDP manipulation and WebRTC interop,
+//! plus an RTP endpoint for IVR prompts, recordings and RFC 4733 DTMF,
+//! answering machine detection for outbound calls and announcements mixed
+//! into answered calls
 
 use anyhow::Result;
 
+pub mod amd;
 pub mod codec;
+pub mod dtmf;
+pub mod g711;
+pub mod ivr;
+pub mod mixer;
+pub mod rtp;
 pub mod sdp;
 pub mod srtp;
+pub mod wav;
+pub mod webrtc;
 
 pub use codec::{Codec, CodecConfig};
 pub use sdp::SdpSession;
-pub use srtp::SrtpConfig;
+pub use srtp::{SrtpConfig, SrtpContext};
 
 /// Media session information
 #[derive(Debug, Clone)]
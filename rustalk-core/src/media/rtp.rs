@@ -0,0 +1,85 @@
+//! RTP packets (RFC 3550)
+
+/// Length of an RTP header without CSRCs or extension
+pub const HEADER_LEN: usize = 12;
+
+/// An RTP packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacket {
+    pub payload_type: u8,
+    pub marker: bool,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload: Vec<u8>,
+}
+
+impl RtpPacket {
+    /// Parse a packet, skipping CSRCs, header extension and padding
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_LEN || data[0] >> 6 != 2 {
+            return None;
+        }
+        let padding = data[0] & 0x20 != 0;
+        let extension = data[0] & 0x10 != 0;
+        let csrc_count = (data[0] & 0x0f) as usize;
+        let mut start = HEADER_LEN + csrc_count * 4;
+        if extension {
+            let words = data.get(start + 2..start + 4)?;
+            start += 4 + u16::from_be_bytes([words[0], words[1]]) as usize * 4;
+        }
+        let mut end = data.len();
+        if padding {
+            end = end.checked_sub(*data.last()? as usize)?;
+        }
+        Some(Self {
+            payload_type: data[1] & 0x7f,
+            marker: data[1] & 0x80 != 0,
+            sequence: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes(data[4..8].try_into().ok()?),
+            ssrc: u32::from_be_bytes(data[8..12].try_into().ok()?),
+            payload: data.get(start..end)?.to_vec(),
+        })
+    }
+
+    /// Serialize with a plain 12-byte header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        data.push(0x80);
+        data.push(self.payload_type | if self.marker { 0x80 } else { 0 });
+        data.extend_from_slice(&self.sequence.to_be_bytes());
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data.extend_from_slice(&self.ssrc.to_be_bytes());
+        data.extend_from_slice(&self.payload);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let packet = RtpPacket {
+            payload_type: 0,
+            marker: true,
+            sequence: 65535,
+            timestamp: 160,
+            ssrc: 0x1234_5678,
+            payload: vec![0xff; 160],
+        };
+        let data = packet.to_bytes();
+        assert_eq!(data.len(), HEADER_LEN + 160);
+        assert_eq!(RtpPacket::parse(&data), Some(packet));
+
+        // One CSRC and two bytes of padding
+        let mut data = vec![0xa1, 101, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1];
+        data.extend_from_slice(&[0, 0, 0, 9, 5, 0x80, 0, 0xa0, 0, 2]);
+        let packet = RtpPacket::parse(&data).unwrap();
+        assert_eq!(packet.payload_type, 101);
+        assert_eq!(packet.payload, [5, 0x80, 0, 0xa0]);
+
+        assert_eq!(RtpPacket::parse(&[0x80, 0]), None);
+    }
+}
@@ -1,6 +1,7 @@
 //! SDP (Session Description Protocol) handling
 
 use anyhow::Result;
+use std::fmt;
 
 /// Simplified SDP session representation
 #[derive(Debug, Clone)]
@@ -65,20 +66,42 @@ impl SdpSession {
 
         Ok(session)
     }
+}
 
-    /// Serialize to SDP string
-    pub fn to_string(&self) -> String {
-        let mut sdp = String::new();
+/// Name of the first audio codec an SDP body lists, from its `rtpmap` or
+/// the static payload type; telephone events are not a codec
+pub fn audio_codec(sdp: &str) -> Option<String> {
+    let session = SdpSession::parse(sdp).ok()?;
+    let audio = session.media.iter().find(|m| m.media_type == "audio")?;
+    let rtpmap = |pt: u8| {
+        sdp.lines().find_map(|line| {
+            let (format, encoding) = line.trim().strip_prefix("a=rtpmap:")?.split_once(' ')?;
+            (format.parse() == Ok(pt)).then(|| encoding.split('/').next().unwrap_or_default())
+        })
+    };
+    audio.formats.iter().find_map(|pt| match rtpmap(*pt) {
+        Some(name) if name.eq_ignore_ascii_case("telephone-event") => None,
+        Some(name) => Some(name.to_string()),
+        None if *pt < 96 => super::codec::standard_codecs()
+            .into_iter()
+            .find(|c| c.payload_type == *pt)
+            .map(|c| c.name),
+        None => None,
+    })
+}
 
-        sdp.push_str(&format!("v={}\r\n", self.version));
-        sdp.push_str(&format!("o={}\r\n", self.origin));
-        sdp.push_str(&format!("s={}\r\n", self.session_name));
+impl fmt::Display for SdpSession {
+    /// Serialize to SDP string
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v={}\r\n", self.version)?;
+        write!(f, "o={}\r\n", self.origin)?;
+        write!(f, "s={}\r\n", self.session_name)?;
 
         if let Some(conn) = &self.connection {
-            sdp.push_str(&format!("c={}\r\n", conn));
+            write!(f, "c={}\r\n", conn)?;
         }
 
-        sdp.push_str("t=0 0\r\n");
+        write!(f, "t=0 0\r\n")?;
 
         for media in &self.media {
             let formats = media
@@ -87,13 +110,14 @@ impl SdpSession {
                 .map(|f| f.to_string())
                 .collect::<Vec<_>>()
                 .join(" ");
-            sdp.push_str(&format!(
+            write!(
+                f,
                 "m={} {} {} {}\r\n",
                 media.media_type, media.port, media.protocol, formats
-            ));
+            )?;
         }
 
-        sdp
+        Ok(())
     }
 }
 
@@ -102,3 +126,19 @@ impl Default for SdpSession {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_codec() {
+        let sdp = "v=0\r\nc=IN IP4 192.0.2.1\r\nm=audio 4000 RTP/AVP 101 8 0\r\n\
+                   a=rtpmap:101 telephone-event/8000\r\n";
+        assert_eq!(audio_codec(sdp).as_deref(), Some("PCMA"));
+
+        let sdp = "v=0\r\nm=audio 4000 RTP/AVP 111\r\na=rtpmap:111 opus/48000/2\r\n";
+        assert_eq!(audio_codec(sdp).as_deref(), Some("opus"));
+        assert_eq!(audio_codec("v=0\r\nm=video 4002 RTP/AVP 96\r\n"), None);
+    }
+}
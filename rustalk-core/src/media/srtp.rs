@@ -1,6 +1,16 @@
-//! SRTP (Secure RTP) configuration and pass-through
+//! SRTP (Secure RTP) configuration, pass-through and protection
+//!
+//! SDES-keyed calls are passed through untouched. Legs that terminate SRTP
+//! themselves, such as WebRTC legs keyed over DTLS, use [`SrtpContext`] to
+//! protect and unprotect packets with `AES_CM_128_HMAC_SHA1_80` (RFC 3711).
 
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use std::collections::HashMap;
 
 /// SRTP configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,6 +97,301 @@ impl SrtpPassthrough {
     }
 }
 
+type HmacSha1 = Hmac<Sha1>;
+
+/// Length of an `AES_CM_128_HMAC_SHA1_80` master key
+pub const MASTER_KEY_LEN: usize = 16;
+/// Length of an `AES_CM_128_HMAC_SHA1_80` master salt
+pub const MASTER_SALT_LEN: usize = 14;
+/// Bytes of keying material to export from DTLS for `SRTP_AES128_CM_SHA1_80`
+pub const DTLS_KEYING_MATERIAL_LEN: usize = 2 * (MASTER_KEY_LEN + MASTER_SALT_LEN);
+
+const AUTH_TAG_LEN: usize = 10;
+const SRTCP_INDEX_LEN: usize = 4;
+
+/// Session keys derived from a master key for one direction of RTP or RTCP
+struct SessionKeys {
+    cipher: Aes128,
+    salt: [u8; MASTER_SALT_LEN],
+    auth_key: [u8; 20],
+}
+
+impl SessionKeys {
+    /// Key derivation with a key derivation rate of zero (RFC 3711 4.3)
+    fn derive(
+        master_key: &[u8; MASTER_KEY_LEN],
+        master_salt: &[u8; MASTER_SALT_LEN],
+        labels: [u8; 3],
+    ) -> Self {
+        let master = Aes128::new(master_key.into());
+        let prf = |label: u8, out: &mut [u8]| {
+            let mut iv = [0u8; 16];
+            iv[..MASTER_SALT_LEN].copy_from_slice(master_salt);
+            iv[7] ^= label;
+            keystream(&master, iv, out);
+        };
+
+        let mut key = [0u8; MASTER_KEY_LEN];
+        let mut auth_key = [0u8; 20];
+        let mut salt = [0u8; MASTER_SALT_LEN];
+        prf(labels[0], &mut key);
+        prf(labels[1], &mut auth_key);
+        prf(labels[2], &mut salt);
+        Self {
+            cipher: Aes128::new(&key.into()),
+            salt,
+            auth_key,
+        }
+    }
+
+    /// XOR a payload with the AES-CM keystream for a packet (RFC 3711 4.1.1)
+    fn apply(&self, ssrc: u32, index: u64, payload: &mut [u8]) {
+        let mut iv = [0u8; 16];
+        iv[..MASTER_SALT_LEN].copy_from_slice(&self.salt);
+        for (byte, value) in iv[4..8].iter_mut().zip(ssrc.to_be_bytes()) {
+            *byte ^= value;
+        }
+        for (byte, value) in iv[8..14].iter_mut().zip(&index.to_be_bytes()[2..]) {
+            *byte ^= value;
+        }
+        let mut stream = vec![0u8; payload.len()];
+        keystream(&self.cipher, iv, &mut stream);
+        for (byte, key) in payload.iter_mut().zip(stream) {
+            *byte ^= key;
+        }
+    }
+
+    /// Truncated HMAC-SHA1 over the authenticated portion and trailer
+    fn tag(&self, authenticated: &[u8], trailer: &[u8]) -> [u8; AUTH_TAG_LEN] {
+        let mut mac =
+            <HmacSha1 as Mac>::new_from_slice(&self.auth_key).expect("HMAC accepts any key");
+        mac.update(authenticated);
+        mac.update(trailer);
+        let digest = mac.finalize().into_bytes();
+        let mut tag = [0u8; AUTH_TAG_LEN];
+        tag.copy_from_slice(&digest[..AUTH_TAG_LEN]);
+        tag
+    }
+}
+
+/// AES in counter mode starting at `iv`
+fn keystream(cipher: &Aes128, iv: [u8; 16], out: &mut [u8]) {
+    let mut counter = u128::from_be_bytes(iv);
+    for chunk in out.chunks_mut(16) {
+        let mut block = counter.to_be_bytes().into();
+        cipher.encrypt_block(&mut block);
+        chunk.copy_from_slice(&block[..chunk.len()]);
+        counter = counter.wrapping_add(1);
+    }
+}
+
+/// Rollover counter and highest sequence number seen for one SSRC
+#[derive(Debug, Clone, Copy)]
+struct StreamState {
+    roc: u32,
+    last_seq: u16,
+}
+
+impl StreamState {
+    /// Estimate the rollover counter of a sequence number (RFC 3711 3.3.1)
+    fn estimate(&self, seq: u16) -> u32 {
+        let (last, seq_i) = (self.last_seq as i32, seq as i32);
+        if last < 0x8000 {
+            if seq_i - last > 0x8000 {
+                self.roc.wrapping_sub(1)
+            } else {
+                self.roc
+            }
+        } else if last - 0x8000 > seq_i {
+            self.roc.wrapping_add(1)
+        } else {
+            self.roc
+        }
+    }
+
+    fn update(&mut self, roc: u32, seq: u16) {
+        let index = ((roc as u64) << 16) | seq as u64;
+        if index > ((self.roc as u64) << 16) | self.last_seq as u64 {
+            self.roc = roc;
+            self.last_seq = seq;
+        }
+    }
+}
+
+/// SRTP and SRTCP protection for one direction of a call
+///
+/// Only the `AES_CM_128_HMAC_SHA1_80` profile is supported, with a key
+/// derivation rate of zero and no MKI, which is what DTLS-SRTP negotiates
+/// with browsers. Replayed packets are not detected.
+pub struct SrtpContext {
+    rtp: SessionKeys,
+    rtcp: SessionKeys,
+    streams: HashMap<u32, StreamState>,
+    rtcp_index: u32,
+}
+
+impl SrtpContext {
+    pub fn new(master_key: &[u8; MASTER_KEY_LEN], master_salt: &[u8; MASTER_SALT_LEN]) -> Self {
+        Self {
+            rtp: SessionKeys::derive(master_key, master_salt, [0, 1, 2]),
+            rtcp: SessionKeys::derive(master_key, master_salt, [3, 4, 5]),
+            streams: HashMap::new(),
+            rtcp_index: 0,
+        }
+    }
+
+    /// Contexts for sending and receiving from keying material exported by a
+    /// DTLS handshake (RFC 5764 4.2)
+    ///
+    /// Returns `(outbound, inbound)` for the side that was the DTLS client
+    /// when `is_client` is set, and for the server otherwise.
+    pub fn from_dtls_keying_material(material: &[u8], is_client: bool) -> Result<(Self, Self)> {
+        if material.len() != DTLS_KEYING_MATERIAL_LEN {
+            return Err(anyhow!(
+                "DTLS-SRTP keying material must be {} bytes",
+                DTLS_KEYING_MATERIAL_LEN
+            ));
+        }
+        let (keys, salts) = material.split_at(2 * MASTER_KEY_LEN);
+        let key = |i: usize| -> [u8; MASTER_KEY_LEN] {
+            keys[i * MASTER_KEY_LEN..(i + 1) * MASTER_KEY_LEN]
+                .try_into()
+                .expect("key length")
+        };
+        let salt = |i: usize| -> [u8; MASTER_SALT_LEN] {
+            salts[i * MASTER_SALT_LEN..(i + 1) * MASTER_SALT_LEN]
+                .try_into()
+                .expect("salt length")
+        };
+        let client = Self::new(&key(0), &salt(0));
+        let server = Self::new(&key(1), &salt(1));
+        Ok(if is_client {
+            (client, server)
+        } else {
+            (server, client)
+        })
+    }
+
+    /// Encrypt and authenticate an RTP packet
+    pub fn protect_rtp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let (header_len, seq, ssrc) = rtp_header(packet)?;
+        let state = *self.streams.entry(ssrc).or_insert(StreamState {
+            roc: 0,
+            last_seq: seq,
+        });
+        let roc = state.estimate(seq);
+
+        let mut out = packet.to_vec();
+        self.rtp
+            .apply(ssrc, index(roc, seq), &mut out[header_len..]);
+        let tag = self.rtp.tag(&out, &roc.to_be_bytes());
+        out.extend_from_slice(&tag);
+
+        if let Some(state) = self.streams.get_mut(&ssrc) {
+            state.update(roc, seq);
+        }
+        Ok(out)
+    }
+
+    /// Authenticate and decrypt an SRTP packet
+    pub fn unprotect_rtp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        if packet.len() < AUTH_TAG_LEN {
+            return Err(anyhow!("SRTP packet too short"));
+        }
+        let (body, tag) = packet.split_at(packet.len() - AUTH_TAG_LEN);
+        let (header_len, seq, ssrc) = rtp_header(body)?;
+        let state = self.streams.get(&ssrc).copied().unwrap_or(StreamState {
+            roc: 0,
+            last_seq: seq,
+        });
+        let roc = state.estimate(seq);
+        if !constant_time_eq(&self.rtp.tag(body, &roc.to_be_bytes()), tag) {
+            return Err(anyhow!("SRTP authentication failed"));
+        }
+
+        let mut out = body.to_vec();
+        self.rtp
+            .apply(ssrc, index(roc, seq), &mut out[header_len..]);
+        self.streams.entry(ssrc).or_insert(state).update(roc, seq);
+        Ok(out)
+    }
+
+    /// Encrypt and authenticate an RTCP packet
+    pub fn protect_rtcp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let ssrc = rtcp_ssrc(packet)?;
+        let index = self.rtcp_index;
+        self.rtcp_index = (self.rtcp_index + 1) & 0x7fff_ffff;
+
+        let mut out = packet.to_vec();
+        self.rtcp.apply(ssrc, index as u64, &mut out[8..]);
+        // The E flag marks the payload as encrypted
+        out.extend_from_slice(&(index | 0x8000_0000).to_be_bytes());
+        let tag = self.rtcp.tag(&out, &[]);
+        out.extend_from_slice(&tag);
+        Ok(out)
+    }
+
+    /// Authenticate and decrypt an SRTCP packet
+    pub fn unprotect_rtcp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        if packet.len() < 8 + SRTCP_INDEX_LEN + AUTH_TAG_LEN {
+            return Err(anyhow!("SRTCP packet too short"));
+        }
+        let (authenticated, tag) = packet.split_at(packet.len() - AUTH_TAG_LEN);
+        if !constant_time_eq(&self.rtcp.tag(authenticated, &[]), tag) {
+            return Err(anyhow!("SRTCP authentication failed"));
+        }
+        let (body, trailer) = authenticated.split_at(authenticated.len() - SRTCP_INDEX_LEN);
+        let trailer = u32::from_be_bytes(trailer.try_into().expect("index length"));
+
+        let mut out = body.to_vec();
+        if trailer & 0x8000_0000 != 0 {
+            let ssrc = rtcp_ssrc(&out)?;
+            self.rtcp
+                .apply(ssrc, (trailer & 0x7fff_ffff) as u64, &mut out[8..]);
+        }
+        Ok(out)
+    }
+}
+
+/// 48-bit packet index from a rollover counter and sequence number
+fn index(roc: u32, seq: u16) -> u64 {
+    ((roc as u64) << 16) | seq as u64
+}
+
+/// Header length, sequence number and SSRC of an RTP packet
+fn rtp_header(packet: &[u8]) -> Result<(usize, u16, u32)> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return Err(anyhow!("Not an RTP packet"));
+    }
+    let mut len = 12 + 4 * (packet[0] & 0x0f) as usize;
+    if packet[0] & 0x10 != 0 {
+        let words = packet
+            .get(len + 2..len + 4)
+            .ok_or_else(|| anyhow!("Truncated RTP header extension"))?;
+        len += 4 + 4 * u16::from_be_bytes([words[0], words[1]]) as usize;
+    }
+    if len > packet.len() {
+        return Err(anyhow!("Truncated RTP header"));
+    }
+    let seq = u16::from_be_bytes([packet[2], packet[3]]);
+    let ssrc = u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
+    Ok((len, seq, ssrc))
+}
+
+/// Sender SSRC of an RTCP packet
+fn rtcp_ssrc(packet: &[u8]) -> Result<u32> {
+    if packet.len() < 8 || packet[0] >> 6 != 2 {
+        return Err(anyhow!("Not an RTCP packet"));
+    }
+    Ok(u32::from_be_bytes([
+        packet[4], packet[5], packet[6], packet[7],
+    ]))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,4 +411,65 @@ mod tests {
         let attr = config.to_crypto_attribute(1);
         assert!(attr.contains("AES_CM_128_HMAC_SHA1_80"));
     }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_key_derivation() {
+        // RFC 3711 appendix B.3
+        let key: [u8; 16] = hex("E1F97A0D3E018BE0D64FA32C06DE4139").try_into().unwrap();
+        let salt: [u8; 14] = hex("0EC675AD498AFEEBB6960B3AABE6").try_into().unwrap();
+        let keys = SessionKeys::derive(&key, &salt, [0, 1, 2]);
+        assert_eq!(keys.salt.to_vec(), hex("30CBBC08863D8C85D49DB34A9AE1"));
+        assert_eq!(
+            keys.auth_key.to_vec(),
+            hex("CEBE321F6FF7716B6FD4AB49AF256A156D38BAA4")
+        );
+
+        let mut block = [0u8; 16].into();
+        keys.cipher.encrypt_block(&mut block);
+        let expected = Aes128::new(hex("C61E7A93744F39EE10734AFE3FF7A087").as_slice().into());
+        let mut expected_block = [0u8; 16].into();
+        expected.encrypt_block(&mut expected_block);
+        assert_eq!(block, expected_block);
+    }
+
+    #[test]
+    fn test_protect_and_unprotect() {
+        let material: Vec<u8> = (0..DTLS_KEYING_MATERIAL_LEN as u8).collect();
+        let (mut client_out, mut client_in) =
+            SrtpContext::from_dtls_keying_material(&material, true).unwrap();
+        let (mut server_out, mut server_in) =
+            SrtpContext::from_dtls_keying_material(&material, false).unwrap();
+
+        let mut rtp = vec![0x80, 0x00, 0xff, 0xff, 0, 0, 0, 160, 0x12, 0x34, 0x56, 0x78];
+        rtp.extend_from_slice(b"audio payload");
+        let protected = client_out.protect_rtp(&rtp).unwrap();
+        assert_eq!(protected.len(), rtp.len() + AUTH_TAG_LEN);
+        assert_ne!(&protected[12..rtp.len()], &rtp[12..]);
+        assert_eq!(server_in.unprotect_rtp(&protected).unwrap(), rtp);
+
+        // The sequence number wraps and the rollover counter follows
+        rtp[2..4].copy_from_slice(&[0, 0]);
+        let protected = client_out.protect_rtp(&rtp).unwrap();
+        assert_eq!(server_in.unprotect_rtp(&protected).unwrap(), rtp);
+
+        let mut tampered = protected.clone();
+        tampered[14] ^= 1;
+        assert!(server_in.unprotect_rtp(&tampered).is_err());
+        // Keys are per direction
+        assert!(client_in.unprotect_rtp(&protected).is_err());
+
+        let rtcp = [0x80, 200, 0, 6, 0x12, 0x34, 0x56, 0x78, 1, 2, 3, 4];
+        let protected = server_out.protect_rtcp(&rtcp).unwrap();
+        assert_ne!(&protected[8..12], &rtcp[8..]);
+        assert_eq!(client_in.unprotect_rtcp(&protected).unwrap(), rtcp);
+
+        assert!(SrtpContext::from_dtls_keying_material(&material[1..], true).is_err());
+    }
 }
@@ -0,0 +1,123 @@
+//! WAV files of narrowband telephone audio
+//!
+//! Prompts, messages and greetings are 8 kHz mono WAV files, either 16-bit
+//! PCM or G.711 (µ-law or A-law). Other rates and channel counts are
+//! refused rather than resampled.
+
+use anyhow::{bail, Context, Result};
+
+use super::g711;
+
+/// Sample rate of telephone audio
+pub const SAMPLE_RATE: u32 = 8000;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_ALAW: u16 = 6;
+const FORMAT_ULAW: u16 = 7;
+
+/// Samples of an 8 kHz mono WAV file
+pub fn decode(data: &[u8]) -> Result<Vec<i16>> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        bail!("Not a WAV file");
+    }
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into()?) as usize;
+        let body = data
+            .get(offset + 8..offset + 8 + size)
+            .or_else(|| data.get(offset + 8..))
+            .context("Truncated WAV chunk")?;
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    bail!("Truncated WAV format chunk");
+                }
+                let tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let rate = u32::from_le_bytes(body[4..8].try_into()?);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                if channels != 1 || rate != SAMPLE_RATE {
+                    bail!(
+                        "WAV must be {} Hz mono, not {} Hz with {} channels",
+                        SAMPLE_RATE,
+                        rate,
+                        channels
+                    );
+                }
+                format = Some((tag, bits));
+            }
+            b"data" => {
+                return match format {
+                    Some((FORMAT_PCM, 16)) => Ok(body
+                        .chunks_exact(2)
+                        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+                        .collect()),
+                    Some((FORMAT_ULAW, 8)) => {
+                        Ok(body.iter().map(|b| g711::ulaw_to_linear(*b)).collect())
+                    }
+                    Some((FORMAT_ALAW, 8)) => {
+                        Ok(body.iter().map(|b| g711::alaw_to_linear(*b)).collect())
+                    }
+                    Some((tag, bits)) => {
+                        bail!("Unsupported WAV encoding {} with {} bits", tag, bits)
+                    }
+                    None => bail!("WAV data before its format"),
+                };
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset += 8 + size + (size & 1);
+    }
+    bail!("WAV file has no audio")
+}
+
+/// 16-bit PCM WAV file of 8 kHz mono samples
+pub fn encode(samples: &[i16]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let samples: Vec<i16> = (0..800).map(|i| ((i % 40) * 800 - 16000) as i16).collect();
+        let wav = encode(&samples);
+        assert_eq!(wav.len(), 44 + 1600);
+        assert_eq!(decode(&wav).unwrap(), samples);
+
+        // µ-law files are expanded to linear samples
+        let mut ulaw = wav[..44].to_vec();
+        ulaw[20..22].copy_from_slice(&FORMAT_ULAW.to_le_bytes());
+        ulaw[34..36].copy_from_slice(&8u16.to_le_bytes());
+        ulaw[40..44].copy_from_slice(&2u32.to_le_bytes());
+        ulaw.extend_from_slice(&[0xff, 0x7f]);
+        assert_eq!(decode(&ulaw).unwrap()[0], 0);
+
+        assert!(decode(b"RIFF").is_err());
+        let mut stereo = wav.clone();
+        stereo[22] = 2;
+        assert!(decode(&stereo).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! DTLS-SRTP handshake of WebRTC legs (RFC 5763, RFC 5764)
+//!
+//! The handshake shares the leg's media port with STUN and SRTP, so rather
+//! than reading the socket it is fed the DTLS records
+//! [`PacketKind`](super::PacketKind) picks out, through a [`DtlsChannel`].
+//! Browsers present self-signed certificates, which are checked against the
+//! fingerprint in their SDP instead of a CA. Keying material exported from
+//! the finished handshake keys the leg's SRTP contexts.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use webrtc_dtls::config::{ClientAuthType, Config, ExtendedMasterSecretType};
+use webrtc_dtls::conn::DTLSConn;
+use webrtc_dtls::extension::extension_use_srtp::SrtpProtectionProfile;
+use webrtc_util::{Conn, KeyingMaterialExporter};
+
+use super::{fingerprint, DtlsIdentity};
+use crate::media::srtp::{SrtpContext, DTLS_KEYING_MATERIAL_LEN};
+
+/// Label SRTP keying material is exported under (RFC 5764 section 4.2)
+const SRTP_EXPORTER_LABEL: &str = "EXTRACTOR-dtls_srtp";
+
+/// Address of the peer on a shared media port, once ICE has found it
+pub type PeerAddress = Arc<Mutex<Option<SocketAddr>>>;
+
+/// Carries DTLS records between a handshake and a socket it shares
+pub struct DtlsChannel {
+    socket: Arc<UdpSocket>,
+    peer: PeerAddress,
+    records: tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+}
+
+impl DtlsChannel {
+    /// A channel sending on `socket` to `peer`, and the sender records
+    /// received from the peer are handed to it with
+    pub fn new(
+        socket: Arc<UdpSocket>,
+        peer: PeerAddress,
+    ) -> (Self, mpsc::UnboundedSender<Vec<u8>>) {
+        let (records_tx, records_rx) = mpsc::unbounded_channel();
+        let channel = Self {
+            socket,
+            peer,
+            records: tokio::sync::Mutex::new(records_rx),
+        };
+        (channel, records_tx)
+    }
+}
+
+#[async_trait]
+impl Conn for DtlsChannel {
+    async fn connect(&self, _addr: SocketAddr) -> webrtc_util::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported).into())
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc_util::Result<usize> {
+        let record = self
+            .records
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionAborted))?;
+        let len = record.len().min(buf.len());
+        buf[..len].copy_from_slice(&record[..len]);
+        Ok(len)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc_util::Result<(usize, SocketAddr)> {
+        let len = self.recv(buf).await?;
+        let peer = self
+            .remote_addr()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok((len, peer))
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc_util::Result<usize> {
+        // Until ICE has found the peer records are dropped; the handshake
+        // retransmits its flights
+        match self.remote_addr() {
+            Some(peer) => Ok(self.socket.send_to(buf, peer).await?),
+            None => Ok(buf.len()),
+        }
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> webrtc_util::Result<usize> {
+        Ok(self.socket.send_to(buf, target).await?)
+    }
+
+    fn local_addr(&self) -> webrtc_util::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        *self.peer.lock().unwrap()
+    }
+
+    async fn close(&self) -> webrtc_util::Result<()> {
+        Ok(())
+    }
+}
+
+/// SRTP contexts of a leg, keyed by its finished DTLS handshake
+pub struct DtlsSrtp {
+    /// Protects what is sent to the peer
+    pub outbound: SrtpContext,
+    /// Unprotects what the peer sends
+    pub inbound: SrtpContext,
+    /// Kept open so the peer's retransmitted records are still answered
+    conn: DTLSConn,
+}
+
+impl DtlsSrtp {
+    /// Send the peer a close_notify alert
+    pub async fn close(&self) -> Result<()> {
+        self.conn.close().await?;
+        Ok(())
+    }
+}
+
+/// Run the DTLS handshake over `conn` as the client or the server,
+/// accepting only a peer certificate matching `remote_fingerprint`, the
+/// `a=fingerprint` value from the peer's SDP
+pub async fn handshake(
+    conn: Arc<dyn Conn + Send + Sync>,
+    identity: &DtlsIdentity,
+    remote_fingerprint: &str,
+    is_client: bool,
+) -> Result<DtlsSrtp> {
+    let expected = remote_fingerprint.trim().to_string();
+    let config = Config {
+        certificates: vec![identity.certificate().clone()],
+        srtp_protection_profiles: vec![SrtpProtectionProfile::Srtp_Aes128_Cm_Hmac_Sha1_80],
+        extended_master_secret: ExtendedMasterSecretType::Require,
+        client_auth: ClientAuthType::RequireAnyClientCert,
+        // Self-signed certificates are verified by fingerprint below
+        insecure_skip_verify: true,
+        verify_peer_certificate: Some(Arc::new(
+            move |certificates: &[Vec<u8>], _| match certificates.first() {
+                Some(der) if fingerprint(der).eq_ignore_ascii_case(&expected) => Ok(()),
+                _ => Err(webrtc_dtls::Error::Other(
+                    "Certificate does not match the SDP fingerprint".to_string(),
+                )),
+            },
+        )),
+        ..Default::default()
+    };
+
+    let conn = DTLSConn::new(conn, config, is_client, None)
+        .await
+        .context("DTLS handshake failed")?;
+    let profile = conn.selected_srtpprotection_profile();
+    if profile != SrtpProtectionProfile::Srtp_Aes128_Cm_Hmac_Sha1_80 {
+        bail!("Unsupported SRTP protection profile {:?}", profile);
+    }
+    let material = conn
+        .connection_state()
+        .await
+        .export_keying_material(SRTP_EXPORTER_LABEL, &[], DTLS_KEYING_MATERIAL_LEN)
+        .await
+        .map_err(|e| anyhow!("Failed to export SRTP keying material: {}", e))?;
+    let (outbound, inbound) = SrtpContext::from_dtls_keying_material(&material, is_client)?;
+    Ok(DtlsSrtp {
+        outbound,
+        inbound,
+        conn,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc_util::conn::conn_pipe::pipe;
+
+    const RTP: [u8; 16] = [
+        0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xa0, 0x12, 0x34, 0x56, 0x78, 1, 2, 3, 4,
+    ];
+
+    #[tokio::test]
+    async fn test_handshake_keys_srtp() {
+        let browser = DtlsIdentity::generate().unwrap();
+        let gateway = DtlsIdentity::generate().unwrap();
+        let (browser_fingerprint, gateway_fingerprint) =
+            (browser.fingerprint(), gateway.fingerprint());
+        let (client_conn, server_conn) = pipe();
+
+        let (client, server) = tokio::join!(
+            handshake(Arc::new(client_conn), &browser, &gateway_fingerprint, true),
+            handshake(Arc::new(server_conn), &gateway, &browser_fingerprint, false),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        let protected = client.outbound.protect_rtp(&RTP).unwrap();
+        assert_ne!(&protected[12..16], &RTP[12..]);
+        assert_eq!(server.inbound.unprotect_rtp(&protected).unwrap(), RTP);
+        let protected = server.outbound.protect_rtp(&RTP).unwrap();
+        assert_eq!(client.inbound.unprotect_rtp(&protected).unwrap(), RTP);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_other_certificate() {
+        let browser = DtlsIdentity::generate().unwrap();
+        let impostor = DtlsIdentity::generate().unwrap();
+        let gateway = DtlsIdentity::generate().unwrap();
+        let (client_conn, server_conn) = pipe();
+
+        let expected = gateway.fingerprint();
+        let client = tokio::spawn(async move {
+            handshake(Arc::new(client_conn), &impostor, &expected, true).await
+        });
+        let server = handshake(
+            Arc::new(server_conn),
+            &gateway,
+            &browser.fingerprint(),
+            false,
+        )
+        .await;
+        client.abort();
+        assert!(server.is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! ICE-lite agent (RFC 8445 section 2.5)
+//!
+//! The server has a public address, so it only offers host candidates and
+//! answers the browser's STUN binding requests; the browser does all the
+//! checking and nominating.
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha1::Sha1;
+use std::net::{IpAddr, SocketAddr};
+
+type HmacSha1 = Hmac<Sha1>;
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const HEADER_LEN: usize = 20;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+
+const ATTR_USERNAME: u16 = 0x0006;
+const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_USE_CANDIDATE: u16 = 0x0025;
+const ATTR_FINGERPRINT: u16 = 0x8028;
+
+const FINGERPRINT_XOR: u32 = 0x5354_554e;
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Local ICE username fragment and password
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceCredentials {
+    pub ufrag: String,
+    pub pwd: String,
+}
+
+impl IceCredentials {
+    /// Random credentials, longer than the 4 and 22 character minimums
+    pub fn generate() -> Self {
+        let random = |len: usize| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(len)
+                .map(char::from)
+                .collect()
+        };
+        Self {
+            ufrag: random(8),
+            pwd: random(24),
+        }
+    }
+}
+
+/// A connectivity check that passed
+#[derive(Debug, Clone)]
+pub struct Binding {
+    /// Binding success response to send back to the sender
+    pub response: Vec<u8>,
+    /// The peer's username fragment
+    pub remote_ufrag: String,
+    /// The browser nominated this candidate pair for media
+    pub use_candidate: bool,
+}
+
+/// Answers STUN binding requests on a WebRTC media port
+pub struct IceLiteAgent {
+    credentials: IceCredentials,
+}
+
+impl IceLiteAgent {
+    pub fn new(credentials: IceCredentials) -> Self {
+        Self { credentials }
+    }
+
+    pub fn credentials(&self) -> &IceCredentials {
+        &self.credentials
+    }
+
+    /// Check a binding request from `from` and build its response
+    ///
+    /// Requests that are not addressed to our username fragment or fail
+    /// MESSAGE-INTEGRITY are rejected; lite agents drop them silently.
+    pub fn handle(&self, packet: &[u8], from: SocketAddr) -> Result<Binding> {
+        let message = Message::parse(packet)?;
+        if message.kind != BINDING_REQUEST {
+            return Err(anyhow!("Not a STUN binding request"));
+        }
+
+        let username = message
+            .attribute(ATTR_USERNAME)
+            .ok_or_else(|| anyhow!("Binding request has no USERNAME"))?;
+        let username = std::str::from_utf8(username)?;
+        let remote_ufrag = username
+            .strip_prefix(&self.credentials.ufrag)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .ok_or_else(|| anyhow!("Binding request is for another ICE username"))?;
+
+        if !message.verify_integrity(self.credentials.pwd.as_bytes()) {
+            return Err(anyhow!("STUN MESSAGE-INTEGRITY check failed"));
+        }
+
+        let mut response = MessageBuilder::new(BINDING_SUCCESS, &message.transaction_id);
+        response.attribute(
+            ATTR_XOR_MAPPED_ADDRESS,
+            &xor_address(from, &message.transaction_id),
+        );
+        response.integrity(self.credentials.pwd.as_bytes());
+        response.fingerprint();
+
+        Ok(Binding {
+            response: response.finish(),
+            remote_ufrag: remote_ufrag.to_string(),
+            use_candidate: message.attribute(ATTR_USE_CANDIDATE).is_some(),
+        })
+    }
+}
+
+/// A parsed STUN message
+struct Message<'a> {
+    packet: &'a [u8],
+    kind: u16,
+    transaction_id: [u8; 12],
+    /// Attribute type, offset of the attribute in the packet, and value
+    attributes: Vec<(u16, usize, &'a [u8])>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(packet: &'a [u8]) -> Result<Self> {
+        if packet.len() < HEADER_LEN || packet[0] & 0xc0 != 0 {
+            return Err(anyhow!("Not a STUN message"));
+        }
+        let length = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]) != MAGIC_COOKIE
+            || !length.is_multiple_of(4)
+            || HEADER_LEN + length != packet.len()
+        {
+            return Err(anyhow!("Malformed STUN message"));
+        }
+
+        let mut attributes = Vec::new();
+        let mut offset = HEADER_LEN;
+        while offset + 4 <= packet.len() {
+            let kind = u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+            let len = u16::from_be_bytes([packet[offset + 2], packet[offset + 3]]) as usize;
+            let value = packet
+                .get(offset + 4..offset + 4 + len)
+                .ok_or_else(|| anyhow!("Truncated STUN attribute"))?;
+            attributes.push((kind, offset, value));
+            offset += 4 + len.div_ceil(4) * 4;
+        }
+
+        Ok(Self {
+            packet,
+            kind: u16::from_be_bytes([packet[0], packet[1]]),
+            transaction_id: packet[8..HEADER_LEN].try_into().expect("12 bytes"),
+            attributes,
+        })
+    }
+
+    fn attribute(&self, kind: u16) -> Option<&'a [u8]> {
+        self.attributes
+            .iter()
+            .find(|(k, _, _)| *k == kind)
+            .map(|(_, _, value)| *value)
+    }
+
+    /// Check MESSAGE-INTEGRITY and, when present, FINGERPRINT
+    fn verify_integrity(&self, key: &[u8]) -> bool {
+        if let Some((_, offset, value)) = self
+            .attributes
+            .iter()
+            .find(|(k, _, _)| *k == ATTR_FINGERPRINT)
+        {
+            let expected = fingerprint(&self.packet[..*offset], *offset + 8);
+            if value != &expected.to_be_bytes() {
+                return false;
+            }
+        }
+
+        let Some((_, offset, value)) = self
+            .attributes
+            .iter()
+            .find(|(k, _, _)| *k == ATTR_MESSAGE_INTEGRITY)
+        else {
+            return false;
+        };
+        let expected = integrity(key, &self.packet[..*offset], *offset + 24);
+        value.len() == expected.len()
+            && value
+                .iter()
+                .zip(&expected)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+/// Serializes a STUN message
+struct MessageBuilder {
+    buf: Vec<u8>,
+}
+
+impl MessageBuilder {
+    fn new(kind: u16, transaction_id: &[u8; 12]) -> Self {
+        let mut buf = Vec::with_capacity(128);
+        buf.extend_from_slice(&kind.to_be_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf.extend_from_slice(transaction_id);
+        Self { buf }
+    }
+
+    fn attribute(&mut self, kind: u16, value: &[u8]) {
+        self.buf.extend_from_slice(&kind.to_be_bytes());
+        self.buf
+            .extend_from_slice(&(value.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(value);
+        let padding = (4 - value.len() % 4) % 4;
+        self.buf.extend(std::iter::repeat_n(0, padding));
+        self.set_length(self.buf.len());
+    }
+
+    fn integrity(&mut self, key: &[u8]) {
+        let mac = integrity(key, &self.buf, self.buf.len() + 24);
+        self.attribute(ATTR_MESSAGE_INTEGRITY, &mac);
+    }
+
+    fn fingerprint(&mut self) {
+        let crc = fingerprint(&self.buf, self.buf.len() + 8);
+        self.attribute(ATTR_FINGERPRINT, &crc.to_be_bytes());
+    }
+
+    fn set_length(&mut self, total: usize) {
+        let length = ((total - HEADER_LEN) as u16).to_be_bytes();
+        self.buf[2..4].copy_from_slice(&length);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// HMAC-SHA1 over a message prefix whose length field claims `total` bytes
+fn integrity(key: &[u8], prefix: &[u8], total: usize) -> [u8; 20] {
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts any key");
+    mac.update(&prefix[..2]);
+    mac.update(&((total - HEADER_LEN) as u16).to_be_bytes());
+    mac.update(&prefix[4..]);
+    mac.finalize().into_bytes().into()
+}
+
+/// CRC-32 over a message prefix whose length field claims `total` bytes
+fn fingerprint(prefix: &[u8], total: usize) -> u32 {
+    let mut digest = CRC32.digest();
+    digest.update(&prefix[..2]);
+    digest.update(&((total - HEADER_LEN) as u16).to_be_bytes());
+    digest.update(&prefix[4..]);
+    digest.finalize() ^ FINGERPRINT_XOR
+}
+
+/// XOR-MAPPED-ADDRESS value for an address
+fn xor_address(address: SocketAddr, transaction_id: &[u8; 12]) -> Vec<u8> {
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let port = address.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    let mut value = vec![0];
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            value.push(0x01);
+            value.extend_from_slice(&port.to_be_bytes());
+            value.extend(ip.octets().iter().zip(cookie).map(|(a, b)| a ^ b));
+        }
+        IpAddr::V6(ip) => {
+            value.push(0x02);
+            value.extend_from_slice(&port.to_be_bytes());
+            let mask = cookie.iter().chain(transaction_id);
+            value.extend(ip.octets().iter().zip(mask).map(|(a, b)| a ^ b));
+        }
+    }
+    value
+}
+
+/// A nominating binding request, as a browser checking `username`
+/// (`local:remote`) sends
+#[cfg(test)]
+pub(crate) fn binding_request(username: &str, pwd: &str) -> Vec<u8> {
+    let mut request = MessageBuilder::new(BINDING_REQUEST, &rand::random());
+    request.attribute(ATTR_USERNAME, username.as_bytes());
+    request.attribute(ATTR_USE_CANDIDATE, &[]);
+    request.integrity(pwd.as_bytes());
+    request.fingerprint();
+    request.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rfc5769_sample_request() {
+        let request = hex("0001 0058 2112a442 b7e7a701 bc34d686 fa87dfae
+             80220010 5354554e 20746573 7420636c 69656e74
+             00240004 6e0001ff
+             80290008 932ff9b1 51263b36
+             00060009 6576746a 3a683676 59202020
+             00080014 9aeaa70c bfd8cb56 781ef2b5 b2d3f249 c1b571a2
+             80280004 e57a3bcf");
+        let message = Message::parse(&request).unwrap();
+        assert_eq!(message.attribute(ATTR_USERNAME), Some(&b"evtj:h6vY"[..]));
+        assert!(message.verify_integrity(b"VOkJxbRl1RmTxUk/WvJxBt"));
+        assert!(!message.verify_integrity(b"wrong password"));
+    }
+
+    #[test]
+    fn test_binding_response() {
+        let agent = IceLiteAgent::new(IceCredentials {
+            ufrag: "server".to_string(),
+            pwd: "server-password-0123456789".to_string(),
+        });
+        let transaction_id = [7u8; 12];
+        let request = |username: &str, key: &[u8]| {
+            let mut request = MessageBuilder::new(BINDING_REQUEST, &transaction_id);
+            request.attribute(ATTR_USERNAME, username.as_bytes());
+            request.attribute(ATTR_USE_CANDIDATE, &[]);
+            request.integrity(key);
+            request.fingerprint();
+            request.finish()
+        };
+        let from: SocketAddr = "192.0.2.10:50000".parse().unwrap();
+
+        let binding = agent
+            .handle(
+                &request("server:browser", b"server-password-0123456789"),
+                from,
+            )
+            .unwrap();
+        assert_eq!(binding.remote_ufrag, "browser");
+        assert!(binding.use_candidate);
+
+        let response = Message::parse(&binding.response).unwrap();
+        assert_eq!(response.kind, BINDING_SUCCESS);
+        assert_eq!(response.transaction_id, transaction_id);
+        assert!(response.verify_integrity(b"server-password-0123456789"));
+        assert_eq!(
+            response.attribute(ATTR_XOR_MAPPED_ADDRESS).unwrap(),
+            xor_address(from, &transaction_id)
+        );
+
+        // RFC 5769 section 2.2
+        assert_eq!(
+            xor_address("192.0.2.1:32853".parse().unwrap(), &transaction_id),
+            hex("0001a147 e112a643")
+        );
+
+        assert!(agent
+            .handle(&request("server:browser", b"wrong"), from)
+            .is_err());
+        assert!(agent
+            .handle(
+                &request("other:browser", b"server-password-0123456789"),
+                from
+            )
+            .is_err());
+
+        let credentials = IceCredentials::generate();
+        assert!(credentials.ufrag.len() >= 4 && credentials.pwd.len() >= 22);
+    }
+}
@@ -0,0 +1,124 @@
+//! WebRTC interop for browser softphones
+//!
+//! Browsers only speak DTLS-SRTP over ICE with RTP and RTCP multiplexed on
+//! one port, while SIP trunks expect plain RTP/AVP (or SDES SRTP). This
+//! module holds the pieces a WebRTC leg needs:
+//!
+//! - [`sdp`] translates offers and answers between the two styles
+//! - [`ice`] answers ICE connectivity checks as an ICE-lite agent
+//! - [`DtlsIdentity`] is the certificate whose fingerprint is advertised
+//! - [`dtls`] runs the DTLS handshake and keys SRTP from it
+//! - [`PacketKind`] demultiplexes STUN, DTLS and (S)RTP arriving on the
+//!   single media port
+//! - [`relay`] puts those together, relaying a browser's SRTP to a SIP
+//!   leg's plain RTP and back
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use webrtc_dtls::crypto::Certificate;
+
+pub mod dtls;
+pub mod ice;
+pub mod relay;
+pub mod sdp;
+
+pub use ice::{IceCredentials, IceLiteAgent};
+pub use relay::WebRtcRelay;
+pub use sdp::{DtlsSetup, LocalWebRtc, RemoteWebRtc};
+
+/// Certificate presented in the DTLS handshake of WebRTC legs
+///
+/// Browsers accept self-signed certificates here; the peer is authenticated
+/// by the fingerprint carried in the (signalling-protected) SDP instead.
+#[derive(Debug, Clone)]
+pub struct DtlsIdentity {
+    certificate: Certificate,
+}
+
+impl DtlsIdentity {
+    /// Generate a fresh ECDSA P-256 certificate
+    pub fn generate() -> Result<Self> {
+        let certificate = Certificate::generate_self_signed(vec!["rustalk".to_string()])?;
+        Ok(Self { certificate })
+    }
+
+    pub fn certificate_der(&self) -> &[u8] {
+        &self.certificate.certificate[0].0
+    }
+
+    /// PKCS#8 private key
+    pub fn private_key_der(&self) -> &[u8] {
+        &self.certificate.private_key.serialized_der
+    }
+
+    /// Value of the SDP `a=fingerprint` attribute, e.g. `sha-256 AB:CD:...`
+    pub fn fingerprint(&self) -> String {
+        fingerprint(self.certificate_der())
+    }
+
+    /// The certificate as the DTLS handshake presents it
+    pub(crate) fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+}
+
+/// SHA-256 fingerprint of a DER certificate in SDP form
+pub fn fingerprint(certificate_der: &[u8]) -> String {
+    let digest = Sha256::digest(certificate_der);
+    let hex: Vec<String> = digest.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("sha-256 {}", hex.join(":"))
+}
+
+/// Protocol of a packet arriving on a WebRTC media port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    Stun,
+    Dtls,
+    Rtp,
+    Rtcp,
+    Unknown,
+}
+
+impl PacketKind {
+    /// Classify a packet by its first byte (RFC 7983), telling RTCP from RTP
+    /// by payload type as `rtcp-mux` requires (RFC 5761)
+    pub fn classify(packet: &[u8]) -> Self {
+        match packet.first() {
+            Some(0..=3) => PacketKind::Stun,
+            Some(20..=63) => PacketKind::Dtls,
+            Some(128..=191) => match packet.get(1) {
+                Some(192..=223) => PacketKind::Rtcp,
+                Some(_) => PacketKind::Rtp,
+                None => PacketKind::Unknown,
+            },
+            _ => PacketKind::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_fingerprint() {
+        let identity = DtlsIdentity::generate().unwrap();
+        let fingerprint = identity.fingerprint();
+        assert!(fingerprint.starts_with("sha-256 "));
+        // 32 bytes as colon-separated hex pairs
+        assert_eq!(fingerprint.len(), "sha-256 ".len() + 32 * 3 - 1);
+        assert_eq!(fingerprint, super::fingerprint(identity.certificate_der()));
+        assert!(!identity.private_key_der().is_empty());
+    }
+
+    #[test]
+    fn test_classify_packets() {
+        assert_eq!(PacketKind::classify(&[0x00, 0x01]), PacketKind::Stun);
+        assert_eq!(PacketKind::classify(&[22, 0xfe, 0xfd]), PacketKind::Dtls);
+        assert_eq!(PacketKind::classify(&[0x80, 111]), PacketKind::Rtp);
+        assert_eq!(PacketKind::classify(&[0x80, 0xe0]), PacketKind::Rtp);
+        assert_eq!(PacketKind::classify(&[0x81, 200]), PacketKind::Rtcp);
+        assert_eq!(PacketKind::classify(&[0x80]), PacketKind::Unknown);
+        assert_eq!(PacketKind::classify(&[]), PacketKind::Unknown);
+    }
+}
@@ -0,0 +1,302 @@
+//! Media relay between a browser's WebRTC leg and a SIP leg's plain RTP
+//!
+//! The relay holds a port for each leg. On the browser's port it answers ICE
+//! connectivity checks as a lite agent and runs the DTLS handshake; once that
+//! has keyed SRTP, the browser's audio is unprotected and sent on as plain
+//! RTP from the SIP port, and what comes back is protected for the browser.
+//! The browser is reached where its checks come from. The SIP leg is
+//! reached at the address in its SDP until its first packet arrives, then
+//! where packets come from, so endpoints behind NAT are heard. RTCP ends at
+//! the relay on both sides.
+
+use anyhow::{Context, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::task::AbortHandle;
+use tracing::{debug, info, warn};
+
+use super::dtls::{self, DtlsChannel, DtlsSrtp, PeerAddress};
+use super::sdp::{self, DtlsSetup, LocalWebRtc};
+use super::{DtlsIdentity, IceCredentials, IceLiteAgent, PacketKind};
+use crate::media::sdp::SdpSession;
+
+/// Media relay of one call from a browser
+#[derive(Debug, Clone)]
+pub struct WebRtcRelay {
+    local: LocalWebRtc,
+    rtp: SocketAddr,
+    sip_peer: PeerAddress,
+    task: AbortHandle,
+}
+
+impl WebRtcRelay {
+    /// Relay a browser's SDP offer, advertising `address` to both legs
+    ///
+    /// Returns the relay and the plain RTP offer for the SIP leg.
+    pub async fn offer(
+        address: IpAddr,
+        identity: DtlsIdentity,
+        offer: &str,
+    ) -> Result<(Self, String)> {
+        let unspecified = match address {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let webrtc = Arc::new(UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?);
+        let rtp_socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+        let candidate = SocketAddr::new(address, webrtc.local_addr()?.port());
+        let rtp = SocketAddr::new(address, rtp_socket.local_addr()?.port());
+
+        let (plain, remote) = sdp::from_webrtc(offer, rtp)?;
+        let local = LocalWebRtc::new(
+            IceCredentials::generate(),
+            identity.fingerprint(),
+            candidate,
+        )
+        .answering(&remote);
+        let sip_peer = PeerAddress::default();
+        let task = tokio::spawn(run(
+            webrtc,
+            rtp_socket,
+            IceLiteAgent::new(local.ice.clone()),
+            identity,
+            remote.fingerprint,
+            local.setup == DtlsSetup::Active,
+            sip_peer.clone(),
+        ))
+        .abort_handle();
+
+        let relay = Self {
+            local,
+            rtp,
+            sip_peer,
+            task,
+        };
+        Ok((relay, plain))
+    }
+
+    /// What the browser is told about our side of its leg
+    pub fn local(&self) -> &LocalWebRtc {
+        &self.local
+    }
+
+    /// Address the SIP leg sends RTP to
+    pub fn rtp_addr(&self) -> SocketAddr {
+        self.rtp
+    }
+
+    /// Take the SIP leg's address from its SDP answer and dress the answer
+    /// up for the browser
+    pub fn answer(&self, answer: &str) -> Result<String> {
+        let session = SdpSession::parse(answer)?;
+        let port = session
+            .media
+            .iter()
+            .find(|m| m.media_type == "audio" && m.port != 0)
+            .map(|m| m.port)
+            .context("Answer has no audio")?;
+        let ip = session
+            .connection
+            .as_deref()
+            .and_then(|c| c.split_whitespace().nth(2))
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .context("Answer has no connection address")?;
+        self.sip_peer
+            .lock()
+            .unwrap()
+            .get_or_insert(SocketAddr::new(ip, port));
+        sdp::to_webrtc(answer, &self.local)
+    }
+
+    /// Stop relaying
+    pub fn close(&self) {
+        self.task.abort();
+    }
+}
+
+async fn run(
+    webrtc: Arc<UdpSocket>,
+    rtp: UdpSocket,
+    agent: IceLiteAgent,
+    identity: DtlsIdentity,
+    remote_fingerprint: String,
+    is_client: bool,
+    sip_peer: PeerAddress,
+) {
+    let browser = PeerAddress::default();
+    let (channel, records) = DtlsChannel::new(webrtc.clone(), browser.clone());
+    let handshake = dtls::handshake(Arc::new(channel), &identity, &remote_fingerprint, is_client);
+    tokio::pin!(handshake);
+    let mut handshaking = true;
+    let mut keyed: Option<DtlsSrtp> = None;
+
+    let mut buf = [0u8; 2048];
+    let mut rtp_buf = [0u8; 2048];
+    loop {
+        tokio::select! {
+            result = &mut handshake, if handshaking => {
+                handshaking = false;
+                match result {
+                    Ok(srtp) => {
+                        info!("DTLS-SRTP established with {:?}", *browser.lock().unwrap());
+                        keyed = Some(srtp);
+                    }
+                    Err(e) => warn!("WebRTC media not relayed: {:#}", e),
+                }
+            }
+            received = webrtc.recv_from(&mut buf) => {
+                let Ok((len, from)) = received else {
+                    continue;
+                };
+                let packet = &buf[..len];
+                let known = *browser.lock().unwrap() == Some(from);
+                match PacketKind::classify(packet) {
+                    PacketKind::Stun => match agent.handle(packet, from) {
+                        Ok(binding) => {
+                            {
+                                let mut browser = browser.lock().unwrap();
+                                if binding.use_candidate || browser.is_none() {
+                                    *browser = Some(from);
+                                }
+                            }
+                            if let Err(e) = webrtc.send_to(&binding.response, from).await {
+                                debug!("Failed to answer connectivity check from {}: {}", from, e);
+                            }
+                        }
+                        Err(e) => debug!("Dropped connectivity check from {}: {:#}", from, e),
+                    },
+                    PacketKind::Dtls if known => {
+                        let _ = records.send(packet.to_vec());
+                    }
+                    PacketKind::Rtp if known => {
+                        let (Some(srtp), Some(peer)) = (keyed.as_mut(), *sip_peer.lock().unwrap())
+                        else {
+                            continue;
+                        };
+                        match srtp.inbound.unprotect_rtp(packet) {
+                            Ok(plain) => {
+                                if let Err(e) = rtp.send_to(&plain, peer).await {
+                                    debug!("Failed to send RTP to {}: {}", peer, e);
+                                }
+                            }
+                            Err(e) => debug!("Dropped SRTP from {}: {:#}", from, e),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            received = rtp.recv_from(&mut rtp_buf) => {
+                let Ok((len, from)) = received else {
+                    continue;
+                };
+                let packet = &rtp_buf[..len];
+                if PacketKind::classify(packet) != PacketKind::Rtp {
+                    continue;
+                }
+                *sip_peer.lock().unwrap() = Some(from);
+                let (Some(srtp), Some(peer)) = (keyed.as_mut(), *browser.lock().unwrap()) else {
+                    continue;
+                };
+                match srtp.outbound.protect_rtp(packet) {
+                    Ok(protected) => {
+                        if let Err(e) = webrtc.send_to(&protected, peer).await {
+                            debug!("Failed to send SRTP to {}: {}", peer, e);
+                        }
+                    }
+                    Err(e) => debug!("Failed to protect RTP from {}: {:#}", from, e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::webrtc::ice::binding_request;
+    use std::sync::Mutex;
+
+    const RTP: [u8; 16] = [
+        0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xa0, 0x12, 0x34, 0x56, 0x78, 1, 2, 3, 4,
+    ];
+
+    #[tokio::test]
+    async fn test_relays_browser_srtp_to_plain_rtp() {
+        let browser_identity = DtlsIdentity::generate().unwrap();
+        let gateway_identity = DtlsIdentity::generate().unwrap();
+        let gateway_fingerprint = gateway_identity.fingerprint();
+        let browser = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let offer = format!(
+            "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0\r\n\
+             m=audio 9 UDP/TLS/RTP/SAVPF 0\r\nc=IN IP4 0.0.0.0\r\na=mid:0\r\n\
+             a=ice-ufrag:brws\r\na=ice-pwd:browser-password-0123456789\r\n\
+             a=fingerprint:{}\r\na=setup:actpass\r\na=rtcp-mux\r\n\
+             a=rtpmap:0 PCMU/8000\r\n",
+            browser_identity.fingerprint()
+        );
+        let (relay, plain) =
+            WebRtcRelay::offer("127.0.0.1".parse().unwrap(), gateway_identity, &offer)
+                .await
+                .unwrap();
+        assert!(plain.contains(&format!("m=audio {} RTP/AVP 0", relay.rtp_addr().port())));
+        assert_eq!(relay.local().setup, DtlsSetup::Passive);
+
+        let answer = format!(
+            "v=0\r\no=- 2 2 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+             m=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n",
+            phone.local_addr().unwrap().port()
+        );
+        let answer = relay.answer(&answer).unwrap();
+        assert!(answer.contains("UDP/TLS/RTP/SAVPF"));
+        assert!(answer.contains(&format!("a=fingerprint:{}", gateway_fingerprint)));
+
+        // The browser checks connectivity, then starts the handshake
+        let candidate = relay.local().candidate;
+        let ice = relay.local().ice.clone();
+        let check = binding_request(&format!("{}:brws", ice.ufrag), &ice.pwd);
+        browser.send_to(&check, candidate).await.unwrap();
+        let mut buf = [0u8; 2048];
+        let (len, _) = browser.recv_from(&mut buf).await.unwrap();
+        assert_eq!(PacketKind::classify(&buf[..len]), PacketKind::Stun);
+
+        let (channel, records) =
+            DtlsChannel::new(browser.clone(), Arc::new(Mutex::new(Some(candidate))));
+        let handshake = dtls::handshake(
+            Arc::new(channel),
+            &browser_identity,
+            &gateway_fingerprint,
+            true,
+        );
+        tokio::pin!(handshake);
+        let mut keys = loop {
+            tokio::select! {
+                keys = &mut handshake => break keys.unwrap(),
+                received = browser.recv_from(&mut buf) => {
+                    let (len, _) = received.unwrap();
+                    records.send(buf[..len].to_vec()).unwrap();
+                }
+            }
+        };
+
+        // SRTP from the browser reaches the phone as plain RTP
+        let protected = keys.outbound.protect_rtp(&RTP).unwrap();
+        browser.send_to(&protected, candidate).await.unwrap();
+        let (len, from) = phone.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], &RTP);
+        assert_eq!(from.port(), relay.rtp_addr().port());
+
+        // and the phone's RTP reaches the browser protected
+        phone.send_to(&RTP, from).await.unwrap();
+        let protected = loop {
+            let (len, _) = browser.recv_from(&mut buf).await.unwrap();
+            if PacketKind::classify(&buf[..len]) == PacketKind::Rtp {
+                break buf[..len].to_vec();
+            }
+        };
+        assert_eq!(keys.inbound.unprotect_rtp(&protected).unwrap(), RTP);
+        relay.close();
+    }
+}
@@ -0,0 +1,541 @@
+//! SDP translation between WebRTC and plain RTP legs
+//!
+//! A browser offers `UDP/TLS/RTP/SAVPF` with ICE credentials, a DTLS
+//! fingerprint, `rtcp-mux`, bundling and a good deal of attributes a SIP
+//! trunk has never heard of. [`from_webrtc`] strips that down to an
+//! `RTP/AVP` description pointing at the gateway's relay port and hands back
+//! what the WebRTC leg needs to know about the browser. [`to_webrtc`] goes
+//! the other way, dressing a trunk's plain SDP up as an ICE-lite,
+//! DTLS-SRTP description a browser accepts.
+//!
+//! Only the first audio stream is carried; other streams are rejected with
+//! port 0 so the answer still lines up with the offer.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use super::ice::IceCredentials;
+
+/// Media protocol of WebRTC streams
+pub const WEBRTC_PROTOCOL: &str = "UDP/TLS/RTP/SAVPF";
+
+/// Attributes that only make sense on the WebRTC side
+const WEBRTC_ONLY: &[&str] = &[
+    "ice-ufrag",
+    "ice-pwd",
+    "ice-options",
+    "ice-lite",
+    "fingerprint",
+    "setup",
+    "mid",
+    "group",
+    "msid",
+    "msid-semantic",
+    "ssrc",
+    "ssrc-group",
+    "candidate",
+    "end-of-candidates",
+    "rtcp",
+    "rtcp-mux",
+    "rtcp-rsize",
+    "rtcp-fb",
+    "extmap",
+    "extmap-allow-mixed",
+    "crypto",
+    "sctp-port",
+    "max-message-size",
+];
+
+/// DTLS role negotiated with `a=setup` (RFC 5763)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DtlsSetup {
+    /// Starts the handshake (DTLS client)
+    Active,
+    /// Waits for the handshake (DTLS server)
+    Passive,
+    /// Either, chosen by the answerer; offers must use this
+    ActPass,
+}
+
+impl DtlsSetup {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "active" => Some(DtlsSetup::Active),
+            "passive" => Some(DtlsSetup::Passive),
+            "actpass" => Some(DtlsSetup::ActPass),
+            _ => None,
+        }
+    }
+
+    /// Our role when answering an offer with this role
+    ///
+    /// Given the choice we stay passive, so the browser, which has already
+    /// finished its ICE checks, starts the handshake.
+    pub fn answer(self) -> Self {
+        match self {
+            DtlsSetup::Active | DtlsSetup::ActPass => DtlsSetup::Passive,
+            DtlsSetup::Passive => DtlsSetup::Active,
+        }
+    }
+}
+
+impl fmt::Display for DtlsSetup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DtlsSetup::Active => "active",
+            DtlsSetup::Passive => "passive",
+            DtlsSetup::ActPass => "actpass",
+        })
+    }
+}
+
+/// Our side of a WebRTC leg, advertised by [`to_webrtc`]
+#[derive(Debug, Clone)]
+pub struct LocalWebRtc {
+    pub ice: IceCredentials,
+    /// `a=fingerprint` value of our DTLS certificate
+    pub fingerprint: String,
+    pub setup: DtlsSetup,
+    /// Host candidate browsers send media to
+    pub candidate: SocketAddr,
+    /// `a=mid` of each media section; sections without one use their index
+    pub mids: Vec<String>,
+}
+
+impl LocalWebRtc {
+    /// Parameters for an offer, with `a=setup:actpass`
+    pub fn new(ice: IceCredentials, fingerprint: String, candidate: SocketAddr) -> Self {
+        Self {
+            ice,
+            fingerprint,
+            setup: DtlsSetup::ActPass,
+            candidate,
+            mids: Vec::new(),
+        }
+    }
+
+    /// Parameters for answering a browser's offer, echoing its media IDs
+    pub fn answering(mut self, offer: &RemoteWebRtc) -> Self {
+        self.setup = offer.setup.answer();
+        self.mids = offer.mids.clone();
+        self
+    }
+
+    fn mid(&self, index: usize) -> String {
+        self.mids
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| index.to_string())
+    }
+}
+
+/// The browser's side of a WebRTC leg, read by [`from_webrtc`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteWebRtc {
+    pub ice_ufrag: String,
+    pub ice_pwd: String,
+    /// `a=fingerprint` value the browser's DTLS certificate must match
+    pub fingerprint: String,
+    pub setup: DtlsSetup,
+    /// `a=mid` of each media section, or its index when it has none
+    pub mids: Vec<String>,
+    /// ICE candidates of the audio stream, without the `a=candidate:` prefix
+    pub candidates: Vec<String>,
+    pub rtcp_mux: bool,
+}
+
+/// Whether an SDP body describes WebRTC media
+pub fn is_webrtc(sdp: &str) -> bool {
+    sections(sdp)
+        .1
+        .iter()
+        .any(|section| section.protocol().contains("/TLS/"))
+}
+
+/// Rewrite a plain RTP description for a browser
+///
+/// The first audio stream is moved to the local candidate and given ICE-lite
+/// credentials, the DTLS fingerprint and role, and `rtcp-mux`; SDES keys are
+/// dropped since DTLS-SRTP keys the stream.
+pub fn to_webrtc(sdp: &str, local: &LocalWebRtc) -> Result<String> {
+    let (session, media) = sections(sdp);
+    let audio = audio_index(&media)
+        .ok_or_else(|| anyhow!("SDP has no audio stream to carry over WebRTC"))?;
+    let address = connection(local.candidate.ip());
+
+    let mut out: Vec<String> = Vec::new();
+    for line in &session.lines {
+        if line.starts_with("c=") {
+            out.push(address.clone());
+        } else if !is_webrtc_only(line) {
+            out.push(line.clone());
+        }
+    }
+    out.push("a=ice-lite".to_string());
+    out.push(format!("a=group:BUNDLE {}", local.mid(audio)));
+
+    for (index, section) in media.iter().enumerate() {
+        if index != audio {
+            out.push(section.rejected(WEBRTC_PROTOCOL));
+            out.push(format!("a=mid:{}", local.mid(index)));
+            continue;
+        }
+        out.push(format!(
+            "m={} {} {} {}",
+            section.media_type(),
+            local.candidate.port(),
+            WEBRTC_PROTOCOL,
+            section.formats()
+        ));
+        out.push(address.clone());
+        out.push(format!("a=mid:{}", local.mid(index)));
+        out.push(format!("a=ice-ufrag:{}", local.ice.ufrag));
+        out.push(format!("a=ice-pwd:{}", local.ice.pwd));
+        out.push(format!("a=fingerprint:{}", local.fingerprint));
+        out.push(format!("a=setup:{}", local.setup));
+        out.push("a=rtcp-mux".to_string());
+        out.extend(section.attributes());
+        out.push(format!(
+            "a=candidate:1 1 udp 2130706431 {} {} typ host",
+            local.candidate.ip(),
+            local.candidate.port()
+        ));
+        out.push("a=end-of-candidates".to_string());
+    }
+
+    Ok(join(out))
+}
+
+/// Rewrite a browser's description as plain RTP sent to `rtp`
+///
+/// Returns the plain SDP for the SIP leg together with the browser's ICE and
+/// DTLS parameters.
+pub fn from_webrtc(sdp: &str, rtp: SocketAddr) -> Result<(String, RemoteWebRtc)> {
+    let (session, media) = sections(sdp);
+    let audio = audio_index(&media).ok_or_else(|| anyhow!("WebRTC SDP has no audio stream"))?;
+    let remote = remote_parameters(&session, &media, audio)?;
+
+    let mut out: Vec<String> = Vec::new();
+    let mut connection_written = false;
+    for line in &session.lines {
+        if line.starts_with("c=") || (line.starts_with("t=") && !connection_written) {
+            out.push(connection(rtp.ip()));
+            connection_written = true;
+            if line.starts_with("c=") {
+                continue;
+            }
+        }
+        if !is_webrtc_only(line) {
+            out.push(line.clone());
+        }
+    }
+
+    for (index, section) in media.iter().enumerate() {
+        if index != audio {
+            out.push(section.rejected("RTP/AVP"));
+            continue;
+        }
+        out.push(format!(
+            "m={} {} RTP/AVP {}",
+            section.media_type(),
+            rtp.port(),
+            section.formats()
+        ));
+        out.extend(section.attributes());
+    }
+
+    Ok((join(out), remote))
+}
+
+/// ICE and DTLS parameters of the audio stream, falling back to session
+/// level attributes
+fn remote_parameters(session: &Section, media: &[Section], audio: usize) -> Result<RemoteWebRtc> {
+    let stream = &media[audio];
+    let attribute = |name: &str| {
+        stream
+            .attribute(name)
+            .or_else(|| session.attribute(name))
+            .map(str::to_string)
+    };
+    let missing = |name: &str| anyhow!("WebRTC SDP has no a={} attribute", name);
+
+    Ok(RemoteWebRtc {
+        ice_ufrag: attribute("ice-ufrag").ok_or_else(|| missing("ice-ufrag"))?,
+        ice_pwd: attribute("ice-pwd").ok_or_else(|| missing("ice-pwd"))?,
+        fingerprint: attribute("fingerprint").ok_or_else(|| missing("fingerprint"))?,
+        setup: attribute("setup")
+            .and_then(|setup| DtlsSetup::parse(&setup))
+            .unwrap_or(DtlsSetup::ActPass),
+        mids: media
+            .iter()
+            .enumerate()
+            .map(|(index, section)| {
+                section
+                    .attribute("mid")
+                    .map(str::to_string)
+                    .unwrap_or_else(|| index.to_string())
+            })
+            .collect(),
+        candidates: stream
+            .lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("a=candidate:"))
+            .map(str::to_string)
+            .collect(),
+        rtcp_mux: stream.attribute("rtcp-mux").is_some(),
+    })
+}
+
+/// Lines of the session part or of one media section
+struct Section {
+    lines: Vec<String>,
+}
+
+impl Section {
+    fn media_line(&self) -> Vec<&str> {
+        self.lines
+            .first()
+            .and_then(|line| line.strip_prefix("m="))
+            .map(|line| line.split_whitespace().collect())
+            .unwrap_or_default()
+    }
+
+    fn media_type(&self) -> String {
+        self.media_line().first().copied().unwrap_or("").to_string()
+    }
+
+    fn port(&self) -> u16 {
+        self.media_line()
+            .get(1)
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn protocol(&self) -> String {
+        self.media_line().get(2).copied().unwrap_or("").to_string()
+    }
+
+    fn formats(&self) -> String {
+        self.media_line().get(3..).unwrap_or(&[]).join(" ")
+    }
+
+    /// Value of an `a=` attribute, or an empty string for a flag
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| {
+            let attribute = line.strip_prefix("a=")?;
+            match attribute.split_once(':') {
+                Some((key, value)) if key == name => Some(value),
+                None if attribute == name => Some(""),
+                _ => None,
+            }
+        })
+    }
+
+    /// Attributes of the section that carry over to the other side
+    fn attributes(&self) -> impl Iterator<Item = String> + '_ {
+        self.lines
+            .iter()
+            .skip(1)
+            .filter(|line| line.starts_with("a=") && !is_webrtc_only(line))
+            .cloned()
+    }
+
+    /// The media line with port 0, declining the stream
+    fn rejected(&self, rtp_protocol: &str) -> String {
+        let protocol = self.protocol();
+        let protocol = if protocol.contains("RTP") {
+            rtp_protocol.to_string()
+        } else {
+            protocol
+        };
+        format!("m={} 0 {} {}", self.media_type(), protocol, self.formats())
+    }
+}
+
+/// Split SDP into its session part and media sections
+fn sections(sdp: &str) -> (Section, Vec<Section>) {
+    let mut session = Section { lines: Vec::new() };
+    let mut media: Vec<Section> = Vec::new();
+    for line in sdp.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if line.starts_with("m=") {
+            media.push(Section {
+                lines: vec![line.to_string()],
+            });
+        } else if let Some(section) = media.last_mut() {
+            section.lines.push(line.to_string());
+        } else {
+            session.lines.push(line.to_string());
+        }
+    }
+    (session, media)
+}
+
+/// The first audio stream that was not declined
+fn audio_index(media: &[Section]) -> Option<usize> {
+    media
+        .iter()
+        .position(|section| section.media_type() == "audio" && section.port() != 0)
+}
+
+fn is_webrtc_only(line: &str) -> bool {
+    line.strip_prefix("a=")
+        .map(|attribute| {
+            let name = attribute.split(':').next().unwrap_or(attribute);
+            WEBRTC_ONLY.contains(&name)
+        })
+        .unwrap_or(false)
+}
+
+fn connection(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("c=IN IP4 {}", ip),
+        IpAddr::V6(ip) => format!("c=IN IP6 {}", ip),
+    }
+}
+
+fn join(lines: Vec<String>) -> String {
+    let mut sdp = lines.join("\r\n");
+    sdp.push_str("\r\n");
+    sdp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROWSER_OFFER: &str = "v=0\r\n\
+        o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        t=0 0\r\n\
+        a=group:BUNDLE 0 1\r\n\
+        a=extmap-allow-mixed\r\n\
+        a=msid-semantic: WMS\r\n\
+        m=audio 9 UDP/TLS/RTP/SAVPF 111 0 8 126\r\n\
+        c=IN IP4 0.0.0.0\r\n\
+        a=rtcp:9 IN IP4 0.0.0.0\r\n\
+        a=candidate:842163049 1 udp 1677729535 198.51.100.7 61665 typ srflx raddr 0.0.0.0 rport 0\r\n\
+        a=ice-ufrag:EsAw\r\n\
+        a=ice-pwd:P2uYro0UCOQ4zxjKXaWCBui1\r\n\
+        a=ice-options:trickle\r\n\
+        a=fingerprint:sha-256 D2:FA:0E:C3:22:59:5E:14:95:69:92:3D:13:B4:84:24:2C:C2:A2:C0:3E:FD:34:8E:5E:EA:6F:AF:52:CE:E6:0F\r\n\
+        a=setup:actpass\r\n\
+        a=mid:0\r\n\
+        a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+        a=sendrecv\r\n\
+        a=msid:- 750f4b4e-2d1b-4a3f-9bd1-f2b53e7b2d3c\r\n\
+        a=rtcp-mux\r\n\
+        a=rtpmap:111 opus/48000/2\r\n\
+        a=rtcp-fb:111 transport-cc\r\n\
+        a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+        a=rtpmap:0 PCMU/8000\r\n\
+        a=rtpmap:8 PCMA/8000\r\n\
+        a=rtpmap:126 telephone-event/8000\r\n\
+        a=ssrc:3735928559 cname:4TOk42mSjXCkVIa6\r\n\
+        m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+        c=IN IP4 0.0.0.0\r\n\
+        a=mid:1\r\n\
+        a=rtpmap:96 VP8/90000\r\n";
+
+    const TRUNK_ANSWER: &str = "v=0\r\n\
+        o=carrier 1 1 IN IP4 203.0.113.5\r\n\
+        s=call\r\n\
+        c=IN IP4 203.0.113.5\r\n\
+        t=0 0\r\n\
+        m=audio 30000 RTP/AVP 0 126\r\n\
+        a=rtpmap:0 PCMU/8000\r\n\
+        a=rtpmap:126 telephone-event/8000\r\n\
+        a=ptime:20\r\n\
+        a=sendrecv\r\n\
+        m=video 0 RTP/AVP 96\r\n";
+
+    #[test]
+    fn test_browser_offer_to_plain_rtp() {
+        assert!(is_webrtc(BROWSER_OFFER));
+        let rtp: SocketAddr = "203.0.113.1:20000".parse().unwrap();
+        let (plain, remote) = from_webrtc(BROWSER_OFFER, rtp).unwrap();
+        assert!(!is_webrtc(&plain));
+
+        assert_eq!(
+            plain,
+            "v=0\r\n\
+             o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+             s=-\r\n\
+             c=IN IP4 203.0.113.1\r\n\
+             t=0 0\r\n\
+             m=audio 20000 RTP/AVP 111 0 8 126\r\n\
+             a=sendrecv\r\n\
+             a=rtpmap:111 opus/48000/2\r\n\
+             a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+             a=rtpmap:0 PCMU/8000\r\n\
+             a=rtpmap:8 PCMA/8000\r\n\
+             a=rtpmap:126 telephone-event/8000\r\n\
+             m=video 0 RTP/AVP 96\r\n"
+        );
+
+        assert_eq!(remote.ice_ufrag, "EsAw");
+        assert_eq!(remote.ice_pwd, "P2uYro0UCOQ4zxjKXaWCBui1");
+        assert!(remote.fingerprint.starts_with("sha-256 D2:FA"));
+        assert_eq!(remote.setup, DtlsSetup::ActPass);
+        assert_eq!(remote.mids, vec!["0", "1"]);
+        assert_eq!(remote.candidates.len(), 1);
+        assert!(remote.rtcp_mux);
+
+        // Plain SDP is not a WebRTC offer
+        assert!(from_webrtc(TRUNK_ANSWER, rtp).is_err());
+    }
+
+    #[test]
+    fn test_plain_answer_to_browser() {
+        let rtp: SocketAddr = "203.0.113.1:20000".parse().unwrap();
+        let (_, remote) = from_webrtc(BROWSER_OFFER, rtp).unwrap();
+        let local = LocalWebRtc::new(
+            IceCredentials {
+                ufrag: "rtlk".to_string(),
+                pwd: "0123456789abcdefghijklmn".to_string(),
+            },
+            "sha-256 AB:CD".to_string(),
+            "203.0.113.1:40000".parse().unwrap(),
+        )
+        .answering(&remote);
+        assert_eq!(local.setup, DtlsSetup::Passive);
+
+        let answer = to_webrtc(TRUNK_ANSWER, &local).unwrap();
+        assert!(is_webrtc(&answer));
+        assert_eq!(
+            answer,
+            "v=0\r\n\
+             o=carrier 1 1 IN IP4 203.0.113.5\r\n\
+             s=call\r\n\
+             c=IN IP4 203.0.113.1\r\n\
+             t=0 0\r\n\
+             a=ice-lite\r\n\
+             a=group:BUNDLE 0\r\n\
+             m=audio 40000 UDP/TLS/RTP/SAVPF 0 126\r\n\
+             c=IN IP4 203.0.113.1\r\n\
+             a=mid:0\r\n\
+             a=ice-ufrag:rtlk\r\n\
+             a=ice-pwd:0123456789abcdefghijklmn\r\n\
+             a=fingerprint:sha-256 AB:CD\r\n\
+             a=setup:passive\r\n\
+             a=rtcp-mux\r\n\
+             a=rtpmap:0 PCMU/8000\r\n\
+             a=rtpmap:126 telephone-event/8000\r\n\
+             a=ptime:20\r\n\
+             a=sendrecv\r\n\
+             a=candidate:1 1 udp 2130706431 203.0.113.1 40000 typ host\r\n\
+             a=end-of-candidates\r\n\
+             m=video 0 UDP/TLS/RTP/SAVPF 96\r\n\
+             a=mid:1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_dtls_setup_answer() {
+        assert_eq!(DtlsSetup::ActPass.answer(), DtlsSetup::Passive);
+        assert_eq!(DtlsSetup::Active.answer(), DtlsSetup::Passive);
+        assert_eq!(DtlsSetup::Passive.answer(), DtlsSetup::Active);
+        assert_eq!(DtlsSetup::parse("actpass"), Some(DtlsSetup::ActPass));
+        assert_eq!(DtlsSetup::parse("holdconn"), None);
+    }
+}
@@ -0,0 +1,512 @@
+//! Media quality per trunk
+//!
+//! Media flows end to end rather than through the B2BUA, so call quality
+//! is learned from the RTP statistics endpoints report when they hang up:
+//! the `P-RTP-Stat` (or `X-RTP-Stat`) header of a BYE carries the packets
+//! received and lost and the interarrival jitter of the call. Lost packets
+//! are the audio the receiver had to conceal, so the loss rate is also the
+//! concealment rate callers heard.
+//!
+//! The tracker keeps the reports of calls that ended within a sliding time
+//! window per trunk. When a trunk's loss or jitter over its window rises
+//! above the alert threshold, the monitor logs it, raises an alarm on the
+//! event stream and posts it to the outbound webhooks, once per metric
+//! until the trunk recovers.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::events::{AlarmEvent, AlarmSeverity, EventBus, SystemEvent};
+use crate::sip::Request;
+use crate::webhooks::WebhookDispatcher;
+
+/// Headers carrying a call's RTP statistics, in order of preference
+const STAT_HEADERS: &[&str] = &["P-RTP-Stat", "X-RTP-Stat"];
+
+/// Sliding window and alert thresholds
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MediaQualityConfig {
+    /// Calls that ended this long ago or less count towards a trunk
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: u64,
+    /// Share of packets lost, and so concealed, above which a trunk is
+    /// reported degraded
+    #[serde(default = "default_max_loss_percent")]
+    pub max_loss_percent: f64,
+    /// Mean jitter above which a trunk is reported degraded
+    #[serde(default = "default_max_jitter_ms")]
+    pub max_jitter_ms: f64,
+    /// Calls a trunk needs in its window before it is alerted on
+    #[serde(default = "default_min_calls")]
+    pub min_calls: usize,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+fn default_window_seconds() -> u64 {
+    900
+}
+
+fn default_max_loss_percent() -> f64 {
+    2.0
+}
+
+fn default_max_jitter_ms() -> f64 {
+    30.0
+}
+
+fn default_min_calls() -> usize {
+    10
+}
+
+fn default_interval_seconds() -> u64 {
+    60
+}
+
+impl Default for MediaQualityConfig {
+    fn default() -> Self {
+        Self {
+            window_seconds: default_window_seconds(),
+            max_loss_percent: default_max_loss_percent(),
+            max_jitter_ms: default_max_jitter_ms(),
+            min_calls: default_min_calls(),
+            interval_seconds: default_interval_seconds(),
+        }
+    }
+}
+
+/// RTP statistics one endpoint reported for a call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaReport {
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    /// Interarrival jitter
+    pub jitter_ms: u64,
+}
+
+impl MediaReport {
+    /// Parse a `P-RTP-Stat` value, such as
+    /// `PS=1054,OS=168640,PR=1053,OR=168480,PL=0,JI=3,LA=0,DU=21`
+    ///
+    /// Fields may also be separated by `;`. Reports without the packets
+    /// received are not reports.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut report = Self {
+            packets_received: 0,
+            packets_lost: 0,
+            jitter_ms: 0,
+        };
+        let mut received = false;
+        for field in value.split([',', ';']) {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse::<u64>() else {
+                continue;
+            };
+            match key.trim().to_ascii_uppercase().as_str() {
+                "PR" => {
+                    report.packets_received = value;
+                    received = true;
+                }
+                "PL" => report.packets_lost = value,
+                "JI" => report.jitter_ms = value,
+                _ => {}
+            }
+        }
+        received.then_some(report)
+    }
+
+    /// The statistics a request reports, if it carries any
+    pub fn from_request(request: &Request) -> Option<Self> {
+        STAT_HEADERS
+            .iter()
+            .find_map(|name| request.get_header_value(name))
+            .and_then(Self::parse)
+    }
+}
+
+/// Quality of a trunk's calls over its window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityStats {
+    pub trunk: String,
+    /// Calls that reported statistics
+    pub calls: usize,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    /// Lost packets as a share of those sent, which is also the share of
+    /// audio concealed
+    pub loss_percent: f64,
+    /// Jitter averaged over the calls
+    pub mean_jitter_ms: f64,
+    pub max_jitter_ms: u64,
+}
+
+impl QualityStats {
+    fn from_reports(trunk: &str, reports: &VecDeque<(DateTime<Utc>, MediaReport)>) -> Self {
+        let calls = reports.len();
+        let received: u64 = reports.iter().map(|(_, r)| r.packets_received).sum();
+        let lost: u64 = reports.iter().map(|(_, r)| r.packets_lost).sum();
+        let jitter: u64 = reports.iter().map(|(_, r)| r.jitter_ms).sum();
+        Self {
+            trunk: trunk.to_string(),
+            calls,
+            packets_received: received,
+            packets_lost: lost,
+            loss_percent: match received + lost {
+                0 => 0.0,
+                sent => lost as f64 * 100.0 / sent as f64,
+            },
+            mean_jitter_ms: match calls {
+                0 => 0.0,
+                calls => jitter as f64 / calls as f64,
+            },
+            max_jitter_ms: reports.iter().map(|(_, r)| r.jitter_ms).max().unwrap_or(0),
+        }
+    }
+}
+
+/// What a trunk is alerted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityMetric {
+    Loss,
+    Jitter,
+}
+
+impl fmt::Display for QualityMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QualityMetric::Loss => "packet loss",
+            QualityMetric::Jitter => "jitter",
+        })
+    }
+}
+
+/// A trunk whose loss or jitter has crossed its alert threshold
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityAlert {
+    pub trunk: String,
+    pub metric: QualityMetric,
+    pub value: f64,
+    pub threshold: f64,
+    pub calls: usize,
+}
+
+impl QualityAlert {
+    pub fn message(&self) -> String {
+        let unit = match self.metric {
+            QualityMetric::Loss => "%",
+            QualityMetric::Jitter => " ms",
+        };
+        format!(
+            "Media quality on {} degraded: {} {:.1}{} over {} calls, threshold {}{}",
+            self.trunk, self.metric, self.value, unit, self.calls, self.threshold, unit
+        )
+    }
+
+    /// Webhook payload
+    pub fn to_event(&self) -> Value {
+        json!({
+            "kind": "media_quality_degraded",
+            "trunk": self.trunk,
+            "metric": self.metric,
+            "value": self.value,
+            "threshold": self.threshold,
+            "calls": self.calls,
+            "message": self.message(),
+            "timestamp": Utc::now(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct TrackerState {
+    trunks: BTreeMap<String, VecDeque<(DateTime<Utc>, MediaReport)>>,
+    /// Trunks and metrics alerted on and not yet recovered
+    degraded: HashSet<(String, QualityMetric)>,
+}
+
+/// Recent media reports per trunk, shared between the B2BUA and the API
+#[derive(Clone)]
+pub struct MediaQualityTracker {
+    config: MediaQualityConfig,
+    state: Arc<Mutex<TrackerState>>,
+    events: Option<EventBus>,
+}
+
+impl MediaQualityTracker {
+    pub fn new(config: MediaQualityConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(TrackerState::default())),
+            events: None,
+        }
+    }
+
+    /// Raise an alarm for each degraded trunk
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn config(&self) -> &MediaQualityConfig {
+        &self.config
+    }
+
+    /// Record the statistics of a call sent to `trunk`
+    pub fn record(&self, trunk: &str, report: MediaReport) {
+        self.record_at(trunk, report, Utc::now());
+    }
+
+    fn record_at(&self, trunk: &str, report: MediaReport, at: DateTime<Utc>) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .trunks
+            .entry(trunk.to_string())
+            .or_default()
+            .push_back((at, report));
+    }
+
+    /// Quality per trunk over the window, by trunk
+    pub fn trunks(&self) -> Vec<QualityStats> {
+        self.trunks_at(Utc::now())
+    }
+
+    fn trunks_at(&self, now: DateTime<Utc>) -> Vec<QualityStats> {
+        let window = chrono::Duration::seconds(self.config.window_seconds as i64);
+        let mut state = self.state.lock().unwrap();
+        state.trunks.retain(|_, reports| {
+            while reports.front().is_some_and(|(at, _)| now - *at > window) {
+                reports.pop_front();
+            }
+            !reports.is_empty()
+        });
+        state
+            .trunks
+            .iter()
+            .map(|(trunk, reports)| QualityStats::from_reports(trunk, reports))
+            .collect()
+    }
+
+    /// Trunks currently over an alert threshold, with the metrics they
+    /// are over on
+    pub fn degraded(&self) -> Vec<(String, QualityMetric)> {
+        let mut degraded: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .degraded
+            .iter()
+            .cloned()
+            .collect();
+        degraded.sort_by(|a, b| a.0.cmp(&b.0).then((a.1 as u8).cmp(&(b.1 as u8))));
+        degraded
+    }
+
+    /// Compare each trunk with the alert thresholds, returning the trunks
+    /// that have degraded since the last check
+    pub fn check(&self) -> Vec<QualityAlert> {
+        self.check_at(Utc::now())
+    }
+
+    fn check_at(&self, now: DateTime<Utc>) -> Vec<QualityAlert> {
+        let stats = self.trunks_at(now);
+        let mut state = self.state.lock().unwrap();
+        // Trunks with no calls left in the window have nothing to judge
+        state
+            .degraded
+            .retain(|(trunk, _)| stats.iter().any(|s| &s.trunk == trunk));
+        let mut alerts = Vec::new();
+        for stats in stats {
+            if stats.calls < self.config.min_calls {
+                continue;
+            }
+            for (metric, value, threshold) in [
+                (
+                    QualityMetric::Loss,
+                    stats.loss_percent,
+                    self.config.max_loss_percent,
+                ),
+                (
+                    QualityMetric::Jitter,
+                    stats.mean_jitter_ms,
+                    self.config.max_jitter_ms,
+                ),
+            ] {
+                let key = (stats.trunk.clone(), metric);
+                if value > threshold {
+                    if state.degraded.insert(key) {
+                        alerts.push(QualityAlert {
+                            trunk: stats.trunk.clone(),
+                            metric,
+                            value,
+                            threshold,
+                            calls: stats.calls,
+                        });
+                    }
+                } else if state.degraded.remove(&key) {
+                    info!(
+                        "Media quality on {} recovered: {} {:.1}",
+                        stats.trunk, metric, value
+                    );
+                }
+            }
+        }
+        alerts
+    }
+
+    /// Check on the configured interval, logging each degraded trunk and
+    /// posting it to the webhooks that want media quality alerts
+    pub fn spawn(&self, webhooks: Option<WebhookDispatcher>) -> JoinHandle<()> {
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(tracker.config.interval_seconds.max(1)));
+            loop {
+                interval.tick().await;
+                for alert in tracker.check() {
+                    warn!("{}", alert.message());
+                    if let Some(bus) = &tracker.events {
+                        bus.publish_system(SystemEvent::Alarm(AlarmEvent::new(
+                            AlarmSeverity::Warning,
+                            "media_quality",
+                            alert.trunk.as_str(),
+                            alert.message(),
+                        )));
+                    }
+                    let Some(dispatcher) = &webhooks else {
+                        continue;
+                    };
+                    let payload = alert.to_event();
+                    for endpoint in dispatcher.endpoints() {
+                        if !endpoint.quality_alerts {
+                            continue;
+                        }
+                        if let Err(e) = dispatcher.deliver_value(endpoint, payload.clone()).await {
+                            warn!("Webhook {} failed: {:#}", endpoint.name, e);
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Default for MediaQualityTracker {
+    fn default() -> Self {
+        Self::new(MediaQualityConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(received: u64, lost: u64, jitter_ms: u64) -> MediaReport {
+        MediaReport {
+            packets_received: received,
+            packets_lost: lost,
+            jitter_ms,
+        }
+    }
+
+    #[test]
+    fn test_parse_rtp_stat() {
+        assert_eq!(
+            MediaReport::parse("PS=1054,OS=168640,PR=1053,OR=168480,PL=12,JI=7,LA=0,DU=21"),
+            Some(report(1053, 12, 7))
+        );
+        assert_eq!(
+            MediaReport::parse("PS=10; PR=9; PL=1"),
+            Some(report(9, 1, 0))
+        );
+        assert_eq!(MediaReport::parse("PS=1054,OS=168640"), None);
+
+        let request = Request::new(
+            crate::sip::Method::Bye,
+            crate::sip::Uri::new("sip".to_string(), "carrier.example".to_string()),
+        )
+        .with_header("X-RTP-Stat", "PR=100,PL=0,JI=2");
+        assert_eq!(MediaReport::from_request(&request), Some(report(100, 0, 2)));
+    }
+
+    #[test]
+    fn test_sliding_window_per_trunk() {
+        let tracker = MediaQualityTracker::new(MediaQualityConfig {
+            window_seconds: 600,
+            ..MediaQualityConfig::default()
+        });
+        let now = Utc::now();
+        let old = now - chrono::Duration::seconds(700);
+        tracker.record_at("carrier.example", report(1000, 1000, 90), old);
+        tracker.record_at("carrier.example", report(990, 10, 10), now);
+        tracker.record_at("carrier.example", report(2000, 0, 20), now);
+        tracker.record_at("gone.example", report(100, 0, 0), old);
+
+        // Calls older than the window no longer count, nor do trunks with
+        // none left
+        let trunks = tracker.trunks_at(now);
+        assert_eq!(trunks.len(), 1);
+        let carrier = &trunks[0];
+        assert_eq!(carrier.trunk, "carrier.example");
+        assert_eq!(carrier.calls, 2);
+        assert_eq!(carrier.packets_lost, 10);
+        assert!((carrier.loss_percent - 10.0 * 100.0 / 3000.0).abs() < 1e-9);
+        assert_eq!(carrier.mean_jitter_ms, 15.0);
+        assert_eq!(carrier.max_jitter_ms, 20);
+    }
+
+    #[test]
+    fn test_alerts_once_per_metric_until_recovered() {
+        let tracker = MediaQualityTracker::new(MediaQualityConfig {
+            window_seconds: 600,
+            max_loss_percent: 1.0,
+            max_jitter_ms: 30.0,
+            min_calls: 3,
+            ..MediaQualityConfig::default()
+        });
+        let start = Utc::now();
+        for _ in 0..2 {
+            tracker.record_at("lossy.example", report(950, 50, 40), start);
+        }
+        // Too few calls to judge
+        assert!(tracker.check_at(start).is_empty());
+
+        tracker.record_at("lossy.example", report(950, 50, 40), start);
+        let alerts = tracker.check_at(start);
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].metric, QualityMetric::Loss);
+        assert_eq!(alerts[0].value, 5.0);
+        assert_eq!(alerts[0].to_event()["kind"], "media_quality_degraded");
+        assert_eq!(alerts[1].metric, QualityMetric::Jitter);
+        assert!(tracker.check_at(start).is_empty());
+        assert_eq!(
+            tracker.degraded(),
+            [
+                ("lossy.example".to_string(), QualityMetric::Loss),
+                ("lossy.example".to_string(), QualityMetric::Jitter),
+            ]
+        );
+
+        // Once the bad calls slide out of the window, good ones recover it
+        let later = start + chrono::Duration::seconds(700);
+        for _ in 0..3 {
+            tracker.record_at("lossy.example", report(1000, 0, 40), later);
+        }
+        let alerts = tracker.check_at(later);
+        assert!(alerts.is_empty());
+        assert_eq!(
+            tracker.degraded(),
+            [("lossy.example".to_string(), QualityMetric::Jitter)]
+        );
+    }
+}
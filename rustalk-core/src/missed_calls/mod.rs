@@ -0,0 +1,220 @@
+//! Missed call notifications
+//!
+//! When a call to an extension ends without being answered, and was not
+//! picked up on another phone (a CANCEL carrying `Reason: SIP;cause=200`),
+//! the B2BUA publishes a `missed` call event with the caller ID, the time
+//! and the number dialed. Webhooks and the API's event stream receive it
+//! like any other event; extensions with an email address configured here
+//! are also emailed.
+//!
+//! A callee counts as an extension when it is listed here or registered
+//! with the registrar.
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::events::{CallEvent, CallEventKind, EventBus};
+use crate::fax::{Mailer, SmtpConfig, SmtpMailer};
+
+/// Missed call settings for one extension
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MissedCallProfile {
+    /// Address emailed about each missed call
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+/// Missed call notification configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MissedCallConfig {
+    /// Settings for each extension number
+    #[serde(default)]
+    pub extensions: HashMap<String, MissedCallProfile>,
+    /// Mail server for email notifications
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+}
+
+impl MissedCallConfig {
+    /// Email address notified of missed calls to `extension`
+    pub fn email_for(&self, extension: &str) -> Option<&str> {
+        self.extensions
+            .get(extension)
+            .and_then(|profile| profile.email.as_deref())
+    }
+}
+
+/// Emails missed call events to the extensions that want them
+#[derive(Clone)]
+pub struct MissedCallNotifier {
+    config: Arc<MissedCallConfig>,
+    mailer: Option<Arc<dyn Mailer>>,
+}
+
+impl MissedCallNotifier {
+    pub fn new(config: MissedCallConfig) -> Result<Self> {
+        let mailer = match &config.smtp {
+            Some(smtp) => Some(Arc::new(
+                SmtpMailer::new(smtp).context("invalid missed call mail settings")?,
+            ) as Arc<dyn Mailer>),
+            None => None,
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            mailer,
+        })
+    }
+
+    /// Notifier sending through `mailer` instead of SMTP
+    pub fn with_mailer(config: MissedCallConfig, mailer: Arc<dyn Mailer>) -> Self {
+        Self {
+            config: Arc::new(config),
+            mailer: Some(mailer),
+        }
+    }
+
+    /// Email the callee about a missed call, returning whether an email was
+    /// sent
+    pub async fn notify(&self, event: &CallEvent) -> Result<bool> {
+        if event.kind != CallEventKind::Missed {
+            return Ok(false);
+        }
+        let Some(extension) = event.callee.as_deref() else {
+            return Ok(false);
+        };
+        let (Some(mailer), Some(to)) = (&self.mailer, self.config.email_for(extension)) else {
+            return Ok(false);
+        };
+
+        let caller = event.caller.as_deref().unwrap_or("an unknown number");
+        let subject = format!("Missed call from {}", caller);
+        let mut body = format!(
+            "You missed a call from {} at {}.\n",
+            caller,
+            event.timestamp.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        if let Some(did) = event.did.as_deref().filter(|did| *did != extension) {
+            body.push_str(&format!("The caller dialed {}.\n", did));
+        }
+        mailer.send_text(to, &subject, &body).await?;
+        Ok(true)
+    }
+
+    /// Email every missed call published on `events`
+    pub fn spawn(&self, events: &EventBus) -> JoinHandle<()> {
+        let notifier = self.clone();
+        let mut rx = events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Missed call notifications skipped {} call events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                match notifier.notify(&event).await {
+                    Ok(true) => debug!("Emailed missed call {}", event.call_id),
+                    Ok(false) => {}
+                    Err(e) => warn!("Missed call email for {} failed: {:#}", event.call_id, e),
+                }
+            }
+        })
+    }
+}
+
+/// Whether a CANCEL's `Reason` header says the call was answered on
+/// another phone (RFC 3326 `cause=200`)
+pub fn completed_elsewhere(reason: &str) -> bool {
+    reason.split(',').any(|reason| {
+        let mut params = reason.split(';');
+        let protocol = params.next().unwrap_or("").trim();
+        protocol.eq_ignore_ascii_case("SIP")
+            && params.any(|param| {
+                param.split_once('=').is_some_and(|(name, value)| {
+                    name.trim().eq_ignore_ascii_case("cause") && value.trim() == "200"
+                })
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingMailer {
+        sent: Mutex<Vec<(String, String, String)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Mailer for RecordingMailer {
+        async fn send(&self, _to: &str, _subject: &str, _body: &str, _pdf: Vec<u8>) -> Result<()> {
+            unreachable!("missed calls are sent without attachments")
+        }
+
+        async fn send_text(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((to.to_string(), subject.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_completed_elsewhere() {
+        assert!(completed_elsewhere(
+            "SIP;cause=200;text=\"Call completed elsewhere\""
+        ));
+        assert!(completed_elsewhere("Q.850;cause=16, SIP ;cause=200"));
+        assert!(!completed_elsewhere(
+            "SIP;cause=487;text=\"Request terminated\""
+        ));
+        assert!(!completed_elsewhere("Q.850;cause=200"));
+    }
+
+    #[tokio::test]
+    async fn test_missed_call_email() {
+        let config: MissedCallConfig = serde_json::from_value(serde_json::json!({
+            "extensions": { "1001": { "email": "alice@example.com" }, "1002": {} }
+        }))
+        .unwrap();
+        let mailer = Arc::new(RecordingMailer::default());
+        let notifier = MissedCallNotifier::with_mailer(config, mailer.clone());
+
+        let missed = |callee: &str| CallEvent {
+            kind: CallEventKind::Missed,
+            call_id: "missed-1".to_string(),
+            caller: Some("+12125551234".to_string()),
+            callee: Some(callee.to_string()),
+            timestamp: Utc::now(),
+            did: Some("+12125550100".to_string()),
+            crm: None,
+        };
+        assert!(notifier.notify(&missed("1001")).await.unwrap());
+        // No email configured
+        assert!(!notifier.notify(&missed("1002")).await.unwrap());
+        // Only missed calls are emailed
+        let hangup = CallEvent {
+            kind: CallEventKind::Hangup,
+            ..missed("1001")
+        };
+        assert!(!notifier.notify(&hangup).await.unwrap());
+
+        let sent = mailer.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "alice@example.com");
+        assert_eq!(sent[0].1, "Missed call from +12125551234");
+        assert!(sent[0].2.contains("dialed +12125550100"));
+    }
+}
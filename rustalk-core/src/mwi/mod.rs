@@ -0,0 +1,686 @@
+//! Message waiting indication (RFC 3842)
+//!
+//! Phones light their message waiting lamp from NOTIFYs carrying an
+//! `application/simple-message-summary` body. A phone asks for them with a
+//! SUBSCRIBE to `message-summary` for its mailbox, which lasts until its
+//! Expires runs out unless the phone refreshes it; the current state is
+//! sent as soon as the subscription is accepted and again whenever the
+//! mailbox changes.
+//!
+//! Many phones never subscribe and expect to be told anyway. With
+//! `implicit_on_register` each registered contact of a mailbox's extension
+//! is also sent an unsolicited NOTIFY when it registers and when the
+//! mailbox changes, unless it holds a subscription of its own.
+
+use crate::b2bua::fork::contact_address;
+use crate::b2bua::OutboundRequest;
+use crate::call_trace::uri_user;
+use crate::registrar::{Registrar, Registration};
+use crate::sip::builder::{header_tag, new_tag, DialogContext, LocalProfile, MessageBuilder};
+use crate::sip::{Method, Request, Response, StatusCode, Uri};
+use crate::voicemail::{MwiStatus, VoicemailManager};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Event package of message waiting subscriptions
+pub const EVENT: &str = "message-summary";
+
+/// Content type of NOTIFY bodies
+pub const CONTENT_TYPE: &str = "application/simple-message-summary";
+
+/// Subscription lifetimes and unsolicited notification
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MwiConfig {
+    /// Notify registered contacts that never subscribed
+    #[serde(default = "enabled")]
+    pub implicit_on_register: bool,
+    /// Lifetime of a subscription whose SUBSCRIBE has no Expires
+    #[serde(default = "default_expires")]
+    pub default_expires: u32,
+    /// Shortest lifetime accepted; shorter ones are refused with 423
+    #[serde(default = "default_min_expires")]
+    pub min_expires: u32,
+    /// Longest lifetime granted; longer ones are cut down to it
+    #[serde(default = "default_max_expires")]
+    pub max_expires: u32,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_expires() -> u32 {
+    3600
+}
+
+fn default_min_expires() -> u32 {
+    60
+}
+
+fn default_max_expires() -> u32 {
+    7200
+}
+
+impl Default for MwiConfig {
+    fn default() -> Self {
+        Self {
+            implicit_on_register: true,
+            default_expires: default_expires(),
+            min_expires: default_min_expires(),
+            max_expires: default_max_expires(),
+        }
+    }
+}
+
+/// Body of a NOTIFY for `status` (RFC 3842 section 5.2)
+pub fn message_summary(status: &MwiStatus, account: &str) -> String {
+    format!(
+        "Messages-Waiting: {}\r\nMessage-Account: {}\r\nVoice-Message: {}/{} ({}/{})\r\n",
+        if status.messages_waiting() {
+            "yes"
+        } else {
+            "no"
+        },
+        account,
+        status.new_messages,
+        status.old_messages,
+        status.new_urgent,
+        status.old_urgent
+    )
+}
+
+/// A subscription accepted from a phone
+#[derive(Debug, Clone)]
+struct Subscription {
+    mailbox_id: String,
+    /// Address of the mailbox, sent as the Message-Account
+    account: String,
+    dialog: DialogContext,
+    destination: SocketAddr,
+    expires_at: Instant,
+}
+
+impl Subscription {
+    fn remaining(&self) -> u64 {
+        self.expires_at
+            .saturating_duration_since(Instant::now())
+            .as_secs()
+    }
+}
+
+/// A message waiting subscription, as reported by the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriptionInfo {
+    pub mailbox_id: String,
+    pub call_id: String,
+    pub destination: SocketAddr,
+    pub expires_in: u64,
+}
+
+/// Sends message waiting NOTIFYs to phones and keeps their subscriptions
+#[derive(Clone)]
+pub struct MwiNotifier {
+    config: MwiConfig,
+    voicemail: VoicemailManager,
+    registrar: Option<Registrar>,
+    builder: MessageBuilder,
+    sink: mpsc::UnboundedSender<OutboundRequest>,
+    /// Subscriptions by Call-ID
+    subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+}
+
+impl MwiNotifier {
+    /// Notify through `sink`, naming `local_addr` in Via and Contact
+    pub fn new(
+        config: MwiConfig,
+        voicemail: VoicemailManager,
+        sink: mpsc::UnboundedSender<OutboundRequest>,
+        local_addr: SocketAddr,
+    ) -> Self {
+        let profile = LocalProfile::new("UDP", local_addr.ip().to_string(), local_addr.port());
+        Self {
+            config,
+            voicemail,
+            registrar: None,
+            builder: MessageBuilder::new(profile),
+            sink,
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Notify the registered contacts of mailboxes' extensions without
+    /// waiting for them to subscribe
+    pub fn with_registrar(mut self, registrar: Registrar) -> Self {
+        self.registrar = Some(registrar);
+        self
+    }
+
+    pub fn config(&self) -> &MwiConfig {
+        &self.config
+    }
+
+    /// Subscriptions that have not expired
+    pub fn subscriptions(&self) -> Vec<SubscriptionInfo> {
+        let mut subscriptions: Vec<_> = self
+            .live_subscriptions()
+            .into_iter()
+            .map(|s| SubscriptionInfo {
+                mailbox_id: s.mailbox_id.clone(),
+                call_id: s.dialog.call_id.clone(),
+                destination: s.destination,
+                expires_in: s.remaining(),
+            })
+            .collect();
+        subscriptions.sort_by(|a, b| (&a.mailbox_id, &a.call_id).cmp(&(&b.mailbox_id, &b.call_id)));
+        subscriptions
+    }
+
+    /// Answer a SUBSCRIBE, sending the mailbox's state when it is accepted
+    ///
+    /// A new subscription needs a mailbox whose ID or extension is the user
+    /// of the Request-URI. A SUBSCRIBE inside an existing subscription
+    /// refreshes it, or with `Expires: 0` ends it.
+    pub async fn subscribe(&self, request: &Request, source: Option<SocketAddr>) -> Response {
+        let event = request
+            .get_header_value("Event")
+            .and_then(|event| event.split(';').next())
+            .map(str::trim);
+        if !event.is_some_and(|event| event.eq_ignore_ascii_case(EVENT)) {
+            return self
+                .builder
+                .response(request, StatusCode::BAD_EVENT)
+                .header("Allow-Events", EVENT)
+                .build();
+        }
+
+        let expires = match request.get_header_value("Expires") {
+            Some(value) => match value.trim().parse::<u32>() {
+                Ok(expires) => expires,
+                Err(_) => {
+                    return self
+                        .builder
+                        .response(request, StatusCode::BAD_REQUEST)
+                        .build()
+                }
+            },
+            None => self.config.default_expires,
+        };
+        if expires > 0 && expires < self.config.min_expires {
+            return self
+                .builder
+                .response(request, StatusCode::INTERVAL_TOO_BRIEF)
+                .header("Min-Expires", self.config.min_expires.to_string())
+                .build();
+        }
+        let expires = expires.min(self.config.max_expires);
+
+        let call_id = request.get_header_value("Call-ID").unwrap_or_default();
+        let to_tag = request.get_header_value("To").and_then(header_tag);
+        let subscription = match to_tag {
+            // A refresh, or the end of a subscription
+            Some(tag) => {
+                let existing = self
+                    .subscriptions
+                    .lock()
+                    .unwrap()
+                    .get(call_id)
+                    .filter(|s| s.dialog.local_tag == tag)
+                    .cloned();
+                let Some(mut subscription) = existing else {
+                    return self
+                        .builder
+                        .response(request, StatusCode::CALL_DOES_NOT_EXIST)
+                        .build();
+                };
+                if let Some(target) = contact_uri(request) {
+                    subscription.dialog.remote_target = target;
+                }
+                subscription
+            }
+            None => {
+                let Some(mailbox) = self.mailbox_for(request).await else {
+                    return self
+                        .builder
+                        .response(request, StatusCode::NOT_FOUND)
+                        .build();
+                };
+                let Some(subscription) = self.new_subscription(request, source, mailbox) else {
+                    return self
+                        .builder
+                        .response(request, StatusCode::BAD_REQUEST)
+                        .build();
+                };
+                subscription
+            }
+        };
+
+        let mut response = self.builder.response(request, StatusCode::OK);
+        // The To of a refresh already carries our tag
+        if to_tag.is_none() {
+            response = response.set_header(
+                "To",
+                format!(
+                    "{};tag={}",
+                    request.get_header_value("To").unwrap_or_default(),
+                    subscription.dialog.local_tag
+                ),
+            );
+        }
+        let response = response
+            .header("Contact", self.builder.profile().contact())
+            .header("Expires", expires.to_string())
+            .build();
+
+        let mut subscription = subscription;
+        if expires == 0 {
+            self.subscriptions.lock().unwrap().remove(call_id);
+            info!("MWI subscription {} ended", call_id);
+            self.send_notify(&mut subscription, "terminated;reason=timeout")
+                .await;
+        } else {
+            subscription.expires_at = Instant::now() + Duration::from_secs(expires.into());
+            info!(
+                "MWI subscription {} to mailbox {} for {}s",
+                call_id, subscription.mailbox_id, expires
+            );
+            self.send_notify(&mut subscription, &format!("active;expires={}", expires))
+                .await;
+            self.subscriptions
+                .lock()
+                .unwrap()
+                .insert(call_id.to_string(), subscription);
+        }
+        response
+    }
+
+    /// Send the mailbox state to the registered contacts of `extension`
+    /// that have no subscription of their own
+    pub async fn registered(&self, extension: &str) {
+        if !self.config.implicit_on_register {
+            return;
+        }
+        let Some(mailbox) = self.voicemail.find_mailbox(extension).await else {
+            return;
+        };
+        let status = self.voicemail.get_mwi_status(&mailbox.id).await;
+        self.notify_contacts(&mailbox.id, &mailbox.extension, &status)
+            .await;
+    }
+
+    /// Send a mailbox's state to everyone following it
+    pub async fn mailbox_changed(&self, mailbox_id: &str) {
+        let status = self.voicemail.get_mwi_status(mailbox_id).await;
+        let mut subscriptions: Vec<Subscription> = self
+            .live_subscriptions()
+            .into_iter()
+            .filter(|s| s.mailbox_id == mailbox_id)
+            .collect();
+        for subscription in &mut subscriptions {
+            let state = format!("active;expires={}", subscription.remaining());
+            self.send(subscription, &state, &status);
+        }
+        // Keep the CSeq each NOTIFY advanced
+        {
+            let mut table = self.subscriptions.lock().unwrap();
+            for subscription in subscriptions {
+                if let Some(current) = table.get_mut(&subscription.dialog.call_id) {
+                    current.dialog.cseq = current.dialog.cseq.max(subscription.dialog.cseq);
+                }
+            }
+        }
+
+        if self.config.implicit_on_register {
+            if let Some(mailbox) = self.voicemail.get_mailbox(mailbox_id).await {
+                self.notify_contacts(mailbox_id, &mailbox.extension, &status)
+                    .await;
+            }
+        }
+    }
+
+    /// Drop the subscription a failed NOTIFY belonged to
+    ///
+    /// A phone that answers 481 or another failure has forgotten the
+    /// subscription or cannot take the event, so it is not notified again.
+    pub fn notify_failed(&self, response: &Response) {
+        if response.status_code.0 < 300 || matches!(response.status_code.0, 401 | 407) {
+            return;
+        }
+        let Some(call_id) = response.get_header_value("Call-ID") else {
+            return;
+        };
+        if self.subscriptions.lock().unwrap().remove(call_id).is_some() {
+            warn!(
+                "MWI subscription {} ended by {} to NOTIFY",
+                call_id, response.status_code
+            );
+        }
+    }
+
+    /// Notify subscribers and registered contacts as mailboxes change
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let notifier = self.clone();
+        let mut changes = self.voicemail.subscribe_changes();
+        tokio::spawn(async move {
+            loop {
+                match changes.recv().await {
+                    Ok(mailbox_id) => notifier.mailbox_changed(&mailbox_id).await,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("MWI missed {} mailbox change(s)", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Mailbox a SUBSCRIBE is for
+    async fn mailbox_for(&self, request: &Request) -> Option<crate::voicemail::VoicemailBox> {
+        let user = request
+            .uri
+            .user
+            .as_deref()
+            .or_else(|| request.get_header_value("To").and_then(uri_user))?;
+        self.voicemail.find_mailbox(user).await
+    }
+
+    fn new_subscription(
+        &self,
+        request: &Request,
+        source: Option<SocketAddr>,
+        mailbox: crate::voicemail::VoicemailBox,
+    ) -> Option<Subscription> {
+        let from = request.get_header_value("From")?;
+        let remote_target = contact_uri(request).or_else(|| addr_spec(from).parse().ok())?;
+        let destination = source.or_else(|| contact_address(&remote_target))?;
+        let account = request.uri.to_string();
+        let dialog = DialogContext {
+            call_id: request.get_header_value("Call-ID")?.to_string(),
+            local_uri: account.clone(),
+            local_tag: new_tag(),
+            remote_uri: addr_spec(from).to_string(),
+            remote_tag: header_tag(from).map(str::to_string),
+            remote_target,
+            cseq: 0,
+            route_set: Vec::new(),
+        };
+        Some(Subscription {
+            mailbox_id: mailbox.id,
+            account,
+            dialog,
+            destination,
+            expires_at: Instant::now(),
+        })
+    }
+
+    /// Subscriptions that have not expired, forgetting the others
+    fn live_subscriptions(&self) -> Vec<Subscription> {
+        let now = Instant::now();
+        let mut table = self.subscriptions.lock().unwrap();
+        table.retain(|call_id, s| {
+            let live = s.expires_at > now;
+            if !live {
+                debug!("MWI subscription {} expired", call_id);
+            }
+            live
+        });
+        table.values().cloned().collect()
+    }
+
+    async fn send_notify(&self, subscription: &mut Subscription, state: &str) {
+        let status = self
+            .voicemail
+            .get_mwi_status(&subscription.mailbox_id)
+            .await;
+        self.send(subscription, state, &status);
+    }
+
+    /// NOTIFY inside a subscription
+    fn send(&self, subscription: &mut Subscription, state: &str, status: &MwiStatus) {
+        let request = self
+            .builder
+            .in_dialog(Method::Notify, &mut subscription.dialog)
+            .header("Contact", self.builder.profile().contact())
+            .header("Event", EVENT)
+            .header("Subscription-State", state)
+            .body(CONTENT_TYPE, message_summary(status, &subscription.account))
+            .build();
+        self.deliver(request, subscription.destination);
+    }
+
+    /// Unsolicited NOTIFY to each registered contact of `extension` not
+    /// already subscribed to the mailbox
+    async fn notify_contacts(&self, mailbox_id: &str, extension: &str, status: &MwiStatus) {
+        let Some(registrar) = &self.registrar else {
+            return;
+        };
+        let subscribed: Vec<SocketAddr> = self
+            .live_subscriptions()
+            .iter()
+            .filter(|s| s.mailbox_id == mailbox_id)
+            .map(|s| s.destination)
+            .collect();
+        for binding in registrar.bindings().await {
+            if uri_user(&binding.aor) != Some(extension) {
+                continue;
+            }
+            let Some((contact, destination)) = binding_target(&binding) else {
+                continue;
+            };
+            if subscribed.contains(&destination) {
+                continue;
+            }
+            let request = self
+                .builder
+                .request(Method::Notify, contact)
+                .from(format!("<{}>;tag={}", binding.aor, new_tag()))
+                .to(format!("<{}>", binding.aor))
+                .header("Contact", self.builder.profile().contact())
+                .header("Event", EVENT)
+                .header("Subscription-State", "active")
+                .body(CONTENT_TYPE, message_summary(status, &binding.aor))
+                .build();
+            self.deliver(request, destination);
+        }
+    }
+
+    fn deliver(&self, request: Request, destination: SocketAddr) {
+        if self
+            .sink
+            .send(OutboundRequest {
+                request,
+                destination,
+            })
+            .is_err()
+        {
+            debug!("Outbound request receiver dropped");
+        }
+    }
+}
+
+/// Contact URI of a binding and the address to send to it
+fn binding_target(binding: &Registration) -> Option<(Uri, SocketAddr)> {
+    let uri: Uri = binding.contact.parse().ok()?;
+    let destination = binding
+        .received
+        .filter(|_| !binding.send_to_contact)
+        .or_else(|| contact_address(&uri))?;
+    Some((uri, destination))
+}
+
+/// URI of a request's Contact header
+pub(crate) fn contact_uri(request: &Request) -> Option<Uri> {
+    addr_spec(request.get_header_value("Contact")?).parse().ok()
+}
+
+/// URI of a name-addr such as `"Alice" <sip:1001@example.com>;tag=1`
+pub(crate) fn addr_spec(value: &str) -> &str {
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or(value).trim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voicemail::VoicemailBox;
+
+    fn subscribe(expires: Option<&str>) -> Request {
+        let mut request = Request::new(
+            Method::Subscribe,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bK-s1")
+        .with_header("Call-ID", "mwi1")
+        .with_header("From", "<sip:1001@example.com>;tag=phone")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("CSeq", "1 SUBSCRIBE")
+        .with_header("Contact", "<sip:1001@192.0.2.10:5060>")
+        .with_header("Event", "message-summary");
+        if let Some(expires) = expires {
+            request = request.with_header("Expires", expires);
+        }
+        request
+    }
+
+    async fn notifier(
+        dir: &str,
+    ) -> (
+        MwiNotifier,
+        VoicemailManager,
+        mpsc::UnboundedReceiver<OutboundRequest>,
+    ) {
+        let voicemail = VoicemailManager::new(std::env::temp_dir().join(dir));
+        voicemail
+            .add_mailbox(VoicemailBox {
+                id: "1001".to_string(),
+                extension: "1001".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let notifier = MwiNotifier::new(MwiConfig::default(), voicemail.clone(), tx, local);
+        (notifier, voicemail, rx)
+    }
+
+    fn body(outbound: &OutboundRequest) -> String {
+        String::from_utf8_lossy(&outbound.request.body).into_owned()
+    }
+
+    #[test]
+    fn test_message_summary() {
+        let status = MwiStatus {
+            mailbox_id: "1001".to_string(),
+            new_messages: 2,
+            old_messages: 8,
+            total_messages: 10,
+            new_urgent: 1,
+            old_urgent: 0,
+        };
+        assert_eq!(
+            message_summary(&status, "sip:1001@example.com"),
+            "Messages-Waiting: yes\r\nMessage-Account: sip:1001@example.com\r\nVoice-Message: 2/8 (1/0)\r\n"
+        );
+        let read = MwiStatus {
+            new_messages: 0,
+            ..status
+        };
+        assert!(message_summary(&read, "sip:1001@example.com").starts_with("Messages-Waiting: no"));
+    }
+
+    #[tokio::test]
+    async fn test_subscription_notifies() {
+        let (notifier, voicemail, mut rx) = notifier("rustalk_mwi_subscribe_test").await;
+        let source: SocketAddr = "198.51.100.7:5062".parse().unwrap();
+
+        let response = notifier
+            .subscribe(&subscribe(Some("600")), Some(source))
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.get_header_value("Expires"), Some("600"));
+        let tag = response
+            .get_header_value("To")
+            .and_then(header_tag)
+            .unwrap()
+            .to_string();
+
+        // The current state is sent at once, to where the SUBSCRIBE came from
+        let notify = rx.try_recv().unwrap();
+        assert_eq!(notify.destination, source);
+        assert_eq!(notify.request.method, Method::Notify);
+        assert_eq!(notify.request.get_header_value("Event"), Some(EVENT));
+        assert_eq!(
+            notify.request.get_header_value("Subscription-State"),
+            Some("active;expires=600")
+        );
+        assert_eq!(notify.request.get_header_value("Call-ID"), Some("mwi1"));
+        assert_eq!(
+            notify.request.get_header_value("To"),
+            Some("<sip:1001@example.com>;tag=phone")
+        );
+        assert!(body(&notify).starts_with("Messages-Waiting: no"));
+
+        voicemail
+            .leave_message("1001", "5551234", None, b"audio", 5)
+            .await
+            .unwrap();
+        notifier.mailbox_changed("1001").await;
+        let notify = rx.try_recv().unwrap();
+        assert!(body(&notify).contains("Messages-Waiting: yes"));
+        assert!(body(&notify).contains("Voice-Message: 1/0 (0/0)"));
+        assert_eq!(notify.request.get_header_value("CSeq"), Some("2 NOTIFY"));
+        assert_eq!(notifier.subscriptions().len(), 1);
+
+        // Unsubscribing ends the subscription with a final NOTIFY
+        let mut unsubscribe = subscribe(Some("0"));
+        unsubscribe.headers.retain(|h| h.name.as_str() != "To");
+        let unsubscribe =
+            unsubscribe.with_header("To", format!("<sip:1001@example.com>;tag={}", tag).as_str());
+        let response = notifier.subscribe(&unsubscribe, Some(source)).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let notify = rx.try_recv().unwrap();
+        assert_eq!(
+            notify.request.get_header_value("Subscription-State"),
+            Some("terminated;reason=timeout")
+        );
+        assert_eq!(notify.request.get_header_value("CSeq"), Some("3 NOTIFY"));
+        assert!(notifier.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_refused() {
+        let (notifier, _, mut rx) = notifier("rustalk_mwi_refused_test").await;
+
+        let mut presence = subscribe(None);
+        presence.headers.retain(|h| h.name.as_str() != "Event");
+        let presence = presence.with_header("Event", "presence");
+        let response = notifier.subscribe(&presence, None).await;
+        assert_eq!(response.status_code, StatusCode::BAD_EVENT);
+        assert_eq!(response.get_header_value("Allow-Events"), Some(EVENT));
+
+        let response = notifier.subscribe(&subscribe(Some("10")), None).await;
+        assert_eq!(response.status_code, StatusCode::INTERVAL_TOO_BRIEF);
+        assert_eq!(response.get_header_value("Min-Expires"), Some("60"));
+
+        let mut unknown = subscribe(None);
+        unknown.uri.user = Some("2002".to_string());
+        let response = notifier.subscribe(&unknown, None).await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+
+        let mut stale = subscribe(None);
+        stale.headers.retain(|h| h.name.as_str() != "To");
+        let stale = stale.with_header("To", "<sip:1001@example.com>;tag=gone");
+        let response = notifier.subscribe(&stale, None).await;
+        assert_eq!(response.status_code, StatusCode::CALL_DOES_NOT_EXIST);
+        assert!(rx.try_recv().is_err());
+    }
+}
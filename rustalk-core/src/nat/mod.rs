@@ -0,0 +1,424 @@
+//! NAT traversal tunables per SIP profile
+//!
+//! Endpoints behind NAT only stay reachable while the NAT keeps their
+//! pinhole open, and what keeps it open differs by network: a carrier-grade
+//! NAT may drop a mapping after 30 seconds where an office firewall keeps it
+//! for minutes. Each SIP profile can therefore set how often its flows are
+//! pinged, the shortest registration it accepts, and how the addresses its
+//! endpoints advertise are treated. Profiles apply to REGISTERs and calls
+//! from their domain and to connections on their port.
+//!
+//! An endpoint behind NAT advertises its private address in its Contact
+//! and SDP, which nobody else can reach. Each profile says whether those
+//! are rewritten to the address the endpoint's packets came from
+//! `always`, `never`, or on `detect`ion: when the address advertised is a
+//! private one other than the source. `never` suits carriers whose
+//! signalling and media come from different public addresses than they
+//! advertise; `detect` suits a mix of those and NATed phones.
+
+use crate::sip::Request;
+use crate::transport::KeepaliveConfig;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+
+/// NAT settings of one SIP profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NatSettings {
+    /// Seconds between keep-alive pings on the profile's flows, also sent
+    /// to outbound clients as their Flow-Timer; the transport's interval
+    /// when unset
+    #[serde(default)]
+    pub keepalive_interval_seconds: Option<u64>,
+    /// Shortest registration accepted; shorter ones are answered `423
+    /// Interval Too Brief`
+    #[serde(default)]
+    pub min_expires: Option<u32>,
+    /// When requests for registered endpoints go to the address their
+    /// REGISTER came from rather than the Contact they registered, and the
+    /// Contact of calls from them is rewritten to their source address
+    #[serde(default)]
+    pub rewrite_contact: NatRewrite,
+    /// When the SDP connection address of calls from the profile's
+    /// endpoints is rewritten to their source address
+    #[serde(default = "default_rewrite_sdp")]
+    pub rewrite_sdp: NatRewrite,
+}
+
+fn default_rewrite_sdp() -> NatRewrite {
+    NatRewrite::Never
+}
+
+impl Default for NatSettings {
+    fn default() -> Self {
+        Self {
+            keepalive_interval_seconds: None,
+            min_expires: None,
+            rewrite_contact: NatRewrite::default(),
+            rewrite_sdp: default_rewrite_sdp(),
+        }
+    }
+}
+
+/// When a far end's advertised address is replaced by its source address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase", try_from = "RewriteSetting")]
+pub enum NatRewrite {
+    #[default]
+    Always,
+    /// Only when the advertised address is private and not the source
+    Detect,
+    Never,
+}
+
+impl NatRewrite {
+    /// Whether a far end advertising `advertised` from `source` is rewritten
+    pub fn applies(self, advertised: Option<IpAddr>, source: IpAddr) -> bool {
+        match self {
+            NatRewrite::Always => true,
+            NatRewrite::Never => false,
+            NatRewrite::Detect => advertised.is_some_and(|ip| ip != source && is_private(ip)),
+        }
+    }
+}
+
+/// `true` and `false` are the earlier spelling of `always` and `never`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RewriteSetting {
+    Flag(bool),
+    Mode(String),
+}
+
+impl TryFrom<RewriteSetting> for NatRewrite {
+    type Error = String;
+
+    fn try_from(setting: RewriteSetting) -> Result<Self, Self::Error> {
+        match setting {
+            RewriteSetting::Flag(true) => Ok(NatRewrite::Always),
+            RewriteSetting::Flag(false) => Ok(NatRewrite::Never),
+            RewriteSetting::Mode(mode) => match mode.to_ascii_lowercase().as_str() {
+                "always" => Ok(NatRewrite::Always),
+                "detect" => Ok(NatRewrite::Detect),
+                "never" => Ok(NatRewrite::Never),
+                _ => Err(format!(
+                    "unknown NAT rewrite mode '{}', expected always, detect or never",
+                    mode
+                )),
+            },
+        }
+    }
+}
+
+/// `sdp` with the address of its origin and every connection line
+/// replaced by `ip`, everything else kept as it was
+fn rewrite_sdp_address(sdp: &str, ip: IpAddr) -> String {
+    let addr_type = if ip.is_ipv6() { "IP6" } else { "IP4" };
+    sdp.split_inclusive('\n')
+        .map(|line| {
+            let content = line.trim_end_matches(['\r', '\n']);
+            let ending = &line[content.len()..];
+            let Some((kind, value)) = content.split_once('=') else {
+                return line.to_string();
+            };
+            let fields: Vec<&str> = value.split_whitespace().collect();
+            match (kind, fields.len()) {
+                ("c", 3..) => format!("c=IN {} {}{}", addr_type, ip, ending),
+                ("o", 6..) => format!(
+                    "o={} IN {} {}{}",
+                    fields[..3].join(" "),
+                    addr_type,
+                    ip,
+                    ending
+                ),
+                _ => line.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Addresses only reachable inside a site or carrier NAT
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is carrier-grade NAT space (RFC 6598)
+            ip.is_private() || ip.is_link_local() || (a == 100 && (64..128).contains(&b))
+        }
+        // Unique local fc00::/7 and link-local fe80::/10
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Address in a SIP URI or Contact header value, when it is an IP
+pub fn contact_ip(contact: &str) -> Option<IpAddr> {
+    let rest = contact.split_once(':').map(|(_, rest)| rest)?;
+    let host_port = rest.rsplit_once('@').map(|(_, h)| h).unwrap_or(rest);
+    let host_port = host_port.split([';', '>', '?']).next().unwrap_or(host_port);
+    if let Some(host) = host_port.strip_prefix('[') {
+        return host.split(']').next()?.parse().ok();
+    }
+    host_port.split(':').next()?.parse().ok()
+}
+
+/// Rewrite the Contact and SDP addresses `request` advertises to `source`,
+/// as `settings` say
+///
+/// Returns whether anything was rewritten.
+pub fn rewrite_far_end(request: &mut Request, source: SocketAddr, settings: &NatSettings) -> bool {
+    let mut rewritten = false;
+    for header in request.headers.iter_mut() {
+        let name = header.name.as_str();
+        if !(name.eq_ignore_ascii_case("Contact") || name.eq_ignore_ascii_case("m")) {
+            continue;
+        }
+        let value = header.value.as_str();
+        if value.trim() != "*"
+            && settings
+                .rewrite_contact
+                .applies(contact_ip(value), source.ip())
+        {
+            let host = match source.ip() {
+                IpAddr::V6(ip) => format!("[{}]", ip),
+                IpAddr::V4(ip) => ip.to_string(),
+            };
+            let value = crate::transport::interface::rewrite_contact(value, &host, source.port());
+            header.value = value.as_str().into();
+            rewritten = true;
+        }
+    }
+
+    let is_sdp = request
+        .get_header_value("Content-Type")
+        .is_some_and(|ct| ct.eq_ignore_ascii_case("application/sdp"));
+    let body = std::str::from_utf8(&request.body).ok().filter(|_| is_sdp);
+    if let Some(body) = body {
+        let advertised = body
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("c="))
+            .and_then(|c| c.split_whitespace().nth(2))
+            .and_then(|ip| ip.parse().ok());
+        if settings.rewrite_sdp.applies(advertised, source.ip()) {
+            request.body = rewrite_sdp_address(body, source.ip()).into();
+            rewritten = true;
+        }
+    }
+    rewritten
+}
+
+impl NatSettings {
+    /// `base` keep-alive timers with the profile's ping interval
+    pub fn keepalive(&self, base: &KeepaliveConfig) -> KeepaliveConfig {
+        KeepaliveConfig {
+            ping_interval_seconds: self
+                .keepalive_interval_seconds
+                .unwrap_or(base.ping_interval_seconds),
+            ..base.clone()
+        }
+    }
+}
+
+/// A SIP profile's NAT settings and what they apply to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NatProfile {
+    /// SIP profile name
+    pub name: String,
+    /// Domain of the address-of-records registering through the profile
+    pub domain: String,
+    /// Port of the profile's listeners
+    #[serde(default)]
+    pub bind_port: Option<u16>,
+    #[serde(flatten)]
+    pub nat: NatSettings,
+}
+
+/// The `nat` configuration section
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct NatConfig {
+    #[serde(default)]
+    pub profiles: Vec<NatProfile>,
+}
+
+/// NAT settings per SIP profile, shared between the registrar and the API
+#[derive(Debug, Clone, Default)]
+pub struct NatPolicy {
+    profiles: Arc<RwLock<Vec<NatProfile>>>,
+}
+
+impl NatPolicy {
+    pub fn new(config: NatConfig) -> Self {
+        Self {
+            profiles: Arc::new(RwLock::new(config.profiles)),
+        }
+    }
+
+    /// Settings for REGISTERs in `domain`, or the defaults when no profile
+    /// serves it
+    pub fn for_domain(&self, domain: &str) -> NatSettings {
+        self.profile_for_domain(domain).unwrap_or_default()
+    }
+
+    /// Settings of the profile serving `domain`, if any
+    pub fn profile_for_domain(&self, domain: &str) -> Option<NatSettings> {
+        self.profiles
+            .read()
+            .unwrap()
+            .iter()
+            .find(|p| p.domain.eq_ignore_ascii_case(domain))
+            .map(|p| p.nat.clone())
+    }
+
+    /// Settings of the profile listening on `port`, if any
+    pub fn for_port(&self, port: u16) -> Option<NatSettings> {
+        self.profiles
+            .read()
+            .unwrap()
+            .iter()
+            .find(|p| p.bind_port == Some(port))
+            .map(|p| p.nat.clone())
+    }
+
+    /// Add a profile, replacing the one of the same name
+    pub fn set_profile(&self, profile: NatProfile) {
+        let mut profiles = self.profiles.write().unwrap();
+        profiles.retain(|p| p.name != profile.name);
+        profiles.push(profile);
+    }
+
+    /// Drop a profile's settings
+    pub fn remove_profile(&self, name: &str) {
+        self.profiles.write().unwrap().retain(|p| p.name != name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_settings() {
+        let config: NatConfig = serde_json::from_value(serde_json::json!({
+            "profiles": [{
+                "name": "external",
+                "domain": "example.com",
+                "bind_port": 5080,
+                "keepalive_interval_seconds": 25,
+                "min_expires": 120,
+                "rewrite_contact": false
+            }]
+        }))
+        .unwrap();
+        let policy = NatPolicy::new(config);
+
+        let external = policy.for_domain("EXAMPLE.com");
+        assert_eq!(external.min_expires, Some(120));
+        assert_eq!(external.rewrite_contact, NatRewrite::Never);
+        assert_eq!(
+            external
+                .keepalive(&KeepaliveConfig::default())
+                .ping_interval_seconds,
+            25
+        );
+        assert_eq!(policy.for_port(5080), Some(external));
+        assert_eq!(policy.for_port(5060), None);
+        assert_eq!(policy.for_domain("other.example"), NatSettings::default());
+
+        policy.set_profile(NatProfile {
+            name: "external".to_string(),
+            domain: "example.com".to_string(),
+            bind_port: None,
+            nat: NatSettings::default(),
+        });
+        assert_eq!(policy.for_port(5080), None);
+        assert_eq!(
+            policy.for_domain("example.com").rewrite_contact,
+            NatRewrite::Always
+        );
+        policy.remove_profile("external");
+        assert_eq!(policy.for_domain("example.com"), NatSettings::default());
+    }
+
+    #[test]
+    fn test_rewrite_modes() {
+        let settings: NatSettings = serde_json::from_value(serde_json::json!({
+            "rewrite_contact": "detect",
+            "rewrite_sdp": "Detect"
+        }))
+        .unwrap();
+        assert_eq!(settings.rewrite_contact, NatRewrite::Detect);
+        assert_eq!(settings.rewrite_sdp, NatRewrite::Detect);
+        assert!(serde_json::from_value::<NatSettings>(serde_json::json!({
+            "rewrite_contact": "sometimes"
+        }))
+        .is_err());
+
+        let source: IpAddr = "203.0.113.9".parse().unwrap();
+        let private = "192.168.1.20".parse().ok();
+        let public = "198.51.100.7".parse().ok();
+        assert!(NatRewrite::Detect.applies(private, source));
+        assert!(NatRewrite::Detect.applies("100.64.0.9".parse().ok(), source));
+        assert!(NatRewrite::Detect.applies("fd00::1".parse().ok(), source));
+        // A carrier advertising another public address is left alone
+        assert!(!NatRewrite::Detect.applies(public, source));
+        assert!(!NatRewrite::Detect.applies(None, source));
+        assert!(NatRewrite::Always.applies(public, source));
+        assert!(!NatRewrite::Never.applies(private, source));
+    }
+
+    #[test]
+    fn test_rewrite_far_end() {
+        let invite = || {
+            Request::new(
+                crate::sip::Method::Invite,
+                crate::sip::Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Contact", "<sip:1001@192.168.1.20:5062;transport=udp>")
+            .with_header("Content-Type", "application/sdp")
+            .with_body(
+                "v=0\r\no=- 1 1 IN IP4 192.168.1.20\r\ns=-\r\nc=IN IP4 192.168.1.20\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n",
+            )
+        };
+        let source: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+        let settings = NatSettings {
+            rewrite_contact: NatRewrite::Detect,
+            rewrite_sdp: NatRewrite::Detect,
+            ..NatSettings::default()
+        };
+
+        let mut request = invite();
+        assert!(rewrite_far_end(&mut request, source, &settings));
+        assert_eq!(
+            request.get_header_value("Contact"),
+            Some("<sip:1001@203.0.113.9:40000;transport=udp>")
+        );
+        let body = String::from_utf8_lossy(&request.body).to_string();
+        assert_eq!(
+            body,
+            "v=0\r\no=- 1 1 IN IP4 203.0.113.9\r\ns=-\r\nc=IN IP4 203.0.113.9\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n"
+        );
+
+        // The defaults leave the SDP alone
+        let mut request = invite();
+        assert!(rewrite_far_end(
+            &mut request,
+            source,
+            &NatSettings::default()
+        ));
+        assert!(String::from_utf8_lossy(&request.body).contains("c=IN IP4 192.168.1.20"));
+
+        let never = NatSettings {
+            rewrite_contact: NatRewrite::Never,
+            ..NatSettings::default()
+        };
+        let mut request = invite();
+        assert!(!rewrite_far_end(&mut request, source, &never));
+        assert_eq!(
+            request.get_header_value("Contact"),
+            Some("<sip:1001@192.168.1.20:5062;transport=udp>")
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Ring timeouts and no-answer handling
+//!
+//! Rather than leaving it to each phone to give up, the B2BUA stops ringing
+//! an extension after its ring time and applies the extension's no-answer
+//! action: voicemail, forwarding to another number, or a busy tone for the
+//! caller. Extensions are set up in the configuration file and updated
+//! through the extensions API, which shares the same [`NoAnswerPolicy`].
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// What happens to a call nobody answers in time
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum NoAnswerAction {
+    /// Send the caller to the extension's voicemail
+    Voicemail,
+    /// Ring another extension or number instead
+    Forward { destination: String },
+    /// Play the caller a busy tone and hang up
+    #[default]
+    Busy,
+}
+
+impl fmt::Display for NoAnswerAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Voicemail => write!(f, "voicemail"),
+            Self::Forward { destination } => write!(f, "forward to {}", destination),
+            Self::Busy => write!(f, "busy"),
+        }
+    }
+}
+
+/// Ring time and no-answer action of one extension
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NoAnswerProfile {
+    /// Seconds to ring; the configured default when unset
+    #[serde(default)]
+    pub ring_seconds: Option<u32>,
+    /// Action once the ring time is up; the configured default when unset
+    #[serde(default)]
+    pub action: Option<NoAnswerAction>,
+}
+
+/// Ring timeout configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NoAnswerConfig {
+    /// Ring time for extensions that do not set their own
+    #[serde(default = "default_ring_seconds")]
+    pub ring_seconds: u32,
+    /// Action for extensions that do not set their own
+    #[serde(default)]
+    pub action: NoAnswerAction,
+    /// Profile for each extension number; other extensions ring until the
+    /// phone gives up
+    #[serde(default)]
+    pub extensions: HashMap<String, NoAnswerProfile>,
+}
+
+impl Default for NoAnswerConfig {
+    fn default() -> Self {
+        Self {
+            ring_seconds: default_ring_seconds(),
+            action: NoAnswerAction::default(),
+            extensions: HashMap::new(),
+        }
+    }
+}
+
+fn default_ring_seconds() -> u32 {
+    30
+}
+
+/// Ring time and action that apply to a call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingTimeout {
+    pub ring_seconds: u32,
+    pub action: NoAnswerAction,
+}
+
+/// Per-extension ring timeouts, shared between the B2BUA and the API
+#[derive(Debug, Clone, Default)]
+pub struct NoAnswerPolicy {
+    config: Arc<RwLock<NoAnswerConfig>>,
+}
+
+impl NoAnswerPolicy {
+    pub fn new(config: NoAnswerConfig) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    /// Ring time and action for a call to `extension`, if it has a profile
+    pub fn timeout_for(&self, extension: &str) -> Option<RingTimeout> {
+        let config = self.config.read().unwrap();
+        let profile = config.extensions.get(extension)?;
+        Some(RingTimeout {
+            ring_seconds: profile.ring_seconds.unwrap_or(config.ring_seconds),
+            action: profile
+                .action
+                .clone()
+                .unwrap_or_else(|| config.action.clone()),
+        })
+    }
+
+    /// Set an extension's profile
+    pub fn set_extension(&self, extension: &str, profile: NoAnswerProfile) {
+        self.config
+            .write()
+            .unwrap()
+            .extensions
+            .insert(extension.to_string(), profile);
+    }
+
+    /// Leave ringing `extension` to its phones again
+    pub fn remove_extension(&self, extension: &str) {
+        self.config.write().unwrap().extensions.remove(extension);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_defaults_and_updates() {
+        let config: NoAnswerConfig = serde_json::from_value(serde_json::json!({
+            "ring_seconds": 25,
+            "action": { "action": "voicemail" },
+            "extensions": {
+                "1001": {},
+                "1002": { "ring_seconds": 10, "action": { "action": "forward", "destination": "+12125551234" } }
+            }
+        }))
+        .unwrap();
+        let policy = NoAnswerPolicy::new(config);
+
+        assert_eq!(
+            policy.timeout_for("1001"),
+            Some(RingTimeout {
+                ring_seconds: 25,
+                action: NoAnswerAction::Voicemail
+            })
+        );
+        let forward = policy.timeout_for("1002").unwrap();
+        assert_eq!(forward.ring_seconds, 10);
+        assert_eq!(forward.action.to_string(), "forward to +12125551234");
+        assert_eq!(policy.timeout_for("1003"), None);
+
+        // Updates through a clone are seen by the original
+        policy.clone().set_extension(
+            "1003",
+            NoAnswerProfile {
+                ring_seconds: None,
+                action: Some(NoAnswerAction::Busy),
+            },
+        );
+        assert_eq!(policy.timeout_for("1003").unwrap().ring_seconds, 25);
+        policy.remove_extension("1002");
+        assert_eq!(policy.timeout_for("1002"), None);
+    }
+}
@@ -0,0 +1,361 @@
+//! Post-dial delay tracking
+//!
+//! Post-dial delay (PDD) is the time from sending an INVITE to hearing the
+//! first sign of life back: a 180 Ringing, a 183 Session Progress or the
+//! 200 OK itself. Callers hear silence for all of it, so carriers commit to
+//! it in their SLAs. The tracker keeps the most recent delays per trunk and
+//! reports their percentiles. When a trunk's 95th percentile rises above
+//! the alert threshold the monitor logs it and posts it to the outbound
+//! webhooks, once until the trunk recovers.
+
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::events::{AlarmEvent, AlarmSeverity, EventBus, SystemEvent};
+use crate::webhooks::WebhookDispatcher;
+
+/// Sample window and alert threshold
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PddConfig {
+    /// Most recent calls kept per trunk
+    #[serde(default = "default_window")]
+    pub window: usize,
+    /// 95th percentile above which a trunk is reported degraded
+    #[serde(default = "default_alert_threshold_ms")]
+    pub alert_threshold_ms: u64,
+    /// Calls a trunk needs in its window before it is alerted on
+    #[serde(default = "default_min_samples")]
+    pub min_samples: usize,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+fn default_window() -> usize {
+    500
+}
+
+fn default_alert_threshold_ms() -> u64 {
+    4000
+}
+
+fn default_min_samples() -> usize {
+    20
+}
+
+fn default_interval_seconds() -> u64 {
+    60
+}
+
+impl Default for PddConfig {
+    fn default() -> Self {
+        Self {
+            window: default_window(),
+            alert_threshold_ms: default_alert_threshold_ms(),
+            min_samples: default_min_samples(),
+            interval_seconds: default_interval_seconds(),
+        }
+    }
+}
+
+/// Delay percentiles of one trunk, or of every call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PddStats {
+    /// Trunk host, `None` for every call together
+    pub trunk: Option<String>,
+    /// Calls in the window
+    pub samples: usize,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+}
+
+impl PddStats {
+    fn from_samples(trunk: Option<String>, samples: &VecDeque<u64>) -> Self {
+        let mut sorted: Vec<u64> = samples.iter().copied().collect();
+        sorted.sort_unstable();
+        Self {
+            trunk,
+            samples: sorted.len(),
+            p50_ms: percentile(&sorted, 50.0),
+            p90_ms: percentile(&sorted, 90.0),
+            p95_ms: percentile(&sorted, 95.0),
+            p99_ms: percentile(&sorted, 99.0),
+            max_ms: sorted.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Nearest-rank percentile (0-100) of sorted values
+fn percentile(sorted: &[u64], percentile: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// A trunk whose delays have crossed the alert threshold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PddAlert {
+    pub trunk: String,
+    pub p95_ms: u64,
+    pub threshold_ms: u64,
+    pub samples: usize,
+}
+
+impl PddAlert {
+    pub fn message(&self) -> String {
+        format!(
+            "Post-dial delay on {} degraded: p95 {} ms over {} calls, threshold {} ms",
+            self.trunk, self.p95_ms, self.samples, self.threshold_ms
+        )
+    }
+
+    /// Webhook payload
+    pub fn to_event(&self) -> Value {
+        json!({
+            "kind": "pdd_degraded",
+            "trunk": self.trunk,
+            "p95_ms": self.p95_ms,
+            "threshold_ms": self.threshold_ms,
+            "samples": self.samples,
+            "message": self.message(),
+            "timestamp": Utc::now(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct TrackerState {
+    all: VecDeque<u64>,
+    trunks: BTreeMap<String, VecDeque<u64>>,
+    /// Trunks alerted on and not yet recovered
+    degraded: HashSet<String>,
+}
+
+/// Recent post-dial delays per trunk, shared between the B2BUA and the API
+#[derive(Clone)]
+pub struct PddTracker {
+    config: PddConfig,
+    state: Arc<Mutex<TrackerState>>,
+    events: Option<EventBus>,
+}
+
+impl PddTracker {
+    pub fn new(config: PddConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(TrackerState::default())),
+            events: None,
+        }
+    }
+
+    /// Raise an alarm for each degraded trunk
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn config(&self) -> &PddConfig {
+        &self.config
+    }
+
+    /// Record the delay of a call sent to `trunk`
+    pub fn record(&self, trunk: &str, delay: Duration) {
+        let ms = delay.as_millis() as u64;
+        let window = self.config.window.max(1);
+        let mut state = self.state.lock().unwrap();
+        push(&mut state.all, ms, window);
+        let samples = state.trunks.entry(trunk.to_string()).or_default();
+        push(samples, ms, window);
+    }
+
+    /// Percentiles over every call
+    pub fn overall(&self) -> PddStats {
+        PddStats::from_samples(None, &self.state.lock().unwrap().all)
+    }
+
+    /// Percentiles per trunk, by trunk
+    pub fn trunks(&self) -> Vec<PddStats> {
+        self.state
+            .lock()
+            .unwrap()
+            .trunks
+            .iter()
+            .map(|(trunk, samples)| PddStats::from_samples(Some(trunk.clone()), samples))
+            .collect()
+    }
+
+    /// Trunks currently over the alert threshold
+    pub fn degraded(&self) -> Vec<String> {
+        let mut degraded: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .degraded
+            .iter()
+            .cloned()
+            .collect();
+        degraded.sort();
+        degraded
+    }
+
+    /// Compare each trunk with the alert threshold, returning the trunks
+    /// that have degraded since the last check
+    pub fn check(&self) -> Vec<PddAlert> {
+        let threshold_ms = self.config.alert_threshold_ms;
+        let stats = self.trunks();
+        let mut state = self.state.lock().unwrap();
+        let mut alerts = Vec::new();
+        for stats in stats {
+            let Some(trunk) = stats.trunk else {
+                continue;
+            };
+            if stats.samples < self.config.min_samples {
+                continue;
+            }
+            if stats.p95_ms > threshold_ms {
+                if state.degraded.insert(trunk.clone()) {
+                    alerts.push(PddAlert {
+                        trunk,
+                        p95_ms: stats.p95_ms,
+                        threshold_ms,
+                        samples: stats.samples,
+                    });
+                }
+            } else if state.degraded.remove(&trunk) {
+                info!(
+                    "Post-dial delay on {} recovered: p95 {} ms",
+                    trunk, stats.p95_ms
+                );
+            }
+        }
+        alerts
+    }
+
+    /// Check on the configured interval, logging each degraded trunk and
+    /// posting it to the webhooks that want PDD alerts
+    pub fn spawn(&self, webhooks: Option<WebhookDispatcher>) -> JoinHandle<()> {
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(tracker.config.interval_seconds.max(1)));
+            loop {
+                interval.tick().await;
+                for alert in tracker.check() {
+                    warn!("{}", alert.message());
+                    if let Some(bus) = &tracker.events {
+                        bus.publish_system(SystemEvent::Alarm(AlarmEvent::new(
+                            AlarmSeverity::Warning,
+                            "pdd",
+                            alert.trunk.as_str(),
+                            alert.message(),
+                        )));
+                    }
+                    let Some(dispatcher) = &webhooks else {
+                        continue;
+                    };
+                    let payload = alert.to_event();
+                    for endpoint in dispatcher.endpoints() {
+                        if !endpoint.pdd_alerts {
+                            continue;
+                        }
+                        if let Err(e) = dispatcher.deliver_value(endpoint, payload.clone()).await {
+                            warn!("Webhook {} failed: {:#}", endpoint.name, e);
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Default for PddTracker {
+    fn default() -> Self {
+        Self::new(PddConfig::default())
+    }
+}
+
+fn push(samples: &mut VecDeque<u64>, ms: u64, window: usize) {
+    if samples.len() == window {
+        samples.pop_front();
+    }
+    samples.push_back(ms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_per_trunk() {
+        let tracker = PddTracker::new(PddConfig {
+            window: 10,
+            ..PddConfig::default()
+        });
+        for ms in 1..=20 {
+            tracker.record("carrier.example", Duration::from_millis(ms * 100));
+        }
+        tracker.record("other.example", Duration::from_millis(250));
+
+        let trunks = tracker.trunks();
+        assert_eq!(trunks.len(), 2);
+        // Only the last 10 calls are kept: 1100 to 2000 ms
+        let carrier = &trunks[0];
+        assert_eq!(carrier.trunk.as_deref(), Some("carrier.example"));
+        assert_eq!(carrier.samples, 10);
+        assert_eq!(carrier.p50_ms, 1500);
+        assert_eq!(carrier.p90_ms, 1900);
+        assert_eq!(carrier.p95_ms, 2000);
+        assert_eq!(carrier.max_ms, 2000);
+        assert_eq!(trunks[1].p99_ms, 250);
+
+        let overall = tracker.overall();
+        assert_eq!(overall.trunk, None);
+        assert_eq!(overall.samples, 10);
+        assert_eq!(overall.max_ms, 2000);
+    }
+
+    #[test]
+    fn test_alerts_once_until_recovered() {
+        let tracker = PddTracker::new(PddConfig {
+            window: 20,
+            alert_threshold_ms: 3000,
+            min_samples: 5,
+            ..PddConfig::default()
+        });
+        for _ in 0..4 {
+            tracker.record("slow.example", Duration::from_secs(6));
+        }
+        // Too few calls to judge
+        assert!(tracker.check().is_empty());
+
+        tracker.record("slow.example", Duration::from_secs(6));
+        let alerts = tracker.check();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].trunk, "slow.example");
+        assert_eq!(alerts[0].p95_ms, 6000);
+        assert_eq!(alerts[0].to_event()["kind"], "pdd_degraded");
+        assert!(tracker.check().is_empty());
+        assert_eq!(tracker.degraded(), ["slow.example"]);
+
+        for _ in 0..20 {
+            tracker.record("slow.example", Duration::from_millis(800));
+        }
+        assert!(tracker.check().is_empty());
+        assert!(tracker.degraded().is_empty());
+        for _ in 0..20 {
+            tracker.record("slow.example", Duration::from_secs(5));
+        }
+        assert_eq!(tracker.check().len(), 1);
+    }
+}
@@ -0,0 +1,807 @@
+//! Presence: busy lamp field lists (RFC 4662)
+//!
+//! Desk phones light their BLF keys from the dialog state (RFC 4235) of the
+//! extensions they watch. Rather than subscribing once per key, a phone
+//! given a BLF list subscribes to `dialog` once, at the list's URI
+//! (`sip:blf-<device>@<domain>`), with `Supported: eventlist`. The first
+//! NOTIFY carries the state of every extension on the list, later ones the
+//! state of each extension whose calls change. Dialog state follows the
+//! call events the B2BUA publishes.
+//!
+//! Lists are kept per device, by inventory ID or MAC address, and their URI
+//! goes into the device's provisioning file (see
+//! [`crate::devices::provisioning`]).
+//!
+//! ```json
+//! {
+//!   "presence": {
+//!     "blf_lists": [
+//!       {
+//!         "device": "00:15:65:12:34:56",
+//!         "name": "Reception",
+//!         "extensions": ["1001", "1002", "1003"]
+//!       }
+//!     ]
+//!   }
+//! }
+//! ```
+
+pub mod rlmi;
+
+use crate::b2bua::fork::contact_address;
+use crate::b2bua::OutboundRequest;
+use crate::devices::{normalize_mac, Device};
+use crate::events::{CallEvent, CallEventKind, EventBus};
+use crate::mwi::{addr_spec, contact_uri};
+use crate::registrar::outbound::has_option_tag;
+use crate::sip::builder::{header_tag, new_tag, DialogContext, LocalProfile, MessageBuilder};
+use crate::sip::{Method, Request, Response, StatusCode};
+use rlmi::{Dialog, DialogState, Direction, Resource, ResourceList, ResourceState};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Event package of BLF subscriptions
+pub const EVENT: &str = "dialog";
+
+/// Option tag of subscriptions to resource lists
+pub const EVENTLIST: &str = "eventlist";
+
+/// The `presence` configuration section
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PresenceConfig {
+    /// BLF lists, one per device
+    #[serde(default)]
+    pub blf_lists: Vec<BlfList>,
+    /// Lifetime of a subscription whose SUBSCRIBE has no Expires
+    #[serde(default = "default_expires")]
+    pub default_expires: u32,
+    /// Shortest lifetime accepted; shorter ones are refused with 423
+    #[serde(default = "default_min_expires")]
+    pub min_expires: u32,
+    /// Longest lifetime granted; longer ones are cut down to it
+    #[serde(default = "default_max_expires")]
+    pub max_expires: u32,
+}
+
+fn default_expires() -> u32 {
+    3600
+}
+
+fn default_min_expires() -> u32 {
+    60
+}
+
+fn default_max_expires() -> u32 {
+    7200
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            blf_lists: Vec::new(),
+            default_expires: default_expires(),
+            min_expires: default_min_expires(),
+            max_expires: default_max_expires(),
+        }
+    }
+}
+
+/// The extensions a device watches
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct BlfList {
+    /// Inventory ID or MAC address of the device
+    pub device: String,
+    /// Name of the list, which some phones show
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Extensions watched, in the order of the phone's keys
+    pub extensions: Vec<String>,
+}
+
+impl BlfList {
+    /// User part of the list's URI
+    pub fn user(&self) -> String {
+        let device: String = self
+            .device
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect();
+        format!("blf-{}", device.to_ascii_lowercase())
+    }
+
+    /// Whether the list belongs to `device`
+    pub fn matches(&self, device: &Device) -> bool {
+        self.device == device.id
+            || normalize_mac(&self.device).is_some_and(|mac| device.mac.as_ref() == Some(&mac))
+    }
+
+    /// The list as served in `domain`
+    pub fn resource_list(&self, domain: &str) -> ResourceList {
+        ResourceList {
+            uri: format!("sip:{}@{}", self.user(), domain),
+            name: self.name.clone(),
+            resources: self
+                .extensions
+                .iter()
+                .map(|extension| Resource {
+                    extension: extension.clone(),
+                    uri: format!("sip:{}@{}", extension, domain),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// BLF lists, shared between the resource list server and the API
+#[derive(Debug, Clone, Default)]
+pub struct BlfLists {
+    /// Domain the lists and their extensions are addressed in
+    domain: String,
+    lists: Arc<RwLock<Vec<BlfList>>>,
+}
+
+impl BlfLists {
+    pub fn new(lists: Vec<BlfList>, domain: impl Into<String>) -> Self {
+        Self {
+            domain: domain.into(),
+            lists: Arc::new(RwLock::new(lists)),
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn list(&self) -> Vec<BlfList> {
+        self.lists.read().unwrap().clone()
+    }
+
+    /// List of `device`
+    pub fn for_device(&self, device: &Device) -> Option<BlfList> {
+        self.lists
+            .read()
+            .unwrap()
+            .iter()
+            .find(|l| l.matches(device))
+            .cloned()
+    }
+
+    /// List whose URI has user part `user`
+    pub fn by_user(&self, user: &str) -> Option<BlfList> {
+        self.lists
+            .read()
+            .unwrap()
+            .iter()
+            .find(|l| l.user().eq_ignore_ascii_case(user))
+            .cloned()
+    }
+
+    /// Set the extensions `device` watches, replacing its list
+    ///
+    /// The list is kept under the device's MAC address when it has one, so
+    /// it survives the device being forgotten and registering again.
+    pub fn set(&self, device: &Device, name: Option<String>, extensions: Vec<String>) -> BlfList {
+        let list = BlfList {
+            device: device.mac.clone().unwrap_or_else(|| device.id.clone()),
+            name,
+            extensions,
+        };
+        let mut lists = self.lists.write().unwrap();
+        lists.retain(|l| !l.matches(device));
+        lists.push(list.clone());
+        list
+    }
+
+    /// Remove the list of `device`
+    pub fn remove(&self, device: &Device) -> Option<BlfList> {
+        let mut lists = self.lists.write().unwrap();
+        let index = lists.iter().position(|l| l.matches(device))?;
+        Some(lists.remove(index))
+    }
+}
+
+/// A subscription to a BLF list
+#[derive(Debug, Clone)]
+struct Subscription {
+    /// User part of the list's URI
+    list: String,
+    dialog: DialogContext,
+    destination: SocketAddr,
+    expires_at: Instant,
+    /// RLMI version of the next NOTIFY
+    version: u32,
+    /// Version of the next dialog-info document, by extension
+    versions: HashMap<String, u32>,
+}
+
+impl Subscription {
+    fn remaining(&self) -> u64 {
+        self.expires_at
+            .saturating_duration_since(Instant::now())
+            .as_secs()
+    }
+}
+
+/// A BLF list subscription, as reported by the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriptionInfo {
+    pub list: String,
+    pub call_id: String,
+    pub destination: SocketAddr,
+    pub expires_in: u64,
+}
+
+/// Serves BLF lists as resource lists and notifies their subscribers as
+/// calls ring, are answered and end
+#[derive(Clone)]
+pub struct ResourceListServer {
+    config: PresenceConfig,
+    lists: BlfLists,
+    builder: MessageBuilder,
+    sink: mpsc::UnboundedSender<OutboundRequest>,
+    /// Calls in progress by extension
+    dialogs: Arc<Mutex<HashMap<String, Vec<Dialog>>>>,
+    /// Subscriptions by Call-ID
+    subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+}
+
+impl ResourceListServer {
+    /// Notify through `sink`, naming `local_addr` in Via and Contact
+    pub fn new(
+        config: PresenceConfig,
+        lists: BlfLists,
+        sink: mpsc::UnboundedSender<OutboundRequest>,
+        local_addr: SocketAddr,
+    ) -> Self {
+        let profile = LocalProfile::new("UDP", local_addr.ip().to_string(), local_addr.port());
+        Self {
+            config,
+            lists,
+            builder: MessageBuilder::new(profile),
+            sink,
+            dialogs: Arc::new(Mutex::new(HashMap::new())),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn lists(&self) -> &BlfLists {
+        &self.lists
+    }
+
+    /// Subscriptions that have not expired
+    pub fn subscriptions(&self) -> Vec<SubscriptionInfo> {
+        let now = Instant::now();
+        let mut table = self.subscriptions.lock().unwrap();
+        table.retain(|_, s| s.expires_at > now);
+        let mut subscriptions: Vec<_> = table
+            .values()
+            .map(|s| SubscriptionInfo {
+                list: s.list.clone(),
+                call_id: s.dialog.call_id.clone(),
+                destination: s.destination,
+                expires_in: s.remaining(),
+            })
+            .collect();
+        subscriptions.sort_by(|a, b| (&a.list, &a.call_id).cmp(&(&b.list, &b.call_id)));
+        subscriptions
+    }
+
+    /// Answer a SUBSCRIBE, sending the whole list's state when it is
+    /// accepted
+    ///
+    /// A new subscription needs a list whose URI has the user of the
+    /// Request-URI, and a subscriber that supports `eventlist`. A SUBSCRIBE
+    /// inside an existing subscription refreshes it, or with `Expires: 0`
+    /// ends it.
+    pub fn subscribe(&self, request: &Request, source: Option<SocketAddr>) -> Response {
+        let event = request
+            .get_header_value("Event")
+            .and_then(|event| event.split(';').next())
+            .map(str::trim);
+        if !event.is_some_and(|event| event.eq_ignore_ascii_case(EVENT)) {
+            return self
+                .builder
+                .response(request, StatusCode::BAD_EVENT)
+                .header("Allow-Events", EVENT)
+                .build();
+        }
+        if !has_option_tag(request, "Supported", EVENTLIST) {
+            return self
+                .builder
+                .response(request, StatusCode::EXTENSION_REQUIRED)
+                .header("Require", EVENTLIST)
+                .build();
+        }
+
+        let expires = match request.get_header_value("Expires") {
+            Some(value) => match value.trim().parse::<u32>() {
+                Ok(expires) => expires,
+                Err(_) => {
+                    return self
+                        .builder
+                        .response(request, StatusCode::BAD_REQUEST)
+                        .build()
+                }
+            },
+            None => self.config.default_expires,
+        };
+        if expires > 0 && expires < self.config.min_expires {
+            return self
+                .builder
+                .response(request, StatusCode::INTERVAL_TOO_BRIEF)
+                .header("Min-Expires", self.config.min_expires.to_string())
+                .build();
+        }
+        let expires = expires.min(self.config.max_expires);
+
+        let call_id = request.get_header_value("Call-ID").unwrap_or_default();
+        let to_tag = request.get_header_value("To").and_then(header_tag);
+        let mut subscription = match to_tag {
+            // A refresh, or the end of a subscription
+            Some(tag) => {
+                let existing = self
+                    .subscriptions
+                    .lock()
+                    .unwrap()
+                    .get(call_id)
+                    .filter(|s| s.dialog.local_tag == tag)
+                    .cloned();
+                let Some(mut subscription) = existing else {
+                    return self
+                        .builder
+                        .response(request, StatusCode::CALL_DOES_NOT_EXIST)
+                        .build();
+                };
+                if let Some(target) = contact_uri(request) {
+                    subscription.dialog.remote_target = target;
+                }
+                subscription
+            }
+            None => {
+                let list = request
+                    .uri
+                    .user
+                    .as_deref()
+                    .and_then(|user| self.lists.by_user(user));
+                let Some(list) = list else {
+                    return self
+                        .builder
+                        .response(request, StatusCode::NOT_FOUND)
+                        .build();
+                };
+                let Some(subscription) = self.new_subscription(request, source, &list) else {
+                    return self
+                        .builder
+                        .response(request, StatusCode::BAD_REQUEST)
+                        .build();
+                };
+                subscription
+            }
+        };
+
+        let mut response = self.builder.response(request, StatusCode::OK);
+        // The To of a refresh already carries our tag
+        if to_tag.is_none() {
+            response = response.set_header(
+                "To",
+                format!(
+                    "{};tag={}",
+                    request.get_header_value("To").unwrap_or_default(),
+                    subscription.dialog.local_tag
+                ),
+            );
+        }
+        let response = response
+            .header("Contact", self.builder.profile().contact())
+            .header("Require", EVENTLIST)
+            .header("Expires", expires.to_string())
+            .build();
+
+        let dialogs = self.dialogs.lock().unwrap().clone();
+        if expires == 0 {
+            self.subscriptions.lock().unwrap().remove(call_id);
+            info!("BLF list subscription {} ended", call_id);
+            if let Some(notify) = self.notify(
+                &mut subscription,
+                "terminated;reason=timeout",
+                &dialogs,
+                None,
+            ) {
+                self.deliver(notify, subscription.destination);
+            }
+        } else {
+            subscription.expires_at = Instant::now() + Duration::from_secs(expires.into());
+            info!(
+                "BLF list subscription {} to {} for {}s",
+                call_id, subscription.list, expires
+            );
+            let state = format!("active;expires={}", expires);
+            if let Some(notify) = self.notify(&mut subscription, &state, &dialogs, None) {
+                self.deliver(notify, subscription.destination);
+            }
+            self.subscriptions
+                .lock()
+                .unwrap()
+                .insert(call_id.to_string(), subscription);
+        }
+        response
+    }
+
+    /// Track a call ringing, being answered or ending, and notify the
+    /// subscribers of lists watching its parties
+    pub fn call_event(&self, event: &CallEvent) {
+        let state = match event.kind {
+            CallEventKind::Ringing => Some(DialogState::Early),
+            CallEventKind::Answered => Some(DialogState::Confirmed),
+            CallEventKind::Hangup => None,
+            // Always follows the hangup
+            CallEventKind::Missed => return,
+        };
+        let parties = [
+            (&event.caller, Direction::Initiator),
+            (&event.callee, Direction::Recipient),
+        ];
+        let mut changed = Vec::new();
+        let dialogs = {
+            let mut table = self.dialogs.lock().unwrap();
+            for (party, direction) in parties {
+                let Some(extension) = party else {
+                    continue;
+                };
+                let calls = table.entry(extension.clone()).or_default();
+                calls.retain(|d| d.call_id != event.call_id);
+                if let Some(state) = state {
+                    calls.push(Dialog {
+                        call_id: event.call_id.clone(),
+                        state,
+                        direction,
+                    });
+                }
+                if calls.is_empty() {
+                    table.remove(extension);
+                }
+                changed.push(extension.clone());
+            }
+            table.clone()
+        };
+
+        let now = Instant::now();
+        let mut notifies = Vec::new();
+        {
+            let mut table = self.subscriptions.lock().unwrap();
+            table.retain(|_, s| s.expires_at > now);
+            for subscription in table.values_mut() {
+                let state = format!("active;expires={}", subscription.remaining());
+                if let Some(notify) =
+                    self.notify(subscription, &state, &dialogs, Some(changed.as_slice()))
+                {
+                    notifies.push((notify, subscription.destination));
+                }
+            }
+        }
+        for (notify, destination) in notifies {
+            self.deliver(notify, destination);
+        }
+    }
+
+    /// Drop the subscription a failed NOTIFY belonged to
+    pub fn notify_failed(&self, response: &Response) {
+        if response.status_code.0 < 300 || matches!(response.status_code.0, 401 | 407) {
+            return;
+        }
+        let Some(call_id) = response.get_header_value("Call-ID") else {
+            return;
+        };
+        if self.subscriptions.lock().unwrap().remove(call_id).is_some() {
+            warn!(
+                "BLF list subscription {} ended by {} to NOTIFY",
+                call_id, response.status_code
+            );
+        }
+    }
+
+    /// Follow the calls published on `events`
+    pub fn spawn(&self, events: &EventBus) -> JoinHandle<()> {
+        let server = self.clone();
+        let mut rx = events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => server.call_event(&event),
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("BLF lists skipped {} call events", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    fn new_subscription(
+        &self,
+        request: &Request,
+        source: Option<SocketAddr>,
+        list: &BlfList,
+    ) -> Option<Subscription> {
+        let from = request.get_header_value("From")?;
+        let remote_target = contact_uri(request).or_else(|| addr_spec(from).parse().ok())?;
+        let destination = source.or_else(|| contact_address(&remote_target))?;
+        let dialog = DialogContext {
+            call_id: request.get_header_value("Call-ID")?.to_string(),
+            local_uri: request.uri.to_string(),
+            local_tag: new_tag(),
+            remote_uri: addr_spec(from).to_string(),
+            remote_tag: header_tag(from).map(str::to_string),
+            remote_target,
+            cseq: 0,
+            route_set: Vec::new(),
+        };
+        Some(Subscription {
+            list: list.user(),
+            dialog,
+            destination,
+            expires_at: Instant::now(),
+            version: 0,
+            versions: HashMap::new(),
+        })
+    }
+
+    /// NOTIFY inside a subscription, with the state of the list's
+    /// extensions among `changed`, or of all of them
+    ///
+    /// Nothing is sent when the list is gone or none of its extensions
+    /// changed.
+    fn notify(
+        &self,
+        subscription: &mut Subscription,
+        state: &str,
+        dialogs: &HashMap<String, Vec<Dialog>>,
+        changed: Option<&[String]>,
+    ) -> Option<Request> {
+        let list = self
+            .lists
+            .by_user(&subscription.list)?
+            .resource_list(self.lists.domain());
+        let reported: Vec<(&Resource, u32)> = list
+            .resources
+            .iter()
+            .filter(|r| changed.is_none_or(|changed| changed.contains(&r.extension)))
+            .map(|r| {
+                let version = subscription
+                    .versions
+                    .entry(r.extension.clone())
+                    .or_insert(0);
+                *version += 1;
+                (r, *version - 1)
+            })
+            .collect();
+        if reported.is_empty() && changed.is_some() {
+            return None;
+        }
+        let states: Vec<ResourceState> = reported
+            .iter()
+            .map(|(resource, version)| ResourceState {
+                resource,
+                version: *version,
+                dialogs: dialogs
+                    .get(&resource.extension)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+            })
+            .collect();
+        let (content_type, body) =
+            list.notify_body(subscription.version, changed.is_none(), &states);
+        subscription.version += 1;
+
+        Some(
+            self.builder
+                .in_dialog(Method::Notify, &mut subscription.dialog)
+                .header("Contact", self.builder.profile().contact())
+                .header("Event", EVENT)
+                .header("Subscription-State", state)
+                .header("Require", EVENTLIST)
+                .body(&content_type, body)
+                .build(),
+        )
+    }
+
+    fn deliver(&self, request: Request, destination: SocketAddr) {
+        if self
+            .sink
+            .send(OutboundRequest {
+                request,
+                destination,
+            })
+            .is_err()
+        {
+            debug!("Outbound request receiver dropped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::Uri;
+    use chrono::Utc;
+
+    fn subscribe(user: &str, expires: Option<&str>) -> Request {
+        let mut request = Request::new(
+            Method::Subscribe,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user(user.to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bK-b1")
+        .with_header("Call-ID", "blf1")
+        .with_header("From", "<sip:1001@example.com>;tag=phone")
+        .with_header("To", format!("<sip:{}@example.com>", user).as_str())
+        .with_header("CSeq", "1 SUBSCRIBE")
+        .with_header("Contact", "<sip:1001@192.0.2.10:5060>")
+        .with_header("Event", "dialog")
+        .with_header("Supported", "replaces, eventlist");
+        if let Some(expires) = expires {
+            request = request.with_header("Expires", expires);
+        }
+        request
+    }
+
+    fn server() -> (ResourceListServer, mpsc::UnboundedReceiver<OutboundRequest>) {
+        let lists = BlfLists::new(
+            vec![BlfList {
+                device: "00:15:65:12:34:56".to_string(),
+                name: Some("Reception".to_string()),
+                extensions: vec!["1002".to_string(), "1003".to_string()],
+            }],
+            "example.com",
+        );
+        let (tx, rx) = mpsc::unbounded_channel();
+        let local: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let server = ResourceListServer::new(PresenceConfig::default(), lists, tx, local);
+        (server, rx)
+    }
+
+    fn event(kind: CallEventKind, caller: &str, callee: &str) -> CallEvent {
+        CallEvent {
+            kind,
+            call_id: "call-1".to_string(),
+            caller: Some(caller.to_string()),
+            callee: Some(callee.to_string()),
+            timestamp: Utc::now(),
+            did: None,
+            crm: None,
+        }
+    }
+
+    fn body(outbound: &OutboundRequest) -> String {
+        String::from_utf8_lossy(&outbound.request.body).into_owned()
+    }
+
+    #[test]
+    fn test_blf_lists() {
+        let lists = BlfLists::new(Vec::new(), "example.com");
+        let mut device: Device = serde_json::from_value(serde_json::json!({
+            "id": "9f2c", "extension": "1001", "user_agent": null, "vendor": "Yealink",
+            "model": "T46S", "firmware": null, "kind": "desk_phone", "mac": null,
+            "instance": null, "label": null, "last_ip": null, "last_contact": null,
+            "first_seen": "2026-01-01T00:00:00Z", "last_registered": "2026-01-01T00:00:00Z",
+            "registrations": 1, "revoked_at": null
+        }))
+        .unwrap();
+        let list = lists.set(&device, None, vec!["1002".to_string()]);
+        assert_eq!(list.user(), "blf-9f2c");
+        assert_eq!(lists.by_user("blf-9f2c"), Some(list));
+
+        // Lists are kept by MAC once the device has one
+        device.mac = Some("00:15:65:12:34:56".to_string());
+        let list = lists.set(&device, Some("Desk".to_string()), vec!["1003".to_string()]);
+        assert_eq!(list.user(), "blf-001565123456");
+        assert_eq!(lists.list(), vec![list.clone()]);
+        let resources = list.resource_list(lists.domain());
+        assert_eq!(resources.uri, "sip:blf-001565123456@example.com");
+        assert_eq!(resources.resources[0].uri, "sip:1003@example.com");
+        assert_eq!(lists.remove(&device), Some(list));
+        assert!(lists.for_device(&device).is_none());
+    }
+
+    #[test]
+    fn test_list_subscription_notifies() {
+        let (server, mut rx) = server();
+        let source: SocketAddr = "198.51.100.7:5062".parse().unwrap();
+
+        let response = server.subscribe(&subscribe("blf-001565123456", Some("600")), Some(source));
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.get_header_value("Require"), Some(EVENTLIST));
+        assert_eq!(response.get_header_value("Expires"), Some("600"));
+        let tag = response
+            .get_header_value("To")
+            .and_then(header_tag)
+            .unwrap()
+            .to_string();
+
+        // Full state at once: every extension on the list, idle
+        let notify = rx.try_recv().unwrap();
+        assert_eq!(notify.destination, source);
+        assert_eq!(notify.request.get_header_value("Event"), Some(EVENT));
+        assert_eq!(notify.request.get_header_value("Require"), Some(EVENTLIST));
+        assert!(notify
+            .request
+            .get_header_value("Content-Type")
+            .unwrap()
+            .starts_with("multipart/related;type=\"application/rlmi+xml\""));
+        let full = body(&notify);
+        assert!(full.contains("version=\"0\" fullState=\"true\""));
+        assert!(full.contains("entity=\"sip:1002@example.com\""));
+        assert!(full.contains("entity=\"sip:1003@example.com\""));
+        assert!(!full.contains("<dialog id"));
+
+        // A call to 1002 from off the list only reports on 1002
+        server.call_event(&event(CallEventKind::Ringing, "5551234", "1002"));
+        let notify = rx.try_recv().unwrap();
+        assert_eq!(notify.request.get_header_value("CSeq"), Some("2 NOTIFY"));
+        let partial = body(&notify);
+        assert!(partial.contains("version=\"1\" fullState=\"false\""));
+        assert!(partial.contains("direction=\"recipient\">\r\n    <state>early</state>"));
+        assert!(!partial.contains("sip:1003@example.com"));
+
+        // A call between two extensions off the list is not reported
+        server.call_event(&event(CallEventKind::Ringing, "2001", "2002"));
+        assert!(rx.try_recv().is_err());
+
+        server.call_event(&event(CallEventKind::Answered, "5551234", "1002"));
+        assert!(body(&rx.try_recv().unwrap()).contains("<state>confirmed</state>"));
+        server.call_event(&event(CallEventKind::Hangup, "5551234", "1002"));
+        let idle = body(&rx.try_recv().unwrap());
+        assert!(idle.contains("version=\"3\" state=\"full\" entity=\"sip:1002@example.com\""));
+        assert!(!idle.contains("<dialog id"));
+        assert_eq!(server.subscriptions().len(), 1);
+
+        // Unsubscribing ends the subscription with a final full-state NOTIFY
+        let mut unsubscribe = subscribe("blf-001565123456", Some("0"));
+        unsubscribe.headers.retain(|h| h.name.as_str() != "To");
+        let unsubscribe = unsubscribe.with_header(
+            "To",
+            format!("<sip:blf-001565123456@example.com>;tag={}", tag).as_str(),
+        );
+        let response = server.subscribe(&unsubscribe, Some(source));
+        assert_eq!(response.status_code, StatusCode::OK);
+        let notify = rx.try_recv().unwrap();
+        assert_eq!(
+            notify.request.get_header_value("Subscription-State"),
+            Some("terminated;reason=timeout")
+        );
+        assert!(body(&notify).contains("fullState=\"true\""));
+        assert!(server.subscriptions().is_empty());
+    }
+
+    #[test]
+    fn test_list_subscribe_refused() {
+        let (server, mut rx) = server();
+
+        let mut presence = subscribe("blf-001565123456", None);
+        presence.headers.retain(|h| h.name.as_str() != "Event");
+        let presence = presence.with_header("Event", "presence");
+        let response = server.subscribe(&presence, None);
+        assert_eq!(response.status_code, StatusCode::BAD_EVENT);
+        assert_eq!(response.get_header_value("Allow-Events"), Some(EVENT));
+
+        let mut single = subscribe("blf-001565123456", None);
+        single.headers.retain(|h| h.name.as_str() != "Supported");
+        let response = server.subscribe(&single, None);
+        assert_eq!(response.status_code, StatusCode::EXTENSION_REQUIRED);
+        assert_eq!(response.get_header_value("Require"), Some(EVENTLIST));
+
+        let response = server.subscribe(&subscribe("blf-001565123456", Some("10")), None);
+        assert_eq!(response.status_code, StatusCode::INTERVAL_TOO_BRIEF);
+
+        let response = server.subscribe(&subscribe("blf-unknown", None), None);
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+        assert!(rx.try_recv().is_err());
+    }
+}
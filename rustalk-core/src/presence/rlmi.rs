@@ -0,0 +1,271 @@
+//! Resource list notifications (RFC 4662) carrying dialog state (RFC 4235)
+//!
+//! A NOTIFY for a resource list has a `multipart/related` body: first an
+//! RLMI document naming the list and the resources the NOTIFY reports on,
+//! then one `application/dialog-info+xml` part per resource, referenced from
+//! the RLMI document by its Content-ID. A full-state NOTIFY reports on every
+//! resource; later ones only on those that changed.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Content type of the list meta-information part
+pub const RLMI_CONTENT_TYPE: &str = "application/rlmi+xml";
+
+/// Content type of each resource's part
+pub const DIALOG_INFO_CONTENT_TYPE: &str = "application/dialog-info+xml";
+
+/// Separates the parts of a NOTIFY body
+const BOUNDARY: &str = "rustalk-rlmi-boundary";
+
+/// State of a dialog, as BLF keys show it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DialogState {
+    /// Ringing
+    Early,
+    /// Answered
+    Confirmed,
+    Terminated,
+}
+
+impl DialogState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Early => "early",
+            Self::Confirmed => "confirmed",
+            Self::Terminated => "terminated",
+        }
+    }
+}
+
+/// Which end of a dialog the watched extension is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// The extension placed the call
+    Initiator,
+    /// The extension is being called; phones offer to pick up an early one
+    Recipient,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Initiator => "initiator",
+            Self::Recipient => "recipient",
+        }
+    }
+}
+
+/// A call an extension is party to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Dialog {
+    pub call_id: String,
+    pub state: DialogState,
+    pub direction: Direction,
+}
+
+/// An extension on a resource list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Resource {
+    pub extension: String,
+    pub uri: String,
+}
+
+/// A resource list, as subscribed to at its URI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ResourceList {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub resources: Vec<Resource>,
+}
+
+/// What one NOTIFY reports about a resource
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceState<'a> {
+    pub resource: &'a Resource,
+    /// Version of the resource's dialog-info document
+    pub version: u32,
+    /// Calls in progress; none when the extension is idle
+    pub dialogs: &'a [Dialog],
+}
+
+impl ResourceList {
+    /// Content type and body of a NOTIFY reporting `states`
+    ///
+    /// `version` is the RLMI version, counting NOTIFYs of the subscription
+    /// from 0. A full-state NOTIFY should report on every resource.
+    pub fn notify_body(
+        &self,
+        version: u32,
+        full_state: bool,
+        states: &[ResourceState],
+    ) -> (String, String) {
+        let start = format!("rlmi.{}@rustalk", version);
+        let cids: Vec<String> = states
+            .iter()
+            .map(|s| format!("{}.{}@rustalk", s.resource.extension, s.version))
+            .collect();
+
+        let mut body = part(
+            &start,
+            RLMI_CONTENT_TYPE,
+            &self.rlmi(version, full_state, states, &cids),
+        );
+        for (state, cid) in states.iter().zip(&cids) {
+            body.push_str(&part(
+                cid,
+                DIALOG_INFO_CONTENT_TYPE,
+                &dialog_info(&state.resource.uri, state.version, state.dialogs),
+            ));
+        }
+        body.push_str(&format!("--{}--\r\n", BOUNDARY));
+
+        let content_type = format!(
+            "multipart/related;type=\"{}\";start=\"<{}>\";boundary=\"{}\"",
+            RLMI_CONTENT_TYPE, start, BOUNDARY
+        );
+        (content_type, body)
+    }
+
+    /// RLMI document naming the resources reported on and the parts
+    /// holding their state
+    fn rlmi(
+        &self,
+        version: u32,
+        full_state: bool,
+        states: &[ResourceState],
+        cids: &[String],
+    ) -> String {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n\
+             <list xmlns=\"urn:ietf:params:xml:ns:rlmi\" uri=\"{}\" version=\"{}\" fullState=\"{}\">\r\n",
+            escape(&self.uri),
+            version,
+            full_state
+        );
+        if let Some(name) = &self.name {
+            xml.push_str(&format!("  <name>{}</name>\r\n", escape(name)));
+        }
+        for (state, cid) in states.iter().zip(cids) {
+            let resource = state.resource;
+            xml.push_str(&format!(
+                "  <resource uri=\"{}\">\r\n    <name>{}</name>\r\n    \
+                 <instance id=\"{}\" state=\"active\" cid=\"{}\"/>\r\n  </resource>\r\n",
+                escape(&resource.uri),
+                escape(&resource.extension),
+                escape(&resource.extension),
+                escape(cid)
+            ));
+        }
+        xml.push_str("</list>\r\n");
+        xml
+    }
+}
+
+/// Full-state dialog-info document for `entity` (RFC 4235 section 4)
+pub fn dialog_info(entity: &str, version: u32, dialogs: &[Dialog]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n\
+         <dialog-info xmlns=\"urn:ietf:params:xml:ns:dialog-info\" version=\"{}\" state=\"full\" entity=\"{}\">\r\n",
+        version,
+        escape(entity)
+    );
+    for dialog in dialogs {
+        xml.push_str(&format!(
+            "  <dialog id=\"{}\" call-id=\"{}\" direction=\"{}\">\r\n    <state>{}</state>\r\n  </dialog>\r\n",
+            escape(&dialog.call_id),
+            escape(&dialog.call_id),
+            dialog.direction.as_str(),
+            dialog.state.as_str()
+        ));
+    }
+    xml.push_str("</dialog-info>\r\n");
+    xml
+}
+
+/// One part of a multipart body, with its delimiter
+fn part(cid: &str, content_type: &str, content: &str) -> String {
+    format!(
+        "--{}\r\nContent-Transfer-Encoding: binary\r\nContent-ID: <{}>\r\n\
+         Content-Type: {};charset=\"UTF-8\"\r\n\r\n{}",
+        BOUNDARY, cid, content_type, content
+    )
+}
+
+/// Text safe inside XML content and attribute values
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list() -> ResourceList {
+        ResourceList {
+            uri: "sip:blf-reception@example.com".to_string(),
+            name: Some("Front & back".to_string()),
+            resources: ["1001", "1002"]
+                .iter()
+                .map(|ext| Resource {
+                    extension: ext.to_string(),
+                    uri: format!("sip:{}@example.com", ext),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_notify_body() {
+        let list = list();
+        let ringing = [Dialog {
+            call_id: "call-1".to_string(),
+            state: DialogState::Early,
+            direction: Direction::Recipient,
+        }];
+        let states = [
+            ResourceState {
+                resource: &list.resources[0],
+                version: 0,
+                dialogs: &[],
+            },
+            ResourceState {
+                resource: &list.resources[1],
+                version: 3,
+                dialogs: &ringing,
+            },
+        ];
+        let (content_type, body) = list.notify_body(4, true, &states);
+        assert_eq!(
+            content_type,
+            "multipart/related;type=\"application/rlmi+xml\";start=\"<rlmi.4@rustalk>\";boundary=\"rustalk-rlmi-boundary\""
+        );
+        assert!(body.starts_with("--rustalk-rlmi-boundary\r\n"));
+        assert!(body.ends_with("--rustalk-rlmi-boundary--\r\n"));
+        assert_eq!(body.matches("--rustalk-rlmi-boundary\r\n").count(), 3);
+        assert!(
+            body.contains("uri=\"sip:blf-reception@example.com\" version=\"4\" fullState=\"true\"")
+        );
+        assert!(body.contains("<name>Front &amp; back</name>"));
+        assert!(body.contains("<instance id=\"1002\" state=\"active\" cid=\"1002.3@rustalk\"/>"));
+        assert!(body
+            .contains("Content-ID: <1002.3@rustalk>\r\nContent-Type: application/dialog-info+xml"));
+        assert!(body.contains(
+            "version=\"3\" state=\"full\" entity=\"sip:1002@example.com\">\r\n  \
+             <dialog id=\"call-1\" call-id=\"call-1\" direction=\"recipient\">\r\n    <state>early</state>"
+        ));
+
+        // A partial NOTIFY only names the resources it reports on
+        let (_, body) = list.notify_body(5, false, &states[1..]);
+        assert!(body.contains("fullState=\"false\""));
+        assert!(!body.contains("sip:1001@example.com"));
+    }
+}
@@ -0,0 +1,426 @@
+//! Per-trunk SIP compatibility profiles
+//!
+//! Many ITSPs only accept a subset of what RFC 3261 allows: no reliable
+//! provisionals, a Request-URI with `user=phone`, a From user that must match
+//! the trunk's auth user. A profile bundles such quirks under a name; trunks
+//! are bound to a profile and every request sent to the trunk is adjusted
+//! before it leaves. Built-in profiles cover the common cases and may be
+//! replaced or extended by name in configuration.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::call_trace::uri_user;
+use crate::sip::{Header, Method, Request, Response, StatusCode};
+
+/// One deviation from standard behaviour
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Quirk {
+    /// Does not support reliable provisionals: `100rel` and PRACK are never
+    /// offered
+    NoPrack,
+    /// Rejects UPDATE, so it is not offered in Allow
+    NoUpdate,
+    /// Requires `user=phone` on telephone-number URIs
+    UserPhone,
+    /// Rejects numbers with a leading `+`
+    StripPlus,
+    /// The From user must be the trunk's auth user; the real caller moves to
+    /// P-Asserted-Identity
+    FromAuthUser,
+    /// Answers with 200 OK without ringing first; callers are sent a 180 ahead
+    /// of it
+    EarlyAnswer,
+    /// Rejects the Diversion header; forwarded calls carry History-Info only
+    NoDiversion,
+    /// Rejects History-Info; forwarded calls carry Diversion only
+    NoHistoryInfo,
+}
+
+/// Named set of quirks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct QuirkProfile {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Profile whose quirks this one adds to
+    #[serde(default)]
+    pub extends: Option<String>,
+    pub quirks: Vec<Quirk>,
+}
+
+/// Profile bound to a trunk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TrunkQuirks {
+    /// Trunk host, as it appears in the Request-URI
+    pub trunk: String,
+    pub profile: String,
+    /// User the trunk authenticates as, for `from_auth_user`
+    #[serde(default)]
+    pub auth_user: Option<String>,
+}
+
+/// Custom profiles and trunk bindings
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct QuirksConfig {
+    /// Profiles added to, or replacing, the built-in ones
+    #[serde(default)]
+    pub profiles: Vec<QuirkProfile>,
+    pub trunks: Vec<TrunkQuirks>,
+}
+
+/// Profiles shipped with RusTalk
+pub fn builtin_profiles() -> Vec<QuirkProfile> {
+    let profile = |name: &str, description: &str, quirks: &[Quirk]| QuirkProfile {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        extends: None,
+        quirks: quirks.to_vec(),
+    };
+    vec![
+        profile("standard", "RFC 3261 compliant, no adjustments", &[]),
+        profile(
+            "no-prack",
+            "No reliable provisionals or PRACK",
+            &[Quirk::NoPrack],
+        ),
+        profile(
+            "legacy-pbx",
+            "Older gateways without PRACK or UPDATE",
+            &[Quirk::NoPrack, Quirk::NoUpdate],
+        ),
+        profile(
+            "user-phone",
+            "Numbers as user=phone URIs without a leading +",
+            &[Quirk::UserPhone, Quirk::StripPlus],
+        ),
+        profile(
+            "auth-user-from",
+            "From must match the registration user",
+            &[Quirk::FromAuthUser],
+        ),
+        profile(
+            "early-answer",
+            "Answers before ringing; ringback is synthesised",
+            &[Quirk::EarlyAnswer, Quirk::NoPrack],
+        ),
+    ]
+}
+
+impl QuirksConfig {
+    /// Profile by name; configured profiles take precedence over built-ins
+    pub fn profile(&self, name: &str) -> Option<QuirkProfile> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+            .or_else(|| builtin_profiles().into_iter().find(|p| p.name == name))
+    }
+
+    /// Every quirk of a profile, including those it extends. `None` when the
+    /// profile or one it extends does not exist, or they extend in a loop.
+    pub fn resolve(&self, name: &str) -> Option<Vec<Quirk>> {
+        let mut quirks = Vec::new();
+        let mut seen = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            if seen.contains(&name) {
+                return None;
+            }
+            let profile = self.profile(&name)?;
+            for quirk in profile.quirks {
+                if !quirks.contains(&quirk) {
+                    quirks.push(quirk);
+                }
+            }
+            next = profile.extends;
+            seen.push(name);
+        }
+        Some(quirks)
+    }
+
+    /// Quirks of the trunk at `host`, if it is bound to a profile
+    pub fn for_trunk(&self, host: &str) -> Option<QuirkSet> {
+        let binding = self
+            .trunks
+            .iter()
+            .find(|t| t.trunk.eq_ignore_ascii_case(host))?;
+        Some(QuirkSet {
+            profile: binding.profile.clone(),
+            quirks: self.resolve(&binding.profile)?,
+            auth_user: binding.auth_user.clone(),
+        })
+    }
+}
+
+/// Resolved quirks for one trunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuirkSet {
+    pub profile: String,
+    pub quirks: Vec<Quirk>,
+    pub auth_user: Option<String>,
+}
+
+impl QuirkSet {
+    pub fn has(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+
+    /// Adjust a request about to be sent to the trunk
+    pub fn apply_to_request(&self, request: &mut Request) {
+        if self.has(Quirk::NoPrack) {
+            remove_token(&mut request.headers, "Supported", "100rel");
+            remove_token(&mut request.headers, "Require", "100rel");
+            remove_token(&mut request.headers, "Allow", "PRACK");
+        }
+        if self.has(Quirk::NoUpdate) {
+            remove_token(&mut request.headers, "Allow", "UPDATE");
+        }
+        if self.has(Quirk::StripPlus) {
+            if let Some(user) = &mut request.uri.user {
+                if let Some(stripped) = user.strip_prefix('+') {
+                    *user = stripped.to_string();
+                }
+            }
+            for name in ["To", "From"] {
+                edit_header(&mut request.headers, name, |value| {
+                    value.replacen("sip:+", "sip:", 1)
+                });
+            }
+        }
+        if self.has(Quirk::FromAuthUser) {
+            if let Some(auth_user) = &self.auth_user {
+                self.rewrite_from(request, auth_user);
+            }
+        }
+        if self.has(Quirk::NoDiversion) {
+            remove_header(&mut request.headers, "Diversion");
+        }
+        if self.has(Quirk::NoHistoryInfo) {
+            remove_header(&mut request.headers, "History-Info");
+        }
+        if self.has(Quirk::UserPhone) {
+            if request.uri.user.as_deref().is_some_and(is_telephone_number)
+                && !request.uri.params.iter().any(|(k, _)| k == "user")
+            {
+                request
+                    .uri
+                    .params
+                    .push(("user".to_string(), Some("phone".to_string())));
+            }
+            for name in ["To", "From"] {
+                edit_header(&mut request.headers, name, add_user_phone);
+            }
+        }
+    }
+
+    /// Responses to relay to the caller for a response received from the
+    /// trunk. `rang` is whether the trunk already sent a 18x for the call.
+    pub fn responses_for_caller(&self, response: Response, rang: bool) -> Vec<Response> {
+        let early = self.has(Quirk::EarlyAnswer)
+            && !rang
+            && response.status_code == StatusCode::OK
+            && response
+                .get_header_value("CSeq")
+                .is_some_and(|cseq| cseq.ends_with(Method::Invite.as_str()));
+        if !early {
+            return vec![response];
+        }
+        let mut ringing = Response::new(StatusCode::RINGING);
+        ringing.headers = response
+            .headers
+            .iter()
+            .filter(|h| {
+                ["Via", "From", "To", "Call-ID", "CSeq"]
+                    .iter()
+                    .any(|name| h.name.as_str().eq_ignore_ascii_case(name))
+            })
+            .cloned()
+            .collect();
+        vec![ringing, response]
+    }
+
+    /// Move the caller to P-Asserted-Identity and present the auth user in
+    /// From
+    fn rewrite_from(&self, request: &mut Request, auth_user: &str) {
+        let Some(from) = request.get_header_value("From").map(str::to_string) else {
+            return;
+        };
+        let Some(caller) = uri_user(&from).map(str::to_string) else {
+            return;
+        };
+        if caller == auth_user {
+            return;
+        }
+        if request.get_header("P-Asserted-Identity").is_none() {
+            let uri = from
+                .find('<')
+                .and_then(|start| Some(&from[start..=start + from[start..].find('>')?]))
+                .unwrap_or(&from);
+            request
+                .headers
+                .push(Header::new("P-Asserted-Identity", uri.to_string()));
+        }
+        edit_header(&mut request.headers, "From", |value| {
+            value.replacen(&format!(":{}@", caller), &format!(":{}@", auth_user), 1)
+        });
+    }
+}
+
+fn is_telephone_number(user: &str) -> bool {
+    let digits = user.strip_prefix('+').unwrap_or(user);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Add `;user=phone` inside the angle brackets of a name-addr whose user is
+/// a telephone number
+fn add_user_phone(value: &str) -> String {
+    let (Some(start), Some(end)) = (value.find('<'), value.find('>')) else {
+        return value.to_string();
+    };
+    let uri = &value[start + 1..end];
+    if uri.contains(";user=") || !uri_user(value).is_some_and(is_telephone_number) {
+        return value.to_string();
+    }
+    format!("{};user=phone{}", &value[..end], &value[end..])
+}
+
+fn edit_header(headers: &mut [Header], name: &str, edit: impl Fn(&str) -> String) {
+    for header in headers
+        .iter_mut()
+        .filter(|h| h.name.as_str().eq_ignore_ascii_case(name))
+    {
+        header.value = edit(header.value.as_str()).into();
+    }
+}
+
+fn remove_header(headers: &mut Vec<Header>, name: &str) {
+    headers.retain(|h| !h.name.as_str().eq_ignore_ascii_case(name));
+}
+
+/// Drop `token` from a comma-separated header, and the header when nothing
+/// is left
+fn remove_token(headers: &mut Vec<Header>, name: &str, token: &str) {
+    edit_header(headers, name, |value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.eq_ignore_ascii_case(token))
+            .collect::<Vec<_>>()
+            .join(", ")
+    });
+    headers.retain(|h| !h.name.as_str().eq_ignore_ascii_case(name) || !h.value.as_str().is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::Uri;
+
+    fn invite() -> Request {
+        Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "sip.carrier.example".to_string())
+                .with_user("+12125551234".to_string()),
+        )
+        .with_header("From", "\"Alice\" <sip:1001@pbx.example.com>;tag=a")
+        .with_header("To", "<sip:+12125551234@sip.carrier.example>")
+        .with_header("Supported", "100rel, timer")
+        .with_header("Require", "100rel")
+        .with_header("Allow", "INVITE, ACK, BYE, CANCEL, PRACK, UPDATE")
+    }
+
+    fn config() -> QuirksConfig {
+        serde_json::from_value(serde_json::json!({
+            "profiles": [{
+                "name": "carrier",
+                "extends": "legacy-pbx",
+                "quirks": ["user_phone", "strip_plus", "from_auth_user"]
+            }],
+            "trunks": [
+                { "trunk": "sip.carrier.example", "profile": "carrier", "auth_user": "4420001" },
+                { "trunk": "loop.example", "profile": "unknown" }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply_profile_to_invite() {
+        let quirks = config().for_trunk("SIP.carrier.example").unwrap();
+        assert!(quirks.has(Quirk::NoUpdate));
+        let mut request = invite();
+        quirks.apply_to_request(&mut request);
+
+        assert_eq!(
+            request.uri.to_string(),
+            "sip:12125551234@sip.carrier.example;user=phone"
+        );
+        assert_eq!(request.get_header_value("Supported"), Some("timer"));
+        assert_eq!(request.get_header_value("Require"), None);
+        assert_eq!(
+            request.get_header_value("Allow"),
+            Some("INVITE, ACK, BYE, CANCEL")
+        );
+        assert_eq!(
+            request.get_header_value("To"),
+            Some("<sip:12125551234@sip.carrier.example;user=phone>")
+        );
+        assert_eq!(
+            request.get_header_value("From"),
+            Some("\"Alice\" <sip:4420001@pbx.example.com;user=phone>;tag=a")
+        );
+        assert_eq!(
+            request.get_header_value("P-Asserted-Identity"),
+            Some("<sip:1001@pbx.example.com>")
+        );
+
+        assert!(config().for_trunk("loop.example").is_none());
+        assert!(config().for_trunk("other.example").is_none());
+    }
+
+    #[test]
+    fn test_redirection_headers_stripped() {
+        let quirks = |quirk| QuirkSet {
+            profile: "test".to_string(),
+            quirks: vec![quirk],
+            auth_user: None,
+        };
+        let forwarded = invite()
+            .with_header("Diversion", "<sip:1001@pbx.example.com>;reason=no-answer")
+            .with_header("History-Info", "<sip:1001@pbx.example.com>;index=1");
+
+        let mut request = forwarded.clone();
+        quirks(Quirk::NoDiversion).apply_to_request(&mut request);
+        assert!(request.get_header("Diversion").is_none());
+        assert!(request.get_header("History-Info").is_some());
+
+        let mut request = forwarded;
+        quirks(Quirk::NoHistoryInfo).apply_to_request(&mut request);
+        assert!(request.get_header("Diversion").is_some());
+        assert!(request.get_header("History-Info").is_none());
+    }
+
+    #[test]
+    fn test_early_answer_gets_ringing() {
+        let quirks = QuirksConfig::default().resolve("early-answer").unwrap();
+        let quirks = QuirkSet {
+            profile: "early-answer".to_string(),
+            quirks,
+            auth_user: None,
+        };
+        let ok = Response::new(StatusCode::OK)
+            .with_header("Call-ID", "c1")
+            .with_header("CSeq", "1 INVITE")
+            .with_header("Contact", "<sip:gw@carrier.example>");
+
+        let relayed = quirks.responses_for_caller(ok.clone(), false);
+        assert_eq!(relayed.len(), 2);
+        assert_eq!(relayed[0].status_code, StatusCode::RINGING);
+        assert_eq!(relayed[0].get_header_value("CSeq"), Some("1 INVITE"));
+        assert_eq!(relayed[0].get_header_value("Contact"), None);
+
+        assert_eq!(quirks.responses_for_caller(ok, true).len(), 1);
+    }
+}
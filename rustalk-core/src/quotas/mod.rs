@@ -0,0 +1,277 @@
+//! Per-tenant resource quotas
+//!
+//! A tenant is a SIP domain, as for prompt locales. Each may be given caps
+//! on the extensions and trunks provisioned for it, the calls it has up at
+//! once and the voicemail it stores. The provisioning API refuses to create
+//! extensions or trunks past a cap, the B2BUA answers INVITEs past the call
+//! cap with 503, and mailboxes past the storage cap take no more messages.
+//! Usage against each quota is reported by the analytics API for billing.
+//!
+//! ```json
+//! {
+//!   "quotas": {
+//!     "tenants": {
+//!       "acme.example": {
+//!         "max_extensions": 50,
+//!         "max_trunks": 2,
+//!         "max_concurrent_calls": 10,
+//!         "max_storage_mb": 1024
+//!       }
+//!     }
+//!   }
+//! }
+//! ```
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Quotas per tenant
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct QuotaConfig {
+    /// Quota per tenant, by SIP domain
+    #[serde(default)]
+    pub tenants: HashMap<String, TenantQuota>,
+}
+
+/// Caps for one tenant; those left out are unlimited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TenantQuota {
+    #[serde(default)]
+    pub max_extensions: Option<u64>,
+    #[serde(default)]
+    pub max_trunks: Option<u64>,
+    #[serde(default)]
+    pub max_concurrent_calls: Option<u64>,
+    /// Voicemail messages stored across the tenant's mailboxes
+    #[serde(default)]
+    pub max_storage_mb: Option<u64>,
+}
+
+impl TenantQuota {
+    /// Cap on `resource`, in its own units (storage in bytes)
+    pub fn limit(&self, resource: QuotaResource) -> Option<u64> {
+        match resource {
+            QuotaResource::Extensions => self.max_extensions,
+            QuotaResource::Trunks => self.max_trunks,
+            QuotaResource::ConcurrentCalls => self.max_concurrent_calls,
+            QuotaResource::Storage => self.max_storage_mb.map(|mb| mb * 1024 * 1024),
+        }
+    }
+}
+
+/// Something a tenant's use of is capped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaResource {
+    Extensions,
+    Trunks,
+    ConcurrentCalls,
+    Storage,
+}
+
+impl fmt::Display for QuotaResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QuotaResource::Extensions => "extensions",
+            QuotaResource::Trunks => "trunks",
+            QuotaResource::ConcurrentCalls => "concurrent calls",
+            QuotaResource::Storage => "storage bytes",
+        })
+    }
+}
+
+/// A tenant would go past one of its caps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub tenant: String,
+    pub resource: QuotaResource,
+    pub limit: u64,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Tenant {} has reached its quota of {} {}",
+            self.tenant, self.limit, self.resource
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// A tenant's use of each resource, with its quota
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantUsage {
+    pub tenant: String,
+    pub extensions: u64,
+    pub trunks: u64,
+    pub concurrent_calls: u64,
+    pub storage_bytes: u64,
+    /// `None` when the tenant has no quota
+    pub quota: Option<TenantQuota>,
+}
+
+/// Tenant quotas and the calls each tenant has up, shared between the
+/// B2BUA, voicemail and the API
+#[derive(Debug, Clone, Default)]
+pub struct Quotas {
+    config: Arc<QuotaConfig>,
+    /// Calls up per tenant
+    calls: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl Quotas {
+    pub fn new(config: QuotaConfig) -> Self {
+        let tenants = config
+            .tenants
+            .into_iter()
+            .map(|(tenant, quota)| (tenant.to_ascii_lowercase(), quota))
+            .collect();
+        Self {
+            config: Arc::new(QuotaConfig { tenants }),
+            calls: Arc::default(),
+        }
+    }
+
+    /// Tenants with a quota, by domain
+    pub fn tenants(&self) -> Vec<String> {
+        let mut tenants: Vec<String> = self.config.tenants.keys().cloned().collect();
+        tenants.sort();
+        tenants
+    }
+
+    pub fn quota(&self, tenant: &str) -> Option<&TenantQuota> {
+        self.config.tenants.get(&tenant.to_ascii_lowercase())
+    }
+
+    /// The first of `domains` that is a tenant with a quota
+    pub fn tenant_of<'a>(&self, domains: impl IntoIterator<Item = &'a str>) -> Option<String> {
+        domains
+            .into_iter()
+            .map(str::to_ascii_lowercase)
+            .find(|domain| self.config.tenants.contains_key(domain))
+    }
+
+    /// Check that `tenant` may use `usage` of `resource`, counting what is
+    /// about to be added
+    pub fn check(
+        &self,
+        tenant: &str,
+        resource: QuotaResource,
+        usage: u64,
+    ) -> Result<(), QuotaExceeded> {
+        match self.quota(tenant).and_then(|q| q.limit(resource)) {
+            Some(limit) if usage > limit => Err(QuotaExceeded {
+                tenant: tenant.to_ascii_lowercase(),
+                resource,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a new call against `tenant`, unless it has all the calls up
+    /// its quota allows
+    pub fn start_call(&self, tenant: &str) -> Result<(), QuotaExceeded> {
+        let tenant = tenant.to_ascii_lowercase();
+        let mut calls = self.calls.lock().unwrap();
+        let active = calls.entry(tenant.clone()).or_default();
+        self.check(&tenant, QuotaResource::ConcurrentCalls, *active + 1)?;
+        *active += 1;
+        Ok(())
+    }
+
+    /// A call counted by [`Quotas::start_call`] has ended
+    pub fn end_call(&self, tenant: &str) {
+        let tenant = tenant.to_ascii_lowercase();
+        let mut calls = self.calls.lock().unwrap();
+        if let Some(active) = calls.get_mut(&tenant) {
+            *active = active.saturating_sub(1);
+            if *active == 0 {
+                calls.remove(&tenant);
+            }
+        }
+    }
+
+    pub fn active_calls(&self, tenant: &str) -> u64 {
+        self.calls
+            .lock()
+            .unwrap()
+            .get(&tenant.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotas() -> Quotas {
+        Quotas::new(
+            serde_json::from_value(serde_json::json!({
+                "tenants": {
+                    "ACME.example": {
+                        "max_extensions": 2,
+                        "max_concurrent_calls": 1,
+                        "max_storage_mb": 1
+                    }
+                }
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let quotas = quotas();
+        assert_eq!(quotas.tenants(), ["acme.example"]);
+        assert!(quotas
+            .check("acme.example", QuotaResource::Extensions, 2)
+            .is_ok());
+        let error = quotas
+            .check("Acme.Example", QuotaResource::Extensions, 3)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Tenant acme.example has reached its quota of 2 extensions"
+        );
+        // Storage is capped in megabytes but counted in bytes
+        assert!(quotas
+            .check("acme.example", QuotaResource::Storage, 1024 * 1024)
+            .is_ok());
+        assert!(quotas
+            .check("acme.example", QuotaResource::Storage, 1024 * 1024 + 1)
+            .is_err());
+        // Unset caps and other tenants are unlimited
+        assert!(quotas
+            .check("acme.example", QuotaResource::Trunks, 100)
+            .is_ok());
+        assert!(quotas
+            .check("other.example", QuotaResource::Extensions, 100)
+            .is_ok());
+
+        assert_eq!(
+            quotas.tenant_of(["192.0.2.1", "Acme.example"]),
+            Some("acme.example".to_string())
+        );
+        assert_eq!(quotas.tenant_of(["other.example"]), None);
+    }
+
+    #[test]
+    fn test_concurrent_calls() {
+        let quotas = quotas();
+        quotas.start_call("acme.example").unwrap();
+        let error = quotas.start_call("acme.example").unwrap_err();
+        assert_eq!(error.resource, QuotaResource::ConcurrentCalls);
+        assert_eq!(quotas.active_calls("acme.example"), 1);
+
+        quotas.end_call("ACME.example");
+        assert_eq!(quotas.active_calls("acme.example"), 0);
+        quotas.start_call("acme.example").unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+//! RADIUS client
+//!
+//! Sends an accounting Start when a call is answered and a Stop when it
+//! hangs up (RFC 2866), and optionally checks REGISTER digest credentials
+//! against the RADIUS server instead of local passwords (RFC 5090). The
+//! server sees the digest response, never a password.
+
+pub mod packet;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, warn};
+
+use crate::auth::DigestResponse;
+use crate::events::{CallEvent, CallEventKind, EventBus};
+use crate::io;
+use packet::Packet;
+
+/// RADIUS servers and shared secret
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RadiusConfig {
+    /// `host:port` that authenticates REGISTER requests; registrations are
+    /// not authenticated when unset
+    pub auth_server: Option<String>,
+    /// `host:port` that receives accounting records; none are sent when
+    /// unset
+    pub accounting_server: Option<String>,
+    pub secret: String,
+    #[serde(default = "default_nas_identifier")]
+    pub nas_identifier: String,
+    /// Wait for each attempt
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Attempts before the server is considered unreachable
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+}
+
+fn default_nas_identifier() -> String {
+    "rustalk".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_attempts() -> u32 {
+    3
+}
+
+/// Acct-Status-Type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountingStatus {
+    Start = 1,
+    Stop = 2,
+}
+
+/// Acct-Terminate-Cause User-Request
+const TERMINATE_USER_REQUEST: u32 = 1;
+
+pub struct RadiusClient {
+    config: RadiusConfig,
+    next_id: AtomicU8,
+}
+
+impl RadiusClient {
+    pub fn new(config: RadiusConfig) -> Self {
+        Self {
+            config,
+            next_id: AtomicU8::new(rand::random()),
+        }
+    }
+
+    pub fn config(&self) -> &RadiusConfig {
+        &self.config
+    }
+
+    /// Check a digest response; `Ok(false)` when the server rejects it
+    pub async fn authenticate(&self, digest: &DigestResponse, method: &str) -> Result<bool> {
+        let server = self
+            .config
+            .auth_server
+            .as_deref()
+            .ok_or_else(|| anyhow!("no RADIUS authentication server"))?;
+        let mut request = Packet::new(packet::ACCESS_REQUEST, self.identifier());
+        request.add_str(packet::USER_NAME, &digest.username);
+        request.add_str(packet::NAS_IDENTIFIER, &self.config.nas_identifier);
+        request.add_str(packet::DIGEST_RESPONSE, &digest.response);
+        request.add_str(packet::DIGEST_REALM, &digest.realm);
+        request.add_str(packet::DIGEST_NONCE, &digest.nonce);
+        request.add_str(packet::DIGEST_METHOD, method);
+        request.add_str(packet::DIGEST_URI, &digest.uri);
+        if let Some(qop) = &digest.qop {
+            request.add_str(packet::DIGEST_QOP, qop);
+        }
+        if let Some(algorithm) = &digest.algorithm {
+            request.add_str(packet::DIGEST_ALGORITHM, algorithm);
+        }
+        if let Some(cnonce) = &digest.cnonce {
+            request.add_str(packet::DIGEST_CNONCE, cnonce);
+        }
+        if let Some(nc) = &digest.nc {
+            request.add_str(packet::DIGEST_NONCE_COUNT, nc);
+        }
+        request.add_str(packet::DIGEST_USERNAME, &digest.username);
+        let wire = request.sign_access_request(self.config.secret.as_bytes());
+
+        let response = self.exchange(server, &request, &wire).await?;
+        match response.code {
+            packet::ACCESS_ACCEPT => Ok(true),
+            // Nonces are issued here, so a challenge is as good as a reject
+            packet::ACCESS_REJECT | packet::ACCESS_CHALLENGE => Ok(false),
+            code => bail!("unexpected RADIUS response code {}", code),
+        }
+    }
+
+    /// Send an accounting record for `event`; `answered_at` is when a
+    /// stopped call was answered
+    pub async fn account(
+        &self,
+        status: AccountingStatus,
+        event: &CallEvent,
+        answered_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let server = self
+            .config
+            .accounting_server
+            .as_deref()
+            .ok_or_else(|| anyhow!("no RADIUS accounting server"))?;
+        let mut request = Packet::new(packet::ACCOUNTING_REQUEST, self.identifier());
+        request.add_u32(packet::ACCT_STATUS_TYPE, status as u32);
+        request.add_str(packet::ACCT_SESSION_ID, &event.call_id);
+        request.add_str(packet::NAS_IDENTIFIER, &self.config.nas_identifier);
+        if let Some(caller) = &event.caller {
+            request.add_str(packet::USER_NAME, caller);
+            request.add_str(packet::CALLING_STATION_ID, caller);
+        }
+        if let Some(callee) = &event.callee {
+            request.add_str(packet::CALLED_STATION_ID, callee);
+        }
+        request.add_u32(
+            packet::EVENT_TIMESTAMP,
+            event.timestamp.timestamp().clamp(0, u32::MAX as i64) as u32,
+        );
+        if status == AccountingStatus::Stop {
+            let seconds = answered_at
+                .map(|at| {
+                    (event.timestamp - at)
+                        .num_seconds()
+                        .clamp(0, u32::MAX as i64)
+                })
+                .unwrap_or(0);
+            request.add_u32(packet::ACCT_SESSION_TIME, seconds as u32);
+            request.add_u32(packet::ACCT_TERMINATE_CAUSE, TERMINATE_USER_REQUEST);
+        }
+        let wire = request.sign_accounting_request(self.config.secret.as_bytes());
+
+        let response = self.exchange(server, &request, &wire).await?;
+        if response.code != packet::ACCOUNTING_RESPONSE {
+            bail!("unexpected RADIUS response code {}", response.code);
+        }
+        Ok(())
+    }
+
+    /// Send Start and Stop records for answered calls published on `events`
+    pub fn spawn_accounting(self: Arc<Self>, events: &EventBus) -> JoinHandle<()> {
+        let mut rx = events.subscribe();
+        tokio::spawn(async move {
+            let mut answered: HashMap<String, DateTime<Utc>> = HashMap::new();
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("RADIUS accounting skipped {} call events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let (status, answered_at) = match event.kind {
+                    CallEventKind::Answered => {
+                        answered.insert(event.call_id.clone(), event.timestamp);
+                        (AccountingStatus::Start, None)
+                    }
+                    // Unanswered calls have no session to account for
+                    CallEventKind::Hangup => match answered.remove(&event.call_id) {
+                        Some(at) => (AccountingStatus::Stop, Some(at)),
+                        None => continue,
+                    },
+                    CallEventKind::Ringing | CallEventKind::Missed => continue,
+                };
+                if let Err(e) = self.account(status, &event, answered_at).await {
+                    warn!(
+                        "RADIUS accounting {:?} for {} failed: {:#}",
+                        status, event.call_id, e
+                    );
+                }
+            }
+        })
+    }
+
+    fn identifier(&self) -> u8 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Send `wire` until a verified response arrives or attempts run out
+    async fn exchange(&self, server: &str, request: &Packet, wire: &[u8]) -> Result<Packet> {
+        let addr: SocketAddr = io::dns::lookup(server)
+            .await
+            .with_context(|| format!("cannot resolve RADIUS server {}", server))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("RADIUS server {} has no address", server))?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+
+        let secret = self.config.secret.as_bytes();
+        let mut buf = vec![0u8; packet::MAX_PACKET_SIZE];
+        for attempt in 1..=self.config.attempts.max(1) {
+            socket.send(wire).await?;
+            let deadline = Instant::now() + Duration::from_millis(self.config.timeout_ms);
+            while let Ok(received) = timeout_at(deadline, socket.recv(&mut buf)).await {
+                let response = match Packet::decode(&buf[..received?]) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        debug!("Ignoring malformed RADIUS packet: {}", e);
+                        continue;
+                    }
+                };
+                if response.identifier != request.identifier {
+                    continue;
+                }
+                if !response.verify_response(&request.authenticator, secret) {
+                    debug!("Ignoring RADIUS response with a bad authenticator");
+                    continue;
+                }
+                return Ok(response);
+            }
+            debug!("RADIUS server {} timed out (attempt {})", server, attempt);
+        }
+        bail!("RADIUS server {} did not respond", server)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    pub(crate) const SECRET: &str = "testing123";
+
+    /// Server that accepts `accept_user`, rejects everyone else and
+    /// forwards every request it receives
+    pub(crate) async fn radius_server(
+        accept_user: &str,
+    ) -> (String, mpsc::UnboundedReceiver<Packet>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let accept_user = accept_user.as_bytes().to_vec();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let request = Packet::decode(&buf[..len]).unwrap();
+                let code = match request.code {
+                    packet::ACCESS_REQUEST
+                        if request.get(packet::USER_NAME) == Some(&accept_user) =>
+                    {
+                        packet::ACCESS_ACCEPT
+                    }
+                    packet::ACCESS_REQUEST => packet::ACCESS_REJECT,
+                    _ => packet::ACCOUNTING_RESPONSE,
+                };
+                let mut response = Packet::new(code, request.identifier);
+                response.authenticator = request.authenticator;
+                response.authenticator = packet::digest(&[&response.encode(), SECRET.as_bytes()]);
+                socket.send_to(&response.encode(), peer).await.unwrap();
+                let _ = tx.send(request);
+            }
+        });
+        (addr, rx)
+    }
+
+    pub(crate) fn client(
+        auth_server: Option<String>,
+        accounting_server: Option<String>,
+    ) -> RadiusClient {
+        RadiusClient::new(RadiusConfig {
+            auth_server,
+            accounting_server,
+            secret: SECRET.to_string(),
+            nas_identifier: default_nas_identifier(),
+            timeout_ms: 500,
+            attempts: 2,
+        })
+    }
+
+    fn event(kind: CallEventKind, seconds: i64) -> CallEvent {
+        CallEvent {
+            kind,
+            call_id: "call-1".to_string(),
+            caller: Some("1001".to_string()),
+            callee: Some("+12125551234".to_string()),
+            timestamp: DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap(),
+            did: None,
+            crm: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accounting_start_and_stop() {
+        let (server, mut requests) = radius_server("").await;
+        let client = Arc::new(client(None, Some(server)));
+        let events = EventBus::new();
+        client.spawn_accounting(&events);
+
+        // An unanswered call sends nothing
+        events.publish(CallEvent {
+            call_id: "call-0".to_string(),
+            ..event(CallEventKind::Hangup, 0)
+        });
+        events.publish(event(CallEventKind::Ringing, 0));
+        events.publish(event(CallEventKind::Answered, 5));
+        events.publish(event(CallEventKind::Hangup, 65));
+
+        let start = requests.recv().await.unwrap();
+        assert_eq!(
+            start.get(packet::ACCT_STATUS_TYPE),
+            Some(&1u32.to_be_bytes()[..])
+        );
+        assert_eq!(start.get(packet::ACCT_SESSION_ID), Some(&b"call-1"[..]));
+        assert_eq!(
+            start.get(packet::CALLED_STATION_ID),
+            Some(&b"+12125551234"[..])
+        );
+        let stop = requests.recv().await.unwrap();
+        assert_eq!(
+            stop.get(packet::ACCT_STATUS_TYPE),
+            Some(&2u32.to_be_bytes()[..])
+        );
+        assert_eq!(
+            stop.get(packet::ACCT_SESSION_TIME),
+            Some(&60u32.to_be_bytes()[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server() {
+        // Bound but never answering
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = client(None, Some(silent.local_addr().unwrap().to_string()));
+        let err = client
+            .account(
+                AccountingStatus::Start,
+                &event(CallEventKind::Answered, 0),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("did not respond"));
+    }
+}
@@ -0,0 +1,90 @@
+//! Globally routable UA URIs (RFC 5627)
+//!
+//! A GRUU names one device of an extension rather than the extension as a
+//! whole, so a transfer or conference can be pointed at the phone that is
+//! actually in the call. A UA registering with `Supported: gruu` and a
+//! `+sip.instance` is handed two: the public GRUU, which is the AoR with a
+//! `gr` parameter carrying the instance and so reveals it, and a temporary
+//! GRUU, an opaque URI that stays valid while the registration does.
+//! Requests for either ring only that instance.
+
+use crate::sip::Uri;
+use rand::Rng;
+
+/// Option tag for GRUU support
+pub const GRUU: &str = "gruu";
+
+/// User part prefix of the temporary GRUUs we issue
+const TEMP_GRUU_PREFIX: &str = "tgruu.";
+
+/// What a Request-URI carrying a `gr` parameter names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GruuTarget {
+    /// Public GRUU: an extension's instance
+    Public { user: String, instance: String },
+    /// Temporary GRUU, by its user part
+    Temporary { user: String },
+}
+
+/// Public GRUU for an instance registered to `aor`
+pub fn pub_gruu(aor: &str, instance: &str) -> String {
+    format!("{};gr={}", aor, instance)
+}
+
+/// A new temporary GRUU in the domain of `aor`
+pub fn temp_gruu(aor: &str) -> String {
+    let domain = aor.rsplit_once('@').map(|(_, d)| d).unwrap_or(aor);
+    let token: u64 = rand::thread_rng().gen();
+    format!("sip:{}{:016x}@{};gr", TEMP_GRUU_PREFIX, token, domain)
+}
+
+/// The GRUU a Request-URI names, if it is one
+pub fn gruu_target(uri: &Uri) -> Option<GruuTarget> {
+    let (_, value) = uri.params.iter().find(|(name, _)| name == "gr")?;
+    let user = uri.user.clone()?;
+    match value.as_deref() {
+        Some(instance) if !instance.is_empty() => Some(GruuTarget::Public {
+            user,
+            instance: instance.to_string(),
+        }),
+        _ => user
+            .starts_with(TEMP_GRUU_PREFIX)
+            .then_some(GruuTarget::Temporary { user }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gruu_targets() {
+        let instance = "urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6";
+        let public = pub_gruu("sip:1001@example.com", instance);
+        assert_eq!(
+            public,
+            "sip:1001@example.com;gr=urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6"
+        );
+        let uri: Uri = public.parse().unwrap();
+        assert_eq!(
+            gruu_target(&uri),
+            Some(GruuTarget::Public {
+                user: "1001".to_string(),
+                instance: instance.to_string()
+            })
+        );
+
+        let temp = temp_gruu("sip:1001@example.com");
+        assert!(temp.starts_with("sip:tgruu."));
+        assert!(temp.ends_with("@example.com;gr"));
+        assert_ne!(temp, temp_gruu("sip:1001@example.com"));
+        let uri: Uri = temp.parse().unwrap();
+        assert!(matches!(
+            gruu_target(&uri),
+            Some(GruuTarget::Temporary { .. })
+        ));
+
+        let plain: Uri = "sip:1001@example.com".parse().unwrap();
+        assert!(gruu_target(&plain).is_none());
+    }
+}
@@ -0,0 +1,1342 @@
+//! SIP registrar
+//!
+//! Keeps the contact bindings created by REGISTER requests, keyed by
+//! address-of-record. Bindings expire on their own; expired entries are
+//! hidden from lookups and dropped by `purge_expired`. With credentials
+//! configured, REGISTERs are challenged and must carry a digest response
+//! for the address-of-record's user; with RADIUS authentication instead,
+//! the RADIUS server checks the response. Every registering device is
+//! recorded in a [`DeviceInventory`], and revoked devices are refused.
+//! Clients supporting outbound register one flow per connection, and are
+//! handed GRUUs naming the device; see [`outbound`] and [`gruu`]. The SIP
+//! profile serving the address-of-record's domain can raise the shortest
+//! expiry accepted and decide where the endpoint's requests are sent; see
+//! [`NatPolicy`]. Addresses-of-record under a domain alias are stored under
+//! the canonical domain and challenged in its realm; see [`DomainMap`].
+
+use crate::auth::{AuthManager, DigestResponse};
+use crate::call_trace::uri_user;
+use crate::devices::{DeviceInventory, Sighting};
+use crate::domains::DomainMap;
+use crate::events::{EventBus, RegistrationEvent, RegistrationEventKind, SystemEvent};
+use crate::nat::{contact_ip, NatPolicy};
+use crate::radius::RadiusClient;
+use crate::sip::builder::{LocalProfile, MessageBuilder, ResponseBuilder};
+use crate::sip::{Request, Response, StatusCode, Uri};
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
+
+pub mod gruu;
+pub mod outbound;
+
+use gruu::{gruu_target, pub_gruu, temp_gruu, GruuTarget, GRUU};
+use outbound::{has_option_tag, instance_id, reg_id, OUTBOUND};
+
+/// Expiry used when a REGISTER carries no Expires header or parameter
+pub const DEFAULT_EXPIRES: u32 = 3600;
+
+/// Local registrar settings
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RegistrarConfig {
+    /// Digest credentials REGISTERs are checked against; registrations are
+    /// not authenticated locally when empty
+    #[serde(default)]
+    pub credentials: Vec<SipCredential>,
+    /// Longest expiry granted to endpoints
+    #[serde(default = "default_max_expires")]
+    pub max_expires: u32,
+}
+
+fn default_max_expires() -> u32 {
+    DEFAULT_EXPIRES
+}
+
+impl Default for RegistrarConfig {
+    fn default() -> Self {
+        Self {
+            credentials: Vec::new(),
+            max_expires: default_max_expires(),
+        }
+    }
+}
+
+/// Username and password an extension registers with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SipCredential {
+    pub username: String,
+    pub password: String,
+    /// Domain the credential is for, or an alias of it; it applies in
+    /// every domain when unset
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+/// A single contact binding for an address-of-record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Registration {
+    /// Address-of-record from the To header (e.g. `sip:1001@example.com`)
+    pub aor: String,
+    /// Contact URI the endpoint can be reached at
+    pub contact: String,
+    pub user_agent: Option<String>,
+    /// Address the REGISTER was received from
+    pub received: Option<SocketAddr>,
+    /// Contact address differs from the source address
+    pub behind_nat: bool,
+    pub registered_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Inventory ID of the registering device
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Whether the contact answered the last call forked to it; unknown
+    /// until one has been
+    #[serde(default)]
+    pub reachable: Option<bool>,
+    /// When reachability was last learned
+    #[serde(default)]
+    pub checked_at: Option<DateTime<Utc>>,
+    /// `+sip.instance` the UA registered with
+    #[serde(default)]
+    pub instance_id: Option<String>,
+    /// Outbound flow of the instance (RFC 5626)
+    #[serde(default)]
+    pub reg_id: Option<u32>,
+    /// Public GRUU of the instance (RFC 5627)
+    #[serde(default)]
+    pub pub_gruu: Option<String>,
+    /// Temporary GRUU of the instance, kept across refreshes
+    #[serde(default)]
+    pub temp_gruu: Option<String>,
+    /// Requests go to the Contact rather than the address the REGISTER
+    /// came from, as the SIP profile's `rewrite_contact` says
+    #[serde(default)]
+    pub send_to_contact: bool,
+}
+
+impl Registration {
+    /// Seconds until the binding expires
+    pub fn expires_in(&self) -> i64 {
+        (self.expires_at - Utc::now()).num_seconds().max(0)
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+
+    fn event(&self, kind: RegistrationEventKind) -> RegistrationEvent {
+        RegistrationEvent {
+            kind,
+            aor: self.aor.clone(),
+            contact: self.contact.clone(),
+            source: self.received,
+            user_agent: self.user_agent.clone(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// In-memory registration store
+#[derive(Clone)]
+pub struct Registrar {
+    bindings: Arc<RwLock<HashMap<String, Vec<Registration>>>>,
+    max_expires: u32,
+    auth: Option<Arc<DigestAuth>>,
+    devices: DeviceInventory,
+    nat: NatPolicy,
+    domains: DomainMap,
+    events: Option<EventBus>,
+}
+
+/// Digest challenges issued here
+struct DigestAuth {
+    /// Realm of domains no SIP profile serves
+    realm: String,
+    nonces: Mutex<AuthManager>,
+    verifier: Verifier,
+}
+
+/// Where digest responses are checked
+enum Verifier {
+    /// Against configured passwords
+    Local(Vec<SipCredential>),
+    Radius(Arc<RadiusClient>),
+}
+
+impl DigestAuth {
+    fn new(realm: &str, verifier: Verifier) -> Self {
+        Self {
+            realm: realm.to_string(),
+            nonces: Mutex::new(AuthManager::new(realm)),
+            verifier,
+        }
+    }
+
+    fn challenge(&self, request: &Request, realm: &str) -> Response {
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.cleanup_nonces();
+        let challenge = nonces.generate_challenge_in(realm);
+        response(request, StatusCode::UNAUTHORIZED)
+            .header(
+                "WWW-Authenticate",
+                AuthManager::format_challenge(&challenge),
+            )
+            .build()
+    }
+
+    /// Rejection for a REGISTER for `aor`, or `None` when its credentials
+    /// are accepted
+    async fn check(&self, request: &Request, aor: &str, domains: &DomainMap) -> Option<Response> {
+        let domain = aor.parse::<Uri>().map(|uri| uri.host).unwrap_or_default();
+        let realm = domains.realm(&domain).unwrap_or_else(|| self.realm.clone());
+        let digest = request
+            .get_header_value("Authorization")
+            .and_then(|header| AuthManager::parse_authorization(header).ok());
+        let Some(digest) = digest.filter(|d| d.realm == realm) else {
+            return Some(self.challenge(request, &realm));
+        };
+        let client = match &self.verifier {
+            Verifier::Local(credentials) => {
+                let password = password_for(credentials, &digest.username, &domain, domains);
+                return self.check_local(request, password, &digest, aor, &realm);
+            }
+            Verifier::Radius(client) => client,
+        };
+        let fresh = self.nonces.lock().unwrap().consume_nonce(&digest.nonce);
+        if let Err(e) = fresh {
+            debug!("Rechallenging REGISTER from {}: {}", digest.username, e);
+            return Some(self.challenge(request, &realm));
+        }
+
+        let status = match client.authenticate(&digest, "REGISTER").await {
+            Ok(true) => return None,
+            Ok(false) => {
+                info!("RADIUS rejected REGISTER from {}", digest.username);
+                StatusCode::FORBIDDEN
+            }
+            Err(e) => {
+                warn!("RADIUS authentication failed: {:#}", e);
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        };
+        Some(response(request, status).build())
+    }
+
+    /// Check a digest response against the configured password; a user may
+    /// only register its own address-of-record
+    fn check_local(
+        &self,
+        request: &Request,
+        password: Option<&str>,
+        digest: &DigestResponse,
+        aor: &str,
+        realm: &str,
+    ) -> Option<Response> {
+        let forbidden = || Some(response(request, StatusCode::FORBIDDEN).build());
+        let Some(password) = password else {
+            info!("REGISTER from unknown user {}", digest.username);
+            return forbidden();
+        };
+        let valid = self
+            .nonces
+            .lock()
+            .unwrap()
+            .validate_response(digest, password, "REGISTER");
+        match valid {
+            Ok(true) if uri_user(aor) == Some(digest.username.as_str()) => None,
+            Ok(true) => {
+                info!("{} may not register {}", digest.username, aor);
+                forbidden()
+            }
+            Ok(false) => {
+                info!("Wrong password in REGISTER from {}", digest.username);
+                forbidden()
+            }
+            Err(e) => {
+                debug!("Rechallenging REGISTER from {}: {}", digest.username, e);
+                Some(self.challenge(request, realm))
+            }
+        }
+    }
+}
+
+/// A response to a REGISTER, echoing its Via, From, To, Call-ID and CSeq
+///
+/// REGISTER responses carry no Contact of ours, so the profile is never
+/// advertised.
+fn response(request: &Request, status: StatusCode) -> ResponseBuilder {
+    static BUILDER: OnceLock<MessageBuilder> = OnceLock::new();
+    BUILDER
+        .get_or_init(|| MessageBuilder::new(LocalProfile::new("UDP", "0.0.0.0", 5060)))
+        .response(request, status)
+}
+
+/// Password of `username` in `domain`, preferring a credential for the
+/// domain over one for every domain
+fn password_for<'a>(
+    credentials: &'a [SipCredential],
+    username: &str,
+    domain: &str,
+    domains: &DomainMap,
+) -> Option<&'a str> {
+    let mut candidates = credentials.iter().filter(|c| c.username == username);
+    let scoped = candidates.clone().find(|c| {
+        c.domain
+            .as_deref()
+            .is_some_and(|d| domains.canonicalize(d).eq_ignore_ascii_case(domain))
+    });
+    scoped
+        .or_else(|| candidates.find(|c| c.domain.is_none()))
+        .map(|c| c.password.as_str())
+}
+
+impl Registrar {
+    pub fn new() -> Self {
+        Self {
+            bindings: Arc::new(RwLock::new(HashMap::new())),
+            max_expires: DEFAULT_EXPIRES,
+            auth: None,
+            devices: DeviceInventory::new(),
+            nat: NatPolicy::default(),
+            domains: DomainMap::default(),
+            events: None,
+        }
+    }
+
+    /// Challenge REGISTERs in `realm` and check the credentials with RADIUS
+    pub fn with_radius_auth(mut self, client: Arc<RadiusClient>, realm: &str) -> Self {
+        self.auth = Some(Arc::new(DigestAuth::new(realm, Verifier::Radius(client))));
+        self
+    }
+
+    /// Challenge REGISTERs in `realm` and check the credentials against
+    /// `credentials`
+    pub fn with_credentials(mut self, credentials: &[SipCredential], realm: &str) -> Self {
+        self.auth = Some(Arc::new(DigestAuth::new(
+            realm,
+            Verifier::Local(credentials.to_vec()),
+        )));
+        self
+    }
+
+    /// Cap the expiry granted to endpoints
+    pub fn with_max_expires(mut self, seconds: u32) -> Self {
+        self.max_expires = seconds;
+        self
+    }
+
+    /// Record registering devices in a shared inventory
+    pub fn with_device_inventory(mut self, devices: DeviceInventory) -> Self {
+        self.devices = devices;
+        self
+    }
+
+    /// Apply the SIP profiles' NAT settings to registrations in their
+    /// domains
+    pub fn with_nat_policy(mut self, nat: NatPolicy) -> Self {
+        self.nat = nat;
+        self
+    }
+
+    /// Store registrations under a domain alias under the canonical domain,
+    /// challenged in that domain's realm
+    pub fn with_domains(mut self, domains: DomainMap) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Publish bindings as they are added, removed and expire
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Devices seen registering
+    pub fn devices(&self) -> &DeviceInventory {
+        &self.devices
+    }
+
+    /// Process a REGISTER request, updating bindings and building the reply
+    pub async fn handle_register(&self, request: &Request, source: Option<SocketAddr>) -> Response {
+        let Some(aor) = request.get_header_value("To").map(uri_of) else {
+            return response(request, StatusCode::BAD_REQUEST).build();
+        };
+        let aor = self.domains.canonical_aor(aor);
+        if let Some(auth) = &self.auth {
+            if let Some(rejection) = auth.check(request, &aor, &self.domains).await {
+                return rejection;
+            }
+        }
+
+        let default_expires = request
+            .get_header_value("Expires")
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .unwrap_or(DEFAULT_EXPIRES);
+        let nat = aor
+            .parse::<Uri>()
+            .map(|uri| self.nat.for_domain(&uri.host))
+            .unwrap_or_default();
+        if let Some(min_expires) = nat.min_expires {
+            let too_brief = request
+                .headers
+                .iter()
+                .filter(|h| h.name.as_str().eq_ignore_ascii_case("Contact"))
+                .map(|h| h.value.as_str())
+                .filter(|value| value.trim() != "*")
+                .map(|value| {
+                    contact_expires(value)
+                        .unwrap_or(default_expires)
+                        .min(self.max_expires)
+                })
+                .any(|expires| expires != 0 && expires < min_expires);
+            if too_brief {
+                debug!("REGISTER for {} below the minimum of {}s", aor, min_expires);
+                return response(request, StatusCode::INTERVAL_TOO_BRIEF)
+                    .header("Min-Expires", min_expires.to_string())
+                    .build();
+            }
+        }
+        let user_agent = request.get_header_value("User-Agent").map(str::to_string);
+        let now = Utc::now();
+
+        let sighting = uri_user(&aor).map(|extension| Sighting {
+            extension: extension.to_string(),
+            user_agent: user_agent.clone(),
+            contact: request
+                .headers
+                .iter()
+                .filter(|h| h.name.as_str().eq_ignore_ascii_case("Contact"))
+                .map(|h| h.value.as_str())
+                .find(|value| value.trim() != "*")
+                .map(str::to_string),
+            source: source.map(|s| s.ip()),
+        });
+        let device_id = sighting.as_ref().map(Sighting::device_id);
+        if let Some(id) = &device_id {
+            if self.devices.is_revoked(id).await {
+                info!("Refused REGISTER for {} from revoked device {}", aor, id);
+                return response(request, StatusCode::FORBIDDEN)
+                    .header("Warning", "399 rustalk \"Device revoked\"")
+                    .build();
+            }
+        }
+        let mut registered = false;
+        // reg-id only means something to a UA that supports outbound
+        let outbound = has_option_tag(request, "Supported", OUTBOUND);
+        let gruu = has_option_tag(request, "Supported", GRUU);
+        let mut flows = false;
+        let mut events = Vec::new();
+
+        let mut bindings = self.bindings.write().await;
+        let entries = bindings.entry(aor.clone()).or_default();
+        for expired in take(entries, |r| r.is_expired(now)) {
+            events.push(expired.event(RegistrationEventKind::Expired));
+        }
+
+        let contacts = request
+            .headers
+            .iter()
+            .filter(|h| h.name.as_str().eq_ignore_ascii_case("Contact"))
+            .map(|h| h.value.as_str());
+        for value in contacts {
+            // "Contact: *" with Expires: 0 removes every binding
+            if value.trim() == "*" {
+                if default_expires == 0 {
+                    for removed in entries.drain(..) {
+                        info!("Unregistered {} at {}", aor, removed.contact);
+                        events.push(removed.event(RegistrationEventKind::Unregistered));
+                    }
+                }
+                continue;
+            }
+
+            let contact = uri_of(value).to_string();
+            let expires = contact_expires(value)
+                .unwrap_or(default_expires)
+                .min(self.max_expires);
+            let instance = instance_id(value);
+            let reg_id = reg_id(value).filter(|_| outbound && instance.is_some());
+            flows |= reg_id.is_some();
+            let temp = instance.as_ref().filter(|_| gruu).map(|instance| {
+                entries
+                    .iter()
+                    .filter(|r| r.instance_id.as_ref() == Some(instance))
+                    .find_map(|r| r.temp_gruu.clone())
+                    .unwrap_or_else(|| temp_gruu(&aor))
+            });
+            // A flow, or an instance without flows, replaces its earlier
+            // binding wherever that was registered from
+            let replaced = take(entries, |r| {
+                r.contact == contact
+                    || (instance.is_some() && r.instance_id == instance && r.reg_id == reg_id)
+            });
+            if expires == 0 {
+                info!("Unregistered {} at {}", aor, contact);
+                for removed in replaced {
+                    events.push(removed.event(RegistrationEventKind::Unregistered));
+                }
+                continue;
+            }
+
+            info!("Registered {} at {} for {}s", aor, contact, expires);
+            let send_to_contact = !source.is_some_and(|source| {
+                nat.rewrite_contact
+                    .applies(contact_ip(&contact), source.ip())
+            });
+            entries.push(Registration {
+                aor: aor.clone(),
+                behind_nat: is_behind_nat(&contact, source),
+                contact,
+                user_agent: user_agent.clone(),
+                received: source,
+                registered_at: now,
+                expires_at: now + Duration::seconds(expires as i64),
+                device_id: device_id.clone(),
+                reachable: None,
+                checked_at: None,
+                pub_gruu: temp
+                    .as_ref()
+                    .and(instance.as_deref())
+                    .map(|i| pub_gruu(&aor, i)),
+                temp_gruu: temp,
+                instance_id: instance,
+                reg_id,
+                send_to_contact,
+            });
+            // Refreshes are not news
+            if replaced.is_empty() {
+                if let Some(added) = entries.last() {
+                    events.push(added.event(RegistrationEventKind::Registered));
+                }
+            }
+            registered = true;
+        }
+
+        let mut ok = response(request, StatusCode::OK);
+        for binding in entries.iter() {
+            let mut contact = format!("<{}>;expires={}", binding.contact, binding.expires_in());
+            if let Some(instance) = &binding.instance_id {
+                contact.push_str(&format!(";+sip.instance=\"<{}>\"", instance));
+            }
+            if let Some(reg_id) = binding.reg_id {
+                contact.push_str(&format!(";reg-id={}", reg_id));
+            }
+            if let (Some(public), Some(temp)) = (&binding.pub_gruu, &binding.temp_gruu) {
+                contact.push_str(&format!(";pub-gruu=\"{}\";temp-gruu=\"{}\"", public, temp));
+            }
+            ok = ok.header("Contact", contact);
+        }
+        if flows {
+            ok = ok.header("Require", OUTBOUND);
+            // How often the client should ping its flows (RFC 5626 section 4.4.1)
+            if let Some(interval) = nat.keepalive_interval_seconds {
+                ok = ok.header("Flow-Timer", interval.to_string());
+            }
+        }
+        if entries.is_empty() {
+            bindings.remove(&aor);
+        }
+        drop(bindings);
+        self.publish(events);
+
+        if let Some(sighting) = sighting.filter(|_| registered) {
+            self.devices.record(&sighting).await;
+        }
+        ok.build()
+    }
+
+    /// Active bindings, ordered by address-of-record
+    pub async fn bindings(&self) -> Vec<Registration> {
+        let now = Utc::now();
+        let bindings = self.bindings.read().await;
+        let mut active: Vec<Registration> = bindings
+            .values()
+            .flatten()
+            .filter(|r| !r.is_expired(now))
+            .cloned()
+            .collect();
+        active.sort_by(|a, b| a.aor.cmp(&b.aor).then(a.contact.cmp(&b.contact)));
+        active
+    }
+
+    /// Active bindings for one address-of-record, under its domain or an
+    /// alias of it
+    pub async fn lookup(&self, aor: &str) -> Vec<Registration> {
+        let now = Utc::now();
+        let bindings = self.bindings.read().await;
+        bindings
+            .get(&self.domains.canonical_aor(aor))
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|r| !r.is_expired(now))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Active bindings of the instance a GRUU names, or `None` when `uri`
+    /// is not a GRUU
+    pub async fn resolve_gruu(&self, uri: &Uri) -> Option<Vec<Registration>> {
+        let target = gruu_target(uri)?;
+        let now = Utc::now();
+        let bindings = self.bindings.read().await;
+        let matches = |r: &Registration| match &target {
+            GruuTarget::Public { user, instance } => {
+                uri_user(&r.aor) == Some(user.as_str()) && r.instance_id.as_ref() == Some(instance)
+            }
+            GruuTarget::Temporary { user } => {
+                r.temp_gruu.as_deref().and_then(uri_user) == Some(user.as_str())
+            }
+        };
+        Some(
+            bindings
+                .values()
+                .flatten()
+                .filter(|r| !r.is_expired(now) && matches(r))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Record whether `contact` could be reached when a call was forked to
+    /// it
+    pub async fn set_reachable(&self, contact: &str, reachable: bool) {
+        let now = Utc::now();
+        let mut bindings = self.bindings.write().await;
+        for binding in bindings
+            .values_mut()
+            .flatten()
+            .filter(|r| r.contact == contact)
+        {
+            if binding.reachable != Some(reachable) {
+                debug!(
+                    "{} at {} is {}",
+                    binding.aor,
+                    contact,
+                    if reachable {
+                        "reachable"
+                    } else {
+                        "unreachable"
+                    }
+                );
+            }
+            binding.reachable = Some(reachable);
+            binding.checked_at = Some(now);
+        }
+    }
+
+    /// Drop every binding a device holds, returning how many were removed
+    pub async fn remove_device(&self, device_id: &str) -> usize {
+        let mut bindings = self.bindings.write().await;
+        let mut removed = 0;
+        for entries in bindings.values_mut() {
+            let before = entries.len();
+            entries.retain(|r| r.device_id.as_deref() != Some(device_id));
+            removed += before - entries.len();
+        }
+        bindings.retain(|_, entries| !entries.is_empty());
+        removed
+    }
+
+    /// Drop expired bindings, returning how many were removed
+    pub async fn purge_expired(&self) -> usize {
+        let now = Utc::now();
+        let mut bindings = self.bindings.write().await;
+        let mut expired = Vec::new();
+        for entries in bindings.values_mut() {
+            expired.extend(take(entries, |r| r.is_expired(now)));
+        }
+        bindings.retain(|_, entries| !entries.is_empty());
+        drop(bindings);
+        let removed = expired.len();
+        if removed > 0 {
+            debug!("Purged {} expired registrations", removed);
+        }
+        self.publish(
+            expired
+                .iter()
+                .map(|r| r.event(RegistrationEventKind::Expired))
+                .collect(),
+        );
+        removed
+    }
+
+    fn publish(&self, events: Vec<RegistrationEvent>) {
+        if let Some(bus) = &self.events {
+            for event in events {
+                bus.publish_system(SystemEvent::Registration(event));
+            }
+        }
+    }
+}
+
+/// Remove and return the bindings matching `remove`
+fn take(
+    entries: &mut Vec<Registration>,
+    remove: impl Fn(&Registration) -> bool,
+) -> Vec<Registration> {
+    let (taken, kept) = entries.drain(..).partition(|r| remove(r));
+    *entries = kept;
+    taken
+}
+
+impl Default for Registrar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// URI from a name-addr or addr-spec header value, without header parameters
+fn uri_of(value: &str) -> &str {
+    let value = value.trim();
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or(value).trim(),
+    }
+}
+
+/// `expires` parameter of a Contact header value
+fn contact_expires(value: &str) -> Option<u32> {
+    contact_param(value, "expires")?.parse().ok()
+}
+
+/// Value of a header parameter of a Contact header value
+fn contact_param<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    let params = match value.find('>') {
+        Some(end) => &value[end + 1..],
+        None => value.split_once(';').map(|(_, p)| p).unwrap_or(""),
+    };
+    params
+        .split(';')
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(param, _)| param.trim().eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+}
+
+/// A contact advertising an IP other than the one the request came from is
+/// behind NAT
+fn is_behind_nat(contact: &str, source: Option<SocketAddr>) -> bool {
+    let Some(source) = source else {
+        return false;
+    };
+    let host_port = contact
+        .split_once(':')
+        .map(|(_, rest)| rest)
+        .unwrap_or(contact);
+    let host_port = host_port
+        .rsplit_once('@')
+        .map(|(_, h)| h)
+        .unwrap_or(host_port);
+    let host_port = host_port.split(';').next().unwrap_or(host_port);
+
+    let (host, port) = match host_port.parse::<SocketAddr>() {
+        Ok(addr) => (addr.ip(), Some(addr.port())),
+        Err(_) => match host_port.split(':').next().unwrap_or("").parse::<IpAddr>() {
+            Ok(ip) => (ip, None),
+            // Hostnames cannot be compared against the source address
+            Err(_) => return false,
+        },
+    };
+
+    host != source.ip() || port.is_some_and(|p| p != source.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nat::{NatProfile, NatRewrite, NatSettings};
+    use crate::sip::{Method, Uri};
+
+    fn register(contact: &str, expires: Option<&str>) -> Request {
+        let mut request = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 192.168.1.20:5060;branch=z9hG4bK-reg1")
+        .with_header("From", "<sip:1001@example.com>;tag=r1")
+        .with_header("Call-ID", "reg1")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("CSeq", "1 REGISTER")
+        .with_header("Contact", contact)
+        .with_header("User-Agent", "Softphone/1.0");
+        if let Some(expires) = expires {
+            request = request.with_header("Expires", expires);
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_register_and_unregister() {
+        let registrar = Registrar::new();
+        let source: SocketAddr = "203.0.113.5:40000".parse().unwrap();
+
+        let response = registrar
+            .handle_register(
+                &register("<sip:1001@192.168.1.20:5060>", Some("600")),
+                Some(source),
+            )
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert!(response
+            .get_header_value("Contact")
+            .unwrap()
+            .starts_with("<sip:1001@192.168.1.20:5060>;expires="));
+        assert_eq!(
+            response.get_header_value("From"),
+            Some("<sip:1001@example.com>;tag=r1")
+        );
+
+        let bindings = registrar.bindings().await;
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].aor, "sip:1001@example.com");
+        assert_eq!(bindings[0].user_agent.as_deref(), Some("Softphone/1.0"));
+        assert_eq!(bindings[0].received, Some(source));
+        assert!(bindings[0].behind_nat);
+        assert!(bindings[0].expires_in() > 590);
+
+        registrar
+            .handle_register(
+                &register("<sip:1001@192.168.1.20:5060>;expires=0", None),
+                Some(source),
+            )
+            .await;
+        assert!(registrar.bindings().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registration_events() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe_system();
+        let registrar = Registrar::new().with_event_bus(bus);
+        let kinds = |events: &mut tokio::sync::broadcast::Receiver<SystemEvent>| {
+            std::iter::from_fn(|| events.try_recv().ok())
+                .map(|event| match event {
+                    SystemEvent::Registration(event) => (event.kind, event.contact),
+                    other => panic!("unexpected {:?}", other),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let contact = "sip:1001@192.168.1.20:5060".to_string();
+        for _ in 0..2 {
+            registrar
+                .handle_register(&register("<sip:1001@192.168.1.20:5060>", None), None)
+                .await;
+        }
+        registrar
+            .handle_register(&register("*", Some("0")), None)
+            .await;
+        // The refresh is not reported
+        assert_eq!(
+            kinds(&mut events),
+            vec![
+                (RegistrationEventKind::Registered, contact.clone()),
+                (RegistrationEventKind::Unregistered, contact.clone()),
+            ]
+        );
+
+        registrar
+            .handle_register(&register("<sip:1001@192.168.1.20:5060>", None), None)
+            .await;
+        for binding in registrar.bindings.write().await.values_mut().flatten() {
+            binding.expires_at = Utc::now() - Duration::seconds(1);
+        }
+        assert_eq!(registrar.purge_expired().await, 1);
+        assert_eq!(
+            kinds(&mut events),
+            vec![
+                (RegistrationEventKind::Registered, contact.clone()),
+                (RegistrationEventKind::Expired, contact),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nat_profile_settings() {
+        let nat = NatPolicy::default();
+        nat.set_profile(NatProfile {
+            name: "external".to_string(),
+            domain: "example.com".to_string(),
+            bind_port: None,
+            nat: NatSettings {
+                keepalive_interval_seconds: Some(25),
+                min_expires: Some(300),
+                rewrite_contact: NatRewrite::Never,
+                rewrite_sdp: NatRewrite::Never,
+            },
+        });
+        let registrar = Registrar::new().with_nat_policy(nat);
+        let source: SocketAddr = "203.0.113.5:40000".parse().unwrap();
+
+        let response = registrar
+            .handle_register(&register("<sip:1001@10.0.0.20>", Some("60")), Some(source))
+            .await;
+        assert_eq!(response.status_code, StatusCode::INTERVAL_TOO_BRIEF);
+        assert_eq!(response.get_header_value("Min-Expires"), Some("300"));
+        assert!(registrar.bindings().await.is_empty());
+
+        let response = registrar
+            .handle_register(
+                &register(
+                    "<sip:1001@10.0.0.20;transport=tcp>;+sip.instance=\"<urn:uuid:1>\";reg-id=1",
+                    Some("600"),
+                )
+                .with_header("Supported", "outbound"),
+                Some(source),
+            )
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.get_header_value("Flow-Timer"), Some("25"));
+        let bindings = registrar.lookup("sip:1001@example.com").await;
+        assert!(bindings[0].behind_nat);
+        assert!(bindings[0].send_to_contact);
+
+        // Unregistering is never too brief
+        let response = registrar
+            .handle_register(
+                &register("<sip:1001@10.0.0.20;transport=tcp>", Some("0")),
+                Some(source),
+            )
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_nat_rewrite_detect() {
+        let nat = NatPolicy::default();
+        nat.set_profile(NatProfile {
+            name: "external".to_string(),
+            domain: "example.com".to_string(),
+            bind_port: None,
+            nat: NatSettings {
+                rewrite_contact: NatRewrite::Detect,
+                ..NatSettings::default()
+            },
+        });
+        let registrar = Registrar::new().with_nat_policy(nat);
+        let source: SocketAddr = "203.0.113.5:40000".parse().unwrap();
+
+        // A phone advertising a private address is reached at its source
+        registrar
+            .handle_register(&register("<sip:1001@10.0.0.20>", None), Some(source))
+            .await;
+        // A carrier node advertising another public address is reached there
+        registrar
+            .handle_register(&register("<sip:1001@198.51.100.7>", None), Some(source))
+            .await;
+        let bindings = registrar.lookup("sip:1001@example.com").await;
+        let send_to_contact: Vec<(&str, bool)> = bindings
+            .iter()
+            .map(|b| (b.contact.as_str(), b.send_to_contact))
+            .collect();
+        assert_eq!(
+            send_to_contact,
+            [
+                ("sip:1001@10.0.0.20", false),
+                ("sip:1001@198.51.100.7", true)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outbound_flows() {
+        let registrar = Registrar::new();
+        let instance = "+sip.instance=\"<urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6>\"";
+        let flow = |host: &str, reg_id: u32| {
+            register(
+                &format!(
+                    "<sip:1001@{};transport=tcp>;{};reg-id={}",
+                    host, instance, reg_id
+                ),
+                Some("600"),
+            )
+            .with_header("Supported", "outbound")
+        };
+
+        let response = registrar.handle_register(&flow("10.0.0.20", 1), None).await;
+        assert_eq!(response.get_header_value("Require"), Some(OUTBOUND));
+        assert!(response
+            .get_header_value("Contact")
+            .unwrap()
+            .ends_with(";reg-id=1"));
+        registrar
+            .handle_register(&flow("100.64.0.9", 2), None)
+            .await;
+        // Wi-Fi reconnects from a new address, replacing its old flow
+        registrar.handle_register(&flow("10.0.0.21", 1), None).await;
+        let mut contacts: Vec<_> = registrar
+            .lookup("sip:1001@example.com")
+            .await
+            .into_iter()
+            .map(|r| (r.contact, r.reg_id))
+            .collect();
+        contacts.sort();
+        assert_eq!(
+            contacts,
+            [
+                ("sip:1001@10.0.0.21;transport=tcp".to_string(), Some(1)),
+                ("sip:1001@100.64.0.9;transport=tcp".to_string(), Some(2)),
+            ]
+        );
+
+        // Without outbound support reg-id is ignored
+        let plain = register(
+            &format!("<sip:1001@10.0.0.22>;{};reg-id=3", instance),
+            Some("600"),
+        );
+        let response = registrar.handle_register(&plain, None).await;
+        assert!(response.get_header_value("Require").is_none());
+        let bindings = registrar.lookup("sip:1001@example.com").await;
+        assert_eq!(bindings.len(), 3);
+        assert!(bindings.iter().any(|r| r.reg_id.is_none()
+            && r.instance_id.as_deref() == Some("urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6")));
+
+        // Unregistering a flow leaves the instance's other flows alone
+        let unregister = register(
+            &format!(
+                "<sip:1001@100.64.0.9;transport=tcp>;{};reg-id=2;expires=0",
+                instance
+            ),
+            None,
+        )
+        .with_header("Supported", "outbound");
+        let response = registrar.handle_register(&unregister, None).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let bindings = registrar.lookup("sip:1001@example.com").await;
+        assert_eq!(bindings.len(), 2);
+        assert!(bindings.iter().all(|r| r.reg_id != Some(2)));
+    }
+
+    #[tokio::test]
+    async fn test_gruu_assignment() {
+        let registrar = Registrar::new();
+        let instance = "urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6";
+        let request = |host: &str| {
+            register(
+                &format!("<sip:1001@{}>;+sip.instance=\"<{}>\"", host, instance),
+                Some("600"),
+            )
+            .with_header("Supported", "gruu")
+        };
+
+        let response = registrar.handle_register(&request("10.0.0.20"), None).await;
+        let contact = response.get_header_value("Contact").unwrap();
+        assert!(contact.contains(&format!(
+            ";pub-gruu=\"sip:1001@example.com;gr={}\"",
+            instance
+        )));
+        let temp = registrar.lookup("sip:1001@example.com").await[0]
+            .temp_gruu
+            .clone()
+            .unwrap();
+        assert!(contact.contains(&format!(";temp-gruu=\"{}\"", temp)));
+
+        // A refresh from a new address keeps the temporary GRUU
+        registrar.handle_register(&request("10.0.0.21"), None).await;
+        registrar
+            .handle_register(&register("<sip:1001@10.0.0.30>", Some("600")), None)
+            .await;
+        let bindings = registrar.lookup("sip:1001@example.com").await;
+        assert!(bindings
+            .iter()
+            .filter(|r| r.instance_id.is_some())
+            .all(|r| r.temp_gruu.as_deref() == Some(temp.as_str())));
+        assert!(bindings
+            .iter()
+            .filter(|r| r.instance_id.is_none())
+            .all(|r| r.pub_gruu.is_none() && r.temp_gruu.is_none()));
+
+        // Either GRUU reaches the instance and not the desk phone
+        let public: Uri = format!("sip:1001@example.com;gr={}", instance)
+            .parse()
+            .unwrap();
+        for uri in [public, temp.parse().unwrap()] {
+            let resolved = registrar.resolve_gruu(&uri).await.unwrap();
+            assert!(!resolved.is_empty());
+            assert!(resolved.iter().all(|r| r.instance_id.is_some()));
+        }
+        let unknown: Uri = "sip:tgruu.0000000000000000@example.com;gr".parse().unwrap();
+        assert!(registrar.resolve_gruu(&unknown).await.unwrap().is_empty());
+        let plain: Uri = "sip:1001@example.com".parse().unwrap();
+        assert!(registrar.resolve_gruu(&plain).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_device_refused() {
+        let registrar = Registrar::new();
+        let source: SocketAddr = "203.0.113.5:40000".parse().unwrap();
+        let request = register("<sip:1001@192.168.1.20:5060>", Some("600"));
+
+        registrar.handle_register(&request, Some(source)).await;
+        let devices = registrar.devices().list().await;
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].extension, "1001");
+        assert_eq!(devices[0].last_ip, Some(source.ip()));
+
+        let id = devices[0].id.clone();
+        registrar.devices().revoke(&id).await;
+        assert_eq!(registrar.remove_device(&id).await, 1);
+        assert!(registrar.bindings().await.is_empty());
+
+        let response = registrar.handle_register(&request, Some(source)).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        assert!(registrar.bindings().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expires_capped_and_nat_detection() {
+        let registrar = Registrar::new().with_max_expires(120);
+        let source: SocketAddr = "198.51.100.7:5060".parse().unwrap();
+
+        registrar
+            .handle_register(
+                &register("<sip:1001@198.51.100.7:5060>", Some("7200")),
+                Some(source),
+            )
+            .await;
+
+        let bindings = registrar.lookup("sip:1001@example.com").await;
+        assert_eq!(bindings.len(), 1);
+        assert!(!bindings[0].behind_nat);
+        assert!(bindings[0].expires_in() <= 120);
+        assert_eq!(bindings[0].reachable, None);
+
+        registrar
+            .set_reachable("sip:1001@198.51.100.7:5060", false)
+            .await;
+        let bindings = registrar.lookup("sip:1001@example.com").await;
+        assert_eq!(bindings[0].reachable, Some(false));
+        assert!(bindings[0].checked_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_radius_digest_auth() {
+        use crate::radius::packet::DIGEST_METHOD;
+        use crate::radius::tests::{client, radius_server};
+
+        let (server, mut requests) = radius_server("1001").await;
+        let registrar =
+            Registrar::new().with_radius_auth(Arc::new(client(Some(server), None)), "example.com");
+        let request = register("<sip:1001@192.168.1.20:5060>", None);
+        let nonce = |response: &Response| {
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            let challenge = response.get_header_value("WWW-Authenticate").unwrap();
+            let nonce = challenge.split("nonce=\"").nth(1).unwrap();
+            nonce.split('"').next().unwrap().to_string()
+        };
+        let signed = |user: &str, nonce: &str| {
+            let authorization = format!(
+                r#"Digest username="{}", realm="example.com", nonce="{}", uri="sip:example.com", response="0123", qop=auth, nc=00000001, cnonce="abc""#,
+                user, nonce
+            );
+            request
+                .clone()
+                .with_header("Authorization", authorization.as_str())
+        };
+
+        let first = nonce(&registrar.handle_register(&request, None).await);
+        let response = registrar
+            .handle_register(&signed("1001", &first), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(registrar.bindings().await.len(), 1);
+        let sent = requests.recv().await.unwrap();
+        assert_eq!(sent.get(DIGEST_METHOD), Some(&b"REGISTER"[..]));
+
+        // Nonces are single use
+        let response = registrar
+            .handle_register(&signed("1001", &first), None)
+            .await;
+        let second = nonce(&response);
+
+        let response = registrar
+            .handle_register(&signed("1002", &second), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_local_digest_auth() {
+        let credentials = [
+            SipCredential {
+                username: "1001".to_string(),
+                password: "secret1001".to_string(),
+                domain: None,
+            },
+            SipCredential {
+                username: "1002".to_string(),
+                password: "secret1002".to_string(),
+                domain: None,
+            },
+        ];
+        let registrar = Registrar::new().with_credentials(&credentials, "example.com");
+        let request = register("<sip:1001@192.168.1.20:5060>", None);
+        let challenge = |response: &Response| {
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            // The challenge answers the REGISTER's transaction
+            assert_eq!(response.get_header_value("CSeq"), Some("1 REGISTER"));
+            assert_eq!(
+                response.get_header_value("Via"),
+                Some("SIP/2.0/UDP 192.168.1.20:5060;branch=z9hG4bK-reg1")
+            );
+            let challenge = response.get_header_value("WWW-Authenticate").unwrap();
+            let nonce = challenge.split("nonce=\"").nth(1).unwrap();
+            nonce.split('"').next().unwrap().to_string()
+        };
+        let signed = |user: &str, password: &str, nonce: &str| {
+            let hash = |input: String| format!("{:x}", md5::compute(input));
+            let ha1 = hash(format!("{}:example.com:{}", user, password));
+            let ha2 = hash("REGISTER:sip:example.com".to_string());
+            let response = hash(format!("{}:{}:00000001:abc:auth:{}", ha1, nonce, ha2));
+            let authorization = format!(
+                r#"Digest username="{}", realm="example.com", nonce="{}", uri="sip:example.com", response="{}", qop=auth, nc=00000001, cnonce="abc""#,
+                user, nonce, response
+            );
+            request
+                .clone()
+                .with_header("Authorization", authorization.as_str())
+        };
+
+        let nonce = challenge(&registrar.handle_register(&request, None).await);
+        let response = registrar
+            .handle_register(&signed("1001", "wrong", &nonce), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        // Nonces are single use, even after a failed attempt
+        let response = registrar
+            .handle_register(&signed("1001", "secret1001", &nonce), None)
+            .await;
+        let nonce = challenge(&response);
+
+        // A valid user cannot register someone else's extension
+        let response = registrar
+            .handle_register(&signed("1002", "secret1002", &nonce), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        assert!(registrar.bindings().await.is_empty());
+
+        let nonce = challenge(&registrar.handle_register(&request, None).await);
+        let response = registrar
+            .handle_register(&signed("1001", "secret1001", &nonce), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(registrar.lookup("sip:1001@example.com").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_domain_aliases() {
+        let domains = DomainMap::new(
+            serde_json::from_value(serde_json::json!({
+                "profiles": [
+                    { "name": "brand-a", "domain": "example.com", "aliases": ["voice.example.com"] },
+                    { "name": "brand-b", "domain": "other.example", "realm": "Other Voice" }
+                ]
+            }))
+            .unwrap(),
+        );
+        let credentials = [
+            SipCredential {
+                username: "1001".to_string(),
+                password: "brand-a".to_string(),
+                domain: Some("voice.example.com".to_string()),
+            },
+            SipCredential {
+                username: "1001".to_string(),
+                password: "brand-b".to_string(),
+                domain: Some("other.example".to_string()),
+            },
+        ];
+        let registrar = Registrar::new()
+            .with_credentials(&credentials, "pbx")
+            .with_domains(domains);
+        let request = |domain: &str| {
+            Request::new(
+                Method::Register,
+                Uri::new("sip".to_string(), domain.to_string()),
+            )
+            .with_header("Call-ID", "reg1")
+            .with_header("To", format!("<sip:1001@{}>", domain).as_str())
+            .with_header("Contact", "<sip:1001@192.168.1.20:5060>")
+        };
+        let challenge = |response: &Response| {
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            let challenge = response.get_header_value("WWW-Authenticate").unwrap();
+            let field = |name: &str| {
+                let value = challenge.split(&format!("{}=\"", name)).nth(1).unwrap();
+                value.split('"').next().unwrap().to_string()
+            };
+            (field("realm"), field("nonce"))
+        };
+        let signed = |domain: &str, password: &str, (realm, nonce): (String, String)| {
+            let hash = |input: String| format!("{:x}", md5::compute(input));
+            let ha1 = hash(format!("1001:{}:{}", realm, password));
+            let ha2 = hash(format!("REGISTER:sip:{}", domain));
+            let response = hash(format!("{}:{}:00000001:abc:auth:{}", ha1, nonce, ha2));
+            let authorization = format!(
+                r#"Digest username="1001", realm="{}", nonce="{}", uri="sip:{}", response="{}", qop=auth, nc=00000001, cnonce="abc""#,
+                realm, nonce, domain, response
+            );
+            request(domain).with_header("Authorization", authorization.as_str())
+        };
+
+        // An alias is challenged in its domain's realm and registers there
+        let first = challenge(
+            &registrar
+                .handle_register(&request("voice.example.com"), None)
+                .await,
+        );
+        assert_eq!(first.0, "example.com");
+        let response = registrar
+            .handle_register(&signed("voice.example.com", "brand-a", first), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(registrar.lookup("sip:1001@example.com").await.len(), 1);
+        assert_eq!(
+            registrar.lookup("sip:1001@voice.example.com").await.len(),
+            1
+        );
+        assert!(registrar.lookup("sip:1001@other.example").await.is_empty());
+
+        // The same extension in another domain has its own password
+        let second = challenge(
+            &registrar
+                .handle_register(&request("other.example"), None)
+                .await,
+        );
+        assert_eq!(second.0, "Other Voice");
+        let response = registrar
+            .handle_register(&signed("other.example", "brand-a", second), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        let third = challenge(
+            &registrar
+                .handle_register(&request("other.example"), None)
+                .await,
+        );
+        let response = registrar
+            .handle_register(&signed("other.example", "brand-b", third), None)
+            .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(registrar.lookup("sip:1001@other.example").await.len(), 1);
+    }
+
+    #[test]
+    fn test_header_helpers() {
+        assert_eq!(uri_of("\"Alice\" <sip:1001@host>;tag=1"), "sip:1001@host");
+        assert_eq!(uri_of("sip:1001@host;expires=60"), "sip:1001@host");
+        assert_eq!(
+            contact_expires("<sip:1001@host>;q=0.5;expires=60"),
+            Some(60)
+        );
+        assert_eq!(contact_expires("<sip:1001@host>"), None);
+        assert!(!is_behind_nat("sip:1001@pbx.example.com", None));
+    }
+}
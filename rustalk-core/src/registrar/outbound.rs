@@ -0,0 +1,133 @@
+//! Client-initiated connections (RFC 5626)
+//!
+//! A UA that supports outbound names itself with a `+sip.instance` and
+//! registers one flow per network path, each with its own `reg-id` - a
+//! phone on Wi-Fi and mobile data at once. A REGISTER for an instance and
+//! reg-id replaces the flow registered before it rather than adding to it,
+//! so a client that reconnects from a new address does not leave a dead
+//! binding behind. Requests for the instance are sent down one live flow.
+
+use super::Registration;
+use crate::sip::Request;
+
+/// Option tag for outbound support
+pub const OUTBOUND: &str = "outbound";
+
+/// Whether any `header` of a request lists option tag `tag`
+pub fn has_option_tag(request: &Request, header: &str, tag: &str) -> bool {
+    request
+        .headers
+        .iter()
+        .filter(|h| h.name.as_str().eq_ignore_ascii_case(header))
+        .flat_map(|h| h.value.as_str().split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(tag))
+}
+
+/// `+sip.instance` of a Contact, without its quotes and angle brackets
+pub fn instance_id(value: &str) -> Option<String> {
+    let instance = super::contact_param(value, "+sip.instance")?;
+    let instance = instance.trim_matches('"');
+    let instance = instance
+        .strip_prefix('<')
+        .and_then(|i| i.strip_suffix('>'))
+        .unwrap_or(instance);
+    (!instance.is_empty()).then(|| instance.to_string())
+}
+
+/// `reg-id` of a Contact
+pub fn reg_id(value: &str) -> Option<u32> {
+    super::contact_param(value, "reg-id")?.parse().ok()
+}
+
+/// Bindings a request for an address-of-record is sent to
+///
+/// Bindings without an instance are all used. Of the flows an instance has
+/// registered, only one is: a flow not known to be unreachable, and of
+/// those the most recently registered.
+pub fn select_flows<'a>(bindings: &[&'a Registration]) -> Vec<&'a Registration> {
+    let mut selected: Vec<&'a Registration> = Vec::new();
+    for binding in bindings {
+        let Some(instance) = &binding.instance_id else {
+            selected.push(binding);
+            continue;
+        };
+        let rank = |r: &Registration| (r.reachable != Some(false), r.registered_at);
+        match selected
+            .iter_mut()
+            .find(|s| s.instance_id.as_ref() == Some(instance))
+        {
+            Some(current) if rank(binding) > rank(current) => *current = binding,
+            Some(_) => {}
+            None => selected.push(binding),
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Method, Uri};
+    use chrono::{Duration, Utc};
+
+    fn flow(
+        contact: &str,
+        instance: Option<&str>,
+        age: i64,
+        reachable: Option<bool>,
+    ) -> Registration {
+        Registration {
+            aor: "sip:1001@example.com".to_string(),
+            contact: contact.to_string(),
+            user_agent: None,
+            received: None,
+            behind_nat: false,
+            registered_at: Utc::now() - Duration::seconds(age),
+            expires_at: Utc::now() + Duration::seconds(600),
+            device_id: None,
+            reachable,
+            checked_at: None,
+            instance_id: instance.map(str::to_string),
+            reg_id: instance.map(|_| 1),
+            pub_gruu: None,
+            temp_gruu: None,
+            send_to_contact: false,
+        }
+    }
+
+    #[test]
+    fn test_contact_instance_and_reg_id() {
+        let contact = "<sip:1001@10.0.0.20;transport=tcp>;+sip.instance=\"<urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6>\";reg-id=2;expires=600";
+        assert_eq!(
+            instance_id(contact).as_deref(),
+            Some("urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6")
+        );
+        assert_eq!(reg_id(contact), Some(2));
+        assert!(instance_id("<sip:1001@10.0.0.20>").is_none());
+
+        let request = Request::new(
+            Method::Register,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Supported", "path, outbound");
+        assert!(has_option_tag(&request, "Supported", OUTBOUND));
+        assert!(!has_option_tag(&request, "Require", OUTBOUND));
+    }
+
+    #[test]
+    fn test_select_flows() {
+        let wifi = flow("sip:1001@10.0.0.20", Some("urn:uuid:a"), 10, None);
+        let mobile = flow("sip:1001@100.64.0.9", Some("urn:uuid:a"), 60, None);
+        let desk = flow("sip:1001@10.0.0.30", None, 600, None);
+        let selected = select_flows(&[&mobile, &desk, &wifi]);
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[0].contact, "sip:1001@10.0.0.20");
+        assert_eq!(selected[1].contact, "sip:1001@10.0.0.30");
+
+        // A flow that failed gives way to an older one that may still work
+        let wifi = flow("sip:1001@10.0.0.20", Some("urn:uuid:a"), 10, Some(false));
+        let selected = select_flows(&[&wifi, &mobile]);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].contact, "sip:1001@100.64.0.9");
+    }
+}
@@ -1,15 +1,23 @@
 //! Route evaluation engine for processing routing rules
 
+use super::failover::{DestinationHop, DestinationList, FailoverPolicy, RerouteRule};
 use super::matcher::ConditionMatcher;
 use super::{RouteAction, RouteDestination, RouteRule, RoutingConfig};
+use crate::lcr::{LeastCostRouter, TrunkQuote};
+use crate::trunk_health::TrunkHealth;
+use crate::voicemail::VoicemailDropConfig;
 use regex::Regex;
 use std::sync::Arc;
 
 /// Context for a call being routed
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CallContext {
     pub caller_id: String,
     pub destination: String,
+    /// SIP URI the destination maps to in ENUM, when looked up
+    pub enum_uri: Option<String>,
+    /// Domain called, canonical when a SIP profile serves it
+    pub domain: Option<String>,
 }
 
 /// Result of matching a route
@@ -19,12 +27,32 @@ pub struct RouteMatch {
     pub route_name: String,
     pub destination: RouteDestination,
     pub action: RouteAction,
+    /// Routes that matched first but were passed over, their trunk being
+    /// down or no trunk of a least-cost route or destination list being
+    /// left
+    pub bypassed: Vec<String>,
+    /// Trunks of a least-cost route in the order they are tried, the one
+    /// chosen as the destination first
+    pub least_cost: Vec<TrunkQuote>,
+    /// Trunks of a least-cost route or destination list passed over for
+    /// being down
+    pub trunks_down: Vec<String>,
+    /// Destinations the call is tried at in turn, the first being
+    /// `destination`; empty when the route has only the one
+    pub hops: Vec<DestinationHop>,
+    /// Responses that move the call on to the next hop
+    pub failover: Option<FailoverPolicy>,
+    /// Reroute rules of the route, their lists resolved as `hops` is
+    pub on_failure: Vec<RerouteRule>,
 }
 
 /// Route evaluator for processing routing rules
 pub struct RouteEvaluator {
     config: RoutingConfig,
     matcher: Arc<ConditionMatcher>,
+    voicemail_drop: Option<VoicemailDropConfig>,
+    trunk_health: Option<TrunkHealth>,
+    least_cost: LeastCostRouter,
 }
 
 impl RouteEvaluator {
@@ -33,26 +61,127 @@ impl RouteEvaluator {
         Self {
             config,
             matcher: Arc::new(ConditionMatcher::new()),
+            voicemail_drop: None,
+            trunk_health: None,
+            least_cost: LeastCostRouter::default(),
         }
     }
 
     /// Create a route evaluator with a custom condition matcher (for testing)
     pub fn with_matcher(config: RoutingConfig, matcher: Arc<ConditionMatcher>) -> Self {
-        Self { config, matcher }
+        Self {
+            config,
+            matcher,
+            voicemail_drop: None,
+            trunk_health: None,
+            least_cost: LeastCostRouter::default(),
+        }
+    }
+
+    /// Send numbers dialed with the voicemail drop prefix straight to the
+    /// extension's voicemail, ahead of every route
+    pub fn with_voicemail_drop(mut self, config: VoicemailDropConfig) -> Self {
+        self.voicemail_drop = Some(config);
+        self
+    }
+
+    /// Pass over routes to trunks that are down, so the call falls through
+    /// to the next matching route
+    pub fn with_trunk_health(mut self, health: TrunkHealth) -> Self {
+        self.trunk_health = Some(health);
+        self
+    }
+
+    /// Choose between the trunks of least-cost routes with these rate
+    /// cards; without them the trunks are tried in the order listed
+    pub fn with_least_cost(mut self, router: LeastCostRouter) -> Self {
+        self.least_cost = router;
+        self
     }
 
     /// Evaluate routes for a given call context
     ///
     /// Returns the first matching route, or None if no routes match
     pub fn evaluate(&self, context: &CallContext) -> Option<RouteMatch> {
+        if let Some(extension) = self
+            .voicemail_drop
+            .as_ref()
+            .and_then(|drop| drop.target(&context.destination))
+        {
+            return Some(RouteMatch {
+                route_id: "voicemail_drop".to_string(),
+                route_name: "Straight to voicemail".to_string(),
+                destination: RouteDestination::Voicemail(extension.to_string()),
+                action: RouteAction::Accept,
+                bypassed: Vec::new(),
+                least_cost: Vec::new(),
+                trunks_down: Vec::new(),
+                hops: Vec::new(),
+                failover: None,
+                on_failure: Vec::new(),
+            });
+        }
+
+        let mut bypassed = Vec::new();
         for route in self.config.enabled_routes() {
             if self.matches_route(route, context) {
-                let route_match = RouteMatch {
+                let mut route_match = RouteMatch {
                     route_id: route.id.clone(),
                     route_name: route.name.clone(),
                     destination: route.destination.clone(),
                     action: route.action.clone(),
+                    bypassed: bypassed.clone(),
+                    least_cost: Vec::new(),
+                    trunks_down: Vec::new(),
+                    hops: Vec::new(),
+                    failover: None,
+                    on_failure: Vec::new(),
                 };
+                if matches!(route.action, RouteAction::Accept) {
+                    match &route.destination {
+                        RouteDestination::LeastCost(trunks) => {
+                            let (up, down) = self.rank_trunks(trunks, context);
+                            route_match.hops = up
+                                .iter()
+                                .map(|quote| {
+                                    DestinationHop::new(RouteDestination::Trunk(
+                                        quote.trunk.clone(),
+                                    ))
+                                })
+                                .collect();
+                            route_match.least_cost = up;
+                            route_match.trunks_down = down;
+                            route_match.failover = Some(self.config.failover.clone());
+                        }
+                        RouteDestination::DestinationList(list) => {
+                            route_match.hops =
+                                self.resolve_hops(list, context, &mut route_match.trunks_down);
+                            route_match.failover = Some(
+                                list.failover
+                                    .clone()
+                                    .unwrap_or_else(|| self.config.failover.clone()),
+                            );
+                        }
+                        destination if self.is_down(destination, context) => {
+                            bypassed.push(route.id.clone());
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    if route_match.failover.is_some() {
+                        // Every trunk is down, or unrated and left out
+                        let Some(first) = route_match.hops.first() else {
+                            bypassed.push(route.id.clone());
+                            continue;
+                        };
+                        route_match.destination = first.destination.clone();
+                    }
+                    route_match.on_failure = route
+                        .on_failure
+                        .iter()
+                        .map(|rule| self.resolve_reroute(rule, context))
+                        .collect();
+                }
 
                 // If continue_on_match is false, return this match immediately
                 if !route.continue_on_match {
@@ -71,6 +200,94 @@ impl RouteEvaluator {
         None
     }
 
+    /// Whether the destination is a trunk that is down
+    fn is_down(&self, destination: &RouteDestination, context: &CallContext) -> bool {
+        let Some(health) = &self.trunk_health else {
+            return false;
+        };
+        match destination {
+            RouteDestination::Trunk(trunk) => health.is_down(trunk),
+            // Only numbers without an ENUM mapping use the fallback trunk
+            RouteDestination::Enum(trunk) => context.enum_uri.is_none() && health.is_down(trunk),
+            _ => false,
+        }
+    }
+
+    /// The hops of a list the call can be tried at, least-cost hops
+    /// expanded cheapest first and trunks that are down set aside
+    fn resolve_hops(
+        &self,
+        list: &DestinationList,
+        context: &CallContext,
+        down: &mut Vec<String>,
+    ) -> Vec<DestinationHop> {
+        let mut hops = Vec::new();
+        for hop in &list.hops {
+            match &hop.destination {
+                RouteDestination::LeastCost(trunks) => {
+                    let (up, trunks_down) = self.rank_trunks(trunks, context);
+                    down.extend(trunks_down);
+                    hops.extend(up.into_iter().map(|quote| DestinationHop {
+                        destination: RouteDestination::Trunk(quote.trunk),
+                        timeout_seconds: hop.timeout_seconds,
+                    }));
+                }
+                RouteDestination::Trunk(trunk) | RouteDestination::Enum(trunk)
+                    if self.is_down(&hop.destination, context) =>
+                {
+                    down.push(trunk.clone());
+                }
+                // Lists are not nested
+                RouteDestination::DestinationList(_) => {}
+                // Nothing after a hangup is reached, and running out of
+                // hops ends the call anyway
+                RouteDestination::Hangup => {
+                    if hops.is_empty() {
+                        hops.push(hop.clone());
+                    }
+                    break;
+                }
+                _ => hops.push(hop.clone()),
+            }
+        }
+        hops
+    }
+
+    /// A reroute rule with its list resolved and its failover policy set
+    fn resolve_reroute(&self, rule: &RerouteRule, context: &CallContext) -> RerouteRule {
+        let list = &rule.destinations;
+        RerouteRule {
+            codes: rule.codes.clone(),
+            destinations: DestinationList {
+                hops: self.resolve_hops(list, context, &mut Vec::new()),
+                failover: Some(
+                    list.failover
+                        .clone()
+                        .unwrap_or_else(|| self.config.failover.clone()),
+                ),
+            },
+        }
+    }
+
+    /// Rank the trunks of a least-cost route, cheapest first, setting
+    /// aside those that are down
+    fn rank_trunks(
+        &self,
+        trunks: &[String],
+        context: &CallContext,
+    ) -> (Vec<TrunkQuote>, Vec<String>) {
+        let (down, up): (Vec<TrunkQuote>, Vec<TrunkQuote>) = self
+            .least_cost
+            .rank(trunks, &context.destination, self.matcher.now())
+            .into_iter()
+            .partition(|quote| {
+                self.trunk_health
+                    .as_ref()
+                    .is_some_and(|health| health.is_down(&quote.trunk))
+            });
+        (up, down.into_iter().map(|quote| quote.trunk).collect())
+    }
+
     /// Check if a route matches the call context
     fn matches_route(&self, route: &RouteRule, context: &CallContext) -> bool {
         // First check if the destination pattern matches
@@ -80,10 +297,7 @@ impl RouteEvaluator {
 
         // Then check if all conditions match
         if let Some(conditions) = &route.conditions {
-            if !self
-                .matcher
-                .matches(conditions, &context.caller_id, &context.destination)
-            {
+            if !self.matcher.matches_context(conditions, context) {
                 return false;
             }
         }
@@ -99,6 +313,18 @@ impl RouteEvaluator {
         }
     }
 
+    /// Host and port a trunk is dialed at, when it is probed there
+    pub fn trunk_target(&self, trunk: &str) -> Option<(String, u16)> {
+        self.trunk_health.as_ref().and_then(|health| {
+            health
+                .config()
+                .trunks
+                .iter()
+                .find(|t| t.name == trunk)
+                .map(|t| (t.host.clone(), t.port))
+        })
+    }
+
     /// Update the routing configuration
     pub fn update_config(&mut self, config: RoutingConfig) {
         self.config = config;
@@ -140,6 +366,7 @@ mod tests {
             conditions: None,
             action: RouteAction::Accept,
             continue_on_match: false,
+            on_failure: Vec::new(),
         }
     }
 
@@ -153,6 +380,8 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "2345".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         let result = evaluator.evaluate(&context);
@@ -160,6 +389,28 @@ mod tests {
         assert_eq!(result.unwrap().route_id, "1");
     }
 
+    #[test]
+    fn test_voicemail_drop_prefix() {
+        let mut config = RoutingConfig::new();
+        config.add_route(create_test_route("1", 10, r".*"));
+
+        let evaluator = RouteEvaluator::new(config).with_voicemail_drop(Default::default());
+        let context = |destination: &str| CallContext {
+            caller_id: "1000".to_string(),
+            destination: destination.to_string(),
+            enum_uri: None,
+            domain: None,
+        };
+
+        let result = evaluator.evaluate(&context("*552345")).unwrap();
+        assert_eq!(result.route_id, "voicemail_drop");
+        assert!(matches!(
+            result.destination,
+            RouteDestination::Voicemail(ref ext) if ext == "2345"
+        ));
+        assert_eq!(evaluator.evaluate(&context("2345")).unwrap().route_id, "1");
+    }
+
     #[test]
     fn test_no_match() {
         let mut config = RoutingConfig::new();
@@ -170,6 +421,8 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "5000".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         let result = evaluator.evaluate(&context);
@@ -187,6 +440,8 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "2345".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         // Should match the high priority route first
@@ -218,6 +473,8 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "2345".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         let result = evaluator.evaluate(&context);
@@ -241,6 +498,8 @@ mod tests {
         let context1 = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "2345".to_string(),
+            enum_uri: None,
+            domain: None,
         };
         assert!(evaluator.evaluate(&context1).is_some());
 
@@ -248,6 +507,8 @@ mod tests {
         let context2 = CallContext {
             caller_id: "+442071234567".to_string(),
             destination: "2345".to_string(),
+            enum_uri: None,
+            domain: None,
         };
         assert!(evaluator.evaluate(&context2).is_none());
     }
@@ -272,6 +533,8 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "5000".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         // Should skip first route and match second
@@ -299,6 +562,8 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "2345".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         let result = evaluator.evaluate(&context);
@@ -319,6 +584,8 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "9001234567".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         let result = evaluator.evaluate(&context);
@@ -356,9 +623,187 @@ mod tests {
         let context = CallContext {
             caller_id: "+12125551234".to_string(),
             destination: "2345".to_string(),
+            enum_uri: None,
+            domain: None,
         };
 
         let result = evaluator.evaluate(&context);
         assert!(result.is_some());
     }
+
+    #[test]
+    fn test_down_trunk_bypassed() {
+        let mut config = RoutingConfig::new();
+        let mut primary = create_test_route("primary", 10, r"^\+1\d+$");
+        primary.destination = RouteDestination::Trunk("carrier-a".to_string());
+        let mut backup = create_test_route("backup", 20, r"^\+1\d+$");
+        backup.destination = RouteDestination::Trunk("carrier-b".to_string());
+        config.add_route(primary);
+        config.add_route(backup);
+
+        let health = TrunkHealth::new(crate::trunk_health::TrunkHealthConfig {
+            down_after: 1,
+            ..Default::default()
+        });
+        let evaluator = RouteEvaluator::new(config).with_trunk_health(health.clone());
+        let context = CallContext {
+            caller_id: "1001".to_string(),
+            destination: "+12125551234".to_string(),
+            enum_uri: None,
+            domain: None,
+        };
+
+        let matched = evaluator.evaluate(&context).unwrap();
+        assert_eq!(matched.route_id, "primary");
+        assert!(matched.bypassed.is_empty());
+
+        health.record("carrier-a", Err(anyhow::anyhow!("No answer")));
+        let matched = evaluator.evaluate(&context).unwrap();
+        assert_eq!(matched.route_id, "backup");
+        assert_eq!(matched.bypassed, vec!["primary"]);
+
+        // With every trunk down nothing routes
+        health.record("carrier-b", Err(anyhow::anyhow!("No answer")));
+        assert!(evaluator.evaluate(&context).is_none());
+    }
+
+    #[test]
+    fn test_least_cost_trunk() {
+        let mut config = RoutingConfig::new();
+        let mut uk = create_test_route("uk", 10, r"^\+44\d+$");
+        uk.destination = RouteDestination::LeastCost(vec![
+            "carrier-a".to_string(),
+            "carrier-b".to_string(),
+            "carrier-c".to_string(),
+        ]);
+        config.add_route(uk);
+        config.add_route(create_test_route("rest", 20, r".*"));
+
+        let rates = serde_json::from_value(serde_json::json!({
+            "rates": [
+                { "id": "a", "name": "A", "description": null, "prefix": "44", "rate_per_minute": 0.02,
+                  "connection_fee": 0.0, "minimum_charge_seconds": 0, "billing_increment_seconds": 1,
+                  "currency": "USD", "effective_date": 0, "end_date": null, "active": true, "trunk": "carrier-a" },
+                { "id": "b", "name": "B", "description": null, "prefix": "44", "rate_per_minute": 0.01,
+                  "connection_fee": 0.0, "minimum_charge_seconds": 0, "billing_increment_seconds": 1,
+                  "currency": "USD", "effective_date": 0, "end_date": null, "active": true, "trunk": "carrier-b" }
+            ],
+            "skip_unrated": true
+        }))
+        .unwrap();
+        let health = TrunkHealth::new(crate::trunk_health::TrunkHealthConfig {
+            down_after: 1,
+            ..Default::default()
+        });
+        let evaluator = RouteEvaluator::new(config)
+            .with_trunk_health(health.clone())
+            .with_least_cost(LeastCostRouter::new(rates));
+        let context = CallContext {
+            caller_id: "1001".to_string(),
+            destination: "+442079460000".to_string(),
+            enum_uri: None,
+            domain: None,
+        };
+
+        let matched = evaluator.evaluate(&context).unwrap();
+        assert_eq!(matched.route_id, "uk");
+        assert_eq!(
+            matched.destination,
+            RouteDestination::Trunk("carrier-b".to_string())
+        );
+        let order: Vec<&str> = matched
+            .least_cost
+            .iter()
+            .map(|q| q.trunk.as_str())
+            .collect();
+        assert_eq!(order, ["carrier-b", "carrier-a"]);
+
+        // The cheapest is down, so the next cheapest carries the call
+        health.record("carrier-b", Err(anyhow::anyhow!("No answer")));
+        let matched = evaluator.evaluate(&context).unwrap();
+        assert_eq!(
+            matched.destination,
+            RouteDestination::Trunk("carrier-a".to_string())
+        );
+        assert_eq!(matched.trunks_down, vec!["carrier-b"]);
+
+        // With every rated trunk down the route is passed over
+        health.record("carrier-a", Err(anyhow::anyhow!("No answer")));
+        let matched = evaluator.evaluate(&context).unwrap();
+        assert_eq!(matched.route_id, "rest");
+        assert_eq!(matched.bypassed, vec!["uk"]);
+    }
+
+    #[test]
+    fn test_destination_list_hops() {
+        let mut config = RoutingConfig::new();
+        let mut sales = create_test_route("sales", 10, r"^\+1\d+$");
+        sales.destination = serde_json::from_value(serde_json::json!({
+            "type": "DestinationList",
+            "value": {
+                "hops": [
+                    { "destination": { "type": "Trunk", "value": "carrier-a" }, "timeout_seconds": 20 },
+                    { "destination": { "type": "LeastCost", "value": ["carrier-b", "carrier-c"] } },
+                    { "destination": { "type": "Voicemail", "value": "1001" } },
+                    { "destination": { "type": "Hangup" } },
+                    { "destination": { "type": "Extension", "value": "1002" } }
+                ],
+                "failover": { "codes": ["503"] }
+            }
+        }))
+        .unwrap();
+        sales.on_failure = serde_json::from_value(serde_json::json!([
+            { "codes": ["404"], "destinations": { "hops": [
+                { "destination": { "type": "Trunk", "value": "carrier-a" } },
+                { "destination": { "type": "Extension", "value": "1002" } }
+            ] } }
+        ]))
+        .unwrap();
+        config.add_route(sales);
+
+        let health = TrunkHealth::new(crate::trunk_health::TrunkHealthConfig {
+            down_after: 1,
+            ..Default::default()
+        });
+        let evaluator = RouteEvaluator::new(config).with_trunk_health(health.clone());
+        let context = CallContext {
+            caller_id: "1001".to_string(),
+            destination: "+12125551234".to_string(),
+            enum_uri: None,
+            domain: None,
+        };
+
+        let trunk = |name: &str| RouteDestination::Trunk(name.to_string());
+        let matched = evaluator.evaluate(&context).unwrap();
+        assert_eq!(matched.destination, trunk("carrier-a"));
+        let hops: Vec<&RouteDestination> = matched.hops.iter().map(|h| &h.destination).collect();
+        // Nothing after the hangup is reached
+        assert_eq!(
+            hops,
+            [
+                &trunk("carrier-a"),
+                &trunk("carrier-b"),
+                &trunk("carrier-c"),
+                &RouteDestination::Voicemail("1001".to_string()),
+            ]
+        );
+        assert_eq!(matched.hops[0].timeout_seconds, Some(20));
+        assert!(matched.failover.unwrap().fails_over(503));
+
+        health.record("carrier-a", Err(anyhow::anyhow!("No answer")));
+        health.record("carrier-c", Err(anyhow::anyhow!("No answer")));
+        let matched = evaluator.evaluate(&context).unwrap();
+        assert_eq!(matched.destination, trunk("carrier-b"));
+        assert_eq!(matched.hops.len(), 2);
+        assert_eq!(matched.trunks_down, vec!["carrier-a", "carrier-c"]);
+        // Reroute lists are resolved too, with the default policy
+        let reroute = &matched.on_failure[0].destinations;
+        assert_eq!(
+            reroute.hops,
+            [DestinationHop::new(RouteDestination::Extension(
+                "1002".to_string()
+            ))]
+        );
+        assert_eq!(reroute.failover, Some(FailoverPolicy::default()));
+    }
 }
@@ -0,0 +1,235 @@
+//! Ordered fallback between route destinations
+//!
+//! A `DestinationList` route tries its destinations one after another:
+//! trunk A, then trunk B if A fails, then voicemail. Each hop may have a
+//! timeout after which an unanswered call moves on, and the failover
+//! policy names the final responses that move it on; any other failure
+//! ends the call there. Least-cost routes fall back between their trunks
+//! the same way, cheapest first.
+//!
+//! Once a route's own destinations are used up, its reroute rules name
+//! alternate lists for particular failures, such as another carrier on a
+//! 503 or voicemail on a 480. Each rule is used at most once per call,
+//! destinations already tried are not tried again and the number of
+//! destinations a call is tried at is capped, so reroutes cannot loop.
+
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use super::RouteDestination;
+
+/// Destinations tried in turn until one answers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DestinationList {
+    pub hops: Vec<DestinationHop>,
+    /// Responses that move the call on; the evaluator's policy when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failover: Option<FailoverPolicy>,
+}
+
+/// One destination of a list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DestinationHop {
+    pub destination: RouteDestination,
+    /// Seconds to wait for an answer before moving on to the next hop
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u32>,
+}
+
+impl DestinationHop {
+    pub fn new(destination: RouteDestination) -> Self {
+        Self {
+            destination,
+            timeout_seconds: None,
+        }
+    }
+}
+
+/// Final responses after which a call moves on to its next destination
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FailoverPolicy {
+    /// Status codes, or classes such as `5xx`
+    #[serde(default = "default_codes")]
+    pub codes: Vec<String>,
+}
+
+fn default_codes() -> Vec<String> {
+    vec!["5xx".to_string(), "408".to_string()]
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        Self {
+            codes: default_codes(),
+        }
+    }
+}
+
+impl FailoverPolicy {
+    /// Whether a final `status` moves the call on
+    pub fn fails_over(&self, status: u16) -> bool {
+        covers(&self.codes, status)
+    }
+
+    /// Codes that are neither a final status nor a class of them
+    pub fn invalid_codes(&self) -> Vec<&str> {
+        invalid_codes(&self.codes)
+    }
+}
+
+/// Alternate destinations for calls whose route failed with one of
+/// `codes`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RerouteRule {
+    /// Status codes, or classes such as `5xx`
+    pub codes: Vec<String>,
+    pub destinations: DestinationList,
+}
+
+impl RerouteRule {
+    /// Whether the rule reroutes calls that failed with `status`
+    pub fn covers(&self, status: u16) -> bool {
+        covers(&self.codes, status)
+    }
+
+    pub fn invalid_codes(&self) -> Vec<&str> {
+        invalid_codes(&self.codes)
+    }
+}
+
+fn covers(codes: &[String], status: u16) -> bool {
+    codes.iter().any(|code| match code_class(code) {
+        Some(class) => status / 100 == class,
+        None => code.parse() == Ok(status),
+    })
+}
+
+fn invalid_codes(codes: &[String]) -> Vec<&str> {
+    codes
+        .iter()
+        .filter(|code| {
+            let valid = match code_class(code) {
+                Some(class) => (3..=6).contains(&class),
+                None => code.parse::<u16>().is_ok_and(|c| (300..700).contains(&c)),
+            };
+            !valid
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+/// The class digit of codes such as `5xx`
+fn code_class(code: &str) -> Option<u16> {
+    let digit = code
+        .strip_suffix("xx")
+        .or_else(|| code.strip_suffix("XX"))?;
+    match digit.len() {
+        1 => digit.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Where a routed call goes if the destination it is at fails
+#[derive(Debug, Clone)]
+pub struct FailoverPlan {
+    /// Hops after the current one
+    remaining: VecDeque<DestinationHop>,
+    policy: FailoverPolicy,
+    /// When the current hop is given up on, if it has a timeout
+    deadline: Option<DateTime<Utc>>,
+}
+
+impl FailoverPlan {
+    /// A plan starting at the first of `hops`
+    pub fn new(hops: Vec<DestinationHop>, policy: FailoverPolicy) -> Self {
+        let mut remaining = VecDeque::from(hops);
+        let first = remaining.pop_front();
+        Self {
+            remaining,
+            policy,
+            deadline: first.and_then(|hop| deadline(&hop)),
+        }
+    }
+
+    /// Whether a final `status` from the current hop moves the call on
+    pub fn fails_over(&self, status: u16) -> bool {
+        !self.remaining.is_empty() && self.policy.fails_over(status)
+    }
+
+    /// Move on to the next hop
+    pub fn advance(&mut self) -> Option<DestinationHop> {
+        let hop = self.remaining.pop_front();
+        self.deadline = hop.as_ref().and_then(deadline);
+        hop
+    }
+
+    /// Whether the current hop's timeout ran out by `now`
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.deadline.is_some_and(|deadline| deadline <= now)
+    }
+
+    pub fn remaining(&self) -> impl Iterator<Item = &DestinationHop> {
+        self.remaining.iter()
+    }
+}
+
+fn deadline(hop: &DestinationHop) -> Option<DateTime<Utc>> {
+    hop.timeout_seconds
+        .map(|seconds| Utc::now() + Duration::seconds(i64::from(seconds)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failover_policy() {
+        let policy = FailoverPolicy::default();
+        assert!(policy.fails_over(503));
+        assert!(policy.fails_over(500));
+        assert!(policy.fails_over(408));
+        assert!(!policy.fails_over(486));
+        assert!(!policy.fails_over(404));
+
+        let policy = FailoverPolicy {
+            codes: vec![
+                "404".to_string(),
+                "6xx".to_string(),
+                "5x".to_string(),
+                "99".to_string(),
+            ],
+        };
+        assert!(policy.fails_over(404));
+        assert!(policy.fails_over(603));
+        assert!(!policy.fails_over(503));
+        assert_eq!(policy.invalid_codes(), ["5x", "99"]);
+    }
+
+    #[test]
+    fn test_failover_plan() {
+        let trunk = |name: &str| RouteDestination::Trunk(name.to_string());
+        let mut plan = FailoverPlan::new(
+            vec![
+                DestinationHop {
+                    destination: trunk("a"),
+                    timeout_seconds: Some(0),
+                },
+                DestinationHop::new(trunk("b")),
+                DestinationHop::new(RouteDestination::Voicemail("1001".to_string())),
+            ],
+            FailoverPolicy::default(),
+        );
+        assert!(plan.expired(Utc::now()));
+        assert!(plan.fails_over(503));
+        assert!(!plan.fails_over(486));
+
+        assert_eq!(plan.advance().unwrap().destination, trunk("b"));
+        assert!(!plan.expired(Utc::now()));
+        plan.advance().unwrap();
+        // Nothing is left to fail over to
+        assert!(!plan.fails_over(503));
+        assert!(plan.advance().is_none());
+    }
+}
@@ -0,0 +1,334 @@
+//! Dialplan graph export
+//!
+//! Turns the route list into a graph of the order routes are tried in, the
+//! conditions guarding each one and where matching calls go, for rendering
+//! with Graphviz (DOT) or other tools (JSON).
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use super::{RouteAction, RouteCondition, RouteDestination, RouteRule, RoutingConfig};
+
+/// Kind of node in the dialplan graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    /// Where every call enters the dialplan
+    Start,
+    Route,
+    Destination,
+    /// Calls that no route matched
+    NoMatch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+    /// Conditions guarding a route, one per line
+    pub conditions: Vec<String>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub label: String,
+}
+
+/// Route tree as nodes and edges
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl RouteGraph {
+    /// Build the graph for a routing configuration
+    ///
+    /// Enabled routes are chained in evaluation order: a route's match edge
+    /// leads to its destination and its fall-through edge to the next route.
+    /// Disabled routes are included but left out of the chain.
+    pub fn from_config(config: &RoutingConfig) -> Self {
+        let mut graph = Self::default();
+        graph.node("start", NodeKind::Start, "Incoming call", Vec::new(), true);
+
+        let mut routes: Vec<&RouteRule> = config.routes.iter().collect();
+        routes.sort_by_key(|r| r.priority);
+
+        let mut previous = ("start".to_string(), String::new());
+        for route in routes {
+            let id = format!("route:{}", route.id);
+            graph.node(
+                &id,
+                NodeKind::Route,
+                &format!("{} [{}]\n{}", route.name, route.priority, route.pattern),
+                route.conditions.iter().flatten().map(describe).collect(),
+                route.enabled,
+            );
+
+            let destination = destination_id(&route.destination);
+            if !graph.nodes.iter().any(|n| n.id == destination) {
+                graph.node(
+                    &destination,
+                    NodeKind::Destination,
+                    &destination_label(&route.destination),
+                    Vec::new(),
+                    true,
+                );
+            }
+            graph.edge(&id, &destination, &action_label(route));
+
+            if route.enabled {
+                graph.edge(&previous.0, &id, &previous.1);
+                let fall_through = if matches!(route.action, RouteAction::Continue) {
+                    "continue"
+                } else {
+                    "no match"
+                };
+                previous = (id, fall_through.to_string());
+            }
+        }
+
+        graph.node("no_match", NodeKind::NoMatch, "No route", Vec::new(), true);
+        graph.edge(&previous.0, "no_match", &previous.1);
+        graph
+    }
+
+    /// Render as a Graphviz DOT digraph
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from(
+            "digraph dialplan {\n    rankdir=LR;\n    node [fontname=\"Helvetica\"];\n",
+        );
+
+        for node in &self.nodes {
+            let mut label = node.label.clone();
+            for condition in &node.conditions {
+                label.push('\n');
+                label.push_str(condition);
+            }
+            let shape = match node.kind {
+                NodeKind::Start | NodeKind::NoMatch => "ellipse",
+                NodeKind::Route => "box",
+                NodeKind::Destination => "box3d",
+            };
+            let style = if node.enabled { "solid" } else { "dashed" };
+            let _ = writeln!(
+                dot,
+                "    {} [shape={}, style={}, label={}];",
+                quote(&node.id),
+                shape,
+                style,
+                quote(&label)
+            );
+        }
+
+        for edge in &self.edges {
+            let _ = write!(dot, "    {} -> {}", quote(&edge.from), quote(&edge.to));
+            if !edge.label.is_empty() {
+                let _ = write!(dot, " [label={}]", quote(&edge.label));
+            }
+            dot.push_str(";\n");
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn node(
+        &mut self,
+        id: &str,
+        kind: NodeKind,
+        label: &str,
+        conditions: Vec<String>,
+        enabled: bool,
+    ) {
+        self.nodes.push(GraphNode {
+            id: id.to_string(),
+            kind,
+            label: label.to_string(),
+            conditions,
+            enabled,
+        });
+    }
+
+    fn edge(&mut self, from: &str, to: &str, label: &str) {
+        self.edges.push(GraphEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            label: label.to_string(),
+        });
+    }
+}
+
+/// Quote a DOT identifier or label
+fn quote(text: &str) -> String {
+    format!(
+        "\"{}\"",
+        text.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+pub(crate) fn describe(condition: &RouteCondition) -> String {
+    let negate = |negate: bool| if negate { "not " } else { "" };
+    match condition {
+        RouteCondition::Time(c) => format!("time {}-{}", c.start_time, c.end_time),
+        RouteCondition::DayOfWeek(c) => {
+            let days: Vec<String> = c.days.iter().map(u8::to_string).collect();
+            format!("days {}", days.join(","))
+        }
+        RouteCondition::DateRange(c) => format!("dates {} to {}", c.start_date, c.end_date),
+        RouteCondition::CallerId(c) => {
+            format!("caller {}matching {}", negate(c.negate), c.pattern)
+        }
+        RouteCondition::Destination(c) => {
+            format!("destination {}matching {}", negate(c.negate), c.pattern)
+        }
+        RouteCondition::CallerGroup(c) => {
+            format!("caller {}in group {}", negate(c.negate), c.group)
+        }
+        RouteCondition::Schedule(c) => {
+            let state = if c.negate { "closed" } else { "open" };
+            format!("schedule {} {}", c.schedule, state)
+        }
+        RouteCondition::Enum(c) => format!("{}ENUM mapped", negate(c.negate)),
+        RouteCondition::Domain(c) => format!("domain {}{}", negate(c.negate), c.domain),
+    }
+}
+
+fn destination_id(destination: &RouteDestination) -> String {
+    match destination {
+        RouteDestination::Extension(v) => format!("extension:{}", v),
+        RouteDestination::Trunk(v) => format!("trunk:{}", v),
+        RouteDestination::RingGroup(v) => format!("ring_group:{}", v),
+        RouteDestination::Voicemail(v) => format!("voicemail:{}", v),
+        RouteDestination::Hangup => "hangup".to_string(),
+        RouteDestination::Custom(v) => format!("custom:{}", v),
+        RouteDestination::Enum(v) => format!("enum:{}", v),
+        RouteDestination::LeastCost(v) => format!("least_cost:{}", v.join(",")),
+        RouteDestination::DestinationList(list) => {
+            let hops: Vec<String> = list
+                .hops
+                .iter()
+                .map(|hop| destination_id(&hop.destination))
+                .collect();
+            format!("list:{}", hops.join(";"))
+        }
+    }
+}
+
+pub(crate) fn destination_label(destination: &RouteDestination) -> String {
+    match destination {
+        RouteDestination::Extension(v) => format!("Extension {}", v),
+        RouteDestination::Trunk(v) => format!("Trunk {}", v),
+        RouteDestination::RingGroup(v) => format!("Ring group {}", v),
+        RouteDestination::Voicemail(v) => format!("Voicemail {}", v),
+        RouteDestination::Hangup => "Hangup".to_string(),
+        RouteDestination::Custom(v) => v.clone(),
+        RouteDestination::Enum(v) => format!("ENUM, else trunk {}", v),
+        RouteDestination::LeastCost(v) => format!("Cheapest of trunks {}", v.join(", ")),
+        RouteDestination::DestinationList(list) => {
+            let hops: Vec<String> = list
+                .hops
+                .iter()
+                .map(|hop| destination_label(&hop.destination))
+                .collect();
+            hops.join(", then ")
+        }
+    }
+}
+
+fn action_label(route: &RouteRule) -> String {
+    match route.action {
+        RouteAction::Accept => "accept",
+        RouteAction::Reject => "reject",
+        RouteAction::Continue => "continue",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::TimeCondition;
+
+    fn route(id: &str, priority: u32, destination: RouteDestination) -> RouteRule {
+        RouteRule {
+            id: id.to_string(),
+            name: format!("Route {}", id),
+            description: None,
+            pattern: "^1\\d{3}$".to_string(),
+            destination,
+            enabled: true,
+            priority,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_graph_chains_enabled_routes() {
+        let mut config = RoutingConfig::new();
+        let mut office = route(
+            "office",
+            10,
+            RouteDestination::Extension("1000".to_string()),
+        );
+        office.conditions = Some(vec![RouteCondition::Time(TimeCondition {
+            start_time: "09:00".to_string(),
+            end_time: "17:00".to_string(),
+        })]);
+        config.add_route(office);
+        let mut disabled = route("old", 15, RouteDestination::Hangup);
+        disabled.enabled = false;
+        config.add_route(disabled);
+        config.add_route(route(
+            "after",
+            20,
+            RouteDestination::Voicemail("1000".to_string()),
+        ));
+
+        let graph = RouteGraph::from_config(&config);
+        let edge = |from: &str, to: &str| {
+            graph
+                .edges
+                .iter()
+                .find(|e| e.from == from && e.to == to)
+                .map(|e| e.label.as_str())
+        };
+
+        assert_eq!(edge("start", "route:office"), Some(""));
+        assert_eq!(edge("route:office", "extension:1000"), Some("accept"));
+        assert_eq!(edge("route:office", "route:after"), Some("no match"));
+        assert_eq!(edge("route:after", "no_match"), Some("no match"));
+        assert_eq!(edge("route:old", "hangup"), Some("accept"));
+        assert!(edge("route:office", "route:old").is_none());
+
+        let office = graph.nodes.iter().find(|n| n.id == "route:office").unwrap();
+        assert_eq!(office.conditions, vec!["time 09:00-17:00"]);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut config = RoutingConfig::new();
+        config.add_route(route(
+            "main",
+            10,
+            RouteDestination::Trunk("carrier".to_string()),
+        ));
+
+        let dot = RouteGraph::from_config(&config).to_dot();
+        assert!(dot.starts_with("digraph dialplan {"));
+        assert!(dot.contains(
+            "\"route:main\" [shape=box, style=solid, label=\"Route main [10]\\n^1\\\\d{3}$\"];"
+        ));
+        assert!(dot.contains("\"route:main\" -> \"trunk:carrier\" [label=\"accept\"];"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+}
@@ -1,9 +1,13 @@
 //! Condition matching for routing rules
 
+use super::CallContext;
 use super::{
-    CallerIdCondition, DateRangeCondition, DayOfWeekCondition, DestinationCondition,
-    RouteCondition, TimeCondition,
+    CallerGroupCondition, CallerIdCondition, DateRangeCondition, DayOfWeekCondition,
+    DestinationCondition, DomainCondition, EnumCondition, RouteCondition, ScheduleCondition,
+    TimeCondition,
 };
+use crate::groups::GroupDirectory;
+use crate::schedules::ScheduleDirectory;
 use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Timelike, Utc};
 use regex::Regex;
 use std::sync::Arc;
@@ -23,9 +27,22 @@ impl TimeProvider for SystemTimeProvider {
     }
 }
 
+/// Time provider stuck at one instant, for replaying calls at the time they
+/// were (or will be) placed
+#[derive(Debug, Clone)]
+pub struct FixedTimeProvider(pub DateTime<Utc>);
+
+impl TimeProvider for FixedTimeProvider {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
 /// Condition matcher for evaluating routing conditions
 pub struct ConditionMatcher {
     time_provider: Arc<dyn TimeProvider>,
+    groups: Arc<GroupDirectory>,
+    schedules: Arc<ScheduleDirectory>,
 }
 
 impl ConditionMatcher {
@@ -33,12 +50,35 @@ impl ConditionMatcher {
     pub fn new() -> Self {
         Self {
             time_provider: Arc::new(SystemTimeProvider),
+            groups: Arc::new(GroupDirectory::default()),
+            schedules: Arc::new(ScheduleDirectory::default()),
         }
     }
 
     /// Create a condition matcher with a custom time provider (for testing)
     pub fn with_time_provider(time_provider: Arc<dyn TimeProvider>) -> Self {
-        Self { time_provider }
+        Self {
+            time_provider,
+            groups: Arc::new(GroupDirectory::default()),
+            schedules: Arc::new(ScheduleDirectory::default()),
+        }
+    }
+
+    /// Resolve caller group conditions against this directory
+    pub fn with_groups(mut self, groups: Arc<GroupDirectory>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    /// Resolve schedule conditions against this directory
+    pub fn with_schedules(mut self, schedules: Arc<ScheduleDirectory>) -> Self {
+        self.schedules = schedules;
+        self
+    }
+
+    /// The time conditions are checked against
+    pub fn now(&self) -> DateTime<Utc> {
+        self.time_provider.now()
     }
 
     /// Check if all conditions match
@@ -50,7 +90,20 @@ impl ConditionMatcher {
     ) -> bool {
         conditions
             .iter()
-            .all(|condition| self.match_condition(condition, caller_id, destination))
+            .all(|condition| self.match_condition(condition, caller_id, destination, None, None))
+    }
+
+    /// Check if all conditions match a call, including its ENUM mapping
+    pub fn matches_context(&self, conditions: &[RouteCondition], context: &CallContext) -> bool {
+        conditions.iter().all(|condition| {
+            self.match_condition(
+                condition,
+                &context.caller_id,
+                &context.destination,
+                context.enum_uri.as_deref(),
+                context.domain.as_deref(),
+            )
+        })
     }
 
     /// Check if a single condition matches
@@ -59,6 +112,8 @@ impl ConditionMatcher {
         condition: &RouteCondition,
         caller_id: &str,
         destination: &str,
+        enum_uri: Option<&str>,
+        domain: Option<&str>,
     ) -> bool {
         match condition {
             RouteCondition::Time(tc) => self.match_time_condition(tc),
@@ -66,6 +121,10 @@ impl ConditionMatcher {
             RouteCondition::DateRange(dr) => self.match_date_range(dr),
             RouteCondition::CallerId(cid) => self.match_caller_id(cid, caller_id),
             RouteCondition::Destination(dest) => self.match_destination(dest, destination),
+            RouteCondition::CallerGroup(cg) => self.match_caller_group(cg, caller_id),
+            RouteCondition::Schedule(sc) => self.match_schedule(sc),
+            RouteCondition::Enum(ec) => match_enum(ec, enum_uri),
+            RouteCondition::Domain(dc) => match_domain(dc, domain),
         }
     }
 
@@ -132,6 +191,24 @@ impl ConditionMatcher {
         }
     }
 
+    /// Check if the caller belongs to the group
+    fn match_caller_group(&self, condition: &CallerGroupCondition, caller_id: &str) -> bool {
+        let matches = self.groups.is_member(&condition.group, caller_id);
+        if condition.negate {
+            !matches
+        } else {
+            matches
+        }
+    }
+
+    /// Check if the schedule is open
+    fn match_schedule(&self, condition: &ScheduleCondition) -> bool {
+        let open = self
+            .schedules
+            .is_open(&condition.schedule, self.time_provider.now());
+        open != condition.negate
+    }
+
     /// Check if destination matches the pattern
     fn match_destination(&self, condition: &DestinationCondition, destination: &str) -> bool {
         let regex = match Regex::new(&condition.pattern) {
@@ -148,6 +225,16 @@ impl ConditionMatcher {
     }
 }
 
+/// Check whether the destination has an ENUM mapping
+fn match_enum(condition: &EnumCondition, enum_uri: Option<&str>) -> bool {
+    enum_uri.is_some() != condition.negate
+}
+
+fn match_domain(condition: &DomainCondition, domain: Option<&str>) -> bool {
+    let called = domain.is_some_and(|d| d.eq_ignore_ascii_case(&condition.domain));
+    called != condition.negate
+}
+
 impl Default for ConditionMatcher {
     fn default() -> Self {
         Self::new()
@@ -155,7 +242,7 @@ impl Default for ConditionMatcher {
 }
 
 /// Parse a time string in HH:MM format
-fn parse_time(time_str: &str) -> Result<NaiveTime, String> {
+pub(crate) fn parse_time(time_str: &str) -> Result<NaiveTime, String> {
     let parts: Vec<&str> = time_str.split(':').collect();
     if parts.len() != 2 {
         return Err("Invalid time format, expected HH:MM".to_string());
@@ -300,6 +387,75 @@ mod tests {
         assert!(!matcher.match_caller_id(&negated_condition, "anonymous"));
     }
 
+    #[test]
+    fn test_match_caller_group() {
+        use crate::groups::{ExtensionGroup, GroupKind};
+
+        let groups = GroupDirectory::new(vec![ExtensionGroup {
+            id: "sales".to_string(),
+            name: "Sales".to_string(),
+            kind: GroupKind::Department,
+            description: None,
+            members: vec!["1001".to_string()],
+            pickup: false,
+            paging: false,
+        }]);
+        let matcher = ConditionMatcher::new().with_groups(Arc::new(groups));
+
+        let condition = CallerGroupCondition {
+            group: "sales".to_string(),
+            negate: false,
+        };
+        assert!(matcher.match_caller_group(&condition, "1001"));
+        assert!(!matcher.match_caller_group(&condition, "1002"));
+
+        let negated_condition = CallerGroupCondition {
+            group: "sales".to_string(),
+            negate: true,
+        };
+        assert!(matcher.match_caller_group(&negated_condition, "1002"));
+
+        // Without a directory no caller is in any group
+        assert!(!ConditionMatcher::new().match_caller_group(&condition, "1001"));
+    }
+
+    #[test]
+    fn test_match_schedule() {
+        use crate::schedules::{OpeningHours, Schedule};
+
+        let schedules = ScheduleDirectory::new(vec![Schedule {
+            id: "office".to_string(),
+            name: "Business hours".to_string(),
+            description: None,
+            hours: vec![OpeningHours {
+                days: vec![1, 2, 3, 4, 5],
+                start_time: "09:00".to_string(),
+                end_time: "17:00".to_string(),
+            }],
+            holidays: Vec::new(),
+        }]);
+        let at = |hour| {
+            let provider = Arc::new(MockTimeProvider {
+                fixed_time: Utc.with_ymd_and_hms(2024, 1, 15, hour, 0, 0).unwrap(),
+            });
+            ConditionMatcher::with_time_provider(provider)
+                .with_schedules(Arc::new(schedules.clone()))
+        };
+
+        let open = ScheduleCondition {
+            schedule: "office".to_string(),
+            negate: false,
+        };
+        let closed = ScheduleCondition {
+            schedule: "office".to_string(),
+            negate: true,
+        };
+        assert!(at(10).match_schedule(&open));
+        assert!(!at(10).match_schedule(&closed));
+        assert!(!at(20).match_schedule(&open));
+        assert!(at(20).match_schedule(&closed));
+    }
+
     #[test]
     fn test_match_destination() {
         let matcher = ConditionMatcher::new();
@@ -339,4 +495,50 @@ mod tests {
         assert!(matcher.matches(&conditions, "+12125551234", "2000"));
         assert!(!matcher.matches(&conditions, "+442071234567", "2000"));
     }
+
+    #[test]
+    fn test_match_enum() {
+        let matcher = ConditionMatcher::new();
+        let mapped = vec![RouteCondition::Enum(EnumCondition { negate: false })];
+        let unmapped = vec![RouteCondition::Enum(EnumCondition { negate: true })];
+        let mut context = CallContext {
+            caller_id: "1000".to_string(),
+            destination: "+12125551234".to_string(),
+            enum_uri: None,
+            domain: None,
+        };
+
+        assert!(!matcher.matches_context(&mapped, &context));
+        assert!(matcher.matches_context(&unmapped, &context));
+        context.enum_uri = Some("sip:+12125551234@voip.example.com".to_string());
+        assert!(matcher.matches_context(&mapped, &context));
+        assert!(!matcher.matches_context(&unmapped, &context));
+    }
+
+    #[test]
+    fn test_match_domain() {
+        let matcher = ConditionMatcher::new();
+        let brand = |negate| {
+            vec![RouteCondition::Domain(DomainCondition {
+                domain: "example.com".to_string(),
+                negate,
+            })]
+        };
+        let mut context = CallContext {
+            caller_id: "1000".to_string(),
+            destination: "2000".to_string(),
+            enum_uri: None,
+            domain: Some("Example.com".to_string()),
+        };
+
+        assert!(matcher.matches_context(&brand(false), &context));
+        assert!(!matcher.matches_context(&brand(true), &context));
+        context.domain = Some("other.example".to_string());
+        assert!(!matcher.matches_context(&brand(false), &context));
+        assert!(matcher.matches_context(&brand(true), &context));
+        // Calls whose domain is unknown only match negated conditions
+        context.domain = None;
+        assert!(!matcher.matches_context(&brand(false), &context));
+        assert!(!matcher.matches(&brand(false), "1000", "2000"));
+    }
 }
@@ -4,26 +4,53 @@
 //! - Time-based routing (time of day, day of week, date ranges)
 //! - Caller ID filtering
 //! - Destination number filtering
+//! - Domain filtering, for deployments serving several SIP domains
 //! - Complex condition matching
 //! - Prioritized route processing
+//! - Ordered fallback between destinations
+//! - Rerouting calls that fail with particular responses
 
 pub mod evaluator;
+pub mod failover;
+pub mod graph;
 pub mod matcher;
+pub mod regression;
+pub mod sampler;
 
 pub use evaluator::{CallContext, RouteEvaluator, RouteMatch};
+pub use failover::{DestinationHop, DestinationList, FailoverPlan, FailoverPolicy, RerouteRule};
+pub use graph::RouteGraph;
 pub use matcher::{ConditionMatcher, TimeProvider};
+pub use regression::{run_route_tests, RouteTestCase, RouteTestReport, RouteTestResult};
+pub use sampler::{TrafficSample, TrafficSampler};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Routing configuration container
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, JsonSchema)]
 pub struct RoutingConfig {
     /// List of routes to evaluate
     pub routes: Vec<RouteRule>,
+    /// Regression cases the routes must keep passing
+    #[serde(default)]
+    pub tests: Vec<RouteTestCase>,
+    /// Responses that move calls on to the next destination of a list or
+    /// least-cost route, unless the list sets its own
+    #[serde(default)]
+    pub failover: FailoverPolicy,
+    /// Most destinations one call is tried at, across failovers and
+    /// reroutes (10 when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
 }
 
+/// Destinations a call is tried at when `RoutingConfig::max_attempts` is
+/// unset
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+
 /// A single routing rule
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RouteRule {
     pub id: String,
     pub name: String,
@@ -35,10 +62,14 @@ pub struct RouteRule {
     pub conditions: Option<Vec<RouteCondition>>,
     pub action: RouteAction,
     pub continue_on_match: bool,
+    /// Where calls go when the destination fails, first rule covering the
+    /// failure first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<RerouteRule>,
 }
 
 /// Destination type for a route
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", content = "value")]
 pub enum RouteDestination {
     Extension(String),
@@ -47,10 +78,33 @@ pub enum RouteDestination {
     Voicemail(String),
     Hangup,
     Custom(String),
+    /// Deliver over SIP to the destination's ENUM mapping, or to this trunk
+    /// when the number has none
+    Enum(String),
+    /// The cheapest of these trunks that is not down, by rate card
+    LeastCost(Vec<String>),
+    /// Each destination in turn until one answers
+    DestinationList(DestinationList),
+}
+
+impl RouteDestination {
+    /// Trunks the destination may send calls out on
+    pub fn trunks(&self) -> Vec<&String> {
+        match self {
+            Self::Trunk(trunk) | Self::Enum(trunk) => vec![trunk],
+            Self::LeastCost(trunks) => trunks.iter().collect(),
+            Self::DestinationList(list) => list
+                .hops
+                .iter()
+                .flat_map(|hop| hop.destination.trunks())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 /// Action to perform when route matches
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum RouteAction {
     Accept,
@@ -59,7 +113,7 @@ pub enum RouteAction {
 }
 
 /// Routing conditions
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum RouteCondition {
     Time(TimeCondition),
@@ -67,54 +121,116 @@ pub enum RouteCondition {
     DateRange(DateRangeCondition),
     CallerId(CallerIdCondition),
     Destination(DestinationCondition),
+    CallerGroup(CallerGroupCondition),
+    Schedule(ScheduleCondition),
+    Enum(EnumCondition),
+    Domain(DomainCondition),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TimeCondition {
     pub start_time: String,
     pub end_time: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DayOfWeekCondition {
     pub days: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DateRangeCondition {
     pub start_date: String,
     pub end_date: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CallerIdCondition {
     pub pattern: String,
     pub negate: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DestinationCondition {
     pub pattern: String,
     pub negate: bool,
 }
 
+/// Matches callers that belong to an extension group
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallerGroupCondition {
+    /// Group id
+    pub group: String,
+    pub negate: bool,
+}
+
+/// Matches while a named schedule is open
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScheduleCondition {
+    /// Schedule id
+    pub schedule: String,
+    /// Match while the schedule is closed instead
+    #[serde(default)]
+    pub negate: bool,
+}
+
+/// Matches destinations with an ENUM mapping to a SIP URI
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EnumCondition {
+    /// Match destinations without a mapping instead
+    #[serde(default)]
+    pub negate: bool,
+}
+
+/// Matches calls to a SIP domain
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DomainCondition {
+    /// Canonical domain; calls to its aliases match too
+    pub domain: String,
+    /// Match calls to other domains instead
+    #[serde(default)]
+    pub negate: bool,
+}
+
 impl RoutingConfig {
     /// Create a new empty routing configuration
     pub fn new() -> Self {
-        Self { routes: Vec::new() }
+        Self {
+            routes: Vec::new(),
+            tests: Vec::new(),
+            failover: FailoverPolicy::default(),
+            max_attempts: None,
+        }
     }
 
     /// Add a route to the configuration
     pub fn add_route(&mut self, route: RouteRule) {
         self.routes.push(route);
         // Keep routes sorted by priority (lower number = higher priority)
-        self.routes.sort_by(|a, b| a.priority.cmp(&b.priority));
+        self.routes.sort_by_key(|r| r.priority);
+    }
+
+    /// Most destinations one call is tried at
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS)
     }
 
     /// Get all enabled routes
     pub fn enabled_routes(&self) -> Vec<&RouteRule> {
         self.routes.iter().filter(|r| r.enabled).collect()
     }
+
+    /// Whether any enabled route needs the destination's ENUM mapping
+    pub fn uses_enum(&self) -> bool {
+        self.enabled_routes().iter().any(|route| {
+            matches!(route.destination, RouteDestination::Enum(_))
+                || route
+                    .conditions
+                    .iter()
+                    .flatten()
+                    .any(|c| matches!(c, RouteCondition::Enum(_)))
+        })
+    }
 }
 
 #[cfg(test)]
@@ -142,6 +258,7 @@ mod tests {
             conditions: None,
             action: RouteAction::Accept,
             continue_on_match: false,
+            on_failure: Vec::new(),
         };
 
         let route2 = RouteRule {
@@ -155,6 +272,7 @@ mod tests {
             conditions: None,
             action: RouteAction::Accept,
             continue_on_match: false,
+            on_failure: Vec::new(),
         };
 
         config.add_route(route1);
@@ -0,0 +1,218 @@
+//! Route regression tests
+//!
+//! Named test cases are stored with the routes: a caller, a dialed number,
+//! optionally the moment the call is placed, and where the call is expected
+//! to end up. Running them after every dialplan change shows whether a new
+//! or reordered route captured calls it should not have.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::matcher::{ConditionMatcher, FixedTimeProvider, SystemTimeProvider, TimeProvider};
+use super::{CallContext, RouteDestination, RouteEvaluator, RoutingConfig};
+use crate::groups::GroupDirectory;
+use crate::schedules::ScheduleDirectory;
+
+/// A call and the routing outcome expected for it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RouteTestCase {
+    pub name: String,
+    pub caller_id: String,
+    pub destination: String,
+    /// Domain called; routes for a domain do not match calls without one
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// When the call is placed; the current time when absent
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Route id expected to match; no route may match when absent
+    pub expected_route: Option<String>,
+    /// Destination the matched route must send the call to
+    #[serde(default)]
+    pub expected_destination: Option<RouteDestination>,
+}
+
+/// Outcome of one test case
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteTestResult {
+    pub name: String,
+    pub passed: bool,
+    pub actual_route: Option<String>,
+    pub actual_destination: Option<RouteDestination>,
+    /// Why the case failed
+    pub message: Option<String>,
+}
+
+/// Outcome of a whole suite
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RouteTestReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub results: Vec<RouteTestResult>,
+}
+
+impl RouteTestReport {
+    /// No test case failed
+    pub fn is_success(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// Run the test cases stored in `routing` against its routes
+pub fn run_route_tests(
+    routing: &RoutingConfig,
+    groups: Arc<GroupDirectory>,
+    schedules: Arc<ScheduleDirectory>,
+) -> RouteTestReport {
+    let mut report = RouteTestReport::default();
+    for case in &routing.tests {
+        let time_provider: Arc<dyn TimeProvider> = match case.timestamp {
+            Some(at) => Arc::new(FixedTimeProvider(at)),
+            None => Arc::new(SystemTimeProvider),
+        };
+        let matcher = ConditionMatcher::with_time_provider(time_provider)
+            .with_groups(groups.clone())
+            .with_schedules(schedules.clone());
+        let evaluator = RouteEvaluator::with_matcher(routing.clone(), Arc::new(matcher));
+        let route_match = evaluator.evaluate(&CallContext {
+            caller_id: case.caller_id.clone(),
+            destination: case.destination.clone(),
+            enum_uri: None,
+            domain: case.domain.clone(),
+        });
+        let actual_route = route_match.as_ref().map(|m| m.route_id.clone());
+        let actual_destination = route_match.map(|m| m.destination);
+
+        let message = if actual_route != case.expected_route {
+            Some(format!(
+                "expected route {}, got {}",
+                describe(case.expected_route.as_deref()),
+                describe(actual_route.as_deref())
+            ))
+        } else {
+            match &case.expected_destination {
+                Some(expected) if actual_destination.as_ref() != Some(expected) => Some(format!(
+                    "expected destination {:?}, got {:?}",
+                    expected, actual_destination
+                )),
+                _ => None,
+            }
+        };
+
+        if message.is_none() {
+            report.passed += 1;
+        } else {
+            report.failed += 1;
+        }
+        report.results.push(RouteTestResult {
+            name: case.name.clone(),
+            passed: message.is_none(),
+            actual_route,
+            actual_destination,
+            message,
+        });
+    }
+    report
+}
+
+fn describe(route: Option<&str>) -> String {
+    match route {
+        Some(id) => format!("'{}'", id),
+        None => "none".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::{DayOfWeekCondition, RouteAction, RouteCondition, RouteRule};
+
+    fn route(id: &str, pattern: &str, priority: u32, destination: RouteDestination) -> RouteRule {
+        RouteRule {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: None,
+            pattern: pattern.to_string(),
+            destination,
+            enabled: true,
+            priority,
+            conditions: None,
+            action: RouteAction::Accept,
+            continue_on_match: false,
+            on_failure: Vec::new(),
+        }
+    }
+
+    fn routing() -> RoutingConfig {
+        let mut weekdays = route(
+            "weekdays",
+            "^2",
+            10,
+            RouteDestination::RingGroup("sales".to_string()),
+        );
+        weekdays.conditions = Some(vec![RouteCondition::DayOfWeek(DayOfWeekCondition {
+            days: vec![1, 2, 3, 4, 5],
+        })]);
+        let mut routing = RoutingConfig::new();
+        routing.add_route(weekdays);
+        routing.add_route(route(
+            "after-hours",
+            "^2",
+            20,
+            RouteDestination::Voicemail("2000".to_string()),
+        ));
+        routing.tests = serde_json::from_value(serde_json::json!([
+            {
+                "name": "monday sales",
+                "caller_id": "+12125551234",
+                "destination": "2000",
+                "timestamp": "2024-01-15T10:00:00Z",
+                "expected_route": "weekdays",
+                "expected_destination": { "type": "RingGroup", "value": "sales" }
+            },
+            {
+                "name": "sunday voicemail",
+                "caller_id": "+12125551234",
+                "destination": "2000",
+                "timestamp": "2024-01-14T10:00:00Z",
+                "expected_route": "after-hours"
+            },
+            {
+                "name": "unrouted",
+                "caller_id": "+12125551234",
+                "destination": "3000",
+                "expected_route": null
+            }
+        ]))
+        .unwrap();
+        routing
+    }
+
+    #[test]
+    fn test_suite_passes() {
+        let report = run_route_tests(&routing(), Default::default(), Default::default());
+        assert!(report.is_success(), "{:?}", report);
+        assert_eq!(report.passed, 3);
+    }
+
+    #[test]
+    fn test_route_change_fails_suite() {
+        let mut routing = routing();
+        // A catch-all put in front of everything captures the sales calls
+        routing.add_route(route("catch-all", ".*", 1, RouteDestination::Hangup));
+
+        let report = run_route_tests(&routing, Default::default(), Default::default());
+        assert!(!report.is_success());
+        assert_eq!(report.failed, 3);
+        assert_eq!(
+            report.results[0].message.as_deref(),
+            Some("expected route 'weekdays', got 'catch-all'")
+        );
+        assert_eq!(
+            report.results[2].actual_destination,
+            Some(RouteDestination::Hangup)
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Recent call samples for shadow evaluation
+//!
+//! The B2BUA records the caller, destination and source address of new
+//! calls into a bounded buffer. Candidate configurations are replayed
+//! against these samples before they go live.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Samples kept when no capacity is given
+pub const DEFAULT_SAMPLE_CAPACITY: usize = 1000;
+
+/// A routed call as seen by the B2BUA
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficSample {
+    pub caller_id: String,
+    pub destination: String,
+    pub source_ip: Option<IpAddr>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Bounded buffer of recent traffic samples, oldest dropped first
+#[derive(Clone)]
+pub struct TrafficSampler {
+    samples: Arc<Mutex<VecDeque<TrafficSample>>>,
+    capacity: usize,
+}
+
+impl TrafficSampler {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, sample: TrafficSample) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// Copy of the buffered samples, oldest first
+    pub fn samples(&self) -> Vec<TrafficSample> {
+        self.samples.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl Default for TrafficSampler {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler_drops_oldest() {
+        let sampler = TrafficSampler::new(2);
+        for destination in ["1001", "1002", "1003"] {
+            sampler.record(TrafficSample {
+                caller_id: "5551234".to_string(),
+                destination: destination.to_string(),
+                source_ip: None,
+                timestamp: Utc::now(),
+            });
+        }
+
+        let samples = sampler.samples();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].destination, "1002");
+        assert_eq!(samples[1].destination, "1003");
+    }
+}
@@ -0,0 +1,210 @@
+//! Named schedules
+//!
+//! Business hours, night service and lunch breaks are usually the same for
+//! every route, ring group and DID of a tenant. A schedule describes them once
+//! under an id that routes reference with a `Schedule` condition, instead of
+//! repeating time and day of week conditions on each one.
+//!
+//! Holidays overlay the weekly hours: on a holiday the schedule is closed all
+//! day, or open only for the hours the holiday lists.
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::routing::matcher::parse_time;
+use crate::routing::TimeCondition;
+
+/// A named set of opening hours
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Schedule {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// Weekly opening hours; the schedule is closed outside them
+    #[serde(default)]
+    pub hours: Vec<OpeningHours>,
+    /// Dates that replace the weekly hours
+    #[serde(default)]
+    pub holidays: Vec<Holiday>,
+}
+
+/// Hours the schedule is open on some days of the week
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OpeningHours {
+    /// 1=Monday, 7=Sunday
+    pub days: Vec<u8>,
+    /// Opening time (HH:MM)
+    pub start_time: String,
+    /// Closing time (HH:MM), exclusive; earlier than `start_time` for hours
+    /// that run past midnight
+    pub end_time: String,
+}
+
+/// A date or run of dates with their own hours
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Holiday {
+    pub name: String,
+    /// First day (YYYY-MM-DD)
+    pub date: String,
+    /// Last day (YYYY-MM-DD) for holidays longer than a day
+    #[serde(default)]
+    pub end_date: Option<String>,
+    /// Hours kept on the holiday; closed all day when empty
+    #[serde(default)]
+    pub hours: Vec<TimeCondition>,
+}
+
+impl OpeningHours {
+    /// Whether `at` falls on one of the days, between the opening and
+    /// closing times
+    pub fn contains(&self, at: NaiveDateTime) -> bool {
+        let weekday = at.weekday().number_from_monday() as u8;
+        self.days.contains(&weekday) && within(&self.start_time, &self.end_time, at.time())
+    }
+}
+
+impl Holiday {
+    fn covers(&self, date: NaiveDate) -> bool {
+        let parse = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok();
+        let Some(start) = parse(&self.date) else {
+            return false;
+        };
+        let end = match &self.end_date {
+            Some(end) => match parse(end) {
+                Some(end) => end,
+                None => return false,
+            },
+            None => start,
+        };
+        (start..=end).contains(&date)
+    }
+}
+
+impl Schedule {
+    /// Whether the schedule is open at `at`
+    pub fn is_open(&self, at: NaiveDateTime) -> bool {
+        let time = at.time();
+        if let Some(holiday) = self.holidays.iter().find(|h| h.covers(at.date())) {
+            return holiday
+                .hours
+                .iter()
+                .any(|h| within(&h.start_time, &h.end_time, time));
+        }
+        self.hours.iter().any(|h| h.contains(at))
+    }
+
+    /// The holiday in force on `date`, if any
+    pub fn holiday_on(&self, date: NaiveDate) -> Option<&Holiday> {
+        self.holidays.iter().find(|h| h.covers(date))
+    }
+}
+
+fn within(start: &str, end: &str, time: chrono::NaiveTime) -> bool {
+    let (Ok(start), Ok(end)) = (parse_time(start), parse_time(end)) else {
+        return false;
+    };
+    if start <= end {
+        time >= start && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+/// Lookup of schedules by id
+#[derive(Debug, Clone, Default)]
+pub struct ScheduleDirectory {
+    schedules: Vec<Schedule>,
+}
+
+impl ScheduleDirectory {
+    pub fn new(schedules: Vec<Schedule>) -> Self {
+        Self { schedules }
+    }
+
+    pub fn schedules(&self) -> &[Schedule] {
+        &self.schedules
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Schedule> {
+        self.schedules.iter().find(|s| s.id == id)
+    }
+
+    /// Whether the schedule with id `schedule` is open at `now`; unknown
+    /// schedules are never open
+    pub fn is_open(&self, schedule: &str, now: DateTime<Utc>) -> bool {
+        self.get(schedule)
+            .is_some_and(|s| s.is_open(now.naive_utc()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn office() -> Schedule {
+        serde_json::from_value(serde_json::json!({
+            "id": "office",
+            "name": "Business hours",
+            "description": null,
+            "hours": [
+                { "days": [1, 2, 3, 4, 5], "start_time": "09:00", "end_time": "17:00" },
+                { "days": [6], "start_time": "10:00", "end_time": "12:00" }
+            ],
+            "holidays": [
+                { "name": "New Year", "date": "2024-01-01" },
+                {
+                    "name": "Christmas Eve",
+                    "date": "2024-12-24",
+                    "hours": [{ "start_time": "09:00", "end_time": "12:00" }]
+                },
+                { "name": "Shutdown", "date": "2024-12-27", "end_date": "2024-12-31" }
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_weekly_hours() {
+        let directory = ScheduleDirectory::new(vec![office()]);
+
+        // Monday 15 January 2024
+        assert!(directory.is_open("office", at(2024, 1, 15, 9, 0)));
+        assert!(directory.is_open("office", at(2024, 1, 15, 16, 59)));
+        assert!(!directory.is_open("office", at(2024, 1, 15, 17, 0)));
+        assert!(!directory.is_open("office", at(2024, 1, 15, 8, 59)));
+        // Saturday morning only, closed Sunday
+        assert!(directory.is_open("office", at(2024, 1, 20, 11, 0)));
+        assert!(!directory.is_open("office", at(2024, 1, 20, 13, 0)));
+        assert!(!directory.is_open("office", at(2024, 1, 21, 11, 0)));
+
+        assert!(!directory.is_open("missing", at(2024, 1, 15, 10, 0)));
+    }
+
+    #[test]
+    fn test_holiday_overlay() {
+        let directory = ScheduleDirectory::new(vec![office()]);
+
+        // A Monday holiday closes all day
+        assert!(!directory.is_open("office", at(2024, 1, 1, 10, 0)));
+        // Short hours on Christmas Eve
+        assert!(directory.is_open("office", at(2024, 12, 24, 11, 0)));
+        assert!(!directory.is_open("office", at(2024, 12, 24, 14, 0)));
+        // Every day of a multi-day closure
+        assert!(!directory.is_open("office", at(2024, 12, 30, 10, 0)));
+        assert!(directory.is_open("office", at(2025, 1, 2, 10, 0)));
+
+        let schedule = directory.get("office").unwrap();
+        assert_eq!(
+            schedule
+                .holiday_on(NaiveDate::from_ymd_opt(2024, 12, 28).unwrap())
+                .map(|h| h.name.as_str()),
+            Some("Shutdown")
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Call screening and caller announcement
+//!
+//! Incoming calls to an extension can be announced before they are
+//! connected: the callee hears "call from <name>", using the caller's
+//! recorded name when one was captured and the caller ID name (CNAM)
+//! otherwise. In screen mode the callee then presses 1 to accept or 2 to
+//! send the caller to voicemail; announce mode connects straight after the
+//! announcement. The mode is set per extension and can be overridden for
+//! each find-me step.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How calls to an extension are screened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreeningMode {
+    #[default]
+    Off,
+    /// Announce the caller, then connect
+    Announce,
+    /// Announce the caller and wait for accept (1) or voicemail (2)
+    Screen,
+}
+
+/// One destination tried in turn when the extension does not answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FindMeStep {
+    pub destination: String,
+    #[serde(default = "default_ring_seconds")]
+    pub ring_seconds: u32,
+    /// Overrides the extension's mode for this step
+    #[serde(default)]
+    pub screening: Option<ScreeningMode>,
+}
+
+fn default_ring_seconds() -> u32 {
+    20
+}
+
+/// Screening settings for one extension
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ScreeningProfile {
+    #[serde(default)]
+    pub mode: ScreeningMode,
+    /// Ask callers to record their name before ringing
+    #[serde(default)]
+    pub record_name: bool,
+    #[serde(default)]
+    pub find_me: Vec<FindMeStep>,
+}
+
+/// Screening configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScreeningConfig {
+    /// Profile for each extension number
+    #[serde(default)]
+    pub extensions: HashMap<String, ScreeningProfile>,
+    /// Time the callee has to press a key before the caller goes to
+    /// voicemail
+    #[serde(default = "default_response_seconds")]
+    pub response_seconds: u32,
+}
+
+impl Default for ScreeningConfig {
+    fn default() -> Self {
+        Self {
+            extensions: HashMap::new(),
+            response_seconds: default_response_seconds(),
+        }
+    }
+}
+
+fn default_response_seconds() -> u32 {
+    10
+}
+
+impl ScreeningConfig {
+    /// Mode for ringing `extension`, or one of its find-me steps
+    pub fn mode_for(&self, extension: &str, find_me_step: Option<usize>) -> ScreeningMode {
+        let Some(profile) = self.extensions.get(extension) else {
+            return ScreeningMode::Off;
+        };
+        find_me_step
+            .and_then(|step| profile.find_me.get(step))
+            .and_then(|step| step.screening)
+            .unwrap_or(profile.mode)
+    }
+}
+
+/// What the callee hears before deciding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    /// Recording of the caller saying their name
+    pub recorded_name: Option<String>,
+    /// Caller ID name
+    pub cnam: Option<String>,
+    pub number: String,
+}
+
+impl Announcement {
+    /// Spoken text, used when there is no recorded name to play
+    pub fn text(&self) -> String {
+        let name = self
+            .cnam
+            .as_deref()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or(&self.number);
+        format!("Call from {}", name)
+    }
+}
+
+/// What to do after the callee's key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreeningDecision {
+    Connect,
+    Voicemail,
+    /// Unrecognised key; announce again
+    Repeat,
+}
+
+impl ScreeningMode {
+    /// Decide on a key press, or `None` when the callee did not respond
+    pub fn decide(self, digit: Option<char>) -> ScreeningDecision {
+        match (self, digit) {
+            (ScreeningMode::Off | ScreeningMode::Announce, _) => ScreeningDecision::Connect,
+            (ScreeningMode::Screen, Some('1')) => ScreeningDecision::Connect,
+            (ScreeningMode::Screen, Some('2') | None) => ScreeningDecision::Voicemail,
+            (ScreeningMode::Screen, Some(_)) => ScreeningDecision::Repeat,
+        }
+    }
+}
+
+/// How a screened call ended up, recorded in the CDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreeningOutcome {
+    /// Announced and connected without a choice
+    Announced,
+    Accepted,
+    Voicemail,
+}
+
+/// Digit carried by a DTMF INFO body (`application/dtmf-relay` or
+/// `application/dtmf`)
+pub fn dtmf_digit(body: &str) -> Option<char> {
+    let signal = body
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("signal")
+                .then_some(value.trim())
+        })
+        .unwrap_or(body.trim());
+    let mut chars = signal.chars();
+    match (chars.next(), chars.next()) {
+        (Some(digit), None) if digit.is_ascii_digit() || matches!(digit, '*' | '#') => Some(digit),
+        _ => None,
+    }
+}
+
+/// Display name of a From header (`"Alice" <sip:1001@example.com>`)
+pub fn display_name(header: &str) -> Option<&str> {
+    let (name, _) = header.split_once('<')?;
+    let name = name.trim().trim_matches('"').trim();
+    (!name.is_empty()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_for_find_me_steps() {
+        let mut config = ScreeningConfig::default();
+        config.extensions.insert(
+            "1001".to_string(),
+            ScreeningProfile {
+                mode: ScreeningMode::Announce,
+                record_name: true,
+                find_me: vec![
+                    FindMeStep {
+                        destination: "+12125551234".to_string(),
+                        ring_seconds: 20,
+                        screening: Some(ScreeningMode::Screen),
+                    },
+                    FindMeStep {
+                        destination: "+12125559876".to_string(),
+                        ring_seconds: 20,
+                        screening: None,
+                    },
+                ],
+            },
+        );
+
+        assert_eq!(config.mode_for("1001", None), ScreeningMode::Announce);
+        assert_eq!(config.mode_for("1001", Some(0)), ScreeningMode::Screen);
+        assert_eq!(config.mode_for("1001", Some(1)), ScreeningMode::Announce);
+        assert_eq!(config.mode_for("1002", None), ScreeningMode::Off);
+    }
+
+    #[test]
+    fn test_decide_and_announcement() {
+        assert_eq!(
+            ScreeningMode::Screen.decide(Some('1')),
+            ScreeningDecision::Connect
+        );
+        assert_eq!(
+            ScreeningMode::Screen.decide(Some('2')),
+            ScreeningDecision::Voicemail
+        );
+        assert_eq!(
+            ScreeningMode::Screen.decide(None),
+            ScreeningDecision::Voicemail
+        );
+        assert_eq!(
+            ScreeningMode::Screen.decide(Some('5')),
+            ScreeningDecision::Repeat
+        );
+        assert_eq!(
+            ScreeningMode::Announce.decide(None),
+            ScreeningDecision::Connect
+        );
+
+        let announcement = Announcement {
+            recorded_name: None,
+            cnam: Some("Alice Smith".to_string()),
+            number: "2125551234".to_string(),
+        };
+        assert_eq!(announcement.text(), "Call from Alice Smith");
+        let anonymous = Announcement {
+            cnam: None,
+            ..announcement
+        };
+        assert_eq!(anonymous.text(), "Call from 2125551234");
+    }
+
+    #[test]
+    fn test_dtmf_digit() {
+        assert_eq!(dtmf_digit("Signal=1\r\nDuration=160\r\n"), Some('1'));
+        assert_eq!(dtmf_digit("signal= 2"), Some('2'));
+        assert_eq!(dtmf_digit("#"), Some('#'));
+        assert_eq!(dtmf_digit("Signal=12"), None);
+        assert_eq!(dtmf_digit(""), None);
+    }
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(
+            display_name("\"Alice Smith\" <sip:1001@example.com>;tag=a"),
+            Some("Alice Smith")
+        );
+        assert_eq!(display_name("Bob <sip:1002@example.com>"), Some("Bob"));
+        assert_eq!(display_name("<sip:1003@example.com>"), None);
+        assert_eq!(display_name("sip:1003@example.com"), None);
+    }
+}
@@ -0,0 +1,591 @@
+//! Fluent construction of SIP requests and responses
+//!
+//! Hand-built messages tend to forget a header or get one subtly wrong: a
+//! Via without a fresh branch, a CSeq that does not advance, a response
+//! whose To has no tag. [`MessageBuilder`] fills the required headers from
+//! a [`LocalProfile`] - where we send from - and, for requests inside a
+//! dialog, a [`DialogContext`]. Anything the caller sets explicitly wins;
+//! Content-Length is always computed from the body.
+
+use super::{Header, Method, Request, Response, StatusCode, Uri};
+use crate::media::SdpSession;
+use crate::transport::NetworkInterface;
+use bytes::Bytes;
+use uuid::Uuid;
+
+/// Max-Forwards placed on new requests (RFC 3261 section 8.1.1.6)
+pub const DEFAULT_MAX_FORWARDS: u32 = 70;
+
+/// Where the messages we build are sent from
+#[derive(Debug, Clone)]
+pub struct LocalProfile {
+    /// Transport named in Via, e.g. `UDP` or `TLS`
+    pub transport: String,
+    /// Host advertised in Via and Contact
+    pub host: String,
+    pub port: u16,
+    /// User part of our Contact
+    pub user: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl LocalProfile {
+    pub fn new(transport: impl Into<String>, host: impl Into<String>, port: u16) -> Self {
+        Self {
+            transport: transport.into().to_uppercase(),
+            host: host.into(),
+            port,
+            user: None,
+            user_agent: None,
+        }
+    }
+
+    /// Profile for sending from `iface`, using its advertised address
+    pub fn for_interface(
+        iface: &NetworkInterface,
+        transport: impl Into<String>,
+        port: u16,
+    ) -> Self {
+        Self::new(transport, iface.advertised_host(), port)
+    }
+
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// `host:port` as written in Via, with IPv6 addresses bracketed
+    pub fn sent_by(&self) -> String {
+        format!("{}:{}", self.bracketed_host(), self.port)
+    }
+
+    /// Our Contact header value
+    pub fn contact(&self) -> String {
+        let user = self
+            .user
+            .as_deref()
+            .map(|u| format!("{}@", u))
+            .unwrap_or_default();
+        let transport = if self.transport == "UDP" {
+            String::new()
+        } else {
+            format!(";transport={}", self.transport.to_lowercase())
+        };
+        format!("<sip:{}{}{}>", user, self.sent_by(), transport)
+    }
+
+    fn bracketed_host(&self) -> String {
+        if self.host.contains(':') && !self.host.starts_with('[') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        }
+    }
+}
+
+/// State of a dialog that requests inside it are built from
+#[derive(Debug, Clone)]
+pub struct DialogContext {
+    pub call_id: String,
+    /// Our address-of-record, sent in From
+    pub local_uri: String,
+    pub local_tag: String,
+    /// The other party's address-of-record, sent in To
+    pub remote_uri: String,
+    /// The other party's tag, once a response has carried one
+    pub remote_tag: Option<String>,
+    /// Request-URI of requests inside the dialog: the other party's
+    /// Contact once known
+    pub remote_target: Uri,
+    /// CSeq of the last request we sent
+    pub cseq: u32,
+    /// Route headers from the Record-Route of the dialog-creating exchange
+    pub route_set: Vec<String>,
+}
+
+impl DialogContext {
+    /// A new dialog from `local_uri` to `remote_target`, with a fresh
+    /// Call-ID and tag
+    pub fn new(local_uri: impl Into<String>, remote_target: Uri) -> Self {
+        Self {
+            call_id: Uuid::new_v4().to_string(),
+            local_uri: local_uri.into(),
+            local_tag: new_tag(),
+            remote_uri: remote_target.to_string(),
+            remote_tag: None,
+            remote_target,
+            cseq: 0,
+            route_set: Vec::new(),
+        }
+    }
+
+    pub fn with_call_id(mut self, call_id: impl Into<String>) -> Self {
+        self.call_id = call_id.into();
+        self
+    }
+
+    /// Take the remote tag from a response's To header, if it has one
+    pub fn update_from_response(&mut self, response: &Response) {
+        if let Some(tag) = response.get_header_value("To").and_then(header_tag) {
+            self.remote_tag = Some(tag.to_string());
+        }
+    }
+
+    fn local_header(&self) -> String {
+        format!("<{}>;tag={}", self.local_uri, self.local_tag)
+    }
+
+    fn remote_header(&self) -> String {
+        match &self.remote_tag {
+            Some(tag) => format!("<{}>;tag={}", self.remote_uri, tag),
+            None => format!("<{}>", self.remote_uri),
+        }
+    }
+}
+
+/// Builds messages sent from one [`LocalProfile`]
+#[derive(Debug, Clone)]
+pub struct MessageBuilder {
+    profile: LocalProfile,
+}
+
+impl MessageBuilder {
+    pub fn new(profile: LocalProfile) -> Self {
+        Self { profile }
+    }
+
+    pub fn profile(&self) -> &LocalProfile {
+        &self.profile
+    }
+
+    /// A request outside any dialog, with a Via carrying a fresh branch,
+    /// Max-Forwards, and our Contact where the method needs one
+    pub fn request(&self, method: Method, uri: Uri) -> RequestBuilder {
+        let mut request = Request::new(method, uri)
+            .with_header(
+                "Via",
+                format!(
+                    "SIP/2.0/{} {};branch={};rport",
+                    self.profile.transport,
+                    self.profile.sent_by(),
+                    new_branch()
+                )
+                .as_str(),
+            )
+            .with_header("Max-Forwards", DEFAULT_MAX_FORWARDS.to_string().as_str());
+        if needs_contact(method) {
+            request = request.with_header("Contact", self.profile.contact().as_str());
+        }
+        if let Some(user_agent) = &self.profile.user_agent {
+            request = request.with_header("User-Agent", user_agent.as_str());
+        }
+        RequestBuilder {
+            request,
+            host: self.profile.host.clone(),
+        }
+    }
+
+    /// A request inside `dialog`, advancing its CSeq
+    ///
+    /// ACK and CANCEL reuse the CSeq number of the request they belong to
+    /// rather than taking a new one.
+    pub fn in_dialog(&self, method: Method, dialog: &mut DialogContext) -> RequestBuilder {
+        if !matches!(method, Method::Ack | Method::Cancel) || dialog.cseq == 0 {
+            dialog.cseq += 1;
+        }
+        let mut builder = self
+            .request(method, dialog.remote_target.clone())
+            .from(dialog.local_header())
+            .to(dialog.remote_header())
+            .call_id(dialog.call_id.as_str())
+            .cseq(dialog.cseq);
+        for route in &dialog.route_set {
+            builder = builder.header("Route", route.as_str());
+        }
+        builder
+    }
+
+    /// A response to `request`, echoing its Via, From, To, Call-ID and CSeq
+    ///
+    /// Responses other than 100 Trying get a To tag when the request had
+    /// none, and provisional and success responses to an INVITE carry our
+    /// Contact.
+    pub fn response(&self, request: &Request, status: StatusCode) -> ResponseBuilder {
+        let mut response = Response::new(status);
+        for header in &request.headers {
+            let name = header.name.as_str();
+            if ["Via", "From", "Call-ID", "CSeq"]
+                .iter()
+                .any(|n| name.eq_ignore_ascii_case(n))
+            {
+                response.headers.push(header.clone());
+            }
+        }
+        if let Some(to) = request.get_header_value("To") {
+            let to = if status != StatusCode::TRYING && header_tag(to).is_none() {
+                format!("{};tag={}", to, new_tag())
+            } else {
+                to.to_string()
+            };
+            response = response.with_header("To", to.as_str());
+        }
+        if request.method == Method::Invite && status.0 > 100 && status.0 < 300 {
+            response = response.with_header("Contact", self.profile.contact().as_str());
+        }
+        if let Some(user_agent) = &self.profile.user_agent {
+            response = response.with_header("Server", user_agent.as_str());
+        }
+        ResponseBuilder { response }
+    }
+}
+
+/// A request being composed
+#[derive(Debug, Clone)]
+pub struct RequestBuilder {
+    request: Request,
+    /// Host used for a generated Call-ID
+    host: String,
+}
+
+impl RequestBuilder {
+    /// Add a header, keeping any of the same name
+    pub fn header(mut self, name: &str, value: impl AsRef<str>) -> Self {
+        self.request.headers.push(Header::new(name, value.as_ref()));
+        self
+    }
+
+    /// Set a header, replacing any of the same name
+    pub fn set_header(mut self, name: &str, value: impl AsRef<str>) -> Self {
+        set_header(&mut self.request.headers, name, value.as_ref());
+        self
+    }
+
+    pub fn from(self, value: impl AsRef<str>) -> Self {
+        self.set_header("From", value)
+    }
+
+    pub fn to(self, value: impl AsRef<str>) -> Self {
+        self.set_header("To", value)
+    }
+
+    pub fn call_id(self, call_id: impl AsRef<str>) -> Self {
+        self.set_header("Call-ID", call_id)
+    }
+
+    /// Set the CSeq number; the method is the request's own
+    pub fn cseq(self, sequence: u32) -> Self {
+        let method = self.request.method;
+        self.set_header("CSeq", format!("{} {}", sequence, method))
+    }
+
+    /// Replace the Via branch, e.g. for a CANCEL that must match its INVITE
+    pub fn branch(mut self, branch: &str) -> Self {
+        if let Some(via) = self
+            .request
+            .headers
+            .iter_mut()
+            .find(|h| h.name.as_str().eq_ignore_ascii_case("Via"))
+        {
+            let value = via.value.as_str();
+            let sent = value.split(";branch=").next().unwrap_or(value);
+            via.value = format!("{};branch={};rport", sent, branch).into();
+        }
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Bytes>) -> Self {
+        self = self.set_header("Content-Type", content_type);
+        self.request.body = body.into();
+        self
+    }
+
+    pub fn sdp(self, sdp: &SdpSession) -> Self {
+        self.body("application/sdp", sdp.to_string())
+    }
+
+    /// The finished request
+    ///
+    /// A missing To is taken from the Request-URI, a missing From from our
+    /// Contact with a fresh tag, and a missing Call-ID and CSeq are
+    /// generated.
+    pub fn build(mut self) -> Request {
+        let headers = &mut self.request.headers;
+        if !has_header(headers, "To") {
+            let to = format!("<{}>", self.request.uri);
+            headers.push(Header::new("To", to.as_str()));
+        }
+        if !has_header(headers, "From") {
+            let from = format!("<sip:{}>;tag={}", self.host, new_tag());
+            headers.push(Header::new("From", from.as_str()));
+        }
+        if !has_header(headers, "Call-ID") {
+            let call_id = format!("{}@{}", Uuid::new_v4().simple(), self.host);
+            headers.push(Header::new("Call-ID", call_id.as_str()));
+        }
+        if !has_header(headers, "CSeq") {
+            let cseq = format!("1 {}", self.request.method);
+            headers.push(Header::new("CSeq", cseq.as_str()));
+        }
+        let length = self.request.body.len().to_string();
+        set_header(&mut self.request.headers, "Content-Length", &length);
+        self.request
+    }
+}
+
+/// A response being composed
+#[derive(Debug, Clone)]
+pub struct ResponseBuilder {
+    response: Response,
+}
+
+impl ResponseBuilder {
+    /// Add a header, keeping any of the same name
+    pub fn header(mut self, name: &str, value: impl AsRef<str>) -> Self {
+        self.response
+            .headers
+            .push(Header::new(name, value.as_ref()));
+        self
+    }
+
+    /// Set a header, replacing any of the same name
+    pub fn set_header(mut self, name: &str, value: impl AsRef<str>) -> Self {
+        set_header(&mut self.response.headers, name, value.as_ref());
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Bytes>) -> Self {
+        self = self.set_header("Content-Type", content_type);
+        self.response.body = body.into();
+        self
+    }
+
+    pub fn sdp(self, sdp: &SdpSession) -> Self {
+        self.body("application/sdp", sdp.to_string())
+    }
+
+    /// The finished response
+    pub fn build(mut self) -> Response {
+        let length = self.response.body.len().to_string();
+        set_header(&mut self.response.headers, "Content-Length", &length);
+        self.response
+    }
+}
+
+/// CANCEL for a request we sent (RFC 3261 section 9.1): same Request-URI,
+/// top Via, From, To, Call-ID, Route and CSeq number
+pub fn cancel_for(request: &Request) -> Request {
+    let mut cancel = Request::new(Method::Cancel, request.uri.clone());
+    if let Some(via) = request.get_header("Via") {
+        cancel.headers.push(via.clone());
+    }
+    for header in &request.headers {
+        let name = header.name.as_str();
+        if ["From", "To", "Call-ID", "Route", "Max-Forwards"]
+            .iter()
+            .any(|n| name.eq_ignore_ascii_case(n))
+        {
+            cancel.headers.push(header.clone());
+        }
+    }
+    let sequence = request
+        .get_header_value("CSeq")
+        .and_then(|cseq| cseq.split_whitespace().next())
+        .unwrap_or("1");
+    cancel.with_header("CSeq", format!("{} CANCEL", sequence).as_str())
+}
+
+/// Methods whose requests must carry a Contact
+fn needs_contact(method: Method) -> bool {
+    matches!(
+        method,
+        Method::Invite | Method::Register | Method::Subscribe | Method::Refer | Method::Update
+    )
+}
+
+fn has_header(headers: &[Header], name: &str) -> bool {
+    headers
+        .iter()
+        .any(|h| h.name.as_str().eq_ignore_ascii_case(name))
+}
+
+fn set_header(headers: &mut Vec<Header>, name: &str, value: &str) {
+    match headers
+        .iter()
+        .position(|h| h.name.as_str().eq_ignore_ascii_case(name))
+    {
+        Some(index) => {
+            headers[index] = Header::new(name, value);
+            let mut seen = false;
+            headers.retain(|h| {
+                if !h.name.as_str().eq_ignore_ascii_case(name) {
+                    return true;
+                }
+                let keep = !seen;
+                seen = true;
+                keep
+            });
+        }
+        None => headers.push(Header::new(name, value)),
+    }
+}
+
+/// `tag` parameter of a From or To header value
+pub fn header_tag(value: &str) -> Option<&str> {
+    let params = match value.rfind('>') {
+        Some(end) => &value[end + 1..],
+        None => value,
+    };
+    params.split(';').find_map(|param| {
+        let (name, tag) = param.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("tag").then(|| tag.trim())
+    })
+}
+
+fn new_branch() -> String {
+    format!("z9hG4bK-{}", Uuid::new_v4().simple())
+}
+
+/// A fresh From or To tag
+pub(crate) fn new_tag() -> String {
+    Uuid::new_v4().simple().to_string()[..10].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> MessageBuilder {
+        MessageBuilder::new(
+            LocalProfile::new("udp", "192.0.2.1", 5060)
+                .with_user("rustalk")
+                .with_user_agent("RusTalk/test"),
+        )
+    }
+
+    fn target() -> Uri {
+        Uri::new("sip".to_string(), "192.0.2.20".to_string())
+            .with_user("1001".to_string())
+            .with_port(5062)
+    }
+
+    #[test]
+    fn test_request_required_headers() {
+        let invite = builder()
+            .request(Method::Invite, target())
+            .body("application/sdp", "v=0\r\n")
+            .build();
+        let via = invite.get_header_value("Via").unwrap();
+        assert!(via.starts_with("SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK-"));
+        assert_eq!(invite.get_header_value("Max-Forwards"), Some("70"));
+        assert_eq!(
+            invite.get_header_value("Contact"),
+            Some("<sip:rustalk@192.0.2.1:5060>")
+        );
+        assert_eq!(invite.get_header_value("CSeq"), Some("1 INVITE"));
+        assert_eq!(
+            invite.get_header_value("To"),
+            Some("<sip:1001@192.0.2.20:5062>")
+        );
+        assert!(header_tag(invite.get_header_value("From").unwrap()).is_some());
+        assert!(invite.get_header_value("Call-ID").is_some());
+        assert_eq!(invite.get_header_value("Content-Length"), Some("5"));
+        assert_eq!(invite.get_header_value("User-Agent"), Some("RusTalk/test"));
+
+        // OPTIONS needs no Contact; each request gets its own branch
+        let options = builder().request(Method::Options, target()).build();
+        assert!(options.get_header("Contact").is_none());
+        assert_ne!(options.get_header_value("Via"), Some(via));
+        assert_eq!(options.get_header_value("Content-Length"), Some("0"));
+
+        let tls = LocalProfile::new("tls", "2001:db8::1", 5061);
+        assert_eq!(tls.sent_by(), "[2001:db8::1]:5061");
+        assert_eq!(tls.contact(), "<sip:[2001:db8::1]:5061;transport=tls>");
+    }
+
+    #[test]
+    fn test_dialog_requests() {
+        let builder = builder();
+        let mut dialog =
+            DialogContext::new("sip:rustalk@192.0.2.1", target()).with_call_id("dialog1");
+        dialog
+            .route_set
+            .push("<sip:proxy.example.com;lr>".to_string());
+
+        let invite = builder.in_dialog(Method::Invite, &mut dialog).build();
+        assert_eq!(invite.get_header_value("CSeq"), Some("1 INVITE"));
+        assert_eq!(invite.get_header_value("Call-ID"), Some("dialog1"));
+        assert_eq!(
+            invite.get_header_value("Route"),
+            Some("<sip:proxy.example.com;lr>")
+        );
+
+        let ok = builder.response(&invite, StatusCode::OK).build();
+        dialog.update_from_response(&ok);
+        assert!(dialog.remote_tag.is_some());
+
+        let ack = builder.in_dialog(Method::Ack, &mut dialog).build();
+        assert_eq!(ack.get_header_value("CSeq"), Some("1 ACK"));
+        assert_eq!(ack.get_header_value("To"), ok.get_header_value("To"));
+        assert_eq!(
+            ack.get_header_value("From"),
+            invite.get_header_value("From")
+        );
+        let bye = builder.in_dialog(Method::Bye, &mut dialog).build();
+        assert_eq!(bye.get_header_value("CSeq"), Some("2 BYE"));
+    }
+
+    #[test]
+    fn test_response_and_cancel() {
+        let builder = builder();
+        let invite = builder
+            .request(Method::Invite, target())
+            .from("<sip:1002@example.com>;tag=abc")
+            .to("<sip:1001@example.com>")
+            .call_id("call1")
+            .cseq(4)
+            .header("Route", "<sip:proxy.example.com;lr>")
+            .build();
+
+        let trying = builder.response(&invite, StatusCode::TRYING).build();
+        assert_eq!(
+            trying.get_header_value("To"),
+            Some("<sip:1001@example.com>")
+        );
+        assert!(trying.get_header("Contact").is_none());
+        let ringing = builder.response(&invite, StatusCode::RINGING).build();
+        assert!(header_tag(ringing.get_header_value("To").unwrap()).is_some());
+        assert_eq!(ringing.get_header_value("CSeq"), Some("4 INVITE"));
+        assert_eq!(
+            ringing.get_header_value("Via"),
+            invite.get_header_value("Via")
+        );
+        assert_eq!(ringing.get_header_value("Server"), Some("RusTalk/test"));
+
+        let mut sdp = SdpSession::new();
+        sdp.origin = "- 1 1 IN IP4 192.0.2.1".to_string();
+        let ok = builder.response(&invite, StatusCode::OK).sdp(&sdp).build();
+        assert_eq!(ok.get_header_value("Content-Type"), Some("application/sdp"));
+        assert_eq!(
+            ok.get_header_value("Content-Length"),
+            Some(ok.body.len().to_string().as_str())
+        );
+
+        let cancel = cancel_for(&invite);
+        assert_eq!(cancel.method, Method::Cancel);
+        assert_eq!(cancel.uri, invite.uri);
+        assert_eq!(
+            cancel.get_header_value("Via"),
+            invite.get_header_value("Via")
+        );
+        assert_eq!(cancel.get_header_value("CSeq"), Some("4 CANCEL"));
+        assert_eq!(
+            cancel.get_header_value("Route"),
+            Some("<sip:proxy.example.com;lr>")
+        );
+    }
+}
@@ -0,0 +1,474 @@
+//! SIP dialogs (RFC 3261 section 12)
+//!
+//! A dialog is the peer-to-peer relationship an INVITE sets up, named by
+//! its Call-ID and the tags each side puts in From and To. Requests inside
+//! it carry both tags, are numbered with each side's own CSeq, go to the
+//! other side's Contact (the remote target) and through the route set the
+//! proxies on the path recorded. A re-INVITE or UPDATE may move the remote
+//! target. A [`Dialog`] keeps that state for one side of a call, whether we
+//! accepted the INVITE (UAS) or sent it (UAC).
+
+use super::builder::header_tag;
+use super::{DialogContext, Method, Request, Response, StatusCode, Uri};
+use std::fmt;
+
+/// Largest CSeq number allowed (RFC 3261 section 8.1.1.5)
+const MAX_SEQUENCE: u32 = (1 << 31) - 1;
+
+/// A parsed CSeq header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CSeq {
+    pub sequence: u32,
+    pub method: Method,
+}
+
+impl CSeq {
+    /// Parse a CSeq header value such as `314159 INVITE`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split_whitespace();
+        let sequence = parts.next()?.parse().ok()?;
+        let method = Method::from_str(parts.next()?)?;
+        if parts.next().is_some() || sequence > MAX_SEQUENCE {
+            return None;
+        }
+        Some(Self { sequence, method })
+    }
+}
+
+impl fmt::Display for CSeq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.sequence, self.method)
+    }
+}
+
+/// Why an in-dialog request was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceError {
+    /// The CSeq cannot be parsed, or names a different method
+    Malformed(String),
+    /// The CSeq is lower than the last one seen from the same side
+    OutOfOrder { last: u32, received: u32 },
+}
+
+impl SequenceError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Malformed(_) => StatusCode::BAD_REQUEST,
+            Self::OutOfOrder { .. } => StatusCode::SERVER_INTERNAL_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(value) => write!(f, "malformed CSeq {:?}", value),
+            Self::OutOfOrder { last, received } => {
+                write!(f, "CSeq {} is lower than {}", received, last)
+            }
+        }
+    }
+}
+
+/// Check a request's CSeq against the last number seen from its sender,
+/// returning the number to remember
+///
+/// ACK and CANCEL carry the number of the INVITE they belong to and leave
+/// it unchanged. Requests without a CSeq are not sequenced.
+pub fn check_sequence(last: Option<u32>, request: &Request) -> Result<Option<u32>, SequenceError> {
+    let Some(value) = request.get_header_value("CSeq") else {
+        return Ok(last);
+    };
+    let cseq = CSeq::parse(value)
+        .filter(|cseq| cseq.method == request.method)
+        .ok_or_else(|| SequenceError::Malformed(value.to_string()))?;
+    if matches!(request.method, Method::Ack | Method::Cancel) {
+        return Ok(last);
+    }
+    match last {
+        // Equal numbers are retransmissions
+        Some(last) if cseq.sequence < last => Err(SequenceError::OutOfOrder {
+            last,
+            received: cseq.sequence,
+        }),
+        _ => Ok(Some(cseq.sequence)),
+    }
+}
+
+/// Whether requests with `method` may move the remote target
+/// (RFC 3261 section 12.2, RFC 3311)
+pub fn is_target_refresh(method: Method) -> bool {
+    matches!(
+        method,
+        Method::Invite | Method::Update | Method::Subscribe | Method::Notify | Method::Refer
+    )
+}
+
+/// Call-ID and tags naming a dialog, from our side
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DialogId {
+    pub call_id: String,
+    pub local_tag: String,
+    /// The other side's tag; missing only for peers predating RFC 3261
+    pub remote_tag: Option<String>,
+}
+
+impl fmt::Display for DialogId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{};local={}", self.call_id, self.local_tag)?;
+        if let Some(remote) = &self.remote_tag {
+            write!(f, ";remote={}", remote)?;
+        }
+        Ok(())
+    }
+}
+
+/// Progress of a dialog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogState {
+    /// Created by a provisional response
+    Early,
+    /// Created or confirmed by a 2xx
+    Confirmed,
+    /// Ended by a BYE
+    Terminated,
+}
+
+/// One side's view of a dialog
+#[derive(Debug, Clone)]
+pub struct Dialog {
+    pub id: DialogId,
+    pub state: DialogState,
+    /// Our address-of-record, in the From of requests we send
+    pub local_uri: String,
+    /// The other side's address-of-record
+    pub remote_uri: String,
+    /// Request-URI of requests we send: the other side's Contact
+    pub remote_target: Uri,
+    /// Route headers for requests we send, from the Record-Route
+    pub route_set: Vec<String>,
+    /// CSeq of the last request we sent
+    pub local_cseq: u32,
+    /// CSeq of the last request the other side sent
+    pub remote_cseq: Option<u32>,
+}
+
+impl Dialog {
+    /// The dialog we create by answering `request` with our tag
+    /// `local_tag` (RFC 3261 section 12.1.1)
+    ///
+    /// Returns `None` when the request lacks a Call-ID, From or To. A
+    /// request without a usable Contact is answered at its From address.
+    pub fn uas(request: &Request, local_tag: impl Into<String>) -> Option<Self> {
+        let call_id = request.get_header_value("Call-ID")?;
+        let from = request.get_header_value("From")?;
+        let to = request.get_header_value("To")?;
+        let remote_target = request
+            .get_header_value("Contact")
+            .and_then(|c| uri_of(c).parse().ok())
+            .or_else(|| uri_of(from).parse().ok())?;
+        let remote_cseq = request
+            .get_header_value("CSeq")
+            .and_then(CSeq::parse)
+            .map(|cseq| cseq.sequence);
+
+        Some(Self {
+            id: DialogId {
+                call_id: call_id.to_string(),
+                local_tag: local_tag.into(),
+                remote_tag: header_tag(from).map(str::to_string),
+            },
+            state: DialogState::Early,
+            local_uri: uri_of(to).to_string(),
+            remote_uri: uri_of(from).to_string(),
+            remote_target,
+            route_set: record_route(&request.headers),
+            local_cseq: 0,
+            remote_cseq,
+        })
+    }
+
+    /// The dialog a response with a To tag creates for a request we sent
+    /// (RFC 3261 section 12.1.2)
+    ///
+    /// The route set is the response's Record-Route in reverse. Returns
+    /// `None` for responses that cannot create a dialog: 100 Trying,
+    /// failures, and anything without a To tag.
+    pub fn uac(request: &Request, response: &Response) -> Option<Self> {
+        let status = response.status_code.0;
+        if !(101..300).contains(&status) {
+            return None;
+        }
+        let call_id = request.get_header_value("Call-ID")?;
+        let from = request.get_header_value("From")?;
+        let to = response.get_header_value("To")?;
+        let remote_tag = header_tag(to)?;
+        let remote_target = response
+            .get_header_value("Contact")
+            .and_then(|c| uri_of(c).parse().ok())
+            .unwrap_or_else(|| request.uri.clone());
+        let mut route_set = record_route(&response.headers);
+        route_set.reverse();
+        let local_cseq = request
+            .get_header_value("CSeq")
+            .and_then(CSeq::parse)
+            .map(|cseq| cseq.sequence)
+            .unwrap_or(0);
+
+        Some(Self {
+            id: DialogId {
+                call_id: call_id.to_string(),
+                local_tag: header_tag(from)?.to_string(),
+                remote_tag: Some(remote_tag.to_string()),
+            },
+            state: if status < 200 {
+                DialogState::Early
+            } else {
+                DialogState::Confirmed
+            },
+            local_uri: uri_of(from).to_string(),
+            remote_uri: uri_of(to).to_string(),
+            remote_target,
+            route_set,
+            local_cseq,
+            remote_cseq: None,
+        })
+    }
+
+    /// Whether `request`, received from the other side, belongs to this
+    /// dialog: its To tag is ours and its From tag theirs
+    pub fn matches(&self, request: &Request) -> bool {
+        let tag = |name| request.get_header_value(name).and_then(header_tag);
+        request.get_header_value("Call-ID") == Some(self.id.call_id.as_str())
+            && tag("To") == Some(self.id.local_tag.as_str())
+            && tag("From") == self.id.remote_tag.as_deref()
+    }
+
+    /// Take a request received in the dialog: check and advance the remote
+    /// CSeq, follow a target refresh to a new Contact, and end the dialog
+    /// on BYE
+    pub fn receive(&mut self, request: &Request) -> Result<(), SequenceError> {
+        self.remote_cseq = check_sequence(self.remote_cseq, request)?;
+        if is_target_refresh(request.method) {
+            if let Some(target) = request
+                .get_header_value("Contact")
+                .and_then(|c| uri_of(c).parse().ok())
+            {
+                self.remote_target = target;
+            }
+        }
+        if request.method == Method::Bye {
+            self.state = DialogState::Terminated;
+        }
+        Ok(())
+    }
+
+    /// Take a response to a request we sent in the dialog
+    ///
+    /// A 2xx confirms an early dialog, and one to a target refresh moves
+    /// the remote target to its Contact.
+    pub fn update_from_response(&mut self, response: &Response) {
+        if !response.status_code.is_success() {
+            return;
+        }
+        if self.state == DialogState::Early {
+            self.state = DialogState::Confirmed;
+        }
+        let refresh = response
+            .get_header_value("CSeq")
+            .and_then(CSeq::parse)
+            .is_some_and(|cseq| is_target_refresh(cseq.method));
+        if let Some(target) = response
+            .get_header_value("Contact")
+            .filter(|_| refresh)
+            .and_then(|c| uri_of(c).parse().ok())
+        {
+            self.remote_target = target;
+        }
+    }
+
+    /// CSeq for the next request we send with `method`
+    ///
+    /// ACK and CANCEL reuse the number of our last request.
+    pub fn next_cseq(&mut self, method: Method) -> CSeq {
+        if !matches!(method, Method::Ack | Method::Cancel) || self.local_cseq == 0 {
+            self.local_cseq += 1;
+        }
+        CSeq {
+            sequence: self.local_cseq,
+            method,
+        }
+    }
+
+    /// State for building requests inside the dialog with
+    /// [`MessageBuilder::in_dialog`](super::MessageBuilder::in_dialog)
+    pub fn context(&self) -> DialogContext {
+        DialogContext {
+            call_id: self.id.call_id.clone(),
+            local_uri: self.local_uri.clone(),
+            local_tag: self.id.local_tag.clone(),
+            remote_uri: self.remote_uri.clone(),
+            remote_tag: self.id.remote_tag.clone(),
+            remote_target: self.remote_target.clone(),
+            cseq: self.local_cseq,
+            route_set: self.route_set.clone(),
+        }
+    }
+
+    /// To header value of our responses in the dialog, carrying our tag
+    pub fn local_header(&self) -> String {
+        format!("<{}>;tag={}", self.local_uri, self.id.local_tag)
+    }
+}
+
+/// Record-Route entries in the order they appear, one per URI
+fn record_route(headers: &[super::Header]) -> Vec<String> {
+    headers
+        .iter()
+        .filter(|h| h.name.as_str().eq_ignore_ascii_case("Record-Route"))
+        .flat_map(|h| h.value.as_str().split(','))
+        .map(|route| route.trim().to_string())
+        .filter(|route| !route.is_empty())
+        .collect()
+}
+
+/// URI of a name-addr or addr-spec header value
+fn uri_of(value: &str) -> &str {
+    let value = value.trim();
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or(value).trim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invite() -> Request {
+        Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1001".to_string()),
+        )
+        .with_header(
+            "Record-Route",
+            "<sip:p1.example.com;lr>, <sip:p2.example.com;lr>",
+        )
+        .with_header("From", "\"Alice\" <sip:1002@example.com>;tag=a1")
+        .with_header("To", "<sip:1001@example.com>")
+        .with_header("Call-ID", "dialog1")
+        .with_header("CSeq", "10 INVITE")
+        .with_header("Contact", "<sip:1002@192.0.2.30:5060>")
+    }
+
+    fn in_dialog(method: Method, cseq: &str, local_tag: &str) -> Request {
+        Request::new(method, "sip:1001@192.0.2.1".parse().unwrap())
+            .with_header("From", "<sip:1002@example.com>;tag=a1")
+            .with_header(
+                "To",
+                format!("<sip:1001@example.com>;tag={}", local_tag).as_str(),
+            )
+            .with_header("Call-ID", "dialog1")
+            .with_header("CSeq", cseq)
+    }
+
+    #[test]
+    fn test_cseq_parse() {
+        let cseq = CSeq::parse("314159 INVITE").unwrap();
+        assert_eq!(cseq.sequence, 314159);
+        assert_eq!(cseq.method, Method::Invite);
+        assert_eq!(cseq.to_string(), "314159 INVITE");
+        assert!(CSeq::parse("INVITE").is_none());
+        assert!(CSeq::parse("1 FOO").is_none());
+        assert!(CSeq::parse("4294967295 BYE").is_none());
+    }
+
+    #[test]
+    fn test_uas_dialog() {
+        let mut dialog = Dialog::uas(&invite(), "b2").unwrap();
+        assert_eq!(dialog.id.remote_tag.as_deref(), Some("a1"));
+        assert_eq!(dialog.remote_target.to_string(), "sip:1002@192.0.2.30:5060");
+        assert_eq!(dialog.remote_uri, "sip:1002@example.com");
+        assert_eq!(
+            dialog.route_set,
+            ["<sip:p1.example.com;lr>", "<sip:p2.example.com;lr>"]
+        );
+        assert_eq!(dialog.remote_cseq, Some(10));
+        assert_eq!(dialog.local_header(), "<sip:1001@example.com>;tag=b2");
+
+        // Only requests carrying both tags are in the dialog
+        let reinvite = in_dialog(Method::Invite, "11 INVITE", "b2")
+            .with_header("Contact", "<sip:1002@198.51.100.7:5062>");
+        assert!(dialog.matches(&reinvite));
+        assert!(!dialog.matches(&in_dialog(Method::Invite, "11 INVITE", "other")));
+        assert!(!dialog.matches(&invite()));
+
+        // A re-INVITE moves the target, an INFO does not
+        dialog.receive(&reinvite).unwrap();
+        assert_eq!(
+            dialog.remote_target.to_string(),
+            "sip:1002@198.51.100.7:5062"
+        );
+        let info = in_dialog(Method::Info, "12 INFO", "b2")
+            .with_header("Contact", "<sip:1002@203.0.113.1>");
+        dialog.receive(&info).unwrap();
+        assert_eq!(
+            dialog.remote_target.to_string(),
+            "sip:1002@198.51.100.7:5062"
+        );
+        assert_eq!(
+            dialog.receive(&in_dialog(Method::Update, "11 UPDATE", "b2")),
+            Err(SequenceError::OutOfOrder {
+                last: 12,
+                received: 11
+            })
+        );
+        dialog
+            .receive(&in_dialog(Method::Bye, "13 BYE", "b2"))
+            .unwrap();
+        assert_eq!(dialog.state, DialogState::Terminated);
+    }
+
+    #[test]
+    fn test_uac_dialog() {
+        let mut request = invite();
+        request
+            .headers
+            .retain(|h| h.name.as_str() != "Record-Route");
+        let response = |status: u16, to_tag: bool| {
+            let to = if to_tag {
+                "<sip:1001@example.com>;tag=c3"
+            } else {
+                "<sip:1001@example.com>"
+            };
+            Response::new(StatusCode(status))
+                .with_header("To", to)
+                .with_header("CSeq", "10 INVITE")
+                .with_header("Contact", "<sip:1001@10.0.0.8:5062>")
+                .with_header("Record-Route", "<sip:p2.example.com;lr>")
+                .with_header("Record-Route", "<sip:p1.example.com;lr>")
+        };
+        assert!(Dialog::uac(&request, &response(100, false)).is_none());
+        assert!(Dialog::uac(&request, &response(180, false)).is_none());
+        assert!(Dialog::uac(&request, &response(486, true)).is_none());
+
+        let mut dialog = Dialog::uac(&request, &response(180, true)).unwrap();
+        assert_eq!(dialog.state, DialogState::Early);
+        assert_eq!(dialog.id.local_tag, "a1");
+        assert_eq!(dialog.id.remote_tag.as_deref(), Some("c3"));
+        assert_eq!(dialog.remote_target.to_string(), "sip:1001@10.0.0.8:5062");
+        // Record-Route is reversed for the side that sent the request
+        assert_eq!(
+            dialog.route_set,
+            ["<sip:p1.example.com;lr>", "<sip:p2.example.com;lr>"]
+        );
+        dialog.update_from_response(&response(200, true));
+        assert_eq!(dialog.state, DialogState::Confirmed);
+
+        assert_eq!(dialog.next_cseq(Method::Bye).to_string(), "11 BYE");
+        let context = dialog.context();
+        assert_eq!(context.cseq, 11);
+        assert_eq!(context.remote_tag.as_deref(), Some("c3"));
+        assert_eq!(context.route_set.len(), 2);
+    }
+}
@@ -52,6 +52,7 @@ impl Method {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "INVITE" => Some(Method::Invite),
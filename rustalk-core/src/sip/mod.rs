@@ -1,11 +1,15 @@
 //! SIP Protocol implementation
 
+pub mod builder;
+pub mod dialog;
 pub mod header;
 pub mod message;
 pub mod method;
 pub mod parser;
 pub mod response;
 
+pub use builder::{DialogContext, LocalProfile, MessageBuilder};
+pub use dialog::{CSeq, Dialog, DialogId, DialogState, SequenceError};
 pub use header::{Header, HeaderName, HeaderValue};
 pub use message::{Message, Request, Response};
 pub use method::Method;
@@ -45,6 +49,29 @@ impl Uri {
     }
 }
 
+impl std::str::FromStr for Uri {
+    type Err = String;
+
+    /// Parse an addr-spec such as `sip:1001@192.0.2.10:5060;transport=udp`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rest, mut uri) =
+            parser::parse_uri(s.trim()).map_err(|e| format!("Invalid URI {}: {}", s, e))?;
+        for param in rest.split(';') {
+            if param.is_empty() {
+                continue;
+            }
+            match param.split_once('=') {
+                Some((key, value)) => uri.params.push((key.to_string(), Some(value.to_string()))),
+                None => uri.params.push((param.to_string(), None)),
+            }
+        }
+        if !rest.is_empty() && !rest.starts_with(';') {
+            return Err(format!("Invalid URI {}", s));
+        }
+        Ok(uri)
+    }
+}
+
 impl std::fmt::Display for Uri {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:", self.scheme)?;
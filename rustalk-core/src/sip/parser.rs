@@ -4,11 +4,11 @@ use super::{Header, Message, Method, Request, Response, StatusCode, Uri};
 use bytes::Bytes;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until, take_while, take_while1},
+    bytes::complete::{tag, take_till1, take_until, take_while1},
     character::complete::{char, digit1, line_ending, space1},
     combinator::{map, map_res, opt},
     multi::many0,
-    sequence::{delimited, preceded, separated_pair, terminated, tuple},
+    sequence::{preceded, separated_pair, terminated, tuple},
     IResult,
 };
 
@@ -33,7 +33,7 @@ fn parse_request(input: &str) -> IResult<&str, Request> {
     let (input, (method, _, uri, _, version, _)) = tuple((
         parse_method,
         space1,
-        parse_uri,
+        parse_request_uri,
         space1,
         tag("SIP/2.0"),
         line_ending,
@@ -101,7 +101,12 @@ fn parse_method(input: &str) -> IResult<&str, Method> {
     ))(input)
 }
 
-fn parse_uri(input: &str) -> IResult<&str, Uri> {
+/// Request-URI, with its parameters
+fn parse_request_uri(input: &str) -> IResult<&str, Uri> {
+    map_res(take_till1(|c: char| c == ' '), str::parse::<Uri>)(input)
+}
+
+pub(crate) fn parse_uri(input: &str) -> IResult<&str, Uri> {
     let (input, scheme) = alt((tag("sip"), tag("sips")))(input)?;
     let (input, _) = char(':')(input)?;
 
@@ -174,6 +179,26 @@ mod tests {
         assert_eq!(request.uri.host, "example.com");
     }
 
+    #[test]
+    fn test_parse_request_uri_params() {
+        let msg = b"INVITE sip:1001@example.com;gr=urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6 SIP/2.0\r\n\
+                     Call-ID: abc123\r\n\
+                     CSeq: 1 INVITE\r\n\
+                     Content-Length: 0\r\n\
+                     \r\n";
+
+        let message = parse_message(msg).unwrap();
+        let request = message.as_request().unwrap();
+        assert_eq!(request.uri.user.as_deref(), Some("1001"));
+        assert_eq!(
+            request.uri.params,
+            [(
+                "gr".to_string(),
+                Some("urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6".to_string())
+            )]
+        );
+    }
+
     #[test]
     fn test_parse_200_response() {
         let msg = b"SIP/2.0 200 OK\r\n\
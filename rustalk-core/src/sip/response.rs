@@ -52,6 +52,7 @@ impl StatusCode {
     pub const BUSY_HERE: StatusCode = StatusCode(486);
     pub const REQUEST_TERMINATED: StatusCode = StatusCode(487);
     pub const NOT_ACCEPTABLE_HERE: StatusCode = StatusCode(488);
+    pub const BAD_EVENT: StatusCode = StatusCode(489);
     pub const REQUEST_PENDING: StatusCode = StatusCode(491);
     pub const UNDECIPHERABLE: StatusCode = StatusCode(493);
 
@@ -110,6 +111,7 @@ impl StatusCode {
             486 => "Busy Here",
             487 => "Request Terminated",
             488 => "Not Acceptable Here",
+            489 => "Bad Event",
             491 => "Request Pending",
             493 => "Undecipherable",
             500 => "Server Internal Error",
@@ -0,0 +1,391 @@
+//! SMS gateway
+//!
+//! Sends and receives text messages through an SMS provider (Twilio's REST
+//! API or an SMPP connection). Inbound messages are matched to an extension
+//! by the DID they were sent to; outbound messages from an extension are
+//! sent from that extension's DID. With the SIP bridge enabled, inbound SMS
+//! are delivered to phones as SIP MESSAGE requests and SIP MESSAGE requests
+//! to external numbers are sent on as SMS.
+
+pub mod smpp;
+pub mod twilio;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+use crate::call_trace::uri_user;
+use crate::sip::{Method, Request, Uri};
+
+pub use smpp::{SmppConfig, SmppProvider};
+pub use twilio::{TwilioConfig, TwilioProvider};
+
+/// Messages kept for the message log
+const MESSAGE_LOG_SIZE: usize = 1000;
+
+/// Provider used to reach the SMS network
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SmsProviderConfig {
+    Twilio(TwilioConfig),
+    Smpp(SmppConfig),
+}
+
+/// SMS gateway configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SmsConfig {
+    pub provider: SmsProviderConfig,
+    /// Extension that receives messages sent to each DID
+    #[serde(default)]
+    pub dids: HashMap<String, String>,
+    /// Deliver SMS to phones as SIP MESSAGE and send SIP MESSAGE to
+    /// external numbers as SMS
+    #[serde(default)]
+    pub sip_bridge: bool,
+}
+
+/// Sends messages over an SMS provider
+#[async_trait::async_trait]
+pub trait SmsProvider: Send + Sync {
+    /// Provider name for logs and the message log
+    fn name(&self) -> &str;
+
+    /// Send a message, returning the provider's message ID
+    async fn send(&self, from: &str, to: &str, body: &str) -> Result<String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmsDirection {
+    Inbound,
+    Outbound,
+}
+
+/// A sent or received text message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmsMessage {
+    pub id: String,
+    pub direction: SmsDirection,
+    /// Sending number
+    pub from: String,
+    /// Receiving number
+    pub to: String,
+    pub body: String,
+    /// Extension the message was sent from or delivered to
+    pub extension: Option<String>,
+    /// Provider's ID for the message
+    pub provider_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Numbers compare equal regardless of formatting (`+1 (212) 555-1234`)
+fn normalize_number(number: &str) -> String {
+    number.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+/// SMS gateway shared by the API and the B2BUA
+#[derive(Clone)]
+pub struct SmsGateway {
+    config: SmsConfig,
+    provider: Arc<dyn SmsProvider>,
+    messages: Arc<Mutex<VecDeque<SmsMessage>>>,
+    /// Transport for SIP MESSAGE requests, and the SIP domain they are
+    /// addressed in
+    sip_sink: Option<(mpsc::UnboundedSender<Request>, String)>,
+}
+
+impl SmsGateway {
+    /// Create a gateway using the provider named in the configuration
+    pub fn new(config: SmsConfig) -> Result<Self> {
+        let provider: Arc<dyn SmsProvider> = match &config.provider {
+            SmsProviderConfig::Twilio(twilio) => Arc::new(TwilioProvider::new(twilio.clone())),
+            SmsProviderConfig::Smpp(smpp) => Arc::new(SmppProvider::new(smpp.clone())),
+        };
+        Ok(Self::with_provider(config, provider))
+    }
+
+    /// Create a gateway sending through `provider`
+    pub fn with_provider(config: SmsConfig, provider: Arc<dyn SmsProvider>) -> Self {
+        Self {
+            config,
+            provider,
+            messages: Arc::new(Mutex::new(VecDeque::new())),
+            sip_sink: None,
+        }
+    }
+
+    /// Send SIP MESSAGE requests for inbound SMS to the transport
+    pub fn with_sip_sink(
+        mut self,
+        sink: mpsc::UnboundedSender<Request>,
+        domain: impl Into<String>,
+    ) -> Self {
+        self.sip_sink = Some((sink, domain.into()));
+        self
+    }
+
+    pub fn config(&self) -> &SmsConfig {
+        &self.config
+    }
+
+    pub fn provider_name(&self) -> &str {
+        self.provider.name()
+    }
+
+    /// Extension that receives messages sent to `did`
+    pub fn extension_for_did(&self, did: &str) -> Option<&str> {
+        let did = normalize_number(did);
+        self.config
+            .dids
+            .iter()
+            .find(|(number, _)| normalize_number(number) == did)
+            .map(|(_, extension)| extension.as_str())
+    }
+
+    /// DID that messages from `extension` are sent from
+    pub fn did_for_extension(&self, extension: &str) -> Option<&str> {
+        let mut dids: Vec<&String> = self
+            .config
+            .dids
+            .iter()
+            .filter(|(_, ext)| ext.as_str() == extension)
+            .map(|(did, _)| did)
+            .collect();
+        // Lowest DID, so the sender is stable when an extension has several
+        dids.sort();
+        dids.first().map(|did| did.as_str())
+    }
+
+    /// Send a message
+    ///
+    /// `from` is either an extension, which is sent from its DID, or one of
+    /// the gateway's DIDs.
+    pub async fn send(&self, from: &str, to: &str, body: &str) -> Result<SmsMessage> {
+        if body.is_empty() {
+            bail!("Message body is empty");
+        }
+        if normalize_number(to).is_empty() {
+            bail!("Invalid destination number '{}'", to);
+        }
+        let (sender, extension) = match self.did_for_extension(from) {
+            Some(did) => (did.to_string(), Some(from.to_string())),
+            None => match self.extension_for_did(from) {
+                Some(extension) => (from.to_string(), Some(extension.to_string())),
+                None => bail!("'{}' is not an SMS-enabled extension or DID", from),
+            },
+        };
+
+        let provider_id = self.provider.send(&sender, to, body).await?;
+        let message = SmsMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            direction: SmsDirection::Outbound,
+            from: sender,
+            to: to.to_string(),
+            body: body.to_string(),
+            extension,
+            provider_id: Some(provider_id),
+            timestamp: Utc::now(),
+        };
+        self.record(message.clone());
+        Ok(message)
+    }
+
+    /// Accept a message from the provider
+    ///
+    /// Messages to a DID without an extension are rejected. With the SIP
+    /// bridge enabled the message is handed to the SIP sink as a MESSAGE
+    /// request for the extension.
+    pub fn receive(
+        &self,
+        from: &str,
+        to: &str,
+        body: &str,
+        provider_id: Option<String>,
+    ) -> Result<SmsMessage> {
+        let Some(extension) = self.extension_for_did(to) else {
+            bail!("No extension for DID '{}'", to);
+        };
+        let message = SmsMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            direction: SmsDirection::Inbound,
+            from: from.to_string(),
+            to: to.to_string(),
+            body: body.to_string(),
+            extension: Some(extension.to_string()),
+            provider_id,
+            timestamp: Utc::now(),
+        };
+
+        if let (true, Some((sink, domain))) = (self.config.sip_bridge, &self.sip_sink) {
+            let request = sip_message(&message, extension, domain);
+            if sink.send(request).is_err() {
+                tracing::warn!("SIP sink closed; SMS to {} not delivered", extension);
+            }
+        }
+        self.record(message.clone());
+        Ok(message)
+    }
+
+    /// Send a SIP MESSAGE from a phone on as SMS
+    ///
+    /// Returns `None` when the bridge is off or the request is not an SMS:
+    /// it has no text body, or the destination is not a phone number.
+    pub async fn bridge_sip_message(&self, request: &Request) -> Option<Result<SmsMessage>> {
+        if !self.config.sip_bridge || request.method != Method::Message {
+            return None;
+        }
+        let is_text = request
+            .get_header_value("Content-Type")
+            .is_none_or(|value| value.trim().starts_with("text/plain"));
+        let to = request.uri.user.as_deref()?;
+        // Extensions are short; anything national length or longer is a
+        // phone number
+        if !is_text || normalize_number(to).len() < 7 || request.body.is_empty() {
+            return None;
+        }
+        let from = request.get_header_value("From").and_then(uri_user)?;
+        let body = String::from_utf8_lossy(&request.body);
+        Some(self.send(from, to, &body).await)
+    }
+
+    /// Recent messages, oldest first
+    pub fn messages(&self) -> Vec<SmsMessage> {
+        self.messages.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn record(&self, message: SmsMessage) {
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() == MESSAGE_LOG_SIZE {
+            messages.pop_front();
+        }
+        messages.push_back(message);
+    }
+}
+
+/// SIP MESSAGE delivering an SMS to an extension
+pub fn sip_message(message: &SmsMessage, extension: &str, domain: &str) -> Request {
+    Request::new(
+        Method::Message,
+        Uri::new("sip".to_string(), domain.to_string()).with_user(extension.to_string()),
+    )
+    .with_header("From", format!("<sip:{}@{}>", message.from, domain))
+    .with_header("To", format!("<sip:{}@{}>", extension, domain))
+    .with_header("Call-ID", message.id.clone())
+    .with_header("CSeq", "1 MESSAGE")
+    .with_header("Content-Type", "text/plain;charset=UTF-8")
+    .with_body(message.body.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Provider that records what it was asked to send
+    #[derive(Default)]
+    struct RecordingProvider {
+        sent: Mutex<Vec<(String, String, String)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SmsProvider for RecordingProvider {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn send(&self, from: &str, to: &str, body: &str) -> Result<String> {
+            let mut sent = self.sent.lock().unwrap();
+            sent.push((from.to_string(), to.to_string(), body.to_string()));
+            Ok(format!("SM{}", sent.len()))
+        }
+    }
+
+    fn gateway(provider: Arc<RecordingProvider>) -> SmsGateway {
+        let config = SmsConfig {
+            provider: SmsProviderConfig::Twilio(TwilioConfig::default()),
+            dids: HashMap::from([
+                ("+12125551000".to_string(), "1001".to_string()),
+                ("+12125552000".to_string(), "1002".to_string()),
+            ]),
+            sip_bridge: true,
+        };
+        SmsGateway::with_provider(config, provider)
+    }
+
+    #[test]
+    fn test_did_mapping() {
+        let gateway = gateway(Arc::default());
+        assert_eq!(gateway.extension_for_did("1 (212) 555-1000"), Some("1001"));
+        assert_eq!(gateway.extension_for_did("+12125559999"), None);
+        assert_eq!(gateway.did_for_extension("1002"), Some("+12125552000"));
+        assert_eq!(gateway.did_for_extension("1003"), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_from_extension() {
+        let provider = Arc::new(RecordingProvider::default());
+        let gateway = gateway(provider.clone());
+
+        let message = gateway.send("1001", "+13105550123", "Hi").await.unwrap();
+        assert_eq!(message.from, "+12125551000");
+        assert_eq!(message.extension.as_deref(), Some("1001"));
+        assert_eq!(message.provider_id.as_deref(), Some("SM1"));
+        assert_eq!(
+            provider.sent.lock().unwrap()[0],
+            (
+                "+12125551000".to_string(),
+                "+13105550123".to_string(),
+                "Hi".to_string()
+            )
+        );
+
+        assert!(gateway.send("1003", "+13105550123", "Hi").await.is_err());
+        assert!(gateway.send("1001", "+13105550123", "").await.is_err());
+        assert_eq!(gateway.messages().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_receive_and_sip_bridge() {
+        let provider = Arc::new(RecordingProvider::default());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let gateway = gateway(provider.clone()).with_sip_sink(tx, "example.com");
+
+        let message = gateway
+            .receive("+13105550123", "+12125551000", "Hello", None)
+            .unwrap();
+        assert_eq!(message.extension.as_deref(), Some("1001"));
+        let request = rx.try_recv().unwrap();
+        assert_eq!(request.method, Method::Message);
+        assert_eq!(request.uri.user.as_deref(), Some("1001"));
+        assert_eq!(&request.body[..], b"Hello");
+        assert!(gateway
+            .receive("+13105550123", "+12125559999", "Hello", None)
+            .is_err());
+
+        // A reply from the phone goes out as SMS
+        let reply = Request::new(
+            Method::Message,
+            Uri::new("sip".to_string(), "example.com".to_string())
+                .with_user("+13105550123".to_string()),
+        )
+        .with_header("From", "<sip:1001@example.com>;tag=a")
+        .with_header("Content-Type", "text/plain")
+        .with_body("Hi back");
+        let sent = gateway.bridge_sip_message(&reply).await.unwrap().unwrap();
+        assert_eq!(sent.from, "+12125551000");
+        assert_eq!(sent.body, "Hi back");
+
+        // Messages between extensions stay on SIP
+        let internal = Request::new(
+            Method::Message,
+            Uri::new("sip".to_string(), "example.com".to_string()).with_user("1002".to_string()),
+        )
+        .with_header("From", "<sip:1001@example.com>")
+        .with_body("Lunch?");
+        assert!(gateway.bridge_sip_message(&internal).await.is_none());
+        assert_eq!(gateway.messages().len(), 2);
+    }
+}
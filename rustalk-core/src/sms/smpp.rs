@@ -0,0 +1,461 @@
+//! SMPP 3.4 adapter
+//!
+//! Messages are sent over a short-lived transmitter bind per message.
+//! Inbound messages arrive over a long-lived receiver bind started with
+//! [`SmppProvider::receive`].
+
+use anyhow::{anyhow, bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use super::SmsProvider;
+
+pub const BIND_RECEIVER: u32 = 0x0000_0001;
+pub const BIND_TRANSMITTER: u32 = 0x0000_0002;
+pub const SUBMIT_SM: u32 = 0x0000_0004;
+pub const DELIVER_SM: u32 = 0x0000_0005;
+pub const UNBIND: u32 = 0x0000_0006;
+pub const ENQUIRE_LINK: u32 = 0x0000_0015;
+/// Set on the command ID of every response
+pub const RESPONSE: u32 = 0x8000_0000;
+
+/// Longest text carried in `short_message`; longer texts use the
+/// `message_payload` TLV
+const MAX_SHORT_MESSAGE: usize = 254;
+const TAG_MESSAGE_PAYLOAD: u16 = 0x0424;
+/// Data coding for UCS-2 (UTF-16BE)
+const DATA_CODING_UCS2: u8 = 0x08;
+/// International number, E.164 plan
+const TON_INTERNATIONAL: u8 = 0x01;
+const NPI_E164: u8 = 0x01;
+
+/// SMSC connection settings
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SmppConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub system_id: String,
+    pub password: String,
+    #[serde(default)]
+    pub system_type: String,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_port() -> u16 {
+    2775
+}
+
+fn default_timeout_seconds() -> u64 {
+    10
+}
+
+/// One SMPP protocol data unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdu {
+    pub command_id: u32,
+    pub status: u32,
+    pub sequence: u32,
+    pub body: Vec<u8>,
+}
+
+impl Pdu {
+    pub fn new(command_id: u32, sequence: u32, body: Vec<u8>) -> Self {
+        Self {
+            command_id,
+            status: 0,
+            sequence,
+            body,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + self.body.len());
+        bytes.extend_from_slice(&(16 + self.body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.command_id.to_be_bytes());
+        bytes.extend_from_slice(&self.status.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    async fn read(stream: &mut TcpStream) -> Result<Self> {
+        let mut header = [0u8; 16];
+        stream.read_exact(&mut header).await?;
+        let word = |i: usize| u32::from_be_bytes(header[i..i + 4].try_into().unwrap());
+        let length = word(0) as usize;
+        if !(16..=64 * 1024).contains(&length) {
+            bail!("Invalid SMPP PDU length {}", length);
+        }
+        let mut body = vec![0u8; length - 16];
+        stream.read_exact(&mut body).await?;
+        Ok(Self {
+            command_id: word(4),
+            status: word(8),
+            sequence: word(12),
+            body,
+        })
+    }
+}
+
+fn put_cstring(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(0);
+}
+
+/// Number without formatting, and its type of number
+fn address(number: &str) -> (u8, String) {
+    let digits: String = number.chars().filter(|c| c.is_ascii_digit()).collect();
+    let ton = if number.starts_with('+') {
+        TON_INTERNATIONAL
+    } else {
+        0
+    };
+    (ton, digits)
+}
+
+/// Body of a bind request
+pub fn bind_body(config: &SmppConfig) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_cstring(&mut body, &config.system_id);
+    put_cstring(&mut body, &config.password);
+    put_cstring(&mut body, &config.system_type);
+    body.push(0x34); // interface_version
+    body.push(0); // addr_ton
+    body.push(0); // addr_npi
+    put_cstring(&mut body, ""); // address_range
+    body
+}
+
+/// Body of a `submit_sm`, in GSM default coding for ASCII text and UCS-2
+/// otherwise
+pub fn submit_sm_body(from: &str, to: &str, text: &str) -> Vec<u8> {
+    let (data_coding, encoded) = if text.is_ascii() {
+        (0, text.as_bytes().to_vec())
+    } else {
+        let encoded = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        (DATA_CODING_UCS2, encoded)
+    };
+
+    let mut body = Vec::new();
+    put_cstring(&mut body, ""); // service_type
+    for number in [from, to] {
+        let (ton, digits) = address(number);
+        body.push(ton);
+        body.push(NPI_E164);
+        put_cstring(&mut body, &digits);
+    }
+    body.push(0); // esm_class
+    body.push(0); // protocol_id
+    body.push(0); // priority_flag
+    put_cstring(&mut body, ""); // schedule_delivery_time
+    put_cstring(&mut body, ""); // validity_period
+    body.push(0); // registered_delivery
+    body.push(0); // replace_if_present_flag
+    body.push(data_coding);
+    body.push(0); // sm_default_msg_id
+    if encoded.len() <= MAX_SHORT_MESSAGE {
+        body.push(encoded.len() as u8);
+        body.extend_from_slice(&encoded);
+    } else {
+        body.push(0);
+        body.extend_from_slice(&TAG_MESSAGE_PAYLOAD.to_be_bytes());
+        body.extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+        body.extend_from_slice(&encoded);
+    }
+    body
+}
+
+/// Message delivered by the SMSC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliverSm {
+    pub from: String,
+    pub to: String,
+    pub text: String,
+}
+
+/// Reads the fields of a PDU body in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8> {
+        let (&byte, rest) = self
+            .bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("Truncated SMPP PDU"))?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn cstring(&mut self) -> Result<String> {
+        let end = self
+            .bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| anyhow!("Unterminated string in SMPP PDU"))?;
+        let value = String::from_utf8_lossy(&self.bytes[..end]).into_owned();
+        self.bytes = &self.bytes[end + 1..];
+        Ok(value)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("Truncated SMPP PDU");
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+fn decode_text(data_coding: u8, bytes: &[u8]) -> String {
+    if data_coding == DATA_CODING_UCS2 {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/// Number with a `+` restored for international addresses
+fn format_address(ton: u8, digits: String) -> String {
+    if ton == TON_INTERNATIONAL {
+        format!("+{}", digits)
+    } else {
+        digits
+    }
+}
+
+/// Parse the body of a `deliver_sm`
+pub fn parse_deliver_sm(body: &[u8]) -> Result<DeliverSm> {
+    let mut reader = Reader { bytes: body };
+    reader.cstring()?; // service_type
+    let from_ton = reader.u8()?;
+    reader.u8()?;
+    let from = format_address(from_ton, reader.cstring()?);
+    let to_ton = reader.u8()?;
+    reader.u8()?;
+    let to = format_address(to_ton, reader.cstring()?);
+    reader.take(3)?; // esm_class, protocol_id, priority_flag
+    reader.cstring()?;
+    reader.cstring()?;
+    reader.take(2)?; // registered_delivery, replace_if_present_flag
+    let data_coding = reader.u8()?;
+    reader.u8()?;
+    let length = reader.u8()? as usize;
+    let mut text = reader.take(length)?;
+
+    // Long messages carry their text in the message_payload TLV
+    while text.is_empty() && reader.bytes.len() >= 4 {
+        let tag = u16::from_be_bytes([reader.u8()?, reader.u8()?]);
+        let len = u16::from_be_bytes([reader.u8()?, reader.u8()?]) as usize;
+        let value = reader.take(len)?;
+        if tag == TAG_MESSAGE_PAYLOAD {
+            text = value;
+        }
+    }
+
+    Ok(DeliverSm {
+        from,
+        to,
+        text: decode_text(data_coding, text),
+    })
+}
+
+/// Sends and receives messages over SMPP
+pub struct SmppProvider {
+    config: SmppConfig,
+    sequence: AtomicU32,
+}
+
+impl SmppProvider {
+    pub fn new(config: SmppConfig) -> Self {
+        Self {
+            config,
+            sequence: AtomicU32::new(1),
+        }
+    }
+
+    fn next_sequence(&self) -> u32 {
+        self.sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout_seconds)
+    }
+
+    /// Send a request and wait for its response
+    async fn request(&self, stream: &mut TcpStream, command_id: u32, body: Vec<u8>) -> Result<Pdu> {
+        let sequence = self.next_sequence();
+        stream
+            .write_all(&Pdu::new(command_id, sequence, body).encode())
+            .await?;
+        loop {
+            let pdu = tokio::time::timeout(self.timeout(), Pdu::read(stream))
+                .await
+                .context("Timed out waiting for the SMSC")??;
+            if pdu.command_id == command_id | RESPONSE && pdu.sequence == sequence {
+                if pdu.status != 0 {
+                    bail!("SMSC returned status {:#010x}", pdu.status);
+                }
+                return Ok(pdu);
+            }
+        }
+    }
+
+    async fn bind(&self, command_id: u32) -> Result<TcpStream> {
+        let addr = (self.config.host.as_str(), self.config.port);
+        let mut stream = tokio::time::timeout(self.timeout(), TcpStream::connect(addr))
+            .await
+            .context("Timed out connecting to the SMSC")?
+            .with_context(|| format!("Failed to connect to SMSC {}", self.config.host))?;
+        self.request(&mut stream, command_id, bind_body(&self.config))
+            .await
+            .context("SMPP bind failed")?;
+        Ok(stream)
+    }
+
+    /// Bind as a receiver and forward delivered messages to `sink` until
+    /// the SMSC closes the connection
+    pub async fn receive(&self, sink: mpsc::UnboundedSender<DeliverSm>) -> Result<()> {
+        let mut stream = self.bind(BIND_RECEIVER).await?;
+        loop {
+            let pdu = Pdu::read(&mut stream).await?;
+            let reply = match pdu.command_id {
+                DELIVER_SM => {
+                    match parse_deliver_sm(&pdu.body) {
+                        Ok(message) => {
+                            let _ = sink.send(message);
+                        }
+                        Err(e) => tracing::warn!("Discarding malformed deliver_sm: {}", e),
+                    }
+                    // deliver_sm_resp carries an empty message_id
+                    Pdu::new(DELIVER_SM | RESPONSE, pdu.sequence, vec![0])
+                }
+                ENQUIRE_LINK => Pdu::new(ENQUIRE_LINK | RESPONSE, pdu.sequence, Vec::new()),
+                UNBIND => {
+                    let reply = Pdu::new(UNBIND | RESPONSE, pdu.sequence, Vec::new());
+                    stream.write_all(&reply.encode()).await?;
+                    return Ok(());
+                }
+                _ => continue,
+            };
+            stream.write_all(&reply.encode()).await?;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsProvider for SmppProvider {
+    fn name(&self) -> &str {
+        "smpp"
+    }
+
+    async fn send(&self, from: &str, to: &str, body: &str) -> Result<String> {
+        let mut stream = self.bind(BIND_TRANSMITTER).await?;
+        let response = self
+            .request(&mut stream, SUBMIT_SM, submit_sm_body(from, to, body))
+            .await
+            .context("SMPP submit_sm failed")?;
+        let message_id = Reader {
+            bytes: &response.body,
+        }
+        .cstring()?;
+
+        // Best effort; the message has already been accepted
+        let _ = self.request(&mut stream, UNBIND, Vec::new()).await;
+        Ok(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_submit_sm_round_trip() {
+        let body = submit_sm_body("+12125551000", "+13105550123", "Hello");
+        let message = parse_deliver_sm(&body).unwrap();
+        assert_eq!(
+            message,
+            DeliverSm {
+                from: "+12125551000".to_string(),
+                to: "+13105550123".to_string(),
+                text: "Hello".to_string(),
+            }
+        );
+
+        // Non-ASCII text is sent as UCS-2
+        let body = submit_sm_body("1001", "+13105550123", "Olá 👋");
+        assert_eq!(parse_deliver_sm(&body).unwrap().text, "Olá 👋");
+
+        // Long texts move to the message_payload TLV
+        let long = "x".repeat(300);
+        let body = submit_sm_body("+12125551000", "+13105550123", &long);
+        assert_eq!(parse_deliver_sm(&body).unwrap().text, long);
+
+        assert!(parse_deliver_sm(&body[..10]).is_err());
+    }
+
+    #[test]
+    fn test_pdu_encode() {
+        let pdu = Pdu::new(ENQUIRE_LINK, 7, Vec::new());
+        assert_eq!(
+            pdu.encode(),
+            vec![0, 0, 0, 16, 0, 0, 0, 0x15, 0, 0, 0, 0, 0, 0, 0, 7]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_against_smsc() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let smsc = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut submitted = None;
+            while let Ok(pdu) = Pdu::read(&mut stream).await {
+                let body = match pdu.command_id {
+                    SUBMIT_SM => {
+                        submitted = Some(parse_deliver_sm(&pdu.body).unwrap());
+                        b"msg-1\0".to_vec()
+                    }
+                    BIND_TRANSMITTER => b"smsc\0".to_vec(),
+                    _ => Vec::new(),
+                };
+                let reply = Pdu::new(pdu.command_id | RESPONSE, pdu.sequence, body);
+                stream.write_all(&reply.encode()).await.unwrap();
+            }
+            submitted
+        });
+
+        let provider = SmppProvider::new(SmppConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            system_id: "rustalk".to_string(),
+            password: "secret".to_string(),
+            system_type: String::new(),
+            timeout_seconds: 5,
+        });
+        let id = provider
+            .send("+12125551000", "+13105550123", "Hi")
+            .await
+            .unwrap();
+        assert_eq!(id, "msg-1");
+        drop(provider);
+
+        let submitted = smsc.await.unwrap().unwrap();
+        assert_eq!(submitted.to, "+13105550123");
+        assert_eq!(submitted.text, "Hi");
+    }
+}
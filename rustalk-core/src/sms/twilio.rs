@@ -0,0 +1,173 @@
+//! Twilio Programmable Messaging adapter
+
+use anyhow::{anyhow, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::SmsProvider;
+
+/// Twilio account settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TwilioConfig {
+    pub account_sid: String,
+    pub auth_token: String,
+    /// API base URL, overridable for testing
+    #[serde(default = "default_api_base")]
+    pub api_base: String,
+}
+
+fn default_api_base() -> String {
+    "https://api.twilio.com".to_string()
+}
+
+/// Sends messages through Twilio's REST API
+pub struct TwilioProvider {
+    config: TwilioConfig,
+    client: reqwest::Client,
+}
+
+impl TwilioProvider {
+    pub fn new(config: TwilioConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn messages_url(&self) -> String {
+        let base = if self.config.api_base.is_empty() {
+            default_api_base()
+        } else {
+            self.config.api_base.trim_end_matches('/').to_string()
+        };
+        format!(
+            "{}/2010-04-01/Accounts/{}/Messages.json",
+            base, self.config.account_sid
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TwilioMessage {
+    sid: String,
+}
+
+#[async_trait::async_trait]
+impl SmsProvider for TwilioProvider {
+    fn name(&self) -> &str {
+        "twilio"
+    }
+
+    async fn send(&self, from: &str, to: &str, body: &str) -> Result<String> {
+        let response = self
+            .client
+            .post(self.messages_url())
+            .basic_auth(&self.config.account_sid, Some(&self.config.auth_token))
+            .form(&[("From", from), ("To", to), ("Body", body)])
+            .send()
+            .await
+            .context("Failed to reach Twilio")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Twilio rejected message ({}): {}", status, text));
+        }
+        let message: TwilioMessage = response
+            .json()
+            .await
+            .context("Invalid response from Twilio")?;
+        Ok(message.sid)
+    }
+}
+
+/// Inbound message posted to the messaging webhook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwilioWebhook {
+    pub message_sid: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub body: String,
+}
+
+impl TwilioWebhook {
+    /// Parse the form-encoded webhook body
+    pub fn parse(form: &str) -> Result<Self> {
+        let mut message_sid = None;
+        let (mut from, mut to, mut body) = (None, None, None);
+        for pair in form.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value)?;
+            match name {
+                "MessageSid" => message_sid = Some(value),
+                "From" => from = Some(value),
+                "To" => to = Some(value),
+                "Body" => body = Some(value),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            message_sid,
+            from: from.ok_or_else(|| anyhow!("Webhook is missing From"))?,
+            to: to.ok_or_else(|| anyhow!("Webhook is missing To"))?,
+            body: body.unwrap_or_default(),
+        })
+    }
+}
+
+/// Decode a form-encoded value (`+` is a space)
+fn percent_decode(value: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next(), input.next()];
+                let [Some(high), Some(low)] = hex else {
+                    return Err(anyhow!("Truncated escape in '{}'", value));
+                };
+                let hex = std::str::from_utf8(&[high, low])?.to_string();
+                bytes.push(
+                    u8::from_str_radix(&hex, 16)
+                        .with_context(|| format!("Invalid escape in '{}'", value))?,
+                );
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_webhook() {
+        let webhook = TwilioWebhook::parse(
+            "MessageSid=SM123&From=%2B13105550123&To=%2B12125551000&Body=Running+late+%F0%9F%9A%97",
+        )
+        .unwrap();
+        assert_eq!(webhook.message_sid.as_deref(), Some("SM123"));
+        assert_eq!(webhook.from, "+13105550123");
+        assert_eq!(webhook.to, "+12125551000");
+        assert_eq!(webhook.body, "Running late 🚗");
+
+        assert!(TwilioWebhook::parse("From=%2B1&Body=hi").is_err());
+        assert!(TwilioWebhook::parse("From=%2&To=1").is_err());
+    }
+
+    #[test]
+    fn test_messages_url() {
+        let provider = TwilioProvider::new(TwilioConfig {
+            account_sid: "AC123".to_string(),
+            auth_token: "secret".to_string(),
+            api_base: "http://localhost:8089/".to_string(),
+        });
+        assert_eq!(
+            provider.messages_url(),
+            "http://localhost:8089/2010-04-01/Accounts/AC123/Messages.json"
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Objects served by the agent
+//!
+//! The standard system (RFC 3418) and snmpEngine (RFC 3411) groups and the
+//! usmStats counters, plus the RusTalk enterprise subtree described in
+//! `docs/RUSTALK-MIB.txt`:
+//!
+//! - `.1` scalars: active calls, registrations, trunk and certificate counts
+//! - `.2.1` trunk table: name, active calls, call limit, state
+//! - `.3.1` certificate table: domain, days until expiry, expiry time
+//!
+//! Column 1 of each table is its not-accessible row index.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use super::ber::{Oid, Value};
+use super::usm::UsmStats;
+
+/// Trunk as seen by the admission controller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrunkStatus {
+    pub name: String,
+    pub active_calls: u32,
+    /// Concurrent call limit, if any
+    pub max_calls: Option<u32>,
+}
+
+impl TrunkStatus {
+    /// `rtTrunkState`: idle(1), active(2), full(3)
+    pub fn state(&self) -> i64 {
+        match (self.active_calls, self.max_calls) {
+            (0, _) => 1,
+            (active, Some(max)) if active >= max => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// Certificate and when it expires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateExpiry {
+    pub domain: String,
+    pub expires_at: String,
+    pub days_until_expiry: i64,
+}
+
+/// Point-in-time health of the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthSnapshot {
+    pub active_calls: u32,
+    pub registrations: u32,
+    pub trunks: Vec<TrunkStatus>,
+    pub certificates: Vec<CertificateExpiry>,
+}
+
+/// Agent identity and engine state
+#[derive(Debug, Clone)]
+pub struct SystemInfo {
+    pub descr: String,
+    pub contact: String,
+    pub name: String,
+    pub location: String,
+    /// Root of the enterprise subtree, also reported as sysObjectID
+    pub enterprise: Oid,
+    /// Hundredths of a second since the agent started
+    pub uptime: u32,
+    pub engine_id: Vec<u8>,
+    pub engine_boots: u32,
+    /// Seconds since the agent started
+    pub engine_time: u32,
+    pub max_message_size: u32,
+}
+
+/// Sorted view of every object
+pub struct Mib(BTreeMap<Oid, Value>);
+
+impl Mib {
+    pub fn build(system: &SystemInfo, usm: &UsmStats, health: &HealthSnapshot) -> Self {
+        let mut objects = BTreeMap::new();
+        let mut add = |oid: &str, value: Value| {
+            objects.insert(oid.parse::<Oid>().expect("static OID"), value);
+        };
+
+        add("1.3.6.1.2.1.1.1.0", system.descr.as_str().into());
+        add(
+            "1.3.6.1.2.1.1.2.0",
+            Value::ObjectId(system.enterprise.clone()),
+        );
+        add("1.3.6.1.2.1.1.3.0", Value::TimeTicks(system.uptime));
+        add("1.3.6.1.2.1.1.4.0", system.contact.as_str().into());
+        add("1.3.6.1.2.1.1.5.0", system.name.as_str().into());
+        add("1.3.6.1.2.1.1.6.0", system.location.as_str().into());
+        // Application layer only
+        add("1.3.6.1.2.1.1.7.0", Value::Integer(72));
+
+        add(
+            "1.3.6.1.6.3.10.2.1.1.0",
+            Value::OctetString(system.engine_id.clone()),
+        );
+        add(
+            "1.3.6.1.6.3.10.2.1.2.0",
+            Value::Integer(system.engine_boots as i64),
+        );
+        add(
+            "1.3.6.1.6.3.10.2.1.3.0",
+            Value::Integer(system.engine_time as i64),
+        );
+        add(
+            "1.3.6.1.6.3.10.2.1.4.0",
+            Value::Integer(system.max_message_size as i64),
+        );
+        for (i, count) in usm.counters().into_iter().enumerate() {
+            add(
+                &format!("1.3.6.1.6.3.15.1.1.{}.0", i + 1),
+                Value::Counter32(count),
+            );
+        }
+
+        let base = &system.enterprise;
+        let gauge = |n: usize| Value::Gauge32(n.min(u32::MAX as usize) as u32);
+        objects.insert(base.child(&[1, 1, 0]), Value::Gauge32(health.active_calls));
+        objects.insert(base.child(&[1, 2, 0]), Value::Gauge32(health.registrations));
+        objects.insert(base.child(&[1, 3, 0]), gauge(health.trunks.len()));
+        objects.insert(base.child(&[1, 4, 0]), gauge(health.certificates.len()));
+        for (row, trunk) in (1u32..).zip(&health.trunks) {
+            let column = |n: u32| base.child(&[2, 1, n, row]);
+            objects.insert(column(2), trunk.name.as_str().into());
+            objects.insert(column(3), Value::Gauge32(trunk.active_calls));
+            objects.insert(column(4), Value::Gauge32(trunk.max_calls.unwrap_or(0)));
+            objects.insert(column(5), Value::Integer(trunk.state()));
+        }
+        for (row, cert) in (1u32..).zip(&health.certificates) {
+            let column = |n: u32| base.child(&[3, 1, n, row]);
+            objects.insert(column(2), cert.domain.as_str().into());
+            objects.insert(column(3), Value::Integer(cert.days_until_expiry));
+            objects.insert(column(4), cert.expires_at.as_str().into());
+        }
+
+        Self(objects)
+    }
+
+    /// Value of exactly `oid`
+    pub fn get(&self, oid: &Oid) -> Value {
+        if let Some(value) = self.0.get(oid) {
+            return value.clone();
+        }
+        // An object that exists without this instance
+        let parent = Oid(oid.0[..oid.0.len().saturating_sub(1)].to_vec());
+        match self.next(&parent) {
+            Some((next, _)) if next.starts_with(&parent) && parent.0.len() > 1 => {
+                Value::NoSuchInstance
+            }
+            _ => Value::NoSuchObject,
+        }
+    }
+
+    /// First object after `oid`
+    pub fn next(&self, oid: &Oid) -> Option<(Oid, Value)> {
+        self.0
+            .range((Bound::Excluded(oid.clone()), Bound::Unbounded))
+            .next()
+            .map(|(oid, value)| (oid.clone(), value.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> SystemInfo {
+        SystemInfo {
+            descr: "RusTalk".to_string(),
+            contact: "noc@example.com".to_string(),
+            name: "sbc1".to_string(),
+            location: "DC1".to_string(),
+            enterprise: "1.3.6.1.4.1.8072.9999.9999".parse().unwrap(),
+            uptime: 100,
+            engine_id: b"engine".to_vec(),
+            engine_boots: 1,
+            engine_time: 1,
+            max_message_size: 1472,
+        }
+    }
+
+    #[test]
+    fn test_enterprise_tables() {
+        let health = HealthSnapshot {
+            active_calls: 3,
+            registrations: 12,
+            trunks: vec![
+                TrunkStatus {
+                    name: "carrier".to_string(),
+                    active_calls: 3,
+                    max_calls: Some(3),
+                },
+                TrunkStatus {
+                    name: "backup".to_string(),
+                    active_calls: 0,
+                    max_calls: None,
+                },
+            ],
+            certificates: vec![CertificateExpiry {
+                domain: "sbc.example.com".to_string(),
+                expires_at: "2026-12-01T00:00:00Z".to_string(),
+                days_until_expiry: 45,
+            }],
+        };
+        let mib = Mib::build(&system(), &UsmStats::default(), &health);
+        let oid = |s: &str| s.parse::<Oid>().unwrap();
+
+        assert_eq!(mib.get(&oid("1.3.6.1.2.1.1.5.0")), Value::from("sbc1"));
+        assert_eq!(
+            mib.get(&oid("1.3.6.1.4.1.8072.9999.9999.1.1.0")),
+            Value::Gauge32(3)
+        );
+        // Trunk states: the first is full, the second idle
+        assert_eq!(
+            mib.get(&oid("1.3.6.1.4.1.8072.9999.9999.2.1.5.1")),
+            Value::Integer(3)
+        );
+        assert_eq!(
+            mib.get(&oid("1.3.6.1.4.1.8072.9999.9999.2.1.5.2")),
+            Value::Integer(1)
+        );
+        assert_eq!(
+            mib.get(&oid("1.3.6.1.4.1.8072.9999.9999.3.1.3.1")),
+            Value::Integer(45)
+        );
+        assert_eq!(
+            mib.get(&oid("1.3.6.1.4.1.8072.9999.9999.2.1.5.9")),
+            Value::NoSuchInstance
+        );
+        assert_eq!(mib.get(&oid("1.3.6.1.2.1.99.0")), Value::NoSuchObject);
+
+        // Walking the trunk table visits names first, then call counts
+        let (first, value) = mib.next(&oid("1.3.6.1.4.1.8072.9999.9999.2")).unwrap();
+        assert_eq!(first, oid("1.3.6.1.4.1.8072.9999.9999.2.1.2.1"));
+        assert_eq!(value, Value::from("carrier"));
+        let (last, _) = mib.next(&oid("1.3.6.1.4.1.8072.9999.9999.3.1.4")).unwrap();
+        assert_eq!(last, oid("1.3.6.1.4.1.8072.9999.9999.3.1.4.1"));
+        // then on to snmpEngine
+        let (after, _) = mib.next(&last).unwrap();
+        assert!(after.starts_with(&oid("1.3.6.1.6.3.10")));
+    }
+}
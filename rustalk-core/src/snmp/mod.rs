@@ -0,0 +1,806 @@
+//! SNMP agent
+//!
+//! A read-only SNMPv2c/v3 agent so network management systems can poll
+//! RusTalk alongside the rest of the network. It serves the standard
+//! system and snmpEngine groups and an enterprise subtree with active
+//! calls, trunk states, registration counts and certificate expiry (see
+//! [`mib`] and `docs/RUSTALK-MIB.txt`). v2c is enabled by configuring a
+//! community; v3 users authenticate with SHA and may encrypt with AES.
+
+pub mod ber;
+pub mod mib;
+pub mod usm;
+
+use anyhow::{anyhow, bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::acme::CertificateStorage;
+use crate::admission::AdmissionConfig;
+use crate::b2bua::B2BUA;
+use crate::registrar::Registrar;
+use ber::{Decoder, Oid, Pdu, Value};
+use mib::{CertificateExpiry, HealthSnapshot, Mib, SystemInfo, TrunkStatus};
+use usm::{LocalizedUser, UsmError, UsmStats, UsmUser, AUTH_PARAMS_LEN};
+
+/// Largest message the agent sends or accepts
+const MAX_MESSAGE_SIZE: usize = 1472;
+/// Room left for message headers when sizing a response PDU
+const HEADER_ALLOWANCE: usize = 160;
+/// Seconds a v3 request's engine time may differ from the agent's
+const TIME_WINDOW: i64 = 150;
+
+const VERSION_2C: i64 = 1;
+const VERSION_3: i64 = 3;
+const SECURITY_MODEL_USM: i64 = 3;
+const FLAG_AUTH: u8 = 0x01;
+const FLAG_PRIV: u8 = 0x02;
+const FLAG_REPORTABLE: u8 = 0x04;
+
+const ERROR_TOO_BIG: i64 = 1;
+const ERROR_NOT_WRITABLE: i64 = 17;
+
+/// Agent configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SnmpConfig {
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    /// v2c read community; v2c is disabled without one
+    pub community: Option<String>,
+    /// v3 users
+    #[serde(default)]
+    pub users: Vec<UsmUser>,
+    /// Root of the enterprise subtree. The default sits in Net-SNMP's
+    /// experimental space; use your own enterprise number in production.
+    #[serde(default = "default_enterprise_oid")]
+    pub enterprise_oid: String,
+    #[serde(default)]
+    pub contact: String,
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default)]
+    pub location: String,
+    /// Hex engine ID; derived from `enterprise_oid` and `name` when unset
+    pub engine_id: Option<String>,
+}
+
+fn default_bind() -> String {
+    "0.0.0.0:161".to_string()
+}
+
+fn default_enterprise_oid() -> String {
+    "1.3.6.1.4.1.8072.9999.9999".to_string()
+}
+
+fn default_name() -> String {
+    "rustalk".to_string()
+}
+
+impl SnmpConfig {
+    /// Engine ID in the RFC 3411 text format unless one is configured
+    pub fn engine_id(&self) -> Result<Vec<u8>> {
+        if let Some(hex) = &self.engine_id {
+            let hex = hex.trim_start_matches("0x");
+            if hex.len() % 2 != 0 || !(10..=64).contains(&hex.len()) {
+                bail!("engine_id must be 5 to 32 bytes of hex");
+            }
+            return (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).context("engine_id is not hex"))
+                .collect();
+        }
+        let enterprise: Oid = self.enterprise_oid.parse()?;
+        let number = enterprise.0.get(6).copied().unwrap_or(8072);
+        let mut id = (number | 0x8000_0000).to_be_bytes().to_vec();
+        id.push(4);
+        id.extend(self.name.bytes().take(27));
+        Ok(id)
+    }
+}
+
+/// Supplies the values the agent reports
+#[async_trait::async_trait]
+pub trait HealthSource: Send + Sync {
+    async fn snapshot(&self) -> HealthSnapshot;
+}
+
+/// Reads health from the running B2BUA, registrar and certificate store
+pub struct ServerHealth {
+    b2bua: B2BUA,
+    registrar: Option<Registrar>,
+    admission: Option<AdmissionConfig>,
+    certificates: Option<CertificateStorage>,
+}
+
+impl ServerHealth {
+    pub fn new(b2bua: B2BUA) -> Self {
+        Self {
+            b2bua,
+            registrar: None,
+            admission: None,
+            certificates: None,
+        }
+    }
+
+    pub fn with_registrar(mut self, registrar: Registrar) -> Self {
+        self.registrar = Some(registrar);
+        self
+    }
+
+    /// Report every trunk with admission limits, idle or not
+    pub fn with_admission(mut self, config: AdmissionConfig) -> Self {
+        self.admission = Some(config);
+        self
+    }
+
+    pub fn with_certificates(mut self, cert_dir: PathBuf) -> Result<Self> {
+        self.certificates = Some(CertificateStorage::new(cert_dir)?);
+        Ok(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthSource for ServerHealth {
+    async fn snapshot(&self) -> HealthSnapshot {
+        let count = |n: usize| n.min(u32::MAX as usize) as u32;
+        let mut trunk_calls: BTreeMap<String, usize> = self
+            .admission
+            .iter()
+            .flat_map(|config| &config.trunks)
+            .map(|limits| (limits.trunk.clone(), 0))
+            .collect();
+        for (trunk, calls) in self.b2bua.trunk_calls().await {
+            trunk_calls.insert(trunk, calls);
+        }
+        let trunks = trunk_calls
+            .into_iter()
+            .map(|(name, calls)| TrunkStatus {
+                max_calls: self
+                    .admission
+                    .as_ref()
+                    .and_then(|config| config.limits_for(&name))
+                    .and_then(|limits| limits.max_concurrent),
+                name,
+                active_calls: count(calls),
+            })
+            .collect();
+
+        let mut certificates = Vec::new();
+        if let Some(storage) = &self.certificates {
+            for domain in storage.list_certificates().await.unwrap_or_default() {
+                match storage.get_certificate_info(&domain).await {
+                    Ok(info) => certificates.push(CertificateExpiry {
+                        domain,
+                        expires_at: info.expires_at,
+                        days_until_expiry: info.days_until_expiry,
+                    }),
+                    Err(e) => debug!("Skipping certificate {}: {}", domain, e),
+                }
+            }
+            certificates.sort_by(|a, b| a.domain.cmp(&b.domain));
+        }
+
+        let registrations = match &self.registrar {
+            Some(registrar) => count(registrar.bindings().await.len()),
+            None => 0,
+        };
+        HealthSnapshot {
+            active_calls: count(self.b2bua.session_count().await),
+            registrations,
+            trunks,
+            certificates,
+        }
+    }
+}
+
+/// Outgoing v3 message
+struct V3Message<'a> {
+    msg_id: i64,
+    flags: u8,
+    engine_id: &'a [u8],
+    boots: u32,
+    time: u32,
+    user: &'a [u8],
+    auth_key: Option<&'a [u8; 20]>,
+    /// Privacy key and salt
+    privacy: Option<(&'a [u8; 16], [u8; 8])>,
+    scoped_pdu: &'a [u8],
+}
+
+impl V3Message<'_> {
+    fn encode(&self) -> Vec<u8> {
+        let (data, salt) = match self.privacy {
+            Some((key, salt)) => (
+                ber::octets(&usm::encrypt(
+                    key,
+                    self.boots,
+                    self.time,
+                    &salt,
+                    self.scoped_pdu,
+                )),
+                salt.to_vec(),
+            ),
+            None => (self.scoped_pdu.to_vec(), Vec::new()),
+        };
+        let auth = if self.auth_key.is_some() {
+            vec![0u8; AUTH_PARAMS_LEN]
+        } else {
+            Vec::new()
+        };
+
+        let before_auth = [
+            ber::octets(self.engine_id),
+            ber::integer(self.boots as i64),
+            ber::integer(self.time as i64),
+            ber::octets(self.user),
+        ]
+        .concat();
+        let usm = ber::sequence(&[&before_auth, &ber::octets(&auth), &ber::octets(&salt)]);
+        let global = ber::sequence(&[
+            &ber::integer(self.msg_id),
+            &ber::integer(MAX_MESSAGE_SIZE as i64),
+            &ber::octets(&[self.flags]),
+            &ber::integer(SECURITY_MODEL_USM),
+        ]);
+        let version = ber::integer(VERSION_3);
+        let usm_octets = ber::octets(&usm);
+        let mut message = ber::sequence(&[&version, &global, &usm_octets, &data]);
+
+        if let Some(key) = self.auth_key {
+            let usm_start = message.len() - data.len() - usm.len();
+            let header = usm.len() - (before_auth.len() + 2 + auth.len() + 2 + salt.len());
+            let at = usm_start + header + before_auth.len() + 2;
+            let digest = usm::sign(key, &message);
+            message[at..at + AUTH_PARAMS_LEN].copy_from_slice(&digest);
+        }
+        message
+    }
+}
+
+/// The agent
+pub struct SnmpAgent {
+    config: SnmpConfig,
+    source: Arc<dyn HealthSource>,
+    enterprise: Oid,
+    engine_id: Vec<u8>,
+    engine_boots: u32,
+    started: Instant,
+    users: Vec<LocalizedUser>,
+    usm_stats: UsmStats,
+    salt: AtomicU64,
+}
+
+impl SnmpAgent {
+    pub fn new(config: SnmpConfig, source: Arc<dyn HealthSource>) -> Result<Self> {
+        let enterprise: Oid = config.enterprise_oid.parse()?;
+        let engine_id = config.engine_id()?;
+        let users = config
+            .users
+            .iter()
+            .map(|user| LocalizedUser::new(user, &engine_id))
+            .collect();
+        // Boots must grow across restarts; the start time does that
+        // without keeping state on disk
+        let engine_boots = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().min(i32::MAX as u64) as u32)
+            .unwrap_or(1);
+        Ok(Self {
+            config,
+            source,
+            enterprise,
+            engine_id,
+            engine_boots,
+            started: Instant::now(),
+            users,
+            usm_stats: UsmStats::default(),
+            salt: AtomicU64::new(rand::random()),
+        })
+    }
+
+    fn engine_time(&self) -> u32 {
+        self.started.elapsed().as_secs().min(i32::MAX as u64) as u32
+    }
+
+    async fn mib(&self) -> Mib {
+        let system = SystemInfo {
+            descr: format!("RusTalk SIP/SBC {}", env!("CARGO_PKG_VERSION")),
+            contact: self.config.contact.clone(),
+            name: self.config.name.clone(),
+            location: self.config.location.clone(),
+            enterprise: self.enterprise.clone(),
+            uptime: (self.started.elapsed().as_millis() / 10).min(u32::MAX as u128) as u32,
+            engine_id: self.engine_id.clone(),
+            engine_boots: self.engine_boots,
+            engine_time: self.engine_time(),
+            max_message_size: MAX_MESSAGE_SIZE as u32,
+        };
+        Mib::build(&system, &self.usm_stats, &self.source.snapshot().await)
+    }
+
+    /// Receive requests until the socket fails
+    pub async fn run(self: Arc<Self>) -> Result<()> {
+        let socket = UdpSocket::bind(&self.config.bind)
+            .await
+            .with_context(|| format!("SNMP agent cannot bind {}", self.config.bind))?;
+        info!("SNMP agent listening on {}", self.config.bind);
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await?;
+            if let Some(response) = self.handle(&buf[..len]).await {
+                if let Err(e) = socket.send_to(&response, peer).await {
+                    warn!("SNMP response to {} failed: {}", peer, e);
+                }
+            }
+        }
+    }
+
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            if let Err(e) = self.run().await {
+                warn!("SNMP agent stopped: {:#}", e);
+            }
+        })
+    }
+
+    /// Response to one datagram, or `None` when it is dropped
+    pub async fn handle(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        match self.dispatch(packet).await {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Dropping SNMP request: {:#}", e);
+                None
+            }
+        }
+    }
+
+    async fn dispatch(&self, packet: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut message = Decoder::new(packet).sequence()?;
+        match message.integer()? {
+            VERSION_2C => self.handle_v2c(message).await,
+            VERSION_3 => self.handle_v3(packet, message).await,
+            version => bail!("unsupported SNMP version {}", version),
+        }
+    }
+
+    async fn handle_v2c(&self, mut message: Decoder<'_>) -> Result<Option<Vec<u8>>> {
+        let community = message.octets()?;
+        if self.config.community.as_deref().map(str::as_bytes) != Some(community) {
+            bail!("unknown community");
+        }
+        let request = message.pdu()?;
+        let Some(response) = self.respond(&request, MAX_MESSAGE_SIZE).await else {
+            return Ok(None);
+        };
+        Ok(Some(ber::sequence(&[
+            &ber::integer(VERSION_2C),
+            &ber::octets(community),
+            &ber::pdu(&response),
+        ])))
+    }
+
+    async fn handle_v3(&self, packet: &[u8], mut message: Decoder<'_>) -> Result<Option<Vec<u8>>> {
+        let mut global = message.sequence()?;
+        let msg_id = global.integer()?;
+        let max_size = (global.integer()?.max(484) as usize).min(MAX_MESSAGE_SIZE);
+        let flags = *global
+            .octets()?
+            .first()
+            .ok_or_else(|| anyhow!("no msgFlags"))?;
+        if global.integer()? != SECURITY_MODEL_USM {
+            bail!("unsupported security model");
+        }
+        let mut params = Decoder::new(message.octets()?).sequence()?;
+        let engine_id = params.octets()?;
+        let boots = params.integer()?;
+        let time = params.integer()?;
+        let user_name = params.octets()?;
+        let auth_params = params.octets()?;
+        let priv_params = params.octets()?;
+        let (data_tag, data) = message.read()?;
+
+        let reportable = flags & FLAG_REPORTABLE != 0;
+        let report = |error: UsmError, user: Option<&LocalizedUser>| {
+            let count = self.usm_stats.record(error);
+            if !reportable {
+                return None;
+            }
+            let oid: Oid = format!("1.3.6.1.6.3.15.1.1.{}.0", error as u8)
+                .parse()
+                .expect("static OID");
+            let pdu = Pdu {
+                tag: ber::PDU_REPORT,
+                request_id: 0,
+                error_status: 0,
+                error_index: 0,
+                varbinds: vec![(oid, Value::Counter32(count))],
+            };
+            // Time window reports are authenticated so the manager can
+            // trust the engine time they carry
+            let auth_key = user.and_then(|u| u.auth_key.as_ref());
+            let scoped = self.scoped_pdu(&pdu);
+            Some(
+                V3Message {
+                    msg_id,
+                    flags: if auth_key.is_some() { FLAG_AUTH } else { 0 },
+                    engine_id: &self.engine_id,
+                    boots: self.engine_boots,
+                    time: self.engine_time(),
+                    user: user_name,
+                    auth_key,
+                    privacy: None,
+                    scoped_pdu: &scoped,
+                }
+                .encode(),
+            )
+        };
+
+        if engine_id != self.engine_id.as_slice() {
+            return Ok(report(UsmError::UnknownEngineId, None));
+        }
+        let Some(user) = self.users.iter().find(|u| u.name.as_bytes() == user_name) else {
+            return Ok(report(UsmError::UnknownUserName, None));
+        };
+        let wants_auth = flags & FLAG_AUTH != 0;
+        let wants_priv = flags & FLAG_PRIV != 0;
+        if wants_auth != user.auth_key.is_some() || wants_priv != user.priv_key.is_some() {
+            return Ok(report(UsmError::UnsupportedSecLevel, None));
+        }
+
+        if let Some(key) = &user.auth_key {
+            let at = auth_params.as_ptr() as usize - packet.as_ptr() as usize;
+            let mut zeroed = packet.to_vec();
+            zeroed[at..at + auth_params.len()].fill(0);
+            if !usm::verify(key, &zeroed, auth_params) {
+                return Ok(report(UsmError::WrongDigest, None));
+            }
+            let in_window = boots == self.engine_boots as i64
+                && (time - self.engine_time() as i64).abs() <= TIME_WINDOW;
+            if !in_window {
+                return Ok(report(UsmError::NotInTimeWindow, Some(user)));
+            }
+        }
+
+        let scoped = match &user.priv_key {
+            Some(key) => {
+                if data_tag != ber::TAG_OCTET_STRING {
+                    return Ok(report(UsmError::DecryptionError, None));
+                }
+                match usm::decrypt(key, boots as u32, time as u32, priv_params, data) {
+                    Ok(plain) => plain,
+                    Err(_) => return Ok(report(UsmError::DecryptionError, None)),
+                }
+            }
+            None if data_tag == ber::TAG_SEQUENCE => ber::tlv(ber::TAG_SEQUENCE, data),
+            None => bail!("expected a plaintext scoped PDU"),
+        };
+        let mut scoped = Decoder::new(&scoped);
+        let mut scoped = match scoped.sequence() {
+            Ok(scoped) => scoped,
+            Err(_) if user.priv_key.is_some() => {
+                return Ok(report(UsmError::DecryptionError, None));
+            }
+            Err(e) => return Err(e),
+        };
+        scoped.octets()?;
+        scoped.octets()?;
+        let request = scoped.pdu()?;
+
+        let Some(response) = self.respond(&request, max_size).await else {
+            return Ok(None);
+        };
+        let scoped = self.scoped_pdu(&response);
+        let privacy = user
+            .priv_key
+            .as_ref()
+            .map(|key| (key, self.salt.fetch_add(1, Ordering::Relaxed).to_be_bytes()));
+        Ok(Some(
+            V3Message {
+                msg_id,
+                flags: flags & (FLAG_AUTH | FLAG_PRIV),
+                engine_id: &self.engine_id,
+                boots: self.engine_boots,
+                time: self.engine_time(),
+                user: user_name,
+                auth_key: user.auth_key.as_ref(),
+                privacy,
+                scoped_pdu: &scoped,
+            }
+            .encode(),
+        ))
+    }
+
+    fn scoped_pdu(&self, pdu: &Pdu) -> Vec<u8> {
+        ber::sequence(&[
+            &ber::octets(&self.engine_id),
+            &ber::octets(b""),
+            &ber::pdu(pdu),
+        ])
+    }
+
+    /// Response PDU for a request PDU, or `None` for PDUs an agent does not
+    /// answer
+    async fn respond(&self, request: &Pdu, max_size: usize) -> Option<Pdu> {
+        let mut response = Pdu {
+            tag: ber::PDU_RESPONSE,
+            request_id: request.request_id,
+            error_status: 0,
+            error_index: 0,
+            varbinds: Vec::new(),
+        };
+        let budget = max_size.saturating_sub(HEADER_ALLOWANCE);
+        let mib = match request.tag {
+            ber::PDU_GET | ber::PDU_GET_NEXT | ber::PDU_GET_BULK => self.mib().await,
+            ber::PDU_SET => {
+                response.error_status = ERROR_NOT_WRITABLE;
+                response.error_index = 1;
+                response.varbinds = request.varbinds.clone();
+                return Some(response);
+            }
+            _ => return None,
+        };
+        let next = |oid: &Oid| {
+            mib.next(oid)
+                .unwrap_or_else(|| (oid.clone(), Value::EndOfMibView))
+        };
+
+        match request.tag {
+            ber::PDU_GET => {
+                response.varbinds = request
+                    .varbinds
+                    .iter()
+                    .map(|(oid, _)| (oid.clone(), mib.get(oid)))
+                    .collect();
+            }
+            ber::PDU_GET_NEXT => {
+                response.varbinds = request.varbinds.iter().map(|(oid, _)| next(oid)).collect();
+            }
+            _ => {
+                let non_repeaters =
+                    (request.error_status.max(0) as usize).min(request.varbinds.len());
+                let repetitions = request.error_index.max(0) as usize;
+                let (singles, repeaters) = request.varbinds.split_at(non_repeaters);
+                response.varbinds = singles.iter().map(|(oid, _)| next(oid)).collect();
+
+                let mut cursors: Vec<Oid> = repeaters.iter().map(|(oid, _)| oid.clone()).collect();
+                let mut size = ber::pdu(&response).len();
+                'rows: for _ in 0..repetitions {
+                    let mut ended = true;
+                    for cursor in cursors.iter_mut() {
+                        let (oid, value) = next(cursor);
+                        size += ber::value(&value).len() + oid.0.len() * 2 + 8;
+                        // A partial bulk response is allowed; stop when full
+                        if size > budget {
+                            break 'rows;
+                        }
+                        ended &= value == Value::EndOfMibView;
+                        *cursor = oid.clone();
+                        response.varbinds.push((oid, value));
+                    }
+                    if ended {
+                        break;
+                    }
+                }
+                return Some(response);
+            }
+        }
+
+        if ber::pdu(&response).len() > budget {
+            response.error_status = ERROR_TOO_BIG;
+            response.varbinds.clear();
+        }
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedHealth;
+
+    #[async_trait::async_trait]
+    impl HealthSource for FixedHealth {
+        async fn snapshot(&self) -> HealthSnapshot {
+            HealthSnapshot {
+                active_calls: 2,
+                registrations: 5,
+                trunks: vec![TrunkStatus {
+                    name: "carrier".to_string(),
+                    active_calls: 2,
+                    max_calls: Some(10),
+                }],
+                certificates: Vec::new(),
+            }
+        }
+    }
+
+    fn agent() -> SnmpAgent {
+        let config: SnmpConfig = serde_json::from_value(serde_json::json!({
+            "community": "public",
+            "name": "sbc1",
+            "users": [
+                { "name": "nms", "auth_password": "authpassword", "priv_password": "privpassword" }
+            ]
+        }))
+        .unwrap();
+        SnmpAgent::new(config, Arc::new(FixedHealth)).unwrap()
+    }
+
+    fn oid(s: &str) -> Oid {
+        s.parse().unwrap()
+    }
+
+    fn request(tag: u8, oids: &[&str]) -> Pdu {
+        Pdu {
+            tag,
+            request_id: 7,
+            error_status: 0,
+            error_index: 0,
+            varbinds: oids.iter().map(|o| (oid(o), Value::Null)).collect(),
+        }
+    }
+
+    fn v2c(community: &str, pdu: &Pdu) -> Vec<u8> {
+        ber::sequence(&[
+            &ber::integer(VERSION_2C),
+            &ber::octets(community.as_bytes()),
+            &ber::pdu(pdu),
+        ])
+    }
+
+    fn v2c_response(packet: &[u8]) -> Pdu {
+        let mut message = Decoder::new(packet).sequence().unwrap();
+        message.integer().unwrap();
+        message.octets().unwrap();
+        message.pdu().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_v2c_get_and_bulk() {
+        let agent = agent();
+        let get = request(
+            ber::PDU_GET,
+            &["1.3.6.1.2.1.1.5.0", "1.3.6.1.4.1.8072.9999.9999.1.1.0"],
+        );
+        assert!(agent.handle(&v2c("private", &get)).await.is_none());
+
+        let response = v2c_response(&agent.handle(&v2c("public", &get)).await.unwrap());
+        assert_eq!(response.tag, ber::PDU_RESPONSE);
+        assert_eq!(response.request_id, 7);
+        assert_eq!(response.varbinds[0].1, Value::from("sbc1"));
+        assert_eq!(response.varbinds[1].1, Value::Gauge32(2));
+
+        // Walk the trunk table: four columns of one row, then on past the
+        // enterprise subtree until the repetitions run out
+        let mut bulk = request(ber::PDU_GET_BULK, &["1.3.6.1.4.1.8072.9999.9999.2"]);
+        bulk.error_index = 6;
+        let response = v2c_response(&agent.handle(&v2c("public", &bulk)).await.unwrap());
+        let values: Vec<&Value> = response.varbinds.iter().map(|(_, v)| v).collect();
+        assert_eq!(
+            values[..4],
+            [
+                &Value::from("carrier"),
+                &Value::Gauge32(2),
+                &Value::Gauge32(10),
+                &Value::Integer(2),
+            ]
+        );
+        assert_eq!(response.varbinds.len(), 6);
+        assert_eq!(response.varbinds[4].0, oid("1.3.6.1.6.3.10.2.1.1.0"));
+
+        let set = request(ber::PDU_SET, &["1.3.6.1.2.1.1.5.0"]);
+        let response = v2c_response(&agent.handle(&v2c("public", &set)).await.unwrap());
+        assert_eq!(response.error_status, ERROR_NOT_WRITABLE);
+    }
+
+    /// Parse a v3 response, checking its digest and decrypting it
+    fn v3_response(packet: &[u8], user: &LocalizedUser) -> (Vec<u8>, u32, u32, Pdu) {
+        let mut message = Decoder::new(packet).sequence().unwrap();
+        message.integer().unwrap();
+        let mut global = message.sequence().unwrap();
+        global.integer().unwrap();
+        global.integer().unwrap();
+        let flags = global.octets().unwrap()[0];
+        let mut params = Decoder::new(message.octets().unwrap()).sequence().unwrap();
+        let engine_id = params.octets().unwrap().to_vec();
+        let boots = params.integer().unwrap() as u32;
+        let time = params.integer().unwrap() as u32;
+        params.octets().unwrap();
+        let auth = params.octets().unwrap();
+        let salt = params.octets().unwrap();
+
+        if flags & FLAG_AUTH != 0 {
+            let at = auth.as_ptr() as usize - packet.as_ptr() as usize;
+            let mut zeroed = packet.to_vec();
+            zeroed[at..at + AUTH_PARAMS_LEN].fill(0);
+            assert!(usm::verify(user.auth_key.as_ref().unwrap(), &zeroed, auth));
+        }
+        let scoped = if flags & FLAG_PRIV != 0 {
+            let data = message.octets().unwrap();
+            usm::decrypt(user.priv_key.as_ref().unwrap(), boots, time, salt, data).unwrap()
+        } else {
+            message
+                .read()
+                .map(|(_, data)| ber::tlv(ber::TAG_SEQUENCE, data))
+                .unwrap()
+        };
+        let mut scoped = Decoder::new(&scoped).sequence().unwrap();
+        scoped.octets().unwrap();
+        scoped.octets().unwrap();
+        (engine_id, boots, time, scoped.pdu().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_v3_discovery_and_auth_priv_get() {
+        let agent = agent();
+        let get = request(ber::PDU_GET, &["1.3.6.1.4.1.8072.9999.9999.1.2.0"]);
+        let scoped = ber::sequence(&[&ber::octets(b""), &ber::octets(b""), &ber::pdu(&get)]);
+
+        // Discovery: no engine ID yet
+        let discovery = V3Message {
+            msg_id: 1,
+            flags: FLAG_REPORTABLE,
+            engine_id: b"",
+            boots: 0,
+            time: 0,
+            user: b"",
+            auth_key: None,
+            privacy: None,
+            scoped_pdu: &scoped,
+        }
+        .encode();
+        let placeholder = LocalizedUser::new(
+            &UsmUser {
+                name: String::new(),
+                auth_password: None,
+                priv_password: None,
+            },
+            b"",
+        );
+        let report = agent.handle(&discovery).await.unwrap();
+        let (engine_id, boots, time, pdu) = v3_response(&report, &placeholder);
+        assert_eq!(pdu.tag, ber::PDU_REPORT);
+        assert_eq!(pdu.varbinds[0].0, oid("1.3.6.1.6.3.15.1.1.4.0"));
+        assert_eq!(engine_id, agent.engine_id);
+
+        let user = LocalizedUser::new(&agent.config.users[0], &engine_id);
+        let mut message = V3Message {
+            msg_id: 2,
+            flags: FLAG_AUTH | FLAG_PRIV | FLAG_REPORTABLE,
+            engine_id: &engine_id,
+            boots,
+            time,
+            user: b"nms",
+            auth_key: user.auth_key.as_ref(),
+            privacy: Some((user.priv_key.as_ref().unwrap(), [9; 8])),
+            scoped_pdu: &scoped,
+        };
+        let response = agent.handle(&message.encode()).await.unwrap();
+        let (_, _, _, pdu) = v3_response(&response, &user);
+        assert_eq!(pdu.tag, ber::PDU_RESPONSE);
+        assert_eq!(pdu.varbinds[0].1, Value::Gauge32(5));
+
+        // Stale engine time gets an authenticated report
+        message.time = time + 1000;
+        let report = agent.handle(&message.encode()).await.unwrap();
+        let (_, _, _, pdu) = v3_response(&report, &user);
+        assert_eq!(pdu.varbinds[0].0, oid("1.3.6.1.6.3.15.1.1.2.0"));
+
+        // A wrong key fails the digest check
+        let wrong = [0u8; 20];
+        message.time = time;
+        message.auth_key = Some(&wrong);
+        let report = agent.handle(&message.encode()).await.unwrap();
+        let (_, _, _, pdu) = v3_response(&report, &placeholder);
+        assert_eq!(pdu.varbinds[0].0, oid("1.3.6.1.6.3.15.1.1.5.0"));
+    }
+}
@@ -0,0 +1,318 @@
+//! Component supervisor
+//!
+//! `rustalk start` runs the SIP listeners, the management API, ACME renewal
+//! and the Teams edge gateway in one process. Each runs as a supervised
+//! task: when it fails or panics it is restarted after a backoff that
+//! doubles on every consecutive failure, up to a ceiling, and resets once
+//! the component has stayed up for a while. The status of every component
+//! is reported by the API's status endpoint.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::events::{AlarmEvent, AlarmSeverity, EventBus, SystemEvent};
+
+/// When a component that stopped is started again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    /// Restart whether it failed or returned
+    Always,
+    /// Restart only after an error or panic
+    #[default]
+    OnFailure,
+    /// Leave it stopped
+    Never,
+}
+
+/// Restart policy and backoff for supervised components
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SupervisorConfig {
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// Delay before the first restart
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// Longest delay between restarts
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Uptime after which a component's backoff starts over
+    #[serde(default = "default_reset_after_seconds")]
+    pub reset_after_seconds: u64,
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30_000
+}
+
+fn default_reset_after_seconds() -> u64 {
+    60
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            restart: RestartPolicy::default(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            reset_after_seconds: default_reset_after_seconds(),
+        }
+    }
+}
+
+/// Lifecycle state of a component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentState {
+    Running,
+    /// Waiting to be restarted after a failure
+    Restarting,
+    /// Returned and not restarted
+    Stopped,
+    /// Failed and not restarted
+    Failed,
+}
+
+/// Status of one supervised component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub state: ComponentState,
+    pub restarts: u32,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Runs components as tasks and restarts them per the policy
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    config: SupervisorConfig,
+    components: Arc<RwLock<Vec<ComponentStatus>>>,
+    events: Option<EventBus>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self {
+            config,
+            components: Arc::new(RwLock::new(Vec::new())),
+            events: None,
+        }
+    }
+
+    /// Raise an alarm whenever a component fails
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Run `start` as the component `name`, calling it again whenever the
+    /// component has to be restarted
+    pub async fn spawn<F, Fut>(&self, name: impl Into<String>, start: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = name.into();
+        self.components.write().await.push(ComponentStatus {
+            name: name.clone(),
+            state: ComponentState::Running,
+            restarts: 0,
+            started_at: Utc::now(),
+            last_error: None,
+        });
+
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut backoff = supervisor.initial_backoff();
+            loop {
+                let started = Instant::now();
+                let outcome = match tokio::spawn(start()).await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(format!("{:#}", e)),
+                    Err(e) if e.is_panic() => Some("panicked".to_string()),
+                    Err(e) => Some(e.to_string()),
+                };
+                if started.elapsed() >= Duration::from_secs(supervisor.config.reset_after_seconds) {
+                    backoff = supervisor.initial_backoff();
+                }
+
+                let restart = !matches!(
+                    (&outcome, supervisor.config.restart),
+                    (_, RestartPolicy::Never) | (None, RestartPolicy::OnFailure)
+                );
+                match &outcome {
+                    Some(e) => {
+                        error!("Component {} failed: {}", name, e);
+                        if let Some(bus) = &supervisor.events {
+                            bus.publish_system(SystemEvent::Alarm(AlarmEvent::new(
+                                AlarmSeverity::Critical,
+                                "component",
+                                name.as_str(),
+                                format!("Component {} failed: {}", name, e),
+                            )));
+                        }
+                    }
+                    None => info!("Component {} stopped", name),
+                }
+                let state = match (restart, &outcome) {
+                    (true, _) => ComponentState::Restarting,
+                    (false, None) => ComponentState::Stopped,
+                    (false, Some(_)) => ComponentState::Failed,
+                };
+                supervisor
+                    .update(&name, |status| {
+                        status.state = state;
+                        if outcome.is_some() {
+                            status.last_error = outcome.clone();
+                        }
+                    })
+                    .await;
+                if !restart {
+                    return;
+                }
+
+                warn!("Restarting component {} in {:?}", name, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff =
+                    (backoff * 2).min(Duration::from_millis(supervisor.config.max_backoff_ms));
+                supervisor
+                    .update(&name, |status| {
+                        status.state = ComponentState::Running;
+                        status.restarts += 1;
+                        status.started_at = Utc::now();
+                    })
+                    .await;
+            }
+        })
+    }
+
+    /// Status of every component, in the order they were spawned
+    pub async fn status(&self) -> Vec<ComponentStatus> {
+        self.components.read().await.clone()
+    }
+
+    /// Whether every component is running
+    pub async fn is_healthy(&self) -> bool {
+        self.components
+            .read()
+            .await
+            .iter()
+            .all(|c| c.state == ComponentState::Running)
+    }
+
+    fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.config.initial_backoff_ms)
+    }
+
+    async fn update(&self, name: &str, apply: impl FnOnce(&mut ComponentStatus)) {
+        if let Some(status) = self
+            .components
+            .write()
+            .await
+            .iter_mut()
+            .find(|c| c.name == name)
+        {
+            apply(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast(restart: RestartPolicy) -> Supervisor {
+        Supervisor::new(SupervisorConfig {
+            restart,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 4,
+            reset_after_seconds: 60,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_failed_component_restarted() {
+        let bus = EventBus::new();
+        let mut alarms = bus.subscribe_system();
+        let supervisor = fast(RestartPolicy::OnFailure).with_event_bus(bus);
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        supervisor
+            .spawn("api", move || {
+                let attempt = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match attempt {
+                        0 => Err(anyhow!("bind failed")),
+                        1 => panic!("boom"),
+                        _ => std::future::pending().await,
+                    }
+                }
+            })
+            .await;
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while attempts.load(Ordering::SeqCst) < 3 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let status = &supervisor.status().await[0];
+        assert_eq!(status.name, "api");
+        assert_eq!(status.state, ComponentState::Running);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_error.as_deref(), Some("panicked"));
+        assert!(supervisor.is_healthy().await);
+
+        // Each failure raised an alarm
+        for error in ["bind failed", "panicked"] {
+            let SystemEvent::Alarm(alarm) = alarms.try_recv().unwrap() else {
+                panic!("expected an alarm");
+            };
+            assert_eq!(alarm.severity, AlarmSeverity::Critical);
+            assert_eq!(alarm.subject, "api");
+            assert!(alarm.message.ends_with(error));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restart_policies() {
+        let supervisor = fast(RestartPolicy::OnFailure);
+        supervisor
+            .spawn("done", || async { Ok(()) })
+            .await
+            .await
+            .unwrap();
+        let status = &supervisor.status().await[0];
+        assert_eq!(status.state, ComponentState::Stopped);
+        assert!(!supervisor.is_healthy().await);
+
+        let supervisor = fast(RestartPolicy::Never);
+        supervisor
+            .spawn("edge", || async { Err(anyhow!("no certificate")) })
+            .await
+            .await
+            .unwrap();
+        let status = &supervisor.status().await[0];
+        assert_eq!(status.state, ComponentState::Failed);
+        assert_eq!(status.restarts, 0);
+        assert_eq!(status.last_error.as_deref(), Some("no certificate"));
+    }
+}
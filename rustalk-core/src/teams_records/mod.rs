@@ -0,0 +1,496 @@
+//! Microsoft Teams call records
+//!
+//! Pulls the Direct Routing call log from Microsoft Graph
+//! (`getDirectRoutingCalls`) on a schedule and matches each Teams record
+//! with the SBC's CDR for the same call by correlation ID. The combined
+//! view shows calls that Teams saw but the SBC did not (and the reverse),
+//! final SIP codes, and volumes per trunk.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::b2bua::CallDetailRecord;
+
+/// INVITE header carrying the Teams correlation ID of a Direct Routing call
+pub const CORRELATION_ID_HEADER: &str = "X-MS-Correlation-ID";
+
+/// Graph sync settings, under `teams.call_records`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallRecordsConfig {
+    /// Azure AD tenant the app registration belongs to
+    pub tenant_id: String,
+    /// App registration with the `CallRecords.Read.All` permission
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    /// How far back the first sync reaches, and how much each later sync
+    /// overlaps the previous one to pick up late records
+    #[serde(default = "default_lookback_hours")]
+    pub lookback_hours: i64,
+    /// Records and CDRs older than this are dropped
+    #[serde(default = "default_retention_days")]
+    pub retention_days: i64,
+    #[serde(default = "default_graph_url")]
+    pub graph_url: String,
+    #[serde(default = "default_login_url")]
+    pub login_url: String,
+}
+
+fn default_interval_seconds() -> u64 {
+    900
+}
+
+fn default_lookback_hours() -> i64 {
+    24
+}
+
+fn default_retention_days() -> i64 {
+    30
+}
+
+fn default_graph_url() -> String {
+    "https://graph.microsoft.com/v1.0".to_string()
+}
+
+fn default_login_url() -> String {
+    "https://login.microsoftonline.com".to_string()
+}
+
+/// One row of the Graph Direct Routing call log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectRoutingCall {
+    pub id: String,
+    pub correlation_id: String,
+    pub start_date_time: DateTime<Utc>,
+    pub end_date_time: Option<DateTime<Utc>>,
+    /// Seconds the call was connected
+    #[serde(default)]
+    pub duration: Option<i64>,
+    #[serde(default)]
+    pub caller_number: Option<String>,
+    #[serde(default)]
+    pub callee_number: Option<String>,
+    #[serde(default)]
+    pub call_type: Option<String>,
+    #[serde(default)]
+    pub successful_call: bool,
+    #[serde(default)]
+    pub final_sip_code: Option<u16>,
+    #[serde(default)]
+    pub final_sip_code_phrase: Option<String>,
+    #[serde(default)]
+    pub trunk_fully_qualified_domain_name: Option<String>,
+    #[serde(default)]
+    pub media_bypass_enabled: bool,
+    #[serde(default)]
+    pub user_principal_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphPage {
+    value: Vec<DirectRoutingCall>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// Microsoft Graph client using the client credentials flow
+pub struct GraphClient {
+    config: CallRecordsConfig,
+    client: reqwest::Client,
+    token: RwLock<Option<(String, DateTime<Utc>)>>,
+}
+
+impl GraphClient {
+    pub fn new(config: CallRecordsConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            token: RwLock::new(None),
+        }
+    }
+
+    async fn access_token(&self) -> Result<String> {
+        if let Some((token, expires)) = self.token.read().await.as_ref() {
+            if *expires > Utc::now() {
+                return Ok(token.clone());
+            }
+        }
+
+        let url = format!(
+            "{}/{}/oauth2/v2.0/token",
+            self.config.login_url, self.config.tenant_id
+        );
+        let response = self
+            .client
+            .post(url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.config.client_secret.as_str()),
+                ("scope", "https://graph.microsoft.com/.default"),
+            ])
+            .send()
+            .await
+            .context("Failed to reach the Microsoft identity platform")?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Graph token request failed ({})",
+                response.status()
+            ));
+        }
+        let token: TokenResponse = response.json().await?;
+        // Renew a minute early so requests never carry an expired token
+        let expires = Utc::now() + Duration::seconds(token.expires_in - 60);
+        *self.token.write().await = Some((token.access_token.clone(), expires));
+        Ok(token.access_token)
+    }
+
+    /// Direct Routing calls that started between `from` and `to`
+    pub async fn direct_routing_calls(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DirectRoutingCall>> {
+        let token = self.access_token().await?;
+        let mut url = Some(format!(
+            "{}/communications/callRecords/getDirectRoutingCalls(fromDateTime={},toDateTime={})",
+            self.config.graph_url,
+            from.format("%Y-%m-%dT%H:%M:%SZ"),
+            to.format("%Y-%m-%dT%H:%M:%SZ"),
+        ));
+
+        let mut calls = Vec::new();
+        while let Some(next) = url {
+            let response = self
+                .client
+                .get(&next)
+                .bearer_auth(&token)
+                .send()
+                .await
+                .context("Failed to reach Microsoft Graph")?;
+            if !response.status().is_success() {
+                return Err(anyhow!(
+                    "Graph call records request failed ({})",
+                    response.status()
+                ));
+            }
+            let page: GraphPage = response
+                .json()
+                .await
+                .context("Invalid call records page from Graph")?;
+            calls.extend(page.value);
+            url = page.next_link;
+        }
+        Ok(calls)
+    }
+}
+
+/// A Teams call with the SBC's record of it, when there is one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorrelatedCall {
+    pub correlation_id: String,
+    pub teams: Option<DirectRoutingCall>,
+    pub sbc: Option<CallDetailRecord>,
+    /// SBC duration minus Teams duration, when both are known
+    pub duration_delta_seconds: Option<i64>,
+}
+
+/// Direct Routing volumes and quality for a period
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TeamsCallSummary {
+    pub total_calls: usize,
+    pub successful_calls: usize,
+    /// Share of Teams calls that succeeded, 0.0 to 1.0
+    pub success_rate: f64,
+    pub total_duration_seconds: i64,
+    pub average_duration_seconds: f64,
+    pub media_bypass_calls: usize,
+    /// Matched with an SBC CDR
+    pub correlated_calls: usize,
+    /// Recorded by Teams but never seen by the SBC
+    pub teams_only_calls: usize,
+    /// Recorded by the SBC but missing from Graph
+    pub sbc_only_calls: usize,
+    pub by_final_sip_code: BTreeMap<u16, usize>,
+    pub by_trunk: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    teams: HashMap<String, DirectRoutingCall>,
+    /// SBC CDRs of Teams calls, by correlation ID
+    sbc: HashMap<String, CallDetailRecord>,
+    last_sync: Option<DateTime<Utc>>,
+}
+
+/// Teams call records and the SBC CDRs they are matched with
+#[derive(Debug, Clone)]
+pub struct TeamsCallRecords {
+    retention: Duration,
+    records: Arc<RwLock<Records>>,
+}
+
+impl TeamsCallRecords {
+    pub fn new(retention_days: i64) -> Self {
+        Self {
+            retention: Duration::days(retention_days),
+            records: Arc::new(RwLock::new(Records::default())),
+        }
+    }
+
+    /// Keep an SBC CDR if it belongs to a Teams call
+    pub async fn record_cdr(&self, cdr: &CallDetailRecord) {
+        if let Some(correlation_id) = &cdr.correlation_id {
+            let mut records = self.records.write().await;
+            records.sbc.insert(correlation_id.clone(), cdr.clone());
+        }
+    }
+
+    /// Add or update records fetched from Graph
+    pub async fn add_teams_calls(&self, calls: Vec<DirectRoutingCall>) {
+        let mut records = self.records.write().await;
+        for call in calls {
+            records.teams.insert(call.correlation_id.clone(), call);
+        }
+    }
+
+    pub async fn last_sync(&self) -> Option<DateTime<Utc>> {
+        self.records.read().await.last_sync
+    }
+
+    /// Fetch new records from Graph, returning how many were received
+    pub async fn sync(&self, client: &GraphClient, lookback: Duration) -> Result<usize> {
+        let now = Utc::now();
+        let from = match self.last_sync().await {
+            Some(last) => last - lookback,
+            None => now - lookback,
+        };
+        let calls = client.direct_routing_calls(from, now).await?;
+        let count = calls.len();
+        self.add_teams_calls(calls).await;
+
+        let cutoff = now - self.retention;
+        let mut records = self.records.write().await;
+        records
+            .teams
+            .retain(|_, call| call.start_date_time >= cutoff);
+        records.sbc.retain(|_, cdr| cdr.start_time >= cutoff);
+        records.last_sync = Some(now);
+        Ok(count)
+    }
+
+    /// Sync on the configured interval until the task is aborted
+    pub fn spawn_sync(&self, config: CallRecordsConfig) -> JoinHandle<()> {
+        let records = self.clone();
+        tokio::spawn(async move {
+            let lookback = Duration::hours(config.lookback_hours);
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(config.interval_seconds));
+            let client = GraphClient::new(config);
+            loop {
+                interval.tick().await;
+                match records.sync(&client, lookback).await {
+                    Ok(count) => info!("Synced {} Teams call records", count),
+                    Err(e) => warn!("Teams call record sync failed: {:#}", e),
+                }
+            }
+        })
+    }
+
+    /// Calls that started between `from` and `to`, newest first
+    pub async fn correlated(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Vec<CorrelatedCall> {
+        let in_range = |start: DateTime<Utc>| {
+            from.is_none_or(|from| start >= from) && to.is_none_or(|to| start < to)
+        };
+        let records = self.records.read().await;
+
+        let ids: HashSet<&String> = records
+            .teams
+            .iter()
+            .filter(|(_, call)| in_range(call.start_date_time))
+            .map(|(id, _)| id)
+            .chain(
+                records
+                    .sbc
+                    .iter()
+                    .filter(|(_, cdr)| in_range(cdr.start_time))
+                    .map(|(id, _)| id),
+            )
+            .collect();
+
+        let mut calls: Vec<CorrelatedCall> = ids
+            .into_iter()
+            .map(|id| {
+                let teams = records.teams.get(id).cloned();
+                let sbc = records.sbc.get(id).cloned();
+                let duration_delta_seconds = match (&teams, &sbc) {
+                    (Some(teams), Some(sbc)) => teams
+                        .duration
+                        .map(|duration| sbc.duration_seconds - duration),
+                    _ => None,
+                };
+                CorrelatedCall {
+                    correlation_id: id.clone(),
+                    teams,
+                    sbc,
+                    duration_delta_seconds,
+                }
+            })
+            .collect();
+        calls.sort_by_key(|call| std::cmp::Reverse(call.start_time()));
+        calls
+    }
+}
+
+impl CorrelatedCall {
+    fn start_time(&self) -> Option<DateTime<Utc>> {
+        self.teams
+            .as_ref()
+            .map(|call| call.start_date_time)
+            .or(self.sbc.as_ref().map(|cdr| cdr.start_time))
+    }
+}
+
+/// Summarize correlated calls
+pub fn summarize(calls: &[CorrelatedCall]) -> TeamsCallSummary {
+    let mut summary = TeamsCallSummary::default();
+    for call in calls {
+        let Some(teams) = &call.teams else {
+            summary.sbc_only_calls += 1;
+            continue;
+        };
+        summary.total_calls += 1;
+        if call.sbc.is_some() {
+            summary.correlated_calls += 1;
+        } else {
+            summary.teams_only_calls += 1;
+        }
+        if teams.successful_call {
+            summary.successful_calls += 1;
+        }
+        if teams.media_bypass_enabled {
+            summary.media_bypass_calls += 1;
+        }
+        summary.total_duration_seconds += teams.duration.unwrap_or(0);
+        if let Some(code) = teams.final_sip_code {
+            *summary.by_final_sip_code.entry(code).or_default() += 1;
+        }
+        if let Some(trunk) = &teams.trunk_fully_qualified_domain_name {
+            *summary.by_trunk.entry(trunk.clone()).or_default() += 1;
+        }
+    }
+    if summary.total_calls > 0 {
+        summary.success_rate = summary.successful_calls as f64 / summary.total_calls as f64;
+        summary.average_duration_seconds =
+            summary.total_duration_seconds as f64 / summary.total_calls as f64;
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::b2bua::{CallDisposition, HangupCause};
+
+    fn teams_call(correlation_id: &str, code: u16, duration: i64) -> DirectRoutingCall {
+        serde_json::from_value(serde_json::json!({
+            "id": format!("row-{}", correlation_id),
+            "correlationId": correlation_id,
+            "startDateTime": "2026-10-01T09:00:00Z",
+            "endDateTime": "2026-10-01T09:05:00Z",
+            "duration": duration,
+            "successfulCall": code == 200,
+            "finalSipCode": code,
+            "trunkFullyQualifiedDomainName": "sbc.example.com",
+            "mediaBypassEnabled": true
+        }))
+        .unwrap()
+    }
+
+    fn cdr(correlation_id: &str, duration: i64) -> CallDetailRecord {
+        let start = "2026-10-01T09:00:01Z".parse().unwrap();
+        CallDetailRecord {
+            call_id: format!("sip-{}", correlation_id),
+            caller: None,
+            callee: None,
+            start_time: start,
+            answer_time: Some(start),
+            end_time: start + Duration::seconds(duration),
+            duration_seconds: duration,
+            disposition: CallDisposition::Answered,
+            hangup_cause: Some(HangupCause::NormalClearing),
+            call_class: None,
+            authorized_by_pin: false,
+            denial_reason: None,
+            account_code: None,
+            dial_pin_user: None,
+            screening: None,
+            voicemail_drop: false,
+            no_answer: None,
+            correlation_id: Some(correlation_id.to_string()),
+            carrier_peer: None,
+            charge: None,
+            decisions: Vec::new(),
+            post_dial_delay_ms: None,
+            setup_ms: None,
+            caller_codec: None,
+            callee_codec: None,
+            attempts: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_correlate_and_summarize() {
+        let records = TeamsCallRecords::new(30);
+        records
+            .add_teams_calls(vec![
+                teams_call("a", 200, 300),
+                teams_call("b", 200, 60),
+                teams_call("c", 487, 0),
+            ])
+            .await;
+        records.record_cdr(&cdr("a", 302)).await;
+        records.record_cdr(&cdr("d", 30)).await;
+        // CDRs of calls that did not come through Teams are ignored
+        let mut local = cdr("e", 10);
+        local.correlation_id = None;
+        records.record_cdr(&local).await;
+
+        let calls = records.correlated(None, None).await;
+        assert_eq!(calls.len(), 4);
+        let a = calls.iter().find(|c| c.correlation_id == "a").unwrap();
+        assert_eq!(a.duration_delta_seconds, Some(2));
+
+        let summary = summarize(&calls);
+        assert_eq!(summary.total_calls, 3);
+        assert_eq!(summary.successful_calls, 2);
+        assert_eq!(summary.correlated_calls, 1);
+        assert_eq!(summary.teams_only_calls, 2);
+        assert_eq!(summary.sbc_only_calls, 1);
+        assert_eq!(summary.total_duration_seconds, 360);
+        assert_eq!(summary.by_final_sip_code.get(&487), Some(&1));
+        assert_eq!(summary.by_trunk.get("sbc.example.com"), Some(&3));
+
+        let later = "2026-10-02T00:00:00Z".parse().unwrap();
+        assert!(records.correlated(Some(later), None).await.is_empty());
+    }
+}
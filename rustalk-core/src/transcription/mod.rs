@@ -0,0 +1,326 @@
+//! Real-time transcription streaming
+//!
+//! Calls can have their audio forked, one WebSocket connection per leg, to
+//! an external transcription or analytics service. Which calls are streamed
+//! is decided when they are routed: a rule must match the route or the ring
+//! group (queue) the call is sent to, neither party may be exempt, and the
+//! consent the configuration asks for must have been given.
+//!
+//! ```json
+//! {
+//!   "transcription": {
+//!     "url": "wss://transcribe.example.com/stream",
+//!     "auth_token": "secret",
+//!     "rules": [{ "route": "support" }, { "queue": "sales", "legs": "caller" }],
+//!     "consent": { "mode": "announced", "announcement": "call-may-be-transcribed" }
+//!   }
+//! }
+//! ```
+//!
+//! Each connection opens with a JSON `start` message carrying the call's
+//! metadata, then carries the leg's audio as binary messages of 8 kHz
+//! 16-bit little-endian PCM, and ends with a JSON `stop` message.
+
+pub mod stream;
+
+pub use stream::MediaFork;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Transcription streaming configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TranscriptionConfig {
+    /// WebSocket endpoint of the service (`ws://` or `wss://`)
+    pub url: String,
+    /// Sent as a bearer token with the upgrade request
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Legs streamed when a rule does not say
+    #[serde(default)]
+    pub legs: StreamLegs,
+    /// Calls to stream; calls no rule matches are not streamed
+    #[serde(default)]
+    pub rules: Vec<TranscriptionRule>,
+    #[serde(default)]
+    pub consent: ConsentConfig,
+}
+
+/// Calls a rule streams
+///
+/// A rule matches calls that meet every field it sets; a rule setting
+/// neither matches every call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TranscriptionRule {
+    /// Route id the call matched
+    #[serde(default)]
+    pub route: Option<String>,
+    /// Ring group the call is routed to
+    #[serde(default)]
+    pub queue: Option<String>,
+    #[serde(default)]
+    pub legs: Option<StreamLegs>,
+}
+
+impl TranscriptionRule {
+    fn matches(&self, call: &CallMetadata) -> bool {
+        let field =
+            |wanted: &Option<String>, actual: &Option<String>| wanted.is_none() || wanted == actual;
+        field(&self.route, &call.route) && field(&self.queue, &call.queue)
+    }
+}
+
+/// A leg of a call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamLeg {
+    Caller,
+    Callee,
+}
+
+impl fmt::Display for StreamLeg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StreamLeg::Caller => "caller",
+            StreamLeg::Callee => "callee",
+        })
+    }
+}
+
+/// Legs of a call that are streamed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamLegs {
+    Caller,
+    Callee,
+    #[default]
+    Both,
+}
+
+impl StreamLegs {
+    pub fn legs(&self) -> Vec<StreamLeg> {
+        match self {
+            StreamLegs::Caller => vec![StreamLeg::Caller],
+            StreamLegs::Callee => vec![StreamLeg::Callee],
+            StreamLegs::Both => vec![StreamLeg::Caller, StreamLeg::Callee],
+        }
+    }
+}
+
+/// What the parties must be told or agree to before audio is streamed
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ConsentConfig {
+    #[serde(default)]
+    pub mode: ConsentMode,
+    /// Prompt played to both parties before streaming starts; required in
+    /// `announced` mode
+    #[serde(default)]
+    pub announcement: Option<String>,
+    /// INVITE header carrying explicit consent (`yes`), set by an IVR or an
+    /// upstream system that asked the caller
+    #[serde(default = "default_consent_header")]
+    pub header: String,
+    /// Number prefixes never streamed, as caller or callee
+    #[serde(default)]
+    pub exempt: Vec<String>,
+}
+
+impl Default for ConsentConfig {
+    fn default() -> Self {
+        Self {
+            mode: ConsentMode::default(),
+            announcement: None,
+            header: default_consent_header(),
+            exempt: Vec::new(),
+        }
+    }
+}
+
+fn default_consent_header() -> String {
+    "X-Transcription-Consent".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentMode {
+    /// Stream without telling anyone, where no consent is needed
+    Implied,
+    /// Stream once the announcement has been played
+    #[default]
+    Announced,
+    /// Stream only calls whose INVITE carries the consent header
+    Explicit,
+}
+
+/// A call being considered for streaming, and the metadata sent with its
+/// audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallMetadata {
+    pub call_id: String,
+    pub caller: String,
+    pub callee: String,
+    pub route: Option<String>,
+    pub queue: Option<String>,
+    /// Value of the consent header on the INVITE
+    #[serde(skip)]
+    pub consent: Option<String>,
+    pub started_at: DateTime<Utc>,
+}
+
+/// How a call is to be streamed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkPlan {
+    pub legs: StreamLegs,
+    /// Prompt to play before any audio is streamed
+    pub announcement: Option<String>,
+}
+
+/// Why a call is not streamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Skip {
+    NoRule,
+    Exempt(String),
+    NoConsent,
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Skip::NoRule => f.write_str("no transcription rule matches"),
+            Skip::Exempt(number) => write!(f, "{} is exempt from transcription", number),
+            Skip::NoConsent => f.write_str("no consent to transcription"),
+        }
+    }
+}
+
+/// Decides which calls are streamed
+#[derive(Debug, Clone)]
+pub struct TranscriptionPolicy {
+    config: TranscriptionConfig,
+}
+
+impl TranscriptionPolicy {
+    pub fn new(config: TranscriptionConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &TranscriptionConfig {
+        &self.config
+    }
+
+    /// How to stream a call, or why it is not streamed
+    pub fn select(&self, call: &CallMetadata) -> Result<ForkPlan, Skip> {
+        let rule = self
+            .config
+            .rules
+            .iter()
+            .find(|rule| rule.matches(call))
+            .ok_or(Skip::NoRule)?;
+        let consent = &self.config.consent;
+        for number in [&call.caller, &call.callee] {
+            if consent
+                .exempt
+                .iter()
+                .any(|prefix| number.starts_with(prefix))
+            {
+                return Err(Skip::Exempt(number.clone()));
+            }
+        }
+        let announcement = match consent.mode {
+            ConsentMode::Implied => None,
+            ConsentMode::Announced => Some(consent.announcement.clone().ok_or(Skip::NoConsent)?),
+            ConsentMode::Explicit => {
+                if !call
+                    .consent
+                    .as_deref()
+                    .is_some_and(|value| value.trim().eq_ignore_ascii_case("yes"))
+                {
+                    return Err(Skip::NoConsent);
+                }
+                None
+            }
+        };
+        Ok(ForkPlan {
+            legs: rule.legs.unwrap_or(self.config.legs),
+            announcement,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(consent: serde_json::Value) -> TranscriptionPolicy {
+        TranscriptionPolicy::new(
+            serde_json::from_value(serde_json::json!({
+                "url": "wss://transcribe.example.com/stream",
+                "rules": [{ "route": "support" }, { "queue": "sales", "legs": "caller" }],
+                "consent": consent
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn call(route: Option<&str>, queue: Option<&str>) -> CallMetadata {
+        CallMetadata {
+            call_id: "abc".to_string(),
+            caller: "+12125550123".to_string(),
+            callee: "2000".to_string(),
+            route: route.map(str::to_string),
+            queue: queue.map(str::to_string),
+            consent: None,
+            started_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_rules_and_announcement() {
+        let policy = policy(serde_json::json!({ "announcement": "may-be-transcribed" }));
+        assert_eq!(
+            policy.select(&call(Some("support"), None)),
+            Ok(ForkPlan {
+                legs: StreamLegs::Both,
+                announcement: Some("may-be-transcribed".to_string()),
+            })
+        );
+        assert_eq!(
+            policy
+                .select(&call(Some("other"), Some("sales")))
+                .unwrap()
+                .legs,
+            StreamLegs::Caller
+        );
+        assert_eq!(policy.select(&call(Some("other"), None)), Err(Skip::NoRule));
+
+        // Announced consent without an announcement streams nothing
+        let policy = self::policy(serde_json::json!({}));
+        assert_eq!(
+            policy.select(&call(Some("support"), None)),
+            Err(Skip::NoConsent)
+        );
+    }
+
+    #[test]
+    fn test_explicit_consent_and_exemptions() {
+        let policy = policy(serde_json::json!({ "mode": "explicit", "exempt": ["+1212"] }));
+        assert_eq!(
+            policy.select(&call(Some("support"), None)),
+            Err(Skip::Exempt("+12125550123".to_string()))
+        );
+
+        let policy = self::policy(serde_json::json!({ "mode": "explicit" }));
+        let mut consenting = call(Some("support"), None);
+        assert_eq!(policy.select(&consenting), Err(Skip::NoConsent));
+        consenting.consent = Some("Yes".to_string());
+        assert_eq!(
+            policy.select(&consenting),
+            Ok(ForkPlan {
+                legs: StreamLegs::Both,
+                announcement: None,
+            })
+        );
+    }
+}
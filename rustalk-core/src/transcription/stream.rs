@@ -0,0 +1,395 @@
+//! WebSocket media fork
+//!
+//! One [`MediaFork`] carries one leg of a call to the transcription
+//! service. We are the WebSocket client here, so every frame we send is
+//! masked and the frames the service sends back are not; the service may
+//! ping us and anything else it sends is ignored.
+
+use super::{CallMetadata, StreamLeg, TranscriptionConfig};
+use crate::media::g711;
+use crate::media::rtp::RtpPacket;
+use crate::media::wav::SAMPLE_RATE;
+use crate::transport::ws::{accept_key, encode_frame, Opcode, WsFramer};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, warn};
+
+/// Time allowed to connect and complete the upgrade
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest upgrade response accepted
+const MAX_RESPONSE_SIZE: usize = 8192;
+
+const PCMU: u8 = 0;
+const PCMA: u8 = 8;
+
+trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+/// Audio of one call leg streaming to the transcription service
+#[derive(Debug)]
+pub struct MediaFork {
+    leg: StreamLeg,
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+    writer: JoinHandle<()>,
+    reader: JoinHandle<()>,
+}
+
+impl MediaFork {
+    /// Connect to the service and announce the call
+    pub async fn connect(
+        config: &TranscriptionConfig,
+        call: &CallMetadata,
+        leg: StreamLeg,
+    ) -> Result<Self> {
+        let (stream, leftover) = tokio::time::timeout(CONNECT_TIMEOUT, open(config))
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "No WebSocket upgrade from {} within {:?}",
+                    config.url,
+                    CONNECT_TIMEOUT
+                )
+            })??;
+        let (mut read, mut write) = tokio::io::split(stream);
+        let (frames, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+
+        let writer = tokio::spawn(async move {
+            while let Some(frame) = outgoing.recv().await {
+                if let Err(e) = write.write_all(&frame).await {
+                    warn!("Transcription stream write failed: {}", e);
+                    return;
+                }
+            }
+            let _ = write.shutdown().await;
+        });
+
+        let pongs = frames.clone();
+        let reader = tokio::spawn(async move {
+            let mut framer = WsFramer::for_client();
+            framer.push(&leftover);
+            let mut chunk = [0u8; 4096];
+            loop {
+                loop {
+                    match framer.next_frame() {
+                        Ok(Some(frame)) => match frame.opcode {
+                            Opcode::Ping => {
+                                let _ = pongs.send(masked(Opcode::Pong, &frame.payload));
+                            }
+                            Opcode::Close => return,
+                            _ => {}
+                        },
+                        Ok(None) => break,
+                        Err(e) => {
+                            debug!("Transcription service sent a bad frame: {}", e);
+                            return;
+                        }
+                    }
+                }
+                match read.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(len) => framer.push(&chunk[..len]),
+                }
+            }
+        });
+
+        let fork = Self {
+            leg,
+            frames,
+            writer,
+            reader,
+        };
+        fork.send_text(json!({
+            "event": "start",
+            "leg": leg,
+            "call": call,
+            "media": {
+                "encoding": "L16",
+                "sample_rate": SAMPLE_RATE,
+                "channels": 1
+            }
+        }))?;
+        Ok(fork)
+    }
+
+    pub fn leg(&self) -> StreamLeg {
+        self.leg
+    }
+
+    /// Stream 8 kHz linear samples
+    pub fn send_audio(&self, samples: &[i16]) -> Result<()> {
+        let payload: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.send(masked(Opcode::Binary, &payload))
+    }
+
+    /// Stream the audio in a G.711 RTP packet; packets of other payload
+    /// types, such as telephone events, are skipped
+    pub fn send_rtp(&self, packet: &RtpPacket) -> Result<()> {
+        let decode: fn(u8) -> i16 = match packet.payload_type {
+            PCMU => g711::ulaw_to_linear,
+            PCMA => g711::alaw_to_linear,
+            _ => return Ok(()),
+        };
+        let samples: Vec<i16> = packet.payload.iter().map(|b| decode(*b)).collect();
+        self.send_audio(&samples)
+    }
+
+    /// End the stream, waiting for what was queued to be sent
+    pub async fn close(self) {
+        let _ = self.send_text(json!({ "event": "stop" }));
+        let _ = self.send(masked(Opcode::Close, &1000u16.to_be_bytes()));
+        drop(self.frames);
+        let _ = self.writer.await;
+        self.reader.abort();
+    }
+
+    fn send_text(&self, message: serde_json::Value) -> Result<()> {
+        self.send(masked(Opcode::Text, message.to_string().as_bytes()))
+    }
+
+    fn send(&self, frame: Vec<u8>) -> Result<()> {
+        self.frames
+            .send(frame)
+            .map_err(|_| anyhow!("Transcription stream for the {} leg has closed", self.leg))
+    }
+}
+
+fn masked(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    encode_frame(opcode, payload, Some(rand::random()))
+}
+
+/// Connect and upgrade, returning the connection and any bytes read past
+/// the upgrade response
+async fn open(config: &TranscriptionConfig) -> Result<(Box<dyn Connection>, Vec<u8>)> {
+    let url = reqwest::Url::parse(&config.url)
+        .with_context(|| format!("Invalid transcription URL '{}'", config.url))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("Transcription URL '{}' has no host", config.url))?
+        .to_string();
+    let secure = match url.scheme() {
+        "ws" => false,
+        "wss" => true,
+        scheme => bail!("Transcription URL scheme must be ws or wss, not {}", scheme),
+    };
+    let port = url.port().unwrap_or(if secure { 443 } else { 80 });
+
+    let tcp = TcpStream::connect((host.as_str(), port))
+        .await
+        .with_context(|| format!("Cannot connect to {}:{}", host, port))?;
+    let mut stream: Box<dyn Connection> = if secure {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let tls = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = ServerName::try_from(host.clone())?;
+        Box::new(
+            TlsConnector::from(Arc::new(tls))
+                .connect(name, tcp)
+                .await
+                .context("TLS handshake failed")?,
+        )
+    } else {
+        Box::new(tcp)
+    };
+
+    let key = BASE64.encode(rand::random::<[u8; 16]>());
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path = format!("{}?{}", path, query);
+    }
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n",
+        path,
+        url.port()
+            .map_or_else(|| host.clone(), |port| format!("{}:{}", host, port)),
+        key
+    );
+    if let Some(token) = &config.auth_token {
+        request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let end = loop {
+        if let Some(end) = response.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if response.len() > MAX_RESPONSE_SIZE {
+            bail!("Upgrade response from {} is too large", config.url);
+        }
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            bail!("{} closed the connection during the upgrade", config.url);
+        }
+        response.extend_from_slice(&chunk[..len]);
+    };
+    let leftover = response.split_off(end);
+    let response = String::from_utf8_lossy(&response);
+    let mut lines = response.lines();
+    let status = lines.next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("101") {
+        bail!("{} refused the upgrade: {}", config.url, status);
+    }
+    let expected = accept_key(&key);
+    let accepted = lines
+        .filter_map(|line| line.split_once(':'))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("sec-websocket-accept") && value.trim() == expected
+        });
+    if !accepted {
+        bail!("{} sent a wrong Sec-WebSocket-Accept", config.url);
+    }
+    Ok((stream, leftover))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::ws::WsFrame;
+    use chrono::Utc;
+    use tokio::net::TcpListener;
+
+    fn metadata() -> CallMetadata {
+        CallMetadata {
+            call_id: "abc".to_string(),
+            caller: "+12125550123".to_string(),
+            callee: "2000".to_string(),
+            route: Some("support".to_string()),
+            queue: None,
+            consent: None,
+            started_at: Utc::now(),
+        }
+    }
+
+    /// Accept one connection as the service would, ping it once, and
+    /// return every message it sends until it closes
+    async fn service(listener: TcpListener) -> (String, Vec<WsFrame>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0u8; 4096];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let len = stream.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..len]);
+        }
+        let request = String::from_utf8(request).unwrap();
+        let key = request
+            .lines()
+            .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+            .unwrap();
+        let mut response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        )
+        .into_bytes();
+        // A ping arriving with the upgrade response is read past it
+        response.extend(encode_frame(Opcode::Ping, b"hi", None));
+        stream.write_all(&response).await.unwrap();
+
+        let mut framer = WsFramer::new();
+        let mut frames = Vec::new();
+        loop {
+            while let Some(frame) = framer.next_frame().unwrap() {
+                if frame.opcode == Opcode::Close {
+                    return (request, frames);
+                }
+                frames.push(frame);
+            }
+            let len = stream.read(&mut chunk).await.unwrap();
+            assert!(len > 0, "connection closed without a close frame");
+            framer.push(&chunk[..len]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_leg() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config: TranscriptionConfig = serde_json::from_value(json!({
+            "url": format!("ws://{}/stream?tenant=acme", listener.local_addr().unwrap()),
+            "auth_token": "secret"
+        }))
+        .unwrap();
+        let service = tokio::spawn(service(listener));
+
+        let fork = MediaFork::connect(&config, &metadata(), StreamLeg::Caller)
+            .await
+            .unwrap();
+        fork.send_audio(&[1, -2]).unwrap();
+        fork.send_rtp(&RtpPacket {
+            payload_type: PCMU,
+            marker: false,
+            sequence: 0,
+            timestamp: 0,
+            ssrc: 1,
+            payload: vec![g711::linear_to_ulaw(0); 4],
+        })
+        .unwrap();
+        // Telephone events carry no audio
+        fork.send_rtp(&RtpPacket {
+            payload_type: 101,
+            marker: false,
+            sequence: 1,
+            timestamp: 0,
+            ssrc: 1,
+            payload: vec![1, 2, 3, 4],
+        })
+        .unwrap();
+        // Let the ping be answered before the stream closes
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        fork.close().await;
+
+        let (request, frames) = tokio::time::timeout(Duration::from_secs(5), service)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(request.starts_with("GET /stream?tenant=acme HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Bearer secret\r\n"));
+
+        let start: serde_json::Value = serde_json::from_slice(&frames[0].payload).unwrap();
+        assert_eq!(frames[0].opcode, Opcode::Text);
+        assert_eq!(start["event"], "start");
+        assert_eq!(start["leg"], "caller");
+        assert_eq!(start["call"]["call_id"], "abc");
+        assert_eq!(start["call"]["route"], "support");
+        assert_eq!(start["media"]["encoding"], "L16");
+
+        let binary: Vec<&WsFrame> = frames
+            .iter()
+            .filter(|f| f.opcode == Opcode::Binary)
+            .collect();
+        assert_eq!(binary.len(), 2);
+        assert_eq!(binary[0].payload, vec![1, 0, 0xfe, 0xff]);
+        assert_eq!(binary[1].payload.len(), 8);
+        assert!(frames
+            .iter()
+            .any(|f| f.opcode == Opcode::Pong && f.payload == b"hi"));
+        let stop: serde_json::Value =
+            serde_json::from_slice(&frames.last().unwrap().payload).unwrap();
+        assert_eq!(stop["event"], "stop");
+    }
+}
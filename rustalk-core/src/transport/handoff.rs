@@ -0,0 +1,254 @@
+//! Passing UDP messages between the processes of a rolling upgrade
+//!
+//! While one process drains and its successor takes over, both receive on
+//! the same `SO_REUSEPORT` ports and the kernel picks which one gets each
+//! datagram by its source, not by which process holds the call. Each
+//! process therefore has a handoff socket on loopback, named in the upgrade
+//! pid file. The successor hands the draining process the in-dialog
+//! messages of calls it does not hold; the draining process hands its
+//! successor everything but its own calls, new calls included. A message
+//! handed over is handled as if it had arrived on the listener it was
+//! received on, and is never handed on again. Only the two processes'
+//! handoff sockets are listened to.
+
+use anyhow::{anyhow, Context, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+use crate::sip::builder::header_tag;
+use crate::sip::parser::parse_message;
+use crate::sip::{Message, Method};
+
+/// First word of a handed over message
+const MESSAGE_TAG: &str = "RUSTALK-HANDOFF";
+
+/// Sent by a draining process to its successor as it exits
+const DONE: &[u8] = b"RUSTALK-HANDOFF-DONE\r\n";
+
+/// A message handed over by the other process
+#[derive(Debug, Clone)]
+pub struct HandedOver {
+    /// Listener the message was received on
+    pub local: SocketAddr,
+    pub source: SocketAddr,
+    pub message: Message,
+}
+
+/// Handoff socket of this process and the processes it hands over to
+#[derive(Debug, Clone)]
+pub struct Handoff {
+    socket: Arc<UdpSocket>,
+    peers: Arc<Mutex<Peers>>,
+}
+
+#[derive(Debug, Default)]
+struct Peers {
+    /// Process draining while this one takes over
+    predecessor: Option<SocketAddr>,
+    /// Process taking over while this one drains
+    successor: Option<SocketAddr>,
+}
+
+impl Handoff {
+    /// Bind a handoff socket on loopback
+    pub async fn bind() -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .context("Failed to bind the handoff socket")?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            peers: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket
+            .local_addr()
+            .expect("a bound socket has an address")
+    }
+
+    /// Hand in-dialog messages of unknown calls to the draining process at
+    /// `addr`
+    pub fn set_predecessor(&self, addr: SocketAddr) {
+        self.peers.lock().unwrap().predecessor = Some(addr);
+    }
+
+    /// Hand everything but this process's calls to `addr` while draining
+    pub fn set_successor(&self, addr: SocketAddr) {
+        self.peers.lock().unwrap().successor = Some(addr);
+    }
+
+    /// Where a message of no call this process holds belongs, if it is
+    /// another process
+    pub fn destination(&self, message: &Message, draining: bool) -> Option<SocketAddr> {
+        let peers = self.peers.lock().unwrap();
+        if draining {
+            return peers.successor;
+        }
+        peers.predecessor.filter(|_| in_dialog(message))
+    }
+
+    /// Hand `message`, received on `local` from `source`, to the process at
+    /// `to`
+    pub async fn forward(
+        &self,
+        to: SocketAddr,
+        local: SocketAddr,
+        source: SocketAddr,
+        message: &Message,
+    ) -> Result<()> {
+        let mut datagram = format!("{} {} {}\r\n", MESSAGE_TAG, local, source).into_bytes();
+        datagram.extend_from_slice(&match message {
+            Message::Request(req) => req.to_bytes(),
+            Message::Response(res) => res.to_bytes(),
+        });
+        self.socket.send_to(&datagram, to).await?;
+        Ok(())
+    }
+
+    /// Tell the successor this process has finished draining
+    pub async fn finish(&self) -> Result<()> {
+        let successor = self.peers.lock().unwrap().successor;
+        if let Some(successor) = successor {
+            self.socket.send_to(DONE, successor).await?;
+        }
+        Ok(())
+    }
+
+    /// Next message handed over by the other process
+    ///
+    /// A predecessor that has finished draining is forgotten, so nothing
+    /// more is handed to it.
+    pub async fn receive(&self) -> Result<HandedOver> {
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            {
+                let mut peers = self.peers.lock().unwrap();
+                if peers.predecessor != Some(from) && peers.successor != Some(from) {
+                    debug!("Ignoring handoff datagram from {}", from);
+                    continue;
+                }
+                if &buf[..len] == DONE {
+                    debug!("Process at {} finished draining", from);
+                    if peers.predecessor == Some(from) {
+                        peers.predecessor = None;
+                    }
+                    continue;
+                }
+            }
+            match decode(&buf[..len]) {
+                Ok(handed) => return Ok(handed),
+                Err(e) => debug!("Dropping handoff datagram from {}: {:#}", from, e),
+            }
+        }
+    }
+}
+
+/// Whether `message` belongs to a dialog or transaction already under way
+fn in_dialog(message: &Message) -> bool {
+    match message {
+        Message::Response(_) => true,
+        Message::Request(req) => {
+            matches!(req.method, Method::Ack | Method::Bye | Method::Cancel)
+                || req.get_header_value("To").and_then(header_tag).is_some()
+        }
+    }
+}
+
+fn decode(datagram: &[u8]) -> Result<HandedOver> {
+    let end = datagram
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .ok_or_else(|| anyhow!("no handoff header"))?;
+    let header = std::str::from_utf8(&datagram[..end])?;
+    let mut fields = header.split(' ');
+    if fields.next() != Some(MESSAGE_TAG) {
+        return Err(anyhow!("not a handed over message"));
+    }
+    let mut address = || -> Result<SocketAddr> {
+        Ok(fields
+            .next()
+            .ok_or_else(|| anyhow!("handoff header is short"))?
+            .parse()?)
+    };
+    let (local, source) = (address()?, address()?);
+    let message = parse_message(&datagram[end + 2..])
+        .map_err(|e| anyhow!("Failed to parse SIP message: {}", e))?;
+    Ok(HandedOver {
+        local,
+        source,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Request, Uri};
+    use std::time::Duration;
+
+    fn request(method: Method, to: &str) -> Message {
+        Message::Request(
+            Request::new(
+                method,
+                Uri::new("sip".to_string(), "example.com".to_string()),
+            )
+            .with_header("Call-ID", "handoff-1")
+            .with_header("From", "<sip:1001@example.com>;tag=a")
+            .with_header("To", to)
+            .with_header("CSeq", "2 BYE"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_hands_over_messages_of_the_other_process() {
+        let (old, new) = (
+            Handoff::bind().await.unwrap(),
+            Handoff::bind().await.unwrap(),
+        );
+        new.set_predecessor(old.local_addr());
+        old.set_successor(new.local_addr());
+        let bye = request(Method::Bye, "<sip:1002@example.com>;tag=b");
+        let invite = request(Method::Invite, "<sip:1002@example.com>");
+
+        // The new process hands on only what belongs to a call under way;
+        // the draining one hands on everything it does not hold
+        assert_eq!(new.destination(&bye, false), Some(old.local_addr()));
+        assert_eq!(new.destination(&invite, false), None);
+        assert_eq!(old.destination(&invite, true), Some(new.local_addr()));
+
+        // Strangers are not heard
+        let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        stranger
+            .send_to(
+                b"RUSTALK-HANDOFF 0.0.0.0:5060 192.0.2.1:5060\r\nOPTIONS",
+                old.local_addr(),
+            )
+            .await
+            .unwrap();
+        let local: SocketAddr = "0.0.0.0:5060".parse().unwrap();
+        let source: SocketAddr = "192.0.2.7:5062".parse().unwrap();
+        new.forward(old.local_addr(), local, source, &bye)
+            .await
+            .unwrap();
+        let handed = old.receive().await.unwrap();
+        assert_eq!(handed.local, local);
+        assert_eq!(handed.source, source);
+        match handed.message {
+            Message::Request(req) => {
+                assert_eq!(req.method, Method::Bye);
+                assert_eq!(req.get_header_value("Call-ID"), Some("handoff-1"));
+            }
+            other => panic!("Expected the BYE, got {:?}", other),
+        }
+
+        // Once the old process is done nothing more is handed to it
+        old.finish().await.unwrap();
+        let receiving = tokio::time::timeout(Duration::from_millis(100), new.receive()).await;
+        assert!(receiving.is_err());
+        assert_eq!(new.destination(&bye, false), None);
+    }
+}
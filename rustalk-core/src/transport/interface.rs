@@ -0,0 +1,587 @@
+//! Local network interfaces and outbound source address selection
+//!
+//! Multihomed SBC deployments typically have an internal NIC facing the PBX
+//! and an external NIC facing carriers. Each outbound request must leave from
+//! the right interface and carry that interface's address in Via, Contact and
+//! SDP, otherwise replies and media are sent to an unreachable address.
+
+use super::Transport;
+use crate::acl::matches_cidr;
+use crate::b2bua::capabilities::uri_host;
+use crate::domains::DomainMap;
+use crate::io;
+use crate::sip::{Message, Request, Response};
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+
+/// A local interface that SIP traffic can be sent from
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NetworkInterface {
+    /// Interface name referenced by SIP profiles (e.g. "internal", "external")
+    pub name: String,
+    /// Local IP address to bind to
+    pub bind_address: IpAddr,
+    /// Address advertised in Via/Contact/SDP when it differs from the bind
+    /// address (e.g. public IP or FQDN behind 1:1 NAT)
+    pub advertised_address: Option<String>,
+    /// Destination networks reachable through this interface (CIDR notation)
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// Use this interface when no other interface matches
+    #[serde(default)]
+    pub default: bool,
+}
+
+impl NetworkInterface {
+    pub fn new(name: impl Into<String>, bind_address: IpAddr) -> Self {
+        Self {
+            name: name.into(),
+            bind_address,
+            advertised_address: None,
+            networks: Vec::new(),
+            default: false,
+        }
+    }
+
+    pub fn with_advertised_address(mut self, address: impl Into<String>) -> Self {
+        self.advertised_address = Some(address.into());
+        self
+    }
+
+    pub fn with_network(mut self, cidr: impl Into<String>) -> Self {
+        self.networks.push(cidr.into());
+        self
+    }
+
+    pub fn as_default(mut self) -> Self {
+        self.default = true;
+        self
+    }
+
+    /// Host to place in Via, Contact and SDP
+    pub fn advertised_host(&self) -> String {
+        self.advertised_address
+            .clone()
+            .unwrap_or_else(|| self.bind_address.to_string())
+    }
+
+    /// Longest prefix of `networks` matching `dest`, if any
+    fn match_prefix(&self, dest: IpAddr) -> Option<u8> {
+        self.networks
+            .iter()
+            .filter(|cidr| matches_cidr(dest, cidr).unwrap_or(false))
+            .map(|cidr| prefix_len(cidr, dest))
+            .max()
+    }
+}
+
+/// Selects the egress interface for outbound traffic
+#[derive(Debug, Clone, Default)]
+pub struct EgressSelector {
+    interfaces: Vec<NetworkInterface>,
+}
+
+impl EgressSelector {
+    pub fn new(interfaces: Vec<NetworkInterface>) -> Self {
+        Self { interfaces }
+    }
+
+    /// Get an interface by name
+    pub fn get(&self, name: &str) -> Option<&NetworkInterface> {
+        self.interfaces.iter().find(|i| i.name == name)
+    }
+
+    /// List all configured interfaces
+    pub fn interfaces(&self) -> &[NetworkInterface] {
+        &self.interfaces
+    }
+
+    /// Select the interface to use for a destination
+    ///
+    /// An interface pinned by the SIP profile wins. Otherwise the interface
+    /// with the most specific matching network is used, falling back to the
+    /// default interface (or the first one configured).
+    pub fn select(
+        &self,
+        dest: IpAddr,
+        profile_interface: Option<&str>,
+    ) -> Option<&NetworkInterface> {
+        if let Some(name) = profile_interface {
+            if let Some(iface) = self.get(name) {
+                return Some(iface);
+            }
+            debug!(
+                "Profile interface '{}' not configured, selecting by route",
+                name
+            );
+        }
+
+        self.interfaces
+            .iter()
+            .filter(|i| i.bind_address.is_ipv4() == dest.is_ipv4())
+            .filter_map(|i| i.match_prefix(dest).map(|len| (len, i)))
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, i)| i)
+            .or_else(|| self.interfaces.iter().find(|i| i.default))
+            .or_else(|| self.interfaces.first())
+    }
+
+    /// Resolve a host to every address it publishes and pick an egress
+    /// interface for each
+    ///
+    /// Carriers commonly publish several A/AAAA records for DNS load
+    /// balancing; the records can sit on different networks, so the egress
+    /// interface is chosen per resolved address rather than per hostname.
+    pub async fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+        profile_interface: Option<&str>,
+    ) -> Result<Vec<(SocketAddr, &NetworkInterface)>> {
+        let targets = io::dns::lookup_host(host, port).await?;
+
+        Ok(targets
+            .into_iter()
+            .filter_map(|addr| {
+                self.select(addr.ip(), profile_interface)
+                    .map(|iface| (addr, iface))
+            })
+            .collect())
+    }
+}
+
+/// Rewrite the local address in an outbound request's Via and Contact headers
+/// and, when present, its SDP body
+pub fn apply_local_address(request: &mut Request, iface: &NetworkInterface, port: u16) {
+    let host = format_host(&iface.advertised_host());
+
+    for header in request.headers.iter_mut() {
+        let name = header.name.as_str();
+        if name.eq_ignore_ascii_case("Via") || name.eq_ignore_ascii_case("v") {
+            header.value = rewrite_via(header.value.as_str(), &host, port).into();
+        } else if name.eq_ignore_ascii_case("Contact") || name.eq_ignore_ascii_case("m") {
+            header.value = rewrite_contact(header.value.as_str(), &host, port).into();
+        }
+    }
+
+    let is_sdp = request
+        .get_header_value("Content-Type")
+        .is_some_and(|ct| ct.eq_ignore_ascii_case("application/sdp"));
+    if is_sdp {
+        if let Ok(body) = std::str::from_utf8(&request.body) {
+            request.body = rewrite_sdp(body, &iface.advertised_host()).into();
+        }
+    }
+}
+
+/// Sends the requests RusTalk originates from the interface facing their
+/// destination
+///
+/// Each UDP listener registers its socket. A request leaves from the socket
+/// bound to the interface [`EgressSelector`] picks, or else a wildcard one,
+/// with the interface's address in its Via, Contact and SDP. The SIP profile
+/// serving the domain in the request's From header may pin the interface.
+#[derive(Clone, Default)]
+pub struct Egress {
+    selector: EgressSelector,
+    domains: DomainMap,
+    sockets: Arc<RwLock<Vec<Arc<dyn Transport>>>>,
+}
+
+impl Egress {
+    pub fn new(selector: EgressSelector) -> Self {
+        Self {
+            selector,
+            ..Default::default()
+        }
+    }
+
+    /// Pin requests to the egress interface of the SIP profile serving their
+    /// From domain
+    pub fn with_domains(mut self, domains: DomainMap) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Send from `socket`, replacing a socket registered earlier on the same
+    /// address (e.g. before its listener restarted)
+    pub fn register(&self, socket: Arc<dyn Transport>) {
+        let mut sockets = self.sockets.write().unwrap();
+        sockets.retain(|s| s.local_addr() != socket.local_addr());
+        sockets.push(socket);
+    }
+
+    /// The socket bound to `local`
+    pub fn socket(&self, local: SocketAddr) -> Option<Arc<dyn Transport>> {
+        let sockets = self.sockets.read().unwrap();
+        sockets.iter().find(|s| s.local_addr() == local).cloned()
+    }
+
+    /// Socket and interface a request to `dest` leaves from
+    ///
+    /// The interface is `None` when none are configured, in which case the
+    /// request is sent as it is.
+    pub fn route(
+        &self,
+        request: &Request,
+        dest: SocketAddr,
+    ) -> Option<(Arc<dyn Transport>, Option<NetworkInterface>)> {
+        self.select(request.get_header_value("From"), dest)
+    }
+
+    /// Socket and interface facing `dest`, pinned by the domain of `from`
+    fn select(
+        &self,
+        from: Option<&str>,
+        dest: SocketAddr,
+    ) -> Option<(Arc<dyn Transport>, Option<NetworkInterface>)> {
+        let pinned = from
+            .and_then(uri_host)
+            .and_then(|host| self.domains.egress_interface(host));
+        let iface = self.selector.select(dest.ip(), pinned.as_deref()).cloned();
+
+        let sockets = self.sockets.read().unwrap();
+        let bound = |ip: IpAddr| sockets.iter().find(|s| s.local_addr().ip() == ip);
+        let socket = iface
+            .as_ref()
+            .and_then(|i| bound(i.bind_address))
+            .or_else(|| {
+                sockets
+                    .iter()
+                    .find(|s| s.local_addr().ip().is_unspecified())
+            })
+            .or(sockets.first())?;
+        Some((socket.clone(), iface))
+    }
+
+    /// Send `request` to `dest` from the interface facing it
+    pub async fn send(&self, mut request: Request, dest: SocketAddr) -> Result<()> {
+        let Some((socket, iface)) = self.route(&request, dest) else {
+            bail!("no UDP socket to send to {} from", dest);
+        };
+        if let Some(iface) = &iface {
+            debug!("Sending to {} via interface {}", dest, iface.name);
+            apply_local_address(&mut request, iface, socket.local_addr().port());
+        }
+        socket.send(&Message::Request(request), dest).await
+    }
+
+    /// Send `response`, relayed from the other leg of a call, to `dest`
+    ///
+    /// The response echoes the Via of the request it answers, so it is sent
+    /// as it is.
+    pub async fn send_response(&self, response: Response, dest: SocketAddr) -> Result<()> {
+        let Some((socket, _)) = self.select(response.get_header_value("From"), dest) else {
+            bail!("no UDP socket to send to {} from", dest);
+        };
+        socket.send(&Message::Response(response), dest).await
+    }
+}
+
+/// Replace the address in the `o=` line and every `c=` line of an SDP body,
+/// leaving the other lines as they are
+fn rewrite_sdp(body: &str, host: &str) -> String {
+    let addr_type = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => Some("IP4"),
+        Ok(IpAddr::V6(_)) => Some("IP6"),
+        // An FQDN keeps the address type the line had
+        Err(_) => None,
+    };
+    body.split_inclusive('\n')
+        .map(|line| {
+            let content = line.trim_end_matches(['\r', '\n']);
+            if !content.starts_with("o=") && !content.starts_with("c=") {
+                return line.to_string();
+            }
+            let mut fields: Vec<&str> = content.split(' ').collect();
+            let last = fields.len() - 1;
+            // Multicast groups (with a TTL) are not a local address
+            if last < 2 || fields[last].contains('/') {
+                return line.to_string();
+            }
+            fields[last] = host;
+            if let Some(addr_type) = addr_type {
+                fields[last - 1] = addr_type;
+            }
+            format!("{}{}", fields.join(" "), &line[content.len()..])
+        })
+        .collect()
+}
+
+/// Replace the sent-by part of a Via header value
+fn rewrite_via(value: &str, host: &str, port: u16) -> String {
+    let (head, params) = match value.find(';') {
+        Some(idx) => value.split_at(idx),
+        None => (value, ""),
+    };
+    let protocol = head.split_whitespace().next().unwrap_or("SIP/2.0/UDP");
+
+    format!("{} {}:{}{}", protocol, host, port, params)
+}
+
+/// Replace the hostport of the URI in a Contact header value
+pub(crate) fn rewrite_contact(value: &str, host: &str, port: u16) -> String {
+    let Some(scheme_idx) = value.find("sip:").or_else(|| value.find("sips:")) else {
+        return value.to_string();
+    };
+    let uri_start = scheme_idx + value[scheme_idx..].find(':').unwrap_or(0) + 1;
+    let uri_end = value[uri_start..]
+        .find(['>', ';'])
+        .map_or(value.len(), |idx| uri_start + idx);
+
+    let host_start = value[uri_start..uri_end]
+        .find('@')
+        .map_or(uri_start, |idx| uri_start + idx + 1);
+
+    format!(
+        "{}{}:{}{}",
+        &value[..host_start],
+        host,
+        port,
+        &value[uri_end..]
+    )
+}
+
+/// Bracket IPv6 literals for use in SIP headers
+fn format_host(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(addr)) => format!("[{}]", addr),
+        _ => host.to_string(),
+    }
+}
+
+fn prefix_len(cidr: &str, dest: IpAddr) -> u8 {
+    cidr.split_once('/')
+        .and_then(|(_, len)| len.parse().ok())
+        .unwrap_or(if dest.is_ipv4() { 32 } else { 128 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Method, Uri};
+
+    fn multihomed() -> EgressSelector {
+        EgressSelector::new(vec![
+            NetworkInterface::new("internal", "10.0.0.5".parse().unwrap())
+                .with_network("10.0.0.0/8")
+                .with_network("192.168.0.0/16"),
+            NetworkInterface::new("external", "203.0.113.10".parse().unwrap())
+                .with_advertised_address("sbc.example.com")
+                .as_default(),
+            NetworkInterface::new("dmz", "172.16.0.5".parse().unwrap())
+                .with_network("10.20.0.0/16"),
+        ])
+    }
+
+    #[test]
+    fn test_select_by_network() {
+        let selector = multihomed();
+
+        let iface = selector.select("10.1.2.3".parse().unwrap(), None).unwrap();
+        assert_eq!(iface.name, "internal");
+
+        let iface = selector.select("8.8.8.8".parse().unwrap(), None).unwrap();
+        assert_eq!(iface.name, "external");
+    }
+
+    #[test]
+    fn test_select_longest_prefix() {
+        let selector = multihomed();
+        let iface = selector.select("10.20.1.1".parse().unwrap(), None).unwrap();
+        assert_eq!(iface.name, "dmz");
+    }
+
+    #[test]
+    fn test_profile_interface_wins() {
+        let selector = multihomed();
+        let iface = selector
+            .select("10.1.2.3".parse().unwrap(), Some("external"))
+            .unwrap();
+        assert_eq!(iface.name, "external");
+
+        // Unknown profile interface falls back to route selection
+        let iface = selector
+            .select("10.1.2.3".parse().unwrap(), Some("missing"))
+            .unwrap();
+        assert_eq!(iface.name, "internal");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_literal() {
+        let selector = multihomed();
+        let targets = selector.resolve("10.1.2.3", 5060, None).await.unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].0, "10.1.2.3:5060".parse().unwrap());
+        assert_eq!(targets[0].1.name, "internal");
+    }
+
+    #[test]
+    fn test_rewrite_via() {
+        assert_eq!(
+            rewrite_via(
+                "SIP/2.0/UDP 0.0.0.0:5060;branch=z9hG4bK776",
+                "203.0.113.10",
+                5080
+            ),
+            "SIP/2.0/UDP 203.0.113.10:5080;branch=z9hG4bK776"
+        );
+        assert_eq!(
+            rewrite_via("SIP/2.0/TLS host", "[2001:db8::1]", 5061),
+            "SIP/2.0/TLS [2001:db8::1]:5061"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_contact() {
+        assert_eq!(
+            rewrite_contact("<sip:alice@0.0.0.0:5060;transport=udp>", "10.0.0.5", 5060),
+            "<sip:alice@10.0.0.5:5060;transport=udp>"
+        );
+        assert_eq!(
+            rewrite_contact("\"Bob\" <sip:192.168.1.1>;expires=3600", "10.0.0.5", 5070),
+            "\"Bob\" <sip:10.0.0.5:5070>;expires=3600"
+        );
+        assert_eq!(rewrite_contact("*", "10.0.0.5", 5060), "*");
+    }
+
+    #[test]
+    fn test_apply_local_address() {
+        let selector = multihomed();
+        let iface = selector.select("8.8.8.8".parse().unwrap(), None).unwrap();
+
+        let sdp = "v=0\r\no=- 1 1 IN IP4 0.0.0.0\r\ns=-\r\nc=IN IP4 0.0.0.0\r\nm=audio 4000 RTP/AVP 0\r\n";
+        let mut request = Request::new(
+            Method::Invite,
+            Uri::new("sip".to_string(), "carrier.example.net".to_string()),
+        )
+        .with_header("Via", "SIP/2.0/UDP 0.0.0.0:5060;branch=z9hG4bK1")
+        .with_header("Contact", "<sip:rustalk@0.0.0.0:5060>")
+        .with_header("Content-Type", "application/sdp")
+        .with_body(sdp);
+
+        apply_local_address(&mut request, iface, 5060);
+
+        assert_eq!(
+            request.get_header_value("Via"),
+            Some("SIP/2.0/UDP sbc.example.com:5060;branch=z9hG4bK1")
+        );
+        assert_eq!(
+            request.get_header_value("Contact"),
+            Some("<sip:rustalk@sbc.example.com:5060>")
+        );
+
+        let body = std::str::from_utf8(&request.body).unwrap();
+        assert!(body.contains("o=- 1 1 IN IP4 sbc.example.com"));
+        assert!(body.contains("c=IN IP4 sbc.example.com"));
+    }
+
+    #[test]
+    fn test_rewrite_sdp_keeps_media_attributes() {
+        let sdp = "v=0\r\n\
+                   o=- 42 7 IN IP4 0.0.0.0\r\n\
+                   s=-\r\n\
+                   c=IN IP4 0.0.0.0\r\n\
+                   b=AS:64\r\n\
+                   t=0 0\r\n\
+                   m=audio 4000 RTP/SAVP 9 101\r\n\
+                   c=IN IP4 0.0.0.0\r\n\
+                   a=rtpmap:9 G722/8000\r\n\
+                   a=rtpmap:101 telephone-event/8000\r\n\
+                   a=fmtp:101 0-16\r\n\
+                   a=crypto:1 AES_CM_128_HMAC_SHA1_80 inline:c2VjcmV0\r\n\
+                   a=sendrecv\r\n\
+                   m=video 0 RTP/AVP 96\r\n\
+                   c=IN IP4 224.2.1.1/127\r\n";
+
+        let expected = sdp.replacen("IN IP4 0.0.0.0", "IN IP6 2001:db8::5", 3);
+        assert_eq!(rewrite_sdp(sdp, "2001:db8::5"), expected);
+
+        // A hostname keeps the address type; LF-only bodies keep their endings
+        assert_eq!(
+            rewrite_sdp(
+                "o=- 1 1 IN IP4 0.0.0.0\nc=IN IP4 0.0.0.0\na=ptime:20\n",
+                "sbc.example.com"
+            ),
+            "o=- 1 1 IN IP4 sbc.example.com\nc=IN IP4 sbc.example.com\na=ptime:20\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_egress_sends_from_selected_interface() {
+        use crate::domains::{DomainConfig, DomainProfile};
+        use crate::transport::{TransportConfig, UdpTransport};
+        use tokio::net::UdpSocket;
+
+        let udp = |addr: &str| {
+            let config = TransportConfig {
+                bind_addr: addr.parse().unwrap(),
+                ..Default::default()
+            };
+            async move { Arc::new(UdpTransport::new(&config).await.unwrap()) as Arc<dyn Transport> }
+        };
+        let loopback = udp("127.0.0.1:0").await;
+        let wildcard = udp("0.0.0.0:0").await;
+        let domains = DomainMap::new(DomainConfig {
+            profiles: vec![DomainProfile {
+                name: "carriers".to_string(),
+                domain: "trunks.example.com".to_string(),
+                aliases: Vec::new(),
+                realm: None,
+                egress_interface: Some("external".to_string()),
+            }],
+        });
+        let egress = Egress::new(EgressSelector::new(vec![
+            NetworkInterface::new("internal", "127.0.0.1".parse().unwrap())
+                .with_network("127.0.0.0/8"),
+            NetworkInterface::new("external", "203.0.113.10".parse().unwrap())
+                .with_advertised_address("sbc.example.com")
+                .as_default(),
+        ]))
+        .with_domains(domains);
+        egress.register(loopback.clone());
+        egress.register(wildcard.clone());
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let dest = peer.local_addr().unwrap();
+        let request = |from: &str| {
+            Request::new(
+                Method::Invite,
+                Uri::new("sip".to_string(), "127.0.0.1".to_string()),
+            )
+            .with_header("Via", "SIP/2.0/UDP 0.0.0.0:5060;branch=z9hG4bK1")
+            .with_header("From", from)
+        };
+
+        // Routed by network: sent from the socket bound to the interface
+        let (socket, iface) = egress
+            .route(&request("<sip:1001@pbx.example.com>"), dest)
+            .unwrap();
+        assert_eq!(socket.local_addr(), loopback.local_addr());
+        assert_eq!(iface.unwrap().name, "internal");
+        egress
+            .send(request("<sip:1001@pbx.example.com>"), dest)
+            .await
+            .unwrap();
+        let mut buf = vec![0u8; 65535];
+        let (len, source) = peer.recv_from(&mut buf).await.unwrap();
+        assert_eq!(source, loopback.local_addr());
+        let sent = String::from_utf8_lossy(&buf[..len]).into_owned();
+        assert!(sent.contains(&format!(
+            "Via: SIP/2.0/UDP 127.0.0.1:{};branch=z9hG4bK1",
+            loopback.local_addr().port()
+        )));
+
+        // Pinned by the From domain's profile, which has no socket of its
+        // own: sent from the wildcard socket with the profile's address
+        let (socket, iface) = egress
+            .route(&request("<sip:+15551234@trunks.example.com>"), dest)
+            .unwrap();
+        assert_eq!(socket.local_addr(), wildcard.local_addr());
+        assert_eq!(iface.unwrap().name, "external");
+    }
+}
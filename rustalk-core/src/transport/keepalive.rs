@@ -0,0 +1,383 @@
+//! Connection keep-alives and flows (RFC 5626)
+//!
+//! A client behind NAT keeps its TCP, TLS or WebSocket connection - its
+//! flow - open by sending a double CRLF "ping", which is answered with a
+//! single CRLF "pong" (section 4.4.1). Requests for the client are sent back
+//! down the same flow, which is named by an opaque flow token. Flows that
+//! go quiet for longer than the idle timeout, or leave a ping of ours
+//! unanswered, are closed; a client that then reconnects from the same
+//! address is counted as a reconnect, so connection churn can be watched.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Keep-alive sent by the client of a flow
+pub const PING: &[u8] = b"\r\n\r\n";
+
+/// Answer to a ping
+pub const PONG: &[u8] = b"\r\n";
+
+/// Keep-alive timers for connection-oriented transports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct KeepaliveConfig {
+    /// Ping a flow that has been quiet this long
+    #[serde(default = "default_ping_interval_seconds")]
+    pub ping_interval_seconds: u64,
+    /// Close a flow whose ping has gone unanswered this long
+    #[serde(default = "default_pong_timeout_seconds")]
+    pub pong_timeout_seconds: u64,
+    /// Close a flow that has been quiet this long, pinged or not
+    #[serde(default = "default_idle_timeout_seconds")]
+    pub idle_timeout_seconds: u64,
+    /// A connection from an address whose flow closed this recently is a
+    /// reconnect
+    #[serde(default = "default_reconnect_window_seconds")]
+    pub reconnect_window_seconds: u64,
+}
+
+/// RFC 5626 section 4.4.1 recommends 95 to 120 seconds for connections
+fn default_ping_interval_seconds() -> u64 {
+    120
+}
+
+/// RFC 5626 section 4.4.1
+fn default_pong_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_idle_timeout_seconds() -> u64 {
+    300
+}
+
+fn default_reconnect_window_seconds() -> u64 {
+    60
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval_seconds: default_ping_interval_seconds(),
+            pong_timeout_seconds: default_pong_timeout_seconds(),
+            idle_timeout_seconds: default_idle_timeout_seconds(),
+            reconnect_window_seconds: default_reconnect_window_seconds(),
+        }
+    }
+}
+
+/// A keep-alive read off a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keepalive {
+    Ping,
+    Pong,
+}
+
+/// Split a keep-alive off the front of data read from a connection
+///
+/// Returns the keep-alive, if the data starts with one, and the rest. A
+/// lone CRLF may be the start of a ping whose second half has not arrived
+/// yet, so callers with more data to come should wait before treating it as
+/// a pong.
+pub fn split_keepalive(data: &[u8]) -> (Option<Keepalive>, &[u8]) {
+    if let Some(rest) = data.strip_prefix(PING) {
+        (Some(Keepalive::Ping), rest)
+    } else if let Some(rest) = data.strip_prefix(PONG) {
+        (Some(Keepalive::Pong), rest)
+    } else {
+        (None, data)
+    }
+}
+
+/// Whether a datagram is nothing but CRLF keep-alives
+pub fn is_keepalive(datagram: &[u8]) -> bool {
+    !datagram.is_empty() && datagram.iter().all(|b| matches!(b, b'\r' | b'\n'))
+}
+
+/// Connection-oriented transport a flow runs over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowTransport {
+    Tcp,
+    Tls,
+    Ws,
+    Wss,
+}
+
+/// Why a flow was closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The peer or the listener closed the connection
+    Closed,
+    /// Nothing was heard for the idle timeout
+    IdleTimeout,
+    /// A ping went unanswered
+    PongTimeout,
+}
+
+/// One open connection
+#[derive(Debug, Clone)]
+pub struct Flow {
+    pub token: String,
+    pub transport: FlowTransport,
+    pub remote: SocketAddr,
+    pub opened_at: Instant,
+    pub last_seen: Instant,
+    /// When we pinged the flow, until it answers
+    pub pinged_at: Option<Instant>,
+}
+
+/// Connection churn counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionMetrics {
+    pub active: usize,
+    pub opened: u64,
+    pub closed: u64,
+    pub idle_timeouts: u64,
+    pub pong_timeouts: u64,
+    pub reconnects: u64,
+    pub pings_received: u64,
+}
+
+#[derive(Debug, Default)]
+struct FlowState {
+    flows: HashMap<String, Flow>,
+    /// When the last flow from each address closed
+    recently_closed: HashMap<(FlowTransport, IpAddr), Instant>,
+    metrics: ConnectionMetrics,
+}
+
+/// Open flows of every connection-oriented listener
+#[derive(Debug, Clone, Default)]
+pub struct FlowTable {
+    config: KeepaliveConfig,
+    state: Arc<Mutex<FlowState>>,
+}
+
+impl FlowTable {
+    pub fn new(config: KeepaliveConfig) -> Self {
+        Self {
+            config,
+            state: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &KeepaliveConfig {
+        &self.config
+    }
+
+    /// Record a new connection, returning its flow token
+    pub fn open(&self, transport: FlowTransport, remote: SocketAddr) -> String {
+        self.open_at(transport, remote, Instant::now())
+    }
+
+    fn open_at(&self, transport: FlowTransport, remote: SocketAddr, now: Instant) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        let window = Duration::from_secs(self.config.reconnect_window_seconds);
+        let mut state = self.state.lock().unwrap();
+        if let Some(closed_at) = state.recently_closed.remove(&(transport, remote.ip())) {
+            if now.duration_since(closed_at) <= window {
+                state.metrics.reconnects += 1;
+            }
+        }
+        state.flows.insert(
+            token.clone(),
+            Flow {
+                token: token.clone(),
+                transport,
+                remote,
+                opened_at: now,
+                last_seen: now,
+                pinged_at: None,
+            },
+        );
+        state.metrics.opened += 1;
+        token
+    }
+
+    /// Record data read from a flow; a pong or any message answers our ping
+    ///
+    /// Returns the reply the flow is owed: a pong for a ping.
+    pub fn received(&self, token: &str, keepalive: Option<Keepalive>) -> Option<&'static [u8]> {
+        let mut state = self.state.lock().unwrap();
+        let flow = state.flows.get_mut(token)?;
+        flow.last_seen = Instant::now();
+        flow.pinged_at = None;
+        if keepalive == Some(Keepalive::Ping) {
+            state.metrics.pings_received += 1;
+            return Some(PONG);
+        }
+        None
+    }
+
+    /// Record that the connection of a flow is gone
+    pub fn close(&self, token: &str, reason: CloseReason) -> Option<Flow> {
+        self.close_at(token, reason, Instant::now())
+    }
+
+    fn close_at(&self, token: &str, reason: CloseReason, now: Instant) -> Option<Flow> {
+        let mut state = self.state.lock().unwrap();
+        let flow = state.flows.remove(token)?;
+        state
+            .recently_closed
+            .insert((flow.transport, flow.remote.ip()), now);
+        state.metrics.closed += 1;
+        match reason {
+            CloseReason::Closed => {}
+            CloseReason::IdleTimeout => state.metrics.idle_timeouts += 1,
+            CloseReason::PongTimeout => state.metrics.pong_timeouts += 1,
+        }
+        Some(flow)
+    }
+
+    /// Address of the peer at the far end of a flow, if it is still open
+    pub fn remote(&self, token: &str) -> Option<SocketAddr> {
+        let state = self.state.lock().unwrap();
+        state.flows.get(token).map(|flow| flow.remote)
+    }
+
+    /// Open flow to `remote` over `transport`
+    pub fn find(&self, transport: FlowTransport, remote: SocketAddr) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .flows
+            .values()
+            .find(|flow| flow.transport == transport && flow.remote == remote)
+            .map(|flow| flow.token.clone())
+    }
+
+    /// Flows quiet for the ping interval, which are now marked as pinged;
+    /// the listener sends each a ping
+    pub fn due_pings(&self) -> Vec<String> {
+        self.due_pings_at(Instant::now())
+    }
+
+    fn due_pings_at(&self, now: Instant) -> Vec<String> {
+        let interval = Duration::from_secs(self.config.ping_interval_seconds);
+        let mut state = self.state.lock().unwrap();
+        state
+            .flows
+            .values_mut()
+            .filter(|flow| {
+                flow.pinged_at.is_none() && now.duration_since(flow.last_seen) >= interval
+            })
+            .map(|flow| {
+                flow.pinged_at = Some(now);
+                flow.token.clone()
+            })
+            .collect()
+    }
+
+    /// Close flows that are idle or have not answered a ping; the listener
+    /// drops their connections
+    pub fn expire(&self) -> Vec<Flow> {
+        self.expire_at(Instant::now())
+    }
+
+    fn expire_at(&self, now: Instant) -> Vec<Flow> {
+        let idle = Duration::from_secs(self.config.idle_timeout_seconds);
+        let pong = Duration::from_secs(self.config.pong_timeout_seconds);
+        let expired: Vec<_> = {
+            let state = self.state.lock().unwrap();
+            state
+                .flows
+                .values()
+                .filter_map(|flow| {
+                    if flow
+                        .pinged_at
+                        .is_some_and(|pinged| now.duration_since(pinged) >= pong)
+                    {
+                        Some((flow.token.clone(), CloseReason::PongTimeout))
+                    } else if now.duration_since(flow.last_seen) >= idle {
+                        Some((flow.token.clone(), CloseReason::IdleTimeout))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        };
+        let expired = expired
+            .into_iter()
+            .filter_map(|(token, reason)| self.close_at(&token, reason, now))
+            .collect();
+        let window = Duration::from_secs(self.config.reconnect_window_seconds);
+        let mut state = self.state.lock().unwrap();
+        state
+            .recently_closed
+            .retain(|_, closed_at| now.duration_since(*closed_at) <= window);
+        expired
+    }
+
+    pub fn metrics(&self) -> ConnectionMetrics {
+        let state = self.state.lock().unwrap();
+        ConnectionMetrics {
+            active: state.flows.len(),
+            ..state.metrics.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_keepalive() {
+        assert_eq!(
+            split_keepalive(b"\r\n\r\n"),
+            (Some(Keepalive::Ping), &b""[..])
+        );
+        assert_eq!(split_keepalive(b"\r\n"), (Some(Keepalive::Pong), &b""[..]));
+        assert_eq!(
+            split_keepalive(b"\r\n\r\nOPTIONS"),
+            (Some(Keepalive::Ping), &b"OPTIONS"[..])
+        );
+        assert_eq!(split_keepalive(b"OPTIONS"), (None, &b"OPTIONS"[..]));
+        assert!(is_keepalive(b"\r\n\r\n"));
+        assert!(!is_keepalive(b""));
+        assert!(!is_keepalive(b"OPTIONS sip:example.com SIP/2.0\r\n"));
+    }
+
+    #[test]
+    fn test_flow_keepalives_and_churn() {
+        let table = FlowTable::new(KeepaliveConfig::default());
+        let remote: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        let start = Instant::now();
+        let token = table.open_at(FlowTransport::Tls, remote, start);
+        assert_eq!(table.remote(&token), Some(remote));
+        assert_eq!(table.find(FlowTransport::Tls, remote), Some(token.clone()));
+        assert!(table.find(FlowTransport::Tcp, remote).is_none());
+        assert_eq!(table.received(&token, Some(Keepalive::Ping)), Some(PONG));
+
+        // Quiet for the ping interval: pinged once, then closed unanswered
+        let later = Instant::now() + Duration::from_secs(120);
+        assert_eq!(table.due_pings_at(later), vec![token.clone()]);
+        assert!(table.due_pings_at(later).is_empty());
+        assert!(table.expire_at(later + Duration::from_secs(5)).is_empty());
+        let expired = table.expire_at(later + Duration::from_secs(10));
+        assert_eq!(expired.len(), 1);
+        assert!(table.remote(&token).is_none());
+
+        // The client comes back from a new port behind the same NAT
+        let reconnected = "203.0.113.7:40001".parse().unwrap();
+        let token = table.open_at(
+            FlowTransport::Tls,
+            reconnected,
+            later + Duration::from_secs(20),
+        );
+        table.expire_at(later + Duration::from_secs(400));
+        assert!(table.remote(&token).is_none());
+
+        let metrics = table.metrics();
+        assert_eq!(metrics.active, 0);
+        assert_eq!(metrics.opened, 2);
+        assert_eq!(metrics.closed, 2);
+        assert_eq!(metrics.pong_timeouts, 1);
+        assert_eq!(metrics.idle_timeouts, 1);
+        assert_eq!(metrics.reconnects, 1);
+        assert_eq!(metrics.pings_received, 1);
+    }
+}
@@ -1,39 +1,147 @@
-//! SIP Transport layer with UDP, TCP, TLS support
+//! SIP Transport layer with UDP, TCP, TLS and WebSocket support
 
+use crate::config::TransportSettings;
 use crate::sip::Message;
 use anyhow::Result;
-use std::net::SocketAddr;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::sync::mpsc;
-use tracing::{debug, error, info};
 
+pub mod handoff;
+pub mod interface;
+pub mod keepalive;
+pub mod socket;
+pub mod tcp;
 pub mod tls;
 pub mod udp;
+pub mod ws;
 
+pub use handoff::{HandedOver, Handoff};
+pub use interface::{apply_local_address, Egress, EgressSelector, NetworkInterface};
+pub use keepalive::{ConnectionMetrics, FlowTable, FlowTransport, KeepaliveConfig};
+pub use socket::{bind_tcp, bind_udp};
+pub use tcp::TcpTransport;
 pub use tls::TlsTransport;
 pub use udp::UdpTransport;
+pub use ws::{WsTransport, WssTransport};
+
+/// Protocol a transport carries SIP over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TransportProtocol {
+    #[default]
+    Udp,
+    Tcp,
+    Tls,
+    /// SIP over WebSocket (RFC 7118)
+    Ws,
+    /// SIP over secure WebSocket
+    Wss,
+}
+
+/// Port WebSocket listeners use when `transport.ws_port` is unset
+pub const DEFAULT_WS_PORT: u16 = 5066;
+
+/// Port secure WebSocket listeners use when `transport.wss_port` is unset
+pub const DEFAULT_WSS_PORT: u16 = 7443;
+
+impl TransportProtocol {
+    /// Name used in `transport.protocols` and the `transport` URI parameter
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Udp => "udp",
+            Self::Tcp => "tcp",
+            Self::Tls => "tls",
+            Self::Ws => "ws",
+            Self::Wss => "wss",
+        }
+    }
+}
+
+impl fmt::Display for TransportProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TransportProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => Ok(Self::Udp),
+            "tcp" => Ok(Self::Tcp),
+            "tls" => Ok(Self::Tls),
+            "ws" => Ok(Self::Ws),
+            "wss" => Ok(Self::Wss),
+            _ => anyhow::bail!("Unknown transport protocol: {}", s),
+        }
+    }
+}
 
 /// Transport configuration
 #[derive(Debug, Clone)]
 pub struct TransportConfig {
     pub bind_addr: SocketAddr,
-    pub use_tls: bool,
+    pub protocol: TransportProtocol,
     pub cert_path: Option<String>,
     pub key_path: Option<String>,
+    /// Bind with `SO_REUSEPORT` so a new process can take over the port
+    pub reuse_port: bool,
+    /// Keep-alive and idle timers for connection-oriented transports
+    pub keepalive: Option<KeepaliveConfig>,
 }
 
 impl Default for TransportConfig {
     fn default() -> Self {
         Self {
             bind_addr: "0.0.0.0:5060".parse().unwrap(),
-            use_tls: false,
+            protocol: TransportProtocol::Udp,
             cert_path: None,
             key_path: None,
+            reuse_port: false,
+            keepalive: None,
         }
     }
 }
 
+impl TransportConfig {
+    /// One configuration per protocol in `transport.protocols`, bound to
+    /// `ip` on the protocol's port
+    ///
+    /// UDP and TCP fall back to `default_port` when they have no port of
+    /// their own, TLS to the port after it, and WebSockets to
+    /// [`DEFAULT_WS_PORT`] and [`DEFAULT_WSS_PORT`].
+    pub fn for_settings(
+        settings: &TransportSettings,
+        ip: IpAddr,
+        default_port: u16,
+    ) -> Result<Vec<Self>> {
+        settings
+            .protocols
+            .iter()
+            .map(|name| {
+                let protocol: TransportProtocol = name.parse()?;
+                let port = match protocol {
+                    TransportProtocol::Udp => settings.udp_port.unwrap_or(default_port),
+                    TransportProtocol::Tcp => settings.tcp_port.unwrap_or(default_port),
+                    TransportProtocol::Tls => settings.tls_port.unwrap_or(default_port + 1),
+                    TransportProtocol::Ws => settings.ws_port.unwrap_or(DEFAULT_WS_PORT),
+                    TransportProtocol::Wss => settings.wss_port.unwrap_or(DEFAULT_WSS_PORT),
+                };
+                Ok(Self {
+                    bind_addr: SocketAddr::new(ip, port),
+                    protocol,
+                    cert_path: settings.tls_cert.clone(),
+                    key_path: settings.tls_key.clone(),
+                    reuse_port: false,
+                    keepalive: settings.keepalive.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
 /// Transport trait for sending and receiving SIP messages
 #[async_trait::async_trait]
 pub trait Transport: Send + Sync {
@@ -49,19 +157,20 @@ pub trait Transport: Send + Sync {
 
 /// Transport layer manager
 pub struct TransportLayer {
-    config: TransportConfig,
     transport: Arc<dyn Transport>,
 }
 
 impl TransportLayer {
     pub async fn new(config: TransportConfig) -> Result<Self> {
-        let transport: Arc<dyn Transport> = if config.use_tls {
-            Arc::new(TlsTransport::new(&config).await?)
-        } else {
-            Arc::new(UdpTransport::new(&config).await?)
+        let transport: Arc<dyn Transport> = match config.protocol {
+            TransportProtocol::Udp => Arc::new(UdpTransport::new(&config).await?),
+            TransportProtocol::Tcp => Arc::new(TcpTransport::new(&config).await?),
+            TransportProtocol::Tls => Arc::new(TlsTransport::new(&config).await?),
+            TransportProtocol::Ws => Arc::new(WsTransport::new(&config).await?),
+            TransportProtocol::Wss => Arc::new(WssTransport::new(&config).await?),
         };
 
-        Ok(Self { config, transport })
+        Ok(Self { transport })
     }
 
     pub async fn send(&self, message: &Message, dest: SocketAddr) -> Result<()> {
@@ -76,3 +185,54 @@ impl TransportLayer {
         self.transport.local_addr()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_transport_configs_for_settings() {
+        let mut settings = Config::default().transport;
+        settings.protocols = vec![
+            "UDP".to_string(),
+            "tcp".to_string(),
+            "tls".to_string(),
+            "ws".to_string(),
+        ];
+        settings.tcp_port = Some(5070);
+        let configs =
+            TransportConfig::for_settings(&settings, "10.0.0.1".parse().unwrap(), 5060).unwrap();
+        let bound: Vec<_> = configs
+            .iter()
+            .map(|c| (c.protocol, c.bind_addr.to_string()))
+            .collect();
+        assert_eq!(
+            bound,
+            [
+                (TransportProtocol::Udp, "10.0.0.1:5060".to_string()),
+                (TransportProtocol::Tcp, "10.0.0.1:5070".to_string()),
+                (TransportProtocol::Tls, "10.0.0.1:5061".to_string()),
+                (TransportProtocol::Ws, "10.0.0.1:5066".to_string()),
+            ]
+        );
+
+        settings.protocols = vec!["sctp".to_string()];
+        assert!(
+            TransportConfig::for_settings(&settings, "10.0.0.1".parse().unwrap(), 5060).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transport_layer_selects_protocol() {
+        let layer = TransportLayer::new(TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            protocol: TransportProtocol::Tcp,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // Bound a TCP listener on the chosen port
+        assert!(std::net::TcpStream::connect(layer.local_addr()).is_ok());
+    }
+}
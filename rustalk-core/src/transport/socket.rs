@@ -0,0 +1,70 @@
+//! Listening sockets that can be shared across a rolling upgrade
+//!
+//! With `reuse_port` set, sockets are bound with `SO_REUSEPORT` so a new
+//! RusTalk process can bind the same SIP and API ports while the old one is
+//! still draining its calls. The kernel then spreads new datagrams and
+//! connections across both processes until the old one exits.
+
+use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Bind a UDP socket, sharing the port with other processes if `reuse_port`
+pub fn bind_udp(addr: SocketAddr, reuse_port: bool) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind udp {}", addr))?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Bind a TCP listener, sharing the port with other processes if
+/// `reuse_port`
+pub fn bind_tcp(addr: SocketAddr, reuse_port: bool) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind tcp {}", addr))?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> Result<()> {
+    socket
+        .set_reuse_port(true)
+        .context("Failed to set SO_REUSEPORT")
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> Result<()> {
+    anyhow::bail!("SO_REUSEPORT is not supported on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reuse_port_shares_address() {
+        let first = bind_udp("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = bind_udp(addr, true).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+        assert!(bind_udp(addr, false).is_err());
+
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(bind_tcp(addr, true).is_ok());
+    }
+}
@@ -0,0 +1,547 @@
+//! TCP Transport implementation
+//!
+//! A byte stream has no message boundaries, so each message is delimited by
+//! its Content-Length (RFC 3261 section 18.3), which is added to outgoing
+//! messages that lack one. A received message without one, or one too
+//! large to buffer, ends the connection. Connections are pooled by
+//! remote address in the [`FlowTable`]: a reply or a request for a peer goes
+//! down the connection it already has open, whichever side opened it, and
+//! a new one is only dialed when there is none. CRLF pings on a connection
+//! are answered with a pong, and connections that go quiet are pinged and
+//! eventually closed. [`TlsTransport`](super::TlsTransport) runs the same
+//! over TLS.
+
+use super::keepalive::{split_keepalive, CloseReason, FlowTable, FlowTransport, Keepalive, PING};
+use super::{bind_tcp, Transport, TransportConfig};
+use crate::sip::{parser::parse_message, Header, Message};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+/// Largest message buffered from a connection before it is dropped
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// How often flows are checked for due pings and idle timeouts
+const FLOW_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client has to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a connection carried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Keepalive(Keepalive),
+    /// One complete SIP message
+    Message(Vec<u8>),
+}
+
+/// Splits the bytes read from a connection into messages and keep-alives
+#[derive(Debug, Default)]
+pub struct StreamFramer {
+    buf: Vec<u8>,
+}
+
+impl StreamFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add bytes read from the connection
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Next complete frame, or `None` until more bytes arrive
+    ///
+    /// Errors mean the stream cannot be resynchronised and the connection
+    /// should be closed.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>> {
+        let (keepalive, rest) = split_keepalive(&self.buf);
+        if let Some(keepalive) = keepalive {
+            let consumed = self.buf.len() - rest.len();
+            self.buf.drain(..consumed);
+            return Ok(Some(Frame::Keepalive(keepalive)));
+        }
+
+        let Some(header_end) = find(&self.buf, b"\r\n\r\n").map(|i| i + 4) else {
+            if self.buf.len() > MAX_MESSAGE_SIZE {
+                anyhow::bail!("No end of headers in {} bytes", self.buf.len());
+            }
+            return Ok(None);
+        };
+        let headers = String::from_utf8_lossy(&self.buf[..header_end]);
+        let length = content_length(&headers)
+            .ok_or_else(|| anyhow::anyhow!("Message without Content-Length"))?;
+        let total = header_end + length;
+        if total > MAX_MESSAGE_SIZE {
+            anyhow::bail!("Message of {} bytes is too large", total);
+        }
+        if self.buf.len() < total {
+            return Ok(None);
+        }
+        let message = self.buf.drain(..total).collect();
+        Ok(Some(Frame::Message(message)))
+    }
+}
+
+/// Content-Length of a message's header section, in full or compact form
+fn content_length(headers: &str) -> Option<usize> {
+    headers.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        let name = name.trim();
+        (name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("l"))
+            .then(|| value.trim().parse().ok())?
+    })
+}
+
+/// Serialize a message for a stream, adding the Content-Length the peer
+/// needs to find its end if the message has none
+pub fn stream_bytes(message: &Message) -> Vec<u8> {
+    match message {
+        Message::Request(req) if !has_content_length(&req.headers) => {
+            let mut req = req.clone();
+            req.headers.push(Header::new(
+                "Content-Length",
+                req.body.len().to_string().as_str(),
+            ));
+            req.to_bytes()
+        }
+        Message::Response(res) if !has_content_length(&res.headers) => {
+            let mut res = res.clone();
+            res.headers.push(Header::new(
+                "Content-Length",
+                res.body.len().to_string().as_str(),
+            ));
+            res.to_bytes()
+        }
+        Message::Request(req) => req.to_bytes(),
+        Message::Response(res) => res.to_bytes(),
+    }
+}
+
+fn has_content_length(headers: &[Header]) -> bool {
+    headers.iter().any(|h| {
+        h.name.as_str().eq_ignore_ascii_case("Content-Length")
+            || h.name.as_str().eq_ignore_ascii_case("l")
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// An open connection: bytes queued for its writer, and its reader
+struct Connection {
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    reader: AbortHandle,
+}
+
+/// State shared by the listener, the connections and senders
+struct Shared {
+    transport: FlowTransport,
+    flows: FlowTable,
+    /// Open connections by flow token
+    connections: Mutex<HashMap<String, Connection>>,
+    incoming: mpsc::UnboundedSender<(Message, SocketAddr)>,
+}
+
+impl Shared {
+    /// Start reading and writing a connection, returning its writer queue
+    fn attach<S>(self: &Arc<Self>, stream: S, remote: SocketAddr) -> mpsc::UnboundedSender<Vec<u8>>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let token = self.flows.open(self.transport, remote);
+        let (mut read_half, mut write_half) = tokio::io::split(stream);
+        let (outgoing, mut queue) = mpsc::unbounded_channel::<Vec<u8>>();
+
+        tokio::spawn(async move {
+            while let Some(bytes) = queue.recv().await {
+                if let Err(e) = write_half.write_all(&bytes).await {
+                    debug!("TCP write to {} failed: {}", remote, e);
+                    break;
+                }
+            }
+        });
+
+        let shared = self.clone();
+        let reader_token = token.clone();
+        let reply = outgoing.clone();
+        let reader = tokio::spawn(async move {
+            let mut framer = StreamFramer::new();
+            let mut buf = vec![0u8; 8192];
+            loop {
+                let len = match read_half.read(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(e) => {
+                        debug!("TCP read from {} failed: {}", remote, e);
+                        break;
+                    }
+                };
+                framer.push(&buf[..len]);
+                if let Err(e) = shared.deliver(&mut framer, &reader_token, remote, &reply) {
+                    warn!("Closing TCP connection from {}: {:#}", remote, e);
+                    break;
+                }
+            }
+            shared.detach(&reader_token, CloseReason::Closed);
+        });
+
+        self.connections.lock().unwrap().insert(
+            token,
+            Connection {
+                outgoing: outgoing.clone(),
+                reader: reader.abort_handle(),
+            },
+        );
+        debug!("TCP connection with {} open", remote);
+        outgoing
+    }
+
+    /// Hand every complete message in `framer` to the receiver, answering
+    /// pings on the way
+    fn deliver(
+        &self,
+        framer: &mut StreamFramer,
+        token: &str,
+        remote: SocketAddr,
+        reply: &mpsc::UnboundedSender<Vec<u8>>,
+    ) -> Result<()> {
+        while let Some(frame) = framer.next_frame()? {
+            match frame {
+                Frame::Keepalive(keepalive) => {
+                    if let Some(pong) = self.flows.received(token, Some(keepalive)) {
+                        let _ = reply.send(pong.to_vec());
+                    }
+                }
+                Frame::Message(bytes) => {
+                    self.flows.received(token, None);
+                    match parse_message(&bytes) {
+                        Ok(message) => {
+                            let _ = self.incoming.send((message, remote));
+                        }
+                        Err(e) => debug!("Dropping unreadable message from {}: {}", remote, e),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget a connection, stopping its reader; its writer ends once the
+    /// queue is dropped
+    fn detach(&self, token: &str, reason: CloseReason) {
+        if let Some(connection) = self.connections.lock().unwrap().remove(token) {
+            connection.reader.abort();
+        }
+        if let Some(flow) = self.flows.close(token, reason) {
+            debug!("TCP connection with {} closed ({:?})", flow.remote, reason);
+        }
+    }
+
+    /// Writer queue of the open connection to `remote`
+    fn connection_to(&self, remote: SocketAddr) -> Option<mpsc::UnboundedSender<Vec<u8>>> {
+        let token = self.flows.find(self.transport, remote)?;
+        let connections = self.connections.lock().unwrap();
+        connections
+            .get(&token)
+            .map(|c| c.outgoing.clone())
+            .filter(|outgoing| !outgoing.is_closed())
+    }
+
+    /// Ping quiet connections and close those that stayed silent
+    fn check_flows(&self) {
+        for token in self.flows.due_pings() {
+            if let Some(connection) = self.connections.lock().unwrap().get(&token) {
+                let _ = connection.outgoing.send(PING.to_vec());
+            }
+        }
+        for flow in self.flows.expire() {
+            if let Some(connection) = self.connections.lock().unwrap().remove(&flow.token) {
+                connection.reader.abort();
+            }
+            debug!("TCP connection with {} timed out", flow.remote);
+        }
+    }
+}
+
+pub struct TcpTransport {
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    incoming: tokio::sync::Mutex<mpsc::UnboundedReceiver<(Message, SocketAddr)>>,
+    /// Accept loop and flow checks, stopped when the transport is dropped
+    tasks: Vec<AbortHandle>,
+}
+
+impl TcpTransport {
+    pub async fn new(config: &TransportConfig) -> Result<Self> {
+        Self::start(config, None).await
+    }
+
+    /// Listen on `config.bind_addr`, handshaking every accepted connection
+    /// with `tls` when given
+    pub(crate) async fn start(config: &TransportConfig, tls: Option<TlsAcceptor>) -> Result<Self> {
+        let listener = bind_tcp(config.bind_addr, config.reuse_port)?;
+        let local_addr = listener.local_addr()?;
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            transport: if tls.is_some() {
+                FlowTransport::Tls
+            } else {
+                FlowTransport::Tcp
+            },
+            flows: FlowTable::new(config.keepalive.clone().unwrap_or_default()),
+            connections: Mutex::new(HashMap::new()),
+            incoming: incoming_tx,
+        });
+
+        let accept = tokio::spawn(accept_loop(listener, tls, shared.clone()));
+        let checks = {
+            let shared = shared.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(FLOW_CHECK_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    shared.check_flows();
+                }
+            })
+        };
+
+        debug!(
+            "{:?} transport listening on {}",
+            shared.transport, local_addr
+        );
+
+        Ok(Self {
+            local_addr,
+            shared,
+            incoming: tokio::sync::Mutex::new(incoming),
+            tasks: vec![accept.abort_handle(), checks.abort_handle()],
+        })
+    }
+
+    /// Open connections and their keep-alive state
+    pub fn flows(&self) -> &FlowTable {
+        &self.shared.flows
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        for (_, connection) in self.shared.connections.lock().unwrap().drain() {
+            connection.reader.abort();
+        }
+    }
+}
+
+async fn accept_loop(listener: TcpListener, tls: Option<TlsAcceptor>, shared: Arc<Shared>) {
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("TCP accept failed: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let Some(tls) = tls.clone() else {
+            shared.attach(stream, remote);
+            continue;
+        };
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    shared.attach(stream, remote);
+                }
+                Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", remote, e),
+                Err(_) => debug!("TLS handshake with {} timed out", remote),
+            }
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for TcpTransport {
+    async fn send(&self, message: &Message, dest: SocketAddr) -> Result<()> {
+        let bytes = stream_bytes(message);
+        let outgoing = match self.shared.connection_to(dest) {
+            Some(outgoing) => outgoing,
+            // TLS peers are only answered over the connections they open
+            None if self.shared.transport == FlowTransport::Tls => {
+                anyhow::bail!("No TLS connection from {}", dest);
+            }
+            None => {
+                let stream = TcpStream::connect(dest).await?;
+                let _ = stream.set_nodelay(true);
+                self.shared.attach(stream, dest)
+            }
+        };
+        let len = bytes.len();
+        outgoing
+            .send(bytes)
+            .map_err(|_| anyhow::anyhow!("TCP connection to {} is closed", dest))?;
+
+        debug!("Sent {} bytes to {}", len, dest);
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<(Message, SocketAddr)> {
+        self.incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("TCP transport closed"))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Method, Request, Uri};
+
+    const OPTIONS: &[u8] =
+        b"OPTIONS sip:example.com SIP/2.0\r\nCall-ID: tcp1\r\nContent-Length: 4\r\n\r\nbody";
+
+    fn config() -> TransportConfig {
+        TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stream_framing() {
+        let mut framer = StreamFramer::new();
+        // A ping, a message split across reads, and the start of the next
+        framer.push(b"\r\n\r\n");
+        framer.push(&OPTIONS[..20]);
+        assert_eq!(
+            framer.next_frame().unwrap(),
+            Some(Frame::Keepalive(Keepalive::Ping))
+        );
+        assert_eq!(framer.next_frame().unwrap(), None);
+        framer.push(&OPTIONS[20..]);
+        framer.push(&OPTIONS[..10]);
+        assert_eq!(
+            framer.next_frame().unwrap(),
+            Some(Frame::Message(OPTIONS.to_vec()))
+        );
+        assert_eq!(framer.next_frame().unwrap(), None);
+
+        // Compact form
+        let mut framer = StreamFramer::new();
+        framer.push(b"OPTIONS sip:example.com SIP/2.0\r\ni: tcp2\r\nl: 0\r\n\r\n");
+        assert!(matches!(
+            framer.next_frame().unwrap(),
+            Some(Frame::Message(_))
+        ));
+
+        let mut framer = StreamFramer::new();
+        framer.push(b"OPTIONS sip:example.com SIP/2.0\r\nCall-ID: tcp3\r\n\r\n");
+        assert!(framer.next_frame().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_receive_and_reply_on_same_connection() {
+        let transport = TcpTransport::new(&config()).await.unwrap();
+        let mut client = TcpStream::connect(transport.local_addr()).await.unwrap();
+        client.write_all(b"\r\n\r\n").await.unwrap();
+        client.write_all(OPTIONS).await.unwrap();
+
+        let (message, source) = transport.receive().await.unwrap();
+        assert_eq!(source, client.local_addr().unwrap());
+        assert!(
+            matches!(&message, Message::Request(request) if request.get_header_value("Call-ID") == Some("tcp1"))
+        );
+
+        // The reply goes back down the client's connection, after the pong
+        let reply = Request::new(
+            Method::Options,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "tcp-reply");
+        transport
+            .send(&Message::Request(reply), source)
+            .await
+            .unwrap();
+        let mut framer = StreamFramer::new();
+        let mut frames = Vec::new();
+        let mut buf = vec![0u8; 4096];
+        while frames.len() < 2 {
+            let len = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            framer.push(&buf[..len]);
+            while let Some(frame) = framer.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames[0], Frame::Keepalive(Keepalive::Pong));
+        assert!(
+            matches!(&frames[1], Frame::Message(bytes) if String::from_utf8_lossy(bytes).contains("tcp-reply"))
+        );
+        assert_eq!(transport.flows().metrics().active, 1);
+        assert_eq!(transport.flows().metrics().pings_received, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_connections_are_reused() {
+        let server = TcpTransport::new(&config()).await.unwrap();
+        let client = TcpTransport::new(&config()).await.unwrap();
+        let request = |call_id: &str| {
+            Message::Request(
+                Request::new(
+                    Method::Options,
+                    Uri::new("sip".to_string(), "example.com".to_string()),
+                )
+                .with_header("Call-ID", call_id),
+            )
+        };
+
+        client
+            .send(&request("first"), server.local_addr())
+            .await
+            .unwrap();
+        client
+            .send(&request("second"), server.local_addr())
+            .await
+            .unwrap();
+        let (_, first) = server.receive().await.unwrap();
+        let (message, second) = server.receive().await.unwrap();
+        assert_eq!(first, second);
+        assert!(
+            matches!(&message, Message::Request(request) if request.get_header_value("Call-ID") == Some("second"))
+        );
+        assert_eq!(client.flows().metrics().opened, 1);
+
+        // The server answers over the client's connection
+        server.send(&request("back"), first).await.unwrap();
+        let (message, _) = client.receive().await.unwrap();
+        assert!(
+            matches!(&message, Message::Request(request) if request.get_header_value("Call-ID") == Some("back"))
+        );
+        assert_eq!(server.flows().metrics().opened, 1);
+    }
+}
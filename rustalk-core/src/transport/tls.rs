@@ -1,64 +1,41 @@
 //! TLS/mTLS Transport implementation for secure SIP (SIPS)
-
-use super::{Transport, TransportConfig};
-use crate::sip::{parser::parse_message, Message};
-use anyhow::Result;
-use rustls::{ClientConfig, ServerConfig};
-use rustls_pemfile::{certs, pkcs8_private_keys};
-use std::fs::File;
-use std::io::BufReader;
+//!
+//! SIP over TLS is SIP over TCP inside a TLS session (RFC 3261 section
+//! 26.2), so the listener is a [`TcpTransport`] that handshakes each
+//! connection it accepts. Peers are answered over the connections they
+//! opened; requests for a peer without one fail, as there is no client
+//! certificate to dial out with.
+
+use super::keepalive::FlowTable;
+use super::{TcpTransport, Transport, TransportConfig};
+use crate::io;
+use crate::sip::Message;
+use anyhow::{Context, Result};
+use rustls::ClientConfig;
+use std::io::{BufReader as PemReader, Cursor};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tracing::{debug, info};
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::info;
 
 pub struct TlsTransport {
-    local_addr: SocketAddr,
-    server_config: Arc<ServerConfig>,
+    inner: TcpTransport,
 }
 
 impl TlsTransport {
     pub async fn new(config: &TransportConfig) -> Result<Self> {
-        let server_config = Self::load_server_config(
-            config
-                .cert_path
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Missing cert_path"))?,
-            config
-                .key_path
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Missing key_path"))?,
-        )?;
-
-        info!("TLS transport configured for {}", config.bind_addr);
-
-        Ok(Self {
-            local_addr: config.bind_addr,
-            server_config: Arc::new(server_config),
-        })
-    }
-
-    fn load_server_config(cert_path: &str, key_path: &str) -> Result<ServerConfig> {
-        // Load certificate chain
-        let cert_file = File::open(cert_path)?;
-        let mut cert_reader = BufReader::new(cert_file);
-        let cert_chain: Vec<_> = certs(&mut cert_reader).collect::<Result<_, _>>()?;
+        let inner = TcpTransport::start(config, Some(acceptor(config).await?)).await?;
 
-        // Load private key
-        let key_file = File::open(key_path)?;
-        let mut key_reader = BufReader::new(key_file);
-        let keys: Vec<_> = pkcs8_private_keys(&mut key_reader).collect::<Result<_, _>>()?;
+        info!("TLS transport listening on {}", inner.local_addr());
 
-        let private_key = keys
-            .into_iter()
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("No private key found"))?;
-
-        let config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(cert_chain, private_key.into())?;
+        Ok(Self { inner })
+    }
 
-        Ok(config)
+    /// Open connections and their keep-alive state
+    pub fn flows(&self) -> &FlowTable {
+        self.inner.flows()
     }
 
     /// Create TLS config for Microsoft Teams mTLS
@@ -73,25 +50,134 @@ impl TlsTransport {
     }
 }
 
+/// Server side of TLS with the listener's certificate and key
+pub(crate) async fn acceptor(config: &TransportConfig) -> Result<TlsAcceptor> {
+    let cert_path = config
+        .cert_path
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Missing cert_path"))?;
+    let key_path = config
+        .key_path
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Missing key_path"))?;
+
+    let pem = io::fs::read(cert_path).await?;
+    let certs = rustls_pemfile::certs(&mut PemReader::new(Cursor::new(pem)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let pem = io::fs::read(key_path).await?;
+    let key = rustls_pemfile::private_key(&mut PemReader::new(Cursor::new(pem)))?
+        .ok_or_else(|| anyhow::anyhow!("No private key in {}", key_path))?;
+    let server_config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Certificate and key do not match")?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
 #[async_trait::async_trait]
 impl Transport for TlsTransport {
-    async fn send(&self, _message: &Message, dest: SocketAddr) -> Result<()> {
-        // TLS sending implementation
-        // This is simplified - actual implementation would maintain TLS connections
-        debug!("TLS send to {} (simplified)", dest);
-        Ok(())
+    async fn send(&self, message: &Message, dest: SocketAddr) -> Result<()> {
+        self.inner.send(message, dest).await
     }
 
     async fn receive(&self) -> Result<(Message, SocketAddr)> {
-        // TLS receiving implementation
-        // This is simplified - actual implementation would accept TLS connections
-        debug!("TLS receive (simplified)");
-
-        // Placeholder for now
-        Err(anyhow::anyhow!("TLS receive not fully implemented"))
+        self.inner.receive().await
     }
 
     fn local_addr(&self) -> SocketAddr {
-        self.local_addr
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Method, Request, Uri};
+    use crate::transport::tcp::{Frame, StreamFramer};
+    use crate::transport::TransportProtocol;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_tls_receive_and_reply() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("rustalk-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        let transport = TlsTransport::new(&TransportConfig {
+            protocol: TransportProtocol::Tls,
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            cert_path: Some(cert_path.display().to_string()),
+            key_path: Some(key_path.display().to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let mut roots = tokio_rustls::rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client_config =
+            tokio_rustls::rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let tcp = TcpStream::connect(transport.local_addr()).await.unwrap();
+        let source = tcp.local_addr().unwrap();
+        let mut client = tokio_rustls::TlsConnector::from(Arc::new(client_config))
+            .connect("localhost".try_into().unwrap(), tcp)
+            .await
+            .unwrap();
+        client
+            .write_all(
+                b"OPTIONS sip:example.com SIP/2.0\r\nCall-ID: tls1\r\nContent-Length: 0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let (message, from) = transport.receive().await.unwrap();
+        assert_eq!(from, source);
+        assert!(
+            matches!(&message, Message::Request(request) if request.get_header_value("Call-ID") == Some("tls1"))
+        );
+
+        // The reply goes back down the client's session
+        let reply = Request::new(
+            Method::Options,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        )
+        .with_header("Call-ID", "tls-reply");
+        transport
+            .send(&Message::Request(reply.clone()), from)
+            .await
+            .unwrap();
+        let mut framer = StreamFramer::new();
+        let mut buf = vec![0u8; 4096];
+        let frame = loop {
+            if let Some(frame) = framer.next_frame().unwrap() {
+                break frame;
+            }
+            let len = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            framer.push(&buf[..len]);
+        };
+        assert!(
+            matches!(&frame, Frame::Message(bytes) if String::from_utf8_lossy(bytes).contains("tls-reply"))
+        );
+        assert_eq!(transport.flows().metrics().active, 1);
+
+        // A peer that never connected cannot be dialed
+        let stranger: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        assert!(transport
+            .send(&Message::Request(reply), stranger)
+            .await
+            .is_err());
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -1,28 +1,28 @@
 //! UDP Transport implementation
 
-use super::{Transport, TransportConfig};
+use super::keepalive::is_keepalive;
+use super::{bind_udp, Transport, TransportConfig};
 use crate::sip::{parser::parse_message, Message};
 use anyhow::Result;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
-use tracing::{debug, error};
+use tracing::debug;
 
 pub struct UdpTransport {
-    socket: Arc<Mutex<UdpSocket>>,
+    socket: Arc<UdpSocket>,
     local_addr: SocketAddr,
 }
 
 impl UdpTransport {
     pub async fn new(config: &TransportConfig) -> Result<Self> {
-        let socket = UdpSocket::bind(config.bind_addr).await?;
+        let socket = bind_udp(config.bind_addr, config.reuse_port)?;
         let local_addr = socket.local_addr()?;
 
         debug!("UDP transport listening on {}", local_addr);
 
         Ok(Self {
-            socket: Arc::new(Mutex::new(socket)),
+            socket: Arc::new(socket),
             local_addr,
         })
     }
@@ -36,8 +36,7 @@ impl Transport for UdpTransport {
             Message::Response(res) => res.to_bytes(),
         };
 
-        let socket = self.socket.lock().await;
-        socket.send_to(&bytes, dest).await?;
+        self.socket.send_to(&bytes, dest).await?;
 
         debug!("Sent {} bytes to {}", bytes.len(), dest);
         Ok(())
@@ -46,9 +45,16 @@ impl Transport for UdpTransport {
     async fn receive(&self) -> Result<(Message, SocketAddr)> {
         let mut buf = vec![0u8; 65535];
 
-        let socket = self.socket.lock().await;
-        let (len, addr) = socket.recv_from(&mut buf).await?;
-        drop(socket);
+        // UdpSocket is safe to use concurrently, so sends are never blocked
+        // behind a pending receive
+        let (len, addr) = loop {
+            let (len, addr) = self.socket.recv_from(&mut buf).await?;
+            // NAT keep-alives carry no message and need no answer
+            if !is_keepalive(&buf[..len]) {
+                break (len, addr);
+            }
+            debug!("Keep-alive from {}", addr);
+        };
 
         buf.truncate(len);
 
@@ -64,3 +70,35 @@ impl Transport for UdpTransport {
         self.local_addr
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_receive_skips_keepalives() {
+        let transport = UdpTransport::new(&TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let options =
+            b"OPTIONS sip:example.com SIP/2.0\r\nCall-ID: ka1\r\nContent-Length: 0\r\n\r\n";
+        client
+            .send_to(b"\r\n\r\n", transport.local_addr())
+            .await
+            .unwrap();
+        client
+            .send_to(options, transport.local_addr())
+            .await
+            .unwrap();
+
+        let (message, source) = transport.receive().await.unwrap();
+        assert_eq!(source, client.local_addr().unwrap());
+        assert!(
+            matches!(message, Message::Request(request) if request.get_header_value("Call-ID") == Some("ka1"))
+        );
+    }
+}
@@ -0,0 +1,929 @@
+//! WebSocket transport for SIP (RFC 7118)
+//!
+//! Browser softphones cannot open raw UDP or TCP sockets, so WebRTC clients
+//! carry SIP over a WebSocket instead: an HTTP request upgraded with the
+//! `sip` subprotocol (RFC 6455 section 4), after which every WebSocket
+//! message holds exactly one SIP message and needs no Content-Length to be
+//! delimited. Clients connect to us and cannot be dialed back, so requests
+//! for a client go down the connection it opened, found by its address in
+//! the [`FlowTable`]. WebSocket ping frames take the place of CRLF
+//! keep-alives. [`WssTransport`] runs the same over TLS.
+
+use super::keepalive::{CloseReason, FlowTable, FlowTransport, Keepalive};
+use super::{bind_tcp, Transport, TransportConfig, TransportProtocol};
+use crate::sip::{parser::parse_message, LocalProfile, Message};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+/// Subprotocol a SIP client asks for in its upgrade (RFC 7118 section 4.1)
+pub const SIP_SUBPROTOCOL: &str = "sip";
+
+/// Appended to the client's key to prove the upgrade was understood (RFC
+/// 6455 section 1.3)
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest upgrade request read before giving up on a connection
+const MAX_HANDSHAKE_SIZE: usize = 8192;
+
+/// Largest WebSocket message buffered from a connection
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// How long a new connection has to complete its upgrade
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often flows are checked for due pings and idle timeouts
+const FLOW_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Kind of a WebSocket frame (RFC 6455 section 5.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Self::Continuation),
+            0x1 => Some(Self::Text),
+            0x2 => Some(Self::Binary),
+            0x8 => Some(Self::Close),
+            0x9 => Some(Self::Ping),
+            0xA => Some(Self::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            Self::Continuation => 0x0,
+            Self::Text => 0x1,
+            Self::Binary => 0x2,
+            Self::Close => 0x8,
+            Self::Ping => 0x9,
+            Self::Pong => 0xA,
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        matches!(self, Self::Close | Self::Ping | Self::Pong)
+    }
+}
+
+/// A complete WebSocket message, or a control frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WsFrame {
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+/// Encode a single, final frame
+///
+/// Frames we send are unmasked; clients must mask theirs with `mask`.
+pub fn encode_frame(opcode: Opcode, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode.as_u8()];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(key) => {
+            frame.extend_from_slice(&key);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+/// Reassembles the frames a client sends into messages
+#[derive(Debug, Default)]
+pub struct WsFramer {
+    buf: Vec<u8>,
+    /// Data message whose continuation frames are still arriving
+    partial: Option<WsFrame>,
+    /// Whether this is the client end, which receives unmasked frames
+    client: bool,
+}
+
+impl WsFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Framer for connections we open, where the server's frames are not
+    /// masked
+    pub fn for_client() -> Self {
+        Self {
+            client: true,
+            ..Self::default()
+        }
+    }
+
+    /// Add bytes read from the connection
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Next complete message or control frame, or `None` until more bytes
+    /// arrive
+    ///
+    /// Errors are protocol violations, after which the connection should be
+    /// closed.
+    pub fn next_frame(&mut self) -> Result<Option<WsFrame>> {
+        loop {
+            let Some((fin, opcode, payload)) = self.next_raw()? else {
+                return Ok(None);
+            };
+            if opcode.is_control() {
+                if !fin || payload.len() > 125 {
+                    bail!("Fragmented or oversized control frame");
+                }
+                return Ok(Some(WsFrame { opcode, payload }));
+            }
+            let message = match (opcode, self.partial.take()) {
+                (Opcode::Continuation, Some(mut partial)) => {
+                    partial.payload.extend_from_slice(&payload);
+                    if partial.payload.len() > MAX_MESSAGE_SIZE {
+                        bail!("Message of {} bytes is too large", partial.payload.len());
+                    }
+                    partial
+                }
+                (Opcode::Continuation, None) => bail!("Continuation frame without a message"),
+                (_, Some(_)) => bail!("New message before the last one finished"),
+                (opcode, None) => WsFrame { opcode, payload },
+            };
+            if fin {
+                return Ok(Some(message));
+            }
+            self.partial = Some(message);
+        }
+    }
+
+    /// Header fields and unmasked payload of the next frame
+    fn next_raw(&mut self) -> Result<Option<(bool, Opcode, Vec<u8>)>> {
+        if self.buf.len() < 2 {
+            return Ok(None);
+        }
+        let fin = self.buf[0] & 0x80 != 0;
+        if self.buf[0] & 0x70 != 0 {
+            bail!("Reserved bits set without a negotiated extension");
+        }
+        let opcode = Opcode::from_u8(self.buf[0] & 0x0F)
+            .ok_or_else(|| anyhow::anyhow!("Unknown opcode {:#x}", self.buf[0] & 0x0F))?;
+        let masked = self.buf[1] & 0x80 != 0;
+        if masked == self.client {
+            bail!(
+                "{} frame from a {}",
+                if masked { "Masked" } else { "Unmasked" },
+                if self.client { "server" } else { "client" }
+            );
+        }
+        let (len, mut offset) = match self.buf[1] & 0x7F {
+            126 => {
+                let Some(bytes) = self.buf.get(2..4) else {
+                    return Ok(None);
+                };
+                (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 4)
+            }
+            127 => {
+                let Some(bytes) = self.buf.get(2..10) else {
+                    return Ok(None);
+                };
+                (u64::from_be_bytes(bytes.try_into().unwrap()), 10)
+            }
+            len => (len as u64, 2),
+        };
+        if len > MAX_MESSAGE_SIZE as u64 {
+            bail!("Frame of {} bytes is too large", len);
+        }
+        let len = len as usize;
+        let mut key = [0u8; 4];
+        if masked {
+            let Some(bytes) = self.buf.get(offset..offset + 4) else {
+                return Ok(None);
+            };
+            key.copy_from_slice(bytes);
+            offset += 4;
+        }
+        if self.buf.len() < offset + len {
+            return Ok(None);
+        }
+        let payload = self.buf[offset..offset + len]
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ key[i % 4])
+            .collect();
+        self.buf.drain(..offset + len);
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+/// `Sec-WebSocket-Accept` for a client's `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(HANDSHAKE_GUID.as_bytes());
+    BASE64.encode(hasher.finalize())
+}
+
+/// `101 Switching Protocols` for an upgrade request asking for the `sip`
+/// subprotocol
+pub fn upgrade_response(request: &str) -> Result<String> {
+    let mut lines = request.lines();
+    let request_line = lines.next().unwrap_or_default();
+    if !request_line.starts_with("GET ") {
+        bail!("Not an upgrade request: {}", request_line);
+    }
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    };
+    let lists = |name: &str, token: &str| {
+        header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    };
+
+    if !lists("Upgrade", "websocket") {
+        bail!("No WebSocket upgrade requested");
+    }
+    let key = header("Sec-WebSocket-Key").context("No Sec-WebSocket-Key")?;
+    if !lists("Sec-WebSocket-Protocol", SIP_SUBPROTOCOL) {
+        bail!("Client did not offer the sip subprotocol");
+    }
+    Ok(format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\
+         Sec-WebSocket-Protocol: {}\r\n\r\n",
+        accept_key(key),
+        SIP_SUBPROTOCOL
+    ))
+}
+
+/// Read a client's upgrade request and answer it, returning any bytes it
+/// sent after the request
+async fn accept_upgrade<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buf.len() > MAX_HANDSHAKE_SIZE {
+            bail!("Upgrade request too large");
+        }
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            bail!("Connection closed during the upgrade");
+        }
+        buf.extend_from_slice(&chunk[..len]);
+    };
+    let request = String::from_utf8_lossy(&buf[..end]).into_owned();
+    match upgrade_response(&request) {
+        Ok(response) => {
+            stream.write_all(response.as_bytes()).await?;
+            Ok(buf.split_off(end))
+        }
+        Err(e) => {
+            let _ = stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                .await;
+            Err(e)
+        }
+    }
+}
+
+/// An open connection: frames queued for its writer, and its reader
+struct Connection {
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    reader: AbortHandle,
+}
+
+/// State shared by the listener, the connections and senders
+struct Shared {
+    transport: FlowTransport,
+    flows: FlowTable,
+    /// Open connections by flow token
+    connections: Mutex<HashMap<String, Connection>>,
+    incoming: mpsc::UnboundedSender<(Message, SocketAddr)>,
+}
+
+impl Shared {
+    /// Start reading and writing an upgraded connection; `leftover` is
+    /// what the client sent right after its upgrade request
+    fn attach<S>(self: &Arc<Self>, stream: S, remote: SocketAddr, leftover: Vec<u8>)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let token = self.flows.open(self.transport, remote);
+        let (mut read_half, mut write_half) = tokio::io::split(stream);
+        let (outgoing, mut queue) = mpsc::unbounded_channel::<Vec<u8>>();
+
+        tokio::spawn(async move {
+            while let Some(bytes) = queue.recv().await {
+                if let Err(e) = write_half.write_all(&bytes).await {
+                    debug!("WebSocket write to {} failed: {}", remote, e);
+                    break;
+                }
+            }
+            let _ = write_half.shutdown().await;
+        });
+
+        let shared = self.clone();
+        let reader_token = token.clone();
+        let reply = outgoing.clone();
+        let reader = tokio::spawn(async move {
+            let mut framer = WsFramer::new();
+            framer.push(&leftover);
+            let mut buf = vec![0u8; 8192];
+            loop {
+                match shared.deliver(&mut framer, &reader_token, remote, &reply) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        warn!("Closing WebSocket from {}: {:#}", remote, e);
+                        let _ =
+                            reply.send(encode_frame(Opcode::Close, &1002u16.to_be_bytes(), None));
+                        break;
+                    }
+                }
+                let len = match read_half.read(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(e) => {
+                        debug!("WebSocket read from {} failed: {}", remote, e);
+                        break;
+                    }
+                };
+                framer.push(&buf[..len]);
+            }
+            shared.detach(&reader_token, CloseReason::Closed);
+        });
+
+        self.connections.lock().unwrap().insert(
+            token,
+            Connection {
+                outgoing,
+                reader: reader.abort_handle(),
+            },
+        );
+        debug!("WebSocket from {} open", remote);
+    }
+
+    /// Hand every complete message in `framer` to the receiver, answering
+    /// pings on the way; `false` once the client has closed
+    fn deliver(
+        &self,
+        framer: &mut WsFramer,
+        token: &str,
+        remote: SocketAddr,
+        reply: &mpsc::UnboundedSender<Vec<u8>>,
+    ) -> Result<bool> {
+        while let Some(frame) = framer.next_frame()? {
+            match frame.opcode {
+                Opcode::Ping => {
+                    self.flows.received(token, Some(Keepalive::Ping));
+                    let _ = reply.send(encode_frame(Opcode::Pong, &frame.payload, None));
+                }
+                Opcode::Pong => {
+                    self.flows.received(token, Some(Keepalive::Pong));
+                }
+                Opcode::Close => {
+                    // Echo the status code back (RFC 6455 section 5.5.1)
+                    let status = frame.payload.get(..2).unwrap_or_default();
+                    let _ = reply.send(encode_frame(Opcode::Close, status, None));
+                    return Ok(false);
+                }
+                Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                    self.flows.received(token, None);
+                    match parse_message(&frame.payload) {
+                        Ok(message) => {
+                            let _ = self.incoming.send((message, remote));
+                        }
+                        Err(e) => debug!("Dropping unreadable message from {}: {}", remote, e),
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Forget a connection, stopping its reader; its writer ends once the
+    /// queue is dropped
+    fn detach(&self, token: &str, reason: CloseReason) {
+        if let Some(connection) = self.connections.lock().unwrap().remove(token) {
+            connection.reader.abort();
+        }
+        if let Some(flow) = self.flows.close(token, reason) {
+            debug!("WebSocket from {} closed ({:?})", flow.remote, reason);
+        }
+    }
+
+    /// Writer queue of the open connection from `remote`
+    fn connection_from(&self, remote: SocketAddr) -> Option<mpsc::UnboundedSender<Vec<u8>>> {
+        let token = self.flows.find(self.transport, remote)?;
+        let connections = self.connections.lock().unwrap();
+        connections
+            .get(&token)
+            .map(|c| c.outgoing.clone())
+            .filter(|outgoing| !outgoing.is_closed())
+    }
+
+    /// Ping quiet connections and close those that stayed silent
+    fn check_flows(&self) {
+        for token in self.flows.due_pings() {
+            if let Some(connection) = self.connections.lock().unwrap().get(&token) {
+                let _ = connection
+                    .outgoing
+                    .send(encode_frame(Opcode::Ping, &[], None));
+            }
+        }
+        for flow in self.flows.expire() {
+            if let Some(connection) = self.connections.lock().unwrap().remove(&flow.token) {
+                connection.reader.abort();
+            }
+            debug!("WebSocket from {} timed out", flow.remote);
+        }
+    }
+}
+
+pub struct WsTransport {
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    incoming: tokio::sync::Mutex<mpsc::UnboundedReceiver<(Message, SocketAddr)>>,
+    /// Accept loop and flow checks, stopped when the transport is dropped
+    tasks: Vec<AbortHandle>,
+}
+
+impl WsTransport {
+    pub async fn new(config: &TransportConfig) -> Result<Self> {
+        Self::start(config, None).await
+    }
+
+    async fn start(config: &TransportConfig, tls: Option<TlsAcceptor>) -> Result<Self> {
+        let listener = bind_tcp(config.bind_addr, config.reuse_port)?;
+        let local_addr = listener.local_addr()?;
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            transport: if tls.is_some() {
+                FlowTransport::Wss
+            } else {
+                FlowTransport::Ws
+            },
+            flows: FlowTable::new(config.keepalive.clone().unwrap_or_default()),
+            connections: Mutex::new(HashMap::new()),
+            incoming: incoming_tx,
+        });
+
+        let accept = tokio::spawn(accept_loop(listener, tls, shared.clone()));
+        let checks = {
+            let shared = shared.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(FLOW_CHECK_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    shared.check_flows();
+                }
+            })
+        };
+
+        debug!("WebSocket transport listening on {}", local_addr);
+
+        Ok(Self {
+            local_addr,
+            shared,
+            incoming: tokio::sync::Mutex::new(incoming),
+            tasks: vec![accept.abort_handle(), checks.abort_handle()],
+        })
+    }
+
+    /// Open connections and their keep-alive state
+    pub fn flows(&self) -> &FlowTable {
+        &self.shared.flows
+    }
+
+    /// Via and Contact for requests sent over this transport, naming
+    /// `transport=ws` or `transport=wss`
+    pub fn local_profile(&self, host: impl Into<String>) -> LocalProfile {
+        let protocol = match self.shared.transport {
+            FlowTransport::Wss => TransportProtocol::Wss,
+            _ => TransportProtocol::Ws,
+        };
+        LocalProfile::new(protocol.as_str(), host, self.local_addr.port())
+    }
+}
+
+impl Drop for WsTransport {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        for (_, connection) in self.shared.connections.lock().unwrap().drain() {
+            connection.reader.abort();
+        }
+    }
+}
+
+async fn accept_loop(listener: TcpListener, tls: Option<TlsAcceptor>, shared: Arc<Shared>) {
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("WebSocket accept failed: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let tls = tls.clone();
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let upgraded = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+                match tls {
+                    Some(tls) => {
+                        let mut stream =
+                            tls.accept(stream).await.context("TLS handshake failed")?;
+                        let leftover = accept_upgrade(&mut stream).await?;
+                        shared.attach(stream, remote, leftover);
+                    }
+                    None => {
+                        let mut stream = stream;
+                        let leftover = accept_upgrade(&mut stream).await?;
+                        shared.attach(stream, remote, leftover);
+                    }
+                }
+                anyhow::Ok(())
+            })
+            .await;
+            match upgraded {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Refused WebSocket from {}: {:#}", remote, e),
+                Err(_) => debug!("WebSocket from {} did not upgrade in time", remote),
+            }
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for WsTransport {
+    async fn send(&self, message: &Message, dest: SocketAddr) -> Result<()> {
+        let bytes = match message {
+            Message::Request(req) => req.to_bytes(),
+            Message::Response(res) => res.to_bytes(),
+        };
+        let Some(outgoing) = self.shared.connection_from(dest) else {
+            bail!("No WebSocket connection from {}", dest);
+        };
+        let opcode = if std::str::from_utf8(&bytes).is_ok() {
+            Opcode::Text
+        } else {
+            Opcode::Binary
+        };
+        let len = bytes.len();
+        outgoing
+            .send(encode_frame(opcode, &bytes, None))
+            .map_err(|_| anyhow::anyhow!("WebSocket from {} is closed", dest))?;
+
+        debug!("Sent {} bytes to {}", len, dest);
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<(Message, SocketAddr)> {
+        self.incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("WebSocket transport closed"))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+/// SIP over secure WebSocket, for clients on HTTPS pages
+pub struct WssTransport {
+    inner: WsTransport,
+}
+
+impl WssTransport {
+    pub async fn new(config: &TransportConfig) -> Result<Self> {
+        let inner = WsTransport::start(config, Some(super::tls::acceptor(config).await?)).await?;
+        Ok(Self { inner })
+    }
+
+    /// Open connections and their keep-alive state
+    pub fn flows(&self) -> &FlowTable {
+        self.inner.flows()
+    }
+
+    /// Via and Contact for requests sent over this transport
+    pub fn local_profile(&self, host: impl Into<String>) -> LocalProfile {
+        self.inner.local_profile(host)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for WssTransport {
+    async fn send(&self, message: &Message, dest: SocketAddr) -> Result<()> {
+        self.inner.send(message, dest).await
+    }
+
+    async fn receive(&self) -> Result<(Message, SocketAddr)> {
+        self.inner.receive().await
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{MessageBuilder, Method, Request, StatusCode, Uri};
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::crypto::ring::default_provider;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    const REGISTER: &str = "REGISTER sip:example.com SIP/2.0\r\n\
+                            Via: SIP/2.0/WSS df7jal23ls0d.invalid;branch=z9hG4bKasudf\r\n\
+                            From: <sip:alice@example.com>;tag=65bnmj.34asd\r\n\
+                            To: <sip:alice@example.com>\r\n\
+                            Call-ID: aiuy7k9njasd\r\n\
+                            CSeq: 1 REGISTER\r\n\
+                            Contact: <sip:alice@df7jal23ls0d.invalid;transport=ws>\r\n\r\n";
+
+    fn config() -> TransportConfig {
+        TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            protocol: TransportProtocol::Ws,
+            ..Default::default()
+        }
+    }
+
+    fn upgrade_request(protocol: &str) -> String {
+        format!(
+            "GET / HTTP/1.1\r\n\
+             Host: sip.example.com\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Protocol: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            protocol
+        )
+    }
+
+    /// Read frames the server sends, which are never masked
+    async fn read_frames(client: &mut TcpStream, count: usize) -> Vec<(Opcode, Vec<u8>)> {
+        let mut buf = Vec::new();
+        let mut frames = Vec::new();
+        let mut chunk = [0u8; 4096];
+        while frames.len() < count {
+            let len = tokio::time::timeout(Duration::from_secs(5), client.read(&mut chunk))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(len > 0, "connection closed");
+            buf.extend_from_slice(&chunk[..len]);
+            while buf.len() >= 2 {
+                let (len, offset) = match buf[1] {
+                    126 => (u16::from_be_bytes([buf[2], buf[3]]) as usize, 4),
+                    len => (len as usize, 2),
+                };
+                if buf.len() < offset + len {
+                    break;
+                }
+                let opcode = Opcode::from_u8(buf[0] & 0x0F).unwrap();
+                frames.push((opcode, buf[offset..offset + len].to_vec()));
+                buf.drain(..offset + len);
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn test_upgrade_response() {
+        // RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        let response = upgrade_response(&upgrade_request("sip")).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(response.contains("Sec-WebSocket-Protocol: sip\r\n"));
+        assert!(upgrade_response(&upgrade_request("xmpp")).is_err());
+    }
+
+    #[test]
+    fn test_frame_reassembly() {
+        let mut framer = WsFramer::new();
+        let mut first = encode_frame(Opcode::Text, b"REGISTER sip:", Some(MASK));
+        first[0] &= 0x7F;
+        framer.push(&first);
+        // A ping between the fragments is answered first
+        framer.push(&encode_frame(Opcode::Ping, b"hi", Some(MASK)));
+        let rest = encode_frame(Opcode::Continuation, b"example.com", Some(MASK));
+        framer.push(&rest[..5]);
+        assert_eq!(
+            framer.next_frame().unwrap(),
+            Some(WsFrame {
+                opcode: Opcode::Ping,
+                payload: b"hi".to_vec()
+            })
+        );
+        assert_eq!(framer.next_frame().unwrap(), None);
+        framer.push(&rest[5..]);
+        assert_eq!(
+            framer.next_frame().unwrap(),
+            Some(WsFrame {
+                opcode: Opcode::Text,
+                payload: b"REGISTER sip:example.com".to_vec()
+            })
+        );
+
+        // Long payloads use the 16-bit length
+        let long = vec![b'a'; 300];
+        framer.push(&encode_frame(Opcode::Binary, &long, Some(MASK)));
+        assert_eq!(framer.next_frame().unwrap().unwrap().payload, long);
+
+        // Clients must mask
+        framer.push(&encode_frame(Opcode::Text, b"x", None));
+        assert!(framer.next_frame().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ws_register_and_reply() {
+        let transport = WsTransport::new(&config()).await.unwrap();
+        let mut client = TcpStream::connect(transport.local_addr()).await.unwrap();
+        client
+            .write_all(upgrade_request("sip").as_bytes())
+            .await
+            .unwrap();
+        let mut response = vec![0u8; 1024];
+        let len = client.read(&mut response).await.unwrap();
+        assert!(String::from_utf8_lossy(&response[..len]).starts_with("HTTP/1.1 101"));
+
+        client
+            .write_all(&encode_frame(Opcode::Ping, b"", Some(MASK)))
+            .await
+            .unwrap();
+        client
+            .write_all(&encode_frame(Opcode::Text, REGISTER.as_bytes(), Some(MASK)))
+            .await
+            .unwrap();
+        let (message, source) = transport.receive().await.unwrap();
+        assert_eq!(source, client.local_addr().unwrap());
+        let Message::Request(request) = message else {
+            panic!("expected a request");
+        };
+        assert_eq!(request.method, Method::Register);
+
+        // The reply goes back down the client's connection as one message
+        transport
+            .send(
+                &Message::Response(
+                    MessageBuilder::new(transport.local_profile("sip.example.com"))
+                        .response(&request, StatusCode::OK)
+                        .build(),
+                ),
+                source,
+            )
+            .await
+            .unwrap();
+        let frames = read_frames(&mut client, 2).await;
+        assert_eq!(frames[0], (Opcode::Pong, Vec::new()));
+        assert_eq!(frames[1].0, Opcode::Text);
+        let reply = parse_message(&frames[1].1).unwrap();
+        assert!(
+            matches!(reply, Message::Response(r) if r.get_header_value("Call-ID") == Some("aiuy7k9njasd"))
+        );
+        assert_eq!(transport.flows().metrics().pings_received, 1);
+
+        // Clients cannot be dialed
+        let request = Request::new(
+            Method::Options,
+            Uri::new("sip".to_string(), "example.com".to_string()),
+        );
+        assert!(transport
+            .send(&Message::Request(request), "127.0.0.1:9".parse().unwrap())
+            .await
+            .is_err());
+
+        client
+            .write_all(&encode_frame(
+                Opcode::Close,
+                &1000u16.to_be_bytes(),
+                Some(MASK),
+            ))
+            .await
+            .unwrap();
+        let frames = read_frames(&mut client, 1).await;
+        assert_eq!(frames[0], (Opcode::Close, 1000u16.to_be_bytes().to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_wss_upgrade() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("rustalk-wss-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        let transport = WssTransport::new(&TransportConfig {
+            protocol: TransportProtocol::Wss,
+            cert_path: Some(cert_path.display().to_string()),
+            key_path: Some(key_path.display().to_string()),
+            ..config()
+        })
+        .await
+        .unwrap();
+
+        let mut roots = tokio_rustls::rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client_config =
+            tokio_rustls::rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let tcp = TcpStream::connect(transport.local_addr()).await.unwrap();
+        let mut client = tokio_rustls::TlsConnector::from(Arc::new(client_config))
+            .connect("localhost".try_into().unwrap(), tcp)
+            .await
+            .unwrap();
+        client
+            .write_all(upgrade_request("sip").as_bytes())
+            .await
+            .unwrap();
+        let mut response = vec![0u8; 1024];
+        let len = client.read(&mut response).await.unwrap();
+        assert!(String::from_utf8_lossy(&response[..len]).starts_with("HTTP/1.1 101"));
+        client
+            .write_all(&encode_frame(Opcode::Text, REGISTER.as_bytes(), Some(MASK)))
+            .await
+            .unwrap();
+        let (message, _) = transport.receive().await.unwrap();
+        assert!(matches!(message, Message::Request(r) if r.method == Method::Register));
+        assert_eq!(transport.flows().metrics().active, 1);
+        assert_eq!(
+            transport.local_profile("sip.example.com").contact(),
+            format!(
+                "<sip:sip.example.com:{};transport=wss>",
+                transport.local_addr().port()
+            )
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ws_advertised_in_via_and_contact() {
+        let profile = LocalProfile::new(TransportProtocol::Ws.as_str(), "sip.example.com", 5066);
+        let request = MessageBuilder::new(profile.clone())
+            .request(Method::Options, "sip:alice@example.com".parse().unwrap())
+            .build();
+        assert!(request
+            .get_header_value("Via")
+            .unwrap()
+            .starts_with("SIP/2.0/WS sip.example.com:5066;branch="));
+        assert_eq!(profile.contact(), "<sip:sip.example.com:5066;transport=ws>");
+    }
+}
@@ -0,0 +1,625 @@
+//! Trunk health monitoring
+//!
+//! Each monitored trunk is sent a SIP OPTIONS on its interval. Any final
+//! answer except `503 Service Unavailable` means the trunk is reachable;
+//! a 503, a timeout or a network error counts as a failure. Consecutive
+//! failures take a trunk from `up` to `degraded` and then `down`, and
+//! consecutive answers bring a down trunk back up. Routes whose
+//! destination is a down trunk are passed over, so calls fall through to
+//! the next matching route. Each change of state is logged and raised as
+//! an alarm on the event stream.
+//!
+//! The probe loop takes any OPTIONS exchange, so the Teams gateway pings
+//! the Teams SIP proxies over mutual TLS through the same state machine.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::events::{AlarmEvent, AlarmSeverity, EventBus, SystemEvent};
+use crate::io;
+use crate::sip::builder::{LocalProfile, MessageBuilder};
+use crate::sip::parser::parse_message;
+use crate::sip::{Message, Method, Request, Response, Uri};
+use crate::transport::tcp::{stream_bytes, Frame, StreamFramer};
+
+/// Probe intervals and the failures that change a trunk's state
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TrunkHealthConfig {
+    /// Seconds between OPTIONS to each trunk
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    /// How long to wait for an answer
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Consecutive failures after which a trunk is degraded
+    #[serde(default = "default_degraded_after")]
+    pub degraded_after: u32,
+    /// Consecutive failures after which a trunk is down and routed around
+    #[serde(default = "default_down_after")]
+    pub down_after: u32,
+    /// Consecutive answers after which a down trunk is up again
+    #[serde(default = "default_up_after")]
+    pub up_after: u32,
+    #[serde(default)]
+    pub trunks: Vec<MonitoredTrunk>,
+}
+
+fn default_interval_seconds() -> u64 {
+    30
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_degraded_after() -> u32 {
+    1
+}
+
+fn default_down_after() -> u32 {
+    3
+}
+
+fn default_up_after() -> u32 {
+    2
+}
+
+impl Default for TrunkHealthConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: default_interval_seconds(),
+            timeout_ms: default_timeout_ms(),
+            degraded_after: default_degraded_after(),
+            down_after: default_down_after(),
+            up_after: default_up_after(),
+            trunks: Vec::new(),
+        }
+    }
+}
+
+impl TrunkHealthConfig {
+    /// Thresholds for `trunk`, with its own settings over the defaults
+    fn thresholds(&self, trunk: &str) -> Thresholds {
+        let own = self.trunks.iter().find(|t| t.name == trunk);
+        Thresholds {
+            degraded_after: own
+                .and_then(|t| t.degraded_after)
+                .unwrap_or(self.degraded_after),
+            down_after: own.and_then(|t| t.down_after).unwrap_or(self.down_after),
+            up_after: self.up_after,
+        }
+    }
+
+    fn interval(&self, trunk: &MonitoredTrunk) -> Duration {
+        Duration::from_secs(
+            trunk
+                .interval_seconds
+                .unwrap_or(self.interval_seconds)
+                .max(1),
+        )
+    }
+}
+
+/// A trunk sent OPTIONS
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MonitoredTrunk {
+    /// Trunk name, as route destinations give it
+    pub name: String,
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub transport: ProbeTransport,
+    /// Seconds between OPTIONS; the section's interval when unset
+    #[serde(default)]
+    pub interval_seconds: Option<u64>,
+    /// Failures before degraded; the section's threshold when unset
+    #[serde(default)]
+    pub degraded_after: Option<u32>,
+    /// Failures before down; the section's threshold when unset
+    #[serde(default)]
+    pub down_after: Option<u32>,
+}
+
+fn default_port() -> u16 {
+    5060
+}
+
+/// Transport OPTIONS are sent over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeTransport {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Thresholds {
+    degraded_after: u32,
+    down_after: u32,
+    up_after: u32,
+}
+
+/// Health of a trunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TrunkState {
+    Up,
+    /// Failing to answer, but still routed to
+    Degraded,
+    /// Routed around until it answers again
+    Down,
+}
+
+impl fmt::Display for TrunkState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Up => "up",
+            Self::Degraded => "degraded",
+            Self::Down => "down",
+        })
+    }
+}
+
+/// A trunk's state and its latest probe
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrunkStatus {
+    pub trunk: String,
+    pub state: TrunkState,
+    /// When the trunk entered its state
+    pub since: DateTime<Utc>,
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+    pub last_probe_at: Option<DateTime<Utc>>,
+    /// Answer to the last OPTIONS, or why there was none
+    pub last_result: Option<String>,
+    /// Round trip of the last answered OPTIONS
+    pub last_rtt_ms: Option<u64>,
+}
+
+impl TrunkStatus {
+    fn new(trunk: &str, now: DateTime<Utc>) -> Self {
+        Self {
+            trunk: trunk.to_string(),
+            state: TrunkState::Up,
+            since: now,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+            last_probe_at: None,
+            last_result: None,
+            last_rtt_ms: None,
+        }
+    }
+}
+
+/// A trunk changing state
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrunkTransition {
+    pub trunk: String,
+    pub from: TrunkState,
+    pub to: TrunkState,
+    /// Result of the probe that changed it
+    pub detail: String,
+}
+
+impl TrunkTransition {
+    pub fn message(&self) -> String {
+        format!(
+            "Trunk {} is {} (was {}): {}",
+            self.trunk, self.to, self.from, self.detail
+        )
+    }
+}
+
+/// Trunk states, shared between the probes, the router and the API
+#[derive(Clone)]
+pub struct TrunkHealth {
+    config: TrunkHealthConfig,
+    state: Arc<Mutex<BTreeMap<String, TrunkStatus>>>,
+    events: Option<EventBus>,
+}
+
+impl TrunkHealth {
+    pub fn new(config: TrunkHealthConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(BTreeMap::new())),
+            events: None,
+        }
+    }
+
+    /// Raise an alarm when a trunk changes state
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn config(&self) -> &TrunkHealthConfig {
+        &self.config
+    }
+
+    /// State of `trunk`; trunks not yet probed are up
+    pub fn state(&self, trunk: &str) -> TrunkState {
+        self.state
+            .lock()
+            .unwrap()
+            .get(trunk)
+            .map_or(TrunkState::Up, |s| s.state)
+    }
+
+    pub fn is_down(&self, trunk: &str) -> bool {
+        self.state(trunk) == TrunkState::Down
+    }
+
+    /// Every probed trunk, by name
+    pub fn statuses(&self) -> Vec<TrunkStatus> {
+        self.state.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Record the outcome of an OPTIONS to `trunk`: the answer and its
+    /// round trip, or why there was none
+    ///
+    /// Returns the change of state it caused, if any.
+    pub fn record(
+        &self,
+        trunk: &str,
+        outcome: Result<(String, Duration)>,
+    ) -> Option<TrunkTransition> {
+        self.record_at(trunk, outcome, Utc::now())
+    }
+
+    fn record_at(
+        &self,
+        trunk: &str,
+        outcome: Result<(String, Duration)>,
+        now: DateTime<Utc>,
+    ) -> Option<TrunkTransition> {
+        let thresholds = self.config.thresholds(trunk);
+        let mut state = self.state.lock().unwrap();
+        let status = state
+            .entry(trunk.to_string())
+            .or_insert_with(|| TrunkStatus::new(trunk, now));
+        status.last_probe_at = Some(now);
+        let from = status.state;
+        let (to, detail) = match outcome {
+            Ok((answer, rtt)) => {
+                status.consecutive_successes += 1;
+                status.consecutive_failures = 0;
+                status.last_rtt_ms = Some(rtt.as_millis() as u64);
+                let to = match from {
+                    TrunkState::Down if status.consecutive_successes < thresholds.up_after => {
+                        TrunkState::Down
+                    }
+                    _ => TrunkState::Up,
+                };
+                (to, answer)
+            }
+            Err(e) => {
+                status.consecutive_failures += 1;
+                status.consecutive_successes = 0;
+                let failures = status.consecutive_failures;
+                let to = if failures >= thresholds.down_after {
+                    TrunkState::Down
+                } else if failures >= thresholds.degraded_after && from == TrunkState::Up {
+                    TrunkState::Degraded
+                } else {
+                    from
+                };
+                (to, format!("{:#}", e))
+            }
+        };
+        status.last_result = Some(detail.clone());
+        if to == from {
+            return None;
+        }
+        status.state = to;
+        status.since = now;
+        Some(TrunkTransition {
+            trunk: trunk.to_string(),
+            from,
+            to,
+            detail,
+        })
+    }
+
+    /// Send OPTIONS to each configured trunk on its interval
+    pub fn spawn(&self) -> Vec<JoinHandle<()>> {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        self.config
+            .trunks
+            .iter()
+            .map(|trunk| {
+                let every = self.config.interval(trunk);
+                let target = trunk.clone();
+                self.spawn_probe(&trunk.name, every, move || {
+                    let target = target.clone();
+                    async move { options(&target, timeout).await }
+                })
+            })
+            .collect()
+    }
+
+    /// Run `probe` against `trunk` every `every`, recording its outcomes
+    ///
+    /// The probe returns the answer to its OPTIONS, or an error when the
+    /// trunk did not answer or answered that it is unavailable.
+    pub fn spawn_probe<F, Fut>(&self, trunk: &str, every: Duration, probe: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        tokio::spawn(self.clone().probe_loop(trunk.to_string(), every, probe))
+    }
+
+    /// The loop [`spawn_probe`](Self::spawn_probe) runs, for callers that
+    /// supervise their own tasks
+    pub async fn probe_loop<F, Fut>(self, trunk: String, every: Duration, probe: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            let started = Instant::now();
+            let outcome = probe().await.map(|answer| (answer, started.elapsed()));
+            if let Some(transition) = self.record(&trunk, outcome) {
+                self.announce(&transition);
+            }
+        }
+    }
+
+    fn announce(&self, transition: &TrunkTransition) {
+        let message = transition.message();
+        let severity = match transition.to {
+            TrunkState::Up => {
+                info!("{}", message);
+                return;
+            }
+            TrunkState::Degraded => AlarmSeverity::Warning,
+            TrunkState::Down => AlarmSeverity::Critical,
+        };
+        warn!("{}", message);
+        if let Some(bus) = &self.events {
+            bus.publish_system(SystemEvent::Alarm(AlarmEvent::new(
+                severity,
+                "trunk_health",
+                transition.trunk.as_str(),
+                message,
+            )));
+        }
+    }
+}
+
+impl Default for TrunkHealth {
+    fn default() -> Self {
+        Self::new(TrunkHealthConfig::default())
+    }
+}
+
+/// Send an OPTIONS to `trunk` and wait for its final answer
+///
+/// Fails when there is none within `timeout` or the trunk answers 503.
+pub async fn options(trunk: &MonitoredTrunk, timeout: Duration) -> Result<String> {
+    let addr = io::dns::lookup_host(&trunk.host, trunk.port)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("{} does not resolve", trunk.host))?;
+    let exchange = async {
+        match trunk.transport {
+            ProbeTransport::Udp => options_udp(trunk, addr).await,
+            ProbeTransport::Tcp => options_tcp(trunk, addr).await,
+        }
+    };
+    let response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| anyhow!("No answer from {} within {:?}", addr, timeout))??;
+    let status = response.status_code.0;
+    if status == 503 {
+        bail!("{} answered 503 {}", addr, response.reason_phrase);
+    }
+    Ok(format!("{} {}", status, response.reason_phrase))
+}
+
+fn request(trunk: &MonitoredTrunk, transport: &str, local: SocketAddr) -> Request {
+    let profile = LocalProfile::new(transport, local.ip().to_string(), local.port())
+        .with_user_agent(format!("RusTalk/{}", env!("CARGO_PKG_VERSION")));
+    let uri = Uri::new("sip".to_string(), trunk.host.clone()).with_port(trunk.port);
+    MessageBuilder::new(profile)
+        .request(Method::Options, uri)
+        .header("Accept", "application/sdp")
+        .build()
+}
+
+/// Whether `response` answers `request` finally
+fn answers(request: &Request, response: &Response) -> bool {
+    response.status_code.0 >= 200
+        && response.get_header_value("Call-ID") == request.get_header_value("Call-ID")
+}
+
+async fn options_udp(trunk: &MonitoredTrunk, addr: SocketAddr) -> Result<Response> {
+    let bind: SocketAddr = if addr.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(addr).await?;
+    let request = request(trunk, "UDP", socket.local_addr()?);
+    socket.send(&request.to_bytes()).await?;
+    let mut buf = vec![0u8; 65535];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        if let Ok(Message::Response(response)) = parse_message(&buf[..len]) {
+            if answers(&request, &response) {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+async fn options_tcp(trunk: &MonitoredTrunk, addr: SocketAddr) -> Result<Response> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = request(trunk, "TCP", stream.local_addr()?);
+    stream
+        .write_all(&stream_bytes(&Message::Request(request.clone())))
+        .await?;
+    let mut framer = StreamFramer::new();
+    let mut buf = vec![0u8; 8192];
+    loop {
+        while let Some(frame) = framer.next_frame()? {
+            let Frame::Message(bytes) = frame else {
+                continue;
+            };
+            if let Ok(Message::Response(response)) = parse_message(&bytes) {
+                if answers(&request, &response) {
+                    return Ok(response);
+                }
+            }
+        }
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            bail!("{} closed the connection", addr);
+        }
+        framer.push(&buf[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answered() -> Result<(String, Duration)> {
+        Ok(("200 OK".to_string(), Duration::from_millis(20)))
+    }
+
+    fn failed() -> Result<(String, Duration)> {
+        Err(anyhow!("No answer"))
+    }
+
+    #[test]
+    fn test_state_transitions() {
+        let health = TrunkHealth::new(TrunkHealthConfig {
+            degraded_after: 1,
+            down_after: 3,
+            up_after: 2,
+            ..Default::default()
+        });
+        assert_eq!(health.state("carrier"), TrunkState::Up);
+        assert!(health.record("carrier", answered()).is_none());
+
+        let degraded = health.record("carrier", failed()).unwrap();
+        assert_eq!(
+            (degraded.from, degraded.to),
+            (TrunkState::Up, TrunkState::Degraded)
+        );
+        assert!(health.record("carrier", failed()).is_none());
+        let down = health.record("carrier", failed()).unwrap();
+        assert_eq!(down.to, TrunkState::Down);
+        assert_eq!(
+            down.message(),
+            "Trunk carrier is down (was degraded): No answer"
+        );
+        assert!(health.is_down("carrier"));
+
+        // A down trunk needs several answers to come back
+        assert!(health.record("carrier", answered()).is_none());
+        assert!(health.is_down("carrier"));
+        let up = health.record("carrier", answered()).unwrap();
+        assert_eq!((up.from, up.to), (TrunkState::Down, TrunkState::Up));
+
+        // A degraded trunk recovers on its first answer
+        health.record("carrier", failed());
+        assert_eq!(health.state("carrier"), TrunkState::Degraded);
+        assert_eq!(
+            health.record("carrier", answered()).unwrap().to,
+            TrunkState::Up
+        );
+
+        let status = &health.statuses()[0];
+        assert_eq!(status.consecutive_successes, 1);
+        assert_eq!(status.last_result.as_deref(), Some("200 OK"));
+        assert_eq!(status.last_rtt_ms, Some(20));
+    }
+
+    #[test]
+    fn test_per_trunk_thresholds() {
+        let health = TrunkHealth::new(TrunkHealthConfig {
+            trunks: vec![MonitoredTrunk {
+                name: "backup".to_string(),
+                host: "192.0.2.50".to_string(),
+                port: 5060,
+                transport: ProbeTransport::Udp,
+                interval_seconds: Some(5),
+                degraded_after: None,
+                down_after: Some(1),
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            health.config().interval(&health.config().trunks[0]),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            health.record("backup", failed()).unwrap().to,
+            TrunkState::Down
+        );
+        assert_eq!(
+            health.record("primary", failed()).unwrap().to,
+            TrunkState::Degraded
+        );
+    }
+
+    #[tokio::test]
+    async fn test_options_probe() {
+        let far_end = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let trunk = MonitoredTrunk {
+            name: "carrier".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: far_end.local_addr().unwrap().port(),
+            transport: ProbeTransport::Udp,
+            interval_seconds: None,
+            degraded_after: None,
+            down_after: None,
+        };
+        let answering = tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (len, from) = far_end.recv_from(&mut buf).await.unwrap();
+                let Ok(Message::Request(request)) = parse_message(&buf[..len]) else {
+                    panic!("expected OPTIONS");
+                };
+                assert_eq!(request.method, Method::Options);
+                let mut response = format!("SIP/2.0 {}\r\n", status);
+                for name in ["Via", "From", "To", "Call-ID", "CSeq"] {
+                    let value = request.get_header_value(name).unwrap();
+                    response.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                response.push_str("Content-Length: 0\r\n\r\n");
+                far_end.send_to(response.as_bytes(), from).await.unwrap();
+            }
+        });
+
+        let timeout = Duration::from_secs(2);
+        assert_eq!(options(&trunk, timeout).await.unwrap(), "200 OK");
+        let error = options(&trunk, timeout).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("answered 503 Service Unavailable"));
+        answering.await.unwrap();
+    }
+}
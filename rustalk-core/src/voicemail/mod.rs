@@ -5,8 +5,18 @@
 
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+
+use crate::io;
+use crate::quotas::{QuotaResource, Quotas};
+
+pub mod retrieval;
+
+pub use retrieval::{RetrievalAction, RetrievalConfig, VoicemailRetrieval};
 
 /// Voicemail box configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +41,9 @@ pub struct VoicemailBox {
     pub greeting: VoicemailGreeting,
     /// Whether the mailbox is enabled
     pub enabled: bool,
+    /// Tenant (SIP domain) whose storage quota the messages count against
+    #[serde(default)]
+    pub tenant: Option<String>,
 }
 
 /// Voicemail greeting types
@@ -64,6 +77,9 @@ pub struct VoicemailMessage {
     pub duration: u32,
     /// Path to audio file
     pub file_path: String,
+    /// Size of the audio file
+    #[serde(default)]
+    pub size_bytes: u64,
     /// Whether message has been listened to
     pub read: bool,
     /// Whether message is marked as urgent
@@ -81,112 +97,254 @@ pub struct MwiStatus {
     pub old_messages: usize,
     /// Total messages
     pub total_messages: usize,
+    /// New messages marked urgent
+    #[serde(default)]
+    pub new_urgent: usize,
+    /// Old messages marked urgent
+    #[serde(default)]
+    pub old_urgent: usize,
+}
+
+impl MwiStatus {
+    /// Whether the message waiting lamp should be lit
+    pub fn messages_waiting(&self) -> bool {
+        self.new_messages > 0
+    }
+}
+
+/// Mailbox change events buffered for a slow subscriber before it misses
+/// some
+const CHANGES_CAPACITY: usize = 256;
+
+/// INVITE header asking for the callee's voicemail without ringing them
+pub const VOICEMAIL_DROP_HEADER: &str = "X-Voicemail-Drop";
+
+/// Straight-to-voicemail dialing
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VoicemailDropConfig {
+    /// Prefix dialed before the extension (e.g. `*551001`)
+    #[serde(default = "default_drop_prefix")]
+    pub prefix: String,
+}
+
+impl Default for VoicemailDropConfig {
+    fn default() -> Self {
+        Self {
+            prefix: default_drop_prefix(),
+        }
+    }
+}
+
+fn default_drop_prefix() -> String {
+    "*55".to_string()
+}
+
+impl VoicemailDropConfig {
+    /// Extension whose voicemail a dialed number asks for
+    pub fn target<'a>(&self, dialed: &'a str) -> Option<&'a str> {
+        if self.prefix.is_empty() {
+            return None;
+        }
+        dialed
+            .strip_prefix(self.prefix.as_str())
+            .filter(|ext| !ext.is_empty() && ext.chars().all(|c| c.is_ascii_digit()))
+    }
+}
+
+/// Whether a voicemail drop header value asks for voicemail
+pub fn wants_voicemail_drop(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "yes" | "true"
+    )
 }
 
 /// Voicemail system manager
+///
+/// Clones share the same mailboxes and messages, so the API and the B2BUA
+/// can each hold one. Audio files are written and removed without holding
+/// the lock.
 #[derive(Debug, Clone)]
 pub struct VoicemailManager {
     /// Base directory for voicemail storage
     base_dir: PathBuf,
-    /// Map of mailbox configurations
+    store: Arc<RwLock<VoicemailStore>>,
+    /// IDs of mailboxes whose messages changed
+    changes: broadcast::Sender<String>,
+    quotas: Option<Quotas>,
+}
+
+#[derive(Debug, Default)]
+struct VoicemailStore {
     mailboxes: Vec<VoicemailBox>,
-    /// Map of messages by mailbox
     messages: Vec<VoicemailMessage>,
 }
 
+impl VoicemailStore {
+    fn mailbox(&self, mailbox_id: &str) -> Option<&VoicemailBox> {
+        self.mailboxes.iter().find(|m| m.id == mailbox_id)
+    }
+
+    /// Bytes of messages stored in the mailboxes of `tenant`
+    fn storage_used(&self, tenant: &str) -> u64 {
+        let mailboxes: Vec<&str> = self
+            .mailboxes
+            .iter()
+            .filter(|m| {
+                m.tenant
+                    .as_deref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(tenant))
+            })
+            .map(|m| m.id.as_str())
+            .collect();
+        self.messages
+            .iter()
+            .filter(|m| mailboxes.contains(&m.mailbox_id.as_str()))
+            .map(|m| m.size_bytes)
+            .sum()
+    }
+
+    /// Check that a mailbox can take another message of `duration` seconds
+    /// and `size` bytes
+    fn check_accepts(
+        &self,
+        mailbox_id: &str,
+        duration: u32,
+        size: u64,
+        quotas: Option<&Quotas>,
+    ) -> Result<()> {
+        let mailbox = self
+            .mailbox(mailbox_id)
+            .context(format!("Mailbox not found: {}", mailbox_id))?;
+
+        if !mailbox.enabled {
+            anyhow::bail!("Mailbox is disabled");
+        }
+
+        let message_count = self
+            .messages
+            .iter()
+            .filter(|m| m.mailbox_id == mailbox_id)
+            .count();
+
+        if message_count >= mailbox.max_messages {
+            anyhow::bail!("Mailbox is full");
+        }
+
+        if duration > mailbox.max_message_length {
+            anyhow::bail!("Message too long");
+        }
+
+        if let (Some(quotas), Some(tenant)) = (quotas, &mailbox.tenant) {
+            let usage = self.storage_used(tenant) + size;
+            quotas.check(tenant, QuotaResource::Storage, usage)?;
+        }
+        Ok(())
+    }
+}
+
 impl VoicemailManager {
     /// Create a new voicemail manager
     pub fn new(base_dir: impl Into<PathBuf>) -> Self {
         Self {
             base_dir: base_dir.into(),
-            mailboxes: Vec::new(),
-            messages: Vec::new(),
+            store: Arc::new(RwLock::new(VoicemailStore::default())),
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
+            quotas: None,
         }
     }
 
+    /// Refuse messages that would take a tenant past its storage quota
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// Bytes of messages stored in the mailboxes of `tenant`
+    pub async fn storage_used(&self, tenant: &str) -> u64 {
+        self.store.read().await.storage_used(tenant)
+    }
+
+    /// IDs of mailboxes as their messages are left, read or deleted, for
+    /// message waiting indicators
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<String> {
+        self.changes.subscribe()
+    }
+
+    fn changed(&self, mailbox_id: &str) {
+        // Nobody listening is not an error
+        let _ = self.changes.send(mailbox_id.to_string());
+    }
+
     /// Add a mailbox
-    pub fn add_mailbox(&mut self, mailbox: VoicemailBox) -> Result<()> {
-        // Check if mailbox already exists
-        if self.mailboxes.iter().any(|m| m.id == mailbox.id) {
+    pub async fn add_mailbox(&self, mailbox: VoicemailBox) -> Result<()> {
+        if self.get_mailbox(&mailbox.id).await.is_some() {
             anyhow::bail!("Mailbox already exists: {}", mailbox.id);
         }
 
         // Create mailbox directory
-        let mailbox_dir = self.mailbox_dir(&mailbox.id);
-        std::fs::create_dir_all(&mailbox_dir).context("Failed to create mailbox directory")?;
+        io::fs::create_dir_all(self.mailbox_dir(&mailbox.id)).await?;
 
-        self.mailboxes.push(mailbox);
+        // Another request may have added it while the directory was created
+        let mut store = self.store.write().await;
+        if store.mailbox(&mailbox.id).is_some() {
+            anyhow::bail!("Mailbox already exists: {}", mailbox.id);
+        }
+        store.mailboxes.push(mailbox);
         Ok(())
     }
 
     /// Get a mailbox by ID
-    pub fn get_mailbox(&self, mailbox_id: &str) -> Option<&VoicemailBox> {
-        self.mailboxes.iter().find(|m| m.id == mailbox_id)
+    pub async fn get_mailbox(&self, mailbox_id: &str) -> Option<VoicemailBox> {
+        self.store.read().await.mailbox(mailbox_id).cloned()
     }
 
-    /// Remove a mailbox
-    pub fn remove_mailbox(&mut self, mailbox_id: &str) -> Result<bool> {
-        let pos = self.mailboxes.iter().position(|m| m.id == mailbox_id);
-
-        if let Some(pos) = pos {
-            self.mailboxes.remove(pos);
-
-            // Remove all messages for this mailbox
-            self.messages.retain(|m| m.mailbox_id != mailbox_id);
-
-            // Remove mailbox directory
-            let mailbox_dir = self.mailbox_dir(mailbox_id);
-            if mailbox_dir.exists() {
-                std::fs::remove_dir_all(&mailbox_dir)
-                    .context("Failed to remove mailbox directory")?;
-            }
+    /// Mailbox with the given ID, else the one of the given extension
+    pub async fn find_mailbox(&self, user: &str) -> Option<VoicemailBox> {
+        let store = self.store.read().await;
+        store
+            .mailbox(user)
+            .or_else(|| store.mailboxes.iter().find(|m| m.extension == user))
+            .cloned()
+    }
 
-            Ok(true)
-        } else {
-            Ok(false)
+    /// Remove a mailbox and its messages
+    pub async fn remove_mailbox(&self, mailbox_id: &str) -> Result<bool> {
+        {
+            let mut store = self.store.write().await;
+            let Some(pos) = store.mailboxes.iter().position(|m| m.id == mailbox_id) else {
+                return Ok(false);
+            };
+            store.mailboxes.remove(pos);
+            store.messages.retain(|m| m.mailbox_id != mailbox_id);
         }
+        self.changed(mailbox_id);
+
+        // Remove mailbox directory
+        io::fs::remove_dir_all(self.mailbox_dir(mailbox_id)).await?;
+        Ok(true)
     }
 
     /// Leave a voicemail message
-    pub fn leave_message(
-        &mut self,
+    pub async fn leave_message(
+        &self,
         mailbox_id: &str,
         from_number: &str,
         from_name: Option<String>,
         audio_data: &[u8],
         duration: u32,
     ) -> Result<String> {
-        let mailbox = self
-            .get_mailbox(mailbox_id)
-            .context(format!("Mailbox not found: {}", mailbox_id))?;
-
-        if !mailbox.enabled {
-            anyhow::bail!("Mailbox is disabled");
-        }
-
-        // Check message limits
-        let message_count = self
-            .messages
-            .iter()
-            .filter(|m| m.mailbox_id == mailbox_id)
-            .count();
-
-        if message_count >= mailbox.max_messages {
-            anyhow::bail!("Mailbox is full");
-        }
-
-        if duration > mailbox.max_message_length {
-            anyhow::bail!("Message too long");
-        }
-
-        // Generate message ID
-        let message_id = uuid::Uuid::new_v4().to_string();
+        let size = audio_data.len() as u64;
+        self.store
+            .read()
+            .await
+            .check_accepts(mailbox_id, duration, size, self.quotas.as_ref())?;
 
         // Save audio file
+        let message_id = uuid::Uuid::new_v4().to_string();
         let file_path = self.message_file_path(mailbox_id, &message_id);
-        std::fs::write(&file_path, audio_data).context("Failed to write audio file")?;
+        io::fs::write(&file_path, audio_data).await?;
 
-        // Create message record
         let message = VoicemailMessage {
             id: message_id.clone(),
             mailbox_id: mailbox_id.to_string(),
@@ -197,56 +355,79 @@ impl VoicemailManager {
             file_path: file_path.to_string_lossy().to_string(),
             read: false,
             urgent: false,
+            size_bytes: size,
         };
 
-        self.messages.push(message);
+        // Check again: the mailbox may have filled up or gone meanwhile
+        let mut store = self.store.write().await;
+        if let Err(e) = store.check_accepts(mailbox_id, duration, size, self.quotas.as_ref()) {
+            drop(store);
+            io::fs::remove_file(&file_path).await?;
+            return Err(e);
+        }
+        store.messages.push(message);
+        drop(store);
+        self.changed(mailbox_id);
 
         Ok(message_id)
     }
 
     /// Get messages for a mailbox
-    pub fn get_messages(&self, mailbox_id: &str, include_read: bool) -> Vec<&VoicemailMessage> {
-        self.messages
+    pub async fn get_messages(
+        &self,
+        mailbox_id: &str,
+        include_read: bool,
+    ) -> Vec<VoicemailMessage> {
+        self.store
+            .read()
+            .await
+            .messages
             .iter()
             .filter(|m| m.mailbox_id == mailbox_id && (include_read || !m.read))
+            .cloned()
             .collect()
     }
 
     /// Mark a message as read
-    pub fn mark_message_read(&mut self, message_id: &str) -> Result<()> {
-        let message = self
+    pub async fn mark_message_read(&self, message_id: &str) -> Result<()> {
+        let mut store = self.store.write().await;
+        let message = store
             .messages
             .iter_mut()
             .find(|m| m.id == message_id)
             .context("Message not found")?;
 
+        let changed = !message.read;
         message.read = true;
+        let mailbox_id = message.mailbox_id.clone();
+        drop(store);
+        if changed {
+            self.changed(&mailbox_id);
+        }
         Ok(())
     }
 
     /// Delete a message
-    pub fn delete_message(&mut self, message_id: &str) -> Result<()> {
-        let pos = self
-            .messages
-            .iter()
-            .position(|m| m.id == message_id)
-            .context("Message not found")?;
-
-        let message = &self.messages[pos];
+    pub async fn delete_message(&self, message_id: &str) -> Result<()> {
+        let message = {
+            let mut store = self.store.write().await;
+            let pos = store
+                .messages
+                .iter()
+                .position(|m| m.id == message_id)
+                .context("Message not found")?;
+            store.messages.remove(pos)
+        };
+        self.changed(&message.mailbox_id);
 
         // Delete audio file
-        let file_path = Path::new(&message.file_path);
-        if file_path.exists() {
-            std::fs::remove_file(file_path).context("Failed to delete audio file")?;
-        }
-
-        self.messages.remove(pos);
-        Ok(())
+        io::fs::remove_file(&message.file_path).await
     }
 
     /// Get MWI status for a mailbox
-    pub fn get_mwi_status(&self, mailbox_id: &str) -> MwiStatus {
-        let messages: Vec<_> = self
+    pub async fn get_mwi_status(&self, mailbox_id: &str) -> MwiStatus {
+        let store = self.store.read().await;
+        let messages: Vec<_> = store
             .messages
             .iter()
             .filter(|m| m.mailbox_id == mailbox_id)
@@ -254,22 +435,63 @@ impl VoicemailManager {
 
         let new_messages = messages.iter().filter(|m| !m.read).count();
         let old_messages = messages.iter().filter(|m| m.read).count();
+        let new_urgent = messages.iter().filter(|m| !m.read && m.urgent).count();
+        let old_urgent = messages.iter().filter(|m| m.read && m.urgent).count();
 
         MwiStatus {
             mailbox_id: mailbox_id.to_string(),
             new_messages,
             old_messages,
             total_messages: messages.len(),
+            new_urgent,
+            old_urgent,
         }
     }
 
     /// Verify PIN for mailbox access
-    pub fn verify_pin(&self, mailbox_id: &str, pin: &str) -> bool {
-        if let Some(mailbox) = self.get_mailbox(mailbox_id) {
-            mailbox.pin == pin
-        } else {
-            false
+    pub async fn verify_pin(&self, mailbox_id: &str, pin: &str) -> bool {
+        self.store
+            .read()
+            .await
+            .mailbox(mailbox_id)
+            .is_some_and(|mailbox| mailbox.pin == pin)
+    }
+
+    /// Change the PIN for a mailbox
+    pub async fn set_pin(&self, mailbox_id: &str, pin: &str) -> Result<()> {
+        if !(4..=10).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+            anyhow::bail!("PIN must be 4 to 10 digits");
         }
+
+        let mut store = self.store.write().await;
+        let mailbox = store
+            .mailboxes
+            .iter_mut()
+            .find(|m| m.id == mailbox_id)
+            .context(format!("Mailbox not found: {}", mailbox_id))?;
+
+        mailbox.pin = pin.to_string();
+        Ok(())
+    }
+
+    /// Store a recorded greeting for a mailbox and switch it to the custom
+    /// greeting
+    pub async fn set_greeting(&self, mailbox_id: &str, audio_data: &[u8]) -> Result<PathBuf> {
+        if self.get_mailbox(mailbox_id).await.is_none() {
+            anyhow::bail!("Mailbox not found: {}", mailbox_id);
+        }
+        let file_path = self.mailbox_dir(mailbox_id).join("greeting.wav");
+        io::fs::create_dir_all(self.mailbox_dir(mailbox_id)).await?;
+        io::fs::write(&file_path, audio_data).await?;
+
+        let mut store = self.store.write().await;
+        let mailbox = store
+            .mailboxes
+            .iter_mut()
+            .find(|m| m.id == mailbox_id)
+            .context(format!("Mailbox not found: {}", mailbox_id))?;
+        mailbox.greeting = VoicemailGreeting::Custom(file_path.to_string_lossy().to_string());
+        Ok(file_path)
     }
 
     /// Get mailbox directory path
@@ -284,8 +506,8 @@ impl VoicemailManager {
     }
 
     /// List all mailboxes
-    pub fn list_mailboxes(&self) -> Vec<&VoicemailBox> {
-        self.mailboxes.iter().collect()
+    pub async fn list_mailboxes(&self) -> Vec<VoicemailBox> {
+        self.store.read().await.mailboxes.clone()
     }
 }
 
@@ -302,6 +524,7 @@ impl Default for VoicemailBox {
             max_message_length: 300, // 5 minutes
             greeting: VoicemailGreeting::Default,
             enabled: true,
+            tenant: None,
         }
     }
 }
@@ -311,8 +534,21 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_create_mailbox() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+    fn test_voicemail_drop_target() {
+        let config = VoicemailDropConfig::default();
+        assert_eq!(config.target("*551001"), Some("1001"));
+        assert_eq!(config.target("1001"), None);
+        assert_eq!(config.target("*55"), None);
+        assert_eq!(config.target("*55abc"), None);
+
+        assert!(wants_voicemail_drop("Yes"));
+        assert!(wants_voicemail_drop("1"));
+        assert!(!wants_voicemail_drop("no"));
+    }
+
+    #[tokio::test]
+    async fn test_create_mailbox() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -322,13 +558,13 @@ mod tests {
             ..Default::default()
         };
 
-        assert!(manager.add_mailbox(mailbox.clone()).is_ok());
-        assert!(manager.get_mailbox("1001").is_some());
+        assert!(manager.add_mailbox(mailbox.clone()).await.is_ok());
+        assert!(manager.get_mailbox("1001").await.is_some());
     }
 
-    #[test]
-    fn test_leave_message() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+    #[tokio::test]
+    async fn test_leave_message() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -338,22 +574,23 @@ mod tests {
             ..Default::default()
         };
 
-        manager.add_mailbox(mailbox).unwrap();
+        manager.add_mailbox(mailbox).await.unwrap();
 
         let audio_data = b"fake audio data";
-        let result =
-            manager.leave_message("1001", "5551234", Some("Bob".to_string()), audio_data, 10);
+        let result = manager
+            .leave_message("1001", "5551234", Some("Bob".to_string()), audio_data, 10)
+            .await;
 
         assert!(result.is_ok());
 
-        let messages = manager.get_messages("1001", false);
+        let messages = manager.get_messages("1001", false).await;
         assert_eq!(messages.len(), 1);
         assert_eq!(messages[0].from_number, "5551234");
     }
 
-    #[test]
-    fn test_mwi_status() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+    #[tokio::test]
+    async fn test_mwi_status() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -363,26 +600,28 @@ mod tests {
             ..Default::default()
         };
 
-        manager.add_mailbox(mailbox).unwrap();
+        manager.add_mailbox(mailbox).await.unwrap();
 
         // Leave two messages
         let audio_data = b"fake audio data";
         manager
             .leave_message("1001", "5551234", None, audio_data, 10)
+            .await
             .unwrap();
         manager
             .leave_message("1001", "5555678", None, audio_data, 15)
+            .await
             .unwrap();
 
-        let status = manager.get_mwi_status("1001");
+        let status = manager.get_mwi_status("1001").await;
         assert_eq!(status.new_messages, 2);
         assert_eq!(status.old_messages, 0);
         assert_eq!(status.total_messages, 2);
     }
 
-    #[test]
-    fn test_mark_message_read() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+    #[tokio::test]
+    async fn test_mark_message_read() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -392,23 +631,24 @@ mod tests {
             ..Default::default()
         };
 
-        manager.add_mailbox(mailbox).unwrap();
+        manager.add_mailbox(mailbox).await.unwrap();
 
         let audio_data = b"fake audio data";
         let message_id = manager
             .leave_message("1001", "5551234", None, audio_data, 10)
+            .await
             .unwrap();
 
-        manager.mark_message_read(&message_id).unwrap();
+        manager.mark_message_read(&message_id).await.unwrap();
 
-        let status = manager.get_mwi_status("1001");
+        let status = manager.get_mwi_status("1001").await;
         assert_eq!(status.new_messages, 0);
         assert_eq!(status.old_messages, 1);
     }
 
-    #[test]
-    fn test_delete_message() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+    #[tokio::test]
+    async fn test_delete_message() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -418,22 +658,23 @@ mod tests {
             ..Default::default()
         };
 
-        manager.add_mailbox(mailbox).unwrap();
+        manager.add_mailbox(mailbox).await.unwrap();
 
         let audio_data = b"fake audio data";
         let message_id = manager
             .leave_message("1001", "5551234", None, audio_data, 10)
+            .await
             .unwrap();
 
-        manager.delete_message(&message_id).unwrap();
+        manager.delete_message(&message_id).await.unwrap();
 
-        let messages = manager.get_messages("1001", true);
+        let messages = manager.get_messages("1001", true).await;
         assert_eq!(messages.len(), 0);
     }
 
-    #[test]
-    fn test_verify_pin() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+    #[tokio::test]
+    async fn test_verify_pin() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -443,15 +684,15 @@ mod tests {
             ..Default::default()
         };
 
-        manager.add_mailbox(mailbox).unwrap();
+        manager.add_mailbox(mailbox).await.unwrap();
 
-        assert!(manager.verify_pin("1001", "1234"));
-        assert!(!manager.verify_pin("1001", "0000"));
+        assert!(manager.verify_pin("1001", "1234").await);
+        assert!(!manager.verify_pin("1001", "0000").await);
     }
 
-    #[test]
-    fn test_mailbox_full() {
-        let mut manager = VoicemailManager::new("/tmp/voicemail_test");
+    #[tokio::test]
+    async fn test_mailbox_full() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
 
         let mailbox = VoicemailBox {
             id: "1001".to_string(),
@@ -462,18 +703,129 @@ mod tests {
             ..Default::default()
         };
 
-        manager.add_mailbox(mailbox).unwrap();
+        manager.add_mailbox(mailbox).await.unwrap();
 
         let audio_data = b"fake audio data";
         manager
             .leave_message("1001", "5551234", None, audio_data, 10)
+            .await
             .unwrap();
         manager
             .leave_message("1001", "5555678", None, audio_data, 10)
+            .await
             .unwrap();
 
         // Third message should fail
-        let result = manager.leave_message("1001", "5559999", None, audio_data, 10);
+        let result = manager
+            .leave_message("1001", "5559999", None, audio_data, 10)
+            .await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_tenant_storage_quota() {
+        let quotas = Quotas::new(
+            serde_json::from_value(serde_json::json!({
+                "tenants": { "acme.example": { "max_storage_mb": 1 } }
+            }))
+            .unwrap(),
+        );
+        let manager = VoicemailManager::new("/tmp/voicemail_quota_test").with_quotas(quotas);
+        for (id, tenant) in [
+            ("3001", Some("acme.example")),
+            ("3002", Some("ACME.example")),
+            ("3003", None),
+        ] {
+            manager
+                .add_mailbox(VoicemailBox {
+                    id: id.to_string(),
+                    extension: id.to_string(),
+                    tenant: tenant.map(str::to_string),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        let audio = vec![0u8; 600 * 1024];
+        manager
+            .leave_message("3001", "5551234", None, &audio, 10)
+            .await
+            .unwrap();
+        assert_eq!(manager.storage_used("acme.example").await, 600 * 1024);
+
+        // The tenant's mailboxes share its quota; others are not counted
+        let error = manager
+            .leave_message("3002", "5551234", None, &audio, 10)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("quota"), "{}", error);
+        manager
+            .leave_message("3003", "5551234", None, &audio, 10)
+            .await
+            .unwrap();
+        assert_eq!(manager.storage_used("acme.example").await, 600 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_set_pin() {
+        let manager = VoicemailManager::new("/tmp/voicemail_test");
+
+        let mailbox = VoicemailBox {
+            id: "1002".to_string(),
+            extension: "1002".to_string(),
+            name: "Bob".to_string(),
+            pin: "1234".to_string(),
+            ..Default::default()
+        };
+
+        manager.add_mailbox(mailbox).await.unwrap();
+
+        manager.set_pin("1002", "908172").await.unwrap();
+        assert!(manager.verify_pin("1002", "908172").await);
+        assert!(!manager.verify_pin("1002", "1234").await);
+
+        assert!(manager.set_pin("1002", "12").await.is_err());
+        assert!(manager.set_pin("1002", "12ab").await.is_err());
+        assert!(manager.set_pin("9999", "5678").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_state() {
+        let manager = VoicemailManager::new(std::env::temp_dir().join("rustalk_vm_shared"));
+        let api = manager.clone();
+
+        api.add_mailbox(VoicemailBox {
+            id: "3001".to_string(),
+            extension: "3001".to_string(),
+            max_messages: 3,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(manager.get_mailbox("3001").await.is_some());
+
+        // Concurrent callers never overfill the mailbox
+        let calls: Vec<_> = (0..8)
+            .map(|i| {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    manager
+                        .leave_message("3001", &format!("555000{}", i), None, b"audio", 5)
+                        .await
+                })
+            })
+            .collect();
+        let mut accepted = 0;
+        for call in calls {
+            if call.await.unwrap().is_ok() {
+                accepted += 1;
+            }
+        }
+        assert_eq!(accepted, 3);
+        assert_eq!(api.get_mwi_status("3001").await.new_messages, 3);
+
+        assert!(api.remove_mailbox("3001").await.unwrap());
+        assert!(manager.get_messages("3001", true).await.is_empty());
+    }
 }
@@ -0,0 +1,619 @@
+//! Voicemail retrieval IVR
+//!
+//! Subscribers dial the retrieval feature code (`*98`) to listen to their
+//! messages. Calling from an extension that has a mailbox, or dialing the
+//! code followed by a mailbox number (`*981001`), goes straight to the PIN
+//! prompt; otherwise the caller first enters the mailbox number. Both are
+//! ended with `#`. After logging in, new messages play before saved ones and
+//! the dial pad moves through them:
+//!
+//! | Key | Action                                   |
+//! |-----|------------------------------------------|
+//! | 1   | Play the current message again           |
+//! | 3   | Call back the caller who left it         |
+//! | 4   | Previous message                         |
+//! | 5   | Record a new greeting                    |
+//! | 6   | Next message                             |
+//! | 7   | Delete the message                       |
+//! | 9   | Save the message (mark it heard)         |
+//! | *   | Help                                     |
+//! | #   | Hang up                                  |
+//!
+//! A greeting is recorded after the tone until `#` saves it; `*` keeps the
+//! old one.
+//!
+//! Each key press returns the prompts and recordings to play next, along
+//! with any callback to place or recording to make. Prompts are spoken in the call's locale,
+//! chosen when the call arrives (see [`crate::locale`]).
+
+use super::{VoicemailManager, VoicemailMessage};
+use crate::locale::{LocaleContext, LocalizedPrompt, PromptSet};
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Longest mailbox number or PIN collected before `#`
+const MAX_DIGITS: usize = 20;
+
+/// Voicemail retrieval configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RetrievalConfig {
+    /// Code dialed to reach the retrieval IVR
+    #[serde(default = "default_feature_code")]
+    pub feature_code: String,
+    /// Wrong PINs allowed before the call is ended
+    #[serde(default = "default_max_pin_attempts")]
+    pub max_pin_attempts: u32,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        Self {
+            feature_code: default_feature_code(),
+            max_pin_attempts: default_max_pin_attempts(),
+        }
+    }
+}
+
+fn default_feature_code() -> String {
+    "*98".to_string()
+}
+
+fn default_max_pin_attempts() -> u32 {
+    3
+}
+
+/// Something for the call to do after a key press
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetrievalAction {
+    /// Speak a prompt, or play its recording
+    Prompt(LocalizedPrompt),
+    /// Play a recorded message
+    Play {
+        message_id: String,
+        file_path: String,
+    },
+    /// Call `number` back on behalf of `extension`
+    Callback { extension: String, number: String },
+    /// Start recording what the caller says, after a tone
+    Record,
+    /// Stop recording and keep it as the greeting of `mailbox_id`, or
+    /// discard it when `mailbox_id` is `None`
+    FinishRecording { mailbox_id: Option<String> },
+    /// End the call
+    Hangup,
+}
+
+impl fmt::Display for RetrievalAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetrievalAction::Prompt(prompt) => {
+                write!(f, "prompt \"{}\" ({})", prompt, prompt.locale)
+            }
+            RetrievalAction::Play { message_id, .. } => write!(f, "play message {}", message_id),
+            RetrievalAction::Callback { extension, number } => {
+                write!(f, "call back {} for {}", number, extension)
+            }
+            RetrievalAction::Record => write!(f, "record"),
+            RetrievalAction::FinishRecording {
+                mailbox_id: Some(mailbox_id),
+            } => write!(f, "save greeting of {}", mailbox_id),
+            RetrievalAction::FinishRecording { mailbox_id: None } => {
+                write!(f, "discard recording")
+            }
+            RetrievalAction::Hangup => write!(f, "hang up"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Mailbox,
+    Pin,
+    Menu,
+    Greeting,
+}
+
+/// Progress of one retrieval call
+#[derive(Debug)]
+struct RetrievalCall {
+    stage: Stage,
+    digits: String,
+    mailbox: String,
+    /// Mailbox came from the caller or the dialed number, not the keypad
+    mailbox_known: bool,
+    failed_pins: u32,
+    /// Extension of the logged-in mailbox, for callbacks
+    extension: String,
+    messages: Vec<VoicemailMessage>,
+    current: usize,
+    /// Locale prompts are spoken in
+    locale: String,
+}
+
+/// Retrieval IVR state for every call in it
+#[derive(Debug, Clone)]
+pub struct VoicemailRetrieval {
+    config: RetrievalConfig,
+    manager: VoicemailManager,
+    prompts: PromptSet,
+    calls: Arc<Mutex<HashMap<String, RetrievalCall>>>,
+}
+
+impl VoicemailRetrieval {
+    pub fn new(config: RetrievalConfig, manager: VoicemailManager) -> Self {
+        Self {
+            config,
+            manager,
+            prompts: PromptSet::default(),
+            calls: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Speak prompts from `prompts` rather than the built-in text alone
+    pub fn with_prompts(mut self, prompts: PromptSet) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
+    pub fn config(&self) -> &RetrievalConfig {
+        &self.config
+    }
+
+    /// Whether a dialed number reaches the retrieval IVR
+    pub fn is_feature_code(&self, dialed: &str) -> bool {
+        !self.config.feature_code.is_empty() && dialed.starts_with(&self.config.feature_code)
+    }
+
+    /// Whether a call is in the IVR
+    pub fn is_active(&self, call_id: &str) -> bool {
+        self.calls.lock().unwrap().contains_key(call_id)
+    }
+
+    /// Answer a call from `caller` to the feature code, in the default
+    /// locale
+    pub async fn start(&self, call_id: &str, caller: &str, dialed: &str) -> Vec<RetrievalAction> {
+        self.start_for(call_id, caller, dialed, &LocaleContext::default())
+            .await
+    }
+
+    /// Answer a call from `caller` to the feature code, in the locale
+    /// `context` selects
+    pub async fn start_for(
+        &self,
+        call_id: &str,
+        caller: &str,
+        dialed: &str,
+        context: &LocaleContext<'_>,
+    ) -> Vec<RetrievalAction> {
+        let explicit = dialed
+            .strip_prefix(self.config.feature_code.as_str())
+            .filter(|mailbox| !mailbox.is_empty());
+        let mailbox = match explicit {
+            Some(mailbox) => Some(mailbox.to_string()),
+            None if self.manager.get_mailbox(caller).await.is_some() => Some(caller.to_string()),
+            None => None,
+        };
+
+        let call = RetrievalCall {
+            stage: if mailbox.is_some() {
+                Stage::Pin
+            } else {
+                Stage::Mailbox
+            },
+            digits: String::new(),
+            mailbox_known: mailbox.is_some(),
+            mailbox: mailbox.unwrap_or_default(),
+            failed_pins: 0,
+            extension: String::new(),
+            messages: Vec::new(),
+            current: 0,
+            locale: self.prompts.select(context),
+        };
+        let prompt = match call.stage {
+            Stage::Mailbox => self.prompt(&call, "vm-enter-mailbox", &[]),
+            _ => self.prompt(&call, "vm-enter-pin", &[]),
+        };
+        self.calls.lock().unwrap().insert(call_id.to_string(), call);
+        vec![prompt]
+    }
+
+    /// Handle a key pressed on a call, or `None` if the call is not in the IVR
+    pub async fn press(&self, call_id: &str, digit: char) -> Option<Vec<RetrievalAction>> {
+        // Taken out while it is handled so the lock is not held across IO
+        let mut call = self.calls.lock().unwrap().remove(call_id)?;
+        let actions = match call.stage {
+            Stage::Mailbox => self.collect_mailbox(&mut call, digit),
+            Stage::Pin => self.collect_pin(&mut call, digit).await,
+            Stage::Menu => self.menu(&mut call, digit).await,
+            Stage::Greeting => self.record_greeting(&mut call, digit),
+        };
+        if !actions.contains(&RetrievalAction::Hangup) {
+            self.calls.lock().unwrap().insert(call_id.to_string(), call);
+        }
+        Some(actions)
+    }
+
+    /// Store a greeting recorded on a call
+    pub async fn save_greeting(&self, mailbox_id: &str, audio: &[u8]) -> Result<PathBuf> {
+        self.manager.set_greeting(mailbox_id, audio).await
+    }
+
+    /// Forget a call that hung up, returning whether it was in the IVR
+    pub fn end(&self, call_id: &str) -> bool {
+        self.calls.lock().unwrap().remove(call_id).is_some()
+    }
+
+    /// A prompt in the call's locale
+    fn prompt(&self, call: &RetrievalCall, key: &str, params: &[(&str, &str)]) -> RetrievalAction {
+        RetrievalAction::Prompt(self.prompts.render(key, &call.locale, params))
+    }
+
+    fn collect_mailbox(&self, call: &mut RetrievalCall, digit: char) -> Vec<RetrievalAction> {
+        match digit {
+            '#' if !call.digits.is_empty() => {
+                call.mailbox = std::mem::take(&mut call.digits);
+                call.stage = Stage::Pin;
+                vec![self.prompt(call, "vm-enter-pin", &[])]
+            }
+            '#' | '*' => {
+                call.digits.clear();
+                vec![self.prompt(call, "vm-enter-mailbox", &[])]
+            }
+            _ => {
+                push_digit(&mut call.digits, digit);
+                Vec::new()
+            }
+        }
+    }
+
+    async fn collect_pin(&self, call: &mut RetrievalCall, digit: char) -> Vec<RetrievalAction> {
+        if digit == '*' {
+            call.digits.clear();
+            return vec![self.prompt(call, "vm-enter-pin", &[])];
+        }
+        if digit != '#' {
+            push_digit(&mut call.digits, digit);
+            return Vec::new();
+        }
+
+        let pin = std::mem::take(&mut call.digits);
+        // An unknown mailbox and a wrong PIN sound the same to the caller
+        if self.manager.verify_pin(&call.mailbox, &pin).await {
+            return self.log_in(call).await;
+        }
+
+        call.failed_pins += 1;
+        warn!(
+            "Wrong voicemail PIN for mailbox {} ({} of {})",
+            call.mailbox, call.failed_pins, self.config.max_pin_attempts
+        );
+        if call.failed_pins >= self.config.max_pin_attempts {
+            return vec![
+                self.prompt(call, "vm-login-incorrect-goodbye", &[]),
+                RetrievalAction::Hangup,
+            ];
+        }
+        let mut actions = vec![self.prompt(call, "vm-login-incorrect", &[])];
+        if call.mailbox_known {
+            actions.push(self.prompt(call, "vm-enter-pin", &[]));
+        } else {
+            call.stage = Stage::Mailbox;
+            actions.push(self.prompt(call, "vm-enter-mailbox", &[]));
+        }
+        actions
+    }
+
+    async fn log_in(&self, call: &mut RetrievalCall) -> Vec<RetrievalAction> {
+        call.stage = Stage::Menu;
+        call.extension = self
+            .manager
+            .get_mailbox(&call.mailbox)
+            .await
+            .map(|mailbox| mailbox.extension)
+            .unwrap_or_else(|| call.mailbox.clone());
+        call.messages = self.manager.get_messages(&call.mailbox, true).await;
+        // New messages first, oldest first within each
+        call.messages.sort_by_key(|m| (m.read, m.timestamp));
+        call.current = 0;
+
+        let new = call.messages.iter().filter(|m| !m.read).count();
+        let saved = call.messages.len() - new;
+        let mut actions = vec![self.prompt(
+            call,
+            "vm-message-count",
+            &[("new", &new.to_string()), ("saved", &saved.to_string())],
+        )];
+        actions.extend(self.play_current(call));
+        actions
+    }
+
+    async fn menu(&self, call: &mut RetrievalCall, digit: char) -> Vec<RetrievalAction> {
+        if digit == '#' {
+            return vec![
+                self.prompt(call, "vm-goodbye", &[]),
+                RetrievalAction::Hangup,
+            ];
+        }
+        if digit == '4' {
+            if call.current == 0 {
+                return vec![self.prompt(call, "vm-no-previous", &[])];
+            }
+            call.current = (call.current - 1).min(call.messages.len().saturating_sub(1));
+            return self.play_current(call);
+        }
+        if digit == '5' {
+            call.stage = Stage::Greeting;
+            return vec![
+                self.prompt(call, "vm-record-greeting", &[]),
+                RetrievalAction::Record,
+            ];
+        }
+        let Some(message) = call.messages.get(call.current).cloned() else {
+            return vec![self.prompt(call, "vm-no-more", &[])];
+        };
+
+        match digit {
+            '1' => self.play_current(call),
+            '3' => vec![
+                self.prompt(call, "vm-calling", &[("number", &message.from_number)]),
+                RetrievalAction::Callback {
+                    extension: call.extension.clone(),
+                    number: message.from_number,
+                },
+            ],
+            '6' => {
+                call.current += 1;
+                self.play_current(call)
+            }
+            '7' => {
+                // Already gone if it was deleted elsewhere meanwhile
+                if let Err(e) = self.manager.delete_message(&message.id).await {
+                    warn!("Failed to delete voicemail {}: {:#}", message.id, e);
+                }
+                call.messages.remove(call.current);
+                let mut actions = vec![self.prompt(call, "vm-deleted", &[])];
+                actions.extend(self.play_current(call));
+                actions
+            }
+            '9' => {
+                if let Err(e) = self.manager.mark_message_read(&message.id).await {
+                    warn!("Failed to save voicemail {}: {:#}", message.id, e);
+                }
+                call.messages[call.current].read = true;
+                call.current += 1;
+                let mut actions = vec![self.prompt(call, "vm-saved", &[])];
+                actions.extend(self.play_current(call));
+                actions
+            }
+            _ => vec![self.prompt(call, "vm-help", &[])],
+        }
+    }
+
+    fn record_greeting(&self, call: &mut RetrievalCall, digit: char) -> Vec<RetrievalAction> {
+        let (mailbox_id, key) = match digit {
+            '#' => (Some(call.mailbox.clone()), "vm-greeting-saved"),
+            '*' => (None, "vm-greeting-cancelled"),
+            _ => return Vec::new(),
+        };
+        call.stage = Stage::Menu;
+        vec![
+            RetrievalAction::FinishRecording { mailbox_id },
+            self.prompt(call, key, &[]),
+        ]
+    }
+
+    /// Announce and play the current message, or say there are no more
+    fn play_current(&self, call: &RetrievalCall) -> Vec<RetrievalAction> {
+        let Some(message) = call.messages.get(call.current) else {
+            return vec![self.prompt(call, "vm-no-more", &[])];
+        };
+        let key = if message.read {
+            "vm-saved-message"
+        } else {
+            "vm-new-message"
+        };
+        vec![
+            self.prompt(
+                call,
+                key,
+                &[
+                    ("index", &(call.current + 1).to_string()),
+                    ("number", &message.from_number),
+                ],
+            ),
+            RetrievalAction::Play {
+                message_id: message.id.clone(),
+                file_path: message.file_path.clone(),
+            },
+        ]
+    }
+}
+
+fn push_digit(digits: &mut String, digit: char) {
+    if digit.is_ascii_digit() && digits.len() < MAX_DIGITS {
+        digits.push(digit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locale::LocaleConfig;
+    use crate::voicemail::VoicemailBox;
+
+    fn prompt(key: &str) -> RetrievalAction {
+        RetrievalAction::Prompt(PromptSet::default().render(key, "en", &[]))
+    }
+
+    fn text(action: &RetrievalAction) -> &str {
+        match action {
+            RetrievalAction::Prompt(prompt) => &prompt.text,
+            _ => "",
+        }
+    }
+
+    async fn retrieval(dir: &str) -> VoicemailRetrieval {
+        let manager = VoicemailManager::new(std::env::temp_dir().join(dir));
+        manager
+            .add_mailbox(VoicemailBox {
+                id: "1001".to_string(),
+                extension: "1001".to_string(),
+                pin: "4321".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        for caller in ["5550001", "5550002"] {
+            manager
+                .leave_message("1001", caller, None, b"audio", 5)
+                .await
+                .unwrap();
+        }
+        VoicemailRetrieval::new(RetrievalConfig::default(), manager)
+    }
+
+    async fn keys(
+        retrieval: &VoicemailRetrieval,
+        call_id: &str,
+        keys: &str,
+    ) -> Vec<RetrievalAction> {
+        let mut actions = Vec::new();
+        for key in keys.chars() {
+            actions.extend(retrieval.press(call_id, key).await.unwrap());
+        }
+        actions
+    }
+
+    #[tokio::test]
+    async fn test_retrieval_navigation() {
+        let retrieval = retrieval("rustalk_vm_retrieval").await;
+        assert!(retrieval.is_feature_code("*98"));
+        assert!(!retrieval.is_feature_code("*97"));
+
+        // Calling from the mailbox's own extension skips the mailbox prompt
+        assert_eq!(
+            retrieval.start("call-1", "1001", "*98").await,
+            vec![prompt("vm-enter-pin")]
+        );
+        let actions = keys(&retrieval, "call-1", "4321#").await;
+        assert_eq!(text(&actions[0]), "You have 2 new and 0 saved messages");
+        assert!(matches!(actions[2], RetrievalAction::Play { .. }));
+
+        // Call back whoever left the first message
+        let actions = keys(&retrieval, "call-1", "3").await;
+        assert_eq!(
+            actions[1],
+            RetrievalAction::Callback {
+                extension: "1001".to_string(),
+                number: "5550001".to_string(),
+            }
+        );
+
+        // Save the first, delete the second
+        keys(&retrieval, "call-1", "97").await;
+        let status = retrieval.manager.get_mwi_status("1001").await;
+        assert_eq!((status.new_messages, status.old_messages), (0, 1));
+
+        assert_eq!(
+            keys(&retrieval, "call-1", "#").await.last(),
+            Some(&RetrievalAction::Hangup)
+        );
+        assert!(!retrieval.is_active("call-1"));
+    }
+
+    #[tokio::test]
+    async fn test_retrieval_login() {
+        let retrieval = retrieval("rustalk_vm_retrieval_login").await;
+
+        // An unknown caller enters the mailbox, and is asked again after a
+        // wrong PIN
+        assert_eq!(
+            retrieval.start("call-2", "2002", "*98").await,
+            vec![prompt("vm-enter-mailbox")]
+        );
+        let actions = keys(&retrieval, "call-2", "1001#0000#").await;
+        assert_eq!(actions.last(), Some(&prompt("vm-enter-mailbox")));
+        let actions = keys(&retrieval, "call-2", "9999#4321#").await;
+        assert_eq!(actions.last(), Some(&prompt("vm-enter-mailbox")));
+
+        // The third wrong PIN ends the call
+        let actions = keys(&retrieval, "call-2", "1001#1111#").await;
+        assert_eq!(actions.last(), Some(&RetrievalAction::Hangup));
+        assert!(retrieval.press("call-2", '1').await.is_none());
+
+        // The mailbox can be dialed after the feature code
+        assert_eq!(
+            retrieval.start("call-3", "2002", "*981001").await,
+            vec![prompt("vm-enter-pin")]
+        );
+        let actions = keys(&retrieval, "call-3", "4321#").await;
+        assert!(text(&actions[0]).starts_with("You have 2"));
+
+        // Record a greeting, then think better of another
+        assert_eq!(
+            keys(&retrieval, "call-3", "5").await,
+            vec![prompt("vm-record-greeting"), RetrievalAction::Record]
+        );
+        let actions = keys(&retrieval, "call-3", "12#").await;
+        assert_eq!(
+            actions,
+            vec![
+                RetrievalAction::FinishRecording {
+                    mailbox_id: Some("1001".to_string())
+                },
+                prompt("vm-greeting-saved")
+            ]
+        );
+        let actions = keys(&retrieval, "call-3", "5*").await;
+        assert_eq!(
+            actions[2],
+            RetrievalAction::FinishRecording { mailbox_id: None }
+        );
+        // Back in the menu
+        assert_eq!(
+            keys(&retrieval, "call-3", "0").await,
+            vec![prompt("vm-help")]
+        );
+        assert!(retrieval.end("call-3"));
+    }
+
+    #[tokio::test]
+    async fn test_retrieval_locale() {
+        let prompts = PromptSet::new(LocaleConfig {
+            dids: HashMap::from([("*98".to_string(), "de".to_string())]),
+            tenants: HashMap::from([("paris.example".to_string(), "fr".to_string())]),
+            ..LocaleConfig::default()
+        });
+        let retrieval = retrieval("rustalk_vm_retrieval_locale")
+            .await
+            .with_prompts(prompts);
+
+        let context = LocaleContext {
+            tenant: Some("paris.example"),
+            ..LocaleContext::default()
+        };
+        let actions = retrieval
+            .start_for("call-4", "1001", "*981001", &context)
+            .await;
+        assert_eq!(
+            text(&actions[0]),
+            "Veuillez saisir votre code secret suivi de dièse"
+        );
+        let actions = keys(&retrieval, "call-4", "4321#").await;
+        assert_eq!(text(&actions[1]), "Nouveau message 1 de 5550001");
+
+        // The DID wins over the tenant
+        let context = LocaleContext {
+            did: Some("*98"),
+            ..context
+        };
+        let actions = retrieval.start_for("call-5", "1001", "*98", &context).await;
+        assert!(text(&actions[0]).starts_with("Bitte geben Sie Ihre PIN"));
+    }
+}
@@ -0,0 +1,340 @@
+//! Outbound webhooks
+//!
+//! Posts call events to HTTP endpoints such as Zapier or IFTTT catch hooks.
+//! Each endpoint picks the events it wants and how the payload is shaped:
+//! the event as-is, flattened to a single level (`crm_customer_name`), or
+//! mapped field by field onto the names the receiving tool expects.
+//! Endpoints can also opt into certificate expiry warnings (see
+//! [`crate::cert_expiry`]), which are shaped the same way.
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::crm::CrmContact;
+use crate::events::{CallEvent, CallEventKind, EventBus};
+
+/// Webhook endpoints
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookConfig {
+    pub endpoints: Vec<WebhookEndpoint>,
+}
+
+/// How an event is laid out in the request body
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// The event as published, with CRM details nested under `crm`
+    #[default]
+    Nested,
+    /// Nested objects joined into one level with `_`
+    Flat,
+}
+
+/// One destination for call events
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookEndpoint {
+    pub name: String,
+    pub url: String,
+    /// Events to send; all of them when empty
+    #[serde(default)]
+    pub events: Vec<CallEventKind>,
+    #[serde(default)]
+    pub format: PayloadFormat,
+    /// Payload field name to event field, with nested fields written as
+    /// `crm.customer_name`. When set, only the mapped fields are sent.
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// Also receive certificate expiry warnings
+    #[serde(default)]
+    pub certificate_warnings: bool,
+    /// Also receive alerts when a trunk's post-dial delay degrades
+    #[serde(default)]
+    pub pdd_alerts: bool,
+    /// Also receive alerts when a trunk's packet loss or jitter degrades
+    #[serde(default)]
+    pub quality_alerts: bool,
+    /// Extra request headers, e.g. a shared secret
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+impl WebhookEndpoint {
+    /// Whether this endpoint wants events of `kind`
+    pub fn accepts(&self, kind: CallEventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    /// Request body for `event`
+    pub fn render(&self, event: &CallEvent) -> Value {
+        self.render_value(json!(event))
+    }
+
+    /// Request body for any event payload
+    pub fn render_value(&self, value: Value) -> Value {
+        if !self.fields.is_empty() {
+            let mapped = self
+                .fields
+                .iter()
+                .map(|(name, path)| {
+                    let pointer = format!("/{}", path.replace('.', "/"));
+                    let field = value.pointer(&pointer).cloned().unwrap_or(Value::Null);
+                    (name.clone(), field)
+                })
+                .collect();
+            return Value::Object(mapped);
+        }
+        match self.format {
+            PayloadFormat::Nested => value,
+            PayloadFormat::Flat => {
+                let mut flat = Map::new();
+                flatten("", value, &mut flat);
+                Value::Object(flat)
+            }
+        }
+    }
+}
+
+fn flatten(prefix: &str, value: Value, out: &mut Map<String, Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{}_{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        value => {
+            out.insert(prefix.to_string(), value);
+        }
+    }
+}
+
+/// Outcome of posting one event to an endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub endpoint: String,
+    /// HTTP status the endpoint answered with
+    pub status: u16,
+    /// Body that was sent
+    pub payload: Value,
+}
+
+/// Delivers call events to the configured endpoints
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    endpoints: Arc<Vec<WebhookEndpoint>>,
+    client: reqwest::Client,
+}
+
+impl WebhookDispatcher {
+    pub fn new(config: WebhookConfig) -> Self {
+        Self {
+            endpoints: Arc::new(config.endpoints),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn endpoints(&self) -> &[WebhookEndpoint] {
+        &self.endpoints
+    }
+
+    pub fn endpoint(&self, name: &str) -> Option<&WebhookEndpoint> {
+        self.endpoints.iter().find(|e| e.name == name)
+    }
+
+    /// Post `event` to one endpoint; non-2xx answers are errors
+    pub async fn deliver(
+        &self,
+        endpoint: &WebhookEndpoint,
+        event: &CallEvent,
+    ) -> Result<WebhookDelivery> {
+        self.deliver_value(endpoint, json!(event)).await
+    }
+
+    /// Post an event payload other than a call event to one endpoint
+    pub async fn deliver_value(
+        &self,
+        endpoint: &WebhookEndpoint,
+        event: Value,
+    ) -> Result<WebhookDelivery> {
+        let payload = endpoint.render_value(event);
+        let mut request = self
+            .client
+            .post(&endpoint.url)
+            .timeout(Duration::from_millis(endpoint.timeout_ms))
+            .json(&payload);
+        for (name, value) in &endpoint.headers {
+            request = request.header(name, value);
+        }
+
+        let status = request.send().await?.status();
+        if !status.is_success() {
+            return Err(anyhow!("{} answered {}", endpoint.url, status));
+        }
+        Ok(WebhookDelivery {
+            endpoint: endpoint.name.clone(),
+            status: status.as_u16(),
+            payload,
+        })
+    }
+
+    /// Post a sample ringing event to the named endpoint, so a no-code
+    /// tool can learn the payload's fields before any real call
+    pub async fn test_delivery(&self, name: &str) -> Result<WebhookDelivery> {
+        let endpoint = self
+            .endpoint(name)
+            .ok_or_else(|| anyhow!("Unknown webhook '{}'", name))?;
+        self.deliver(endpoint, &sample_event()).await
+    }
+
+    /// Forward every event published on `events` to the endpoints that
+    /// want it
+    pub fn spawn(&self, events: &EventBus) -> JoinHandle<()> {
+        let dispatcher = self.clone();
+        let mut rx = events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Webhooks skipped {} call events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                for endpoint in dispatcher.endpoints.iter() {
+                    if !endpoint.accepts(event.kind) {
+                        continue;
+                    }
+                    // One slow endpoint must not hold up the others
+                    let dispatcher = dispatcher.clone();
+                    let endpoint = endpoint.clone();
+                    let event = event.clone();
+                    tokio::spawn(async move {
+                        match dispatcher.deliver(&endpoint, &event).await {
+                            Ok(_) => {
+                                debug!("Delivered {} to webhook {}", event.call_id, endpoint.name)
+                            }
+                            Err(e) => warn!("Webhook {} failed: {:#}", endpoint.name, e),
+                        }
+                    });
+                }
+            }
+        })
+    }
+}
+
+/// Example event used for test deliveries
+pub fn sample_event() -> CallEvent {
+    CallEvent {
+        kind: CallEventKind::Ringing,
+        call_id: "rustalk-webhook-test".to_string(),
+        caller: Some("+12125551234".to_string()),
+        callee: Some("1001".to_string()),
+        timestamp: Utc::now(),
+        did: None,
+        crm: Some(CrmContact {
+            customer_name: Some("Example Customer".to_string()),
+            ticket_url: Some("https://crm.example.com/tickets/1".to_string()),
+            extra: Map::new(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn endpoint(url: &str) -> WebhookEndpoint {
+        serde_json::from_value(json!({ "name": "zapier", "url": url })).unwrap()
+    }
+
+    #[test]
+    fn test_payload_templates() {
+        let event = sample_event();
+        let mut zapier = endpoint("https://hooks.zapier.com/hooks/catch/1/a");
+
+        let nested = zapier.render(&event);
+        assert_eq!(nested["crm"]["customer_name"], "Example Customer");
+
+        zapier.format = PayloadFormat::Flat;
+        let flat = zapier.render(&event);
+        assert_eq!(flat["kind"], "ringing");
+        assert_eq!(flat["crm_customer_name"], "Example Customer");
+        assert!(flat.get("crm").is_none());
+
+        zapier.fields = BTreeMap::from([
+            ("value1".to_string(), "caller".to_string()),
+            ("value2".to_string(), "crm.customer_name".to_string()),
+            ("value3".to_string(), "crm.account".to_string()),
+        ]);
+        assert_eq!(
+            zapier.render(&event),
+            json!({
+                "value1": "+12125551234",
+                "value2": "Example Customer",
+                "value3": null
+            })
+        );
+
+        assert!(zapier.accepts(CallEventKind::Hangup));
+        zapier.events = vec![CallEventKind::Ringing];
+        assert!(!zapier.accepts(CallEventKind::Hangup));
+    }
+
+    #[tokio::test]
+    async fn test_delivery() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // Headers and body may arrive in separate reads
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let _ = tx.send(String::from_utf8_lossy(&request).to_string());
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let mut zapier = endpoint(&url);
+        zapier.format = PayloadFormat::Flat;
+        zapier.headers = HashMap::from([("X-Hook-Secret".to_string(), "s3cret".to_string())]);
+        let dispatcher = WebhookDispatcher::new(WebhookConfig {
+            endpoints: vec![zapier],
+        });
+
+        let delivery = dispatcher.test_delivery("zapier").await.unwrap();
+        assert_eq!(delivery.status, 200);
+        let request = rx.await.unwrap().to_lowercase();
+        assert!(request.contains("x-hook-secret: s3cret"));
+        assert!(request.contains("\"crm_customer_name\":\"example customer\""));
+
+        assert!(dispatcher.test_delivery("ifttt").await.is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Wholesale carrier peers
+//!
+//! Wholesale interconnects do not register or answer digest challenges: a
+//! peer is recognised by the address its INVITEs come from, optionally
+//! narrowed by a tech-prefix in front of the dialed number so several
+//! customers can share one gateway. Each peer has its own concurrent call
+//! cap and may be bound to a rate deck; the peer and the rated cost are
+//! written to the CDR.
+
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+
+use crate::acl::matches_cidr;
+
+/// Carrier peers and the rate decks they bill against
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct WholesaleConfig {
+    pub peers: Vec<CarrierPeer>,
+    #[serde(default)]
+    pub rate_decks: Vec<RateDeck>,
+}
+
+/// A carrier that sends traffic without registering
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CarrierPeer {
+    pub name: String,
+    /// Source addresses: an IP, a CIDR block, or `ip:port` to also match
+    /// the source port
+    pub addresses: Vec<String>,
+    /// Digits the peer dials in front of every number; stripped before
+    /// routing. Calls without it are not from this peer.
+    pub tech_prefix: Option<String>,
+    /// Concurrent calls the peer may hold
+    pub max_concurrent: Option<u32>,
+    /// Rate deck calls are billed against; destinations without a rate are
+    /// refused
+    pub rate_deck: Option<String>,
+}
+
+/// Per-minute prices by destination prefix
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RateDeck {
+    pub name: String,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    pub rates: Vec<Rate>,
+}
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+/// Price of one destination prefix
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Rate {
+    pub prefix: String,
+    pub per_minute: f64,
+    /// Minimum billed seconds
+    #[serde(default = "default_increment")]
+    pub initial_seconds: u32,
+    /// Billing increment after the initial period
+    #[serde(default = "default_increment")]
+    pub increment_seconds: u32,
+}
+
+fn default_increment() -> u32 {
+    1
+}
+
+/// Cost of one call, as written to the CDR
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Charge {
+    pub rate_deck: String,
+    /// Destination prefix the rate was taken from
+    pub prefix: String,
+    pub per_minute: f64,
+    pub billed_seconds: u32,
+    pub amount: f64,
+    pub currency: String,
+}
+
+impl RateDeck {
+    /// Longest-prefix rate for `number`
+    pub fn rate_for(&self, number: &str) -> Option<&Rate> {
+        let number = number.trim_start_matches('+');
+        self.rates
+            .iter()
+            .filter(|rate| number.starts_with(rate.prefix.trim_start_matches('+')))
+            .max_by_key(|rate| rate.prefix.trim_start_matches('+').len())
+    }
+
+    /// Charge for `seconds` of talk time to `number`; unanswered calls cost
+    /// nothing
+    pub fn charge(&self, number: &str, seconds: u32) -> Option<Charge> {
+        let rate = self.rate_for(number)?;
+        let billed_seconds = if seconds == 0 {
+            0
+        } else if seconds <= rate.initial_seconds {
+            rate.initial_seconds
+        } else {
+            let increment = rate.increment_seconds.max(1);
+            let extra = seconds - rate.initial_seconds;
+            rate.initial_seconds + extra.div_ceil(increment) * increment
+        };
+        let amount = rate.per_minute * billed_seconds as f64 / 60.0;
+        Some(Charge {
+            rate_deck: self.name.clone(),
+            prefix: rate.prefix.clone(),
+            per_minute: rate.per_minute,
+            billed_seconds,
+            // Stored to a hundredth of a cent
+            amount: (amount * 10_000.0).round() / 10_000.0,
+            currency: self.currency.clone(),
+        })
+    }
+}
+
+impl CarrierPeer {
+    fn matches_source(&self, source: SocketAddr) -> bool {
+        self.addresses
+            .iter()
+            .any(|address| match address.parse::<SocketAddr>() {
+                Ok(exact) => exact == source,
+                Err(_) => matches_cidr(source.ip(), address).unwrap_or(false),
+            })
+    }
+}
+
+/// A call identified as coming from a peer
+#[derive(Debug, Clone)]
+pub struct PeerCall<'a> {
+    pub peer: &'a CarrierPeer,
+    /// Dialed number with the tech-prefix removed
+    pub number: String,
+}
+
+/// Why a peer's call was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerRejection {
+    /// The peer is at its concurrent call cap
+    AtCapacity { limit: u32 },
+    /// The peer's rate deck has no rate for the destination
+    NoRate,
+}
+
+impl std::fmt::Display for PeerRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerRejection::AtCapacity { limit } => {
+                write!(f, "peer is at its limit of {} calls", limit)
+            }
+            PeerRejection::NoRate => write!(f, "no rate for destination"),
+        }
+    }
+}
+
+/// Identifies carrier peers and tracks their calls
+pub struct WholesaleGateway {
+    config: WholesaleConfig,
+    active: Mutex<HashMap<String, u32>>,
+}
+
+impl WholesaleGateway {
+    pub fn new(config: WholesaleConfig) -> Result<Self> {
+        for peer in &config.peers {
+            for address in &peer.addresses {
+                if address.parse::<SocketAddr>().is_err() {
+                    matches_cidr(IpAddr::from([0, 0, 0, 0]), address)?;
+                }
+            }
+            if let Some(deck) = &peer.rate_deck {
+                if !config.rate_decks.iter().any(|d| &d.name == deck) {
+                    bail!("peer {} uses unknown rate deck {}", peer.name, deck);
+                }
+            }
+        }
+        Ok(Self {
+            config,
+            active: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn peers(&self) -> &[CarrierPeer] {
+        &self.config.peers
+    }
+
+    pub fn peer(&self, name: &str) -> Option<&CarrierPeer> {
+        self.config.peers.iter().find(|p| p.name == name)
+    }
+
+    pub fn rate_deck(&self, name: &str) -> Option<&RateDeck> {
+        self.config.rate_decks.iter().find(|d| d.name == name)
+    }
+
+    /// Peer an INVITE from `source` dialing `dialed` belongs to. Peers
+    /// sharing an address are told apart by the longest matching
+    /// tech-prefix.
+    pub fn identify(&self, source: SocketAddr, dialed: &str) -> Option<PeerCall<'_>> {
+        self.config
+            .peers
+            .iter()
+            .filter(|peer| peer.matches_source(source))
+            .filter_map(|peer| {
+                let prefix = peer.tech_prefix.as_deref().unwrap_or("");
+                let number = dialed.strip_prefix(prefix)?;
+                Some((
+                    prefix.len(),
+                    PeerCall {
+                        peer,
+                        number: number.to_string(),
+                    },
+                ))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, call)| call)
+    }
+
+    /// Take a call slot on the peer, checking it can be billed
+    pub fn admit(&self, call: &PeerCall) -> Result<(), PeerRejection> {
+        if let Some(deck) = call.peer.rate_deck.as_deref() {
+            let rated = self
+                .rate_deck(deck)
+                .is_some_and(|deck| deck.rate_for(&call.number).is_some());
+            if !rated {
+                return Err(PeerRejection::NoRate);
+            }
+        }
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(call.peer.name.clone()).or_insert(0);
+        if let Some(limit) = call.peer.max_concurrent {
+            if *count >= limit {
+                return Err(PeerRejection::AtCapacity { limit });
+            }
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    /// Return a call slot taken by `admit`
+    pub fn release(&self, peer: &str) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(peer) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Calls each peer currently holds
+    pub fn active_calls(&self) -> HashMap<String, u32> {
+        self.active.lock().unwrap().clone()
+    }
+
+    /// Charge for a call from `peer` to `number`, if the peer is rated
+    pub fn charge(&self, peer: &str, number: &str, seconds: u32) -> Option<Charge> {
+        let deck = self.peer(peer)?.rate_deck.as_deref()?;
+        self.rate_deck(deck)?.charge(number, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway() -> WholesaleGateway {
+        let config: WholesaleConfig = serde_json::from_value(serde_json::json!({
+            "peers": [
+                {
+                    "name": "acme",
+                    "addresses": ["203.0.113.0/29"],
+                    "tech_prefix": "1234#",
+                    "max_concurrent": 1,
+                    "rate_deck": "standard"
+                },
+                {
+                    "name": "globex",
+                    "addresses": ["203.0.113.5", "198.51.100.9:5080"],
+                    "tech_prefix": "99#"
+                }
+            ],
+            "rate_decks": [
+                {
+                    "name": "standard",
+                    "rates": [
+                        { "prefix": "1", "per_minute": 0.01 },
+                        { "prefix": "1212", "per_minute": 0.006, "initial_seconds": 30, "increment_seconds": 6 }
+                    ]
+                }
+            ]
+        }))
+        .unwrap();
+        WholesaleGateway::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_identify_by_address_and_tech_prefix() {
+        let gateway = gateway();
+        let shared: SocketAddr = "203.0.113.5:5060".parse().unwrap();
+
+        let call = gateway.identify(shared, "1234#12125551234").unwrap();
+        assert_eq!(call.peer.name, "acme");
+        assert_eq!(call.number, "12125551234");
+        let call = gateway.identify(shared, "99#12125551234").unwrap();
+        assert_eq!(call.peer.name, "globex");
+
+        // Wrong prefix, wrong port, unknown address
+        assert!(gateway.identify(shared, "12125551234").is_none());
+        let wrong_port: SocketAddr = "198.51.100.9:5060".parse().unwrap();
+        assert!(gateway.identify(wrong_port, "99#1").is_none());
+        let right_port: SocketAddr = "198.51.100.9:5080".parse().unwrap();
+        assert!(gateway.identify(right_port, "99#1").is_some());
+        let outside: SocketAddr = "203.0.113.9:5060".parse().unwrap();
+        assert!(gateway.identify(outside, "1234#1").is_none());
+    }
+
+    #[test]
+    fn test_capacity_and_rating() {
+        let gateway = gateway();
+        let source: SocketAddr = "203.0.113.1:5060".parse().unwrap();
+
+        let unrated = gateway.identify(source, "1234#4420").unwrap();
+        assert_eq!(gateway.admit(&unrated), Err(PeerRejection::NoRate));
+
+        let call = gateway.identify(source, "1234#12125551234").unwrap();
+        assert_eq!(gateway.admit(&call), Ok(()));
+        assert_eq!(
+            gateway.admit(&call),
+            Err(PeerRejection::AtCapacity { limit: 1 })
+        );
+        gateway.release("acme");
+        assert_eq!(gateway.admit(&call), Ok(()));
+
+        // 30s minimum, then 6s increments: 61s bills as 66s
+        let charge = gateway.charge("acme", "+12125551234", 61).unwrap();
+        assert_eq!(charge.prefix, "1212");
+        assert_eq!(charge.billed_seconds, 66);
+        assert_eq!(charge.amount, 0.0066);
+        assert_eq!(
+            gateway
+                .charge("acme", "13105551234", 10)
+                .unwrap()
+                .billed_seconds,
+            10
+        );
+        assert_eq!(
+            gateway.charge("acme", "12125551234", 0).unwrap().amount,
+            0.0
+        );
+        assert!(gateway.charge("globex", "12125551234", 60).is_none());
+    }
+}
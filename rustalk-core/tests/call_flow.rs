@@ -0,0 +1,342 @@
+//! End-to-end call flow tests against a B2BUA over UDP
+
+mod common;
+
+use common::{trunk_routes, SipEndpoint, TestServer};
+use rustalk_core::b2bua::{CallDisposition, B2BUA};
+use rustalk_core::sip::{Method, StatusCode};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_options_ping() {
+    let server = TestServer::start().await;
+    let mut alice = server.endpoint("alice").await;
+
+    let options = alice.request(Method::Options, "ping-1", "rustalk");
+    alice.send(&options).await;
+
+    let response = alice.expect_response(StatusCode::OK).await;
+    assert!(response
+        .get_header_value("Allow")
+        .unwrap()
+        .contains("INVITE"));
+    assert_eq!(server.log().sequence(), vec!["-> OPTIONS", "<- 200"]);
+}
+
+#[tokio::test]
+async fn test_happy_path_call() {
+    let mut server = TestServer::start().await;
+    let mut alice = server.endpoint("alice").await;
+    let mut bob = server.endpoint("bob").await;
+    bob.register().await;
+    server.log().clear();
+
+    // Alice calls bob, who rings and answers
+    let invite = alice.invite("call-1", "bob");
+    alice.send(&invite).await;
+    let trying = alice.expect_response(StatusCode::TRYING).await;
+    assert_eq!(trying.get_header_value("Call-ID"), Some("call-1"));
+    let forked = bob.expect_request(Method::Invite).await;
+    assert!(String::from_utf8_lossy(&forked.body).contains("m=audio 4000"));
+    bob.respond(&forked, StatusCode::RINGING).await;
+    let ringing = alice.expect_response(StatusCode::RINGING).await;
+    bob.respond(&forked, StatusCode::OK).await;
+    let ok = alice.expect_response(StatusCode::OK).await;
+    assert_eq!(ok.get_header_value("CSeq"), invite.get_header_value("CSeq"));
+    assert_eq!(ok.get_header_value("To"), ringing.get_header_value("To"));
+    assert!(String::from_utf8_lossy(&ok.body).contains("m=audio 4002"));
+    let ack = bob.expect_request(Method::Ack).await;
+    assert_eq!(ack.get_header_value("Call-ID"), Some("call-1"));
+
+    // Alice confirms the call, then hangs up and bob is sent a BYE
+    let ack = alice.in_dialog(Method::Ack, "bob", &ok);
+    alice.send(&ack).await;
+    alice.expect_silence(Duration::from_millis(100)).await;
+    assert_eq!(server.b2bua.session_count().await, 1);
+    let bye = alice.in_dialog(Method::Bye, "bob", &ok);
+    alice.send(&bye).await;
+    alice.expect_response(StatusCode::OK).await;
+    let bye = bob.expect_request(Method::Bye).await;
+    assert_eq!(bye.get_header_value("Call-ID"), Some("call-1"));
+    assert!(bye.get_header_value("To").unwrap().contains("tag=bob"));
+    assert_eq!(server.b2bua.session_count().await, 0);
+
+    assert_eq!(
+        server.log().sequence(),
+        vec![
+            "-> INVITE",
+            "<- 100",
+            "<- INVITE",
+            "-> 180",
+            "<- 180",
+            "-> 200",
+            "<- 200",
+            "<- ACK",
+            "-> ACK",
+            "-> BYE",
+            "<- 200",
+            "<- BYE"
+        ]
+    );
+    bob.respond(&bye, StatusCode::OK).await;
+
+    let cdr = server.next_cdr().await;
+    assert_eq!(cdr.call_id, "call-1");
+    assert_eq!(cdr.disposition, CallDisposition::Answered);
+    assert!(cdr.caller.unwrap().contains("sip:alice@"));
+    assert!(cdr.callee.unwrap().contains("sip:bob@"));
+    assert!(cdr.answer_time.is_some());
+    assert!(cdr.setup_ms.is_some());
+    assert!(cdr.end_time >= cdr.start_time);
+}
+
+#[tokio::test]
+async fn test_call_forked_to_registered_endpoint() {
+    let mut server = TestServer::start().await;
+    let mut alice = server.endpoint("alice").await;
+    let mut desk = server.endpoint("bob").await;
+    let mut soft = server.endpoint("bob").await;
+    desk.register().await;
+    soft.register().await;
+
+    // Both of bob's devices ring
+    let invite = alice.invite("call-f", "bob");
+    alice.send(&invite).await;
+    alice.expect_response(StatusCode::TRYING).await;
+    let to_desk = desk.expect_request(Method::Invite).await;
+    let to_soft = soft.expect_request(Method::Invite).await;
+    assert_eq!(to_desk.uri.to_string(), format!("sip:bob@{}", desk.addr));
+    assert_eq!(to_soft.uri.to_string(), format!("sip:bob@{}", soft.addr));
+    assert_ne!(
+        to_desk.get_header_value("Via"),
+        to_soft.get_header_value("Via")
+    );
+
+    // The softphone rings and the desk phone answers, so the softphone is
+    // cancelled and only the desk phone is ACKed
+    soft.respond(&to_soft, StatusCode::RINGING).await;
+    alice.expect_response(StatusCode::RINGING).await;
+    desk.respond(&to_desk, StatusCode::OK).await;
+    let ok = alice.expect_response(StatusCode::OK).await;
+    desk.expect_request(Method::Ack).await;
+    let cancel = soft.expect_request(Method::Cancel).await;
+    assert!(cancel
+        .get_header_value("Reason")
+        .unwrap()
+        .contains("Call completed elsewhere"));
+    soft.respond(&cancel, StatusCode::OK).await;
+    soft.respond(&to_soft, StatusCode::REQUEST_TERMINATED).await;
+
+    // Alice's BYE reaches the phone that answered
+    let ack = alice.in_dialog(Method::Ack, "bob", &ok);
+    alice.send(&ack).await;
+    let bye = alice.in_dialog(Method::Bye, "bob", &ok);
+    alice.send(&bye).await;
+    alice.expect_response(StatusCode::OK).await;
+    desk.expect_request(Method::Bye).await;
+    soft.expect_silence(Duration::from_millis(100)).await;
+
+    let cdr = server.next_cdr().await;
+    assert_eq!(cdr.call_id, "call-f");
+    assert_eq!(cdr.disposition, CallDisposition::Answered);
+    assert!(cdr.answer_time.is_some());
+}
+
+#[tokio::test]
+async fn test_callee_hangs_up() {
+    let mut server = TestServer::start().await;
+    let mut alice = server.endpoint("alice").await;
+    let mut bob = server.endpoint("bob").await;
+    bob.register().await;
+
+    let invite = alice.invite("call-h", "bob");
+    alice.send(&invite).await;
+    alice.expect_response(StatusCode::TRYING).await;
+    let forked = bob.expect_request(Method::Invite).await;
+    bob.respond(&forked, StatusCode::OK).await;
+    let ok = alice.expect_response(StatusCode::OK).await;
+    bob.expect_request(Method::Ack).await;
+    let ack = alice.in_dialog(Method::Ack, "bob", &ok);
+    alice.send(&ack).await;
+
+    // Bob hangs up in his own dialog, and alice is sent a BYE in hers
+    let mut bye = bob.request(Method::Bye, "call-h", "alice");
+    bye.headers.retain(|h| h.name.as_str() != "To");
+    let bye = bye.with_header("To", forked.get_header_value("From").unwrap());
+    bob.send(&bye).await;
+    bob.expect_response(StatusCode::OK).await;
+    let bye = alice.expect_request(Method::Bye).await;
+    assert_eq!(bye.get_header_value("Call-ID"), Some("call-h"));
+    assert_eq!(bye.get_header_value("From"), ok.get_header_value("To"));
+    assert_eq!(bye.uri.to_string(), format!("sip:alice@{}", alice.addr));
+    assert_eq!(server.b2bua.session_count().await, 0);
+
+    let cdr = server.next_cdr().await;
+    assert_eq!(cdr.call_id, "call-h");
+    assert_eq!(cdr.disposition, CallDisposition::Answered);
+}
+
+#[tokio::test]
+async fn test_forked_call_refused() {
+    let mut server = TestServer::start().await;
+    let mut alice = server.endpoint("alice").await;
+    let mut bob = server.endpoint("bob").await;
+    bob.register().await;
+
+    let invite = alice.invite("call-r", "bob");
+    alice.send(&invite).await;
+    alice.expect_response(StatusCode::TRYING).await;
+    let forked = bob.expect_request(Method::Invite).await;
+    bob.respond(&forked, StatusCode::BUSY_HERE).await;
+    let busy = alice.expect_response(StatusCode::BUSY_HERE).await;
+    assert_eq!(
+        busy.get_header_value("CSeq"),
+        invite.get_header_value("CSeq")
+    );
+
+    let cdr = server.next_cdr().await;
+    assert_eq!(cdr.disposition, CallDisposition::Busy);
+    assert!(cdr.answer_time.is_none());
+    assert_eq!(server.b2bua.session_count().await, 0);
+}
+
+#[tokio::test]
+async fn test_call_sent_to_trunk() {
+    let placeholder = "127.0.0.1:9".parse().unwrap();
+    let mut carrier = SipEndpoint::new("carrier", placeholder).await;
+    let routing = trunk_routes(
+        &[(
+            r"^1",
+            serde_json::json!({ "type": "Trunk", "value": "carrier" }),
+        )],
+        &[("carrier", carrier.addr)],
+    );
+    let server = TestServer::start_with(B2BUA::new().with_routing(Arc::new(routing))).await;
+    carrier.connect(server.addr);
+    let mut alice = server.endpoint("alice").await;
+
+    let invite = alice.invite("call-t", "12125551234");
+    alice.send(&invite).await;
+    alice.expect_response(StatusCode::TRYING).await;
+
+    // The INVITE goes out to the trunk's address, from our own Via
+    let sent = carrier.expect_request(Method::Invite).await;
+    assert_eq!(sent.get_header_value("Call-ID"), Some("call-t"));
+    assert_eq!(sent.uri.host, carrier.addr.ip().to_string());
+    assert_eq!(sent.uri.port, Some(carrier.addr.port()));
+    assert!(sent
+        .get_header_value("Via")
+        .unwrap()
+        .contains(&server.addr.to_string()));
+    assert_eq!(
+        server.log().sequence(),
+        vec!["-> INVITE", "<- 100", "<- INVITE"]
+    );
+
+    // The carrier's answer reaches alice and is ACKed
+    carrier.respond(&sent, StatusCode::OK).await;
+    alice.expect_response(StatusCode::OK).await;
+    let ack = carrier.expect_request(Method::Ack).await;
+    assert_eq!(ack.get_header_value("Call-ID"), Some("call-t"));
+    assert_eq!(server.b2bua.session_count().await, 1);
+}
+
+#[tokio::test]
+async fn test_call_fails_over_to_next_trunk() {
+    let placeholder = "127.0.0.1:9".parse().unwrap();
+    let mut carrier_a = SipEndpoint::new("carrier-a", placeholder).await;
+    let mut carrier_b = SipEndpoint::new("carrier-b", placeholder).await;
+    let hops = serde_json::json!({
+        "type": "DestinationList",
+        "value": { "hops": [
+            { "destination": { "type": "Trunk", "value": "carrier-a" } },
+            { "destination": { "type": "Trunk", "value": "carrier-b" } }
+        ] }
+    });
+    let routing = trunk_routes(
+        &[(r"^1", hops)],
+        &[("carrier-a", carrier_a.addr), ("carrier-b", carrier_b.addr)],
+    );
+    let server = TestServer::start_with(B2BUA::new().with_routing(Arc::new(routing))).await;
+    carrier_a.connect(server.addr);
+    carrier_b.connect(server.addr);
+    let mut alice = server.endpoint("alice").await;
+
+    let invite = alice.invite("call-o", "12125551234");
+    alice.send(&invite).await;
+    alice.expect_response(StatusCode::TRYING).await;
+
+    // The first trunk is out of service, so the call moves on to the second
+    let first = carrier_a.expect_request(Method::Invite).await;
+    carrier_a
+        .respond(&first, StatusCode::SERVICE_UNAVAILABLE)
+        .await;
+    let second = carrier_b.expect_request(Method::Invite).await;
+    assert_eq!(second.get_header_value("Call-ID"), Some("call-o"));
+    assert_eq!(second.uri.port, Some(carrier_b.addr.port()));
+    assert_ne!(
+        second.get_header_value("Via"),
+        first.get_header_value("Via")
+    );
+    assert_eq!(server.b2bua.session_count().await, 1);
+}
+
+#[tokio::test]
+async fn test_cancelled_call() {
+    let mut server = TestServer::start().await;
+    let mut alice = server.endpoint("alice").await;
+
+    let invite = alice.invite("call-2", "bob");
+    alice.send(&invite).await;
+    alice.expect_response(StatusCode::TRYING).await;
+
+    let cancel = alice.request(Method::Cancel, "call-2", "bob");
+    alice.send(&cancel).await;
+    alice.expect_response(StatusCode::OK).await;
+    let terminated = alice.expect_response(StatusCode::REQUEST_TERMINATED).await;
+    assert_eq!(
+        terminated.get_header_value("CSeq"),
+        invite.get_header_value("CSeq")
+    );
+    assert_eq!(server.b2bua.session_count().await, 0);
+
+    let cdr = server.next_cdr().await;
+    assert_eq!(cdr.disposition, CallDisposition::Cancelled);
+    assert!(cdr.answer_time.is_none());
+    assert_eq!(cdr.duration_seconds, 0);
+}
+
+#[tokio::test]
+async fn test_two_endpoints_concurrent_calls() {
+    let mut server = TestServer::start().await;
+    let mut alice = server.endpoint("alice").await;
+    let mut bob = server.endpoint("bob").await;
+
+    let invite = alice.invite("call-a", "carol");
+    alice.send(&invite).await;
+    alice.expect_response(StatusCode::TRYING).await;
+
+    let invite = bob.invite("call-b", "dave");
+    bob.send(&invite).await;
+    bob.expect_response(StatusCode::TRYING).await;
+
+    assert_eq!(server.b2bua.session_count().await, 2);
+
+    let bye = bob.request(Method::Bye, "call-b", "dave");
+    bob.send(&bye).await;
+    bob.expect_response(StatusCode::OK).await;
+
+    let bye = alice.request(Method::Bye, "call-a", "carol");
+    alice.send(&bye).await;
+    alice.expect_response(StatusCode::OK).await;
+
+    assert_eq!(server.b2bua.session_count().await, 0);
+
+    // Neither call was acknowledged, so both end unanswered
+    let first = server.next_cdr().await;
+    let second = server.next_cdr().await;
+    assert_eq!(first.call_id, "call-b");
+    assert_eq!(second.call_id, "call-a");
+    assert_eq!(first.disposition, CallDisposition::Failed);
+}
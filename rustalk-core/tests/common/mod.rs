@@ -0,0 +1,405 @@
+//! Call flow test harness
+//!
+//! Spins up a B2BUA behind a real UDP transport on the loopback interface and
+//! provides simulated SIP endpoints that exchange messages with it. Every
+//! message the server sees is recorded so tests can assert on the exact
+//! sequence, and CDRs emitted by the B2BUA are captured for inspection.
+//!
+//! The server runs a registrar: endpoints that REGISTER are rung by INVITEs
+//! the B2BUA forks to them, and their ringing and answer are relayed back to
+//! the caller. A call to an extension nobody registered is never forked, so
+//! only the caller's ACK marks it answered. Endpoints can also stand in for
+//! trunks: calls routed to a trunk by [`trunk_routes`] are sent to the
+//! endpoint's address.
+
+#![allow(dead_code)]
+
+use rustalk_core::b2bua::{CallDetailRecord, OutboundRequest, OutboundResponse, B2BUA};
+use rustalk_core::registrar::Registrar;
+use rustalk_core::routing::{RouteEvaluator, RoutingConfig};
+use rustalk_core::sip::{
+    parser::parse_message, Message, Method, Request, Response, StatusCode, Uri,
+};
+use rustalk_core::transport::{Transport, TransportConfig, UdpTransport};
+use rustalk_core::trunk_health::{TrunkHealth, TrunkHealthConfig};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How long to wait for an expected message before failing the test
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Ordered record of messages seen by the server
+#[derive(Clone, Default)]
+pub struct MessageLog {
+    entries: Arc<Mutex<Vec<String>>>,
+}
+
+impl MessageLog {
+    fn record(&self, direction: &str, message: &Message) {
+        let summary = match message {
+            Message::Request(req) => format!("{} {}", direction, req.method),
+            Message::Response(res) => format!("{} {}", direction, res.status_code.0),
+        };
+        self.entries.lock().unwrap().push(summary);
+    }
+
+    /// Messages in arrival order, e.g. `["-> INVITE", "<- 100"]`
+    ///
+    /// `->` is a request or response received by the server and `<-` is one
+    /// sent by the server.
+    pub fn sequence(&self) -> Vec<String> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Routes sending numbers matching each pattern to a destination, with
+/// each trunk dialed at the address given for it
+pub fn trunk_routes(
+    routes: &[(&str, serde_json::Value)],
+    trunks: &[(&str, SocketAddr)],
+) -> RouteEvaluator {
+    let routes: Vec<_> = routes
+        .iter()
+        .map(|(pattern, destination)| {
+            serde_json::json!({
+                "id": pattern,
+                "name": pattern,
+                "description": null,
+                "pattern": pattern,
+                "destination": destination,
+                "enabled": true,
+                "priority": 10,
+                "conditions": null,
+                "action": "accept",
+                "continue_on_match": false
+            })
+        })
+        .collect();
+    let routing: RoutingConfig =
+        serde_json::from_value(serde_json::json!({ "routes": routes })).unwrap();
+    let trunks: Vec<_> = trunks
+        .iter()
+        .map(|(name, addr)| {
+            serde_json::json!({ "name": name, "host": addr.ip().to_string(), "port": addr.port() })
+        })
+        .collect();
+    let health: TrunkHealthConfig =
+        serde_json::from_value(serde_json::json!({ "trunks": trunks })).unwrap();
+    RouteEvaluator::new(routing).with_trunk_health(TrunkHealth::new(health))
+}
+
+/// A B2BUA listening on a loopback UDP port
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub b2bua: B2BUA,
+    log: MessageLog,
+    cdrs: mpsc::UnboundedReceiver<CallDetailRecord>,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(B2BUA::new()).await
+    }
+
+    /// Start a server around a preconfigured B2BUA
+    pub async fn start_with(b2bua: B2BUA) -> Self {
+        let config = TransportConfig {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        };
+        let transport = Arc::new(UdpTransport::new(&config).await.unwrap());
+        let addr = transport.local_addr();
+        let log = MessageLog::default();
+
+        let (cdr_tx, cdrs) = mpsc::unbounded_channel();
+        let (outbound_tx, mut outbound) = mpsc::unbounded_channel::<OutboundRequest>();
+        let (relay_tx, mut relayed) = mpsc::unbounded_channel::<OutboundResponse>();
+        let b2bua = b2bua
+            .with_cdr_sink(cdr_tx)
+            .with_registrar(Registrar::new())
+            .with_outbound_sink(outbound_tx, addr)
+            .with_relay_sink(relay_tx);
+
+        let handle = {
+            let b2bua = b2bua.clone();
+            let log = log.clone();
+            tokio::spawn(async move {
+                loop {
+                    // Whatever the last message queued goes out before the
+                    // next is read, so the log follows the call
+                    tokio::select! {
+                        biased;
+                        // Responses relayed from one leg to the other
+                        Some(relayed) = relayed.recv() => {
+                            let message = Message::Response(relayed.response);
+                            log.record("<-", &message);
+                            let _ = transport.send(&message, relayed.destination).await;
+                        }
+                        // Requests the B2BUA originates, such as forked INVITEs
+                        Some(outbound) = outbound.recv() => {
+                            let message = Message::Request(outbound.request);
+                            log.record("<-", &message);
+                            let _ = transport.send(&message, outbound.destination).await;
+                        }
+                        received = transport.receive() => {
+                            let Ok((message, source)) = received else {
+                                continue;
+                            };
+                            log.record("->", &message);
+
+                            if let Ok(Some(reply)) = b2bua.handle_message_from(message, source).await {
+                                log.record("<-", &reply);
+                                let _ = transport.send(&reply, source).await;
+                            }
+                        }
+                    }
+                }
+            })
+        };
+
+        Self {
+            addr,
+            b2bua,
+            log,
+            cdrs,
+            handle,
+        }
+    }
+
+    pub fn log(&self) -> &MessageLog {
+        &self.log
+    }
+
+    /// Create an endpoint that talks to this server
+    pub async fn endpoint(&self, user: &str) -> SipEndpoint {
+        SipEndpoint::new(user, self.addr).await
+    }
+
+    /// Wait for the next CDR emitted by the B2BUA
+    pub async fn next_cdr(&mut self) -> CallDetailRecord {
+        tokio::time::timeout(RECV_TIMEOUT, self.cdrs.recv())
+            .await
+            .expect("timed out waiting for CDR")
+            .expect("CDR channel closed")
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// A simulated SIP user agent
+pub struct SipEndpoint {
+    pub user: String,
+    pub addr: SocketAddr,
+    socket: UdpSocket,
+    server: SocketAddr,
+    cseq: u32,
+}
+
+impl SipEndpoint {
+    pub async fn new(user: &str, server: SocketAddr) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        Self {
+            user: user.to_string(),
+            addr,
+            socket,
+            server,
+            cseq: 0,
+        }
+    }
+
+    /// Send to `server` from now on, for an endpoint created before it
+    pub fn connect(&mut self, server: SocketAddr) {
+        self.server = server;
+    }
+
+    /// Build a request from this endpoint with the mandatory headers set
+    pub fn request(&mut self, method: Method, call_id: &str, to_user: &str) -> Request {
+        self.cseq += 1;
+        let uri = Uri::new("sip".to_string(), self.server.ip().to_string())
+            .with_user(to_user.to_string())
+            .with_port(self.server.port());
+
+        Request::new(method, uri)
+            .with_header(
+                "Via",
+                format!(
+                    "SIP/2.0/UDP {};branch=z9hG4bK{}",
+                    self.addr,
+                    uuid::Uuid::new_v4().simple()
+                ),
+            )
+            .with_header("Max-Forwards", "70")
+            .with_header(
+                "From",
+                format!("<sip:{}@{}>;tag={}", self.user, self.addr.ip(), self.user),
+            )
+            .with_header("To", format!("<sip:{}@{}>", to_user, self.server.ip()))
+            .with_header("Call-ID", call_id)
+            .with_header("CSeq", format!("{} {}", self.cseq, method))
+            .with_header("Contact", format!("<sip:{}@{}>", self.user, self.addr))
+            .with_header("Content-Length", "0")
+    }
+
+    /// Build a request in the dialog `answer` set up, with its To tag
+    ///
+    /// An ACK reuses the CSeq of the INVITE it acknowledges.
+    pub fn in_dialog(&mut self, method: Method, to_user: &str, answer: &Response) -> Request {
+        let call_id = answer.get_header_value("Call-ID").unwrap();
+        let mut request = self.request(method, call_id, to_user);
+        let to = answer.get_header_value("To").unwrap().to_string();
+        let cseq = answer.get_header_value("CSeq").unwrap();
+        let cseq = match method {
+            Method::Ack => {
+                self.cseq -= 1;
+                cseq.replace("INVITE", "ACK")
+            }
+            _ => format!("{} {}", self.cseq, method),
+        };
+        request
+            .headers
+            .retain(|h| !["To", "CSeq"].contains(&h.name.as_str()));
+        request.with_header("To", to).with_header("CSeq", cseq)
+    }
+
+    /// Minimal audio session description at this endpoint's address
+    pub fn sdp(&self, port: u16) -> String {
+        format!(
+            "v=0\r\no=- 1 1 IN IP4 {ip}\r\ns=-\r\nc=IN IP4 {ip}\r\nt=0 0\r\nm=audio {port} RTP/AVP 0 8\r\n",
+            ip = self.addr.ip()
+        )
+    }
+
+    /// Build an INVITE carrying a minimal audio offer
+    pub fn invite(&mut self, call_id: &str, to_user: &str) -> Request {
+        let sdp = self.sdp(4000);
+
+        let mut request = self.request(Method::Invite, call_id, to_user);
+        request
+            .headers
+            .retain(|h| !h.name.as_str().eq_ignore_ascii_case("Content-Length"));
+        request
+            .with_header("Content-Type", "application/sdp")
+            .with_header("Content-Length", sdp.len().to_string())
+            .with_body(sdp)
+    }
+
+    /// Register this endpoint's address for its user, so calls to the user
+    /// are forked to it
+    pub async fn register(&mut self) {
+        let user = self.user.clone();
+        let call_id = format!("register-{}-{}", user, self.addr.port());
+        let mut register = self.request(Method::Register, &call_id, &user);
+        register.uri.user = None;
+        self.send(&register).await;
+        self.expect_response(StatusCode::OK).await;
+    }
+
+    /// Answer `request` from the server with `status`, tagging the To header
+    /// as this endpoint's side of the dialog
+    ///
+    /// A 2xx to an INVITE carries an audio answer.
+    pub async fn respond(&self, request: &Request, status: StatusCode) {
+        let mut response = Response::new(status);
+        for name in ["Via", "From", "Call-ID", "CSeq"] {
+            if let Some(value) = request.get_header_value(name) {
+                response = response.with_header(name, value);
+            }
+        }
+        let to = request.get_header_value("To").unwrap_or_default();
+        let to = if status == StatusCode::TRYING {
+            to.to_string()
+        } else {
+            format!("{};tag={}", to, self.user)
+        };
+        let response = response
+            .with_header("To", to)
+            .with_header("Contact", format!("<sip:{}@{}>", self.user, self.addr));
+        let response = if request.method == Method::Invite && status.is_success() {
+            let sdp = self.sdp(4002);
+            response
+                .with_header("Content-Type", "application/sdp")
+                .with_header("Content-Length", sdp.len().to_string())
+                .with_body(sdp)
+        } else {
+            response.with_header("Content-Length", "0")
+        };
+        self.socket
+            .send_to(&response.to_bytes(), self.server)
+            .await
+            .unwrap();
+    }
+
+    pub async fn send(&self, request: &Request) {
+        self.socket
+            .send_to(&request.to_bytes(), self.server)
+            .await
+            .unwrap();
+    }
+
+    /// Receive the next message from the server
+    pub async fn recv(&self) -> Message {
+        let mut buf = vec![0u8; 65535];
+        let (len, _) = tokio::time::timeout(RECV_TIMEOUT, self.socket.recv_from(&mut buf))
+            .await
+            .expect("timed out waiting for SIP message")
+            .unwrap();
+
+        parse_message(&buf[..len]).expect("server sent an unparseable message")
+    }
+
+    /// Receive the next message and assert it is a response with `status`
+    pub async fn expect_response(&self, status: StatusCode) -> Response {
+        match self.recv().await {
+            Message::Response(res) => {
+                assert_eq!(res.status_code, status, "unexpected response status");
+                res
+            }
+            Message::Request(req) => {
+                panic!("expected {} response, got {} request", status.0, req.method)
+            }
+        }
+    }
+
+    /// Receive the next message and assert it is a `method` request
+    pub async fn expect_request(&self, method: Method) -> Request {
+        match self.recv().await {
+            Message::Request(req) => {
+                assert_eq!(req.method, method, "unexpected request method");
+                req
+            }
+            Message::Response(res) => {
+                panic!(
+                    "expected {} request, got {} response",
+                    method, res.status_code.0
+                )
+            }
+        }
+    }
+
+    /// Assert nothing arrives within `wait`
+    pub async fn expect_silence(&self, wait: Duration) {
+        let mut buf = vec![0u8; 65535];
+        if let Ok(Ok((len, _))) = tokio::time::timeout(wait, self.socket.recv_from(&mut buf)).await
+        {
+            panic!(
+                "expected no message, got: {}",
+                String::from_utf8_lossy(&buf[..len])
+            );
+        }
+    }
+}
@@ -2,16 +2,28 @@
 
 use crate::teams::TeamsConfig;
 use anyhow::Result;
+use rustalk_core::direct_routing::{self, ValidationTarget};
 use rustalk_core::prelude::{Config as CoreConfig, B2BUA};
+use rustalk_core::trunk_health::TrunkHealth;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::time::{interval, Duration};
-use tracing::{error, info};
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+use tracing::info;
+
+/// Port the Teams SIP proxies take TLS on
+const TEAMS_TLS_PORT: u16 = 5061;
+
+/// An OPTIONS ping in flight, resolving to the proxy's answer
+type PingFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
 
 /// Teams Gateway - SBC for Microsoft Teams Direct Routing
 pub struct TeamsGateway {
     config: TeamsConfig,
     _core_config: CoreConfig,
     _b2bua: Arc<B2BUA>,
+    health: TrunkHealth,
 }
 
 impl TeamsGateway {
@@ -20,9 +32,21 @@ impl TeamsGateway {
             config,
             _core_config: core_config,
             _b2bua: Arc::new(B2BUA::new()),
+            health: TrunkHealth::default(),
         }
     }
 
+    /// Track the Teams SIP proxies alongside the other trunks
+    pub fn with_trunk_health(mut self, health: TrunkHealth) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Up, degraded or down for each Teams SIP proxy pinged
+    pub fn trunk_health(&self) -> &TrunkHealth {
+        &self.health
+    }
+
     /// Start the Teams Gateway
     pub async fn start(&self) -> Result<()> {
         info!("Starting Teams Gateway");
@@ -34,46 +58,54 @@ impl TeamsGateway {
 
         // Start OPTIONS ping if enabled
         if self.config.options_ping_enabled {
-            let config = self.config.clone();
-            tokio::spawn(async move {
-                Self::options_ping_loop(config).await;
-            });
+            for proxy in &self.config.sip_proxies {
+                let (every, probe) = self.options_ping(proxy);
+                self.health.spawn_probe(proxy, every, probe);
+            }
         }
 
         Ok(())
     }
 
-    /// OPTIONS ping loop for Teams health checks
-    async fn options_ping_loop(config: TeamsConfig) {
-        let mut ticker = interval(Duration::from_secs(config.options_ping_interval));
-
-        info!(
-            "Starting OPTIONS ping every {} seconds",
-            config.options_ping_interval
-        );
-
-        loop {
-            ticker.tick().await;
-
-            for proxy in &config.sip_proxies {
-                match Self::send_options_ping(proxy).await {
-                    Ok(_) => {
-                        tracing::debug!("OPTIONS ping successful to {}", proxy);
-                    }
-                    Err(e) => {
-                        error!("OPTIONS ping failed to {}: {}", proxy, e);
-                    }
-                }
+    /// Run the gateway in the foreground, for callers that supervise it
+    ///
+    /// Unlike [`start`](Self::start), the OPTIONS ping loops run as
+    /// children of the calling task and stop with it, so this only returns
+    /// if the configuration is invalid.
+    pub async fn run(&self) -> Result<()> {
+        info!("Running Teams Gateway for {}", self.config.sbc_fqdn);
+        self.config.validate()?;
+        let mut pings = JoinSet::new();
+        if self.config.options_ping_enabled {
+            info!(
+                "Starting OPTIONS ping every {} seconds",
+                self.config.options_ping_interval
+            );
+            for proxy in &self.config.sip_proxies {
+                let (every, probe) = self.options_ping(proxy);
+                pings.spawn(self.health.clone().probe_loop(proxy.clone(), every, probe));
             }
         }
+        while pings.join_next().await.is_some() {}
+        std::future::pending().await
     }
 
-    /// Send OPTIONS ping to a SIP proxy
-    async fn send_options_ping(proxy: &str) -> Result<()> {
-        // This would send an actual OPTIONS request
-        // For now, this is a placeholder
-        tracing::debug!("Sending OPTIONS to {}", proxy);
-        Ok(())
+    /// Interval and probe of the OPTIONS ping to a Teams SIP proxy: over
+    /// mutual TLS with the SBC certificate, answered 200 when the proxy
+    /// recognises the SBC
+    fn options_ping(&self, proxy: &str) -> (Duration, impl Fn() -> PingFuture + Send + 'static) {
+        let target = ValidationTarget::new(
+            &self.config.sbc_fqdn,
+            &self.config.mtls_cert_path,
+            &self.config.mtls_key_path,
+        )
+        .with_proxy(proxy, TEAMS_TLS_PORT);
+        let every = Duration::from_secs(self.config.options_ping_interval.max(1));
+        let probe = move || -> PingFuture {
+            let target = target.clone();
+            Box::pin(async move { direct_routing::options_ping(&target).await })
+        };
+        (every, probe)
     }
 
     /// Handle incoming call from Teams
@@ -0,0 +1,31 @@
+// Writes .br and .gz copies of the text files in dist/ for the server to
+// send to clients that accept them
+import { readdirSync, readFileSync, statSync, writeFileSync } from 'node:fs'
+import { join } from 'node:path'
+import { brotliCompressSync, constants, gzipSync } from 'node:zlib'
+
+const COMPRESSIBLE = /\.(html|js|css|svg|json|txt|map)$/
+// Smaller files are not worth the extra request headers
+const MIN_SIZE = 1024
+
+function walk(dir) {
+  for (const entry of readdirSync(dir)) {
+    const path = join(dir, entry)
+    if (statSync(path).isDirectory()) {
+      walk(path)
+      continue
+    }
+    if (!COMPRESSIBLE.test(path)) continue
+    const contents = readFileSync(path)
+    if (contents.length < MIN_SIZE) continue
+    writeFileSync(`${path}.gz`, gzipSync(contents, { level: 9 }))
+    writeFileSync(
+      `${path}.br`,
+      brotliCompressSync(contents, {
+        params: { [constants.BROTLI_PARAM_QUALITY]: constants.BROTLI_MAX_QUALITY },
+      }),
+    )
+  }
+}
+
+walk(new URL('../dist', import.meta.url).pathname)
@@ -18,6 +18,7 @@ import type {
   RateImportRequest,
   RateImportResponse,
   CallLogExportRequest,
+  AccountCodeCost,
   CodecListResponse,
   CodecUpdateRequest,
   CodecAddRequest,
@@ -48,11 +49,68 @@ export const getCall = async (id: string): Promise<CallInfo> => {
   return response.data;
 };
 
+const LIVE_EVENTS = [
+  'ringing',
+  'answered',
+  'hangup',
+  'missed',
+  'registered',
+  'unregistered',
+  'expired',
+  'alarm',
+] as const;
+
+/** Listen for call, registration and alarm events; returns a function that stops listening */
+export const subscribeEvents = (
+  onEvent: (event: import('../types').LiveEvent) => void
+): (() => void) => {
+  const source = new EventSource('/api/v1/events');
+  for (const name of LIVE_EVENTS) {
+    source.addEventListener(name, (message) => {
+      const data = JSON.parse((message as MessageEvent).data);
+      onEvent({ event: name, data } as import('../types').LiveEvent);
+    });
+  }
+  return () => source.close();
+};
+
+export const playAnnouncement = async (
+  callId: string,
+  file: string,
+  legs: Array<'a' | 'b'> = ['a', 'b']
+): Promise<import('../types').Playback> => {
+  const response = await api.post(`/calls/${encodeURIComponent(callId)}/play`, { file, legs });
+  return response.data;
+};
+
+export const getCallTopology = async (
+  callId: string
+): Promise<import('../types').CallTopology> => {
+  const response = await api.get(`/calls/${encodeURIComponent(callId)}/topology`);
+  return response.data;
+};
+
 export const getStats = async (): Promise<Stats> => {
   const response = await api.get('/stats');
   return response.data;
 };
 
+export const readinessCheck = async (): Promise<import('../types').ReadinessResponse> => {
+  // 503 while starting or draining still carries the body
+  const response = await axios.get('/ready', { validateStatus: () => true });
+  return response.data;
+};
+
+export const getServerStatus = async (): Promise<import('../types').ServerStatus> => {
+  const response = await api.get('/status');
+  return response.data;
+};
+
+export const search = async (q: string, limit?: number): Promise<import('../types').SearchResponse> => {
+  const response = await api.get('/search', { params: { q, limit } });
+  return response.data;
+};
+
 export const getConfig = async (): Promise<Config> => {
   const response = await api.get('/config');
   return response.data;
@@ -90,6 +148,9 @@ export const getCallLogs = async (params?: {
   start_date?: number;
   end_date?: number;
   status?: string;
+  caller?: string;
+  callee?: string;
+  account_code?: string;
 }): Promise<CallLogList> => {
   const response = await api.get('/call-logs', { params });
   return response.data;
@@ -100,6 +161,14 @@ export const getCallLog = async (id: string): Promise<CallLogDetail> => {
   return response.data;
 };
 
+export const getAccountCodeCosts = async (params?: {
+  start_date?: number;
+  end_date?: number;
+}): Promise<{ account_codes: AccountCodeCost[]; total: number }> => {
+  const response = await api.get('/call-logs/account-codes', { params });
+  return response.data;
+};
+
 export const exportCallLogs = async (request: CallLogExportRequest): Promise<any> => {
   const response = await api.post('/call-logs/export', request);
   return response.data;
@@ -178,6 +247,16 @@ export const deleteDid = async (id: string): Promise<{ success: boolean; message
   return response.data;
 };
 
+export const getDeletedDids = async (): Promise<{ dids: import('../types').Tombstone<import('../types').Did>[]; total: number }> => {
+  const response = await api.get('/dids/deleted');
+  return response.data;
+};
+
+export const restoreDid = async (id: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.post(`/dids/${id}/restore`);
+  return response.data;
+};
+
 export const reorderDids = async (request: import('../types').ReorderRequest): Promise<{ success: boolean; message: string; dids: any[] }> => {
   const response = await api.post('/dids/reorder', request);
   return response.data;
@@ -199,13 +278,31 @@ export const createExtension = async (extension: import('../types').Extension):
   return response.data;
 };
 
-export const updateExtension = async (id: string, extension: import('../types').Extension): Promise<{ success: boolean; message: string }> => {
-  const response = await api.put(`/extensions/${id}`, extension);
+export const updateExtension = async (id: string, extension: import('../types').Extension, etag = '*'): Promise<{ success: boolean; message: string }> => {
+  const response = await api.put(`/extensions/${id}`, extension, { headers: { 'If-Match': etag } });
+  return response.data;
+};
+
+export const deleteExtension = async (id: string, etag = '*'): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/extensions/${id}`, { headers: { 'If-Match': etag } });
+  return response.data;
+};
+
+export const getDeletedExtensions = async (): Promise<{ extensions: import('../types').Tombstone<import('../types').Extension>[]; total: number }> => {
+  const response = await api.get('/extensions/deleted');
   return response.data;
 };
 
-export const deleteExtension = async (id: string): Promise<{ success: boolean; message: string }> => {
-  const response = await api.delete(`/extensions/${id}`);
+export const restoreExtension = async (id: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.post(`/extensions/${id}/restore`);
+  return response.data;
+};
+
+export const getCallHistory = async (id: string, limit?: number, role?: string): Promise<import('../types').CallHistoryResponse> => {
+  const response = await api.get(`/extensions/${id}/call-history`, {
+    params: { limit },
+    headers: role ? { 'X-RusTalk-Role': role } : undefined,
+  });
   return response.data;
 };
 
@@ -230,13 +327,23 @@ export const createTrunk = async (trunk: import('../types').Trunk): Promise<{ su
   return response.data;
 };
 
-export const updateTrunk = async (id: string, trunk: import('../types').Trunk): Promise<{ success: boolean; message: string }> => {
-  const response = await api.put(`/trunks/${id}`, trunk);
+export const updateTrunk = async (id: string, trunk: import('../types').Trunk, etag = '*'): Promise<{ success: boolean; message: string }> => {
+  const response = await api.put(`/trunks/${id}`, trunk, { headers: { 'If-Match': etag } });
   return response.data;
 };
 
-export const deleteTrunk = async (id: string): Promise<{ success: boolean; message: string }> => {
-  const response = await api.delete(`/trunks/${id}`);
+export const deleteTrunk = async (id: string, etag = '*'): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/trunks/${id}`, { headers: { 'If-Match': etag } });
+  return response.data;
+};
+
+export const getDeletedTrunks = async (): Promise<{ trunks: import('../types').Tombstone<import('../types').Trunk>[]; total: number }> => {
+  const response = await api.get('/trunks/deleted');
+  return response.data;
+};
+
+export const restoreTrunk = async (id: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.post(`/trunks/${id}/restore`);
   return response.data;
 };
 
@@ -245,6 +352,32 @@ export const reorderTrunks = async (request: import('../types').ReorderRequest):
   return response.data;
 };
 
+// Device inventory API calls
+export const getDevices = async (params?: { extension?: string; revoked?: boolean }): Promise<import('../types').DeviceListResponse> => {
+  const response = await api.get('/devices', { params });
+  return response.data;
+};
+
+export const updateDevice = async (id: string, update: { mac?: string; label?: string }): Promise<import('../types').Device> => {
+  const response = await api.put(`/devices/${id}`, update);
+  return response.data;
+};
+
+export const revokeDevice = async (id: string, rotatePassword = false): Promise<import('../types').DeviceRevokeResponse> => {
+  const response = await api.post(`/devices/${id}/revoke`, { rotate_password: rotatePassword });
+  return response.data;
+};
+
+export const reinstateDevice = async (id: string): Promise<{ success: boolean; message: string; device: import('../types').Device }> => {
+  const response = await api.post(`/devices/${id}/reinstate`);
+  return response.data;
+};
+
+export const deleteDevice = async (id: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/devices/${id}`);
+  return response.data;
+};
+
 // Ring Group management API calls
 export const getRingGroups = async (): Promise<import('../types').RingGroupListResponse> => {
   const response = await api.get('/ring-groups');
@@ -276,6 +409,73 @@ export const reorderRingGroups = async (request: import('../types').ReorderReque
   return response.data;
 };
 
+// Extension group management API calls
+export const getGroups = async (): Promise<import('../types').ExtensionGroupListResponse> => {
+  const response = await api.get('/groups');
+  return response.data;
+};
+
+export const createGroup = async (group: import('../types').ExtensionGroup): Promise<{ success: boolean; message: string; id: string }> => {
+  const response = await api.post('/groups', group);
+  return response.data;
+};
+
+export const updateGroup = async (id: string, group: import('../types').ExtensionGroup): Promise<{ success: boolean; message: string }> => {
+  const response = await api.put(`/groups/${id}`, group);
+  return response.data;
+};
+
+export const deleteGroup = async (id: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/groups/${id}`);
+  return response.data;
+};
+
+export const addGroupMember = async (id: string, extension: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.put(`/groups/${id}/members/${extension}`);
+  return response.data;
+};
+
+export const removeGroupMember = async (id: string, extension: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/groups/${id}/members/${extension}`);
+  return response.data;
+};
+
+// IVR flow management API calls
+export const getIvrFlows = async (): Promise<import('../types').IvrFlowListResponse> => {
+  const response = await api.get('/ivr/flows');
+  return response.data;
+};
+
+export const getIvrFlow = async (id: string): Promise<import('../types').IvrFlow> => {
+  const response = await api.get(`/ivr/flows/${id}`);
+  return response.data;
+};
+
+export const createIvrFlow = async (flow: import('../types').IvrFlow): Promise<{ success: boolean; message: string; id: string }> => {
+  const response = await api.post('/ivr/flows', flow);
+  return response.data;
+};
+
+export const updateIvrFlow = async (id: string, flow: import('../types').IvrFlow): Promise<{ success: boolean; message: string }> => {
+  const response = await api.put(`/ivr/flows/${id}`, flow);
+  return response.data;
+};
+
+export const deleteIvrFlow = async (id: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/ivr/flows/${id}`);
+  return response.data;
+};
+
+export const validateIvrFlow = async (flow: import('../types').IvrFlow): Promise<import('../types').IvrValidationResponse> => {
+  const response = await api.post('/ivr/validate', flow);
+  return response.data;
+};
+
+export const simulateIvrFlow = async (id: string, inputs: string[], variables?: Record<string, string>): Promise<import('../types').IvrSimulationResponse> => {
+  const response = await api.post(`/ivr/flows/${id}/simulate`, { inputs, variables });
+  return response.data;
+};
+
 // Route management API calls
 export const getRoutes = async (): Promise<import('../types').RouteListResponse> => {
   const response = await api.get('/routes');
@@ -292,13 +492,13 @@ export const createRoute = async (route: import('../types').Route): Promise<{ su
   return response.data;
 };
 
-export const updateRoute = async (id: string, route: import('../types').Route): Promise<{ success: boolean; message: string }> => {
-  const response = await api.put(`/routes/${id}`, route);
+export const updateRoute = async (id: string, route: import('../types').Route, etag = '*'): Promise<{ success: boolean; message: string }> => {
+  const response = await api.put(`/routes/${id}`, route, { headers: { 'If-Match': etag } });
   return response.data;
 };
 
-export const deleteRoute = async (id: string): Promise<{ success: boolean; message: string }> => {
-  const response = await api.delete(`/routes/${id}`);
+export const deleteRoute = async (id: string, etag = '*'): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/routes/${id}`, { headers: { 'If-Match': etag } });
   return response.data;
 };
 
@@ -312,6 +512,11 @@ export const testRoute = async (request: import('../types').RouteTestRequest): P
   return response.data;
 };
 
+export const simulateRoute = async (request: import('../types').RouteSimulationRequest): Promise<import('../types').RouteSimulationResponse> => {
+  const response = await api.post('/routes/simulate', request);
+  return response.data;
+};
+
 // SIP Profile management API calls
 export const getSipProfiles = async (): Promise<import('../types').SipProfileListResponse> => {
   const response = await api.get('/sip-profiles');
@@ -343,6 +548,102 @@ export const reorderSipProfiles = async (request: import('../types').ReorderRequ
   return response.data;
 };
 
+// SMS messaging API calls
+export const getMessages = async (params?: {
+  extension?: string;
+}): Promise<{ messages: import('../types').SmsMessage[]; total: number }> => {
+  const response = await api.get('/messages', { params });
+  return response.data;
+};
+
+export const sendMessage = async (request: import('../types').SendMessageRequest): Promise<import('../types').SmsMessage> => {
+  const response = await api.post('/messages', request);
+  return response.data;
+};
+
+// Fax API calls
+export const getFaxes = async (): Promise<{ faxes: import('../types').FaxJob[]; total: number }> => {
+  const response = await api.get('/fax');
+  return response.data;
+};
+
+export const sendFax = async (request: import('../types').SendFaxRequest): Promise<import('../types').FaxJob> => {
+  const response = await api.post('/fax', request);
+  return response.data;
+};
+
+// Outbound campaign API calls
+export const getCampaigns = async (): Promise<{ campaigns: import('../types').CampaignSummary[]; total: number }> => {
+  const response = await api.get('/campaigns');
+  return response.data;
+};
+
+export const getCampaign = async (id: string): Promise<import('../types').CampaignSummary> => {
+  const response = await api.get(`/campaigns/${id}`);
+  return response.data;
+};
+
+export const createCampaign = async (campaign: import('../types').Campaign): Promise<{ success: boolean; message: string; id: string }> => {
+  const response = await api.post('/campaigns', campaign);
+  return response.data;
+};
+
+export const deleteCampaign = async (id: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/campaigns/${id}`);
+  return response.data;
+};
+
+export const uploadCampaignContacts = async (id: string, request: import('../types').CampaignContactsRequest): Promise<import('../types').CampaignContactsAdded> => {
+  const response = await api.post(`/campaigns/${id}/contacts`, request);
+  return response.data;
+};
+
+export const startCampaign = async (id: string): Promise<{ id: string; status: import('../types').CampaignStatus }> => {
+  const response = await api.post(`/campaigns/${id}/start`);
+  return response.data;
+};
+
+export const pauseCampaign = async (id: string): Promise<{ id: string; status: import('../types').CampaignStatus }> => {
+  const response = await api.post(`/campaigns/${id}/pause`);
+  return response.data;
+};
+
+export const getDoNotCall = async (): Promise<{ numbers: string[]; total: number }> => {
+  const response = await api.get('/dnc');
+  return response.data;
+};
+
+export const addDoNotCall = async (number: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.post('/dnc', { number });
+  return response.data;
+};
+
+export const removeDoNotCall = async (number: string): Promise<{ success: boolean; message: string }> => {
+  const response = await api.delete(`/dnc/${encodeURIComponent(number)}`);
+  return response.data;
+};
+
+// Outbound webhook API calls
+export const getWebhooks = async (): Promise<{ webhooks: import('../types').Webhook[]; total: number }> => {
+  const response = await api.get('/webhooks');
+  return response.data;
+};
+
+export const previewWebhook = async (name: string): Promise<Record<string, unknown>> => {
+  const response = await api.get(`/webhooks/${name}/preview`);
+  return response.data;
+};
+
+export const testWebhook = async (name: string): Promise<import('../types').WebhookDelivery> => {
+  const response = await api.post(`/webhooks/${name}/test`);
+  return response.data;
+};
+
+export const getHangupCauses = async (params?: { start_date?: number; end_date?: number }): Promise<{ causes: import('../types').HangupCauseCount[]; total: number }> => {
+  const response = await api.get('/analytics/hangup-causes', { params });
+  return response.data;
+};
+
 // Teams/Edge SBC management API calls
 export const getTeamsStatus = async (): Promise<import('../types').TeamsStatusResponse> => {
   const response = await api.get('/teams/status');
@@ -359,6 +660,11 @@ export const updateTeamsConfig = async (config: import('../types').TeamsConfig):
   return response.data;
 };
 
+export const validateTeamsDirectRouting = async (options?: { test_number?: string; caller?: string; proxy?: string }): Promise<import('../types').DirectRoutingReport> => {
+  const response = await api.post('/teams/validate', options ?? {});
+  return response.data;
+};
+
 export const testTeamsConnection = async (): Promise<{ success: boolean; message: string; health_status: import('../types').TeamsHealthStatus[] }> => {
   const response = await api.post('/teams/test');
   return response.data;
@@ -0,0 +1,64 @@
+import React from 'react';
+import { Autocomplete, Box, Chip, TextField } from '@mui/material';
+import { useNavigate } from 'react-router-dom';
+import { search } from '../api/client';
+import type { SearchHit, SearchKind } from '../types';
+
+// Page each kind of result opens
+const kindPaths: Record<SearchKind, string> = {
+  extension: '/extensions',
+  did: '/dids',
+  trunk: '/trunks',
+  route: '/routes',
+  mailbox: '/extensions',
+};
+
+export default function GlobalSearch() {
+  const [query, setQuery] = React.useState('');
+  const [results, setResults] = React.useState<SearchHit[]>([]);
+  const navigate = useNavigate();
+
+  // Wait for typing to pause before searching
+  React.useEffect(() => {
+    const q = query.trim();
+    if (!q) {
+      setResults([]);
+      return;
+    }
+    const timer = setTimeout(() => {
+      search(q, 10)
+        .then((response) => setResults(response.results))
+        .catch(() => setResults([]));
+    }, 250);
+    return () => clearTimeout(timer);
+  }, [query]);
+
+  return (
+    <Autocomplete
+      size="small"
+      sx={{ width: 320, bgcolor: 'background.paper', borderRadius: 1 }}
+      options={results}
+      filterOptions={(options) => options}
+      getOptionLabel={(hit) => hit.label}
+      isOptionEqualToValue={(a, b) => a.kind === b.kind && a.id === b.id}
+      inputValue={query}
+      onInputChange={(_, value) => setQuery(value)}
+      onChange={(_, hit) => {
+        if (hit) {
+          navigate(kindPaths[hit.kind]);
+          setQuery('');
+        }
+      }}
+      renderOption={(props, hit) => (
+        <Box component="li" {...props} key={`${hit.kind}-${hit.id}`} sx={{ gap: 1 }}>
+          <Chip label={hit.kind} size="small" />
+          {hit.label}
+        </Box>
+      )}
+      renderInput={(params) => (
+        <TextField {...params} placeholder="Search extensions, numbers, trunks..." />
+      )}
+      noOptionsText={query.trim() ? 'No matches' : 'Type to search'}
+    />
+  );
+}
@@ -39,6 +39,7 @@ import {
 } from '@mui/icons-material';
 import { useNavigate, useLocation } from 'react-router-dom';
 import { useThemeMode } from '../theme';
+import GlobalSearch from './GlobalSearch';
 
 const drawerWidth = 240;
 
@@ -262,6 +263,7 @@ export default function Layout({ children }: LayoutProps) {
           <Typography variant="h6" noWrap component="div" sx={{ flexGrow: 1 }}>
             RusTalk Admin Console
           </Typography>
+          <GlobalSearch />
           <Tooltip title={`Switch to ${mode === 'light' ? 'dark' : 'light'} mode`}>
             <IconButton
               color="inherit"
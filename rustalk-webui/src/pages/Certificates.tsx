@@ -110,7 +110,7 @@ export default function Certificates() {
         fetchCertificates();
       }, 2000);
     } catch (err: any) {
-      setError(err.response?.data?.message || 'Failed to request certificate');
+      setError(err.response?.data?.detail || 'Failed to request certificate');
       console.error(err);
     } finally {
       setRequesting(false);
@@ -137,7 +137,7 @@ export default function Certificates() {
       // Refresh list
       fetchCertificates();
     } catch (err: any) {
-      setError(err.response?.data?.message || 'Failed to renew certificate');
+      setError(err.response?.data?.detail || 'Failed to renew certificate');
       console.error(err);
     } finally {
       setRenewing(false);
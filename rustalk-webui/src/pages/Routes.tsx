@@ -23,7 +23,7 @@ import {
 } from '@dnd-kit/sortable';
 import { CSS } from '@dnd-kit/utilities';
 import { getRoutes, createRoute, updateRoute, deleteRoute, reorderRoutes, testRoute } from '../api/client';
-import type { Route, RouteDestination, RouteCondition, RouteActionType, TimeCondition, DayOfWeekCondition, DateRangeCondition, CallerIdCondition, DestinationCondition } from '../types';
+import type { Route, RouteDestination, RouteCondition, RouteActionType, TimeCondition, DayOfWeekCondition, DateRangeCondition, CallerIdCondition, DestinationCondition, CallerGroupCondition } from '../types';
 
 interface SortableRowProps {
   route: Route;
@@ -202,6 +202,7 @@ export default function RoutesNew() {
       type === 'DayOfWeek' ? { type: 'DayOfWeek', days: [1, 2, 3, 4, 5] } :
       type === 'DateRange' ? { type: 'DateRange', start_date: '', end_date: '' } :
       type === 'CallerId' ? { type: 'CallerId', pattern: '', negate: false } :
+      type === 'CallerGroup' ? { type: 'CallerGroup', group: '', negate: false } :
       { type: 'Destination', pattern: '', negate: false };
     
     setCurrent({ ...current, conditions: [...(current.conditions || []), newCondition] });
@@ -331,6 +332,7 @@ export default function RoutesNew() {
                   <MenuItem value="Voicemail">Voicemail</MenuItem>
                   <MenuItem value="Hangup">Hangup</MenuItem>
                   <MenuItem value="Custom">Custom</MenuItem>
+                  <MenuItem value="Enum">ENUM (fallback trunk)</MenuItem>
                 </Select>
               </FormControl>
             </Grid>
@@ -497,6 +499,25 @@ export default function RoutesNew() {
                         </Grid>
                       </Grid>
                     )}
+
+                    {condition.type === 'CallerGroup' && (
+                      <Grid container spacing={2}>
+                        <Grid item xs={10}>
+                          <TextField fullWidth label="Extension Group ID"
+                            value={(condition as CallerGroupCondition).group}
+                            onChange={(e) => updateCondition(index, { ...condition, group: e.target.value } as CallerGroupCondition)}
+                            placeholder="e.g., sales" />
+                        </Grid>
+                        <Grid item xs={2}>
+                          <FormControlLabel control={
+                            <Checkbox
+                              checked={(condition as CallerGroupCondition).negate}
+                              onChange={(e) => updateCondition(index, { ...condition, negate: e.target.checked } as CallerGroupCondition)}
+                            />
+                          } label="Negate" />
+                        </Grid>
+                      </Grid>
+                    )}
                   </AccordionDetails>
                 </Accordion>
               ))}
@@ -507,6 +528,7 @@ export default function RoutesNew() {
                 <Button size="small" onClick={() => addCondition('DateRange')}>+ Date Range</Button>
                 <Button size="small" onClick={() => addCondition('CallerId')}>+ Caller ID</Button>
                 <Button size="small" onClick={() => addCondition('Destination')}>+ Destination</Button>
+                <Button size="small" onClick={() => addCondition('CallerGroup')}>+ Caller Group</Button>
               </Stack>
             </Grid>
           </Grid>
@@ -9,6 +9,36 @@ export interface CallInfo {
   duration?: number;
 }
 
+export interface Playback {
+  id: string;
+  call_id: string;
+  legs: Array<'a' | 'b'>;
+  duration_ms: number;
+}
+
+export interface TopologyLeg {
+  id: string;
+  kind: 'caller' | 'target' | 'branch' | 'outbound';
+  // Session state for caller and outbound legs, active/replaced for
+  // targets, fork state for branches
+  state: string;
+  uri?: string;
+  destination?: string;
+  diverted?: string;
+  remote_addr?: string;
+  status?: number;
+  reason?: string;
+}
+
+export interface CallTopology {
+  session_id: string;
+  call_id: string;
+  state: 'initial' | 'ringing' | 'established' | 'terminating' | 'terminated';
+  created_at: string;
+  legs: TopologyLeg[];
+  links: Array<{ from: string; to: string; relation: 'child' | 'replaced_by' }>;
+}
+
 export interface Stats {
   active_calls: number;
   total_calls_today: number;
@@ -62,10 +92,52 @@ export interface Config {
   };
 }
 
+// Error responses (RFC 7807 problem details)
+export interface ApiProblem {
+  type: string;
+  title: string;
+  status: number;
+  detail: string;
+  code: string;
+  [extension: string]: unknown;
+}
+
+export interface HealthWarning {
+  kind: 'certificate_expiring';
+  certificate: string;
+  path: string;
+  expires_at: string;
+  days_until_expiry: number;
+  threshold_days: number;
+  message: string;
+  timestamp: string;
+}
+
 export interface HealthResponse {
   status: string;
   service: string;
   version: string;
+  warnings: HealthWarning[];
+}
+
+export interface ReadinessResponse {
+  ready: boolean;
+  draining: boolean;
+}
+
+export interface ComponentStatus {
+  name: string;
+  state: 'running' | 'restarting' | 'stopped' | 'failed';
+  restarts: number;
+  started_at: string;
+  last_error?: string;
+}
+
+export interface ServerStatus {
+  status: 'running' | 'degraded' | 'draining';
+  version: string;
+  active_calls: number;
+  components: ComponentStatus[];
 }
 
 // Certificate types
@@ -124,6 +196,174 @@ export interface CallLog {
   b_leg_codec?: string;
   recording_path?: string;
   cost?: number;
+  account_code?: string;
+}
+
+export interface AccountCodeCost {
+  account_code?: string;
+  calls: number;
+  duration_seconds: number;
+  total_cost: number;
+}
+
+export interface SmsMessage {
+  id: string;
+  direction: 'inbound' | 'outbound';
+  from: string;
+  to: string;
+  body: string;
+  extension?: string;
+  provider_id?: string;
+  timestamp: string;
+}
+
+export interface SendMessageRequest {
+  from: string;
+  to: string;
+  body: string;
+}
+
+export interface FaxJob {
+  id: string;
+  direction: 'inbound' | 'outbound';
+  from: string;
+  to: string;
+  pages: number;
+  status: 'queued' | 'sending' | 'delivered' | 'failed';
+  email?: string;
+  error?: string;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface SendFaxRequest {
+  from: string;
+  to: string;
+  /** Base64 TIFF-F image */
+  document: string;
+}
+
+export type CampaignStatus = 'draft' | 'running' | 'paused' | 'completed';
+
+export interface Campaign {
+  id: string;
+  name: string;
+  caller_id: string;
+  trunk?: string;
+  connect_to: { type: 'queue' | 'ivr' | 'extension'; target: string };
+  amd?: AmdConfig;
+  on_machine?: { type: 'queue' | 'ivr' | 'extension'; target: string };
+  pacing?: { calls_per_minute: number; max_concurrent: number };
+  calling_hours?: { days: number[]; start_time: string; end_time: string }[];
+  schedule?: string;
+  max_attempts?: number;
+  retry_delay_seconds?: number;
+  ring_timeout_seconds?: number;
+}
+
+export interface AmdConfig {
+  initial_silence_ms?: number;
+  greeting_ms?: number;
+  after_greeting_silence_ms?: number;
+  min_word_ms?: number;
+  between_words_silence_ms?: number;
+  max_words?: number;
+  total_analysis_ms?: number;
+  silence_threshold?: number;
+}
+
+export interface CampaignStats {
+  contacts: number;
+  pending: number;
+  dialing: number;
+  answered: number;
+  failed: number;
+  exhausted: number;
+  do_not_call: number;
+  attempts: number;
+  no_answer: number;
+  busy: number;
+  machines: number;
+  answer_rate: number;
+}
+
+export interface CampaignSummary extends Campaign {
+  status: CampaignStatus;
+  stats: CampaignStats;
+}
+
+export interface CampaignContact {
+  number: string;
+  name?: string;
+  variables?: Record<string, string>;
+}
+
+export interface CampaignContactsRequest {
+  contacts?: CampaignContact[];
+  /** `number,name` rows */
+  csv?: string;
+}
+
+export interface CampaignContactsAdded {
+  added: number;
+  duplicates: number;
+  do_not_call: number;
+}
+
+export interface CrmContact {
+  customer_name?: string;
+  ticket_url?: string;
+  [field: string]: unknown;
+}
+
+export interface CallEvent {
+  kind: 'ringing' | 'answered' | 'hangup' | 'missed';
+  call_id: string;
+  caller?: string;
+  callee?: string;
+  timestamp: string;
+  did?: string;
+  crm?: CrmContact;
+}
+
+export interface RegistrationEvent {
+  type: 'registration';
+  kind: 'registered' | 'unregistered' | 'expired';
+  aor: string;
+  contact: string;
+  source?: string;
+  user_agent?: string;
+  timestamp: string;
+}
+
+export interface AlarmEvent {
+  type: 'alarm';
+  severity: 'warning' | 'critical';
+  source: 'component' | 'certificate' | 'pdd' | 'media_quality';
+  subject: string;
+  message: string;
+  timestamp: string;
+}
+
+/** An event from GET /api/v1/events, with the name it was streamed under */
+export type LiveEvent =
+  | { event: CallEvent['kind']; data: CallEvent }
+  | { event: RegistrationEvent['kind']; data: RegistrationEvent }
+  | { event: 'alarm'; data: AlarmEvent };
+
+export interface Webhook {
+  name: string;
+  url: string;
+  events: CallEvent['kind'][];
+  format: 'nested' | 'flat';
+  fields: Record<string, string>;
+  certificate_warnings: boolean;
+}
+
+export interface WebhookDelivery {
+  endpoint: string;
+  status: number;
+  payload: Record<string, unknown>;
 }
 
 export interface ChargeItem {
@@ -134,6 +374,33 @@ export interface ChargeItem {
   amount: number;
 }
 
+export type DecisionStage =
+  | 'acl'
+  | 'authentication'
+  | 'policy'
+  | 'portability'
+  | 'route'
+  | 'admission'
+  | 'trunk'
+  | 'response';
+
+export interface CallDecision {
+  at: string;
+  stage: DecisionStage;
+  passed: boolean;
+  detail: string;
+  leg?: 'a' | 'b';
+}
+
+export interface CallAttempt {
+  destination: string | null;
+  uri: string;
+  started_at: string;
+  status: number | null;
+  timed_out: boolean;
+  rerouted: boolean;
+}
+
 export interface CallLogDetail {
   id: string;
   call_id: string;
@@ -155,6 +422,8 @@ export interface CallLogDetail {
   to_tag?: string;
   charge_breakdown?: ChargeItem[];
   total_cost?: number;
+  decision_trail: CallDecision[];
+  attempts: CallAttempt[];
 }
 
 export interface CallLogList {
@@ -177,6 +446,7 @@ export interface RateCard {
   effective_date: number;
   end_date?: number;
   active: boolean;
+  trunk?: string;
 }
 
 export interface RateListResponse {
@@ -252,6 +522,83 @@ export interface Did {
   priority: number;
 }
 
+// Global search types
+export type SearchKind = 'extension' | 'did' | 'trunk' | 'route' | 'mailbox';
+
+export interface SearchHit {
+  kind: SearchKind;
+  id: string;
+  label: string;
+  field: string;
+  score: number;
+}
+
+export interface SearchResponse {
+  query: string;
+  results: SearchHit[];
+  total: number;
+}
+
+// Device inventory types
+export type DeviceKind = 'desk_phone' | 'softphone' | 'gateway' | 'unknown';
+
+export interface Device {
+  id: string;
+  extension: string;
+  user_agent?: string;
+  vendor?: string;
+  model?: string;
+  firmware?: string;
+  kind: DeviceKind;
+  mac?: string;
+  instance?: string;
+  label?: string;
+  last_ip?: string;
+  last_contact?: string;
+  first_seen: string;
+  last_registered: string;
+  registrations: number;
+  revoked_at?: string;
+}
+
+export interface DeviceListResponse {
+  devices: Device[];
+  total: number;
+}
+
+export interface DeviceRevokeResponse {
+  success: boolean;
+  message: string;
+  device: Device;
+  unregistered: number;
+  // Only present when the extension's password was rotated
+  password?: string;
+}
+
+// Extensions a device watches on its BLF keys
+export interface BlfListRequest {
+  name?: string;
+  extensions: string[];
+}
+
+// A device's BLF list, as served to it for subscription (RFC 4662)
+export interface ResourceList {
+  uri: string;
+  name?: string;
+  resources: {
+    extension: string;
+    uri: string;
+  }[];
+}
+
+// A soft-deleted resource that can be restored until purge_at
+export interface Tombstone<T> {
+  resource: T;
+  was_enabled: boolean;
+  deleted_at: string;
+  purge_at: string;
+}
+
 export interface DidListResponse {
   dids: Did[];
   total: number;
@@ -266,6 +613,12 @@ export interface Extension {
   enabled: boolean;
   voicemail_enabled: boolean;
   priority: number;
+  class_of_service?: string;
+  require_dial_pin?: boolean;
+  screening?: ScreeningMode;
+  ring_seconds?: number;
+  no_answer?: NoAnswerAction;
+  tenant?: string;
 }
 
 export interface ExtensionListResponse {
@@ -273,6 +626,28 @@ export interface ExtensionListResponse {
   total: number;
 }
 
+export interface CallHistoryEntry {
+  call_id: string;
+  direction: 'inbound' | 'outbound';
+  peer?: string;
+  start_time: string;
+  duration_seconds: number;
+  disposition: 'answered' | 'cancelled' | 'failed' | 'denied' | 'busy';
+  hangup_cause?: string;
+}
+
+export interface HangupCauseCount {
+  cause: string;
+  q850: number;
+  count: number;
+}
+
+export interface CallHistoryResponse {
+  extension: string;
+  calls: CallHistoryEntry[];
+  total: number;
+}
+
 // Trunk types
 export interface Trunk {
   id: string;
@@ -284,6 +659,8 @@ export interface Trunk {
   password?: string;
   enabled: boolean;
   priority: number;
+  quirks?: string;
+  tenant?: string;
 }
 
 export interface TrunkListResponse {
@@ -303,6 +680,7 @@ export interface RingGroup {
   timeout_seconds: number;
   enabled: boolean;
   priority: number;
+  schedule?: string;
 }
 
 export interface RingGroupListResponse {
@@ -310,14 +688,170 @@ export interface RingGroupListResponse {
   total: number;
 }
 
+// Extension group types
+export type GroupKind = 'department' | 'site';
+
+export type ScreeningMode = 'off' | 'announce' | 'screen';
+
+export type NoAnswerAction =
+  | { action: 'voicemail' }
+  | { action: 'forward'; destination: string }
+  | { action: 'busy' };
+
+export interface ExtensionGroup {
+  id: string;
+  name: string;
+  kind: GroupKind;
+  description?: string;
+  members: string[];
+  pickup: boolean;
+  paging: boolean;
+}
+
+export interface ExtensionGroupListResponse {
+  groups: ExtensionGroup[];
+  total: number;
+}
+
+// Schedule types
+export interface OpeningHours {
+  days: number[];
+  start_time: string;
+  end_time: string;
+}
+
+export interface Holiday {
+  name: string;
+  date: string;
+  end_date?: string;
+  hours: { start_time: string; end_time: string }[];
+}
+
+export interface Schedule {
+  id: string;
+  name: string;
+  description?: string;
+  hours: OpeningHours[];
+  holidays: Holiday[];
+}
+
+export interface ScheduleListResponse {
+  schedules: Schedule[];
+  total: number;
+}
+
+// IVR flow types
+export type IvrNodeType = 'menu' | 'play' | 'collect' | 'branch' | 'transfer' | 'voicemail';
+
+interface IvrNodeBase {
+  id: string;
+  position?: { x: number; y: number };
+}
+
+export interface IvrMenuNode extends IvrNodeBase {
+  type: 'menu';
+  prompt: string;
+  options: Record<string, string>;
+  timeout_ms?: number;
+  retries?: number;
+  invalid_prompt?: string;
+  on_failure?: string;
+}
+
+export interface IvrPlayNode extends IvrNodeBase {
+  type: 'play';
+  prompt: string;
+  next?: string;
+}
+
+export interface IvrCollectNode extends IvrNodeBase {
+  type: 'collect';
+  prompt: string;
+  variable: string;
+  max_digits?: number;
+  terminator?: string;
+  timeout_ms?: number;
+  next?: string;
+}
+
+export interface IvrBranchNode extends IvrNodeBase {
+  type: 'branch';
+  variable: string;
+  cases: { pattern: string; next: string }[];
+  default?: string;
+}
+
+export interface IvrTransferNode extends IvrNodeBase {
+  type: 'transfer';
+  destination: string;
+}
+
+export interface IvrVoicemailNode extends IvrNodeBase {
+  type: 'voicemail';
+  mailbox: string;
+}
+
+export type IvrNode = IvrMenuNode | IvrPlayNode | IvrCollectNode | IvrBranchNode | IvrTransferNode | IvrVoicemailNode;
+
+export interface IvrFlow {
+  id: string;
+  name: string;
+  description?: string;
+  start: string;
+  nodes: IvrNode[];
+}
+
+export interface IvrFlowListResponse {
+  flows: IvrFlow[];
+  total: number;
+}
+
+export interface IvrFlowIssue {
+  node: string | null;
+  message: string;
+}
+
+export interface IvrValidationResponse {
+  valid: boolean;
+  issues: IvrFlowIssue[];
+}
+
+export type IvrAction =
+  | { action: 'play'; value: string }
+  | { action: 'await_input'; value: number }
+  | { action: 'transfer'; value: string }
+  | { action: 'voicemail'; value: string }
+  | { action: 'hangup' };
+
+export interface IvrSimulationResponse {
+  flow: string;
+  steps: { input: string; actions: IvrAction[] }[];
+  variables: Record<string, string>;
+  finished: boolean;
+}
+
 // Route types
-export type RouteDestinationType = 'Extension' | 'Trunk' | 'RingGroup' | 'Voicemail' | 'Hangup' | 'Custom';
+export type RouteDestinationType = 'Extension' | 'Trunk' | 'RingGroup' | 'Voicemail' | 'Hangup' | 'Custom' | 'Enum' | 'LeastCost' | 'DestinationList';
 export type RouteActionType = 'accept' | 'reject' | 'continue';
-export type RouteConditionType = 'Time' | 'DayOfWeek' | 'DateRange' | 'CallerId' | 'Destination';
+export type RouteConditionType = 'Time' | 'DayOfWeek' | 'DateRange' | 'CallerId' | 'Destination' | 'CallerGroup' | 'Schedule' | 'Enum' | 'Domain';
 
 export interface RouteDestination {
   type: RouteDestinationType;
-  value?: string;
+  value?: string | string[] | DestinationList; // Trunk names for LeastCost
+}
+
+export interface DestinationHop {
+  destination: RouteDestination;
+  timeout_seconds?: number;
+}
+
+export interface FailoverPolicy {
+  codes: string[]; // Status codes, or classes such as '5xx'
+}
+
+export interface DestinationList {
+  hops: DestinationHop[];
+  failover?: FailoverPolicy;
 }
 
 export interface TimeCondition {
@@ -349,7 +883,30 @@ export interface DestinationCondition {
   negate: boolean;
 }
 
-export type RouteCondition = TimeCondition | DayOfWeekCondition | DateRangeCondition | CallerIdCondition | DestinationCondition;
+export interface CallerGroupCondition {
+  type: 'CallerGroup';
+  group: string;
+  negate: boolean;
+}
+
+export interface ScheduleCondition {
+  type: 'Schedule';
+  schedule: string;
+  negate: boolean;
+}
+
+export interface EnumCondition {
+  type: 'Enum';
+  negate: boolean;
+}
+
+export interface DomainCondition {
+  type: 'Domain';
+  domain: string;
+  negate: boolean;
+}
+
+export type RouteCondition = TimeCondition | DayOfWeekCondition | DateRangeCondition | CallerIdCondition | DestinationCondition | CallerGroupCondition | ScheduleCondition | EnumCondition | DomainCondition;
 
 export interface Route {
   id: string;
@@ -362,6 +919,12 @@ export interface Route {
   conditions?: RouteCondition[];
   action: RouteActionType;
   continue_on_match: boolean;
+  on_failure?: RerouteRule[];
+}
+
+export interface RerouteRule {
+  codes: string[]; // Status codes, or classes such as '5xx'
+  destinations: DestinationList;
 }
 
 export interface RouteListResponse {
@@ -384,6 +947,61 @@ export interface RouteTestResponse {
   message?: string;
 }
 
+export interface RouteSimulationRequest {
+  number: string;
+  caller_id?: string;
+  domain?: string;
+  duration_seconds?: number;
+}
+
+export interface CostedTrunk {
+  trunk: string;
+  rate: RateCard | null;
+  cost: number | null;
+  currency: string | null;
+}
+
+export interface RouteSimulationResponse {
+  number: string;
+  matched: boolean;
+  route_id?: string;
+  route_name?: string;
+  action?: RouteActionType;
+  destination?: RouteDestination;
+  duration_seconds?: number;
+  chosen?: CostedTrunk | null;
+  candidates?: CostedTrunk[];
+  hops?: DestinationHop[];
+  failover?: FailoverPolicy | null;
+  on_failure?: RerouteRule[];
+  trunks_down?: string[];
+  bypassed?: string[];
+  message?: string;
+}
+
+export interface RouteTestCase {
+  name: string;
+  caller_id: string;
+  destination: string;
+  timestamp?: string;
+  expected_route: string | null;
+  expected_destination?: RouteDestination;
+}
+
+export interface RouteTestResult {
+  name: string;
+  passed: boolean;
+  actual_route: string | null;
+  actual_destination: RouteDestination | null;
+  message: string | null;
+}
+
+export interface RouteTestReport {
+  passed: number;
+  failed: number;
+  results: RouteTestResult[];
+}
+
 // SIP Profile types
 export interface SipProfile {
   id: string;
@@ -392,8 +1010,11 @@ export interface SipProfile {
   bind_address: string;
   bind_port: number;
   domain: string;
+  aliases?: string[];
+  realm?: string;
   enabled: boolean;
   priority: number;
+  egress_interface?: string;
 }
 
 export interface SipProfileListResponse {
@@ -426,6 +1047,20 @@ export interface TeamsHealthStatus {
   error?: string;
 }
 
+export interface DirectRoutingCheck {
+  name: 'certificate' | 'sbc_dns' | 'proxy_dns' | 'tls' | 'options' | 'invite';
+  status: 'pass' | 'fail' | 'skipped';
+  detail: string;
+}
+
+export interface DirectRoutingReport {
+  sbc_fqdn: string;
+  proxy: string;
+  passed: boolean;
+  checks: DirectRoutingCheck[];
+  checked_at: string;
+}
+
 export interface TeamsStatusResponse {
   enabled: boolean;
   config?: TeamsConfig;